@@ -0,0 +1,157 @@
+use std::fmt;
+use borsh::{BorshDeserialize, BorshSerialize};
+use thiserror::Error;
+
+/// Lamports in one SOL
+pub const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+/// Arithmetic on an `Amount` that left the range of a `u64`
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountError {
+    #[error("Amount overflowed")]
+    Overflow,
+
+    #[error("Amount underflowed")]
+    Underflow,
+
+    #[error("Amount is not a finite, non-negative number of SOL")]
+    InvalidSol,
+}
+
+/// An amount in a token's smallest unit - lamports for SOL
+///
+/// Serializes exactly like the `u64` it wraps, so configs and accounts that
+/// stored bare lamports read back unchanged. Arithmetic is checked: use
+/// `checked_add`/`checked_sub` where overflow must be reported, and the
+/// saturating variants where clamping is the intended behavior.
+#[derive(
+    BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct Amount(u64);
+
+impl Amount {
+    /// Nothing
+    pub const ZERO: Amount = Amount(0);
+
+    /// The largest representable amount
+    pub const MAX: Amount = Amount(u64::MAX);
+
+    /// An amount of `lamports` (or raw token units)
+    pub const fn from_lamports(lamports: u64) -> Self {
+        Self(lamports)
+    }
+
+    /// The amount in lamports (or raw token units)
+    pub const fn lamports(self) -> u64 {
+        self.0
+    }
+
+    /// Converts a SOL amount, rounding to the nearest lamport
+    ///
+    /// Only for user input and display code: floats have no place in
+    /// on-chain amount handling.
+    #[cfg(feature = "float")]
+    pub fn from_sol(sol: f64) -> Result<Self, AmountError> {
+        if !sol.is_finite() || sol < 0.0 {
+            return Err(AmountError::InvalidSol);
+        }
+        let lamports = (sol * LAMPORTS_PER_SOL as f64).round();
+        if lamports >= u64::MAX as f64 {
+            return Err(AmountError::Overflow);
+        }
+        Ok(Self(lamports as u64))
+    }
+
+    /// Whether this is zero
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// `self + other`, or `AmountError::Overflow`
+    pub fn checked_add(self, other: Amount) -> Result<Amount, AmountError> {
+        self.0.checked_add(other.0).map(Amount).ok_or(AmountError::Overflow)
+    }
+
+    /// `self - other`, or `AmountError::Underflow`
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, AmountError> {
+        self.0.checked_sub(other.0).map(Amount).ok_or(AmountError::Underflow)
+    }
+
+    /// `self + other`, clamped to `Amount::MAX`
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    /// `self - other`, clamped to zero
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+}
+
+impl From<Amount> for u64 {
+    fn from(amount: Amount) -> u64 {
+        amount.0
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Formats as SOL with at least two decimals: "1.50 SOL", "0.000000001 SOL"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.0 / LAMPORTS_PER_SOL;
+        let fraction = format!("{:09}", self.0 % LAMPORTS_PER_SOL);
+        let trimmed = fraction.trim_end_matches('0');
+        let decimals = if trimmed.len() < 2 { &fraction[..2] } else { trimmed };
+        write!(f, "{}.{} SOL", whole, decimals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_like_u64() {
+        for lamports in [0, 1, 1_500_000_000, u64::MAX] {
+            let amount = Amount::from_lamports(lamports);
+            assert_eq!(borsh::to_vec(&amount).unwrap(), borsh::to_vec(&lamports).unwrap());
+            assert_eq!(borsh::from_slice::<Amount>(&lamports.to_le_bytes()).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let one = Amount::from_lamports(1);
+
+        assert_eq!(one.checked_add(one), Ok(Amount::from_lamports(2)));
+        assert_eq!(Amount::MAX.checked_add(one), Err(AmountError::Overflow));
+        assert_eq!(one.checked_sub(one), Ok(Amount::ZERO));
+        assert_eq!(Amount::ZERO.checked_sub(one), Err(AmountError::Underflow));
+    }
+
+    #[test]
+    fn test_saturating_arithmetic() {
+        let one = Amount::from_lamports(1);
+
+        assert_eq!(Amount::MAX.saturating_add(one), Amount::MAX);
+        assert_eq!(Amount::ZERO.saturating_sub(one), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Amount::from_lamports(1_500_000_000).to_string(), "1.50 SOL");
+        assert_eq!(Amount::from_lamports(2 * LAMPORTS_PER_SOL).to_string(), "2.00 SOL");
+        assert_eq!(Amount::from_lamports(1_234_500_000).to_string(), "1.2345 SOL");
+        assert_eq!(Amount::from_lamports(1).to_string(), "0.000000001 SOL");
+        assert_eq!(Amount::ZERO.to_string(), "0.00 SOL");
+    }
+
+    #[cfg(feature = "float")]
+    #[test]
+    fn test_from_sol() {
+        assert_eq!(Amount::from_sol(1.5), Ok(Amount::from_lamports(1_500_000_000)));
+        assert_eq!(Amount::from_sol(0.000000001), Ok(Amount::from_lamports(1)));
+        assert_eq!(Amount::from_sol(-1.0), Err(AmountError::InvalidSol));
+        assert_eq!(Amount::from_sol(f64::NAN), Err(AmountError::InvalidSol));
+        assert_eq!(Amount::from_sol(1e12), Err(AmountError::Overflow));
+    }
+}
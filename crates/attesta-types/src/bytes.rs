@@ -0,0 +1,182 @@
+//! Reading fixed-width fields out of byte buffers
+//!
+//! Every reader takes the buffer and a cursor, checks the field fits,
+//! and advances the cursor past it. A field that doesn't fit is a
+//! `ParseError` and leaves the cursor where it was, so the error's offset
+//! is where the field should have started.
+//!
+//! Attesta's own layouts (policy configs, signatures, instruction data)
+//! are little-endian, like Borsh. WebAuthn's authenticator data is
+//! big-endian, so it has its own readers; pick by the format, never by
+//! what happens to work on the test machine:
+//!
+//! ```
+//! use attesta_types::bytes::{read_u32_be, read_u32_le};
+//!
+//! let buf = [0x01, 0x00, 0x00, 0x00];
+//! assert_eq!(read_u32_le(&buf, &mut 0), Ok(1));
+//! assert_eq!(read_u32_be(&buf, &mut 0), Ok(0x0100_0000));
+//! ```
+
+use crate::pubkey::Pubkey;
+use thiserror::Error;
+
+/// A field that runs past the end of its buffer
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Need {len} bytes at offset {offset}")]
+pub struct ParseError {
+    /// Where the field starts
+    pub offset: usize,
+
+    /// How long the field is
+    pub len: usize,
+}
+
+/// Reads `N` bytes at `offset`
+pub fn read_array<const N: usize>(buf: &[u8], offset: &mut usize) -> Result<[u8; N], ParseError> {
+    let error = ParseError { offset: *offset, len: N };
+    let end = offset.checked_add(N).ok_or(error)?;
+    let bytes = buf.get(*offset..end).ok_or(error)?.try_into().map_err(|_| error)?;
+    *offset = end;
+    Ok(bytes)
+}
+
+/// Reads `len` bytes at `offset`, for fields whose length is in the data
+pub fn read_slice<'a>(buf: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8], ParseError> {
+    let error = ParseError { offset: *offset, len };
+    let end = offset.checked_add(len).ok_or(error)?;
+    let bytes = buf.get(*offset..end).ok_or(error)?;
+    *offset = end;
+    Ok(bytes)
+}
+
+/// Reads a little-endian `u64`
+///
+/// ```
+/// use attesta_types::bytes::read_u64_le;
+///
+/// let buf = [0x2a, 0, 0, 0, 0, 0, 0, 0, 0xff];
+/// let mut offset = 0;
+/// assert_eq!(read_u64_le(&buf, &mut offset), Ok(42));
+/// assert_eq!(offset, 8);
+/// ```
+pub fn read_u64_le(buf: &[u8], offset: &mut usize) -> Result<u64, ParseError> {
+    read_array(buf, offset).map(u64::from_le_bytes)
+}
+
+/// Reads a little-endian `i64`
+///
+/// ```
+/// use attesta_types::bytes::read_i64_le;
+///
+/// assert_eq!(read_i64_le(&(-2i64).to_le_bytes(), &mut 0), Ok(-2));
+/// ```
+pub fn read_i64_le(buf: &[u8], offset: &mut usize) -> Result<i64, ParseError> {
+    read_array(buf, offset).map(i64::from_le_bytes)
+}
+
+/// Reads a little-endian `u32`
+///
+/// ```
+/// use attesta_types::bytes::read_u32_le;
+///
+/// assert_eq!(read_u32_le(&[0x00, 0x01, 0x00, 0x00], &mut 0), Ok(256));
+/// ```
+pub fn read_u32_le(buf: &[u8], offset: &mut usize) -> Result<u32, ParseError> {
+    read_array(buf, offset).map(u32::from_le_bytes)
+}
+
+/// Reads a big-endian `u32` (WebAuthn's signature counter)
+///
+/// ```
+/// use attesta_types::bytes::read_u32_be;
+///
+/// assert_eq!(read_u32_be(&[0x00, 0x00, 0x01, 0x00], &mut 0), Ok(256));
+/// ```
+pub fn read_u32_be(buf: &[u8], offset: &mut usize) -> Result<u32, ParseError> {
+    read_array(buf, offset).map(u32::from_be_bytes)
+}
+
+/// Reads a big-endian `u16` (WebAuthn's credential ID length)
+///
+/// ```
+/// use attesta_types::bytes::read_u16_be;
+///
+/// assert_eq!(read_u16_be(&[0x01, 0x00], &mut 0), Ok(256));
+/// ```
+pub fn read_u16_be(buf: &[u8], offset: &mut usize) -> Result<u16, ParseError> {
+    read_array(buf, offset).map(u16::from_be_bytes)
+}
+
+/// Reads a 32-byte address
+pub fn read_pubkey(buf: &[u8], offset: &mut usize) -> Result<Pubkey, ParseError> {
+    read_array(buf, offset).map(Pubkey::new_from_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a reader at an exact fit, one byte short, and with the offset at the end
+    fn check_bounds<T: PartialEq + std::fmt::Debug>(
+        len: usize,
+        read: fn(&[u8], &mut usize) -> Result<T, ParseError>,
+    ) {
+        let buf: Vec<u8> = (1..=len as u8 + 2).collect();
+
+        // Exact fit, after a two-byte prefix
+        let mut offset = 2;
+        assert!(read(&buf, &mut offset).is_ok());
+        assert_eq!(offset, buf.len());
+
+        // One byte short
+        let mut offset = 3;
+        assert_eq!(read(&buf, &mut offset), Err(ParseError { offset: 3, len }));
+        assert_eq!(offset, 3, "a failed read moved the cursor");
+
+        // Offset at the end, and past it
+        let mut offset = buf.len();
+        assert_eq!(read(&buf, &mut offset), Err(ParseError { offset: buf.len(), len }));
+        let mut offset = usize::MAX;
+        assert_eq!(read(&buf, &mut offset), Err(ParseError { offset: usize::MAX, len }));
+    }
+
+    #[test]
+    fn test_reader_bounds() {
+        check_bounds(8, read_u64_le);
+        check_bounds(8, read_i64_le);
+        check_bounds(4, read_u32_le);
+        check_bounds(4, read_u32_be);
+        check_bounds(2, read_u16_be);
+        check_bounds(32, read_pubkey);
+        check_bounds(5, |buf, offset| read_slice(buf, offset, 5).map(<[u8]>::to_vec));
+    }
+
+    #[test]
+    fn test_endianness() {
+        let buf = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(read_u64_le(&buf, &mut 0), Ok(0x0807_0605_0403_0201));
+        assert_eq!(read_i64_le(&[0xff; 8], &mut 0), Ok(-1));
+        assert_eq!(read_u32_le(&buf, &mut 4), Ok(0x0807_0605));
+        assert_eq!(read_u32_be(&buf, &mut 4), Ok(0x0506_0708));
+        assert_eq!(read_u16_be(&buf, &mut 6), Ok(0x0708));
+    }
+
+    #[test]
+    fn test_reads_advance_in_sequence() {
+        let mut buf = [7u8; 32].to_vec();
+        buf.extend_from_slice(&5u64.to_le_bytes());
+        buf.extend_from_slice(&(-5i64).to_le_bytes());
+
+        let mut offset = 0;
+        assert_eq!(read_pubkey(&buf, &mut offset), Ok(Pubkey::new_from_array([7; 32])));
+        assert_eq!(read_u64_le(&buf, &mut offset), Ok(5));
+        assert_eq!(read_i64_le(&buf, &mut offset), Ok(-5));
+        assert_eq!(offset, buf.len());
+    }
+
+    #[test]
+    fn test_oversized_length_is_an_error() {
+        assert_eq!(read_slice(&[0; 4], &mut 2, usize::MAX), Err(ParseError { offset: 2, len: usize::MAX }));
+    }
+}
@@ -0,0 +1,88 @@
+//! Sizes shared by the program, the SDK and the libraries
+//!
+//! Field widths and account layout sizes that several crates need to agree
+//! on. Use these instead of writing the numbers out, so a size is decided in
+//! exactly one place.
+
+/// A Solana address
+pub const PUBKEY_LEN: usize = 32;
+
+/// A SHA-256 digest (message hashes, challenges, credential ID hashes)
+pub const HASH_LEN: usize = 32;
+
+/// A P-256 public key as Attesta stores it: the x and y coordinates, 32 bytes each
+pub const P256_PUBKEY_LEN: usize = 64;
+
+/// A SEC1 uncompressed P-256 public key: `0x04`, then x and y
+pub const P256_SEC1_UNCOMPRESSED_LEN: usize = 1 + P256_PUBKEY_LEN;
+
+/// A SEC1 compressed P-256 public key: a parity byte, then x
+pub const P256_SEC1_COMPRESSED_LEN: usize = 1 + P256_PUBKEY_LEN / 2;
+
+/// A raw P-256 ECDSA signature: r then s, 32 bytes each
+pub const P256_SIGNATURE_LEN: usize = 64;
+
+/// WebAuthn authenticator data without extensions or attested credential
+/// data: RP ID hash (32), flags (1) and signature counter (4)
+pub const MIN_AUTHENTICATOR_DATA_LEN: usize = HASH_LEN + 1 + 4;
+
+/// An authenticator model identifier (AAGUID), as reported in attested
+/// credential data
+pub const AAGUID_LEN: usize = 16;
+
+/// The nonce AES-GCM encrypts backups with (96 bits)
+pub const AES_GCM_NONCE_LEN: usize = 12;
+
+/// The type tag Anchor puts in front of every account's data
+pub const ACCOUNT_DISCRIMINATOR_LEN: usize = 8;
+
+/// The length prefix Borsh writes before a `Vec` or `String`
+pub const BORSH_LEN_PREFIX: usize = 4;
+
+/// The longest credential ID a new account is allocated room for
+pub const MAX_CREDENTIAL_ID_LEN: usize = 256;
+
+/// The longest name (UTF-8 bytes) a passkey can be added under
+pub const MAX_PASSKEY_NAME_LEN: usize = 64;
+
+/// The longest policy a new account is allocated room for
+///
+/// Accounts grow when a larger policy is set later; this only sizes the
+/// initial allocation.
+pub const MAX_INITIAL_POLICY_LEN: usize = 256;
+
+/// The program's error code for `ConcurrentModification`
+///
+/// Anchor numbers program errors from 6000 in the order they're declared.
+/// Clients match on this one to fetch the account again and retry.
+pub const CONCURRENT_MODIFICATION_ERROR: u32 = 6068;
+
+/// The program's error codes for an `execute` its policy checks denied
+///
+/// `PolicyDenied`, `ZeroAmountTransfer`, `SelfTransfer`,
+/// `ParentPolicyDenied` and `OverRecoveryAllowance`. A lockout isn't among
+/// them: it's the signatures, not the transaction, being refused.
+pub const POLICY_DENIAL_ERRORS: [u32; 5] = [6003, 6016, 6017, 6019, 6087];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These are baked into accounts and instructions already on-chain
+    #[test]
+    fn test_sizes_are_stable() {
+        assert_eq!(PUBKEY_LEN, 32);
+        assert_eq!(HASH_LEN, 32);
+        assert_eq!(P256_PUBKEY_LEN, 64);
+        assert_eq!(P256_SEC1_UNCOMPRESSED_LEN, 65);
+        assert_eq!(P256_SEC1_COMPRESSED_LEN, 33);
+        assert_eq!(P256_SIGNATURE_LEN, 64);
+        assert_eq!(MIN_AUTHENTICATOR_DATA_LEN, 37);
+        assert_eq!(AAGUID_LEN, 16);
+        assert_eq!(AES_GCM_NONCE_LEN, 12);
+        assert_eq!(ACCOUNT_DISCRIMINATOR_LEN, 8);
+        assert_eq!(BORSH_LEN_PREFIX, 4);
+        assert_eq!(MAX_CREDENTIAL_ID_LEN, 256);
+        assert_eq!(MAX_INITIAL_POLICY_LEN, 256);
+    }
+}
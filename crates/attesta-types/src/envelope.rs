@@ -0,0 +1,39 @@
+//! A completed passkey authorization, ready to submit
+
+use crate::pubkey::Pubkey;
+use crate::webauthn::WebAuthnSignature;
+
+/// Length of a client-supplied idempotency key
+pub const IDEMPOTENCY_KEY_LEN: usize = 16;
+
+/// A key the client attaches to an `execute` so it can safely retry it
+pub type IdempotencyKey = [u8; IDEMPOTENCY_KEY_LEN];
+
+/// A checked authorization, ready to submit with `execute`
+#[derive(Debug, Clone)]
+pub struct ProofEnvelope {
+    /// The WebAuthn signature, with the signature normalized to raw r || s
+    pub webauthn_sig: WebAuthnSignature,
+
+    /// The nonce the signature consumes
+    pub nonce: u64,
+
+    /// The hash of the authorized transaction
+    pub message_hash: [u8; 32],
+
+    /// The request's memo, which `message_hash` covers (empty for none)
+    pub memo: Vec<u8>,
+
+    /// Idempotency key submitted with the proof
+    pub idempotency_key: IdempotencyKey,
+
+    /// The account's parent, if it's a sub-account
+    pub parent_account: Option<Pubkey>,
+
+    /// Whether the account records executions in its proof log (`execute` must pass it)
+    pub logs_proofs: bool,
+
+    /// Whether `execute` should also post the memo through the SPL Memo
+    /// program, so explorers show it (not signed; off by default)
+    pub emit_memo: bool,
+}
@@ -0,0 +1,606 @@
+//! What a policy does, in terms a wallet can show its user
+//!
+//! `Policy::explain` decodes a policy into a `PolicyExplanation`: what kind
+//! of rule it is, its parameters with their units, and a template key such
+//! as `policy.spending_limit`. An app that localizes looks the key up in its
+//! own strings and fills in the parameters (`render_with`); `Display` uses
+//! the English templates (`english_template`):
+//!
+//! ```text
+//! You can spend up to 1.00 SOL per transaction
+//!   - Up to 250 of token EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v per transfer
+//!   - Other tokens can't be sent
+//! ```
+//!
+//! A composite explains each of its rules in order, and limits, bindings and
+//! allowlists explain their entries the same way, as `parts`.
+
+use std::fmt;
+use crate::amount::Amount;
+use crate::bytes::{read_pubkey, read_u64_le};
+use crate::policy::{Policy, PolicyType, SECONDS_PER_DAY};
+use crate::pubkey::Pubkey;
+
+/// What a `PolicyExplanation` describes: a whole policy, or one entry of one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExplanationKind {
+    /// A policy of this type
+    Policy(PolicyType),
+
+    /// A policy of this type whose config can't be read; it denies every transaction
+    Malformed(PolicyType),
+
+    /// One token's cap in a `SpendingLimit` or `DailyLimit`
+    MintLimit,
+
+    /// What a limit allows for tokens without a cap of their own
+    UnlistedMints,
+
+    /// One address a `DestinationAllowlist` allows
+    AllowedDestination,
+
+    /// One group of destinations in a `CredentialBinding`, and who may sign for it
+    Binding,
+
+    /// Who may sign what no binding of a `CredentialBinding` covers
+    DefaultBinding,
+
+    /// One destination's own budget in a `PerDestinationLimit`
+    DestinationLimit,
+}
+
+/// A parameter's value, carrying its unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamValue {
+    /// SOL, shown the way `Amount` displays it
+    Sol(Amount),
+
+    /// A token amount in the mint's smallest unit, with the mint's decimals
+    Token { amount: u64, decimals: u8 },
+
+    /// A raw amount in the smallest unit of whatever is transferred
+    /// (a `PerDestinationLimit` budget)
+    BaseUnits(u64),
+
+    /// A length of time
+    Seconds(u32),
+
+    /// A point in time (Unix timestamp)
+    Timestamp(i64),
+
+    Count(usize),
+
+    Address(Pubkey),
+
+    /// SHA-256 of a passkey's credential ID
+    Credential([u8; 32]),
+}
+
+/// One named parameter of an explanation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExplanationParam {
+    /// The placeholder it fills in its template: `{name}`
+    pub name: &'static str,
+
+    pub value: ParamValue,
+}
+
+/// A structured description of a policy (see the module docs)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyExplanation {
+    pub kind: ExplanationKind,
+
+    template_key: &'static str,
+
+    /// The template's arguments, in the order the template uses them
+    pub params: Vec<ExplanationParam>,
+
+    /// A composite's rules, or a rule's entries, in the order they apply
+    pub parts: Vec<PolicyExplanation>,
+}
+
+impl PolicyExplanation {
+    fn new(kind: ExplanationKind, template_key: &'static str, params: Vec<ExplanationParam>) -> Self {
+        Self { kind, template_key, params, parts: Vec::new() }
+    }
+
+    fn with_parts(mut self, parts: Vec<PolicyExplanation>) -> Self {
+        self.parts = parts;
+        self
+    }
+
+    /// The key of the sentence that describes this, such as `policy.spending_limit`
+    ///
+    /// Keys are stable: a localized string table can be written against
+    /// them. `english_template` has the English for each.
+    pub fn template_key(&self) -> &'static str {
+        self.template_key
+    }
+
+    /// The parameter named `name`, if this explanation has one
+    pub fn param(&self, name: &str) -> Option<ParamValue> {
+        self.params.iter().find(|param| param.name == name).map(|param| param.value)
+    }
+
+    /// Renders this explanation and its parts with `template_for`'s strings
+    ///
+    /// Each line is one explanation, its parts below it indented and
+    /// bulleted. A key `template_for` has no string for falls back to
+    /// English. Parameters fill their `{name}` placeholders as their
+    /// `Display` shows them; an app that formats amounts or dates its own
+    /// way reads `params` instead.
+    pub fn render_with<'a>(&self, template_for: &impl Fn(&str) -> Option<&'a str>) -> String {
+        let mut lines = Vec::new();
+        self.render_lines(template_for, 0, &mut lines);
+        lines.join("\n")
+    }
+
+    fn render_lines<'a>(&self, template_for: &impl Fn(&str) -> Option<&'a str>, depth: usize, lines: &mut Vec<String>) {
+        let template = template_for(self.template_key)
+            .or_else(|| english_template(self.template_key))
+            .unwrap_or(self.template_key);
+        let text = self
+            .params
+            .iter()
+            .fold(template.to_string(), |text, param| text.replace(&format!("{{{}}}", param.name), &param.value.to_string()));
+        lines.push(match depth {
+            0 => text,
+            depth => format!("{}- {}", "  ".repeat(depth), text),
+        });
+        for part in &self.parts {
+            part.render_lines(template_for, depth + 1, lines);
+        }
+    }
+}
+
+impl fmt::Display for PolicyExplanation {
+    /// Renders in English (see `render_with`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render_with(&|_| None))
+    }
+}
+
+/// The English sentence for a template key, or `None` for a key `explain` never uses
+pub fn english_template(template_key: &str) -> Option<&'static str> {
+    let template = match template_key {
+        "policy.open" => "Any transaction is allowed",
+        "policy.spending_limit" => "You can spend up to {max_amount} per transaction",
+        "policy.daily_limit" => "You can spend up to {max_amount} per day",
+        "policy.windowed_limit" => "You can spend up to {max_amount} every {window}",
+        "policy.mint_limit" => "Up to {max_amount} of token {mint} per transfer",
+        "policy.mint_limits.none" => "Tokens can't be sent",
+        "policy.mint_limits.unlisted_allowed" => "Other tokens have no limit",
+        "policy.mint_limits.unlisted_denied" => "Other tokens can't be sent",
+        "policy.multi_sig" => "Every transaction needs all {signers} required signers to approve it",
+        "policy.time_locked" => "Nothing can be sent before {unlock_at}",
+        "policy.destination_allowlist" => "Transfers can only go to these {destinations} addresses",
+        "policy.destination_allowlist.destination" => "{destination}",
+        "policy.composite" => "Every one of these must allow a transaction",
+        "policy.credential_binding" => "Some transfers need a particular passkey",
+        "policy.credential_binding.bound" => "Transfers to {destinations} listed addresses need passkey {credential}",
+        "policy.credential_binding.any" => "Transfers to {destinations} listed addresses can be signed by any passkey",
+        "policy.credential_binding.default_bound" => "Everything else needs passkey {credential}",
+        "policy.credential_binding.default_any" => "Everything else can be signed by any passkey",
+        "policy.per_destination_limit" => "Each address can be sent up to {max_amount} every {window}",
+        "policy.per_destination_limit.limited" => "{destination} can be sent up to {max_amount} every {window}",
+        "policy.per_destination_limit.unlimited" => "{destination} has no limit",
+        "policy.vault" => "Transfers over {threshold} wait {delay} after they're announced, and any passkey can veto them",
+        "policy.malformed" => "This policy can't be read, so it denies every transaction",
+        _ => return None,
+    };
+    Some(template)
+}
+
+impl Policy {
+    /// Describes what this policy does, for showing to the account's user
+    ///
+    /// A config that can't be read explains as `ExplanationKind::Malformed`,
+    /// since that's what it does: deny everything. In a composite, only the
+    /// rule that can't be read is.
+    pub fn explain(&self) -> PolicyExplanation {
+        self.explain_config().unwrap_or_else(|| {
+            PolicyExplanation::new(ExplanationKind::Malformed(self.policy_type), "policy.malformed", Vec::new())
+        })
+    }
+
+    fn explain_config(&self) -> Option<PolicyExplanation> {
+        let kind = ExplanationKind::Policy(self.policy_type);
+        let param = |name, value| ExplanationParam { name, value };
+        let explanation = match self.policy_type {
+            PolicyType::Open => PolicyExplanation::new(kind, "policy.open", Vec::new()),
+            PolicyType::SpendingLimit => {
+                let max_amount = read_u64_le(&self.config, &mut 0).ok()?;
+                PolicyExplanation::new(kind, "policy.spending_limit", vec![param("max_amount", ParamValue::Sol(Amount::from_lamports(max_amount)))])
+                    .with_parts(self.explain_mint_limits())
+            }
+            PolicyType::DailyLimit => {
+                let limit = self.daily_limit_config()?;
+                let max_amount = param("max_amount", ParamValue::Sol(Amount::from_lamports(limit.max_amount)));
+                let explanation = if limit.window_seconds == SECONDS_PER_DAY {
+                    PolicyExplanation::new(kind, "policy.daily_limit", vec![max_amount])
+                } else {
+                    let window = param("window", ParamValue::Seconds(limit.window_seconds));
+                    PolicyExplanation::new(kind, "policy.windowed_limit", vec![max_amount, window])
+                };
+                explanation.with_parts(self.explain_mint_limits())
+            }
+            PolicyType::MultiSig => {
+                let signers = self.addresses()?.len();
+                PolicyExplanation::new(kind, "policy.multi_sig", vec![param("signers", ParamValue::Count(signers))])
+            }
+            PolicyType::TimeLocked => {
+                let unlock_at = i64::from_le_bytes(self.config.as_slice().try_into().ok()?);
+                PolicyExplanation::new(kind, "policy.time_locked", vec![param("unlock_at", ParamValue::Timestamp(unlock_at))])
+            }
+            PolicyType::DestinationAllowlist => {
+                let destinations = self.addresses()?;
+                let parts = destinations
+                    .iter()
+                    .map(|destination| {
+                        PolicyExplanation::new(
+                            ExplanationKind::AllowedDestination,
+                            "policy.destination_allowlist.destination",
+                            vec![param("destination", ParamValue::Address(*destination))],
+                        )
+                    })
+                    .collect();
+                let count = param("destinations", ParamValue::Count(destinations.len()));
+                PolicyExplanation::new(kind, "policy.destination_allowlist", vec![count]).with_parts(parts)
+            }
+            PolicyType::Composite => {
+                let rules = self.rules()?;
+                PolicyExplanation::new(kind, "policy.composite", Vec::new())
+                    .with_parts(rules.iter().map(Policy::explain).collect())
+            }
+            PolicyType::CredentialBinding => {
+                let bindings = self.credential_bindings()?;
+                let binding = |kind, template_keys: (&'static str, &'static str), mut params: Vec<ExplanationParam>, credential: Option<[u8; 32]>| {
+                    match credential {
+                        Some(credential) => {
+                            params.push(param("credential", ParamValue::Credential(credential)));
+                            PolicyExplanation::new(kind, template_keys.0, params)
+                        }
+                        None => PolicyExplanation::new(kind, template_keys.1, params),
+                    }
+                };
+                let mut parts: Vec<PolicyExplanation> = bindings
+                    .bindings
+                    .iter()
+                    .map(|entry| {
+                        binding(
+                            ExplanationKind::Binding,
+                            ("policy.credential_binding.bound", "policy.credential_binding.any"),
+                            vec![param("destinations", ParamValue::Count(entry.destinations.len()))],
+                            entry.credential_id_hash,
+                        )
+                    })
+                    .collect();
+                parts.push(binding(
+                    ExplanationKind::DefaultBinding,
+                    ("policy.credential_binding.default_bound", "policy.credential_binding.default_any"),
+                    Vec::new(),
+                    bindings.default,
+                ));
+                PolicyExplanation::new(kind, "policy.credential_binding", Vec::new()).with_parts(parts)
+            }
+            PolicyType::PerDestinationLimit => {
+                let limits = self.destination_limits()?;
+                let window = param("window", ParamValue::Seconds(limits.window_seconds));
+                let parts = limits
+                    .limits
+                    .iter()
+                    .map(|limit| {
+                        let destination = param("destination", ParamValue::Address(limit.destination));
+                        match limit.max_amount {
+                            Some(max_amount) => PolicyExplanation::new(
+                                ExplanationKind::DestinationLimit,
+                                "policy.per_destination_limit.limited",
+                                vec![destination, param("max_amount", ParamValue::BaseUnits(max_amount)), window],
+                            ),
+                            None => PolicyExplanation::new(
+                                ExplanationKind::DestinationLimit,
+                                "policy.per_destination_limit.unlimited",
+                                vec![destination],
+                            ),
+                        }
+                    })
+                    .collect();
+                let default_max_amount = param("max_amount", ParamValue::BaseUnits(limits.default_max_amount));
+                PolicyExplanation::new(kind, "policy.per_destination_limit", vec![default_max_amount, window]).with_parts(parts)
+            }
+            PolicyType::Vault => {
+                let vault = self.vault_config()?;
+                let threshold = param("threshold", ParamValue::Sol(Amount::from_lamports(vault.threshold_lamports)));
+                let delay = param("delay", ParamValue::Seconds(vault.delay_seconds));
+                PolicyExplanation::new(kind, "policy.vault", vec![threshold, delay])
+            }
+        };
+        Some(explanation)
+    }
+
+    /// A limit's token caps, then what it does with every other token
+    fn explain_mint_limits(&self) -> Vec<PolicyExplanation> {
+        let Some(limits) = self.mint_limits() else {
+            return vec![PolicyExplanation::new(ExplanationKind::UnlistedMints, "policy.mint_limits.none", Vec::new())];
+        };
+        let mut parts: Vec<PolicyExplanation> = limits
+            .limits
+            .iter()
+            .map(|limit| {
+                PolicyExplanation::new(
+                    ExplanationKind::MintLimit,
+                    "policy.mint_limit",
+                    vec![
+                        ExplanationParam {
+                            name: "max_amount",
+                            value: ParamValue::Token { amount: limit.max_amount, decimals: limit.decimals },
+                        },
+                        ExplanationParam { name: "mint", value: ParamValue::Address(limit.mint) },
+                    ],
+                )
+            })
+            .collect();
+        let unlisted = if limits.allow_unlisted { "policy.mint_limits.unlisted_allowed" } else { "policy.mint_limits.unlisted_denied" };
+        parts.push(PolicyExplanation::new(ExplanationKind::UnlistedMints, unlisted, Vec::new()));
+        parts
+    }
+
+    /// The keys a `MultiSig` or `DestinationAllowlist` config lists, or
+    /// `None` if it isn't a whole number of them
+    fn addresses(&self) -> Option<Vec<Pubkey>> {
+        let chunks = self.config.chunks_exact(32);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+        chunks.map(|chunk| read_pubkey(chunk, &mut 0).ok()).collect()
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ParamValue::Sol(amount) => write!(f, "{}", amount),
+            ParamValue::Token { amount, decimals } => f.write_str(&token_amount(amount, decimals)),
+            ParamValue::BaseUnits(amount) => write!(f, "{} base units", amount),
+            ParamValue::Seconds(seconds) => f.write_str(&duration(seconds)),
+            ParamValue::Timestamp(timestamp) => f.write_str(&utc_time(timestamp)),
+            ParamValue::Count(count) => write!(f, "{}", count),
+            ParamValue::Address(address) => f.write_str(&base58(address.as_ref())),
+            ParamValue::Credential(hash) => {
+                // Enough of the hash to tell a user's passkeys apart
+                hash.iter().take(4).try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+        }
+    }
+}
+
+/// `amount` smallest units as a decimal number of tokens, trailing zeros trimmed
+fn token_amount(amount: u64, decimals: u8) -> String {
+    let digits = format!("{:0>width$}", amount, width = usize::from(decimals) + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - usize::from(decimals));
+    match fraction.trim_end_matches('0') {
+        "" => whole.to_string(),
+        fraction => format!("{}.{}", whole, fraction),
+    }
+}
+
+/// A length of time in the largest unit that divides it: "2 days", "90 seconds"
+fn duration(seconds: u32) -> String {
+    let (count, unit) = [(SECONDS_PER_DAY, "day"), (3_600, "hour"), (60, "minute")]
+        .into_iter()
+        .find(|(unit_seconds, _)| seconds >= *unit_seconds && seconds.is_multiple_of(*unit_seconds))
+        .map_or((seconds, "second"), |(unit_seconds, unit)| (seconds / unit_seconds, unit));
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+/// A Unix timestamp as a UTC date and time: "2033-05-18 03:33:20 UTC"
+fn utc_time(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(i64::from(SECONDS_PER_DAY));
+    let seconds = timestamp.rem_euclid(i64::from(SECONDS_PER_DAY));
+
+    // Days since 1970-01-01 to a proleptic Gregorian date, counted in
+    // 400-year eras from 0000-03-01 so leap days fall at the end of a year
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
+/// Bytes in the base58 alphabet Solana writes addresses in
+fn base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    // Base-58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in bytes {
+        let mut carry = u32::from(*byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    let encoded = digits.iter().rev().map(|digit| ALPHABET.get(usize::from(*digit)).map_or('1', |c| char::from(*c)));
+    std::iter::repeat_n('1', leading_zeros).chain(encoded).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{CredentialBinding, CredentialBindings, DestinationLimit, DestinationLimits, MintLimit, MintLimits};
+
+    fn key(n: u8) -> Pubkey {
+        Pubkey::new_from_array([n; 32])
+    }
+
+    fn usdc_limits(allow_unlisted: bool) -> MintLimits {
+        MintLimits {
+            allow_unlisted,
+            limits: vec![MintLimit { mint: key(0), max_amount: 250_500_000, decimals: 6 }],
+        }
+    }
+
+    #[test]
+    fn test_every_policy_type_explains() {
+        let binding = CredentialBindings {
+            bindings: vec![
+                CredentialBinding { destinations: vec![key(1), key(2)], credential_id_hash: Some([0xab; 32]) },
+                CredentialBinding { destinations: vec![key(3)], credential_id_hash: None },
+            ],
+            default: Some([0xcd; 32]),
+        };
+        let destination_limits = DestinationLimits {
+            limits: vec![
+                DestinationLimit { destination: key(0), max_amount: Some(5_000) },
+                DestinationLimit { destination: key(0), max_amount: None },
+            ],
+            default_max_amount: 1_000,
+            window_seconds: 3_600,
+            anchor_timestamp: 1_700_000_000,
+        };
+        let cases = [
+            (Policy::open(), "Any transaction is allowed"),
+            (
+                Policy::spending_limit(Amount::from_lamports(1_000_000_000)),
+                "You can spend up to 1.00 SOL per transaction\n  - Tokens can't be sent",
+            ),
+            (
+                Policy::spending_limit(Amount::from_lamports(1_500_000_001)).with_mint_limits(usdc_limits(false)),
+                "You can spend up to 1.500000001 SOL per transaction\n\
+                 \x20 - Up to 250.5 of token 11111111111111111111111111111111 per transfer\n\
+                 \x20 - Other tokens can't be sent",
+            ),
+            (
+                Policy::daily_limit(Amount::from_lamports(10_000_000_000), 1_700_000_000).with_mint_limits(usdc_limits(true)),
+                "You can spend up to 10.00 SOL per day\n\
+                 \x20 - Up to 250.5 of token 11111111111111111111111111111111 per transfer\n\
+                 \x20 - Other tokens have no limit",
+            ),
+            (
+                Policy::windowed_limit(Amount::from_lamports(20_000_000), 7 * 86_400, 1_700_000_000),
+                "You can spend up to 0.02 SOL every 7 days\n  - Tokens can't be sent",
+            ),
+            (Policy::multi_sig(vec![key(1), key(2)]), "Every transaction needs all 2 required signers to approve it"),
+            (Policy::time_locked(2_000_000_000), "Nothing can be sent before 2033-05-18 03:33:20 UTC"),
+            (
+                Policy::destination_allowlist(vec![key(0)]),
+                "Transfers can only go to these 1 addresses\n  - 11111111111111111111111111111111",
+            ),
+            (
+                Policy::credential_binding(binding),
+                "Some transfers need a particular passkey\n\
+                 \x20 - Transfers to 2 listed addresses need passkey abababab\n\
+                 \x20 - Transfers to 1 listed addresses can be signed by any passkey\n\
+                 \x20 - Everything else needs passkey cdcdcdcd",
+            ),
+            (
+                Policy::per_destination_limit(destination_limits),
+                "Each address can be sent up to 1000 base units every 1 hour\n\
+                 \x20 - 11111111111111111111111111111111 can be sent up to 5000 base units every 1 hour\n\
+                 \x20 - 11111111111111111111111111111111 has no limit",
+            ),
+            (
+                Policy::vault(Amount::from_lamports(10_000_000_000), 2 * 86_400),
+                "Transfers over 10.00 SOL wait 2 days after they're announced, and any passkey can veto them",
+            ),
+        ];
+        for (policy, expected) in cases {
+            let explanation = policy.explain();
+            assert_eq!(explanation.kind, ExplanationKind::Policy(policy.policy_type));
+            assert_eq!(explanation.to_string(), expected);
+            assert!(english_template(explanation.template_key()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_composite_explains_its_rules_in_order() {
+        let policy = Policy::composite(vec![
+            Policy::vault(Amount::from_lamports(90), 90),
+            Policy::spending_limit(Amount::from_lamports(1_000_000_000)).with_mint_limits(usdc_limits(false)),
+            Policy::time_locked(1_700_000_000),
+        ]);
+        let explanation = policy.explain();
+        assert_eq!(explanation.template_key(), "policy.composite");
+        let keys: Vec<&str> = explanation.parts.iter().map(PolicyExplanation::template_key).collect();
+        assert_eq!(keys, ["policy.vault", "policy.spending_limit", "policy.time_locked"]);
+        assert_eq!(
+            explanation.to_string(),
+            "Every one of these must allow a transaction\n\
+             \x20 - Transfers over 0.00000009 SOL wait 90 seconds after they're announced, and any passkey can veto them\n\
+             \x20 - You can spend up to 1.00 SOL per transaction\n\
+             \x20   - Up to 250.5 of token 11111111111111111111111111111111 per transfer\n\
+             \x20   - Other tokens can't be sent\n\
+             \x20 - Nothing can be sent before 2023-11-14 22:13:20 UTC"
+        );
+    }
+
+    #[test]
+    fn test_unreadable_config_explains_as_denying_everything() {
+        let composite = Policy::composite(vec![Policy::open(), Policy { policy_type: PolicyType::TimeLocked, config: vec![1, 2] }]);
+        let explanation = composite.explain();
+        assert_eq!(explanation.parts[0].kind, ExplanationKind::Policy(PolicyType::Open));
+        assert_eq!(explanation.parts[1].kind, ExplanationKind::Malformed(PolicyType::TimeLocked));
+
+        let unreadable = Policy { policy_type: PolicyType::Composite, config: vec![9] };
+        assert_eq!(unreadable.explain().to_string(), "This policy can't be read, so it denies every transaction");
+    }
+
+    #[test]
+    fn test_localized_templates_take_the_same_params() {
+        let policy = Policy::spending_limit(Amount::from_lamports(1_000_000_000)).with_mint_limits(usdc_limits(true));
+        let explanation = policy.explain();
+        assert_eq!(explanation.param("max_amount"), Some(ParamValue::Sol(Amount::from_lamports(1_000_000_000))));
+
+        // Keys without a translation fall back to English
+        let french = |key: &str| match key {
+            "policy.spending_limit" => Some("Jusqu'à {max_amount} par transaction"),
+            "policy.mint_limit" => Some("Jusqu'à {max_amount} du jeton {mint} par transfert"),
+            _ => None,
+        };
+        assert_eq!(
+            explanation.render_with(&french),
+            "Jusqu'à 1.00 SOL par transaction\n\
+             \x20 - Jusqu'à 250.5 du jeton 11111111111111111111111111111111 par transfert\n\
+             \x20 - Other tokens have no limit"
+        );
+    }
+
+    #[test]
+    fn test_param_formatting() {
+        assert_eq!(token_amount(0, 6), "0");
+        assert_eq!(token_amount(1, 6), "0.000001");
+        assert_eq!(token_amount(42, 0), "42");
+        assert_eq!(token_amount(u64::MAX, 30), "0.000000000018446744073709551615");
+        assert_eq!(duration(0), "0 seconds");
+        assert_eq!(duration(1), "1 second");
+        assert_eq!(duration(90), "90 seconds");
+        assert_eq!(duration(5_400), "90 minutes");
+        assert_eq!(duration(86_400), "1 day");
+        assert_eq!(utc_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc_time(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(utc_time(-1), "1969-12-31 23:59:59 UTC");
+        assert_eq!(base58(&[0; 32]), "11111111111111111111111111111111");
+        assert_eq!(base58(&[0, 0, 1]), "112");
+        assert_eq!(base58(&[255]), "5Q");
+    }
+}
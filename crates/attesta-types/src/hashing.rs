@@ -0,0 +1,196 @@
+//! Domain-separated hashing
+//!
+//! Every hash Attesta defines over its own data is SHA-256 of a fixed
+//! domain prefix followed by the payload. Each `Domain` has its own prefix,
+//! and no prefix starts another, so two hashes from different domains can't
+//! be equal however their payloads are chosen: a challenge can't stand in
+//! for a policy hash, or an action for a transaction.
+//!
+//! Prefixes are frozen once shipped, since signatures and stored hashes
+//! depend on them. The ones without a version suffix are the first version
+//! of their domain, kept exactly as they were first written. A domain whose
+//! payload has to change gets a new variant with the next version in its
+//! prefix, and the old one stays here so existing hashes still verify.
+//!
+//! Hashes defined by someone else stay plain SHA-256: WebAuthn's RP ID,
+//! origin and client data hashes, and Anchor's discriminators. So do the
+//! identifiers accounts store and look things up by (credential ID,
+//! destination and memo hashes), and the backup key derived from a recovery
+//! phrase that may have been written down long ago.
+//!
+//! `test-vectors/domain_hashes.txt` pins every domain's hash of a fixed
+//! payload, for other implementations to check against.
+
+use sha2::{Digest, Sha256};
+
+/// What a hash is of; each has its own prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Domain {
+    /// The challenge a passkey signs: account owner, nonce, message hash
+    ExecChallenge,
+
+    /// The message hash of a transaction's data
+    Transaction,
+
+    /// The message hash of a transaction's data and memo
+    MemoTransaction,
+
+    /// The message hash of a management action and its payload
+    Action,
+
+    /// A policy in canonical form
+    PolicyCanonical,
+
+    /// An account's stored bytes, as exported for support tooling
+    StateSnapshot,
+
+    /// A recovery's new passkey, as guardians approve it
+    RecoveryRequest,
+
+    /// A program version and its features, as owners pin it
+    ProgramVersion,
+
+    /// A challenge, for the display code users compare
+    DisplayCode,
+
+    /// An account and nonce, deciding whether an allowed execution is sampled
+    AllowedSample,
+
+    /// A sign-in payload, for the challenge the passkey signs
+    SignIn,
+
+    /// A challenge, for the idempotency key the SDK derives from it
+    IdempotencyKey,
+
+    /// A backup's encryption key, for checking the key before opening it
+    BackupKey,
+
+    /// A backup's encryption key and creation time, for its nonce
+    BackupNonce,
+
+    /// A message, time and user, for a one-off replay nonce
+    ReplayNonce,
+}
+
+impl Domain {
+    /// Every domain, in the order they're listed above
+    pub const ALL: [Domain; 15] = [
+        Domain::ExecChallenge,
+        Domain::Transaction,
+        Domain::MemoTransaction,
+        Domain::Action,
+        Domain::PolicyCanonical,
+        Domain::StateSnapshot,
+        Domain::RecoveryRequest,
+        Domain::ProgramVersion,
+        Domain::DisplayCode,
+        Domain::AllowedSample,
+        Domain::SignIn,
+        Domain::IdempotencyKey,
+        Domain::BackupKey,
+        Domain::BackupNonce,
+        Domain::ReplayNonce,
+    ];
+
+    /// The bytes hashed in front of this domain's payloads
+    pub const fn prefix(self) -> &'static [u8] {
+        match self {
+            Domain::ExecChallenge => b"attesta-challenge",
+            Domain::Transaction => b"attesta-transaction",
+            Domain::MemoTransaction => b"attesta-memo-transaction",
+            Domain::Action => b"attesta-action",
+            Domain::PolicyCanonical => b"attesta-policy-v1",
+            Domain::StateSnapshot => b"attesta-state-snapshot-v1",
+            Domain::RecoveryRequest => b"attesta-recovery",
+            Domain::ProgramVersion => b"attesta-program-version",
+            Domain::DisplayCode => b"attesta-display-code",
+            Domain::AllowedSample => b"attesta-allowed-sample-v1",
+            Domain::SignIn => b"attesta-siwa",
+            Domain::IdempotencyKey => b"attesta-idempotency",
+            Domain::BackupKey => b"attesta-backup-key-v1",
+            Domain::BackupNonce => b"attesta-backup-nonce-v1",
+            Domain::ReplayNonce => b"attesta-replay-nonce-v1",
+        }
+    }
+
+    /// The name used in the test vectors
+    pub const fn name(self) -> &'static str {
+        match self {
+            Domain::ExecChallenge => "exec_challenge",
+            Domain::Transaction => "transaction",
+            Domain::MemoTransaction => "memo_transaction",
+            Domain::Action => "action",
+            Domain::PolicyCanonical => "policy_canonical",
+            Domain::StateSnapshot => "state_snapshot",
+            Domain::RecoveryRequest => "recovery_request",
+            Domain::ProgramVersion => "program_version",
+            Domain::DisplayCode => "display_code",
+            Domain::AllowedSample => "allowed_sample",
+            Domain::SignIn => "sign_in",
+            Domain::IdempotencyKey => "idempotency_key",
+            Domain::BackupKey => "backup_key",
+            Domain::BackupNonce => "backup_nonce",
+            Domain::ReplayNonce => "replay_nonce",
+        }
+    }
+}
+
+/// SHA-256 of `domain`'s prefix followed by `payload`
+pub fn domain_hash(domain: Domain, payload: &[u8]) -> [u8; 32] {
+    domain_hasher(domain).chain_update(payload).finalize().into()
+}
+
+/// A hasher that has already taken `domain`'s prefix
+///
+/// For payloads made of several parts, which can then be fed in one at a
+/// time instead of being concatenated first.
+pub fn domain_hasher(domain: Domain) -> Sha256 {
+    Sha256::new_with_prefix(domain.prefix())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS: &str = include_str!("../test-vectors/domain_hashes.txt");
+
+    /// The payload every vector hashes
+    const VECTOR_PAYLOAD: &[u8] = b"attesta";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_domain_vectors() {
+        let vectors: Vec<(&str, &str)> = VECTORS
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split_once(' ').unwrap())
+            .collect();
+        assert_eq!(vectors.len(), Domain::ALL.len());
+        for (domain, (name, hash)) in Domain::ALL.into_iter().zip(vectors) {
+            assert_eq!(domain.name(), name);
+            assert_eq!(hex(&domain_hash(domain, VECTOR_PAYLOAD)), hash, "{} changed", name);
+        }
+    }
+
+    #[test]
+    fn test_domains_never_collide() {
+        for (i, a) in Domain::ALL.into_iter().enumerate() {
+            for b in Domain::ALL.into_iter().skip(i + 1) {
+                // No prefix starts another, so no payload can make up the difference
+                assert!(!a.prefix().starts_with(b.prefix()) && !b.prefix().starts_with(a.prefix()), "{:?} and {:?}", a, b);
+                assert_ne!(domain_hash(a, VECTOR_PAYLOAD), domain_hash(b, VECTOR_PAYLOAD));
+                assert_ne!(domain_hash(a, &[]), domain_hash(b, &[]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hasher_matches_one_shot() {
+        let streamed: [u8; 32] = domain_hasher(Domain::Action).chain_update(b"att").chain_update(b"esta").finalize().into();
+        assert_eq!(streamed, domain_hash(Domain::Action, VECTOR_PAYLOAD));
+        assert_eq!(domain_hash(Domain::Action, VECTOR_PAYLOAD), <[u8; 32]>::from(Sha256::digest(b"attesta-actionattesta")));
+    }
+}
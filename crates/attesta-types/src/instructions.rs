@@ -0,0 +1,241 @@
+//! Instruction data for `initialize`, `execute` and `update_policy`
+//!
+//! Anchor identifies an instruction by the first 8 bytes of
+//! `sha256("global:<name>")` and follows them with the instruction's
+//! arguments, Borsh-encoded in the order the handler declares them. The
+//! encoders here build exactly those bytes, so a backend that can't take
+//! `anchor-client` can still send the program's main instructions; the
+//! decoders read them back out of transactions for the log and replay
+//! tooling.
+//!
+//! Accounts aren't covered: each instruction's account list is in the
+//! program's `#[derive(Accounts)]` structs and the SDK's builders.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use crate::envelope::IdempotencyKey;
+
+/// Bytes of an Anchor instruction discriminator
+pub const DISCRIMINATOR_LEN: usize = 8;
+
+/// `sha256("global:initialize")[..8]`
+pub const INITIALIZE_DISCRIMINATOR: [u8; DISCRIMINATOR_LEN] = [175, 175, 109, 31, 13, 152, 155, 237];
+
+/// `sha256("global:execute")[..8]`
+pub const EXECUTE_DISCRIMINATOR: [u8; DISCRIMINATOR_LEN] = [130, 221, 242, 154, 13, 193, 189, 29];
+
+/// `sha256("global:update_policy")[..8]`
+pub const UPDATE_POLICY_DISCRIMINATOR: [u8; DISCRIMINATOR_LEN] = [212, 245, 246, 7, 163, 151, 18, 57];
+
+/// Errors from decoding instruction data
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InstructionDataError {
+    #[error("Instruction data is shorter than a discriminator")]
+    TooShort,
+
+    #[error("Not an {0} instruction")]
+    WrongInstruction(&'static str),
+
+    #[error("Invalid {0} arguments")]
+    InvalidArguments(&'static str),
+}
+
+/// The Anchor discriminator of the instruction called `name`
+pub fn instruction_discriminator(name: &str) -> [u8; DISCRIMINATOR_LEN] {
+    let hash = Sha256::new().chain_update(b"global:").chain_update(name.as_bytes()).finalize();
+    let mut discriminator = [0u8; DISCRIMINATOR_LEN];
+    discriminator.iter_mut().zip(hash.iter()).for_each(|(out, byte)| *out = *byte);
+    discriminator
+}
+
+/// Arguments of `initialize`, in the order the program takes them
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InitializeArgs {
+    /// The first passkey's uncompressed P-256 public key (x || y)
+    pub passkey_public_key: [u8; 64],
+
+    /// The first passkey's WebAuthn credential ID
+    pub credential_id: Vec<u8>,
+
+    /// The serialized `Policy` (empty for an open account)
+    pub policy: Vec<u8>,
+
+    /// Store only the hash of the credential ID
+    pub privacy_mode: bool,
+
+    /// The serialized `WebAuthnSignature` over the registration challenge
+    pub registration_sig: Vec<u8>,
+
+    /// Authenticator models allowed to enroll (empty for any)
+    pub aaguid_allowlist: Vec<[u8; 16]>,
+}
+
+/// Arguments of `execute`, in the order the program takes them
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExecuteArgs {
+    /// The serialized `WebAuthnSignature`
+    pub webauthn_sig: Vec<u8>,
+
+    pub nonce: u64,
+
+    /// `transaction_message_hash` of `transaction_data`
+    pub message_hash: [u8; 32],
+
+    pub transaction_data: Vec<u8>,
+
+    pub idempotency_key: Option<IdempotencyKey>,
+
+    /// The payment memo the passkey signed (empty for none)
+    pub memo: Vec<u8>,
+
+    /// Also post the memo through SPL Memo
+    pub emit_memo: bool,
+}
+
+/// Arguments of `update_policy`, in the order the program takes them
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UpdatePolicyArgs {
+    /// The serialized `Policy` (empty for no restrictions)
+    pub new_policy: Vec<u8>,
+
+    /// The account's `state_version` as last read
+    pub expected_version: u64,
+}
+
+/// `initialize` instruction data for `args`
+pub fn encode_initialize(args: &InitializeArgs) -> Result<Vec<u8>, InstructionDataError> {
+    encode("initialize", &INITIALIZE_DISCRIMINATOR, args)
+}
+
+/// `execute` instruction data for `args`
+pub fn encode_execute(args: &ExecuteArgs) -> Result<Vec<u8>, InstructionDataError> {
+    encode("execute", &EXECUTE_DISCRIMINATOR, args)
+}
+
+/// `update_policy` instruction data for `args`
+pub fn encode_update_policy(args: &UpdatePolicyArgs) -> Result<Vec<u8>, InstructionDataError> {
+    encode("update_policy", &UPDATE_POLICY_DISCRIMINATOR, args)
+}
+
+/// The arguments of `initialize` instruction data
+pub fn decode_initialize(data: &[u8]) -> Result<InitializeArgs, InstructionDataError> {
+    decode("initialize", &INITIALIZE_DISCRIMINATOR, data)
+}
+
+/// The arguments of `execute` instruction data
+pub fn decode_execute(data: &[u8]) -> Result<ExecuteArgs, InstructionDataError> {
+    decode("execute", &EXECUTE_DISCRIMINATOR, data)
+}
+
+/// The arguments of `update_policy` instruction data
+pub fn decode_update_policy(data: &[u8]) -> Result<UpdatePolicyArgs, InstructionDataError> {
+    decode("update_policy", &UPDATE_POLICY_DISCRIMINATOR, data)
+}
+
+/// Borsh only fails to encode lengths past `u32::MAX`
+fn encode<T: BorshSerialize>(
+    name: &'static str,
+    discriminator: &[u8; DISCRIMINATOR_LEN],
+    args: &T,
+) -> Result<Vec<u8>, InstructionDataError> {
+    let mut data = discriminator.to_vec();
+    args.serialize(&mut data).map_err(|_| InstructionDataError::InvalidArguments(name))?;
+    Ok(data)
+}
+
+fn decode<T: BorshDeserialize>(
+    name: &'static str,
+    discriminator: &[u8; DISCRIMINATOR_LEN],
+    data: &[u8],
+) -> Result<T, InstructionDataError> {
+    let (prefix, args) = data
+        .get(..DISCRIMINATOR_LEN)
+        .zip(data.get(DISCRIMINATOR_LEN..))
+        .ok_or(InstructionDataError::TooShort)?;
+    if prefix != discriminator {
+        return Err(InstructionDataError::WrongInstruction(name));
+    }
+    T::try_from_slice(args).map_err(|_| InstructionDataError::InvalidArguments(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute_args() -> ExecuteArgs {
+        ExecuteArgs {
+            webauthn_sig: vec![1; 70],
+            nonce: 7,
+            message_hash: [2; 32],
+            transaction_data: vec![3; 40],
+            idempotency_key: Some([4; 16]),
+            memo: b"order-1042".to_vec(),
+            emit_memo: true,
+        }
+    }
+
+    #[test]
+    fn test_discriminators_follow_anchors_rule() {
+        // What the IDL's instruction names hash to
+        assert_eq!(instruction_discriminator("initialize"), INITIALIZE_DISCRIMINATOR);
+        assert_eq!(instruction_discriminator("execute"), EXECUTE_DISCRIMINATOR);
+        assert_eq!(instruction_discriminator("update_policy"), UPDATE_POLICY_DISCRIMINATOR);
+        let full = Sha256::digest(b"global:execute");
+        assert_eq!(full.get(..DISCRIMINATOR_LEN), Some(&EXECUTE_DISCRIMINATOR[..]));
+    }
+
+    #[test]
+    fn test_arguments_are_the_handlers_in_order() {
+        // A struct encodes like the tuple of its fields, which is what Anchor writes
+        let args = execute_args();
+        let tuple = (
+            args.webauthn_sig.clone(),
+            args.nonce,
+            args.message_hash,
+            args.transaction_data.clone(),
+            args.idempotency_key,
+            args.memo.clone(),
+            args.emit_memo,
+        );
+        let data = encode_execute(&args).unwrap();
+        assert_eq!(data[..DISCRIMINATOR_LEN], EXECUTE_DISCRIMINATOR);
+        assert_eq!(data[DISCRIMINATOR_LEN..], borsh::to_vec(&tuple).unwrap());
+
+        let args = UpdatePolicyArgs { new_policy: vec![4, 8, 0, 0, 0], expected_version: 3 };
+        let data = encode_update_policy(&args).unwrap();
+        assert_eq!(data[DISCRIMINATOR_LEN..], borsh::to_vec(&(args.new_policy.clone(), 3u64)).unwrap());
+    }
+
+    #[test]
+    fn test_decode_reverses_encode() {
+        let args = execute_args();
+        assert_eq!(decode_execute(&encode_execute(&args).unwrap()), Ok(args));
+
+        let args = InitializeArgs {
+            passkey_public_key: [5; 64],
+            credential_id: b"phone".to_vec(),
+            policy: Vec::new(),
+            privacy_mode: true,
+            registration_sig: vec![6; 70],
+            aaguid_allowlist: vec![[7; 16]],
+        };
+        assert_eq!(decode_initialize(&encode_initialize(&args).unwrap()), Ok(args));
+
+        let args = UpdatePolicyArgs { new_policy: Vec::new(), expected_version: 0 };
+        assert_eq!(decode_update_policy(&encode_update_policy(&args).unwrap()), Ok(args));
+    }
+
+    #[test]
+    fn test_decode_checks_the_instruction() {
+        let data = encode_execute(&execute_args()).unwrap();
+        assert_eq!(decode_update_policy(&data), Err(InstructionDataError::WrongInstruction("update_policy")));
+        assert_eq!(decode_execute(&data[..4]), Err(InstructionDataError::TooShort));
+        assert_eq!(decode_execute(&data[..20]), Err(InstructionDataError::InvalidArguments("execute")));
+
+        // Trailing bytes aren't part of any argument
+        let mut padded = data.clone();
+        padded.push(0);
+        assert_eq!(decode_execute(&padded), Err(InstructionDataError::InvalidArguments("execute")));
+    }
+}
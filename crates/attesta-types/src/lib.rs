@@ -0,0 +1,198 @@
+//! Attesta data layouts, shared by the program, the SDK and off-chain services
+//!
+//! Everything here is plain data and its serialization: policies, amounts,
+//! passkey entries, transaction requests, WebAuthn signatures and proof
+//! envelopes, the bounds client-supplied timestamps are checked against,
+//! and the instruction data of `initialize`, `execute` and `update_policy`
+//! (see `instructions`). `Policy::explain` describes a policy for end users
+//! (see `explain`). Nothing depends on the Solana runtime, so a backend can read
+//! and build Attesta data without `solana-program` or `anchor-lang`. The
+//! program's structured log lines are defined here too (see `log`), and so
+//! is the domain-separated hashing everything else hashes through (see
+//! `hashing`), and the bounds-checked field readers every parser here
+//! uses (see `bytes`). Enable
+//! the `solana` feature to use `solana_program`'s `Pubkey` for addresses;
+//! the on-chain crates do, and re-export these types from their usual paths.
+//!
+//! The encodings match what's already stored on-chain byte for byte
+//! (see `test-vectors/serialization.txt`).
+
+// On-chain code must not panic on attacker-controlled input
+#![cfg_attr(not(test), deny(clippy::indexing_slicing, clippy::unwrap_used, clippy::expect_used))]
+
+pub mod amount;
+pub mod bytes;
+pub mod consts;
+pub mod envelope;
+pub mod explain;
+pub mod hashing;
+pub mod instructions;
+pub mod log;
+pub mod passkey;
+pub mod policy;
+pub mod pubkey;
+pub mod time;
+pub mod transaction;
+pub mod webauthn;
+
+pub use amount::{Amount, AmountError, LAMPORTS_PER_SOL};
+pub use bytes::ParseError;
+pub use envelope::{IdempotencyKey, ProofEnvelope, IDEMPOTENCY_KEY_LEN};
+pub use explain::{english_template, ExplanationKind, ExplanationParam, ParamValue, PolicyExplanation};
+pub use hashing::{domain_hash, domain_hasher, Domain};
+pub use instructions::{
+    decode_execute, decode_initialize, decode_update_policy, encode_execute, encode_initialize, encode_update_policy,
+    ExecuteArgs, InitializeArgs, InstructionDataError, UpdatePolicyArgs,
+};
+pub use log::{format_log_line, LOG_CODES, LOG_PREFIX, MAX_LOG_FIELDS};
+pub use passkey::{CredentialIdStorage, PasskeyEntry};
+pub use policy::{
+    CredentialBinding, CredentialBindings, DailyLimitConfig, DestinationLimit, DestinationLimits, DestinationSpend,
+    DestinationSpends, LimitSpend, MintLimit, MintLimits, Policy, PolicyBuildError, PolicyBuilder, PolicyContext, PolicyError,
+    PolicyType,
+};
+pub use pubkey::Pubkey;
+pub use time::{validate_timestamp, TimeError, MAX_CLOCK_SKEW_SECONDS, MAX_FUTURE_SECONDS, MAX_PAST_SECONDS};
+pub use transaction::{
+    check_memo, memo_hash, transaction_memo_message_hash, transaction_message_hash, TokenTransfer, TransactionRequest,
+    TransactionRequestError, MAX_MEMO_LEN, MAX_TRANSACTION_DATA_LEN,
+};
+pub use webauthn::{SignatureFormatError, WebAuthnSignature};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS: &str = include_str!("../test-vectors/serialization.txt");
+
+    fn key(n: u8) -> Pubkey {
+        Pubkey::new_from_array([n; 32])
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn vector(name: &str) -> &'static str {
+        VECTORS
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no test vector named {}", name))
+    }
+
+    /// A `DailyLimit` in the layout written before limits had windows
+    #[allow(deprecated)]
+    fn legacy_daily_limit(max_amount: u64, reset_timestamp: i64) -> Policy {
+        let config = [max_amount.to_le_bytes(), reset_timestamp.to_le_bytes()].concat();
+        Policy::new(PolicyType::DailyLimit, config)
+    }
+
+    fn token_transfer() -> TokenTransfer {
+        TokenTransfer { mint: key(6), amount: 1_500_000, decimals: 6, destination_ata: key(7) }
+    }
+
+    #[test]
+    fn test_policy_vectors() {
+        let mint_limits = MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint: key(1), max_amount: 100_000_000, decimals: 6 }],
+        };
+        let cases = [
+            ("policy_open", Policy::open()),
+            ("policy_spending_limit", Policy::spending_limit(Amount::from_lamports(1_000_000_000))),
+            ("policy_daily_limit", Policy::daily_limit(Amount::from_lamports(5_000_000_000), 1_700_000_000)),
+            ("policy_daily_limit_legacy", legacy_daily_limit(5_000_000_000, 1_700_000_000)),
+            ("policy_mint_limits", Policy::spending_limit(Amount::ZERO).with_mint_limits(mint_limits)),
+            ("policy_multi_sig", Policy::multi_sig(vec![key(2), key(3)])),
+            ("policy_time_locked", Policy::time_locked(1_800_000_000)),
+            ("policy_destination_allowlist", Policy::destination_allowlist(vec![key(4)])),
+            (
+                "policy_credential_binding",
+                Policy::credential_binding(CredentialBindings {
+                    bindings: vec![CredentialBinding { destinations: vec![key(5)], credential_id_hash: None }],
+                    default: Some([8; 32]),
+                }),
+            ),
+            (
+                "policy_composite",
+                Policy::composite(vec![Policy::spending_limit(Amount::from_lamports(7)), Policy::time_locked(1_800_000_000)]),
+            ),
+        ];
+
+        for (name, policy) in cases {
+            let bytes = policy.to_bytes().unwrap();
+            assert_eq!(hex(&bytes), vector(name), "{}", name);
+            assert_eq!(Policy::from_bytes(&bytes).unwrap(), policy, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_passkey_entry_vector() {
+        let entry = PasskeyEntry::new([5; 64], b"phone".to_vec(), "Phone".to_string(), 1_700_000_000);
+        let bytes = borsh::to_vec(&entry).unwrap();
+        assert_eq!(hex(&bytes), vector("passkey_entry"));
+
+        let decoded: PasskeyEntry = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(decoded.name_str().unwrap(), "Phone");
+        assert_eq!(decoded.added_at, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_transaction_vectors() {
+        let data = token_transfer().to_transaction_data();
+        assert_eq!(hex(&data), vector("token_transfer"));
+        assert_eq!(TokenTransfer::from_transaction_data(&data), Some(token_transfer()));
+
+        assert_eq!(
+            hex(&TransactionRequest::new(b"transfer 1 SOL".to_vec()).message_hash()),
+            vector("transaction_message_hash")
+        );
+        assert_eq!(
+            hex(&TransactionRequest::from_token_transfer(token_transfer()).message_hash()),
+            vector("token_transfer_message_hash")
+        );
+    }
+
+    #[test]
+    fn test_instruction_vectors() {
+        let initialize = InitializeArgs {
+            passkey_public_key: [5; 64],
+            credential_id: b"phone".to_vec(),
+            policy: Policy::time_locked(1_800_000_000).to_bytes().unwrap(),
+            privacy_mode: false,
+            registration_sig: vec![8; 4],
+            aaguid_allowlist: vec![[9; 16]],
+        };
+        let execute = ExecuteArgs {
+            webauthn_sig: vec![8; 4],
+            nonce: 3,
+            message_hash: TransactionRequest::from_token_transfer(token_transfer()).message_hash(),
+            transaction_data: token_transfer().to_transaction_data(),
+            idempotency_key: Some([7; 16]),
+            memo: b"order-1042".to_vec(),
+            emit_memo: false,
+        };
+        let update_policy = UpdatePolicyArgs { new_policy: Policy::open().to_bytes().unwrap(), expected_version: 2 };
+
+        let cases = [
+            ("instruction_initialize", encode_initialize(&initialize).unwrap()),
+            ("instruction_execute", encode_execute(&execute).unwrap()),
+            ("instruction_update_policy", encode_update_policy(&update_policy).unwrap()),
+        ];
+        for (name, data) in &cases {
+            assert_eq!(&hex(data), vector(name), "{}", name);
+        }
+        assert_eq!(decode_initialize(&cases[0].1), Ok(initialize));
+        assert_eq!(decode_execute(&cases[1].1), Ok(execute));
+        assert_eq!(decode_update_policy(&cases[2].1), Ok(update_policy));
+    }
+
+    #[test]
+    fn test_webauthn_signature_vector() {
+        let sig = WebAuthnSignature::new(vec![8; 37], b"{}".to_vec(), vec![9; 64], b"phone".to_vec());
+        let bytes = sig.to_bytes();
+        assert_eq!(hex(&bytes), vector("webauthn_signature"));
+        assert_eq!(WebAuthnSignature::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+    }
+}
@@ -0,0 +1,236 @@
+//! Program log lines meant for indexers
+//!
+//! Free-text `msg!` wording changes from one release to the next, so the
+//! program also logs every outcome as one structured line: `LOG_PREFIX`,
+//! an event code from `codes`, then at most `MAX_LOG_FIELDS` `key=value`
+//! pairs, separated by single spaces:
+//!
+//! ```text
+//! ATST1 exec_ok nonce=42 amount=1000000 signer=passkey
+//! ```
+//!
+//! Codes and field names only ever get added. A change to the line format
+//! itself bumps the number in the prefix.
+
+use std::fmt::{Display, Write};
+
+/// First word of every structured log line
+pub const LOG_PREFIX: &str = "ATST1";
+
+/// Most `key=value` pairs one line carries
+///
+/// Each one costs compute to format on-chain; extras are dropped.
+pub const MAX_LOG_FIELDS: usize = 4;
+
+/// Event codes, the second word of a log line
+pub mod codes {
+    /// An account was created: `owner`, and `pool` if a sponsor pool paid
+    pub const ACCOUNT_INITIALIZED: &str = "account_init";
+
+    /// The code the wallet showed next to the passkey prompt: `code`
+    pub const DISPLAY_CODE: &str = "display_code";
+
+    /// A transaction ran: `nonce`, `amount` (0 unless a token transfer),
+    /// `signer` (`passkey`, `owner`, `schedule` or `announcement`)
+    pub const EXECUTED: &str = "exec_ok";
+
+    /// A retry of a transaction that already ran: `nonce`
+    pub const ALREADY_EXECUTED: &str = "exec_dup";
+
+    /// A transaction needs more approvals
+    pub const NEEDS_APPROVAL: &str = "exec_pending";
+
+    /// A transaction or claim was denied: `reason` (see `DenyReason`)
+    pub const DENIED: &str = "exec_denied";
+
+    /// A passkey signature didn't verify on a lockout account: `count` in a row
+    pub const AUTH_FAILED: &str = "auth_failed";
+
+    /// The account is locked out: `until`
+    pub const LOCKED_OUT: &str = "locked_out";
+
+    /// The instruction failed: `error`, the program error's name
+    pub const REJECTED: &str = "rejected";
+
+    /// The account's policies were replaced or changed: `count`
+    pub const POLICY_UPDATED: &str = "policy_set";
+
+    /// A policy attestation was written: `nonce`, `expires_at`
+    pub const POLICY_ATTESTED: &str = "policy_attested";
+
+    pub const BACKUP_STORED: &str = "backup_stored";
+
+    /// The escrowed backup was replaced: `account`, and `generation`, how
+    /// many times the backup has been rekeyed
+    pub const BACKUP_UPDATED: &str = "backup_updated";
+
+    pub const BACKUP_DELETED: &str = "backup_deleted";
+    pub const PROOF_LOG_ENABLED: &str = "proof_log_on";
+    pub const PRIVACY_MODE_ENABLED: &str = "privacy_on";
+    pub const SETTINGS_UPDATED: &str = "settings_set";
+
+    /// `mode`, `locked`
+    pub const AUTH_MODE_SET: &str = "auth_mode_set";
+
+    /// `executor`, `count` now listed
+    pub const EXECUTOR_ADDED: &str = "executor_added";
+
+    /// `executor`, `count` still listed (0 makes `execute` permissionless again)
+    pub const EXECUTOR_REMOVED: &str = "executor_removed";
+
+    /// The emergency override's limits were set: `max`, `period` (0 and 0 turn it off)
+    pub const EMERGENCY_OVERRIDE_SET: &str = "emergency_set";
+
+    /// A transaction ran past the account's time locks: `nonce`, `amount`,
+    /// `until` the override may run again
+    pub const EMERGENCY_OVERRIDE_USED: &str = "emergency_used";
+
+    pub const PASSKEY_ADDED: &str = "passkey_added";
+    pub const PASSKEY_REVOKED: &str = "passkey_revoked";
+
+    /// A sub-account was created: `sub_account`, `index`
+    pub const SUB_ACCOUNT_CREATED: &str = "sub_account_init";
+
+    pub const RECOVERY_INITIATED: &str = "recovery_start";
+
+    /// A recovery or drill approval was counted: `approvals`, `threshold`
+    pub const RECOVERY_APPROVED: &str = "recovery_approval";
+
+    pub const RECOVERY_FINALIZED: &str = "recovery_done";
+    pub const RECOVERY_CANCELLED: &str = "recovery_cancel";
+    pub const RECOVERY_DRILL_PASSED: &str = "drill_ok";
+    pub const INHERITANCE_CONFIGURED: &str = "inheritance_set";
+    pub const INHERITANCE_CLAIMED: &str = "inheritance_claim";
+    pub const HEARTBEAT: &str = "heartbeat";
+
+    /// The account accepted a program version: `version`
+    pub const UPGRADE_ACKNOWLEDGED: &str = "upgrade_ack";
+
+    /// A transaction was scheduled: `nonce`, `after`, `before`
+    pub const SCHEDULED: &str = "schedule_set";
+
+    /// A scheduled transaction's window passed, and its PDA was closed: `before`
+    pub const SCHEDULE_EXPIRED: &str = "schedule_expired";
+
+    pub const SCHEDULE_CANCELLED: &str = "schedule_cancel";
+
+    /// A passkey approved a proposal: `approvals` so far, `required`
+    pub const PROPOSAL_APPROVED: &str = "proposal_approval";
+
+    pub const PROPOSAL_CANCELLED: &str = "proposal_cancel";
+
+    /// A claim ticket was paid: `nonce`, `amount`, `to`
+    pub const CLAIM_PAID: &str = "claim_paid";
+
+    /// `authority`
+    pub const SPONSOR_POOL_CREATED: &str = "pool_created";
+
+    /// `amount`
+    pub const SPONSOR_POOL_WITHDRAWN: &str = "pool_withdraw";
+
+    /// A transfer over a vault's threshold was announced: `nonce`, `amount`, `at` it can run
+    pub const ANNOUNCED: &str = "exec_announced";
+
+    /// An announced transfer wasn't executed in time, and its PDA was closed: `expired`
+    pub const ANNOUNCEMENT_EXPIRED: &str = "announce_expired";
+
+    /// A passkey vetoed an announced transfer: `nonce` it was announced with
+    pub const ANNOUNCEMENT_VETOED: &str = "announce_veto";
+
+    /// The primary passkey signed an `execute` while a recovery to replace
+    /// it is pending, whatever came of it: `nonce`, `allowed`
+    pub const PRIMARY_ACTIVE_IN_RECOVERY: &str = "recovery_primary_active";
+}
+
+/// Every code in `codes`
+pub const LOG_CODES: &[&str] = &[
+    codes::ACCOUNT_INITIALIZED,
+    codes::DISPLAY_CODE,
+    codes::EXECUTED,
+    codes::ALREADY_EXECUTED,
+    codes::NEEDS_APPROVAL,
+    codes::DENIED,
+    codes::AUTH_FAILED,
+    codes::LOCKED_OUT,
+    codes::REJECTED,
+    codes::POLICY_UPDATED,
+    codes::POLICY_ATTESTED,
+    codes::BACKUP_STORED,
+    codes::BACKUP_UPDATED,
+    codes::BACKUP_DELETED,
+    codes::PROOF_LOG_ENABLED,
+    codes::PRIVACY_MODE_ENABLED,
+    codes::SETTINGS_UPDATED,
+    codes::AUTH_MODE_SET,
+    codes::EXECUTOR_ADDED,
+    codes::EXECUTOR_REMOVED,
+    codes::EMERGENCY_OVERRIDE_SET,
+    codes::EMERGENCY_OVERRIDE_USED,
+    codes::PASSKEY_ADDED,
+    codes::PASSKEY_REVOKED,
+    codes::SUB_ACCOUNT_CREATED,
+    codes::RECOVERY_INITIATED,
+    codes::RECOVERY_APPROVED,
+    codes::RECOVERY_FINALIZED,
+    codes::RECOVERY_CANCELLED,
+    codes::RECOVERY_DRILL_PASSED,
+    codes::INHERITANCE_CONFIGURED,
+    codes::INHERITANCE_CLAIMED,
+    codes::HEARTBEAT,
+    codes::UPGRADE_ACKNOWLEDGED,
+    codes::SCHEDULED,
+    codes::SCHEDULE_EXPIRED,
+    codes::SCHEDULE_CANCELLED,
+    codes::PROPOSAL_APPROVED,
+    codes::PROPOSAL_CANCELLED,
+    codes::CLAIM_PAID,
+    codes::SPONSOR_POOL_CREATED,
+    codes::SPONSOR_POOL_WITHDRAWN,
+    codes::ANNOUNCED,
+    codes::ANNOUNCEMENT_EXPIRED,
+    codes::ANNOUNCEMENT_VETOED,
+    codes::PRIMARY_ACTIVE_IN_RECOVERY,
+];
+
+/// Formats one structured log line
+///
+/// Only the first `MAX_LOG_FIELDS` fields are written. Whitespace in a
+/// value becomes `_`, so every value stays a single word.
+pub fn format_log_line(code: &str, fields: &[(&str, &dyn Display)]) -> String {
+    let mut line = format!("{} {}", LOG_PREFIX, code);
+    let mut value = String::new();
+    for (key, field) in fields.iter().take(MAX_LOG_FIELDS) {
+        value.clear();
+        // Writing to a String can't fail
+        let _ = write!(value, "{}", field);
+        let _ = write!(line, " {}=", key);
+        line.extend(value.chars().map(|c| if c.is_whitespace() { '_' } else { c }));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_log_line() {
+        assert_eq!(format_log_line(codes::EXECUTED, &[("nonce", &42), ("amount", &1_000_000)]), "ATST1 exec_ok nonce=42 amount=1000000");
+        assert_eq!(format_log_line(codes::HEARTBEAT, &[]), "ATST1 heartbeat");
+        assert_eq!(format_log_line(codes::REJECTED, &[("error", &"two words")]), "ATST1 rejected error=two_words");
+    }
+
+    #[test]
+    fn test_fields_are_capped() {
+        let line = format_log_line(codes::EXECUTED, &[("a", &1), ("b", &2), ("c", &3), ("d", &4), ("e", &5)]);
+        assert_eq!(line, "ATST1 exec_ok a=1 b=2 c=3 d=4");
+    }
+
+    #[test]
+    fn test_codes_are_distinct_words() {
+        for (i, code) in LOG_CODES.iter().enumerate() {
+            assert!(!code.is_empty() && code.chars().all(|c| c.is_ascii_lowercase() || c == '_'), "{}", code);
+            assert!(!LOG_CODES[..i].contains(code), "{} is listed twice", code);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! One registered passkey, as stored in an account's passkey registry
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use crate::consts::{AAGUID_LEN, BORSH_LEN_PREFIX, HASH_LEN, MAX_CREDENTIAL_ID_LEN, MAX_PASSKEY_NAME_LEN, P256_PUBKEY_LEN};
+
+/// How an entry's credential ID is kept on-chain
+///
+/// Some authenticators produce credential IDs of 200 bytes or more, and every
+/// stored byte costs rent. A `Hashed` entry keeps only the SHA-256 of the ID;
+/// the full ID stays with the client (and in the encrypted backup), and
+/// assertions are matched by hashing the ID they present.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CredentialIdStorage {
+    /// `credential_id` is the credential ID itself
+    #[default]
+    Full = 0,
+    /// `credential_id` is the SHA-256 of the credential ID
+    Hashed = 1,
+}
+
+impl CredentialIdStorage {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Full),
+            1 => Some(Self::Hashed),
+            _ => None,
+        }
+    }
+}
+
+/// Represents a single passkey entry in a multi-passkey setup
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct PasskeyEntry {
+    /// The P-256 public key from the passkey (64 bytes uncompressed)
+    pub public_key: [u8; P256_PUBKEY_LEN],
+    
+    /// The credential ID from WebAuthn, or its hash (see `id_storage`)
+    pub credential_id: Vec<u8>,
+    
+    /// A human-readable name/description for this passkey
+    pub name: Vec<u8>, // UTF-8 encoded string
+    
+    /// Whether this passkey is enabled
+    pub enabled: bool,
+    
+    /// Timestamp when this passkey was added
+    pub added_at: i64,
+
+    /// The authenticator model the passkey attested when it was enrolled,
+    /// if it was enrolled with attested credential data
+    ///
+    /// Not part of the entry's own encoding: `MultiPasskey` stores the
+    /// AAGUIDs after its other fields, so older registries still read.
+    #[borsh(skip)]
+    pub aaguid: Option<[u8; AAGUID_LEN]>,
+
+    /// Whether `credential_id` holds the full ID or only its hash
+    ///
+    /// Stored by `MultiPasskey` after the AAGUIDs, like `aaguid`.
+    #[borsh(skip)]
+    pub id_storage: CredentialIdStorage,
+}
+
+impl PasskeyEntry {
+    /// Length of the largest entry a registry accepts: a credential ID of
+    /// `MAX_CREDENTIAL_ID_LEN` and a name of `MAX_PASSKEY_NAME_LEN`
+    pub const MAX_SERIALIZED_SIZE: usize = Self::size_for(MAX_CREDENTIAL_ID_LEN, MAX_PASSKEY_NAME_LEN);
+
+    pub fn new(
+        public_key: [u8; P256_PUBKEY_LEN],
+        credential_id: Vec<u8>,
+        name: String,
+        added_at: i64,
+    ) -> Self {
+        Self {
+            public_key,
+            credential_id,
+            name: name.into_bytes(),
+            enabled: true,
+            added_at,
+            aaguid: None,
+            id_storage: CredentialIdStorage::Full,
+        }
+    }
+
+    /// An entry that stores only the hash of `credential_id`
+    pub fn new_hashed(
+        public_key: [u8; P256_PUBKEY_LEN],
+        credential_id: &[u8],
+        name: String,
+        added_at: i64,
+    ) -> Self {
+        Self {
+            credential_id: Sha256::digest(credential_id).to_vec(),
+            id_storage: CredentialIdStorage::Hashed,
+            ..Self::new(public_key, Vec::new(), name, added_at)
+        }
+    }
+
+    /// An entry that stores whichever form of `credential_id` is smaller
+    ///
+    /// IDs longer than a hash are stored hashed, shorter ones as they are.
+    pub fn compact(
+        public_key: [u8; P256_PUBKEY_LEN],
+        credential_id: Vec<u8>,
+        name: String,
+        added_at: i64,
+    ) -> Self {
+        if credential_id.len() > HASH_LEN {
+            Self::new_hashed(public_key, &credential_id, name, added_at)
+        } else {
+            Self::new(public_key, credential_id, name, added_at)
+        }
+    }
+
+    /// Whether an assertion's credential ID belongs to this entry
+    pub fn matches(&self, credential_id: &[u8]) -> bool {
+        match self.id_storage {
+            CredentialIdStorage::Full => self.credential_id == credential_id,
+            CredentialIdStorage::Hashed => self.credential_id == <[u8; HASH_LEN]>::from(Sha256::digest(credential_id)),
+        }
+    }
+
+    /// SHA-256 of the entry's credential ID, whichever form is stored
+    pub fn credential_id_hash(&self) -> [u8; HASH_LEN] {
+        match self.id_storage {
+            CredentialIdStorage::Hashed => self.credential_id.as_slice().try_into().unwrap_or_default(),
+            CredentialIdStorage::Full => Sha256::digest(&self.credential_id).into(),
+        }
+    }
+
+    /// The same entry, recording the authenticator model it attested
+    pub fn with_aaguid(mut self, aaguid: Option<[u8; AAGUID_LEN]>) -> Self {
+        self.aaguid = aaguid;
+        self
+    }
+
+    pub fn name_str(&self) -> Result<String, std::string::FromUtf8Error> {
+        String::from_utf8(self.name.clone())
+    }
+
+    /// Length of the entry's Borsh encoding, without serializing
+    ///
+    /// Doesn't include the AAGUID or the storage tag (see `aaguid`).
+    pub fn serialized_size(&self) -> usize {
+        Self::size_for(self.credential_id.len(), self.name.len())
+    }
+
+    /// Length of an entry storing `credential_id_len` and `name_len` bytes
+    const fn size_for(credential_id_len: usize, name_len: usize) -> usize {
+        // public_key (64) + credential_id (4 + len) + name (4 + len) + enabled (1) + added_at (8)
+        P256_PUBKEY_LEN + BORSH_LEN_PREFIX + credential_id_len + BORSH_LEN_PREFIX + name_len + 1 + 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialized_size_matches_borsh() {
+        for entry in [
+            PasskeyEntry::new([1; 64], vec![], String::new(), 0),
+            PasskeyEntry::new([2; 64], vec![3; 255], "Hardware key".to_string(), 1_700_000_000).with_aaguid(Some([4; 16])),
+        ] {
+            assert_eq!(entry.serialized_size(), borsh::to_vec(&entry).unwrap().len());
+        }
+
+        let largest = PasskeyEntry::new([5; 64], vec![6; MAX_CREDENTIAL_ID_LEN], "k".repeat(MAX_PASSKEY_NAME_LEN), 0);
+        assert_eq!(borsh::to_vec(&largest).unwrap().len(), PasskeyEntry::MAX_SERIALIZED_SIZE);
+    }
+
+    #[test]
+    fn test_hashed_entry_matches_the_full_id() {
+        let long_id = vec![7u8; 240];
+        let full = PasskeyEntry::new([1; 64], long_id.clone(), "Key".to_string(), 0);
+        let hashed = PasskeyEntry::new_hashed([1; 64], &long_id, "Key".to_string(), 0);
+
+        assert_eq!(hashed.id_storage, CredentialIdStorage::Hashed);
+        assert_eq!(hashed.credential_id.len(), HASH_LEN);
+        assert!(hashed.matches(&long_id));
+        assert!(!hashed.matches(&hashed.credential_id));
+        assert!(!hashed.matches(&[7u8; 239]));
+        assert_eq!(hashed.credential_id_hash(), full.credential_id_hash());
+        assert_eq!(full.serialized_size() - hashed.serialized_size(), 240 - HASH_LEN);
+    }
+
+    #[test]
+    fn test_compact_only_hashes_ids_longer_than_a_hash() {
+        let short = PasskeyEntry::compact([1; 64], vec![1; HASH_LEN], String::new(), 0);
+        assert_eq!(short.id_storage, CredentialIdStorage::Full);
+        assert_eq!(short.credential_id, vec![1; HASH_LEN]);
+
+        let long = PasskeyEntry::compact([1; 64], vec![1; HASH_LEN + 1], String::new(), 0);
+        assert_eq!(long.id_storage, CredentialIdStorage::Hashed);
+        assert!(long.matches(&[1; HASH_LEN + 1]));
+    }
+}
@@ -0,0 +1,2604 @@
+use std::ops::RangeInclusive;
+use borsh::{BorshDeserialize, BorshSerialize};
+use crate::pubkey::Pubkey;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use crate::amount::Amount;
+use crate::bytes::{read_i64_le, read_u32_le, read_u64_le};
+use crate::hashing::{domain_hash, Domain};
+use crate::time::{validate_timestamp, TimeError, MAX_FUTURE_SECONDS, MAX_PAST_SECONDS};
+
+/// Earliest timestamp a policy may use (2020-01-01)
+///
+/// Anything earlier is almost certainly a unit mistake (milliseconds vs.
+/// seconds are caught by the upper bound) or an unset value.
+pub const MIN_POLICY_TIMESTAMP: i64 = 1_577_836_800;
+
+/// Latest timestamp a policy may use (2100-01-01)
+pub const MAX_POLICY_TIMESTAMP: i64 = 4_102_444_800;
+
+/// The window `Policy::daily_limit` resets on
+pub const SECONDS_PER_DAY: u32 = 86_400;
+
+/// Where a legacy `DailyLimit` config has its reset timestamp, the windowed
+/// layout has this instead - no policy could be built with it
+const WINDOWED_DAILY_LIMIT: i64 = i64::MIN;
+
+/// Length of a legacy `DailyLimit` config: max amount, reset timestamp
+const LEGACY_DAILY_LIMIT_LEN: usize = 16;
+
+/// Length of a windowed `DailyLimit` config: max amount, marker, window, anchor
+const DAILY_LIMIT_LEN: usize = 28;
+
+/// Most signers a `MultiSig` policy may require
+pub const MAX_POLICY_SIGNERS: usize = 16;
+
+/// Most destinations a `DestinationAllowlist` policy may list
+pub const MAX_POLICY_DESTINATIONS: usize = 32;
+
+/// Most destinations a `DestinationSpends` tracker holds at once
+///
+/// Past this, the least recently paid destination is dropped and what it
+/// was sent is carried by `DestinationSpends::evicted`.
+pub const MAX_TRACKED_DESTINATIONS: usize = 16;
+
+/// Serialized size of a `DestinationSpend`: destination_hash (32) + spent (8)
+pub const DESTINATION_SPEND_SIZE: usize = 32 + 8;
+
+/// Longest a `Vault` may hold a transfer back: 30 days
+///
+/// An announced transfer runs on the proof signed when it was announced,
+/// so this also bounds how long that proof stays good for.
+pub const MAX_VAULT_DELAY_SECONDS: u32 = 30 * SECONDS_PER_DAY;
+
+/// Most compute units a policy may be estimated to cost `execute`
+///
+/// Execution also has to verify a P-256 signature and move funds within the
+/// transaction's compute budget. A policy past this could leave the account
+/// unable to execute anything, so the program refuses to set it.
+pub const MAX_POLICY_COMPUTE_UNITS: u32 = 100_000;
+
+/// What evaluating a policy costs `execute`, in compute units
+///
+/// Calibrated against the program's compute-budget test
+/// (`programs/attesta/tests/compute_budget.rs`), which checks estimates stay
+/// within 25% of the measured cost. Re-run it after changing how policies
+/// are read or evaluated.
+pub mod compute_units {
+    /// Any policy: decoding it and reading the clock
+    pub const BASE: u32 = 2_500;
+
+    /// Each byte of config, copied as the policy is decoded
+    pub const PER_CONFIG_BYTE: u32 = 3;
+
+    /// Each per-mint token limit, decoded and compared
+    pub const PER_MINT_LIMIT: u32 = 180;
+
+    /// Each key of a `MultiSig` or `DestinationAllowlist` policy, and each
+    /// destination or binding of a `CredentialBinding` one
+    pub const PER_KEY: u32 = 70;
+
+    /// Each rule of a `Composite` policy, decoded again before it's evaluated
+    pub const PER_RULE: u32 = 900;
+
+    /// A `PerDestinationLimit`'s lookup in the account's tracker: hashing
+    /// the destination and scanning up to `MAX_TRACKED_DESTINATIONS` entries
+    pub const DESTINATION_TRACKER: u32 = 1_200;
+}
+
+/// Different types of policies users can set for their account
+///
+/// Policies are rules that control when transactions are allowed.
+/// They help protect users by limiting what their account can do,
+/// even if someone gets hold of their passkey.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PolicyType {
+    /// No restrictions - all transactions are allowed (default setting)
+    /// Use this if you trust your passkey completely
+    Open,
+    
+    /// Maximum amount allowed per transaction
+    /// Example: "Never spend more than 1 SOL at a time"
+    SpendingLimit,
+    
+    /// Maximum amount allowed per day
+    /// Example: "Never spend more than 10 SOL per day"
+    DailyLimit,
+    
+    /// Requires multiple passkeys to sign the same transaction
+    /// Example: "Both my phone and laptop must approve large transactions"
+    MultiSig,
+    
+    /// Transactions can only happen after a specific time
+    /// Example: "Lock my account until next month" (for savings)
+    TimeLocked,
+
+    /// Transfers may only go to listed destinations
+    /// Example: "Only ever send to my exchange deposit address"
+    DestinationAllowlist,
+
+    /// Several of the above at once - a transaction must pass all of them
+    Composite,
+
+    /// Transfers to some destinations must be signed by a particular passkey
+    /// Example: "Anything but my cold wallet needs my hardware key"
+    CredentialBinding,
+
+    /// A cap per window on what each destination may be sent
+    /// Example: "At most 0.5 SOL a day to any one new address, no limit to my cold wallet"
+    PerDestinationLimit,
+
+    /// Transfers over a threshold are announced first and only run after a delay,
+    /// during which any of the account's passkeys can veto them
+    /// Example: "Anything over 10 SOL waits 48 hours, in case it isn't me"
+    Vault,
+}
+
+/// Why a policy config was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolicyBuildError {
+    #[error("Policy config has the wrong layout for its type")]
+    MalformedConfig,
+
+    #[error("Limit would deny every transaction; set a non-zero limit")]
+    ZeroLimit,
+
+    #[error("Mint limits need a spending or daily limit to attach to")]
+    MintLimitsWithoutLimit,
+
+    #[error("Limit window must be at least one second")]
+    ZeroWindow,
+
+    #[error("Timestamp {0} is outside {MIN_POLICY_TIMESTAMP}..={MAX_POLICY_TIMESTAMP}")]
+    TimestampOutOfRange(i64),
+
+    #[error("Multi-sig needs 1 to {MAX_POLICY_SIGNERS} signers, got {0}")]
+    SignerCount(usize),
+
+    #[error("Allowlist needs 1 to {MAX_POLICY_DESTINATIONS} destinations, got {0}")]
+    DestinationCount(usize),
+
+    #[error("The same key is listed twice")]
+    DuplicateKey,
+
+    #[error("A composite policy can't contain another composite policy")]
+    NestedComposite,
+
+    #[error("Vault delay must be 1 to {MAX_VAULT_DELAY_SECONDS} seconds, got {0}")]
+    VaultDelay(u32),
+
+    #[error("{0}")]
+    Time(#[from] TimeError),
+}
+
+/// Custom error codes `PolicyError` uses (see `PolicyError::code`)
+///
+/// `core_crypto::CryptoError` keeps below 1000, `recovery::RecoveryError`
+/// has 1000 to 1099, and the program's own errors start at 6000.
+pub const POLICY_ERROR_CODES: RangeInclusive<u32> = 1100..=1199;
+
+/// Why a policy denied a transaction
+///
+/// Each variant has a fixed code in `POLICY_ERROR_CODES`, so on-chain code
+/// can return it as `ProgramError::Custom` (with the `solana` feature) and
+/// clients can tell the reasons apart. Codes are never reused.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PolicyError {
+    #[error("Policy config can't be read")]
+    MalformedConfig = 1100,
+
+    #[error("Amount is over the spending limit")]
+    OverSpendingLimit = 1101,
+
+    #[error("Amount would take the window's spending over its limit")]
+    OverWindowLimit = 1102,
+
+    #[error("Policy is still time-locked")]
+    TimeLocked = 1103,
+
+    #[error("Destination isn't on the allowlist")]
+    DestinationNotAllowed = 1104,
+
+    #[error("This passkey can't send to this destination")]
+    CredentialNotBound = 1105,
+
+    #[error("Amount would take the destination's spending over its limit")]
+    OverDestinationLimit = 1106,
+
+    #[error("Token transfer is over the limit for its mint, or its mint has none")]
+    OverMintLimit = 1107,
+}
+
+impl PolicyError {
+    /// Every variant, in code order
+    pub const ALL: [PolicyError; 8] = [
+        PolicyError::MalformedConfig,
+        PolicyError::OverSpendingLimit,
+        PolicyError::OverWindowLimit,
+        PolicyError::TimeLocked,
+        PolicyError::DestinationNotAllowed,
+        PolicyError::CredentialNotBound,
+        PolicyError::OverDestinationLimit,
+        PolicyError::OverMintLimit,
+    ];
+
+    /// The error's custom code
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+#[cfg(feature = "solana")]
+impl From<PolicyError> for solana_program::program_error::ProgramError {
+    fn from(e: PolicyError) -> Self {
+        solana_program::program_error::ProgramError::Custom(e.code())
+    }
+}
+
+/// Which passkey may sign transfers to a group of destinations
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct CredentialBinding {
+    /// The destinations this binding covers
+    pub destinations: Vec<Pubkey>,
+
+    /// SHA-256 of the credential ID that must sign, or `None` for any of the account's passkeys
+    pub credential_id_hash: Option<[u8; 32]>,
+}
+
+/// The config of a `CredentialBinding` policy
+///
+/// A transfer to a listed destination needs the signer its binding names.
+/// Anything else (including transactions that don't say where funds go)
+/// falls through to `default`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq)]
+pub struct CredentialBindings {
+    /// Destination groups and the signer each requires
+    pub bindings: Vec<CredentialBinding>,
+
+    /// Signer required for everything no binding covers (`None` for any passkey)
+    pub default: Option<[u8; 32]>,
+}
+
+impl CredentialBindings {
+    /// The credential a transaction to `destination` must be signed with
+    ///
+    /// # Returns
+    /// - `Some(hash)` if only that credential may sign it
+    /// - `None` if any of the account's passkeys may
+    pub fn required_credential(&self, destination: Option<&Pubkey>) -> Option<[u8; 32]> {
+        destination
+            .and_then(|destination| {
+                self.bindings
+                    .iter()
+                    .find(|binding| binding.destinations.contains(destination))
+            })
+            .map_or(self.default, |binding| binding.credential_id_hash)
+    }
+
+    /// Whether a transaction to `destination`, signed by `signer`, may run
+    pub fn allows(&self, destination: Option<&Pubkey>, signer: Option<&[u8; 32]>) -> bool {
+        match self.required_credential(destination) {
+            Some(required) => signer == Some(&required),
+            None => true,
+        }
+    }
+
+    fn destination_count(&self) -> usize {
+        self.bindings.iter().map(|binding| binding.destinations.len()).sum()
+    }
+}
+
+/// A destination's own cap in a `PerDestinationLimit` policy
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct DestinationLimit {
+    pub destination: Pubkey,
+
+    /// Most it may be sent per window, or `None` for no limit
+    pub max_amount: Option<u64>,
+}
+
+/// The config of a `PerDestinationLimit` policy
+///
+/// Every destination gets its own budget per window: a listed one its
+/// `max_amount`, any other `default_max_amount`. Amounts are raw, in the
+/// units of what's transferred; a token account only ever holds one mint,
+/// so a destination's total is always in one unit. What's been sent is
+/// tracked in the account's `DestinationSpends`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct DestinationLimits {
+    /// Destinations with a budget of their own
+    pub limits: Vec<DestinationLimit>,
+
+    /// The budget of each destination that isn't listed
+    pub default_max_amount: u64,
+
+    /// How long each window lasts
+    pub window_seconds: u32,
+
+    /// When the first window starts (Unix timestamp)
+    pub anchor_timestamp: i64,
+}
+
+impl DestinationLimits {
+    /// How much `destination` may be sent per window (`None` for no limit)
+    pub fn limit_for(&self, destination: &Pubkey) -> Option<u64> {
+        match self.limits.iter().find(|limit| limit.destination == *destination) {
+            Some(limit) => limit.max_amount,
+            None => Some(self.default_max_amount),
+        }
+    }
+
+    /// When the window `now` falls in started (see `DailyLimitConfig::window_start`)
+    pub fn window_start(&self, now: i64) -> Option<i64> {
+        DailyLimitConfig { max_amount: 0, window_seconds: self.window_seconds, anchor_timestamp: self.anchor_timestamp }
+            .window_start(now)
+    }
+
+    /// Whether sending `amount` to `destination` at `now` stays within its budget
+    pub fn allows(&self, destination: &Pubkey, amount: u64, now: i64, spends: &DestinationSpends) -> bool {
+        let max_amount = match self.limit_for(destination) {
+            Some(max_amount) => max_amount,
+            None => return true,
+        };
+        let window_start = match self.window_start(now) {
+            Some(window_start) => window_start,
+            None => return false,
+        };
+        matches!(
+            spends.spent_in(window_start, destination).checked_add(amount),
+            Some(total) if total <= max_amount
+        )
+    }
+}
+
+/// The config of a `Vault` policy
+///
+/// Like a `PerDestinationLimit`, the threshold is a raw amount in the
+/// units of what's transferred. Only transfers say how much they move, so
+/// other transactions are never held back.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VaultConfig {
+    /// Transfers of more than this are announced and delayed
+    pub threshold_lamports: u64,
+
+    /// How long an announced transfer waits before it can run
+    pub delay_seconds: u32,
+}
+
+impl VaultConfig {
+    /// Serialized size: threshold_lamports (8) + delay_seconds (4)
+    pub const SERIALIZED_SIZE: usize = 8 + 4;
+}
+
+/// What one destination has been sent in a `DestinationSpends` window
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DestinationSpend {
+    /// SHA-256 of the destination's address (see `destination_hash`)
+    pub destination_hash: [u8; 32],
+
+    pub spent: u64,
+}
+
+/// What's been sent to each destination in a `PerDestinationLimit` window
+///
+/// The account keeps one, holding at most `MAX_TRACKED_DESTINATIONS`
+/// destinations, least recently paid first. When a new one doesn't fit, the
+/// oldest is dropped, and every destination that isn't tracked counts as
+/// already sent `evicted`: the most any dropped destination was sent. So
+/// paying sixteen other addresses to push one out of the tracker can't give
+/// it a fresh budget; at worst, new destinations paid after an eviction get
+/// less. It all starts over once something is sent in a later window.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DestinationSpends {
+    /// Start of the window these were counted in
+    pub window_start: i64,
+
+    /// What's been sent to each tracked destination, least recently paid first
+    pub entries: Vec<DestinationSpend>,
+
+    /// The most sent to any destination since dropped from `entries`
+    pub evicted: u64,
+}
+
+impl DestinationSpends {
+    /// Serialized size with `entries` tracked destinations
+    pub fn serialized_size(entries: usize) -> usize {
+        8 + 4 + entries * DESTINATION_SPEND_SIZE + 8
+    }
+
+    /// What counts as sent to `destination` in the window starting at `window_start`
+    ///
+    /// Its own total if it's tracked, otherwise everything evicted.
+    pub fn spent_in(&self, window_start: i64, destination: &Pubkey) -> u64 {
+        if self.window_start != window_start {
+            return 0;
+        }
+        let hash = destination_hash(destination);
+        match self.entries.iter().find(|entry| entry.destination_hash == hash) {
+            Some(entry) => entry.spent,
+            None => self.evicted,
+        }
+    }
+
+    /// Adds `amount` sent to `destination` in the window starting at
+    /// `window_start`, dropping an older window's totals
+    pub fn record(&mut self, window_start: i64, destination: &Pubkey, amount: u64) {
+        if self.window_start != window_start {
+            *self = Self { window_start, ..Self::default() };
+        }
+        let hash = destination_hash(destination);
+        let entry = match self.entries.iter().position(|entry| entry.destination_hash == hash) {
+            Some(index) => self.entries.remove(index),
+            // Untracked, it was already charged everything evicted
+            None => DestinationSpend { destination_hash: hash, spent: self.evicted },
+        };
+        if self.entries.len() >= MAX_TRACKED_DESTINATIONS {
+            let oldest = self.entries.remove(0);
+            self.evicted = self.evicted.max(oldest.spent);
+        }
+        self.entries.push(DestinationSpend { spent: entry.spent.saturating_add(amount), ..entry });
+    }
+}
+
+/// The key a destination is tracked under in `DestinationSpends`
+pub fn destination_hash(destination: &Pubkey) -> [u8; 32] {
+    Sha256::digest(destination.as_ref()).into()
+}
+
+/// A per-mint cap for SPL token transfers
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct MintLimit {
+    /// The token mint this limit applies to
+    pub mint: Pubkey,
+
+    /// Maximum raw amount (in the token's smallest unit)
+    pub max_amount: u64,
+
+    /// The mint's decimals - transfers must state the same decimals
+    pub decimals: u8,
+}
+
+/// Token limits attached to a `SpendingLimit` or `DailyLimit` policy
+///
+/// Stored after the policy's SOL limit in its config. Mints that aren't
+/// listed are denied unless `allow_unlisted` is set.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq)]
+pub struct MintLimits {
+    /// Whether mints without a limit may be transferred freely
+    pub allow_unlisted: bool,
+
+    /// The per-mint limits
+    pub limits: Vec<MintLimit>,
+}
+
+impl MintLimits {
+    /// Checks a token transfer against these limits
+    ///
+    /// Amounts are compared raw, so the transfer's decimals must match the
+    /// decimals the limit was set with - otherwise the same number could
+    /// mean a very different amount, and the transfer is denied.
+    pub fn allows(&self, mint: &Pubkey, amount: u64, decimals: u8) -> bool {
+        match self.limits.iter().find(|l| l.mint == *mint) {
+            Some(limit) => limit.decimals == decimals && amount <= limit.max_amount,
+            None => self.allow_unlisted,
+        }
+    }
+}
+
+/// The SOL limit of a `DailyLimit` policy
+///
+/// Spending is counted in back-to-back windows of `window_seconds`, the
+/// first starting at `anchor_timestamp`. Legacy configs stored only the
+/// moment the current day ended; they read as day-long windows ending then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyLimitConfig {
+    /// Most lamports that may be spent in one window
+    pub max_amount: u64,
+
+    /// How long each window lasts
+    pub window_seconds: u32,
+
+    /// When the first window starts (Unix timestamp)
+    pub anchor_timestamp: i64,
+}
+
+impl DailyLimitConfig {
+    /// Reads either layout from the start of a config, with the length it took up
+    fn read(config: &[u8]) -> Option<(Self, usize)> {
+        let mut offset = 0;
+        let max_amount = read_u64_le(config, &mut offset).ok()?;
+        let reset_timestamp = read_i64_le(config, &mut offset).ok()?;
+        if reset_timestamp != WINDOWED_DAILY_LIMIT {
+            let limit = Self {
+                max_amount,
+                window_seconds: SECONDS_PER_DAY,
+                anchor_timestamp: reset_timestamp.saturating_sub(i64::from(SECONDS_PER_DAY)),
+            };
+            return Some((limit, LEGACY_DAILY_LIMIT_LEN));
+        }
+
+        let window_seconds = read_u32_le(config, &mut offset).ok()?;
+        let anchor_timestamp = read_i64_le(config, &mut offset).ok()?;
+        Some((Self { max_amount, window_seconds, anchor_timestamp }, DAILY_LIMIT_LEN))
+    }
+
+    /// When the window `now` falls in started
+    ///
+    /// Times before the anchor fall in the first window, so spending before
+    /// the limit starts still counts against it.
+    ///
+    /// # Returns
+    /// `None` if the window is empty, which denies everything
+    pub fn window_start(&self, now: i64) -> Option<i64> {
+        let window = i64::from(self.window_seconds);
+        if window == 0 {
+            return None;
+        }
+        let elapsed = now.saturating_sub(self.anchor_timestamp).max(0);
+        Some(self.anchor_timestamp.saturating_add(elapsed / window * window))
+    }
+}
+
+/// Lamports spent against a `DailyLimit` in its current window, and held
+/// for transactions that were proposed but haven't executed
+///
+/// Whoever enforces the limit keeps one of these per daily limit and passes
+/// it to `Policy::check_with_spend` and `Policy::record_spend`. `spent`
+/// starts over by itself once a transaction lands in a later window;
+/// `reserved` doesn't, so proposals made before a window ends can't all be
+/// approved once the next one starts.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitSpend {
+    /// Start of the window `spent` was counted in
+    pub window_start: i64,
+
+    /// Lamports spent in that window
+    pub spent: u64,
+
+    /// Lamports held for proposed transactions (see `Policy::check_and_reserve`)
+    ///
+    /// Counts against every window until the transaction executes
+    /// (`Policy::settle_spend`) or its proposal expires or is cancelled
+    /// (`release`).
+    pub reserved: u64,
+}
+
+impl LimitSpend {
+    /// What's been spent in the window starting at `window_start`
+    pub fn spent_in(&self, window_start: i64) -> u64 {
+        if self.window_start == window_start {
+            self.spent
+        } else {
+            0
+        }
+    }
+
+    /// What's been spent in the window starting at `window_start`, plus
+    /// what's reserved
+    pub fn committed_in(&self, window_start: i64) -> u64 {
+        self.spent_in(window_start).saturating_add(self.reserved)
+    }
+
+    /// Adds `amount` to the window starting at `window_start`, dropping an older window's total
+    pub fn record(&mut self, window_start: i64, amount: u64) {
+        self.spent = self.spent_in(window_start).saturating_add(amount);
+        self.window_start = window_start;
+    }
+
+    /// Holds `amount` for a proposed transaction
+    pub fn reserve(&mut self, amount: u64) {
+        self.reserved = self.reserved.saturating_add(amount);
+    }
+
+    /// Gives back a reservation whose proposal expired or was cancelled
+    pub fn release(&mut self, amount: u64) {
+        self.reserved = self.reserved.saturating_sub(amount);
+    }
+}
+
+/// What a policy needs to know about a transaction to evaluate it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolicyContext {
+    /// Amount being moved: lamports for SOL, the raw token amount for SPL tokens
+    pub amount: Amount,
+
+    /// The token mint and its decimals, or `None` for SOL
+    pub token: Option<(Pubkey, u8)>,
+
+    /// The current time (Unix timestamp)
+    pub timestamp: i64,
+
+    /// Where the transaction sends funds, if known
+    pub destination: Option<Pubkey>,
+
+    /// SHA-256 of the credential ID that signed the transaction, if known
+    pub signer_credential_id: Option<[u8; 32]>,
+}
+
+impl PolicyContext {
+    /// Context for a SOL transaction
+    pub fn sol(amount: Amount, timestamp: i64) -> Self {
+        Self {
+            amount,
+            token: None,
+            timestamp,
+            destination: None,
+            signer_credential_id: None,
+        }
+    }
+
+    /// Context for an SPL token transfer
+    pub fn token(mint: Pubkey, amount: u64, decimals: u8, timestamp: i64) -> Self {
+        Self {
+            amount: Amount::from_lamports(amount),
+            token: Some((mint, decimals)),
+            timestamp,
+            destination: None,
+            signer_credential_id: None,
+        }
+    }
+
+    /// Sets where the transaction sends funds
+    pub fn with_destination(mut self, destination: Pubkey) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Sets which credential signed the transaction (by the hash of its ID)
+    pub fn with_signer(mut self, credential_id_hash: [u8; 32]) -> Self {
+        self.signer_credential_id = Some(credential_id_hash);
+        self
+    }
+}
+
+/// A policy that controls what transactions are allowed
+///
+/// Each account can have one policy that defines restrictions on transactions.
+/// The policy type determines what kind of restriction, and the config
+/// contains the specific values (like the spending limit amount).
+///
+/// # Example
+/// ```ignore
+/// // Allow spending up to 1 SOL per transaction
+/// let policy = Policy::spending_limit(Amount::from_lamports(1_000_000_000)); // 1 SOL
+/// ```
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// What type of policy this is
+    pub policy_type: PolicyType,
+    
+    /// The specific settings for this policy (depends on the type)
+    /// 
+    /// Format depends on policy_type:
+    /// - `Open`: Empty (no config needed)
+    /// - `SpendingLimit`: 8 bytes (u64 in little-endian) - max amount in lamports,
+    ///   optionally followed by Borsh-encoded `MintLimits`
+    /// - `DailyLimit`: 28 bytes (u64 amount + i64 `i64::MIN` marker + u32
+    ///   window_seconds + i64 anchor_timestamp), optionally followed by
+    ///   Borsh-encoded `MintLimits`. Legacy configs have 16 bytes (u64 amount
+    ///   + i64 reset_timestamp) before the `MintLimits`; see `DailyLimitConfig`
+    /// - `MultiSig`: Variable length - list of required signer public keys (32 bytes each)
+    /// - `TimeLocked`: 8 bytes (i64 in little-endian) - unlock timestamp
+    /// - `DestinationAllowlist`: Variable length - allowed destinations (32 bytes each)
+    /// - `Composite`: Borsh-encoded `Vec<Policy>` (none of them `Composite`)
+    /// - `CredentialBinding`: Borsh-encoded `CredentialBindings`
+    /// - `PerDestinationLimit`: Borsh-encoded `DestinationLimits`
+    /// - `Vault`: Borsh-encoded `VaultConfig` (12 bytes)
+    pub config: Vec<u8>,
+}
+
+impl Policy {
+    /// Creates a new policy from a hand-packed config
+    ///
+    /// Nothing checks the config, and a malformed one denies every
+    /// transaction. Use `PolicyBuilder`, or call `validate_config`.
+    #[deprecated(note = "use PolicyBuilder, which validates the config")]
+    pub fn new(policy_type: PolicyType, config: Vec<u8>) -> Self {
+        Self {
+            policy_type,
+            config,
+        }
+    }
+
+    /// Creates an open policy (no restrictions)
+    pub fn open() -> Self {
+        Self {
+            policy_type: PolicyType::Open,
+            config: Vec::new(),
+        }
+    }
+
+    /// Creates a spending limit policy
+    pub fn spending_limit(max_amount: Amount) -> Self {
+        let config = max_amount.lamports().to_le_bytes().to_vec();
+        Self {
+            policy_type: PolicyType::SpendingLimit,
+            config,
+        }
+    }
+
+    /// Creates a daily limit policy, with days starting at `anchor_timestamp`
+    pub fn daily_limit(max_amount: Amount, anchor_timestamp: i64) -> Self {
+        Self::windowed_limit(max_amount, SECONDS_PER_DAY, anchor_timestamp)
+    }
+
+    /// Creates a `DailyLimit` policy whose windows last `window_seconds`
+    /// instead of a day, the first starting at `anchor_timestamp`
+    pub fn windowed_limit(max_amount: Amount, window_seconds: u32, anchor_timestamp: i64) -> Self {
+        let mut config = Vec::with_capacity(DAILY_LIMIT_LEN);
+        config.extend_from_slice(&max_amount.lamports().to_le_bytes());
+        config.extend_from_slice(&WINDOWED_DAILY_LIMIT.to_le_bytes());
+        config.extend_from_slice(&window_seconds.to_le_bytes());
+        config.extend_from_slice(&anchor_timestamp.to_le_bytes());
+        Self {
+            policy_type: PolicyType::DailyLimit,
+            config,
+        }
+    }
+
+    /// The SOL limit of a `DailyLimit` policy, in either layout, or `None`
+    /// if it isn't one (or is malformed)
+    pub fn daily_limit_config(&self) -> Option<DailyLimitConfig> {
+        match self.policy_type {
+            PolicyType::DailyLimit => DailyLimitConfig::read(&self.config).map(|(limit, _)| limit),
+            _ => None,
+        }
+    }
+
+    /// Creates a multi-sig policy
+    pub fn multi_sig(required_signers: Vec<Pubkey>) -> Self {
+        let mut config = Vec::with_capacity(required_signers.len() * 32);
+        for signer in required_signers {
+            config.extend_from_slice(signer.as_ref());
+        }
+        Self {
+            policy_type: PolicyType::MultiSig,
+            config,
+        }
+    }
+
+    /// Creates a time-locked policy
+    pub fn time_locked(unlock_timestamp: i64) -> Self {
+        let config = unlock_timestamp.to_le_bytes().to_vec();
+        Self {
+            policy_type: PolicyType::TimeLocked,
+            config,
+        }
+    }
+
+    /// Creates a policy that only allows transfers to `destinations`
+    pub fn destination_allowlist(destinations: Vec<Pubkey>) -> Self {
+        let mut config = Vec::with_capacity(destinations.len() * 32);
+        for destination in destinations {
+            config.extend_from_slice(destination.as_ref());
+        }
+        Self {
+            policy_type: PolicyType::DestinationAllowlist,
+            config,
+        }
+    }
+
+    /// Creates a policy that allows a transaction only if all of `rules` do
+    pub fn composite(rules: Vec<Policy>) -> Self {
+        Self {
+            policy_type: PolicyType::Composite,
+            // Serializing into a Vec can't fail
+            config: borsh::to_vec(&rules).unwrap_or_default(),
+        }
+    }
+
+    /// Creates a policy requiring particular passkeys for particular destinations
+    pub fn credential_binding(bindings: CredentialBindings) -> Self {
+        Self {
+            policy_type: PolicyType::CredentialBinding,
+            // Serializing into a Vec can't fail
+            config: borsh::to_vec(&bindings).unwrap_or_default(),
+        }
+    }
+
+    /// The bindings of a `CredentialBinding` policy, or `None` if it isn't one (or is malformed)
+    pub fn credential_bindings(&self) -> Option<CredentialBindings> {
+        match self.policy_type {
+            PolicyType::CredentialBinding => borsh::from_slice(&self.config).ok(),
+            _ => None,
+        }
+    }
+
+    /// Creates a policy capping what each destination may be sent per window
+    pub fn per_destination_limit(limits: DestinationLimits) -> Self {
+        Self {
+            policy_type: PolicyType::PerDestinationLimit,
+            // Serializing into a Vec can't fail
+            config: borsh::to_vec(&limits).unwrap_or_default(),
+        }
+    }
+
+    /// The limits of a `PerDestinationLimit` policy, or `None` if it isn't one (or is malformed)
+    pub fn destination_limits(&self) -> Option<DestinationLimits> {
+        match self.policy_type {
+            PolicyType::PerDestinationLimit => borsh::from_slice(&self.config).ok(),
+            _ => None,
+        }
+    }
+
+    /// Creates a policy holding back transfers over `threshold` for `delay_seconds`
+    pub fn vault(threshold: Amount, delay_seconds: u32) -> Self {
+        let config = VaultConfig { threshold_lamports: threshold.lamports(), delay_seconds };
+        Self {
+            policy_type: PolicyType::Vault,
+            // Serializing into a Vec can't fail
+            config: borsh::to_vec(&config).unwrap_or_default(),
+        }
+    }
+
+    /// The config of a `Vault` policy, or `None` if it isn't one (or is malformed)
+    pub fn vault_config(&self) -> Option<VaultConfig> {
+        match self.policy_type {
+            PolicyType::Vault => borsh::from_slice(&self.config).ok(),
+            _ => None,
+        }
+    }
+
+    /// How long a transfer of `amount` has to be announced before it runs
+    ///
+    /// `None` unless this policy, or a rule of it, is a `Vault` whose
+    /// threshold `amount` is over. With several, the longest delay applies.
+    pub fn announcement_delay(&self, amount: u64) -> Option<u32> {
+        match self.policy_type {
+            PolicyType::Vault => self
+                .vault_config()
+                .filter(|vault| amount > vault.threshold_lamports)
+                .map(|vault| vault.delay_seconds),
+            PolicyType::Composite => {
+                self.rules().unwrap_or_default().iter().filter_map(|rule| rule.announcement_delay(amount)).max()
+            }
+            _ => None,
+        }
+    }
+
+    /// The rules of a `Composite` policy, or `None` if it isn't one (or is malformed)
+    pub fn rules(&self) -> Option<Vec<Policy>> {
+        match self.policy_type {
+            PolicyType::Composite => borsh::from_slice(&self.config).ok(),
+            _ => None,
+        }
+    }
+
+    /// Checks that the config is well-formed and sensible for the policy type
+    ///
+    /// Catches configs that would silently deny everything: wrong layouts,
+    /// zero limits, timestamps outside `MIN_POLICY_TIMESTAMP..=MAX_POLICY_TIMESTAMP`,
+    /// and empty, oversized or duplicated key lists. A `SpendingLimit` or
+    /// `DailyLimit` may have a zero SOL limit only if it has mint limits.
+    pub fn validate_config(&self) -> Result<(), PolicyBuildError> {
+        match self.policy_type {
+            PolicyType::Open => {
+                if !self.config.is_empty() {
+                    return Err(PolicyBuildError::MalformedConfig);
+                }
+            }
+            PolicyType::SpendingLimit | PolicyType::DailyLimit => {
+                let base_len = self.limit_config_len().unwrap_or_default();
+                let rest = self.config.get(base_len..).ok_or(PolicyBuildError::MalformedConfig)?;
+                let mint_limits = match rest {
+                    [] => None,
+                    rest => Some(
+                        borsh::from_slice::<MintLimits>(rest)
+                            .map_err(|_| PolicyBuildError::MalformedConfig)?,
+                    ),
+                };
+                if let Some(limits) = &mint_limits {
+                    if limits.limits.iter().any(|limit| limit.max_amount == 0) {
+                        return Err(PolicyBuildError::ZeroLimit);
+                    }
+                }
+                if read_u64_le(&self.config, &mut 0) == Ok(0) && mint_limits.is_none() {
+                    return Err(PolicyBuildError::ZeroLimit);
+                }
+                if self.policy_type == PolicyType::DailyLimit {
+                    let limit = self.daily_limit_config().ok_or(PolicyBuildError::MalformedConfig)?;
+                    if base_len == LEGACY_DAILY_LIMIT_LEN {
+                        validate_timestamp_range(read_i64_le(&self.config, &mut 8).unwrap_or_default())?;
+                    } else {
+                        if limit.window_seconds == 0 {
+                            return Err(PolicyBuildError::ZeroWindow);
+                        }
+                        validate_timestamp_range(limit.anchor_timestamp)?;
+                    }
+                }
+            }
+            PolicyType::TimeLocked => {
+                if self.config.len() != 8 {
+                    return Err(PolicyBuildError::MalformedConfig);
+                }
+                validate_timestamp_range(read_i64_le(&self.config, &mut 0).unwrap_or_default())?;
+            }
+            PolicyType::MultiSig => {
+                let count = validate_key_list(&self.config)?;
+                if !(1..=MAX_POLICY_SIGNERS).contains(&count) {
+                    return Err(PolicyBuildError::SignerCount(count));
+                }
+            }
+            PolicyType::DestinationAllowlist => {
+                let count = validate_key_list(&self.config)?;
+                if !(1..=MAX_POLICY_DESTINATIONS).contains(&count) {
+                    return Err(PolicyBuildError::DestinationCount(count));
+                }
+            }
+            PolicyType::Composite => {
+                let rules = self.rules().ok_or(PolicyBuildError::MalformedConfig)?;
+                if rules.is_empty() {
+                    return Err(PolicyBuildError::MalformedConfig);
+                }
+                for rule in &rules {
+                    if rule.policy_type == PolicyType::Composite {
+                        return Err(PolicyBuildError::NestedComposite);
+                    }
+                    rule.validate_config()?;
+                }
+            }
+            PolicyType::CredentialBinding => {
+                let bindings = self.credential_bindings().ok_or(PolicyBuildError::MalformedConfig)?;
+                // Bindings that all allow any passkey would restrict nothing
+                let binds_anything = bindings.default.is_some()
+                    || bindings.bindings.iter().any(|binding| binding.credential_id_hash.is_some());
+                if !binds_anything || bindings.bindings.iter().any(|binding| binding.destinations.is_empty()) {
+                    return Err(PolicyBuildError::MalformedConfig);
+                }
+                let count = bindings.destination_count();
+                if count > MAX_POLICY_DESTINATIONS {
+                    return Err(PolicyBuildError::DestinationCount(count));
+                }
+                let destinations: Vec<&Pubkey> =
+                    bindings.bindings.iter().flat_map(|binding| &binding.destinations).collect();
+                for (i, destination) in destinations.iter().enumerate() {
+                    if destinations.iter().take(i).any(|seen| seen == destination) {
+                        return Err(PolicyBuildError::DuplicateKey);
+                    }
+                }
+            }
+            PolicyType::PerDestinationLimit => {
+                let limits = self.destination_limits().ok_or(PolicyBuildError::MalformedConfig)?;
+                if limits.limits.len() > MAX_POLICY_DESTINATIONS {
+                    return Err(PolicyBuildError::DestinationCount(limits.limits.len()));
+                }
+                for (i, limit) in limits.limits.iter().enumerate() {
+                    if limits.limits.iter().take(i).any(|seen| seen.destination == limit.destination) {
+                        return Err(PolicyBuildError::DuplicateKey);
+                    }
+                }
+                // A zero budget for every destination would deny every transfer
+                let any_budget = limits.default_max_amount > 0
+                    || limits.limits.iter().any(|limit| limit.max_amount != Some(0));
+                if limits.limits.iter().any(|limit| limit.max_amount == Some(0)) || !any_budget {
+                    return Err(PolicyBuildError::ZeroLimit);
+                }
+                if limits.window_seconds == 0 {
+                    return Err(PolicyBuildError::ZeroWindow);
+                }
+                validate_timestamp_range(limits.anchor_timestamp)?;
+            }
+            PolicyType::Vault => {
+                let vault = self.vault_config().ok_or(PolicyBuildError::MalformedConfig)?;
+                if !(1..=MAX_VAULT_DELAY_SECONDS).contains(&vault.delay_seconds) {
+                    return Err(PolicyBuildError::VaultDelay(vault.delay_seconds));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the policy's time locks against the current time
+    ///
+    /// An unlock time may be at most `MAX_FUTURE_SECONDS` ahead of `now`, or
+    /// any time up to `MAX_POLICY_TIMESTAMP` with `allow_long_lock`, and at
+    /// most `MAX_PAST_SECONDS` behind it. Limit windows aren't checked: their
+    /// anchor only sets where windows start, so an old one is fine. Call
+    /// this wherever a policy is accepted from a client, after `validate_config`.
+    pub fn validate_timestamps(&self, now: i64, allow_long_lock: bool) -> Result<(), TimeError> {
+        match self.policy_type {
+            PolicyType::TimeLocked => {
+                let max_future = if allow_long_lock { i64::MAX } else { MAX_FUTURE_SECONDS };
+                validate_timestamp(read_i64_le(&self.config, &mut 0).unwrap_or_default(), now, max_future, MAX_PAST_SECONDS)
+            }
+            PolicyType::Composite => self
+                .rules()
+                .unwrap_or_default()
+                .iter()
+                .try_for_each(|rule| rule.validate_timestamps(now, allow_long_lock)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds per-mint token limits to a `SpendingLimit` or `DailyLimit` policy
+    ///
+    /// Replaces any token limits the policy already had. Other policy types
+    /// don't limit amounts, so they're returned unchanged.
+    pub fn with_mint_limits(mut self, mint_limits: MintLimits) -> Self {
+        let base_len = match self.limit_config_len() {
+            Some(len) => len,
+            None => return self,
+        };
+        self.config.truncate(base_len);
+        // Serializing into a Vec can't fail
+        self.config.extend(borsh::to_vec(&mint_limits).unwrap_or_default());
+        self
+    }
+
+    /// Reads the per-mint token limits of a `SpendingLimit` or `DailyLimit` policy
+    ///
+    /// # Returns
+    /// - `Some(MintLimits)` if the policy has token limits
+    /// - `None` if it has none (token transfers are then denied), or isn't a limit policy
+    pub fn mint_limits(&self) -> Option<MintLimits> {
+        let base_len = self.limit_config_len()?;
+        let rest = self.config.get(base_len..)?;
+        if rest.is_empty() {
+            return None;
+        }
+        borsh::from_slice(rest).ok()
+    }
+
+    /// Length of the SOL part of a limit policy's config
+    fn limit_config_len(&self) -> Option<usize> {
+        match self.policy_type {
+            PolicyType::SpendingLimit => Some(8),
+            PolicyType::DailyLimit => DailyLimitConfig::read(&self.config).map(|(_, len)| len),
+            _ => None,
+        }
+    }
+
+    /// Checks a transaction described by `context` against this policy
+    ///
+    /// SOL amounts are checked against the policy's lamport limit. SPL token
+    /// transfers are checked against its per-mint limits instead; a limit
+    /// policy without token limits denies all token transfers.
+    pub fn check_context(&self, context: &PolicyContext) -> Result<(), PolicyError> {
+        match self.policy_type {
+            PolicyType::Composite => return self.check_rules(|rule| rule.check_context(context)),
+            // Transactions that don't say where they send funds aren't transfers
+            PolicyType::DestinationAllowlist => {
+                return match context.destination {
+                    Some(destination) if !self.lists_key(&destination) => Err(PolicyError::DestinationNotAllowed),
+                    _ => Ok(()),
+                };
+            }
+            PolicyType::CredentialBinding => {
+                let bindings = self.credential_bindings().ok_or(PolicyError::MalformedConfig)?;
+                return if bindings.allows(context.destination.as_ref(), context.signer_credential_id.as_ref()) {
+                    Ok(())
+                } else {
+                    Err(PolicyError::CredentialNotBound)
+                };
+            }
+            // As if nothing had been sent in the window yet; see `check_destination_spend`
+            PolicyType::PerDestinationLimit => {
+                return self.check_destination_spend(context, &DestinationSpends::default());
+            }
+            _ => {}
+        }
+
+        let (mint, decimals) = match context.token {
+            Some(token) => token,
+            None => return self.check(context.amount.lamports(), context.timestamp),
+        };
+
+        if self.limit_config_len().is_none() {
+            // Open, TimeLocked and MultiSig don't look at amounts - check
+            // them the same way as for SOL
+            return self.check(0, context.timestamp);
+        }
+
+        // Still validates the SOL part of the config
+        self.check(0, context.timestamp)?;
+
+        match self.mint_limits() {
+            Some(limits) if limits.allows(&mint, context.amount.lamports(), decimals) => Ok(()),
+            _ => Err(PolicyError::OverMintLimit),
+        }
+    }
+
+    /// Whether this policy allows the transaction described by `context`
+    #[deprecated(note = "use `check_context`, which says why a transaction is denied")]
+    pub fn evaluate_context(&self, context: &PolicyContext) -> bool {
+        self.check_context(context).is_ok()
+    }
+
+    /// Checks a transaction against this policy
+    ///
+    /// This function looks at the transaction amount and current time,
+    /// then decides if the policy allows it.
+    ///
+    /// # Parameters
+    /// - `transaction_amount`: How much the transaction wants to spend (in lamports)
+    /// - `current_timestamp`: The current time (Unix timestamp)
+    ///
+    /// # Returns
+    /// The reason the policy blocks the transaction, if it does. A config
+    /// that can't be read blocks everything (`PolicyError::MalformedConfig`).
+    ///
+    /// # Note
+    /// For `DailyLimit`, this only checks the transaction on its own; use
+    /// `check_with_spend` to count what's already been spent in the window.
+    pub fn check(&self, transaction_amount: u64, current_timestamp: i64) -> Result<(), PolicyError> {
+        match self.policy_type {
+            PolicyType::Open => {
+                // No restrictions - always allow
+                Ok(())
+            }
+            
+            PolicyType::SpendingLimit => {
+                // Extract the maximum allowed amount (first 8 bytes)
+                let max_amount = read_u64_le(&self.config, &mut 0).map_err(|_| PolicyError::MalformedConfig)?;
+                if transaction_amount <= max_amount {
+                    Ok(())
+                } else {
+                    Err(PolicyError::OverSpendingLimit)
+                }
+            }
+            
+            PolicyType::DailyLimit => {
+                // As if nothing had been spent in the window yet
+                self.check_with_spend(transaction_amount, current_timestamp, &LimitSpend::default())
+            }
+            
+            PolicyType::TimeLocked => {
+                // Allow only if current time is past unlock time
+                let unlock_timestamp = read_i64_le(&self.config, &mut 0).map_err(|_| PolicyError::MalformedConfig)?;
+                if current_timestamp >= unlock_timestamp {
+                    Ok(())
+                } else {
+                    Err(PolicyError::TimeLocked)
+                }
+            }
+            
+            PolicyType::MultiSig => {
+                // Multi-sig policies require checking multiple signatures
+                // The signature checking happens in the execution layer,
+                // so we just allow it here (assuming signatures will be checked)
+                // TODO: In production, verify that enough signatures are present
+                Ok(())
+            }
+
+            PolicyType::DestinationAllowlist => {
+                // No destination to check here - see `check_context`
+                Ok(())
+            }
+
+            PolicyType::CredentialBinding => {
+                // Without a destination or signer, only a default that allows
+                // any passkey passes - see `check_context`
+                let bindings = self.credential_bindings().ok_or(PolicyError::MalformedConfig)?;
+                if bindings.allows(None, None) {
+                    Ok(())
+                } else {
+                    Err(PolicyError::CredentialNotBound)
+                }
+            }
+
+            PolicyType::PerDestinationLimit => {
+                // No destination to check here - see `check_context`
+                self.destination_limits().map(|_| ()).ok_or(PolicyError::MalformedConfig)
+            }
+
+            PolicyType::Vault => {
+                // Delays transfers rather than denying them - see `announcement_delay`
+                self.vault_config().map(|_| ()).ok_or(PolicyError::MalformedConfig)
+            }
+
+            PolicyType::Composite => {
+                self.check_rules(|rule| rule.check(transaction_amount, current_timestamp))
+            }
+        }
+    }
+
+    /// Whether this policy allows a transaction of `transaction_amount` at `current_timestamp`
+    #[deprecated(note = "use `check`, which says why a transaction is denied")]
+    pub fn evaluate(&self, transaction_amount: u64, current_timestamp: i64) -> bool {
+        self.check(transaction_amount, current_timestamp).is_ok()
+    }
+
+    /// Checks a transaction against this policy, counting what's been spent
+    /// in the current `DailyLimit` window and what's reserved
+    ///
+    /// Other policy types are checked as by `check`; a `Composite` passes
+    /// `spend` to each rule, so it should hold at most one daily limit.
+    /// For a transaction whose own amount is reserved, use
+    /// `check_reserved` so it isn't counted twice.
+    pub fn check_with_spend(&self, transaction_amount: u64, current_timestamp: i64, spend: &LimitSpend) -> Result<(), PolicyError> {
+        match self.policy_type {
+            PolicyType::DailyLimit => {
+                let limit = self.daily_limit_config().ok_or(PolicyError::MalformedConfig)?;
+                let window_start = limit.window_start(current_timestamp).ok_or(PolicyError::MalformedConfig)?;
+                match spend.committed_in(window_start).checked_add(transaction_amount) {
+                    Some(total) if total <= limit.max_amount => Ok(()),
+                    _ => Err(PolicyError::OverWindowLimit),
+                }
+            }
+            PolicyType::Composite => {
+                self.check_rules(|rule| rule.check_with_spend(transaction_amount, current_timestamp, spend))
+            }
+            _ => self.check(transaction_amount, current_timestamp),
+        }
+    }
+
+    /// Whether this policy allows the transaction, counting `spend` (see `check_with_spend`)
+    #[deprecated(note = "use `check_with_spend`, which says why a transaction is denied")]
+    pub fn evaluate_with_spend(&self, transaction_amount: u64, current_timestamp: i64, spend: &LimitSpend) -> bool {
+        self.check_with_spend(transaction_amount, current_timestamp, spend).is_ok()
+    }
+
+    /// Counts an executed transaction against the current `DailyLimit` window
+    ///
+    /// Does nothing for policies without a daily limit.
+    pub fn record_spend(&self, transaction_amount: u64, current_timestamp: i64, spend: &mut LimitSpend) {
+        match self.policy_type {
+            PolicyType::DailyLimit => {
+                if let Some(window_start) = self
+                    .daily_limit_config()
+                    .and_then(|limit| limit.window_start(current_timestamp))
+                {
+                    spend.record(window_start, transaction_amount);
+                }
+            }
+            PolicyType::Composite => {
+                for rule in self.rules().unwrap_or_default() {
+                    rule.record_spend(transaction_amount, current_timestamp, spend);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks a proposed transaction against this policy and, if it
+    /// passes, reserves its amount until it executes or is given up
+    ///
+    /// Nothing is reserved for a transaction that doesn't pass.
+    pub fn check_and_reserve(&self, transaction_amount: u64, current_timestamp: i64, spend: &mut LimitSpend) -> Result<(), PolicyError> {
+        self.check_with_spend(transaction_amount, current_timestamp, spend)?;
+        spend.reserve(transaction_amount);
+        Ok(())
+    }
+
+    /// Reserves a proposed transaction's amount if it passes (see `check_and_reserve`)
+    ///
+    /// # Returns
+    /// Whether the transaction passed (and was reserved)
+    #[deprecated(note = "use `check_and_reserve`, which says why a transaction is denied")]
+    pub fn reserve_spend(&self, transaction_amount: u64, current_timestamp: i64, spend: &mut LimitSpend) -> bool {
+        self.check_and_reserve(transaction_amount, current_timestamp, spend).is_ok()
+    }
+
+    /// Checks a transaction reserved with `check_and_reserve` again, as it's
+    /// about to execute
+    ///
+    /// Its own reservation doesn't count against it; everything else spent
+    /// or reserved does, in the window it executes in.
+    pub fn check_reserved(&self, transaction_amount: u64, current_timestamp: i64, spend: &LimitSpend) -> Result<(), PolicyError> {
+        let mut others = *spend;
+        others.release(transaction_amount);
+        self.check_with_spend(transaction_amount, current_timestamp, &others)
+    }
+
+    /// Whether a reserved transaction still passes (see `check_reserved`)
+    #[deprecated(note = "use `check_reserved`, which says why a transaction is denied")]
+    pub fn evaluate_reserved(&self, transaction_amount: u64, current_timestamp: i64, spend: &LimitSpend) -> bool {
+        self.check_reserved(transaction_amount, current_timestamp, spend).is_ok()
+    }
+
+    /// Turns a reserved transaction's reservation into spending, in the
+    /// window it executes in
+    pub fn settle_spend(&self, transaction_amount: u64, current_timestamp: i64, spend: &mut LimitSpend) {
+        spend.release(transaction_amount);
+        self.record_spend(transaction_amount, current_timestamp, spend);
+    }
+
+    /// Checks a transfer against a `PerDestinationLimit`, counting what its
+    /// destination has been sent in the current window
+    ///
+    /// A transaction that doesn't say where it sends funds isn't a transfer
+    /// and passes. Other policy types pass; a `Composite` checks each rule,
+    /// so an account should hold at most one per-destination limit.
+    pub fn check_destination_spend(&self, context: &PolicyContext, spends: &DestinationSpends) -> Result<(), PolicyError> {
+        match self.policy_type {
+            PolicyType::PerDestinationLimit => {
+                let limits = self.destination_limits().ok_or(PolicyError::MalformedConfig)?;
+                match &context.destination {
+                    Some(destination) if !limits.allows(destination, context.amount.lamports(), context.timestamp, spends) => {
+                        Err(PolicyError::OverDestinationLimit)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            PolicyType::Composite => self.check_rules(|rule| rule.check_destination_spend(context, spends)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a transfer stays within its destination's budget (see `check_destination_spend`)
+    #[deprecated(note = "use `check_destination_spend`, which says why a transaction is denied")]
+    pub fn evaluate_destination_spend(&self, context: &PolicyContext, spends: &DestinationSpends) -> bool {
+        self.check_destination_spend(context, spends).is_ok()
+    }
+
+    /// Whether this policy, or a rule of it, is a `PerDestinationLimit`
+    pub fn has_destination_limit(&self) -> bool {
+        match self.policy_type {
+            PolicyType::PerDestinationLimit => true,
+            PolicyType::Composite => self.rules().unwrap_or_default().iter().any(Policy::has_destination_limit),
+            _ => false,
+        }
+    }
+
+    /// Counts an executed transfer against its destination's budget
+    ///
+    /// Does nothing for policies without a per-destination limit, or for a
+    /// transaction that doesn't say where it sends funds.
+    pub fn record_destination_spend(&self, context: &PolicyContext, spends: &mut DestinationSpends) {
+        match self.policy_type {
+            PolicyType::PerDestinationLimit => {
+                let window_start = self.destination_limits().and_then(|limits| limits.window_start(context.timestamp));
+                if let (Some(window_start), Some(destination)) = (window_start, &context.destination) {
+                    spends.record(window_start, destination, context.amount.lamports());
+                }
+            }
+            PolicyType::Composite => {
+                for rule in self.rules().unwrap_or_default() {
+                    rule.record_destination_spend(context, spends);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs `check` on each rule of a `Composite` policy, stopping at the first that fails
+    ///
+    /// Malformed or nested composites fail closed.
+    fn check_rules(&self, check: impl Fn(&Policy) -> Result<(), PolicyError>) -> Result<(), PolicyError> {
+        let rules = self.rules().ok_or(PolicyError::MalformedConfig)?;
+        for rule in &rules {
+            if rule.policy_type == PolicyType::Composite {
+                return Err(PolicyError::MalformedConfig);
+            }
+            check(rule)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `key` is in a key-list config (`MultiSig`, `DestinationAllowlist`)
+    fn lists_key(&self, key: &Pubkey) -> bool {
+        self.config.chunks_exact(32).any(|chunk| chunk == key.as_ref())
+    }
+
+    /// Estimates the compute units `execute` spends evaluating this policy
+    ///
+    /// Grows with the config: per mint limit, per listed key, and per rule
+    /// of a composite. Costs come from `compute_units`; anything estimated
+    /// over `MAX_POLICY_COMPUTE_UNITS` is refused on-chain.
+    pub fn estimated_compute_units(&self) -> u32 {
+        compute_units::BASE.saturating_add(self.evaluation_compute_units())
+    }
+
+    /// `estimated_compute_units` without the one-off base cost
+    fn evaluation_compute_units(&self) -> u32 {
+        let config_len = u32::try_from(self.config.len()).unwrap_or(u32::MAX);
+        let count = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+
+        let elements = match self.policy_type {
+            PolicyType::Open | PolicyType::TimeLocked | PolicyType::Vault => 0,
+            PolicyType::SpendingLimit | PolicyType::DailyLimit => self
+                .mint_limits()
+                .map(|limits| count(limits.limits.len()).saturating_mul(compute_units::PER_MINT_LIMIT))
+                .unwrap_or(0),
+            PolicyType::MultiSig | PolicyType::DestinationAllowlist => {
+                count(self.config.len() / 32).saturating_mul(compute_units::PER_KEY)
+            }
+            PolicyType::CredentialBinding => self
+                .credential_bindings()
+                .map(|bindings| {
+                    count(bindings.destination_count() + bindings.bindings.len())
+                        .saturating_mul(compute_units::PER_KEY)
+                })
+                .unwrap_or(0),
+            PolicyType::Composite => self
+                .rules()
+                .unwrap_or_default()
+                .iter()
+                .map(|rule| compute_units::PER_RULE.saturating_add(rule.evaluation_compute_units()))
+                .fold(0u32, u32::saturating_add),
+            PolicyType::PerDestinationLimit => self
+                .destination_limits()
+                .map(|limits| count(limits.limits.len()).saturating_mul(compute_units::PER_KEY))
+                .unwrap_or(0)
+                .saturating_add(compute_units::DESTINATION_TRACKER),
+        };
+        config_len.saturating_mul(compute_units::PER_CONFIG_BYTE).saturating_add(elements)
+    }
+
+    /// Length of `to_bytes()`, without serializing
+    pub fn serialized_size(&self) -> usize {
+        // policy_type (1) + config length (4) + config
+        1 + 4 + self.config.len()
+    }
+
+    /// Serializes the policy to bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
+        borsh::to_vec(self)
+    }
+
+    /// Deserializes bytes into a Policy
+    pub fn from_bytes(data: &[u8]) -> Result<Self, std::io::Error> {
+        borsh::from_slice(data)
+    }
+
+    /// SHA-256 of the policy in a canonical form
+    ///
+    /// Policies that differ only in the order of a list - multisig signers,
+    /// allowlisted destinations, per-mint limits, credential bindings,
+    /// per-destination limits, the rules of a composite - allow the same
+    /// transactions, and hash the
+    /// same. Any other difference changes the hash. A config that doesn't
+    /// decode is hashed as it is.
+    pub fn canonical_hash(&self) -> [u8; 32] {
+        // Serializing into a Vec can't fail
+        domain_hash(Domain::PolicyCanonical, &borsh::to_vec(&self.canonical()).unwrap_or_default())
+    }
+
+    /// The policy with every list in its config sorted
+    fn canonical(&self) -> Policy {
+        let config = match self.policy_type {
+            PolicyType::Open | PolicyType::TimeLocked | PolicyType::Vault => None,
+            PolicyType::MultiSig | PolicyType::DestinationAllowlist => {
+                let keys = self.config.chunks_exact(32);
+                keys.remainder().is_empty().then(|| {
+                    let mut keys: Vec<&[u8]> = keys.collect();
+                    keys.sort();
+                    keys.concat()
+                })
+            }
+            PolicyType::SpendingLimit | PolicyType::DailyLimit => self.mint_limits().map(|mut mint_limits| {
+                // Stable, so a mint listed twice keeps its first limit first
+                mint_limits.limits.sort_by_key(|limit| limit.mint);
+                self.clone().with_mint_limits(mint_limits).config
+            }),
+            PolicyType::Composite => self.rules().map(|rules| {
+                let mut rules: Vec<Policy> = rules.iter().map(Policy::canonical).collect();
+                rules.sort_by_cached_key(|rule| borsh::to_vec(rule).unwrap_or_default());
+                borsh::to_vec(&rules).unwrap_or_default()
+            }),
+            // No destination is in two bindings, so their order doesn't matter either
+            PolicyType::CredentialBinding => self.credential_bindings().map(|mut bindings| {
+                for binding in &mut bindings.bindings {
+                    binding.destinations.sort();
+                }
+                bindings.bindings.sort_by_cached_key(|binding| borsh::to_vec(binding).unwrap_or_default());
+                borsh::to_vec(&bindings).unwrap_or_default()
+            }),
+            PolicyType::PerDestinationLimit => self.destination_limits().map(|mut limits| {
+                limits.limits.sort_by_key(|limit| limit.destination);
+                borsh::to_vec(&limits).unwrap_or_default()
+            }),
+        };
+        Policy {
+            policy_type: self.policy_type,
+            config: config.unwrap_or_else(|| self.config.clone()),
+        }
+    }
+}
+
+/// Builds a validated `Policy` from typed settings
+///
+/// Setting one rule builds that policy; setting several builds a
+/// `Composite` policy that a transaction must pass in full. Nothing set
+/// builds an open policy.
+///
+/// # Example
+/// ```ignore
+/// let policy = PolicyBuilder::new()
+///     .spending_limit(Amount::from_lamports(1_000_000_000))
+///     .unlock_at(1_800_000_000)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PolicyBuilder {
+    spending_limit: Option<Amount>,
+    daily_limit: Option<(Amount, u32, i64)>,
+    mint_limits: Option<MintLimits>,
+    unlock_at: Option<i64>,
+    signers: Option<Vec<Pubkey>>,
+    destinations: Option<Vec<Pubkey>>,
+    credential_bindings: Option<CredentialBindings>,
+    destination_limits: Option<DestinationLimits>,
+    vault: Option<(Amount, u32)>,
+    now: Option<i64>,
+    allow_long_lock: bool,
+}
+
+impl PolicyBuilder {
+    /// Starts an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caps each transaction at `max_amount`
+    pub fn spending_limit(mut self, max_amount: Amount) -> Self {
+        self.spending_limit = Some(max_amount);
+        self
+    }
+
+    /// Caps spending at `max_amount` per day, with days starting at `anchor`
+    pub fn daily_limit(self, max_amount: Amount, anchor: i64) -> Self {
+        self.windowed_limit(max_amount, SECONDS_PER_DAY, anchor)
+    }
+
+    /// Caps spending at `max_amount` per window of `window_seconds`, the
+    /// first starting at `anchor` (replaces any daily limit)
+    pub fn windowed_limit(mut self, max_amount: Amount, window_seconds: u32, anchor: i64) -> Self {
+        self.daily_limit = Some((max_amount, window_seconds, anchor));
+        self
+    }
+
+    /// Adds per-mint token limits to the spending (or daily) limit
+    pub fn mint_limits(mut self, mint_limits: MintLimits) -> Self {
+        self.mint_limits = Some(mint_limits);
+        self
+    }
+
+    /// Locks the account until `timestamp`
+    pub fn unlock_at(mut self, timestamp: i64) -> Self {
+        self.unlock_at = Some(timestamp);
+        self
+    }
+
+    /// Requires signatures from all of `signers`
+    pub fn require_signers(mut self, signers: &[Pubkey]) -> Self {
+        self.signers = Some(signers.to_vec());
+        self
+    }
+
+    /// Only allows transfers to `destinations`
+    pub fn destinations(mut self, destinations: &[Pubkey]) -> Self {
+        self.destinations = Some(destinations.to_vec());
+        self
+    }
+
+    /// Requires particular passkeys to sign transfers to particular destinations
+    pub fn credential_bindings(mut self, bindings: CredentialBindings) -> Self {
+        self.credential_bindings = Some(bindings);
+        self
+    }
+
+    /// Caps what each destination may be sent per window
+    pub fn destination_limits(mut self, limits: DestinationLimits) -> Self {
+        self.destination_limits = Some(limits);
+        self
+    }
+
+    /// Holds back transfers over `threshold` for `delay_seconds`, during
+    /// which any passkey can veto them
+    pub fn vault(mut self, threshold: Amount, delay_seconds: u32) -> Self {
+        self.vault = Some((threshold, delay_seconds));
+        self
+    }
+
+    /// Checks timestamps against `now` when building, too (see
+    /// `Policy::validate_timestamps`)
+    pub fn checked_at(mut self, now: i64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Lets the unlock time be more than `MAX_FUTURE_SECONDS` away
+    ///
+    /// The program only accepts such a policy from `add_policy` or
+    /// `replace_policy` with their long-lock flag set, signed by a passkey.
+    pub fn allow_long_lock(mut self) -> Self {
+        self.allow_long_lock = true;
+        self
+    }
+
+    /// Builds the policy, checking it with `Policy::validate_config`, and
+    /// with `Policy::validate_timestamps` if `checked_at` was set
+    pub fn build(self) -> Result<Policy, PolicyBuildError> {
+        if self.mint_limits.is_some() && self.spending_limit.is_none() && self.daily_limit.is_none() {
+            return Err(PolicyBuildError::MintLimitsWithoutLimit);
+        }
+
+        let with_mint_limits = |policy: Policy| match &self.mint_limits {
+            Some(limits) => policy.with_mint_limits(limits.clone()),
+            None => policy,
+        };
+
+        let mut rules = Vec::new();
+        if let Some(max_amount) = self.spending_limit {
+            rules.push(with_mint_limits(Policy::spending_limit(max_amount)));
+        }
+        if let Some((max_amount, window_seconds, anchor)) = self.daily_limit {
+            rules.push(with_mint_limits(Policy::windowed_limit(max_amount, window_seconds, anchor)));
+        }
+        if let Some(timestamp) = self.unlock_at {
+            rules.push(Policy::time_locked(timestamp));
+        }
+        if let Some(signers) = &self.signers {
+            rules.push(Policy::multi_sig(signers.clone()));
+        }
+        if let Some(destinations) = &self.destinations {
+            rules.push(Policy::destination_allowlist(destinations.clone()));
+        }
+        if let Some(bindings) = &self.credential_bindings {
+            rules.push(Policy::credential_binding(bindings.clone()));
+        }
+        if let Some(limits) = &self.destination_limits {
+            rules.push(Policy::per_destination_limit(limits.clone()));
+        }
+        if let Some((threshold, delay_seconds)) = self.vault {
+            rules.push(Policy::vault(threshold, delay_seconds));
+        }
+
+        let policy = match rules.len() {
+            0 => Policy::open(),
+            1 => rules.remove(0),
+            _ => Policy::composite(rules),
+        };
+        policy.validate_config()?;
+        if let Some(now) = self.now {
+            policy.validate_timestamps(now, self.allow_long_lock)?;
+        }
+        Ok(policy)
+    }
+}
+
+fn validate_timestamp_range(timestamp: i64) -> Result<(), PolicyBuildError> {
+    if (MIN_POLICY_TIMESTAMP..=MAX_POLICY_TIMESTAMP).contains(&timestamp) {
+        Ok(())
+    } else {
+        Err(PolicyBuildError::TimestampOutOfRange(timestamp))
+    }
+}
+
+/// Checks a list of 32-byte keys and returns how many there are
+fn validate_key_list(config: &[u8]) -> Result<usize, PolicyBuildError> {
+    let chunks = config.chunks_exact(32);
+    if !chunks.remainder().is_empty() {
+        return Err(PolicyBuildError::MalformedConfig);
+    }
+    let keys: Vec<&[u8]> = chunks.collect();
+    for (i, key) in keys.iter().enumerate() {
+        if keys.iter().take(i).any(|seen| seen == key) {
+            return Err(PolicyBuildError::DuplicateKey);
+        }
+    }
+    Ok(keys.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_open_policy() {
+        let policy = Policy::open();
+        assert!(policy.check(1000, 1234567890).is_ok());
+        assert!(policy.check(1_000_000_000, 1234567890).is_ok());
+    }
+
+    #[test]
+    fn test_spending_limit_policy() {
+        let policy = Policy::spending_limit(Amount::from_lamports(1_000_000_000)); // 1 SOL
+        
+        assert!(policy.check(500_000_000, 1234567890).is_ok()); // 0.5 SOL - allowed
+        assert!(policy.check(1_000_000_000, 1234567890).is_ok()); // 1 SOL - allowed (at limit)
+        assert!(policy.check(1_000_000_001, 1234567890).is_err()); // More than 1 SOL - denied
+    }
+
+    #[test]
+    fn test_time_locked_policy() {
+        let unlock_time = 2000000000i64;
+        let policy = Policy::time_locked(unlock_time);
+        
+        assert!(policy.check(1000, 1000000000).is_err()); // Before unlock - denied
+        assert!(policy.check(1000, unlock_time).is_ok()); // At unlock time - allowed
+        assert!(policy.check(1000, 3000000000).is_ok()); // After unlock - allowed
+    }
+
+    #[test]
+    fn test_daily_limit_policy() {
+        let reset_time = 2000000000i64;
+        let policy = Policy::daily_limit(Amount::from_lamports(1_000_000_000), reset_time);
+        
+        // Before reset time - check per-transaction limit
+        assert!(policy.check(500_000_000, 1000000000).is_ok());
+        assert!(policy.check(1_000_000_001, 1000000000).is_err());
+        
+        // After reset time - limit has reset
+        assert!(policy.check(500_000_000, reset_time + 1).is_ok());
+    }
+
+    /// Spends `amount` if the policy allows it, returning whether it did
+    fn spend(policy: &Policy, spend: &mut LimitSpend, amount: u64, now: i64) -> bool {
+        let allowed = policy.check_with_spend(amount, now, spend).is_ok();
+        if allowed {
+            policy.record_spend(amount, now, spend);
+        }
+        allowed
+    }
+
+    #[test]
+    fn test_daily_limit_resets_every_window() {
+        let day = i64::from(SECONDS_PER_DAY);
+        let anchor = NEXT_YEAR;
+        let policy = Policy::daily_limit(Amount::from_lamports(100), anchor);
+        let mut spent = LimitSpend::default();
+
+        for day_index in 0..5 {
+            let morning = anchor + day_index * day + 1;
+            assert!(spend(&policy, &mut spent, 60, morning), "day {}", day_index);
+            assert!(spend(&policy, &mut spent, 40, morning + 100), "day {}", day_index);
+            // The last second of the window still counts towards it
+            assert!(!spend(&policy, &mut spent, 1, anchor + (day_index + 1) * day - 1), "day {}", day_index);
+            assert_eq!(spent, LimitSpend { window_start: anchor + day_index * day, spent: 100, reserved: 0 });
+        }
+
+        // Skipping whole windows doesn't carry anything over
+        assert!(spend(&policy, &mut spent, 100, anchor + 30 * day));
+        assert_eq!(spent.window_start, anchor + 30 * day);
+    }
+
+    #[test]
+    fn test_reservations_use_up_headroom_until_released() {
+        let anchor = NEXT_YEAR;
+        let policy = Policy::daily_limit(Amount::from_lamports(100), anchor);
+        let mut spend = LimitSpend::default();
+
+        assert!(policy.check_and_reserve(60, anchor + 10, &mut spend).is_ok());
+        assert!(policy.check_and_reserve(50, anchor + 20, &mut spend).is_err());
+        assert!(policy.check_with_spend(41, anchor + 20, &spend).is_err());
+        assert!(policy.check_with_spend(40, anchor + 20, &spend).is_ok());
+        assert_eq!(spend.reserved, 60);
+
+        // Expiring the proposal gives the headroom back
+        spend.release(60);
+        assert!(policy.check_with_spend(100, anchor + 30, &spend).is_ok());
+        assert_eq!(spend, LimitSpend::default());
+    }
+
+    #[test]
+    fn test_reservations_carry_over_into_the_next_window() {
+        let day = i64::from(SECONDS_PER_DAY);
+        let anchor = NEXT_YEAR;
+        let policy = Policy::daily_limit(Amount::from_lamports(100), anchor);
+        let mut spend = LimitSpend::default();
+
+        // Two 50s proposed just before midnight fill the day...
+        assert!(policy.check_and_reserve(50, anchor + day - 2, &mut spend).is_ok());
+        assert!(policy.check_and_reserve(50, anchor + day - 1, &mut spend).is_ok());
+        assert!(policy.check_and_reserve(50, anchor + day - 1, &mut spend).is_err());
+
+        // ...and still count once the next day starts
+        assert!(policy.check_with_spend(1, anchor + day, &spend).is_err());
+        assert!(policy.check_reserved(50, anchor + day, &spend).is_ok());
+
+        // Executing one moves it into the new day's spending
+        policy.settle_spend(50, anchor + day, &mut spend);
+        assert_eq!(spend, LimitSpend { window_start: anchor + day, spent: 50, reserved: 50 });
+        assert!(policy.check_reserved(50, anchor + day + 1, &spend).is_ok());
+        assert!(policy.check_with_spend(1, anchor + day + 1, &spend).is_err());
+
+        // A reserved transaction can't execute past what the day has left
+        policy.record_spend(30, anchor + day + 2, &mut spend);
+        assert!(policy.check_reserved(50, anchor + day + 3, &spend).is_err());
+        assert!(policy.check_reserved(50, anchor + 2 * day, &spend).is_ok());
+    }
+
+    #[test]
+    fn test_daily_limit_counts_time_before_anchor_as_first_window() {
+        let anchor = NEXT_YEAR;
+        let policy = Policy::windowed_limit(Amount::from_lamports(100), 3_600, anchor);
+        let mut spent = LimitSpend::default();
+
+        assert!(spend(&policy, &mut spent, 70, anchor - 10_000));
+        assert!(!spend(&policy, &mut spent, 31, anchor + 3_599));
+        assert!(spend(&policy, &mut spent, 100, anchor + 3_600));
+        assert_eq!(policy.daily_limit_config().unwrap().window_start(i64::MIN), Some(anchor));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_legacy_daily_limit_windows_end_at_reset() {
+        let reset = NEXT_YEAR;
+        let mut config = 100u64.to_le_bytes().to_vec();
+        config.extend_from_slice(&reset.to_le_bytes());
+        let policy = Policy::new(PolicyType::DailyLimit, config);
+
+        let limit = policy.daily_limit_config().unwrap();
+        assert_eq!(limit.window_seconds, SECONDS_PER_DAY);
+        assert_eq!(limit.window_start(reset - 1), Some(reset - i64::from(SECONDS_PER_DAY)));
+        assert_eq!(policy.validate_config(), Ok(()));
+
+        // The limit keeps resetting daily after the stored reset time
+        let mut spent = LimitSpend::default();
+        assert!(spend(&policy, &mut spent, 100, reset - 1));
+        assert!(!spend(&policy, &mut spent, 1, reset - 1));
+        assert!(spend(&policy, &mut spent, 100, reset));
+        assert!(spend(&policy, &mut spent, 100, reset + 3 * i64::from(SECONDS_PER_DAY)));
+
+        // Mint limits after the legacy config are still found
+        let (usdc, limits) = usdc_limits(false);
+        let policy = policy.with_mint_limits(limits.clone());
+        assert_eq!(policy.config.len(), 16 + borsh::to_vec(&limits).unwrap().len());
+        assert_eq!(policy.mint_limits(), Some(limits));
+        assert!(policy.check_context(&PolicyContext::token(usdc, 100_000_000, 6, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_zero_window_is_rejected() {
+        let policy = Policy::windowed_limit(Amount::from_lamports(1), 0, NEXT_YEAR);
+        assert_eq!(policy.validate_config(), Err(PolicyBuildError::ZeroWindow));
+        assert!(policy.check(0, NEXT_YEAR).is_err());
+    }
+
+    fn usdc_limits(allow_unlisted: bool) -> (Pubkey, MintLimits) {
+        let usdc = Pubkey::new_unique();
+        let limits = MintLimits {
+            allow_unlisted,
+            limits: vec![MintLimit { mint: usdc, max_amount: 100_000_000, decimals: 6 }],
+        };
+        (usdc, limits)
+    }
+
+    #[test]
+    fn test_spending_limit_with_mint_limits() {
+        let (usdc, limits) = usdc_limits(false);
+        let policy = Policy::spending_limit(Amount::from_lamports(1_000_000_000)).with_mint_limits(limits.clone());
+
+        assert_eq!(policy.mint_limits(), Some(limits));
+        // The SOL limit still applies
+        assert!(policy.check(1_000_000_000, 0).is_ok());
+        assert!(policy.check(1_000_000_001, 0).is_err());
+
+        assert!(policy.check_context(&PolicyContext::token(usdc, 100_000_000, 6, 0)).is_ok());
+        assert!(policy.check_context(&PolicyContext::token(usdc, 100_000_001, 6, 0)).is_err());
+    }
+
+    #[test]
+    fn test_mint_limit_requires_matching_decimals() {
+        let (usdc, limits) = usdc_limits(false);
+        let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(limits);
+
+        // 1 unit with 0 decimals is not the same amount as with 6
+        assert!(policy.check_context(&PolicyContext::token(usdc, 1, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_unlisted_mints_denied_by_default() {
+        let (_, limits) = usdc_limits(false);
+        let other = Pubkey::new_unique();
+
+        let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(limits);
+        assert!(policy.check_context(&PolicyContext::token(other, 1, 6, 0)).is_err());
+
+        // A limit policy without any token limits denies all token transfers
+        let sol_only = Policy::spending_limit(Amount::from_lamports(1_000_000_000));
+        assert_eq!(sol_only.mint_limits(), None);
+        assert!(sol_only.check_context(&PolicyContext::token(other, 1, 6, 0)).is_err());
+    }
+
+    #[test]
+    fn test_unlisted_mints_allowed_when_overridden() {
+        let (usdc, limits) = usdc_limits(true);
+        let policy = Policy::daily_limit(Amount::ZERO, 0).with_mint_limits(limits);
+
+        assert!(policy.check_context(&PolicyContext::token(Pubkey::new_unique(), u64::MAX, 9, 0)).is_ok());
+        // Listed mints are still limited
+        assert!(policy.check_context(&PolicyContext::token(usdc, 100_000_001, 6, 0)).is_err());
+    }
+
+    #[test]
+    fn test_with_mint_limits_replaces_existing_limits() {
+        let (_, first) = usdc_limits(false);
+        let (_, second) = usdc_limits(true);
+
+        let policy = Policy::spending_limit(Amount::from_lamports(5)).with_mint_limits(first).with_mint_limits(second.clone());
+        assert_eq!(policy.mint_limits(), Some(second));
+        assert!(policy.check(5, 0).is_ok());
+    }
+
+    #[test]
+    fn test_non_limit_policies_ignore_mint_limits() {
+        let (usdc, limits) = usdc_limits(false);
+        let policy = Policy::open().with_mint_limits(limits);
+
+        assert!(policy.config.is_empty());
+        assert!(policy.check_context(&PolicyContext::token(usdc, u64::MAX, 6, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_serialize_deserialize() {
+        let policy = Policy::spending_limit(Amount::from_lamports(1_000_000_000));
+        let bytes = policy.to_bytes().unwrap();
+        let deserialized = Policy::from_bytes(&bytes).unwrap();
+        
+        assert_eq!(policy.policy_type, deserialized.policy_type);
+        assert_eq!(policy.config, deserialized.config);
+    }
+
+    #[test]
+    fn test_limit_config_format_is_stable() {
+        // Configs are stored on-chain as bare little-endian lamports
+        let policy = Policy::spending_limit(Amount::from_lamports(1_500_000_000));
+        assert_eq!(policy.to_bytes().unwrap(), [1, 8, 0, 0, 0, 0x00, 0x2f, 0x68, 0x59, 0, 0, 0, 0]);
+
+        let policy = Policy::windowed_limit(Amount::from_lamports(1), 3_600, -1);
+        assert_eq!(policy.config, [
+            1, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0x80,
+            0x10, 0x0e, 0, 0,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+    }
+
+    #[test]
+    fn test_context_carries_amount() {
+        let context = PolicyContext::sol(Amount::from_lamports(1_000_000_001), 0);
+        assert!(Policy::spending_limit(Amount::from_lamports(1_000_000_000)).check_context(&context).is_err());
+    }
+
+    const NEXT_YEAR: i64 = 1_800_000_000;
+
+    #[test]
+    fn test_builder_single_rule_builds_plain_policy() {
+        let policy = PolicyBuilder::new().spending_limit(Amount::from_lamports(5)).build().unwrap();
+        assert_eq!(policy, Policy::spending_limit(Amount::from_lamports(5)));
+
+        assert_eq!(PolicyBuilder::new().build().unwrap(), Policy::open());
+
+        let policy = PolicyBuilder::new()
+            .windowed_limit(Amount::from_lamports(5), 3_600, NEXT_YEAR)
+            .build()
+            .unwrap();
+        assert_eq!(policy, Policy::windowed_limit(Amount::from_lamports(5), 3_600, NEXT_YEAR));
+    }
+
+    #[test]
+    fn test_builder_composite() {
+        let (usdc, limits) = usdc_limits(false);
+        let exchange = Pubkey::new_unique();
+        let policy = PolicyBuilder::new()
+            .spending_limit(Amount::from_lamports(1_000_000_000))
+            .mint_limits(limits)
+            .unlock_at(NEXT_YEAR)
+            .destinations(&[exchange])
+            .build()
+            .unwrap();
+
+        assert_eq!(policy.policy_type, PolicyType::Composite);
+        assert_eq!(policy.rules().unwrap().len(), 3);
+        assert_eq!(policy.validate_config(), Ok(()));
+
+        let transfer = |amount, timestamp, destination| {
+            PolicyContext::token(usdc, amount, 6, timestamp).with_destination(destination)
+        };
+        assert!(policy.check_context(&transfer(100_000_000, NEXT_YEAR, exchange)).is_ok());
+        // Each rule can deny on its own
+        assert!(policy.check_context(&transfer(100_000_001, NEXT_YEAR, exchange)).is_err());
+        assert!(policy.check_context(&transfer(1, NEXT_YEAR - 1, exchange)).is_err());
+        assert!(policy.check_context(&transfer(1, NEXT_YEAR, Pubkey::new_unique())).is_err());
+    }
+
+    #[test]
+    fn test_builder_rejects_zero_limit() {
+        assert_eq!(
+            PolicyBuilder::new().spending_limit(Amount::ZERO).build(),
+            Err(PolicyBuildError::ZeroLimit)
+        );
+        assert_eq!(
+            PolicyBuilder::new().daily_limit(Amount::ZERO, NEXT_YEAR).build(),
+            Err(PolicyBuildError::ZeroLimit)
+        );
+
+        // Zero SOL is fine when tokens are allowed
+        let (usdc, mut limits) = usdc_limits(false);
+        assert!(PolicyBuilder::new().spending_limit(Amount::ZERO).mint_limits(limits.clone()).build().is_ok());
+
+        limits.limits.push(MintLimit { mint: usdc, max_amount: 0, decimals: 6 });
+        assert_eq!(
+            PolicyBuilder::new().spending_limit(Amount::ZERO).mint_limits(limits).build(),
+            Err(PolicyBuildError::ZeroLimit)
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_mint_limits_without_limit() {
+        let (_, limits) = usdc_limits(false);
+        assert_eq!(
+            PolicyBuilder::new().mint_limits(limits).unlock_at(NEXT_YEAR).build(),
+            Err(PolicyBuildError::MintLimitsWithoutLimit)
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_out_of_range_timestamps() {
+        for timestamp in [0, MIN_POLICY_TIMESTAMP - 1, MAX_POLICY_TIMESTAMP + 1, NEXT_YEAR * 1000] {
+            assert_eq!(
+                PolicyBuilder::new().unlock_at(timestamp).build(),
+                Err(PolicyBuildError::TimestampOutOfRange(timestamp))
+            );
+        }
+        assert_eq!(
+            PolicyBuilder::new().daily_limit(Amount::from_lamports(1), -1).build(),
+            Err(PolicyBuildError::TimestampOutOfRange(-1))
+        );
+        assert!(PolicyBuilder::new().unlock_at(MIN_POLICY_TIMESTAMP).build().is_ok());
+        assert!(PolicyBuilder::new().unlock_at(MAX_POLICY_TIMESTAMP).build().is_ok());
+    }
+
+    #[test]
+    fn test_builder_checks_timestamps_against_now() {
+        // Far enough from both ends of the absolute range that only the relative bounds apply
+        const NOW: i64 = 1_900_000_000;
+        let latest = NOW + MAX_FUTURE_SECONDS;
+        let earliest = NOW - MAX_PAST_SECONDS;
+        let cases = [
+            (NOW + 1, false, Ok(())),
+            (latest, false, Ok(())),
+            (latest + 1, false, Err(TimeError::TooFarInFuture { timestamp: latest + 1, latest })),
+            (latest + 1, true, Ok(())),
+            (MAX_POLICY_TIMESTAMP, true, Ok(())),
+            (earliest, true, Ok(())),
+            (earliest - 1, false, Err(TimeError::TooFarInPast { timestamp: earliest - 1, earliest })),
+            (earliest - 1, true, Err(TimeError::TooFarInPast { timestamp: earliest - 1, earliest })),
+        ];
+        for (unlock_at, allow_long_lock, expected) in cases {
+            let mut builder = PolicyBuilder::new().unlock_at(unlock_at).checked_at(NOW);
+            if allow_long_lock {
+                builder = builder.allow_long_lock();
+            }
+            assert_eq!(builder.build().map(|_| ()), expected.map_err(PolicyBuildError::Time), "{unlock_at} {allow_long_lock}");
+        }
+
+        // Locks inside a composite are checked too; limit anchors aren't
+        let composite = PolicyBuilder::new().spending_limit(Amount::from_lamports(1)).unlock_at(latest + 1).checked_at(NOW);
+        assert!(matches!(composite.build(), Err(PolicyBuildError::Time(TimeError::TooFarInFuture { .. }))));
+        let old_anchor = PolicyBuilder::new().daily_limit(Amount::from_lamports(1), MIN_POLICY_TIMESTAMP).checked_at(NOW);
+        assert!(old_anchor.build().is_ok());
+
+        // The absolute range still applies to long locks
+        assert_eq!(
+            PolicyBuilder::new().unlock_at(MAX_POLICY_TIMESTAMP + 1).checked_at(NOW).allow_long_lock().build(),
+            Err(PolicyBuildError::TimestampOutOfRange(MAX_POLICY_TIMESTAMP + 1))
+        );
+    }
+
+    #[test]
+    fn test_builder_checks_signer_count() {
+        assert_eq!(PolicyBuilder::new().require_signers(&[]).build(), Err(PolicyBuildError::SignerCount(0)));
+
+        let signers: Vec<Pubkey> = (0..=MAX_POLICY_SIGNERS).map(|_| Pubkey::new_unique()).collect();
+        assert!(PolicyBuilder::new().require_signers(&signers[..MAX_POLICY_SIGNERS]).build().is_ok());
+        assert_eq!(
+            PolicyBuilder::new().require_signers(&signers).build(),
+            Err(PolicyBuildError::SignerCount(MAX_POLICY_SIGNERS + 1))
+        );
+
+        let signer = Pubkey::new_unique();
+        assert_eq!(
+            PolicyBuilder::new().require_signers(&[signer, signer]).build(),
+            Err(PolicyBuildError::DuplicateKey)
+        );
+    }
+
+    #[test]
+    fn test_builder_checks_destination_count() {
+        assert_eq!(PolicyBuilder::new().destinations(&[]).build(), Err(PolicyBuildError::DestinationCount(0)));
+
+        let destinations: Vec<Pubkey> = (0..=MAX_POLICY_DESTINATIONS).map(|_| Pubkey::new_unique()).collect();
+        assert_eq!(
+            PolicyBuilder::new().destinations(&destinations).build(),
+            Err(PolicyBuildError::DestinationCount(MAX_POLICY_DESTINATIONS + 1))
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_validate_config_rejects_malformed_configs() {
+        let malformed = [
+            Policy::new(PolicyType::SpendingLimit, vec![1, 2, 3]),
+            Policy::new(PolicyType::Open, vec![1]),
+            Policy::new(PolicyType::TimeLocked, vec![0; 9]),
+            Policy::new(PolicyType::MultiSig, vec![7; 33]),
+            Policy::new(PolicyType::Composite, vec![1, 2]),
+            Policy::new(PolicyType::Composite, borsh::to_vec(&Vec::<Policy>::new()).unwrap()),
+        ];
+        for policy in malformed {
+            assert_eq!(policy.validate_config(), Err(PolicyBuildError::MalformedConfig), "{:?}", policy);
+        }
+
+        let inner = Policy::composite(vec![Policy::time_locked(NEXT_YEAR)]);
+        assert_eq!(
+            Policy::composite(vec![inner.clone()]).validate_config(),
+            Err(PolicyBuildError::NestedComposite)
+        );
+        // ...and one that got stored anyway denies
+        assert!(Policy::composite(vec![inner]).check(0, NEXT_YEAR).is_err());
+    }
+
+    fn cold_wallet_bindings(cold: Pubkey, hardware_key: [u8; 32]) -> CredentialBindings {
+        CredentialBindings {
+            bindings: vec![CredentialBinding { destinations: vec![cold], credential_id_hash: None }],
+            default: Some(hardware_key),
+        }
+    }
+
+    #[test]
+    fn test_credential_binding() {
+        let (cold, exchange) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (phone, hardware_key) = ([1u8; 32], [2u8; 32]);
+        let policy = Policy::credential_binding(cold_wallet_bindings(cold, hardware_key));
+        assert_eq!(policy.validate_config(), Ok(()));
+        let transfer = |destination: Pubkey, signer: [u8; 32]| {
+            PolicyContext::sol(Amount::from_lamports(1), NEXT_YEAR)
+                .with_destination(destination)
+                .with_signer(signer)
+        };
+
+        // The cold wallet takes any passkey
+        assert!(policy.check_context(&transfer(cold, phone)).is_ok());
+        assert!(policy.check_context(&transfer(cold, hardware_key)).is_ok());
+
+        // Anywhere else falls through to the default: the hardware key only
+        assert!(policy.check_context(&transfer(exchange, hardware_key)).is_ok());
+        assert!(policy.check_context(&transfer(exchange, phone)).is_err());
+
+        // So do transactions without a destination, and unknown signers never match
+        let no_destination = PolicyContext::sol(Amount::ZERO, NEXT_YEAR);
+        assert!(policy.check_context(&no_destination.with_signer(hardware_key)).is_ok());
+        assert!(policy.check_context(&no_destination).is_err());
+        assert!(policy.check(0, NEXT_YEAR).is_err());
+    }
+
+    #[test]
+    fn test_credential_binding_validation() {
+        let cold = Pubkey::new_unique();
+        let open_to_all = CredentialBindings {
+            bindings: vec![CredentialBinding { destinations: vec![cold], credential_id_hash: None }],
+            default: None,
+        };
+        assert_eq!(
+            Policy::credential_binding(open_to_all).validate_config(),
+            Err(PolicyBuildError::MalformedConfig)
+        );
+
+        let mut duplicated = cold_wallet_bindings(cold, [2; 32]);
+        duplicated.bindings.push(CredentialBinding { destinations: vec![cold], credential_id_hash: Some([1; 32]) });
+        assert_eq!(
+            Policy::credential_binding(duplicated).validate_config(),
+            Err(PolicyBuildError::DuplicateKey)
+        );
+
+        let mut too_many = cold_wallet_bindings(cold, [2; 32]);
+        too_many.bindings[0].destinations = (0..=MAX_POLICY_DESTINATIONS).map(|_| Pubkey::new_unique()).collect();
+        assert_eq!(
+            Policy::credential_binding(too_many).validate_config(),
+            Err(PolicyBuildError::DestinationCount(MAX_POLICY_DESTINATIONS + 1))
+        );
+
+        // A malformed config denies
+        let garbage = Policy { policy_type: PolicyType::CredentialBinding, config: vec![1, 2, 3] };
+        assert_eq!(garbage.validate_config(), Err(PolicyBuildError::MalformedConfig));
+        assert!(garbage.check_context(&PolicyContext::sol(Amount::ZERO, NEXT_YEAR).with_signer([2; 32])).is_err());
+    }
+
+    #[test]
+    fn test_check_reports_why() {
+        let (allowed, elsewhere, cold) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let sol = |amount: u64| PolicyContext::sol(Amount::from_lamports(amount), NEXT_YEAR);
+
+        let garbage = Policy { policy_type: PolicyType::SpendingLimit, config: vec![1, 2, 3] };
+        assert_eq!(garbage.check(0, NEXT_YEAR), Err(PolicyError::MalformedConfig));
+
+        let limit = Policy::spending_limit(Amount::from_lamports(100));
+        assert_eq!(limit.check(101, NEXT_YEAR), Err(PolicyError::OverSpendingLimit));
+
+        let daily = Policy::daily_limit(Amount::from_lamports(100), NEXT_YEAR);
+        let mut spend = LimitSpend::default();
+        assert_eq!(daily.check_and_reserve(60, NEXT_YEAR, &mut spend), Ok(()));
+        daily.settle_spend(60, NEXT_YEAR, &mut spend);
+        assert_eq!(daily.check_with_spend(41, NEXT_YEAR, &spend), Err(PolicyError::OverWindowLimit));
+
+        let locked = Policy::time_locked(NEXT_YEAR + 1);
+        assert_eq!(locked.check(0, NEXT_YEAR), Err(PolicyError::TimeLocked));
+
+        let allowlist = Policy::destination_allowlist(vec![allowed]);
+        assert_eq!(allowlist.check_context(&sol(1).with_destination(elsewhere)), Err(PolicyError::DestinationNotAllowed));
+
+        let binding = Policy::credential_binding(cold_wallet_bindings(cold, [2; 32]));
+        assert_eq!(
+            binding.check_context(&sol(1).with_destination(elsewhere).with_signer([1; 32])),
+            Err(PolicyError::CredentialNotBound)
+        );
+
+        let per_destination = Policy::per_destination_limit(payroll_limits(allowed));
+        assert_eq!(
+            per_destination.check_destination_spend(&sol(101).with_destination(elsewhere), &DestinationSpends::default()),
+            Err(PolicyError::OverDestinationLimit)
+        );
+
+        let (usdc, limits) = usdc_limits(false);
+        let mint_limited = Policy::spending_limit(Amount::ZERO).with_mint_limits(limits);
+        assert_eq!(
+            mint_limited.check_context(&PolicyContext::token(usdc, 100_000_001, 6, 0)),
+            Err(PolicyError::OverMintLimit)
+        );
+
+        // A composite reports the first rule that denies
+        let composite = Policy::composite(vec![limit, locked]);
+        assert_eq!(composite.check(101, NEXT_YEAR + 1), Err(PolicyError::OverSpendingLimit));
+        assert_eq!(composite.check(1, NEXT_YEAR), Err(PolicyError::TimeLocked));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_evaluate_wrappers_agree_with_check() {
+        let limit = Policy::spending_limit(Amount::from_lamports(100));
+        assert!(limit.evaluate(100, NEXT_YEAR));
+        assert!(!limit.evaluate(101, NEXT_YEAR));
+        assert!(!Policy::time_locked(NEXT_YEAR + 1).evaluate_context(&PolicyContext::sol(Amount::ZERO, NEXT_YEAR)));
+    }
+
+    #[test]
+    fn test_vault_delays_rather_than_denies() {
+        const TWO_DAYS: u32 = 2 * SECONDS_PER_DAY;
+        let vault = Policy::vault(Amount::from_lamports(1_000), TWO_DAYS);
+        assert_eq!(vault.validate_config(), Ok(()));
+        assert_eq!(vault.vault_config(), Some(VaultConfig { threshold_lamports: 1_000, delay_seconds: TWO_DAYS }));
+        assert_eq!(vault.config.len(), VaultConfig::SERIALIZED_SIZE);
+
+        // Nothing is denied, only held back
+        assert_eq!(vault.check(u64::MAX, NEXT_YEAR), Ok(()));
+        assert_eq!(vault.announcement_delay(1_000), None);
+        assert_eq!(vault.announcement_delay(1_001), Some(TWO_DAYS));
+
+        // In a composite, the longest delay that applies wins
+        let composite = Policy::composite(vec![Policy::vault(Amount::from_lamports(100), 60), vault.clone()]);
+        assert_eq!(composite.announcement_delay(100), None);
+        assert_eq!(composite.announcement_delay(101), Some(60));
+        assert_eq!(composite.announcement_delay(1_001), Some(TWO_DAYS));
+        assert_eq!(Policy::spending_limit(Amount::from_lamports(1)).announcement_delay(u64::MAX), None);
+
+        assert_eq!(Policy::vault(Amount::ZERO, 0).validate_config(), Err(PolicyBuildError::VaultDelay(0)));
+        assert_eq!(
+            Policy::vault(Amount::ZERO, MAX_VAULT_DELAY_SECONDS + 1).validate_config(),
+            Err(PolicyBuildError::VaultDelay(MAX_VAULT_DELAY_SECONDS + 1))
+        );
+        let garbage = Policy { policy_type: PolicyType::Vault, config: vec![1, 2, 3] };
+        assert_eq!(garbage.validate_config(), Err(PolicyBuildError::MalformedConfig));
+        assert_eq!(garbage.check(0, NEXT_YEAR), Err(PolicyError::MalformedConfig));
+        assert_eq!(garbage.announcement_delay(u64::MAX), None);
+
+        let built = PolicyBuilder::new().vault(Amount::from_lamports(1_000), TWO_DAYS).build().unwrap();
+        assert_eq!(built, vault);
+    }
+
+    /// 1000 a day to `payroll`, 100 a day to anywhere else
+    fn payroll_limits(payroll: Pubkey) -> DestinationLimits {
+        DestinationLimits {
+            limits: vec![DestinationLimit { destination: payroll, max_amount: Some(1_000) }],
+            default_max_amount: 100,
+            window_seconds: SECONDS_PER_DAY,
+            anchor_timestamp: NEXT_YEAR,
+        }
+    }
+
+    /// Sends `amount` to `destination` if the policy allows it, returning whether it did
+    fn send(policy: &Policy, spends: &mut DestinationSpends, destination: Pubkey, amount: u64, now: i64) -> bool {
+        let context = PolicyContext::sol(Amount::from_lamports(amount), now).with_destination(destination);
+        let allowed = policy.check_context(&context).is_ok() && policy.check_destination_spend(&context, spends).is_ok();
+        if allowed {
+            policy.record_destination_spend(&context, spends);
+        }
+        allowed
+    }
+
+    #[test]
+    fn test_per_destination_limit_listed_and_default_buckets() {
+        let (payroll, vendor, other) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let policy = Policy::per_destination_limit(payroll_limits(payroll));
+        assert_eq!(policy.validate_config(), Ok(()));
+        let mut spends = DestinationSpends::default();
+
+        assert!(send(&policy, &mut spends, payroll, 600, NEXT_YEAR));
+        assert!(send(&policy, &mut spends, payroll, 400, NEXT_YEAR));
+        assert!(!send(&policy, &mut spends, payroll, 1, NEXT_YEAR));
+
+        // Each unlisted destination has a budget of its own
+        assert!(!send(&policy, &mut spends, vendor, 101, NEXT_YEAR));
+        assert!(send(&policy, &mut spends, vendor, 100, NEXT_YEAR));
+        assert!(!send(&policy, &mut spends, vendor, 1, NEXT_YEAR));
+        assert!(send(&policy, &mut spends, other, 100, NEXT_YEAR));
+        assert_eq!(spends.entries.len(), 3);
+
+        // Without spend state only single transfers are checked, and only transfers
+        assert!(policy.check_context(&PolicyContext::sol(Amount::from_lamports(1_000), NEXT_YEAR).with_destination(payroll)).is_ok());
+        assert!(policy.check_context(&PolicyContext::sol(Amount::from_lamports(101), NEXT_YEAR).with_destination(vendor)).is_err());
+        assert!(policy.check_context(&PolicyContext::sol(Amount::from_lamports(u64::MAX), NEXT_YEAR)).is_ok());
+
+        // An unlimited destination is never tracked against a budget
+        let mut limits = payroll_limits(payroll);
+        limits.limits[0].max_amount = None;
+        let unlimited = Policy::per_destination_limit(limits);
+        assert!(send(&unlimited, &mut DestinationSpends::default(), payroll, u64::MAX, NEXT_YEAR));
+    }
+
+    #[test]
+    fn test_per_destination_limit_rolls_over_each_window() {
+        let (payroll, vendor) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let policy = Policy::per_destination_limit(payroll_limits(payroll));
+        let day = i64::from(SECONDS_PER_DAY);
+        let mut spends = DestinationSpends::default();
+
+        assert!(send(&policy, &mut spends, payroll, 1_000, NEXT_YEAR));
+        assert!(send(&policy, &mut spends, vendor, 100, NEXT_YEAR + day - 1));
+        assert!(!send(&policy, &mut spends, payroll, 1, NEXT_YEAR + day - 1));
+
+        // The next window starts from nothing, and drops the old totals
+        assert!(send(&policy, &mut spends, vendor, 100, NEXT_YEAR + day));
+        assert_eq!(spends.window_start, NEXT_YEAR + day);
+        assert_eq!(spends.entries.len(), 1);
+        assert!(send(&policy, &mut spends, payroll, 1_000, NEXT_YEAR + day + 1));
+        assert!(!send(&policy, &mut spends, payroll, 1, NEXT_YEAR + 2 * day - 1));
+    }
+
+    #[test]
+    fn test_per_destination_eviction_is_no_bypass() {
+        let target = Pubkey::new_unique();
+        let policy = Policy::per_destination_limit(payroll_limits(Pubkey::new_unique()));
+        let mut spends = DestinationSpends::default();
+
+        assert!(send(&policy, &mut spends, target, 100, NEXT_YEAR));
+        // Paying enough other addresses pushes it out of the tracker...
+        for _ in 0..MAX_TRACKED_DESTINATIONS {
+            assert!(send(&policy, &mut spends, Pubkey::new_unique(), 1, NEXT_YEAR));
+        }
+        assert_eq!(spends.entries.len(), MAX_TRACKED_DESTINATIONS);
+        assert_eq!(spends.evicted, 100);
+        assert_eq!(spends.spent_in(NEXT_YEAR, &target), 100);
+
+        // ...but doesn't give it a fresh budget back
+        assert!(!send(&policy, &mut spends, target, 1, NEXT_YEAR));
+        // New destinations pay for it until the window ends
+        assert!(!send(&policy, &mut spends, Pubkey::new_unique(), 1, NEXT_YEAR));
+        assert!(send(&policy, &mut spends, target, 100, NEXT_YEAR + i64::from(SECONDS_PER_DAY)));
+        assert_eq!(spends.evicted, 0);
+
+        let bytes = borsh::to_vec(&spends).unwrap();
+        assert_eq!(bytes.len(), DestinationSpends::serialized_size(spends.entries.len()));
+        assert_eq!(borsh::from_slice::<DestinationSpends>(&bytes).unwrap(), spends);
+    }
+
+    #[test]
+    fn test_per_destination_limit_validation() {
+        let payroll = Pubkey::new_unique();
+        let check = |limits: DestinationLimits| Policy::per_destination_limit(limits).validate_config();
+
+        let mut duplicated = payroll_limits(payroll);
+        duplicated.limits.push(DestinationLimit { destination: payroll, max_amount: None });
+        assert_eq!(check(duplicated), Err(PolicyBuildError::DuplicateKey));
+
+        let mut too_many = payroll_limits(payroll);
+        too_many.limits = (0..=MAX_POLICY_DESTINATIONS)
+            .map(|_| DestinationLimit { destination: Pubkey::new_unique(), max_amount: Some(1) })
+            .collect();
+        assert_eq!(check(too_many), Err(PolicyBuildError::DestinationCount(MAX_POLICY_DESTINATIONS + 1)));
+
+        let mut zero_listed = payroll_limits(payroll);
+        zero_listed.limits[0].max_amount = Some(0);
+        assert_eq!(check(zero_listed), Err(PolicyBuildError::ZeroLimit));
+
+        // A zero default is fine as long as something may be paid
+        let mut listed_only = payroll_limits(payroll);
+        listed_only.default_max_amount = 0;
+        assert_eq!(check(listed_only.clone()), Ok(()));
+        listed_only.limits.clear();
+        assert_eq!(check(listed_only), Err(PolicyBuildError::ZeroLimit));
+
+        let mut no_window = payroll_limits(payroll);
+        no_window.window_seconds = 0;
+        assert_eq!(check(no_window), Err(PolicyBuildError::ZeroWindow));
+
+        let garbage = Policy { policy_type: PolicyType::PerDestinationLimit, config: vec![1, 2, 3] };
+        assert_eq!(garbage.validate_config(), Err(PolicyBuildError::MalformedConfig));
+        let transfer = PolicyContext::sol(Amount::ZERO, NEXT_YEAR).with_destination(payroll);
+        assert!(garbage.check_context(&transfer).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_truncated_configs_deny() {
+        let truncated = [
+            Policy::new(PolicyType::SpendingLimit, vec![0xff; 7]),
+            Policy::new(PolicyType::DailyLimit, vec![0xff; 12]),
+            Policy::new(PolicyType::TimeLocked, vec![0; 4]),
+        ];
+        for policy in truncated {
+            assert!(policy.check(0, NEXT_YEAR).is_err(), "{:?}", policy);
+            assert_eq!(policy.validate_config(), Err(PolicyBuildError::MalformedConfig), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_destination_allowlist_ignores_unknown_destination() {
+        let exchange = Pubkey::new_unique();
+        let policy = Policy::destination_allowlist(vec![exchange]);
+
+        assert!(policy.check_context(&PolicyContext::sol(Amount::ZERO, 0)).is_ok());
+        assert!(policy.check_context(&PolicyContext::sol(Amount::ZERO, 0).with_destination(exchange)).is_ok());
+        assert!(policy.check_context(&PolicyContext::sol(Amount::ZERO, 0).with_destination(Pubkey::new_unique())).is_err());
+    }
+
+    #[test]
+    fn test_serialized_size_matches_to_bytes() {
+        let (_, limits) = usdc_limits(true);
+        let cases = [
+            Policy::open(),
+            Policy::spending_limit(Amount::from_lamports(1_000_000_000)),
+            Policy::daily_limit(Amount::from_lamports(5), 0).with_mint_limits(limits),
+            Policy::multi_sig(vec![Pubkey::new_unique(); 10]),
+            Policy::composite(vec![Policy::time_locked(NEXT_YEAR), Policy::open()]),
+        ];
+
+        for policy in cases {
+            assert_eq!(policy.serialized_size(), policy.to_bytes().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_estimated_compute_units_grows_with_config() {
+        let open = Policy::open();
+        assert_eq!(open.estimated_compute_units(), compute_units::BASE);
+
+        let one = Policy::destination_allowlist(vec![Pubkey::new_unique()]);
+        let full = Policy::destination_allowlist(vec![Pubkey::new_unique(); MAX_POLICY_DESTINATIONS]);
+        assert_eq!(
+            full.estimated_compute_units() - one.estimated_compute_units(),
+            (MAX_POLICY_DESTINATIONS as u32 - 1) * (32 * compute_units::PER_CONFIG_BYTE + compute_units::PER_KEY)
+        );
+
+        let (_, limits) = usdc_limits(false);
+        let limited = Policy::spending_limit(Amount::ZERO).with_mint_limits(limits);
+        assert!(limited.estimated_compute_units() > Policy::spending_limit(Amount::ZERO).estimated_compute_units());
+
+        // A composite pays for each rule, but for the base cost only once
+        let composite = Policy::composite(vec![full.clone(), limited.clone()]);
+        assert!(
+            composite.estimated_compute_units()
+                > full.estimated_compute_units() + limited.estimated_compute_units() - compute_units::BASE
+        );
+    }
+
+    #[test]
+    fn test_canonical_hash_ignores_list_order() {
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let limit = |mint: Pubkey, max_amount: u64| MintLimit { mint, max_amount, decimals: 6 };
+        let binding = |destinations: Vec<Pubkey>, hash: u8| CredentialBinding { destinations, credential_id_hash: Some([hash; 32]) };
+        let destination_limit = |destination: Pubkey, max_amount: Option<u64>| DestinationLimit { destination, max_amount };
+
+        let pairs = [
+            (Policy::multi_sig(vec![a, b, c]), Policy::multi_sig(vec![c, a, b])),
+            (Policy::destination_allowlist(vec![a, b]), Policy::destination_allowlist(vec![b, a])),
+            (
+                Policy::spending_limit(Amount::from_lamports(10)).with_mint_limits(MintLimits {
+                    allow_unlisted: false,
+                    limits: vec![limit(a, 1), limit(b, 2)],
+                }),
+                Policy::spending_limit(Amount::from_lamports(10)).with_mint_limits(MintLimits {
+                    allow_unlisted: false,
+                    limits: vec![limit(b, 2), limit(a, 1)],
+                }),
+            ),
+            (
+                Policy::composite(vec![Policy::multi_sig(vec![a, b]), Policy::time_locked(1_700_000_000)]),
+                Policy::composite(vec![Policy::time_locked(1_700_000_000), Policy::multi_sig(vec![b, a])]),
+            ),
+            (
+                Policy::credential_binding(CredentialBindings {
+                    bindings: vec![binding(vec![a, b], 1), binding(vec![c], 2)],
+                    default: None,
+                }),
+                Policy::credential_binding(CredentialBindings {
+                    bindings: vec![binding(vec![c], 2), binding(vec![b, a], 1)],
+                    default: None,
+                }),
+            ),
+            (
+                Policy::per_destination_limit(DestinationLimits {
+                    limits: vec![destination_limit(a, Some(1)), destination_limit(b, None)],
+                    ..payroll_limits(c)
+                }),
+                Policy::per_destination_limit(DestinationLimits {
+                    limits: vec![destination_limit(b, None), destination_limit(a, Some(1))],
+                    ..payroll_limits(c)
+                }),
+            ),
+        ];
+        for (policy, permuted) in pairs {
+            assert_ne!(policy, permuted);
+            assert_eq!(policy.canonical_hash(), permuted.canonical_hash(), "{:?}", policy.policy_type);
+        }
+    }
+
+    #[test]
+    fn test_canonical_hash_changes_with_any_value() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let policies = [
+            Policy::open(),
+            Policy::spending_limit(Amount::from_lamports(10)),
+            Policy::spending_limit(Amount::from_lamports(11)),
+            Policy::daily_limit(Amount::from_lamports(10), 1_700_000_000),
+            Policy::daily_limit(Amount::from_lamports(10), 1_700_000_001),
+            Policy::windowed_limit(Amount::from_lamports(10), 3_600, 1_700_000_000),
+            Policy::time_locked(1_700_000_000),
+            Policy::multi_sig(vec![a]),
+            Policy::multi_sig(vec![a, b]),
+            // Same keys, different meaning
+            Policy::destination_allowlist(vec![a, b]),
+            Policy::spending_limit(Amount::from_lamports(10)).with_mint_limits(MintLimits::default()),
+            Policy::spending_limit(Amount::from_lamports(10))
+                .with_mint_limits(MintLimits { allow_unlisted: true, limits: vec![] }),
+            Policy::composite(vec![Policy::multi_sig(vec![a, b])]),
+            // Malformed configs are hashed as they are
+            Policy { policy_type: PolicyType::MultiSig, config: vec![1; 33] },
+            Policy { policy_type: PolicyType::MultiSig, config: vec![1; 34] },
+        ];
+        let hashes: Vec<[u8; 32]> = policies.iter().map(Policy::canonical_hash).collect();
+        for (i, hash) in hashes.iter().enumerate() {
+            assert!(!hashes[..i].contains(hash), "{:?} collides", policies[i]);
+        }
+    }
+
+    #[test]
+    fn test_estimated_compute_units_ceiling() {
+        // The largest single-rule policies fit comfortably
+        let signers = Policy::multi_sig(vec![Pubkey::new_unique(); MAX_POLICY_SIGNERS]);
+        let destinations = Policy::destination_allowlist(vec![Pubkey::new_unique(); MAX_POLICY_DESTINATIONS]);
+        assert!(signers.estimated_compute_units() <= MAX_POLICY_COMPUTE_UNITS);
+        assert!(destinations.estimated_compute_units() <= MAX_POLICY_COMPUTE_UNITS);
+
+        // Thousands of mint limits, or dozens of full allowlists, don't
+        let limits = MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint: Pubkey::new_unique(), max_amount: 1, decimals: 6 }; 2_000],
+        };
+        let huge_limits = Policy::spending_limit(Amount::ZERO).with_mint_limits(limits);
+        assert!(huge_limits.estimated_compute_units() > MAX_POLICY_COMPUTE_UNITS);
+        let huge_composite = Policy::composite(vec![destinations; 40]);
+        assert!(huge_composite.estimated_compute_units() > MAX_POLICY_COMPUTE_UNITS);
+    }
+
+    fn policy_type() -> impl Strategy<Value = PolicyType> {
+        prop_oneof![
+            Just(PolicyType::Open),
+            Just(PolicyType::SpendingLimit),
+            Just(PolicyType::DailyLimit),
+            Just(PolicyType::MultiSig),
+            Just(PolicyType::TimeLocked),
+            Just(PolicyType::DestinationAllowlist),
+            Just(PolicyType::Composite),
+            Just(PolicyType::CredentialBinding),
+            Just(PolicyType::PerDestinationLimit),
+            Just(PolicyType::Vault),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_serialized_size_matches_to_bytes(
+            policy_type in policy_type(),
+            config in prop::collection::vec(any::<u8>(), 0..512),
+        ) {
+            let policy = Policy { policy_type, config };
+            prop_assert_eq!(policy.serialized_size(), policy.to_bytes().unwrap().len());
+        }
+    }
+}
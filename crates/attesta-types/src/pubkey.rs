@@ -0,0 +1,68 @@
+//! Account addresses
+//!
+//! With the `solana` feature this is `solana_program`'s own `Pubkey`, so
+//! on-chain code and the SDK pass addresses straight through. Without it,
+//! `Pubkey` is a plain 32-byte newtype with the same Borsh encoding, for
+//! services that read Attesta data without the Solana runtime.
+
+#[cfg(feature = "solana")]
+pub use solana_program::pubkey::Pubkey;
+
+#[cfg(not(feature = "solana"))]
+pub use self::standalone::Pubkey;
+
+#[cfg(not(feature = "solana"))]
+mod standalone {
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use std::fmt;
+
+    /// A 32-byte account address
+    #[derive(
+        BorshSerialize, BorshDeserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+    )]
+    pub struct Pubkey([u8; 32]);
+
+    impl Pubkey {
+        /// The address with these bytes
+        pub const fn new_from_array(bytes: [u8; 32]) -> Self {
+            Self(bytes)
+        }
+
+        /// A different address on every call (for tests)
+        pub fn new_unique() -> Self {
+            use std::sync::atomic::{AtomicU64, Ordering};
+            static NEXT: AtomicU64 = AtomicU64::new(1);
+
+            let mut bytes = [0u8; 32];
+            bytes[..8].copy_from_slice(&NEXT.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+            Self(bytes)
+        }
+
+        /// The address's bytes
+        pub const fn to_bytes(self) -> [u8; 32] {
+            self.0
+        }
+    }
+
+    impl AsRef<[u8]> for Pubkey {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    impl From<[u8; 32]> for Pubkey {
+        fn from(bytes: [u8; 32]) -> Self {
+            Self(bytes)
+        }
+    }
+
+    impl fmt::Debug for Pubkey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Pubkey(")?;
+            for byte in self.0 {
+                write!(f, "{:02x}", byte)?;
+            }
+            write!(f, ")")
+        }
+    }
+}
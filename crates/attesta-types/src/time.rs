@@ -0,0 +1,104 @@
+//! Bounds on timestamps that come from clients
+//!
+//! Unlock times, schedule windows, inheritance periods and backup dates are
+//! all chosen off-chain. A typo there (a year of 52000, a timestamp in
+//! milliseconds, a sign flip) is easy to make and can lock an account for
+//! good, so every place that takes one checks it against the current time
+//! with `validate_timestamp` before storing it.
+
+use thiserror::Error;
+
+/// Seconds in a (non-leap) year, for the bounds below
+pub const SECONDS_PER_YEAR: i64 = 365 * 24 * 60 * 60;
+
+/// How far ahead of now a timestamp may be, by default (10 years)
+///
+/// A time lock may go further with an explicit long-lock authorization;
+/// see `Policy::validate_timestamps`.
+pub const MAX_FUTURE_SECONDS: i64 = 10 * SECONDS_PER_YEAR;
+
+/// How far behind now a timestamp may be, by default (10 years)
+pub const MAX_PAST_SECONDS: i64 = 10 * SECONDS_PER_YEAR;
+
+/// How far ahead of the chain's clock a client's idea of "now" may be
+///
+/// For timestamps that record when something happened off-chain, like a
+/// backup's `created_at`.
+pub const MAX_CLOCK_SKEW_SECONDS: i64 = 60 * 60;
+
+/// Why a timestamp was rejected
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeError {
+    #[error("Timestamp {0} is negative")]
+    Negative(i64),
+
+    #[error("Timestamp {timestamp} is later than {latest}")]
+    TooFarInFuture { timestamp: i64, latest: i64 },
+
+    #[error("Timestamp {timestamp} is earlier than {earliest}")]
+    TooFarInPast { timestamp: i64, earliest: i64 },
+}
+
+/// Checks that `timestamp` is no more than `max_future` seconds after `now`
+/// and no more than `max_past` seconds before it
+///
+/// Negative timestamps are always rejected. The bounds saturate, so
+/// `i64::MAX` means no bound on that side.
+///
+/// # Example
+/// ```ignore
+/// validate_timestamp(unlock_at, now, MAX_FUTURE_SECONDS, MAX_PAST_SECONDS)?;
+/// ```
+pub fn validate_timestamp(timestamp: i64, now: i64, max_future: i64, max_past: i64) -> Result<(), TimeError> {
+    if timestamp < 0 {
+        return Err(TimeError::Negative(timestamp));
+    }
+    let latest = now.saturating_add(max_future);
+    if timestamp > latest {
+        return Err(TimeError::TooFarInFuture { timestamp, latest });
+    }
+    let earliest = now.saturating_sub(max_past);
+    if timestamp < earliest {
+        return Err(TimeError::TooFarInPast { timestamp, earliest });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_800_000_000;
+
+    #[test]
+    fn test_validate_timestamp_bounds() {
+        let latest = NOW + MAX_FUTURE_SECONDS;
+        let earliest = NOW - MAX_PAST_SECONDS;
+        let cases = [
+            (NOW, Ok(())),
+            (latest, Ok(())),
+            (latest + 1, Err(TimeError::TooFarInFuture { timestamp: latest + 1, latest })),
+            (earliest, Ok(())),
+            (earliest - 1, Err(TimeError::TooFarInPast { timestamp: earliest - 1, earliest })),
+            (-1, Err(TimeError::Negative(-1))),
+            (i64::MIN, Err(TimeError::Negative(i64::MIN))),
+            // Year 52000
+            (1_578_914_841_600, Err(TimeError::TooFarInFuture { timestamp: 1_578_914_841_600, latest })),
+            // Milliseconds instead of seconds
+            (NOW * 1000, Err(TimeError::TooFarInFuture { timestamp: NOW * 1000, latest })),
+        ];
+        for (timestamp, expected) in cases {
+            assert_eq!(validate_timestamp(timestamp, NOW, MAX_FUTURE_SECONDS, MAX_PAST_SECONDS), expected, "{timestamp}");
+        }
+    }
+
+    #[test]
+    fn test_unbounded_sides_saturate() {
+        assert_eq!(validate_timestamp(i64::MAX, NOW, i64::MAX, 0), Ok(()));
+        assert_eq!(validate_timestamp(0, NOW, 0, i64::MAX), Ok(()));
+        assert_eq!(
+            validate_timestamp(NOW + 1, NOW, 0, i64::MAX),
+            Err(TimeError::TooFarInFuture { timestamp: NOW + 1, latest: NOW })
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Transaction requests: the data an account executes and the hash a passkey signs
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use crate::hashing::{domain_hash, domain_hasher, Domain};
+use crate::pubkey::Pubkey;
+
+/// Largest `transaction_data` an account executes, in bytes
+///
+/// Accounts can lower this for themselves (`AccountSettings`), never raise it.
+pub const MAX_TRANSACTION_DATA_LEN: usize = 1024;
+
+/// Largest memo a transaction request carries, in bytes
+pub const MAX_MEMO_LEN: usize = 128;
+
+/// Errors from reading a transaction request
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionRequestError {
+    #[error("Transaction data is {len} bytes, over the {max}-byte limit")]
+    TooLarge { len: usize, max: usize },
+
+    #[error("Memo is {len} bytes, over the {max}-byte limit")]
+    MemoTooLong { len: usize, max: usize },
+
+    #[error("Memo is not valid UTF-8")]
+    MemoNotUtf8,
+}
+
+/// A transaction an account owner wants to execute
+///
+/// This is what gets signed: the passkey signs a challenge built from the
+/// request's `message_hash()`, and `execute_transaction` checks the
+/// submitted transaction data hashes to the same value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionRequest {
+    /// The transaction data to execute
+    pub transaction_data: Vec<u8>,
+
+    /// A reference for the payment (an order ID, an invoice number), signed
+    /// along with the transaction; empty for none
+    pub memo: Vec<u8>,
+}
+
+impl TransactionRequest {
+    /// Creates a new transaction request
+    pub fn new(transaction_data: Vec<u8>) -> Self {
+        Self { transaction_data, memo: Vec::new() }
+    }
+
+    /// Attaches a memo, replacing any earlier one
+    ///
+    /// # Returns
+    /// - `Ok(TransactionRequest)` with the memo set
+    /// - `Err(TransactionRequestError::MemoTooLong)` if it's over `MAX_MEMO_LEN` bytes
+    pub fn memo(mut self, memo: &str) -> Result<Self, TransactionRequestError> {
+        check_memo(memo.as_bytes())?;
+        self.memo = memo.as_bytes().to_vec();
+        Ok(self)
+    }
+
+    /// Reads a request from raw transaction data
+    ///
+    /// # Returns
+    /// - `Ok(TransactionRequest)` if the data fits in `MAX_TRANSACTION_DATA_LEN`
+    /// - `Err(TransactionRequestError::TooLarge)` if it doesn't
+    pub fn from_bytes(transaction_data: &[u8]) -> Result<Self, TransactionRequestError> {
+        if transaction_data.len() > MAX_TRANSACTION_DATA_LEN {
+            return Err(TransactionRequestError::TooLarge {
+                len: transaction_data.len(),
+                max: MAX_TRANSACTION_DATA_LEN,
+            });
+        }
+        Ok(Self::new(transaction_data.to_vec()))
+    }
+
+    /// Creates a request for an SPL token transfer out of the account
+    pub fn from_token_transfer(transfer: TokenTransfer) -> Self {
+        Self::new(transfer.to_transaction_data())
+    }
+
+    /// The token transfer this request makes, if it is one
+    pub fn token_transfer(&self) -> Option<TokenTransfer> {
+        TokenTransfer::from_transaction_data(&self.transaction_data)
+    }
+
+    /// The message hash a passkey authorizes for this transaction (and its memo)
+    pub fn message_hash(&self) -> [u8; 32] {
+        transaction_memo_message_hash(&self.transaction_data, &self.memo)
+    }
+
+    /// The hash receipts and events report for the memo, if there is one
+    pub fn memo_hash(&self) -> Option<[u8; 32]> {
+        memo_hash(&self.memo)
+    }
+}
+
+/// Computes the message hash for a transaction's data
+///
+/// Uses its own domain so it never collides with `action_message_hash`.
+pub fn transaction_message_hash(transaction_data: &[u8]) -> [u8; 32] {
+    domain_hash(Domain::Transaction, transaction_data)
+}
+
+/// Computes the message hash for a transaction's data and memo
+///
+/// Without a memo this is `transaction_message_hash`, so requests that
+/// don't use one sign what they always have. A memo switches to a separate
+/// domain (not an extension of the plain one) and is length-prefixed,
+/// so no memo can be moved into the transaction data or the other way round.
+pub fn transaction_memo_message_hash(transaction_data: &[u8], memo: &[u8]) -> [u8; 32] {
+    if memo.is_empty() {
+        return transaction_message_hash(transaction_data);
+    }
+    let mut hasher = domain_hasher(Domain::MemoTransaction);
+    hasher.update((memo.len() as u32).to_le_bytes());
+    hasher.update(memo);
+    hasher.update(transaction_data);
+    hasher.finalize().into()
+}
+
+/// SHA-256 of a memo, as reported in receipts and events (`None` without one)
+pub fn memo_hash(memo: &[u8]) -> Option<[u8; 32]> {
+    (!memo.is_empty()).then(|| Sha256::digest(memo).into())
+}
+
+/// Checks a memo is one an account will execute with
+///
+/// # Returns
+/// - `Ok(())` if it's at most `MAX_MEMO_LEN` bytes of UTF-8
+/// - `Err(TransactionRequestError)` naming the problem otherwise
+pub fn check_memo(memo: &[u8]) -> Result<(), TransactionRequestError> {
+    if memo.len() > MAX_MEMO_LEN {
+        return Err(TransactionRequestError::MemoTooLong { len: memo.len(), max: MAX_MEMO_LEN });
+    }
+    std::str::from_utf8(memo).map_err(|_| TransactionRequestError::MemoNotUtf8)?;
+    Ok(())
+}
+
+/// Marks transaction data that encodes a `TokenTransfer`
+///
+/// Token transfers travel in the same `transaction_data` as any other
+/// transaction, so they're covered by the signed message hash. The prefix
+/// tells them apart from arbitrary data.
+pub const TOKEN_TRANSFER_PREFIX: [u8; 8] = *b"spl-xfer";
+
+/// An SPL token transfer out of an Attesta account
+///
+/// The tokens move from a token account owned by the Attesta PDA, which
+/// signs the transfer. `transfer_checked` makes the token program confirm
+/// `decimals` against the mint, so the amount a user approved can't be
+/// reinterpreted with different decimals.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TokenTransfer {
+    /// The token mint
+    pub mint: Pubkey,
+
+    /// Raw amount to transfer (in the token's smallest unit)
+    pub amount: u64,
+
+    /// The mint's decimals
+    pub decimals: u8,
+
+    /// The token account receiving the tokens
+    pub destination_ata: Pubkey,
+}
+
+impl TokenTransfer {
+    /// Encodes this transfer as `transaction_data` for `execute`
+    pub fn to_transaction_data(&self) -> Vec<u8> {
+        let mut data = TOKEN_TRANSFER_PREFIX.to_vec();
+        // Serializing into a Vec can't fail
+        data.extend(borsh::to_vec(self).unwrap_or_default());
+        data
+    }
+
+    /// Decodes a transfer from `transaction_data`
+    ///
+    /// # Returns
+    /// - `Some(TokenTransfer)` if the data is an encoded token transfer
+    /// - `None` for any other transaction data
+    pub fn from_transaction_data(data: &[u8]) -> Option<Self> {
+        let body = data.strip_prefix(TOKEN_TRANSFER_PREFIX.as_slice())?;
+        borsh::from_slice(body).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer() -> TokenTransfer {
+        TokenTransfer {
+            mint: Pubkey::new_unique(),
+            amount: 1_500_000,
+            decimals: 6,
+            destination_ata: Pubkey::new_unique(),
+        }
+    }
+
+    #[test]
+    fn test_transaction_data_round_trip() {
+        let transfer = transfer();
+        let data = transfer.to_transaction_data();
+
+        assert!(data.starts_with(&TOKEN_TRANSFER_PREFIX));
+        assert_eq!(TokenTransfer::from_transaction_data(&data), Some(transfer));
+        assert_eq!(TransactionRequest::from_token_transfer(transfer).token_transfer(), Some(transfer));
+    }
+
+    #[test]
+    fn test_other_transaction_data_is_not_a_transfer() {
+        assert_eq!(TokenTransfer::from_transaction_data(b"transfer 1 SOL"), None);
+        // The prefix alone isn't enough
+        assert_eq!(TokenTransfer::from_transaction_data(&TOKEN_TRANSFER_PREFIX), None);
+    }
+
+    #[test]
+    fn test_request_from_bytes_enforces_limit() {
+        let at_limit = vec![7u8; MAX_TRANSACTION_DATA_LEN];
+        assert_eq!(TransactionRequest::from_bytes(&at_limit), Ok(TransactionRequest::new(at_limit)));
+
+        assert_eq!(
+            TransactionRequest::from_bytes(&[7u8; MAX_TRANSACTION_DATA_LEN + 1]),
+            Err(TransactionRequestError::TooLarge { len: MAX_TRANSACTION_DATA_LEN + 1, max: MAX_TRANSACTION_DATA_LEN })
+        );
+    }
+
+    #[test]
+    fn test_memo_is_signed() {
+        let plain = TransactionRequest::new(b"pay 5 USDC".to_vec());
+        let with_memo = plain.clone().memo("order-1042").unwrap();
+
+        assert_eq!(plain.message_hash(), transaction_message_hash(b"pay 5 USDC"));
+        assert_ne!(with_memo.message_hash(), plain.message_hash());
+        assert_ne!(with_memo.message_hash(), plain.clone().memo("order-1043").unwrap().message_hash());
+        // Shifting bytes between the memo and the data changes the hash too
+        assert_ne!(
+            transaction_memo_message_hash(b"ab", b"cd"),
+            transaction_memo_message_hash(b"b", b"cda")
+        );
+
+        assert_eq!(plain.memo_hash(), None);
+        assert_eq!(with_memo.memo_hash(), Some(Sha256::digest(b"order-1042").into()));
+    }
+
+    #[test]
+    fn test_memo_limits() {
+        let request = TransactionRequest::new(vec![1]);
+        let at_limit = "m".repeat(MAX_MEMO_LEN);
+        assert_eq!(request.clone().memo(&at_limit).unwrap().memo.len(), MAX_MEMO_LEN);
+        assert_eq!(
+            request.memo(&format!("{}m", at_limit)),
+            Err(TransactionRequestError::MemoTooLong { len: MAX_MEMO_LEN + 1, max: MAX_MEMO_LEN })
+        );
+
+        assert_eq!(check_memo("ünïcödé".as_bytes()), Ok(()));
+        assert_eq!(check_memo(&[0xff, 0xfe]), Err(TransactionRequestError::MemoNotUtf8));
+    }
+}
@@ -0,0 +1,175 @@
+//! The WebAuthn signature, as submitted with every authorization
+//!
+//! Verifying it needs P-256 and lives in `core-crypto`; this is only the
+//! structure and its length-prefixed wire format.
+
+use crate::bytes::{read_slice, read_u32_le};
+use thiserror::Error;
+
+/// Serialized signature data that's truncated or has a bad length
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Invalid signature format")]
+pub struct SignatureFormatError;
+
+/// All the parts of a WebAuthn signature that we need to verify it
+///
+/// When a user authenticates with their passkey (TouchID, FaceID, etc.),
+/// the browser/device creates this structure. We store it and use it to
+/// verify the signature on-chain without ever seeing the private key.
+#[derive(Debug, Clone)]
+pub struct WebAuthnSignature {
+    /// The raw data from the authenticator (contains flags, counter, etc.)
+    /// This tells us things like whether the user was present, verified, etc.
+    pub authenticator_data: Vec<u8>,
+    
+    /// The client-side data as JSON (contains the challenge, origin, type of operation)
+    /// This proves the signature was created in response to our specific challenge
+    pub client_data_json: Vec<u8>,
+    
+    /// The actual signature over the combined authenticator_data + client_data_json hash
+    /// This is what we verify using the public key
+    pub signature: Vec<u8>,
+    
+    /// The credential ID that identifies which passkey was used
+    /// This helps us find the right public key to verify with
+    pub credential_id: Vec<u8>,
+}
+
+impl WebAuthnSignature {
+    /// Creates a new WebAuthnSignature from all its parts
+    ///
+    /// This is the simplest way to create a WebAuthnSignature when you already
+    /// have all the pieces from a WebAuthn authentication.
+    pub fn new(
+        authenticator_data: Vec<u8>,
+        client_data_json: Vec<u8>,
+        signature: Vec<u8>,
+        credential_id: Vec<u8>,
+    ) -> Self {
+        Self {
+            authenticator_data,
+            client_data_json,
+            signature,
+            credential_id,
+        }
+    }
+
+    /// Length of `to_bytes()`, without serializing
+    pub fn serialized_size(&self) -> usize {
+        4 * 4  // Four length fields (u32 each = 4 bytes)
+            + self.authenticator_data.len()
+            + self.client_data_json.len()
+            + self.signature.len()
+            + self.credential_id.len()
+    }
+
+    /// Converts this signature into bytes so we can store it on-chain
+    ///
+    /// The format is: length1 + data1 + length2 + data2 + ...
+    /// We store the length of each field before the field itself so we know
+    /// how to read it back later.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.serialized_size());
+        
+        // Write each field: length first, then the actual data
+        bytes.extend_from_slice(&(self.authenticator_data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.authenticator_data);
+        
+        bytes.extend_from_slice(&(self.client_data_json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.client_data_json);
+        
+        bytes.extend_from_slice(&(self.signature.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.signature);
+        
+        bytes.extend_from_slice(&(self.credential_id.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.credential_id);
+        
+        bytes
+    }
+
+    /// Reads bytes back into a WebAuthnSignature
+    ///
+    /// This does the opposite of `to_bytes()`. It reads the length-prefixed
+    /// format and reconstructs the signature structure.
+    ///
+    /// # Returns
+    /// - `Ok(WebAuthnSignature)` if the data is valid
+    /// - `Err(SignatureFormatError)` if the data is corrupted or incomplete
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SignatureFormatError> {
+        // Each field is a u32 length, then that many bytes
+        fn read_field(data: &[u8], offset: &mut usize) -> Result<Vec<u8>, SignatureFormatError> {
+            let len = read_u32_le(data, offset).map_err(|_| SignatureFormatError)?;
+            let len = usize::try_from(len).map_err(|_| SignatureFormatError)?;
+            read_slice(data, offset, len).map(<[u8]>::to_vec).map_err(|_| SignatureFormatError)
+        }
+
+        let mut offset = 0;
+
+        // Read authenticator_data
+        let authenticator_data = read_field(data, &mut offset)?;
+
+        // Read client_data_json
+        let client_data_json = read_field(data, &mut offset)?;
+
+        // Read signature
+        let signature = read_field(data, &mut offset)?;
+
+        // Read credential_id
+        let credential_id = read_field(data, &mut offset)?;
+
+        Ok(Self {
+            authenticator_data,
+            client_data_json,
+            signature,
+            credential_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_serialized_size_matches_to_bytes() {
+        let cases = [
+            WebAuthnSignature::new(vec![], vec![], vec![], vec![]),
+            WebAuthnSignature::new(vec![1; 37], b"{}".to_vec(), vec![2; 64], vec![3; 16]),
+            WebAuthnSignature::new(vec![1; 1024], vec![2; 4096], vec![3; 72], vec![4; 1023]),
+        ];
+
+        for sig in cases {
+            assert_eq!(sig.serialized_size(), sig.to_bytes().len());
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_truncated_data() {
+        let bytes = WebAuthnSignature::new(vec![1; 37], b"{}".to_vec(), vec![2; 64], vec![3; 16]).to_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(WebAuthnSignature::from_bytes(&bytes[..len]).err(), Some(SignatureFormatError));
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_oversized_lengths() {
+        // A length prefix near u32::MAX mustn't overflow the offset arithmetic
+        let mut bytes = vec![0xff; 4];
+        bytes.extend_from_slice(&[0; 16]);
+        assert_eq!(WebAuthnSignature::from_bytes(&bytes).err(), Some(SignatureFormatError));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_serialized_size_matches_to_bytes(
+            authenticator_data in prop::collection::vec(any::<u8>(), 0..512),
+            client_data_json in prop::collection::vec(any::<u8>(), 0..512),
+            signature in prop::collection::vec(any::<u8>(), 0..80),
+            credential_id in prop::collection::vec(any::<u8>(), 0..256),
+        ) {
+            let sig = WebAuthnSignature::new(authenticator_data, client_data_json, signature, credential_id);
+            prop_assert_eq!(sig.serialized_size(), sig.to_bytes().len());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Checks another implementation's wire formats against the fixtures
+//!
+//! ```text
+//! conformance-check formats    # <format> <fields> - <output>, one per line
+//! conformance-check list       # <format> <name> <input>, one per line
+//! conformance-check verify     # reads <format> <name> <output> from stdin
+//! ```
+//!
+//! A typical run pipes `list` through the implementation under test and
+//! back into `verify`, which prints each failure and exits with status 1 if
+//! there were any.
+
+use attesta_conformance::{fixtures, verify, ConformanceError, FIXTURE_VERSION, FORMATS};
+use std::io::Read;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: conformance-check <formats | list | verify>";
+
+fn main() -> ExitCode {
+    let command = std::env::args().nth(1);
+    let result = match command.as_deref() {
+        Some("formats") => {
+            for format in &FORMATS {
+                println!("{} {} - {}", format.name, format.fields.join(":"), format.output);
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some("list") => list(),
+        Some("verify") => run_verify(),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        ExitCode::from(2)
+    })
+}
+
+fn list() -> Result<ExitCode, ConformanceError> {
+    println!("# attesta conformance fixtures v{}", FIXTURE_VERSION);
+    for fixture in fixtures()? {
+        println!("{} {} {}", fixture.format, fixture.name, fixture.input);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_verify() -> Result<ExitCode, ConformanceError> {
+    let mut outputs = String::new();
+    if let Err(e) = std::io::stdin().read_to_string(&mut outputs) {
+        eprintln!("error: can't read stdin: {}", e);
+        return Ok(ExitCode::from(2));
+    }
+
+    let report = verify(&outputs)?;
+    for failure in &report.failures {
+        println!("FAIL {}", failure);
+    }
+    println!("{} passed, {} failed", report.passed, report.failures.len());
+    Ok(if report.is_success() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
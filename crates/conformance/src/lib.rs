@@ -0,0 +1,544 @@
+//! Conformance fixtures for Attesta's wire formats
+//!
+//! Every format a client has to produce byte-for-byte the same as the
+//! program (challenges, message hashes, canonical policy hashes, signature
+//! and instruction encodings, backups, display codes) has a fixture file
+//! under `fixtures/v1/`, with inputs and the output this implementation
+//! gives for them. `FORMATS` lists them; a test fails if one has no
+//! fixtures, or if a fixture no longer matches what the Rust crates compute.
+//!
+//! Other SDKs check themselves against the same files with the
+//! `conformance-check` binary, over stdin and stdout:
+//!
+//! ```text
+//! conformance-check list                 # <format> <name> <input>, one per line
+//! <your implementation> | conformance-check verify    # reads <format> <name> <output>
+//! ```
+//!
+//! `verify` reports every fixture that's missing or differs, and exits
+//! non-zero if any did.
+//!
+//! # Fixture files
+//!
+//! Lines starting with `#` are comments; every other line is
+//! `<name> <input> <expected>`. An input is one or more fields separated by
+//! `:`, each hex (numbers as little-endian bytes, text as UTF-8). A lone
+//! empty field is written `-`, so the input is never blank. Outputs are
+//! lowercase hex, except the formats that are text by nature.
+//!
+//! Fixtures are versioned by directory. A shipped fixture never changes: a
+//! format that changes gets a new directory and `FIXTURE_VERSION` moves on.
+
+use attesta_types::{
+    encode_execute, transaction_memo_message_hash, transaction_message_hash, ExecuteArgs, Pubkey,
+    WebAuthnSignature,
+};
+use core_crypto::challenge::base64url_encode;
+use core_crypto::{compute_challenge, display_code};
+use recovery::encrypted_backup::derive_backup_key;
+use recovery::{EncryptedBackup, Policy};
+use smart_account::action_message_hash;
+use thiserror::Error;
+
+/// The fixture set `FORMATS` points at
+pub const FIXTURE_VERSION: u32 = 1;
+
+/// Why an input couldn't be run or a fixture couldn't be read
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConformanceError {
+    #[error("{format} takes {expected} input fields, got {found}")]
+    FieldCount { format: &'static str, expected: usize, found: usize },
+
+    #[error("{format} field {field} must be {expected} bytes, got {found}")]
+    FieldLength { format: &'static str, field: &'static str, expected: usize, found: usize },
+
+    #[error("Input field is not hex: {0}")]
+    InvalidHex(String),
+
+    #[error("{format} field {field} is invalid")]
+    InvalidField { format: &'static str, field: &'static str },
+
+    #[error("Fixture line is malformed: {0}")]
+    MalformedFixture(String),
+}
+
+/// A wire format and its fixtures
+pub struct Format {
+    /// Name used in fixture files and by `conformance-check`
+    pub name: &'static str,
+
+    /// The input fields, in order
+    pub fields: &'static [&'static str],
+
+    /// What the output is, for `conformance-check formats`
+    pub output: &'static str,
+
+    /// Contents of the format's fixture file
+    fixtures: &'static str,
+
+    compute: fn(&[Vec<u8>]) -> Result<String, ConformanceError>,
+}
+
+/// Every wire format with fixtures
+pub const FORMATS: [Format; 12] = [
+    Format {
+        name: "challenge",
+        fields: &["owner", "nonce_le", "message_hash"],
+        output: "the 32-byte challenge the passkey signs",
+        fixtures: include_str!("../fixtures/v1/challenge.txt"),
+        compute: challenge,
+    },
+    Format {
+        name: "display_code",
+        fields: &["challenge"],
+        output: "the code shown to the user, as text",
+        fixtures: include_str!("../fixtures/v1/display_code.txt"),
+        compute: display_code_of,
+    },
+    Format {
+        name: "client_data_challenge",
+        fields: &["challenge"],
+        output: "the challenge as it appears in clientDataJSON, as text",
+        fixtures: include_str!("../fixtures/v1/client_data_challenge.txt"),
+        compute: client_data_challenge,
+    },
+    Format {
+        name: "transaction_message_hash",
+        fields: &["transaction_data"],
+        output: "the message hash of a transaction",
+        fixtures: include_str!("../fixtures/v1/transaction_message_hash.txt"),
+        compute: transaction_hash,
+    },
+    Format {
+        name: "memo_message_hash",
+        fields: &["transaction_data", "memo"],
+        output: "the message hash of a transaction and its memo",
+        fixtures: include_str!("../fixtures/v1/memo_message_hash.txt"),
+        compute: memo_hash,
+    },
+    Format {
+        name: "action_message_hash",
+        fields: &["action", "payload"],
+        output: "the message hash of a management action",
+        fixtures: include_str!("../fixtures/v1/action_message_hash.txt"),
+        compute: action_hash,
+    },
+    Format {
+        name: "policy_hash",
+        fields: &["policy"],
+        output: "the canonical hash of a serialized policy",
+        fixtures: include_str!("../fixtures/v1/policy_hash.txt"),
+        compute: policy_hash,
+    },
+    Format {
+        name: "webauthn_signature",
+        fields: &["authenticator_data", "client_data_json", "signature", "credential_id"],
+        output: "the serialized WebAuthnSignature",
+        fixtures: include_str!("../fixtures/v1/webauthn_signature.txt"),
+        compute: webauthn_signature,
+    },
+    Format {
+        name: "execute_instruction",
+        fields: &[
+            "webauthn_sig",
+            "nonce_le",
+            "message_hash",
+            "transaction_data",
+            "idempotency_key",
+            "memo",
+            "emit_memo",
+        ],
+        output: "execute instruction data (an empty idempotency_key is none)",
+        fixtures: include_str!("../fixtures/v1/execute_instruction.txt"),
+        compute: execute_instruction,
+    },
+    Format {
+        name: "backup_key",
+        fields: &["recovery_phrase"],
+        output: "the key derived from a recovery phrase",
+        fixtures: include_str!("../fixtures/v1/backup_key.txt"),
+        compute: backup_key,
+    },
+    Format {
+        name: "encrypted_backup",
+        fields: &["encryption_key", "account_data", "created_at_le"],
+        output: "the serialized EncryptedBackup",
+        fixtures: include_str!("../fixtures/v1/encrypted_backup.txt"),
+        compute: encrypted_backup,
+    },
+    Format {
+        name: "rekeyed_backup",
+        fields: &["encryption_key", "account_data", "created_at_le", "rekey_generation_le"],
+        output: "the serialized EncryptedBackup, at a rekey generation",
+        fixtures: include_str!("../fixtures/v1/rekeyed_backup.txt"),
+        compute: rekeyed_backup,
+    },
+];
+
+/// One input of a format and the output it must give
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixture {
+    pub format: &'static str,
+    pub name: &'static str,
+    pub input: &'static str,
+    pub expected: &'static str,
+}
+
+/// How a set of outputs compared with the fixtures
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Fixtures whose output matched
+    pub passed: usize,
+
+    /// Everything that didn't, one line each
+    pub failures: Vec<String>,
+}
+
+impl Report {
+    /// Whether every fixture was given and matched
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty() && self.passed > 0
+    }
+}
+
+impl Format {
+    /// This format's fixtures
+    pub fn fixtures(&self) -> Result<Vec<Fixture>, ConformanceError> {
+        self.fixtures
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.split(' ');
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(name), Some(input), Some(expected), None) => {
+                        Ok(Fixture { format: self.name, name, input, expected })
+                    }
+                    _ => Err(ConformanceError::MalformedFixture(line.to_string())),
+                }
+            })
+            .collect()
+    }
+
+    /// This implementation's output for `input`, written as in a fixture
+    pub fn compute(&self, input: &str) -> Result<String, ConformanceError> {
+        let fields = if input == "-" {
+            vec![Vec::new()]
+        } else {
+            input.split(':').map(from_hex).collect::<Result<Vec<_>, _>>()?
+        };
+        if fields.len() != self.fields.len() {
+            return Err(ConformanceError::FieldCount {
+                format: self.name,
+                expected: self.fields.len(),
+                found: fields.len(),
+            });
+        }
+        (self.compute)(&fields)
+    }
+}
+
+/// The format named `name`
+pub fn format(name: &str) -> Option<&'static Format> {
+    FORMATS.iter().find(|format| format.name == name)
+}
+
+/// Every format's fixtures, in `FORMATS` order
+pub fn fixtures() -> Result<Vec<Fixture>, ConformanceError> {
+    let mut all = Vec::new();
+    for format in &FORMATS {
+        all.extend(format.fixtures()?);
+    }
+    Ok(all)
+}
+
+/// Compares another implementation's outputs with the fixtures
+///
+/// `outputs` has a `<format> <name> <output>` line per fixture; blank and
+/// `#` lines are skipped. Fixtures with no line are reported as missing.
+pub fn verify(outputs: &str) -> Result<Report, ConformanceError> {
+    let fixtures = fixtures()?;
+    let mut answered = vec![false; fixtures.len()];
+    let mut report = Report::default();
+
+    for line in outputs.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let mut parts = line.splitn(3, ' ');
+        let (Some(format), Some(name), Some(output)) = (parts.next(), parts.next(), parts.next()) else {
+            report.failures.push(format!("malformed line: {}", line));
+            continue;
+        };
+        let Some(index) = fixtures.iter().position(|f| f.format == format && f.name == name) else {
+            report.failures.push(format!("{}/{}: no such fixture", format, name));
+            continue;
+        };
+        if std::mem::replace(&mut answered[index], true) {
+            report.failures.push(format!("{}/{}: given more than once", format, name));
+            continue;
+        }
+        let expected = fixtures.get(index).map_or("", |f| f.expected);
+        if output == expected {
+            report.passed += 1;
+        } else {
+            report
+                .failures
+                .push(format!("{}/{}: expected {}, got {}", format, name, expected, output));
+        }
+    }
+
+    for (fixture, answered) in fixtures.iter().zip(answered) {
+        if !answered {
+            report.failures.push(format!("{}/{}: missing", fixture.format, fixture.name));
+        }
+    }
+    Ok(report)
+}
+
+fn challenge(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [owner, nonce, message_hash] = fields else { unreachable!() };
+    let owner: [u8; 32] = fixed("challenge", "owner", owner)?;
+    let nonce = u64::from_le_bytes(fixed("challenge", "nonce_le", nonce)?);
+    let message_hash = fixed("challenge", "message_hash", message_hash)?;
+    Ok(hex(&compute_challenge(&Pubkey::new_from_array(owner), nonce, &message_hash)))
+}
+
+fn display_code_of(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [challenge] = fields else { unreachable!() };
+    Ok(display_code(&fixed("display_code", "challenge", challenge)?))
+}
+
+fn client_data_challenge(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [challenge] = fields else { unreachable!() };
+    Ok(base64url_encode(challenge))
+}
+
+fn transaction_hash(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [transaction_data] = fields else { unreachable!() };
+    Ok(hex(&transaction_message_hash(transaction_data)))
+}
+
+fn memo_hash(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [transaction_data, memo] = fields else { unreachable!() };
+    Ok(hex(&transaction_memo_message_hash(transaction_data, memo)))
+}
+
+fn action_hash(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [action, payload] = fields else { unreachable!() };
+    Ok(hex(&action_message_hash(action, payload)))
+}
+
+fn policy_hash(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [policy] = fields else { unreachable!() };
+    let policy = Policy::from_bytes(policy)
+        .map_err(|_| ConformanceError::InvalidField { format: "policy_hash", field: "policy" })?;
+    Ok(hex(&policy.canonical_hash()))
+}
+
+fn webauthn_signature(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [authenticator_data, client_data_json, signature, credential_id] = fields else { unreachable!() };
+    let sig = WebAuthnSignature::new(
+        authenticator_data.clone(),
+        client_data_json.clone(),
+        signature.clone(),
+        credential_id.clone(),
+    );
+    Ok(hex(&sig.to_bytes()))
+}
+
+fn execute_instruction(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    const FORMAT: &str = "execute_instruction";
+    let [webauthn_sig, nonce, message_hash, transaction_data, idempotency_key, memo, emit_memo] = fields else {
+        unreachable!()
+    };
+    let idempotency_key = match idempotency_key.is_empty() {
+        true => None,
+        false => Some(fixed(FORMAT, "idempotency_key", idempotency_key)?),
+    };
+    let emit_memo = match emit_memo.as_slice() {
+        [0] => false,
+        [1] => true,
+        _ => return Err(ConformanceError::InvalidField { format: FORMAT, field: "emit_memo" }),
+    };
+    let args = ExecuteArgs {
+        webauthn_sig: webauthn_sig.clone(),
+        nonce: u64::from_le_bytes(fixed(FORMAT, "nonce_le", nonce)?),
+        message_hash: fixed(FORMAT, "message_hash", message_hash)?,
+        transaction_data: transaction_data.clone(),
+        idempotency_key,
+        memo: memo.clone(),
+        emit_memo,
+    };
+    let data = encode_execute(&args)
+        .map_err(|_| ConformanceError::InvalidField { format: FORMAT, field: "webauthn_sig" })?;
+    Ok(hex(&data))
+}
+
+fn backup_key(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    let [phrase] = fields else { unreachable!() };
+    let phrase = std::str::from_utf8(phrase)
+        .map_err(|_| ConformanceError::InvalidField { format: "backup_key", field: "recovery_phrase" })?;
+    Ok(hex(&derive_backup_key(phrase)))
+}
+
+fn encrypted_backup(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    const FORMAT: &str = "encrypted_backup";
+    let [encryption_key, account_data, created_at] = fields else { unreachable!() };
+    let created_at = i64::from_le_bytes(fixed(FORMAT, "created_at_le", created_at)?);
+    let bytes = EncryptedBackup::new(encryption_key, account_data, created_at)
+        .to_bytes()
+        .map_err(|_| ConformanceError::InvalidField { format: FORMAT, field: "account_data" })?;
+    Ok(hex(&bytes))
+}
+
+fn rekeyed_backup(fields: &[Vec<u8>]) -> Result<String, ConformanceError> {
+    const FORMAT: &str = "rekeyed_backup";
+    let [encryption_key, account_data, created_at, generation] = fields else { unreachable!() };
+    let created_at = i64::from_le_bytes(fixed(FORMAT, "created_at_le", created_at)?);
+    let generation = u16::from_le_bytes(fixed(FORMAT, "rekey_generation_le", generation)?);
+    let bytes = EncryptedBackup::new(encryption_key, account_data, created_at)
+        .with_generation(generation)
+        .to_bytes()
+        .map_err(|_| ConformanceError::InvalidField { format: FORMAT, field: "account_data" })?;
+    Ok(hex(&bytes))
+}
+
+/// `bytes` as an array of `N`, for a field of fixed length
+fn fixed<const N: usize>(
+    format: &'static str,
+    field: &'static str,
+    bytes: &[u8],
+) -> Result<[u8; N], ConformanceError> {
+    bytes
+        .try_into()
+        .map_err(|_| ConformanceError::FieldLength { format, field, expected: N, found: bytes.len() })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>, ConformanceError> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2 && pair.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| ConformanceError::InvalidHex(text.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use recovery::PolicyType;
+
+    #[test]
+    fn test_every_fixture_matches() {
+        for fixture in fixtures().unwrap() {
+            let format = format(fixture.format).unwrap();
+            assert_eq!(format.compute(fixture.input).unwrap(), fixture.expected, "{}/{}", fixture.format, fixture.name);
+        }
+    }
+
+    #[test]
+    fn test_every_format_has_fixtures() {
+        for format in &FORMATS {
+            let fixtures = format.fixtures().unwrap();
+            assert!(!fixtures.is_empty(), "{} has no fixtures", format.name);
+
+            let mut names: Vec<&str> = fixtures.iter().map(|f| f.name).collect();
+            names.sort_unstable();
+            names.dedup();
+            assert_eq!(names.len(), fixtures.len(), "{} has two fixtures with one name", format.name);
+        }
+
+        // And every fixture file belongs to a format
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/v1");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let file = entry.unwrap().file_name().into_string().unwrap();
+            let name = file.strip_suffix(".txt").unwrap();
+            assert!(format(name).is_some(), "{} isn't in FORMATS", file);
+        }
+    }
+
+    #[test]
+    fn test_every_policy_type_has_a_hash_fixture() {
+        let hashed: Vec<PolicyType> = format("policy_hash")
+            .unwrap()
+            .fixtures()
+            .unwrap()
+            .iter()
+            .map(|f| Policy::from_bytes(&from_hex(f.input).unwrap()).unwrap().policy_type)
+            .collect();
+
+        // Exhaustive, so a new policy type doesn't compile until it's listed here
+        let every = |policy_type: PolicyType| match policy_type {
+            PolicyType::Open
+            | PolicyType::SpendingLimit
+            | PolicyType::DailyLimit
+            | PolicyType::MultiSig
+            | PolicyType::TimeLocked
+            | PolicyType::DestinationAllowlist
+            | PolicyType::Composite
+            | PolicyType::CredentialBinding
+            | PolicyType::PerDestinationLimit
+            | PolicyType::Vault => policy_type,
+        };
+        for policy_type in [
+            PolicyType::Open,
+            PolicyType::SpendingLimit,
+            PolicyType::DailyLimit,
+            PolicyType::MultiSig,
+            PolicyType::TimeLocked,
+            PolicyType::DestinationAllowlist,
+            PolicyType::Composite,
+            PolicyType::CredentialBinding,
+            PolicyType::PerDestinationLimit,
+            PolicyType::Vault,
+        ]
+        .map(every)
+        {
+            assert!(hashed.contains(&policy_type), "no policy_hash fixture for {:?}", policy_type);
+        }
+    }
+
+    #[test]
+    fn test_verify_reports_mismatches_and_missing() {
+        let fixtures = fixtures().unwrap();
+        let lines: Vec<String> =
+            fixtures.iter().map(|f| format!("{} {} {}", f.format, f.name, f.expected)).collect();
+
+        let report = verify(&lines.join("\n")).unwrap();
+        assert!(report.is_success(), "{:?}", report.failures);
+        assert_eq!(report.passed, fixtures.len());
+
+        let mut wrong = lines[1..].to_vec();
+        wrong[0] = format!("{} {} 00", fixtures[1].format, fixtures[1].name);
+        wrong.push("display_code nonexistent RFJ85Q0M".to_string());
+        let report = verify(&wrong.join("\n")).unwrap();
+        assert!(!report.is_success());
+        assert_eq!(report.passed, fixtures.len() - 2);
+        assert_eq!(report.failures.len(), 3);
+        assert!(report.failures.iter().any(|f| f.ends_with(": missing")));
+        assert!(report.failures.iter().any(|f| f.ends_with("got 00")));
+
+        assert!(!verify("").unwrap().is_success());
+    }
+
+    #[test]
+    fn test_compute_rejects_bad_input() {
+        let challenge = format("challenge").unwrap();
+        assert!(matches!(challenge.compute("00:00"), Err(ConformanceError::FieldCount { expected: 3, found: 2, .. })));
+        assert!(matches!(challenge.compute("0g:00:00"), Err(ConformanceError::InvalidHex(_))));
+        assert!(matches!(
+            challenge.compute("00:00:00"),
+            Err(ConformanceError::FieldLength { field: "owner", expected: 32, found: 1, .. })
+        ));
+        assert!(matches!(
+            format("execute_instruction").unwrap().compute(&format!("::{}::::01", "00".repeat(32))),
+            Err(ConformanceError::FieldLength { field: "nonce_le", .. })
+        ));
+        assert!(format("policy_hash").unwrap().compute("ff").is_err());
+    }
+}
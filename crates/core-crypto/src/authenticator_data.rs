@@ -0,0 +1,277 @@
+//! Parsing WebAuthn authenticator data
+//!
+//! Authenticator data is a 37-byte header (RP ID hash, flags, signature
+//! counter) optionally followed by attested credential data (when the AT
+//! flag is set, mostly at registration) and a CBOR map of extension outputs
+//! (when the ED flag is set). The flags say exactly what follows the header,
+//! so anything else is rejected rather than guessed at.
+
+use crate::cose::{cbor_item_len, parse_cose_p256_key};
+use attesta_types::bytes::{read_array, read_slice, read_u16_be, read_u32_be, ParseError};
+use attesta_types::consts::{AAGUID_LEN, HASH_LEN, MIN_AUTHENTICATOR_DATA_LEN, P256_PUBKEY_LEN};
+use crate::errors::{CryptoError, FailureDetail, VerifyFailure};
+
+/// Length of the fixed header: RP ID hash (32) + flags (1) + signature counter (4)
+pub const AUTHENTICATOR_DATA_HEADER_LEN: usize = MIN_AUTHENTICATOR_DATA_LEN;
+
+/// Flag: the user was present (touched the authenticator)
+pub const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// Flag: the user was verified (biometric or PIN)
+pub const FLAG_USER_VERIFIED: u8 = 0x04;
+
+/// Flag: attested credential data follows the header
+pub const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// Flag: extension data follows (after any attested credential data)
+pub const FLAG_EXTENSION_DATA: u8 = 0x80;
+
+/// Authenticator data split into its parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedAuthenticatorData<'a> {
+    /// SHA-256 of the relying party ID the authenticator signed for
+    pub rp_id_hash: [u8; HASH_LEN],
+
+    /// The flags byte (see the `FLAG_*` constants)
+    pub flags: u8,
+
+    /// The signature counter (0 if the authenticator doesn't keep one)
+    pub sign_count: u32,
+
+    /// The credential the authenticator reports, when the AT flag is set
+    pub attested_credential: Option<AttestedCredentialData<'a>>,
+
+    /// The extension outputs (one CBOR map, unparsed), when the ED flag is set
+    pub extensions: Option<&'a [u8]>,
+}
+
+/// The credential part of authenticator data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestedCredentialData<'a> {
+    /// Identifies the authenticator model
+    pub aaguid: [u8; AAGUID_LEN],
+
+    /// The credential ID
+    pub credential_id: &'a [u8],
+
+    /// The credential's public key, 64 bytes (x || y)
+    pub public_key: [u8; P256_PUBKEY_LEN],
+}
+
+impl ParsedAuthenticatorData<'_> {
+    /// Whether the user was present
+    pub fn user_present(&self) -> bool {
+        self.flags & FLAG_USER_PRESENT != 0
+    }
+
+    /// Whether the user was verified
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_USER_VERIFIED != 0
+    }
+}
+
+/// Parses authenticator data
+///
+/// # Returns
+/// - `Ok(ParsedAuthenticatorData)` if the data is exactly what its flags describe
+/// - `Err(CryptoError::InvalidAuthenticatorData)` if it's too short, truncated,
+///   or has bytes its flags don't account for
+/// - `Err(CryptoError::InvalidP256PublicKey)` if attested credential data holds
+///   anything but a P-256 key
+pub fn parse_authenticator_data(data: &[u8]) -> Result<ParsedAuthenticatorData<'_>, CryptoError> {
+    parse_authenticator_data_detailed(data).map_err(CryptoError::from)
+}
+
+/// Like `parse_authenticator_data`, saying where in `data` parsing stopped
+///
+/// The offset is where the item that didn't parse starts: a truncated
+/// field, the COSE key, the extensions map, or the first unexplained byte.
+pub fn parse_authenticator_data_detailed(data: &[u8]) -> Result<ParsedAuthenticatorData<'_>, VerifyFailure> {
+    if data.len() < AUTHENTICATOR_DATA_HEADER_LEN {
+        return Err(VerifyFailure::new(
+            CryptoError::InvalidAuthenticatorData,
+            "authenticator_data",
+            FailureDetail::TooShort { minimum: AUTHENTICATOR_DATA_HEADER_LEN, actual: data.len() },
+        ));
+    }
+    let truncated = |error: ParseError| failure_at(error.offset, CryptoError::InvalidAuthenticatorData);
+
+    let mut offset = 0;
+    let rp_id_hash = read_array::<HASH_LEN>(data, &mut offset).map_err(truncated)?;
+    let [flags] = read_array::<1>(data, &mut offset).map_err(truncated)?;
+    let sign_count = read_u32_be(data, &mut offset).map_err(truncated)?;
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // AAGUID (16) + credential ID length (2, big-endian) + credential ID + COSE key
+        let aaguid = read_array::<AAGUID_LEN>(data, &mut offset).map_err(truncated)?;
+        let credential_id_len = read_u16_be(data, &mut offset).map_err(truncated)?;
+        let credential_id = read_slice(data, &mut offset, usize::from(credential_id_len)).map_err(truncated)?;
+
+        let key_data = data.get(offset..).unwrap_or_default();
+        let (public_key, key_len) = parse_cose_p256_key(key_data).map_err(|error| failure_at(offset, error))?;
+        read_slice(data, &mut offset, key_len).map_err(truncated)?;
+        Some(AttestedCredentialData { aaguid, credential_id, public_key })
+    } else {
+        None
+    };
+
+    let extensions = if flags & FLAG_EXTENSION_DATA != 0 {
+        let rest = data.get(offset..).unwrap_or_default();
+        let len = cbor_item_len(rest).map_err(|error| failure_at(offset, error))?;
+        Some(read_slice(data, &mut offset, len).map_err(truncated)?)
+    } else {
+        None
+    };
+
+    if offset != data.len() {
+        return Err(failure_at(offset, CryptoError::InvalidAuthenticatorData));
+    }
+
+    Ok(ParsedAuthenticatorData { rp_id_hash, flags, sign_count, attested_credential, extensions })
+}
+
+/// A failure at `offset` into the authenticator data
+fn failure_at(offset: usize, error: CryptoError) -> VerifyFailure {
+    VerifyFailure::new(error, "authenticator_data", FailureDetail::Offset(offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use crate::cose::tests::cose_key;
+    use crate::test_utils::TestPasskey;
+
+    const AAGUID: [u8; 16] = [0xad; 16];
+
+    fn header(flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(b"localhost").to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    fn attested_credential(credential_id: &[u8], public_key: &[u8; 64]) -> Vec<u8> {
+        let mut data = AAGUID.to_vec();
+        data.extend_from_slice(&(credential_id.len() as u16).to_be_bytes());
+        data.extend_from_slice(credential_id);
+        data.extend_from_slice(&cose_key(public_key));
+        data
+    }
+
+    /// {"credProtect": 2}
+    fn extensions() -> Vec<u8> {
+        let mut data = vec![0xa1, 0x6b];
+        data.extend_from_slice(b"credProtect");
+        data.push(0x02);
+        data
+    }
+
+    #[test]
+    fn test_header_only() {
+        let data = header(FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 7);
+        let parsed = parse_authenticator_data(&data).unwrap();
+
+        assert_eq!(parsed.rp_id_hash.as_slice(), Sha256::digest(b"localhost").as_slice());
+        assert!(parsed.user_present() && parsed.user_verified());
+        assert_eq!(parsed.sign_count, 7);
+        assert_eq!(parsed.attested_credential, None);
+        assert_eq!(parsed.extensions, None);
+    }
+
+    #[test]
+    fn test_attested_credential_data() {
+        let public_key = TestPasskey::new(1).public_key();
+        let mut data = header(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+        data.extend_from_slice(&attested_credential(b"cred-1", &public_key));
+
+        let credential = parse_authenticator_data(&data).unwrap().attested_credential.unwrap();
+        assert_eq!(credential.aaguid, AAGUID);
+        assert_eq!(credential.credential_id, b"cred-1");
+        assert_eq!(credential.public_key, public_key);
+    }
+
+    #[test]
+    fn test_extensions_with_and_without_credential() {
+        let mut data = header(FLAG_USER_PRESENT | FLAG_EXTENSION_DATA, 1);
+        data.extend_from_slice(&extensions());
+        assert_eq!(parse_authenticator_data(&data).unwrap().extensions, Some(extensions().as_slice()));
+
+        let public_key = TestPasskey::new(1).public_key();
+        let mut data = header(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA | FLAG_EXTENSION_DATA, 1);
+        data.extend_from_slice(&attested_credential(b"cred-1", &public_key));
+        data.extend_from_slice(&extensions());
+
+        let parsed = parse_authenticator_data(&data).unwrap();
+        assert_eq!(parsed.attested_credential.unwrap().credential_id, b"cred-1");
+        assert_eq!(parsed.extensions, Some(extensions().as_slice()));
+    }
+
+    #[test]
+    fn test_rejects_inconsistent_data() {
+        let public_key = TestPasskey::new(1).public_key();
+        let credential = attested_credential(b"cred-1", &public_key);
+
+        // Too short for the header
+        assert!(parse_authenticator_data(&[0u8; 36]).is_err());
+
+        // AT set, credential data truncated at every length
+        for len in 0..credential.len() {
+            let mut data = header(FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+            data.extend_from_slice(&credential[..len]);
+            assert!(parse_authenticator_data(&data).is_err(), "accepted {} bytes of credential data", len);
+        }
+
+        // A credential ID length running past the end of the data
+        let mut data = header(FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+        data.extend_from_slice(&AAGUID);
+        data.extend_from_slice(&[0xff, 0xff]);
+        data.extend_from_slice(b"cred-1");
+        assert_eq!(parse_authenticator_data(&data), Err(CryptoError::InvalidAuthenticatorData));
+
+        // ED set with nothing (or a truncated map) after the header
+        assert!(parse_authenticator_data(&header(FLAG_EXTENSION_DATA, 0)).is_err());
+        let mut data = header(FLAG_EXTENSION_DATA, 0);
+        data.extend_from_slice(&extensions()[..4]);
+        assert!(parse_authenticator_data(&data).is_err());
+
+        // Data the flags don't mention
+        let mut data = header(FLAG_USER_PRESENT, 0);
+        data.extend_from_slice(&extensions());
+        assert_eq!(parse_authenticator_data(&data), Err(CryptoError::InvalidAuthenticatorData));
+
+        let mut data = header(FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+        data.extend_from_slice(&credential);
+        data.push(0);
+        assert_eq!(parse_authenticator_data(&data), Err(CryptoError::InvalidAuthenticatorData));
+    }
+
+    #[test]
+    fn test_detailed_failure_gives_offset() {
+        let offset_of = |data: &[u8]| parse_authenticator_data_detailed(data).unwrap_err().detail;
+        let public_key = TestPasskey::new(1).public_key();
+
+        // Truncated inside the credential ID length: it starts after the AAGUID
+        let mut data = header(FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+        data.extend_from_slice(&AAGUID);
+        data.push(0);
+        assert_eq!(offset_of(&data), FailureDetail::Offset(37 + 16));
+
+        // A key that isn't P-256: where the COSE key starts
+        let mut data = header(FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+        let mut credential = attested_credential(b"cred-1", &public_key);
+        credential.truncate(16 + 2 + 6);
+        credential.extend_from_slice(&[0xa1, 0x01, 0x03]);
+        data.extend_from_slice(&credential);
+        let failure = parse_authenticator_data_detailed(&data).unwrap_err();
+        assert_eq!(failure.error, CryptoError::InvalidP256PublicKey);
+        assert_eq!(failure.detail, FailureDetail::Offset(37 + 16 + 2 + 6));
+
+        // The first byte the flags don't explain
+        let mut data = header(FLAG_EXTENSION_DATA, 0);
+        data.extend_from_slice(&extensions());
+        data.push(0);
+        assert_eq!(offset_of(&data), FailureDetail::Offset(37 + extensions().len()));
+    }
+}
+
@@ -0,0 +1,81 @@
+//! Which WebAuthn ceremony a signature came from
+//!
+//! Browsers write the ceremony into `clientDataJSON`'s `type` field:
+//! `webauthn.get` for an assertion, `webauthn.create` for a registration.
+//! Both are signed the same way, so without checking it a registration
+//! could be replayed as an assertion or the other way round. The field must
+//! be exactly the expected string; anything else, including ceremony types
+//! added to the spec later, is rejected.
+
+use crate::challenge::client_data_field;
+use crate::errors::{CryptoError, FailureDetail, VerifyFailure};
+
+/// The ceremony a signature is expected to come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CeremonyType {
+    /// `navigator.credentials.get()`: authorizing something with an enrolled passkey
+    Get,
+
+    /// `navigator.credentials.create()`: enrolling a passkey
+    Create,
+}
+
+impl CeremonyType {
+    /// The `type` field `clientDataJSON` carries for this ceremony
+    pub fn client_data_type(self) -> &'static str {
+        match self {
+            CeremonyType::Get => "webauthn.get",
+            CeremonyType::Create => "webauthn.create",
+        }
+    }
+}
+
+/// Checks `clientDataJSON`'s `type` field is exactly `expected`'s
+///
+/// # Returns
+/// - `Ok(())` if it matches
+/// - `Err(CryptoError::CeremonyTypeMismatch)` if it's missing or different
+pub fn verify_client_data_type(client_data_json: &[u8], expected: CeremonyType) -> Result<(), CryptoError> {
+    verify_client_data_type_detailed(client_data_json, expected).map_err(CryptoError::from)
+}
+
+/// Like `verify_client_data_type`, as a `VerifyFailure` on the `type` field
+pub fn verify_client_data_type_detailed(client_data_json: &[u8], expected: CeremonyType) -> Result<(), VerifyFailure> {
+    if client_data_field(client_data_json, "type") == Some(expected.client_data_type()) {
+        return Ok(());
+    }
+    Err(VerifyFailure::new(CryptoError::CeremonyTypeMismatch, "type", FailureDetail::None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_must_match_exactly() {
+        let json = |kind: &str| format!(r#"{{"type":"{}","challenge":"abc"}}"#, kind).into_bytes();
+
+        assert_eq!(verify_client_data_type(&json("webauthn.get"), CeremonyType::Get), Ok(()));
+        assert_eq!(verify_client_data_type(&json("webauthn.create"), CeremonyType::Create), Ok(()));
+        for (kind, expected) in [
+            ("webauthn.create", CeremonyType::Get),
+            ("webauthn.get", CeremonyType::Create),
+            ("payment.get", CeremonyType::Get),
+            ("webauthn.get ", CeremonyType::Get),
+            ("WebAuthn.get", CeremonyType::Get),
+            ("webauthn.getx", CeremonyType::Get),
+        ] {
+            assert_eq!(
+                verify_client_data_type(&json(kind), expected),
+                Err(CryptoError::CeremonyTypeMismatch),
+                "{:?} for {:?}",
+                kind,
+                expected
+            );
+        }
+        assert_eq!(
+            verify_client_data_type(br#"{"challenge":"abc"}"#, CeremonyType::Get),
+            Err(CryptoError::CeremonyTypeMismatch)
+        );
+    }
+}
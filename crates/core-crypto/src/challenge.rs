@@ -0,0 +1,181 @@
+use sha2::{Digest, Sha256};
+use solana_program::pubkey::Pubkey;
+use attesta_types::consts::HASH_LEN;
+use crate::errors::{CryptoError, FailureDetail, VerifyFailure};
+use crate::hashing::{domain_hasher, Domain};
+
+/// Length of the challenge a passkey signs (in bytes)
+pub const CHALLENGE_LEN: usize = HASH_LEN;
+
+/// Computes the WebAuthn challenge for an Attesta authorization
+///
+/// The challenge ties a passkey signature to one specific authorization:
+/// the account it's for, the nonce being consumed, and the hash of what's
+/// being authorized. Changing any of them changes the challenge, so a
+/// signature can't be moved to a different account, nonce, or transaction.
+///
+/// # Parameters
+/// - `account_owner`: The owner of the Attesta account being authorized
+/// - `nonce`: The nonce this authorization consumes
+/// - `message_hash`: The hash of the transaction or action being authorized
+///
+/// # Returns
+/// The 32-byte challenge to pass to `navigator.credentials.get()`
+pub fn compute_challenge(account_owner: &Pubkey, nonce: u64, message_hash: &[u8; 32]) -> [u8; CHALLENGE_LEN] {
+    let mut hasher = domain_hasher(Domain::ExecChallenge);
+    hasher.update(account_owner.as_ref());
+    hasher.update(nonce.to_le_bytes());
+    hasher.update(message_hash);
+    hasher.finalize().into()
+}
+
+/// Encodes bytes as unpadded base64url
+///
+/// This is how browsers put the challenge into `clientDataJSON`, so it's the
+/// form we compare against when checking a signature.
+pub fn base64url_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 4).div_ceil(3));
+    out.extend(base64url_chars(bytes));
+    out
+}
+
+/// The characters of `base64url_encode(bytes)`, without collecting them
+///
+/// Comparing against these lets a challenge be checked without allocating.
+fn base64url_chars(bytes: &[u8]) -> impl Iterator<Item = char> + '_ {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    bytes.chunks(3).flat_map(|chunk| {
+        let b0 = chunk.first().copied().unwrap_or(0) as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+
+        // 1 input byte -> 2 chars, 2 bytes -> 3 chars, 3 bytes -> 4 chars
+        (0..=chunk.len()).filter_map(move |i| {
+            let index = (triple >> (18 - 6 * i)) & 0x3f;
+            ALPHABET.get(index as usize).map(|&c| c as char)
+        })
+    })
+}
+
+/// Reads a string field from `clientDataJSON`
+///
+/// Browsers produce a flat JSON object with plain string values, so this
+/// only needs to find `"field":"value"` - it doesn't handle escapes or nesting.
+///
+/// # Returns
+/// - `Some(value)` if the field is present with a string value
+/// - `None` if it's missing or not a string
+pub fn client_data_field<'a>(client_data_json: &'a [u8], field: &str) -> Option<&'a str> {
+    let json = std::str::from_utf8(client_data_json).ok()?;
+
+    // Find `"field"` by trying each opening quote, rather than formatting the key
+    let mut rest = json;
+    let after_key = loop {
+        let candidate = rest.get(rest.find('"')? + 1..)?;
+        match candidate.strip_prefix(field).and_then(|after| after.strip_prefix('"')) {
+            Some(after_key) => break after_key,
+            None => rest = candidate,
+        }
+    };
+    let after_colon = after_key.trim_start().strip_prefix(':')?;
+    let value = after_colon.trim_start().strip_prefix('"')?;
+    let end = value.find('"')?;
+
+    value.get(..end)
+}
+
+/// Checks that `clientDataJSON` was produced for the expected challenge
+///
+/// # Parameters
+/// - `client_data_json`: The raw `clientDataJSON` from the authenticator response
+/// - `expected_challenge`: The challenge we asked the passkey to sign
+///
+/// # Returns
+/// - `Ok(())` if the challenge field matches exactly
+/// - `Err(CryptoError::ChallengeMismatch)` if it's missing or different
+pub fn verify_client_data_challenge(
+    client_data_json: &[u8],
+    expected_challenge: &[u8],
+) -> Result<(), CryptoError> {
+    verify_client_data_challenge_detailed(client_data_json, expected_challenge).map_err(CryptoError::from)
+}
+
+/// Like `verify_client_data_challenge`, with hashes of both challenges on a mismatch
+pub fn verify_client_data_challenge_detailed(
+    client_data_json: &[u8],
+    expected_challenge: &[u8],
+) -> Result<(), VerifyFailure> {
+    let found = client_data_field(client_data_json, "challenge");
+    if !expected_challenge.is_empty() && found.is_some_and(|found| found.chars().eq(base64url_chars(expected_challenge))) {
+        return Ok(());
+    }
+    Err(VerifyFailure::new(CryptoError::ChallengeMismatch, "challenge", mismatch_detail(expected_challenge, found)))
+}
+
+#[cfg(feature = "detailed-errors")]
+fn mismatch_detail(expected_challenge: &[u8], found: Option<&str>) -> FailureDetail {
+    FailureDetail::Mismatch {
+        expected: Sha256::digest(base64url_encode(expected_challenge)).into(),
+        found: found.map(|found| Sha256::digest(found).into()),
+    }
+}
+
+/// Hashing both challenges costs compute units a failing transaction
+/// would only spend on a message nobody reads
+#[cfg(not(feature = "detailed-errors"))]
+fn mismatch_detail(_expected_challenge: &[u8], _found: Option<&str>) -> FailureDetail {
+    FailureDetail::None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64url_encode() {
+        // RFC 4648 test vectors, without padding
+        assert_eq!(base64url_encode(b""), "");
+        assert_eq!(base64url_encode(b"f"), "Zg");
+        assert_eq!(base64url_encode(b"fo"), "Zm8");
+        assert_eq!(base64url_encode(b"foo"), "Zm9v");
+        assert_eq!(base64url_encode(b"foobar"), "Zm9vYmFy");
+        // URL-safe alphabet
+        assert_eq!(base64url_encode(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_compute_challenge_binds_all_inputs() {
+        let owner = Pubkey::new_unique();
+        let challenge = compute_challenge(&owner, 1, &[7u8; 32]);
+
+        assert_eq!(challenge, compute_challenge(&owner, 1, &[7u8; 32]));
+        assert_ne!(challenge, compute_challenge(&Pubkey::new_unique(), 1, &[7u8; 32]));
+        assert_ne!(challenge, compute_challenge(&owner, 2, &[7u8; 32]));
+        assert_ne!(challenge, compute_challenge(&owner, 1, &[8u8; 32]));
+    }
+
+    #[test]
+    fn test_client_data_field() {
+        let json = br#"{"type":"webauthn.get", "challenge" : "abc-_", "origin":"https://example.com"}"#;
+        assert_eq!(client_data_field(json, "type"), Some("webauthn.get"));
+        assert_eq!(client_data_field(json, "challenge"), Some("abc-_"));
+        assert_eq!(client_data_field(json, "crossOrigin"), None);
+    }
+
+    #[test]
+    fn test_verify_client_data_challenge() {
+        let challenge = [5u8; 32];
+        let json = format!(r#"{{"type":"webauthn.get","challenge":"{}"}}"#, base64url_encode(&challenge));
+
+        assert!(verify_client_data_challenge(json.as_bytes(), &challenge).is_ok());
+        assert_eq!(
+            verify_client_data_challenge(json.as_bytes(), &[6u8; 32]),
+            Err(CryptoError::ChallengeMismatch)
+        );
+        // A challenge that only appears as a substring doesn't count
+        let prefixed = format!(r#"{{"challenge":"x{}"}}"#, base64url_encode(&challenge));
+        assert!(verify_client_data_challenge(prefixed.as_bytes(), &challenge).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! COSE public keys, as passkeys report them at registration
+//!
+//! Authenticators encode the credential public key as a COSE_Key: a CBOR
+//! map of integer labels. We only accept ES256 keys (EC2 on P-256), so this
+//! reads just enough CBOR to find the coordinates and to know where the key
+//! ends.
+
+use attesta_types::consts::P256_PUBKEY_LEN;
+use crate::errors::CryptoError;
+use crate::p256_verify::validate_p256_public_key;
+
+/// COSE key type for elliptic-curve keys with x and y coordinates
+const COSE_KTY_EC2: i64 = 2;
+
+/// COSE curve identifier for P-256
+const COSE_CRV_P256: i64 = 1;
+
+/// COSE algorithm identifier for ES256 (ECDSA with SHA-256)
+const COSE_ALG_ES256: i64 = -7;
+
+/// How deeply nested CBOR we'll walk before giving up
+///
+/// Real keys and extensions nest two or three levels; the limit keeps
+/// hostile input from exhausting the stack on-chain.
+const MAX_CBOR_DEPTH: usize = 8;
+
+/// Reads a COSE_Key holding a P-256 public key
+///
+/// # Parameters
+/// - `data`: Bytes starting with the COSE_Key (anything after it is ignored)
+///
+/// # Returns
+/// - `Ok((public_key, len))`: The key as 64 bytes (x || y), and how many
+///   bytes of `data` the COSE_Key took
+/// - `Err(CryptoError::InvalidAuthenticatorData)` if it isn't well-formed CBOR
+/// - `Err(CryptoError::InvalidP256PublicKey)` if it's a different kind of key
+///   or not on the curve
+pub fn parse_cose_p256_key(data: &[u8]) -> Result<([u8; P256_PUBKEY_LEN], usize), CryptoError> {
+    let mut reader = CborReader::new(data);
+    let (major, entries) = reader.read_header()?;
+    if major != MAJOR_MAP {
+        return Err(CryptoError::InvalidAuthenticatorData);
+    }
+
+    let (mut kty, mut crv, mut alg) = (None, None, None);
+    let (mut x, mut y) = (None, None);
+    for _ in 0..entries {
+        let label = match reader.peek_major()? {
+            MAJOR_UNSIGNED | MAJOR_NEGATIVE => reader.read_int()?,
+            // Text labels are allowed but none of ours use them
+            _ => {
+                reader.skip_item(0)?;
+                reader.skip_item(0)?;
+                continue;
+            }
+        };
+        match label {
+            1 => kty = Some(reader.read_int()?),
+            3 => alg = Some(reader.read_int()?),
+            -1 => crv = Some(reader.read_int()?),
+            -2 => x = Some(reader.read_bytes()?),
+            -3 => y = Some(reader.read_bytes()?),
+            _ => reader.skip_item(0)?,
+        }
+    }
+
+    if kty != Some(COSE_KTY_EC2) || crv != Some(COSE_CRV_P256) || alg.is_some_and(|alg| alg != COSE_ALG_ES256) {
+        return Err(CryptoError::InvalidP256PublicKey);
+    }
+    let (Some(x), Some(y)) = (x, y) else {
+        return Err(CryptoError::InvalidP256PublicKey);
+    };
+    const COORDINATE_LEN: usize = P256_PUBKEY_LEN / 2;
+    if x.len() != COORDINATE_LEN || y.len() != COORDINATE_LEN {
+        return Err(CryptoError::InvalidP256PublicKey);
+    }
+
+    let mut public_key = [0u8; P256_PUBKEY_LEN];
+    public_key[..COORDINATE_LEN].copy_from_slice(x);
+    public_key[COORDINATE_LEN..].copy_from_slice(y);
+    validate_p256_public_key(&public_key)?;
+    Ok((public_key, reader.pos))
+}
+
+/// Length of the CBOR item at the start of `data`
+///
+/// # Returns
+/// - `Ok(len)` for a well-formed item (anything after it is ignored)
+/// - `Err(CryptoError::InvalidAuthenticatorData)` otherwise
+pub fn cbor_item_len(data: &[u8]) -> Result<usize, CryptoError> {
+    let mut reader = CborReader::new(data);
+    reader.skip_item(0)?;
+    Ok(reader.pos)
+}
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+
+/// A cursor over definite-length CBOR (all CTAP2 produces)
+struct CborReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CryptoError> {
+        let end = self.pos.checked_add(len).ok_or(CryptoError::InvalidAuthenticatorData)?;
+        let bytes = self.data.get(self.pos..end).ok_or(CryptoError::InvalidAuthenticatorData)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn take_byte(&mut self) -> Result<u8, CryptoError> {
+        let byte = self.data.get(self.pos).copied().ok_or(CryptoError::InvalidAuthenticatorData)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn peek_major(&self) -> Result<u8, CryptoError> {
+        self.data.get(self.pos).map(|b| b >> 5).ok_or(CryptoError::InvalidAuthenticatorData)
+    }
+
+    /// Reads an item's major type and argument
+    fn read_header(&mut self) -> Result<(u8, u64), CryptoError> {
+        let initial = self.take_byte()?;
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => self.take_byte()? as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap_or_default()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap_or_default()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap_or_default()),
+            // Reserved, or indefinite length
+            _ => return Err(CryptoError::InvalidAuthenticatorData),
+        };
+        Ok((major, argument))
+    }
+
+    fn read_int(&mut self) -> Result<i64, CryptoError> {
+        let (major, argument) = self.read_header()?;
+        let value = i64::try_from(argument).map_err(|_| CryptoError::InvalidAuthenticatorData)?;
+        match major {
+            MAJOR_UNSIGNED => Ok(value),
+            MAJOR_NEGATIVE => Ok(-1 - value),
+            _ => Err(CryptoError::InvalidAuthenticatorData),
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], CryptoError> {
+        match self.read_header()? {
+            (MAJOR_BYTES, len) => self.take(usize::try_from(len).map_err(|_| CryptoError::InvalidAuthenticatorData)?),
+            _ => Err(CryptoError::InvalidAuthenticatorData),
+        }
+    }
+
+    fn skip_item(&mut self, depth: usize) -> Result<(), CryptoError> {
+        if depth > MAX_CBOR_DEPTH {
+            return Err(CryptoError::InvalidAuthenticatorData);
+        }
+        let (major, argument) = self.read_header()?;
+        let count = usize::try_from(argument).map_err(|_| CryptoError::InvalidAuthenticatorData)?;
+        match major {
+            MAJOR_BYTES | MAJOR_TEXT => {
+                self.take(count)?;
+            }
+            MAJOR_ARRAY => {
+                for _ in 0..count {
+                    self.skip_item(depth + 1)?;
+                }
+            }
+            MAJOR_MAP => {
+                for _ in 0..count {
+                    self.skip_item(depth + 1)?;
+                    self.skip_item(depth + 1)?;
+                }
+            }
+            MAJOR_TAG => self.skip_item(depth + 1)?,
+            // Integers, simple values and floats carry everything in the header
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::test_utils::TestPasskey;
+
+    /// Encodes a public key as the COSE_Key an authenticator would report
+    pub(crate) fn cose_key(public_key: &[u8; 64]) -> Vec<u8> {
+        let mut key = vec![
+            0xa5, // map(5)
+            0x01, 0x02, // kty: EC2
+            0x03, 0x26, // alg: ES256
+            0x20, 0x01, // crv: P-256
+            0x21, 0x58, 0x20, // x: bytes(32)
+        ];
+        key.extend_from_slice(&public_key[..32]);
+        key.extend_from_slice(&[0x22, 0x58, 0x20]); // y: bytes(32)
+        key.extend_from_slice(&public_key[32..]);
+        key
+    }
+
+    #[test]
+    fn test_parse_cose_p256_key() {
+        let public_key = TestPasskey::new(1).public_key();
+        let mut data = cose_key(&public_key);
+        let key_len = data.len();
+        data.extend_from_slice(b"trailing");
+
+        assert_eq!(parse_cose_p256_key(&data), Ok((public_key, key_len)));
+    }
+
+    #[test]
+    fn test_rejects_other_keys() {
+        let public_key = TestPasskey::new(1).public_key();
+
+        let mut rsa = cose_key(&public_key);
+        rsa[2] = 0x03; // kty: RSA
+        assert_eq!(parse_cose_p256_key(&rsa), Err(CryptoError::InvalidP256PublicKey));
+
+        let mut off_curve = public_key;
+        off_curve[63] ^= 1;
+        assert_eq!(parse_cose_p256_key(&cose_key(&off_curve)), Err(CryptoError::InvalidP256PublicKey));
+
+        let truncated = cose_key(&public_key);
+        assert_eq!(
+            parse_cose_p256_key(&truncated[..truncated.len() - 1]),
+            Err(CryptoError::InvalidAuthenticatorData)
+        );
+    }
+
+    #[test]
+    fn test_cbor_item_len() {
+        // {"credProtect": 2}
+        let mut extensions = vec![0xa1, 0x6b];
+        extensions.extend_from_slice(b"credProtect");
+        extensions.push(0x02);
+
+        assert_eq!(cbor_item_len(&extensions), Ok(extensions.len()));
+        assert!(cbor_item_len(&extensions[..extensions.len() - 1]).is_err());
+        // Indefinite-length items aren't produced by authenticators
+        assert!(cbor_item_len(&[0xbf, 0xff]).is_err());
+        // Deep nesting is cut off
+        assert!(cbor_item_len(&[0x81; 64]).is_err());
+    }
+}
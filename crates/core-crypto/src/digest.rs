@@ -0,0 +1,119 @@
+//! Short codes users can compare across devices
+//!
+//! A passkey prompt only shows the site name, so a user can't tell which
+//! transaction they're approving. Wallets show a display code derived from
+//! the challenge next to the prompt, the dApp shows the one it computed, and
+//! the program logs the one it executed - if all three match, the user signed
+//! what they meant to.
+//!
+//! The code is 7 Crockford base32 characters from a hash of the challenge,
+//! plus a Crockford check character. It's for eyeballing, not security: 35
+//! bits won't stop a determined collision search.
+
+use crate::challenge::CHALLENGE_LEN;
+use crate::hashing::{domain_hash, Domain};
+
+/// Length of a display code, including the check character
+pub const DISPLAY_CODE_LEN: usize = 8;
+
+/// Crockford base32 digits (no I, L, O or U)
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Check characters: the base32 digits followed by the five extra symbols
+const CHECK_ALPHABET: &[u8; 37] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ*~$=U";
+
+/// Bits of the hash the code carries
+const CODE_BITS: u32 = 5 * (DISPLAY_CODE_LEN as u32 - 1);
+
+/// Derives the display code for `challenge`
+///
+/// Deterministic, so the wallet, the dApp and the program all arrive at the
+/// same code. The TypeScript SDK's `displayCode` must match the vectors in
+/// `test-vectors/display_code.txt`.
+///
+/// # Returns
+/// An 8-character uppercase code, e.g. `"RFJ85Q0M"`
+pub fn display_code(challenge: &[u8; CHALLENGE_LEN]) -> String {
+    let digest = domain_hash(Domain::DisplayCode, challenge);
+
+    // The top 35 bits of the hash
+    let mut top = [0u8; 8];
+    top[3..].copy_from_slice(&digest[..5]);
+    let value = u64::from_be_bytes(top) >> (40 - CODE_BITS);
+
+    let mut code = String::with_capacity(DISPLAY_CODE_LEN);
+    for i in (0..DISPLAY_CODE_LEN as u32 - 1).rev() {
+        code.extend(ALPHABET.get(((value >> (5 * i)) & 0x1f) as usize).map(|&c| c as char));
+    }
+    code.extend(CHECK_ALPHABET.get((value % 37) as usize).map(|&c| c as char));
+    code
+}
+
+/// Checks a code the user typed or read back against `challenge`
+///
+/// Follows Crockford's decoding rules, so lowercase letters, `O` for `0`,
+/// `I`/`L` for `1`, and `-` separators are all accepted.
+pub fn verify_display_code(code: &str, challenge: &[u8; CHALLENGE_LEN]) -> bool {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect();
+    normalized == display_code(challenge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_hex(hex: &str) -> [u8; CHALLENGE_LEN] {
+        let mut bytes = [0u8; CHALLENGE_LEN];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    fn vectors() -> Vec<([u8; CHALLENGE_LEN], &'static str)> {
+        include_str!("../test-vectors/display_code.txt")
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (challenge, code) = line.split_once(' ').unwrap();
+                (parse_hex(challenge), code)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_display_code_vectors() {
+        let vectors = vectors();
+        assert!(!vectors.is_empty());
+        for (challenge, code) in vectors {
+            assert_eq!(display_code(&challenge), code);
+            assert!(verify_display_code(code, &challenge));
+        }
+    }
+
+    #[test]
+    fn test_display_code_shape() {
+        let code = display_code(&[9u8; CHALLENGE_LEN]);
+        assert_eq!(code.len(), DISPLAY_CODE_LEN);
+        assert!(code[..DISPLAY_CODE_LEN - 1].bytes().all(|c| ALPHABET.contains(&c)));
+        assert!(CHECK_ALPHABET.contains(&code.as_bytes()[DISPLAY_CODE_LEN - 1]));
+    }
+
+    #[test]
+    fn test_verify_display_code_is_forgiving_about_input() {
+        // "RFJ85Q0M"
+        let challenge = [0u8; CHALLENGE_LEN];
+        assert!(verify_display_code("rfj8-5qOm", &challenge));
+        assert!(!verify_display_code("RFJ85Q0N", &challenge));
+        assert!(!verify_display_code("RFJ85Q0", &challenge));
+        assert!(!verify_display_code("RFJ85Q0M", &[1u8; CHALLENGE_LEN]));
+    }
+}
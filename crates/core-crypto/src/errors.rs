@@ -1,3 +1,4 @@
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone, PartialEq)]
@@ -28,6 +29,119 @@ pub enum CryptoError {
 
     #[error("Invalid authenticator data")]
     InvalidAuthenticatorData,
+
+    #[error("Credential has been revoked")]
+    RevokedCredential,
+
+    #[error("Idempotency key was already used for a different transaction")]
+    IdempotencyKeyReused,
+
+    #[error("Signed for a different relying party ID")]
+    RpIdMismatch,
+
+    #[error("Signed on a different origin")]
+    OriginMismatch,
+
+    #[error("User presence flag not set")]
+    UserNotPresent,
+
+    #[error("User verification flag not set")]
+    UserNotVerified,
+
+    #[error("Signature counter did not increase")]
+    SignCountNotIncreased,
+
+    #[error("Signature is not low-S")]
+    HighSSignature,
+
+    #[error("Signed in a different WebAuthn ceremony")]
+    CeremonyTypeMismatch,
+}
+
+/// What was wrong with the part of a signature that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureDetail {
+    /// Nothing more to say than the error itself
+    None,
+
+    /// The part has the wrong length
+    Length { expected: usize, actual: usize },
+
+    /// The part is shorter than its smallest valid length
+    TooShort { minimum: usize, actual: usize },
+
+    /// The part stops parsing at this byte offset
+    Offset(usize),
+
+    /// The challenge signed isn't the one expected
+    ///
+    /// Both are SHA-256 hashes of the base64url challenge strings, so logs
+    /// don't carry the challenges themselves. `found` is `None` when
+    /// `clientDataJSON` has no challenge field.
+    Mismatch { expected: [u8; 32], found: Option<[u8; 32]> },
+}
+
+/// A failed verification, with where and why it failed
+///
+/// Returned by the `*_detailed` verification functions; it converts into
+/// the `CryptoError` the plain ones return. Formatting the detail needs the
+/// `detailed-errors` feature (on by default, off for on-chain builds), and
+/// without it challenge mismatches don't hash the challenges either.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyFailure {
+    /// The error the plain verification functions return
+    pub error: CryptoError,
+
+    /// The part that failed: `authenticator_data`, `type`, `challenge`, `signature` or `public_key`
+    pub field: &'static str,
+
+    /// What was wrong with it
+    pub detail: FailureDetail,
+}
+
+impl VerifyFailure {
+    pub fn new(error: CryptoError, field: &'static str, detail: FailureDetail) -> Self {
+        Self { error, field, detail }
+    }
+}
+
+impl From<VerifyFailure> for CryptoError {
+    fn from(failure: VerifyFailure) -> Self {
+        failure.error
+    }
+}
+
+impl fmt::Display for VerifyFailure {
+    #[cfg(feature = "detailed-errors")]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.error, self.field)?;
+        match &self.detail {
+            FailureDetail::None => {}
+            FailureDetail::Length { expected, actual } => write!(f, ": expected {} bytes, got {}", expected, actual)?,
+            FailureDetail::TooShort { minimum, actual } => write!(f, ": expected at least {} bytes, got {}", minimum, actual)?,
+            FailureDetail::Offset(offset) => write!(f, ": at byte {}", offset)?,
+            FailureDetail::Mismatch { expected, found } => {
+                write!(f, ": expected hash {}, found ", hex(expected))?;
+                match found {
+                    Some(found) => write!(f, "hash {}", hex(found))?,
+                    None => f.write_str("no challenge")?,
+                }
+            }
+        }
+        f.write_str(")")
+    }
+
+    #[cfg(not(feature = "detailed-errors"))]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.error, self.field)
+    }
+}
+
+impl std::error::Error for VerifyFailure {}
+
+#[cfg(feature = "detailed-errors")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
 
 impl From<CryptoError> for solana_program::program_error::ProgramError {
@@ -35,3 +149,9 @@ impl From<CryptoError> for solana_program::program_error::ProgramError {
         solana_program::program_error::ProgramError::Custom(e as u32)
     }
 }
+
+impl From<attesta_types::webauthn::SignatureFormatError> for CryptoError {
+    fn from(_: attesta_types::webauthn::SignatureFormatError) -> Self {
+        CryptoError::InvalidSignatureFormat
+    }
+}
@@ -0,0 +1,8 @@
+//! Domain-separated hashing
+//!
+//! The registry itself lives in `attesta_types::hashing`, below everything
+//! that hashes, so the transaction and policy hashes defined there go
+//! through it too. It's re-exported here next to the challenge and display
+//! code, which are the hashes a passkey's signature depends on.
+
+pub use attesta_types::hashing::{domain_hash, domain_hasher, Domain};
@@ -16,23 +16,54 @@
 //! - **WebAuthn signature verification**: Verifies signatures from user devices
 //! - **P-256 cryptography**: Uses industry-standard elliptic curve cryptography
 //! - **Replay protection**: Prevents the same transaction from being executed twice
+//! - **Challenges**: Binds each passkey signature to one account, nonce, and message
+//! - **Ceremony types**: Keeps registrations and assertions from standing in for each other
+//! - **Display codes**: Short codes users can compare to spot blind-signing
+//! - **Domain-separated hashing**: One prefix per kind of hash, so no two can collide
+//! - **Authenticator data**: Flags, counter, attested credential data and extensions
+//! - **Verification profiles**: Opt-in RP ID, origin, UP/UV, counter and low-S checks
 //!
 //! # Example
 //!
 //! ```ignore
-//! use core_crypto::{verify_webauthn_signature, WebAuthnSignature};
+//! use core_crypto::{verify_webauthn_signature, CeremonyType, WebAuthnSignature};
 //!
 //! // Verify a WebAuthn signature
 //! let webauthn_sig = WebAuthnSignature::new(/* ... */);
-//! verify_webauthn_signature(&webauthn_sig, &public_key, &challenge)?;
+//! verify_webauthn_signature(&webauthn_sig, &public_key, &challenge, CeremonyType::Get)?;
 //! ```
 
+// On-chain code must not panic on attacker-controlled input
+#![cfg_attr(not(test), deny(clippy::indexing_slicing, clippy::unwrap_used, clippy::expect_used))]
+
+pub mod authenticator_data;
+pub mod ceremony;
+pub mod challenge;
+pub mod cose;
+pub mod digest;
 pub mod errors;
+pub mod hashing;
 pub mod p256_verify;
+pub mod profile;
 pub mod replay;
 pub mod webauthn;
 
-pub use errors::CryptoError;
-pub use p256_verify::verify_p256_signature;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+
+pub use errors::{CryptoError, FailureDetail, VerifyFailure};
+pub use ceremony::CeremonyType;
+pub use authenticator_data::{parse_authenticator_data, parse_authenticator_data_detailed, AttestedCredentialData, ParsedAuthenticatorData};
+pub use challenge::{compute_challenge, CHALLENGE_LEN};
+pub use digest::{display_code, verify_display_code, DISPLAY_CODE_LEN};
+pub use hashing::{domain_hash, domain_hasher, Domain};
+pub use p256_verify::{
+    compress_p256_public_key, decompress_p256_public_key, is_low_s, validate_p256_public_key, verify_p256_digest_detailed,
+    verify_p256_signature, verify_p256_signature_detailed, PasskeyPublicKey,
+};
+pub use profile::{RelyingParty, WebAuthnExpectations, WebAuthnVerificationProfile};
 pub use replay::ReplayProtection;
-pub use webauthn::{WebAuthnSignature, verify_webauthn_signature};
+pub use webauthn::{
+    SignatureFormatError, WebAuthnSignature, verify_webauthn_signature, verify_webauthn_signature_detailed,
+    verify_webauthn_signature_with_profile, verify_webauthn_signature_with_profile_detailed,
+};
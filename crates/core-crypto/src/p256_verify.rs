@@ -1,6 +1,8 @@
-use p256::ecdsa::{Signature, VerifyingKey};
+use borsh::{BorshDeserialize, BorshSerialize};
+use p256::ecdsa::{signature::DigestVerifier, Signature, VerifyingKey};
 use sha2::{Digest, Sha256};
-use crate::errors::CryptoError;
+use attesta_types::consts::{P256_PUBKEY_LEN, P256_SEC1_COMPRESSED_LEN, P256_SEC1_UNCOMPRESSED_LEN, P256_SIGNATURE_LEN};
+use crate::errors::{CryptoError, FailureDetail, VerifyFailure};
 
 /// Checks if a P-256 signature is valid
 ///
@@ -29,59 +31,156 @@ pub fn verify_p256_signature(
     signature: &[u8],
     public_key: &[u8],
 ) -> Result<(), CryptoError> {
+    verify_p256_signature_detailed(message, signature, public_key).map_err(CryptoError::from)
+}
+
+/// Like `verify_p256_signature`, saying which input was wrong and how
+pub fn verify_p256_signature_detailed(
+    message: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<(), VerifyFailure> {
+    verify_p256_digest_detailed(Sha256::new_with_prefix(message), signature, public_key)
+}
+
+/// Like `verify_p256_signature_detailed`, for a message already fed into `digest`
+///
+/// A message made of several parts can be hashed part by part instead of
+/// being copied into one buffer first.
+pub fn verify_p256_digest_detailed(
+    digest: Sha256,
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<(), VerifyFailure> {
     // Make sure we have the right length of public key
-    if public_key.len() != 64 {
-        return Err(CryptoError::InvalidP256PublicKey);
+    if public_key.len() != P256_PUBKEY_LEN {
+        return Err(VerifyFailure::new(
+            CryptoError::InvalidP256PublicKey,
+            "public_key",
+            FailureDetail::Length { expected: P256_PUBKEY_LEN, actual: public_key.len() },
+        ));
     }
 
-    // Hash the message using SHA-256
-    // We hash first because ECDSA signatures work with hashed messages
-    let message_hash = Sha256::digest(message);
-
-    // Convert the public key bytes into a format we can use for verification
-    let verifying_key = VerifyingKey::from_sec1_bytes(public_key)
-        .map_err(|_| CryptoError::InvalidP256PublicKey)?;
+    let malformed_signature = |detail| VerifyFailure::new(CryptoError::InvalidSignatureFormat, "signature", detail);
 
     // Handle different signature formats
     // Some signatures are 64 bytes (just r + s), others are 65 bytes (r + s + recovery id)
     let sig_bytes: &[u8] = match signature.len() {
-        64 => signature,
+        P256_SIGNATURE_LEN => signature,
         65 => {
             // If 65 bytes, use only the first 64 (skip the recovery id)
-            signature.get(..64)
-                .ok_or(CryptoError::InvalidSignatureFormat)?
+            signature.get(..P256_SIGNATURE_LEN)
+                .ok_or(malformed_signature(FailureDetail::None))?
         },
-        _ => return Err(CryptoError::InvalidSignatureFormat),
+        actual => return Err(malformed_signature(FailureDetail::Length { expected: P256_SIGNATURE_LEN, actual })),
     };
 
     // Convert the signature bytes into a Signature object
     // TryFrom is more explicit and safer than into()
+    // (r or s is zero or not below the curve order)
     let sig = Signature::try_from(sig_bytes)
-        .map_err(|_| CryptoError::InvalidSignatureFormat)?;
+        .map_err(|_| malformed_signature(FailureDetail::None))?;
+
+    // Convert the public key bytes into a format we can use for verification
+    let verifying_key = parse_public_key(public_key)
+        .map_err(|error| VerifyFailure::new(error, "public_key", FailureDetail::None))?;
 
     // Actually verify the signature matches the message and public key
+    // ECDSA signs the SHA-256 of the message, which `digest` holds
     verifying_key
-        .verify(&message_hash, &sig)
-        .map_err(|_| CryptoError::SignatureVerificationFailed)?;
+        .verify_digest(digest, &sig)
+        .map_err(|_| VerifyFailure::new(CryptoError::SignatureVerificationFailed, "signature", FailureDetail::None))?;
 
     Ok(())
 }
 
+/// Checks that a 64-byte public key is a point on the P-256 curve
+///
+/// Stored keys are checked when they're written, so a bad key is caught
+/// when it's registered rather than the first time it signs something.
+///
+/// # Returns
+/// - `Ok(())` if the key can be used for verification
+/// - `Err(CryptoError::InvalidP256PublicKey)` if it's the wrong length or not on the curve
+pub fn validate_p256_public_key(public_key: &[u8]) -> Result<(), CryptoError> {
+    parse_public_key(public_key).map(|_| ())
+}
+
+/// Parses an uncompressed 64-byte key (x || y, no SEC1 prefix)
+fn parse_public_key(public_key: &[u8]) -> Result<VerifyingKey, CryptoError> {
+    if public_key.len() != P256_PUBKEY_LEN {
+        return Err(CryptoError::InvalidP256PublicKey);
+    }
+
+    // SEC1 uncompressed keys are the x and y coordinates behind a 0x04 prefix
+    let mut sec1_key = [0u8; P256_SEC1_UNCOMPRESSED_LEN];
+    sec1_key[0] = 0x04;
+    sec1_key[1..].copy_from_slice(public_key);
+    VerifyingKey::from_sec1_bytes(&sec1_key).map_err(|_| CryptoError::InvalidP256PublicKey)
+}
+
+/// Converts a signature into the raw 64-byte format we verify on-chain
+///
+/// Authenticators return ECDSA signatures DER-encoded (usually 70-72 bytes),
+/// but on-chain verification expects the fixed-size `r || s` form.
+///
+/// # Parameters
+/// - `signature`: A DER-encoded signature, or one that's already 64 bytes
+///
+/// # Returns
+/// - `Ok([u8; 64])` with the raw `r || s` signature
+/// - `Err(CryptoError::InvalidSignatureFormat)` if it can't be decoded
+pub fn signature_to_raw(signature: &[u8]) -> Result<[u8; P256_SIGNATURE_LEN], CryptoError> {
+    let sig = if signature.len() == P256_SIGNATURE_LEN {
+        Signature::try_from(signature)
+    } else {
+        Signature::from_der(signature)
+    }
+    .map_err(|_| CryptoError::InvalidSignatureFormat)?;
+
+    let mut raw = [0u8; P256_SIGNATURE_LEN];
+    raw.copy_from_slice(&sig.to_bytes());
+    Ok(raw)
+}
+
+/// Whether a raw signature's `s` is in the lower half of the curve order
+///
+/// ECDSA accepts `(r, s)` and `(r, n - s)` alike, so anyone can turn one
+/// valid signature into a second; requiring low S leaves only one. Takes the
+/// same 64- or 65-byte forms as `verify_p256_signature`, and is `false` for
+/// anything that isn't a signature.
+pub fn is_low_s(signature: &[u8]) -> bool {
+    signature
+        .get(..P256_SIGNATURE_LEN)
+        .filter(|_| matches!(signature.len(), P256_SIGNATURE_LEN | 65))
+        .and_then(|raw| Signature::try_from(raw).ok())
+        .is_some_and(|sig| sig.normalize_s().is_none())
+}
+
+/// SEC1 prefix of a compressed key whose y coordinate is even
+const SEC1_EVEN_Y: u8 = 0x02;
+
+/// SEC1 prefix of a compressed key whose y coordinate is odd
+const SEC1_ODD_Y: u8 = 0x03;
+
 /// Converts a compressed public key to uncompressed format
 ///
 /// Compressed keys are 33 bytes (just x coordinate + a sign bit), while
 /// uncompressed keys are 65 bytes (0x04 prefix + x + y). This function
 /// expands the compressed key to get the full 64 bytes (x + y, no prefix).
+/// The result is checked to parse as a key again and to compress back to
+/// `compressed`, so what comes out can always be verified with.
 ///
 /// # Parameters
 /// - `compressed`: A compressed P-256 public key (must be exactly 33 bytes)
 ///
 /// # Returns
 /// - `Ok([u8; 64])` with the uncompressed key (x and y coordinates)
-/// - `Err(CryptoError)` if the input is invalid
-pub fn decompress_p256_public_key(compressed: &[u8]) -> Result<[u8; 64], CryptoError> {
-    // Compressed keys must be exactly 33 bytes
-    if compressed.len() != 33 {
+/// - `Err(CryptoError::InvalidP256PublicKey)` if the length or sign byte is
+///   wrong, or x isn't the x coordinate of a point on the curve
+pub fn decompress_p256_public_key(compressed: &[u8]) -> Result<[u8; P256_PUBKEY_LEN], CryptoError> {
+    // Compressed keys must be exactly 33 bytes, behind a 0x02 or 0x03
+    if compressed.len() != P256_SEC1_COMPRESSED_LEN || !matches!(compressed.first(), Some(&(SEC1_EVEN_Y | SEC1_ODD_Y))) {
         return Err(CryptoError::InvalidP256PublicKey);
     }
 
@@ -92,25 +191,135 @@ pub fn decompress_p256_public_key(compressed: &[u8]) -> Result<[u8; 64], CryptoE
     // Convert to uncompressed format (65 bytes: 0x04 prefix + 32 bytes x + 32 bytes y)
     let point = verifying_key.to_encoded_point(false);
     let coords = point.as_bytes();
-    
-    // Make sure we have enough bytes
-    if coords.len() < 65 {
-        return Err(CryptoError::InvalidP256PublicKey);
-    }
 
     // Extract just the x and y coordinates (skip the 0x04 prefix)
-    let mut uncompressed = [0u8; 64];
+    let mut uncompressed = [0u8; P256_PUBKEY_LEN];
     uncompressed.copy_from_slice(
-        coords.get(1..65)
+        coords.get(1..P256_SEC1_UNCOMPRESSED_LEN)
             .ok_or(CryptoError::InvalidP256PublicKey)?
     );
 
+    // What we hand out must be usable as is, and be the same key
+    if compress_p256_public_key(&uncompressed)? != compressed {
+        return Err(CryptoError::InvalidP256PublicKey);
+    }
+
     Ok(uncompressed)
 }
 
+/// Converts an uncompressed public key to the 33-byte compressed format
+///
+/// The reverse of `decompress_p256_public_key`: the x coordinate behind
+/// 0x02 or 0x03 for an even or odd y. Half the size, for storage; verify
+/// with the uncompressed form.
+///
+/// # Returns
+/// - `Ok([u8; 33])` with the compressed key
+/// - `Err(CryptoError::InvalidP256PublicKey)` if it's not a point on the curve
+pub fn compress_p256_public_key(uncompressed: &[u8; P256_PUBKEY_LEN]) -> Result<[u8; P256_SEC1_COMPRESSED_LEN], CryptoError> {
+    let point = parse_public_key(uncompressed)?.to_encoded_point(true);
+
+    let mut compressed = [0u8; P256_SEC1_COMPRESSED_LEN];
+    compressed.copy_from_slice(point.as_bytes());
+    Ok(compressed)
+}
+
+/// A passkey's P-256 public key, checked to be on the curve
+///
+/// Stored compressed (Borsh writes the 33-byte form, and reading it back
+/// decompresses and checks it again), and always verified with the
+/// uncompressed form. Converts from either form, and into the 64-byte form
+/// the rest of the crate takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasskeyPublicKey {
+    uncompressed: [u8; P256_PUBKEY_LEN],
+}
+
+impl PasskeyPublicKey {
+    /// From the 64-byte `x || y` form
+    pub fn from_uncompressed(uncompressed: &[u8; P256_PUBKEY_LEN]) -> Result<Self, CryptoError> {
+        validate_p256_public_key(uncompressed)?;
+        Ok(Self { uncompressed: *uncompressed })
+    }
+
+    /// From the 33-byte SEC1 compressed form
+    pub fn from_compressed(compressed: &[u8]) -> Result<Self, CryptoError> {
+        Ok(Self { uncompressed: decompress_p256_public_key(compressed)? })
+    }
+
+    /// The 64-byte `x || y` form, to verify with
+    pub fn uncompressed(&self) -> &[u8; P256_PUBKEY_LEN] {
+        &self.uncompressed
+    }
+
+    /// The 33-byte compressed form, to store
+    pub fn compressed(&self) -> [u8; P256_SEC1_COMPRESSED_LEN] {
+        // The key was checked when it was made
+        compress_p256_public_key(&self.uncompressed).unwrap_or([0u8; P256_SEC1_COMPRESSED_LEN])
+    }
+
+    /// Checks `signature` over `message` with this key
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), CryptoError> {
+        verify_p256_signature(message, signature, &self.uncompressed)
+    }
+}
+
+impl TryFrom<&[u8]> for PasskeyPublicKey {
+    type Error = CryptoError;
+
+    /// Takes either form, told apart by length
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        match bytes.len() {
+            P256_SEC1_COMPRESSED_LEN => Self::from_compressed(bytes),
+            P256_PUBKEY_LEN => {
+                let mut uncompressed = [0u8; P256_PUBKEY_LEN];
+                uncompressed.copy_from_slice(bytes);
+                Self::from_uncompressed(&uncompressed)
+            }
+            _ => Err(CryptoError::InvalidP256PublicKey),
+        }
+    }
+}
+
+impl TryFrom<[u8; P256_PUBKEY_LEN]> for PasskeyPublicKey {
+    type Error = CryptoError;
+
+    fn try_from(uncompressed: [u8; P256_PUBKEY_LEN]) -> Result<Self, Self::Error> {
+        Self::from_uncompressed(&uncompressed)
+    }
+}
+
+impl From<PasskeyPublicKey> for [u8; P256_PUBKEY_LEN] {
+    fn from(key: PasskeyPublicKey) -> Self {
+        key.uncompressed
+    }
+}
+
+impl From<PasskeyPublicKey> for [u8; P256_SEC1_COMPRESSED_LEN] {
+    fn from(key: PasskeyPublicKey) -> Self {
+        key.compressed()
+    }
+}
+
+impl BorshSerialize for PasskeyPublicKey {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.compressed())
+    }
+}
+
+impl BorshDeserialize for PasskeyPublicKey {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut compressed = [0u8; P256_SEC1_COMPRESSED_LEN];
+        reader.read_exact(&mut compressed)?;
+        Self::from_compressed(&compressed)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_verify_p256_signature_invalid_key_length() {
@@ -134,6 +343,77 @@ mod tests {
         assert_eq!(result.unwrap_err(), CryptoError::InvalidSignatureFormat);
     }
 
+    #[test]
+    fn test_verify_p256_signature_valid() {
+        use p256::ecdsa::{signature::Signer, SigningKey};
+
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let public_key = &point.as_bytes()[1..];
+
+        let message = b"test message";
+        let signature: Signature = signing_key.sign(message);
+        let signature = signature.to_bytes();
+
+        assert!(verify_p256_signature(message, &signature, public_key).is_ok());
+        assert_eq!(
+            verify_p256_signature(b"other message", &signature, public_key),
+            Err(CryptoError::SignatureVerificationFailed)
+        );
+    }
+
+    #[test]
+    fn test_signature_to_raw_from_der() {
+        use p256::ecdsa::{signature::Signer, SigningKey};
+
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let signature: Signature = signing_key.sign(b"test message");
+        let der = signature.to_der();
+
+        assert_eq!(signature_to_raw(der.as_bytes()).unwrap()[..], signature.to_bytes()[..]);
+        assert_eq!(signature_to_raw(&signature.to_bytes()).unwrap()[..], signature.to_bytes()[..]);
+        assert_eq!(signature_to_raw(&[1u8; 10]), Err(CryptoError::InvalidSignatureFormat));
+    }
+
+    #[test]
+    fn test_is_low_s() {
+        use p256::ecdsa::{signature::Signer, SigningKey};
+
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let signature: Signature = signing_key.sign(b"test message");
+        let low = signature.normalize_s().unwrap_or(signature);
+        let (r, s) = low.split_scalars();
+        let high = Signature::from_scalars(r, -s).unwrap();
+
+        // Both verify; only one is low-S
+        for signature in [low, high] {
+            assert!(verify_p256_signature(b"test message", &signature.to_bytes(), &point.as_bytes()[1..]).is_ok());
+        }
+        assert!(is_low_s(&low.to_bytes()));
+        assert!(!is_low_s(&high.to_bytes()));
+
+        let mut with_recovery_id = low.to_bytes().to_vec();
+        with_recovery_id.push(0);
+        assert!(is_low_s(&with_recovery_id));
+        assert!(!is_low_s(&low.to_bytes()[..63]));
+        assert!(!is_low_s(&[0u8; 64]));
+    }
+
+    #[test]
+    fn test_validate_p256_public_key() {
+        use p256::ecdsa::SigningKey;
+
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        assert!(validate_p256_public_key(&point.as_bytes()[1..]).is_ok());
+
+        // Right length, but not a point on the curve
+        assert_eq!(validate_p256_public_key(&[1u8; 64]), Err(CryptoError::InvalidP256PublicKey));
+        assert_eq!(validate_p256_public_key(&[0u8; 64]), Err(CryptoError::InvalidP256PublicKey));
+        assert_eq!(validate_p256_public_key(point.as_bytes()), Err(CryptoError::InvalidP256PublicKey));
+    }
+
     #[test]
     fn test_decompress_p256_public_key_invalid_length() {
         let compressed = &[0u8; 32]; // Wrong length
@@ -141,4 +421,110 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), CryptoError::InvalidP256PublicKey);
     }
+
+
+    /// A key from a signing key's scalar, or `None` if it isn't one
+    fn key_from_scalar(scalar: [u8; 32]) -> Option<[u8; 64]> {
+        let signing_key = p256::ecdsa::SigningKey::from_slice(&scalar).ok()?;
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let mut key = [0u8; 64];
+        key.copy_from_slice(&point.as_bytes()[1..]);
+        Some(key)
+    }
+
+    #[test]
+    fn test_compress_p256_public_key() {
+        let key = key_from_scalar([7u8; 32]).unwrap();
+        let compressed = compress_p256_public_key(&key).unwrap();
+        assert!(matches!(compressed[0], 0x02 | 0x03));
+        assert_eq!(compressed[1..], key[..32]);
+        assert_eq!(decompress_p256_public_key(&compressed), Ok(key));
+
+        assert_eq!(compress_p256_public_key(&[1u8; 64]), Err(CryptoError::InvalidP256PublicKey));
+        assert_eq!(compress_p256_public_key(&[0u8; 64]), Err(CryptoError::InvalidP256PublicKey));
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_keys() {
+        let key = key_from_scalar([7u8; 32]).unwrap();
+        let compressed = compress_p256_public_key(&key).unwrap();
+
+        // The other sign is the negated point: a different, valid key
+        let mut flipped = compressed;
+        flipped[0] ^= 1;
+        let negated = decompress_p256_public_key(&flipped).unwrap();
+        assert_eq!(negated[..32], key[..32]);
+        assert_ne!(negated, key);
+
+        // x at or above the field prime
+        let mut too_big = [0xffu8; 33];
+        too_big[0] = 0x02;
+        assert_eq!(decompress_p256_public_key(&too_big), Err(CryptoError::InvalidP256PublicKey));
+
+        // Some x has no y on the curve
+        let off_curve = (0u8..=255)
+            .map(|x| {
+                let mut candidate = [0u8; 33];
+                candidate[0] = 0x02;
+                candidate[32] = x;
+                candidate
+            })
+            .find(|candidate| VerifyingKey::from_sec1_bytes(candidate).is_err())
+            .unwrap();
+        assert_eq!(decompress_p256_public_key(&off_curve), Err(CryptoError::InvalidP256PublicKey));
+    }
+
+    #[test]
+    fn test_passkey_public_key_conversions() {
+        use p256::ecdsa::{signature::Signer, SigningKey};
+
+        let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let key = key_from_scalar([7u8; 32]).unwrap();
+        let passkey_key = PasskeyPublicKey::try_from(key).unwrap();
+        let compressed = passkey_key.compressed();
+
+        assert_eq!(PasskeyPublicKey::try_from(&compressed[..]), Ok(passkey_key));
+        assert_eq!(PasskeyPublicKey::try_from(&key[..]), Ok(passkey_key));
+        assert_eq!(<[u8; 64]>::from(passkey_key), key);
+        assert_eq!(<[u8; 33]>::from(passkey_key), compressed);
+        assert_eq!(PasskeyPublicKey::try_from(&key[..32]), Err(CryptoError::InvalidP256PublicKey));
+        assert_eq!(PasskeyPublicKey::try_from([1u8; 64]), Err(CryptoError::InvalidP256PublicKey));
+
+        // Stored compressed
+        let stored = borsh::to_vec(&passkey_key).unwrap();
+        assert_eq!(stored, compressed);
+        assert_eq!(borsh::from_slice::<PasskeyPublicKey>(&stored).unwrap(), passkey_key);
+        assert!(borsh::from_slice::<PasskeyPublicKey>(&[0u8; 33]).is_err());
+
+        let signature: Signature = signing_key.sign(b"test message");
+        assert_eq!(passkey_key.verify(b"test message", &signature.to_bytes()), Ok(()));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_compression_round_trips(scalar in any::<[u8; 32]>()) {
+            let Some(key) = key_from_scalar(scalar) else { return Ok(()) };
+            let compressed = compress_p256_public_key(&key).unwrap();
+            prop_assert_eq!(decompress_p256_public_key(&compressed), Ok(key));
+            prop_assert_eq!(compress_p256_public_key(&decompress_p256_public_key(&compressed).unwrap()), Ok(compressed));
+        }
+
+        #[test]
+        fn prop_any_x_decompresses_to_itself_or_fails(sign in 0x02u8..=0x03, x in any::<[u8; 32]>()) {
+            let compressed = [&[sign][..], &x].concat();
+            if let Ok(key) = decompress_p256_public_key(&compressed) {
+                prop_assert_eq!(&compress_p256_public_key(&key).unwrap()[..], &compressed[..]);
+                prop_assert!(validate_p256_public_key(&key).is_ok());
+            }
+        }
+
+        #[test]
+        fn prop_invalid_sign_bytes_are_rejected(sign in any::<u8>(), scalar in any::<[u8; 32]>()) {
+            prop_assume!(!matches!(sign, 0x02 | 0x03));
+            let Some(key) = key_from_scalar(scalar) else { return Ok(()) };
+            let mut compressed = compress_p256_public_key(&key).unwrap();
+            compressed[0] = sign;
+            prop_assert_eq!(decompress_p256_public_key(&compressed), Err(CryptoError::InvalidP256PublicKey));
+        }
+    }
 }
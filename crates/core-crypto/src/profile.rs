@@ -0,0 +1,235 @@
+//! Which optional WebAuthn checks an assertion is held to
+//!
+//! `verify_webauthn_signature` always checks the authenticator data's shape,
+//! the challenge and the signature. A `WebAuthnVerificationProfile` turns on
+//! the rest: the relying party ID and origin the passkey signed for, the
+//! user presence and verification flags, the signature counter, and low-S
+//! signatures. Each is a bit of the profile's one-byte encoding, so accounts
+//! can store it compactly and turn checks on one at a time while migrating.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use attesta_types::consts::HASH_LEN;
+use crate::authenticator_data::ParsedAuthenticatorData;
+use crate::challenge::client_data_field;
+use crate::errors::{CryptoError, FailureDetail, VerifyFailure};
+
+/// The optional checks to run on a WebAuthn assertion
+///
+/// The default is `legacy()`: none of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WebAuthnVerificationProfile {
+    /// The authenticator data's RP ID hash must be the relying party's
+    pub check_rp_id: bool,
+
+    /// `clientDataJSON`'s origin must be the relying party's
+    pub check_origin: bool,
+
+    /// The UP flag must be set (the user touched the authenticator)
+    pub require_user_present: bool,
+
+    /// The UV flag must be set (biometric or PIN)
+    pub require_user_verified: bool,
+
+    /// The signature counter must have moved past the last one seen
+    ///
+    /// Authenticators that don't keep a counter always report 0; that
+    /// passes as long as no nonzero count was seen before.
+    pub check_sign_count: bool,
+
+    /// `s` must be in the lower half of the curve order
+    pub require_low_s: bool,
+}
+
+impl WebAuthnVerificationProfile {
+    /// Bit for `check_rp_id`
+    pub const RP_ID: u8 = 1 << 0;
+
+    /// Bit for `check_origin`
+    pub const ORIGIN: u8 = 1 << 1;
+
+    /// Bit for `require_user_present`
+    pub const USER_PRESENT: u8 = 1 << 2;
+
+    /// Bit for `require_user_verified`
+    pub const USER_VERIFIED: u8 = 1 << 3;
+
+    /// Bit for `check_sign_count`
+    pub const SIGN_COUNT: u8 = 1 << 4;
+
+    /// Bit for `require_low_s`
+    pub const LOW_S: u8 = 1 << 5;
+
+    /// Every check's bit
+    pub const ALL: u8 = Self::RP_ID | Self::ORIGIN | Self::USER_PRESENT | Self::USER_VERIFIED | Self::SIGN_COUNT | Self::LOW_S;
+
+    /// Every check on
+    pub fn strict() -> Self {
+        Self::from_bits_truncate(Self::ALL)
+    }
+
+    /// The relying party ID, the origin and user presence
+    pub fn standard() -> Self {
+        Self::from_bits_truncate(Self::RP_ID | Self::ORIGIN | Self::USER_PRESENT)
+    }
+
+    /// No optional checks, as before profiles existed
+    pub fn legacy() -> Self {
+        Self::default()
+    }
+
+    /// The profile as one byte, a bit per check
+    pub fn to_bits(&self) -> u8 {
+        [
+            (self.check_rp_id, Self::RP_ID),
+            (self.check_origin, Self::ORIGIN),
+            (self.require_user_present, Self::USER_PRESENT),
+            (self.require_user_verified, Self::USER_VERIFIED),
+            (self.check_sign_count, Self::SIGN_COUNT),
+            (self.require_low_s, Self::LOW_S),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
+
+    /// Reads a profile written by `to_bits`
+    ///
+    /// # Returns
+    /// `None` if a bit is set that no check uses (written by a newer
+    /// version, or corrupt); dropping it could turn a check off
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        (bits & !Self::ALL == 0).then(|| Self::from_bits_truncate(bits))
+    }
+
+    fn from_bits_truncate(bits: u8) -> Self {
+        Self {
+            check_rp_id: bits & Self::RP_ID != 0,
+            check_origin: bits & Self::ORIGIN != 0,
+            require_user_present: bits & Self::USER_PRESENT != 0,
+            require_user_verified: bits & Self::USER_VERIFIED != 0,
+            check_sign_count: bits & Self::SIGN_COUNT != 0,
+            require_low_s: bits & Self::LOW_S != 0,
+        }
+    }
+
+    /// Whether this profile needs to know the relying party
+    pub fn needs_relying_party(&self) -> bool {
+        self.check_rp_id || self.check_origin
+    }
+}
+
+/// The relying party assertions must be made for
+///
+/// Both are SHA-256 hashes, so they take the same space whatever the
+/// domain, and the origin is compared without storing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct RelyingParty {
+    /// SHA-256 of the RP ID (what the authenticator data carries)
+    pub rp_id_hash: [u8; HASH_LEN],
+
+    /// SHA-256 of the origin, e.g. `https://wallet.example`
+    pub origin_hash: [u8; HASH_LEN],
+}
+
+impl RelyingParty {
+    pub fn new(rp_id: &str, origin: &str) -> Self {
+        Self {
+            rp_id_hash: Sha256::digest(rp_id.as_bytes()).into(),
+            origin_hash: Sha256::digest(origin.as_bytes()).into(),
+        }
+    }
+}
+
+/// What an assertion is compared against beyond its challenge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WebAuthnExpectations {
+    /// Needed by the RP ID and origin checks; they fail without it
+    pub relying_party: Option<RelyingParty>,
+
+    /// The highest signature counter seen from this passkey so far
+    pub previous_sign_count: u32,
+}
+
+/// Runs the profile's checks on the parsed authenticator data
+pub(crate) fn check_authenticator_data(
+    profile: &WebAuthnVerificationProfile,
+    expectations: &WebAuthnExpectations,
+    authenticator_data: &ParsedAuthenticatorData,
+) -> Result<(), VerifyFailure> {
+    if profile.check_rp_id
+        && expectations.relying_party.map(|party| party.rp_id_hash) != Some(authenticator_data.rp_id_hash)
+    {
+        return Err(VerifyFailure::new(CryptoError::RpIdMismatch, "authenticator_data", FailureDetail::None));
+    }
+    if profile.require_user_present && !authenticator_data.user_present() {
+        return Err(VerifyFailure::new(CryptoError::UserNotPresent, "authenticator_data", FailureDetail::None));
+    }
+    if profile.require_user_verified && !authenticator_data.user_verified() {
+        return Err(VerifyFailure::new(CryptoError::UserNotVerified, "authenticator_data", FailureDetail::None));
+    }
+    if profile.check_sign_count && !sign_count_advanced(expectations.previous_sign_count, authenticator_data.sign_count) {
+        return Err(VerifyFailure::new(CryptoError::SignCountNotIncreased, "authenticator_data", FailureDetail::None));
+    }
+    Ok(())
+}
+
+/// Runs the profile's origin check on `clientDataJSON`
+pub(crate) fn check_client_data(
+    profile: &WebAuthnVerificationProfile,
+    expectations: &WebAuthnExpectations,
+    client_data_json: &[u8],
+) -> Result<(), VerifyFailure> {
+    if !profile.check_origin {
+        return Ok(());
+    }
+    let origin_hash: Option<[u8; HASH_LEN]> = client_data_field(client_data_json, "origin")
+        .map(|origin| Sha256::digest(origin.as_bytes()).into());
+    match (expectations.relying_party, origin_hash) {
+        (Some(party), Some(origin_hash)) if party.origin_hash == origin_hash => Ok(()),
+        _ => Err(VerifyFailure::new(CryptoError::OriginMismatch, "client_data_json", FailureDetail::None)),
+    }
+}
+
+/// The WebAuthn counter rule: a counter that's in use must go up
+///
+/// Both 0 means the authenticator doesn't keep one. A counter that didn't go
+/// up may mean the credential was cloned.
+pub fn sign_count_advanced(previous: u32, current: u32) -> bool {
+    (previous == 0 && current == 0) || current > previous
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(WebAuthnVerificationProfile::legacy().to_bits(), 0);
+        assert_eq!(WebAuthnVerificationProfile::legacy(), WebAuthnVerificationProfile::default());
+        assert_eq!(WebAuthnVerificationProfile::strict().to_bits(), WebAuthnVerificationProfile::ALL);
+
+        let standard = WebAuthnVerificationProfile::standard();
+        assert!(standard.check_rp_id && standard.check_origin && standard.require_user_present);
+        assert!(!standard.require_user_verified && !standard.check_sign_count && !standard.require_low_s);
+    }
+
+    #[test]
+    fn test_bits_round_trip() {
+        for bits in 0..=WebAuthnVerificationProfile::ALL {
+            assert_eq!(WebAuthnVerificationProfile::from_bits(bits).unwrap().to_bits(), bits);
+        }
+        assert_eq!(WebAuthnVerificationProfile::from_bits(1 << 6), None);
+        assert_eq!(WebAuthnVerificationProfile::from_bits(0xff), None);
+    }
+
+    #[test]
+    fn test_sign_count_rule() {
+        assert!(sign_count_advanced(0, 0));
+        assert!(sign_count_advanced(0, 1));
+        assert!(sign_count_advanced(5, 6));
+        assert!(!sign_count_advanced(5, 5));
+        assert!(!sign_count_advanced(5, 4));
+        assert!(!sign_count_advanced(5, 0));
+    }
+}
@@ -1,5 +1,6 @@
-use sha2::{Digest, Sha256};
+use sha2::Digest;
 use crate::errors::CryptoError;
+use crate::hashing::{domain_hasher, Domain};
 
 /// Tools for preventing replay attacks
 ///
@@ -23,9 +24,9 @@ impl ReplayProtection {
     /// # Returns
     /// A 32-byte nonce that's unique for this combination of inputs
     pub fn generate_nonce(message: &[u8], timestamp: i64, user_pubkey: &[u8]) -> [u8; 32] {
-        let mut hasher = Sha256::new();
+        let mut hasher = domain_hasher(Domain::ReplayNonce);
         hasher.update(message);
-        hasher.update(&timestamp.to_le_bytes());
+        hasher.update(timestamp.to_le_bytes());
         hasher.update(user_pubkey);
         hasher.finalize().into()
     }
@@ -0,0 +1,229 @@
+//! Deterministic software passkeys for tests
+//!
+//! Real passkeys live in secure hardware, which makes end-to-end tests hard
+//! to write. `TestPasskey` produces the same kind of assertion a browser
+//! would (authenticator data, client data JSON, ECDSA signature), from a
+//! fixed seed so test results are reproducible.
+//!
+//! Only available in tests, or with the `test-utils` feature.
+
+// Test fixtures panic on bad setup rather than threading errors through tests
+#![allow(clippy::indexing_slicing, clippy::unwrap_used, clippy::expect_used)]
+
+use attesta_types::consts::{AAGUID_LEN, P256_PUBKEY_LEN};
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use sha2::{Digest, Sha256};
+use crate::challenge::base64url_encode;
+use crate::webauthn::WebAuthnSignature;
+
+/// A software passkey with a deterministic key pair
+pub struct TestPasskey {
+    signing_key: SigningKey,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl TestPasskey {
+    /// Relying party ID the test passkey signs for
+    pub const RP_ID: &'static str = "localhost";
+
+    /// Origin written into the client data JSON
+    pub const ORIGIN: &'static str = "https://localhost";
+
+    /// Creates a test passkey from a seed
+    ///
+    /// The same seed always gives the same key pair and credential ID.
+    /// Different seeds give different passkeys.
+    pub fn new(seed: u8) -> Self {
+        let mut secret: [u8; 32] = Sha256::digest([b"attesta-test-passkey".as_slice(), &[seed]].concat()).into();
+        // Keep the scalar well below the curve order so it's always valid
+        secret[0] &= 0x7f;
+        secret[31] |= 1;
+
+        let signing_key = SigningKey::from_slice(&secret).expect("valid P-256 scalar");
+
+        Self {
+            signing_key,
+            credential_id: format!("test-passkey-{}", seed).into_bytes(),
+            sign_count: 0,
+        }
+    }
+
+    /// The passkey's public key in the 64-byte format stored on accounts
+    pub fn public_key(&self) -> [u8; P256_PUBKEY_LEN] {
+        let point = self.signing_key.verifying_key().to_encoded_point(false);
+        let mut public_key = [0u8; P256_PUBKEY_LEN];
+        public_key.copy_from_slice(&point.as_bytes()[1..]);
+        public_key
+    }
+
+    /// The passkey's credential ID
+    pub fn credential_id(&self) -> Vec<u8> {
+        self.credential_id.clone()
+    }
+
+    /// Signs a challenge the way `navigator.credentials.get()` would
+    ///
+    /// Returns a raw 64-byte signature; use `sign_der` for the DER encoding
+    /// real authenticators return.
+    pub fn sign(&mut self, challenge: &[u8]) -> WebAuthnSignature {
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.get","challenge":"{}","origin":"{}","crossOrigin":false}}"#,
+            base64url_encode(challenge),
+            Self::ORIGIN,
+        );
+        self.sign_client_data(client_data_json.into_bytes())
+    }
+
+    /// Signs a challenge and returns the signature DER-encoded
+    pub fn sign_der(&mut self, challenge: &[u8]) -> WebAuthnSignature {
+        let mut webauthn_sig = self.sign(challenge);
+        let signature = Signature::try_from(webauthn_sig.signature.as_slice())
+            .expect("raw signature from sign()");
+        webauthn_sig.signature = signature.to_der().as_bytes().to_vec();
+        webauthn_sig
+    }
+
+    /// Signs a challenge as a `webauthn.create` ceremony, without attested
+    /// credential data
+    ///
+    /// For enrolling a passkey in tests that don't care about its model.
+    pub fn sign_create(&mut self, challenge: &[u8]) -> WebAuthnSignature {
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.create","challenge":"{}","origin":"{}","crossOrigin":false}}"#,
+            base64url_encode(challenge),
+            Self::ORIGIN,
+        );
+        self.sign_client_data(client_data_json.into_bytes())
+    }
+
+    /// Signs a challenge the way `navigator.credentials.create()` would with
+    /// self attestation
+    ///
+    /// The authenticator data carries attested credential data: `aaguid`,
+    /// then this passkey's credential ID and public key.
+    pub fn sign_registration(&mut self, challenge: &[u8], aaguid: [u8; AAGUID_LEN]) -> WebAuthnSignature {
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.create","challenge":"{}","origin":"{}","crossOrigin":false}}"#,
+            base64url_encode(challenge),
+            Self::ORIGIN,
+        );
+
+        let public_key = self.public_key();
+        let mut attested_credential = aaguid.to_vec();
+        attested_credential.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        attested_credential.extend_from_slice(&self.credential_id);
+        attested_credential.extend_from_slice(&[
+            0xa5, // map(5)
+            0x01, 0x02, // kty: EC2
+            0x03, 0x26, // alg: ES256
+            0x20, 0x01, // crv: P-256
+            0x21, 0x58, 0x20, // x: bytes(32)
+        ]);
+        attested_credential.extend_from_slice(&public_key[..32]);
+        attested_credential.extend_from_slice(&[0x22, 0x58, 0x20]); // y: bytes(32)
+        attested_credential.extend_from_slice(&public_key[32..]);
+
+        // user present + user verified + attested credential data
+        self.sign_with(Self::RP_ID, 0x45, &attested_credential, client_data_json.into_bytes())
+    }
+
+    /// Signs arbitrary client data JSON
+    ///
+    /// Useful for producing assertions with a wrong type, origin, or
+    /// challenge in negative tests.
+    pub fn sign_client_data(&mut self, client_data_json: Vec<u8>) -> WebAuthnSignature {
+        self.sign_with(Self::RP_ID, 0x05, &[], client_data_json) // user present + user verified
+    }
+
+    /// Signs a challenge for another relying party, with the given flags
+    ///
+    /// For testing the checks a `WebAuthnVerificationProfile` turns on.
+    pub fn sign_as(&mut self, challenge: &[u8], rp_id: &str, origin: &str, flags: u8) -> WebAuthnSignature {
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.get","challenge":"{}","origin":"{}","crossOrigin":false}}"#,
+            base64url_encode(challenge),
+            origin,
+        );
+        self.sign_with(rp_id, flags, &[], client_data_json.into_bytes())
+    }
+
+    /// Signs a challenge, then swaps `s` for `n - s`
+    ///
+    /// The result still verifies, but isn't low-S.
+    pub fn sign_high_s(&mut self, challenge: &[u8]) -> WebAuthnSignature {
+        let mut webauthn_sig = self.sign(challenge);
+        let signature = Signature::try_from(webauthn_sig.signature.as_slice()).expect("raw signature from sign()");
+        let (r, s) = signature.split_scalars();
+        webauthn_sig.signature = Signature::from_scalars(r, -s).expect("valid scalars").to_bytes().to_vec();
+        webauthn_sig
+    }
+
+    /// Sets the signature counter the next assertion counts up from
+    pub fn set_sign_count(&mut self, sign_count: u32) {
+        self.sign_count = sign_count;
+    }
+
+    /// The signature is always low-S (see `sign_high_s` for the other form)
+    fn sign_with(&mut self, rp_id: &str, flags: u8, attested_credential: &[u8], client_data_json: Vec<u8>) -> WebAuthnSignature {
+        self.sign_count += 1;
+
+        // RP ID hash (32) + flags (1) + signature counter (4), then any attested credential data
+        let mut authenticator_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        authenticator_data.push(flags);
+        authenticator_data.extend_from_slice(&self.sign_count.to_be_bytes());
+        authenticator_data.extend_from_slice(attested_credential);
+
+        let client_data_hash = Sha256::digest(&client_data_json);
+        let mut message = authenticator_data.clone();
+        message.extend_from_slice(&client_data_hash);
+
+        let signature: Signature = self.signing_key.sign(&message);
+        let signature = signature.normalize_s().unwrap_or(signature);
+
+        WebAuthnSignature::new(
+            authenticator_data,
+            client_data_json,
+            signature.to_bytes().to_vec(),
+            self.credential_id.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ceremony::CeremonyType;
+    use crate::webauthn::verify_webauthn_signature;
+
+    #[test]
+    fn test_passkey_is_deterministic() {
+        assert_eq!(TestPasskey::new(1).public_key(), TestPasskey::new(1).public_key());
+        assert_ne!(TestPasskey::new(1).public_key(), TestPasskey::new(2).public_key());
+    }
+
+    #[test]
+    fn test_signature_verifies() {
+        let mut passkey = TestPasskey::new(1);
+        let challenge = [3u8; 32];
+        let webauthn_sig = passkey.sign(&challenge);
+
+        assert!(verify_webauthn_signature(&webauthn_sig, &passkey.public_key(), &challenge, CeremonyType::Get).is_ok());
+        assert!(verify_webauthn_signature(&webauthn_sig, &passkey.public_key(), &[4u8; 32], CeremonyType::Get).is_err());
+        assert!(verify_webauthn_signature(&webauthn_sig, &TestPasskey::new(2).public_key(), &challenge, CeremonyType::Get).is_err());
+    }
+
+    #[test]
+    fn test_registration_carries_attested_credential() {
+        let mut passkey = TestPasskey::new(1);
+        let challenge = [3u8; 32];
+        let webauthn_sig = passkey.sign_registration(&challenge, [9; 16]);
+
+        assert!(verify_webauthn_signature(&webauthn_sig, &passkey.public_key(), &challenge, CeremonyType::Create).is_ok());
+        let parsed = crate::parse_authenticator_data(&webauthn_sig.authenticator_data).unwrap();
+        let credential = parsed.attested_credential.unwrap();
+        assert_eq!(credential.aaguid, [9; 16]);
+        assert_eq!(credential.credential_id, passkey.credential_id().as_slice());
+        assert_eq!(credential.public_key, passkey.public_key());
+    }
+}
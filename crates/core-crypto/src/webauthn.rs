@@ -1,153 +1,28 @@
 use sha2::{Digest, Sha256};
-use crate::errors::CryptoError;
-use crate::p256_verify::verify_p256_signature;
+use crate::authenticator_data::parse_authenticator_data_detailed;
+use crate::ceremony::{verify_client_data_type_detailed, CeremonyType};
+use crate::errors::{CryptoError, FailureDetail, VerifyFailure};
+use crate::challenge::verify_client_data_challenge_detailed;
+use crate::p256_verify::{is_low_s, verify_p256_digest_detailed};
+use crate::profile::{check_authenticator_data, check_client_data, WebAuthnExpectations, WebAuthnVerificationProfile};
 
-/// All the parts of a WebAuthn signature that we need to verify it
-///
-/// When a user authenticates with their passkey (TouchID, FaceID, etc.),
-/// the browser/device creates this structure. We store it and use it to
-/// verify the signature on-chain without ever seeing the private key.
-#[derive(Debug, Clone)]
-pub struct WebAuthnSignature {
-    /// The raw data from the authenticator (contains flags, counter, etc.)
-    /// This tells us things like whether the user was present, verified, etc.
-    pub authenticator_data: Vec<u8>,
-    
-    /// The client-side data as JSON (contains the challenge, origin, type of operation)
-    /// This proves the signature was created in response to our specific challenge
-    pub client_data_json: Vec<u8>,
-    
-    /// The actual signature over the combined authenticator_data + client_data_json hash
-    /// This is what we verify using the public key
-    pub signature: Vec<u8>,
-    
-    /// The credential ID that identifies which passkey was used
-    /// This helps us find the right public key to verify with
-    pub credential_id: Vec<u8>,
-}
-
-impl WebAuthnSignature {
-    /// Creates a new WebAuthnSignature from all its parts
-    ///
-    /// This is the simplest way to create a WebAuthnSignature when you already
-    /// have all the pieces from a WebAuthn authentication.
-    pub fn new(
-        authenticator_data: Vec<u8>,
-        client_data_json: Vec<u8>,
-        signature: Vec<u8>,
-        credential_id: Vec<u8>,
-    ) -> Self {
-        Self {
-            authenticator_data,
-            client_data_json,
-            signature,
-            credential_id,
-        }
-    }
-
-    /// Converts this signature into bytes so we can store it on-chain
-    ///
-    /// The format is: length1 + data1 + length2 + data2 + ...
-    /// We store the length of each field before the field itself so we know
-    /// how to read it back later.
-    pub fn to_bytes(&self) -> Vec<u8> {
-        // Calculate total size to avoid reallocations
-        let total_size = 4 * 4  // Four length fields (u32 each = 4 bytes)
-            + self.authenticator_data.len()
-            + self.client_data_json.len()
-            + self.signature.len()
-            + self.credential_id.len();
-        
-        let mut bytes = Vec::with_capacity(total_size);
-        
-        // Write each field: length first, then the actual data
-        bytes.extend_from_slice(&(self.authenticator_data.len() as u32).to_le_bytes());
-        bytes.extend_from_slice(&self.authenticator_data);
-        
-        bytes.extend_from_slice(&(self.client_data_json.len() as u32).to_le_bytes());
-        bytes.extend_from_slice(&self.client_data_json);
-        
-        bytes.extend_from_slice(&(self.signature.len() as u32).to_le_bytes());
-        bytes.extend_from_slice(&self.signature);
-        
-        bytes.extend_from_slice(&(self.credential_id.len() as u32).to_le_bytes());
-        bytes.extend_from_slice(&self.credential_id);
-        
-        bytes
-    }
-
-    /// Reads bytes back into a WebAuthnSignature
-    ///
-    /// This does the opposite of `to_bytes()`. It reads the length-prefixed
-    /// format and reconstructs the signature structure.
-    ///
-    /// # Returns
-    /// - `Ok(WebAuthnSignature)` if the data is valid
-    /// - `Err(CryptoError::InvalidSignatureFormat)` if the data is corrupted or incomplete
-    pub fn from_bytes(data: &[u8]) -> Result<Self, CryptoError> {
-        // Helper function to read a u32 length and advance the offset
-        fn read_length(data: &[u8], offset: &mut usize) -> Result<usize, CryptoError> {
-            if *offset + 4 > data.len() {
-                return Err(CryptoError::InvalidSignatureFormat);
-            }
-            let len = u32::from_le_bytes([
-                data[*offset],
-                data[*offset + 1],
-                data[*offset + 2],
-                data[*offset + 3],
-            ]) as usize;
-            *offset += 4;
-            Ok(len)
-        }
-
-        // Helper function to read a slice of bytes
-        fn read_bytes(data: &[u8], offset: &mut usize, len: usize) -> Result<Vec<u8>, CryptoError> {
-            if *offset + len > data.len() {
-                return Err(CryptoError::InvalidSignatureFormat);
-            }
-            let result = data[*offset..*offset + len].to_vec();
-            *offset += len;
-            Ok(result)
-        }
-
-        let mut offset = 0;
-
-        // Read authenticator_data
-        let auth_data_len = read_length(data, &mut offset)?;
-        let authenticator_data = read_bytes(data, &mut offset, auth_data_len)?;
-
-        // Read client_data_json
-        let client_data_len = read_length(data, &mut offset)?;
-        let client_data_json = read_bytes(data, &mut offset, client_data_len)?;
-
-        // Read signature
-        let sig_len = read_length(data, &mut offset)?;
-        let signature = read_bytes(data, &mut offset, sig_len)?;
-
-        // Read credential_id
-        let cred_id_len = read_length(data, &mut offset)?;
-        let credential_id = read_bytes(data, &mut offset, cred_id_len)?;
-
-        Ok(Self {
-            authenticator_data,
-            client_data_json,
-            signature,
-            credential_id,
-        })
-    }
-}
+pub use attesta_types::webauthn::{SignatureFormatError, WebAuthnSignature};
 
 /// Verifies that a WebAuthn signature is valid
 ///
 /// This checks that:
 /// 1. The signature was created by the private key matching the public key
 /// 2. The challenge in the signature matches what we expected
-/// 3. The signature format is correct
+/// 3. It came from the ceremony we expected (an assertion or a registration)
+/// 4. The signature format is correct
 ///
 /// # Parameters
 /// - `webauthn_sig`: The complete WebAuthn signature structure
 /// - `public_key`: The public key from the passkey (64 bytes, uncompressed)
-/// - `expected_challenge`: The challenge we sent - must match what's in the signature
+/// - `expected_challenge`: The challenge we sent - its base64url form must be the
+///   `challenge` field of the client data JSON
+/// - `ceremony`: `Get` for an assertion, `Create` when enrolling a passkey -
+///   the client data JSON's `type` must be exactly its string
 ///
 /// # Returns
 /// - `Ok(())` if the signature is valid and the challenge matches
@@ -163,39 +38,392 @@ pub fn verify_webauthn_signature(
     webauthn_sig: &WebAuthnSignature,
     public_key: &[u8],
     expected_challenge: &[u8],
+    ceremony: CeremonyType,
 ) -> Result<(), CryptoError> {
-    // Authenticator data must be at least 37 bytes (RP ID hash + flags + counter)
-    // If it's shorter, the data is definitely invalid
-    const MIN_AUTHENTICATOR_DATA_LEN: usize = 37;
-    if webauthn_sig.authenticator_data.len() < MIN_AUTHENTICATOR_DATA_LEN {
-        return Err(CryptoError::InvalidAuthenticatorData);
-    }
+    verify_webauthn_signature_detailed(webauthn_sig, public_key, expected_challenge, ceremony).map_err(CryptoError::from)
+}
 
-    // Check that the client_data_json contains our expected challenge
+/// Like `verify_webauthn_signature`, saying which part failed and how
+///
+/// For integrations to log or show while debugging; the `CryptoError` it
+/// converts into is the same one `verify_webauthn_signature` returns.
+pub fn verify_webauthn_signature_detailed(
+    webauthn_sig: &WebAuthnSignature,
+    public_key: &[u8],
+    expected_challenge: &[u8],
+    ceremony: CeremonyType,
+) -> Result<(), VerifyFailure> {
+    verify_webauthn_signature_with_profile_detailed(
+        webauthn_sig,
+        public_key,
+        expected_challenge,
+        ceremony,
+        &WebAuthnVerificationProfile::legacy(),
+        &WebAuthnExpectations::default(),
+    )
+    .map(|_| ())
+}
+
+/// Like `verify_webauthn_signature`, also running the checks `profile` turns on
+///
+/// # Parameters
+/// - `profile`: The optional checks to run (see `WebAuthnVerificationProfile`)
+/// - `expectations`: The relying party and last signature counter to check against
+///
+/// # Returns
+/// - `Ok(sign_count)` with the assertion's signature counter, for the
+///   caller to record if it checks counters
+/// - `Err(CryptoError)` if a check fails
+pub fn verify_webauthn_signature_with_profile(
+    webauthn_sig: &WebAuthnSignature,
+    public_key: &[u8],
+    expected_challenge: &[u8],
+    ceremony: CeremonyType,
+    profile: &WebAuthnVerificationProfile,
+    expectations: &WebAuthnExpectations,
+) -> Result<u32, CryptoError> {
+    verify_webauthn_signature_with_profile_detailed(webauthn_sig, public_key, expected_challenge, ceremony, profile, expectations)
+        .map_err(CryptoError::from)
+}
+
+/// Like `verify_webauthn_signature_with_profile`, saying which part failed and how
+pub fn verify_webauthn_signature_with_profile_detailed(
+    webauthn_sig: &WebAuthnSignature,
+    public_key: &[u8],
+    expected_challenge: &[u8],
+    ceremony: CeremonyType,
+    profile: &WebAuthnVerificationProfile,
+    expectations: &WebAuthnExpectations,
+) -> Result<u32, VerifyFailure> {
+    // Authenticator data must be exactly what its flags say: a 37-byte header
+    // plus any attested credential data and extensions
+    let authenticator_data = parse_authenticator_data_detailed(&webauthn_sig.authenticator_data)?;
+    check_authenticator_data(profile, expectations, &authenticator_data)?;
+
+    // A registration must never pass as an assertion, nor the other way round
+    verify_client_data_type_detailed(&webauthn_sig.client_data_json, ceremony)?;
+
+    // Check that the client_data_json was created for our expected challenge
     // This ensures the signature was created in response to our specific request
-    let client_data_str = String::from_utf8_lossy(&webauthn_sig.client_data_json);
-    
-    // Convert expected_challenge to a string for searching (but handle errors gracefully)
-    let expected_challenge_str = std::str::from_utf8(expected_challenge)
-        .unwrap_or("");
-    
-    if expected_challenge_str.is_empty() || !client_data_str.contains(expected_challenge_str) {
-        return Err(CryptoError::ChallengeMismatch);
-    }
+    verify_client_data_challenge_detailed(&webauthn_sig.client_data_json, expected_challenge)?;
+    check_client_data(profile, expectations, &webauthn_sig.client_data_json)?;
 
     // Hash the client data JSON using SHA-256
     // This is part of the WebAuthn specification
     let client_data_hash = Sha256::digest(&webauthn_sig.client_data_json);
 
-    // Build the exact message that was signed
-    // Format: authenticator_data (variable length) + client_data_hash (32 bytes)
-    let message_len = webauthn_sig.authenticator_data.len() + client_data_hash.len();
-    let mut message = Vec::with_capacity(message_len);
-    message.extend_from_slice(&webauthn_sig.authenticator_data);
-    message.extend_from_slice(&client_data_hash);
+    // The signed message is authenticator_data (variable length) followed by
+    // client_data_hash (32 bytes); feed both to the hasher rather than
+    // joining them in a new buffer
+    let message_digest = Sha256::new()
+        .chain_update(&webauthn_sig.authenticator_data)
+        .chain_update(client_data_hash);
 
     // Now verify the signature over this combined message
-    verify_p256_signature(&message, &webauthn_sig.signature, public_key)?;
+    verify_p256_digest_detailed(message_digest, &webauthn_sig.signature, public_key)?;
+
+    // Only a valid signature is worth asking which of its two forms it is
+    if profile.require_low_s && !is_low_s(&webauthn_sig.signature) {
+        return Err(VerifyFailure::new(CryptoError::HighSSignature, "signature", FailureDetail::None));
+    }
+
+    Ok(authenticator_data.sign_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_rejects_unexplained_authenticator_data() {
+        let mut passkey = crate::test_utils::TestPasskey::new(1);
+        let challenge = [3u8; 32];
+        let mut webauthn_sig = passkey.sign(&challenge);
+        // No ED flag, so nothing may follow the header
+        webauthn_sig.authenticator_data.extend_from_slice(&[0xa0]);
+
+        assert_eq!(
+            verify_webauthn_signature(&webauthn_sig, &passkey.public_key(), &challenge, CeremonyType::Get),
+            Err(CryptoError::InvalidAuthenticatorData)
+        );
+    }
+
+    #[test]
+    fn test_ceremonies_dont_stand_in_for_each_other() {
+        let mut passkey = crate::test_utils::TestPasskey::new(1);
+        let public_key = passkey.public_key();
+        let challenge = [3u8; 32];
+        let created = passkey.sign_create(&challenge);
+        let asserted = passkey.sign(&challenge);
+
+        assert_eq!(
+            verify_webauthn_signature_detailed(&created, &public_key, &challenge, CeremonyType::Get),
+            Err(VerifyFailure::new(CryptoError::CeremonyTypeMismatch, "type", FailureDetail::None))
+        );
+        assert_eq!(
+            verify_webauthn_signature(&asserted, &public_key, &challenge, CeremonyType::Create),
+            Err(CryptoError::CeremonyTypeMismatch)
+        );
+        assert!(verify_webauthn_signature(&created, &public_key, &challenge, CeremonyType::Create).is_ok());
+    }
+
+    #[test]
+    fn test_each_profile_bit_gates_its_check() {
+        use crate::profile::RelyingParty;
+        use crate::test_utils::TestPasskey;
+
+        type Profile = WebAuthnVerificationProfile;
+        let challenge = [3u8; 32];
+        let expectations = WebAuthnExpectations {
+            relying_party: Some(RelyingParty::new(TestPasskey::RP_ID, TestPasskey::ORIGIN)),
+            previous_sign_count: 5,
+        };
+        let passkey = || {
+            let mut passkey = TestPasskey::new(1);
+            passkey.set_sign_count(5);
+            passkey
+        };
+
+        // One assertion per check, failing that check and passing the others
+        let cases: [(u8, WebAuthnSignature, CryptoError); 6] = [
+            (Profile::RP_ID, passkey().sign_as(&challenge, "evil.example", TestPasskey::ORIGIN, 0x05), CryptoError::RpIdMismatch),
+            (Profile::ORIGIN, passkey().sign_as(&challenge, TestPasskey::RP_ID, "https://evil.example", 0x05), CryptoError::OriginMismatch),
+            (Profile::USER_PRESENT, passkey().sign_as(&challenge, TestPasskey::RP_ID, TestPasskey::ORIGIN, 0x04), CryptoError::UserNotPresent),
+            (Profile::USER_VERIFIED, passkey().sign_as(&challenge, TestPasskey::RP_ID, TestPasskey::ORIGIN, 0x01), CryptoError::UserNotVerified),
+            (
+                Profile::SIGN_COUNT,
+                {
+                    let mut replayed = passkey();
+                    replayed.set_sign_count(4);
+                    replayed.sign(&challenge)
+                },
+                CryptoError::SignCountNotIncreased,
+            ),
+            (Profile::LOW_S, passkey().sign_high_s(&challenge), CryptoError::HighSSignature),
+        ];
+        let public_key = passkey().public_key();
+        let verify = |webauthn_sig: &WebAuthnSignature, bits: u8| {
+            let profile = Profile::from_bits(bits).unwrap();
+            verify_webauthn_signature_with_profile(webauthn_sig, &public_key, &challenge, CeremonyType::Get, &profile, &expectations)
+        };
+
+        for (bit, webauthn_sig, error) in &cases {
+            assert_eq!(verify(webauthn_sig, *bit), Err(error.clone()), "bit {:#04x} alone", bit);
+            assert_eq!(verify(webauthn_sig, Profile::ALL), Err(error.clone()), "bit {:#04x} in strict", bit);
+            assert!(verify(webauthn_sig, Profile::ALL & !bit).is_ok(), "everything but bit {:#04x}", bit);
+            assert!(verify_webauthn_signature(webauthn_sig, &public_key, &challenge, CeremonyType::Get).is_ok());
+        }
+
+        // An assertion that passes everything reports its counter
+        assert_eq!(verify(&passkey().sign(&challenge), Profile::ALL), Ok(6));
+    }
+
+    #[test]
+    fn test_relying_party_checks_fail_without_one() {
+        let mut passkey = crate::test_utils::TestPasskey::new(1);
+        let challenge = [3u8; 32];
+        let webauthn_sig = passkey.sign(&challenge);
+        let verify = |profile: WebAuthnVerificationProfile| {
+            verify_webauthn_signature_with_profile(
+                &webauthn_sig,
+                &passkey.public_key(),
+                &challenge,
+                CeremonyType::Get,
+                &profile,
+                &WebAuthnExpectations::default(),
+            )
+        };
+
+        assert_eq!(verify(WebAuthnVerificationProfile::standard()), Err(CryptoError::RpIdMismatch));
+        let origin_only = WebAuthnVerificationProfile { check_origin: true, ..Default::default() };
+        assert_eq!(verify(origin_only), Err(CryptoError::OriginMismatch));
+        assert_eq!(verify(WebAuthnVerificationProfile::legacy()), Ok(1));
+    }
+
+    /// Checks both entry points fail the same way, and returns the detail
+    fn failure(webauthn_sig: &WebAuthnSignature, public_key: &[u8], challenge: &[u8]) -> VerifyFailure {
+        let failure = verify_webauthn_signature_detailed(webauthn_sig, public_key, challenge, CeremonyType::Get).unwrap_err();
+        assert_eq!(verify_webauthn_signature(webauthn_sig, public_key, challenge, CeremonyType::Get), Err(failure.error.clone()));
+        failure
+    }
+
+    #[test]
+    fn test_detailed_failures_say_what_went_wrong() {
+        #[cfg(feature = "detailed-errors")]
+        use crate::challenge::base64url_encode;
+        use crate::errors::FailureDetail;
+
+        let mut passkey = crate::test_utils::TestPasskey::new(1);
+        let public_key = passkey.public_key();
+        let challenge = [3u8; 32];
+        let signed = passkey.sign(&challenge);
+
+        let mut short = signed.clone();
+        short.authenticator_data.truncate(20);
+        assert_eq!(
+            failure(&short, &public_key, &challenge),
+            VerifyFailure::new(
+                CryptoError::InvalidAuthenticatorData,
+                "authenticator_data",
+                FailureDetail::TooShort { minimum: 37, actual: 20 }
+            )
+        );
+
+        let mut trailing = signed.clone();
+        trailing.authenticator_data.push(0xa0);
+        assert_eq!(failure(&trailing, &public_key, &challenge).detail, FailureDetail::Offset(37));
+
+        // Hashes of the base64url challenges, not the challenges
+        let other = [4u8; 32];
+        let wrong_challenge = failure(&signed, &public_key, &other);
+        assert_eq!(wrong_challenge.error, CryptoError::ChallengeMismatch);
+        assert_eq!(wrong_challenge.field, "challenge");
+        #[cfg(feature = "detailed-errors")]
+        assert_eq!(
+            wrong_challenge.detail,
+            FailureDetail::Mismatch {
+                expected: Sha256::digest(base64url_encode(&other)).into(),
+                found: Some(Sha256::digest(base64url_encode(&challenge)).into()),
+            }
+        );
+
+        let mut no_challenge = signed.clone();
+        no_challenge.client_data_json = br#"{"type":"webauthn.get"}"#.to_vec();
+        #[cfg(feature = "detailed-errors")]
+        assert!(matches!(
+            failure(&no_challenge, &public_key, &challenge).detail,
+            FailureDetail::Mismatch { found: None, .. }
+        ));
+
+        let mut short_signature = signed.clone();
+        short_signature.signature.pop();
+        assert_eq!(
+            failure(&short_signature, &public_key, &challenge),
+            VerifyFailure::new(
+                CryptoError::InvalidSignatureFormat,
+                "signature",
+                FailureDetail::Length { expected: 64, actual: 63 }
+            )
+        );
+
+        assert_eq!(
+            failure(&signed, &public_key[..33], &challenge),
+            VerifyFailure::new(
+                CryptoError::InvalidP256PublicKey,
+                "public_key",
+                FailureDetail::Length { expected: 64, actual: 33 }
+            )
+        );
+
+        let other_key = crate::test_utils::TestPasskey::new(2).public_key();
+        assert_eq!(
+            failure(&signed, &other_key, &challenge),
+            VerifyFailure::new(CryptoError::SignatureVerificationFailed, "signature", FailureDetail::None)
+        );
 
-    Ok(())
+        #[cfg(feature = "detailed-errors")]
+        {
+            assert_eq!(
+                failure(&short, &public_key, &challenge).to_string(),
+                "Invalid authenticator data (authenticator_data: expected at least 37 bytes, got 20)"
+            );
+            assert_eq!(
+                failure(&trailing, &public_key, &challenge).to_string(),
+                "Invalid authenticator data (authenticator_data: at byte 37)"
+            );
+            assert_eq!(
+                failure(&no_challenge, &public_key, &challenge).to_string(),
+                format!(
+                    "Challenge mismatch (challenge: expected hash {}, found no challenge)",
+                    Sha256::digest(base64url_encode(&challenge)).iter().map(|b| format!("{:02x}", b)).collect::<String>()
+                )
+            );
+            assert_eq!(
+                failure(&short_signature, &public_key, &challenge).to_string(),
+                "Invalid signature format (signature: expected 64 bytes, got 63)"
+            );
+        }
+        #[cfg(not(feature = "detailed-errors"))]
+        assert_eq!(failure(&short, &public_key, &challenge).to_string(), "Invalid authenticator data (authenticator_data)");
+    }
+
+    /// Counts heap allocations made on the thread that's counting
+    struct CountingAllocator;
+
+    thread_local! {
+        static COUNTING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            if COUNTING.with(|counting| counting.get()) {
+                ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            }
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        ALLOCATIONS.with(|count| count.set(0));
+        COUNTING.with(|counting| counting.set(true));
+        let result = f();
+        COUNTING.with(|counting| counting.set(false));
+        (result, ALLOCATIONS.with(|count| count.get()))
+    }
+
+    #[test]
+    fn test_verification_does_not_allocate() {
+        use crate::profile::RelyingParty;
+        use crate::test_utils::TestPasskey;
+
+        let mut passkey = TestPasskey::new(1);
+        let challenge = [3u8; 32];
+        let webauthn_sig = passkey.sign(&challenge);
+        let public_key = passkey.public_key();
+
+        let (result, count) = allocations(|| verify_webauthn_signature(&webauthn_sig, &public_key, &challenge, CeremonyType::Get));
+        assert_eq!((result, count), (Ok(()), 0));
+
+        let expectations = WebAuthnExpectations {
+            relying_party: Some(RelyingParty::new(TestPasskey::RP_ID, TestPasskey::ORIGIN)),
+            previous_sign_count: 0,
+        };
+        let profile = WebAuthnVerificationProfile::strict();
+        let (result, count) = allocations(|| {
+            verify_webauthn_signature_with_profile(&webauthn_sig, &public_key, &challenge, CeremonyType::Get, &profile, &expectations)
+        });
+        assert_eq!((result, count), (Ok(1), 0));
+    }
+
+    #[test]
+    fn test_streamed_digest_matches_joined_message() {
+        use crate::p256_verify::verify_p256_signature;
+        use crate::test_utils::TestPasskey;
+
+        let mut passkey = TestPasskey::new(1);
+        let challenge = [3u8; 32];
+        let signed = passkey.sign(&challenge);
+        let mut tampered = signed.clone();
+        if let Some(flags) = tampered.authenticator_data.get_mut(32) {
+            *flags ^= 0x04;
+        }
+        let other_key = TestPasskey::new(2).public_key();
+
+        for (webauthn_sig, public_key) in [(&signed, passkey.public_key()), (&tampered, passkey.public_key()), (&signed, other_key)] {
+            // The message as it used to be built, in one buffer
+            let message = [&webauthn_sig.authenticator_data[..], &Sha256::digest(&webauthn_sig.client_data_json)[..]].concat();
+            assert_eq!(
+                verify_webauthn_signature(webauthn_sig, &public_key, &challenge, CeremonyType::Get),
+                verify_p256_signature(&message, &webauthn_sig.signature, &public_key)
+            );
+        }
+    }
 }
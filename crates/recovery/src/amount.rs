@@ -0,0 +1,6 @@
+//! Token amounts in their smallest unit
+//!
+//! Re-exported from `attesta-types`, where the policy layouts that use
+//! `Amount` live.
+
+pub use attesta_types::amount::*;
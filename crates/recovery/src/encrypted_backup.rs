@@ -1,13 +1,72 @@
+use attesta_types::consts::{AES_GCM_NONCE_LEN, BORSH_LEN_PREFIX, HASH_LEN};
 use sha2::{Digest, Sha256};
 use borsh::{BorshDeserialize, BorshSerialize};
+use attesta_types::passkey::{CredentialIdStorage, PasskeyEntry};
+use core_crypto::hashing::{domain_hash, domain_hasher, Domain};
+use crate::errors::RecoveryError;
+
+/// Largest serialized backup accepted by the on-chain backup escrow (2KB)
+///
+/// Every byte stored on-chain costs rent, so we cap escrowed backups at a
+/// size that comfortably fits several passkeys plus policy data.
+pub const MAX_ESCROW_BACKUP_SIZE: usize = 2048;
+
+/// Action name a passkey signs to store or replace the escrowed backup
+pub const BACKUP_WRITE_ACTION: &[u8] = b"write_backup";
+
+/// Action name a passkey signs to delete the escrowed backup
+pub const BACKUP_DELETE_ACTION: &[u8] = b"delete_backup";
+
+/// Backups whose key hash and nonce are plain SHA-256
+pub const BACKUP_VERSION_PLAIN_HASH: u8 = 1;
+
+/// The version `EncryptedBackup::new` writes: the key hash and nonce are
+/// hashed in their own domains
+pub const BACKUP_VERSION: u8 = 2;
+
+/// Version 2 followed by the backup's `rekey_generation`, written by
+/// `EncryptedBackup::rekey`
+pub const BACKUP_VERSION_REKEYED: u8 = 3;
+
+/// What goes inside an `EncryptedBackup`
+///
+/// Passkeys with long credential IDs may only have the ID's hash on-chain
+/// (`CredentialIdStorage::Hashed`). The backup keeps every full ID, so a
+/// recovered client can still present them in assertions.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct BackupContents {
+    /// Full credential ID of every passkey the account had when backed up
+    pub credential_ids: Vec<Vec<u8>>,
+
+    /// Everything else the client wants to restore (keys, policy, metadata)
+    pub account_data: Vec<u8>,
+}
+
+impl BackupContents {
+    /// The full credential ID for a registry entry
+    ///
+    /// Entries that store their full ID return it as is; hashed entries are
+    /// looked up among the backed-up IDs.
+    pub fn credential_id_for<'a>(&'a self, entry: &'a PasskeyEntry) -> Option<&'a [u8]> {
+        match entry.id_storage {
+            CredentialIdStorage::Full => Some(&entry.credential_id),
+            CredentialIdStorage::Hashed => {
+                self.credential_ids.iter().map(Vec::as_slice).find(|id| entry.matches(id))
+            }
+        }
+    }
+}
 
 /// Encrypted backup of account recovery information
 /// This enables users to recover their account even if they lose all devices
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+///
+/// Serialized as Borsh, except that `rekey_generation` is only written for
+/// `BACKUP_VERSION_REKEYED`, so earlier backups keep their layout.
+#[derive(Debug, Clone)]
 pub struct EncryptedBackup {
     /// Hash of the encryption key (for verification)
     /// The actual key should be derived from a user's recovery phrase or secret
-    pub key_hash: [u8; 32],
+    pub key_hash: [u8; HASH_LEN],
     
     /// Encrypted data containing:
     /// - Passkey public keys
@@ -17,13 +76,46 @@ pub struct EncryptedBackup {
     pub encrypted_data: Vec<u8>,
     
     /// Nonce/IV used for encryption (should be random for each backup)
-    pub nonce: [u8; 12], // 96 bits for AES-GCM
+    pub nonce: [u8; AES_GCM_NONCE_LEN], // 96 bits for AES-GCM
     
     /// Timestamp when backup was created
     pub created_at: i64,
     
     /// Version of the backup format (for future compatibility)
     pub version: u8,
+
+    /// How many times the backup has been moved to a new key (see `rekey`);
+    /// 0 for versions before `BACKUP_VERSION_REKEYED`
+    pub rekey_generation: u16,
+}
+
+impl BorshSerialize for EncryptedBackup {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.key_hash.serialize(writer)?;
+        self.encrypted_data.serialize(writer)?;
+        self.nonce.serialize(writer)?;
+        self.created_at.serialize(writer)?;
+        self.version.serialize(writer)?;
+        if self.version == BACKUP_VERSION_REKEYED {
+            self.rekey_generation.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl BorshDeserialize for EncryptedBackup {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let key_hash = <[u8; HASH_LEN]>::deserialize_reader(reader)?;
+        let encrypted_data = Vec::<u8>::deserialize_reader(reader)?;
+        let nonce = <[u8; AES_GCM_NONCE_LEN]>::deserialize_reader(reader)?;
+        let created_at = i64::deserialize_reader(reader)?;
+        let version = u8::deserialize_reader(reader)?;
+        let rekey_generation = match version {
+            BACKUP_VERSION_REKEYED => u16::deserialize_reader(reader)?,
+            _ => 0,
+        };
+        Ok(Self { key_hash, encrypted_data, nonce, created_at, version, rekey_generation })
+    }
 }
 
 impl EncryptedBackup {
@@ -36,63 +128,339 @@ impl EncryptedBackup {
         created_at: i64,
     ) -> Self {
         // Hash the encryption key for verification
-        let key_hash = Sha256::digest(encryption_key);
-        let mut key_hash_array = [0u8; 32];
-        key_hash_array.copy_from_slice(&key_hash);
+        let key_hash = domain_hash(Domain::BackupKey, encryption_key);
 
         // Generate a random nonce (in production, use secure random)
         // For now, derive from timestamp and key
-        let mut nonce = [0u8; 12];
-        let nonce_input = Sha256::digest(&[encryption_key, &created_at.to_le_bytes()].concat());
-        nonce.copy_from_slice(&nonce_input[..12]);
+        let mut nonce = [0u8; AES_GCM_NONCE_LEN];
+        let nonce_input = domain_hasher(Domain::BackupNonce)
+            .chain_update(encryption_key)
+            .chain_update(created_at.to_le_bytes())
+            .finalize();
+        for (byte, input) in nonce.iter_mut().zip(nonce_input) {
+            *byte = input;
+        }
 
         // In production: Encrypt account_data using AES-GCM with encryption_key and nonce
         // For now, we'll just store a placeholder
         let encrypted_data = account_data.to_vec(); // Should be encrypted in production
 
         Self {
-            key_hash: key_hash_array,
+            key_hash,
             encrypted_data,
             nonce,
             created_at,
-            version: 1,
+            version: BACKUP_VERSION,
+            rekey_generation: 0,
+        }
+    }
+
+    /// Re-encrypts the backup under a new key, for when the recovery phrase
+    /// may have leaked
+    ///
+    /// The contents carry over as they are, so no device has to be present
+    /// to rebuild them. The new backup has a fresh nonce, is dated `now`, and
+    /// its `rekey_generation` is one more than this one's. The escrow never
+    /// goes back to a lower generation, so once the rekeyed backup is stored,
+    /// a copy still readable with the old phrase can't replace it.
+    pub fn rekey(&self, old_key: &[u8], new_key: &[u8], now: i64) -> Result<Self, RecoveryError> {
+        let data = self.decrypt(old_key)?;
+        let generation = self.rekey_generation.checked_add(1).ok_or(RecoveryError::RekeyLimitReached)?;
+        Ok(Self::new(new_key, &data, now).with_generation(generation))
+    }
+
+    /// This backup, marked as rekey generation `generation`
+    ///
+    /// For a backup made with `new` or `seal` for an account whose escrowed
+    /// backup has been rekeyed: it needs at least that generation to be
+    /// accepted in its place.
+    pub fn with_generation(mut self, generation: u16) -> Self {
+        self.version = BACKUP_VERSION_REKEYED;
+        self.rekey_generation = generation;
+        self
+    }
+
+    /// Checks that the provided key matches the backup's key hash
+    ///
+    /// The key is hashed the way the backup's version did; a version this
+    /// build doesn't know matches no key.
+    pub fn check_key(&self, encryption_key: &[u8]) -> Result<(), RecoveryError> {
+        let key_hash: Option<[u8; HASH_LEN]> = match self.version {
+            BACKUP_VERSION_PLAIN_HASH => Some(Sha256::digest(encryption_key).into()),
+            BACKUP_VERSION | BACKUP_VERSION_REKEYED => Some(domain_hash(Domain::BackupKey, encryption_key)),
+            _ => None,
+        };
+        if key_hash != Some(self.key_hash) {
+            return Err(RecoveryError::InvalidEncryptionKey);
         }
+        Ok(())
     }
 
     /// Verifies that the provided key matches the backup's key hash
+    #[deprecated(note = "use `check_key`, which says why it failed")]
     pub fn verify_key(&self, encryption_key: &[u8]) -> bool {
-        let key_hash = Sha256::digest(encryption_key);
-        key_hash.as_slice() == self.key_hash
+        self.check_key(encryption_key).is_ok()
     }
 
     /// Decrypts the backup data (simplified - in production use AES-GCM)
     /// Returns the decrypted account data if the key is correct
-    pub fn decrypt(&self, encryption_key: &[u8]) -> Result<Vec<u8>, &'static str> {
-        // Verify the key
-        if !self.verify_key(encryption_key) {
-            return Err("Invalid encryption key");
-        }
+    pub fn decrypt(&self, encryption_key: &[u8]) -> Result<Vec<u8>, RecoveryError> {
+        self.check_key(encryption_key)?;
 
         // In production: Decrypt encrypted_data using AES-GCM with encryption_key and nonce
         // For now, just return the data (since we didn't actually encrypt it)
         Ok(self.encrypted_data.clone())
     }
 
+    /// Creates a backup of structured contents
+    pub fn seal(encryption_key: &[u8], contents: &BackupContents, created_at: i64) -> Result<Self, std::io::Error> {
+        Ok(Self::new(encryption_key, &borsh::to_vec(contents)?, created_at))
+    }
+
+    /// Decrypts a backup made with `seal`
+    pub fn open(&self, encryption_key: &[u8]) -> Result<BackupContents, RecoveryError> {
+        let data = self.decrypt(encryption_key)?;
+        borsh::from_slice(&data).map_err(|_| RecoveryError::InvalidBackupContents)
+    }
+
+    /// Length of `to_bytes()`, without serializing
+    pub fn serialized_size(&self) -> usize {
+        // key_hash + vec length + data + nonce + created_at (8) + version (1)
+        // [+ rekey_generation (2)]
+        let generation = if self.version == BACKUP_VERSION_REKEYED { 2 } else { 0 };
+        HASH_LEN + BORSH_LEN_PREFIX + self.encrypted_data.len() + AES_GCM_NONCE_LEN + 8 + 1 + generation
+    }
+
     /// Serializes the backup to bytes
     pub fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
         borsh::to_vec(self)
     }
 
     /// Deserializes bytes into an EncryptedBackup
+    ///
+    /// Accepts anything that decodes, so tooling can look at a broken
+    /// backup; see `from_bytes_strict`.
     pub fn from_bytes(data: &[u8]) -> Result<Self, std::io::Error> {
         borsh::from_slice(data)
     }
+
+    /// `from_bytes`, rejecting backups whose key hash, nonce or data were
+    /// never filled in, or whose version isn't known
+    ///
+    /// A zeroed blob decodes as a backup, and nothing would notice until
+    /// someone tried to open it.
+    pub fn from_bytes_strict(data: &[u8]) -> Result<Self, RecoveryError> {
+        let backup = Self::from_bytes(data).map_err(|_| RecoveryError::InvalidBackupFormat)?;
+        if !matches!(backup.version, BACKUP_VERSION_PLAIN_HASH | BACKUP_VERSION | BACKUP_VERSION_REKEYED) {
+            return Err(RecoveryError::UnknownBackupVersion);
+        }
+        if backup.key_hash == [0; HASH_LEN] {
+            return Err(RecoveryError::ZeroBackupKeyHash);
+        }
+        if backup.nonce == [0; AES_GCM_NONCE_LEN] {
+            return Err(RecoveryError::ZeroBackupNonce);
+        }
+        if backup.encrypted_data.is_empty() {
+            return Err(RecoveryError::EmptyBackup);
+        }
+        Ok(backup)
+    }
+
+    /// Checks that this backup fits in the on-chain backup escrow
+    pub fn validate_escrow_size(&self) -> Result<(), RecoveryError> {
+        if self.serialized_size() > MAX_ESCROW_BACKUP_SIZE {
+            return Err(RecoveryError::BackupTooLarge);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a backup that is about to be written to the on-chain escrow
+    ///
+    /// The size is checked before deserializing so oversized blobs are
+    /// rejected without doing any parsing work. The backup must pass
+    /// `from_bytes_strict`.
+    pub fn from_escrow_bytes(data: &[u8]) -> Result<Self, RecoveryError> {
+        if data.len() > MAX_ESCROW_BACKUP_SIZE {
+            return Err(RecoveryError::BackupTooLarge);
+        }
+
+        Self::from_bytes_strict(data)
+    }
 }
 
 /// Helper for deriving an encryption key from a recovery phrase
 pub fn derive_backup_key(recovery_phrase: &str) -> [u8; 32] {
-    let hash = Sha256::digest(recovery_phrase.as_bytes());
-    let mut key = [0u8; 32];
-    key.copy_from_slice(&hash);
-    key
+    Sha256::digest(recovery_phrase.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_escrow_size_within_limit() {
+        let backup = EncryptedBackup::new(b"key", &[7u8; 512], 1234567890);
+        assert!(backup.validate_escrow_size().is_ok());
+
+        let bytes = backup.to_bytes().unwrap();
+        let restored = EncryptedBackup::from_escrow_bytes(&bytes).unwrap();
+        assert_eq!(restored.encrypted_data, backup.encrypted_data);
+    }
+
+    #[test]
+    fn test_escrow_size_rejects_oversized_backup() {
+        let backup = EncryptedBackup::new(b"key", &[7u8; MAX_ESCROW_BACKUP_SIZE], 1234567890);
+        assert_eq!(
+            backup.validate_escrow_size(),
+            Err(RecoveryError::BackupTooLarge)
+        );
+
+        let bytes = backup.to_bytes().unwrap();
+        assert!(EncryptedBackup::from_escrow_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_escrow_size_boundary() {
+        // Fixed fields: key_hash + vec length + nonce + created_at (8) + version (1)
+        let overhead = HASH_LEN + BORSH_LEN_PREFIX + AES_GCM_NONCE_LEN + 8 + 1;
+        let at_limit = EncryptedBackup::new(b"key", &vec![0u8; MAX_ESCROW_BACKUP_SIZE - overhead], 0);
+        assert!(at_limit.validate_escrow_size().is_ok());
+
+        let over_limit = EncryptedBackup::new(b"key", &vec![0u8; MAX_ESCROW_BACKUP_SIZE - overhead + 1], 0);
+        assert!(over_limit.validate_escrow_size().is_err());
+    }
+
+    #[test]
+    fn test_escrow_rejects_garbage() {
+        assert_eq!(
+            EncryptedBackup::from_escrow_bytes(&[1, 2, 3]).unwrap_err(),
+            RecoveryError::InvalidBackupFormat
+        );
+    }
+
+    #[test]
+    fn test_strict_rejects_unfilled_backups() {
+        let backup = EncryptedBackup::new(b"key", b"data", 100);
+        let cases = [
+            (EncryptedBackup { key_hash: [0; HASH_LEN], ..backup.clone() }, RecoveryError::ZeroBackupKeyHash),
+            (EncryptedBackup { nonce: [0; AES_GCM_NONCE_LEN], ..backup.clone() }, RecoveryError::ZeroBackupNonce),
+            (EncryptedBackup { encrypted_data: Vec::new(), ..backup.clone() }, RecoveryError::EmptyBackup),
+            (EncryptedBackup { version: 0, ..backup.clone() }, RecoveryError::UnknownBackupVersion),
+            (EncryptedBackup { version: BACKUP_VERSION_REKEYED + 1, ..backup.clone() }, RecoveryError::UnknownBackupVersion),
+        ];
+
+        for (broken, error) in cases {
+            let bytes = broken.to_bytes().unwrap();
+            assert!(EncryptedBackup::from_bytes(&bytes).is_ok(), "{:?}", error);
+            assert_eq!(EncryptedBackup::from_bytes_strict(&bytes).unwrap_err(), error);
+            assert_eq!(EncryptedBackup::from_escrow_bytes(&bytes).unwrap_err(), error);
+        }
+
+        // All zeros decodes, as a version 0 backup of nothing
+        let zeroed = vec![0u8; HASH_LEN + BORSH_LEN_PREFIX + AES_GCM_NONCE_LEN + 8 + 1];
+        assert!(EncryptedBackup::from_bytes(&zeroed).is_ok());
+        assert_eq!(EncryptedBackup::from_bytes_strict(&zeroed).unwrap_err(), RecoveryError::UnknownBackupVersion);
+
+        assert_eq!(EncryptedBackup::from_bytes_strict(&backup.to_bytes().unwrap()).unwrap().encrypted_data, b"data");
+        let plain = EncryptedBackup { version: BACKUP_VERSION_PLAIN_HASH, ..backup };
+        assert!(EncryptedBackup::from_bytes_strict(&plain.to_bytes().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_contents_recover_hashed_credential_ids() {
+        let long_id = vec![3u8; 200];
+        let contents = BackupContents { credential_ids: vec![b"phone".to_vec(), long_id.clone()], account_data: b"data".to_vec() };
+        let backup = EncryptedBackup::seal(b"key", &contents, 100).unwrap();
+
+        assert_eq!(backup.open(b"wrong key"), Err(RecoveryError::InvalidEncryptionKey));
+        let opened = backup.open(b"key").unwrap();
+        assert_eq!(opened, contents);
+
+        let hashed = PasskeyEntry::new_hashed([1; 64], &long_id, "Key".to_string(), 0);
+        let full = PasskeyEntry::new([1; 64], b"phone".to_vec(), "Phone".to_string(), 0);
+        let lost = PasskeyEntry::new_hashed([1; 64], b"not backed up", "Old".to_string(), 0);
+        assert_eq!(opened.credential_id_for(&hashed), Some(long_id.as_slice()));
+        assert_eq!(opened.credential_id_for(&full), Some(b"phone".as_slice()));
+        assert_eq!(opened.credential_id_for(&lost), None);
+    }
+
+    #[test]
+    fn test_plain_hash_backups_still_open() {
+        let mut backup = EncryptedBackup::new(b"key", b"data", 100);
+        assert_eq!(backup.version, BACKUP_VERSION);
+        assert_ne!(backup.key_hash, <[u8; HASH_LEN]>::from(Sha256::digest(b"key")));
+        assert_eq!(backup.check_key(b"key"), Ok(()));
+
+        backup.key_hash = Sha256::digest(b"key").into();
+        assert_eq!(backup.check_key(b"key"), Err(RecoveryError::InvalidEncryptionKey));
+        backup.version = BACKUP_VERSION_PLAIN_HASH;
+        assert_eq!(backup.decrypt(b"key"), Ok(b"data".to_vec()));
+        assert_eq!(backup.check_key(b"other key"), Err(RecoveryError::InvalidEncryptionKey));
+
+        backup.version = BACKUP_VERSION_REKEYED + 1;
+        assert_eq!(backup.check_key(b"key"), Err(RecoveryError::InvalidEncryptionKey));
+    }
+
+    #[test]
+    fn test_serialized_size_matches_to_bytes() {
+        let cases = [
+            EncryptedBackup::new(b"key", &[], 0),
+            EncryptedBackup::new(b"key", &[7u8; 512], 1234567890),
+            EncryptedBackup::new(b"key", &[7u8; MAX_ESCROW_BACKUP_SIZE], -1),
+            EncryptedBackup::new(b"key", &[7u8; 512], 0).with_generation(3),
+        ];
+
+        for backup in cases {
+            assert_eq!(backup.serialized_size(), backup.to_bytes().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_rekey_round_trip() {
+        let contents = BackupContents { credential_ids: vec![b"phone".to_vec()], account_data: b"data".to_vec() };
+        let old_key = derive_backup_key("old phrase");
+        let new_key = derive_backup_key("new phrase");
+        let backup = EncryptedBackup::seal(&old_key, &contents, 100).unwrap();
+
+        let rekeyed = backup.rekey(&old_key, &new_key, 200).unwrap();
+        assert_eq!(rekeyed.open(&new_key), Ok(contents.clone()));
+        assert_eq!(rekeyed.open(&old_key), Err(RecoveryError::InvalidEncryptionKey));
+        assert_ne!(rekeyed.nonce, backup.nonce);
+        assert_eq!(rekeyed.created_at, 200);
+        assert_eq!((backup.rekey_generation, rekeyed.rekey_generation), (0, 1));
+
+        // The generation survives serialization, and keeps counting
+        let restored = EncryptedBackup::from_escrow_bytes(&rekeyed.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.version, BACKUP_VERSION_REKEYED);
+        let again = restored.rekey(&new_key, &old_key, 300).unwrap();
+        assert_eq!(again.rekey_generation, 2);
+        assert_eq!(again.open(&old_key), Ok(contents));
+
+        assert_eq!(backup.rekey(&new_key, &old_key, 200).unwrap_err(), RecoveryError::InvalidEncryptionKey);
+    }
+
+    #[test]
+    fn test_unrekeyed_backups_keep_their_layout() {
+        let backup = EncryptedBackup::new(b"key", b"data", 100);
+        let bytes = backup.to_bytes().unwrap();
+        assert_eq!(bytes.last(), Some(&BACKUP_VERSION));
+        assert_eq!(EncryptedBackup::from_bytes(&bytes).unwrap().rekey_generation, 0);
+
+        let rekeyed = backup.with_generation(0x0102).to_bytes().unwrap();
+        assert_eq!(rekeyed.len(), bytes.len() + 2);
+        assert!(rekeyed.ends_with(&[BACKUP_VERSION_REKEYED, 0x02, 0x01]));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_serialized_size_matches_to_bytes(
+            data in prop::collection::vec(any::<u8>(), 0..MAX_ESCROW_BACKUP_SIZE + 64),
+            created_at in any::<i64>(),
+        ) {
+            let backup = EncryptedBackup::new(b"key", &data, created_at);
+            prop_assert_eq!(backup.serialized_size(), backup.to_bytes().unwrap().len());
+        }
+    }
 }
@@ -0,0 +1,235 @@
+//! Errors from the passkey registry and encrypted backups
+//!
+//! Every variant has a fixed custom code, so the program can return it as a
+//! `ProgramError::Custom` and clients can match on it. The custom codes
+//! Attesta's crates use never overlap:
+//!
+//! | Codes | Errors |
+//! |---|---|
+//! | 0 to 999 | `core_crypto::CryptoError` |
+//! | 1000 to 1099 | `RecoveryError` |
+//! | 1100 to 1199 | `PolicyError` (see `POLICY_ERROR_CODES`) |
+//! | 6000 and up | the program's own errors (Anchor's range) |
+//!
+//! A variant's code never changes and is never reused once it has shipped.
+
+use std::ops::RangeInclusive;
+use thiserror::Error;
+
+/// Custom error codes `RecoveryError` uses (see `RecoveryError::code`)
+pub const RECOVERY_ERROR_CODES: RangeInclusive<u32> = 1000..=1099;
+
+/// Why a passkey registry or backup operation failed
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum RecoveryError {
+    #[error("Maximum number of passkeys reached")]
+    MaxPasskeysReached = 1000,
+
+    #[error("An account holds at most 8 passkeys")]
+    RegistryFull = 1001,
+
+    #[error("Credential ID is too long")]
+    CredentialIdTooLong = 1002,
+
+    #[error("Passkey name is too long")]
+    PasskeyNameTooLong = 1003,
+
+    #[error("Credential ID already exists")]
+    DuplicateCredentialId = 1004,
+
+    #[error("Credential ID has been revoked")]
+    CredentialRevoked = 1005,
+
+    #[error("Cannot remove primary passkey")]
+    CannotRemovePrimary = 1006,
+
+    #[error("Passkey not found")]
+    PasskeyNotFound = 1007,
+
+    #[error("Passkey is disabled")]
+    PasskeyDisabled = 1008,
+
+    #[error("Invalid encryption key")]
+    InvalidEncryptionKey = 1009,
+
+    #[error("Invalid backup contents")]
+    InvalidBackupContents = 1010,
+
+    #[error("Backup exceeds maximum escrow size")]
+    BackupTooLarge = 1011,
+
+    #[error("Invalid backup format")]
+    InvalidBackupFormat = 1012,
+
+    #[error("Backup has been rekeyed as many times as it can be")]
+    RekeyLimitReached = 1013,
+
+    #[error("Credential ID is empty")]
+    EmptyCredentialId = 1014,
+
+    #[error("Backup holds no encrypted data")]
+    EmptyBackup = 1015,
+
+    #[error("Backup key hash is all zeros")]
+    ZeroBackupKeyHash = 1016,
+
+    #[error("Backup nonce is all zeros")]
+    ZeroBackupNonce = 1017,
+
+    #[error("Backup format version isn't one this build reads")]
+    UnknownBackupVersion = 1018,
+}
+
+impl RecoveryError {
+    /// Every variant, in code order
+    pub const ALL: [RecoveryError; 19] = [
+        RecoveryError::MaxPasskeysReached,
+        RecoveryError::RegistryFull,
+        RecoveryError::CredentialIdTooLong,
+        RecoveryError::PasskeyNameTooLong,
+        RecoveryError::DuplicateCredentialId,
+        RecoveryError::CredentialRevoked,
+        RecoveryError::CannotRemovePrimary,
+        RecoveryError::PasskeyNotFound,
+        RecoveryError::PasskeyDisabled,
+        RecoveryError::InvalidEncryptionKey,
+        RecoveryError::InvalidBackupContents,
+        RecoveryError::BackupTooLarge,
+        RecoveryError::InvalidBackupFormat,
+        RecoveryError::RekeyLimitReached,
+        RecoveryError::EmptyCredentialId,
+        RecoveryError::EmptyBackup,
+        RecoveryError::ZeroBackupKeyHash,
+        RecoveryError::ZeroBackupNonce,
+        RecoveryError::UnknownBackupVersion,
+    ];
+
+    /// The error's custom code
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+impl From<RecoveryError> for solana_program::program_error::ProgramError {
+    fn from(e: RecoveryError) -> Self {
+        solana_program::program_error::ProgramError::Custom(e.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attesta_types::policy::{PolicyError, POLICY_ERROR_CODES};
+    use core_crypto::CryptoError;
+    use solana_program::program_error::ProgramError;
+
+    /// Where Anchor starts numbering the program's own errors
+    const ANCHOR_ERROR_CODE_OFFSET: u32 = 6000;
+
+    const CRYPTO_ERRORS: [CryptoError; 18] = [
+        CryptoError::InvalidWebAuthnSignature,
+        CryptoError::InvalidP256PublicKey,
+        CryptoError::SignatureVerificationFailed,
+        CryptoError::InvalidSignatureFormat,
+        CryptoError::ReplayAttack,
+        CryptoError::InvalidNonce,
+        CryptoError::ChallengeMismatch,
+        CryptoError::InvalidCredentialId,
+        CryptoError::InvalidAuthenticatorData,
+        CryptoError::RevokedCredential,
+        CryptoError::IdempotencyKeyReused,
+        CryptoError::RpIdMismatch,
+        CryptoError::OriginMismatch,
+        CryptoError::UserNotPresent,
+        CryptoError::UserNotVerified,
+        CryptoError::SignCountNotIncreased,
+        CryptoError::HighSSignature,
+        CryptoError::CeremonyTypeMismatch,
+    ];
+
+    #[test]
+    fn test_error_codes_are_unique_and_in_range() {
+        let crypto: Vec<u32> = CRYPTO_ERRORS.iter().map(|e| e.clone() as u32).collect();
+        let recovery: Vec<u32> = RecoveryError::ALL.iter().map(|e| e.code()).collect();
+        let policy: Vec<u32> = PolicyError::ALL.iter().map(|e| e.code()).collect();
+
+        assert!(crypto.iter().all(|code| *code < *RECOVERY_ERROR_CODES.start()));
+        assert!(recovery.iter().all(|code| RECOVERY_ERROR_CODES.contains(code)));
+        assert!(policy.iter().all(|code| POLICY_ERROR_CODES.contains(code)));
+        assert!(*POLICY_ERROR_CODES.end() < ANCHOR_ERROR_CODE_OFFSET);
+
+        let mut all: Vec<u32> = crypto.into_iter().chain(recovery).chain(policy).collect();
+        let count = all.len();
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), count, "two errors share a code");
+    }
+
+    #[test]
+    fn test_codes_are_pinned() {
+        // Clients match on these; a change here breaks them
+        assert_eq!(RecoveryError::MaxPasskeysReached.code(), 1000);
+        assert_eq!(RecoveryError::InvalidBackupFormat.code(), 1012);
+        assert_eq!(PolicyError::MalformedConfig.code(), 1100);
+        assert_eq!(PolicyError::OverMintLimit.code(), 1107);
+        for (i, e) in RecoveryError::ALL.iter().enumerate() {
+            assert_eq!(e.code(), 1000 + i as u32, "{:?}", e);
+        }
+        for (i, e) in PolicyError::ALL.iter().enumerate() {
+            assert_eq!(e.code(), 1100 + i as u32, "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn test_each_variant_is_reported() {
+        use crate::{EncryptedBackup, MultiPasskey, PasskeyEntry, MAX_ESCROW_BACKUP_SIZE};
+        use attesta_types::consts::{MAX_CREDENTIAL_ID_LEN, MAX_PASSKEY_NAME_LEN};
+
+        let key = [1u8; 64];
+        let name = || "Laptop".to_string();
+        let mut seen = Vec::new();
+
+        let mut small = MultiPasskey::new(key, b"phone".to_vec(), "Phone".to_string(), 0, 1, 1);
+        seen.push(small.add_passkey(key, b"laptop".to_vec(), name(), 0).unwrap_err());
+
+        let mut multi = MultiPasskey::new(key, b"phone".to_vec(), "Phone".to_string(), 0, 1, u8::MAX);
+        for seed in 1..8u8 {
+            multi.add_passkey(key, vec![seed], name(), 0).unwrap();
+        }
+        seen.push(multi.add_passkey(key, b"ninth".to_vec(), name(), 0).unwrap_err());
+
+        let mut multi = MultiPasskey::new(key, b"phone".to_vec(), "Phone".to_string(), 0, 1, 5);
+        seen.push(multi.add_passkey(key, vec![0; MAX_CREDENTIAL_ID_LEN + 1], name(), 0).unwrap_err());
+        seen.push(multi.add_passkey(key, b"laptop".to_vec(), "x".repeat(MAX_PASSKEY_NAME_LEN + 1), 0).unwrap_err());
+        seen.push(multi.add_passkey(key, b"phone".to_vec(), name(), 0).unwrap_err());
+        multi.add_passkey(key, b"laptop".to_vec(), name(), 0).unwrap();
+        multi.remove_passkey(b"laptop", 10).unwrap();
+        seen.push(multi.add_passkey(key, b"laptop".to_vec(), name(), 0).unwrap_err());
+        seen.push(multi.remove_passkey(b"phone", 10).unwrap_err());
+        seen.push(multi.authorize_signer(b"tablet").unwrap_err());
+        multi.add_entry(PasskeyEntry { enabled: false, ..PasskeyEntry::new(key, b"tablet".to_vec(), name(), 0) }).unwrap();
+        seen.push(multi.authorize_signer(b"tablet").unwrap_err());
+
+        let backup = EncryptedBackup::new(b"key", b"not borsh", 0);
+        seen.push(backup.open(b"wrong key").unwrap_err());
+        seen.push(backup.open(b"key").unwrap_err());
+        seen.push(EncryptedBackup::from_escrow_bytes(&[0; MAX_ESCROW_BACKUP_SIZE + 1]).unwrap_err());
+        seen.push(EncryptedBackup::from_escrow_bytes(&[1, 2, 3]).unwrap_err());
+        seen.push(backup.clone().with_generation(u16::MAX).rekey(b"key", b"new key", 0).unwrap_err());
+        seen.push(multi.add_passkey(key, vec![], name(), 0).unwrap_err());
+        let strict = |backup: EncryptedBackup| EncryptedBackup::from_bytes_strict(&backup.to_bytes().unwrap()).unwrap_err();
+        seen.push(strict(EncryptedBackup::new(b"key", &[], 0)));
+        seen.push(strict(EncryptedBackup { key_hash: [0; 32], ..backup.clone() }));
+        seen.push(strict(EncryptedBackup { nonce: Default::default(), ..backup.clone() }));
+        seen.push(strict(EncryptedBackup { version: 0, ..backup.clone() }));
+
+        assert_eq!(seen, RecoveryError::ALL);
+    }
+
+    #[test]
+    fn test_converts_to_program_error() {
+        assert_eq!(ProgramError::from(RecoveryError::PasskeyNotFound), ProgramError::Custom(1007));
+        assert_eq!(ProgramError::from(PolicyError::TimeLocked), ProgramError::Custom(1103));
+    }
+}
@@ -10,6 +10,7 @@
 //! - **Social recovery**: Recover your account using other passkeys
 //! - **Policy management**: Set spending limits, time locks, and more
 //! - **Encrypted backups**: Securely backup account information for recovery
+//! - **Policy templates**: Ready-made policies for common setups (with the `float` feature)
 //!
 //! # Policy Types
 //!
@@ -18,23 +19,46 @@
 //! - `DailyLimit`: Maximum amount per day
 //! - `TimeLocked`: Transactions only allowed after a certain time
 //! - `MultiSig`: Requires multiple passkeys to sign
+//! - `DestinationAllowlist`: Transfers only to listed addresses
+//! - `Composite`: Several of the above at once
+//! - `CredentialBinding`: Particular passkeys for particular destinations
 //!
 //! # Example
 //!
 //! ```ignore
-//! use recovery::{Policy, MultiPasskey};
+//! use recovery::{Amount, PolicyBuilder, MultiPasskey};
 //!
 //! // Create a spending limit policy
-//! let policy = Policy::spending_limit(1_000_000_000); // 1 SOL max
+//! let policy = PolicyBuilder::new()
+//!     .spending_limit(Amount::from_lamports(1_000_000_000)) // 1 SOL max
+//!     .build()?;
 //!
 //! // Set up multi-passkey recovery
 //! let multi_passkey = MultiPasskey::new(/* ... */);
 //! ```
 
+// On-chain code must not panic on attacker-controlled input
+#![cfg_attr(not(test), deny(clippy::indexing_slicing, clippy::unwrap_used, clippy::expect_used))]
+
+pub mod amount;
 pub mod encrypted_backup;
+pub mod errors;
 pub mod multi_passkey;
 pub mod policies;
+#[cfg(feature = "float")]
+pub mod templates;
 
-pub use encrypted_backup::EncryptedBackup;
-pub use multi_passkey::{MultiPasskey, PasskeyEntry};
-pub use policies::{Policy, PolicyType};
+pub use amount::{Amount, AmountError, LAMPORTS_PER_SOL};
+pub use encrypted_backup::{
+    BackupContents, EncryptedBackup, BACKUP_DELETE_ACTION, BACKUP_VERSION, BACKUP_VERSION_REKEYED, BACKUP_WRITE_ACTION,
+    MAX_ESCROW_BACKUP_SIZE,
+};
+pub use errors::{RecoveryError, RECOVERY_ERROR_CODES};
+pub use multi_passkey::{credential_id_hash, CredentialIdStorage, MultiPasskey, MultiPasskeyError, PasskeyEntry, RevokedEntry};
+pub use policies::{
+    CredentialBinding, CredentialBindings, DailyLimitConfig, DestinationLimit, DestinationLimits, DestinationSpend,
+    DestinationSpends, ExplanationKind, ExplanationParam, LimitSpend, MintLimit, MintLimits, ParamValue, Policy, PolicyBuildError,
+    PolicyBuilder, PolicyContext, PolicyError, PolicyExplanation, PolicyType, MAX_TRACKED_DESTINATIONS, POLICY_ERROR_CODES,
+};
+#[cfg(feature = "float")]
+pub use templates::{Template, TemplateError, TemplateKind};
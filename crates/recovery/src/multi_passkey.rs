@@ -1,49 +1,106 @@
+use attesta_types::consts::{AAGUID_LEN, BORSH_LEN_PREFIX, HASH_LEN, MAX_CREDENTIAL_ID_LEN, MAX_PASSKEY_NAME_LEN, P256_PUBKEY_LEN};
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::pubkey::Pubkey;
+use core_crypto::validate_p256_public_key;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use crate::errors::RecoveryError;
 
-/// Represents a single passkey entry in a multi-passkey setup
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct PasskeyEntry {
-    /// The P-256 public key from the passkey (64 bytes uncompressed)
-    pub public_key: [u8; 64],
-    
-    /// The credential ID from WebAuthn
-    pub credential_id: Vec<u8>,
-    
-    /// A human-readable name/description for this passkey
-    pub name: Vec<u8>, // UTF-8 encoded string
-    
-    /// Whether this passkey is enabled
-    pub enabled: bool,
-    
-    /// Timestamp when this passkey was added
-    pub added_at: i64,
+pub use attesta_types::passkey::{CredentialIdStorage, PasskeyEntry};
+
+/// Current serialization version of `MultiPasskey`
+///
+/// - Version 1: primary, additional, recovery_threshold, max_passkeys
+/// - Version 2: adds a trailing version byte and the `revoked` tombstone list
+/// - Version 3: adds each passkey's AAGUID, primary first, after `revoked`
+/// - Version 4: adds each passkey's `CredentialIdStorage` tag after the AAGUIDs
+pub const MULTI_PASSKEY_VERSION: u8 = 4;
+
+/// Action name a passkey signs to register another passkey
+pub const PASSKEY_ADD_ACTION: &[u8] = b"add_passkey";
+
+/// Action name a passkey signs to remove (revoke) a passkey
+pub const PASSKEY_REMOVE_ACTION: &[u8] = b"remove_passkey";
+
+/// Passkey limit used when an account's registry is created implicitly
+pub const DEFAULT_MAX_PASSKEYS: u8 = 5;
+
+/// Most passkeys (the primary included) any registry can hold
+///
+/// A hard limit on top of each registry's own `max_passkeys`, so an
+/// account's size stays bounded whatever it was configured with.
+pub const MAX_PASSKEYS: u8 = 8;
+
+/// Registry bytes one passkey can take: its largest entry, its AAGUID slot
+/// and its storage tag
+///
+/// Adding a passkey grows the account by at most this much.
+pub const PASSKEY_SLOT_SPACE: usize = PasskeyEntry::MAX_SERIALIZED_SIZE + 1 + AAGUID_LEN + 1;
+
+/// Maximum number of tombstones kept for removed passkeys
+///
+/// When the list is full, removing another passkey evicts the oldest tombstone.
+pub const MAX_REVOKED_ENTRIES: usize = 16;
+
+/// Hashes a credential ID so it can be stored and compared in fixed size
+pub fn credential_id_hash(credential_id: &[u8]) -> [u8; 32] {
+    Sha256::digest(credential_id).into()
 }
 
-impl PasskeyEntry {
-    pub fn new(
-        public_key: [u8; 64],
-        credential_id: Vec<u8>,
-        name: String,
-        added_at: i64,
-    ) -> Self {
-        Self {
-            public_key,
-            credential_id,
-            name: name.into_bytes(),
-            enabled: true,
-            added_at,
-        }
-    }
+/// Reasons a `MultiPasskey` can be rejected
+///
+/// Each broken invariant has its own variant so a corrupted registry can be
+/// told apart from one that was written with bad values.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MultiPasskeyError {
+    #[error("Malformed MultiPasskey data: {0}")]
+    Serialization(String),
+
+    #[error("max_passkeys must be at least 1")]
+    InvalidMaxPasskeys,
+
+    #[error("Recovery threshold {threshold} must be between 1 and max_passkeys ({max_passkeys})")]
+    InvalidThreshold { threshold: u8, max_passkeys: u8 },
+
+    #[error("{count} passkeys stored, but max_passkeys is {max_passkeys}")]
+    TooManyPasskeys { count: usize, max_passkeys: u8 },
+
+    #[error("{count} passkeys stored, but no account holds more than {MAX_PASSKEYS}")]
+    AboveProtocolLimit { count: usize },
 
-    pub fn name_str(&self) -> Result<String, std::string::FromUtf8Error> {
-        String::from_utf8(self.name.clone())
+    #[error("Credential ID is registered more than once")]
+    DuplicateCredentialId,
+
+    #[error("Passkey {index} has an invalid P-256 public key")]
+    InvalidPublicKey { index: usize },
+
+    #[error("Passkey {index} has an empty credential ID")]
+    EmptyCredentialId { index: usize },
+}
+
+impl From<std::io::Error> for MultiPasskeyError {
+    fn from(e: std::io::Error) -> Self {
+        MultiPasskeyError::Serialization(e.to_string())
     }
 }
 
+/// A tombstone left behind when a passkey is removed
+///
+/// Removed passkeys aren't simply erased: anything they signed before
+/// removal (like a pending recovery approval) must stop counting. Keeping
+/// the hash of the credential ID lets every approval and execution path
+/// reject it, without storing the full credential ID.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct RevokedEntry {
+    /// SHA-256 of the removed credential ID
+    pub credential_id_hash: [u8; 32],
+
+    /// When the passkey was removed (Unix timestamp)
+    pub revoked_at: i64,
+}
+
 /// Manages multiple passkeys for an account
 /// Enables social recovery and multi-device access
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct MultiPasskey {
     /// The primary passkey (main authentication method)
     pub primary: PasskeyEntry,
@@ -56,12 +113,104 @@ pub struct MultiPasskey {
     
     /// Maximum number of passkeys allowed
     pub max_passkeys: u8,
+
+    /// Serialization version (always `MULTI_PASSKEY_VERSION` once loaded)
+    pub version: u8,
+
+    /// Tombstones for removed passkeys, oldest first
+    pub revoked: Vec<RevokedEntry>,
+}
+
+impl BorshSerialize for MultiPasskey {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.primary.serialize(writer)?;
+        self.additional.serialize(writer)?;
+        self.recovery_threshold.serialize(writer)?;
+        self.max_passkeys.serialize(writer)?;
+        self.version.serialize(writer)?;
+        self.revoked.serialize(writer)?;
+        self.entries().map(|entry| entry.aaguid).collect::<Vec<_>>().serialize(writer)?;
+        self.entries().map(|entry| entry.id_storage as u8).collect::<Vec<u8>>().serialize(writer)
+    }
+}
+
+impl BorshDeserialize for MultiPasskey {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut primary = PasskeyEntry::deserialize_reader(reader)?;
+        let mut additional = Vec::<PasskeyEntry>::deserialize_reader(reader)?;
+        let recovery_threshold = u8::deserialize_reader(reader)?;
+        let max_passkeys = u8::deserialize_reader(reader)?;
+
+        // Version 1 data ends here. Later versions append a version byte
+        // followed by the tombstone list, from version 3 the AAGUIDs, and
+        // from version 4 the credential ID storage tags.
+        let mut version_byte = [0u8; 1];
+        let (revoked, aaguids, id_storage) = match reader.read(&mut version_byte)? {
+            0 => (Vec::new(), Vec::new(), Vec::new()),
+            _ if version_byte[0] > MULTI_PASSKEY_VERSION => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Unsupported MultiPasskey version",
+                ));
+            }
+            _ => {
+                let revoked = Vec::<RevokedEntry>::deserialize_reader(reader)?;
+                let aaguids = if version_byte[0] >= 3 {
+                    Vec::<Option<[u8; AAGUID_LEN]>>::deserialize_reader(reader)?
+                } else {
+                    Vec::new()
+                };
+                let id_storage = if version_byte[0] >= 4 {
+                    Vec::<u8>::deserialize_reader(reader)?
+                } else {
+                    Vec::new()
+                };
+                (revoked, aaguids, id_storage)
+            }
+        };
+
+        if !aaguids.is_empty() {
+            if aaguids.len() != additional.len() + 1 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "One AAGUID slot per passkey expected",
+                ));
+            }
+            for (entry, aaguid) in std::iter::once(&mut primary).chain(additional.iter_mut()).zip(aaguids) {
+                entry.aaguid = aaguid;
+            }
+        }
+
+        if !id_storage.is_empty() {
+            if id_storage.len() != additional.len() + 1 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "One storage tag per passkey expected",
+                ));
+            }
+            for (entry, tag) in std::iter::once(&mut primary).chain(additional.iter_mut()).zip(id_storage) {
+                entry.id_storage = CredentialIdStorage::from_u8(tag).ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown credential ID storage")
+                })?;
+            }
+        }
+
+        Ok(Self {
+            primary,
+            additional,
+            recovery_threshold,
+            max_passkeys,
+            // Older layouts are upgraded in memory and written back in the current format
+            version: MULTI_PASSKEY_VERSION,
+            revoked,
+        })
+    }
 }
 
 impl MultiPasskey {
     /// Creates a new MultiPasskey setup with a single primary passkey
     pub fn new(
-        primary_public_key: [u8; 64],
+        primary_public_key: [u8; P256_PUBKEY_LEN],
         primary_credential_id: Vec<u8>,
         primary_name: String,
         created_at: i64,
@@ -78,58 +227,196 @@ impl MultiPasskey {
         Self {
             primary,
             additional: Vec::new(),
-            recovery_threshold: recovery_threshold.max(1).min(max_passkeys),
+            recovery_threshold: recovery_threshold.max(1).min(max_passkeys.max(1)),
             max_passkeys: max_passkeys.max(1),
+            version: MULTI_PASSKEY_VERSION,
+            revoked: Vec::new(),
         }
     }
 
     /// Adds an additional passkey
     pub fn add_passkey(
         &mut self,
-        public_key: [u8; 64],
+        public_key: [u8; P256_PUBKEY_LEN],
         credential_id: Vec<u8>,
         name: String,
         added_at: i64,
-    ) -> Result<(), &'static str> {
-        // Check if we've reached the maximum
-        if (self.additional.len() as u8 + 1) >= self.max_passkeys {
-            return Err("Maximum number of passkeys reached");
+    ) -> Result<(), RecoveryError> {
+        self.add_entry(PasskeyEntry::new(public_key, credential_id, name, added_at))
+    }
+
+    /// Adds an additional passkey from a full entry (keeping its AAGUID)
+    ///
+    /// Fails once the registry holds `max_passkeys` or `MAX_PASSKEYS`
+    /// passkeys, whichever is lower, and for entries larger than
+    /// `PasskeyEntry::MAX_SERIALIZED_SIZE`.
+    pub fn add_entry(&mut self, entry: PasskeyEntry) -> Result<(), RecoveryError> {
+        // Check if we've reached the maximum (counted in usize: a registry
+        // built without `validate` can hold more entries than a u8 counts)
+        if self.additional.len() + 1 >= self.max_passkeys as usize {
+            return Err(RecoveryError::MaxPasskeysReached);
+        }
+        if self.is_full() {
+            return Err(RecoveryError::RegistryFull);
+        }
+
+        if entry.credential_id.is_empty() {
+            return Err(RecoveryError::EmptyCredentialId);
+        }
+        if entry.credential_id.len() > MAX_CREDENTIAL_ID_LEN {
+            return Err(RecoveryError::CredentialIdTooLong);
+        }
+        if entry.name.len() > MAX_PASSKEY_NAME_LEN {
+            return Err(RecoveryError::PasskeyNameTooLong);
         }
 
         // Check if this credential ID already exists
-        if self.additional.iter().any(|p| p.credential_id == credential_id) {
-            return Err("Credential ID already exists");
+        if self.holds_credential(&entry) {
+            return Err(RecoveryError::DuplicateCredentialId);
+        }
+
+        // A revoked credential stays revoked until its tombstone is purged,
+        // otherwise old approvals it signed would start counting again
+        if self.is_revoked_hash(&entry.credential_id_hash()) {
+            return Err(RecoveryError::CredentialRevoked);
         }
 
-        let entry = PasskeyEntry::new(public_key, credential_id, name, added_at);
         self.additional.push(entry);
 
         Ok(())
     }
 
-    /// Removes a passkey by credential ID
-    pub fn remove_passkey(&mut self, credential_id: &[u8]) -> Result<(), &'static str> {
+    /// Whether the registry holds `MAX_PASSKEYS` passkeys already
+    pub fn is_full(&self) -> bool {
+        self.additional.len() + 1 >= usize::from(MAX_PASSKEYS)
+    }
+
+    /// Removes a passkey by credential ID, leaving a tombstone behind
+    ///
+    /// The entry is moved into the `revoked` list so that approvals it made
+    /// before removal are rejected too. If the list is full, the oldest
+    /// tombstone is evicted to keep the account size bounded.
+    pub fn remove_passkey(&mut self, credential_id: &[u8], revoked_at: i64) -> Result<(), RecoveryError> {
         // Can't remove the primary passkey
-        if self.primary.credential_id == credential_id {
-            return Err("Cannot remove primary passkey");
+        if self.primary.matches(credential_id) {
+            return Err(RecoveryError::CannotRemovePrimary);
         }
 
-        let initial_len = self.additional.len();
-        self.additional.retain(|p| p.credential_id != credential_id);
+        let position = self.additional
+            .iter()
+            .position(|p| p.matches(credential_id))
+            .ok_or(RecoveryError::PasskeyNotFound)?;
+        let removed = self.additional.remove(position);
+        self.push_tombstone(removed.credential_id_hash(), revoked_at);
 
-        if self.additional.len() == initial_len {
-            Err("Passkey not found")
-        } else {
-            Ok(())
+        Ok(())
+    }
+
+    /// Replaces the primary passkey, revoking the old one
+    ///
+    /// This is how a completed recovery takes effect: the lost primary is
+    /// tombstoned like a removed passkey, so nothing it signs counts anymore.
+    pub fn replace_primary(&mut self, entry: PasskeyEntry, revoked_at: i64) -> Result<(), RecoveryError> {
+        if self.holds_credential(&entry) {
+            return Err(RecoveryError::DuplicateCredentialId);
+        }
+        if self.is_revoked_hash(&entry.credential_id_hash()) {
+            return Err(RecoveryError::CredentialRevoked);
         }
+
+        let old = std::mem::replace(&mut self.primary, entry);
+        self.push_tombstone(old.credential_id_hash(), revoked_at);
+
+        Ok(())
+    }
+
+    /// Records a tombstone, evicting the oldest one if the list is full
+    fn push_tombstone(&mut self, credential_id_hash: [u8; HASH_LEN], revoked_at: i64) {
+        if self.revoked.len() >= MAX_REVOKED_ENTRIES {
+            self.revoked.remove(0);
+        }
+        self.revoked.push(RevokedEntry { credential_id_hash, revoked_at });
+    }
+
+    /// Whether an entry for the same credential is already registered,
+    /// however either of them stores its ID
+    fn holds_credential(&self, entry: &PasskeyEntry) -> bool {
+        let hash = entry.credential_id_hash();
+        self.entries().any(|p| p.credential_id_hash() == hash)
+    }
+
+    /// Checks whether a credential ID belongs to a removed passkey
+    pub fn is_revoked(&self, credential_id: &[u8]) -> bool {
+        self.is_revoked_hash(&credential_id_hash(credential_id))
+    }
+
+    fn is_revoked_hash(&self, hash: &[u8; HASH_LEN]) -> bool {
+        self.revoked.iter().any(|r| r.credential_id_hash == *hash)
+    }
+
+    /// Drops tombstones for passkeys removed before `older_than`
+    ///
+    /// # Returns
+    /// The number of tombstones removed
+    pub fn purge_revoked(&mut self, older_than: i64) -> usize {
+        let initial_len = self.revoked.len();
+        self.revoked.retain(|r| r.revoked_at >= older_than);
+        initial_len - self.revoked.len()
+    }
+
+    /// Looks up the passkey allowed to sign for this account
+    ///
+    /// This is the check every execution path goes through: the credential
+    /// must be registered, enabled, and not revoked.
+    pub fn authorize_signer(&self, credential_id: &[u8]) -> Result<&PasskeyEntry, RecoveryError> {
+        if self.is_revoked(credential_id) {
+            return Err(RecoveryError::CredentialRevoked);
+        }
+
+        match self.find_passkey(credential_id) {
+            Some(entry) if entry.enabled => Ok(entry),
+            Some(_) => Err(RecoveryError::PasskeyDisabled),
+            None => Err(RecoveryError::PasskeyNotFound),
+        }
+    }
+
+    /// Counts the approvals that still count towards the recovery threshold
+    ///
+    /// Approvals are recorded as credential ID hashes. Hashes from revoked,
+    /// disabled, or unknown passkeys are ignored, as are duplicates - so an
+    /// approval recorded before its passkey was removed no longer counts.
+    pub fn count_valid_approvals(&self, approvals: &[[u8; 32]]) -> usize {
+        let mut counted: Vec<[u8; 32]> = Vec::with_capacity(approvals.len());
+
+        for approval in approvals {
+            if counted.contains(approval) {
+                continue;
+            }
+            if self.revoked.iter().any(|r| r.credential_id_hash == *approval) {
+                continue;
+            }
+            let is_enabled_passkey = self.enabled_passkeys()
+                .iter()
+                .any(|p| p.credential_id_hash() == *approval);
+            if is_enabled_passkey {
+                counted.push(*approval);
+            }
+        }
+
+        counted.len()
     }
 
     /// Finds a passkey by credential ID
+    ///
+    /// `credential_id` is the full ID; entries that store only its hash are
+    /// matched by hashing it.
     pub fn find_passkey(&self, credential_id: &[u8]) -> Option<&PasskeyEntry> {
-        if self.primary.credential_id == credential_id {
-            return Some(&self.primary);
-        }
-        self.additional.iter().find(|p| p.credential_id == credential_id)
+        self.entries().find(|p| p.matches(credential_id))
+    }
+
+    /// The primary passkey, then the additional ones
+    pub fn entries(&self) -> impl Iterator<Item = &PasskeyEntry> {
+        std::iter::once(&self.primary).chain(self.additional.iter())
     }
 
     /// Gets all enabled passkeys
@@ -150,13 +437,618 @@ impl MultiPasskey {
         self.enabled_passkeys().len() >= self.recovery_threshold as usize
     }
 
+    /// Checks the invariants every stored registry must hold
+    ///
+    /// `new` and `add_passkey` keep these true, but data read from an account
+    /// could have been written by anything. Checked in order:
+    /// - `max_passkeys` is at least 1
+    /// - `1 <= recovery_threshold <= max_passkeys`
+    /// - the primary plus additional passkeys don't exceed `max_passkeys`,
+    ///   nor `MAX_PASSKEYS`
+    /// - no credential ID appears twice (comparing hashes, so a hashed and a
+    ///   full copy of the same ID count as the same credential)
+    /// - every public key is a valid P-256 point
+    pub fn validate(&self) -> Result<(), MultiPasskeyError> {
+        if self.max_passkeys == 0 {
+            return Err(MultiPasskeyError::InvalidMaxPasskeys);
+        }
+
+        if self.recovery_threshold == 0 || self.recovery_threshold > self.max_passkeys {
+            return Err(MultiPasskeyError::InvalidThreshold {
+                threshold: self.recovery_threshold,
+                max_passkeys: self.max_passkeys,
+            });
+        }
+
+        let count = self.additional.len() + 1;
+        if count > self.max_passkeys as usize {
+            return Err(MultiPasskeyError::TooManyPasskeys {
+                count,
+                max_passkeys: self.max_passkeys,
+            });
+        }
+        if count > usize::from(MAX_PASSKEYS) {
+            return Err(MultiPasskeyError::AboveProtocolLimit { count });
+        }
+
+        let entries: Vec<&PasskeyEntry> = self.entries().collect();
+
+        for (index, entry) in entries.iter().enumerate() {
+            if entries.iter().take(index).any(|p| p.credential_id_hash() == entry.credential_id_hash()) {
+                return Err(MultiPasskeyError::DuplicateCredentialId);
+            }
+            validate_p256_public_key(&entry.public_key)
+                .map_err(|_| MultiPasskeyError::InvalidPublicKey { index })?;
+        }
+
+        Ok(())
+    }
+
+    /// `validate`, also rejecting entries with an empty credential ID
+    ///
+    /// An all-zero public key already fails `validate` (it isn't a P-256
+    /// point), but an empty credential ID decodes fine and only fails once
+    /// something looks it up. `add_entry` never writes one.
+    pub fn validate_strict(&self) -> Result<(), MultiPasskeyError> {
+        self.validate()?;
+        if let Some(index) = self.entries().position(|entry| entry.credential_id.is_empty()) {
+            return Err(MultiPasskeyError::EmptyCredentialId { index });
+        }
+        Ok(())
+    }
+
+    /// Length of `to_bytes()`, without serializing
+    pub fn serialized_size(&self) -> usize {
+        // credential_id_hash + revoked_at (8) per tombstone
+        const REVOKED_ENTRY_SIZE: usize = HASH_LEN + 8;
+
+        self.primary.serialized_size()
+            + BORSH_LEN_PREFIX + self.additional.iter().map(PasskeyEntry::serialized_size).sum::<usize>()
+            // recovery_threshold + max_passkeys + version
+            + 3
+            + BORSH_LEN_PREFIX + self.revoked.len() * REVOKED_ENTRY_SIZE
+            + BORSH_LEN_PREFIX + self.entries().map(|entry| 1 + entry.aaguid.map_or(0, |_| AAGUID_LEN)).sum::<usize>()
+            // one storage tag per passkey
+            + BORSH_LEN_PREFIX + self.additional.len() + 1
+    }
+
     /// Serializes to bytes
     pub fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
         borsh::to_vec(self)
     }
 
-    /// Deserializes from bytes
-    pub fn from_bytes(data: &[u8]) -> Result<Self, std::io::Error> {
-        borsh::from_slice(data)
+    /// Deserializes from bytes, rejecting registries that break an invariant
+    ///
+    /// See `validate` for the checks.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MultiPasskeyError> {
+        let multi: Self = borsh::from_slice(data)?;
+        multi.validate()?;
+        Ok(multi)
+    }
+
+    /// `from_bytes`, also rejecting registries that fail `validate_strict`
+    ///
+    /// How accounts read their registry; `from_bytes` stays lenient enough
+    /// for tooling to inspect a registry that was written by something else.
+    pub fn from_bytes_strict(data: &[u8]) -> Result<Self, MultiPasskeyError> {
+        let multi: Self = borsh::from_slice(data)?;
+        multi.validate_strict()?;
+        Ok(multi)
+    }
+}
+
+/// Same as `MultiPasskey::from_bytes`: the registry must pass `validate`
+impl TryFrom<&[u8]> for MultiPasskey {
+    type Error = MultiPasskeyError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use proptest::prelude::*;
+
+    fn key(seed: u8) -> [u8; 64] {
+        TestPasskey::new(seed).public_key()
+    }
+
+    fn setup() -> MultiPasskey {
+        let mut multi = MultiPasskey::new(key(1), b"primary".to_vec(), "Phone".to_string(), 100, 2, 5);
+        multi.add_passkey(key(2), b"laptop".to_vec(), "Laptop".to_string(), 110).unwrap();
+        multi.add_passkey(key(3), b"yubikey".to_vec(), "YubiKey".to_string(), 120).unwrap();
+        multi
+    }
+
+    #[test]
+    fn test_remove_passkey_leaves_tombstone() {
+        let mut multi = setup();
+        multi.remove_passkey(b"laptop", 200).unwrap();
+
+        assert!(multi.find_passkey(b"laptop").is_none());
+        assert!(multi.is_revoked(b"laptop"));
+        assert_eq!(multi.revoked.len(), 1);
+        assert_eq!(multi.revoked[0].credential_id_hash, credential_id_hash(b"laptop"));
+        assert_eq!(multi.revoked[0].revoked_at, 200);
+    }
+
+    #[test]
+    fn test_replace_primary_revokes_old_primary() {
+        let mut multi = setup();
+        let entry = PasskeyEntry::new(key(4), b"new-phone".to_vec(), "New phone".to_string(), 300);
+        multi.replace_primary(entry, 300).unwrap();
+
+        assert_eq!(multi.primary.credential_id, b"new-phone");
+        assert!(multi.is_revoked(b"primary"));
+        assert!(multi.authorize_signer(b"primary").is_err());
+        assert_eq!(multi.validate(), Ok(()));
+
+        // An existing or revoked credential can't become the primary
+        let laptop = PasskeyEntry::new(key(5), b"laptop".to_vec(), "Laptop".to_string(), 300);
+        assert_eq!(multi.replace_primary(laptop, 300), Err(RecoveryError::DuplicateCredentialId));
+        let old = PasskeyEntry::new(key(5), b"primary".to_vec(), "Phone".to_string(), 300);
+        assert_eq!(multi.replace_primary(old, 300), Err(RecoveryError::CredentialRevoked));
+    }
+
+    #[test]
+    fn test_revoked_signer_rejected() {
+        let mut multi = setup();
+        assert!(multi.authorize_signer(b"laptop").is_ok());
+
+        multi.remove_passkey(b"laptop", 200).unwrap();
+        assert_eq!(multi.authorize_signer(b"laptop").unwrap_err(), RecoveryError::CredentialRevoked);
+        assert_eq!(multi.authorize_signer(b"unknown").unwrap_err(), RecoveryError::PasskeyNotFound);
+    }
+
+    #[test]
+    fn test_revoked_credential_cannot_be_re_added_until_purged() {
+        let mut multi = setup();
+        multi.remove_passkey(b"laptop", 200).unwrap();
+
+        assert_eq!(
+            multi.add_passkey(key(2), b"laptop".to_vec(), "Laptop".to_string(), 210),
+            Err(RecoveryError::CredentialRevoked)
+        );
+
+        assert_eq!(multi.purge_revoked(201), 1);
+        assert!(multi.add_passkey(key(2), b"laptop".to_vec(), "Laptop".to_string(), 210).is_ok());
+    }
+
+    #[test]
+    fn test_purge_revoked_keeps_recent_tombstones() {
+        let mut multi = setup();
+        multi.remove_passkey(b"laptop", 200).unwrap();
+        multi.remove_passkey(b"yubikey", 300).unwrap();
+
+        assert_eq!(multi.purge_revoked(250), 1);
+        assert!(!multi.is_revoked(b"laptop"));
+        assert!(multi.is_revoked(b"yubikey"));
+    }
+
+    #[test]
+    fn test_revoked_list_is_bounded() {
+        let mut multi = MultiPasskey::new(key(1), b"primary".to_vec(), "Phone".to_string(), 100, 1, 2);
+
+        for i in 0..(MAX_REVOKED_ENTRIES + 3) {
+            let id = format!("device-{}", i).into_bytes();
+            multi.add_passkey(key(2), id.clone(), "Device".to_string(), i as i64).unwrap();
+            multi.remove_passkey(&id, i as i64).unwrap();
+        }
+
+        assert_eq!(multi.revoked.len(), MAX_REVOKED_ENTRIES);
+        // The oldest tombstones were evicted first
+        assert!(!multi.is_revoked(b"device-0"));
+        assert!(multi.is_revoked(format!("device-{}", MAX_REVOKED_ENTRIES + 2).as_bytes()));
+    }
+
+    #[test]
+    fn test_approve_then_revoke_then_finalize() {
+        let mut multi = setup();
+
+        // Two devices approve while both are still registered
+        let approvals = vec![credential_id_hash(b"primary"), credential_id_hash(b"laptop")];
+        assert_eq!(multi.count_valid_approvals(&approvals), 2);
+
+        // The laptop is removed before the approvals are counted
+        multi.remove_passkey(b"laptop", 200).unwrap();
+
+        // Its recorded approval no longer counts towards the threshold
+        assert_eq!(multi.count_valid_approvals(&approvals), 1);
+        assert!(multi.count_valid_approvals(&approvals) < multi.recovery_threshold as usize);
+    }
+
+    #[test]
+    fn test_duplicate_and_unknown_approvals_ignored() {
+        let multi = setup();
+        let approvals = vec![
+            credential_id_hash(b"laptop"),
+            credential_id_hash(b"laptop"),
+            credential_id_hash(b"stranger"),
+        ];
+        assert_eq!(multi.count_valid_approvals(&approvals), 1);
+    }
+
+    #[test]
+    fn test_serialize_round_trip_with_tombstones() {
+        let mut multi = setup();
+        multi.remove_passkey(b"laptop", 200).unwrap();
+
+        let bytes = multi.to_bytes().unwrap();
+        let restored = MultiPasskey::from_bytes(&bytes).unwrap();
+
+        assert_eq!(restored.version, MULTI_PASSKEY_VERSION);
+        assert_eq!(restored.revoked, multi.revoked);
+        assert_eq!(restored.additional.len(), 1);
+    }
+
+    #[test]
+    fn test_deserialize_version_1_layout() {
+        let multi = setup();
+
+        // Version 1 stopped after max_passkeys
+        let mut legacy = Vec::new();
+        multi.primary.serialize(&mut legacy).unwrap();
+        multi.additional.serialize(&mut legacy).unwrap();
+        legacy.push(multi.recovery_threshold);
+        legacy.push(multi.max_passkeys);
+
+        let restored = MultiPasskey::from_bytes(&legacy).unwrap();
+        assert_eq!(restored.version, MULTI_PASSKEY_VERSION);
+        assert!(restored.revoked.is_empty());
+        assert_eq!(restored.additional.len(), 2);
+
+        // Writing it back upgrades to the current layout
+        assert_eq!(restored.to_bytes().unwrap(), multi.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_version_2_layout() {
+        let mut multi = setup();
+        multi.remove_passkey(b"laptop", 200).unwrap();
+
+        // Version 2 stopped after the tombstones, with no AAGUIDs
+        let mut legacy = Vec::new();
+        multi.primary.serialize(&mut legacy).unwrap();
+        multi.additional.serialize(&mut legacy).unwrap();
+        legacy.extend_from_slice(&[multi.recovery_threshold, multi.max_passkeys, 2]);
+        multi.revoked.serialize(&mut legacy).unwrap();
+
+        let restored = MultiPasskey::from_bytes(&legacy).unwrap();
+        assert_eq!(restored.revoked, multi.revoked);
+        assert!(restored.entries().all(|entry| entry.aaguid.is_none()));
+        assert_eq!(restored.to_bytes().unwrap(), multi.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_aaguids_round_trip() {
+        let mut multi = setup();
+        multi.primary.aaguid = Some([1; 16]);
+        multi
+            .add_entry(PasskeyEntry::new(key(4), b"security-key".to_vec(), "Security key".to_string(), 300).with_aaguid(Some([2; 16])))
+            .unwrap();
+
+        let restored = MultiPasskey::from_bytes(&multi.to_bytes().unwrap()).unwrap();
+        let aaguids: Vec<_> = restored.entries().map(|entry| entry.aaguid).collect();
+        assert_eq!(aaguids, vec![Some([1; 16]), None, None, Some([2; 16])]);
+
+        // A list that leaves out a passkey is rejected: drop the last slot
+        // and fix up the length so only the count is wrong
+        let mut bytes = multi.to_bytes().unwrap();
+        let storage_tags = bytes.split_off(bytes.len() - (4 + 4));
+        bytes.truncate(bytes.len() - (1 + 16));
+        let length_offset = bytes.len() - (17 + 1 + 1) - 4;
+        bytes[length_offset] = 3;
+        bytes.extend_from_slice(&storage_tags);
+        assert!(MultiPasskey::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_hashed_entries_are_found_and_revoked_by_full_id() {
+        let long_id = vec![9u8; 220];
+        let mut multi = setup();
+        multi.add_entry(PasskeyEntry::new_hashed(key(4), &long_id, "Security key".to_string(), 300)).unwrap();
+
+        let restored = MultiPasskey::from_bytes(&multi.to_bytes().unwrap()).unwrap();
+        let storage: Vec<_> = restored.entries().map(|entry| entry.id_storage).collect();
+        assert_eq!(storage[3], CredentialIdStorage::Hashed);
+        assert!(storage[..3].iter().all(|tag| *tag == CredentialIdStorage::Full));
+        assert_eq!(restored.authorize_signer(&long_id).unwrap().public_key, key(4));
+        assert!(restored.find_passkey(&credential_id_hash(&long_id)).is_none());
+
+        // The same ID can't come back in full form
+        assert_eq!(
+            multi.add_passkey(key(5), long_id.clone(), "Again".to_string(), 310),
+            Err(RecoveryError::DuplicateCredentialId)
+        );
+        assert_eq!(multi.count_valid_approvals(&[credential_id_hash(&long_id)]), 1);
+
+        multi.remove_passkey(&long_id, 400).unwrap();
+        assert!(multi.is_revoked(&long_id));
+        assert_eq!(multi.authorize_signer(&long_id).unwrap_err(), RecoveryError::CredentialRevoked);
+    }
+
+    #[test]
+    fn test_deserialize_version_3_layout() {
+        let mut multi = setup();
+        multi.primary.aaguid = Some([1; 16]);
+
+        // Version 3 stopped after the AAGUIDs; every entry stored its full ID
+        let mut legacy = multi.to_bytes().unwrap();
+        legacy.truncate(legacy.len() - (4 + 3));
+        let version_offset = legacy.len() - (4 + 17 + 1 + 1) - 4 - 1;
+        legacy[version_offset] = 3;
+
+        let restored = MultiPasskey::from_bytes(&legacy).unwrap();
+        assert!(restored.entries().all(|entry| entry.id_storage == CredentialIdStorage::Full));
+        assert_eq!(restored.primary.aaguid, Some([1; 16]));
+        assert_eq!(restored.to_bytes().unwrap(), multi.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_rejects_unknown_storage_tag() {
+        let mut bytes = setup().to_bytes().unwrap();
+        *bytes.last_mut().unwrap() = 2;
+        assert!(MultiPasskey::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_rejects_future_version() {
+        let multi = setup();
+        let mut bytes = multi.to_bytes().unwrap();
+        // Empty revoked vec is a 4-byte length, then one `None` AAGUID and
+        // one storage tag per passkey
+        let version_offset = bytes.len() - (4 + 3) - (4 + 3) - 4 - 1;
+        assert_eq!(bytes[version_offset], MULTI_PASSKEY_VERSION);
+        bytes[version_offset] = MULTI_PASSKEY_VERSION + 1;
+
+        assert!(MultiPasskey::from_bytes(&bytes).is_err());
+    }
+
+    /// Serializes without going through `new`/`add_passkey`, like a hand-written account would
+    fn raw_bytes(multi: &MultiPasskey) -> Vec<u8> {
+        borsh::to_vec(multi).unwrap()
+    }
+
+    #[test]
+    fn test_valid_registry_passes_validation() {
+        assert_eq!(setup().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_zero_threshold() {
+        let mut multi = setup();
+        multi.recovery_threshold = 0;
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::InvalidThreshold { threshold: 0, max_passkeys: 5 }
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_threshold_above_max() {
+        let mut multi = setup();
+        multi.recovery_threshold = 6;
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::InvalidThreshold { threshold: 6, max_passkeys: 5 }
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_zero_max_passkeys() {
+        let mut multi = setup();
+        multi.max_passkeys = 0;
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::InvalidMaxPasskeys
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_too_many_passkeys() {
+        let mut multi = setup();
+        multi.max_passkeys = 2;
+        multi.recovery_threshold = 1;
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::TooManyPasskeys { count: 3, max_passkeys: 2 }
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_duplicate_credential_ids() {
+        let mut multi = setup();
+        multi.additional[1].credential_id = b"laptop".to_vec();
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::DuplicateCredentialId
+        );
+
+        // A copy of the primary counts too
+        let mut multi = setup();
+        multi.additional[0].credential_id = b"primary".to_vec();
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::DuplicateCredentialId
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_invalid_public_key() {
+        let mut multi = setup();
+        multi.additional[1].public_key = [1u8; 64];
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::InvalidPublicKey { index: 2 }
+        );
+        assert_eq!(
+            MultiPasskey::try_from(raw_bytes(&multi).as_slice()).unwrap_err(),
+            MultiPasskeyError::InvalidPublicKey { index: 2 }
+        );
+
+        let bytes = setup().to_bytes().unwrap();
+        assert_eq!(MultiPasskey::try_from(bytes.as_slice()).unwrap().to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_truncated_data() {
+        let bytes = setup().to_bytes().unwrap();
+        assert!(matches!(
+            MultiPasskey::from_bytes(&bytes[..10]),
+            Err(MultiPasskeyError::Serialization(_))
+        ));
+    }
+
+    #[test]
+    fn test_strict_from_bytes_rejects_empty_credential_ids() {
+        let mut multi = setup();
+        multi.additional[0].credential_id = Vec::new();
+        let bytes = raw_bytes(&multi);
+
+        assert_eq!(MultiPasskey::from_bytes(&bytes).unwrap().additional[0].credential_id, Vec::<u8>::new());
+        assert_eq!(MultiPasskey::from_bytes_strict(&bytes).unwrap_err(), MultiPasskeyError::EmptyCredentialId { index: 1 });
+        assert!(MultiPasskey::from_bytes_strict(&setup().to_bytes().unwrap()).is_ok());
+
+        // An all-zero key isn't a point, strict or not
+        let mut multi = setup();
+        multi.primary.public_key = [0; 64];
+        assert_eq!(MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(), MultiPasskeyError::InvalidPublicKey { index: 0 });
+
+        // Nor can one be added
+        assert_eq!(setup().add_passkey(key(4), Vec::new(), "Tablet".to_string(), 130), Err(RecoveryError::EmptyCredentialId));
+    }
+
+    #[test]
+    fn test_add_passkey_to_oversized_registry_does_not_overflow() {
+        // 255 additional entries used to overflow the u8 count
+        let mut multi = setup();
+        multi.max_passkeys = u8::MAX;
+        let entry = multi.additional[0].clone();
+        multi.additional = vec![entry; 255];
+
+        assert_eq!(
+            multi.add_passkey(key(4), b"tablet".to_vec(), "Tablet".to_string(), 130),
+            Err(RecoveryError::MaxPasskeysReached)
+        );
+    }
+
+    #[test]
+    fn test_protocol_limit_applies_whatever_max_passkeys_says() {
+        let mut multi = MultiPasskey::new(key(1), vec![1; MAX_CREDENTIAL_ID_LEN], "Phone".to_string(), 100, 1, u8::MAX);
+        for seed in 2..=MAX_PASSKEYS {
+            let size = multi.serialized_size();
+            let name = "k".repeat(MAX_PASSKEY_NAME_LEN);
+            multi.add_passkey(key(seed), vec![seed; MAX_CREDENTIAL_ID_LEN], name, 110).unwrap();
+            assert!(multi.serialized_size() - size <= PASSKEY_SLOT_SPACE);
+        }
+        assert_eq!(multi.entries().count(), usize::from(MAX_PASSKEYS));
+        assert!(multi.is_full());
+        assert_eq!(multi.validate(), Ok(()));
+
+        assert_eq!(
+            multi.add_passkey(key(20), b"one-more".to_vec(), "Tablet".to_string(), 130),
+            Err(RecoveryError::RegistryFull)
+        );
+
+        // Removing one makes room again
+        multi.remove_passkey(&[2; MAX_CREDENTIAL_ID_LEN], 140).unwrap();
+        multi.add_passkey(key(20), b"one-more".to_vec(), "Tablet".to_string(), 150).unwrap();
+
+        // A registry written with more doesn't load
+        let entry = multi.additional[0].clone();
+        multi.additional.push(PasskeyEntry { credential_id: b"ninth".to_vec(), ..entry });
+        assert_eq!(
+            MultiPasskey::from_bytes(&raw_bytes(&multi)).unwrap_err(),
+            MultiPasskeyError::AboveProtocolLimit { count: 9 }
+        );
+    }
+
+    #[test]
+    fn test_add_rejects_entries_larger_than_a_slot() {
+        let mut multi = setup();
+        assert_eq!(
+            multi.add_passkey(key(4), vec![4; MAX_CREDENTIAL_ID_LEN + 1], "Tablet".to_string(), 130),
+            Err(RecoveryError::CredentialIdTooLong)
+        );
+        assert_eq!(
+            multi.add_passkey(key(4), b"tablet".to_vec(), "t".repeat(MAX_PASSKEY_NAME_LEN + 1), 130),
+            Err(RecoveryError::PasskeyNameTooLong)
+        );
+        // Hashing keeps a long credential ID within the slot
+        multi.add_entry(PasskeyEntry::compact(key(4), vec![4; 1023], "Tablet".to_string(), 130)).unwrap();
+    }
+
+    #[test]
+    fn test_new_clamps_to_valid_registry() {
+        let multi = MultiPasskey::new(key(1), b"primary".to_vec(), "Phone".to_string(), 100, 0, 0);
+        assert_eq!(multi.max_passkeys, 1);
+        assert_eq!(multi.recovery_threshold, 1);
+        assert_eq!(multi.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_serialized_size_matches_to_bytes() {
+        let single = MultiPasskey::new(key(1), vec![], String::new(), 0, 1, 1);
+
+        let mut removed = setup();
+        removed.remove_passkey(b"laptop", 200).unwrap();
+
+        let mut full = MultiPasskey::new(key(1), vec![1; 1023], "x".repeat(255), 0, 1, u8::MAX);
+        for seed in 2..MAX_PASSKEYS {
+            full.add_passkey(key(seed), vec![seed; MAX_CREDENTIAL_ID_LEN], "y".repeat(MAX_PASSKEY_NAME_LEN), 0).unwrap();
+        }
+        full.add_entry(PasskeyEntry::new_hashed(key(11), &[11; 255], "z".repeat(MAX_PASSKEY_NAME_LEN), 0)).unwrap();
+        assert!(full.is_full());
+
+        for multi in [single, setup(), removed, full] {
+            assert_eq!(multi.serialized_size(), multi.to_bytes().unwrap().len());
+        }
+    }
+
+    fn passkey_entry() -> impl Strategy<Value = PasskeyEntry> {
+        (
+            prop::collection::vec(any::<u8>(), 0..128),
+            prop::collection::vec(any::<u8>(), 0..64),
+            any::<bool>(),
+            any::<i64>(),
+            any::<Option<[u8; 16]>>(),
+            any::<bool>(),
+        )
+            .prop_map(|(credential_id, name, enabled, added_at, aaguid, hashed)| PasskeyEntry {
+                public_key: [4; 64],
+                credential_id,
+                name,
+                enabled,
+                added_at,
+                aaguid,
+                id_storage: if hashed { CredentialIdStorage::Hashed } else { CredentialIdStorage::Full },
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_serialized_size_matches_to_bytes(
+            primary in passkey_entry(),
+            additional in prop::collection::vec(passkey_entry(), 0..8),
+            revoked in prop::collection::vec((any::<[u8; 32]>(), any::<i64>()), 0..8),
+            recovery_threshold in any::<u8>(),
+            max_passkeys in any::<u8>(),
+        ) {
+            let multi = MultiPasskey {
+                primary,
+                additional,
+                recovery_threshold,
+                max_passkeys,
+                version: MULTI_PASSKEY_VERSION,
+                revoked: revoked
+                    .into_iter()
+                    .map(|(credential_id_hash, revoked_at)| RevokedEntry { credential_id_hash, revoked_at })
+                    .collect(),
+            };
+            prop_assert_eq!(multi.serialized_size(), multi.to_bytes().unwrap().len());
+        }
     }
 }
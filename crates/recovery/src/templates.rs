@@ -0,0 +1,269 @@
+//! Ready-made policies for the setups most users want
+//!
+//! Each `template_*` function takes plain inputs (SOL as a decimal, days,
+//! signer lists), builds the policy with `PolicyBuilder` so it's validated
+//! like any other, and returns a `Template` holding the policy together with
+//! what it was made from. `Template::describe` turns that into a sentence a
+//! wallet can show before the user signs the policy change.
+//!
+//! SOL amounts are rounded to the nearest lamport; NaN, infinite and
+//! negative amounts are rejected.
+
+use std::fmt;
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use crate::amount::{Amount, AmountError};
+use crate::policies::{Policy, PolicyBuildError, PolicyBuilder, MIN_POLICY_TIMESTAMP, SECONDS_PER_DAY};
+
+/// Seconds in the week a `KidAllowance` resets after
+pub const SECONDS_PER_WEEK: u32 = 7 * SECONDS_PER_DAY;
+
+/// Why a template couldn't be built from its inputs
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("Invalid SOL amount: {0}")]
+    Amount(#[from] AmountError),
+
+    #[error("Invalid policy: {0}")]
+    Policy(#[from] PolicyBuildError),
+
+    #[error("Threshold must be between 1 and {signers} (the number of signers), got {threshold}")]
+    Threshold { threshold: u8, signers: usize },
+}
+
+/// Which template a policy came from, and its settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateKind {
+    /// Up to `max_per_day` a day, days starting at midnight UTC
+    DailySpender { max_per_day: Amount },
+
+    /// Nothing moves until `unlock_at` (Unix timestamp)
+    SavingsVault { unlock_at: i64 },
+
+    /// Spending shared by `signers`, up to `large_transaction` at a time
+    ///
+    /// `threshold` is how many of them should approve larger transfers. The
+    /// program doesn't collect approvals yet, so until it does anything above
+    /// `large_transaction` is denied outright.
+    SharedTreasury { signers: Vec<Pubkey>, threshold: u8, large_transaction: Amount },
+
+    /// Up to `per_week` a week, weeks starting at `week_start`
+    KidAllowance { per_week: Amount, week_start: i64 },
+}
+
+/// A policy built from a template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    /// The template and the settings it was built with
+    pub kind: TemplateKind,
+
+    /// The validated policy, ready to set on an account
+    pub policy: Policy,
+}
+
+impl Template {
+    /// A one-sentence summary of what the policy allows
+    pub fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            TemplateKind::DailySpender { max_per_day } => {
+                write!(f, "Daily spender: up to {} a day (days start at midnight UTC)", max_per_day)
+            }
+            TemplateKind::SavingsVault { unlock_at } => {
+                write!(f, "Savings vault: nothing can be sent until {} UTC", utc_date(*unlock_at))
+            }
+            TemplateKind::SharedTreasury { signers, threshold, large_transaction } => write!(
+                f,
+                "Shared treasury ({} of {} signers): up to {} per transaction; larger transfers are refused",
+                threshold,
+                signers.len(),
+                large_transaction,
+            ),
+            TemplateKind::KidAllowance { per_week, week_start } => write!(
+                f,
+                "Allowance: up to {} a week, each week starting {} UTC",
+                per_week,
+                utc_date(*week_start),
+            ),
+        }
+    }
+}
+
+/// Spend up to `max_sol_per_day` a day, days starting at midnight UTC
+pub fn template_daily_spender(max_sol_per_day: f64) -> Result<Template, TemplateError> {
+    let max_per_day = Amount::from_sol(max_sol_per_day)?;
+    // Any midnight works as the anchor; this one is the earliest policies accept
+    let policy = PolicyBuilder::new().daily_limit(max_per_day, MIN_POLICY_TIMESTAMP).build()?;
+    Ok(Template { kind: TemplateKind::DailySpender { max_per_day }, policy })
+}
+
+/// Lock the account for `lock_days` days from `now`
+pub fn template_savings_vault(lock_days: u32, now: i64) -> Result<Template, TemplateError> {
+    let unlock_at = now.saturating_add(i64::from(lock_days) * i64::from(SECONDS_PER_DAY));
+    let policy = PolicyBuilder::new().unlock_at(unlock_at).checked_at(now).build()?;
+    Ok(Template { kind: TemplateKind::SavingsVault { unlock_at }, policy })
+}
+
+/// Share the account between `signers`, capping each transfer at `large_tx_sol`
+///
+/// See `TemplateKind::SharedTreasury` for how `threshold` applies.
+pub fn template_shared_treasury(signers: &[Pubkey], threshold: u8, large_tx_sol: f64) -> Result<Template, TemplateError> {
+    if threshold == 0 || usize::from(threshold) > signers.len() {
+        return Err(TemplateError::Threshold { threshold, signers: signers.len() });
+    }
+    let large_transaction = Amount::from_sol(large_tx_sol)?;
+    let policy = PolicyBuilder::new()
+        .spending_limit(large_transaction)
+        .require_signers(signers)
+        .build()?;
+    Ok(Template {
+        kind: TemplateKind::SharedTreasury { signers: signers.to_vec(), threshold, large_transaction },
+        policy,
+    })
+}
+
+/// Spend up to `sol_per_week` a week, the first week starting `now`
+pub fn template_kid_allowance(sol_per_week: f64, now: i64) -> Result<Template, TemplateError> {
+    let per_week = Amount::from_sol(sol_per_week)?;
+    let policy = PolicyBuilder::new().windowed_limit(per_week, SECONDS_PER_WEEK, now).build()?;
+    Ok(Template { kind: TemplateKind::KidAllowance { per_week, week_start: now }, policy })
+}
+
+/// Formats a Unix timestamp as a UTC date, `YYYY-MM-DD`
+fn utc_date(timestamp: i64) -> String {
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let days = timestamp.div_euclid(i64::from(SECONDS_PER_DAY)) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attesta_types::time::TimeError;
+    use crate::policies::{LimitSpend, MAX_POLICY_SIGNERS};
+
+    const NOW: i64 = 1_767_225_600; // 2026-01-01T00:00:00Z
+    const SOL: u64 = 1_000_000_000;
+
+    /// Whether `amount` is allowed at `now` after `spent` earlier in the same window
+    fn allows_after(policy: &Policy, spent: u64, amount: u64, now: i64) -> bool {
+        let mut spend = LimitSpend::default();
+        policy.record_spend(spent, now, &mut spend);
+        policy.check_with_spend(amount, now, &spend).is_ok()
+    }
+
+    #[test]
+    fn test_daily_spender_resets_at_midnight_utc() {
+        let template = template_daily_spender(1.5).unwrap();
+        let limit = 1_500_000_000;
+        let late_evening = NOW + i64::from(SECONDS_PER_DAY) - 1;
+
+        assert!(allows_after(&template.policy, 0, limit, NOW));
+        assert!(!allows_after(&template.policy, 0, limit + 1, NOW));
+        assert!(!allows_after(&template.policy, limit, 1, late_evening));
+
+        // What was spent yesterday doesn't count after midnight
+        let mut spend = LimitSpend::default();
+        template.policy.record_spend(limit, late_evening, &mut spend);
+        assert!(template.policy.check_with_spend(limit, late_evening + 1, &spend).is_ok());
+
+        assert_eq!(template.describe(), "Daily spender: up to 1.50 SOL a day (days start at midnight UTC)");
+    }
+
+    #[test]
+    fn test_savings_vault_opens_after_lock_period() {
+        let template = template_savings_vault(30, NOW).unwrap();
+        let unlock_at = NOW + 30 * i64::from(SECONDS_PER_DAY);
+
+        assert_eq!(template.kind, TemplateKind::SavingsVault { unlock_at });
+        assert!(template.policy.check(1, unlock_at - 1).is_err());
+        assert!(template.policy.check(1, unlock_at).is_ok());
+        assert_eq!(template.describe(), "Savings vault: nothing can be sent until 2026-01-31 UTC");
+
+        assert!(matches!(
+            template_savings_vault(u32::MAX, NOW),
+            Err(TemplateError::Policy(PolicyBuildError::TimestampOutOfRange(_)))
+        ));
+        // More than ten years is almost certainly a typo
+        assert!(matches!(
+            template_savings_vault(20 * 365, NOW),
+            Err(TemplateError::Policy(PolicyBuildError::Time(TimeError::TooFarInFuture { .. })))
+        ));
+    }
+
+    #[test]
+    fn test_shared_treasury_caps_each_transfer() {
+        let signers: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let template = template_shared_treasury(&signers, 2, 10.0).unwrap();
+
+        assert!(template.policy.check(10 * SOL, NOW).is_ok());
+        assert!(template.policy.check(10 * SOL + 1, NOW).is_err());
+        assert_eq!(
+            template.describe(),
+            "Shared treasury (2 of 3 signers): up to 10.00 SOL per transaction; larger transfers are refused"
+        );
+
+        for threshold in [0, 4] {
+            assert_eq!(
+                template_shared_treasury(&signers, threshold, 10.0),
+                Err(TemplateError::Threshold { threshold, signers: 3 })
+            );
+        }
+        let too_many: Vec<Pubkey> = (0..=MAX_POLICY_SIGNERS).map(|_| Pubkey::new_unique()).collect();
+        assert_eq!(
+            template_shared_treasury(&too_many, 2, 10.0),
+            Err(TemplateError::Policy(PolicyBuildError::SignerCount(MAX_POLICY_SIGNERS + 1)))
+        );
+    }
+
+    #[test]
+    fn test_kid_allowance_resets_weekly() {
+        let template = template_kid_allowance(0.25, NOW).unwrap();
+        let allowance = SOL / 4;
+        let end_of_week = NOW + i64::from(SECONDS_PER_WEEK) - 1;
+
+        assert!(allows_after(&template.policy, allowance - 1, 1, end_of_week));
+        assert!(!allows_after(&template.policy, allowance, 1, end_of_week));
+
+        let mut spend = LimitSpend::default();
+        template.policy.record_spend(allowance, end_of_week, &mut spend);
+        assert!(template.policy.check_with_spend(allowance, end_of_week + 1, &spend).is_ok());
+
+        assert_eq!(template.describe(), "Allowance: up to 0.25 SOL a week, each week starting 2026-01-01 UTC");
+    }
+
+    #[test]
+    fn test_sol_inputs_are_checked_and_rounded() {
+        for bad in [f64::NAN, f64::INFINITY, -0.5] {
+            assert_eq!(template_daily_spender(bad), Err(TemplateError::Amount(AmountError::InvalidSol)));
+            assert_eq!(template_kid_allowance(bad, NOW), Err(TemplateError::Amount(AmountError::InvalidSol)));
+        }
+        assert_eq!(template_daily_spender(0.0), Err(TemplateError::Policy(PolicyBuildError::ZeroLimit)));
+
+        // Rounds to the nearest lamport, the same way every time
+        let template = template_daily_spender(0.1 + 0.2).unwrap();
+        assert_eq!(template.kind, TemplateKind::DailySpender { max_per_day: Amount::from_lamports(300_000_000) });
+        let template = template_kid_allowance(0.0000000014, NOW).unwrap();
+        assert_eq!(template.kind, TemplateKind::KidAllowance { per_week: Amount::from_lamports(1), week_start: NOW });
+    }
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(0), "1970-01-01");
+        assert_eq!(utc_date(951_782_400), "2000-02-29");
+        assert_eq!(utc_date(NOW - 1), "2025-12-31");
+    }
+}
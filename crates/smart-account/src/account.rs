@@ -1,5 +1,23 @@
+use attesta_types::consts::{AAGUID_LEN, ACCOUNT_DISCRIMINATOR_LEN, BORSH_LEN_PREFIX, HASH_LEN, MAX_INITIAL_POLICY_LEN, P256_PUBKEY_LEN, PUBKEY_LEN};
 use borsh::{BorshDeserialize, BorshSerialize};
+use thiserror::Error;
+use core_crypto::{parse_authenticator_data, RelyingParty, WebAuthnExpectations, WebAuthnSignature, WebAuthnVerificationProfile};
+use recovery::multi_passkey::{credential_id_hash, CredentialIdStorage, MultiPasskey, MultiPasskeyError, PasskeyEntry, DEFAULT_MAX_PASSKEYS};
+use recovery::policies::{PolicyBuildError, DESTINATION_SPEND_SIZE, MAX_POLICY_COMPUTE_UNITS, MAX_TRACKED_DESTINATIONS};
+use recovery::{DestinationSpends, Policy, PolicyType};
 use solana_program::pubkey::Pubkey;
+use crate::execute::{TransactionRequestError, MAX_TRANSACTION_DATA_LEN};
+use crate::inheritance::InheritanceConfig;
+use crate::proof_log::{RetiredKey, MAX_KEY_HISTORY, RETIRED_KEY_SIZE};
+use crate::idempotency::{IdempotencyKey, IdempotencyRecord, IDEMPOTENCY_RECORD_SIZE, MAX_IDEMPOTENCY_RECORDS};
+use crate::social_recovery::RecoveryRequest;
+use crate::auth_mode::AuthMode;
+use crate::executors::MAX_AUTHORIZED_EXECUTORS;
+use crate::sampling::MAX_SAMPLE_RATE;
+use crate::settings::{tags, SettingsExt};
+use crate::compat::{ACCOUNT_LAYOUT_VERSION, WEBAUTHN_PROFILE_RECORDED_LAYOUT};
+use crate::emergency::{EmergencyOverride, EMERGENCY_OVERRIDE_SIZE};
+use crate::policy_list::MAX_ACCOUNT_POLICIES;
 
 /// A smart account that uses passkeys instead of traditional private keys
 ///
@@ -13,14 +31,14 @@ use solana_program::pubkey::Pubkey;
 /// - It can enforce policies (spending limits, time locks, etc.)
 /// - It has built-in replay protection
 /// - It supports multi-passkey recovery
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AttestaAccount {
     /// Who owns this account (their Solana wallet address)
     pub owner: Pubkey,
     
     /// The public key from the user's passkey (64 bytes: 32 bytes x coordinate + 32 bytes y coordinate)
     /// This is what we use to verify signatures - the private key never leaves the user's device
-    pub passkey_public_key: [u8; 64],
+    pub passkey_public_key: [u8; P256_PUBKEY_LEN],
     
     /// The unique ID that identifies which passkey was used (from WebAuthn)
     /// This helps us match signatures to the right public key
@@ -32,7 +50,8 @@ pub struct AttestaAccount {
     
     /// The policy settings for this account (spending limits, time locks, etc.)
     /// Stored as bytes so we can add new policy types without breaking old accounts
-    pub policy: Vec<u8>,
+    /// Read with `policy()`; set with `set_policy` or `set_policies`
+    pub(crate) policy: Vec<u8>,
     
     /// When this account was first created (Unix timestamp)
     pub created_at: i64,
@@ -40,6 +59,509 @@ pub struct AttestaAccount {
     /// When this account was last updated (Unix timestamp)
     /// Updated whenever a transaction is executed
     pub updated_at: i64,
+
+    /// Serialized `MultiPasskey` registry (empty for single-passkey accounts)
+    /// Once present, signers are resolved through it so removed passkeys stay rejected
+    /// Read with `passkey_registry`; set with `set_passkey_registry`
+    pub(crate) passkeys: Vec<u8>,
+
+    /// Whether credential IDs are stored as SHA-256 hashes
+    /// In privacy mode anyone listing accounts sees hashes instead of the user's
+    /// device inventory; signatures are matched by hashing the presented credential ID
+    pub privacy_mode: bool,
+
+    /// The most recent executions that carried an idempotency key, oldest first
+    /// Lets a client retry an `execute` that may already have landed
+    pub idempotency_records: Vec<IdempotencyRecord>,
+
+    /// A recovery waiting for approvals or its delay, if one was started
+    pub pending_recovery: Option<RecoveryRequest>,
+
+    /// An unfinished recovery drill (kept apart so drills never touch a real recovery)
+    pub pending_drill: Option<RecoveryRequest>,
+
+    /// When a recovery drill last succeeded (Unix timestamp, 0 if never)
+    pub last_drill_at: i64,
+
+    /// Account-level checks applied before the policy
+    pub settings: AccountSettings,
+
+    /// The parent account, if this is a sub-account
+    /// Transactions must pass the parent's policy as well as this account's own
+    pub parent: Option<Pubkey>,
+
+    /// Tells a parent's sub-accounts apart (part of the address; 0 for top-level accounts)
+    pub sub_account_index: u8,
+
+    /// Who inherits the account after a long inactivity, if anyone
+    pub inheritance: Option<InheritanceConfig>,
+
+    /// Last sign of life (Unix timestamp, 0 if none yet): an execution,
+    /// heartbeat, or inheritance change
+    pub last_execution_at: i64,
+
+    /// Signatures that failed verification since the last good one
+    /// Only counted when `settings.lockout_threshold` is set
+    pub failed_auth_count: u8,
+
+    /// Executions are refused until this time (Unix timestamp, 0 if not locked)
+    pub locked_until: i64,
+
+    /// Passkeys that were replaced or removed, oldest first (at most `MAX_KEY_HISTORY`)
+    pub key_history: Vec<RetiredKey>,
+
+    /// Whether every execution is recorded in the account's proof log PDA
+    pub proof_log_enabled: bool,
+
+    /// Policies evaluated after `policy`, in order (see `policies`)
+    /// Always empty when `policy` is
+    pub additional_policies: Vec<Vec<u8>>,
+
+    /// The authenticator model (AAGUID) `passkey_public_key` attested when
+    /// it was enrolled, if it did
+    pub passkey_aaguid: Option<[u8; AAGUID_LEN]>,
+
+    /// The last signature counter seen from each passkey
+    ///
+    /// Only kept while `settings.webauthn_profile` checks counters.
+    pub sign_counts: Vec<SignCount>,
+
+    /// Canonical hash of the account's policies (see `compute_policy_hash`)
+    ///
+    /// Kept up to date by `set_policies`, so indexers can tell whether a
+    /// policy changed without decoding any.
+    pub policy_hash: [u8; HASH_LEN],
+
+    /// What's been sent to each destination in the current window of a
+    /// `PerDestinationLimit` policy
+    pub destination_spends: DestinationSpends,
+
+    /// Counts changes to the account's policies, settings and passkeys
+    ///
+    /// Instructions that make such changes take the version their caller
+    /// read, and fail if it has moved on since, so two admins editing at
+    /// once can't silently overwrite each other. Executions don't move it.
+    pub state_version: u64,
+
+    /// The layout the account was created in (see `compat`), or 0 if it
+    /// predates recording it
+    pub created_layout: u8,
+
+    /// The emergency override's limits and when it last ran (see `emergency`)
+    pub emergency_override: EmergencyOverride,
+
+    /// The index the account's address was derived from, if it was created
+    /// with `initialize_with_index` (see `derive_attesta_account_by_index`)
+    ///
+    /// `None` for an account at the owner's own address, `[b"attesta", owner]`.
+    pub account_index: Option<u16>,
+}
+
+/// Why a policy can't be set on an account, or read back from it
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AccountPolicyError {
+    #[error("Invalid policy: {0}")]
+    Invalid(#[from] PolicyBuildError),
+
+    #[error("Policy is estimated at {estimated} compute units (at most {MAX_POLICY_COMPUTE_UNITS})")]
+    TooExpensive { estimated: u32 },
+
+    #[error("Policy is {len} bytes; an account has room for {MAX_INITIAL_POLICY_LEN}")]
+    TooLong { len: usize },
+
+    #[error("The account holds a policy that doesn't decode")]
+    Undecodable,
+}
+
+/// Why `AttestaAccount::from_bytes_strict` (or `validate`) rejected an account
+///
+/// Each names the field at fault, so a spoofed or half-written account
+/// can be told apart from one that merely failed to decode.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AccountValidationError {
+    #[error("Malformed account data: {0}")]
+    Serialization(String),
+
+    #[error("owner is the default pubkey")]
+    DefaultOwner,
+
+    #[error("passkey_public_key is all zeros")]
+    ZeroPublicKey,
+
+    #[error("credential_id is empty")]
+    EmptyCredentialId,
+
+    #[error("credential_id must be a 32-byte hash in privacy mode")]
+    PrivacyCredentialId,
+
+    #[error("additional_policies are set without a policy")]
+    OrphanedPolicies,
+
+    #[error("{field} holds {len} entries (at most {max})")]
+    TooManyEntries { field: &'static str, len: usize, max: usize },
+
+    #[error("settings can't be stored")]
+    InvalidSettings,
+
+    #[error("Invalid passkey registry: {0}")]
+    InvalidPasskeys(#[from] MultiPasskeyError),
+}
+
+/// The last signature counter one passkey reported
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignCount {
+    /// SHA-256 of the passkey's credential ID
+    pub credential_id_hash: [u8; HASH_LEN],
+
+    pub sign_count: u32,
+}
+
+/// Serialized size of a `SignCount`: credential_id_hash (32) + sign_count (4)
+pub const SIGN_COUNT_SIZE: usize = HASH_LEN + 4;
+
+/// Account-level checks applied before the policy runs
+///
+/// All are off by default, so accounts created before settings existed
+/// behave exactly as they did. They're stored as flags and tagged entries in
+/// one section at the end of the account (see `settings`).
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountSettings {
+    /// Deny transfers of a zero amount (they'd only burn a nonce)
+    pub reject_zero_amount: bool,
+
+    /// Deny transfers back to the account itself or its own token accounts
+    pub reject_self_transfer: bool,
+
+    /// Largest `transaction_data` this account executes, in bytes
+    ///
+    /// 0 means `MAX_TRANSACTION_DATA_LEN`. Can only tighten the global limit.
+    pub max_transaction_data_len: u16,
+
+    /// Failed signatures in a row that lock the account (0 turns lockout off)
+    ///
+    /// Once reached, each further failure doubles the lockout, up to
+    /// `MAX_LOCKOUT_DURATION`.
+    pub lockout_threshold: u8,
+
+    /// Authenticator models (AAGUIDs) new passkeys must come from; empty allows any
+    ///
+    /// Checked whenever a passkey is enrolled: at `initialize`, `add_passkey`
+    /// and when a recovery is finalized. The AAGUID is the one in the
+    /// attested credential data the passkey signs when it registers; the
+    /// attestation certificate chain isn't checked on-chain.
+    #[borsh(skip)]
+    pub aaguid_allowlist: Vec<[u8; AAGUID_LEN]>,
+
+    /// Hash of the program version `execute` may run under; `None` accepts any
+    ///
+    /// See `upgrade`: after an upgrade, the owner re-pins with
+    /// `acknowledge_upgrade`.
+    #[borsh(skip)]
+    pub pinned_program_version: Option<[u8; HASH_LEN]>,
+
+    /// The optional WebAuthn checks passkey signatures are held to
+    ///
+    /// Legacy (none) by default. Only the primary passkey can change it, or
+    /// `relying_party`.
+    #[borsh(skip)]
+    pub webauthn_profile: WebAuthnVerificationProfile,
+
+    /// The relying party the profile's RP ID and origin checks expect
+    #[borsh(skip)]
+    pub relying_party: Option<RelyingParty>,
+
+    /// Which signers may authorize `execute` (see `auth_mode`)
+    ///
+    /// Changed only with `AUTH_MODE_ACTION`, never by a settings update.
+    #[borsh(skip)]
+    pub auth_mode: AuthMode,
+
+    /// Whether `auth_mode` is locked to `PasskeyOnly` until a recovery
+    #[borsh(skip)]
+    pub auth_mode_locked: bool,
+
+    /// Who may submit `execute`; empty lets anyone relay (see `executors`)
+    ///
+    /// Changed only with the executor actions, never by a settings update.
+    #[borsh(skip)]
+    pub authorized_executors: Vec<Pubkey>,
+
+    /// Percentage of allowed executions that emit an `AllowedWithContext`
+    /// event (see `sampling`); 0 emits none
+    ///
+    /// Which ones is decided by the nonce, so whoever submits the
+    /// transaction can't choose.
+    #[borsh(skip)]
+    pub log_allowed_sample_rate: u8,
+
+    /// Largest transfer `execute` still allows while a recovery is pending,
+    /// in the mint's base units; 0 allows none
+    ///
+    /// Lets a holder who kept their passkey pay for something urgent without
+    /// handing a thief the balance (see `social_recovery`). Each transfer is
+    /// held to it, not their total, so keep it small.
+    #[borsh(skip)]
+    pub recovery_allowance: u64,
+}
+
+/// Most authenticator models an account's allowlist can hold
+pub const MAX_AAGUID_ALLOWLIST_LEN: usize = 8;
+
+impl AccountSettings {
+    /// Size of the Borsh-serialized settings (the fields first stored, which
+    /// start what a settings update signs)
+    pub const SERIALIZED_SIZE: usize = 5;
+
+    /// The bytes a passkey signs (with `SETTINGS_UPDATE_ACTION`) to apply these settings
+    ///
+    /// The fixed-size settings, then the allowlisted AAGUIDs if there are
+    /// any, then a 1 and the pinned version hash if there is one, then a 2,
+    /// the profile's bits and the relying party if either is set, then a 3
+    /// and the sample rate if it isn't 0, then a 4 and the recovery allowance
+    /// (little-endian) if it isn't 0. Without any of them, these are the
+    /// bytes signed before they existed. (The marker bytes keep each part
+    /// from reading as more AAGUIDs.)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let [len_lo, len_hi] = self.max_transaction_data_len.to_le_bytes();
+        let mut bytes = vec![self.reject_zero_amount as u8, self.reject_self_transfer as u8, len_lo, len_hi, self.lockout_threshold];
+        for aaguid in &self.aaguid_allowlist {
+            bytes.extend_from_slice(aaguid);
+        }
+        if let Some(version) = &self.pinned_program_version {
+            bytes.push(1);
+            bytes.extend_from_slice(version);
+        }
+        if self.webauthn_profile != WebAuthnVerificationProfile::legacy() || self.relying_party.is_some() {
+            bytes.extend_from_slice(&[2, self.webauthn_profile.to_bits()]);
+            if let Some(party) = &self.relying_party {
+                bytes.extend_from_slice(&party.rp_id_hash);
+                bytes.extend_from_slice(&party.origin_hash);
+            }
+        }
+        if self.log_allowed_sample_rate != 0 {
+            bytes.extend_from_slice(&[3, self.log_allowed_sample_rate]);
+        }
+        if self.recovery_allowance != 0 {
+            bytes.push(4);
+            bytes.extend_from_slice(&self.recovery_allowance.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Whether `other` checks passkey signatures differently (the profile
+    /// or the relying party changed)
+    pub fn changes_webauthn_checks(&self, other: &AccountSettings) -> bool {
+        self.webauthn_profile != other.webauthn_profile || self.relying_party != other.relying_party
+    }
+
+    /// Whether a passkey that attested `aaguid` may be enrolled
+    ///
+    /// `None` (no attested credential data) only passes an empty allowlist.
+    pub fn allows_authenticator(&self, aaguid: Option<&[u8; AAGUID_LEN]>) -> bool {
+        self.aaguid_allowlist.is_empty() || aaguid.is_some_and(|aaguid| self.aaguid_allowlist.contains(aaguid))
+    }
+
+    /// The transaction data limit in effect for this account
+    pub fn transaction_data_limit(&self) -> usize {
+        match self.max_transaction_data_len as usize {
+            0 => MAX_TRANSACTION_DATA_LEN,
+            len => len.min(MAX_TRANSACTION_DATA_LEN),
+        }
+    }
+
+    /// Checks `len` bytes of transaction data against this account's limit
+    pub fn check_transaction_data_len(&self, len: usize) -> Result<(), TransactionRequestError> {
+        let max = self.transaction_data_limit();
+        if len > max {
+            return Err(TransactionRequestError::TooLarge { len, max });
+        }
+        Ok(())
+    }
+
+    /// Whether these settings can be stored (the override is within the
+    /// global limit, the allowlist within `MAX_AAGUID_ALLOWLIST_LEN`, a
+    /// profile that checks the RP ID or origin has a relying party, and
+    /// only `PasskeyOnly` is locked, the executor list within
+    /// `MAX_AUTHORIZED_EXECUTORS`, and the sample rate a percentage)
+    pub fn is_valid(&self) -> bool {
+        self.max_transaction_data_len as usize <= MAX_TRANSACTION_DATA_LEN
+            && self.aaguid_allowlist.len() <= MAX_AAGUID_ALLOWLIST_LEN
+            && (self.relying_party.is_some() || !self.webauthn_profile.needs_relying_party())
+            && (!self.auth_mode_locked || self.auth_mode == AuthMode::PasskeyOnly)
+            && self.authorized_executors.len() <= MAX_AUTHORIZED_EXECUTORS
+            && self.log_allowed_sample_rate <= MAX_SAMPLE_RATE
+    }
+}
+
+impl BorshSerialize for AttestaAccount {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.owner.serialize(writer)?;
+        self.passkey_public_key.serialize(writer)?;
+        self.credential_id.serialize(writer)?;
+        self.nonce.serialize(writer)?;
+        self.policy.serialize(writer)?;
+        self.created_at.serialize(writer)?;
+        self.updated_at.serialize(writer)?;
+        self.passkeys.serialize(writer)?;
+        self.privacy_mode.serialize(writer)?;
+        self.idempotency_records.serialize(writer)?;
+        self.pending_recovery.serialize(writer)?;
+        self.pending_drill.serialize(writer)?;
+        self.last_drill_at.serialize(writer)?;
+        // The settings are stored in the compact section at the end (see
+        // `settings`); the places they were stored before hold defaults
+        AccountSettings::default().serialize(writer)?;
+        self.parent.serialize(writer)?;
+        self.sub_account_index.serialize(writer)?;
+        self.inheritance.serialize(writer)?;
+        self.last_execution_at.serialize(writer)?;
+        self.failed_auth_count.serialize(writer)?;
+        self.locked_until.serialize(writer)?;
+        self.key_history.serialize(writer)?;
+        self.proof_log_enabled.serialize(writer)?;
+        self.additional_policies.serialize(writer)?;
+        self.passkey_aaguid.serialize(writer)?;
+        // Parts of nested values that were added after the values themselves
+        Vec::<[u8; AAGUID_LEN]>::new().serialize(writer)?;
+        self.pending_recovery.as_ref().and_then(|request| request.new_aaguid).serialize(writer)?;
+        None::<[u8; HASH_LEN]>.serialize(writer)?;
+        WebAuthnVerificationProfile::legacy().to_bits().serialize(writer)?;
+        None::<RelyingParty>.serialize(writer)?;
+        self.sign_counts.serialize(writer)?;
+        (AuthMode::PasskeyOnly as u8).serialize(writer)?;
+        false.serialize(writer)?;
+        self.policy_hash.serialize(writer)?;
+        self.destination_spends.serialize(writer)?;
+        Vec::<Pubkey>::new().serialize(writer)?;
+        self.state_version.serialize(writer)?;
+        0u8.serialize(writer)?;
+        Some((self.settings.flags(), self.stored_settings_ext().to_bytes())).serialize(writer)?;
+        self.created_layout.serialize(writer)?;
+        self.emergency_override.serialize(writer)?;
+        self.account_index.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for AttestaAccount {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        Self::deserialize_with_layout(reader).map(|(account, _)| account)
+    }
+}
+
+impl AttestaAccount {
+    /// Reads an account and the layout it was written in
+    ///
+    /// Fields after the end of the data take the defaults `compat` lists.
+    pub(crate) fn deserialize_with_layout<R: std::io::Read>(reader: &mut R) -> std::io::Result<(Self, u8)> {
+        let mut trailing = TrailingFields { reader, present: 0 };
+        let mut account = Self {
+            owner: Pubkey::deserialize_reader(trailing.reader)?,
+            passkey_public_key: <[u8; P256_PUBKEY_LEN]>::deserialize_reader(trailing.reader)?,
+            credential_id: Vec::deserialize_reader(trailing.reader)?,
+            nonce: u64::deserialize_reader(trailing.reader)?,
+            policy: Vec::deserialize_reader(trailing.reader)?,
+            created_at: i64::deserialize_reader(trailing.reader)?,
+            updated_at: i64::deserialize_reader(trailing.reader)?,
+            // Fields below were added later - accounts created before them simply end here
+            passkeys: trailing.read()?,
+            privacy_mode: trailing.read()?,
+            idempotency_records: trailing.read()?,
+            pending_recovery: trailing.read()?,
+            pending_drill: trailing.read()?,
+            last_drill_at: trailing.read()?,
+            settings: trailing.read()?,
+            parent: trailing.read()?,
+            sub_account_index: trailing.read()?,
+            inheritance: trailing.read()?,
+            last_execution_at: trailing.read()?,
+            failed_auth_count: trailing.read()?,
+            locked_until: trailing.read()?,
+            key_history: trailing.read()?,
+            proof_log_enabled: trailing.read()?,
+            additional_policies: trailing.read()?,
+            passkey_aaguid: trailing.read()?,
+            sign_counts: Vec::new(),
+            policy_hash: [0; HASH_LEN],
+            destination_spends: DestinationSpends::default(),
+            state_version: 0,
+            created_layout: 0,
+            emergency_override: EmergencyOverride::default(),
+            account_index: None,
+        };
+        account.settings.aaguid_allowlist = trailing.read()?;
+        let recovery_aaguid = trailing.read()?;
+        if let Some(request) = account.pending_recovery.as_mut() {
+            request.new_aaguid = recovery_aaguid;
+        }
+        account.settings.pinned_program_version = trailing.read()?;
+        // A check this version doesn't know can't be dropped silently
+        account.settings.webauthn_profile = WebAuthnVerificationProfile::from_bits(trailing.read()?)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown WebAuthn check in profile"))?;
+        account.settings.relying_party = trailing.read()?;
+        account.sign_counts = trailing.read()?;
+        // Reading an unknown mode as a known one could let the wrong signer in
+        account.settings.auth_mode = AuthMode::from_u8(trailing.read()?)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown auth mode"))?;
+        account.settings.auth_mode_locked = trailing.read()?;
+        account.policy_hash = trailing.read()?;
+        // Accounts stored before the hash was get it now; no real hash is all zeros
+        if account.policy_hash == [0; HASH_LEN] {
+            account.policy_hash = account.compute_policy_hash();
+        }
+        account.destination_spends = trailing.read()?;
+        account.settings.authorized_executors = trailing.read()?;
+        account.state_version = trailing.read()?;
+        account.settings.log_allowed_sample_rate = trailing.read()?;
+        // Accounts saved before the compact section keep the settings read above
+        let compact: Option<(u32, Vec<u8>)> = trailing.read()?;
+        let mut profile_stored = false;
+        if let Some((settings_flags, ext)) = compact {
+            account.settings = AccountSettings::from_compact(settings_flags, &ext)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            profile_stored = SettingsExt::parse(&ext).is_ok_and(|ext| ext.get(tags::WEBAUTHN_PROFILE).is_some());
+        }
+        account.created_layout = trailing.read()?;
+        // Reading a lost profile as legacy would drop every check it had
+        if account.created_layout >= WEBAUTHN_PROFILE_RECORDED_LAYOUT && !profile_stored {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "WebAuthn profile is missing"));
+        }
+        account.emergency_override = trailing.read()?;
+        account.account_index = trailing.read()?;
+        Ok((account, trailing.present))
+    }
+
+    /// The settings entries as stored: accounts that must record their
+    /// WebAuthn profile (see `compat`) keep it even when it's the default
+    fn stored_settings_ext(&self) -> SettingsExt {
+        let mut ext = self.settings.ext();
+        if self.created_layout >= WEBAUTHN_PROFILE_RECORDED_LAYOUT && ext.get(tags::WEBAUTHN_PROFILE).is_none() {
+            // One byte always fits the profile's tag
+            let _ = ext.set(tags::WEBAUTHN_PROFILE, vec![self.settings.webauthn_profile.to_bits()]);
+        }
+        ext
+    }
+}
+
+/// Reads the trailing fields, counting the ones the data holds
+///
+/// Lets accounts written before a field existed keep deserializing. Only the
+/// very end of the input may be missing: a field that starts but is cut off
+/// is still an error.
+struct TrailingFields<'a, R> {
+    reader: &'a mut R,
+    present: u8,
+}
+
+impl<R: std::io::Read> TrailingFields<'_, R> {
+    /// Reads the next trailing field, defaulting it if the data ends first
+    fn read<T: BorshDeserialize + Default>(&mut self) -> std::io::Result<T> {
+        let mut first = [0u8; 1];
+        if self.reader.read(&mut first)? == 0 {
+            return Ok(T::default());
+        }
+        self.present = self.present.saturating_add(1);
+        T::deserialize_reader(&mut std::io::Read::chain(first.as_slice(), &mut *self.reader))
+    }
 }
 
 impl AttestaAccount {
@@ -59,12 +581,12 @@ impl AttestaAccount {
     /// A new AttestaAccount with nonce set to 0 (ready for first transaction)
     pub fn new(
         owner: Pubkey,
-        passkey_public_key: [u8; 64],
+        passkey_public_key: [u8; P256_PUBKEY_LEN],
         credential_id: Vec<u8>,
         policy: Vec<u8>,
         created_at: i64,
     ) -> Self {
-        Self {
+        let mut account = Self {
             owner,
             passkey_public_key,
             credential_id,
@@ -72,28 +594,458 @@ impl AttestaAccount {
             policy,
             created_at,
             updated_at: created_at, // Initially same as created_at
+            passkeys: Vec::new(), // Single passkey until another one is added
+            privacy_mode: false,
+            idempotency_records: Vec::new(),
+            pending_recovery: None,
+            pending_drill: None,
+            last_drill_at: 0,
+            settings: AccountSettings::default(),
+            parent: None,
+            sub_account_index: 0,
+            inheritance: None,
+            last_execution_at: 0,
+            failed_auth_count: 0,
+            locked_until: 0,
+            key_history: Vec::new(),
+            proof_log_enabled: false,
+            additional_policies: Vec::new(),
+            passkey_aaguid: None,
+            sign_counts: Vec::new(),
+            policy_hash: [0; HASH_LEN],
+            destination_spends: DestinationSpends::default(),
+            state_version: 0,
+            created_layout: ACCOUNT_LAYOUT_VERSION,
+            emergency_override: EmergencyOverride::default(),
+            account_index: None,
+        };
+        account.policy_hash = account.compute_policy_hash();
+        account
+    }
+
+    /// Whether the account's policies, settings and passkeys are still at
+    /// `expected_version` (see `state_version`)
+    pub fn is_at_version(&self, expected_version: u64) -> bool {
+        self.state_version == expected_version
+    }
+
+    /// Records a change to the account's policies, settings or passkeys
+    pub fn bump_state_version(&mut self) {
+        self.state_version = self.state_version.wrapping_add(1);
+    }
+
+    /// Bytes `destination_spends` may still grow by under the current policies
+    ///
+    /// Zero unless a policy has a per-destination limit. Executing has no
+    /// payer to grow the account with, so the program sets this much aside
+    /// whenever the policies change.
+    pub fn destination_spends_headroom(&self) -> usize {
+        let limits_destinations = self
+            .policies()
+            .into_iter()
+            .any(|bytes| Policy::from_bytes(bytes).is_ok_and(|policy| policy.has_destination_limit()));
+        if !limits_destinations {
+            return 0;
+        }
+        MAX_TRACKED_DESTINATIONS.saturating_sub(self.destination_spends.entries.len()) * DESTINATION_SPEND_SIZE
+    }
+
+    /// Returns the form of a credential ID that's stored on this account
+    ///
+    /// In privacy mode that's the SHA-256 hash of the credential ID, otherwise
+    /// the credential ID itself. Every lookup by credential ID goes through
+    /// this, so callers can always pass the credential ID from the assertion.
+    pub fn credential_lookup_id(&self, credential_id: &[u8]) -> Vec<u8> {
+        if self.privacy_mode {
+            credential_id_hash(credential_id).to_vec()
+        } else {
+            credential_id.to_vec()
+        }
+    }
+
+    /// What a signature from `credential_id` is checked against under the
+    /// account's WebAuthn profile
+    pub fn webauthn_expectations(&self, credential_id: &[u8]) -> WebAuthnExpectations {
+        let credential_id_hash = credential_id_hash(credential_id);
+        WebAuthnExpectations {
+            relying_party: self.settings.relying_party,
+            previous_sign_count: self
+                .sign_counts
+                .iter()
+                .find(|count| count.credential_id_hash == credential_id_hash)
+                .map_or(0, |count| count.sign_count),
+        }
+    }
+
+    /// Remembers the signature counter of a signature that was just accepted
+    ///
+    /// Does nothing unless the profile checks counters, or for passkeys that
+    /// don't keep one. A passkey seen for the first time drops the counters
+    /// of passkeys the account no longer has.
+    pub fn record_sign_count(&mut self, webauthn_sig: &WebAuthnSignature) {
+        if !self.settings.webauthn_profile.check_sign_count {
+            return;
+        }
+        let sign_count = match parse_authenticator_data(&webauthn_sig.authenticator_data) {
+            Ok(authenticator_data) if authenticator_data.sign_count > 0 => authenticator_data.sign_count,
+            _ => return,
+        };
+
+        let credential_id_hash = credential_id_hash(&webauthn_sig.credential_id);
+        if let Some(count) = self.sign_counts.iter_mut().find(|count| count.credential_id_hash == credential_id_hash) {
+            count.sign_count = sign_count;
+            return;
+        }
+        let current = self.credential_id_hashes();
+        self.sign_counts.retain(|count| current.contains(&count.credential_id_hash));
+        self.sign_counts.push(SignCount { credential_id_hash, sign_count });
+    }
+
+    /// SHA-256 of each passkey's credential ID (stored IDs already are, in privacy mode)
+    fn credential_id_hashes(&self) -> Vec<[u8; HASH_LEN]> {
+        match self.passkey_registry() {
+            Ok(Some(registry)) => registry.entries().map(|entry| self.entry_credential_hash(entry)).collect(),
+            _ => vec![self.stored_credential_hash(&self.credential_id)],
+        }
+    }
+
+    /// Whether `credential_id` is the account's primary passkey, the one
+    /// that administers it
+    pub fn is_primary_credential(&self, credential_id: &[u8]) -> bool {
+        self.credential_lookup_id(credential_id) == self.credential_id
+    }
+
+    /// Whether `hash` (SHA-256 of a credential ID) is one of the account's
+    /// passkeys now
+    pub fn holds_credential_hash(&self, hash: &[u8; HASH_LEN]) -> bool {
+        self.credential_id_hashes().contains(hash)
+    }
+
+    /// When the passkey whose credential ID hashes to `hash` was removed
+    ///
+    /// # Returns
+    /// `None` if it never was, or its tombstone has since been evicted or purged
+    pub fn credential_revoked_at(&self, hash: &[u8; HASH_LEN]) -> Option<i64> {
+        // Tombstones hash the stored ID, which privacy mode already hashed
+        let tombstone = if self.privacy_mode { credential_id_hash(hash) } else { *hash };
+        let registry = self.passkey_registry().ok()??;
+        registry.revoked.iter().find(|revoked| revoked.credential_id_hash == tombstone).map(|revoked| revoked.revoked_at)
+    }
+
+    /// Switches the account to privacy mode, hashing every stored credential ID
+    ///
+    /// This also migrates existing accounts: the primary credential ID and all
+    /// registry entries are replaced by their hashes, and revocation tombstones
+    /// are rehashed so revoked passkeys stay revoked. Hashes can't be reversed,
+    /// so privacy mode can't be turned off again. Calling this on an account
+    /// that's already private does nothing.
+    pub fn enable_privacy_mode(&mut self) -> Result<(), MultiPasskeyError> {
+        if self.privacy_mode {
+            return Ok(());
+        }
+
+        if let Some(mut registry) = self.passkey_registry()? {
+            for entry in std::iter::once(&mut registry.primary).chain(registry.additional.iter_mut()) {
+                // A hashed entry already holds what privacy mode stores
+                match entry.id_storage {
+                    CredentialIdStorage::Full => entry.credential_id = credential_id_hash(&entry.credential_id).to_vec(),
+                    CredentialIdStorage::Hashed => entry.id_storage = CredentialIdStorage::Full,
+                }
+            }
+            // Tombstones hold hash(credential ID); lookups will now hash the stored form
+            for revoked in registry.revoked.iter_mut() {
+                revoked.credential_id_hash = credential_id_hash(&revoked.credential_id_hash);
+            }
+            self.set_passkey_registry(&registry)?;
+        }
+
+        self.credential_id = credential_id_hash(&self.credential_id).to_vec();
+        if let Some(inheritance) = self.inheritance.as_mut() {
+            inheritance.beneficiary_credential_id = credential_id_hash(&inheritance.beneficiary_credential_id).to_vec();
+        }
+        self.privacy_mode = true;
+        self.bump_state_version();
+        Ok(())
+    }
+
+    /// Loads the passkey registry, if this account has one
+    ///
+    /// # Returns
+    /// - `Ok(None)` for single-passkey accounts
+    /// - `Ok(Some(MultiPasskey))` once additional passkeys have been registered
+    /// - `Err(MultiPasskeyError)` if the stored registry is corrupted or invalid
+    pub fn passkey_registry(&self) -> Result<Option<MultiPasskey>, MultiPasskeyError> {
+        if self.passkeys.is_empty() {
+            return Ok(None);
+        }
+        MultiPasskey::from_bytes_strict(&self.passkeys).map(Some)
+    }
+
+    /// Loads the passkey registry, creating one from the primary passkey if needed
+    ///
+    /// Used before adding a second passkey: the account's own passkey becomes
+    /// the registry's primary entry.
+    pub fn passkey_registry_or_default(&self) -> Result<MultiPasskey, MultiPasskeyError> {
+        match self.passkey_registry()? {
+            Some(registry) => Ok(registry),
+            None => {
+                let mut registry = MultiPasskey::new(
+                    self.passkey_public_key,
+                    self.credential_id.clone(),
+                    "Primary".to_string(),
+                    self.created_at,
+                    1,
+                    DEFAULT_MAX_PASSKEYS,
+                );
+                registry.primary.aaguid = self.passkey_aaguid;
+                Ok(registry)
+            }
         }
     }
 
+    /// Stores an updated passkey registry on the account
+    ///
+    /// The registry is validated first, so an account never holds one that
+    /// `passkey_registry` would refuse to load.
+    pub fn set_passkey_registry(&mut self, registry: &MultiPasskey) -> Result<(), MultiPasskeyError> {
+        registry.validate_strict()?;
+        self.passkeys = registry.to_bytes()?;
+        Ok(())
+    }
+
+    /// Looks up a previous execution by its idempotency key
+    pub fn find_idempotency_record(&self, key: &IdempotencyKey) -> Option<&IdempotencyRecord> {
+        self.idempotency_records.iter().find(|r| r.key == *key)
+    }
+
+    /// Remembers a successful execution so a retry with the same key can be answered
+    ///
+    /// Keeps at most `MAX_IDEMPOTENCY_RECORDS`, dropping the oldest first.
+    pub fn record_idempotency_key(&mut self, key: IdempotencyKey, message_hash: [u8; 32], nonce: u64) {
+        if self.idempotency_records.len() >= MAX_IDEMPOTENCY_RECORDS {
+            self.idempotency_records.remove(0);
+        }
+        self.idempotency_records.push(IdempotencyRecord { key, message_hash, nonce });
+    }
+
+    /// Whether a recovery of the primary passkey is pending (a drill isn't)
+    ///
+    /// Until it's finalized or cancelled, `execute` only moves what
+    /// `settings.recovery_allowance` allows, and the program refuses changes
+    /// to the account's policies, passkeys and settings (see `social_recovery`).
+    pub fn recovery_pending(&self) -> bool {
+        self.pending_recovery.is_some()
+    }
+
+    /// Whether executions are currently refused after repeated bad signatures
+    pub fn is_locked_out(&self, now: i64) -> bool {
+        now < self.locked_until
+    }
+
+    /// Counts a signature that failed verification
+    ///
+    /// Once `settings.lockout_threshold` failures have piled up, the account
+    /// is locked for `lockout_duration` of the count. Does nothing while
+    /// lockout is off.
+    pub fn record_failed_auth(&mut self, now: i64) {
+        let threshold = self.settings.lockout_threshold;
+        if threshold == 0 {
+            return;
+        }
+
+        self.failed_auth_count = self.failed_auth_count.saturating_add(1);
+        if self.failed_auth_count >= threshold {
+            self.locked_until = now.saturating_add(lockout_duration(self.failed_auth_count - threshold));
+        }
+    }
+
+    /// Forgets earlier failures once a signature verifies
+    pub fn reset_failed_auth(&mut self) {
+        self.failed_auth_count = 0;
+        self.locked_until = 0;
+    }
+
+    /// Remembers a passkey that's being replaced or removed
+    ///
+    /// Call this before the key stops being accepted, so proofs it signed
+    /// earlier can still be verified. Keeps at most `MAX_KEY_HISTORY`,
+    /// dropping the oldest first.
+    ///
+    /// # Parameters
+    /// - `stored_credential_id`: The credential ID as stored on the account
+    ///   (already hashed in privacy mode)
+    /// - `public_key`: The key it had
+    /// - `now`: When it's retired
+    pub fn retire_key(&mut self, stored_credential_id: &[u8], public_key: [u8; P256_PUBKEY_LEN], now: i64) {
+        if self.key_history.len() >= MAX_KEY_HISTORY {
+            self.key_history.remove(0);
+        }
+        self.key_history.push(RetiredKey {
+            credential_id_hash: self.stored_credential_hash(stored_credential_id),
+            public_key,
+            retired_at: now,
+        });
+    }
+
+    /// The public key a credential had at time `at`
+    ///
+    /// A key retired at or after `at` is the one that was in use then; if the
+    /// credential hasn't been retired since, its current key is.
+    ///
+    /// # Parameters
+    /// - `credential_id_hash`: SHA-256 of the credential ID from the assertion
+    /// - `at`: The Unix timestamp to look the key up for
+    pub fn public_key_at(&self, credential_id_hash: &[u8; 32], at: i64) -> Option<[u8; P256_PUBKEY_LEN]> {
+        let retired = self
+            .key_history
+            .iter()
+            .filter(|key| key.credential_id_hash == *credential_id_hash && key.retired_at >= at)
+            .min_by_key(|key| key.retired_at);
+        if let Some(key) = retired {
+            return Some(key.public_key);
+        }
+
+        if self.stored_credential_hash(&self.credential_id) == *credential_id_hash {
+            return Some(self.passkey_public_key);
+        }
+        let registry = self.passkey_registry().ok()??;
+        registry
+            .additional
+            .iter()
+            .find(|entry| self.entry_credential_hash(entry) == *credential_id_hash)
+            .map(|entry| entry.public_key)
+    }
+
+    /// SHA-256 of the credential ID behind a registry entry
+    fn entry_credential_hash(&self, entry: &PasskeyEntry) -> [u8; 32] {
+        match entry.id_storage {
+            CredentialIdStorage::Full => self.stored_credential_hash(&entry.credential_id),
+            CredentialIdStorage::Hashed => entry.credential_id_hash(),
+        }
+    }
+
+    /// SHA-256 of the credential ID behind a stored one
+    ///
+    /// In privacy mode the stored ID already is that hash.
+    fn stored_credential_hash(&self, stored_credential_id: &[u8]) -> [u8; 32] {
+        if self.privacy_mode {
+            stored_credential_id.try_into().unwrap_or_default()
+        } else {
+            credential_id_hash(stored_credential_id)
+        }
+    }
+
+    /// The account's policies in evaluation order, each serialized
+    ///
+    /// `policy` comes first, then `additional_policies`. An account with a
+    /// single policy (including every account from before there could be
+    /// more) is a one-element list; one with no policy is an empty list.
+    pub fn policies(&self) -> Vec<&[u8]> {
+        if self.policy.is_empty() {
+            return Vec::new();
+        }
+        std::iter::once(self.policy.as_slice())
+            .chain(self.additional_policies.iter().map(Vec::as_slice))
+            .collect()
+    }
+
+    /// Replaces the account's policies with `policies`, in order
+    ///
+    /// Empty entries are dropped, since an empty `policy` means none at all.
+    /// Doesn't check the policies or their number; see `policy_list`.
+    pub fn set_policies(&mut self, policies: Vec<Vec<u8>>) {
+        let mut policies = policies.into_iter().filter(|policy| !policy.is_empty());
+        self.policy = policies.next().unwrap_or_default();
+        self.additional_policies = policies.collect();
+        self.policy_hash = self.compute_policy_hash();
+    }
+
+    /// The account's first policy, serialized (empty if it has none)
+    ///
+    /// Decode the policy a transaction must pass with `Policy::try_from`.
+    pub fn policy(&self) -> &[u8] {
+        &self.policy
+    }
+
+    /// Replaces the account's policies with `policy` alone
+    ///
+    /// Unlike `set_policies`, the policy is checked first: its config must
+    /// pass `Policy::validate_config`, its estimated cost must be at most
+    /// `MAX_POLICY_COMPUTE_UNITS`, and it must fit the
+    /// `MAX_INITIAL_POLICY_LEN` bytes a new account has room for. Its time
+    /// locks depend on the clock, so check them with
+    /// `Policy::validate_timestamps` as well.
+    pub fn set_policy(&mut self, policy: &Policy) -> Result<(), AccountPolicyError> {
+        policy.validate_config()?;
+        let estimated = policy.estimated_compute_units();
+        if estimated > MAX_POLICY_COMPUTE_UNITS {
+            return Err(AccountPolicyError::TooExpensive { estimated });
+        }
+        let len = policy.serialized_size();
+        if len > MAX_INITIAL_POLICY_LEN {
+            return Err(AccountPolicyError::TooLong { len });
+        }
+
+        // Serializing into a Vec can't fail
+        self.set_policies(vec![policy.to_bytes().unwrap_or_default()]);
+        Ok(())
+    }
+
+    /// The account with `policy` as its only policy, checked as by `set_policy`
+    pub fn with_policy(mut self, policy: Policy) -> Result<Self, AccountPolicyError> {
+        self.set_policy(&policy)?;
+        Ok(self)
+    }
+
+    /// The serialized passkey registry (empty for single-passkey accounts)
+    ///
+    /// Decode it with `passkey_registry`.
+    pub fn passkeys(&self) -> &[u8] {
+        &self.passkeys
+    }
+
+    /// The canonical hash of the account's policies
+    ///
+    /// A single policy hashes as its `Policy::canonical_hash`. Several hash
+    /// as the `Composite` of them, since a transaction must pass them all,
+    /// and no policy hashes as `Policy::open()`. A stored policy that doesn't
+    /// decode is hashed as an `Open` policy with its bytes for config, which
+    /// no valid policy has.
+    pub fn compute_policy_hash(&self) -> [u8; HASH_LEN] {
+        let mut policies: Vec<Policy> = self
+            .policies()
+            .into_iter()
+            .map(|bytes| {
+                Policy::from_bytes(bytes).unwrap_or(Policy { policy_type: PolicyType::Open, config: bytes.to_vec() })
+            })
+            .collect();
+        match policies.len() {
+            0 => Policy::open(),
+            1 => policies.remove(0),
+            _ => Policy::composite(policies),
+        }
+        .canonical_hash()
+    }
+
     /// Marks a transaction as complete by incrementing the nonce
     ///
     /// This should be called after successfully processing a transaction.
     /// It prevents anyone from replaying the same transaction later.
     ///
+    /// # Parameters
+    /// - `now`: The current Unix timestamp (see `cluster_time`)
+    ///
     /// # Side Effects
     /// - Increments the nonce counter
-    /// - Updates the `updated_at` timestamp
-    pub fn increment_nonce(&mut self) {
+    /// - Sets the `updated_at` timestamp to `now`
+    pub fn increment_nonce(&mut self, now: i64) {
         // Overflow check: if we've reached u64::MAX, we have bigger problems
         // but let's prevent silent wrapping
         if self.nonce < u64::MAX {
             self.nonce = self.nonce.wrapping_add(1);
         }
         
-        // Update the timestamp to now
-        self.updated_at = solana_program::clock::Clock::get()
-            .map(|c| c.unix_timestamp)
-            .unwrap_or(self.updated_at); // If we can't get clock, keep old timestamp
+        self.updated_at = now;
     }
 
     /// Checks if a nonce is valid (higher than the last one used)
@@ -113,6 +1065,53 @@ impl AttestaAccount {
         provided_nonce > self.nonce
     }
 
+    /// Length of `to_bytes()`, computed from the field lengths
+    ///
+    /// Lets the program size allocations without serializing the account
+    /// just to measure it.
+    pub fn serialized_size(&self) -> usize {
+        PUBKEY_LEN                           // owner
+            + P256_PUBKEY_LEN                // passkey_public_key
+            + BORSH_LEN_PREFIX + self.credential_id.len()
+            + 8                              // nonce
+            + BORSH_LEN_PREFIX + self.policy.len()
+            + 8 + 8                          // created_at, updated_at
+            + BORSH_LEN_PREFIX + self.passkeys.len()
+            + 1                              // privacy_mode
+            + BORSH_LEN_PREFIX + self.idempotency_records.len() * IDEMPOTENCY_RECORD_SIZE
+            + 1 + self.pending_recovery.as_ref().map_or(0, RecoveryRequest::serialized_size)
+            + 1 + self.pending_drill.as_ref().map_or(0, RecoveryRequest::serialized_size)
+            + 8                              // last_drill_at
+            + AccountSettings::SERIALIZED_SIZE      // defaults, see `settings`
+            + 1 + if self.parent.is_some() { PUBKEY_LEN } else { 0 }
+            + 1                              // sub_account_index
+            + 1 + self.inheritance.as_ref().map_or(0, InheritanceConfig::serialized_size)
+            + 8                              // last_execution_at
+            + 1                              // failed_auth_count
+            + 8                              // locked_until
+            + BORSH_LEN_PREFIX + self.key_history.len() * RETIRED_KEY_SIZE
+            + 1                              // proof_log_enabled
+            + BORSH_LEN_PREFIX
+            + self.additional_policies.iter().map(|policy| BORSH_LEN_PREFIX + policy.len()).sum::<usize>()
+            + 1 + self.passkey_aaguid.map_or(0, |_| AAGUID_LEN)
+            + BORSH_LEN_PREFIX               // empty settings.aaguid_allowlist
+            + 1 + self.pending_recovery.as_ref().and_then(|request| request.new_aaguid).map_or(0, |_| AAGUID_LEN)
+            + 1                              // no settings.pinned_program_version
+            + 1                              // legacy settings.webauthn_profile
+            + 1                              // no settings.relying_party
+            + BORSH_LEN_PREFIX + self.sign_counts.len() * SIGN_COUNT_SIZE
+            + 1 + 1                          // default settings.auth_mode, settings.auth_mode_locked
+            + HASH_LEN                       // policy_hash
+            + DestinationSpends::serialized_size(self.destination_spends.entries.len())
+            + BORSH_LEN_PREFIX               // empty settings.authorized_executors
+            + 8                              // state_version
+            + 1                              // zero settings.log_allowed_sample_rate
+            + 1 + 4 + BORSH_LEN_PREFIX + self.stored_settings_ext().serialized_size()
+            + 1                              // created_layout
+            + EMERGENCY_OVERRIDE_SIZE
+            + 1 + self.account_index.map_or(0, |_| 2)
+    }
+
     /// Converts this account to bytes for storage on-chain
     ///
     /// Uses Borsh serialization which is efficient and deterministic.
@@ -138,19 +1137,180 @@ impl AttestaAccount {
     pub fn from_bytes(data: &[u8]) -> Result<Self, std::io::Error> {
         borsh::from_slice(data)
     }
+
+    /// Reads an account the way the program does: `from_bytes`, then `validate`
+    ///
+    /// Bytes that decode aren't necessarily an account anyone created: a
+    /// zeroed owner or passkey decodes fine, and would only fail much later
+    /// (or, for the owner, never). `from_bytes` stays lenient for migration
+    /// tooling that needs to look at broken accounts.
+    pub fn from_bytes_strict(data: &[u8]) -> Result<Self, AccountValidationError> {
+        let account = Self::from_bytes(data).map_err(|e| AccountValidationError::Serialization(e.to_string()))?;
+        account.validate()?;
+        Ok(account)
+    }
+
+    /// Checks the fields nothing the program writes leaves at zero, and the
+    /// invariants it keeps
+    ///
+    /// Checked in order:
+    /// - `owner` isn't the default pubkey
+    /// - `passkey_public_key` isn't all zeros
+    /// - `credential_id` isn't empty, and is a 32-byte hash in privacy mode
+    /// - there are no `additional_policies` without a `policy`, and no more
+    ///   policies, idempotency records, retired keys or tracked destinations
+    ///   than the account has room for
+    /// - the settings are ones `update_settings` would store
+    /// - the passkey registry, if any, passes `MultiPasskey::validate_strict`
+    ///
+    /// Whether the public key is on the curve isn't checked here: the
+    /// program proved it when the passkey was registered.
+    pub fn validate(&self) -> Result<(), AccountValidationError> {
+        if self.owner == Pubkey::default() {
+            return Err(AccountValidationError::DefaultOwner);
+        }
+        if self.passkey_public_key == [0; P256_PUBKEY_LEN] {
+            return Err(AccountValidationError::ZeroPublicKey);
+        }
+        if self.credential_id.is_empty() {
+            return Err(AccountValidationError::EmptyCredentialId);
+        }
+        if self.privacy_mode && self.credential_id.len() != HASH_LEN {
+            return Err(AccountValidationError::PrivacyCredentialId);
+        }
+
+        if self.policy.is_empty() && !self.additional_policies.is_empty() {
+            return Err(AccountValidationError::OrphanedPolicies);
+        }
+        let counts = [
+            ("policies", self.policies().len(), MAX_ACCOUNT_POLICIES),
+            ("idempotency_records", self.idempotency_records.len(), MAX_IDEMPOTENCY_RECORDS),
+            ("key_history", self.key_history.len(), MAX_KEY_HISTORY),
+            ("destination_spends", self.destination_spends.entries.len(), MAX_TRACKED_DESTINATIONS),
+        ];
+        if let Some(&(field, len, max)) = counts.iter().find(|(_, len, max)| len > max) {
+            return Err(AccountValidationError::TooManyEntries { field, len, max });
+        }
+
+        if !self.settings.is_valid() {
+            return Err(AccountValidationError::InvalidSettings);
+        }
+        self.passkey_registry()?;
+        Ok(())
+    }
+}
+
+/// The policy a transaction must pass on `account`: its only policy, the
+/// `Composite` of several (as `compute_policy_hash` hashes them), or
+/// `Policy::open()` if it has none
+impl TryFrom<&AttestaAccount> for Policy {
+    type Error = AccountPolicyError;
+
+    fn try_from(account: &AttestaAccount) -> Result<Self, Self::Error> {
+        let mut policies = account
+            .policies()
+            .into_iter()
+            .map(Policy::from_bytes)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| AccountPolicyError::Undecodable)?;
+        Ok(match policies.len() {
+            0 => Policy::open(),
+            1 => policies.remove(0),
+            _ => Policy::composite(policies),
+        })
+    }
+}
+
+/// The cluster's Unix timestamp, or `fallback` where there's no clock to read
+///
+/// Off-chain there's no clock sysvar, so callers pass the last time they
+/// know of (usually the account's `updated_at`). Built with the `wasm`
+/// feature, the sysvar is never read and this is always `fallback`.
+pub fn cluster_time(fallback: i64) -> i64 {
+    #[cfg(not(feature = "wasm"))]
+    {
+        use solana_program::{clock::Clock, sysvar::Sysvar};
+        if let Ok(clock) = Clock::get() {
+            return clock.unix_timestamp;
+        }
+    }
+    fallback
+}
+
+/// How long the first lockout lasts, in seconds
+pub const LOCKOUT_BASE_DURATION: i64 = 60;
+
+/// Longest lockout, in seconds (one day)
+pub const MAX_LOCKOUT_DURATION: i64 = 24 * 60 * 60;
+
+/// How long an account stays locked after `failures_past_threshold` more
+/// failures than its threshold: 1 minute, doubling each time, at most a day
+pub fn lockout_duration(failures_past_threshold: u8) -> i64 {
+    // 60 << 11 is already past a day
+    let doublings = failures_past_threshold.min(11) as u32;
+    (LOCKOUT_BASE_DURATION << doublings).min(MAX_LOCKOUT_DURATION)
 }
 
-/// Account discriminator to identify Attesta accounts
-pub const ATTESTA_ACCOUNT_DISCRIMINATOR: [u8; 8] = [0x41, 0x54, 0x54, 0x45, 0x53, 0x54, 0x41, 0x00]; // "ATTESTA\0"
+/// Action name a passkey signs to switch an account to privacy mode
+pub const PRIVACY_MODE_ACTION: &[u8] = b"enable_privacy_mode";
+
+/// Action name a passkey signs to change an account's `AccountSettings`
+pub const SETTINGS_UPDATE_ACTION: &[u8] = b"update_settings";
+
+/// Discriminator of the legacy raw account layout (`storage::AccountLayout::LegacyRaw`)
+///
+/// The program stores accounts under Anchor's discriminator instead; this is
+/// only used to read accounts written in the old layout.
+pub const ATTESTA_ACCOUNT_DISCRIMINATOR: [u8; ACCOUNT_DISCRIMINATOR_LEN] = [0x41, 0x54, 0x54, 0x45, 0x53, 0x54, 0x41, 0x00]; // "ATTESTA\0"
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use proptest::prelude::*;
     use solana_program::pubkey::Pubkey;
 
+    /// Bytes the empty fields stored after the rest take at the end of an
+    /// account: no passkey AAGUID (1), an empty allowlist (4), no recovery
+    /// AAGUID (1), no pinned program version (1), the legacy profile (1), no
+    /// relying party (1), no sign counts (4), passkey-only auth (1), unlocked (1),
+    /// the policy hash, an empty destination tracker, no executors, the
+    /// state version, the sample rate, default compact settings, the
+    /// created layout, the emergency override and no account index
+    const EMPTY_TRAILING_FIELDS_LEN: usize = 1 + 4 + 1 + 1 + 1 + 1 + 4 + 1 + 1 + HASH_LEN
+        + EMPTY_DESTINATION_SPENDS_LEN + EMPTY_EXECUTORS_LEN + STATE_VERSION_LEN + SAMPLE_RATE_LEN + DEFAULT_COMPACT_SETTINGS_LEN + CREATED_LAYOUT_LEN + EMERGENCY_OVERRIDE_SIZE
+        + NO_ACCOUNT_INDEX_LEN;
+
+    /// `account_index` of an account at the owner's own address
+    const NO_ACCOUNT_INDEX_LEN: usize = 1;
+
+    /// An empty `DestinationSpends`: window start, no entries, nothing evicted
+    const EMPTY_DESTINATION_SPENDS_LEN: usize = 8 + 4 + 8;
+
+    /// An empty executor list
+    const EMPTY_EXECUTORS_LEN: usize = 4;
+
+    /// `state_version`
+    const STATE_VERSION_LEN: usize = 8;
+
+    /// `settings.log_allowed_sample_rate`
+    const SAMPLE_RATE_LEN: usize = 1;
+
+    /// The compact settings section holding only defaults: present (1), no
+    /// flags (4), then the legacy profile as the only entry (4 + 4)
+    const DEFAULT_COMPACT_SETTINGS_LEN: usize = 1 + 4 + 4 + 4;
+
+    /// `created_layout`
+    const CREATED_LAYOUT_LEN: usize = 1;
+
+    /// Bytes `account`'s compact settings section takes
+    fn compact_settings_len(account: &AttestaAccount) -> usize {
+        1 + 4 + 4 + account.stored_settings_ext().serialized_size()
+    }
+
     fn create_test_account() -> AttestaAccount {
         let owner = Pubkey::new_unique();
-        let passkey_pubkey = [42u8; 64];
+        let passkey_pubkey = TestPasskey::new(42).public_key();
         let credential_id = b"test_credential".to_vec();
         let policy = vec![];
         let created_at = 1234567890i64;
@@ -176,11 +1336,12 @@ mod tests {
         let mut account = create_test_account();
         assert_eq!(account.nonce, 0);
 
-        account.increment_nonce();
+        account.increment_nonce(100);
         assert_eq!(account.nonce, 1);
 
-        account.increment_nonce();
+        account.increment_nonce(200);
         assert_eq!(account.nonce, 2);
+        assert_eq!(account.updated_at, 200);
     }
 
     #[test]
@@ -198,7 +1359,7 @@ mod tests {
         assert!(account.validate_nonce(2));
         assert!(!account.validate_nonce(0)); // Less than current - invalid
 
-        account.increment_nonce(); // Now nonce is 1
+        account.increment_nonce(100); // Now nonce is 1
         assert!(!account.validate_nonce(1)); // Equal to current - invalid
         assert!(account.validate_nonce(2)); // Greater than current - valid
     }
@@ -216,8 +1377,8 @@ mod tests {
     #[test]
     fn test_serialize_deserialize_with_data() {
         let mut account = create_test_account();
-        account.increment_nonce();
-        account.increment_nonce();
+        account.increment_nonce(100);
+        account.increment_nonce(100);
         
         let bytes = account.to_bytes().unwrap();
         let deserialized = AttestaAccount::from_bytes(&bytes).unwrap();
@@ -225,4 +1386,690 @@ mod tests {
         assert_eq!(account.nonce, deserialized.nonce);
         assert_eq!(account.passkey_public_key, deserialized.passkey_public_key);
     }
+
+    #[test]
+    fn test_deserialize_account_without_passkey_registry() {
+        let account = create_test_account();
+
+        // Accounts created before the registry existed end after `updated_at`
+        // (empty registry: 4-byte length, privacy flag: 1 byte, no idempotency records: 4-byte length,
+        // no pending recovery or drill: 1 byte each, last drill: 8 bytes, settings,
+        // no parent: 1 byte, sub-account index: 1 byte, no inheritance: 1 byte, last execution: 8 bytes,
+        // failed auth count: 1 byte, locked until: 8 bytes)
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - 4 - 1 - 4 - 1 - 1 - 8 - AccountSettings::SERIALIZED_SIZE - 1 - 1 - 1 - 8 - 1 - 8 - 4 - 1 - 4 - EMPTY_TRAILING_FIELDS_LEN);
+
+        let deserialized = AttestaAccount::from_bytes(&bytes).unwrap();
+        assert_eq!(AttestaAccount { created_layout: 0, ..account.clone() }, deserialized);
+        assert!(deserialized.passkey_registry().unwrap().is_none());
+
+        // A truncated registry is still rejected
+        let mut registry_account = account.clone();
+        registry_account.set_passkey_registry(&registry_account.passkey_registry_or_default().unwrap()).unwrap();
+        let bytes = registry_account.to_bytes().unwrap();
+        let registry_end = bytes.len() - 1 - 4 - 1 - 1 - 8 - AccountSettings::SERIALIZED_SIZE - 1 - 1 - 1 - 8 - 1 - 8 - 4 - 1 - 4 - EMPTY_TRAILING_FIELDS_LEN;
+        assert!(AttestaAccount::from_bytes(&bytes[..registry_end - 2]).is_err());
+    }
+
+    #[test]
+    fn test_enable_privacy_mode_hashes_credentials() {
+        let mut account = create_test_account();
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(TestPasskey::new(7).public_key(), b"laptop".to_vec(), "Laptop".to_string(), 10).unwrap();
+        registry.add_passkey(TestPasskey::new(8).public_key(), b"old-phone".to_vec(), "Old phone".to_string(), 10).unwrap();
+        registry.remove_passkey(b"old-phone", 20).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        account.enable_privacy_mode().unwrap();
+
+        assert!(account.privacy_mode);
+        assert_eq!(account.credential_id, credential_id_hash(b"test_credential").to_vec());
+
+        let registry = account.passkey_registry().unwrap().unwrap();
+        assert!(registry.find_passkey(b"laptop").is_none());
+        assert!(registry.find_passkey(&account.credential_lookup_id(b"laptop")).is_some());
+        assert!(registry.is_revoked(&account.credential_lookup_id(b"old-phone")));
+
+        // Enabling twice doesn't hash twice
+        let before = account.clone();
+        account.enable_privacy_mode().unwrap();
+        assert_eq!(account, before);
+
+        // The flag survives serialization
+        let restored = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        assert!(restored.privacy_mode);
+    }
+
+    #[test]
+    fn test_credential_history_by_hash() {
+        let mut account = create_test_account();
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(TestPasskey::new(7).public_key(), b"laptop".to_vec(), "Laptop".to_string(), 10).unwrap();
+        registry.add_passkey(TestPasskey::new(8).public_key(), b"old-phone".to_vec(), "Old phone".to_string(), 10).unwrap();
+        registry.remove_passkey(b"old-phone", 20).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        // Privacy mode rehashes the tombstones, but the answers stay the same
+        for private in [false, true] {
+            if private {
+                account.enable_privacy_mode().unwrap();
+            }
+            assert!(account.holds_credential_hash(&credential_id_hash(b"laptop")));
+            assert!(account.holds_credential_hash(&credential_id_hash(b"test_credential")));
+            assert!(!account.holds_credential_hash(&credential_id_hash(b"old-phone")));
+            assert_eq!(account.credential_revoked_at(&credential_id_hash(b"old-phone")), Some(20));
+            assert_eq!(account.credential_revoked_at(&credential_id_hash(b"laptop")), None);
+            assert_eq!(account.credential_revoked_at(&credential_id_hash(b"stranger")), None);
+        }
+    }
+
+    #[test]
+    fn test_set_passkey_registry_rejects_invalid_registry() {
+        let mut account = create_test_account();
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey([7u8; 64], b"laptop".to_vec(), "Laptop".to_string(), 10).unwrap();
+
+        assert_eq!(
+            account.set_passkey_registry(&registry),
+            Err(MultiPasskeyError::InvalidPublicKey { index: 1 })
+        );
+        assert!(account.passkeys.is_empty());
+    }
+
+    #[test]
+    fn test_set_policy_rejects_what_raw_assignment_allowed() {
+        let mut account = create_test_account();
+
+        let zero = Policy::spending_limit(recovery::Amount::ZERO);
+        assert_eq!(account.set_policy(&zero), Err(AccountPolicyError::Invalid(PolicyBuildError::ZeroLimit)));
+
+        let signers: Vec<Pubkey> = (0..16).map(|_| Pubkey::new_unique()).collect();
+        let wide = Policy::multi_sig(signers);
+        assert!(wide.validate_config().is_ok());
+        assert_eq!(account.set_policy(&wide), Err(AccountPolicyError::TooLong { len: wide.serialized_size() }));
+
+        assert!(account.policy().is_empty());
+        assert_eq!(account.policy_hash, Policy::open().canonical_hash());
+    }
+
+    #[test]
+    fn test_with_policy_stores_and_decodes() {
+        let lock = Policy::time_locked(1_700_000_000);
+        let mut account = create_test_account().with_policy(lock.clone()).unwrap();
+        assert_eq!(account.policy(), lock.to_bytes().unwrap().as_slice());
+        assert_eq!(account.policy_hash, lock.canonical_hash());
+        assert_eq!(Policy::try_from(&account), Ok(lock.clone()));
+
+        // Setting one policy drops the others
+        let later = Policy::time_locked(1_800_000_000);
+        account.set_policies(vec![lock.to_bytes().unwrap(), later.to_bytes().unwrap()]);
+        assert_eq!(Policy::try_from(&account), Ok(Policy::composite(vec![lock.clone(), later.clone()])));
+        account.set_policy(&later).unwrap();
+        assert!(account.additional_policies.is_empty());
+
+        account.set_policies(vec![]);
+        assert_eq!(Policy::try_from(&account), Ok(Policy::open()));
+        account.set_policies(vec![vec![0xff, 1, 2]]);
+        assert_eq!(Policy::try_from(&account), Err(AccountPolicyError::Undecodable));
+    }
+
+    #[test]
+    fn test_idempotency_records_are_bounded() {
+        let mut account = create_test_account();
+        for i in 0..(MAX_IDEMPOTENCY_RECORDS as u64 + 2) {
+            account.record_idempotency_key([i as u8; 16], [0u8; 32], i + 1);
+        }
+
+        assert_eq!(account.idempotency_records.len(), MAX_IDEMPOTENCY_RECORDS);
+        // The oldest keys were dropped first
+        assert!(account.find_idempotency_record(&[0u8; 16]).is_none());
+        assert_eq!(account.find_idempotency_record(&[9u8; 16]).unwrap().nonce, 10);
+
+        let restored = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.idempotency_records, account.idempotency_records);
+    }
+
+    #[test]
+    fn test_passkey_registry_round_trip() {
+        let mut account = create_test_account();
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        assert_eq!(registry.primary.credential_id, account.credential_id);
+
+        registry.add_passkey(TestPasskey::new(7).public_key(), b"laptop".to_vec(), "Laptop".to_string(), 10).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        let restored = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        let restored_registry = restored.passkey_registry().unwrap().unwrap();
+        assert!(restored_registry.find_passkey(b"laptop").is_some());
+    }
+
+    #[test]
+    fn test_strict_from_bytes_rejects_zeroed_fields() {
+        let good = create_test_account();
+        assert_eq!(AttestaAccount::from_bytes_strict(&good.to_bytes().unwrap()), Ok(good.clone()));
+
+        let mut registry = good.passkey_registry_or_default().unwrap();
+        registry.add_passkey(TestPasskey::new(7).public_key(), b"laptop".to_vec(), "Laptop".to_string(), 10).unwrap();
+        registry.additional[0].credential_id.clear();
+        let too_many = MAX_IDEMPOTENCY_RECORDS + 1;
+
+        let cases: Vec<(fn(&mut AttestaAccount), AccountValidationError)> = vec![
+            (|a| a.owner = Pubkey::default(), AccountValidationError::DefaultOwner),
+            (|a| a.passkey_public_key = [0; P256_PUBKEY_LEN], AccountValidationError::ZeroPublicKey),
+            (|a| a.credential_id.clear(), AccountValidationError::EmptyCredentialId),
+            (|a| a.privacy_mode = true, AccountValidationError::PrivacyCredentialId),
+            (|a| a.additional_policies = vec![vec![0]], AccountValidationError::OrphanedPolicies),
+            (|a| a.settings.log_allowed_sample_rate = MAX_SAMPLE_RATE + 1, AccountValidationError::InvalidSettings),
+        ];
+        let mut broken: Vec<(AttestaAccount, AccountValidationError)> = cases
+            .into_iter()
+            .map(|(edit, error)| {
+                let mut account = good.clone();
+                edit(&mut account);
+                (account, error)
+            })
+            .collect();
+        let mut records = good.clone();
+        records.idempotency_records = vec![IdempotencyRecord { key: [1; 16], message_hash: [2; 32], nonce: 3 }; too_many];
+        let error = AccountValidationError::TooManyEntries { field: "idempotency_records", len: too_many, max: MAX_IDEMPOTENCY_RECORDS };
+        broken.push((records, error));
+        let mut empty_id = good.clone();
+        empty_id.passkeys = borsh::to_vec(&registry).unwrap();
+        broken.push((empty_id, AccountValidationError::InvalidPasskeys(MultiPasskeyError::EmptyCredentialId { index: 1 })));
+
+        for (account, error) in broken {
+            let bytes = account.to_bytes().unwrap();
+            assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account, "{}", error);
+            assert_eq!(AttestaAccount::from_bytes_strict(&bytes), Err(error));
+        }
+
+        // A zeroed account decodes, and fails on its owner first
+        let zeroed = AttestaAccount::new(Pubkey::default(), [0; P256_PUBKEY_LEN], vec![], vec![], 0).to_bytes().unwrap();
+        assert!(AttestaAccount::from_bytes(&zeroed).is_ok());
+        assert_eq!(AttestaAccount::from_bytes_strict(&zeroed), Err(AccountValidationError::DefaultOwner));
+        assert!(matches!(AttestaAccount::from_bytes_strict(&zeroed[..10]), Err(AccountValidationError::Serialization(_))));
+
+        // A registry with an empty ID can't be set either
+        let mut account = good;
+        assert_eq!(account.set_passkey_registry(&registry), Err(MultiPasskeyError::EmptyCredentialId { index: 1 }));
+    }
+
+    #[test]
+    fn test_serialized_size_matches_to_bytes() {
+        let empty = AttestaAccount::new(Pubkey::new_unique(), [0u8; 64], vec![], vec![], 0);
+
+        let mut full = create_test_account();
+        full.credential_id = vec![1; 1023];
+        full.policy = vec![2; 256];
+        full.passkeys = vec![3; 4096];
+        full.privacy_mode = true;
+        for i in 0..MAX_IDEMPOTENCY_RECORDS as u8 {
+            full.record_idempotency_key([i; 16], [i; 32], i as u64);
+        }
+        let mut request = RecoveryRequest::new([5; 64], vec![6; 255], 100);
+        request.approvals = vec![[7; 32]; 5];
+        request.threshold_met_at = Some(200);
+        full.pending_recovery = Some(request.clone());
+        full.pending_drill = Some(RecoveryRequest::new([5; 64], vec![], 100));
+        full.last_drill_at = 300;
+        full.settings = AccountSettings {
+            reject_zero_amount: true,
+            reject_self_transfer: true,
+            max_transaction_data_len: 512,
+            lockout_threshold: 5,
+            aaguid_allowlist: vec![[14; 16], [15; 16]],
+            pinned_program_version: Some([18; 32]),
+            webauthn_profile: WebAuthnVerificationProfile::strict(),
+            relying_party: Some(RelyingParty::new("wallet.example", "https://wallet.example")),
+            auth_mode: AuthMode::PasskeyOnly,
+            auth_mode_locked: true,
+            authorized_executors: vec![Pubkey::new_unique(); MAX_AUTHORIZED_EXECUTORS],
+            log_allowed_sample_rate: MAX_SAMPLE_RATE,
+            recovery_allowance: u64::MAX,
+        };
+        full.parent = Some(Pubkey::new_unique());
+        full.sub_account_index = 7;
+        full.inheritance = Some(InheritanceConfig::new([8; 64], vec![9; 255], 1, 2));
+        full.last_execution_at = 400;
+        full.failed_auth_count = 3;
+        full.locked_until = 500;
+        full.key_history = vec![RetiredKey { credential_id_hash: [10; 32], public_key: [11; 64], retired_at: 600 }; 3];
+        full.proof_log_enabled = true;
+        full.additional_policies = vec![vec![12; 20], vec![13; 40]];
+        full.passkey_aaguid = Some([16; 16]);
+        full.pending_recovery.as_mut().unwrap().new_aaguid = Some([17; 16]);
+        full.sign_counts = vec![SignCount { credential_id_hash: [19; 32], sign_count: 20 }; 2];
+
+        for account in [empty, create_test_account(), full] {
+            assert_eq!(account.serialized_size(), account.to_bytes().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_settings_survive_migration() {
+        let mut account = create_test_account();
+
+        // Accounts written before settings existed get both checks off
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - AccountSettings::SERIALIZED_SIZE - 1 - 1 - 1 - 8 - 1 - 8 - 4 - 1 - 4 - EMPTY_TRAILING_FIELDS_LEN);
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap().settings, AccountSettings::default());
+
+        account.settings = AccountSettings {
+            reject_zero_amount: true,
+            max_transaction_data_len: 300,
+            lockout_threshold: 3,
+            ..Default::default()
+        };
+        let deserialized = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        assert_eq!(deserialized.settings, account.settings);
+        assert_eq!(account.settings.to_bytes(), borsh::to_vec(&account.settings).unwrap());
+    }
+
+    #[test]
+    fn test_aaguid_allowlist() {
+        let mut settings = AccountSettings::default();
+        // An empty list allows every passkey, attested or not
+        assert!(settings.allows_authenticator(None));
+        assert!(settings.allows_authenticator(Some(&[1; 16])));
+
+        settings.aaguid_allowlist = vec![[1; 16], [2; 16]];
+        assert!(settings.allows_authenticator(Some(&[2; 16])));
+        assert!(!settings.allows_authenticator(Some(&[3; 16])));
+        assert!(!settings.allows_authenticator(None));
+
+        // Signed along with the other settings, and stored with the account
+        assert_eq!(settings.to_bytes().len(), AccountSettings::SERIALIZED_SIZE + 2 * 16);
+        let mut account = create_test_account();
+        account.settings = settings.clone();
+        account.passkey_aaguid = Some([1; 16]);
+        let restored = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.settings, settings);
+        assert_eq!(restored.passkey_registry_or_default().unwrap().primary.aaguid, Some([1; 16]));
+
+        settings.aaguid_allowlist = vec![[0; 16]; MAX_AAGUID_ALLOWLIST_LEN + 1];
+        assert!(!settings.is_valid());
+    }
+
+    #[test]
+    fn test_webauthn_profile_is_stored_and_signed() {
+        let mut account = create_test_account();
+        let legacy = account.settings.clone();
+
+        // Accounts written before profiles existed are legacy
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN - EMPTY_DESTINATION_SPENDS_LEN - HASH_LEN - 1 - 1 - 4 - 1 - 1);
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap().settings.webauthn_profile, WebAuthnVerificationProfile::legacy());
+
+        // A profile that checks the RP ID needs a relying party
+        account.settings.webauthn_profile = WebAuthnVerificationProfile::standard();
+        assert!(!account.settings.is_valid());
+        account.settings.relying_party = Some(RelyingParty::new("wallet.example", "https://wallet.example"));
+        assert!(account.settings.is_valid());
+        assert!(legacy.changes_webauthn_checks(&account.settings));
+
+        // Signed after everything else: a marker, the bits, then the relying party
+        let signed = account.settings.to_bytes();
+        assert_eq!(signed.len(), AccountSettings::SERIALIZED_SIZE + 2 + 64);
+        assert_eq!(signed[AccountSettings::SERIALIZED_SIZE..][..2], [2, WebAuthnVerificationProfile::standard().to_bits()]);
+        let restored = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.settings, account.settings);
+
+        // A check this version doesn't know about isn't dropped on read,
+        // whether it's stored in the compact section or where it used to be
+        let mut bytes = account.to_bytes().unwrap();
+        let compact_len = compact_settings_len(&account);
+        let profile_entry = bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - compact_len + 1 + 4 + 4 + 3;
+        assert_eq!(bytes[profile_entry - 3], crate::settings::tags::WEBAUTHN_PROFILE);
+        bytes[profile_entry] = 1 << 7;
+        assert!(AttestaAccount::from_bytes(&bytes).is_err());
+
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - compact_len);
+        let profile_offset = bytes.len() - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN - EMPTY_DESTINATION_SPENDS_LEN - HASH_LEN - 1 - 1 - 4 - 1 - 1;
+        bytes[profile_offset] = 1 << 7;
+        assert!(AttestaAccount::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_auth_mode_is_stored_before_policy_hash() {
+        let mut account = create_test_account();
+
+        // Accounts written before auth modes existed are passkey-only
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN - EMPTY_DESTINATION_SPENDS_LEN - HASH_LEN - 1 - 1);
+        let migrated = AttestaAccount::from_bytes(&bytes).unwrap();
+        assert_eq!(migrated.settings.auth_mode, AuthMode::PasskeyOnly);
+        assert!(!migrated.settings.auth_mode_locked);
+
+        // Accounts written before the compact section keep the mode stored where it was
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN);
+        let mode_offset = bytes.len() - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN - EMPTY_DESTINATION_SPENDS_LEN - HASH_LEN - 2;
+        assert_eq!(bytes[mode_offset..mode_offset + 2], [0, 0]);
+        bytes[mode_offset] = 2;
+        account.settings.auth_mode = AuthMode::OwnerOnly;
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap().settings, account.settings);
+        assert_eq!(AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap().settings, account.settings);
+        // Not part of what a settings update signs
+        assert_eq!(account.settings.to_bytes(), AccountSettings::default().to_bytes());
+
+        // A mode this version doesn't know isn't read as a known one
+        bytes[mode_offset] = 3;
+        assert!(AttestaAccount::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_policy_hash_tracks_the_policies() {
+        let mut account = create_test_account();
+        assert_eq!(account.policy_hash, Policy::open().canonical_hash());
+
+        let limit = Policy::time_locked(100).to_bytes().unwrap();
+        account.set_policies(vec![limit.clone()]);
+        assert_eq!(account.policy_hash, Policy::time_locked(100).canonical_hash());
+        assert_eq!(AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap().policy_hash, account.policy_hash);
+
+        account.set_policies(vec![limit, Policy::time_locked(200).to_bytes().unwrap()]);
+        assert_ne!(account.policy_hash, Policy::time_locked(100).canonical_hash());
+        assert_eq!(account.policy_hash, account.compute_policy_hash());
+
+        // Accounts written before the hash existed get it computed when read
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN - EMPTY_DESTINATION_SPENDS_LEN - HASH_LEN);
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap().policy_hash, account.policy_hash);
+    }
+
+    #[test]
+    fn test_destination_spends_headroom() {
+        let mut account = create_test_account();
+        assert_eq!(account.destination_spends_headroom(), 0);
+
+        let limits = Policy::per_destination_limit(recovery::DestinationLimits {
+            limits: vec![],
+            default_max_amount: 100,
+            window_seconds: 3_600,
+            anchor_timestamp: 100,
+        });
+        account.set_policies(vec![
+            Policy::time_locked(100).to_bytes().unwrap(),
+            Policy::composite(vec![limits]).to_bytes().unwrap(),
+        ]);
+        assert_eq!(account.destination_spends_headroom(), MAX_TRACKED_DESTINATIONS * DESTINATION_SPEND_SIZE);
+
+        account.destination_spends.entries.push(recovery::DestinationSpend { destination_hash: [7; 32], spent: 50 });
+        assert_eq!(account.destination_spends_headroom(), (MAX_TRACKED_DESTINATIONS - 1) * DESTINATION_SPEND_SIZE);
+    }
+
+    #[test]
+    fn test_destination_spends_are_stored_last() {
+        let mut account = create_test_account();
+        account.destination_spends = DestinationSpends {
+            window_start: 100,
+            entries: vec![recovery::DestinationSpend { destination_hash: [7; 32], spent: 50 }],
+            evicted: 10,
+        };
+        let bytes = account.to_bytes().unwrap();
+        assert_eq!(bytes.len(), account.serialized_size());
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account);
+
+        // Accounts written before the tracker existed start with nothing sent
+        let mut bytes = bytes;
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN - DestinationSpends::serialized_size(1));
+        let migrated = AttestaAccount::from_bytes(&bytes).unwrap();
+        assert_eq!(migrated.destination_spends, DestinationSpends::default());
+        assert_eq!(migrated.policy_hash, account.policy_hash);
+    }
+
+    #[test]
+    fn test_executors_are_stored_last() {
+        let mut account = create_test_account();
+        account.settings.authorized_executors = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let bytes = account.to_bytes().unwrap();
+        assert_eq!(bytes.len(), account.serialized_size());
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account);
+
+        // Accounts written before the list existed stay permissionless
+        let mut bytes = bytes;
+        bytes.truncate(bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - compact_settings_len(&account) - SAMPLE_RATE_LEN - STATE_VERSION_LEN - EMPTY_EXECUTORS_LEN);
+        let migrated = AttestaAccount::from_bytes(&bytes).unwrap();
+        assert!(migrated.settings.authorized_executors.is_empty());
+        assert_eq!(migrated.destination_spends, account.destination_spends);
+
+        account.settings.authorized_executors = vec![Pubkey::new_unique(); MAX_AUTHORIZED_EXECUTORS + 1];
+        assert!(!account.settings.is_valid());
+    }
+
+    #[test]
+    fn test_state_version_is_stored_last() {
+        let mut account = create_test_account();
+        assert_eq!(account.state_version, 0);
+        account.bump_state_version();
+        account.bump_state_version();
+        assert!(account.is_at_version(2));
+        assert!(!account.is_at_version(1));
+
+        let bytes = account.to_bytes().unwrap();
+        assert_eq!(bytes.len(), account.serialized_size());
+        let version_end = bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN - SAMPLE_RATE_LEN;
+        assert_eq!(bytes[version_end - STATE_VERSION_LEN..version_end], 2u64.to_le_bytes());
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account);
+
+        // Accounts written before the version existed start at 0
+        let migrated = AttestaAccount::from_bytes(&bytes[..version_end - STATE_VERSION_LEN]).unwrap();
+        assert_eq!(migrated.state_version, 0);
+        assert_eq!(migrated.settings, account.settings);
+    }
+
+    #[test]
+    fn test_sample_rate_is_stored_and_signed() {
+        let mut account = create_test_account();
+        account.settings.log_allowed_sample_rate = 25;
+        let bytes = account.to_bytes().unwrap();
+        assert_eq!(bytes.len(), account.serialized_size());
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account);
+
+        // Accounts written before the compact section kept it in its own byte
+        let mut legacy = bytes[..bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - compact_settings_len(&account)].to_vec();
+        assert_eq!(legacy.pop(), Some(0));
+        let migrated = AttestaAccount::from_bytes(&legacy).unwrap();
+        assert_eq!(migrated.settings.log_allowed_sample_rate, 0, "accounts written before sampling log nothing extra");
+        legacy.push(25);
+        assert_eq!(AttestaAccount::from_bytes(&legacy).unwrap(), AttestaAccount { created_layout: 0, ..account.clone() });
+
+        // Part of what a settings update signs, but only once it's on
+        let off = AccountSettings::default().to_bytes();
+        assert_eq!(account.settings.to_bytes(), [off.as_slice(), &[3, 25]].concat());
+
+        account.settings.log_allowed_sample_rate = 101;
+        assert!(!account.settings.is_valid());
+    }
+
+    #[test]
+    fn test_settings_are_stored_in_the_compact_section() {
+        let mut account = create_test_account();
+        account.settings = AccountSettings {
+            reject_self_transfer: true,
+            lockout_threshold: 4,
+            aaguid_allowlist: vec![[1; 16]],
+            pinned_program_version: Some([2; 32]),
+            auth_mode: AuthMode::OwnerOrPasskey,
+            authorized_executors: vec![Pubkey::new_unique()],
+            ..AccountSettings::default()
+        };
+        let bytes = account.to_bytes().unwrap();
+        assert_eq!(bytes.len(), account.serialized_size());
+        assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account);
+
+        // Only the section differs from an account with default settings
+        let mut defaults = account.clone();
+        defaults.settings = AccountSettings::default();
+        let default_bytes = defaults.to_bytes().unwrap();
+        let section = bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - compact_settings_len(&account);
+        assert_eq!(bytes[..section], default_bytes[..default_bytes.len() - NO_ACCOUNT_INDEX_LEN - EMERGENCY_OVERRIDE_SIZE - CREATED_LAYOUT_LEN - DEFAULT_COMPACT_SETTINGS_LEN]);
+        let flags = account.settings.flags().to_le_bytes();
+        assert_eq!(bytes[section..section + 5], [1, flags[0], flags[1], flags[2], flags[3]]);
+
+        // A section that doesn't parse fails the read rather than reverting to defaults
+        let mut bytes = bytes;
+        bytes[section + 1] = 0xff;
+        assert!(AttestaAccount::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_sign_counts_follow_the_passkeys() {
+        let mut phone = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        account.record_sign_count(&phone.sign(&[1; 32]));
+        assert!(account.sign_counts.is_empty(), "only kept when the profile checks them");
+
+        account.settings.webauthn_profile.check_sign_count = true;
+        account.record_sign_count(&phone.sign(&[1; 32]));
+        assert_eq!(account.webauthn_expectations(&phone.credential_id()).previous_sign_count, 2);
+
+        // Counters of passkeys that are gone are dropped when a new one is seen
+        account.sign_counts.push(SignCount { credential_id_hash: [9; 32], sign_count: 7 });
+        let mut laptop = TestPasskey::new(3);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 110).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+        account.record_sign_count(&laptop.sign(&[1; 32]));
+        let hashes: Vec<_> = account.sign_counts.iter().map(|count| count.credential_id_hash).collect();
+        assert_eq!(hashes, vec![credential_id_hash(&phone.credential_id()), credential_id_hash(&laptop.credential_id())]);
+    }
+
+    #[test]
+    fn test_policies_in_order() {
+        let mut account = create_test_account();
+        account.policy = vec![];
+        assert!(account.policies().is_empty());
+
+        // A single-policy account is a one-element list, with nothing to migrate
+        account.policy = vec![1; 10];
+        let mut bytes = account.to_bytes().unwrap();
+        bytes.truncate(bytes.len() - 4 - EMPTY_TRAILING_FIELDS_LEN);
+        let migrated = AttestaAccount::from_bytes(&bytes).unwrap();
+        assert_eq!(migrated.policies(), vec![&[1u8; 10][..]]);
+
+        account.set_policies(vec![vec![2; 5], vec![], vec![3; 6], vec![4; 7]]);
+        assert_eq!(account.policies(), vec![&[2u8; 5][..], &[3u8; 6][..], &[4u8; 7][..]]);
+        assert_eq!(account.policy, vec![2; 5]);
+
+        account.set_policies(vec![]);
+        assert!(account.policy.is_empty());
+        assert!(account.additional_policies.is_empty());
+    }
+
+    #[test]
+    fn test_transaction_data_limit_override() {
+        let mut settings = AccountSettings::default();
+        assert_eq!(settings.transaction_data_limit(), MAX_TRANSACTION_DATA_LEN);
+
+        // Lowering the bound
+        settings.max_transaction_data_len = 100;
+        assert_eq!(settings.check_transaction_data_len(100), Ok(()));
+        assert_eq!(
+            settings.check_transaction_data_len(101),
+            Err(TransactionRequestError::TooLarge { len: 101, max: 100 })
+        );
+
+        // Raising it again, up to the global limit but not past it
+        settings.max_transaction_data_len = MAX_TRANSACTION_DATA_LEN as u16;
+        assert!(settings.is_valid());
+        assert_eq!(settings.check_transaction_data_len(MAX_TRANSACTION_DATA_LEN), Ok(()));
+
+        settings.max_transaction_data_len = MAX_TRANSACTION_DATA_LEN as u16 + 1;
+        assert!(!settings.is_valid());
+        assert_eq!(settings.transaction_data_limit(), MAX_TRANSACTION_DATA_LEN);
+        assert!(settings.check_transaction_data_len(MAX_TRANSACTION_DATA_LEN + 1).is_err());
+    }
+
+    #[test]
+    fn test_lockout_is_opt_in() {
+        let mut account = create_test_account();
+        assert_eq!(account.settings.lockout_threshold, 0);
+
+        for _ in 0..100 {
+            account.record_failed_auth(1000);
+        }
+        assert_eq!(account.failed_auth_count, 0);
+        assert!(!account.is_locked_out(1000));
+    }
+
+    #[test]
+    fn test_lockout_boundary_and_backoff() {
+        let mut account = create_test_account();
+        account.settings.lockout_threshold = 3;
+
+        account.record_failed_auth(1000);
+        account.record_failed_auth(1000);
+        assert!(!account.is_locked_out(1000));
+
+        // The third failure locks for the base duration, up to but not including its end
+        account.record_failed_auth(1000);
+        assert!(account.is_locked_out(1000));
+        assert!(account.is_locked_out(1000 + LOCKOUT_BASE_DURATION - 1));
+        assert!(!account.is_locked_out(1000 + LOCKOUT_BASE_DURATION));
+
+        // Each failure after that doubles it
+        account.record_failed_auth(2000);
+        assert_eq!(account.locked_until, 2000 + 2 * LOCKOUT_BASE_DURATION);
+        account.record_failed_auth(3000);
+        assert_eq!(account.locked_until, 3000 + 4 * LOCKOUT_BASE_DURATION);
+    }
+
+    #[test]
+    fn test_lockout_duration_is_capped() {
+        assert_eq!(lockout_duration(0), LOCKOUT_BASE_DURATION);
+        assert_eq!(lockout_duration(10), LOCKOUT_BASE_DURATION << 10);
+        assert_eq!(lockout_duration(11), MAX_LOCKOUT_DURATION);
+        assert_eq!(lockout_duration(u8::MAX), MAX_LOCKOUT_DURATION);
+
+        let mut account = create_test_account();
+        account.settings.lockout_threshold = 1;
+        account.failed_auth_count = u8::MAX;
+        account.record_failed_auth(0);
+        assert_eq!(account.failed_auth_count, u8::MAX);
+        assert_eq!(account.locked_until, MAX_LOCKOUT_DURATION);
+    }
+
+    #[test]
+    fn test_reset_failed_auth() {
+        let mut account = create_test_account();
+        account.settings.lockout_threshold = 2;
+        account.record_failed_auth(1000);
+        account.record_failed_auth(1000);
+        assert!(account.is_locked_out(1000));
+
+        account.reset_failed_auth();
+        assert_eq!(account.failed_auth_count, 0);
+        assert!(!account.is_locked_out(1000));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_serialized_size_matches_to_bytes(
+            credential_id in prop::collection::vec(any::<u8>(), 0..256),
+            policy in prop::collection::vec(any::<u8>(), 0..256),
+            passkeys in prop::collection::vec(any::<u8>(), 0..1024),
+            privacy_mode in any::<bool>(),
+            records in prop::collection::vec((any::<[u8; 16]>(), any::<u64>()), 0..=MAX_IDEMPOTENCY_RECORDS),
+            recovery in prop::option::of((prop::collection::vec(any::<u8>(), 0..64), 0..5usize, any::<Option<i64>>())),
+        ) {
+            let mut account = AttestaAccount::new(Pubkey::new_unique(), [4u8; 64], credential_id, policy, 0);
+            account.passkeys = passkeys;
+            account.privacy_mode = privacy_mode;
+            for (key, nonce) in records {
+                account.record_idempotency_key(key, [0u8; 32], nonce);
+            }
+            account.pending_recovery = recovery.map(|(credential_id, approvals, threshold_met_at)| RecoveryRequest {
+                new_public_key: [4u8; 64],
+                new_credential_id: credential_id,
+                approvals: vec![[1u8; 32]; approvals],
+                initiated_at: 0,
+                threshold_met_at,
+                new_aaguid: None,
+            });
+
+            prop_assert_eq!(account.serialized_size(), account.to_bytes().unwrap().len());
+        }
+    }
 }
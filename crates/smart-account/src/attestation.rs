@@ -0,0 +1,252 @@
+//! Proof of an account's policy for third parties, without spend rights
+//!
+//! A lender who wants to know that a borrower's account has, say, a 30-day
+//! time lock shouldn't need the borrower to execute anything. The owner's
+//! passkey signs the account's current `policy_hash` and an expiry with
+//! `POLICY_ATTEST_ACTION`, and the program writes the `PolicyAttestation`
+//! into a PDA it owns, so nobody else could have written it. Reading it
+//! takes one account fetch and grants nothing.
+//!
+//! Attestations are never revoked explicitly. One holds only while the
+//! account still has the policy it names: `check_attestation` fails as soon
+//! as the account's `policy_hash` changes, and after the expiry.
+
+use attesta_types::consts::{HASH_LEN, PUBKEY_LEN};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_action;
+
+/// Action name a passkey signs, over `attestation_payload`, to attest the account's policy
+pub const POLICY_ATTEST_ACTION: &[u8] = b"attest_policy";
+
+/// Longest an attestation may stay valid, in seconds (a year)
+pub const MAX_ATTESTATION_LIFETIME: i64 = 365 * 24 * 60 * 60;
+
+/// Errors from attesting a policy or checking an attestation
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AttestationError {
+    #[error("Attestation signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("An attestation must expire after {now} and at most {MAX_ATTESTATION_LIFETIME} seconds later, not at {expires_at}")]
+    InvalidExpiry { expires_at: i64, now: i64 },
+
+    #[error("The attestation is for another account")]
+    WrongAccount,
+
+    #[error("The attestation is for a different policy than expected")]
+    UnexpectedPolicy,
+
+    #[error("The attestation expired at {expires_at}")]
+    Expired { expires_at: i64 },
+
+    #[error("The account's policy has changed since it was attested")]
+    PolicyChanged,
+
+    #[error("Invalid attestation data")]
+    InvalidData,
+}
+
+/// A program-written record that an account had a policy
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PolicyAttestation {
+    /// The Attesta account the attestation is about
+    pub attesta_account: Pubkey,
+
+    /// The account's `policy_hash` when it was attested
+    pub policy_hash: [u8; HASH_LEN],
+
+    /// When it was attested (Unix timestamp)
+    pub attested_at: i64,
+
+    /// When it stops counting (Unix timestamp)
+    pub expires_at: i64,
+
+    /// The nonce that authorized it, which also keys its PDA
+    pub nonce: u64,
+}
+
+impl PolicyAttestation {
+    /// Bytes a serialized attestation takes
+    pub const SERIALIZED_SIZE: usize = PUBKEY_LEN + HASH_LEN + 8 + 8 + 8;
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, AttestationError> {
+        borsh::to_vec(self).map_err(|_| AttestationError::InvalidData)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, AttestationError> {
+        borsh::from_slice(data).map_err(|_| AttestationError::InvalidData)
+    }
+}
+
+/// The payload a passkey signs with `POLICY_ATTEST_ACTION`
+///
+/// The policy hash it vouches for, then the expiry.
+pub fn attestation_payload(policy_hash: &[u8; HASH_LEN], expires_at: i64) -> Vec<u8> {
+    [&policy_hash[..], &expires_at.to_le_bytes()].concat()
+}
+
+/// Checks the owner's signature and returns the attestation to store
+///
+/// Uses up `nonce`. The attestation is of the account's policies as they
+/// are now.
+///
+/// # Parameters
+/// - `webauthn_sig`: The owner's signature over `POLICY_ATTEST_ACTION` for
+///   `attestation_payload(&account.policy_hash, expires_at)`
+/// - `expires_at`: When the attestation lapses, after `now` and at most
+///   `MAX_ATTESTATION_LIFETIME` later
+pub fn attest_policy(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    expires_at: i64,
+    now: i64,
+) -> Result<PolicyAttestation, AttestationError> {
+    if expires_at <= now || expires_at.saturating_sub(now) > MAX_ATTESTATION_LIFETIME {
+        return Err(AttestationError::InvalidExpiry { expires_at, now });
+    }
+
+    let policy_hash = account.policy_hash;
+    authorize_action(account, webauthn_sig, nonce, POLICY_ATTEST_ACTION, &attestation_payload(&policy_hash, expires_at))?;
+
+    Ok(PolicyAttestation { attesta_account: *account_address, policy_hash, attested_at: now, expires_at, nonce })
+}
+
+/// Checks that an attestation still vouches for `expected_policy_hash`
+///
+/// # Parameters
+/// - `account_address`, `account`: The attested account as it is now
+/// - `expected_policy_hash`: The `Policy::canonical_hash` (or
+///   `AttestaAccount::compute_policy_hash`) of the policy the verifier requires
+///
+/// # Returns
+/// - `Ok(())` if it's about this account and that policy, hasn't expired,
+///   and the account still has the policy
+/// - `Err(AttestationError::PolicyChanged)` if the account no longer has
+///   the attested policy
+pub fn check_attestation(
+    attestation: &PolicyAttestation,
+    account_address: &Pubkey,
+    account: &AttestaAccount,
+    expected_policy_hash: &[u8; HASH_LEN],
+    now: i64,
+) -> Result<(), AttestationError> {
+    if attestation.attesta_account != *account_address {
+        return Err(AttestationError::WrongAccount);
+    }
+    if attestation.policy_hash != *expected_policy_hash {
+        return Err(AttestationError::UnexpectedPolicy);
+    }
+    if now >= attestation.expires_at {
+        return Err(AttestationError::Expired { expires_at: attestation.expires_at });
+    }
+    if account.policy_hash != attestation.policy_hash {
+        return Err(AttestationError::PolicyChanged);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::Policy;
+    use crate::auth::action_message_hash;
+
+    const NOW: i64 = 1_800_000_000;
+    const MONTH: i64 = 30 * 24 * 60 * 60;
+
+    fn sign(passkey: &mut TestPasskey, account: &AttestaAccount, payload: &[u8]) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let challenge = compute_challenge(&account.owner, nonce, &action_message_hash(POLICY_ATTEST_ACTION, payload));
+        (passkey.sign(&challenge), nonce)
+    }
+
+    /// An account with a 30-day time lock, attested for a month
+    fn setup() -> (AttestaAccount, Pubkey, TestPasskey, PolicyAttestation) {
+        let mut owner = TestPasskey::new(1);
+        let lock = Policy::time_locked(NOW + MONTH).to_bytes().unwrap();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), owner.public_key(), owner.credential_id(), lock, 100);
+        let address = Pubkey::new_unique();
+
+        let (sig, nonce) = sign(&mut owner, &account, &attestation_payload(&account.policy_hash, NOW + MONTH));
+        let attestation = attest_policy(&mut account, &address, sig, nonce, NOW + MONTH, NOW).unwrap();
+        (account, address, owner, attestation)
+    }
+
+    #[test]
+    fn test_attestation_vouches_for_the_current_policy() {
+        let (account, address, _, attestation) = setup();
+        let expected = Policy::time_locked(NOW + MONTH).canonical_hash();
+        assert_eq!(attestation.policy_hash, expected);
+        assert_eq!(attestation.attested_at, NOW);
+        assert_eq!((attestation.nonce, account.nonce), (1, 1));
+
+        let bytes = attestation.to_bytes().unwrap();
+        assert_eq!(bytes.len(), PolicyAttestation::SERIALIZED_SIZE);
+        assert_eq!(PolicyAttestation::from_bytes(&bytes), Ok(attestation.clone()));
+
+        assert_eq!(check_attestation(&attestation, &address, &account, &expected, NOW + 1), Ok(()));
+        assert_eq!(
+            check_attestation(&attestation, &Pubkey::new_unique(), &account, &expected, NOW + 1),
+            Err(AttestationError::WrongAccount)
+        );
+        let other = Policy::time_locked(NOW + 1).canonical_hash();
+        assert_eq!(
+            check_attestation(&attestation, &address, &account, &other, NOW + 1),
+            Err(AttestationError::UnexpectedPolicy)
+        );
+    }
+
+    #[test]
+    fn test_attestation_expires() {
+        let (account, address, _, attestation) = setup();
+        let expected = attestation.policy_hash;
+
+        assert_eq!(check_attestation(&attestation, &address, &account, &expected, NOW + MONTH - 1), Ok(()));
+        for now in [NOW + MONTH, NOW + 2 * MONTH] {
+            assert_eq!(
+                check_attestation(&attestation, &address, &account, &expected, now),
+                Err(AttestationError::Expired { expires_at: NOW + MONTH })
+            );
+        }
+    }
+
+    #[test]
+    fn test_policy_change_invalidates_attestation() {
+        let (mut account, address, _, attestation) = setup();
+        let expected = attestation.policy_hash;
+
+        account.set_policies(vec![Policy::time_locked(NOW + 1).to_bytes().unwrap()]);
+        assert_eq!(
+            check_attestation(&attestation, &address, &account, &expected, NOW + 1),
+            Err(AttestationError::PolicyChanged)
+        );
+    }
+
+    #[test]
+    fn test_expiry_is_bounded_and_signed() {
+        let (mut account, address, mut owner, _) = setup();
+
+        for expires_at in [NOW, NOW - 1, NOW + MAX_ATTESTATION_LIFETIME + 1] {
+            let (sig, nonce) = sign(&mut owner, &account, &attestation_payload(&account.policy_hash, expires_at));
+            assert_eq!(
+                attest_policy(&mut account, &address, sig, nonce, expires_at, NOW),
+                Err(AttestationError::InvalidExpiry { expires_at, now: NOW })
+            );
+        }
+
+        // Signed for a month, submitted for a year
+        let (sig, nonce) = sign(&mut owner, &account, &attestation_payload(&account.policy_hash, NOW + MONTH));
+        assert!(matches!(
+            attest_policy(&mut account, &address, sig, nonce, NOW + MAX_ATTESTATION_LIFETIME, NOW),
+            Err(AttestationError::Unauthorized(_))
+        ));
+        assert_eq!(account.nonce, 1);
+    }
+}
@@ -1,23 +1,36 @@
+use attesta_types::consts::{AAGUID_LEN, HASH_LEN, MIN_AUTHENTICATOR_DATA_LEN, P256_PUBKEY_LEN};
+use sha2::Digest;
 use solana_program::pubkey::Pubkey;
-use core_crypto::{WebAuthnSignature, verify_webauthn_signature, CryptoError};
-use crate::account::AttestaAccount;
+use core_crypto::{
+    WebAuthnSignature, verify_webauthn_signature, verify_webauthn_signature_with_profile, compute_challenge,
+    parse_authenticator_data, domain_hasher, CeremonyType, CryptoError, Domain,
+};
+use crate::account::{cluster_time, AttestaAccount};
+use attesta_types::envelope::ProofEnvelope;
+use crate::idempotency::IdempotencyKey;
 
 /// Checks if a passkey signature authorizes a transaction
 ///
 /// This is the core authentication function. It verifies that:
 /// 1. The signature came from the correct passkey (by checking credential ID)
 /// 2. The signature is valid (was created by the matching private key)
-/// 3. The challenge matches what we expected
+/// 3. It was made over `compute_challenge(owner, nonce, message)`, so it
+///    authorizes this message at this nonce and nothing else
+/// 4. It passes the checks the account's `settings.webauthn_profile` turns on
+///
+/// The structural checks run first, so malformed input is rejected before
+/// any hashing or curve arithmetic.
 ///
 /// # Parameters
 /// - `account`: The user's Attesta account (contains their passkey public key)
 /// - `webauthn_sig`: The signature created by their device's passkey
-/// - `challenge`: The challenge/nonce we sent them (must match what they signed)
-/// - `message`: The transaction message/hash being authorized
+/// - `nonce`: The nonce the signature was made for
+/// - `message`: The hash of the transaction being authorized (32 bytes)
 ///
 /// # Returns
 /// - `Ok(())` if the authorization is valid
-/// - `Err(CryptoError)` if anything is wrong (wrong passkey, invalid signature, etc.)
+/// - `Err(CryptoError::ChallengeMismatch)` if `message` isn't a 32-byte hash
+/// - `Err(CryptoError)` if anything else is wrong (wrong passkey, invalid signature, etc.)
 ///
 /// # How it works
 /// When a user wants to make a transaction:
@@ -29,34 +42,61 @@ use crate::account::AttestaAccount;
 pub fn verify_passkey_authorization(
     account: &AttestaAccount,
     webauthn_sig: &WebAuthnSignature,
-    challenge: &[u8],
+    nonce: u64,
     message: &[u8],
 ) -> Result<(), CryptoError> {
-    // First, make sure they're using the right passkey
-    // The credential ID must match the one we have on file
-    if webauthn_sig.credential_id != account.credential_id {
-        return Err(CryptoError::InvalidCredentialId);
+    // The message is the transaction hash the challenge commits to
+    let message_hash: &[u8; HASH_LEN] = message.try_into().map_err(|_| CryptoError::ChallengeMismatch)?;
+
+    if webauthn_sig.authenticator_data.len() < MIN_AUTHENTICATOR_DATA_LEN {
+        return Err(CryptoError::InvalidAuthenticatorData);
     }
 
-    // Verify the signature itself is valid
-    // This checks that it was created by the private key matching the public key
-    verify_webauthn_signature(
+    // Make sure they're using a passkey registered to this account
+    let public_key = resolve_signing_key(account, &webauthn_sig.credential_id)?;
+
+    // Rebuild the challenge the passkey should have signed, then verify the
+    // signature was created by the private key matching the public key
+    let challenge = compute_challenge(&account.owner, nonce, message_hash);
+    verify_webauthn_signature_with_profile(
         webauthn_sig,
-        &account.passkey_public_key,
-        challenge,
-    )?;
+        &public_key,
+        &challenge,
+        CeremonyType::Get,
+        &account.settings.webauthn_profile,
+        &account.webauthn_expectations(&webauthn_sig.credential_id),
+    )
+    .map(|_| ())
+}
 
-    // Basic sanity checks: challenge and message shouldn't be empty
-    // In production, you'd also verify the challenge matches the transaction hash exactly
-    if challenge.is_empty() {
-        return Err(CryptoError::ChallengeMismatch);
-    }
-    
-    if message.is_empty() {
-        return Err(CryptoError::ChallengeMismatch);
-    }
+/// Finds the public key for the passkey that produced a signature
+///
+/// Single-passkey accounts only accept their own credential. Accounts with a
+/// passkey registry accept any enabled passkey in it, and reject removed
+/// passkeys before any signature is checked. In privacy mode the credential
+/// ID is hashed before it's matched.
+///
+/// # Parameters
+/// - `account`: The account to look the passkey up on
+/// - `credential_id`: The credential ID from the assertion (never pre-hashed)
+pub fn resolve_signing_key(
+    account: &AttestaAccount,
+    credential_id: &[u8],
+) -> Result<[u8; P256_PUBKEY_LEN], CryptoError> {
+    let lookup_id = account.credential_lookup_id(credential_id);
+    let registry = account
+        .passkey_registry()
+        .map_err(|_| CryptoError::InvalidCredentialId)?;
 
-    Ok(())
+    match registry {
+        None if lookup_id == account.credential_id => Ok(account.passkey_public_key),
+        None => Err(CryptoError::InvalidCredentialId),
+        Some(registry) if registry.is_revoked(&lookup_id) => Err(CryptoError::RevokedCredential),
+        Some(registry) => registry
+            .authorize_signer(&lookup_id)
+            .map(|entry| entry.public_key)
+            .map_err(|_| CryptoError::InvalidCredentialId),
+    }
 }
 
 /// Proof that a user authorized a transaction with their passkey
@@ -74,6 +114,17 @@ pub struct AuthorizationProof {
     
     /// The hash of the transaction that was authorized (32 bytes)
     pub message_hash: [u8; 32],
+
+    /// Optional key that makes retrying this execution safe
+    ///
+    /// Not part of the signed challenge: it only lets the program recognize
+    /// a retry of a transaction that already ran.
+    pub idempotency_key: Option<IdempotencyKey>,
+
+    /// The memo the passkey signed along with the transaction (empty for none)
+    ///
+    /// `message_hash` covers it, so it must match what was signed.
+    pub memo: Vec<u8>,
 }
 
 impl AuthorizationProof {
@@ -90,14 +141,23 @@ impl AuthorizationProof {
             webauthn_sig,
             nonce,
             message_hash,
+            idempotency_key: None,
+            memo: Vec::new(),
         }
     }
 
+    /// Attaches an idempotency key to the proof
+    pub fn with_idempotency_key(mut self, key: IdempotencyKey) -> Self {
+        self.idempotency_key = Some(key);
+        self
+    }
+
     /// Verifies that this proof is valid for a given account
     ///
     /// This checks two things:
     /// 1. The nonce hasn't been used before (replay protection)
-    /// 2. The signature is valid (came from the account owner's passkey)
+    /// 2. The signature is valid (came from the account owner's passkey) and
+    ///    was made over `compute_challenge(owner, nonce, message_hash)`
     ///
     /// # Parameters
     /// - `account`: The Attesta account to verify against
@@ -113,17 +173,439 @@ impl AuthorizationProof {
             return Err(CryptoError::ReplayAttack);
         }
 
-        // Convert the nonce to bytes to use as the challenge
-        // The nonce is part of what gets signed, so it must match
-        let challenge = self.nonce.to_le_bytes();
-
         // Second check: is the signature valid?
-        // This verifies the signature came from the account owner's passkey
-        verify_passkey_authorization(
-            account,
-            &self.webauthn_sig,
-            &challenge,
-            &self.message_hash,
-        )
+        // The challenge covers the account, the nonce, and the message hash,
+        // so the signature can't be reused for anything else
+        verify_passkey_authorization(account, &self.webauthn_sig, self.nonce, &self.message_hash)
+    }
+}
+
+impl From<ProofEnvelope> for AuthorizationProof {
+    /// The proof the program verifies for a checked envelope
+    fn from(envelope: ProofEnvelope) -> Self {
+        let mut proof = AuthorizationProof::new(envelope.webauthn_sig, envelope.nonce, envelope.message_hash)
+            .with_idempotency_key(envelope.idempotency_key);
+        proof.memo = envelope.memo;
+        proof
+    }
+}
+
+/// Computes the message hash a passkey signs to authorize an account management action
+///
+/// Management instructions (storing a backup, changing settings, etc.) don't
+/// carry a transaction to hash, so instead we hash the name of the action
+/// together with its payload. Including the action name means a signature
+/// for one kind of action can never be replayed as a different action.
+///
+/// # Parameters
+/// - `action`: A short name for the action (e.g. `b"store_backup"`)
+/// - `payload`: The instruction arguments being authorized
+///
+/// # Returns
+/// The 32-byte hash to use as the `message_hash` of the authorization proof
+pub fn action_message_hash(action: &[u8], payload: &[u8]) -> [u8; 32] {
+    let mut hasher = domain_hasher(Domain::Action);
+    // Length-prefix the action name so (action, payload) pairs can't be shifted
+    hasher.update((action.len() as u32).to_le_bytes());
+    hasher.update(action);
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+/// Action name a passkey signs to enroll itself on a new account
+pub const REGISTRATION_ACTION: &[u8] = b"register_passkey";
+
+/// Computes the challenge a passkey signs to be enrolled on a new account
+///
+/// It commits to the owner, the account's address and the passkey itself,
+/// so an assertion made for one registration can't enroll a different key
+/// or be replayed on another account. Registrations sign nonce 0, which no
+/// later authorization can use (nonces must be above the account's, which
+/// starts at 0).
+///
+/// # Parameters
+/// - `owner`: The wallet that will own the account
+/// - `account_address`: The account's PDA
+/// - `passkey_public_key`: The passkey being enrolled (64 bytes, uncompressed)
+/// - `credential_id`: The passkey's credential ID
+pub fn registration_challenge(
+    owner: &Pubkey,
+    account_address: &Pubkey,
+    passkey_public_key: &[u8; P256_PUBKEY_LEN],
+    credential_id: &[u8],
+) -> [u8; 32] {
+    let payload = [account_address.as_ref(), passkey_public_key, credential_id].concat();
+    compute_challenge(owner, 0, &action_message_hash(REGISTRATION_ACTION, &payload))
+}
+
+/// Checks that a passkey being enrolled signed its `registration_challenge`
+///
+/// Proves whoever enrolls the passkey controls it, and meant to enroll it
+/// on this account for this owner.
+///
+/// The signature must come from `navigator.credentials.create()`: an
+/// assertion (`webauthn.get`) is rejected, so a signature made to authorize
+/// something can't double as an enrollment. If its authenticator data
+/// carries attested credential data (self attestation), that must describe
+/// this same passkey, and the AAGUID in it is returned.
+///
+/// # Returns
+/// - `Ok(Some(aaguid))` if the passkey signed and attested its model
+/// - `Ok(None)` if it signed without attested credential data
+/// - `Err(CryptoError::CeremonyTypeMismatch)` if it isn't a `webauthn.create` signature
+/// - `Err(CryptoError::InvalidAuthenticatorData)` if the attested credential
+///   is a different passkey
+/// - `Err(CryptoError)` if the signature doesn't verify
+pub fn verify_registration(
+    owner: &Pubkey,
+    account_address: &Pubkey,
+    passkey_public_key: &[u8; P256_PUBKEY_LEN],
+    credential_id: &[u8],
+    webauthn_sig: &WebAuthnSignature,
+) -> Result<Option<[u8; AAGUID_LEN]>, CryptoError> {
+    let challenge = registration_challenge(owner, account_address, passkey_public_key, credential_id);
+    verify_webauthn_signature(webauthn_sig, passkey_public_key, &challenge, CeremonyType::Create)?;
+
+    match parse_authenticator_data(&webauthn_sig.authenticator_data)?.attested_credential {
+        Some(credential) if credential.public_key != *passkey_public_key || credential.credential_id != credential_id => {
+            Err(CryptoError::InvalidAuthenticatorData)
+        }
+        Some(credential) => Ok(Some(credential.aaguid)),
+        None => Ok(None),
+    }
+}
+
+/// Verifies a passkey-authorized management action and consumes its nonce
+///
+/// This is the shared authorization path for every instruction that changes
+/// account state without executing a transaction. It checks the proof exactly
+/// like `execute` does and then increments the nonce so the same approval
+/// can't be submitted twice.
+///
+/// # Parameters
+/// - `account`: The account being modified (its nonce is incremented on success)
+/// - `webauthn_sig`: The signature from the user's passkey
+/// - `nonce`: The nonce the user signed (must be > the account's current nonce)
+/// - `action`: The action name passed to `action_message_hash`
+/// - `payload`: The action payload passed to `action_message_hash`
+///
+/// # Returns
+/// - `Ok(())` if the passkey authorized this exact action
+/// - `Err(CryptoError)` if the proof is invalid or replayed
+pub fn authorize_action(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    action: &[u8],
+    payload: &[u8],
+) -> Result<(), CryptoError> {
+    let message_hash = action_message_hash(action, payload);
+    let proof = AuthorizationProof::new(webauthn_sig, nonce, message_hash);
+    proof.verify(account)?;
+    account.record_sign_count(&proof.webauthn_sig);
+
+    // The approval has been used - make sure it can't be used again
+    let now = cluster_time(account.updated_at);
+    account.increment_nonce(now);
+    Ok(())
+}
+
+/// `authorize_action` for actions only the account's primary passkey may take
+///
+/// Used for changes to how the account checks passkeys (its WebAuthn
+/// profile), so a secondary passkey can't weaken the checks it's held to.
+///
+/// # Returns
+/// - `Err(CryptoError::InvalidCredentialId)` if another passkey signed
+/// - Otherwise, what `authorize_action` returns
+pub fn authorize_admin_action(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    action: &[u8],
+    payload: &[u8],
+) -> Result<(), CryptoError> {
+    if !account.is_primary_credential(&webauthn_sig.credential_id) {
+        return Err(CryptoError::InvalidCredentialId);
+    }
+    authorize_action(account, webauthn_sig, nonce, action, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use solana_program::pubkey::Pubkey;
+    use recovery::multi_passkey::{credential_id_hash, PasskeyEntry};
+
+    fn key(seed: u8) -> [u8; 64] {
+        TestPasskey::new(seed).public_key()
+    }
+
+    fn account_with_laptop() -> AttestaAccount {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), key(1), b"phone".to_vec(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(key(2), b"laptop".to_vec(), "Laptop".to_string(), 110).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+        account
+    }
+
+    #[test]
+    fn test_message_is_bound_into_the_challenge() {
+        let mut passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let webauthn_sig = passkey.sign(&compute_challenge(&account.owner, 1, &[7u8; 32]));
+
+        assert_eq!(verify_passkey_authorization(&account, &webauthn_sig, 1, &[7u8; 32]), Ok(()));
+        assert!(verify_passkey_authorization(&account, &webauthn_sig, 1, &[8u8; 32]).is_err());
+        assert!(verify_passkey_authorization(&account, &webauthn_sig, 2, &[7u8; 32]).is_err());
+    }
+
+    #[test]
+    fn test_registration_signature_cannot_authorize() {
+        let mut passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        // Signed over the right challenge, but in a create ceremony
+        let webauthn_sig = passkey.sign_create(&compute_challenge(&account.owner, 1, &[7u8; 32]));
+
+        assert_eq!(
+            verify_passkey_authorization(&account, &webauthn_sig, 1, &[7u8; 32]),
+            Err(CryptoError::CeremonyTypeMismatch)
+        );
+        // The proof `execute` checks
+        let proof = AuthorizationProof::new(webauthn_sig, 1, [7u8; 32]);
+        assert_eq!(proof.verify(&account), Err(CryptoError::CeremonyTypeMismatch));
+    }
+
+    #[test]
+    fn test_malformed_input_fails_before_verification() {
+        let mut passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let mut webauthn_sig = passkey.sign(&compute_challenge(&account.owner, 1, &[7u8; 32]));
+
+        // An unusable signature would fail verification with its own error,
+        // so these errors show the checks ran first
+        webauthn_sig.signature = vec![0u8; 64];
+        assert_eq!(verify_passkey_authorization(&account, &webauthn_sig, 1, &[]), Err(CryptoError::ChallengeMismatch));
+        assert_eq!(verify_passkey_authorization(&account, &webauthn_sig, 1, &[7u8; 31]), Err(CryptoError::ChallengeMismatch));
+
+        webauthn_sig.credential_id = b"someone-else".to_vec();
+        webauthn_sig.authenticator_data.truncate(MIN_AUTHENTICATOR_DATA_LEN - 1);
+        assert_eq!(
+            verify_passkey_authorization(&account, &webauthn_sig, 1, &[7u8; 32]),
+            Err(CryptoError::InvalidAuthenticatorData)
+        );
+    }
+
+    #[test]
+    fn test_single_passkey_account_resolves_own_credential() {
+        let account = AttestaAccount::new(Pubkey::new_unique(), key(1), b"phone".to_vec(), vec![], 100);
+        assert_eq!(resolve_signing_key(&account, b"phone"), Ok(key(1)));
+        assert_eq!(resolve_signing_key(&account, b"laptop"), Err(CryptoError::InvalidCredentialId));
+    }
+
+    #[test]
+    fn test_registry_resolves_additional_passkey() {
+        let account = account_with_laptop();
+        assert_eq!(resolve_signing_key(&account, b"phone"), Ok(key(1)));
+        assert_eq!(resolve_signing_key(&account, b"laptop"), Ok(key(2)));
+    }
+
+    #[test]
+    fn test_revoked_passkey_rejected_before_signature_check() {
+        let mut account = account_with_laptop();
+        let mut registry = account.passkey_registry().unwrap().unwrap();
+        registry.remove_passkey(b"laptop", 200).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        assert_eq!(resolve_signing_key(&account, b"laptop"), Err(CryptoError::RevokedCredential));
+
+        // Even a proof built before removal is rejected
+        let sig = WebAuthnSignature::new(vec![0; 37], vec![0; 10], vec![0; 64], b"laptop".to_vec());
+        let proof = AuthorizationProof::new(sig, 1, [9u8; 32]);
+        assert_eq!(proof.verify(&account), Err(CryptoError::RevokedCredential));
+    }
+
+    #[test]
+    fn test_proof_verifies_against_bound_challenge() {
+        let mut passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let message_hash = [9u8; 32];
+
+        let challenge = compute_challenge(&account.owner, 1, &message_hash);
+        let proof = AuthorizationProof::new(passkey.sign(&challenge), 1, message_hash);
+        assert!(proof.verify(&account).is_ok());
+
+        // The same signature doesn't authorize a different message
+        let moved = AuthorizationProof::new(proof.webauthn_sig.clone(), 1, [8u8; 32]);
+        assert_eq!(moved.verify(&account), Err(CryptoError::ChallengeMismatch));
+
+        // ...or the same message under another account
+        let other = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        assert_eq!(proof.verify(&other), Err(CryptoError::ChallengeMismatch));
+    }
+
+    #[test]
+    fn test_signatures_verify_in_privacy_mode() {
+        let mut phone = TestPasskey::new(1);
+        let mut laptop = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 110).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        // Migrate the existing account
+        account.enable_privacy_mode().unwrap();
+        assert_ne!(account.credential_id, phone.credential_id());
+
+        let message_hash = [9u8; 32];
+        let challenge = compute_challenge(&account.owner, 1, &message_hash);
+
+        // Assertions carry the real credential ID; matching happens by hash
+        for passkey in [&mut phone, &mut laptop] {
+            let proof = AuthorizationProof::new(passkey.sign(&challenge), 1, message_hash);
+            assert!(proof.verify(&account).is_ok());
+        }
+
+        // Presenting the stored hash as if it were the credential ID doesn't match
+        let mut forged = phone.sign(&challenge);
+        forged.credential_id = account.credential_id.clone();
+        let proof = AuthorizationProof::new(forged, 1, message_hash);
+        assert_eq!(proof.verify(&account), Err(CryptoError::InvalidCredentialId));
+    }
+
+    #[test]
+    fn test_hashed_credential_id_still_authorizes() {
+        let phone = TestPasskey::new(1);
+        let mut security_key = TestPasskey::new(2);
+        // Some authenticators return IDs this long; only their hash is stored
+        let long_id = vec![0x5a; 220];
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry
+            .add_entry(PasskeyEntry::new_hashed(security_key.public_key(), &long_id, "Key".to_string(), 110))
+            .unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        let message_hash = [9u8; 32];
+        let challenge = compute_challenge(&account.owner, 1, &message_hash);
+        let mut sig = security_key.sign(&challenge);
+        sig.credential_id = long_id.clone();
+        let proof = AuthorizationProof::new(sig.clone(), 1, message_hash);
+        assert!(proof.verify(&account).is_ok());
+        assert_eq!(resolve_signing_key(&account, &long_id), Ok(security_key.public_key()));
+
+        // A different credential, or the stored hash itself, doesn't match
+        for wrong in [vec![0x5a; 219], credential_id_hash(&long_id).to_vec()] {
+            let mut forged = sig.clone();
+            forged.credential_id = wrong;
+            let proof = AuthorizationProof::new(forged, 1, message_hash);
+            assert_eq!(proof.verify(&account), Err(CryptoError::InvalidCredentialId));
+        }
+    }
+
+    #[test]
+    fn test_account_profile_is_enforced_and_counters_recorded() {
+        use core_crypto::{RelyingParty, WebAuthnVerificationProfile};
+
+        let mut phone = TestPasskey::new(1);
+        let mut laptop = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 110).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+        let challenge = |account: &AttestaAccount, nonce| compute_challenge(&account.owner, nonce, &action_message_hash(b"test", b""));
+
+        // Legacy accounts accept a signature for another site, and keep no counters
+        let elsewhere = phone.sign_as(&challenge(&account, 1), "evil.example", TestPasskey::ORIGIN, 0x05);
+        assert_eq!(authorize_action(&mut account, elsewhere, 1, b"test", b""), Ok(()));
+        assert!(account.sign_counts.is_empty());
+
+        account.settings.webauthn_profile = WebAuthnVerificationProfile::strict();
+        account.settings.relying_party = Some(RelyingParty::new(TestPasskey::RP_ID, TestPasskey::ORIGIN));
+        let elsewhere = phone.sign_as(&challenge(&account, 2), "evil.example", TestPasskey::ORIGIN, 0x05);
+        assert_eq!(authorize_action(&mut account, elsewhere, 2, b"test", b""), Err(CryptoError::RpIdMismatch));
+
+        // Each passkey's counter is kept apart
+        let signed = phone.sign(&challenge(&account, 2));
+        assert_eq!(authorize_action(&mut account, signed, 2, b"test", b""), Ok(()));
+        let signed = laptop.sign(&challenge(&account, 3));
+        assert_eq!(authorize_action(&mut account, signed, 3, b"test", b""), Ok(()));
+        assert_eq!(account.webauthn_expectations(&phone.credential_id()).previous_sign_count, 3);
+        assert_eq!(account.webauthn_expectations(&laptop.credential_id()).previous_sign_count, 1);
+
+        // A cloned phone that's behind on its counter is refused
+        let mut clone = TestPasskey::new(1);
+        let signed = clone.sign(&challenge(&account, 4));
+        assert_eq!(authorize_action(&mut account, signed, 4, b"test", b""), Err(CryptoError::SignCountNotIncreased));
+        assert_eq!(account.nonce, 3);
+    }
+
+    #[test]
+    fn test_only_the_primary_passkey_authorizes_admin_actions() {
+        let mut phone = TestPasskey::new(1);
+        let mut laptop = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 110).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+        let challenge = compute_challenge(&account.owner, 1, &action_message_hash(b"test", b""));
+
+        let signed = laptop.sign(&challenge);
+        assert_eq!(authorize_admin_action(&mut account, signed, 1, b"test", b""), Err(CryptoError::InvalidCredentialId));
+        assert_eq!(account.nonce, 0);
+
+        let signed = phone.sign(&challenge);
+        assert_eq!(authorize_admin_action(&mut account, signed, 1, b"test", b""), Ok(()));
+
+        // Also in privacy mode, where the stored ID is a hash
+        account.enable_privacy_mode().unwrap();
+        let challenge = compute_challenge(&account.owner, 2, &action_message_hash(b"test", b""));
+        assert_eq!(authorize_admin_action(&mut account, phone.sign(&challenge), 2, b"test", b""), Ok(()));
+    }
+
+    #[test]
+    fn test_registration_binds_owner_account_and_passkey() {
+        let mut passkey = TestPasskey::new(1);
+        let (owner, address) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (public_key, credential_id) = (passkey.public_key(), passkey.credential_id());
+        let challenge = registration_challenge(&owner, &address, &public_key, &credential_id);
+        let sig = passkey.sign_create(&challenge);
+
+        assert_eq!(verify_registration(&owner, &address, &public_key, &credential_id, &sig), Ok(None));
+
+        // An assertion over the same challenge isn't an enrollment
+        assert_eq!(
+            verify_registration(&owner, &address, &public_key, &credential_id, &passkey.sign(&challenge)),
+            Err(CryptoError::CeremonyTypeMismatch)
+        );
+
+        // Any other owner, address or key makes it a different challenge
+        assert!(verify_registration(&Pubkey::new_unique(), &address, &public_key, &credential_id, &sig).is_err());
+        assert!(verify_registration(&owner, &Pubkey::new_unique(), &public_key, &credential_id, &sig).is_err());
+        assert!(verify_registration(&owner, &address, &public_key, b"other", &sig).is_err());
+
+        // An attacker enrolling their own key can't sign for it with the victim's assertion
+        let attacker = TestPasskey::new(2);
+        assert!(verify_registration(&owner, &address, &attacker.public_key(), &credential_id, &sig).is_err());
+    }
+
+    #[test]
+    fn test_registration_reports_attested_aaguid() {
+        let mut passkey = TestPasskey::new(1);
+        let (owner, address) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (public_key, credential_id) = (passkey.public_key(), passkey.credential_id());
+        let challenge = registration_challenge(&owner, &address, &public_key, &credential_id);
+
+        let sig = passkey.sign_registration(&challenge, [7; 16]);
+        assert_eq!(verify_registration(&owner, &address, &public_key, &credential_id, &sig), Ok(Some([7; 16])));
+
+        // The attested credential must be the passkey being enrolled
+        let other_id = b"other-credential".to_vec();
+        let sig = passkey.sign_registration(&registration_challenge(&owner, &address, &public_key, &other_id), [7; 16]);
+        assert_eq!(
+            verify_registration(&owner, &address, &public_key, &other_id, &sig),
+            Err(CryptoError::InvalidAuthenticatorData)
+        );
     }
 }
@@ -0,0 +1,355 @@
+//! Who may authorize `execute`: the passkey, the owner's wallet, or either
+//!
+//! Accounts moving from Ed25519 owner signing to passkeys can run both for a
+//! while. `AccountSettings::auth_mode` says which signer `execute` accepts;
+//! with the owner allowed, `execute_as_owner` runs a transaction the owner's
+//! wallet signed, through the same policy checks and nonce as a passkey
+//! signature. Only execution is affected: managing the account still takes
+//! a passkey.
+//!
+//! Changing the mode takes the primary passkey. Setting `PasskeyOnly` can
+//! also lock it there (`AccountSettings::auth_mode_locked`), so a stolen
+//! wallet key can't be turned back into a way in; only a finalized recovery
+//! lifts the lock.
+
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_admin_action;
+use crate::execute::{evaluate_policy, record_transfer, DenyReason, PolicyResult};
+use crate::vault::announcement_delay;
+
+/// Action name the primary passkey signs, over `auth_mode_payload`, to change the auth mode
+pub const AUTH_MODE_ACTION: &[u8] = b"set_auth_mode";
+
+/// Which signers may authorize an account's transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[repr(u8)]
+pub enum AuthMode {
+    /// Only a passkey signature (every account created before auth modes)
+    #[default]
+    PasskeyOnly = 0,
+
+    /// A passkey signature or the owner's wallet, while an account migrates
+    OwnerOrPasskey = 1,
+
+    /// Only the owner's wallet
+    OwnerOnly = 2,
+}
+
+impl AuthMode {
+    /// Decodes a stored mode
+    ///
+    /// # Returns
+    /// `None` for a mode this version doesn't know
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(AuthMode::PasskeyOnly),
+            1 => Some(AuthMode::OwnerOrPasskey),
+            2 => Some(AuthMode::OwnerOnly),
+            _ => None,
+        }
+    }
+
+    /// Whether a passkey signature may authorize `execute`
+    pub fn allows_passkey(self) -> bool {
+        self != AuthMode::OwnerOnly
+    }
+
+    /// Whether the owner's wallet may authorize `execute`
+    pub fn allows_owner(self) -> bool {
+        self != AuthMode::PasskeyOnly
+    }
+}
+
+/// Errors from changing the auth mode or executing as the owner
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AuthModeError {
+    #[error("Auth mode change rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The account is locked to passkey-only signing until a recovery")]
+    Locked,
+
+    #[error("Only passkey-only signing can be locked")]
+    CannotLock,
+
+    #[error("The account's auth mode doesn't accept the owner's signature")]
+    OwnerNotAllowed,
+
+    #[error("Signer is not the account owner")]
+    NotOwner,
+
+    #[error("Invalid nonce")]
+    InvalidNonce,
+
+    #[error("The sub-account's parent account wasn't provided")]
+    MissingParent,
+}
+
+/// The bytes the primary passkey signs to set `mode`, locking it if `lock`
+pub fn auth_mode_payload(mode: AuthMode, lock: bool) -> [u8; 2] {
+    [mode as u8, lock as u8]
+}
+
+/// Changes which signers may authorize the account's transactions
+///
+/// Uses up `nonce`. A lock only ever tightens: once set, the mode stays
+/// `PasskeyOnly` and the lock stays on whatever `lock` says, until
+/// `social_recovery::finalize_recovery` clears it.
+///
+/// # Parameters
+/// - `webauthn_sig`: The primary passkey's signature over `AUTH_MODE_ACTION`
+///   for `auth_mode_payload(mode, lock)`
+/// - `lock`: Keep the account passkey-only (only with `AuthMode::PasskeyOnly`)
+pub fn set_auth_mode(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    mode: AuthMode,
+    lock: bool,
+) -> Result<(), AuthModeError> {
+    if lock && mode != AuthMode::PasskeyOnly {
+        return Err(AuthModeError::CannotLock);
+    }
+    if account.settings.auth_mode_locked && mode != AuthMode::PasskeyOnly {
+        return Err(AuthModeError::Locked);
+    }
+    authorize_admin_action(account, webauthn_sig, nonce, AUTH_MODE_ACTION, &auth_mode_payload(mode, lock))?;
+    account.settings.auth_mode = mode;
+    account.settings.auth_mode_locked |= lock;
+    account.bump_state_version();
+    Ok(())
+}
+
+/// Runs a transaction the account's owner signed with their wallet
+///
+/// The caller checks that `owner` signed the instruction. Otherwise this is
+/// `execute_transaction_at` without the passkey: the settings and policies
+/// are checked the same way (a policy bound to particular passkeys sees no
+/// passkey signer), and an allowed transaction uses up `nonce`. A locked-out
+/// account is refused here too.
+///
+/// # Returns
+/// - `Ok(PolicyResult)` as `execute_transaction_at` reports it
+/// - `Err(AuthModeError::OwnerNotAllowed)` if the account is `PasskeyOnly`
+/// - `Err(AuthModeError::NotOwner)` or `Err(AuthModeError::InvalidNonce)`
+///   for the wrong signer or a used nonce
+pub fn execute_as_owner(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
+    owner: &Pubkey,
+    nonce: u64,
+    transaction_data: &[u8],
+    now: i64,
+) -> Result<PolicyResult, AuthModeError> {
+    if account.owner != *owner {
+        return Err(AuthModeError::NotOwner);
+    }
+    if !account.settings.auth_mode.allows_owner() {
+        return Err(AuthModeError::OwnerNotAllowed);
+    }
+    if !account.validate_nonce(nonce) {
+        return Err(AuthModeError::InvalidNonce);
+    }
+    if account.settings.lockout_threshold > 0 && account.is_locked_out(now) {
+        return Ok(PolicyResult::Denied(DenyReason::LockedOut { until: account.locked_until }));
+    }
+
+    let mut result = evaluate_policy(account, account_address, parent, None, transaction_data, now)
+        .map_err(|_| AuthModeError::MissingParent)?;
+    // Only a passkey's `execute` can announce a transfer a vault holds back
+    if result == PolicyResult::Allowed && announcement_delay(account, parent, transaction_data).is_some() {
+        result = PolicyResult::Denied(DenyReason::Policy);
+    }
+    if result == PolicyResult::Allowed {
+        // Nonces only move forward one at a time, as with a passkey
+        account.increment_nonce(now);
+        account.last_execution_at = account.updated_at;
+        record_transfer(account, transaction_data, now);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::policies::MIN_POLICY_TIMESTAMP;
+    use recovery::{Amount, MintLimit, MintLimits, Policy};
+    use solana_program::program_error::ProgramError;
+    use crate::auth::{action_message_hash, AuthorizationProof};
+    use crate::execute::{execute_transaction_at, TransactionRequest};
+    use crate::token::TokenTransfer;
+
+    const NOW: i64 = MIN_POLICY_TIMESTAMP + 1_000;
+
+    fn sign_mode(passkey: &mut TestPasskey, account: &AttestaAccount, mode: AuthMode, lock: bool) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let message_hash = action_message_hash(AUTH_MODE_ACTION, &auth_mode_payload(mode, lock));
+        (passkey.sign(&compute_challenge(&account.owner, nonce, &message_hash)), nonce)
+    }
+
+    fn setup() -> (AttestaAccount, TestPasskey) {
+        let passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        (account, passkey)
+    }
+
+    fn with_mode(mode: AuthMode) -> (AttestaAccount, TestPasskey) {
+        let (mut account, mut passkey) = setup();
+        let (sig, nonce) = sign_mode(&mut passkey, &account, mode, false);
+        set_auth_mode(&mut account, sig, nonce, mode, false).unwrap();
+        (account, passkey)
+    }
+
+    /// Executes `data` with a passkey signature, as `execute` would
+    fn execute_with_passkey(account: &mut AttestaAccount, passkey: &mut TestPasskey, data: &[u8]) -> Result<PolicyResult, ProgramError> {
+        let nonce = account.nonce + 1;
+        let message_hash = TransactionRequest::new(data.to_vec()).message_hash();
+        let proof = AuthorizationProof::new(passkey.sign(&compute_challenge(&account.owner, nonce, &message_hash)), nonce, message_hash);
+        execute_transaction_at(account, &Pubkey::new_unique(), None, &proof, data, NOW)
+    }
+
+    #[test]
+    fn test_auth_mode_codes_are_stable() {
+        for mode in [AuthMode::PasskeyOnly, AuthMode::OwnerOrPasskey, AuthMode::OwnerOnly] {
+            assert_eq!(AuthMode::from_u8(mode as u8), Some(mode));
+        }
+        assert_eq!(AuthMode::PasskeyOnly as u8, 0);
+        assert_eq!(AuthMode::OwnerOnly as u8, 2);
+        assert_eq!(AuthMode::from_u8(3), None);
+        assert_eq!(AuthMode::default(), AuthMode::PasskeyOnly);
+    }
+
+    #[test]
+    fn test_each_mode_accepts_only_its_signers() {
+        for (mode, passkey_ok, owner_ok) in [
+            (AuthMode::PasskeyOnly, true, false),
+            (AuthMode::OwnerOrPasskey, true, true),
+            (AuthMode::OwnerOnly, false, true),
+        ] {
+            let (mut account, mut passkey) = with_mode(mode);
+            let nonce = account.nonce;
+
+            let result = execute_with_passkey(&mut account, &mut passkey, b"transfer 1 SOL");
+            if passkey_ok {
+                assert_eq!(result, Ok(PolicyResult::Allowed), "{mode:?}");
+                assert_eq!(account.nonce, nonce + 1);
+            } else {
+                assert_eq!(result, Err(ProgramError::MissingRequiredSignature), "{mode:?}");
+                assert_eq!(account.nonce, nonce);
+            }
+
+            let owner = account.owner;
+            let next = account.nonce + 1;
+            let result = execute_as_owner(&mut account, &Pubkey::new_unique(), None, &owner, next, b"transfer 1 SOL", NOW);
+            if owner_ok {
+                assert_eq!(result, Ok(PolicyResult::Allowed), "{mode:?}");
+                assert_eq!(account.nonce, next);
+                assert_eq!(account.last_execution_at, NOW);
+            } else {
+                assert_eq!(result, Err(AuthModeError::OwnerNotAllowed), "{mode:?}");
+                assert_eq!(account.nonce, next - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_owner_execution_checks_signer_nonce_and_policy() {
+        let (mut account, mut passkey) = setup();
+        let mint = Pubkey::new_unique();
+        account.policy = Policy::spending_limit(Amount::ZERO)
+            .with_mint_limits(MintLimits { allow_unlisted: false, limits: vec![MintLimit { mint, max_amount: 100, decimals: 6 }] })
+            .to_bytes()
+            .unwrap();
+        let (sig, nonce) = sign_mode(&mut passkey, &account, AuthMode::OwnerOrPasskey, false);
+        set_auth_mode(&mut account, sig, nonce, AuthMode::OwnerOrPasskey, false).unwrap();
+        let address = Pubkey::new_unique();
+        let owner = account.owner;
+        let transfer = |amount| TokenTransfer { mint, amount, decimals: 6, destination_ata: Pubkey::new_unique() }.to_transaction_data();
+
+        assert_eq!(
+            execute_as_owner(&mut account, &address, None, &Pubkey::new_unique(), 2, &transfer(50), NOW),
+            Err(AuthModeError::NotOwner)
+        );
+        assert_eq!(execute_as_owner(&mut account, &address, None, &owner, 1, &transfer(50), NOW), Err(AuthModeError::InvalidNonce));
+
+        // Over the limit: denied, and the nonce isn't used up
+        assert_eq!(
+            execute_as_owner(&mut account, &address, None, &owner, 2, &transfer(500), NOW),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+        assert_eq!(account.nonce, 1);
+        assert_eq!(execute_as_owner(&mut account, &address, None, &owner, 2, &transfer(50), NOW), Ok(PolicyResult::Allowed));
+        assert_eq!(account.nonce, 2);
+
+        // The passkey and the owner share one nonce sequence
+        assert_eq!(execute_as_owner(&mut account, &address, None, &owner, 2, &transfer(50), NOW), Err(AuthModeError::InvalidNonce));
+        assert_eq!(execute_with_passkey(&mut account, &mut passkey, &transfer(50)), Ok(PolicyResult::Allowed));
+        assert_eq!(account.nonce, 3);
+    }
+
+    #[test]
+    fn test_owner_execution_respects_lockout() {
+        let (mut account, _) = with_mode(AuthMode::OwnerOnly);
+        account.settings.lockout_threshold = 1;
+        account.locked_until = NOW + 60;
+        let owner = account.owner;
+        let next = account.nonce + 1;
+        assert_eq!(
+            execute_as_owner(&mut account, &Pubkey::new_unique(), None, &owner, next, b"transfer", NOW),
+            Ok(PolicyResult::Denied(DenyReason::LockedOut { until: NOW + 60 }))
+        );
+        assert_eq!(account.nonce, next - 1);
+    }
+
+    #[test]
+    fn test_only_the_primary_passkey_changes_the_mode() {
+        let (mut account, _) = setup();
+        let mut stranger = TestPasskey::new(2);
+        let (sig, nonce) = sign_mode(&mut stranger, &account, AuthMode::OwnerOnly, false);
+        assert!(matches!(
+            set_auth_mode(&mut account, sig, nonce, AuthMode::OwnerOnly, false),
+            Err(AuthModeError::Unauthorized(_))
+        ));
+        assert_eq!(account.settings.auth_mode, AuthMode::PasskeyOnly);
+
+        // The signature covers the mode: it can't be replayed for another one
+        let (mut account, mut passkey) = setup();
+        let (sig, nonce) = sign_mode(&mut passkey, &account, AuthMode::OwnerOrPasskey, false);
+        assert!(set_auth_mode(&mut account, sig, nonce, AuthMode::OwnerOnly, false).is_err());
+    }
+
+    #[test]
+    fn test_locked_passkey_only_cannot_be_loosened() {
+        let (mut account, mut passkey) = with_mode(AuthMode::OwnerOrPasskey);
+
+        // Only passkey-only signing can be locked
+        let (sig, nonce) = sign_mode(&mut passkey, &account, AuthMode::OwnerOnly, true);
+        assert_eq!(set_auth_mode(&mut account, sig, nonce, AuthMode::OwnerOnly, true), Err(AuthModeError::CannotLock));
+
+        let (sig, nonce) = sign_mode(&mut passkey, &account, AuthMode::PasskeyOnly, true);
+        set_auth_mode(&mut account, sig, nonce, AuthMode::PasskeyOnly, true).unwrap();
+        assert!(account.settings.auth_mode_locked);
+
+        for mode in [AuthMode::OwnerOrPasskey, AuthMode::OwnerOnly] {
+            let (sig, nonce) = sign_mode(&mut passkey, &account, mode, false);
+            assert_eq!(set_auth_mode(&mut account, sig, nonce, mode, false), Err(AuthModeError::Locked));
+        }
+        // Setting passkey-only again without the lock doesn't lift it
+        let (sig, nonce) = sign_mode(&mut passkey, &account, AuthMode::PasskeyOnly, false);
+        set_auth_mode(&mut account, sig, nonce, AuthMode::PasskeyOnly, false).unwrap();
+        assert!(account.settings.auth_mode_locked);
+
+        let owner = account.owner;
+        let next = account.nonce + 1;
+        assert_eq!(
+            execute_as_owner(&mut account, &Pubkey::new_unique(), None, &owner, next, b"transfer", NOW),
+            Err(AuthModeError::OwnerNotAllowed)
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! One-time payment links
+//!
+//! The owner's passkey signs `CLAIM_TICKET_ACTION` over an amount, an expiry
+//! and a claim key: the public half of a keypair made for the ticket alone.
+//! The ticket and the claim key's secret travel in a link; whoever holds it
+//! submits `claim` signed with the claim key, naming where the lamports go.
+//! The destination isn't in the ticket, but the claim key signs the
+//! transaction that names it, so nobody relaying the claim can redirect it.
+//!
+//! A ticket reserves a nonce like any other proof. Claiming moves the
+//! account's nonce up to it, so a ticket pays out once, and anything the
+//! owner authorizes with a higher nonce first voids it. That is also how to
+//! take a link back before it's used.
+
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use recovery::{credential_id_hash, Amount, PolicyContext};
+use crate::account::AttestaAccount;
+use crate::auth::{action_message_hash, AuthorizationProof};
+use crate::execute::{evaluate_policies, record_destination_spends, DenyReason, PolicyResult};
+use crate::vault::vault_delay;
+
+/// Action name a passkey signs, over `claim_ticket_payload`, to issue a claim ticket
+pub const CLAIM_TICKET_ACTION: &[u8] = b"claim_ticket";
+
+/// Bytes a ticket takes before its signature: account, claim key, amount, expiry and nonce
+const CLAIM_TICKET_HEADER_LEN: usize = 32 + 32 + 8 + 8 + 8;
+
+/// Errors from claiming a ticket
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ClaimError {
+    #[error("Claim ticket signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The claim ticket is for another account")]
+    WrongAccount,
+
+    #[error("The claim ticket expired at {expires_at}")]
+    Expired { expires_at: i64 },
+
+    #[error("Sub-accounts can't issue claim tickets")]
+    SubAccount,
+
+    #[error("The account doesn't accept passkey signatures")]
+    PasskeysDisabled,
+
+    #[error("Invalid claim ticket")]
+    InvalidTicket,
+}
+
+/// A passkey's authorization for one claim of `amount` lamports
+#[derive(Debug, Clone)]
+pub struct ClaimTicket {
+    /// The account paying out
+    pub account: Pubkey,
+
+    /// The key that must sign the claim
+    pub claim_key: Pubkey,
+
+    /// Lamports the claim transfers
+    pub amount: u64,
+
+    /// From this time on (Unix timestamp) the ticket can't be claimed
+    pub expires_at: i64,
+
+    /// The nonce the ticket consumes
+    pub nonce: u64,
+
+    /// The passkey's signature over `CLAIM_TICKET_ACTION` for `claim_ticket_payload`
+    pub webauthn_sig: WebAuthnSignature,
+}
+
+impl ClaimTicket {
+    /// The fixed fields, then the signature in `WebAuthnSignature::to_bytes` form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CLAIM_TICKET_HEADER_LEN + self.webauthn_sig.serialized_size());
+        bytes.extend_from_slice(self.account.as_ref());
+        bytes.extend_from_slice(self.claim_key.as_ref());
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        bytes.extend_from_slice(&self.expires_at.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(&self.webauthn_sig.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ClaimError> {
+        let header = data.get(..CLAIM_TICKET_HEADER_LEN).ok_or(ClaimError::InvalidTicket)?;
+        let field = |start: usize, len: usize| header.get(start..start + len).ok_or(ClaimError::InvalidTicket);
+        let le_bytes = |start: usize| -> Result<[u8; 8], ClaimError> {
+            field(start, 8)?.try_into().map_err(|_| ClaimError::InvalidTicket)
+        };
+
+        let webauthn_sig = data
+            .get(CLAIM_TICKET_HEADER_LEN..)
+            .and_then(|bytes| WebAuthnSignature::from_bytes(bytes).ok())
+            .ok_or(ClaimError::InvalidTicket)?;
+        Ok(Self {
+            account: Pubkey::try_from(field(0, 32)?).map_err(|_| ClaimError::InvalidTicket)?,
+            claim_key: Pubkey::try_from(field(32, 32)?).map_err(|_| ClaimError::InvalidTicket)?,
+            amount: u64::from_le_bytes(le_bytes(64)?),
+            expires_at: i64::from_le_bytes(le_bytes(72)?),
+            nonce: u64::from_le_bytes(le_bytes(80)?),
+            webauthn_sig,
+        })
+    }
+
+    /// The hash the passkey signed, as `action_message_hash` gives it
+    pub fn message_hash(&self) -> [u8; 32] {
+        action_message_hash(
+            CLAIM_TICKET_ACTION,
+            &claim_ticket_payload(&self.account, &self.claim_key, self.amount, self.expires_at),
+        )
+    }
+}
+
+/// The payload a passkey signs with `CLAIM_TICKET_ACTION`
+pub fn claim_ticket_payload(account_address: &Pubkey, claim_key: &Pubkey, amount: u64, expires_at: i64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(32 + 32 + 8 + 8);
+    payload.extend_from_slice(account_address.as_ref());
+    payload.extend_from_slice(claim_key.as_ref());
+    payload.extend_from_slice(&amount.to_le_bytes());
+    payload.extend_from_slice(&expires_at.to_le_bytes());
+    payload
+}
+
+/// Checks a claim of `ticket` paying out to `destination`
+///
+/// The caller checks the claim key signed, and moves the lamports when the
+/// result is `Allowed`; only then is the ticket's nonce used up. The amount
+/// is checked against the account's settings and policies like a transfer
+/// of that many lamports to `destination`.
+///
+/// # Returns
+/// - `Ok(PolicyResult::Allowed)` if the claim can be paid
+/// - `Ok(PolicyResult::Denied(reason))` if the account's lockout, settings or policies refuse it
+/// - `Err(ClaimError)` if the ticket itself is no good
+pub fn redeem_claim(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    destination: &Pubkey,
+    ticket: &ClaimTicket,
+    now: i64,
+) -> Result<PolicyResult, ClaimError> {
+    if ticket.account != *account_address {
+        return Err(ClaimError::WrongAccount);
+    }
+    if now >= ticket.expires_at {
+        return Err(ClaimError::Expired { expires_at: ticket.expires_at });
+    }
+    // The parent's policy would have to be checked as well
+    if account.parent.is_some() {
+        return Err(ClaimError::SubAccount);
+    }
+    if !account.settings.auth_mode.allows_passkey() {
+        return Err(ClaimError::PasskeysDisabled);
+    }
+    if account.settings.lockout_threshold > 0 && account.is_locked_out(now) {
+        return Ok(PolicyResult::Denied(DenyReason::LockedOut { until: account.locked_until }));
+    }
+
+    let proof = AuthorizationProof::new(ticket.webauthn_sig.clone(), ticket.nonce, ticket.message_hash());
+    proof.verify(account)?;
+
+    if account.settings.reject_zero_amount && ticket.amount == 0 {
+        return Ok(PolicyResult::Denied(DenyReason::ZeroAmount));
+    }
+    if account.settings.reject_self_transfer && destination == account_address {
+        return Ok(PolicyResult::Denied(DenyReason::SelfTransfer));
+    }
+    let context = PolicyContext::sol(Amount::from_lamports(ticket.amount), now)
+        .with_destination(*destination)
+        .with_signer(credential_id_hash(&ticket.webauthn_sig.credential_id));
+    let mut result = evaluate_policies(account, &context);
+    // A claim can't be announced, so one a vault would hold back is refused
+    if result == PolicyResult::Allowed && vault_delay(account, None, ticket.amount).is_some() {
+        result = PolicyResult::Denied(DenyReason::Policy);
+    }
+
+    if result == PolicyResult::Allowed {
+        account.record_sign_count(&ticket.webauthn_sig);
+        // Straight to the ticket's nonce, however far ahead it reserved, so
+        // it can't be claimed twice
+        account.nonce = ticket.nonce;
+        account.updated_at = now;
+        record_destination_spends(account, &context);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::policies::MIN_POLICY_TIMESTAMP;
+    use recovery::Policy;
+
+    const NOW: i64 = MIN_POLICY_TIMESTAMP + 1_000;
+    const EXPIRES_AT: i64 = NOW + 3_600;
+    const AMOUNT: u64 = 100_000_000;
+
+    fn issue(passkey: &mut TestPasskey, account: &AttestaAccount, address: Pubkey, amount: u64) -> ClaimTicket {
+        let claim_key = Pubkey::new_unique();
+        let nonce = account.nonce + 1;
+        let message_hash =
+            action_message_hash(CLAIM_TICKET_ACTION, &claim_ticket_payload(&address, &claim_key, amount, EXPIRES_AT));
+        let webauthn_sig = passkey.sign(&compute_challenge(&account.owner, nonce, &message_hash));
+        ClaimTicket { account: address, claim_key, amount, expires_at: EXPIRES_AT, nonce, webauthn_sig }
+    }
+
+    fn setup() -> (AttestaAccount, Pubkey, TestPasskey) {
+        let passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        (account, Pubkey::new_unique(), passkey)
+    }
+
+    #[test]
+    fn test_ticket_claims_once() {
+        let (mut account, address, mut passkey) = setup();
+        let ticket = issue(&mut passkey, &account, address, AMOUNT);
+        let destination = Pubkey::new_unique();
+
+        assert_eq!(redeem_claim(&mut account, &address, &destination, &ticket, NOW), Ok(PolicyResult::Allowed));
+        assert_eq!(account.nonce, ticket.nonce);
+        assert!(matches!(
+            redeem_claim(&mut account, &address, &destination, &ticket, NOW),
+            Err(ClaimError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_expired_ticket() {
+        let (mut account, address, mut passkey) = setup();
+        let ticket = issue(&mut passkey, &account, address, AMOUNT);
+
+        assert_eq!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &ticket, EXPIRES_AT),
+            Err(ClaimError::Expired { expires_at: EXPIRES_AT })
+        );
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_raised_amount_is_rejected() {
+        let (mut account, address, mut passkey) = setup();
+        let mut ticket = issue(&mut passkey, &account, address, AMOUNT);
+        ticket.amount = AMOUNT * 10;
+
+        assert!(matches!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &ticket, NOW),
+            Err(ClaimError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_amount_over_spending_limit_is_denied() {
+        let (mut account, address, mut passkey) = setup();
+        account.policy = Policy::spending_limit(Amount::from_lamports(AMOUNT / 2)).to_bytes().unwrap();
+        let ticket = issue(&mut passkey, &account, address, AMOUNT);
+
+        assert_eq!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &ticket, NOW),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_ticket_is_bound_to_its_account_and_claim_key() {
+        let (mut account, address, mut passkey) = setup();
+        let ticket = issue(&mut passkey, &account, address, AMOUNT);
+
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            redeem_claim(&mut account, &other, &Pubkey::new_unique(), &ticket, NOW),
+            Err(ClaimError::WrongAccount)
+        );
+
+        let mut swapped = ticket.clone();
+        swapped.claim_key = Pubkey::new_unique();
+        assert!(matches!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &swapped, NOW),
+            Err(ClaimError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_later_authorization_voids_ticket() {
+        let (mut account, address, mut passkey) = setup();
+        let ticket = issue(&mut passkey, &account, address, AMOUNT);
+        account.increment_nonce(NOW);
+
+        assert!(matches!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &ticket, NOW),
+            Err(ClaimError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_ticket_round_trips() {
+        let (account, address, mut passkey) = setup();
+        let ticket = issue(&mut passkey, &account, address, AMOUNT);
+        let decoded = ClaimTicket::from_bytes(&ticket.to_bytes()).unwrap();
+
+        assert_eq!(decoded.to_bytes(), ticket.to_bytes());
+        assert_eq!(decoded.message_hash(), ticket.message_hash());
+        assert_eq!(ClaimTicket::from_bytes(&ticket.to_bytes()[..CLAIM_TICKET_HEADER_LEN]).unwrap_err(), ClaimError::InvalidTicket);
+    }
+}
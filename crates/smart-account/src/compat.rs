@@ -0,0 +1,119 @@
+//! What accounts written by older versions read as
+//!
+//! `AttestaAccount` only ever grows at the end: every field added after the
+//! first release is a trailing field, and an account written before one
+//! existed simply ends before it. An account's *layout* is how many trailing
+//! fields it holds, so layout `n` holds the first `n` of `TRAILING_FIELDS`.
+//!
+//! A missing field reads as the value that changes nothing about how the
+//! account behaved when it was written:
+//!
+//! | Field | Missing reads as |
+//! |---|---|
+//! | `passkeys` | no registry: the primary passkey is the only one |
+//! | `privacy_mode` | off |
+//! | `idempotency_records` | none |
+//! | `pending_recovery`, `pending_drill` | none |
+//! | `last_drill_at` | never |
+//! | `settings` and each setting added after it | `AccountSettings::default()`: no checks beyond the ones the account had |
+//! | `parent`, `sub_account_index` | a top-level account, index 0 |
+//! | `inheritance` | none |
+//! | `last_execution_at` | never |
+//! | `failed_auth_count`, `locked_until` | no failures, unlocked |
+//! | `key_history` | empty |
+//! | `proof_log_enabled` | off |
+//! | `additional_policies` | none |
+//! | `passkey_aaguid`, `recovery_aaguid` | not attested |
+//! | `webauthn_profile` | the legacy profile, only for accounts created before `WEBAUTHN_PROFILE_RECORDED_LAYOUT` |
+//! | `sign_counts` | none: the next counter seen is the first |
+//! | `policy_hash` | recomputed from the policies |
+//! | `destination_spends` | nothing spent; the next transfer starts the window |
+//! | `state_version` | 0 |
+//! | `compact_settings` | the settings read from the trailing fields before it |
+//! | `created_layout` | 0: created before layouts were recorded |
+//! | `emergency_override` | off and never used |
+//! | `account_index` | none: the account is at the owner's own address |
+//!
+//! The legacy WebAuthn profile is the one default that relaxes a check, so
+//! it's only taken where it's what the account had. Accounts created from
+//! `WEBAUTHN_PROFILE_RECORDED_LAYOUT` on always store their profile; one
+//! that's missing was lost, and the read fails.
+//!
+//! `format_stability` loads an account at every layout and pins each of
+//! these defaults.
+
+use crate::account::AttestaAccount;
+
+/// The trailing fields of an account, in the order they were added
+pub const TRAILING_FIELDS: &[&str] = &[
+    "passkeys",
+    "privacy_mode",
+    "idempotency_records",
+    "pending_recovery",
+    "pending_drill",
+    "last_drill_at",
+    "settings",
+    "parent",
+    "sub_account_index",
+    "inheritance",
+    "last_execution_at",
+    "failed_auth_count",
+    "locked_until",
+    "key_history",
+    "proof_log_enabled",
+    "additional_policies",
+    "passkey_aaguid",
+    "aaguid_allowlist",
+    "recovery_aaguid",
+    "pinned_program_version",
+    "webauthn_profile",
+    "relying_party",
+    "sign_counts",
+    "auth_mode",
+    "auth_mode_locked",
+    "policy_hash",
+    "destination_spends",
+    "authorized_executors",
+    "state_version",
+    "log_allowed_sample_rate",
+    "compact_settings",
+    "created_layout",
+    "emergency_override",
+    "account_index",
+];
+
+/// The layout this version writes: every trailing field
+pub const ACCOUNT_LAYOUT_VERSION: u8 = 34;
+
+/// The layout that started recording `created_layout`; accounts created at
+/// it or after always store their WebAuthn profile
+pub const WEBAUTHN_PROFILE_RECORDED_LAYOUT: u8 = 32;
+
+/// The layout `data` was written in
+///
+/// Fails where `AttestaAccount::from_bytes` would.
+pub fn stored_layout(data: &[u8]) -> std::io::Result<u8> {
+    let mut reader = data;
+    let (_, layout) = AttestaAccount::deserialize_with_layout(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not all bytes read"));
+    }
+    Ok(layout)
+}
+
+/// The name of trailing field `index` (0 for the first)
+pub fn trailing_field(index: u8) -> Option<&'static str> {
+    TRAILING_FIELDS.get(usize::from(index)).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_version_counts_every_trailing_field() {
+        assert_eq!(TRAILING_FIELDS.len(), usize::from(ACCOUNT_LAYOUT_VERSION));
+        assert_eq!(trailing_field(ACCOUNT_LAYOUT_VERSION - 1), Some("account_index"));
+        assert_eq!(trailing_field(ACCOUNT_LAYOUT_VERSION), None);
+    }
+}
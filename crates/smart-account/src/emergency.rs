@@ -0,0 +1,450 @@
+//! A capped, rate-limited way past an account's time locks
+//!
+//! An owner who locked their funds with a `TimeLocked` policy can still
+//! need some of them before it ends. The emergency override lets two of the
+//! account's passkeys together run one transaction that ignores time locks:
+//! the primary passkey, which administers the account, and any other
+//! enabled one. Both sign the same challenge, and both signatures are
+//! checked in the same instruction.
+//!
+//! The override is off until the primary passkey configures it, and even
+//! then it's narrow:
+//!
+//! - It moves at most `max_lamports` (the raw amount, for a token transfer)
+//! - It can be used once per `period_seconds`
+//! - Every other policy still applies, destination allowlists included
+//! - It's refused while the account is locked out after bad signatures
+//! - While a recovery is pending, it's held to the recovery allowance
+//!
+//! Sub-accounts can't use it: their parent's policy is a ceiling they can't
+//! lift. The program emits `EmergencyOverrideUsed` every time it runs.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use recovery::{credential_id_hash, Amount, Policy, PolicyContext, PolicyType};
+use crate::account::AttestaAccount;
+use crate::auth::{action_message_hash, authorize_admin_action, AuthorizationProof};
+use crate::execute::{record_transfer, DenyReason};
+use crate::social_recovery::within_recovery_allowance;
+use crate::token::{is_self_transfer, TokenTransfer};
+
+/// Action name the primary passkey signs, over `EmergencyOverride::config_bytes`, to configure the override
+pub const EMERGENCY_CONFIGURE_ACTION: &[u8] = b"configure_emergency_override";
+
+/// Action name both passkeys sign, over the transaction data, to run it as an override
+pub const EMERGENCY_OVERRIDE_ACTION: &[u8] = b"emergency_override";
+
+/// Serialized size of an `EmergencyOverride`: max_lamports (8) + period_seconds (4) + last_used_at (8)
+pub const EMERGENCY_OVERRIDE_SIZE: usize = 8 + 4 + 8;
+
+/// An account's emergency override: its limits, and when it last ran
+///
+/// All zeros (the default) is off and never used.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EmergencyOverride {
+    /// Most an override may move; 0 turns the override off
+    pub max_lamports: u64,
+
+    /// Seconds after one override before the next may run
+    pub period_seconds: u32,
+
+    /// When the override last ran (Unix timestamp), 0 if never
+    ///
+    /// Kept when the limits change, so reconfiguring can't restart the period.
+    pub last_used_at: i64,
+}
+
+impl EmergencyOverride {
+    /// Whether the override can be used at all
+    pub fn is_enabled(&self) -> bool {
+        self.max_lamports > 0
+    }
+
+    /// The earliest time the override may run again
+    pub fn available_at(&self) -> i64 {
+        if self.last_used_at == 0 {
+            return 0;
+        }
+        self.last_used_at.saturating_add(i64::from(self.period_seconds))
+    }
+
+    /// The bytes the primary passkey signs to set these limits
+    pub fn config_bytes(max_lamports: u64, period_seconds: u32) -> Vec<u8> {
+        let mut bytes = max_lamports.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&period_seconds.to_le_bytes());
+        bytes
+    }
+}
+
+/// Errors from configuring or using the emergency override
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EmergencyError {
+    #[error("Emergency override rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The account has no emergency override configured")]
+    NotEnabled,
+
+    #[error("An enabled emergency override needs a period of at least one second")]
+    InvalidPeriod,
+
+    #[error("Sub-accounts can't use the emergency override")]
+    SubAccount,
+
+    #[error("The account's auth mode doesn't accept passkeys")]
+    PasskeyNotAllowed,
+
+    #[error("An emergency override needs the primary passkey and one other enabled passkey")]
+    SecondCredentialRequired,
+
+    #[error("The account is locked out until {until}")]
+    LockedOut { until: i64 },
+
+    #[error("The emergency override can't be used again until {available_at}")]
+    TooSoon { available_at: i64 },
+
+    #[error("The emergency override moves at most {max}, not {amount}")]
+    OverCap { amount: u64, max: u64 },
+
+    #[error("The transaction is denied even without time locks: {0:?}")]
+    Denied(DenyReason),
+}
+
+/// The message hash both passkeys sign to run `transaction_data` as an override
+pub fn emergency_message_hash(transaction_data: &[u8]) -> [u8; 32] {
+    action_message_hash(EMERGENCY_OVERRIDE_ACTION, transaction_data)
+}
+
+/// Sets the override's limits
+///
+/// Uses up `nonce`. A `max_lamports` of 0 turns the override off (with a
+/// period of 0). When the override last ran is kept either way.
+///
+/// # Parameters
+/// - `webauthn_sig`: The primary passkey's signature over
+///   `EMERGENCY_CONFIGURE_ACTION` for `EmergencyOverride::config_bytes`
+pub fn configure_emergency_override(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    max_lamports: u64,
+    period_seconds: u32,
+) -> Result<(), EmergencyError> {
+    if account.parent.is_some() {
+        return Err(EmergencyError::SubAccount);
+    }
+    if (max_lamports == 0) != (period_seconds == 0) {
+        return Err(EmergencyError::InvalidPeriod);
+    }
+    let config = EmergencyOverride::config_bytes(max_lamports, period_seconds);
+    authorize_admin_action(account, webauthn_sig, nonce, EMERGENCY_CONFIGURE_ACTION, &config)?;
+    account.emergency_override.max_lamports = max_lamports;
+    account.emergency_override.period_seconds = period_seconds;
+    account.bump_state_version();
+    Ok(())
+}
+
+/// Runs `transaction_data` past the account's time locks
+///
+/// Everything that needs no signature is checked first: that the override
+/// is on and due, the cap, and the policies with their time locks left out.
+/// Then both signatures, over `emergency_message_hash(transaction_data)` at
+/// `nonce`.
+///
+/// # Parameters
+/// - `admin_sig`: The primary passkey's signature
+/// - `cosigner_sig`: Another enabled passkey's signature over the same challenge
+/// - `now`: The Unix timestamp the period and policies are checked against
+///
+/// # Returns
+/// The amount the transaction moves (0 unless it's a token transfer)
+///
+/// # Side Effects
+/// Uses up `nonce`, records the transfer against per-destination limits,
+/// and starts the next period.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_emergency_override(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    admin_sig: &WebAuthnSignature,
+    cosigner_sig: &WebAuthnSignature,
+    nonce: u64,
+    transaction_data: &[u8],
+    now: i64,
+) -> Result<u64, EmergencyError> {
+    let config = account.emergency_override;
+    if !config.is_enabled() {
+        return Err(EmergencyError::NotEnabled);
+    }
+    if account.parent.is_some() {
+        return Err(EmergencyError::SubAccount);
+    }
+    if !account.settings.auth_mode.allows_passkey() {
+        return Err(EmergencyError::PasskeyNotAllowed);
+    }
+    // The override lifts time locks, never a lockout
+    if account.is_locked_out(now) {
+        return Err(EmergencyError::LockedOut { until: account.locked_until });
+    }
+    if now < config.available_at() {
+        return Err(EmergencyError::TooSoon { available_at: config.available_at() });
+    }
+
+    let transfer = TokenTransfer::from_transaction_data(transaction_data);
+    let amount = transfer.as_ref().map_or(0, |transfer| transfer.amount);
+    if amount > config.max_lamports {
+        return Err(EmergencyError::OverCap { amount, max: config.max_lamports });
+    }
+    let signer = credential_id_hash(&admin_sig.credential_id);
+    check_without_time_locks(account, account_address, transfer.as_ref(), signer, now)?;
+
+    // Two roles: the primary passkey and a different one
+    if !account.is_primary_credential(&admin_sig.credential_id)
+        || account.credential_lookup_id(&cosigner_sig.credential_id) == account.credential_lookup_id(&admin_sig.credential_id)
+    {
+        return Err(EmergencyError::SecondCredentialRequired);
+    }
+    let message_hash = emergency_message_hash(transaction_data);
+    for webauthn_sig in [admin_sig, cosigner_sig] {
+        AuthorizationProof::new(webauthn_sig.clone(), nonce, message_hash).verify(account)?;
+    }
+
+    account.increment_nonce(now);
+    account.record_sign_count(admin_sig);
+    account.record_sign_count(cosigner_sig);
+    record_transfer(account, transaction_data, now);
+    account.last_execution_at = account.updated_at;
+    account.emergency_override.last_used_at = now;
+    Ok(amount)
+}
+
+/// Runs the account's settings and policies, with every `TimeLocked`
+/// policy (or composite rule) left out
+fn check_without_time_locks(
+    account: &AttestaAccount,
+    account_address: &Pubkey,
+    transfer: Option<&TokenTransfer>,
+    signer: [u8; 32],
+    now: i64,
+) -> Result<(), EmergencyError> {
+    let mut context = match transfer {
+        Some(transfer) => {
+            if account.settings.reject_zero_amount && transfer.amount == 0 {
+                return Err(EmergencyError::Denied(DenyReason::ZeroAmount));
+            }
+            if account.settings.reject_self_transfer && is_self_transfer(transfer, account_address) {
+                return Err(EmergencyError::Denied(DenyReason::SelfTransfer));
+            }
+            PolicyContext::token(transfer.mint, transfer.amount, transfer.decimals, now)
+                .with_destination(transfer.destination_ata)
+        }
+        None => PolicyContext::sol(Amount::ZERO, now),
+    };
+    context.signer_credential_id = Some(signer);
+    // Lifting time locks doesn't lift a pending recovery's allowance
+    if !within_recovery_allowance(account, transfer) {
+        return Err(EmergencyError::Denied(DenyReason::RecoveryPending));
+    }
+
+    for bytes in account.policies() {
+        // A policy we can't read still says no
+        let allowed = Policy::from_bytes(bytes).is_ok_and(|policy| match without_time_locks(policy) {
+            Some(policy) => {
+                policy
+                    .check_context(&context)
+                    .and_then(|()| policy.check_destination_spend(&context, &account.destination_spends))
+                    .is_ok()
+            }
+            None => true,
+        });
+        if !allowed {
+            return Err(EmergencyError::Denied(DenyReason::Policy));
+        }
+    }
+    Ok(())
+}
+
+/// `policy` with its time locks taken out, or `None` if it's only a time lock
+fn without_time_locks(policy: Policy) -> Option<Policy> {
+    match policy.policy_type {
+        PolicyType::TimeLocked => None,
+        PolicyType::Composite => match policy.rules() {
+            Some(rules) => Some(Policy::composite(
+                rules.into_iter().filter(|rule| rule.policy_type != PolicyType::TimeLocked).collect(),
+            )),
+            // Evaluated as it is, which denies
+            None => Some(policy),
+        },
+        _ => Some(policy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+
+    const NOW: i64 = 1_000_000;
+    const DAY: u32 = 24 * 60 * 60;
+
+    struct Setup {
+        account: AttestaAccount,
+        address: Pubkey,
+        phone: TestPasskey,
+        laptop: TestPasskey,
+    }
+
+    /// A time-locked account with two passkeys and the override on
+    fn setup(max_lamports: u64) -> Setup {
+        let mut phone = TestPasskey::new(1);
+        let laptop = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 110).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+        account.set_policies(vec![Policy::time_locked(NOW + 365 * i64::from(DAY)).to_bytes().unwrap()]);
+
+        let config = EmergencyOverride::config_bytes(max_lamports, DAY);
+        let challenge = compute_challenge(&account.owner, 1, &action_message_hash(EMERGENCY_CONFIGURE_ACTION, &config));
+        configure_emergency_override(&mut account, phone.sign(&challenge), 1, max_lamports, DAY).unwrap();
+        Setup { account, address: Pubkey::new_unique(), phone, laptop }
+    }
+
+    fn transfer(amount: u64, destination_ata: Pubkey) -> Vec<u8> {
+        TokenTransfer { mint: Pubkey::new_unique(), amount, decimals: 6, destination_ata }.to_transaction_data()
+    }
+
+    fn sign(account: &AttestaAccount, passkey: &mut TestPasskey, transaction_data: &[u8]) -> WebAuthnSignature {
+        passkey.sign(&compute_challenge(&account.owner, account.nonce + 1, &emergency_message_hash(transaction_data)))
+    }
+
+    fn run(setup: &mut Setup, transaction_data: &[u8], now: i64) -> Result<u64, EmergencyError> {
+        let admin_sig = sign(&setup.account, &mut setup.phone, transaction_data);
+        let cosigner_sig = sign(&setup.account, &mut setup.laptop, transaction_data);
+        let nonce = setup.account.nonce + 1;
+        execute_emergency_override(&mut setup.account, &setup.address, &admin_sig, &cosigner_sig, nonce, transaction_data, now)
+    }
+
+    #[test]
+    fn test_two_passkeys_get_past_a_time_lock() {
+        let mut setup = setup(1_000);
+        let data = transfer(600, Pubkey::new_unique());
+        assert_eq!(run(&mut setup, &data, NOW), Ok(600));
+        assert_eq!(setup.account.nonce, 2);
+        assert_eq!(setup.account.emergency_override.last_used_at, NOW);
+    }
+
+    #[test]
+    fn test_single_credential_fails() {
+        let mut setup = setup(1_000);
+        let data = transfer(600, Pubkey::new_unique());
+        let nonce = setup.account.nonce + 1;
+
+        // The primary passkey twice is one credential, not two
+        let admin_sig = sign(&setup.account, &mut setup.phone, &data);
+        let again = sign(&setup.account, &mut setup.phone, &data);
+        assert_eq!(
+            execute_emergency_override(&mut setup.account, &setup.address, &admin_sig, &again, nonce, &data, NOW),
+            Err(EmergencyError::SecondCredentialRequired)
+        );
+        // And two passkeys without the primary lack the admin role
+        let laptop_sig = sign(&setup.account, &mut setup.laptop, &data);
+        let laptop_again = sign(&setup.account, &mut setup.laptop, &data);
+        assert_eq!(
+            execute_emergency_override(&mut setup.account, &setup.address, &laptop_sig, &laptop_again, nonce, &data, NOW),
+            Err(EmergencyError::SecondCredentialRequired)
+        );
+        // A second signature over something else doesn't count either
+        let other = sign(&setup.account, &mut setup.laptop, &transfer(1, Pubkey::new_unique()));
+        assert!(matches!(
+            execute_emergency_override(&mut setup.account, &setup.address, &admin_sig, &other, nonce, &data, NOW),
+            Err(EmergencyError::Unauthorized(_))
+        ));
+        assert_eq!(setup.account.nonce, 1);
+        assert_eq!(setup.account.emergency_override.last_used_at, 0);
+    }
+
+    #[test]
+    fn test_cap_is_enforced() {
+        let mut setup = setup(1_000);
+        assert_eq!(
+            run(&mut setup, &transfer(1_001, Pubkey::new_unique()), NOW),
+            Err(EmergencyError::OverCap { amount: 1_001, max: 1_000 })
+        );
+        assert_eq!(run(&mut setup, &transfer(1_000, Pubkey::new_unique()), NOW), Ok(1_000));
+    }
+
+    #[test]
+    fn test_once_per_period() {
+        let mut setup = setup(1_000);
+        assert_eq!(run(&mut setup, &transfer(10, Pubkey::new_unique()), NOW), Ok(10));
+
+        let available_at = NOW + i64::from(DAY);
+        assert_eq!(
+            run(&mut setup, &transfer(10, Pubkey::new_unique()), available_at - 1),
+            Err(EmergencyError::TooSoon { available_at })
+        );
+
+        // Reconfiguring doesn't restart the period
+        let config = EmergencyOverride::config_bytes(2_000, DAY);
+        let challenge =
+            compute_challenge(&setup.account.owner, 3, &action_message_hash(EMERGENCY_CONFIGURE_ACTION, &config));
+        let sig = setup.phone.sign(&challenge);
+        configure_emergency_override(&mut setup.account, sig, 3, 2_000, DAY).unwrap();
+        assert_eq!(setup.account.emergency_override.available_at(), available_at);
+
+        assert_eq!(run(&mut setup, &transfer(10, Pubkey::new_unique()), available_at), Ok(10));
+    }
+
+    #[test]
+    fn test_allowlist_still_applies() {
+        let mut setup = setup(1_000);
+        let mint = Pubkey::new_unique();
+        let allowed = Pubkey::new_unique();
+        // The allowlist sits next to a time lock inside one composite policy too
+        setup.account.set_policies(vec![
+            Policy::time_locked(NOW + 1_000).to_bytes().unwrap(),
+            Policy::composite(vec![Policy::time_locked(NOW + 1_000), Policy::destination_allowlist(vec![allowed])]).to_bytes().unwrap(),
+        ]);
+
+        let elsewhere = TokenTransfer { mint, amount: 5, decimals: 6, destination_ata: Pubkey::new_unique() };
+        assert_eq!(
+            run(&mut setup, &elsewhere.to_transaction_data(), NOW),
+            Err(EmergencyError::Denied(DenyReason::Policy))
+        );
+        let listed = TokenTransfer { destination_ata: allowed, ..elsewhere };
+        assert_eq!(run(&mut setup, &listed.to_transaction_data(), NOW), Ok(5));
+    }
+
+    #[test]
+    fn test_lockout_is_never_bypassed() {
+        let mut setup = setup(1_000);
+        setup.account.settings.lockout_threshold = 3;
+        setup.account.locked_until = NOW + 60;
+        assert_eq!(
+            run(&mut setup, &transfer(10, Pubkey::new_unique()), NOW),
+            Err(EmergencyError::LockedOut { until: NOW + 60 })
+        );
+    }
+
+    #[test]
+    fn test_off_until_configured() {
+        let mut setup = setup(1_000);
+        setup.account.emergency_override = EmergencyOverride::default();
+        assert_eq!(run(&mut setup, &transfer(10, Pubkey::new_unique()), NOW), Err(EmergencyError::NotEnabled));
+
+        // Configuring takes the primary passkey, and a period with a cap
+        let config = EmergencyOverride::config_bytes(1_000, DAY);
+        let challenge = compute_challenge(&setup.account.owner, 2, &action_message_hash(EMERGENCY_CONFIGURE_ACTION, &config));
+        let sig = setup.laptop.sign(&challenge);
+        assert!(matches!(
+            configure_emergency_override(&mut setup.account, sig, 2, 1_000, DAY),
+            Err(EmergencyError::Unauthorized(_))
+        ));
+        let sig = setup.phone.sign(&challenge);
+        assert_eq!(configure_emergency_override(&mut setup.account, sig, 2, 1_000, 0), Err(EmergencyError::InvalidPeriod));
+    }
+}
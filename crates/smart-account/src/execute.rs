@@ -1,6 +1,18 @@
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{pubkey::Pubkey, program_error::ProgramError};
-use crate::account::AttestaAccount;
+use core_crypto::CryptoError;
+use recovery::{credential_id_hash, Amount, Policy, PolicyContext};
+use crate::account::{cluster_time, AttestaAccount};
 use crate::auth::AuthorizationProof;
+use crate::idempotency::{ExecutionReceipt, ExecutionStatus, IdempotencyKey};
+use crate::social_recovery::within_recovery_allowance;
+use crate::token::{is_self_transfer, TokenTransfer};
+use crate::vault::announcement_delay;
+
+pub use attesta_types::transaction::{
+    check_memo, memo_hash, transaction_memo_message_hash, transaction_message_hash, TransactionRequest,
+    TransactionRequestError, MAX_MEMO_LEN, MAX_TRANSACTION_DATA_LEN,
+};
 
 /// The result of checking if a transaction is allowed by the account's policy
 ///
@@ -11,11 +23,221 @@ pub enum PolicyResult {
     /// The transaction is allowed and can proceed
     Allowed,
     
-    /// The transaction is denied by the policy (e.g., exceeds spending limit)
-    Denied,
+    /// The transaction is denied (e.g., exceeds spending limit)
+    Denied(DenyReason),
     
     /// The transaction needs additional approvals (e.g., multi-sig required)
     RequiresApproval,
+
+    /// A retry of a transaction that already ran (matched by idempotency key)
+    ///
+    /// Nothing is executed again; `nonce` is the nonce the original consumed.
+    AlreadyExecuted { nonce: u64 },
+
+    /// A transfer over a vault's threshold, announced instead of executed
+    ///
+    /// The proof's nonce is used up; the same proof runs it with
+    /// `vault::execute_announced` from `executable_at`.
+    Announced { executable_at: i64 },
+}
+
+/// Why a transaction was denied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenyReason {
+    /// The account's policy doesn't allow it (or can't be read)
+    Policy,
+
+    /// It moves nothing and the account rejects zero-amount transfers
+    ZeroAmount,
+
+    /// It sends to the account itself and the account rejects self-transfers
+    SelfTransfer,
+
+    /// The account's own policy allows it, but its parent's doesn't
+    ParentPolicy,
+
+    /// The signature didn't verify (only with lockout on; it's counted)
+    AuthenticationFailed,
+
+    /// Too many bad signatures: nothing is checked until `until`
+    LockedOut { until: i64 },
+
+    /// A recovery is pending, and the transaction isn't a transfer within
+    /// the account's `recovery_allowance`
+    RecoveryPending,
+}
+
+impl DenyReason {
+    /// The reason's code in an `ExecuteOutcome`
+    pub fn code(&self) -> u8 {
+        match self {
+            DenyReason::Policy => 0,
+            DenyReason::ZeroAmount => 1,
+            DenyReason::SelfTransfer => 2,
+            DenyReason::ParentPolicy => 3,
+            DenyReason::AuthenticationFailed => 4,
+            DenyReason::LockedOut { .. } => 5,
+            DenyReason::RecoveryPending => 6,
+        }
+    }
+}
+
+/// What an `execute` decided, as reported in the instruction's return data
+///
+/// Set on every path that reaches the policy, including a denial that
+/// fails the instruction: the runtime still reports the return data in simulations and transaction
+/// metadata, so relayers needn't fetch the account to learn what happened.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecuteOutcome {
+    /// The nonce the transaction consumed if it ran (for a retry, when it
+    /// first ran); otherwise the account's nonce, which didn't change
+    pub new_nonce: u64,
+
+    /// Which `PolicyResult` it was: `ALLOWED`, `DENIED`, `REQUIRES_APPROVAL`
+    /// or `ALREADY_EXECUTED`
+    pub policy_result: u8,
+
+    /// Raw token amount moved by this instruction (0 unless it ran a token transfer)
+    pub amount_charged: u64,
+
+    /// `DenyReason::code` when it was denied
+    pub deny_reason: Option<u8>,
+
+    /// SHA-256 of the transaction's memo, if it had one
+    pub memo_hash: Option<[u8; 32]>,
+
+    /// SHA-256 of the credential ID that signed it, when a passkey did and
+    /// its signature was read (not for retries answered from the records)
+    pub credential_id_hash: Option<[u8; 32]>,
+}
+
+/// `ExecuteOutcome` as programs without memos encoded it
+#[derive(BorshDeserialize)]
+struct LegacyExecuteOutcome {
+    new_nonce: u64,
+    policy_result: u8,
+    amount_charged: u64,
+    deny_reason: Option<u8>,
+}
+
+/// `ExecuteOutcome` as programs with memos, but before signer hashes, encoded it
+#[derive(BorshDeserialize)]
+struct MemoExecuteOutcome {
+    new_nonce: u64,
+    policy_result: u8,
+    amount_charged: u64,
+    deny_reason: Option<u8>,
+    memo_hash: Option<[u8; 32]>,
+}
+
+impl ExecuteOutcome {
+    /// It ran in this instruction
+    pub const ALLOWED: u8 = 0;
+
+    /// Nothing ran; `deny_reason` says why
+    pub const DENIED: u8 = 1;
+
+    /// Nothing ran: it's valid but needs more approvals, and waits in its
+    /// proposal PDA (see `proposal::find_proposal_address`) for them
+    pub const REQUIRES_APPROVAL: u8 = 2;
+
+    /// A retry of a transaction that already ran; nothing ran again
+    pub const ALREADY_EXECUTED: u8 = 3;
+
+    /// Nothing ran: it's over a vault's threshold and waits in its
+    /// announcement PDA (see `vault::find_announcement_address`) for the delay
+    pub const ANNOUNCED: u8 = 4;
+
+    /// The outcome of `result`, leaving the account at `new_nonce`
+    ///
+    /// A retry reports the nonce from `PolicyResult::AlreadyExecuted` instead.
+    pub fn new(result: &PolicyResult, new_nonce: u64, amount_charged: u64) -> Self {
+        let (policy_result, new_nonce, deny_reason) = match result {
+            PolicyResult::Allowed => (Self::ALLOWED, new_nonce, None),
+            PolicyResult::Denied(reason) => (Self::DENIED, new_nonce, Some(reason.code())),
+            PolicyResult::RequiresApproval => (Self::REQUIRES_APPROVAL, new_nonce, None),
+            PolicyResult::AlreadyExecuted { nonce } => (Self::ALREADY_EXECUTED, *nonce, None),
+            PolicyResult::Announced { .. } => (Self::ANNOUNCED, new_nonce, None),
+        };
+        Self { new_nonce, policy_result, amount_charged, deny_reason, memo_hash: None, credential_id_hash: None }
+    }
+
+    /// Reports the memo's hash as well
+    pub fn with_memo_hash(mut self, memo_hash: Option<[u8; 32]>) -> Self {
+        self.memo_hash = memo_hash;
+        self
+    }
+
+    /// Reports which credential signed it as well
+    pub fn with_credential_id_hash(mut self, credential_id_hash: Option<[u8; 32]>) -> Self {
+        self.credential_id_hash = credential_id_hash;
+        self
+    }
+
+    /// Encodes the outcome for `set_return_data`
+    pub fn to_return_data(&self) -> Vec<u8> {
+        // Serializing into a Vec can't fail
+        borsh::to_vec(self).unwrap_or_default()
+    }
+
+    /// Decodes an outcome from `execute`'s return data
+    ///
+    /// # Returns
+    /// `None` if the data isn't an outcome (in this encoding, the one from
+    /// before signer hashes, or the one from before memos)
+    pub fn from_return_data(data: &[u8]) -> Option<Self> {
+        borsh::from_slice(data)
+            .ok()
+            .or_else(|| {
+                let memo: MemoExecuteOutcome = borsh::from_slice(data).ok()?;
+                Some(Self {
+                    new_nonce: memo.new_nonce,
+                    policy_result: memo.policy_result,
+                    amount_charged: memo.amount_charged,
+                    deny_reason: memo.deny_reason,
+                    memo_hash: memo.memo_hash,
+                    credential_id_hash: None,
+                })
+            })
+            .or_else(|| {
+                let legacy: LegacyExecuteOutcome = borsh::from_slice(data).ok()?;
+                Some(Self {
+                    new_nonce: legacy.new_nonce,
+                    policy_result: legacy.policy_result,
+                    amount_charged: legacy.amount_charged,
+                    deny_reason: legacy.deny_reason,
+                    memo_hash: None,
+                    credential_id_hash: None,
+                })
+            })
+    }
+
+    /// Whether the transaction ran in this instruction
+    pub fn executed(&self) -> bool {
+        self.policy_result == Self::ALLOWED
+    }
+
+    /// The outcome as an `ExecutionReceipt`, for the paths that don't fail the
+    /// instruction and didn't leave the transaction waiting for approvals
+    pub fn receipt(&self) -> Option<ExecutionReceipt> {
+        let status = match (self.policy_result, self.deny_reason) {
+            (Self::ALLOWED, _) => ExecutionStatus::Executed,
+            (Self::ALREADY_EXECUTED, _) => ExecutionStatus::AlreadyExecuted,
+            (Self::DENIED, Some(code)) if code == DenyReason::AuthenticationFailed.code() => {
+                ExecutionStatus::AuthenticationFailed
+            }
+            (Self::DENIED, Some(code)) if code == DenyReason::LockedOut { until: 0 }.code() => {
+                ExecutionStatus::LockedOut
+            }
+            _ => return None,
+        };
+        Some(ExecutionReceipt {
+            status,
+            nonce: self.new_nonce,
+            memo_hash: self.memo_hash,
+            credential_id_hash: self.credential_id_hash,
+        })
+    }
 }
 
 /// Executes a transaction on behalf of an Attesta account
@@ -27,39 +249,146 @@ pub enum PolicyResult {
 ///
 /// # Parameters
 /// - `account`: The user's Attesta account (will be updated if transaction succeeds)
+/// - `account_address`: The account's address (its PDA), for self-transfer checks
+/// - `parent`: The parent account, required if `account` is a sub-account
 /// - `proof`: The authorization proof showing they signed the transaction
-/// - `transaction_data`: The transaction data to execute (for policy evaluation)
+/// - `transaction_data`: The transaction data to execute (must hash to `proof.message_hash`)
 ///
 /// # Returns
 /// - `Ok(PolicyResult::Allowed)` if the transaction is executed successfully
 /// - `Ok(PolicyResult::RequiresApproval)` if more signatures are needed
-/// - `Ok(PolicyResult::Denied)` if the policy blocks it
+/// - `Ok(PolicyResult::Denied(reason))` if the settings or policy block it
+/// - `Ok(PolicyResult::AlreadyExecuted)` if the proof's idempotency key,
+///   nonce, and message hash match an earlier execution
+/// - `Ok(PolicyResult::Announced)` if it's a transfer over a vault's
+///   threshold; the nonce is used up but nothing runs yet (see `vault`)
 /// - `Err(ProgramError)` if the proof is invalid or something goes wrong
+///   (with lockout on, a bad signature is `Denied(AuthenticationFailed)`
+///   instead, so the failure can be counted)
+///   (a sub-account without its `parent` is `ProgramError::NotEnoughAccountKeys`)
+///   (an idempotency key reused for a different transaction is
+///   `CryptoError::IdempotencyKeyReused`)
+///   (an `AuthMode::OwnerOnly` account, which takes `execute_as_owner`
+///   instead, is `ProgramError::MissingRequiredSignature`)
 ///
 /// # Side Effects
 /// If the transaction is allowed, this will:
 /// - Increment the account's nonce (prevents replay)
 /// - Update the account's `updated_at` timestamp
+/// - Remember the idempotency key, if the proof has one
+/// - Clear any count of failed signatures
+///
+/// With lockout on, a bad signature is counted (and may lock the account)
+/// even though nothing executes.
 pub fn execute_transaction(
     account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
+    proof: &AuthorizationProof,
+    transaction_data: &[u8],
+) -> Result<PolicyResult, ProgramError> {
+    // Off-chain there's no clock - use the last known time
+    let now = cluster_time(account.updated_at);
+    execute_transaction_at(account, account_address, parent, proof, transaction_data, now)
+}
+
+/// Answers a resubmitted proof from the account alone
+///
+/// When several relayers race to submit the same envelope, the ones that
+/// land after the first find its idempotency record here, before their
+/// signature is parsed or verified. A nonce that's used up without such a
+/// record can never run either, and fails as cheaply.
+///
+/// # Returns
+/// - `Ok(Some(nonce))` if the account recorded this execution, under `nonce`
+/// - `Ok(None)` if it's new and still has to be verified
+/// - `Err(IdempotencyKeyReused)` if the key was recorded for another transaction
+/// - `Err(ReplayAttack)` if the nonce isn't above the account's
+pub fn check_replay(
+    account: &AttestaAccount,
+    nonce: u64,
+    message_hash: &[u8; 32],
+    idempotency_key: Option<&IdempotencyKey>,
+) -> Result<Option<u64>, ProgramError> {
+    // A retry of something that already ran is answered from the record.
+    // Its nonce is used up, so it would otherwise fail as a replay.
+    if let Some(record) = idempotency_key.and_then(|key| account.find_idempotency_record(key)) {
+        if record.message_hash == *message_hash && record.nonce == nonce {
+            return Ok(Some(record.nonce));
+        }
+        return Err(ProgramError::Custom(CryptoError::IdempotencyKeyReused as u32));
+    }
+    if !account.validate_nonce(nonce) {
+        return Err(ProgramError::Custom(CryptoError::ReplayAttack as u32));
+    }
+    Ok(None)
+}
+
+/// `execute_transaction` at a given time, for callers that supply the clock
+///
+/// `now` is the Unix timestamp lockouts and time-based policies are checked
+/// against, and the account's new `updated_at`.
+pub fn execute_transaction_at(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
     proof: &AuthorizationProof,
     transaction_data: &[u8],
+    now: i64,
 ) -> Result<PolicyResult, ProgramError> {
     // Step 1: Verify the user actually authorized this transaction
-    // This checks the signature and nonce
-    proof.verify(account)
-        .map_err(|e| ProgramError::Custom(e as u32))?;
+    // The proof must be for this exact transaction data, and the signature
+    // and nonce must check out
+    if proof.message_hash != transaction_memo_message_hash(transaction_data, &proof.memo) {
+        return Err(ProgramError::Custom(CryptoError::ChallengeMismatch as u32));
+    }
+
+    if let Some(nonce) = check_replay(account, proof.nonce, &proof.message_hash, proof.idempotency_key.as_ref())? {
+        return Ok(PolicyResult::AlreadyExecuted { nonce });
+    }
+
+    // An owner-only account takes the owner's wallet signature instead
+    if !account.settings.auth_mode.allows_passkey() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let lockout = account.settings.lockout_threshold > 0;
+    if lockout && account.is_locked_out(now) {
+        return Ok(PolicyResult::Denied(DenyReason::LockedOut { until: account.locked_until }));
+    }
+
+    if let Err(e) = proof.verify(account) {
+        if !lockout {
+            return Err(ProgramError::Custom(e as u32));
+        }
+        account.record_failed_auth(now);
+        return Ok(PolicyResult::Denied(DenyReason::AuthenticationFailed));
+    }
+    account.reset_failed_auth();
 
     // Step 2: Check if the policy allows this transaction
     // Even if the signature is valid, the policy might block it
-    let policy_result = evaluate_policy(account, transaction_data)?;
+    let signer = credential_id_hash(&proof.webauthn_sig.credential_id);
+    let mut policy_result = evaluate_policy(account, account_address, parent, Some(signer), transaction_data, now)?;
+    if policy_result == PolicyResult::Allowed {
+        if let Some(delay) = announcement_delay(account, parent, transaction_data) {
+            policy_result = PolicyResult::Announced { executable_at: now.saturating_add(i64::from(delay)) };
+        }
+    }
 
     // Step 3: If everything checks out, execute the transaction
     match policy_result {
         PolicyResult::Allowed => {
             // Mark the transaction as complete
             // This increments the nonce so it can't be replayed
-            account.increment_nonce();
+            account.increment_nonce(now);
+            account.record_sign_count(&proof.webauthn_sig);
+            record_transfer(account, transaction_data, now);
+            // A sign of life: pushes back any inheritance claim
+            account.last_execution_at = account.updated_at;
+            if let Some(key) = proof.idempotency_key {
+                account.record_idempotency_key(key, proof.message_hash, proof.nonce);
+            }
             Ok(PolicyResult::Allowed)
         }
         PolicyResult::RequiresApproval => {
@@ -67,16 +396,28 @@ pub fn execute_transaction(
             // Don't increment nonce yet - wait for additional approvals
             Ok(PolicyResult::RequiresApproval)
         }
-        PolicyResult::Denied => {
-            // Policy says no - reject the transaction
-            Err(ProgramError::InvalidArgument)
+        PolicyResult::Denied(reason) => {
+            // Policy says no - nothing runs and the nonce isn't used up
+            Ok(PolicyResult::Denied(reason))
+        }
+        PolicyResult::Announced { executable_at } => {
+            // The nonce reserves the announcement, so it's used up now. The
+            // idempotency key isn't recorded: nothing has executed yet
+            account.increment_nonce(now);
+            account.record_sign_count(&proof.webauthn_sig);
+            account.last_execution_at = account.updated_at;
+            Ok(PolicyResult::Announced { executable_at })
         }
+        // evaluate_policy never returns this - retries are handled above
+        PolicyResult::AlreadyExecuted { nonce } => Ok(PolicyResult::AlreadyExecuted { nonce }),
     }
 }
 
-/// Checks if a transaction is allowed by the account's policy
+/// Checks if a transaction is allowed by the account's settings and policy
 ///
-/// Policies can restrict transactions based on things like:
+/// The account's `AccountSettings` are checked first, then any pending
+/// recovery's allowance, then its policy, then (for a sub-account) the
+/// parent's policy. Policies can restrict transactions based on things like:
 /// - Spending limits (max amount per transaction)
 /// - Daily limits (max amount per day)
 /// - Time locks (transactions only allowed after a certain time)
@@ -84,38 +425,143 @@ pub fn execute_transaction(
 ///
 /// # Parameters
 /// - `account`: The account with the policy to check
+/// - `account_address`: The account's address
+/// - `parent`: The parent account, if `account` is a sub-account
+/// - `signer`: SHA-256 of the verified signing credential's ID, if known
 /// - `transaction_data`: The transaction data (for extracting amount, destination, etc.)
+/// - `now`: The Unix timestamp time-based policies are checked against
 ///
 /// # Returns
 /// - `Ok(PolicyResult::Allowed)` if the policy allows it
-/// - `Ok(PolicyResult::Denied)` if the policy blocks it
+/// - `Ok(PolicyResult::Denied(reason))` if the settings or policy block it
 /// - `Ok(PolicyResult::RequiresApproval)` if more approvals are needed
+/// - `Err(ProgramError::NotEnoughAccountKeys)` for a sub-account without its parent
 ///
 /// # Note
-/// This is a simplified implementation. In production, you'd:
-/// - Parse the policy structure properly
-/// - Extract transaction details (amount, destination, program ID)
-/// - Check spending limits, time locks, allowlists, etc.
-/// - Track daily spending separately
-fn evaluate_policy(
+/// SPL token transfers are checked against the policy's per-mint limits.
+/// Other transaction data doesn't say how much it moves yet, so it's
+/// evaluated as moving nothing (time locks still apply).
+pub(crate) fn evaluate_policy(
     account: &AttestaAccount,
-    _transaction_data: &[u8],
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
+    signer: Option<[u8; 32]>,
+    transaction_data: &[u8],
+    now: i64,
 ) -> Result<PolicyResult, ProgramError> {
+    let transfer = TokenTransfer::from_transaction_data(transaction_data);
+
+    // Only token transfers say how much they move and where to; other
+    // transaction data is never treated as a zero or self transfer
+    if let Some(transfer) = &transfer {
+        if account.settings.reject_zero_amount && transfer.amount == 0 {
+            return Ok(PolicyResult::Denied(DenyReason::ZeroAmount));
+        }
+        if account.settings.reject_self_transfer && is_self_transfer(transfer, account_address) {
+            return Ok(PolicyResult::Denied(DenyReason::SelfTransfer));
+        }
+    }
+
+    // The passkey signing may be the one a recovery is replacing
+    if !within_recovery_allowance(account, transfer.as_ref()) {
+        return Ok(PolicyResult::Denied(DenyReason::RecoveryPending));
+    }
+
+    let own_result = evaluate_account_policy(account, transfer.as_ref(), signer, now);
+    if own_result != PolicyResult::Allowed || account.parent.is_none() {
+        return Ok(own_result);
+    }
+
+    // The parent's policy is a ceiling: the sub-account can't do anything it forbids
+    let parent = parent.ok_or(ProgramError::NotEnoughAccountKeys)?;
+    match evaluate_account_policy(parent, transfer.as_ref(), signer, now) {
+        PolicyResult::Denied(_) => Ok(PolicyResult::Denied(DenyReason::ParentPolicy)),
+        parent_result => Ok(parent_result),
+    }
+}
+
+/// Evaluates just `account`'s own policies for a transaction
+fn evaluate_account_policy(
+    account: &AttestaAccount,
+    transfer: Option<&TokenTransfer>,
+    signer: Option<[u8; 32]>,
+    now: i64,
+) -> PolicyResult {
+    let mut context = match transfer {
+        Some(transfer) => PolicyContext::token(transfer.mint, transfer.amount, transfer.decimals, now)
+            .with_destination(transfer.destination_ata),
+        None => PolicyContext::sol(Amount::ZERO, now),
+    };
+    context.signer_credential_id = signer;
+    evaluate_policies(account, &context)
+}
+
+/// Runs the account's own policies against `context`
+///
+/// Per-destination limits count what the account's `destination_spends`
+/// says was already sent this window.
+pub(crate) fn evaluate_policies(account: &AttestaAccount, context: &PolicyContext) -> PolicyResult {
     // If there's no policy configured, default to allowing all transactions
     // This makes it easier for users to get started
-    if account.policy.is_empty() {
-        return Ok(PolicyResult::Allowed);
+    let policies = account.policies();
+    if policies.is_empty() {
+        return PolicyResult::Allowed;
     }
 
-    // TODO: In production, properly parse and evaluate the policy
-    // For now, we'll do basic validation:
-    // - Check policy structure is valid
-    // - Extract transaction details from transaction_data
-    // - Evaluate spending limits, time locks, etc.
-    
-    // Placeholder: assume policy passes basic checks
-    // In real implementation, decode policy and check all conditions
-    Ok(PolicyResult::Allowed)
+    combine_policy_results(policies.into_iter().map(|bytes| {
+        // A policy we can't read is treated as a policy that says no
+        match Policy::from_bytes(bytes) {
+            Ok(policy)
+                if policy
+                    .check_context(context)
+                    .and_then(|()| policy.check_destination_spend(context, &account.destination_spends))
+                    .is_ok() =>
+            {
+                PolicyResult::Allowed
+            }
+            _ => PolicyResult::Denied(DenyReason::Policy),
+        }
+    }))
+}
+
+/// Counts an executed transfer against the account's per-destination limits
+///
+/// Call once the transfer described by `context` is allowed. A
+/// sub-account's parent is only a ceiling, so its tracker isn't updated.
+pub(crate) fn record_destination_spends(account: &mut AttestaAccount, context: &PolicyContext) {
+    let policies: Vec<Policy> =
+        account.policies().into_iter().filter_map(|bytes| Policy::from_bytes(bytes).ok()).collect();
+    for policy in policies {
+        policy.record_destination_spend(context, &mut account.destination_spends);
+    }
+}
+
+/// `record_destination_spends` for executed transaction data
+///
+/// Only token transfers say where they send funds; other data records nothing.
+pub(crate) fn record_transfer(account: &mut AttestaAccount, transaction_data: &[u8], now: i64) {
+    if let Some(transfer) = TokenTransfer::from_transaction_data(transaction_data) {
+        let context = PolicyContext::token(transfer.mint, transfer.amount, transfer.decimals, now)
+            .with_destination(transfer.destination_ata);
+        record_destination_spends(account, &context);
+    }
+}
+
+/// Combines the results of an account's policies, in evaluation order
+///
+/// The first denial wins and the rest aren't evaluated. Otherwise the
+/// transaction needs approval if any policy asked for it, and is allowed
+/// if every one allowed it.
+fn combine_policy_results(results: impl IntoIterator<Item = PolicyResult>) -> PolicyResult {
+    let mut combined = PolicyResult::Allowed;
+    for result in results {
+        match result {
+            PolicyResult::Denied(_) => return result,
+            PolicyResult::RequiresApproval => combined = PolicyResult::RequiresApproval,
+            PolicyResult::Allowed | PolicyResult::AlreadyExecuted { .. } | PolicyResult::Announced { .. } => {}
+        }
+    }
+    combined
 }
 
 /// Checks if an instruction is allowed by the account's policy
@@ -141,3 +587,560 @@ pub fn validate_instruction(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::{CredentialBinding, CredentialBindings, DestinationLimit, DestinationLimits, MintLimit, MintLimits};
+    use crate::account::AccountSettings;
+    use crate::sub_account::new_sub_account;
+    use crate::token::derive_associated_token_address;
+
+    fn signed_proof(passkey: &mut TestPasskey, account: &AttestaAccount, nonce: u64, request: &TransactionRequest) -> AuthorizationProof {
+        let message_hash = request.message_hash();
+        let challenge = compute_challenge(&account.owner, nonce, &message_hash);
+        let mut proof = AuthorizationProof::new(passkey.sign(&challenge), nonce, message_hash);
+        proof.memo = request.memo.clone();
+        proof
+    }
+
+    #[test]
+    fn test_execute_signed_transaction() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data), Ok(PolicyResult::Allowed));
+        assert_eq!(account.nonce, 1);
+
+        // The same proof can't be submitted twice
+        assert!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data).is_err());
+    }
+
+    #[test]
+    fn test_execute_rejects_different_transaction_data() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, b"transfer 100 SOL"),
+            Err(ProgramError::Custom(CryptoError::ChallengeMismatch as u32))
+        );
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_execute_checks_the_signed_memo() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let request = TransactionRequest::new(b"pay 5 USDC".to_vec());
+        let mismatch = Err(ProgramError::Custom(CryptoError::ChallengeMismatch as u32));
+
+        // Signed without a memo: a relayer can't attach one
+        let mut proof = signed_proof(&mut passkey, &account, 1, &request);
+        proof.memo = b"order-1042".to_vec();
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data), mismatch);
+
+        // Signed with one: it can't be swapped or dropped
+        let with_memo = request.memo("order-1042").unwrap();
+        let mut proof = signed_proof(&mut passkey, &account, 1, &with_memo);
+        proof.memo = b"order-9999".to_vec();
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &with_memo.transaction_data), mismatch);
+        proof.memo.clear();
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &with_memo.transaction_data), mismatch);
+
+        proof.memo = with_memo.memo.clone();
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &with_memo.transaction_data),
+            Ok(PolicyResult::Allowed)
+        );
+        assert_eq!(account.nonce, 1);
+    }
+
+    #[test]
+    fn test_token_transfer_checked_against_mint_limits() {
+        let mut passkey = TestPasskey::new(1);
+        let usdc = Pubkey::new_unique();
+        let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint: usdc, max_amount: 100_000_000, decimals: 6 }],
+        });
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(
+            Pubkey::new_unique(),
+            passkey.public_key(),
+            passkey.credential_id(),
+            policy.to_bytes().unwrap(),
+            100,
+        );
+
+        let transfer = |mint, amount| TransactionRequest::from_token_transfer(TokenTransfer {
+            mint,
+            amount,
+            decimals: 6,
+            destination_ata: Pubkey::new_unique(),
+        });
+
+        let under = transfer(usdc, 100_000_000);
+        let proof = signed_proof(&mut passkey, &account, 1, &under);
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &under.transaction_data), Ok(PolicyResult::Allowed));
+
+        let over = transfer(usdc, 100_000_001);
+        let proof = signed_proof(&mut passkey, &account, 2, &over);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &over.transaction_data),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+
+        let unlisted = transfer(Pubkey::new_unique(), 1);
+        let proof = signed_proof(&mut passkey, &account, 2, &unlisted);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &unlisted.transaction_data),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+        assert_eq!(account.nonce, 1);
+    }
+
+    #[test]
+    fn test_retry_with_idempotency_key_returns_original_result() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+
+        let proof = signed_proof(&mut passkey, &account, 1, &request).with_idempotency_key([7u8; 16]);
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data), Ok(PolicyResult::Allowed));
+
+        // The first attempt landed; the client's retry is answered, not replayed
+        let before = account.clone();
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &request.transaction_data),
+            Ok(PolicyResult::AlreadyExecuted { nonce: 1 })
+        );
+        assert_eq!(account, before);
+
+        // Without the key, the same proof is still a replay
+        let mut without_key = proof.clone();
+        without_key.idempotency_key = None;
+        assert!(execute_transaction(&mut account, &address, None, &without_key, &request.transaction_data).is_err());
+    }
+
+    #[test]
+    fn test_replays_are_answered_before_the_signature_is_checked() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        account.settings.lockout_threshold = 1;
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+        let proof = signed_proof(&mut passkey, &account, 1, &request).with_idempotency_key([7u8; 16]);
+        assert_eq!(check_replay(&account, 1, &proof.message_hash, Some(&[7u8; 16])), Ok(None));
+        execute_transaction(&mut account, &address, None, &proof, &request.transaction_data).unwrap();
+
+        // A second relayer's copy, even with its signature mangled in transit
+        let mut copy = proof.clone();
+        copy.webauthn_sig.signature.clear();
+        assert_eq!(check_replay(&account, 1, &copy.message_hash, Some(&[7u8; 16])), Ok(Some(1)));
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &copy, &request.transaction_data),
+            Ok(PolicyResult::AlreadyExecuted { nonce: 1 })
+        );
+
+        // A used-up nonce without a record fails without counting against a
+        // lockout, since no signature was checked
+        copy.idempotency_key = None;
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &copy, &request.transaction_data),
+            Err(ProgramError::Custom(CryptoError::ReplayAttack as u32))
+        );
+        assert_eq!(account.failed_auth_count, 0);
+    }
+
+    #[test]
+    fn test_idempotency_key_reused_for_different_transaction_rejected() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let first = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+        let second = TransactionRequest::new(b"transfer 2 SOL".to_vec());
+
+        let proof = signed_proof(&mut passkey, &account, 1, &first).with_idempotency_key([7u8; 16]);
+        execute_transaction(&mut account, &address, None, &proof, &first.transaction_data).unwrap();
+
+        let reused = signed_proof(&mut passkey, &account, 2, &second).with_idempotency_key([7u8; 16]);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &reused, &second.transaction_data),
+            Err(ProgramError::Custom(CryptoError::IdempotencyKeyReused as u32))
+        );
+        assert_eq!(account.nonce, 1);
+
+        // A fresh key goes through normally
+        let fresh = reused.with_idempotency_key([8u8; 16]);
+        assert_eq!(execute_transaction(&mut account, &address, None, &fresh, &second.transaction_data), Ok(PolicyResult::Allowed));
+    }
+
+    fn settings_account(settings: AccountSettings) -> AttestaAccount {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [1u8; 64], vec![1], vec![], 100);
+        account.settings = settings;
+        account
+    }
+
+    #[test]
+    fn test_bad_signatures_lock_the_account() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        account.settings.lockout_threshold = 2;
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+
+        let mut forged = signed_proof(&mut passkey, &account, 1, &request);
+        forged.webauthn_sig.signature[0] ^= 1;
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &forged, &request.transaction_data),
+            Ok(PolicyResult::Denied(DenyReason::AuthenticationFailed))
+        );
+        assert_eq!(account.failed_auth_count, 1);
+
+        // A good signature clears the count
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data), Ok(PolicyResult::Allowed));
+        assert_eq!(account.failed_auth_count, 0);
+
+        // Two in a row lock it, and then even a good signature is refused
+        let mut forged = signed_proof(&mut passkey, &account, 2, &request);
+        forged.webauthn_sig.signature[0] ^= 1;
+        for _ in 0..2 {
+            execute_transaction(&mut account, &address, None, &forged, &request.transaction_data).unwrap();
+        }
+        let proof = signed_proof(&mut passkey, &account, 2, &request);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &request.transaction_data),
+            Ok(PolicyResult::Denied(DenyReason::LockedOut { until: account.updated_at + 60 }))
+        );
+        assert_eq!(account.nonce, 1);
+    }
+
+    #[test]
+    fn test_bad_signature_is_an_error_without_lockout() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+
+        let mut forged = signed_proof(&mut passkey, &account, 1, &request);
+        forged.webauthn_sig.signature[0] ^= 1;
+        assert!(execute_transaction(&mut account, &address, None, &forged, &request.transaction_data).is_err());
+        assert_eq!(account.failed_auth_count, 0);
+    }
+
+    fn transfer_data(amount: u64, destination_ata: Pubkey) -> Vec<u8> {
+        TokenTransfer { mint: Pubkey::new_unique(), amount, decimals: 6, destination_ata }.to_transaction_data()
+    }
+
+    #[test]
+    fn test_zero_amount_rejected_only_when_enabled() {
+        let address = Pubkey::new_unique();
+        let strict = settings_account(AccountSettings { reject_zero_amount: true, ..Default::default() });
+
+        assert_eq!(
+            evaluate_policy(&strict, &address, None, None, &transfer_data(0, Pubkey::new_unique()), strict.updated_at),
+            Ok(PolicyResult::Denied(DenyReason::ZeroAmount))
+        );
+        assert_eq!(
+            evaluate_policy(&strict, &address, None, None, &transfer_data(1, Pubkey::new_unique()), strict.updated_at),
+            Ok(PolicyResult::Allowed)
+        );
+        // Data that doesn't state an amount isn't a zero-amount transfer
+        assert_eq!(evaluate_policy(&strict, &address, None, None, b"data", strict.updated_at), Ok(PolicyResult::Allowed));
+
+        let default = settings_account(AccountSettings::default());
+        assert_eq!(
+            evaluate_policy(&default, &address, None, None, &transfer_data(0, Pubkey::new_unique()), default.updated_at),
+            Ok(PolicyResult::Allowed)
+        );
+    }
+
+    #[test]
+    fn test_self_transfer_rejected_only_when_enabled() {
+        let address = Pubkey::new_unique();
+        let strict = settings_account(AccountSettings { reject_self_transfer: true, ..Default::default() });
+
+        assert_eq!(
+            evaluate_policy(&strict, &address, None, None, &transfer_data(5, address), strict.updated_at),
+            Ok(PolicyResult::Denied(DenyReason::SelfTransfer))
+        );
+        let mint = Pubkey::new_unique();
+        let own_ata = TokenTransfer {
+            mint,
+            amount: 5,
+            decimals: 6,
+            destination_ata: derive_associated_token_address(&address, &mint),
+        };
+        assert_eq!(
+            evaluate_policy(&strict, &address, None, None, &own_ata.to_transaction_data(), strict.updated_at),
+            Ok(PolicyResult::Denied(DenyReason::SelfTransfer))
+        );
+        assert_eq!(
+            evaluate_policy(&strict, &address, None, None, &transfer_data(5, Pubkey::new_unique()), strict.updated_at),
+            Ok(PolicyResult::Allowed)
+        );
+
+        let default = settings_account(AccountSettings::default());
+        assert_eq!(evaluate_policy(&default, &address, None, None, &transfer_data(5, address), default.updated_at), Ok(PolicyResult::Allowed));
+    }
+
+    #[test]
+    fn test_denied_transaction_keeps_nonce() {
+        let mut passkey = TestPasskey::new(1);
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        account.settings.reject_self_transfer = true;
+        let request = TransactionRequest::new(transfer_data(5, address));
+
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &request.transaction_data),
+            Ok(PolicyResult::Denied(DenyReason::SelfTransfer))
+        );
+        assert_eq!(account.nonce, 0);
+    }
+
+    fn limited_account(max_amount: u64, mint: Pubkey) -> AttestaAccount {
+        let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint, max_amount, decimals: 6 }],
+        });
+        AttestaAccount::new(Pubkey::new_unique(), [1u8; 64], vec![1], policy.to_bytes().unwrap(), 100)
+    }
+
+    #[test]
+    fn test_credential_binding_checks_the_verified_signer() {
+        let mut phone = TestPasskey::new(1);
+        let hardware_key = TestPasskey::new(2);
+        let (cold_wallet, exchange) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let bind = |default: &TestPasskey| {
+            Policy::credential_binding(CredentialBindings {
+                bindings: vec![CredentialBinding { destinations: vec![cold_wallet], credential_id_hash: None }],
+                default: Some(credential_id_hash(&default.credential_id())),
+            })
+            .to_bytes()
+            .unwrap()
+        };
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), bind(&hardware_key), 100);
+        let address = Pubkey::new_unique();
+
+        // The phone may pay the cold wallet...
+        let request = TransactionRequest::new(transfer_data(5, cold_wallet));
+        let proof = signed_proof(&mut phone, &account, 1, &request);
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data), Ok(PolicyResult::Allowed));
+
+        // ...but anywhere else needs the hardware key
+        let request = TransactionRequest::new(transfer_data(5, exchange));
+        let proof = signed_proof(&mut phone, &account, 2, &request);
+        assert_eq!(
+            execute_transaction(&mut account, &address, None, &proof, &request.transaction_data),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+
+        account.policy = bind(&phone);
+        assert_eq!(execute_transaction(&mut account, &address, None, &proof, &request.transaction_data), Ok(PolicyResult::Allowed));
+    }
+
+    #[test]
+    fn test_per_destination_limit_counts_executed_transfers() {
+        let mut passkey = TestPasskey::new(1);
+        let (payroll, vendor) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (anchor, day) = (1_700_000_000, 86_400);
+        let policy = Policy::per_destination_limit(DestinationLimits {
+            limits: vec![DestinationLimit { destination: payroll, max_amount: Some(1_000) }],
+            default_max_amount: 100,
+            window_seconds: day as u32,
+            anchor_timestamp: anchor,
+        });
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), policy.to_bytes().unwrap(), 100);
+        let address = Pubkey::new_unique();
+        let mut send = |account: &mut AttestaAccount, amount: u64, destination: Pubkey, now: i64| {
+            let request = TransactionRequest::new(transfer_data(amount, destination));
+            let proof = signed_proof(&mut passkey, account, account.nonce + 1, &request);
+            execute_transaction_at(account, &address, None, &proof, &request.transaction_data, now)
+        };
+
+        assert_eq!(send(&mut account, 800, payroll, anchor + 1), Ok(PolicyResult::Allowed));
+        assert_eq!(send(&mut account, 100, vendor, anchor + 2), Ok(PolicyResult::Allowed));
+        assert_eq!(send(&mut account, 1, vendor, anchor + 3), Ok(PolicyResult::Denied(DenyReason::Policy)));
+        assert_eq!(send(&mut account, 201, payroll, anchor + 4), Ok(PolicyResult::Denied(DenyReason::Policy)));
+        assert_eq!(send(&mut account, 200, payroll, anchor + 5), Ok(PolicyResult::Allowed));
+        assert_eq!(account.destination_spends.entries.len(), 2);
+
+        // Denied transfers aren't counted, and the next window starts over
+        assert_eq!(send(&mut account, 100, vendor, anchor + day), Ok(PolicyResult::Allowed));
+        assert_eq!(account.destination_spends.window_start, anchor + day);
+        assert_eq!(account.destination_spends.entries.len(), 1);
+        assert_eq!(account.nonce, 4);
+    }
+
+    #[test]
+    fn test_parent_policy_caps_sub_account() {
+        let usdc = Pubkey::new_unique();
+        let parent_address = Pubkey::new_unique();
+        let parent = limited_account(100, usdc);
+        let hot = new_sub_account(&parent, parent_address, 0, [2u8; 64], vec![2], vec![], 100).unwrap();
+        let transfer = |amount| TokenTransfer {
+            mint: usdc,
+            amount,
+            decimals: 6,
+            destination_ata: Pubkey::new_unique(),
+        }
+        .to_transaction_data();
+
+        // The sub-account's own (empty) policy allows anything; the parent's doesn't
+        assert_eq!(
+            evaluate_policy(&hot, &Pubkey::new_unique(), Some(&parent), None, &transfer(100), hot.updated_at),
+            Ok(PolicyResult::Allowed)
+        );
+        assert_eq!(
+            evaluate_policy(&hot, &Pubkey::new_unique(), Some(&parent), None, &transfer(101), hot.updated_at),
+            Ok(PolicyResult::Denied(DenyReason::ParentPolicy))
+        );
+
+        // A stricter sub-account policy still applies on its own
+        let mut strict = limited_account(10, usdc);
+        strict.parent = Some(parent_address);
+        assert_eq!(
+            evaluate_policy(&strict, &Pubkey::new_unique(), Some(&parent), None, &transfer(11), strict.updated_at),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+    }
+
+    #[test]
+    fn test_sub_account_without_parent_account_fails() {
+        let parent = AttestaAccount::new(Pubkey::new_unique(), [1u8; 64], vec![1], vec![], 100);
+        let hot = new_sub_account(&parent, Pubkey::new_unique(), 0, [2u8; 64], vec![2], vec![], 100).unwrap();
+
+        assert_eq!(
+            evaluate_policy(&hot, &Pubkey::new_unique(), None, None, b"data", hot.updated_at),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn test_unreadable_policy_denies() {
+        let account = AttestaAccount::new(Pubkey::new_unique(), [1u8; 64], vec![1], vec![0xff; 3], 100);
+        assert_eq!(
+            evaluate_policy(&account, &Pubkey::new_unique(), None, None, b"data", account.updated_at),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+    }
+
+    #[test]
+    fn test_deny_beats_approval_beats_allow() {
+        use PolicyResult::{Allowed, Denied, RequiresApproval};
+        let denied = Denied(DenyReason::Policy);
+
+        assert_eq!(combine_policy_results([]), Allowed);
+        assert_eq!(combine_policy_results([Allowed, Allowed]), Allowed);
+        assert_eq!(combine_policy_results([Allowed, RequiresApproval, Allowed]), RequiresApproval);
+        assert_eq!(combine_policy_results([RequiresApproval, denied]), denied);
+        assert_eq!(combine_policy_results([denied, RequiresApproval]), denied);
+
+        // Policies after the first denial aren't evaluated
+        let mut evaluated = 0;
+        let results = [Allowed, denied, Allowed].into_iter().inspect(|_| evaluated += 1);
+        assert_eq!(combine_policy_results(results), denied);
+        assert_eq!(evaluated, 2);
+    }
+
+    #[test]
+    fn test_every_policy_must_allow() {
+        let unlock = recovery::policies::MIN_POLICY_TIMESTAMP + 1_000;
+        let mint = Pubkey::new_unique();
+        let mut account = limited_account(100, mint);
+        let mut policies: Vec<Vec<u8>> = account.policies().into_iter().map(<[u8]>::to_vec).collect();
+        policies.push(Policy::time_locked(unlock).to_bytes().unwrap());
+        account.set_policies(policies);
+
+        let transfer = |amount| TokenTransfer { mint, amount, decimals: 6, destination_ata: Pubkey::new_unique() }
+            .to_transaction_data();
+        let address = Pubkey::new_unique();
+
+        // Within the limit, but still locked
+        assert_eq!(
+            evaluate_policy(&account, &address, None, None, &transfer(100), unlock - 1),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+        assert_eq!(evaluate_policy(&account, &address, None, None, &transfer(100), unlock), Ok(PolicyResult::Allowed));
+        // Unlocked, but over the limit
+        assert_eq!(
+            evaluate_policy(&account, &address, None, None, &transfer(101), unlock),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+
+        // A list entry that can't be read denies like a bad single policy
+        account.additional_policies.push(vec![0xff]);
+        assert_eq!(
+            evaluate_policy(&account, &address, None, None, &transfer(1), unlock),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+    }
+
+    #[test]
+    fn test_execute_outcome_return_data() {
+        let cases = [
+            (PolicyResult::Allowed, ExecuteOutcome::ALLOWED, None),
+            (PolicyResult::Denied(DenyReason::ParentPolicy), ExecuteOutcome::DENIED, Some(3)),
+            (PolicyResult::RequiresApproval, ExecuteOutcome::REQUIRES_APPROVAL, None),
+        ];
+        for (result, policy_result, deny_reason) in cases {
+            let outcome = ExecuteOutcome::new(&result, 4, 250);
+            let decoded = ExecuteOutcome::from_return_data(&outcome.to_return_data()).unwrap();
+            assert_eq!(
+                decoded,
+                ExecuteOutcome { new_nonce: 4, policy_result, amount_charged: 250, deny_reason, memo_hash: None, credential_id_hash: None }
+            );
+            assert_eq!(decoded.executed(), result == PolicyResult::Allowed);
+        }
+
+        // A retry reports the nonce it originally consumed
+        let retry = ExecuteOutcome::new(&PolicyResult::AlreadyExecuted { nonce: 2 }, 4, 0);
+        assert_eq!((retry.policy_result, retry.new_nonce), (ExecuteOutcome::ALREADY_EXECUTED, 2));
+
+        assert_eq!(ExecuteOutcome::from_return_data(&[0; 9]), None);
+    }
+
+    #[test]
+    fn test_execute_outcome_memo_hash() {
+        let outcome = ExecuteOutcome::new(&PolicyResult::Allowed, 4, 0).with_memo_hash(Some([9; 32]));
+        assert_eq!(ExecuteOutcome::from_return_data(&outcome.to_return_data()), Some(outcome));
+
+        // Programs from before memos didn't encode the field
+        let legacy = borsh::to_vec(&(4u64, ExecuteOutcome::ALLOWED, 250u64, None::<u8>)).unwrap();
+        assert_eq!(
+            ExecuteOutcome::from_return_data(&legacy),
+            Some(ExecuteOutcome::new(&PolicyResult::Allowed, 4, 250))
+        );
+    }
+
+    #[test]
+    fn test_execute_outcome_credential_id_hash() {
+        let outcome = ExecuteOutcome::new(&PolicyResult::Allowed, 4, 0)
+            .with_memo_hash(Some([9; 32]))
+            .with_credential_id_hash(Some([7; 32]));
+        assert_eq!(ExecuteOutcome::from_return_data(&outcome.to_return_data()), Some(outcome));
+        assert_eq!(outcome.receipt().unwrap().credential_id_hash, Some([7; 32]));
+
+        // Programs from before signer hashes stopped after the memo hash
+        let memo = borsh::to_vec(&(4u64, ExecuteOutcome::ALLOWED, 0u64, None::<u8>, Some([9u8; 32]))).unwrap();
+        assert_eq!(
+            ExecuteOutcome::from_return_data(&memo),
+            Some(ExecuteOutcome::new(&PolicyResult::Allowed, 4, 0).with_memo_hash(Some([9; 32])))
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Who may submit `execute` for an account
+//!
+//! A passkey proof can be submitted by anyone, which is what lets relayers
+//! pay the fee for users with no SOL. An account that wants only its own
+//! relayers (or its own wallet) to submit lists them in
+//! `AccountSettings::authorized_executors`: `execute` then requires its
+//! `authority` to sign and be on the list. An empty list keeps relaying
+//! permissionless, as every account was before the list existed.
+//!
+//! The list only decides who may submit. The passkey proof and the policies
+//! are checked exactly the same either way. Changing the list takes the
+//! primary passkey.
+
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_admin_action;
+
+/// Action name the primary passkey signs, over the executor's address, to add it
+pub const EXECUTOR_ADD_ACTION: &[u8] = b"add_executor";
+
+/// Action name the primary passkey signs, over the executor's address, to remove it
+pub const EXECUTOR_REMOVE_ACTION: &[u8] = b"remove_executor";
+
+/// Most executors an account can list
+pub const MAX_AUTHORIZED_EXECUTORS: usize = 8;
+
+/// Errors from changing the executor list or submitting as an executor
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ExecutorError {
+    #[error("Executor change rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The executor is already listed")]
+    AlreadyListed,
+
+    #[error("The executor isn't listed")]
+    NotListed,
+
+    #[error("The account already lists {MAX_AUTHORIZED_EXECUTORS} executors")]
+    TooManyExecutors,
+
+    #[error("The account only accepts executions submitted by a listed executor")]
+    ExecutorNotAllowed,
+}
+
+/// Checks that `authority` may submit `execute` for `account`
+///
+/// # Parameters
+/// - `authority`: The instruction's `authority` account
+/// - `is_signer`: Whether `authority` signed the transaction
+///
+/// # Returns
+/// - `Ok(())` if the list is empty, or `authority` signed and is on it
+/// - `Err(ExecutorError::ExecutorNotAllowed)` otherwise
+pub fn check_executor(account: &AttestaAccount, authority: &Pubkey, is_signer: bool) -> Result<(), ExecutorError> {
+    let executors = &account.settings.authorized_executors;
+    if executors.is_empty() || (is_signer && executors.contains(authority)) {
+        return Ok(());
+    }
+    Err(ExecutorError::ExecutorNotAllowed)
+}
+
+/// Lists `executor` as allowed to submit `execute`
+///
+/// Uses up `nonce`. The first executor added ends permissionless relaying.
+///
+/// # Parameters
+/// - `webauthn_sig`: The primary passkey's signature over
+///   `EXECUTOR_ADD_ACTION` for the executor's address
+pub fn add_executor(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    executor: &Pubkey,
+) -> Result<(), ExecutorError> {
+    let executors = &account.settings.authorized_executors;
+    if executors.contains(executor) {
+        return Err(ExecutorError::AlreadyListed);
+    }
+    if executors.len() >= MAX_AUTHORIZED_EXECUTORS {
+        return Err(ExecutorError::TooManyExecutors);
+    }
+    authorize_admin_action(account, webauthn_sig, nonce, EXECUTOR_ADD_ACTION, executor.as_ref())?;
+    account.settings.authorized_executors.push(*executor);
+    account.bump_state_version();
+    Ok(())
+}
+
+/// Takes `executor` off the list
+///
+/// Uses up `nonce`. Removing the last executor makes relaying
+/// permissionless again.
+///
+/// # Parameters
+/// - `webauthn_sig`: The primary passkey's signature over
+///   `EXECUTOR_REMOVE_ACTION` for the executor's address
+pub fn remove_executor(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    executor: &Pubkey,
+) -> Result<(), ExecutorError> {
+    if !account.settings.authorized_executors.contains(executor) {
+        return Err(ExecutorError::NotListed);
+    }
+    authorize_admin_action(account, webauthn_sig, nonce, EXECUTOR_REMOVE_ACTION, executor.as_ref())?;
+    account.settings.authorized_executors.retain(|listed| listed != executor);
+    account.bump_state_version();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use crate::auth::action_message_hash;
+
+    fn setup() -> (AttestaAccount, TestPasskey) {
+        let passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        (account, passkey)
+    }
+
+    fn sign(account: &AttestaAccount, passkey: &mut TestPasskey, action: &[u8], executor: &Pubkey) -> WebAuthnSignature {
+        let challenge = compute_challenge(&account.owner, account.nonce + 1, &action_message_hash(action, executor.as_ref()));
+        passkey.sign(&challenge)
+    }
+
+    #[test]
+    fn test_empty_list_is_permissionless() {
+        let (account, _) = setup();
+        assert_eq!(check_executor(&account, &Pubkey::new_unique(), false), Ok(()));
+        assert_eq!(check_executor(&account, &Pubkey::new_unique(), true), Ok(()));
+    }
+
+    #[test]
+    fn test_listed_executor_must_sign() {
+        let (mut account, mut passkey) = setup();
+        let relayer = Pubkey::new_unique();
+        let sig = sign(&account, &mut passkey, EXECUTOR_ADD_ACTION, &relayer);
+        add_executor(&mut account, sig, 1, &relayer).unwrap();
+        assert_eq!(account.nonce, 1);
+        assert_eq!(account.settings.authorized_executors, vec![relayer]);
+
+        assert_eq!(check_executor(&account, &relayer, true), Ok(()));
+        assert_eq!(check_executor(&account, &relayer, false), Err(ExecutorError::ExecutorNotAllowed));
+        assert_eq!(check_executor(&account, &Pubkey::new_unique(), true), Err(ExecutorError::ExecutorNotAllowed));
+
+        let sig = sign(&account, &mut passkey, EXECUTOR_ADD_ACTION, &relayer);
+        assert_eq!(add_executor(&mut account, sig, 2, &relayer), Err(ExecutorError::AlreadyListed));
+
+        // Removing the last one opens relaying back up
+        let sig = sign(&account, &mut passkey, EXECUTOR_REMOVE_ACTION, &relayer);
+        remove_executor(&mut account, sig, 2, &relayer).unwrap();
+        assert!(account.settings.authorized_executors.is_empty());
+        assert_eq!(check_executor(&account, &Pubkey::new_unique(), false), Ok(()));
+        let sig = sign(&account, &mut passkey, EXECUTOR_REMOVE_ACTION, &relayer);
+        assert_eq!(remove_executor(&mut account, sig, 3, &relayer), Err(ExecutorError::NotListed));
+    }
+
+    #[test]
+    fn test_changes_take_the_primary_passkey_over_the_right_action() {
+        let (mut account, mut passkey) = setup();
+        let relayer = Pubkey::new_unique();
+
+        // A signature over the remove action doesn't add
+        let sig = sign(&account, &mut passkey, EXECUTOR_REMOVE_ACTION, &relayer);
+        assert!(matches!(add_executor(&mut account, sig, 1, &relayer), Err(ExecutorError::Unauthorized(_))));
+        // Nor does one over a different executor
+        let sig = sign(&account, &mut passkey, EXECUTOR_ADD_ACTION, &Pubkey::new_unique());
+        assert!(matches!(add_executor(&mut account, sig, 1, &relayer), Err(ExecutorError::Unauthorized(_))));
+        assert!(account.settings.authorized_executors.is_empty());
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_list_is_bounded() {
+        let (mut account, mut passkey) = setup();
+        account.settings.authorized_executors = (0..MAX_AUTHORIZED_EXECUTORS).map(|_| Pubkey::new_unique()).collect();
+        let relayer = Pubkey::new_unique();
+        let sig = sign(&account, &mut passkey, EXECUTOR_ADD_ACTION, &relayer);
+        assert_eq!(add_executor(&mut account, sig, 1, &relayer), Err(ExecutorError::TooManyExecutors));
+    }
+}
@@ -0,0 +1,393 @@
+//! Byte-for-byte stability of everything stored on-chain
+//!
+//! Each persisted type has a hex fixture in `fixtures/`, written by an
+//! earlier build. These tests check that the current code still reads every
+//! fixture and writes it back unchanged. A layout change that wasn't meant
+//! fails here, instead of corrupting accounts the next time one is saved.
+//!
+//! When a change is deliberate (a new trailing field on `AttestaAccount`, or
+//! a new `MULTI_PASSKEY_VERSION`), regenerate the fixtures and commit them
+//! with the change:
+//!
+//! ```text
+//! cargo test -p smart-account regen_fixtures -- --ignored
+//! ```
+//!
+//! Fixtures of older layouts stay in place (older `multi_passkey_vN`, an
+//! account from before its settings were stored as flags and tagged
+//! entries, one from before it recorded its layout, one from before the
+//! emergency override, one from before it recorded its account index, and
+//! a backup whose key hash predates
+//! domain-separated hashing): they must keep deserializing, but are
+//! rewritten in the current layout, so only the current one is held to a
+//! byte-identical round trip. Every older account
+//! layout is also loaded and checked against the defaults `compat` lists.
+
+use std::fs;
+use std::path::PathBuf;
+use borsh::{BorshDeserialize, BorshSerialize};
+use core_crypto::test_utils::TestPasskey;
+use core_crypto::{RelyingParty, WebAuthnVerificationProfile};
+use recovery::multi_passkey::MULTI_PASSKEY_VERSION;
+use recovery::{
+    Amount, CredentialBinding, CredentialBindings, DestinationLimit, DestinationLimits, DestinationSpend, DestinationSpends,
+    EncryptedBackup, MintLimit, MintLimits, MultiPasskey, PasskeyEntry, Policy,
+};
+use solana_program::pubkey::Pubkey;
+use crate::account::{AccountSettings, AttestaAccount, SignCount};
+use crate::auth_mode::AuthMode;
+use crate::compat::{stored_layout, ACCOUNT_LAYOUT_VERSION, TRAILING_FIELDS, WEBAUTHN_PROFILE_RECORDED_LAYOUT};
+use crate::emergency::{EmergencyOverride, EMERGENCY_OVERRIDE_SIZE};
+use crate::idempotency::IdempotencyRecord;
+use crate::inheritance::InheritanceConfig;
+use crate::proof_log::RetiredKey;
+use crate::social_recovery::RecoveryRequest;
+
+/// One persisted type, with a sample value that exercises its whole layout
+struct Fixture {
+    name: String,
+    sample: Vec<u8>,
+    reencode: fn(&[u8]) -> Vec<u8>,
+}
+
+impl Fixture {
+    fn new<T: BorshSerialize + BorshDeserialize>(name: impl Into<String>, sample: &T) -> Self {
+        Self { name: name.into(), sample: borsh::to_vec(sample).unwrap(), reencode: reencode::<T> }
+    }
+
+    fn path(&self) -> PathBuf {
+        fixtures_dir().join(format!("{}.hex", self.name))
+    }
+}
+
+/// Reads `bytes` as a `T` and writes it back
+fn reencode<T: BorshSerialize + BorshDeserialize>(bytes: &[u8]) -> Vec<u8> {
+    let value = T::try_from_slice(bytes).expect("fixture no longer deserializes");
+    borsh::to_vec(&value).unwrap()
+}
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+fn pubkey(seed: u8) -> Pubkey {
+    Pubkey::new_from_array([seed; 32])
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut rest = hex.as_str();
+    while !rest.is_empty() {
+        let (line, tail) = rest.split_at(rest.len().min(64));
+        lines.push(line);
+        rest = tail;
+    }
+    lines.join("\n") + "\n"
+}
+
+fn from_hex(text: &str) -> Vec<u8> {
+    let digits: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).expect("bad hex in fixture"))
+        .collect()
+}
+
+fn sample_registry() -> MultiPasskey {
+    let mut registry = MultiPasskey::new(TestPasskey::new(1).public_key(), b"phone".to_vec(), "Phone".to_string(), 100, 2, 5);
+    registry.add_passkey(TestPasskey::new(2).public_key(), b"laptop".to_vec(), "Laptop".to_string(), 110).unwrap();
+    let key = PasskeyEntry::new(TestPasskey::new(3).public_key(), b"security-key".to_vec(), "Key".to_string(), 120)
+        .with_aaguid(Some([0xaa; 16]));
+    registry.add_entry(key).unwrap();
+    let long_id = [0x11; 64];
+    registry.add_entry(PasskeyEntry::new_hashed(TestPasskey::new(7).public_key(), &long_id, "Tablet".to_string(), 130)).unwrap();
+    registry
+}
+
+pub(crate) fn sample_account() -> AttestaAccount {
+    let policy = Policy::spending_limit(Amount::from_lamports(1_000)).to_bytes().unwrap();
+    let mut account = AttestaAccount::new(pubkey(1), TestPasskey::new(1).public_key(), b"phone".to_vec(), policy, 100);
+    account.set_passkey_registry(&sample_registry()).unwrap();
+    account.nonce = 7;
+    account.updated_at = 200;
+    account.idempotency_records = vec![IdempotencyRecord { key: [5; 16], message_hash: [6; 32], nonce: 7 }];
+    account.pending_recovery = Some(RecoveryRequest {
+        new_public_key: TestPasskey::new(4).public_key(),
+        new_credential_id: b"new-phone".to_vec(),
+        approvals: vec![[8; 32]],
+        initiated_at: 150,
+        threshold_met_at: None,
+        new_aaguid: Some([0xbb; 16]),
+    });
+    account.settings = AccountSettings {
+        reject_zero_amount: true,
+        lockout_threshold: 3,
+        aaguid_allowlist: vec![[0xaa; 16], [0xbb; 16]],
+        pinned_program_version: Some([0xcc; 32]),
+        webauthn_profile: WebAuthnVerificationProfile::standard(),
+        relying_party: Some(RelyingParty::new("wallet.example", "https://wallet.example")),
+        auth_mode: AuthMode::OwnerOrPasskey,
+        authorized_executors: vec![pubkey(10)],
+        log_allowed_sample_rate: 20,
+        ..AccountSettings::default()
+    };
+    account.parent = Some(pubkey(2));
+    account.sub_account_index = 1;
+    account.inheritance = Some(InheritanceConfig::new(TestPasskey::new(5).public_key(), b"heir".to_vec(), 86_400, 3_600));
+    account.last_execution_at = 190;
+    account.key_history = vec![RetiredKey {
+        credential_id_hash: [9; 32],
+        public_key: TestPasskey::new(6).public_key(),
+        retired_at: 120,
+    }];
+    account.proof_log_enabled = true;
+    let policies = vec![account.policy.clone(), Policy::time_locked(1_700_000_000).to_bytes().unwrap()];
+    account.set_policies(policies);
+    account.passkey_aaguid = Some([0xaa; 16]);
+    account.sign_counts = vec![SignCount { credential_id_hash: [0xdd; 32], sign_count: 12 }];
+    account.destination_spends = DestinationSpends {
+        window_start: 180,
+        entries: vec![DestinationSpend { destination_hash: [0xee; 32], spent: 40 }],
+        evicted: 5,
+    };
+    account.state_version = 4;
+    account.emergency_override = EmergencyOverride { max_lamports: 500, period_seconds: 86_400, last_used_at: 170 };
+    account.account_index = Some(7);
+    account
+}
+
+pub(crate) fn sample_policies() -> Vec<(&'static str, Policy)> {
+    vec![
+        ("open", Policy::open()),
+        (
+            "spending_limit",
+            Policy::spending_limit(Amount::from_lamports(1_000)).with_mint_limits(MintLimits {
+                allow_unlisted: false,
+                limits: vec![MintLimit { mint: pubkey(3), max_amount: 500, decimals: 6 }],
+            }),
+        ),
+        ("daily_limit", Policy::windowed_limit(Amount::from_lamports(5_000), 3_600, 1_700_000_000)),
+        ("multi_sig", Policy::multi_sig(vec![pubkey(4), pubkey(5)])),
+        ("time_locked", Policy::time_locked(1_700_000_000)),
+        ("destination_allowlist", Policy::destination_allowlist(vec![pubkey(6), pubkey(7)])),
+        (
+            "composite",
+            Policy::composite(vec![Policy::spending_limit(Amount::from_lamports(10)), Policy::time_locked(1_700_000_000)]),
+        ),
+        (
+            "credential_binding",
+            Policy::credential_binding(CredentialBindings {
+                bindings: vec![CredentialBinding { destinations: vec![pubkey(8)], credential_id_hash: Some([1; 32]) }],
+                default: None,
+            }),
+        ),
+        (
+            "per_destination_limit",
+            Policy::per_destination_limit(DestinationLimits {
+                limits: vec![DestinationLimit { destination: pubkey(9), max_amount: Some(1_000) }],
+                default_max_amount: 100,
+                window_seconds: 86_400,
+                anchor_timestamp: 1_700_000_000,
+            }),
+        ),
+    ]
+}
+
+fn fixtures() -> Vec<Fixture> {
+    let mut fixtures = vec![
+        Fixture::new("attesta_account", &sample_account()),
+        Fixture::new(format!("multi_passkey_v{}", MULTI_PASSKEY_VERSION), &sample_registry()),
+        Fixture::new("encrypted_backup", &EncryptedBackup::new(b"backup key", b"account data", 100)),
+    ];
+    for (name, policy) in sample_policies() {
+        fixtures.push(Fixture::new(format!("policy_{}", name), &policy));
+    }
+    fixtures
+}
+
+#[test]
+fn test_fixtures_round_trip_byte_for_byte() {
+    for fixture in fixtures() {
+        let text = fs::read_to_string(fixture.path())
+            .unwrap_or_else(|_| panic!("missing fixture {}; run regen_fixtures if the format changed on purpose", fixture.name));
+        let bytes = from_hex(&text);
+        assert_eq!((fixture.reencode)(&bytes), bytes, "{} is no longer written the way it was stored", fixture.name);
+    }
+}
+
+#[test]
+fn test_current_samples_match_fixtures() {
+    // Catches a layout change that still round-trips, like a reordered field
+    for fixture in fixtures() {
+        let stored = fs::read_to_string(fixture.path()).map(|text| from_hex(&text)).unwrap_or_default();
+        assert_eq!(fixture.sample, stored, "{} layout changed; bump its version and run regen_fixtures", fixture.name);
+    }
+}
+
+#[test]
+fn test_older_registry_layouts_still_read() {
+    let current = format!("multi_passkey_v{}.hex", MULTI_PASSKEY_VERSION);
+    for entry in fs::read_dir(fixtures_dir()).unwrap() {
+        let name = entry.unwrap().file_name().into_string().unwrap();
+        if name.starts_with("multi_passkey_v") && name != current {
+            let bytes = from_hex(&fs::read_to_string(fixtures_dir().join(&name)).unwrap());
+            let registry = MultiPasskey::try_from_slice(&bytes).unwrap_or_else(|e| panic!("{} no longer reads: {}", name, e));
+            assert_eq!(registry.version, MULTI_PASSKEY_VERSION);
+        }
+    }
+}
+
+#[test]
+fn test_legacy_settings_layout_still_reads() {
+    // Written before settings moved into flags and tagged entries
+    let bytes = from_hex(&fs::read_to_string(fixtures_dir().join("attesta_account_legacy_settings.hex")).unwrap());
+    let account = AttestaAccount::from_bytes(&bytes).unwrap();
+    let sample = AttestaAccount {
+        created_layout: 0,
+        emergency_override: EmergencyOverride::default(),
+        account_index: None,
+        ..sample_account()
+    };
+    assert_eq!(account.settings, sample.settings);
+    assert_eq!(account.to_bytes().unwrap(), sample.to_bytes().unwrap());
+}
+
+#[test]
+fn test_older_backup_versions_still_open() {
+    let bytes = from_hex(&fs::read_to_string(fixtures_dir().join("encrypted_backup_v1.hex")).unwrap());
+    let backup = EncryptedBackup::from_bytes(&bytes).unwrap();
+    assert_eq!(backup.version, 1);
+    assert_eq!(backup.decrypt(b"backup key"), Ok(b"account data".to_vec()));
+    assert!(backup.check_key(b"another key").is_err());
+}
+
+/// `account` as read from data that ends after `layout` trailing fields:
+/// every field after that takes the default `compat` documents
+///
+/// Spelled out field by field rather than taken from `Default`, so a
+/// changed default fails the matrix below instead of following along.
+fn with_defaults_after(mut account: AttestaAccount, layout: u8) -> AttestaAccount {
+    for field in &TRAILING_FIELDS[usize::from(layout)..] {
+        match *field {
+            "passkeys" => account.passkeys = Vec::new(),
+            "privacy_mode" => account.privacy_mode = false,
+            "idempotency_records" => account.idempotency_records = Vec::new(),
+            "pending_recovery" => account.pending_recovery = None,
+            "pending_drill" => account.pending_drill = None,
+            "last_drill_at" => account.last_drill_at = 0,
+            "settings" => {
+                account.settings.reject_zero_amount = false;
+                account.settings.reject_self_transfer = false;
+                account.settings.max_transaction_data_len = 0;
+                account.settings.lockout_threshold = 0;
+            }
+            "parent" => account.parent = None,
+            "sub_account_index" => account.sub_account_index = 0,
+            "inheritance" => account.inheritance = None,
+            "last_execution_at" => account.last_execution_at = 0,
+            "failed_auth_count" => account.failed_auth_count = 0,
+            "locked_until" => account.locked_until = 0,
+            "key_history" => account.key_history = Vec::new(),
+            "proof_log_enabled" => account.proof_log_enabled = false,
+            "additional_policies" => account.additional_policies = Vec::new(),
+            "passkey_aaguid" => account.passkey_aaguid = None,
+            "aaguid_allowlist" => account.settings.aaguid_allowlist = Vec::new(),
+            "recovery_aaguid" => {
+                if let Some(request) = account.pending_recovery.as_mut() {
+                    request.new_aaguid = None;
+                }
+            }
+            "pinned_program_version" => account.settings.pinned_program_version = None,
+            "webauthn_profile" => account.settings.webauthn_profile = WebAuthnVerificationProfile::legacy(),
+            "relying_party" => account.settings.relying_party = None,
+            "sign_counts" => account.sign_counts = Vec::new(),
+            "auth_mode" => account.settings.auth_mode = AuthMode::PasskeyOnly,
+            "auth_mode_locked" => account.settings.auth_mode_locked = false,
+            "policy_hash" => account.policy_hash = account.compute_policy_hash(),
+            "destination_spends" => account.destination_spends = DestinationSpends { window_start: 0, entries: Vec::new(), evicted: 0 },
+            "authorized_executors" => account.settings.authorized_executors = Vec::new(),
+            "state_version" => account.state_version = 0,
+            "log_allowed_sample_rate" => account.settings.log_allowed_sample_rate = 0,
+            "compact_settings" => {}
+            "created_layout" => account.created_layout = 0,
+            "emergency_override" => account.emergency_override = EmergencyOverride::default(),
+            "account_index" => account.account_index = None,
+            other => panic!("no documented default for {}; add it to compat and here", other),
+        }
+    }
+    account
+}
+
+#[test]
+fn test_every_account_layout_reads_with_documented_defaults() {
+    let fixture = |name: &str| from_hex(&fs::read_to_string(fixtures_dir().join(name)).unwrap());
+    let current = fixture("attesta_account.hex");
+    let before_layouts = fixture("attesta_account_layout_31.hex");
+    let before_emergency = fixture("attesta_account_layout_32.hex");
+    let before_index = fixture("attesta_account_layout_33.hex");
+    let legacy_settings = fixture("attesta_account_legacy_settings.hex");
+    assert_eq!(stored_layout(&current).unwrap(), ACCOUNT_LAYOUT_VERSION);
+    assert_eq!(stored_layout(&before_layouts).unwrap(), 31);
+    assert_eq!(stored_layout(&before_emergency).unwrap(), 32);
+    assert_eq!(stored_layout(&before_index).unwrap(), 33);
+    assert_eq!(stored_layout(&legacy_settings).unwrap(), 30);
+
+    // Each older layout is the legacy fixture cut where that layout ended
+    let mut accounts: Vec<(u8, &[u8])> = Vec::new();
+    for len in 0..=legacy_settings.len() {
+        let prefix = &legacy_settings[..len];
+        if let Ok(layout) = stored_layout(prefix) {
+            if accounts.last().map(|(last, _)| *last) != Some(layout) {
+                accounts.push((layout, prefix));
+            }
+        }
+    }
+    accounts.push((31, &before_layouts));
+    accounts.push((32, &before_emergency));
+    accounts.push((33, &before_index));
+    accounts.push((ACCOUNT_LAYOUT_VERSION, &current));
+    let layouts: Vec<u8> = accounts.iter().map(|(layout, _)| *layout).collect();
+    assert_eq!(layouts, (0..=ACCOUNT_LAYOUT_VERSION).collect::<Vec<_>>());
+
+    let full = sample_account();
+    for (layout, bytes) in accounts {
+        let account = AttestaAccount::from_bytes(bytes).unwrap_or_else(|e| panic!("layout {} no longer reads: {}", layout, e));
+        let mut expected = with_defaults_after(full.clone(), layout);
+        // Accounts that record their layout were created in the one they're stored in
+        if layout >= WEBAUTHN_PROFILE_RECORDED_LAYOUT {
+            expected.created_layout = layout;
+        }
+        assert_eq!(account, expected, "layout {} read a different default", layout);
+    }
+}
+
+#[test]
+fn test_recorded_layouts_must_store_their_profile() {
+    // An account that records its layout stores its profile even when it's legacy
+    let mut account = sample_account();
+    account.settings = AccountSettings::default();
+    let bytes = account.to_bytes().unwrap();
+    assert_eq!(AttestaAccount::from_bytes(&bytes).unwrap(), account);
+
+    // So one without it lost it, and doesn't read as legacy
+    let mut unrecorded = account.clone();
+    unrecorded.created_layout = 0;
+    let mut bytes = unrecorded.to_bytes().unwrap();
+    // Behind it: the emergency override, then the account index (a tag and a u16)
+    let created_layout = bytes.len() - 3 - EMERGENCY_OVERRIDE_SIZE - 1;
+    bytes[created_layout] = ACCOUNT_LAYOUT_VERSION;
+    assert!(AttestaAccount::from_bytes(&bytes).is_err());
+}
+
+/// Rewrites every fixture from the current code
+///
+/// Only for deliberate, versioned format changes.
+#[test]
+#[ignore]
+fn regen_fixtures() {
+    fs::create_dir_all(fixtures_dir()).unwrap();
+    for fixture in fixtures() {
+        fs::write(fixture.path(), to_hex(&fixture.sample)).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use attesta_types::consts::{BORSH_LEN_PREFIX, HASH_LEN};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::execute::ExecuteOutcome;
+
+pub use attesta_types::envelope::{IdempotencyKey, IDEMPOTENCY_KEY_LEN};
+
+/// How many executions an account remembers for retries
+///
+/// Older records are dropped first. A retry only needs to outlive the
+/// client's timeout, so a handful is plenty.
+pub const MAX_IDEMPOTENCY_RECORDS: usize = 8;
+
+/// Serialized size of one `IdempotencyRecord`: key + message hash + nonce
+pub const IDEMPOTENCY_RECORD_SIZE: usize = IDEMPOTENCY_KEY_LEN + HASH_LEN + 8;
+
+/// Account space needed for a full list of records (including the Vec length)
+pub const IDEMPOTENCY_RECORDS_SPACE: usize = BORSH_LEN_PREFIX + MAX_IDEMPOTENCY_RECORDS * IDEMPOTENCY_RECORD_SIZE;
+
+/// A successful execution, remembered so a retry can be answered
+///
+/// The key isn't signed, so a record only answers a retry that carries the
+/// same nonce and message hash too - the exact transaction that ran.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct IdempotencyRecord {
+    /// The key the client sent with the execution
+    pub key: IdempotencyKey,
+
+    /// The message hash of the executed transaction
+    pub message_hash: [u8; 32],
+
+    /// The nonce the execution consumed
+    pub nonce: u64,
+}
+
+/// What an `execute` did, as reported in the instruction's return data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExecutionStatus {
+    /// The transaction ran in this instruction
+    Executed = 0,
+
+    /// A retry: the transaction had already run, nothing was done again
+    AlreadyExecuted = 1,
+
+    /// Nothing ran: the signature didn't verify, and the failure was counted
+    AuthenticationFailed = 2,
+
+    /// Nothing ran: the account is locked out after repeated bad signatures
+    LockedOut = 3,
+}
+
+/// What an `execute` that didn't fail did
+///
+/// The program reports an `ExecuteOutcome` in its return data;
+/// `ExecuteOutcome::receipt` turns it into one of these. Older program
+/// versions returned this receipt itself, in the `LEN`-byte encoding below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionReceipt {
+    /// Whether the transaction ran now, was already executed, or was refused
+    pub status: ExecutionStatus,
+
+    /// The nonce the transaction consumed (the original one for retries, the
+    /// submitted one if it was refused)
+    pub nonce: u64,
+
+    /// SHA-256 of the transaction's memo, if it had one (always `None` in
+    /// the older `LEN`-byte encoding)
+    pub memo_hash: Option<[u8; 32]>,
+
+    /// SHA-256 of the credential ID of the passkey that signed it, when
+    /// the program reported one (see `ExecuteOutcome::credential_id_hash`)
+    pub credential_id_hash: Option<[u8; 32]>,
+}
+
+impl ExecutionReceipt {
+    /// Size of the encoded receipt: status byte + nonce
+    pub const LEN: usize = 1 + 8;
+
+    /// Encodes the receipt for `set_return_data`
+    pub fn to_return_data(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = self.status as u8;
+        data[1..].copy_from_slice(&self.nonce.to_le_bytes());
+        data
+    }
+
+    /// Decodes a receipt from `execute`'s return data, in either encoding
+    ///
+    /// # Returns
+    /// `None` if the data isn't a receipt, or is an `ExecuteOutcome` of a
+    /// failed instruction
+    pub fn from_return_data(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN {
+            return ExecuteOutcome::from_return_data(data)?.receipt();
+        }
+        let (&status, nonce) = data.split_first()?;
+
+        let status = match status {
+            0 => ExecutionStatus::Executed,
+            1 => ExecutionStatus::AlreadyExecuted,
+            2 => ExecutionStatus::AuthenticationFailed,
+            3 => ExecutionStatus::LockedOut,
+            _ => return None,
+        };
+        let nonce = u64::from_le_bytes(nonce.try_into().ok()?);
+
+        Some(Self { status, nonce, memo_hash: None, credential_id_hash: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_size_matches_serialization() {
+        let record = IdempotencyRecord { key: [1; IDEMPOTENCY_KEY_LEN], message_hash: [2; 32], nonce: 3 };
+        assert_eq!(borsh::to_vec(&record).unwrap().len(), IDEMPOTENCY_RECORD_SIZE);
+    }
+
+    #[test]
+    fn test_receipt_return_data_round_trip() {
+        for status in [
+            ExecutionStatus::Executed,
+            ExecutionStatus::AlreadyExecuted,
+            ExecutionStatus::AuthenticationFailed,
+            ExecutionStatus::LockedOut,
+        ] {
+            let receipt = ExecutionReceipt { status, nonce: 42, memo_hash: None, credential_id_hash: None };
+            assert_eq!(ExecutionReceipt::from_return_data(&receipt.to_return_data()), Some(receipt));
+        }
+
+        assert_eq!(ExecutionReceipt::from_return_data(&[4, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+        assert_eq!(ExecutionReceipt::from_return_data(&[0; 4]), None);
+        assert_eq!(ExecutionReceipt::from_return_data(&[]), None);
+    }
+
+    #[test]
+    fn test_receipt_from_execute_outcome() {
+        use crate::execute::{DenyReason, PolicyResult};
+
+        let executed = ExecuteOutcome::new(&PolicyResult::Allowed, 7, 5);
+        assert_eq!(
+            ExecutionReceipt::from_return_data(&executed.to_return_data()),
+            Some(ExecutionReceipt { status: ExecutionStatus::Executed, nonce: 7, memo_hash: None, credential_id_hash: None })
+        );
+
+        let locked = ExecuteOutcome::new(&PolicyResult::Denied(DenyReason::LockedOut { until: 9 }), 6, 0);
+        assert_eq!(
+            ExecutionReceipt::from_return_data(&locked.to_return_data()),
+            Some(ExecutionReceipt { status: ExecutionStatus::LockedOut, nonce: 6, memo_hash: None, credential_id_hash: None })
+        );
+
+        // A policy denial fails the instruction, so there's no receipt for it
+        let denied = ExecuteOutcome::new(&PolicyResult::Denied(DenyReason::Policy), 6, 0);
+        assert_eq!(ExecutionReceipt::from_return_data(&denied.to_return_data()), None);
+
+        let memo_hash = Some([5; 32]);
+        let with_memo = ExecuteOutcome::new(&PolicyResult::Allowed, 8, 0).with_memo_hash(memo_hash);
+        assert_eq!(ExecutionReceipt::from_return_data(&with_memo.to_return_data()).unwrap().memo_hash, memo_hash);
+    }
+}
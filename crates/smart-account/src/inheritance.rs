@@ -0,0 +1,385 @@
+//! Inheritance: a dead-man switch that hands the account to a beneficiary
+//!
+//! The owner names a beneficiary passkey and an inactivity period. Every
+//! executed transaction, and an explicit heartbeat, resets the clock. Once
+//! the account has been inactive for the period, the claim is pending for
+//! the grace period: a heartbeat (or any execution) from the owner cancels
+//! it. After that anyone can finalize the claim, which installs the
+//! beneficiary's passkey as primary.
+//!
+//! The account's `owner` stays the same, since it's part of the account's
+//! address. Other registered passkeys stay too; the beneficiary can remove
+//! them once in control.
+
+use attesta_types::consts::{BORSH_LEN_PREFIX, P256_PUBKEY_LEN};
+use attesta_types::time::{validate_timestamp, TimeError, MAX_FUTURE_SECONDS};
+use borsh::{BorshDeserialize, BorshSerialize};
+use thiserror::Error;
+use core_crypto::{validate_p256_public_key, CryptoError, WebAuthnSignature};
+use recovery::multi_passkey::{MultiPasskeyError, PasskeyEntry};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_action;
+
+/// Action name a passkey signs to set (or clear) the inheritance config
+pub const INHERITANCE_CONFIGURE_ACTION: &[u8] = b"configure_inheritance";
+
+/// Action name a passkey signs to show the owner is still around
+pub const HEARTBEAT_ACTION: &[u8] = b"heartbeat";
+
+/// Shortest inactivity period that can be configured (30 days)
+///
+/// Anything shorter is too easy to trip by simply not using the account.
+pub const MIN_INACTIVITY_PERIOD: i64 = 30 * 24 * 60 * 60;
+
+/// Errors from the inheritance flow
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InheritanceError {
+    #[error("Inheritance signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("Invalid passkey registry: {0}")]
+    Registry(#[from] MultiPasskeyError),
+
+    #[error("Invalid inheritance config")]
+    InvalidConfig,
+
+    #[error("The account would first be claimable at a bad time: {0}")]
+    InvalidTimestamp(#[from] TimeError),
+
+    #[error("No inheritance is configured")]
+    NotConfigured,
+
+    #[error("The account can't be claimed before {claimable_at}")]
+    NotYetClaimable { claimable_at: i64 },
+}
+
+/// Who inherits the account, and after how long
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InheritanceConfig {
+    /// The beneficiary passkey's public key
+    pub beneficiary_public_key: [u8; P256_PUBKEY_LEN],
+
+    /// The beneficiary passkey's credential ID (hashed in privacy mode)
+    pub beneficiary_credential_id: Vec<u8>,
+
+    /// Seconds without activity before a claim starts
+    pub inactivity_period: i64,
+
+    /// Seconds a started claim waits, during which the owner can cancel it
+    pub grace_period: i64,
+}
+
+impl InheritanceConfig {
+    /// Creates a config; check it with `validate` before storing
+    pub fn new(
+        beneficiary_public_key: [u8; P256_PUBKEY_LEN],
+        beneficiary_credential_id: Vec<u8>,
+        inactivity_period: i64,
+        grace_period: i64,
+    ) -> Self {
+        Self { beneficiary_public_key, beneficiary_credential_id, inactivity_period, grace_period }
+    }
+
+    /// Serializes the config (this is also what the owner's passkey signs)
+    pub fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
+        borsh::to_vec(self)
+    }
+
+    /// Deserializes a config
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, std::io::Error> {
+        borsh::from_slice(bytes)
+    }
+
+    /// Length of the config's Borsh encoding, without serializing
+    pub fn serialized_size(&self) -> usize {
+        P256_PUBKEY_LEN + BORSH_LEN_PREFIX + self.beneficiary_credential_id.len() + 8 + 8
+    }
+
+    /// Checks the beneficiary passkey and the periods
+    pub fn validate(&self) -> Result<(), InheritanceError> {
+        validate_p256_public_key(&self.beneficiary_public_key).map_err(|_| InheritanceError::InvalidConfig)?;
+        if self.beneficiary_credential_id.is_empty()
+            || self.inactivity_period < MIN_INACTIVITY_PERIOD
+            || self.grace_period < 0
+        {
+            return Err(InheritanceError::InvalidConfig);
+        }
+        Ok(())
+    }
+
+    /// When a claim starts, given the last activity
+    pub fn claim_starts_at(&self, last_execution_at: i64) -> i64 {
+        last_execution_at.saturating_add(self.inactivity_period)
+    }
+
+    /// When the beneficiary can finalize a claim, given the last activity
+    pub fn claimable_at(&self, last_execution_at: i64) -> i64 {
+        self.claim_starts_at(last_execution_at).saturating_add(self.grace_period)
+    }
+}
+
+/// Where an account with inheritance configured stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InheritanceStatus {
+    /// The owner has been active recently
+    Active { claim_starts_at: i64 },
+
+    /// A claim is pending: the owner can still cancel it with a heartbeat
+    GracePeriod { claimable_at: i64 },
+
+    /// The beneficiary can claim the account
+    Claimable,
+}
+
+/// Reports the inheritance status, or `None` if inheritance isn't configured
+pub fn inheritance_status(account: &AttestaAccount, now: i64) -> Option<InheritanceStatus> {
+    let config = account.inheritance.as_ref()?;
+    let claim_starts_at = config.claim_starts_at(account.last_execution_at);
+    let claimable_at = config.claimable_at(account.last_execution_at);
+
+    Some(if now < claim_starts_at {
+        InheritanceStatus::Active { claim_starts_at }
+    } else if now < claimable_at {
+        InheritanceStatus::GracePeriod { claimable_at }
+    } else {
+        InheritanceStatus::Claimable
+    })
+}
+
+/// Sets the inheritance config, or clears it with `None`
+///
+/// Configuring counts as activity, so the clock starts now.
+///
+/// # Parameters
+/// - `webauthn_sig`: The owner's signature over `INHERITANCE_CONFIGURE_ACTION`
+///   for `config.to_bytes()` (an empty payload to clear it)
+/// - `config`: The new config, with the credential ID as the authenticator reports it
+pub fn configure_inheritance(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    config: Option<InheritanceConfig>,
+    now: i64,
+) -> Result<(), InheritanceError> {
+    let payload = match &config {
+        Some(config) => {
+            config.validate()?;
+            // Periods adding up to decades would make the beneficiary wait forever
+            validate_timestamp(config.claimable_at(now), now, MAX_FUTURE_SECONDS, 0)?;
+            config.to_bytes().map_err(|_| InheritanceError::InvalidConfig)?
+        }
+        None => Vec::new(),
+    };
+    authorize_action(account, webauthn_sig, nonce, INHERITANCE_CONFIGURE_ACTION, &payload)?;
+
+    account.inheritance = config.map(|mut config| {
+        config.beneficiary_credential_id = account.credential_lookup_id(&config.beneficiary_credential_id);
+        config
+    });
+    account.last_execution_at = now;
+    Ok(())
+}
+
+/// Resets the inactivity clock, cancelling a pending claim
+///
+/// # Parameters
+/// - `webauthn_sig`: A signature over `HEARTBEAT_ACTION` with an empty payload
+pub fn heartbeat(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    now: i64,
+) -> Result<(), InheritanceError> {
+    authorize_action(account, webauthn_sig, nonce, HEARTBEAT_ACTION, &[])?;
+    account.last_execution_at = now;
+    Ok(())
+}
+
+/// Hands the account to the beneficiary once the inactivity and grace periods are over
+///
+/// Needs no signature: the elapsed time is the authorization. The
+/// beneficiary's passkey becomes primary, the config is used up, and any
+/// pending recovery is dropped so it can't undo the handover.
+pub fn claim_inheritance(account: &mut AttestaAccount, now: i64) -> Result<(), InheritanceError> {
+    let config = account.inheritance.clone().ok_or(InheritanceError::NotConfigured)?;
+    let claimable_at = config.claimable_at(account.last_execution_at);
+    if now < claimable_at {
+        return Err(InheritanceError::NotYetClaimable { claimable_at });
+    }
+
+    if let Some(mut registry) = account.passkey_registry()? {
+        let entry = PasskeyEntry::new(
+            config.beneficiary_public_key,
+            config.beneficiary_credential_id.clone(),
+            "Inherited".to_string(),
+            now,
+        );
+        registry
+            .replace_primary(entry, now)
+            .map_err(|_| InheritanceError::InvalidConfig)?;
+        account.set_passkey_registry(&registry)?;
+    }
+
+    let old_credential_id = std::mem::take(&mut account.credential_id);
+    account.retire_key(&old_credential_id, account.passkey_public_key, now);
+    account.passkey_public_key = config.beneficiary_public_key;
+    account.credential_id = config.beneficiary_credential_id;
+    account.passkey_aaguid = None;
+    account.inheritance = None;
+    account.pending_recovery = None;
+    account.last_execution_at = now;
+    account.bump_state_version();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use solana_program::pubkey::Pubkey;
+    use crate::auth::action_message_hash;
+
+    const YEAR: i64 = 365 * 24 * 60 * 60;
+    const GRACE: i64 = 7 * 24 * 60 * 60;
+    const CONFIGURED_AT: i64 = 1_000;
+
+    fn sign(passkey: &mut TestPasskey, account: &AttestaAccount, action: &[u8], payload: &[u8]) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let challenge = compute_challenge(&account.owner, nonce, &action_message_hash(action, payload));
+        (passkey.sign(&challenge), nonce)
+    }
+
+    /// An account whose owner named `spouse` with a year of inactivity and a week of grace
+    fn setup() -> (AttestaAccount, TestPasskey, TestPasskey) {
+        let mut owner = TestPasskey::new(1);
+        let spouse = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), owner.public_key(), owner.credential_id(), vec![], 100);
+
+        let config = InheritanceConfig::new(spouse.public_key(), spouse.credential_id(), YEAR, GRACE);
+        let (sig, nonce) = sign(&mut owner, &account, INHERITANCE_CONFIGURE_ACTION, &config.to_bytes().unwrap());
+        configure_inheritance(&mut account, sig, nonce, Some(config), CONFIGURED_AT).unwrap();
+
+        (account, owner, spouse)
+    }
+
+    #[test]
+    fn test_claim_too_early_fails() {
+        let (mut account, _, _) = setup();
+        let claimable_at = CONFIGURED_AT + YEAR + GRACE;
+
+        for now in [CONFIGURED_AT, CONFIGURED_AT + YEAR, claimable_at - 1] {
+            assert_eq!(
+                claim_inheritance(&mut account, now),
+                Err(InheritanceError::NotYetClaimable { claimable_at })
+            );
+        }
+        assert!(account.inheritance.is_some());
+    }
+
+    #[test]
+    fn test_claim_after_expiry_installs_beneficiary() {
+        let (mut account, owner, mut spouse) = setup();
+        let now = CONFIGURED_AT + YEAR + GRACE;
+        assert_eq!(inheritance_status(&account, now), Some(InheritanceStatus::Claimable));
+
+        claim_inheritance(&mut account, now).unwrap();
+
+        assert_eq!(account.passkey_public_key, spouse.public_key());
+        assert_eq!(account.credential_id, spouse.credential_id());
+        assert!(account.inheritance.is_none());
+        assert_eq!(account.key_history.len(), 1);
+        assert_eq!(account.key_history[0].public_key, owner.public_key());
+
+        // The beneficiary can now authorize
+        let (sig, nonce) = sign(&mut spouse, &account, HEARTBEAT_ACTION, &[]);
+        assert!(heartbeat(&mut account, sig, nonce, now).is_ok());
+    }
+
+    #[test]
+    fn test_heartbeat_resets_the_clock() {
+        let (mut account, mut owner, _) = setup();
+        let later = CONFIGURED_AT + YEAR / 2;
+
+        let (sig, nonce) = sign(&mut owner, &account, HEARTBEAT_ACTION, &[]);
+        heartbeat(&mut account, sig, nonce, later).unwrap();
+
+        assert_eq!(
+            inheritance_status(&account, CONFIGURED_AT + YEAR),
+            Some(InheritanceStatus::Active { claim_starts_at: later + YEAR })
+        );
+        assert!(claim_inheritance(&mut account, CONFIGURED_AT + YEAR + GRACE).is_err());
+        assert!(claim_inheritance(&mut account, later + YEAR + GRACE).is_ok());
+    }
+
+    #[test]
+    fn test_owner_cancels_during_grace() {
+        let (mut account, mut owner, spouse) = setup();
+        let during_grace = CONFIGURED_AT + YEAR + 1;
+        assert_eq!(
+            inheritance_status(&account, during_grace),
+            Some(InheritanceStatus::GracePeriod { claimable_at: CONFIGURED_AT + YEAR + GRACE })
+        );
+
+        let (sig, nonce) = sign(&mut owner, &account, HEARTBEAT_ACTION, &[]);
+        heartbeat(&mut account, sig, nonce, during_grace).unwrap();
+
+        assert!(claim_inheritance(&mut account, CONFIGURED_AT + YEAR + GRACE).is_err());
+        assert_ne!(account.passkey_public_key, spouse.public_key());
+    }
+
+    #[test]
+    fn test_beneficiary_cannot_heartbeat_before_claiming() {
+        let (mut account, _, mut spouse) = setup();
+
+        let (sig, nonce) = sign(&mut spouse, &account, HEARTBEAT_ACTION, &[]);
+        assert!(matches!(
+            heartbeat(&mut account, sig, nonce, CONFIGURED_AT + YEAR),
+            Err(InheritanceError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let spouse = TestPasskey::new(2);
+        let valid = InheritanceConfig::new(spouse.public_key(), spouse.credential_id(), YEAR, 0);
+        assert_eq!(valid.validate(), Ok(()));
+        assert_eq!(valid.serialized_size(), valid.to_bytes().unwrap().len());
+
+        let invalid = [
+            InheritanceConfig { inactivity_period: MIN_INACTIVITY_PERIOD - 1, ..valid.clone() },
+            InheritanceConfig { grace_period: -1, ..valid.clone() },
+            InheritanceConfig { beneficiary_credential_id: vec![], ..valid.clone() },
+            InheritanceConfig { beneficiary_public_key: [0u8; 64], ..valid },
+        ];
+        for config in invalid {
+            assert_eq!(config.validate(), Err(InheritanceError::InvalidConfig));
+        }
+    }
+
+    #[test]
+    fn test_periods_are_bounded() {
+        let mut owner = TestPasskey::new(1);
+        let spouse = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), owner.public_key(), owner.credential_id(), vec![], 100);
+
+        let cases = [
+            (MAX_FUTURE_SECONDS - GRACE, GRACE, Ok(())),
+            (MAX_FUTURE_SECONDS - GRACE, GRACE + 1, Err(TimeError::TooFarInFuture {
+                timestamp: CONFIGURED_AT + MAX_FUTURE_SECONDS + 1,
+                latest: CONFIGURED_AT + MAX_FUTURE_SECONDS,
+            })),
+            // A typo'd inactivity period of ~50,000 years
+            (50_000 * YEAR, GRACE, Err(TimeError::TooFarInFuture {
+                timestamp: CONFIGURED_AT + 50_000 * YEAR + GRACE,
+                latest: CONFIGURED_AT + MAX_FUTURE_SECONDS,
+            })),
+        ];
+        for (inactivity_period, grace_period, expected) in cases {
+            let config = InheritanceConfig::new(spouse.public_key(), spouse.credential_id(), inactivity_period, grace_period);
+            let (sig, nonce) = sign(&mut owner, &account, INHERITANCE_CONFIGURE_ACTION, &config.to_bytes().unwrap());
+            let result = configure_inheritance(&mut account, sig, nonce, Some(config), CONFIGURED_AT);
+            assert_eq!(result, expected.map_err(InheritanceError::InvalidTimestamp), "{inactivity_period} {grace_period}");
+        }
+        assert_eq!(account.nonce, 1, "rejected configs don't use the nonce");
+    }
+}
@@ -0,0 +1,1330 @@
+//! Account state as portable JSON, for support tooling
+//!
+//! `export_account` writes an account decoded field by field: addresses in
+//! base58, keys and hashes in hex, policies and the passkey registry as
+//! their parts rather than the bytes they're stored as. Nothing is
+//! redacted. The export also carries a hash of the account's stored bytes
+//! (`state_hash`), so a ticket's attachment can be matched to the on-chain
+//! state it was taken from.
+//!
+//! `import_account` reads an export back into an `AttestaAccount`, checking
+//! the invariants the program keeps, so it can be replayed locally. The
+//! result serializes to the same bytes that were exported, with two
+//! exceptions: a passkey registry in an older layout is written in the
+//! current one (as the program does the next time it saves it), and
+//! passkey names that aren't UTF-8 come back with replacement characters.
+//!
+//! Field names are part of the schema: support tooling reads them, so they
+//! only change with `ACCOUNT_JSON_SCHEMA_VERSION`.
+
+use std::str::FromStr;
+use attesta_types::consts::{AAGUID_LEN, HASH_LEN, P256_PUBKEY_LEN, PUBKEY_LEN};
+use core_crypto::{domain_hash, validate_p256_public_key, Domain, RelyingParty, WebAuthnVerificationProfile};
+use recovery::multi_passkey::{MultiPasskey, MultiPasskeyError, RevokedEntry, MULTI_PASSKEY_VERSION};
+use recovery::{
+    Amount, CredentialBinding, CredentialIdStorage, CredentialBindings, DestinationLimit, DestinationLimits, DestinationSpend,
+    DestinationSpends, MintLimit, MintLimits, PasskeyEntry, Policy, PolicyType, MAX_TRACKED_DESTINATIONS,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use crate::account::{AccountSettings, AttestaAccount, SignCount};
+use crate::auth_mode::AuthMode;
+use crate::emergency::EmergencyOverride;
+use crate::idempotency::{IdempotencyRecord, MAX_IDEMPOTENCY_RECORDS};
+use crate::inheritance::InheritanceConfig;
+use crate::policy_list::MAX_ACCOUNT_POLICIES;
+use crate::proof_log::{RetiredKey, MAX_KEY_HISTORY};
+use crate::social_recovery::RecoveryRequest;
+
+/// Version of the JSON layout below
+///
+/// Bumped whenever a field is renamed or removed; imports of another
+/// version are refused rather than half-read.
+pub const ACCOUNT_JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Why account JSON couldn't be written or read
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AccountJsonError {
+    #[error("Malformed account JSON: {0}")]
+    Json(String),
+
+    #[error("Account JSON schema version {0} isn't supported (expected {ACCOUNT_JSON_SCHEMA_VERSION})")]
+    UnsupportedSchemaVersion(u32),
+
+    #[error("{field} is not hex")]
+    InvalidHex { field: &'static str },
+
+    #[error("{field} is {len} bytes (expected {expected})")]
+    InvalidLength { field: &'static str, expected: usize, len: usize },
+
+    #[error("{field} is not a base58 address")]
+    InvalidAddress { field: &'static str },
+
+    #[error("Policy doesn't decode: {0}")]
+    InvalidPolicy(String),
+
+    #[error("Invalid passkey registry: {0}")]
+    InvalidPasskeys(#[from] MultiPasskeyError),
+
+    #[error("Invalid account: {0}")]
+    InvalidAccount(&'static str),
+
+    #[error("state_hash doesn't match the account (was it edited after export?)")]
+    StateHashMismatch,
+
+    #[error("Account serialization failed: {0}")]
+    Serialization(String),
+}
+
+impl From<std::io::Error> for AccountJsonError {
+    fn from(e: std::io::Error) -> Self {
+        AccountJsonError::Serialization(e.to_string())
+    }
+}
+
+/// An exported account, as written by `export_account`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountExport {
+    /// Always `ACCOUNT_JSON_SCHEMA_VERSION` when written
+    pub schema_version: u32,
+
+    /// The account's address (base58), if the exporter knew it
+    #[serde(default)]
+    pub address: Option<String>,
+
+    /// `state_hash` of the account (hex)
+    ///
+    /// Checked on import if present; remove it to import an export that
+    /// was edited on purpose. Exports that carry the plain SHA-256 used
+    /// before the hash had its own domain still import.
+    #[serde(default)]
+    pub state_hash: Option<String>,
+
+    pub account: AccountJson,
+}
+
+impl AccountExport {
+    /// Describes `account`, found at `address` if given
+    pub fn new(address: Option<&Pubkey>, account: &AttestaAccount) -> Result<Self, AccountJsonError> {
+        Ok(Self {
+            schema_version: ACCOUNT_JSON_SCHEMA_VERSION,
+            address: address.map(Pubkey::to_string),
+            state_hash: Some(hex(&state_hash(account)?)),
+            account: AccountJson::new(account)?,
+        })
+    }
+
+    /// The account this export describes, if it's one the program could have stored
+    pub fn into_account(self) -> Result<AttestaAccount, AccountJsonError> {
+        if self.schema_version != ACCOUNT_JSON_SCHEMA_VERSION {
+            return Err(AccountJsonError::UnsupportedSchemaVersion(self.schema_version));
+        }
+        let account = self.account.into_account()?;
+        if let Some(expected) = &self.state_hash {
+            let bytes = account.to_bytes()?;
+            let legacy: [u8; HASH_LEN] = Sha256::digest(&bytes).into();
+            if *expected != hex(&domain_hash(Domain::StateSnapshot, &bytes)) && *expected != hex(&legacy) {
+                return Err(AccountJsonError::StateHashMismatch);
+            }
+        }
+        Ok(account)
+    }
+}
+
+/// Hash of the account's stored bytes (without the Anchor discriminator)
+pub fn state_hash(account: &AttestaAccount) -> Result<[u8; HASH_LEN], AccountJsonError> {
+    Ok(domain_hash(Domain::StateSnapshot, &account.to_bytes()?))
+}
+
+/// Writes `account` as pretty-printed JSON (see the module docs)
+pub fn export_account(address: Option<&Pubkey>, account: &AttestaAccount) -> Result<String, AccountJsonError> {
+    serde_json::to_string_pretty(&AccountExport::new(address, account)?).map_err(|e| AccountJsonError::Json(e.to_string()))
+}
+
+/// Reads an account written by `export_account`, checking its invariants
+pub fn import_account(json: &str) -> Result<AttestaAccount, AccountJsonError> {
+    let export: AccountExport = serde_json::from_str(json).map_err(|e| AccountJsonError::Json(e.to_string()))?;
+    export.into_account()
+}
+
+/// `AttestaAccount`, field for field
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountJson {
+    pub owner: String,
+    pub passkey_public_key: String,
+    pub credential_id: String,
+    pub nonce: u64,
+
+    /// `None` for an account without a policy
+    pub policy: Option<PolicyJson>,
+
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    /// `None` for a single-passkey account
+    pub passkeys: Option<PasskeyRegistryJson>,
+
+    pub privacy_mode: bool,
+    pub idempotency_records: Vec<IdempotencyRecordJson>,
+    pub pending_recovery: Option<RecoveryRequestJson>,
+    pub pending_drill: Option<RecoveryRequestJson>,
+    pub last_drill_at: i64,
+    pub settings: SettingsJson,
+    pub parent: Option<String>,
+    pub sub_account_index: u8,
+    pub inheritance: Option<InheritanceJson>,
+    pub last_execution_at: i64,
+    pub failed_auth_count: u8,
+    pub locked_until: i64,
+    pub key_history: Vec<RetiredKeyJson>,
+    pub proof_log_enabled: bool,
+    pub additional_policies: Vec<PolicyJson>,
+    pub passkey_aaguid: Option<String>,
+    pub sign_counts: Vec<SignCountJson>,
+
+    /// Canonical hash of the policies (hex); recomputed on import, so if
+    /// present it must match
+    #[serde(default)]
+    pub policy_hash: Option<String>,
+
+    #[serde(default)]
+    pub destination_spends: DestinationSpendsJson,
+
+    #[serde(default)]
+    pub state_version: u64,
+
+    /// 0 for an account created before layouts were recorded
+    #[serde(default)]
+    pub created_layout: u8,
+
+    #[serde(default)]
+    pub emergency_override: EmergencyOverrideJson,
+
+    /// The index the address was derived from (`None` for the owner's own address)
+    #[serde(default)]
+    pub account_index: Option<u16>,
+}
+
+impl AccountJson {
+    pub fn new(account: &AttestaAccount) -> Result<Self, AccountJsonError> {
+        Ok(Self {
+            owner: account.owner.to_string(),
+            passkey_public_key: hex(&account.passkey_public_key),
+            credential_id: hex(&account.credential_id),
+            nonce: account.nonce,
+            policy: (!account.policy.is_empty()).then(|| PolicyJson::from_bytes(&account.policy)),
+            created_at: account.created_at,
+            updated_at: account.updated_at,
+            passkeys: account.passkey_registry()?.as_ref().map(PasskeyRegistryJson::new),
+            privacy_mode: account.privacy_mode,
+            idempotency_records: account.idempotency_records.iter().map(IdempotencyRecordJson::new).collect(),
+            pending_recovery: account.pending_recovery.as_ref().map(RecoveryRequestJson::new),
+            pending_drill: account.pending_drill.as_ref().map(RecoveryRequestJson::new),
+            last_drill_at: account.last_drill_at,
+            settings: SettingsJson::new(&account.settings),
+            parent: account.parent.as_ref().map(Pubkey::to_string),
+            sub_account_index: account.sub_account_index,
+            inheritance: account.inheritance.as_ref().map(InheritanceJson::new),
+            last_execution_at: account.last_execution_at,
+            failed_auth_count: account.failed_auth_count,
+            locked_until: account.locked_until,
+            key_history: account.key_history.iter().map(RetiredKeyJson::new).collect(),
+            proof_log_enabled: account.proof_log_enabled,
+            additional_policies: account.additional_policies.iter().map(|policy| PolicyJson::from_bytes(policy)).collect(),
+            passkey_aaguid: account.passkey_aaguid.as_ref().map(|aaguid| hex(aaguid)),
+            sign_counts: account.sign_counts.iter().map(SignCountJson::new).collect(),
+            policy_hash: Some(hex(&account.policy_hash)),
+            destination_spends: DestinationSpendsJson::new(&account.destination_spends),
+            state_version: account.state_version,
+            created_layout: account.created_layout,
+            emergency_override: EmergencyOverrideJson::new(&account.emergency_override),
+            account_index: account.account_index,
+        })
+    }
+
+    /// The account, if it keeps the invariants the program does
+    pub fn into_account(self) -> Result<AttestaAccount, AccountJsonError> {
+        let passkeys = match self.passkeys {
+            Some(registry) => registry.into_registry()?.to_bytes()?,
+            None => Vec::new(),
+        };
+        let mut account = AttestaAccount {
+            owner: address("owner", &self.owner)?,
+            passkey_public_key: hex_array("passkey_public_key", &self.passkey_public_key)?,
+            credential_id: from_hex("credential_id", &self.credential_id)?,
+            nonce: self.nonce,
+            policy: self.policy.map(|policy| policy.to_bytes()).transpose()?.unwrap_or_default(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            passkeys,
+            privacy_mode: self.privacy_mode,
+            idempotency_records: self.idempotency_records.into_iter().map(IdempotencyRecordJson::into_record).collect::<Result<_, _>>()?,
+            pending_recovery: self.pending_recovery.map(RecoveryRequestJson::into_request).transpose()?,
+            pending_drill: self.pending_drill.map(RecoveryRequestJson::into_request).transpose()?,
+            last_drill_at: self.last_drill_at,
+            settings: self.settings.into_settings()?,
+            parent: self.parent.map(|parent| address("parent", &parent)).transpose()?,
+            sub_account_index: self.sub_account_index,
+            inheritance: self.inheritance.map(InheritanceJson::into_config).transpose()?,
+            last_execution_at: self.last_execution_at,
+            failed_auth_count: self.failed_auth_count,
+            locked_until: self.locked_until,
+            key_history: self.key_history.into_iter().map(RetiredKeyJson::into_key).collect::<Result<_, _>>()?,
+            proof_log_enabled: self.proof_log_enabled,
+            additional_policies: self.additional_policies.iter().map(PolicyJson::to_bytes).collect::<Result<_, _>>()?,
+            passkey_aaguid: self.passkey_aaguid.map(|aaguid| hex_array("passkey_aaguid", &aaguid)).transpose()?,
+            sign_counts: self.sign_counts.into_iter().map(SignCountJson::into_sign_count).collect::<Result<_, _>>()?,
+            policy_hash: [0; HASH_LEN],
+            destination_spends: self.destination_spends.into_spends()?,
+            state_version: self.state_version,
+            created_layout: self.created_layout,
+            emergency_override: self.emergency_override.into_override(),
+            account_index: self.account_index,
+        };
+        account.policy_hash = account.compute_policy_hash();
+        if let Some(policy_hash) = &self.policy_hash {
+            if hex_array::<HASH_LEN>("policy_hash", policy_hash)? != account.policy_hash {
+                return Err(AccountJsonError::InvalidAccount("policy_hash doesn't match the policies"));
+            }
+        }
+        check_invariants(&account)?;
+        Ok(account)
+    }
+}
+
+/// The checks `into_account` makes beyond each field decoding
+fn check_invariants(account: &AttestaAccount) -> Result<(), AccountJsonError> {
+    validate_p256_public_key(&account.passkey_public_key)
+        .map_err(|_| AccountJsonError::InvalidAccount("passkey_public_key is not a P-256 point"))?;
+    if account.privacy_mode && account.credential_id.len() != HASH_LEN {
+        return Err(AccountJsonError::InvalidAccount("credential_id must be a 32-byte hash in privacy mode"));
+    }
+    if account.policy.is_empty() && !account.additional_policies.is_empty() {
+        return Err(AccountJsonError::InvalidAccount("additional_policies without a policy"));
+    }
+    if account.policies().len() > MAX_ACCOUNT_POLICIES {
+        return Err(AccountJsonError::InvalidAccount("too many policies"));
+    }
+    if account.idempotency_records.len() > MAX_IDEMPOTENCY_RECORDS {
+        return Err(AccountJsonError::InvalidAccount("too many idempotency_records"));
+    }
+    if account.key_history.len() > MAX_KEY_HISTORY {
+        return Err(AccountJsonError::InvalidAccount("too many key_history entries"));
+    }
+    if account.destination_spends.entries.len() > MAX_TRACKED_DESTINATIONS {
+        return Err(AccountJsonError::InvalidAccount("too many destination_spends entries"));
+    }
+    if !account.settings.is_valid() {
+        return Err(AccountJsonError::InvalidAccount("settings can't be stored"));
+    }
+    Ok(())
+}
+
+/// A policy with its config decoded
+///
+/// The `type` field names the variant. `raw` holds a policy this schema
+/// can't describe byte for byte (a legacy `daily_limit` layout, or a
+/// config that doesn't decode), as its serialized bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PolicyJson {
+    /// No restrictions
+    Open,
+
+    /// A cap per transaction
+    SpendingLimit { max_lamports: u64, mint_limits: Option<MintLimitsJson> },
+
+    /// A cap per window of `window_seconds`, the first starting at `anchor_timestamp`
+    DailyLimit { max_lamports: u64, window_seconds: u32, anchor_timestamp: i64, mint_limits: Option<MintLimitsJson> },
+
+    /// Every listed signer (base58) must sign
+    MultiSig { required_signers: Vec<String> },
+
+    /// Nothing runs before `unlock_timestamp`
+    TimeLocked { unlock_timestamp: i64 },
+
+    /// Transfers only to the listed destinations (base58)
+    DestinationAllowlist { destinations: Vec<String> },
+
+    /// All of `rules` must pass
+    Composite { rules: Vec<PolicyJson> },
+
+    /// Particular passkeys for particular destinations
+    CredentialBinding { bindings: Vec<CredentialBindingJson>, default: Option<String> },
+
+    /// A budget per destination per window, `default_max_amount` for any not listed
+    PerDestinationLimit {
+        limits: Vec<DestinationLimitJson>,
+        default_max_amount: u64,
+        window_seconds: u32,
+        anchor_timestamp: i64,
+    },
+
+    /// Transfers over `threshold_lamports` wait `delay_seconds` after being announced
+    Vault { threshold_lamports: u64, delay_seconds: u32 },
+
+    /// A stored policy, as hex
+    Raw { bytes: String },
+}
+
+impl PolicyJson {
+    /// Describes a stored policy, falling back to `Raw`
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let decoded = Policy::from_bytes(bytes).ok().and_then(|policy| Self::decode(&policy));
+        match decoded {
+            // Only if it writes back exactly what's stored
+            Some(json) if json.to_policy().ok().and_then(|policy| policy.to_bytes().ok()).as_deref() == Some(bytes) => json,
+            _ => PolicyJson::Raw { bytes: hex(bytes) },
+        }
+    }
+
+    fn decode(policy: &Policy) -> Option<Self> {
+        let mint_limits = || policy.mint_limits().as_ref().map(MintLimitsJson::new);
+        Some(match policy.policy_type {
+            PolicyType::Open => PolicyJson::Open,
+            PolicyType::SpendingLimit => PolicyJson::SpendingLimit {
+                max_lamports: u64::from_le_bytes(policy.config.get(..8)?.try_into().ok()?),
+                mint_limits: mint_limits(),
+            },
+            PolicyType::DailyLimit => {
+                let limit = policy.daily_limit_config()?;
+                PolicyJson::DailyLimit {
+                    max_lamports: limit.max_amount,
+                    window_seconds: limit.window_seconds,
+                    anchor_timestamp: limit.anchor_timestamp,
+                    mint_limits: mint_limits(),
+                }
+            }
+            PolicyType::MultiSig => PolicyJson::MultiSig { required_signers: addresses(&policy.config)? },
+            PolicyType::TimeLocked => PolicyJson::TimeLocked {
+                unlock_timestamp: i64::from_le_bytes(policy.config.as_slice().try_into().ok()?),
+            },
+            PolicyType::DestinationAllowlist => PolicyJson::DestinationAllowlist { destinations: addresses(&policy.config)? },
+            PolicyType::Composite => PolicyJson::Composite {
+                rules: policy.rules()?.iter().map(Self::decode).collect::<Option<_>>()?,
+            },
+            PolicyType::CredentialBinding => {
+                let bindings = policy.credential_bindings()?;
+                PolicyJson::CredentialBinding {
+                    bindings: bindings.bindings.iter().map(CredentialBindingJson::new).collect(),
+                    default: bindings.default.as_ref().map(|hash| hex(hash)),
+                }
+            }
+            PolicyType::PerDestinationLimit => {
+                let limits = policy.destination_limits()?;
+                PolicyJson::PerDestinationLimit {
+                    limits: limits.limits.iter().map(DestinationLimitJson::new).collect(),
+                    default_max_amount: limits.default_max_amount,
+                    window_seconds: limits.window_seconds,
+                    anchor_timestamp: limits.anchor_timestamp,
+                }
+            }
+            PolicyType::Vault => {
+                let vault = policy.vault_config()?;
+                PolicyJson::Vault { threshold_lamports: vault.threshold_lamports, delay_seconds: vault.delay_seconds }
+            }
+        })
+    }
+
+    /// The policy this describes
+    pub fn to_policy(&self) -> Result<Policy, AccountJsonError> {
+        let with_mint_limits = |policy: Policy, mint_limits: &Option<MintLimitsJson>| -> Result<Policy, AccountJsonError> {
+            Ok(match mint_limits {
+                Some(mint_limits) => policy.with_mint_limits(mint_limits.to_mint_limits()?),
+                None => policy,
+            })
+        };
+        match self {
+            PolicyJson::Open => Ok(Policy::open()),
+            PolicyJson::SpendingLimit { max_lamports, mint_limits } => {
+                with_mint_limits(Policy::spending_limit(Amount::from_lamports(*max_lamports)), mint_limits)
+            }
+            PolicyJson::DailyLimit { max_lamports, window_seconds, anchor_timestamp, mint_limits } => with_mint_limits(
+                Policy::windowed_limit(Amount::from_lamports(*max_lamports), *window_seconds, *anchor_timestamp),
+                mint_limits,
+            ),
+            PolicyJson::MultiSig { required_signers } => {
+                Ok(Policy::multi_sig(required_signers.iter().map(|key| address("required_signers", key)).collect::<Result<_, _>>()?))
+            }
+            PolicyJson::TimeLocked { unlock_timestamp } => Ok(Policy::time_locked(*unlock_timestamp)),
+            PolicyJson::DestinationAllowlist { destinations } => Ok(Policy::destination_allowlist(
+                destinations.iter().map(|key| address("destinations", key)).collect::<Result<_, _>>()?,
+            )),
+            PolicyJson::Composite { rules } => Ok(Policy::composite(rules.iter().map(Self::to_policy).collect::<Result<_, _>>()?)),
+            PolicyJson::CredentialBinding { bindings, default } => Ok(Policy::credential_binding(CredentialBindings {
+                bindings: bindings.iter().map(CredentialBindingJson::to_binding).collect::<Result<_, _>>()?,
+                default: default.as_ref().map(|hash| hex_array("default", hash)).transpose()?,
+            })),
+            PolicyJson::PerDestinationLimit { limits, default_max_amount, window_seconds, anchor_timestamp } => {
+                Ok(Policy::per_destination_limit(DestinationLimits {
+                    limits: limits.iter().map(DestinationLimitJson::to_limit).collect::<Result<_, _>>()?,
+                    default_max_amount: *default_max_amount,
+                    window_seconds: *window_seconds,
+                    anchor_timestamp: *anchor_timestamp,
+                }))
+            }
+            PolicyJson::Vault { threshold_lamports, delay_seconds } => {
+                Ok(Policy::vault(Amount::from_lamports(*threshold_lamports), *delay_seconds))
+            }
+            PolicyJson::Raw { bytes } => {
+                Policy::from_bytes(&from_hex("bytes", bytes)?).map_err(|e| AccountJsonError::InvalidPolicy(e.to_string()))
+            }
+        }
+    }
+
+    /// The policy as stored on the account
+    pub fn to_bytes(&self) -> Result<Vec<u8>, AccountJsonError> {
+        match self {
+            // Kept as stored, even where decoding and re-encoding would differ
+            PolicyJson::Raw { bytes } => {
+                let bytes = from_hex("bytes", bytes)?;
+                Policy::from_bytes(&bytes).map_err(|e| AccountJsonError::InvalidPolicy(e.to_string()))?;
+                Ok(bytes)
+            }
+            _ => Ok(self.to_policy()?.to_bytes()?),
+        }
+    }
+}
+
+/// `MintLimits`, with mints in base58
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MintLimitsJson {
+    pub allow_unlisted: bool,
+    pub limits: Vec<MintLimitJson>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MintLimitJson {
+    pub mint: String,
+    pub max_amount: u64,
+    pub decimals: u8,
+}
+
+impl MintLimitsJson {
+    fn new(mint_limits: &MintLimits) -> Self {
+        Self {
+            allow_unlisted: mint_limits.allow_unlisted,
+            limits: mint_limits
+                .limits
+                .iter()
+                .map(|limit| MintLimitJson { mint: limit.mint.to_string(), max_amount: limit.max_amount, decimals: limit.decimals })
+                .collect(),
+        }
+    }
+
+    fn to_mint_limits(&self) -> Result<MintLimits, AccountJsonError> {
+        Ok(MintLimits {
+            allow_unlisted: self.allow_unlisted,
+            limits: self
+                .limits
+                .iter()
+                .map(|limit| {
+                    Ok(MintLimit { mint: address("mint", &limit.mint)?, max_amount: limit.max_amount, decimals: limit.decimals })
+                })
+                .collect::<Result<_, AccountJsonError>>()?,
+        })
+    }
+}
+
+/// `CredentialBinding`, with destinations in base58 and the hash in hex
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CredentialBindingJson {
+    pub destinations: Vec<String>,
+    pub credential_id_hash: Option<String>,
+}
+
+impl CredentialBindingJson {
+    fn new(binding: &CredentialBinding) -> Self {
+        Self {
+            destinations: binding.destinations.iter().map(Pubkey::to_string).collect(),
+            credential_id_hash: binding.credential_id_hash.as_ref().map(|hash| hex(hash)),
+        }
+    }
+
+    fn to_binding(&self) -> Result<CredentialBinding, AccountJsonError> {
+        Ok(CredentialBinding {
+            destinations: self.destinations.iter().map(|key| address("destinations", key)).collect::<Result<_, _>>()?,
+            credential_id_hash: self.credential_id_hash.as_ref().map(|hash| hex_array("credential_id_hash", hash)).transpose()?,
+        })
+    }
+}
+
+/// `DestinationLimit`, with the destination in base58
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DestinationLimitJson {
+    pub destination: String,
+
+    /// `None` for no limit
+    pub max_amount: Option<u64>,
+}
+
+impl DestinationLimitJson {
+    fn new(limit: &DestinationLimit) -> Self {
+        Self { destination: limit.destination.to_string(), max_amount: limit.max_amount }
+    }
+
+    fn to_limit(&self) -> Result<DestinationLimit, AccountJsonError> {
+        Ok(DestinationLimit { destination: address("destination", &self.destination)?, max_amount: self.max_amount })
+    }
+}
+
+/// `MultiPasskey`, always in the current version's layout
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PasskeyRegistryJson {
+    pub primary: PasskeyJson,
+    pub additional: Vec<PasskeyJson>,
+    pub recovery_threshold: u8,
+    pub max_passkeys: u8,
+    pub revoked: Vec<RevokedPasskeyJson>,
+}
+
+impl PasskeyRegistryJson {
+    pub fn new(registry: &MultiPasskey) -> Self {
+        Self {
+            primary: PasskeyJson::new(&registry.primary),
+            additional: registry.additional.iter().map(PasskeyJson::new).collect(),
+            recovery_threshold: registry.recovery_threshold,
+            max_passkeys: registry.max_passkeys,
+            revoked: registry
+                .revoked
+                .iter()
+                .map(|entry| RevokedPasskeyJson { credential_id_hash: hex(&entry.credential_id_hash), revoked_at: entry.revoked_at })
+                .collect(),
+        }
+    }
+
+    /// The registry, checked with `MultiPasskey::validate`
+    pub fn into_registry(self) -> Result<MultiPasskey, AccountJsonError> {
+        let registry = MultiPasskey {
+            primary: self.primary.into_entry()?,
+            additional: self.additional.into_iter().map(PasskeyJson::into_entry).collect::<Result<_, _>>()?,
+            recovery_threshold: self.recovery_threshold,
+            max_passkeys: self.max_passkeys,
+            version: MULTI_PASSKEY_VERSION,
+            revoked: self
+                .revoked
+                .into_iter()
+                .map(|entry| {
+                    Ok(RevokedEntry {
+                        credential_id_hash: hex_array("revoked.credential_id_hash", &entry.credential_id_hash)?,
+                        revoked_at: entry.revoked_at,
+                    })
+                })
+                .collect::<Result<_, AccountJsonError>>()?,
+        };
+        registry.validate()?;
+        Ok(registry)
+    }
+}
+
+/// `PasskeyEntry`, with its name as text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PasskeyJson {
+    pub public_key: String,
+    pub credential_id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub added_at: i64,
+    pub aaguid: Option<String>,
+    #[serde(default)]
+    pub id_storage: CredentialIdStorageJson,
+}
+
+impl PasskeyJson {
+    fn new(entry: &PasskeyEntry) -> Self {
+        Self {
+            public_key: hex(&entry.public_key),
+            credential_id: hex(&entry.credential_id),
+            name: String::from_utf8_lossy(&entry.name).into_owned(),
+            enabled: entry.enabled,
+            added_at: entry.added_at,
+            aaguid: entry.aaguid.as_ref().map(|aaguid| hex(aaguid)),
+            id_storage: entry.id_storage.into(),
+        }
+    }
+
+    fn into_entry(self) -> Result<PasskeyEntry, AccountJsonError> {
+        Ok(PasskeyEntry {
+            public_key: hex_array("passkeys.public_key", &self.public_key)?,
+            credential_id: from_hex("passkeys.credential_id", &self.credential_id)?,
+            name: self.name.into_bytes(),
+            enabled: self.enabled,
+            added_at: self.added_at,
+            aaguid: self.aaguid.map(|aaguid| hex_array::<AAGUID_LEN>("passkeys.aaguid", &aaguid)).transpose()?,
+            id_storage: self.id_storage.into(),
+        })
+    }
+}
+
+/// `CredentialIdStorage`, by name
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialIdStorageJson {
+    #[default]
+    Full,
+    Hashed,
+}
+
+impl From<CredentialIdStorage> for CredentialIdStorageJson {
+    fn from(storage: CredentialIdStorage) -> Self {
+        match storage {
+            CredentialIdStorage::Full => CredentialIdStorageJson::Full,
+            CredentialIdStorage::Hashed => CredentialIdStorageJson::Hashed,
+        }
+    }
+}
+
+impl From<CredentialIdStorageJson> for CredentialIdStorage {
+    fn from(storage: CredentialIdStorageJson) -> Self {
+        match storage {
+            CredentialIdStorageJson::Full => CredentialIdStorage::Full,
+            CredentialIdStorageJson::Hashed => CredentialIdStorage::Hashed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RevokedPasskeyJson {
+    pub credential_id_hash: String,
+    pub revoked_at: i64,
+}
+
+/// `AccountSettings`, with the WebAuthn profile spelled out check by check
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SettingsJson {
+    pub reject_zero_amount: bool,
+    pub reject_self_transfer: bool,
+    pub max_transaction_data_len: u16,
+    pub lockout_threshold: u8,
+    pub aaguid_allowlist: Vec<String>,
+    pub pinned_program_version: Option<String>,
+    pub webauthn_profile: WebAuthnProfileJson,
+    pub relying_party: Option<RelyingPartyJson>,
+    #[serde(default)]
+    pub auth_mode: AuthModeJson,
+    #[serde(default)]
+    pub auth_mode_locked: bool,
+    #[serde(default)]
+    pub authorized_executors: Vec<String>,
+    #[serde(default)]
+    pub log_allowed_sample_rate: u8,
+    #[serde(default)]
+    pub recovery_allowance: u64,
+}
+
+impl SettingsJson {
+    pub fn new(settings: &AccountSettings) -> Self {
+        let profile = &settings.webauthn_profile;
+        Self {
+            reject_zero_amount: settings.reject_zero_amount,
+            reject_self_transfer: settings.reject_self_transfer,
+            max_transaction_data_len: settings.max_transaction_data_len,
+            lockout_threshold: settings.lockout_threshold,
+            aaguid_allowlist: settings.aaguid_allowlist.iter().map(|aaguid| hex(aaguid)).collect(),
+            pinned_program_version: settings.pinned_program_version.as_ref().map(|version| hex(version)),
+            webauthn_profile: WebAuthnProfileJson {
+                check_rp_id: profile.check_rp_id,
+                check_origin: profile.check_origin,
+                require_user_present: profile.require_user_present,
+                require_user_verified: profile.require_user_verified,
+                check_sign_count: profile.check_sign_count,
+                require_low_s: profile.require_low_s,
+            },
+            relying_party: settings.relying_party.as_ref().map(|party| RelyingPartyJson {
+                rp_id_hash: hex(&party.rp_id_hash),
+                origin_hash: hex(&party.origin_hash),
+            }),
+            auth_mode: settings.auth_mode.into(),
+            auth_mode_locked: settings.auth_mode_locked,
+            authorized_executors: settings.authorized_executors.iter().map(Pubkey::to_string).collect(),
+            log_allowed_sample_rate: settings.log_allowed_sample_rate,
+            recovery_allowance: settings.recovery_allowance,
+        }
+    }
+
+    pub fn into_settings(self) -> Result<AccountSettings, AccountJsonError> {
+        let profile = self.webauthn_profile;
+        Ok(AccountSettings {
+            reject_zero_amount: self.reject_zero_amount,
+            reject_self_transfer: self.reject_self_transfer,
+            max_transaction_data_len: self.max_transaction_data_len,
+            lockout_threshold: self.lockout_threshold,
+            aaguid_allowlist: self
+                .aaguid_allowlist
+                .iter()
+                .map(|aaguid| hex_array("aaguid_allowlist", aaguid))
+                .collect::<Result<_, _>>()?,
+            pinned_program_version: self
+                .pinned_program_version
+                .map(|version| hex_array("pinned_program_version", &version))
+                .transpose()?,
+            webauthn_profile: WebAuthnVerificationProfile {
+                check_rp_id: profile.check_rp_id,
+                check_origin: profile.check_origin,
+                require_user_present: profile.require_user_present,
+                require_user_verified: profile.require_user_verified,
+                check_sign_count: profile.check_sign_count,
+                require_low_s: profile.require_low_s,
+            },
+            relying_party: self
+                .relying_party
+                .map(|party| {
+                    Ok::<_, AccountJsonError>(RelyingParty {
+                        rp_id_hash: hex_array("relying_party.rp_id_hash", &party.rp_id_hash)?,
+                        origin_hash: hex_array("relying_party.origin_hash", &party.origin_hash)?,
+                    })
+                })
+                .transpose()?,
+            auth_mode: self.auth_mode.into(),
+            auth_mode_locked: self.auth_mode_locked,
+            authorized_executors: self
+                .authorized_executors
+                .iter()
+                .map(|executor| address("authorized_executors", executor))
+                .collect::<Result<_, _>>()?,
+            log_allowed_sample_rate: self.log_allowed_sample_rate,
+            recovery_allowance: self.recovery_allowance,
+        })
+    }
+}
+
+/// `AuthMode`, by name
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthModeJson {
+    #[default]
+    PasskeyOnly,
+    OwnerOrPasskey,
+    OwnerOnly,
+}
+
+impl From<AuthMode> for AuthModeJson {
+    fn from(mode: AuthMode) -> Self {
+        match mode {
+            AuthMode::PasskeyOnly => AuthModeJson::PasskeyOnly,
+            AuthMode::OwnerOrPasskey => AuthModeJson::OwnerOrPasskey,
+            AuthMode::OwnerOnly => AuthModeJson::OwnerOnly,
+        }
+    }
+}
+
+impl From<AuthModeJson> for AuthMode {
+    fn from(mode: AuthModeJson) -> Self {
+        match mode {
+            AuthModeJson::PasskeyOnly => AuthMode::PasskeyOnly,
+            AuthModeJson::OwnerOrPasskey => AuthMode::OwnerOrPasskey,
+            AuthModeJson::OwnerOnly => AuthMode::OwnerOnly,
+        }
+    }
+}
+
+/// `WebAuthnVerificationProfile`: which optional WebAuthn checks run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct WebAuthnProfileJson {
+    pub check_rp_id: bool,
+    pub check_origin: bool,
+    pub require_user_present: bool,
+    pub require_user_verified: bool,
+    pub check_sign_count: bool,
+    pub require_low_s: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RelyingPartyJson {
+    pub rp_id_hash: String,
+    pub origin_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IdempotencyRecordJson {
+    pub key: String,
+    pub message_hash: String,
+    pub nonce: u64,
+}
+
+impl IdempotencyRecordJson {
+    fn new(record: &IdempotencyRecord) -> Self {
+        Self { key: hex(&record.key), message_hash: hex(&record.message_hash), nonce: record.nonce }
+    }
+
+    fn into_record(self) -> Result<IdempotencyRecord, AccountJsonError> {
+        Ok(IdempotencyRecord {
+            key: hex_array("idempotency_records.key", &self.key)?,
+            message_hash: hex_array("idempotency_records.message_hash", &self.message_hash)?,
+            nonce: self.nonce,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecoveryRequestJson {
+    pub new_public_key: String,
+    pub new_credential_id: String,
+    pub approvals: Vec<String>,
+    pub initiated_at: i64,
+    pub threshold_met_at: Option<i64>,
+    pub new_aaguid: Option<String>,
+}
+
+impl RecoveryRequestJson {
+    fn new(request: &RecoveryRequest) -> Self {
+        Self {
+            new_public_key: hex(&request.new_public_key),
+            new_credential_id: hex(&request.new_credential_id),
+            approvals: request.approvals.iter().map(|approval| hex(approval)).collect(),
+            initiated_at: request.initiated_at,
+            threshold_met_at: request.threshold_met_at,
+            new_aaguid: request.new_aaguid.as_ref().map(|aaguid| hex(aaguid)),
+        }
+    }
+
+    fn into_request(self) -> Result<RecoveryRequest, AccountJsonError> {
+        Ok(RecoveryRequest {
+            new_public_key: hex_array("new_public_key", &self.new_public_key)?,
+            new_credential_id: from_hex("new_credential_id", &self.new_credential_id)?,
+            approvals: self.approvals.iter().map(|approval| hex_array("approvals", approval)).collect::<Result<_, _>>()?,
+            initiated_at: self.initiated_at,
+            threshold_met_at: self.threshold_met_at,
+            new_aaguid: self.new_aaguid.map(|aaguid| hex_array("new_aaguid", &aaguid)).transpose()?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InheritanceJson {
+    pub beneficiary_public_key: String,
+    pub beneficiary_credential_id: String,
+    pub inactivity_period: i64,
+    pub grace_period: i64,
+}
+
+impl InheritanceJson {
+    fn new(config: &InheritanceConfig) -> Self {
+        Self {
+            beneficiary_public_key: hex(&config.beneficiary_public_key),
+            beneficiary_credential_id: hex(&config.beneficiary_credential_id),
+            inactivity_period: config.inactivity_period,
+            grace_period: config.grace_period,
+        }
+    }
+
+    fn into_config(self) -> Result<InheritanceConfig, AccountJsonError> {
+        Ok(InheritanceConfig::new(
+            hex_array("beneficiary_public_key", &self.beneficiary_public_key)?,
+            from_hex("beneficiary_credential_id", &self.beneficiary_credential_id)?,
+            self.inactivity_period,
+            self.grace_period,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RetiredKeyJson {
+    pub credential_id_hash: String,
+    pub public_key: String,
+    pub retired_at: i64,
+}
+
+impl RetiredKeyJson {
+    fn new(key: &RetiredKey) -> Self {
+        Self { credential_id_hash: hex(&key.credential_id_hash), public_key: hex(&key.public_key), retired_at: key.retired_at }
+    }
+
+    fn into_key(self) -> Result<RetiredKey, AccountJsonError> {
+        Ok(RetiredKey {
+            credential_id_hash: hex_array("key_history.credential_id_hash", &self.credential_id_hash)?,
+            public_key: hex_array::<P256_PUBKEY_LEN>("key_history.public_key", &self.public_key)?,
+            retired_at: self.retired_at,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignCountJson {
+    pub credential_id_hash: String,
+    pub sign_count: u32,
+}
+
+impl SignCountJson {
+    fn new(count: &SignCount) -> Self {
+        Self { credential_id_hash: hex(&count.credential_id_hash), sign_count: count.sign_count }
+    }
+
+    fn into_sign_count(self) -> Result<SignCount, AccountJsonError> {
+        Ok(SignCount {
+            credential_id_hash: hex_array("sign_counts.credential_id_hash", &self.credential_id_hash)?,
+            sign_count: self.sign_count,
+        })
+    }
+}
+
+/// `DestinationSpends`, with destination hashes in hex
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DestinationSpendsJson {
+    pub window_start: i64,
+    pub entries: Vec<DestinationSpendJson>,
+    pub evicted: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DestinationSpendJson {
+    pub destination_hash: String,
+    pub spent: u64,
+}
+
+impl DestinationSpendsJson {
+    fn new(spends: &DestinationSpends) -> Self {
+        Self {
+            window_start: spends.window_start,
+            entries: spends
+                .entries
+                .iter()
+                .map(|entry| DestinationSpendJson { destination_hash: hex(&entry.destination_hash), spent: entry.spent })
+                .collect(),
+            evicted: spends.evicted,
+        }
+    }
+
+    fn into_spends(self) -> Result<DestinationSpends, AccountJsonError> {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                Ok(DestinationSpend {
+                    destination_hash: hex_array("destination_spends.destination_hash", &entry.destination_hash)?,
+                    spent: entry.spent,
+                })
+            })
+            .collect::<Result<_, AccountJsonError>>()?;
+        Ok(DestinationSpends { window_start: self.window_start, entries, evicted: self.evicted })
+    }
+}
+
+/// `EmergencyOverride`; all zeros for an account that never configured one
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EmergencyOverrideJson {
+    pub max_lamports: u64,
+    pub period_seconds: u32,
+    pub last_used_at: i64,
+}
+
+impl EmergencyOverrideJson {
+    fn new(config: &EmergencyOverride) -> Self {
+        Self { max_lamports: config.max_lamports, period_seconds: config.period_seconds, last_used_at: config.last_used_at }
+    }
+
+    fn into_override(self) -> EmergencyOverride {
+        EmergencyOverride { max_lamports: self.max_lamports, period_seconds: self.period_seconds, last_used_at: self.last_used_at }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(field: &'static str, text: &str) -> Result<Vec<u8>, AccountJsonError> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2 && pair.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(AccountJsonError::InvalidHex { field })
+        })
+        .collect()
+}
+
+fn hex_array<const N: usize>(field: &'static str, text: &str) -> Result<[u8; N], AccountJsonError> {
+    let bytes = from_hex(field, text)?;
+    let len = bytes.len();
+    bytes.try_into().map_err(|_| AccountJsonError::InvalidLength { field, expected: N, len })
+}
+
+fn address(field: &'static str, text: &str) -> Result<Pubkey, AccountJsonError> {
+    Pubkey::from_str(text).map_err(|_| AccountJsonError::InvalidAddress { field })
+}
+
+/// A config of back-to-back addresses, in base58
+fn addresses(config: &[u8]) -> Option<Vec<String>> {
+    let chunks = config.chunks_exact(PUBKEY_LEN);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    chunks.map(|key| Pubkey::try_from(key).ok().map(|key| key.to_string())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use crate::format_stability::{sample_account, sample_policies};
+
+    /// Every field path in `value`, with array elements under `[]`
+    fn field_paths(value: &Value, prefix: &str, paths: &mut Vec<String>) {
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields {
+                    let path = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+                    paths.push(path.clone());
+                    field_paths(field, &path, paths);
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    field_paths(item, &format!("{}[]", prefix), paths);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_account_round_trips_to_the_same_bytes() {
+        let account = sample_account();
+        let address = Pubkey::new_unique();
+        let json = export_account(Some(&address), &account).unwrap();
+
+        let imported = import_account(&json).unwrap();
+        assert_eq!(imported.to_bytes().unwrap(), account.to_bytes().unwrap());
+
+        let export: AccountExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(export.address, Some(address.to_string()));
+        assert_eq!(export.state_hash, Some(hex(&state_hash(&account).unwrap())));
+    }
+
+    #[test]
+    fn test_exports_with_the_plain_state_hash_still_import() {
+        let account = sample_account();
+        let mut export = AccountExport::new(None, &account).unwrap();
+        export.state_hash = Some(hex(&Sha256::digest(account.to_bytes().unwrap())));
+        assert_eq!(export.into_account().unwrap(), account);
+    }
+
+    #[test]
+    fn test_single_passkey_account_without_policy_round_trips() {
+        let account = AttestaAccount::new(Pubkey::new_unique(), sample_account().passkey_public_key, b"phone".to_vec(), Vec::new(), 100);
+        let imported = import_account(&export_account(None, &account).unwrap()).unwrap();
+        assert_eq!(imported, account);
+    }
+
+    #[test]
+    fn test_policies_decode_or_fall_back_to_raw() {
+        for (name, policy) in sample_policies() {
+            let bytes = policy.to_bytes().unwrap();
+            let json = PolicyJson::from_bytes(&bytes);
+            assert!(!matches!(json, PolicyJson::Raw { .. }), "{} didn't decode", name);
+            assert_eq!(json.to_bytes().unwrap(), bytes, "{}", name);
+        }
+
+        // A legacy daily limit reads, but would be rewritten in the windowed layout
+        #[allow(deprecated)]
+        let legacy = Policy::new(PolicyType::DailyLimit, [5_000u64.to_le_bytes(), 1_700_086_400i64.to_le_bytes()].concat());
+        let bytes = legacy.to_bytes().unwrap();
+        let json = PolicyJson::from_bytes(&bytes);
+        assert_eq!(json, PolicyJson::Raw { bytes: hex(&bytes) });
+        assert_eq!(json.to_bytes().unwrap(), bytes);
+
+        let spending = PolicyJson::from_bytes(&Policy::spending_limit(Amount::from_lamports(42)).to_bytes().unwrap());
+        assert_eq!(
+            serde_json::to_value(&spending).unwrap(),
+            serde_json::json!({ "type": "spending_limit", "max_lamports": 42, "mint_limits": null })
+        );
+    }
+
+    #[test]
+    fn test_import_checks_invariants() {
+        let export = || AccountExport::new(None, &sample_account()).unwrap();
+
+        let mut edited = export();
+        edited.account.nonce += 1;
+        assert_eq!(edited.clone().into_account(), Err(AccountJsonError::StateHashMismatch));
+        edited.state_hash = None;
+        assert_eq!(edited.into_account().unwrap().nonce, sample_account().nonce + 1);
+
+        let mut unknown_version = export();
+        unknown_version.schema_version = ACCOUNT_JSON_SCHEMA_VERSION + 1;
+        assert!(matches!(unknown_version.into_account(), Err(AccountJsonError::UnsupportedSchemaVersion(_))));
+
+        let mut bad_key = export();
+        bad_key.account.passkey_public_key = hex(&[0; P256_PUBKEY_LEN]);
+        bad_key.state_hash = None;
+        assert!(matches!(bad_key.into_account(), Err(AccountJsonError::InvalidAccount(_))));
+
+        let mut orphaned_policies = export();
+        orphaned_policies.account.policy = None;
+        orphaned_policies.state_hash = None;
+        assert!(matches!(orphaned_policies.into_account(), Err(AccountJsonError::InvalidAccount(_))));
+
+        // A policy edited without its hash
+        let mut stale_policy_hash = export();
+        stale_policy_hash.account.additional_policies.clear();
+        stale_policy_hash.state_hash = None;
+        assert_eq!(
+            stale_policy_hash.clone().into_account(),
+            Err(AccountJsonError::InvalidAccount("policy_hash doesn't match the policies"))
+        );
+        stale_policy_hash.account.policy_hash = None;
+        let imported = stale_policy_hash.into_account().unwrap();
+        assert_eq!(imported.policy_hash, imported.compute_policy_hash());
+
+        let mut bad_registry = export();
+        bad_registry.account.passkeys.as_mut().unwrap().recovery_threshold = 0;
+        bad_registry.state_hash = None;
+        assert!(matches!(bad_registry.into_account(), Err(AccountJsonError::InvalidPasskeys(_))));
+
+        let mut short_hash = export();
+        short_hash.account.sign_counts[0].credential_id_hash = "abcd".to_string();
+        short_hash.state_hash = None;
+        assert_eq!(
+            short_hash.into_account(),
+            Err(AccountJsonError::InvalidLength { field: "sign_counts.credential_id_hash", expected: 32, len: 2 })
+        );
+
+        assert!(matches!(import_account("{"), Err(AccountJsonError::Json(_))));
+    }
+
+    #[test]
+    fn test_schema_field_names_are_stable() {
+        // Support tooling reads these names; changing one needs a new ACCOUNT_JSON_SCHEMA_VERSION
+        const FIELDS: &[&str] = &[
+            "schema_version",
+            "address",
+            "state_hash",
+            "account",
+            "account.owner",
+            "account.passkey_public_key",
+            "account.credential_id",
+            "account.nonce",
+            "account.policy",
+            "account.policy.type",
+            "account.policy.max_lamports",
+            "account.policy.mint_limits",
+            "account.created_at",
+            "account.updated_at",
+            "account.passkeys",
+            "account.passkeys.primary",
+            "account.passkeys.primary.public_key",
+            "account.passkeys.primary.credential_id",
+            "account.passkeys.primary.name",
+            "account.passkeys.primary.enabled",
+            "account.passkeys.primary.added_at",
+            "account.passkeys.primary.aaguid",
+            "account.passkeys.primary.id_storage",
+            "account.passkeys.additional",
+            "account.passkeys.additional[].public_key",
+            "account.passkeys.additional[].credential_id",
+            "account.passkeys.additional[].name",
+            "account.passkeys.additional[].enabled",
+            "account.passkeys.additional[].added_at",
+            "account.passkeys.additional[].aaguid",
+            "account.passkeys.additional[].id_storage",
+            "account.passkeys.recovery_threshold",
+            "account.passkeys.max_passkeys",
+            "account.passkeys.revoked",
+            "account.privacy_mode",
+            "account.idempotency_records",
+            "account.idempotency_records[].key",
+            "account.idempotency_records[].message_hash",
+            "account.idempotency_records[].nonce",
+            "account.pending_recovery",
+            "account.pending_recovery.new_public_key",
+            "account.pending_recovery.new_credential_id",
+            "account.pending_recovery.approvals",
+            "account.pending_recovery.initiated_at",
+            "account.pending_recovery.threshold_met_at",
+            "account.pending_recovery.new_aaguid",
+            "account.pending_drill",
+            "account.last_drill_at",
+            "account.settings",
+            "account.settings.reject_zero_amount",
+            "account.settings.reject_self_transfer",
+            "account.settings.max_transaction_data_len",
+            "account.settings.lockout_threshold",
+            "account.settings.aaguid_allowlist",
+            "account.settings.pinned_program_version",
+            "account.settings.webauthn_profile",
+            "account.settings.webauthn_profile.check_rp_id",
+            "account.settings.webauthn_profile.check_origin",
+            "account.settings.webauthn_profile.require_user_present",
+            "account.settings.webauthn_profile.require_user_verified",
+            "account.settings.webauthn_profile.check_sign_count",
+            "account.settings.webauthn_profile.require_low_s",
+            "account.settings.relying_party",
+            "account.settings.relying_party.rp_id_hash",
+            "account.settings.relying_party.origin_hash",
+            "account.settings.auth_mode",
+            "account.settings.auth_mode_locked",
+            "account.settings.authorized_executors",
+            "account.settings.log_allowed_sample_rate",
+            "account.settings.recovery_allowance",
+            "account.parent",
+            "account.sub_account_index",
+            "account.inheritance",
+            "account.inheritance.beneficiary_public_key",
+            "account.inheritance.beneficiary_credential_id",
+            "account.inheritance.inactivity_period",
+            "account.inheritance.grace_period",
+            "account.last_execution_at",
+            "account.failed_auth_count",
+            "account.locked_until",
+            "account.key_history",
+            "account.key_history[].credential_id_hash",
+            "account.key_history[].public_key",
+            "account.key_history[].retired_at",
+            "account.proof_log_enabled",
+            "account.additional_policies",
+            "account.additional_policies[].type",
+            "account.additional_policies[].unlock_timestamp",
+            "account.passkey_aaguid",
+            "account.sign_counts",
+            "account.sign_counts[].credential_id_hash",
+            "account.sign_counts[].sign_count",
+            "account.policy_hash",
+            "account.destination_spends",
+            "account.destination_spends.window_start",
+            "account.destination_spends.entries",
+            "account.destination_spends.entries[].destination_hash",
+            "account.destination_spends.entries[].spent",
+            "account.destination_spends.evicted",
+            "account.state_version",
+            "account.created_layout",
+            "account.emergency_override",
+            "account.emergency_override.max_lamports",
+            "account.emergency_override.period_seconds",
+            "account.emergency_override.last_used_at",
+            "account.account_index",
+        ];
+        let value = serde_json::to_value(AccountExport::new(None, &sample_account()).unwrap()).unwrap();
+        let mut paths = Vec::new();
+        field_paths(&value, "", &mut paths);
+        paths.sort();
+        paths.dedup();
+        let mut expected: Vec<String> = FIELDS.iter().map(|field| field.to_string()).collect();
+        expected.sort();
+        assert_eq!(paths, expected);
+
+        // And the name of every policy type
+        let types: Vec<Value> = sample_policies()
+            .iter()
+            .map(|(_, policy)| serde_json::to_value(PolicyJson::from_bytes(&policy.to_bytes().unwrap())).unwrap()["type"].clone())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "open",
+                "spending_limit",
+                "daily_limit",
+                "multi_sig",
+                "time_locked",
+                "destination_allowlist",
+                "composite",
+                "credential_binding",
+                "per_destination_limit"
+            ]
+            .map(Value::from)
+        );
+    }
+}
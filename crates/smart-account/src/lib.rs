@@ -22,9 +22,34 @@
 //! # Key Components
 //!
 //! - `account.rs`: The main `AttestaAccount` struct that represents an account
+//! - `attestation.rs`: Program-written proof of an account's policy for third parties
 //! - `auth.rs`: Functions for verifying passkey signatures
+//! - `auth_mode.rs`: Letting the owner's wallet sign in place of a passkey during a migration
+//! - `claim.rs`: One-time payment links a passkey signs ahead for whoever holds the link
+//! - `compat.rs`: What accounts written by older versions read as
+//! - `emergency.rs`: A capped, rate-limited override of time locks that takes two passkeys
 //! - `execute.rs`: Transaction execution logic with policy enforcement
+//! - `executors.rs`: Limiting who may submit `execute` for an account
+//! - `idempotency.rs`: Idempotency keys that make retrying `execute` safe
+//! - `inheritance.rs`: Handing an inactive account to a beneficiary
+//! - `json.rs`: Account state as portable JSON for support tooling (`serde` feature)
+//! - `policy_list.rs`: Up to four policies per account, evaluated in order
+//! - `proposal.rs`: Transactions waiting for approvals, approving and withdrawing them
+//! - `proof_log.rs`: A record of executed proofs, re-verifiable after key rotation
+//! - `protocol.rs`: The limits a deployed program reports, declared once for program and clients
+//! - `sampling.rs`: Context for a deterministic sample of allowed executions
+//! - `schedule.rs`: Transactions signed now that anyone can execute inside a time window
+//! - `settings.rs`: How `AccountSettings` are stored: flags and tagged entries
+//! - `simulate.rs`: Predicting the outcome of `execute` without changing the account
+//! - `social_recovery.rs`: Guardian-approved recovery of a lost primary passkey, and drills
+//! - `sponsorship.rs`: Pools that pay new accounts' rent, with per-account and per-day limits
 //! - `storage.rs`: Utilities for reading and writing accounts on-chain
+//! - `sub_account.rs`: Sub-accounts whose transactions must also pass the parent's policy
+//! - `summary.rs`: A report of every check an account currently enforces
+//! - `token.rs`: SPL token transfers made by the account
+//! - `upgrade.rs`: Refusing to execute under a program version the owner hasn't acknowledged,
+//!   and the instruction groups a build may leave out
+//! - `vault.rs`: Large transfers announced a delay before they run, and vetoing them
 //!
 //! # Example
 //!
@@ -32,15 +57,95 @@
 //! use smart_account::{AttestaAccount, execute_transaction, AuthorizationProof};
 //!
 //! // Execute a transaction with an authorization proof
-//! let result = execute_transaction(&mut account, &proof, &transaction_data)?;
+//! let result = execute_transaction(&mut account, &account_address, None, &proof, &transaction_data)?;
 //! ```
 
+// On-chain code must not panic on attacker-controlled input
+#![cfg_attr(not(test), deny(clippy::indexing_slicing, clippy::unwrap_used, clippy::expect_used))]
+
 pub mod account;
+pub mod attestation;
 pub mod auth;
+pub mod auth_mode;
+pub mod claim;
+pub mod compat;
+pub mod emergency;
 pub mod execute;
+pub mod executors;
+pub mod idempotency;
+pub mod inheritance;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod policy_list;
+pub mod proof_log;
+pub mod proposal;
+pub mod protocol;
+pub mod sampling;
+pub mod schedule;
+pub mod settings;
+pub mod simulate;
+pub mod social_recovery;
+pub mod sponsorship;
 pub mod storage;
+pub mod sub_account;
+pub mod summary;
+pub mod token;
+pub mod upgrade;
+pub mod vault;
+
+#[cfg(test)]
+mod format_stability;
 
-pub use account::AttestaAccount;
-pub use auth::{verify_passkey_authorization, AuthorizationProof};
-pub use execute::{execute_transaction, PolicyResult};
-pub use storage::{load_attesta_account, save_attesta_account, init_attesta_account};
+pub use account::{
+    cluster_time, AccountPolicyError, AccountSettings, AccountValidationError, AttestaAccount, SignCount, MAX_AAGUID_ALLOWLIST_LEN, PRIVACY_MODE_ACTION, SETTINGS_UPDATE_ACTION,
+};
+pub use attestation::{
+    attestation_payload, check_attestation, AttestationError, PolicyAttestation, MAX_ATTESTATION_LIFETIME, POLICY_ATTEST_ACTION,
+};
+pub use auth::{
+    verify_passkey_authorization, authorize_action, authorize_admin_action, action_message_hash, registration_challenge, resolve_signing_key,
+    verify_registration, AuthorizationProof, REGISTRATION_ACTION,
+};
+pub use auth_mode::{auth_mode_payload, execute_as_owner, AuthMode, AuthModeError, AUTH_MODE_ACTION};
+pub use claim::{claim_ticket_payload, redeem_claim, ClaimError, ClaimTicket, CLAIM_TICKET_ACTION};
+pub use compat::{stored_layout, ACCOUNT_LAYOUT_VERSION};
+pub use emergency::{
+    emergency_message_hash, EmergencyError, EmergencyOverride, EMERGENCY_CONFIGURE_ACTION, EMERGENCY_OVERRIDE_ACTION,
+};
+pub use execute::{
+    check_memo, check_replay, execute_transaction, execute_transaction_at, memo_hash, transaction_memo_message_hash, transaction_message_hash,
+    DenyReason, ExecuteOutcome, PolicyResult, TransactionRequest, TransactionRequestError, MAX_MEMO_LEN, MAX_TRANSACTION_DATA_LEN,
+};
+pub use executors::{check_executor, ExecutorError, EXECUTOR_ADD_ACTION, EXECUTOR_REMOVE_ACTION, MAX_AUTHORIZED_EXECUTORS};
+pub use idempotency::{ExecutionReceipt, ExecutionStatus, IdempotencyKey, IdempotencyRecord};
+pub use inheritance::{InheritanceConfig, InheritanceError, InheritanceStatus};
+pub use policy_list::{
+    PolicyListError, MAX_ACCOUNT_POLICIES, POLICY_ADD_ACTION, POLICY_REMOVE_ACTION, POLICY_REPLACE_ACTION,
+};
+pub use proof_log::{verify_logged_proof, ProofLog, ProofLogEntry, ProofLogError, RetiredKey};
+pub use proposal::{
+    approve_proposal_payload, cancel_proposal_payload, find_proposal_address, Approval, CancelReason, PendingTransaction,
+    ProposalError, MAX_PROPOSAL_APPROVALS, PROPOSAL_APPROVE_ACTION, PROPOSAL_CANCEL_ACTION, PROPOSAL_LIFETIME, PROPOSAL_SEED,
+};
+pub use protocol::{ProtocolConstants, ProtocolLimits};
+pub use sampling::{is_sampled, sample_allowed, AllowedContext, MAX_SAMPLE_RATE};
+pub use schedule::{
+    schedule_payload, ScheduleError, ScheduledTransaction, SCHEDULE_ACTION, SCHEDULE_CANCEL_ACTION,
+};
+pub use settings::{SettingsEncodingError, SettingsExt};
+pub use simulate::{simulate_execute, SimulationOutcome};
+pub use social_recovery::{within_recovery_allowance, RecoveryFlowError, RecoveryMode, RecoveryProgress, RecoveryRequest};
+pub use sponsorship::{SponsorPool, SponsorshipError};
+pub use storage::{
+    credential_seed, decode_attesta_account, derive_attesta_account, derive_attesta_account_by_index,
+    derive_attesta_account_for_credential, detect_attesta_account, encode_attesta_account, init_attesta_account, load_attesta_account,
+    load_attesta_account_any_layout, save_attesta_account, AccountLayout,
+};
+pub use sub_account::{SubAccountError, SUB_ACCOUNT_CREATE_ACTION};
+pub use summary::{PasskeyRole, PolicySummary, SecuritySummary};
+pub use token::{derive_associated_token_address, TokenTransfer, ASSOCIATED_TOKEN_PROGRAM_ID, MEMO_PROGRAM_ID, TOKEN_PROGRAM_ID};
+pub use upgrade::{ProgramFeature, ProgramFeatures, ProgramVersion, UpgradeError, UPGRADE_ACKNOWLEDGE_ACTION};
+pub use vault::{
+    execute_announced, find_announcement_address, veto_announcement, Announcement, VaultError, ANNOUNCEMENT_GRACE_PERIOD,
+    ANNOUNCEMENT_SEED, ANNOUNCEMENT_VETO_ACTION,
+};
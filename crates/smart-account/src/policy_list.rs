@@ -0,0 +1,333 @@
+//! Several policies on one account, evaluated in order
+//!
+//! An account can hold up to `MAX_ACCOUNT_POLICIES` policies - say a
+//! spending limit and a time lock - instead of folding them into one
+//! composite. `execute` evaluates them in order: the first that denies
+//! decides, otherwise the transaction needs approval if any policy asks for
+//! it, and is allowed if none does.
+//!
+//! Each change is authorized by a passkey, which signs the action with the
+//! index and the policy as payload (see `policy_change_payload`).
+//!
+//! A new policy's time lock may be at most `MAX_FUTURE_SECONDS` away. A
+//! longer lock needs the passkey to sign the long-lock variant of the
+//! action, so a lock meant to outlast that is never a typo.
+
+use attesta_types::time::TimeError;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use recovery::policies::{Policy, MAX_POLICY_COMPUTE_UNITS};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_action;
+
+/// Most policies one account can hold
+pub const MAX_ACCOUNT_POLICIES: usize = 4;
+
+/// Action name a passkey signs to insert a policy
+pub const POLICY_ADD_ACTION: &[u8] = b"add_policy";
+
+/// Action name a passkey signs to remove a policy
+pub const POLICY_REMOVE_ACTION: &[u8] = b"remove_policy";
+
+/// Action name a passkey signs to replace a policy
+pub const POLICY_REPLACE_ACTION: &[u8] = b"replace_policy";
+
+/// Action name a passkey signs to insert a policy locked for longer than
+/// `MAX_FUTURE_SECONDS`
+pub const POLICY_ADD_LONG_LOCK_ACTION: &[u8] = b"add_policy_long_lock";
+
+/// Action name a passkey signs to replace a policy with one locked for
+/// longer than `MAX_FUTURE_SECONDS`
+pub const POLICY_REPLACE_LONG_LOCK_ACTION: &[u8] = b"replace_policy_long_lock";
+
+/// Errors from changing an account's policies
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PolicyListError {
+    #[error("Policy change signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("Invalid policy")]
+    InvalidPolicy,
+
+    #[error("Invalid policy time lock: {0}")]
+    InvalidTimestamp(#[from] TimeError),
+
+    #[error("An account holds at most {MAX_ACCOUNT_POLICIES} policies")]
+    TooManyPolicies,
+
+    #[error("No policy at index {0}")]
+    IndexOutOfRange(u8),
+
+    #[error("Policies are estimated at {estimated} compute units together (at most {MAX_POLICY_COMPUTE_UNITS})")]
+    TooExpensive { estimated: u32 },
+}
+
+/// What a passkey signs to change the policy at `index`
+///
+/// The index, then the serialized policy (nothing, for a removal).
+pub fn policy_change_payload(index: u8, policy: &[u8]) -> Vec<u8> {
+    [&[index][..], policy].concat()
+}
+
+/// Inserts `policy` at `index`, moving the policies from there on back one
+///
+/// `index` may be the number of policies, to append. The policy's time
+/// lock is checked against `now`; with `allow_long_lock` the passkey signs
+/// `POLICY_ADD_LONG_LOCK_ACTION` instead of `POLICY_ADD_ACTION`.
+pub fn add_policy(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    index: u8,
+    policy: Vec<u8>,
+    now: i64,
+    allow_long_lock: bool,
+) -> Result<(), PolicyListError> {
+    let mut policies = owned_policies(account);
+    if policies.len() >= MAX_ACCOUNT_POLICIES {
+        return Err(PolicyListError::TooManyPolicies);
+    }
+    if index as usize > policies.len() {
+        return Err(PolicyListError::IndexOutOfRange(index));
+    }
+    policies.insert(index as usize, policy.clone());
+    check_policies(&policies)?;
+    check_time_lock(&policy, now, allow_long_lock)?;
+
+    let action = if allow_long_lock { POLICY_ADD_LONG_LOCK_ACTION } else { POLICY_ADD_ACTION };
+    authorize_action(account, webauthn_sig, nonce, action, &policy_change_payload(index, &policy))?;
+    account.set_policies(policies);
+    account.bump_state_version();
+    Ok(())
+}
+
+/// Removes the policy at `index`
+pub fn remove_policy(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    index: u8,
+) -> Result<(), PolicyListError> {
+    let mut policies = owned_policies(account);
+    if index as usize >= policies.len() {
+        return Err(PolicyListError::IndexOutOfRange(index));
+    }
+    policies.remove(index as usize);
+
+    authorize_action(account, webauthn_sig, nonce, POLICY_REMOVE_ACTION, &policy_change_payload(index, &[]))?;
+    account.set_policies(policies);
+    account.bump_state_version();
+    Ok(())
+}
+
+/// Replaces the policy at `index` with `policy`, keeping its place in the order
+///
+/// `now` and `allow_long_lock` work as for `add_policy`, with
+/// `POLICY_REPLACE_LONG_LOCK_ACTION` as the long-lock action.
+pub fn replace_policy(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    index: u8,
+    policy: Vec<u8>,
+    now: i64,
+    allow_long_lock: bool,
+) -> Result<(), PolicyListError> {
+    let mut policies = owned_policies(account);
+    let slot = policies.get_mut(index as usize).ok_or(PolicyListError::IndexOutOfRange(index))?;
+    *slot = policy.clone();
+    check_policies(&policies)?;
+    check_time_lock(&policy, now, allow_long_lock)?;
+
+    let action = if allow_long_lock { POLICY_REPLACE_LONG_LOCK_ACTION } else { POLICY_REPLACE_ACTION };
+    authorize_action(account, webauthn_sig, nonce, action, &policy_change_payload(index, &policy))?;
+    account.set_policies(policies);
+    account.bump_state_version();
+    Ok(())
+}
+
+fn owned_policies(account: &AttestaAccount) -> Vec<Vec<u8>> {
+    account.policies().into_iter().map(<[u8]>::to_vec).collect()
+}
+
+/// Checks the time locks of a policy being added (the ones already held
+/// were checked when they were)
+fn check_time_lock(policy: &[u8], now: i64, allow_long_lock: bool) -> Result<(), PolicyListError> {
+    let policy = Policy::from_bytes(policy).map_err(|_| PolicyListError::InvalidPolicy)?;
+    Ok(policy.validate_timestamps(now, allow_long_lock)?)
+}
+
+/// Checks each policy is one the program accepts, and that together they
+/// fit the compute budget `execute` has for policies
+fn check_policies(policies: &[Vec<u8>]) -> Result<(), PolicyListError> {
+    let mut estimated: u32 = 0;
+    for bytes in policies {
+        let policy = Policy::from_bytes(bytes)
+            .ok()
+            .filter(|policy| policy.validate_config().is_ok())
+            .ok_or(PolicyListError::InvalidPolicy)?;
+        estimated = estimated.saturating_add(policy.estimated_compute_units());
+    }
+    if estimated > MAX_POLICY_COMPUTE_UNITS {
+        return Err(PolicyListError::TooExpensive { estimated });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use attesta_types::time::{MAX_FUTURE_SECONDS, SECONDS_PER_YEAR};
+    use recovery::Amount;
+    use solana_program::pubkey::Pubkey;
+    use crate::auth::action_message_hash;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn sign(passkey: &mut TestPasskey, account: &AttestaAccount, action: &[u8], index: u8, policy: &[u8]) -> WebAuthnSignature {
+        let message_hash = action_message_hash(action, &policy_change_payload(index, policy));
+        passkey.sign(&compute_challenge(&account.owner, account.nonce + 1, &message_hash))
+    }
+
+    fn add(passkey: &mut TestPasskey, account: &mut AttestaAccount, index: u8, policy: &Policy) -> Result<(), PolicyListError> {
+        let bytes = policy.to_bytes().unwrap();
+        let sig = sign(passkey, account, POLICY_ADD_ACTION, index, &bytes);
+        let nonce = account.nonce + 1;
+        add_policy(account, sig, nonce, index, bytes, NOW, false)
+    }
+
+    fn limit(lamports: u64) -> Policy {
+        Policy::spending_limit(Amount::from_lamports(lamports))
+    }
+
+    #[test]
+    fn test_add_remove_and_replace_keep_order() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+
+        add(&mut passkey, &mut account, 0, &limit(2)).unwrap();
+        add(&mut passkey, &mut account, 0, &limit(1)).unwrap();
+        add(&mut passkey, &mut account, 2, &limit(3)).unwrap();
+        assert_eq!(owned_policies(&account), vec![
+            limit(1).to_bytes().unwrap(),
+            limit(2).to_bytes().unwrap(),
+            limit(3).to_bytes().unwrap(),
+        ]);
+
+        let replacement = limit(9).to_bytes().unwrap();
+        let sig = sign(&mut passkey, &account, POLICY_REPLACE_ACTION, 1, &replacement);
+        replace_policy(&mut account, sig, 4, 1, replacement.clone(), NOW, false).unwrap();
+        assert_eq!(owned_policies(&account)[1], replacement);
+
+        // Removing the first promotes the next one into `policy`
+        let sig = sign(&mut passkey, &account, POLICY_REMOVE_ACTION, 0, &[]);
+        remove_policy(&mut account, sig, 5, 0).unwrap();
+        assert_eq!(account.policy, replacement);
+        assert_eq!(account.policies().len(), 2);
+        assert_eq!(account.nonce, 5);
+    }
+
+    #[test]
+    fn test_list_is_bounded() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        for i in 0..MAX_ACCOUNT_POLICIES {
+            add(&mut passkey, &mut account, i as u8, &limit(i as u64 + 1)).unwrap();
+        }
+
+        assert_eq!(add(&mut passkey, &mut account, 0, &limit(9)), Err(PolicyListError::TooManyPolicies));
+        assert_eq!(account.nonce, MAX_ACCOUNT_POLICIES as u64);
+    }
+
+    #[test]
+    fn test_bad_changes_are_rejected_before_the_nonce_is_used() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+
+        assert_eq!(add(&mut passkey, &mut account, 1, &limit(1)), Err(PolicyListError::IndexOutOfRange(1)));
+
+        let sig = sign(&mut passkey, &account, POLICY_REMOVE_ACTION, 0, &[]);
+        assert_eq!(remove_policy(&mut account, sig, 1, 0), Err(PolicyListError::IndexOutOfRange(0)));
+
+        let sig = sign(&mut passkey, &account, POLICY_ADD_ACTION, 0, &[0xff]);
+        assert_eq!(add_policy(&mut account, sig, 1, 0, vec![0xff], NOW, false), Err(PolicyListError::InvalidPolicy));
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_combined_cost_is_bounded() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let expensive = Policy::composite(
+            (0..4).map(|_| Policy::destination_allowlist((0..32).map(|_| Pubkey::new_unique()).collect())).collect(),
+        );
+        let cost = expensive.estimated_compute_units();
+        assert!(cost <= MAX_POLICY_COMPUTE_UNITS);
+
+        let mut added = 0;
+        let result = loop {
+            match add(&mut passkey, &mut account, added, &expensive) {
+                Ok(()) => added += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(result, PolicyListError::TooExpensive { estimated: cost * (added as u32 + 1) });
+    }
+
+    #[test]
+    fn test_signature_covers_index_and_policy() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        add(&mut passkey, &mut account, 0, &limit(1)).unwrap();
+
+        // Signed for the end of the list, submitted for the front
+        let bytes = limit(2).to_bytes().unwrap();
+        let sig = sign(&mut passkey, &account, POLICY_ADD_ACTION, 1, &bytes);
+        assert!(matches!(add_policy(&mut account, sig, 2, 0, bytes, NOW, false), Err(PolicyListError::Unauthorized(_))));
+        assert_eq!(account.policies().len(), 1);
+    }
+
+    #[test]
+    fn test_long_locks_need_the_long_lock_action() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let lock = |unlock_at| Policy::time_locked(unlock_at).to_bytes().unwrap();
+        let latest = NOW + MAX_FUTURE_SECONDS;
+
+        let cases = [
+            (lock(latest), POLICY_ADD_ACTION, false, Ok(())),
+            (lock(latest + 1), POLICY_ADD_ACTION, false, Err(PolicyListError::InvalidTimestamp(TimeError::TooFarInFuture {
+                timestamp: latest + 1,
+                latest,
+            }))),
+            (lock(NOW + 20 * SECONDS_PER_YEAR), POLICY_ADD_LONG_LOCK_ACTION, true, Ok(())),
+        ];
+        for (bytes, action, allow_long_lock, expected) in cases {
+            let sig = sign(&mut passkey, &account, action, 0, &bytes);
+            let nonce = account.nonce + 1;
+            let result = add_policy(&mut account, sig, nonce, 0, bytes, NOW, allow_long_lock);
+            assert_eq!(result, expected, "{allow_long_lock}");
+        }
+        assert_eq!(account.policies().len(), 2);
+
+        // The flag must be signed: the ordinary action doesn't carry it
+        let bytes = lock(NOW + 20 * SECONDS_PER_YEAR);
+        let sig = sign(&mut passkey, &account, POLICY_ADD_ACTION, 0, &bytes);
+        assert!(matches!(
+            add_policy(&mut account, sig, 3, 0, bytes, NOW, true),
+            Err(PolicyListError::Unauthorized(_))
+        ));
+
+        // Replacing is held to the same bounds
+        let bytes = lock(latest + 1);
+        let sig = sign(&mut passkey, &account, POLICY_REPLACE_ACTION, 0, &bytes);
+        assert!(matches!(
+            replace_policy(&mut account, sig, 3, 0, bytes.clone(), NOW, false),
+            Err(PolicyListError::InvalidTimestamp(_))
+        ));
+        let sig = sign(&mut passkey, &account, POLICY_REPLACE_LONG_LOCK_ACTION, 0, &bytes);
+        replace_policy(&mut account, sig, 3, 0, bytes.clone(), NOW, true).unwrap();
+        assert_eq!(account.policy, bytes);
+    }
+}
@@ -0,0 +1,266 @@
+//! Proof log: a record of what each execution was authorized for
+//!
+//! Merchants sometimes need to show, long after the fact, that the account's
+//! passkey approved exactly some payload. An account that enables its proof
+//! log gets a companion PDA, and every successful `execute` appends the
+//! nonce, the signed message hash, which credential signed, and when. With
+//! the archived `ProofEnvelope` in hand, anyone can re-check the signature
+//! against the log.
+//!
+//! Passkeys get replaced (recovery, inheritance) and removed, so the account
+//! also keeps its last few retired keys. A logged proof is checked against
+//! the key its credential had when the entry was written, not whatever key
+//! the account holds today.
+
+use attesta_types::consts::{BORSH_LEN_PREFIX, HASH_LEN, P256_PUBKEY_LEN};
+use borsh::{BorshDeserialize, BorshSerialize};
+use thiserror::Error;
+use core_crypto::{compute_challenge, verify_webauthn_signature, CeremonyType, CryptoError};
+use recovery::multi_passkey::credential_id_hash;
+use attesta_types::envelope::ProofEnvelope;
+use crate::account::AttestaAccount;
+use crate::auth::AuthorizationProof;
+
+/// Action name a passkey signs to turn on the proof log
+pub const PROOF_LOG_ENABLE_ACTION: &[u8] = b"enable_proof_log";
+
+/// Most entries a proof log keeps (the oldest are dropped first)
+pub const MAX_PROOF_LOG_ENTRIES: usize = 64;
+
+/// Serialized size of one `ProofLogEntry`
+pub const PROOF_LOG_ENTRY_SIZE: usize = 8 + HASH_LEN + HASH_LEN + 8;
+
+/// Most retired keys an account remembers (the oldest are dropped first)
+///
+/// Proofs signed by a key that has aged out of the history can no longer be
+/// verified from chain state alone.
+pub const MAX_KEY_HISTORY: usize = 8;
+
+/// Serialized size of one `RetiredKey`
+pub const RETIRED_KEY_SIZE: usize = HASH_LEN + P256_PUBKEY_LEN + 8;
+
+/// One successful execution
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofLogEntry {
+    /// The nonce the execution consumed
+    pub nonce: u64,
+
+    /// The transaction hash the passkey signed
+    pub message_hash: [u8; 32],
+
+    /// SHA-256 of the signing credential's ID (as the authenticator reported it)
+    pub credential_id_hash: [u8; 32],
+
+    /// When the execution ran (Unix timestamp)
+    pub timestamp: i64,
+}
+
+impl ProofLogEntry {
+    /// The entry for an execution authorized by `proof`
+    pub fn new(proof: &AuthorizationProof, timestamp: i64) -> Self {
+        Self {
+            nonce: proof.nonce,
+            message_hash: proof.message_hash,
+            credential_id_hash: credential_id_hash(&proof.webauthn_sig.credential_id),
+            timestamp,
+        }
+    }
+}
+
+/// The most recent executions of an account, oldest first
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProofLog {
+    /// Entries ever appended, including ones since dropped
+    pub total_entries: u64,
+
+    /// The kept entries (at most `MAX_PROOF_LOG_ENTRIES`)
+    pub entries: Vec<ProofLogEntry>,
+}
+
+impl ProofLog {
+    /// Serialized size of a full log
+    pub const MAX_SERIALIZED_SIZE: usize = 8 + BORSH_LEN_PREFIX + MAX_PROOF_LOG_ENTRIES * PROOF_LOG_ENTRY_SIZE;
+
+    /// Appends an entry, dropping the oldest once the log is full
+    pub fn append(&mut self, entry: ProofLogEntry) {
+        if self.entries.len() >= MAX_PROOF_LOG_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.entries.push(entry);
+        self.total_entries = self.total_entries.saturating_add(1);
+    }
+
+    /// The entry for the execution that consumed `nonce`, if it's still kept
+    pub fn find(&self, nonce: u64) -> Option<&ProofLogEntry> {
+        self.entries.iter().find(|entry| entry.nonce == nonce)
+    }
+
+    /// Serializes the log to bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
+        borsh::to_vec(self)
+    }
+
+    /// Deserializes a log from bytes
+    pub fn from_bytes(data: &[u8]) -> Result<Self, std::io::Error> {
+        borsh::from_slice(data)
+    }
+}
+
+/// A passkey the account no longer accepts, kept so its old proofs still verify
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RetiredKey {
+    /// SHA-256 of the credential ID (the same hash `ProofLogEntry` uses)
+    pub credential_id_hash: [u8; 32],
+
+    /// The public key the credential had
+    pub public_key: [u8; P256_PUBKEY_LEN],
+
+    /// When it was replaced or removed (Unix timestamp)
+    pub retired_at: i64,
+}
+
+/// Why an archived proof doesn't check out against the log
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ProofLogError {
+    #[error("Proof doesn't match the logged entry ({0} differs)")]
+    EntryMismatch(&'static str),
+
+    #[error("The account has no record of the signing key at that time")]
+    UnknownKey,
+
+    #[error("Logged proof signature is invalid: {0}")]
+    InvalidSignature(#[from] CryptoError),
+}
+
+/// Checks that `envelope` is the authorization behind a logged execution
+///
+/// The envelope must name the entry's nonce, message hash and credential,
+/// and its signature must verify against the key that credential had when
+/// the entry was logged: a retired key if it has since been replaced or
+/// removed, otherwise the one the account holds now.
+///
+/// # Parameters
+/// - `account`: The account the log belongs to (its current state)
+/// - `entry`: The logged execution
+/// - `envelope`: The archived proof that was submitted for it
+pub fn verify_logged_proof(
+    account: &AttestaAccount,
+    entry: &ProofLogEntry,
+    envelope: &ProofEnvelope,
+) -> Result<(), ProofLogError> {
+    if envelope.nonce != entry.nonce {
+        return Err(ProofLogError::EntryMismatch("nonce"));
+    }
+    if envelope.message_hash != entry.message_hash {
+        return Err(ProofLogError::EntryMismatch("message_hash"));
+    }
+    if credential_id_hash(&envelope.webauthn_sig.credential_id) != entry.credential_id_hash {
+        return Err(ProofLogError::EntryMismatch("credential_id"));
+    }
+
+    let public_key = account
+        .public_key_at(&entry.credential_id_hash, entry.timestamp)
+        .ok_or(ProofLogError::UnknownKey)?;
+    let challenge = compute_challenge(&account.owner, envelope.nonce, &envelope.message_hash);
+    verify_webauthn_signature(&envelope.webauthn_sig, &public_key, &challenge, CeremonyType::Get)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use solana_program::pubkey::Pubkey;
+
+    fn signed_entry(passkey: &mut TestPasskey, account: &AttestaAccount, nonce: u64, timestamp: i64) -> (ProofLogEntry, ProofEnvelope) {
+        let message_hash = [nonce as u8; 32];
+        let envelope = ProofEnvelope {
+            webauthn_sig: passkey.sign(&compute_challenge(&account.owner, nonce, &message_hash)),
+            nonce,
+            message_hash,
+            idempotency_key: [0; 16],
+            parent_account: None,
+            logs_proofs: true,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+        let entry = ProofLogEntry::new(&AuthorizationProof::from(envelope.clone()), timestamp);
+        (entry, envelope)
+    }
+
+    #[test]
+    fn test_log_drops_oldest_entries() {
+        let mut log = ProofLog::default();
+        for nonce in 1..=(MAX_PROOF_LOG_ENTRIES as u64 + 3) {
+            log.append(ProofLogEntry { nonce, message_hash: [0; 32], credential_id_hash: [0; 32], timestamp: 0 });
+        }
+
+        assert_eq!(log.entries.len(), MAX_PROOF_LOG_ENTRIES);
+        assert_eq!(log.total_entries, MAX_PROOF_LOG_ENTRIES as u64 + 3);
+        assert!(log.find(3).is_none());
+        assert_eq!(log.find(4).unwrap().nonce, 4);
+        assert_eq!(log.to_bytes().unwrap().len(), ProofLog::MAX_SERIALIZED_SIZE);
+        assert_eq!(ProofLog::from_bytes(&log.to_bytes().unwrap()).unwrap(), log);
+    }
+
+    #[test]
+    fn test_verify_logged_proof() {
+        let mut phone = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 0);
+        let (entry, envelope) = signed_entry(&mut phone, &account, 1, 100);
+
+        assert_eq!(verify_logged_proof(&account, &entry, &envelope), Ok(()));
+
+        // A proof for a different execution doesn't match the entry
+        let (_, other) = signed_entry(&mut phone, &account, 2, 100);
+        assert_eq!(verify_logged_proof(&account, &entry, &other), Err(ProofLogError::EntryMismatch("nonce")));
+
+        // Nor does one whose signature was tampered with
+        let mut forged = envelope.clone();
+        forged.webauthn_sig.signature[0] ^= 1;
+        assert!(matches!(
+            verify_logged_proof(&account, &entry, &forged),
+            Err(ProofLogError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_proof_from_before_rotation_verifies_against_retired_key() {
+        let mut old_phone = TestPasskey::new(1);
+        let mut new_phone = TestPasskey::new(2);
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), old_phone.public_key(), old_phone.credential_id(), vec![], 0);
+        let (old_entry, old_envelope) = signed_entry(&mut old_phone, &account, 1, 100);
+
+        // Recovery-style rotation at t=200
+        let stored_id = account.credential_id.clone();
+        account.retire_key(&stored_id, account.passkey_public_key, 200);
+        account.passkey_public_key = new_phone.public_key();
+        account.credential_id = new_phone.credential_id();
+
+        let (new_entry, new_envelope) = signed_entry(&mut new_phone, &account, 2, 300);
+        assert_eq!(verify_logged_proof(&account, &old_entry, &old_envelope), Ok(()));
+        assert_eq!(verify_logged_proof(&account, &new_entry, &new_envelope), Ok(()));
+
+        // A log entry claiming the old credential signed after it was retired has no key
+        let late = ProofLogEntry { timestamp: 201, ..old_entry };
+        assert_eq!(verify_logged_proof(&account, &late, &old_envelope), Err(ProofLogError::UnknownKey));
+    }
+
+    #[test]
+    fn test_rotation_in_privacy_mode() {
+        let mut old_phone = TestPasskey::new(1);
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), old_phone.public_key(), old_phone.credential_id(), vec![], 0);
+        account.enable_privacy_mode().unwrap();
+        let (entry, envelope) = signed_entry(&mut old_phone, &account, 1, 100);
+
+        // The stored credential ID is already hashed; it must not be hashed again
+        let stored_id = account.credential_id.clone();
+        account.retire_key(&stored_id, account.passkey_public_key, 200);
+        account.passkey_public_key = TestPasskey::new(2).public_key();
+        account.credential_id = account.credential_lookup_id(&TestPasskey::new(2).credential_id());
+
+        assert_eq!(verify_logged_proof(&account, &entry, &envelope), Ok(()));
+    }
+}
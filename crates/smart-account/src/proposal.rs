@@ -0,0 +1,654 @@
+//! Transactions proposed by one passkey, waiting for others to approve
+//!
+//! A transaction whose policy needs more approvals than the passkey that
+//! signed it is kept as a `PendingTransaction` in a proposal PDA, at
+//! `[PROPOSAL_SEED, attesta_account, message_hash]`. `execute` creates it
+//! instead of failing, without using up a nonce; submitting the same
+//! transaction again finds the proposal already there. The record
+//! remembers who proposed it and who paid for the PDA, so a proposal made
+//! by mistake can be withdrawn before co-signers act on it: the proposer, or
+//! the account's primary passkey, signs `PROPOSAL_CANCEL_ACTION` over the
+//! PDA's address and a `CancelReason`, and the program closes the PDA,
+//! refunding its rent to whoever paid for it.
+//!
+//! Another passkey approves by signing `PROPOSAL_APPROVE_ACTION` over the
+//! PDA's address and the transaction's message hash, so an approval can't
+//! be moved to a different proposal. Proposals stop taking approvals
+//! `PROPOSAL_LIFETIME` after they're made.
+//!
+//! Approvals are a set of credential ID hashes, kept sorted, so the count
+//! doesn't depend on the order they arrived in. A passkey that approves
+//! again is told it succeeded and nothing changes: its nonce isn't used,
+//! and it doesn't count twice. An approval arriving once the threshold is
+//! met is rejected with `ThresholdReached`. A proposal also remembers the
+//! account's policy hash: once the policy changes, its approvals were
+//! given under rules that no longer apply, and it takes no more.
+//!
+//! Once enough approvals are in, execution takes priority and the proposal
+//! can no longer be cancelled. Approving a proposal goes through the PDA,
+//! so once it's closed an approval fails like one for a proposal that never
+//! existed; nothing but proposing creates the record again.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use recovery::{credential_id_hash, LimitSpend};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_action;
+use crate::execute::transaction_message_hash;
+
+/// PDA seed prefix for proposals: `[PROPOSAL_SEED, attesta_account, message_hash]`
+pub const PROPOSAL_SEED: &[u8] = b"proposal";
+
+/// Action name a passkey signs, over `cancel_proposal_payload`, to cancel a proposal
+pub const PROPOSAL_CANCEL_ACTION: &[u8] = b"cancel_proposal";
+
+/// Action name a passkey signs, over `approve_proposal_payload`, to approve a proposal
+pub const PROPOSAL_APPROVE_ACTION: &[u8] = b"approve_proposal";
+
+/// How long a proposal collects approvals (seconds): 7 days
+pub const PROPOSAL_LIFETIME: i64 = 7 * 24 * 60 * 60;
+
+/// Most approvals a proposal can need, and so hold
+pub const MAX_PROPOSAL_APPROVALS: u8 = 16;
+
+/// Errors from approving or cancelling a proposal
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ProposalError {
+    #[error("Proposal signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("Only the passkey that proposed the transaction or the primary passkey can cancel it")]
+    NotProposer,
+
+    #[error("The proposal has its approvals and can only be executed")]
+    ThresholdReached,
+
+    #[error("Unknown cancellation reason {0}")]
+    UnknownReason(u8),
+
+    #[error("Invalid proposal data")]
+    InvalidData,
+
+    #[error("The proposal expired at {0}")]
+    Expired(i64),
+
+    #[error("The account's policy changed since the transaction was proposed")]
+    PolicyChanged,
+}
+
+/// What `approve_proposal` did with an approval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    /// The approval counts now; the nonce is used up
+    Counted,
+    /// The passkey had approved already; nothing changed
+    AlreadyCounted,
+}
+
+/// Why a proposal was cancelled, reported in the `ProposalCancelled` event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CancelReason {
+    /// The proposer got something wrong (amount, destination) and will propose again
+    Mistake = 0,
+    /// The payment isn't needed anymore
+    NoLongerNeeded = 1,
+    /// The primary passkey is rejecting a proposal it doesn't recognize
+    Rejected = 2,
+}
+
+impl CancelReason {
+    pub fn from_u8(value: u8) -> Result<Self, ProposalError> {
+        match value {
+            0 => Ok(Self::Mistake),
+            1 => Ok(Self::NoLongerNeeded),
+            2 => Ok(Self::Rejected),
+            other => Err(ProposalError::UnknownReason(other)),
+        }
+    }
+}
+
+/// A transaction waiting in a proposal PDA for its approvals
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PendingTransaction {
+    /// The transaction to execute, as `execute` takes it
+    pub transaction_data: Vec<u8>,
+
+    /// `transaction_message_hash` of `transaction_data`, identifying the proposal
+    pub message_hash: [u8; 32],
+
+    /// The account's `policy_hash` when it was proposed
+    pub policy_hash: [u8; 32],
+
+    /// SHA-256 of the credential ID that proposed it
+    pub proposer: [u8; 32],
+
+    /// Who paid for the proposal PDA, and gets the rent back when it closes
+    pub rent_payer: Pubkey,
+
+    /// SHA-256 of each credential ID that approved it, the proposer's
+    /// included, in ascending order and each once
+    pub approvals: Vec<[u8; 32]>,
+
+    /// Approvals it needs before it can execute, at most `MAX_PROPOSAL_APPROVALS`
+    pub required_approvals: u8,
+
+    /// Lamports held against the account's daily limit while it's pending
+    pub reserved: u64,
+
+    /// When it was proposed (Unix timestamp)
+    pub created_at: i64,
+}
+
+impl PendingTransaction {
+    /// A proposal of `transaction_data` from `account`, approved by its proposer
+    ///
+    /// It needs as many approvals as the account's recovery threshold, and
+    /// never fewer than two: the proposer's own and at least one more. It's
+    /// tied to the account's current policy.
+    ///
+    /// # Parameters
+    /// - `proposer_credential_id`: The credential ID of the passkey that signed it
+    /// - `rent_payer`: Who pays for the proposal PDA
+    /// - `now`: The current Unix timestamp
+    pub fn propose(
+        account: &AttestaAccount,
+        transaction_data: Vec<u8>,
+        proposer_credential_id: &[u8],
+        rent_payer: Pubkey,
+        now: i64,
+    ) -> Result<Self, ProposalError> {
+        let proposer = credential_id_hash(proposer_credential_id);
+        Ok(Self {
+            message_hash: transaction_message_hash(&transaction_data),
+            policy_hash: account.policy_hash,
+            transaction_data,
+            proposer,
+            rent_payer,
+            approvals: vec![proposer],
+            required_approvals: Self::required_approvals(account)?,
+            reserved: 0,
+            created_at: now,
+        })
+    }
+
+    fn required_approvals(account: &AttestaAccount) -> Result<u8, ProposalError> {
+        let registry = account.passkey_registry_or_default().map_err(|_| ProposalError::InvalidData)?;
+        Ok(registry.recovery_threshold.clamp(2, MAX_PROPOSAL_APPROVALS))
+    }
+
+    /// Bytes to allocate for a proposal from `account`
+    ///
+    /// Approvals stop once the threshold is met, so this is room for
+    /// exactly the approvals it needs.
+    pub fn space_for(account: &AttestaAccount, transaction_data_len: usize) -> Result<usize, ProposalError> {
+        let required = Self::required_approvals(account)?;
+        Ok(Self::serialized_size(transaction_data_len, usize::from(required)))
+    }
+
+    /// Bytes the record takes when serialized
+    pub fn serialized_size(transaction_data_len: usize, max_approvals: usize) -> usize {
+        4 + transaction_data_len // transaction_data
+            + 32                 // message_hash
+            + 32                 // policy_hash
+            + 32                 // proposer
+            + 32                 // rent_payer
+            + 4 + 32 * max_approvals
+            + 1                  // required_approvals
+            + 8                  // reserved
+            + 8                  // created_at
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ProposalError> {
+        borsh::to_vec(self).map_err(|_| ProposalError::InvalidData)
+    }
+
+    /// Decodes a record, refusing approvals that aren't a sorted set
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ProposalError> {
+        let proposal: Self = borsh::from_slice(data).map_err(|_| ProposalError::InvalidData)?;
+        let sorted = proposal.approvals.windows(2).all(|pair| matches!(pair, [a, b] if a < b));
+        if !sorted || proposal.approvals.len() > usize::from(MAX_PROPOSAL_APPROVALS) {
+            return Err(ProposalError::InvalidData);
+        }
+        Ok(proposal)
+    }
+
+    /// Whether it has the approvals it needs
+    pub fn threshold_reached(&self) -> bool {
+        self.approvals.len() >= usize::from(self.required_approvals)
+    }
+
+    /// Approvals it still needs before it can execute
+    pub fn approvals_needed(&self) -> u8 {
+        let approvals = u8::try_from(self.approvals.len()).unwrap_or(u8::MAX);
+        self.required_approvals.saturating_sub(approvals)
+    }
+
+    /// When it stops taking approvals (Unix timestamp)
+    pub fn expires_at(&self) -> i64 {
+        self.created_at.saturating_add(PROPOSAL_LIFETIME)
+    }
+
+    /// Whether it has stopped taking approvals at `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        now >= self.expires_at()
+    }
+
+    /// Whether the credential with this ID hash has approved it
+    pub fn is_approved_by(&self, credential_id_hash: &[u8; 32]) -> bool {
+        self.approvals.binary_search(credential_id_hash).is_ok()
+    }
+
+    /// Adds an approval to the set, keeping it sorted
+    ///
+    /// # Returns
+    /// Whether it wasn't there yet
+    fn insert_approval(&mut self, credential_id_hash: [u8; 32]) -> bool {
+        match self.approvals.binary_search(&credential_id_hash) {
+            Ok(_) => false,
+            Err(index) => {
+                self.approvals.insert(index, credential_id_hash);
+                true
+            }
+        }
+    }
+
+    /// Gives back the daily-limit headroom held for it
+    pub fn release_reservation(&self, spend: &mut LimitSpend) {
+        spend.release(self.reserved);
+    }
+}
+
+/// The proposal PDA for the transaction with `message_hash`, and its bump
+pub fn find_proposal_address(program_id: &Pubkey, attesta_account: &Pubkey, message_hash: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PROPOSAL_SEED, attesta_account.as_ref(), message_hash], program_id)
+}
+
+/// The payload a passkey signs with `PROPOSAL_CANCEL_ACTION`
+pub fn cancel_proposal_payload(proposal_address: &Pubkey, reason: CancelReason) -> Vec<u8> {
+    let mut payload = proposal_address.to_bytes().to_vec();
+    payload.push(reason as u8);
+    payload
+}
+
+/// The payload a passkey signs with `PROPOSAL_APPROVE_ACTION`
+pub fn approve_proposal_payload(proposal_address: &Pubkey, message_hash: &[u8; 32]) -> Vec<u8> {
+    [proposal_address.as_ref(), message_hash.as_ref()].concat()
+}
+
+/// Counts a passkey's approval of the proposal at `proposal_address`
+///
+/// Uses up `nonce`. Any of the account's passkeys can approve, and counts
+/// once: approving again succeeds without checking the signature or using
+/// the nonce, since there's nothing to change.
+///
+/// # Parameters
+/// - `webauthn_sig`: The approving passkey's signature over
+///   `PROPOSAL_APPROVE_ACTION` for `approve_proposal_payload(proposal_address, message_hash)`
+/// - `now`: The current Unix timestamp
+///
+/// # Returns
+/// - `Ok(Approval::AlreadyCounted)` if this passkey approved before
+/// - `Err(ProposalError::Expired)` once `PROPOSAL_LIFETIME` has passed
+/// - `Err(ProposalError::PolicyChanged)` if the account's policy isn't the
+///   one it was proposed under
+/// - `Err(ProposalError::ThresholdReached)` if it already has its approvals
+pub fn approve_proposal(
+    account: &mut AttestaAccount,
+    proposal: &mut PendingTransaction,
+    proposal_address: &Pubkey,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    now: i64,
+) -> Result<Approval, ProposalError> {
+    if proposal.is_expired(now) {
+        return Err(ProposalError::Expired(proposal.expires_at()));
+    }
+    if proposal.policy_hash != account.policy_hash {
+        return Err(ProposalError::PolicyChanged);
+    }
+    let approver = credential_id_hash(&webauthn_sig.credential_id);
+    if proposal.is_approved_by(&approver) {
+        return Ok(Approval::AlreadyCounted);
+    }
+    if proposal.threshold_reached() {
+        return Err(ProposalError::ThresholdReached);
+    }
+
+    let payload = approve_proposal_payload(proposal_address, &proposal.message_hash);
+    authorize_action(account, webauthn_sig, nonce, PROPOSAL_APPROVE_ACTION, &payload)?;
+    proposal.insert_approval(approver);
+    Ok(Approval::Counted)
+}
+
+/// Checks a signature cancelling the proposal at `proposal_address`
+///
+/// Uses up `nonce`; the caller closes the PDA and releases the reservation.
+///
+/// # Parameters
+/// - `webauthn_sig`: The proposer's or the primary passkey's signature over
+///   `PROPOSAL_CANCEL_ACTION` for `cancel_proposal_payload(proposal_address, reason)`
+///
+/// # Returns
+/// - `Err(ProposalError::ThresholdReached)` once the proposal can execute
+/// - `Err(ProposalError::NotProposer)` if another passkey signed
+pub fn cancel_proposal(
+    account: &mut AttestaAccount,
+    proposal: &PendingTransaction,
+    proposal_address: &Pubkey,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    reason: CancelReason,
+) -> Result<(), ProposalError> {
+    if proposal.threshold_reached() {
+        return Err(ProposalError::ThresholdReached);
+    }
+
+    let signer = &webauthn_sig.credential_id;
+    if credential_id_hash(signer) != proposal.proposer && !account.is_primary_credential(signer) {
+        return Err(ProposalError::NotProposer);
+    }
+
+    let payload = cancel_proposal_payload(proposal_address, reason);
+    authorize_action(account, webauthn_sig, nonce, PROPOSAL_CANCEL_ACTION, &payload)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use crate::auth::action_message_hash;
+
+    fn sign(passkey: &mut TestPasskey, account: &AttestaAccount, payload: &[u8]) -> (WebAuthnSignature, u64) {
+        sign_action(passkey, account, PROPOSAL_CANCEL_ACTION, payload)
+    }
+
+    fn sign_action(passkey: &mut TestPasskey, account: &AttestaAccount, action: &[u8], payload: &[u8]) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let challenge = compute_challenge(&account.owner, nonce, &action_message_hash(action, payload));
+        (passkey.sign(&challenge), nonce)
+    }
+
+    /// An account with a phone (primary), laptop and tablet, and a transfer the laptop proposed
+    fn setup() -> (AttestaAccount, [TestPasskey; 3], PendingTransaction) {
+        let passkeys = [TestPasskey::new(1), TestPasskey::new(2), TestPasskey::new(3)];
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), passkeys[0].public_key(), passkeys[0].credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        for (passkey, name) in passkeys[1..].iter().zip(["Laptop", "Tablet"]) {
+            registry.add_passkey(passkey.public_key(), passkey.credential_id(), name.to_string(), 110).unwrap();
+        }
+        account.set_passkey_registry(&registry).unwrap();
+
+        let transaction_data = vec![7; 40];
+        let laptop = credential_id_hash(&passkeys[1].credential_id());
+        let proposal = PendingTransaction {
+            message_hash: transaction_message_hash(&transaction_data),
+            policy_hash: account.policy_hash,
+            transaction_data,
+            proposer: laptop,
+            rent_payer: Pubkey::new_unique(),
+            approvals: vec![laptop],
+            required_approvals: 2,
+            reserved: 500,
+            created_at: 120,
+        };
+        (account, passkeys, proposal)
+    }
+
+    #[test]
+    fn test_proposer_cancels() {
+        let (mut account, [_, mut laptop, _], proposal) = setup();
+        let address = Pubkey::new_unique();
+        let payload = cancel_proposal_payload(&address, CancelReason::Mistake);
+        let (sig, nonce) = sign(&mut laptop, &account, &payload);
+
+        cancel_proposal(&mut account, &proposal, &address, sig, nonce, CancelReason::Mistake).unwrap();
+        assert_eq!(account.nonce, nonce);
+
+        let mut spend = LimitSpend { window_start: 0, spent: 100, reserved: 800 };
+        proposal.release_reservation(&mut spend);
+        assert_eq!(spend.reserved, 300);
+    }
+
+    #[test]
+    fn test_primary_cancels_any_proposal() {
+        let (mut account, [mut phone, _, _], proposal) = setup();
+        let address = Pubkey::new_unique();
+        let (sig, nonce) = sign(&mut phone, &account, &cancel_proposal_payload(&address, CancelReason::Rejected));
+
+        cancel_proposal(&mut account, &proposal, &address, sig, nonce, CancelReason::Rejected).unwrap();
+    }
+
+    #[test]
+    fn test_other_passkeys_cannot_cancel() {
+        let (mut account, [_, _, mut tablet], proposal) = setup();
+        let address = Pubkey::new_unique();
+        let (sig, nonce) = sign(&mut tablet, &account, &cancel_proposal_payload(&address, CancelReason::Mistake));
+
+        assert_eq!(
+            cancel_proposal(&mut account, &proposal, &address, sig, nonce, CancelReason::Mistake),
+            Err(ProposalError::NotProposer)
+        );
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_cancel_is_bound_to_proposal_and_reason() {
+        let (mut account, [_, mut laptop, _], proposal) = setup();
+        let address = Pubkey::new_unique();
+        let (sig, nonce) = sign(&mut laptop, &account, &cancel_proposal_payload(&address, CancelReason::Mistake));
+
+        let other = Pubkey::new_unique();
+        assert!(matches!(
+            cancel_proposal(&mut account, &proposal, &other, sig.clone(), nonce, CancelReason::Mistake),
+            Err(ProposalError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            cancel_proposal(&mut account, &proposal, &address, sig, nonce, CancelReason::NoLongerNeeded),
+            Err(ProposalError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_after_threshold_fails() {
+        let (mut account, [_, mut laptop, tablet], mut proposal) = setup();
+        proposal.approvals.push(credential_id_hash(&tablet.credential_id()));
+        let address = Pubkey::new_unique();
+        let (sig, nonce) = sign(&mut laptop, &account, &cancel_proposal_payload(&address, CancelReason::Mistake));
+
+        assert_eq!(
+            cancel_proposal(&mut account, &proposal, &address, sig, nonce, CancelReason::Mistake),
+            Err(ProposalError::ThresholdReached)
+        );
+    }
+
+    #[test]
+    fn test_approvals_are_counted_once() {
+        let (mut account, [_, _, mut tablet], mut proposal) = setup();
+        let address = Pubkey::new_unique();
+        let payload = approve_proposal_payload(&address, &proposal.message_hash);
+
+        let (sig, nonce) = sign_action(&mut tablet, &account, PROPOSAL_APPROVE_ACTION, &payload);
+        assert_eq!(approve_proposal(&mut account, &mut proposal, &address, sig, nonce, 130), Ok(Approval::Counted));
+        assert_eq!(account.nonce, nonce);
+        assert!(proposal.threshold_reached());
+        assert!(proposal.is_approved_by(&credential_id_hash(&tablet.credential_id())));
+    }
+
+    #[test]
+    fn test_repeat_approval_succeeds_without_changing_anything() {
+        let (mut account, [_, mut laptop, _], mut proposal) = setup();
+        proposal.required_approvals = 3;
+        let address = Pubkey::new_unique();
+        let payload = approve_proposal_payload(&address, &proposal.message_hash);
+        let before = proposal.clone();
+
+        // The proposer's approval is already in; approving again is a no-op
+        for _ in 0..2 {
+            let (sig, nonce) = sign_action(&mut laptop, &account, PROPOSAL_APPROVE_ACTION, &payload);
+            assert_eq!(approve_proposal(&mut account, &mut proposal, &address, sig, nonce, 130), Ok(Approval::AlreadyCounted));
+        }
+        assert_eq!(proposal, before);
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_approval_after_threshold_is_rejected() {
+        let (mut account, [mut phone, mut laptop, tablet], mut proposal) = setup();
+        proposal.insert_approval(credential_id_hash(&tablet.credential_id()));
+        assert!(proposal.threshold_reached());
+        let address = Pubkey::new_unique();
+        let payload = approve_proposal_payload(&address, &proposal.message_hash);
+
+        let (sig, nonce) = sign_action(&mut phone, &account, PROPOSAL_APPROVE_ACTION, &payload);
+        assert_eq!(
+            approve_proposal(&mut account, &mut proposal, &address, sig, nonce, 130),
+            Err(ProposalError::ThresholdReached)
+        );
+        assert_eq!((proposal.approvals.len(), account.nonce), (2, 0));
+
+        // A passkey that's already in the set still gets its idempotent answer
+        let (sig, nonce) = sign_action(&mut laptop, &account, PROPOSAL_APPROVE_ACTION, &payload);
+        assert_eq!(approve_proposal(&mut account, &mut proposal, &address, sig, nonce, 130), Ok(Approval::AlreadyCounted));
+    }
+
+    #[test]
+    fn test_policy_change_mid_flight_stops_approvals() {
+        let (mut account, [_, _, mut tablet], mut proposal) = setup();
+        let address = Pubkey::new_unique();
+        let payload = approve_proposal_payload(&address, &proposal.message_hash);
+
+        // The policy is replaced after the transaction was proposed
+        account.set_policies(vec![recovery::Policy::time_locked(0).to_bytes().unwrap()]);
+        assert_ne!(account.policy_hash, proposal.policy_hash);
+
+        let (sig, nonce) = sign_action(&mut tablet, &account, PROPOSAL_APPROVE_ACTION, &payload);
+        assert_eq!(
+            approve_proposal(&mut account, &mut proposal, &address, sig, nonce, 130),
+            Err(ProposalError::PolicyChanged)
+        );
+        assert_eq!((proposal.approvals.len(), account.nonce), (1, 0));
+    }
+
+    #[test]
+    fn test_approvals_are_a_sorted_set_whatever_the_order() {
+        let hashes = [[9; 32], [1; 32], [5; 32], [3; 32]];
+        let (_, _, mut forwards) = setup();
+        let (_, _, mut backwards) = setup();
+        forwards.approvals.clear();
+        backwards.approvals.clear();
+        for hash in hashes {
+            assert!(forwards.insert_approval(hash));
+        }
+        for hash in hashes.iter().rev() {
+            assert!(backwards.insert_approval(*hash));
+        }
+        assert!(!forwards.insert_approval([5; 32]));
+        assert_eq!(forwards.approvals, vec![[1; 32], [3; 32], [5; 32], [9; 32]]);
+        assert_eq!(forwards.approvals, backwards.approvals);
+        assert!(hashes.iter().all(|hash| forwards.is_approved_by(hash)));
+
+        // A stored record has to hold a set, within the bound
+        let bytes = forwards.to_bytes().unwrap();
+        assert_eq!(PendingTransaction::from_bytes(&bytes), Ok(forwards.clone()));
+        forwards.approvals.swap(0, 1);
+        assert_eq!(PendingTransaction::from_bytes(&forwards.to_bytes().unwrap()), Err(ProposalError::InvalidData));
+        forwards.approvals = vec![[2; 32], [2; 32]];
+        assert_eq!(PendingTransaction::from_bytes(&forwards.to_bytes().unwrap()), Err(ProposalError::InvalidData));
+        forwards.approvals = (0..=MAX_PROPOSAL_APPROVALS).map(|i| [i; 32]).collect();
+        assert_eq!(PendingTransaction::from_bytes(&forwards.to_bytes().unwrap()), Err(ProposalError::InvalidData));
+    }
+
+    #[test]
+    fn test_approval_is_bound_to_proposal_and_lifetime() {
+        let (mut account, [mut phone, _, _], mut proposal) = setup();
+        let address = Pubkey::new_unique();
+
+        // Signed for another proposal
+        let payload = approve_proposal_payload(&Pubkey::new_unique(), &proposal.message_hash);
+        let (sig, nonce) = sign_action(&mut phone, &account, PROPOSAL_APPROVE_ACTION, &payload);
+        assert!(matches!(
+            approve_proposal(&mut account, &mut proposal, &address, sig, nonce, 130),
+            Err(ProposalError::Unauthorized(_))
+        ));
+
+        let payload = approve_proposal_payload(&address, &proposal.message_hash);
+        let (sig, nonce) = sign_action(&mut phone, &account, PROPOSAL_APPROVE_ACTION, &payload);
+        let expired = proposal.created_at + PROPOSAL_LIFETIME;
+        assert_eq!(
+            approve_proposal(&mut account, &mut proposal, &address, sig, nonce, expired),
+            Err(ProposalError::Expired(expired))
+        );
+        assert_eq!(proposal.approvals.len(), 1);
+        assert_eq!(account.nonce, 0);
+    }
+
+    #[test]
+    fn test_reason_codes_are_stable() {
+        for (code, reason) in [(0, CancelReason::Mistake), (1, CancelReason::NoLongerNeeded), (2, CancelReason::Rejected)] {
+            assert_eq!(reason as u8, code);
+            assert_eq!(CancelReason::from_u8(code), Ok(reason));
+        }
+        assert_eq!(CancelReason::from_u8(3), Err(ProposalError::UnknownReason(3)));
+    }
+
+    #[test]
+    fn test_serialized_size_matches_to_bytes() {
+        let (_, _, mut proposal) = setup();
+        proposal.approvals = (1..=4).map(|i| [i; 32]).collect();
+        assert_eq!(proposal.to_bytes().unwrap().len(), PendingTransaction::serialized_size(40, 4));
+        assert_eq!(PendingTransaction::from_bytes(&proposal.to_bytes().unwrap()), Ok(proposal));
+    }
+
+    #[test]
+    fn test_propose_starts_with_the_proposers_approval() {
+        let (account, [_, laptop, _], _) = setup();
+        let rent_payer = Pubkey::new_unique();
+        let proposal = PendingTransaction::propose(&account, vec![9; 40], &laptop.credential_id(), rent_payer, 500).unwrap();
+        let laptop = credential_id_hash(&laptop.credential_id());
+        assert_eq!(proposal.message_hash, transaction_message_hash(&[9; 40]));
+        assert_eq!(proposal.policy_hash, account.policy_hash);
+        assert_eq!((proposal.proposer, proposal.approvals.clone()), (laptop, vec![laptop]));
+        assert_eq!((proposal.rent_payer, proposal.created_at, proposal.reserved), (rent_payer, 500, 0));
+        // The default registry recovers with one passkey, but a proposal always needs a second
+        assert_eq!((proposal.required_approvals, proposal.approvals_needed()), (2, 1));
+        assert!(!proposal.threshold_reached());
+
+        // Room for exactly the approvals it needs
+        let space = PendingTransaction::space_for(&account, 40).unwrap();
+        let mut full = proposal.clone();
+        full.approvals = vec![[1; 32], [2; 32]];
+        assert_eq!(full.to_bytes().unwrap().len(), space);
+        assert_eq!(full.approvals_needed(), 0);
+    }
+
+    #[test]
+    fn test_required_approvals_are_bounded() {
+        let (mut account, [_, laptop, _], _) = setup();
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.max_passkeys = u8::MAX;
+        registry.recovery_threshold = 40;
+        account.set_passkey_registry(&registry).unwrap();
+
+        let proposal = PendingTransaction::propose(&account, vec![9; 40], &laptop.credential_id(), Pubkey::new_unique(), 0).unwrap();
+        assert_eq!(proposal.required_approvals, MAX_PROPOSAL_APPROVALS);
+        assert_eq!(
+            PendingTransaction::space_for(&account, 40),
+            Ok(PendingTransaction::serialized_size(40, usize::from(MAX_PROPOSAL_APPROVALS)))
+        );
+    }
+
+    #[test]
+    fn test_each_transaction_has_its_own_proposal_address() {
+        let program_id = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let (address, _) = find_proposal_address(&program_id, &account, &[1; 32]);
+        assert_eq!(find_proposal_address(&program_id, &account, &[1; 32]).0, address);
+        assert_ne!(find_proposal_address(&program_id, &account, &[2; 32]).0, address);
+        assert_ne!(find_proposal_address(&program_id, &Pubkey::new_unique(), &[1; 32]).0, address);
+    }
+}
@@ -0,0 +1,220 @@
+//! The limits a deployed program enforces, as it reports them
+//!
+//! Clients that compile limits in (the longest credential ID, the largest
+//! policy, how long a proposal stays open) drift from the program after an
+//! upgrade. `get_protocol_constants` returns a `ProtocolConstants` instead,
+//! and `ProtocolLimits::compiled` is what this build of the crates says,
+//! for clients that haven't asked the program.
+//!
+//! Each limit is declared once, below, together with the shared constant it
+//! comes from; the program and the crates both build their copy from that
+//! list.
+
+use attesta_types::consts::{MAX_CREDENTIAL_ID_LEN, MAX_INITIAL_POLICY_LEN, MAX_PASSKEY_NAME_LEN};
+use attesta_types::policy::{PolicyType, MAX_POLICY_COMPUTE_UNITS, MAX_POLICY_DESTINATIONS, MAX_POLICY_SIGNERS};
+use attesta_types::transaction::{TransactionRequestError, MAX_MEMO_LEN, MAX_TRANSACTION_DATA_LEN};
+use borsh::{BorshDeserialize, BorshSerialize};
+use recovery::encrypted_backup::MAX_ESCROW_BACKUP_SIZE;
+use recovery::multi_passkey::MAX_PASSKEYS;
+use crate::compat::ACCOUNT_LAYOUT_VERSION;
+use crate::executors::MAX_AUTHORIZED_EXECUTORS;
+use crate::idempotency::MAX_IDEMPOTENCY_RECORDS;
+use crate::policy_list::MAX_ACCOUNT_POLICIES;
+use crate::proposal::{MAX_PROPOSAL_APPROVALS, PROPOSAL_LIFETIME};
+use crate::upgrade::{ProgramFeature, ProgramVersion};
+
+/// Declares `ProtocolLimits` from a list of fields and the constants they
+/// are set from
+///
+/// New limits go at the end: the struct is Borsh-encoded, and clients read
+/// a newer program's longer encoding by ignoring what follows the fields
+/// they know.
+macro_rules! protocol_limits {
+    ($($(#[doc = $doc:literal])* $field:ident: $ty:ty = $value:expr,)*) => {
+        /// The limits a program enforces
+        #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct ProtocolLimits {
+            $($(#[doc = $doc])* pub $field: $ty,)*
+        }
+
+        impl ProtocolLimits {
+            /// The limits these crates were built with
+            pub const fn compiled() -> Self {
+                Self { $($field: $value as $ty,)* }
+            }
+
+            /// Each limit's name and value, in the order they're encoded
+            pub fn entries(&self) -> Vec<(&'static str, i64)> {
+                vec![$((stringify!($field), self.$field as i64),)*]
+            }
+        }
+    };
+}
+
+protocol_limits! {
+    /// Longest credential ID a new account is allocated room for
+    max_credential_id_len: u32 = MAX_CREDENTIAL_ID_LEN,
+    /// Longest passkey name, in UTF-8 bytes
+    max_passkey_name_len: u32 = MAX_PASSKEY_NAME_LEN,
+    /// Longest policy a new account is allocated room for
+    max_initial_policy_len: u32 = MAX_INITIAL_POLICY_LEN,
+    /// Most transaction data one execution carries
+    max_transaction_data_len: u32 = MAX_TRANSACTION_DATA_LEN,
+    /// Longest memo signed with a transaction
+    max_memo_len: u32 = MAX_MEMO_LEN,
+    /// Most passkeys one account holds
+    max_passkeys: u8 = MAX_PASSKEYS,
+    /// Most signers a multi-sig policy lists
+    max_policy_signers: u32 = MAX_POLICY_SIGNERS,
+    /// Most destinations an allowlist policy lists
+    max_policy_destinations: u32 = MAX_POLICY_DESTINATIONS,
+    /// Most policies one account has at once
+    max_account_policies: u32 = MAX_ACCOUNT_POLICIES,
+    /// Highest estimated cost of a policy the program accepts
+    max_policy_compute_units: u32 = MAX_POLICY_COMPUTE_UNITS,
+    /// Highest `PolicyType` the program reads (the last one declared)
+    max_policy_type: u8 = PolicyType::Vault,
+    /// Largest backup the escrow holds
+    max_escrow_backup_size: u32 = MAX_ESCROW_BACKUP_SIZE,
+    /// Most approvals a proposal collects
+    max_proposal_approvals: u8 = MAX_PROPOSAL_APPROVALS,
+    /// Seconds a proposal stays open
+    proposal_lifetime: i64 = PROPOSAL_LIFETIME,
+    /// Most executors an account lists
+    max_authorized_executors: u32 = MAX_AUTHORIZED_EXECUTORS,
+    /// Executions an account remembers idempotency keys for
+    max_idempotency_records: u32 = MAX_IDEMPOTENCY_RECORDS,
+    /// The account layout the program writes
+    account_layout_version: u8 = ACCOUNT_LAYOUT_VERSION,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self::compiled()
+    }
+}
+
+impl ProtocolLimits {
+    /// Checks transaction data against `max_transaction_data_len`
+    pub fn check_transaction_data_len(&self, len: usize) -> Result<(), TransactionRequestError> {
+        let max = self.max_transaction_data_len as usize;
+        if len > max {
+            return Err(TransactionRequestError::TooLarge { len, max });
+        }
+        Ok(())
+    }
+
+    /// Checks a memo against `max_memo_len`, and that it's UTF-8
+    pub fn check_memo(&self, memo: &[u8]) -> Result<(), TransactionRequestError> {
+        let max = self.max_memo_len as usize;
+        if memo.len() > max {
+            return Err(TransactionRequestError::MemoTooLong { len: memo.len(), max });
+        }
+        std::str::from_utf8(memo).map_err(|_| TransactionRequestError::MemoNotUtf8)?;
+        Ok(())
+    }
+}
+
+/// What `get_protocol_constants` returns: the program's version, its
+/// optional features, and its limits
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolConstants {
+    /// The version `get_program_version` reports
+    pub program_version: ProgramVersion,
+
+    /// The features built in, one bit per `ProgramFeature` in
+    /// `ProgramFeature::ALL` order (bit 0 is the first)
+    pub features: u32,
+
+    /// The limits the program enforces
+    pub limits: ProtocolLimits,
+}
+
+impl ProtocolConstants {
+    /// The constants of a program at `program_version` built with `features`
+    ///
+    /// Its limits are always this build's (`ProtocolLimits::compiled`).
+    pub fn new(program_version: ProgramVersion, features: &[ProgramFeature]) -> Self {
+        let features = ProgramFeature::ALL
+            .iter()
+            .enumerate()
+            .filter(|(_, feature)| features.contains(feature))
+            .fold(0, |bits, (bit, _)| bits | 1 << bit);
+        Self { program_version, features, limits: ProtocolLimits::compiled() }
+    }
+
+    pub fn is_enabled(&self, feature: ProgramFeature) -> bool {
+        ProgramFeature::ALL
+            .iter()
+            .position(|known| *known == feature)
+            .is_some_and(|bit| self.features & (1 << bit) != 0)
+    }
+
+    /// Encodes the constants for `set_return_data`
+    pub fn to_return_data(&self) -> Vec<u8> {
+        // Serializing into a Vec can't fail
+        borsh::to_vec(self).unwrap_or_default()
+    }
+
+    /// Decodes constants from `get_protocol_constants`' return data
+    ///
+    /// Anything after the limits this build knows (added by a newer
+    /// program) is ignored.
+    ///
+    /// # Returns
+    /// `None` if the data doesn't start with the constants
+    pub fn from_return_data(mut data: &[u8]) -> Option<Self> {
+        Self::deserialize(&mut data).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compiled_limits_are_the_shared_constants() {
+        let limits = ProtocolLimits::compiled();
+        assert_eq!(limits.max_credential_id_len as usize, MAX_CREDENTIAL_ID_LEN);
+        assert_eq!(limits.max_transaction_data_len as usize, MAX_TRANSACTION_DATA_LEN);
+        assert_eq!(limits.max_memo_len as usize, MAX_MEMO_LEN);
+        assert_eq!(limits.max_passkeys, MAX_PASSKEYS);
+        assert_eq!(limits.max_policy_compute_units, MAX_POLICY_COMPUTE_UNITS);
+        assert_eq!(limits.proposal_lifetime, PROPOSAL_LIFETIME);
+        assert_eq!(limits.account_layout_version, ACCOUNT_LAYOUT_VERSION);
+
+        assert!(limits.entries().iter().all(|(_, value)| *value > 0));
+
+        // The policy type ceiling is the last type there is
+        assert!(borsh::from_slice::<PolicyType>(&[limits.max_policy_type]).is_ok());
+        assert!(borsh::from_slice::<PolicyType>(&[limits.max_policy_type + 1]).is_err());
+    }
+
+    #[test]
+    fn test_constants_round_trip() {
+        let constants = ProtocolConstants::new(ProgramVersion::new("0.1.0", &["no-claims"]), &[ProgramFeature::Inheritance]);
+        assert_eq!(constants.features, 0b010);
+        assert!(constants.is_enabled(ProgramFeature::Inheritance));
+        assert!(!constants.is_enabled(ProgramFeature::Claims));
+        assert_eq!(ProtocolConstants::from_return_data(&constants.to_return_data()), Some(constants.clone()));
+
+        // A newer program's extra limits are skipped
+        let mut newer = constants.to_return_data();
+        newer.extend_from_slice(&[7; 12]);
+        assert_eq!(ProtocolConstants::from_return_data(&newer), Some(constants));
+        assert_eq!(ProtocolConstants::from_return_data(&[1, 2]), None);
+    }
+
+    #[test]
+    fn test_limits_checks_use_the_reported_values() {
+        let mut limits = ProtocolLimits::compiled();
+        assert!(limits.check_memo(&[b'a'; MAX_MEMO_LEN]).is_ok());
+
+        limits.max_memo_len = 4;
+        limits.max_transaction_data_len = 10;
+        assert_eq!(limits.check_memo(b"hello"), Err(TransactionRequestError::MemoTooLong { len: 5, max: 4 }));
+        assert_eq!(limits.check_memo(&[0xff]), Err(TransactionRequestError::MemoNotUtf8));
+        assert_eq!(limits.check_transaction_data_len(11), Err(TransactionRequestError::TooLarge { len: 11, max: 10 }));
+        assert!(limits.check_transaction_data_len(10).is_ok());
+    }
+}
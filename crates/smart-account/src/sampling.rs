@@ -0,0 +1,234 @@
+//! Sampled context for allowed executions
+//!
+//! A denial is logged with its reason, but an allowed execution only says
+//! that it ran. An account that wants to see which of its rules are doing
+//! the work sets `AccountSettings::log_allowed_sample_rate`: that
+//! percentage of its allowed executions also emit an `AllowedWithContext`
+//! event with the policy hash, the amount, a hash of the destination, and
+//! the rule and entry (the mint limit, listed destination or binding) that
+//! matched.
+//!
+//! Which executions are sampled comes from hashing the account's address
+//! and the nonce. The same nonce always gets the same answer, and whoever
+//! submits the transaction has no say in it: they can't resubmit until a
+//! transfer is, or isn't, logged.
+
+use attesta_types::consts::HASH_LEN;
+use borsh::{BorshDeserialize, BorshSerialize};
+use recovery::policies::destination_hash;
+use recovery::{Policy, PolicyType};
+use core_crypto::{domain_hasher, Domain};
+use sha2::Digest;
+use solana_program::pubkey::Pubkey;
+use crate::account::AttestaAccount;
+use crate::token::TokenTransfer;
+
+/// Highest `log_allowed_sample_rate`: every allowed execution is logged
+pub const MAX_SAMPLE_RATE: u8 = 100;
+
+/// Domain tag hashed in front of the account and nonce
+pub const SAMPLE_DOMAIN: &[u8] = Domain::AllowedSample.prefix();
+
+/// `matched_rule` and `matched_entry` when no rule matched a listed entry
+pub const NO_MATCH: u8 = u8::MAX;
+
+/// Whether the execution of `nonce` on `account_address` is in the sample
+///
+/// `rate` is a percentage: 0 samples nothing, `MAX_SAMPLE_RATE` (or more)
+/// everything.
+pub fn is_sampled(account_address: &Pubkey, nonce: u64, rate: u8) -> bool {
+    if rate == 0 {
+        return false;
+    }
+    if rate >= MAX_SAMPLE_RATE {
+        return true;
+    }
+    let hash = domain_hasher(Domain::AllowedSample)
+        .chain_update(account_address.as_ref())
+        .chain_update(nonce.to_le_bytes())
+        .finalize();
+    // A SHA-256 hash always has 8 bytes to read
+    let head = hash.get(..8).and_then(|head| head.try_into().ok()).map_or(0, u64::from_le_bytes);
+    head % u64::from(MAX_SAMPLE_RATE) < u64::from(rate)
+}
+
+/// What an `AllowedWithContext` event says about one allowed execution
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllowedContext {
+    /// The account's `policy_hash` when it was allowed
+    pub policy_hash: [u8; HASH_LEN],
+
+    /// Base units a token transfer moved (0 for other transactions)
+    pub amount: u64,
+
+    /// `destination_hash` of the token account paid into (all zeros for
+    /// other transactions)
+    pub destination_hash: [u8; HASH_LEN],
+
+    /// Index of the rule that matched, counting the account's policies in
+    /// order with a composite's rules in its place, or `NO_MATCH`
+    pub matched_rule: u8,
+
+    /// Index of the entry within that rule: the mint limit, the listed
+    /// destination, the credential binding or the destination limit
+    pub matched_entry: u8,
+}
+
+impl AllowedContext {
+    /// Describes an allowed execution of `transaction_data` by `account`
+    ///
+    /// The first rule with an entry for the transfer's mint or destination
+    /// is the one reported. Rules that treat every transaction alike (SOL
+    /// limits, time locks, a binding's default) have no entries to match,
+    /// so an account with only those reports `NO_MATCH`.
+    pub fn new(account: &AttestaAccount, transaction_data: &[u8]) -> Self {
+        let transfer = TokenTransfer::from_transaction_data(transaction_data);
+        let (matched_rule, matched_entry) = transfer
+            .as_ref()
+            .and_then(|transfer| matched_branch(account, transfer))
+            .unwrap_or((NO_MATCH, NO_MATCH));
+        Self {
+            policy_hash: account.policy_hash,
+            amount: transfer.as_ref().map_or(0, |transfer| transfer.amount),
+            destination_hash: transfer.as_ref().map_or([0; HASH_LEN], |transfer| destination_hash(&transfer.destination_ata)),
+            matched_rule,
+            matched_entry,
+        }
+    }
+}
+
+/// The context to emit for an allowed execution, if the account samples it
+///
+/// Call once `account` has executed, so its nonce is the transaction's.
+pub fn sample_allowed(account: &AttestaAccount, account_address: &Pubkey, transaction_data: &[u8]) -> Option<AllowedContext> {
+    is_sampled(account_address, account.nonce, account.settings.log_allowed_sample_rate)
+        .then(|| AllowedContext::new(account, transaction_data))
+}
+
+/// The first rule, and its entry, listing the transfer's mint or destination
+fn matched_branch(account: &AttestaAccount, transfer: &TokenTransfer) -> Option<(u8, u8)> {
+    let rules = account
+        .policies()
+        .into_iter()
+        .filter_map(|bytes| Policy::from_bytes(bytes).ok())
+        .flat_map(|policy| policy.rules().unwrap_or_else(|| vec![policy]));
+    rules.enumerate().find_map(|(rule, policy)| {
+        let entry = matched_entry(&policy, transfer)?;
+        Some((u8::try_from(rule).ok()?, u8::try_from(entry).ok()?))
+    })
+}
+
+fn matched_entry(policy: &Policy, transfer: &TokenTransfer) -> Option<usize> {
+    let destination = &transfer.destination_ata;
+    match policy.policy_type {
+        PolicyType::SpendingLimit | PolicyType::DailyLimit => {
+            policy.mint_limits()?.limits.iter().position(|limit| limit.mint == transfer.mint)
+        }
+        PolicyType::DestinationAllowlist => policy.config.chunks_exact(32).position(|key| key == destination.as_ref()),
+        PolicyType::CredentialBinding => {
+            policy.credential_bindings()?.bindings.iter().position(|binding| binding.destinations.contains(destination))
+        }
+        PolicyType::PerDestinationLimit => {
+            policy.destination_limits()?.limits.iter().position(|limit| limit.destination == *destination)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use recovery::{Amount, MintLimit, MintLimits};
+
+    fn transfer(mint: Pubkey, destination_ata: Pubkey) -> TokenTransfer {
+        TokenTransfer { mint, amount: 250, decimals: 6, destination_ata }
+    }
+
+    fn account_with(policies: Vec<Policy>) -> AttestaAccount {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [1; 64], vec![1], vec![], 100);
+        account.set_policies(policies.iter().map(|policy| policy.to_bytes().unwrap()).collect());
+        account
+    }
+
+    #[test]
+    fn test_same_nonce_same_decision() {
+        let address = Pubkey::new_unique();
+        for nonce in 0..200 {
+            let first = is_sampled(&address, nonce, 30);
+            assert!((0..5).all(|_| is_sampled(&address, nonce, 30) == first));
+        }
+        // A nonce in the sample at one rate stays in it at any higher rate
+        for nonce in 0..200 {
+            if is_sampled(&address, nonce, 10) {
+                assert!(is_sampled(&address, nonce, 11));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rate_is_honored_over_many_nonces() {
+        let address = Pubkey::new_unique();
+        let count = |rate| (1..=10_000u64).filter(|&nonce| is_sampled(&address, nonce, rate)).count();
+        assert_eq!(count(0), 0);
+        assert_eq!(count(MAX_SAMPLE_RATE), 10_000);
+        for rate in [1u8, 10, 25, 50, 90] {
+            // Binomial, so comfortably inside a few standard deviations
+            let expected = 100 * rate as usize;
+            let sampled = count(rate);
+            assert!(sampled.abs_diff(expected) < 300, "rate {}: {} of 10000", rate, sampled);
+        }
+    }
+
+    #[test]
+    fn test_accounts_sample_independently() {
+        let [a, b] = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let differ = (1..=1_000u64).filter(|&nonce| is_sampled(&a, nonce, 50) != is_sampled(&b, nonce, 50)).count();
+        assert!((300..700).contains(&differ), "{}", differ);
+    }
+
+    #[test]
+    fn test_context_reports_the_matching_entry() {
+        let mint = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let limit = Policy::spending_limit(Amount::from_lamports(1_000)).with_mint_limits(MintLimits {
+            allow_unlisted: false,
+            limits: vec![
+                MintLimit { mint: Pubkey::new_unique(), max_amount: 500, decimals: 6 },
+                MintLimit { mint, max_amount: 500, decimals: 6 },
+            ],
+        });
+        let allowlist = Policy::destination_allowlist(vec![Pubkey::new_unique(), destination]);
+        let composite = Policy::composite(vec![Policy::time_locked(0), allowlist]);
+        let data = transfer(mint, destination).to_transaction_data();
+
+        // The composite's second rule lists the destination
+        let account = account_with(vec![composite.clone()]);
+        let context = AllowedContext::new(&account, &data);
+        assert_eq!((context.matched_rule, context.matched_entry), (1, 1));
+        assert_eq!(context.amount, 250);
+        assert_eq!(context.destination_hash, destination_hash(&destination));
+        assert_eq!(context.policy_hash, account.policy_hash);
+
+        // Earlier policies come first: the mint limit is rule 0
+        let account = account_with(vec![limit, composite]);
+        let context = AllowedContext::new(&account, &data);
+        assert_eq!((context.matched_rule, context.matched_entry), (0, 1));
+
+        // Nothing lists anything for transactions that aren't transfers
+        let context = AllowedContext::new(&account, &[7; 40]);
+        assert_eq!((context.matched_rule, context.matched_entry, context.amount), (NO_MATCH, NO_MATCH, 0));
+        assert_eq!(context.destination_hash, [0; HASH_LEN]);
+    }
+
+    #[test]
+    fn test_sampling_is_off_by_default() {
+        let mut account = account_with(vec![]);
+        let address = Pubkey::new_unique();
+        account.nonce = 5;
+        assert_eq!(sample_allowed(&account, &address, &[]), None);
+
+        account.settings.log_allowed_sample_rate = MAX_SAMPLE_RATE;
+        let context = sample_allowed(&account, &address, &[]).unwrap();
+        assert_eq!(context.policy_hash, account.policy_hash);
+    }
+}
@@ -0,0 +1,355 @@
+//! Transactions signed now and executed later
+//!
+//! The owner signs a transaction together with a window (`executable_after`,
+//! and optionally `executable_before`) with `SCHEDULE_ACTION`. The program
+//! keeps it in a schedule PDA, and once the window opens anyone may execute
+//! it: the signature was checked when it was scheduled, and the account's
+//! settings and policies are checked again at execution, against the
+//! account as it is then. A policy tightened in the meantime applies.
+//!
+//! Scheduling uses up the nonce the owner signed, and the schedule PDA is
+//! keyed by it, so that nonce is the schedule's reservation: executing or
+//! cancelling closes the PDA, and the same schedule can't run twice.
+//! Executing doesn't move the account's nonce, so a keeper running a
+//! schedule never invalidates a proof the owner has signed in the meantime.
+
+use attesta_types::time::{validate_timestamp, TimeError, MAX_FUTURE_SECONDS, MAX_PAST_SECONDS};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use recovery::credential_id_hash;
+use crate::account::AttestaAccount;
+use crate::auth::{authorize_action, resolve_signing_key};
+use crate::execute::{evaluate_policy, record_transfer, transaction_message_hash, DenyReason, PolicyResult, TransactionRequestError};
+use crate::vault::announcement_delay;
+
+/// Action name a passkey signs, over `schedule_payload`, to schedule a transaction
+pub const SCHEDULE_ACTION: &[u8] = b"schedule_transaction";
+
+/// Action name a passkey signs, over the schedule's address, to cancel it
+pub const SCHEDULE_CANCEL_ACTION: &[u8] = b"cancel_scheduled";
+
+/// Errors from scheduling, executing or cancelling a scheduled transaction
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ScheduleError {
+    #[error("Schedule signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The window closes before it opens")]
+    InvalidWindow,
+
+    #[error("Invalid window: {0}")]
+    InvalidTimestamp(#[from] TimeError),
+
+    #[error("{0}")]
+    InvalidTransaction(#[from] TransactionRequestError),
+
+    #[error("The scheduled transaction can't execute before {executable_after}")]
+    TooEarly { executable_after: i64 },
+
+    #[error("The scheduled transaction's window closed at {executable_before}")]
+    Expired { executable_before: i64 },
+
+    #[error("The passkey that scheduled the transaction is no longer on the account")]
+    SignerRemoved,
+
+    #[error("The account is a sub-account and its parent wasn't provided")]
+    MissingParent,
+
+    #[error("Invalid scheduled transaction data")]
+    InvalidData,
+}
+
+/// A transaction waiting in a schedule PDA for its window to open
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledTransaction {
+    /// The transaction to execute, as `execute` takes it
+    pub transaction_data: Vec<u8>,
+
+    /// Unix timestamp before which it can't execute
+    pub executable_after: i64,
+
+    /// Unix timestamp from which it can't execute, if the window closes
+    pub executable_before: Option<i64>,
+
+    /// The nonce the owner signed when scheduling (the PDA's seed)
+    pub nonce: u64,
+
+    /// The credential ID that signed it, as the authenticator reported it
+    /// (it was already public in the scheduling transaction)
+    pub credential_id: Vec<u8>,
+}
+
+impl ScheduledTransaction {
+    /// Bytes the scheduled transaction takes when serialized
+    pub fn serialized_size(transaction_data_len: usize, credential_id_len: usize) -> usize {
+        4 + transaction_data_len // transaction_data
+            + 8                  // executable_after
+            + 1 + 8              // executable_before
+            + 8                  // nonce
+            + 4 + credential_id_len
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ScheduleError> {
+        borsh::to_vec(self).map_err(|_| ScheduleError::InvalidData)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ScheduleError> {
+        borsh::from_slice(data).map_err(|_| ScheduleError::InvalidData)
+    }
+
+    /// Checks that `now` is inside the window
+    pub fn check_window(&self, now: i64) -> Result<(), ScheduleError> {
+        if now < self.executable_after {
+            return Err(ScheduleError::TooEarly { executable_after: self.executable_after });
+        }
+        match self.executable_before {
+            Some(executable_before) if now >= executable_before => Err(ScheduleError::Expired { executable_before }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The payload a passkey signs with `SCHEDULE_ACTION`
+///
+/// Binds the transaction and its window, so neither can be changed after signing.
+pub fn schedule_payload(transaction_data: &[u8], executable_after: i64, executable_before: Option<i64>) -> Vec<u8> {
+    let mut payload = transaction_message_hash(transaction_data).to_vec();
+    payload.extend_from_slice(&executable_after.to_le_bytes());
+    match executable_before {
+        Some(executable_before) => {
+            payload.push(1);
+            payload.extend_from_slice(&executable_before.to_le_bytes());
+        }
+        None => payload.push(0),
+    }
+    payload
+}
+
+/// Checks the owner's signature and returns the transaction to store
+///
+/// Uses up `nonce`. Nothing about the account's policy is checked here:
+/// that happens at execution.
+///
+/// # Parameters
+/// - `webauthn_sig`: The owner's signature over `SCHEDULE_ACTION` for
+///   `schedule_payload(transaction_data, executable_after, executable_before)`
+/// - `now`: The current time; both ends of the window must be within
+///   `MAX_FUTURE_SECONDS` after it and `MAX_PAST_SECONDS` before it
+pub fn schedule_transaction(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    transaction_data: Vec<u8>,
+    executable_after: i64,
+    executable_before: Option<i64>,
+    now: i64,
+) -> Result<ScheduledTransaction, ScheduleError> {
+    if executable_before.is_some_and(|before| before <= executable_after) {
+        return Err(ScheduleError::InvalidWindow);
+    }
+    for timestamp in [Some(executable_after), executable_before].into_iter().flatten() {
+        validate_timestamp(timestamp, now, MAX_FUTURE_SECONDS, MAX_PAST_SECONDS)?;
+    }
+    account.settings.check_transaction_data_len(transaction_data.len())?;
+
+    let payload = schedule_payload(&transaction_data, executable_after, executable_before);
+    let credential_id = webauthn_sig.credential_id.clone();
+    authorize_action(account, webauthn_sig, nonce, SCHEDULE_ACTION, &payload)?;
+
+    Ok(ScheduledTransaction { transaction_data, executable_after, executable_before, nonce, credential_id })
+}
+
+/// Checks whether a scheduled transaction may execute now
+///
+/// Anyone may call this once the window opens. The transaction goes through
+/// the account's settings and policies as they are at `now`, as if it had
+/// just been signed by the passkey that scheduled it.
+///
+/// # Returns
+/// - `Ok(PolicyResult::Allowed)` if it executes; `updated_at` becomes `now`
+/// - `Ok(PolicyResult::Denied(reason))` or `Ok(PolicyResult::RequiresApproval)`
+///   if the account no longer allows it; nothing changes
+/// - `Err(ScheduleError::TooEarly)` or `Err(ScheduleError::Expired)` outside the window
+/// - `Err(ScheduleError::SignerRemoved)` if its passkey has been removed since
+pub fn execute_scheduled(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
+    scheduled: &ScheduledTransaction,
+    now: i64,
+) -> Result<PolicyResult, ScheduleError> {
+    scheduled.check_window(now)?;
+    resolve_signing_key(account, &scheduled.credential_id).map_err(|_| ScheduleError::SignerRemoved)?;
+
+    if account.settings.lockout_threshold > 0 && account.is_locked_out(now) {
+        return Ok(PolicyResult::Denied(DenyReason::LockedOut { until: account.locked_until }));
+    }
+
+    let signer = credential_id_hash(&scheduled.credential_id);
+    let mut result = evaluate_policy(account, account_address, parent, Some(signer), &scheduled.transaction_data, now)
+        .map_err(|_| ScheduleError::MissingParent)?;
+    // Only `execute` can announce, and a schedule's window isn't the vault's delay
+    if result == PolicyResult::Allowed && announcement_delay(account, parent, &scheduled.transaction_data).is_some() {
+        result = PolicyResult::Denied(DenyReason::Policy);
+    }
+    if result == PolicyResult::Allowed {
+        // Not a sign of life: the owner signed this before the inactivity clock
+        // it would reset, so `last_execution_at` stays
+        account.updated_at = now;
+        record_transfer(account, &scheduled.transaction_data, now);
+    }
+    Ok(result)
+}
+
+/// Checks the owner's signature to cancel the schedule at `schedule_address`
+///
+/// Uses up `nonce`; the caller closes the PDA.
+///
+/// # Parameters
+/// - `webauthn_sig`: The owner's signature over `SCHEDULE_CANCEL_ACTION` for
+///   the schedule PDA's address
+pub fn cancel_scheduled(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    schedule_address: &Pubkey,
+) -> Result<(), ScheduleError> {
+    authorize_action(account, webauthn_sig, nonce, SCHEDULE_CANCEL_ACTION, schedule_address.as_ref())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::policies::MIN_POLICY_TIMESTAMP;
+    use recovery::{Amount, MintLimit, MintLimits, Policy};
+    use crate::auth::action_message_hash;
+    use crate::token::TokenTransfer;
+
+    const AFTER: i64 = MIN_POLICY_TIMESTAMP + 1_000;
+    const BEFORE: i64 = AFTER + 3_600;
+    const NOW: i64 = AFTER - 60;
+
+    fn sign(passkey: &mut TestPasskey, account: &AttestaAccount, action: &[u8], payload: &[u8]) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let challenge = compute_challenge(&account.owner, nonce, &action_message_hash(action, payload));
+        (passkey.sign(&challenge), nonce)
+    }
+
+    /// An account limited to 100 of `mint` per transfer, and a transfer of 100 scheduled in it
+    fn setup() -> (AttestaAccount, TestPasskey, ScheduledTransaction, Pubkey) {
+        let mut owner = TestPasskey::new(1);
+        let mint = Pubkey::new_unique();
+        let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint, max_amount: 100, decimals: 6 }],
+        });
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), owner.public_key(), owner.credential_id(), policy.to_bytes().unwrap(), 100);
+
+        let data = TokenTransfer { mint, amount: 100, decimals: 6, destination_ata: Pubkey::new_unique() }.to_transaction_data();
+        let (sig, nonce) = sign(&mut owner, &account, SCHEDULE_ACTION, &schedule_payload(&data, AFTER, Some(BEFORE)));
+        let scheduled = schedule_transaction(&mut account, sig, nonce, data, AFTER, Some(BEFORE), NOW).unwrap();
+
+        (account, owner, scheduled, mint)
+    }
+
+    #[test]
+    fn test_schedule_uses_the_nonce() {
+        let (mut account, mut owner, scheduled, _) = setup();
+        assert_eq!(scheduled.nonce, 1);
+        assert_eq!(account.nonce, 1);
+        assert_eq!(ScheduledTransaction::from_bytes(&scheduled.to_bytes().unwrap()), Ok(scheduled.clone()));
+        assert_eq!(
+            scheduled.to_bytes().unwrap().len(),
+            ScheduledTransaction::serialized_size(scheduled.transaction_data.len(), scheduled.credential_id.len())
+        );
+
+        // The window is part of what was signed
+        let data = scheduled.transaction_data.clone();
+        let (sig, nonce) = sign(&mut owner, &account, SCHEDULE_ACTION, &schedule_payload(&data, AFTER, Some(BEFORE)));
+        assert!(matches!(
+            schedule_transaction(&mut account, sig, nonce, data.clone(), AFTER, None, NOW),
+            Err(ScheduleError::Unauthorized(_))
+        ));
+
+        let (sig, nonce) = sign(&mut owner, &account, SCHEDULE_ACTION, &schedule_payload(&data, AFTER, Some(AFTER)));
+        assert_eq!(schedule_transaction(&mut account, sig, nonce, data.clone(), AFTER, Some(AFTER), NOW), Err(ScheduleError::InvalidWindow));
+        assert_eq!(account.nonce, 1);
+
+        // Both ends of the window are bounded, with no way around it
+        let far = NOW + MAX_FUTURE_SECONDS + 1;
+        let cases = [
+            (far, None, TimeError::TooFarInFuture { timestamp: far, latest: NOW + MAX_FUTURE_SECONDS }),
+            (AFTER, Some(far), TimeError::TooFarInFuture { timestamp: far, latest: NOW + MAX_FUTURE_SECONDS }),
+            (-1, Some(AFTER), TimeError::Negative(-1)),
+            (0, Some(AFTER), TimeError::TooFarInPast { timestamp: 0, earliest: NOW - MAX_PAST_SECONDS }),
+        ];
+        for (after, before, expected) in cases {
+            let (sig, nonce) = sign(&mut owner, &account, SCHEDULE_ACTION, &schedule_payload(&data, after, before));
+            assert_eq!(
+                schedule_transaction(&mut account, sig, nonce, data.clone(), after, before, NOW),
+                Err(ScheduleError::InvalidTimestamp(expected))
+            );
+        }
+        assert_eq!(account.nonce, 1);
+    }
+
+    #[test]
+    fn test_execute_only_inside_the_window() {
+        let (mut account, _, scheduled, _) = setup();
+        let address = Pubkey::new_unique();
+
+        assert_eq!(
+            execute_scheduled(&mut account, &address, None, &scheduled, AFTER - 1),
+            Err(ScheduleError::TooEarly { executable_after: AFTER })
+        );
+        assert_eq!(
+            execute_scheduled(&mut account, &address, None, &scheduled, BEFORE),
+            Err(ScheduleError::Expired { executable_before: BEFORE })
+        );
+        assert_eq!(execute_scheduled(&mut account, &address, None, &scheduled, AFTER), Ok(PolicyResult::Allowed));
+        assert_eq!(account.updated_at, AFTER);
+        // Executing doesn't invalidate proofs signed since scheduling
+        assert_eq!(account.nonce, 1);
+    }
+
+    #[test]
+    fn test_policy_is_checked_at_execution() {
+        let (mut account, mut owner, scheduled, mint) = setup();
+        let address = Pubkey::new_unique();
+
+        // After scheduling, the owner halves the limit
+        let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint, max_amount: 50, decimals: 6 }],
+        });
+        account.policy = policy.to_bytes().unwrap();
+        assert_eq!(
+            execute_scheduled(&mut account, &address, None, &scheduled, AFTER),
+            Ok(PolicyResult::Denied(DenyReason::Policy))
+        );
+        assert_eq!(account.updated_at, 100);
+
+        // A schedule from a passkey that has since been replaced doesn't run
+        account.passkey_public_key = TestPasskey::new(2).public_key();
+        account.credential_id = TestPasskey::new(2).credential_id();
+        assert_eq!(execute_scheduled(&mut account, &address, None, &scheduled, AFTER), Err(ScheduleError::SignerRemoved));
+
+        // Cancelling is signed over the schedule's address
+        account.passkey_public_key = owner.public_key();
+        account.credential_id = owner.credential_id();
+        let schedule_address = Pubkey::new_unique();
+        let (sig, nonce) = sign(&mut owner, &account, SCHEDULE_CANCEL_ACTION, Pubkey::new_unique().as_ref());
+        assert!(matches!(
+            cancel_scheduled(&mut account, sig, nonce, &schedule_address),
+            Err(ScheduleError::Unauthorized(_))
+        ));
+        let (sig, nonce) = sign(&mut owner, &account, SCHEDULE_CANCEL_ACTION, schedule_address.as_ref());
+        assert_eq!(cancel_scheduled(&mut account, sig, nonce, &schedule_address), Ok(()));
+        assert_eq!(account.nonce, 2);
+    }
+}
@@ -0,0 +1,476 @@
+//! How `AccountSettings` are stored
+//!
+//! Settings used to be stored one trailing field at a time, each added
+//! after the last, so every new option grew every account. They're now kept
+//! in one compact section at the end of the account:
+//!
+//! - `settings_flags`, a `u32` with one bit per on/off option (see `flags`).
+//!   Bits this version doesn't know fail the read: each is a check, and one
+//!   silently dropped would stop being enforced.
+//! - `settings_ext`, a list of `tag`, `u16` length, value entries for the
+//!   options that have a value (see `tags`), in increasing tag order. Only
+//!   options that differ from their default are written.
+//!
+//! A new option takes a flag bit or a tag instead of a new field. Tags this
+//! version doesn't know are skipped on read, so an older reader still loads
+//! an account a newer program wrote, unless the tag has `CRITICAL_TAG` set:
+//! that marks an option the reader can't ignore without weakening the
+//! account, and fails the read instead. Unknown tags are never written.
+//!
+//! `AccountSettings` stays the typed view; nothing outside this module
+//! deals with the encoding. Accounts stored before the section existed read
+//! their settings from the old trailing fields, and are written in the
+//! compact form the next time they're saved.
+
+use attesta_types::consts::{AAGUID_LEN, HASH_LEN, PUBKEY_LEN};
+use core_crypto::{RelyingParty, WebAuthnVerificationProfile};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use crate::account::AccountSettings;
+use crate::auth_mode::AuthMode;
+
+/// Bits of `settings_flags`
+pub mod flags {
+    /// `AccountSettings::reject_zero_amount`
+    pub const REJECT_ZERO_AMOUNT: u32 = 1 << 0;
+
+    /// `AccountSettings::reject_self_transfer`
+    pub const REJECT_SELF_TRANSFER: u32 = 1 << 1;
+
+    /// `AccountSettings::auth_mode_locked`
+    pub const AUTH_MODE_LOCKED: u32 = 1 << 2;
+
+    /// Every bit this version knows
+    pub const KNOWN: u32 = REJECT_ZERO_AMOUNT | REJECT_SELF_TRANSFER | AUTH_MODE_LOCKED;
+}
+
+/// Tags of `settings_ext` entries
+pub mod tags {
+    /// `max_transaction_data_len`: a little-endian `u16`
+    pub const MAX_TRANSACTION_DATA_LEN: u8 = 0x01;
+
+    /// `lockout_threshold`: one byte
+    pub const LOCKOUT_THRESHOLD: u8 = 0x02;
+
+    /// `aaguid_allowlist`: the AAGUIDs back to back
+    pub const AAGUID_ALLOWLIST: u8 = 0x03;
+
+    /// `pinned_program_version`: the version hash
+    pub const PINNED_PROGRAM_VERSION: u8 = 0x04;
+
+    /// `webauthn_profile`: its bits
+    pub const WEBAUTHN_PROFILE: u8 = 0x05;
+
+    /// `relying_party`: the RP ID hash, then the origin hash
+    pub const RELYING_PARTY: u8 = 0x06;
+
+    /// `auth_mode`: one byte
+    pub const AUTH_MODE: u8 = 0x07;
+
+    /// `authorized_executors`: the addresses back to back
+    pub const AUTHORIZED_EXECUTORS: u8 = 0x08;
+
+    /// `log_allowed_sample_rate`: one byte
+    pub const LOG_ALLOWED_SAMPLE_RATE: u8 = 0x09;
+
+    /// `recovery_allowance`: a little-endian `u64`
+    pub const RECOVERY_ALLOWANCE: u8 = 0x0a;
+
+    /// Every tag this version knows, in order
+    pub const KNOWN: &[u8] = &[
+        MAX_TRANSACTION_DATA_LEN,
+        LOCKOUT_THRESHOLD,
+        AAGUID_ALLOWLIST,
+        PINNED_PROGRAM_VERSION,
+        WEBAUTHN_PROFILE,
+        RELYING_PARTY,
+        AUTH_MODE,
+        AUTHORIZED_EXECUTORS,
+        LOG_ALLOWED_SAMPLE_RATE,
+        RECOVERY_ALLOWANCE,
+    ];
+}
+
+/// Tag bit marking an option a reader must understand to load the account
+pub const CRITICAL_TAG: u8 = 0x80;
+
+/// Bytes in front of each `settings_ext` value: the tag and a `u16` length
+pub const TLV_HEADER_LEN: usize = 3;
+
+/// Errors from reading or writing the compact settings
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SettingsEncodingError {
+    #[error("Unknown settings flags {0:#x}")]
+    UnknownFlags(u32),
+
+    #[error("Settings entry is cut off")]
+    Truncated,
+
+    #[error("Settings tag {0:#04x} is out of order or repeated")]
+    OutOfOrder(u8),
+
+    #[error("Settings tag {tag:#04x} can't hold {len} bytes")]
+    InvalidLength { tag: u8, len: usize },
+
+    #[error("Settings tag {0:#04x} holds a value this version doesn't accept")]
+    InvalidValue(u8),
+
+    #[error("Unknown settings tag {0:#04x}")]
+    UnknownTag(u8),
+}
+
+/// The `settings_ext` entries, kept sorted by tag
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SettingsExt {
+    entries: Vec<(u8, Vec<u8>)>,
+}
+
+impl SettingsExt {
+    /// Parses stored entries
+    ///
+    /// Tags must be strictly increasing. Unknown tags are skipped unless
+    /// they're critical; known tags must hold a value of the right length.
+    pub fn parse(mut bytes: &[u8]) -> Result<Self, SettingsEncodingError> {
+        let mut entries: Vec<(u8, Vec<u8>)> = Vec::new();
+        let mut last_tag = None;
+        while let Some((&tag, rest)) = bytes.split_first() {
+            let (len, rest) = rest.split_first_chunk::<2>().ok_or(SettingsEncodingError::Truncated)?;
+            let len = u16::from_le_bytes(*len) as usize;
+            let value = rest.get(..len).ok_or(SettingsEncodingError::Truncated)?;
+            bytes = rest.get(len..).unwrap_or_default();
+
+            if last_tag.is_some_and(|last| tag <= last) {
+                return Err(SettingsEncodingError::OutOfOrder(tag));
+            }
+            last_tag = Some(tag);
+            if tags::KNOWN.contains(&tag) {
+                check_length(tag, len)?;
+                entries.push((tag, value.to_vec()));
+            } else if tag & CRITICAL_TAG != 0 {
+                return Err(SettingsEncodingError::UnknownTag(tag));
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// The value stored under `tag`
+    pub fn get(&self, tag: u8) -> Option<&[u8]> {
+        self.entries.iter().find(|(entry_tag, _)| *entry_tag == tag).map(|(_, value)| value.as_slice())
+    }
+
+    /// Stores `value` under `tag`, replacing what was there
+    ///
+    /// Only known tags, with a value of the right length, can be written.
+    pub fn set(&mut self, tag: u8, value: Vec<u8>) -> Result<(), SettingsEncodingError> {
+        if !tags::KNOWN.contains(&tag) {
+            return Err(SettingsEncodingError::UnknownTag(tag));
+        }
+        check_length(tag, value.len())?;
+        match self.entries.binary_search_by_key(&tag, |(entry_tag, _)| *entry_tag) {
+            Ok(i) => {
+                if let Some(entry) = self.entries.get_mut(i) {
+                    entry.1 = value;
+                }
+            }
+            Err(i) => self.entries.insert(i, (tag, value)),
+        }
+        Ok(())
+    }
+
+    /// Drops the entry under `tag`, leaving that option at its default
+    pub fn remove(&mut self, tag: u8) {
+        self.entries.retain(|(entry_tag, _)| *entry_tag != tag);
+    }
+
+    /// The entries as stored
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.serialized_size());
+        for (tag, value) in &self.entries {
+            bytes.push(*tag);
+            // `check_length` keeps every value under `u16::MAX`
+            bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(value);
+        }
+        bytes
+    }
+
+    /// Length of `to_bytes()`
+    pub fn serialized_size(&self) -> usize {
+        self.entries.iter().map(|(_, value)| TLV_HEADER_LEN + value.len()).sum()
+    }
+}
+
+/// Checks that a value of `len` bytes fits a known `tag`
+fn check_length(tag: u8, len: usize) -> Result<(), SettingsEncodingError> {
+    let fits = match tag {
+        tags::MAX_TRANSACTION_DATA_LEN => len == 2,
+        tags::LOCKOUT_THRESHOLD | tags::WEBAUTHN_PROFILE | tags::AUTH_MODE | tags::LOG_ALLOWED_SAMPLE_RATE => len == 1,
+        tags::AAGUID_ALLOWLIST => len.is_multiple_of(AAGUID_LEN),
+        tags::PINNED_PROGRAM_VERSION => len == HASH_LEN,
+        tags::RELYING_PARTY => len == 2 * HASH_LEN,
+        tags::RECOVERY_ALLOWANCE => len == 8,
+        tags::AUTHORIZED_EXECUTORS => len.is_multiple_of(PUBKEY_LEN),
+        _ => true,
+    };
+    if !fits || len > u16::MAX as usize {
+        return Err(SettingsEncodingError::InvalidLength { tag, len });
+    }
+    Ok(())
+}
+
+impl AccountSettings {
+    /// The on/off options as `settings_flags`
+    pub fn flags(&self) -> u32 {
+        let mut bits = 0;
+        for (set, flag) in [
+            (self.reject_zero_amount, flags::REJECT_ZERO_AMOUNT),
+            (self.reject_self_transfer, flags::REJECT_SELF_TRANSFER),
+            (self.auth_mode_locked, flags::AUTH_MODE_LOCKED),
+        ] {
+            if set {
+                bits |= flag;
+            }
+        }
+        bits
+    }
+
+    /// The options with a value, as `settings_ext` entries
+    ///
+    /// Options at their default are left out.
+    pub fn ext(&self) -> SettingsExt {
+        let mut entries = Vec::new();
+        if self.max_transaction_data_len != 0 {
+            entries.push((tags::MAX_TRANSACTION_DATA_LEN, self.max_transaction_data_len.to_le_bytes().to_vec()));
+        }
+        if self.lockout_threshold != 0 {
+            entries.push((tags::LOCKOUT_THRESHOLD, vec![self.lockout_threshold]));
+        }
+        if !self.aaguid_allowlist.is_empty() {
+            entries.push((tags::AAGUID_ALLOWLIST, self.aaguid_allowlist.concat()));
+        }
+        if let Some(version) = self.pinned_program_version {
+            entries.push((tags::PINNED_PROGRAM_VERSION, version.to_vec()));
+        }
+        if self.webauthn_profile != WebAuthnVerificationProfile::legacy() {
+            entries.push((tags::WEBAUTHN_PROFILE, vec![self.webauthn_profile.to_bits()]));
+        }
+        if let Some(party) = &self.relying_party {
+            entries.push((tags::RELYING_PARTY, [party.rp_id_hash, party.origin_hash].concat()));
+        }
+        if self.auth_mode != AuthMode::PasskeyOnly {
+            entries.push((tags::AUTH_MODE, vec![self.auth_mode as u8]));
+        }
+        if !self.authorized_executors.is_empty() {
+            let executors: Vec<u8> = self.authorized_executors.iter().flat_map(|executor| executor.to_bytes()).collect();
+            entries.push((tags::AUTHORIZED_EXECUTORS, executors));
+        }
+        if self.log_allowed_sample_rate != 0 {
+            entries.push((tags::LOG_ALLOWED_SAMPLE_RATE, vec![self.log_allowed_sample_rate]));
+        }
+        if self.recovery_allowance != 0 {
+            entries.push((tags::RECOVERY_ALLOWANCE, self.recovery_allowance.to_le_bytes().to_vec()));
+        }
+        SettingsExt { entries }
+    }
+
+    /// Reads settings from `settings_flags` and stored `settings_ext` bytes
+    pub fn from_compact(settings_flags: u32, ext: &[u8]) -> Result<Self, SettingsEncodingError> {
+        let unknown = settings_flags & !flags::KNOWN;
+        if unknown != 0 {
+            return Err(SettingsEncodingError::UnknownFlags(unknown));
+        }
+        let ext = SettingsExt::parse(ext)?;
+        let byte = |tag| ext.get(tag).and_then(|value| value.first().copied());
+
+        let mut settings = AccountSettings {
+            reject_zero_amount: settings_flags & flags::REJECT_ZERO_AMOUNT != 0,
+            reject_self_transfer: settings_flags & flags::REJECT_SELF_TRANSFER != 0,
+            auth_mode_locked: settings_flags & flags::AUTH_MODE_LOCKED != 0,
+            lockout_threshold: byte(tags::LOCKOUT_THRESHOLD).unwrap_or_default(),
+            log_allowed_sample_rate: byte(tags::LOG_ALLOWED_SAMPLE_RATE).unwrap_or_default(),
+            ..AccountSettings::default()
+        };
+        if let Some(len) = ext.get(tags::MAX_TRANSACTION_DATA_LEN).and_then(|value| value.try_into().ok()) {
+            settings.max_transaction_data_len = u16::from_le_bytes(len);
+        }
+        if let Some(allowance) = ext.get(tags::RECOVERY_ALLOWANCE).and_then(|value| value.try_into().ok()) {
+            settings.recovery_allowance = u64::from_le_bytes(allowance);
+        }
+        if let Some(allowlist) = ext.get(tags::AAGUID_ALLOWLIST) {
+            settings.aaguid_allowlist =
+                allowlist.chunks_exact(AAGUID_LEN).filter_map(|aaguid| aaguid.try_into().ok()).collect();
+        }
+        settings.pinned_program_version = ext.get(tags::PINNED_PROGRAM_VERSION).and_then(|version| version.try_into().ok());
+        if let Some(bits) = byte(tags::WEBAUTHN_PROFILE) {
+            // A check this version doesn't know can't be dropped silently
+            settings.webauthn_profile = WebAuthnVerificationProfile::from_bits(bits)
+                .ok_or(SettingsEncodingError::InvalidValue(tags::WEBAUTHN_PROFILE))?;
+        }
+        if let Some((rp_id_hash, origin_hash)) = ext.get(tags::RELYING_PARTY).and_then(|party| party.split_first_chunk::<HASH_LEN>()) {
+            let origin_hash = origin_hash.try_into().map_err(|_| SettingsEncodingError::InvalidValue(tags::RELYING_PARTY))?;
+            settings.relying_party = Some(RelyingParty { rp_id_hash: *rp_id_hash, origin_hash });
+        }
+        if let Some(mode) = byte(tags::AUTH_MODE) {
+            // Reading an unknown mode as a known one could let the wrong signer in
+            settings.auth_mode = AuthMode::from_u8(mode).ok_or(SettingsEncodingError::InvalidValue(tags::AUTH_MODE))?;
+        }
+        if let Some(executors) = ext.get(tags::AUTHORIZED_EXECUTORS) {
+            settings.authorized_executors = executors.chunks_exact(PUBKEY_LEN).filter_map(|key| Pubkey::try_from(key).ok()).collect();
+        }
+        Ok(settings)
+    }
+
+    /// Bytes the compact section takes: the flags, then the entries with a
+    /// `u32` length in front
+    pub fn compact_size(&self) -> usize {
+        4 + 4 + self.ext().serialized_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn full_settings() -> AccountSettings {
+        AccountSettings {
+            reject_zero_amount: true,
+            reject_self_transfer: true,
+            max_transaction_data_len: 512,
+            lockout_threshold: 5,
+            aaguid_allowlist: vec![[1; 16], [2; 16]],
+            pinned_program_version: Some([3; 32]),
+            webauthn_profile: WebAuthnVerificationProfile::strict(),
+            relying_party: Some(RelyingParty::new("wallet.example", "https://wallet.example")),
+            auth_mode: AuthMode::PasskeyOnly,
+            auth_mode_locked: true,
+            authorized_executors: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            log_allowed_sample_rate: 20,
+            recovery_allowance: 1_000,
+        }
+    }
+
+    fn entry(tag: u8, value: &[u8]) -> Vec<u8> {
+        [&[tag][..], &(value.len() as u16).to_le_bytes(), value].concat()
+    }
+
+    #[test]
+    fn test_defaults_take_no_entries() {
+        let settings = AccountSettings::default();
+        assert_eq!(settings.flags(), 0);
+        assert_eq!(settings.ext(), SettingsExt::default());
+        assert_eq!(settings.compact_size(), 8);
+        assert_eq!(AccountSettings::from_compact(0, &[]).unwrap(), settings);
+    }
+
+    #[test]
+    fn test_accessors_round_trip() {
+        let settings = full_settings();
+        assert_eq!(settings.flags(), flags::KNOWN);
+        let ext = settings.ext();
+        assert_eq!(ext.get(tags::LOCKOUT_THRESHOLD), Some(&[5][..]));
+        assert_eq!(ext.get(tags::MAX_TRANSACTION_DATA_LEN), Some(&512u16.to_le_bytes()[..]));
+        assert_eq!(ext.get(tags::AUTH_MODE), None, "passkey-only is the default");
+        assert_eq!(ext.get(tags::RECOVERY_ALLOWANCE), Some(&1_000u64.to_le_bytes()[..]));
+        assert_eq!(AccountSettings::from_compact(settings.flags(), &ext.to_bytes()).unwrap(), settings);
+        assert_eq!(settings.compact_size(), 8 + ext.to_bytes().len());
+
+        let owner_only = AccountSettings { auth_mode: AuthMode::OwnerOnly, ..AccountSettings::default() };
+        let bytes = owner_only.ext().to_bytes();
+        assert_eq!(bytes, entry(tags::AUTH_MODE, &[2]));
+        assert_eq!(AccountSettings::from_compact(0, &bytes).unwrap(), owner_only);
+    }
+
+    #[test]
+    fn test_unknown_tags_are_read_past_but_never_written() {
+        let known = full_settings().ext().to_bytes();
+        let with_unknown = [known.as_slice(), &entry(0x40, &[9; 7])].concat();
+        let ext = SettingsExt::parse(&with_unknown).unwrap();
+        assert_eq!(ext.get(0x40), None);
+        assert_eq!(ext.to_bytes(), known);
+        assert_eq!(AccountSettings::from_compact(0, &with_unknown).unwrap().lockout_threshold, 5);
+
+        // Unless the writer marked them as critical
+        let critical = [known.as_slice(), &entry(0x40 | CRITICAL_TAG, &[])].concat();
+        assert_eq!(SettingsExt::parse(&critical), Err(SettingsEncodingError::UnknownTag(0xc0)));
+
+        let mut ext = SettingsExt::default();
+        assert_eq!(ext.set(0x40, vec![1]), Err(SettingsEncodingError::UnknownTag(0x40)));
+        assert_eq!(ext.set(tags::LOCKOUT_THRESHOLD, vec![1, 2]), Err(SettingsEncodingError::InvalidLength { tag: 2, len: 2 }));
+        ext.set(tags::AUTH_MODE, vec![1]).unwrap();
+        ext.set(tags::LOCKOUT_THRESHOLD, vec![3]).unwrap();
+        ext.set(tags::LOCKOUT_THRESHOLD, vec![4]).unwrap();
+        assert_eq!(ext.to_bytes(), [entry(tags::LOCKOUT_THRESHOLD, &[4]), entry(tags::AUTH_MODE, &[1])].concat());
+        ext.remove(tags::LOCKOUT_THRESHOLD);
+        assert_eq!(ext.to_bytes(), entry(tags::AUTH_MODE, &[1]));
+    }
+
+    #[test]
+    fn test_malformed_entries_are_refused() {
+        let lockout = entry(tags::LOCKOUT_THRESHOLD, &[3]);
+        assert_eq!(SettingsExt::parse(&lockout[..2]), Err(SettingsEncodingError::Truncated));
+        assert_eq!(SettingsExt::parse(&lockout[..3]), Err(SettingsEncodingError::Truncated));
+        assert_eq!(SettingsExt::parse(&[lockout.clone(), lockout.clone()].concat()), Err(SettingsEncodingError::OutOfOrder(2)));
+        let out_of_order = [entry(tags::AUTH_MODE, &[1]), lockout].concat();
+        assert_eq!(SettingsExt::parse(&out_of_order), Err(SettingsEncodingError::OutOfOrder(2)));
+        assert_eq!(
+            SettingsExt::parse(&entry(tags::AAGUID_ALLOWLIST, &[1; 17])),
+            Err(SettingsEncodingError::InvalidLength { tag: tags::AAGUID_ALLOWLIST, len: 17 })
+        );
+
+        assert_eq!(AccountSettings::from_compact(1 << 31, &[]), Err(SettingsEncodingError::UnknownFlags(1 << 31)));
+        assert_eq!(
+            AccountSettings::from_compact(0, &entry(tags::WEBAUTHN_PROFILE, &[1 << 7])),
+            Err(SettingsEncodingError::InvalidValue(tags::WEBAUTHN_PROFILE))
+        );
+        assert_eq!(
+            AccountSettings::from_compact(0, &entry(tags::AUTH_MODE, &[9])),
+            Err(SettingsEncodingError::InvalidValue(tags::AUTH_MODE))
+        );
+    }
+
+    fn arb_settings() -> impl Strategy<Value = AccountSettings> {
+        (
+            any::<(bool, bool, bool)>(),
+            any::<(u16, u8, u8, u64)>(),
+            prop::collection::vec(any::<[u8; 16]>(), 0..8),
+            any::<Option<[u8; 32]>>(),
+            (any::<Option<([u8; 32], [u8; 32])>>(), 0..3u8, prop::collection::vec(any::<[u8; 32]>(), 0..8)),
+        )
+            .prop_map(|((zero, own, locked), (max_len, lockout, rate, allowance), aaguids, version, (party, mode, executors))| {
+                AccountSettings {
+                    reject_zero_amount: zero,
+                    reject_self_transfer: own,
+                    max_transaction_data_len: max_len,
+                    lockout_threshold: lockout,
+                    aaguid_allowlist: aaguids,
+                    pinned_program_version: version,
+                    webauthn_profile: if party.is_some() { WebAuthnVerificationProfile::standard() } else { WebAuthnVerificationProfile::legacy() },
+                    relying_party: party.map(|(rp_id_hash, origin_hash)| RelyingParty { rp_id_hash, origin_hash }),
+                    auth_mode: AuthMode::from_u8(mode).unwrap_or_default(),
+                    auth_mode_locked: locked,
+                    authorized_executors: executors.into_iter().map(Pubkey::new_from_array).collect(),
+                    log_allowed_sample_rate: rate,
+                    recovery_allowance: allowance,
+                }
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_settings_round_trip(settings in arb_settings()) {
+            let ext = settings.ext().to_bytes();
+            prop_assert_eq!(AccountSettings::from_compact(settings.flags(), &ext).unwrap(), settings.clone());
+            prop_assert_eq!(SettingsExt::parse(&ext).unwrap().to_bytes(), ext);
+        }
+
+        #[test]
+        fn prop_parsing_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512), flags in any::<u32>()) {
+            // Whatever it returns, anything it accepts writes back to entries it also accepts
+            if let Ok(ext) = SettingsExt::parse(&bytes) {
+                prop_assert_eq!(SettingsExt::parse(&ext.to_bytes()).unwrap(), ext);
+            }
+            let _ = AccountSettings::from_compact(flags, &bytes);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Predicting what `execute` will do, without touching the account
+//!
+//! Wallet UIs (including ones compiled to `wasm32-unknown-unknown`, with the
+//! `wasm` feature) want to show a user the outcome of a transaction before
+//! they submit it. `simulate_execute` runs exactly the checks the program
+//! runs - signature, lockout, settings, policy and parent policy - against a
+//! copy of the account, at a time the caller supplies.
+
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use crate::account::AttestaAccount;
+use crate::auth::AuthorizationProof;
+use crate::execute::{execute_transaction_at, DenyReason, PolicyResult, TransactionRequest};
+
+/// What submitting a transaction would do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulationOutcome {
+    /// It would execute, moving the account to `new_nonce`
+    Executed { new_nonce: u64 },
+
+    /// It's valid but needs more approvals; nothing would execute
+    RequiresApproval,
+
+    /// The transaction would land but nothing would execute
+    ///
+    /// With lockout on, `DenyReason::AuthenticationFailed` still counts
+    /// against the account when submitted.
+    Denied(DenyReason),
+
+    /// It's a retry of a transaction that already executed with `nonce`
+    AlreadyExecuted { nonce: u64 },
+
+    /// It would be announced, and could execute from `executable_at`
+    Announced { executable_at: i64 },
+
+    /// The transaction would fail with this error
+    Failed(ProgramError),
+}
+
+/// Predicts the outcome of submitting `request` with `proof` at time `now`
+///
+/// `account` (and `parent`, for a sub-account) are left as they are; pass
+/// them as they were last read from the chain.
+///
+/// # Parameters
+/// - `account`: The account the transaction is for
+/// - `account_address`: The account's address, for self-transfer checks
+/// - `parent`: The parent account, required if `account` is a sub-account
+/// - `proof`: The signed proof that would be submitted
+/// - `request`: The transaction that would be executed
+/// - `now`: The Unix timestamp the cluster is expected to have when it lands
+pub fn simulate_execute(
+    account: &AttestaAccount,
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
+    proof: &AuthorizationProof,
+    request: &TransactionRequest,
+    now: i64,
+) -> SimulationOutcome {
+    // The same code path as `execute`, on a copy, so the two can't drift apart
+    let mut scratch = account.clone();
+    match execute_transaction_at(&mut scratch, account_address, parent, proof, &request.transaction_data, now) {
+        Ok(PolicyResult::Allowed) => SimulationOutcome::Executed { new_nonce: scratch.nonce },
+        Ok(PolicyResult::RequiresApproval) => SimulationOutcome::RequiresApproval,
+        Ok(PolicyResult::Denied(reason)) => SimulationOutcome::Denied(reason),
+        Ok(PolicyResult::AlreadyExecuted { nonce }) => SimulationOutcome::AlreadyExecuted { nonce },
+        Ok(PolicyResult::Announced { executable_at }) => SimulationOutcome::Announced { executable_at },
+        Err(e) => SimulationOutcome::Failed(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::policies::MIN_POLICY_TIMESTAMP;
+    use recovery::{Amount, Policy};
+    use crate::account::AccountSettings;
+    use crate::execute::execute_transaction;
+    use crate::token::TokenTransfer;
+
+    fn signed_proof(passkey: &mut TestPasskey, account: &AttestaAccount, nonce: u64, request: &TransactionRequest) -> AuthorizationProof {
+        let message_hash = request.message_hash();
+        let challenge = compute_challenge(&account.owner, nonce, &message_hash);
+        AuthorizationProof::new(passkey.sign(&challenge), nonce, message_hash)
+    }
+
+    fn account_with_policy(passkey: &TestPasskey, policy: Option<Policy>) -> AttestaAccount {
+        let policy = policy.map(|policy| policy.to_bytes().unwrap()).unwrap_or_default();
+        AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), policy, 100)
+    }
+
+    fn transfer(amount: u64) -> TransactionRequest {
+        TransactionRequest::from_token_transfer(TokenTransfer {
+            mint: Pubkey::new_unique(),
+            amount,
+            decimals: 6,
+            destination_ata: Pubkey::new_unique(),
+        })
+    }
+
+    /// Simulates, then executes for real, and checks the two agree
+    fn assert_parity(
+        account: &mut AttestaAccount,
+        proof: &AuthorizationProof,
+        request: &TransactionRequest,
+        now: i64,
+    ) -> SimulationOutcome {
+        let address = Pubkey::new_unique();
+        let before = account.clone();
+        let simulated = simulate_execute(account, &address, None, proof, request, now);
+        assert_eq!(*account, before, "simulation changed the account");
+
+        let executed = execute_transaction_at(account, &address, None, proof, &request.transaction_data, now);
+        let expected = match executed {
+            Ok(PolicyResult::Allowed) => SimulationOutcome::Executed { new_nonce: account.nonce },
+            Ok(PolicyResult::RequiresApproval) => SimulationOutcome::RequiresApproval,
+            Ok(PolicyResult::Denied(reason)) => SimulationOutcome::Denied(reason),
+            Ok(PolicyResult::AlreadyExecuted { nonce }) => SimulationOutcome::AlreadyExecuted { nonce },
+            Ok(PolicyResult::Announced { executable_at }) => SimulationOutcome::Announced { executable_at },
+            Err(e) => SimulationOutcome::Failed(e),
+        };
+        assert_eq!(simulated, expected);
+        simulated
+    }
+
+    #[test]
+    fn test_allowed_transaction_matches_execution() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = account_with_policy(&passkey, None);
+        let request = transfer(5);
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+
+        assert_eq!(assert_parity(&mut account, &proof, &request, 200), SimulationOutcome::Executed { new_nonce: 1 });
+        assert_eq!(account.updated_at, 200);
+
+        // Submitting the same proof again is a replay
+        assert!(matches!(assert_parity(&mut account, &proof, &request, 300), SimulationOutcome::Failed(_)));
+    }
+
+    #[test]
+    fn test_policy_denial_matches_execution() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = account_with_policy(&passkey, Some(Policy::spending_limit(Amount::ZERO)));
+        let request = transfer(5);
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+
+        assert_eq!(assert_parity(&mut account, &proof, &request, 200), SimulationOutcome::Denied(DenyReason::Policy));
+    }
+
+    #[test]
+    fn test_time_lock_uses_the_given_time() {
+        let mut passkey = TestPasskey::new(1);
+        let unlock = MIN_POLICY_TIMESTAMP + 1_000;
+        let mut account = account_with_policy(&passkey, Some(Policy::time_locked(unlock)));
+        let request = transfer(5);
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+
+        assert_eq!(
+            assert_parity(&mut account, &proof, &request, unlock - 1),
+            SimulationOutcome::Denied(DenyReason::Policy)
+        );
+        assert_eq!(assert_parity(&mut account, &proof, &request, unlock), SimulationOutcome::Executed { new_nonce: 1 });
+    }
+
+    #[test]
+    fn test_bad_signature_matches_execution() {
+        let mut passkey = TestPasskey::new(1);
+        let mut other = TestPasskey::new(2);
+        let request = transfer(5);
+
+        let mut account = account_with_policy(&passkey, None);
+        let proof = signed_proof(&mut other, &account, 1, &request);
+        assert!(matches!(assert_parity(&mut account, &proof, &request, 200), SimulationOutcome::Failed(_)));
+
+        // With lockout on, it's a denial that counts against the account
+        account.settings = AccountSettings { lockout_threshold: 1, ..AccountSettings::default() };
+        assert_eq!(
+            assert_parity(&mut account, &proof, &request, 200),
+            SimulationOutcome::Denied(DenyReason::AuthenticationFailed)
+        );
+        let good = signed_proof(&mut passkey, &account, 1, &request);
+        assert!(matches!(
+            assert_parity(&mut account, &good, &request, 200),
+            SimulationOutcome::Denied(DenyReason::LockedOut { .. })
+        ));
+    }
+
+    #[test]
+    fn test_idempotent_retry_matches_execution() {
+        let mut passkey = TestPasskey::new(1);
+        let mut account = account_with_policy(&passkey, None);
+        let request = transfer(5);
+        let proof = signed_proof(&mut passkey, &account, 1, &request).with_idempotency_key([7u8; 16]);
+
+        assert_eq!(assert_parity(&mut account, &proof, &request, 200), SimulationOutcome::Executed { new_nonce: 1 });
+        assert_eq!(
+            assert_parity(&mut account, &proof, &request, 300),
+            SimulationOutcome::AlreadyExecuted { nonce: 1 }
+        );
+    }
+
+    #[test]
+    fn test_native_path_uses_account_time_off_chain() {
+        // Without a clock sysvar, `execute_transaction` runs at the account's last update
+        let mut passkey = TestPasskey::new(1);
+        let mut account = account_with_policy(&passkey, None);
+        let request = transfer(5);
+        let proof = signed_proof(&mut passkey, &account, 1, &request);
+        let address = Pubkey::new_unique();
+
+        let simulated = simulate_execute(&account, &address, None, &proof, &request, account.updated_at);
+        execute_transaction(&mut account, &address, None, &proof, &request.transaction_data).unwrap();
+        assert_eq!(simulated, SimulationOutcome::Executed { new_nonce: account.nonce });
+        assert_eq!(account.updated_at, 100);
+    }
+}
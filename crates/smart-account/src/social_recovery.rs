@@ -0,0 +1,655 @@
+//! Social recovery: replacing a lost primary passkey with guardian approvals
+//!
+//! The other passkeys in the account's registry act as guardians. One of
+//! them starts a recovery for a new passkey, the others approve it, and once
+//! `recovery_threshold` approvals are in, the new passkey can be installed
+//! as primary after `RECOVERY_DELAY_SECONDS`. Any registered passkey can
+//! cancel a pending recovery before then.
+//!
+//! A recovery drill runs the same steps through the same code, but on its
+//! own request slot: reaching the threshold only records `last_drill_at`.
+//! Drill signatures use their own action names, so they can never be
+//! submitted as approvals for a real recovery.
+//!
+//! A pending recovery means the primary passkey may be in someone else's
+//! hands. Until it's finalized or cancelled, executions are held to the
+//! account's `recovery_allowance` (see `within_recovery_allowance`), the
+//! program refuses to change its policies, passkeys or settings, and every
+//! `execute` the primary passkey signs is announced for guardians to see.
+
+use attesta_types::consts::{AAGUID_LEN, BORSH_LEN_PREFIX, P256_PUBKEY_LEN, PUBKEY_LEN};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::Digest;
+use thiserror::Error;
+use core_crypto::{domain_hasher, validate_p256_public_key, CryptoError, Domain, WebAuthnSignature};
+use recovery::multi_passkey::{credential_id_hash, MultiPasskeyError, PasskeyEntry};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_action;
+use crate::token::TokenTransfer;
+
+/// Action name a guardian signs to start a recovery
+pub const RECOVERY_INITIATE_ACTION: &[u8] = b"initiate_recovery";
+
+/// Action name a guardian signs to approve a pending recovery
+pub const RECOVERY_APPROVE_ACTION: &[u8] = b"approve_recovery";
+
+/// Action name a passkey signs to cancel a pending recovery
+pub const RECOVERY_CANCEL_ACTION: &[u8] = b"cancel_recovery";
+
+/// Action name a guardian signs to start a recovery drill
+pub const RECOVERY_DRILL_INITIATE_ACTION: &[u8] = b"initiate_recovery_drill";
+
+/// Action name a guardian signs to approve a recovery drill
+pub const RECOVERY_DRILL_APPROVE_ACTION: &[u8] = b"approve_recovery_drill";
+
+/// How long an approved recovery waits before it can be finalized (48 hours)
+///
+/// Gives the owner time to notice a recovery they didn't start and cancel it.
+pub const RECOVERY_DELAY_SECONDS: i64 = 48 * 60 * 60;
+
+/// Errors from the recovery flow
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RecoveryFlowError {
+    #[error("Recovery signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("Invalid passkey registry: {0}")]
+    Registry(#[from] MultiPasskeyError),
+
+    #[error("Account has no guardians: register more passkeys first")]
+    NoPasskeyRegistry,
+
+    #[error("The new passkey is invalid or already registered")]
+    InvalidNewPasskey,
+
+    #[error("A recovery is already pending")]
+    AlreadyPending,
+
+    #[error("No recovery is pending")]
+    NoPendingRecovery,
+
+    #[error("Not enough approvals")]
+    ThresholdNotMet,
+
+    #[error("Recovery can't be finalized before {ready_at}")]
+    DelayNotElapsed { ready_at: i64 },
+
+    #[error("The new passkey's authenticator model isn't on the account's allowlist")]
+    AuthenticatorNotAllowed,
+}
+
+/// Whether a recovery request replaces the primary passkey or is only a drill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// A real recovery: installs the new passkey once approved and delayed
+    Recovery,
+
+    /// A dry run: records `last_drill_at` once approved, changes no keys
+    Drill,
+}
+
+impl RecoveryMode {
+    /// The action name signed to start a request in this mode
+    pub fn initiate_action(self) -> &'static [u8] {
+        match self {
+            RecoveryMode::Recovery => RECOVERY_INITIATE_ACTION,
+            RecoveryMode::Drill => RECOVERY_DRILL_INITIATE_ACTION,
+        }
+    }
+
+    /// The action name signed to approve a request in this mode
+    pub fn approve_action(self) -> &'static [u8] {
+        match self {
+            RecoveryMode::Recovery => RECOVERY_APPROVE_ACTION,
+            RecoveryMode::Drill => RECOVERY_DRILL_APPROVE_ACTION,
+        }
+    }
+}
+
+/// A request to make a new passkey the account's primary
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct RecoveryRequest {
+    /// The new passkey's public key
+    pub new_public_key: [u8; P256_PUBKEY_LEN],
+
+    /// The new passkey's credential ID (hashed in privacy mode)
+    pub new_credential_id: Vec<u8>,
+
+    /// Credential ID hashes of the passkeys that approved, in order
+    pub approvals: Vec<[u8; 32]>,
+
+    /// When the request was started (Unix timestamp)
+    pub initiated_at: i64,
+
+    /// When the approvals first reached the threshold
+    pub threshold_met_at: Option<i64>,
+
+    /// The authenticator model the new passkey attested, if it did
+    ///
+    /// Stored at the end of the account (see `AttestaAccount`), so pending
+    /// requests written before it existed still read.
+    #[borsh(skip)]
+    pub new_aaguid: Option<[u8; AAGUID_LEN]>,
+}
+
+impl RecoveryRequest {
+    /// Creates a request with no approvals yet
+    pub fn new(new_public_key: [u8; P256_PUBKEY_LEN], new_credential_id: Vec<u8>, initiated_at: i64) -> Self {
+        Self {
+            new_public_key,
+            new_credential_id,
+            approvals: Vec::new(),
+            initiated_at,
+            threshold_met_at: None,
+            new_aaguid: None,
+        }
+    }
+
+    /// The payload guardians sign: binds their approval to this new passkey
+    pub fn request_hash(&self) -> [u8; 32] {
+        recovery_request_hash(&self.new_public_key, &self.new_credential_id)
+    }
+
+    /// Length of the request's Borsh encoding, without serializing
+    ///
+    /// Doesn't include `new_aaguid`.
+    pub fn serialized_size(&self) -> usize {
+        P256_PUBKEY_LEN + BORSH_LEN_PREFIX + self.new_credential_id.len()
+            + BORSH_LEN_PREFIX + self.approvals.len() * PUBKEY_LEN
+            + 8                                          // initiated_at
+            + 1 + self.threshold_met_at.map_or(0, |_| 8)
+    }
+
+    /// When the request can be finalized, once the threshold has been met
+    pub fn ready_at(&self) -> Option<i64> {
+        self.threshold_met_at.map(|at| at.saturating_add(RECOVERY_DELAY_SECONDS))
+    }
+}
+
+/// Hashes a recovery's new passkey into the payload guardians sign
+///
+/// `credential_id` is the form stored on the account (see
+/// `AttestaAccount::credential_lookup_id`).
+pub fn recovery_request_hash(new_public_key: &[u8; P256_PUBKEY_LEN], credential_id: &[u8]) -> [u8; 32] {
+    let mut hasher = domain_hasher(Domain::RecoveryRequest);
+    hasher.update(new_public_key);
+    hasher.update(credential_id);
+    hasher.finalize().into()
+}
+
+/// Where a recovery request stands after an approval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// Approvals that currently count
+    pub approvals: usize,
+
+    /// Approvals needed
+    pub threshold: usize,
+}
+
+impl RecoveryProgress {
+    /// Whether the request has enough approvals
+    pub fn threshold_met(&self) -> bool {
+        self.approvals >= self.threshold
+    }
+}
+
+/// Starts a recovery (or drill) for a new passkey, counting the signer's approval
+///
+/// Starting a real recovery fails while one is pending. A new drill simply
+/// replaces an unfinished one.
+///
+/// # Parameters
+/// - `webauthn_sig`: A guardian's signature over `mode.initiate_action()` for the request hash
+/// - `new_credential_id`: The new passkey's credential ID as the authenticator reports it
+/// - `new_aaguid`: The authenticator model the new passkey attested (see
+///   `verify_registration`), checked against the allowlist at finalization
+#[allow(clippy::too_many_arguments)]
+pub fn initiate_recovery(
+    account: &mut AttestaAccount,
+    mode: RecoveryMode,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    new_public_key: [u8; P256_PUBKEY_LEN],
+    new_credential_id: &[u8],
+    new_aaguid: Option<[u8; AAGUID_LEN]>,
+    now: i64,
+) -> Result<RecoveryProgress, RecoveryFlowError> {
+    let registry = account.passkey_registry()?.ok_or(RecoveryFlowError::NoPasskeyRegistry)?;
+    if mode == RecoveryMode::Recovery && account.pending_recovery.is_some() {
+        return Err(RecoveryFlowError::AlreadyPending);
+    }
+
+    validate_p256_public_key(&new_public_key).map_err(|_| RecoveryFlowError::InvalidNewPasskey)?;
+    if new_credential_id.is_empty() {
+        return Err(RecoveryFlowError::InvalidNewPasskey);
+    }
+    let lookup_id = account.credential_lookup_id(new_credential_id);
+    if registry.find_passkey(&lookup_id).is_some() || registry.is_revoked(&lookup_id) {
+        return Err(RecoveryFlowError::InvalidNewPasskey);
+    }
+
+    let request = RecoveryRequest { new_aaguid, ..RecoveryRequest::new(new_public_key, lookup_id, now) };
+    let request_hash = request.request_hash();
+    let credential_id = webauthn_sig.credential_id.clone();
+    authorize_action(account, webauthn_sig, nonce, mode.initiate_action(), &request_hash)?;
+
+    *pending_request(account, mode) = Some(request);
+    record_approval(account, mode, &credential_id, now)
+}
+
+/// Adds a guardian's approval to the pending recovery (or drill)
+///
+/// # Parameters
+/// - `webauthn_sig`: A guardian's signature over `mode.approve_action()` for the request hash
+pub fn approve_recovery(
+    account: &mut AttestaAccount,
+    mode: RecoveryMode,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    now: i64,
+) -> Result<RecoveryProgress, RecoveryFlowError> {
+    let request_hash = pending_request(account, mode)
+        .as_ref()
+        .ok_or(RecoveryFlowError::NoPendingRecovery)?
+        .request_hash();
+    let credential_id = webauthn_sig.credential_id.clone();
+    authorize_action(account, webauthn_sig, nonce, mode.approve_action(), &request_hash)?;
+
+    record_approval(account, mode, &credential_id, now)
+}
+
+/// Installs the recovered passkey as primary
+///
+/// Anyone may call this once the recovery has enough approvals and the
+/// delay has passed. Approvals are counted again, so ones from passkeys
+/// removed in the meantime no longer count. The new passkey must also pass
+/// the account's AAGUID allowlist as it stands now.
+pub fn finalize_recovery(account: &mut AttestaAccount, now: i64) -> Result<(), RecoveryFlowError> {
+    let request = account.pending_recovery.clone().ok_or(RecoveryFlowError::NoPendingRecovery)?;
+    let mut registry = account.passkey_registry()?.ok_or(RecoveryFlowError::NoPasskeyRegistry)?;
+
+    if !account.settings.allows_authenticator(request.new_aaguid.as_ref()) {
+        return Err(RecoveryFlowError::AuthenticatorNotAllowed);
+    }
+
+    if registry.count_valid_approvals(&request.approvals) < registry.recovery_threshold as usize {
+        return Err(RecoveryFlowError::ThresholdNotMet);
+    }
+    let ready_at = request.ready_at().ok_or(RecoveryFlowError::ThresholdNotMet)?;
+    if now < ready_at {
+        return Err(RecoveryFlowError::DelayNotElapsed { ready_at });
+    }
+
+    let entry = PasskeyEntry::new(
+        request.new_public_key,
+        request.new_credential_id.clone(),
+        "Recovered".to_string(),
+        now,
+    )
+    .with_aaguid(request.new_aaguid);
+    registry
+        .replace_primary(entry, now)
+        .map_err(|_| RecoveryFlowError::InvalidNewPasskey)?;
+    account.set_passkey_registry(&registry)?;
+
+    let old_credential_id = std::mem::take(&mut account.credential_id);
+    account.retire_key(&old_credential_id, account.passkey_public_key, now);
+    account.passkey_public_key = request.new_public_key;
+    account.credential_id = request.new_credential_id;
+    account.passkey_aaguid = request.new_aaguid;
+    account.pending_recovery = None;
+    // The one way out of a locked auth mode: the new primary passkey decides
+    account.settings.auth_mode_locked = false;
+    account.bump_state_version();
+    Ok(())
+}
+
+/// Cancels a pending recovery
+///
+/// Any registered passkey can cancel, including the primary the recovery
+/// would replace: if it can still sign, it isn't lost.
+pub fn cancel_recovery(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+) -> Result<(), RecoveryFlowError> {
+    let request_hash = account.pending_recovery
+        .as_ref()
+        .ok_or(RecoveryFlowError::NoPendingRecovery)?
+        .request_hash();
+    authorize_action(account, webauthn_sig, nonce, RECOVERY_CANCEL_ACTION, &request_hash)?;
+
+    account.pending_recovery = None;
+    Ok(())
+}
+
+/// Whether a transaction moving `transfer` may run given any pending recovery
+///
+/// Without one, anything may (the policies still apply). With one, only a
+/// token transfer of at most `settings.recovery_allowance` base units:
+/// other transaction data doesn't say what it moves.
+pub fn within_recovery_allowance(account: &AttestaAccount, transfer: Option<&TokenTransfer>) -> bool {
+    !account.recovery_pending() || transfer.is_some_and(|transfer| transfer.amount <= account.settings.recovery_allowance)
+}
+
+fn pending_request(account: &mut AttestaAccount, mode: RecoveryMode) -> &mut Option<RecoveryRequest> {
+    match mode {
+        RecoveryMode::Recovery => &mut account.pending_recovery,
+        RecoveryMode::Drill => &mut account.pending_drill,
+    }
+}
+
+/// Records an authorized approval and checks the threshold
+///
+/// Shared by recoveries and drills so a drill passes exactly when the same
+/// approvals would pass a real recovery. Only what happens at the threshold
+/// differs: a recovery starts its delay, a drill is complete.
+fn record_approval(
+    account: &mut AttestaAccount,
+    mode: RecoveryMode,
+    credential_id: &[u8],
+    now: i64,
+) -> Result<RecoveryProgress, RecoveryFlowError> {
+    let registry = account.passkey_registry()?.ok_or(RecoveryFlowError::NoPasskeyRegistry)?;
+    let approval = credential_id_hash(&account.credential_lookup_id(credential_id));
+
+    let request = pending_request(account, mode)
+        .as_mut()
+        .ok_or(RecoveryFlowError::NoPendingRecovery)?;
+    if !request.approvals.contains(&approval) {
+        request.approvals.push(approval);
+    }
+
+    let progress = RecoveryProgress {
+        approvals: registry.count_valid_approvals(&request.approvals),
+        threshold: registry.recovery_threshold as usize,
+    };
+
+    if progress.threshold_met() {
+        match mode {
+            RecoveryMode::Recovery => {
+                request.threshold_met_at.get_or_insert(now);
+            }
+            RecoveryMode::Drill => {
+                account.pending_drill = None;
+                account.last_drill_at = now;
+            }
+        }
+    }
+
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::MultiPasskey;
+    use solana_program::pubkey::Pubkey;
+    use crate::auth::action_message_hash;
+
+    const NEW_CREDENTIAL: &[u8] = b"new-phone";
+
+    /// Phone (primary, lost), laptop and yubikey; two approvals needed
+    fn setup() -> (AttestaAccount, TestPasskey, TestPasskey, TestPasskey) {
+        let phone = TestPasskey::new(1);
+        let laptop = TestPasskey::new(2);
+        let yubikey = TestPasskey::new(3);
+
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = MultiPasskey::new(phone.public_key(), phone.credential_id(), "Phone".to_string(), 100, 2, 5);
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 100).unwrap();
+        registry.add_passkey(yubikey.public_key(), yubikey.credential_id(), "YubiKey".to_string(), 100).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        (account, phone, laptop, yubikey)
+    }
+
+    fn new_key() -> [u8; 64] {
+        TestPasskey::new(9).public_key()
+    }
+
+    fn sign(passkey: &mut TestPasskey, account: &AttestaAccount, action: &[u8], request_hash: &[u8; 32]) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let challenge = compute_challenge(&account.owner, nonce, &action_message_hash(action, request_hash));
+        (passkey.sign(&challenge), nonce)
+    }
+
+    /// Runs initiate + approvals in `mode`, returning the progress after each step
+    fn run(account: &mut AttestaAccount, mode: RecoveryMode, approvers: &mut [&mut TestPasskey]) -> Vec<RecoveryProgress> {
+        let request_hash = recovery_request_hash(&new_key(), NEW_CREDENTIAL);
+        let mut progress = Vec::new();
+
+        let (first, rest) = approvers.split_first_mut().unwrap();
+        let (sig, nonce) = sign(first, account, mode.initiate_action(), &request_hash);
+        progress.push(initiate_recovery(account, mode, sig, nonce, new_key(), NEW_CREDENTIAL, None, 1_000).unwrap());
+
+        for approver in rest {
+            let (sig, nonce) = sign(approver, account, mode.approve_action(), &request_hash);
+            progress.push(approve_recovery(account, mode, sig, nonce, 1_000).unwrap());
+        }
+        progress
+    }
+
+    #[test]
+    fn test_drill_tracks_recovery_when_threshold_met() {
+        let (mut recovery_account, _, mut laptop, mut yubikey) = setup();
+        let mut drill_account = recovery_account.clone();
+
+        let recovery = run(&mut recovery_account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+        let drill = run(&mut drill_account, RecoveryMode::Drill, &mut [&mut laptop, &mut yubikey]);
+
+        assert_eq!(recovery, drill);
+        assert!(recovery.last().unwrap().threshold_met());
+        assert_eq!(recovery_account.pending_recovery.as_ref().unwrap().threshold_met_at, Some(1_000));
+        assert_eq!(drill_account.last_drill_at, 1_000);
+        assert!(drill_account.pending_drill.is_none());
+    }
+
+    #[test]
+    fn test_drill_tracks_recovery_when_threshold_not_met() {
+        let (mut recovery_account, _, mut laptop, _) = setup();
+        let mut drill_account = recovery_account.clone();
+
+        let recovery = run(&mut recovery_account, RecoveryMode::Recovery, &mut [&mut laptop]);
+        let drill = run(&mut drill_account, RecoveryMode::Drill, &mut [&mut laptop]);
+
+        assert_eq!(recovery, drill);
+        assert_eq!(recovery, vec![RecoveryProgress { approvals: 1, threshold: 2 }]);
+        assert_eq!(recovery_account.pending_recovery.as_ref().unwrap().threshold_met_at, None);
+        assert_eq!(drill_account.last_drill_at, 0);
+        assert!(drill_account.pending_drill.is_some());
+    }
+
+    #[test]
+    fn test_drill_changes_no_keys_and_leaves_recovery_alone() {
+        let (mut account, _, mut laptop, mut yubikey) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop]);
+        let pending = account.pending_recovery.clone();
+        let keys = (account.passkey_public_key, account.credential_id.clone(), account.passkeys.clone());
+
+        run(&mut account, RecoveryMode::Drill, &mut [&mut laptop, &mut yubikey]);
+
+        assert_eq!(account.last_drill_at, 1_000);
+        assert_eq!(account.pending_recovery, pending);
+        assert_eq!((account.passkey_public_key, account.credential_id.clone(), account.passkeys.clone()), keys);
+    }
+
+    #[test]
+    fn test_drill_signature_is_not_a_recovery_approval() {
+        let (mut account, _, mut laptop, mut yubikey) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop]);
+
+        let request_hash = recovery_request_hash(&new_key(), NEW_CREDENTIAL);
+        let (sig, nonce) = sign(&mut yubikey, &account, RECOVERY_DRILL_APPROVE_ACTION, &request_hash);
+        assert!(matches!(
+            approve_recovery(&mut account, RecoveryMode::Recovery, sig, nonce, 1_000),
+            Err(RecoveryFlowError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_repeated_approval_counts_once() {
+        let (mut account, _, mut laptop, _) = setup();
+        let progress = run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop]);
+        assert_eq!(progress[0].approvals, 1);
+
+        let request_hash = recovery_request_hash(&new_key(), NEW_CREDENTIAL);
+        let (sig, nonce) = sign(&mut laptop, &account, RECOVERY_APPROVE_ACTION, &request_hash);
+        let progress = approve_recovery(&mut account, RecoveryMode::Recovery, sig, nonce, 1_000).unwrap();
+        assert_eq!(progress.approvals, 1);
+    }
+
+    #[test]
+    fn test_second_recovery_rejected_while_pending() {
+        let (mut account, _, mut laptop, mut yubikey) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop]);
+
+        let request_hash = recovery_request_hash(&new_key(), NEW_CREDENTIAL);
+        let (sig, nonce) = sign(&mut yubikey, &account, RECOVERY_INITIATE_ACTION, &request_hash);
+        assert_eq!(
+            initiate_recovery(&mut account, RecoveryMode::Recovery, sig, nonce, new_key(), NEW_CREDENTIAL, None, 1_000),
+            Err(RecoveryFlowError::AlreadyPending)
+        );
+    }
+
+    #[test]
+    fn test_recovery_to_an_empty_credential_id_is_rejected() {
+        let (mut account, _, mut laptop, _) = setup();
+
+        let request_hash = recovery_request_hash(&new_key(), &[]);
+        let (sig, nonce) = sign(&mut laptop, &account, RECOVERY_INITIATE_ACTION, &request_hash);
+        assert_eq!(
+            initiate_recovery(&mut account, RecoveryMode::Recovery, sig, nonce, new_key(), &[], None, 1_000),
+            Err(RecoveryFlowError::InvalidNewPasskey)
+        );
+        assert!(!account.recovery_pending());
+    }
+
+    #[test]
+    fn test_finalize_waits_for_delay_then_replaces_primary() {
+        let (mut account, mut phone, mut laptop, mut yubikey) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+
+        let ready_at = 1_000 + RECOVERY_DELAY_SECONDS;
+        assert_eq!(
+            finalize_recovery(&mut account, ready_at - 1),
+            Err(RecoveryFlowError::DelayNotElapsed { ready_at })
+        );
+
+        finalize_recovery(&mut account, ready_at).unwrap();
+        assert_eq!(account.passkey_public_key, new_key());
+        assert_eq!(account.credential_id, NEW_CREDENTIAL);
+        assert!(account.pending_recovery.is_none());
+
+        // ...but what it signed before still verifies against the proof log
+        let phone_hash = recovery::multi_passkey::credential_id_hash(&phone.credential_id());
+        assert_eq!(account.public_key_at(&phone_hash, ready_at), Some(phone.public_key()));
+
+        // The lost phone can't sign anymore
+        let registry = account.passkey_registry().unwrap().unwrap();
+        assert!(registry.is_revoked(&phone.credential_id()));
+        let (sig, _) = sign(&mut phone, &account, RECOVERY_CANCEL_ACTION, &[0; 32]);
+        assert_eq!(
+            crate::auth::resolve_signing_key(&account, &sig.credential_id),
+            Err(CryptoError::RevokedCredential)
+        );
+    }
+
+    #[test]
+    fn test_finalize_lifts_the_auth_mode_lock() {
+        let (mut account, _, mut laptop, mut yubikey) = setup();
+        account.settings.auth_mode_locked = true;
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+
+        finalize_recovery(&mut account, 1_000 + RECOVERY_DELAY_SECONDS).unwrap();
+        assert!(!account.settings.auth_mode_locked);
+        assert_eq!(account.settings.auth_mode, crate::auth_mode::AuthMode::PasskeyOnly);
+    }
+
+    #[test]
+    fn test_finalize_requires_threshold() {
+        let (mut account, _, mut laptop, _) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop]);
+
+        assert_eq!(
+            finalize_recovery(&mut account, i64::MAX),
+            Err(RecoveryFlowError::ThresholdNotMet)
+        );
+    }
+
+    #[test]
+    fn test_finalize_checks_aaguid_allowlist() {
+        let (mut account, _, mut laptop, mut yubikey) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+        account.settings.aaguid_allowlist = vec![[1; 16]];
+        let ready_at = 1_000 + RECOVERY_DELAY_SECONDS;
+
+        // Not attested, or attested as an unlisted model
+        assert_eq!(finalize_recovery(&mut account, ready_at), Err(RecoveryFlowError::AuthenticatorNotAllowed));
+        account.pending_recovery.as_mut().unwrap().new_aaguid = Some([2; 16]);
+        assert_eq!(finalize_recovery(&mut account, ready_at), Err(RecoveryFlowError::AuthenticatorNotAllowed));
+
+        // A listed model is installed, and its AAGUID kept, including across a save while pending
+        account.pending_recovery.as_mut().unwrap().new_aaguid = Some([1; 16]);
+        let mut account = AttestaAccount::from_bytes(&account.to_bytes().unwrap()).unwrap();
+        finalize_recovery(&mut account, ready_at).unwrap();
+        assert_eq!(account.passkey_aaguid, Some([1; 16]));
+        assert_eq!(account.passkey_registry().unwrap().unwrap().primary.aaguid, Some([1; 16]));
+    }
+
+    #[test]
+    fn test_primary_can_cancel_recovery() {
+        let (mut account, mut phone, mut laptop, mut yubikey) = setup();
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+
+        let request_hash = account.pending_recovery.as_ref().unwrap().request_hash();
+        let (sig, nonce) = sign(&mut phone, &account, RECOVERY_CANCEL_ACTION, &request_hash);
+        cancel_recovery(&mut account, sig, nonce).unwrap();
+
+        assert!(account.pending_recovery.is_none());
+        assert_eq!(finalize_recovery(&mut account, i64::MAX), Err(RecoveryFlowError::NoPendingRecovery));
+    }
+
+    #[test]
+    fn test_allowance_holds_only_while_a_recovery_is_pending() {
+        let (mut account, mut phone, mut laptop, mut yubikey) = setup();
+        account.settings.recovery_allowance = 50;
+        let transfer = |amount| TokenTransfer { mint: Pubkey::new_unique(), amount, decimals: 6, destination_ata: Pubkey::new_unique() };
+        assert!(within_recovery_allowance(&account, Some(&transfer(1_000))));
+        assert!(within_recovery_allowance(&account, None));
+
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+        assert!(account.recovery_pending());
+        assert!(within_recovery_allowance(&account, Some(&transfer(50))));
+        assert!(!within_recovery_allowance(&account, Some(&transfer(51))));
+        assert!(!within_recovery_allowance(&account, None));
+
+        // Cancelling lifts it
+        let request_hash = account.pending_recovery.as_ref().unwrap().request_hash();
+        let (sig, nonce) = sign(&mut phone, &account, RECOVERY_CANCEL_ACTION, &request_hash);
+        cancel_recovery(&mut account, sig, nonce).unwrap();
+        assert!(within_recovery_allowance(&account, Some(&transfer(1_000))));
+
+        // And so does finishing
+        run(&mut account, RecoveryMode::Recovery, &mut [&mut laptop, &mut yubikey]);
+        assert!(!within_recovery_allowance(&account, Some(&transfer(1_000))));
+        finalize_recovery(&mut account, 1_000 + RECOVERY_DELAY_SECONDS).unwrap();
+        assert!(!account.recovery_pending());
+        assert!(within_recovery_allowance(&account, Some(&transfer(1_000))));
+    }
+
+    #[test]
+    fn test_single_passkey_account_cannot_recover() {
+        let mut phone = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+
+        let request_hash = recovery_request_hash(&new_key(), NEW_CREDENTIAL);
+        let (sig, nonce) = sign(&mut phone, &account, RECOVERY_DRILL_INITIATE_ACTION, &request_hash);
+        assert_eq!(
+            initiate_recovery(&mut account, RecoveryMode::Drill, sig, nonce, new_key(), NEW_CREDENTIAL, None, 1_000),
+            Err(RecoveryFlowError::NoPasskeyRegistry)
+        );
+    }
+}
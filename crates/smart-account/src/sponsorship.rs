@@ -0,0 +1,175 @@
+//! Pools that pay new accounts' rent
+//!
+//! A project can onboard users who hold no SOL by funding a sponsor pool:
+//! `sponsored_initialize` takes the new account's rent from the pool instead
+//! of the owner. Anyone may fund a pool; only its authority may withdraw.
+//!
+//! Two limits keep a pool from being drained by one abuser: the rent it
+//! pays per account is capped, and so is the number of accounts it
+//! sponsors per UTC day. The day's counter starts over the first time the
+//! pool is used on a new day.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use thiserror::Error;
+
+/// Length of the day the per-day limit counts over, in seconds
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Errors from sponsoring an account out of a pool
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SponsorshipError {
+    #[error("The pool has sponsored its {max} accounts for today")]
+    DailyLimitReached { max: u32 },
+
+    #[error("The account's rent ({rent} lamports) is over the pool's {max} per account")]
+    RentTooHigh { rent: u64, max: u64 },
+
+    #[error("The pool has {available} lamports to spare, {needed} needed")]
+    PoolExhausted { available: u64, needed: u64 },
+
+    #[error("Invalid sponsor pool data")]
+    InvalidData,
+}
+
+/// A sponsor pool's limits and counters
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SponsorPool {
+    /// Accounts the pool sponsors per day at most
+    pub max_accounts_per_day: u32,
+
+    /// Rent the pool pays for one account at most, in lamports
+    pub max_lamports_per_account: u64,
+
+    /// The day (Unix time / `SECONDS_PER_DAY`) `sponsored_today` counts
+    pub day: i64,
+
+    /// Accounts sponsored on `day`
+    pub sponsored_today: u32,
+
+    /// Accounts sponsored since the pool was created
+    pub total_sponsored: u64,
+
+    /// Lamports paid out for rent since the pool was created
+    pub total_lamports: u64,
+}
+
+impl SponsorPool {
+    /// Bytes a serialized pool takes
+    pub const SERIALIZED_SIZE: usize = 4 + 8 + 8 + 4 + 8 + 8;
+
+    pub fn new(max_accounts_per_day: u32, max_lamports_per_account: u64) -> Self {
+        Self {
+            max_accounts_per_day,
+            max_lamports_per_account,
+            day: 0,
+            sponsored_today: 0,
+            total_sponsored: 0,
+            total_lamports: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SponsorshipError> {
+        borsh::to_vec(self).map_err(|_| SponsorshipError::InvalidData)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SponsorshipError> {
+        borsh::from_slice(data).map_err(|_| SponsorshipError::InvalidData)
+    }
+
+    /// Accounts sponsored on the day `now` falls in
+    pub fn sponsored_on(&self, now: i64) -> u32 {
+        if day_of(now) == self.day {
+            self.sponsored_today
+        } else {
+            0
+        }
+    }
+
+    /// Accounts the pool can still sponsor on the day `now` falls in, funds permitting
+    pub fn remaining_on(&self, now: i64) -> u32 {
+        self.max_accounts_per_day.saturating_sub(self.sponsored_on(now))
+    }
+
+    /// Counts one account sponsored for `rent` lamports at `now`
+    ///
+    /// # Parameters
+    /// - `rent`: The lamports the pool is about to pay
+    /// - `spare_lamports`: What the pool holds above its own rent-exempt minimum
+    /// - `now`: The current Unix timestamp
+    ///
+    /// # Returns
+    /// - `Ok(())` with the counters updated, if the pool may pay
+    /// - `Err(SponsorshipError)` naming the limit it would break, with nothing changed
+    pub fn sponsor(&mut self, rent: u64, spare_lamports: u64, now: i64) -> Result<(), SponsorshipError> {
+        if rent > self.max_lamports_per_account {
+            return Err(SponsorshipError::RentTooHigh { rent, max: self.max_lamports_per_account });
+        }
+        if self.remaining_on(now) == 0 {
+            return Err(SponsorshipError::DailyLimitReached { max: self.max_accounts_per_day });
+        }
+        if rent > spare_lamports {
+            return Err(SponsorshipError::PoolExhausted { available: spare_lamports, needed: rent });
+        }
+
+        self.sponsored_today = self.sponsored_on(now) + 1;
+        self.day = day_of(now);
+        self.total_sponsored = self.total_sponsored.saturating_add(1);
+        self.total_lamports = self.total_lamports.saturating_add(rent);
+        Ok(())
+    }
+}
+
+fn day_of(now: i64) -> i64 {
+    now.div_euclid(SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOON: i64 = 1_800_000_000 - 1_800_000_000 % SECONDS_PER_DAY + SECONDS_PER_DAY / 2;
+    const RENT: u64 = 2_000_000;
+
+    #[test]
+    fn test_daily_limit_rolls_over() {
+        let mut pool = SponsorPool::new(2, RENT);
+        assert_eq!(pool.sponsor(RENT, 10 * RENT, NOON), Ok(()));
+        assert_eq!(pool.sponsor(RENT, 9 * RENT, NOON + 60), Ok(()));
+        assert_eq!(pool.sponsor(RENT, 8 * RENT, NOON + 120), Err(SponsorshipError::DailyLimitReached { max: 2 }));
+        assert_eq!(pool.remaining_on(NOON + 120), 0);
+
+        // Just after midnight UTC the day starts over
+        let tomorrow = NOON + SECONDS_PER_DAY / 2;
+        assert_eq!(pool.remaining_on(tomorrow), 2);
+        assert_eq!(pool.sponsor(RENT, 8 * RENT, tomorrow), Ok(()));
+        assert_eq!(pool.sponsored_on(tomorrow), 1);
+        assert_eq!(pool.sponsored_on(NOON), 0);
+        assert_eq!((pool.total_sponsored, pool.total_lamports), (3, 3 * RENT));
+    }
+
+    #[test]
+    fn test_limits_leave_the_pool_unchanged() {
+        let mut pool = SponsorPool::new(5, RENT);
+        let before = pool.clone();
+
+        assert_eq!(
+            pool.sponsor(RENT + 1, 10 * RENT, NOON),
+            Err(SponsorshipError::RentTooHigh { rent: RENT + 1, max: RENT })
+        );
+        assert_eq!(
+            pool.sponsor(RENT, RENT - 1, NOON),
+            Err(SponsorshipError::PoolExhausted { available: RENT - 1, needed: RENT })
+        );
+        assert_eq!(pool, before);
+    }
+
+    #[test]
+    fn test_round_trip_and_size() {
+        let mut pool = SponsorPool::new(3, RENT);
+        pool.sponsor(RENT, RENT, NOON).unwrap();
+        let bytes = pool.to_bytes().unwrap();
+        assert_eq!(bytes.len(), SponsorPool::SERIALIZED_SIZE);
+        assert_eq!(SponsorPool::from_bytes(&bytes), Ok(pool));
+        assert_eq!(SponsorPool::from_bytes(&bytes[1..]), Err(SponsorshipError::InvalidData));
+    }
+}
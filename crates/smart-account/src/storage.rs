@@ -1,8 +1,11 @@
 use solana_program::{
     account_info::AccountInfo,
     program_error::ProgramError,
-    pubkey::Pubkey,
+    pubkey::{Pubkey, MAX_SEED_LEN},
 };
+use attesta_types::consts::{ACCOUNT_DISCRIMINATOR_LEN, BORSH_LEN_PREFIX, P256_PUBKEY_LEN};
+use borsh::BorshDeserialize;
+use recovery::credential_id_hash;
 use crate::account::{AttestaAccount, ATTESTA_ACCOUNT_DISCRIMINATOR};
 
 /// Finds the address where an Attesta account is stored (PDA)
@@ -11,15 +14,18 @@ use crate::account::{AttestaAccount, ATTESTA_ACCOUNT_DISCRIMINATOR};
 /// that are controlled by our program. This function calculates the address
 /// where a user's Attesta account will be stored.
 ///
+/// Solana caps each seed at 32 bytes, and WebAuthn credential IDs are often
+/// longer, so don't pass one here: use `derive_attesta_account_for_credential`,
+/// which seeds with its hash.
+///
 /// # Parameters
 /// - `program_id`: The ID of our Attesta program
 /// - `owner`: The user's wallet address
-/// - `seed`: Additional seed data (e.g., credential ID) to make it unique
+/// - `seed`: Additional seed data to make it unique (at most `MAX_SEED_LEN` bytes)
 ///
 /// # Returns
-/// A tuple of (Pubkey, bump_seed) where:
-/// - The Pubkey is the address of the account
-/// - The bump_seed is needed to derive this address again
+/// - `Ok((address, bump_seed))`; the bump seed is needed to derive the address again
+/// - `Err(ProgramError::MaxSeedLengthExceeded)` if `seed` is too long
 ///
 /// # How PDAs work
 /// PDAs are addresses that don't have a private key. Instead, they're
@@ -29,22 +35,142 @@ pub fn derive_attesta_account(
     program_id: &Pubkey,
     owner: &Pubkey,
     seed: &[u8],
-) -> (Pubkey, u8) {
-    Pubkey::find_program_address(
+) -> Result<(Pubkey, u8), ProgramError> {
+    if seed.len() > MAX_SEED_LEN {
+        return Err(ProgramError::MaxSeedLengthExceeded);
+    }
+    Ok(Pubkey::find_program_address(
         &[
             b"attesta",           // Prefix to identify Attesta accounts
             owner.as_ref(),        // Owner's public key
-            seed,                  // Additional seed (e.g., credential ID)
+            seed,                  // Additional seed
         ],
         program_id,
-    )
+    ))
+}
+
+/// Finds the address of an owner's Attesta account for one of its passkeys
+///
+/// The credential ID goes into the seeds as `credential_seed(credential_id)`,
+/// so IDs of any length work.
+pub fn derive_attesta_account_for_credential(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    credential_id: &[u8],
+) -> (Pubkey, u8) {
+    let seed = credential_seed(credential_id);
+    Pubkey::find_program_address(&[b"attesta", owner.as_ref(), &seed], program_id)
+}
+
+/// Finds the address of an owner's Attesta account at a chosen index
+///
+/// The index goes into the seeds little-endian, so an owner can hold many
+/// accounts and know each address before creating its passkey. The
+/// account records the index it was created at (`account_index`).
+pub fn derive_attesta_account_by_index(program_id: &Pubkey, owner: &Pubkey, index: u16) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"attesta", owner.as_ref(), &index.to_le_bytes()], program_id)
+}
+
+/// The PDA seed that stands for a credential ID: its SHA-256 hash
+pub fn credential_seed(credential_id: &[u8]) -> [u8; MAX_SEED_LEN] {
+    credential_id_hash(credential_id)
+}
+
+/// Anchor's discriminator for the program's `AttestaAccountData` account
+///
+/// Anchor derives it as the first 8 bytes of `sha256("account:AttestaAccountData")`.
+pub const ATTESTA_ACCOUNT_DATA_DISCRIMINATOR: [u8; ACCOUNT_DISCRIMINATOR_LEN] = [0x91, 0xc0, 0x33, 0xea, 0xa6, 0xbe, 0x6a, 0x38];
+
+/// The ways an Attesta account has been laid out on-chain
+///
+/// Only `Anchor` is written today. The others are what older versions of
+/// these helpers wrote, and are only read so they can be migrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountLayout {
+    /// The program's layout: Anchor's discriminator, then the serialized
+    /// account as a length-prefixed byte vector (the `AttestaAccountData` wrapper)
+    Anchor,
+
+    /// `ATTESTA_ACCOUNT_DISCRIMINATOR` followed directly by the serialized account
+    LegacyRaw,
+
+    /// The Anchor wrapper around a `LegacyRaw` account, discriminator and all
+    LegacyWrapped,
+}
+
+/// Serializes an account in the canonical (`AccountLayout::Anchor`) layout
+///
+/// These are exactly the bytes the program writes at the start of the
+/// account; the rest of its allocation is spare room.
+pub fn encode_attesta_account(account: &AttestaAccount) -> Result<Vec<u8>, ProgramError> {
+    let serialized = account.to_bytes()
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let length = u32::try_from(serialized.len())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+
+    let mut data = Vec::with_capacity(ACCOUNT_DISCRIMINATOR_LEN + BORSH_LEN_PREFIX + serialized.len());
+    data.extend_from_slice(&ATTESTA_ACCOUNT_DATA_DISCRIMINATOR);
+    data.extend_from_slice(&length.to_le_bytes());
+    data.extend_from_slice(&serialized);
+    Ok(data)
+}
+
+/// Reads an account in the canonical (`AccountLayout::Anchor`) layout
+///
+/// Anything after the wrapped account is spare room and is ignored.
+pub fn decode_attesta_account(data: &[u8]) -> Result<AttestaAccount, ProgramError> {
+    let inner = anchor_wrapped_bytes(data).ok_or(ProgramError::InvalidAccountData)?;
+    AttestaAccount::from_bytes(inner)
+        .map_err(|_| ProgramError::InvalidAccountData)
+}
+
+/// Reads an account in any layout it has ever been stored in
+///
+/// For migration tooling: the layout found tells it whether the account
+/// needs rewriting with `save_attesta_account`.
+///
+/// # Returns
+/// - `Ok((account, layout))` with the account and the layout it was stored in
+/// - `Err(ProgramError::InvalidAccountData)` if it's in none of them
+pub fn detect_attesta_account(data: &[u8]) -> Result<(AttestaAccount, AccountLayout), ProgramError> {
+    if let Some(inner) = anchor_wrapped_bytes(data) {
+        // A canonical account whose owner happens to start with the legacy
+        // discriminator would also match below, so try it as canonical first
+        if let Ok(account) = AttestaAccount::from_bytes(inner) {
+            return Ok((account, AccountLayout::Anchor));
+        }
+        if let Some(account) = decode_legacy_raw(inner) {
+            return Ok((account, AccountLayout::LegacyWrapped));
+        }
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    decode_legacy_raw(data)
+        .map(|account| (account, AccountLayout::LegacyRaw))
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+/// The serialized account inside an `AttestaAccountData` wrapper
+fn anchor_wrapped_bytes(data: &[u8]) -> Option<&[u8]> {
+    let rest = data.strip_prefix(&ATTESTA_ACCOUNT_DATA_DISCRIMINATOR[..])?;
+    let length: [u8; BORSH_LEN_PREFIX] = rest.get(..BORSH_LEN_PREFIX)?.try_into().ok()?;
+    let length = usize::try_from(u32::from_le_bytes(length)).ok()?;
+    rest.get(BORSH_LEN_PREFIX..)?.get(..length)
+}
+
+/// Reads a `LegacyRaw` account, which was written without a length, so
+/// whatever follows it in the allocation is ignored
+fn decode_legacy_raw(data: &[u8]) -> Option<AttestaAccount> {
+    let mut rest = data.strip_prefix(&ATTESTA_ACCOUNT_DISCRIMINATOR[..])?;
+    AttestaAccount::deserialize(&mut rest).ok()
 }
 
 /// Reads an Attesta account from on-chain storage
 ///
 /// This function takes a Solana account and reads the Attesta account
-/// data from it. It validates the discriminator to make sure it's
-/// actually an Attesta account, then deserializes the data.
+/// data from it. The account must be in the layout the program writes
+/// (`AccountLayout::Anchor`); use `load_attesta_account_any_layout` to
+/// read accounts written by older versions.
 ///
 /// # Parameters
 /// - `account_info`: The Solana account to read from
@@ -56,32 +182,25 @@ pub fn load_attesta_account(
     account_info: &AccountInfo,
 ) -> Result<AttestaAccount, ProgramError> {
     let data = account_info.data.borrow();
-    
-    // First, check the discriminator (first 8 bytes)
-    // This is like a file type indicator - makes sure it's actually an Attesta account
-    const DISCRIMINATOR_SIZE: usize = 8;
-    if data.len() < DISCRIMINATOR_SIZE {
-        return Err(ProgramError::InvalidAccountData);
-    }
-    
-    if data[..DISCRIMINATOR_SIZE] != ATTESTA_ACCOUNT_DISCRIMINATOR {
-        return Err(ProgramError::InvalidAccountData);
-    }
-
-    // Skip past the discriminator and deserialize the actual account data
-    let account_data = data.get(DISCRIMINATOR_SIZE..)
-        .ok_or(ProgramError::InvalidAccountData)?;
-    
-    let account = AttestaAccount::from_bytes(account_data)
-        .map_err(|_| ProgramError::InvalidAccountData)?;
+    decode_attesta_account(&data)
+}
 
-    Ok(account)
+/// Reads an Attesta account stored in any layout, and says which one
+///
+/// See `detect_attesta_account`.
+pub fn load_attesta_account_any_layout(
+    account_info: &AccountInfo,
+) -> Result<(AttestaAccount, AccountLayout), ProgramError> {
+    let data = account_info.data.borrow();
+    detect_attesta_account(&data)
 }
 
 /// Saves an Attesta account to on-chain storage
 ///
-/// This function takes an Attesta account and writes it to a Solana account.
-/// It writes the discriminator first, then the serialized account data.
+/// This function takes an Attesta account and writes it to a Solana account
+/// in the layout the program uses (`AccountLayout::Anchor`), so the program
+/// can read it back. An account in a legacy layout is migrated by loading it
+/// with `load_attesta_account_any_layout` and saving it with this.
 ///
 /// # Parameters
 /// - `account`: The Attesta account to save
@@ -99,27 +218,12 @@ pub fn save_attesta_account(
     account_info: &AccountInfo,
 ) -> Result<(), ProgramError> {
     let mut data = account_info.data.borrow_mut();
-    
-    // Serialize the account to bytes
-    let serialized = account.to_bytes()
-        .map_err(|_| ProgramError::InvalidAccountData)?;
-    
-    // Calculate how much space we need
-    const DISCRIMINATOR_SIZE: usize = 8;
-    let total_size = DISCRIMINATOR_SIZE + serialized.len();
-    
-    // Make sure the account is big enough
-    if data.len() < total_size {
-        return Err(ProgramError::InvalidAccountData);
-    }
+    let encoded = encode_attesta_account(account)?;
 
-    // Write the discriminator (first 8 bytes)
-    data[..DISCRIMINATOR_SIZE].copy_from_slice(&ATTESTA_ACCOUNT_DISCRIMINATOR);
-    
-    // Write the account data (after the discriminator)
-    let data_slice = data.get_mut(DISCRIMINATOR_SIZE..total_size)
-        .ok_or(ProgramError::InvalidAccountData)?;
-    data_slice.copy_from_slice(&serialized);
+    // Make sure the account is big enough
+    data.get_mut(..encoded.len())
+        .ok_or(ProgramError::InvalidAccountData)?
+        .copy_from_slice(&encoded);
 
     Ok(())
 }
@@ -142,7 +246,7 @@ pub fn save_attesta_account(
 pub fn init_attesta_account(
     account_info: &AccountInfo,
     owner: &Pubkey,
-    passkey_public_key: [u8; 64],
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
     credential_id: Vec<u8>,
     policy: Vec<u8>,
 ) -> Result<(), ProgramError> {
@@ -162,3 +266,177 @@ pub fn init_attesta_account(
     // Save it to storage
     save_attesta_account(&account, account_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn account() -> AttestaAccount {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![1, 2, 3], 100);
+        account.nonce = 7;
+        account
+    }
+
+    /// Calls `f` with an `AccountInfo` over `data`
+    fn with_account_info<R>(data: &mut [u8], f: impl FnOnce(&AccountInfo) -> R) -> R {
+        let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut lamports = 0;
+        let info = AccountInfo::new(&key, false, true, &mut lamports, data, &owner, false, 0);
+        f(&info)
+    }
+
+    fn legacy_raw(account: &AttestaAccount) -> Vec<u8> {
+        [&ATTESTA_ACCOUNT_DISCRIMINATOR[..], &account.to_bytes().unwrap()].concat()
+    }
+
+    fn anchor_wrapped(inner: &[u8]) -> Vec<u8> {
+        let mut data = ATTESTA_ACCOUNT_DATA_DISCRIMINATOR.to_vec();
+        data.extend(borsh::to_vec(&inner.to_vec()).unwrap());
+        data
+    }
+
+    #[test]
+    fn test_discriminator_is_anchors() {
+        let hash = Sha256::digest(b"account:AttestaAccountData");
+        assert_eq!(ATTESTA_ACCOUNT_DATA_DISCRIMINATOR[..], hash[..8]);
+    }
+
+    #[test]
+    fn test_encoding_matches_the_anchor_wrapper() {
+        // What Anchor writes for `AttestaAccountData { data }`
+        let account = account();
+        let expected = anchor_wrapped(&account.to_bytes().unwrap());
+
+        assert_eq!(encode_attesta_account(&account).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let account = account();
+        // Allocated with spare room, like the program's accounts
+        let mut data = vec![0u8; encode_attesta_account(&account).unwrap().len() + 100];
+
+        with_account_info(&mut data, |info| {
+            save_attesta_account(&account, info).unwrap();
+            assert_eq!(load_attesta_account(info).unwrap(), account);
+            assert_eq!(load_attesta_account_any_layout(info).unwrap(), (account.clone(), AccountLayout::Anchor));
+        });
+    }
+
+    #[test]
+    fn test_save_needs_room() {
+        let account = account();
+        let mut data = vec![0u8; encode_attesta_account(&account).unwrap().len() - 1];
+
+        with_account_info(&mut data, |info| {
+            assert_eq!(save_attesta_account(&account, info), Err(ProgramError::InvalidAccountData));
+        });
+    }
+
+    #[test]
+    fn test_legacy_layouts_are_detected() {
+        let account = account();
+        let mut raw = legacy_raw(&account);
+        let wrapped = anchor_wrapped(&raw);
+        raw.resize(raw.len() + 100, 0);
+
+        assert_eq!(detect_attesta_account(&raw).unwrap(), (account.clone(), AccountLayout::LegacyRaw));
+        assert_eq!(detect_attesta_account(&wrapped).unwrap(), (account.clone(), AccountLayout::LegacyWrapped));
+
+        // The strict reader only takes the canonical layout
+        assert!(decode_attesta_account(&raw).is_err());
+        assert!(decode_attesta_account(&wrapped).is_err());
+    }
+
+    #[test]
+    fn test_legacy_account_migrates() {
+        let account = account();
+        let mut data = legacy_raw(&account);
+        data.resize(data.len() + 100, 0);
+
+        with_account_info(&mut data, |info| {
+            let (loaded, layout) = load_attesta_account_any_layout(info).unwrap();
+            assert_eq!(layout, AccountLayout::LegacyRaw);
+            save_attesta_account(&loaded, info).unwrap();
+            assert_eq!(load_attesta_account(info).unwrap(), account);
+        });
+    }
+
+    #[test]
+    fn test_owner_resembling_legacy_discriminator_reads_as_canonical() {
+        let mut account = account();
+        let mut owner = [9u8; 32];
+        owner[..8].copy_from_slice(&ATTESTA_ACCOUNT_DISCRIMINATOR);
+        account.owner = Pubkey::new_from_array(owner);
+
+        let data = encode_attesta_account(&account).unwrap();
+        assert_eq!(detect_attesta_account(&data).unwrap(), (account, AccountLayout::Anchor));
+    }
+
+    #[test]
+    fn test_credential_ids_of_any_length_derive() {
+        let (program_id, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let addresses: Vec<Pubkey> = [16, 32, 96]
+            .iter()
+            .map(|len| {
+                let credential_id = vec![7u8; *len];
+                let (address, bump) = derive_attesta_account_for_credential(&program_id, &owner, &credential_id);
+                let seed = credential_seed(&credential_id);
+                assert_eq!(
+                    Pubkey::create_program_address(&[b"attesta", owner.as_ref(), &seed, &[bump]], &program_id),
+                    Ok(address)
+                );
+                address
+            })
+            .collect();
+
+        assert_ne!(addresses[0], addresses[1]);
+        assert_ne!(addresses[1], addresses[2]);
+        // A different owner with the same passkey gets a different account
+        let other = derive_attesta_account_for_credential(&program_id, &Pubkey::new_unique(), &[7u8; 16]).0;
+        assert_ne!(other, addresses[0]);
+    }
+
+    #[test]
+    fn test_indexed_accounts_derive_apart() {
+        let (program_id, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (first, bump) = derive_attesta_account_by_index(&program_id, &owner, 0);
+        assert_eq!(
+            Pubkey::create_program_address(&[b"attesta", owner.as_ref(), &[0, 0], &[bump]], &program_id),
+            Ok(first)
+        );
+        assert_ne!(derive_attesta_account_by_index(&program_id, &owner, 1).0, first);
+        assert_ne!(derive_attesta_account_by_index(&program_id, &Pubkey::new_unique(), 0).0, first);
+        // The owner's own account has no index in its seeds
+        assert_ne!(derive_attesta_account(&program_id, &owner, &[]).unwrap().0, first);
+    }
+
+    #[test]
+    fn test_raw_seed_length_is_checked() {
+        let (program_id, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert!(derive_attesta_account(&program_id, &owner, &[1u8; 16]).is_ok());
+        assert!(derive_attesta_account(&program_id, &owner, &[1u8; 32]).is_ok());
+        assert_eq!(
+            derive_attesta_account(&program_id, &owner, &[1u8; 96]),
+            Err(ProgramError::MaxSeedLengthExceeded)
+        );
+        // A hashed seed passed raw gives the same address
+        let credential_id = [2u8; 96];
+        assert_eq!(
+            derive_attesta_account(&program_id, &owner, &credential_seed(&credential_id)),
+            Ok(derive_attesta_account_for_credential(&program_id, &owner, &credential_id))
+        );
+    }
+
+    #[test]
+    fn test_unknown_data_is_rejected() {
+        assert!(detect_attesta_account(&[]).is_err());
+        assert!(detect_attesta_account(&[0u8; 64]).is_err());
+        // A wrapper whose length runs past the data
+        let mut data = ATTESTA_ACCOUNT_DATA_DISCRIMINATOR.to_vec();
+        data.extend(u32::MAX.to_le_bytes());
+        assert!(detect_attesta_account(&data).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Sub-accounts: accounts whose policy sits under a parent's
+//!
+//! A sub-account (say, a "hot" account for daily spending) has its own
+//! passkey and policy, but every transaction must also pass the parent's
+//! policy. Its own policy can only make it stricter than the parent.
+//!
+//! Only top-level accounts can have sub-accounts, so chains are at most
+//! `MAX_ACCOUNT_DEPTH` long and can't loop.
+
+use attesta_types::consts::{BORSH_LEN_PREFIX, P256_PUBKEY_LEN};
+use thiserror::Error;
+use solana_program::pubkey::Pubkey;
+use crate::account::AttestaAccount;
+
+/// Action name the parent's passkey signs to create a sub-account
+pub const SUB_ACCOUNT_CREATE_ACTION: &[u8] = b"create_sub_account";
+
+/// Longest parent chain: a top-level account and one level of sub-accounts
+pub const MAX_ACCOUNT_DEPTH: usize = 2;
+
+/// Errors from creating sub-accounts
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubAccountError {
+    #[error("A sub-account can't have sub-accounts of its own")]
+    ParentIsSubAccount,
+}
+
+/// The payload the parent's passkey signs (with `SUB_ACCOUNT_CREATE_ACTION`)
+///
+/// Commits to everything the new sub-account is created with.
+pub fn sub_account_payload(
+    index: u8,
+    passkey_public_key: &[u8; P256_PUBKEY_LEN],
+    credential_id: &[u8],
+    policy: &[u8],
+) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(1 + P256_PUBKEY_LEN + BORSH_LEN_PREFIX + credential_id.len() + policy.len());
+    payload.push(index);
+    payload.extend_from_slice(passkey_public_key);
+    // Length-prefixed so the credential ID / policy boundary can't shift
+    payload.extend_from_slice(&(credential_id.len() as u32).to_le_bytes());
+    payload.extend_from_slice(credential_id);
+    payload.extend_from_slice(policy);
+    payload
+}
+
+/// Creates a sub-account of `parent`
+///
+/// The sub-account has the same owner as its parent. Authorizing the
+/// creation (a parent passkey signature over `sub_account_payload`) is up to
+/// the caller.
+///
+/// # Parameters
+/// - `parent`: The parent account
+/// - `parent_address`: The parent account's address
+/// - `index`: Tells the parent's sub-accounts apart (part of the sub-account's address)
+/// - `passkey_public_key`, `credential_id`, `policy`: As for `AttestaAccount::new`
+/// - `created_at`: The current timestamp
+///
+/// # Returns
+/// The new sub-account, or `SubAccountError::ParentIsSubAccount`
+pub fn new_sub_account(
+    parent: &AttestaAccount,
+    parent_address: Pubkey,
+    index: u8,
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Vec<u8>,
+    created_at: i64,
+) -> Result<AttestaAccount, SubAccountError> {
+    if parent.parent.is_some() {
+        return Err(SubAccountError::ParentIsSubAccount);
+    }
+
+    let mut account = AttestaAccount::new(parent.owner, passkey_public_key, credential_id, policy, created_at);
+    account.parent = Some(parent_address);
+    account.sub_account_index = index;
+    Ok(account)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent() -> AttestaAccount {
+        AttestaAccount::new(Pubkey::new_unique(), [1u8; 64], b"phone".to_vec(), vec![], 100)
+    }
+
+    #[test]
+    fn test_new_sub_account_links_to_parent() {
+        let parent = parent();
+        let parent_address = Pubkey::new_unique();
+
+        let hot = new_sub_account(&parent, parent_address, 3, [2u8; 64], b"hot".to_vec(), vec![], 200).unwrap();
+
+        assert_eq!(hot.owner, parent.owner);
+        assert_eq!(hot.parent, Some(parent_address));
+        assert_eq!(hot.sub_account_index, 3);
+        assert_eq!(hot.credential_id, b"hot".to_vec());
+    }
+
+    #[test]
+    fn test_sub_account_cannot_be_a_parent() {
+        let parent = parent();
+        let hot = new_sub_account(&parent, Pubkey::new_unique(), 0, [2u8; 64], b"hot".to_vec(), vec![], 200).unwrap();
+
+        assert_eq!(
+            new_sub_account(&hot, Pubkey::new_unique(), 0, [3u8; 64], b"hotter".to_vec(), vec![], 300),
+            Err(SubAccountError::ParentIsSubAccount)
+        );
+    }
+
+    #[test]
+    fn test_payload_commits_to_every_field() {
+        let payload = sub_account_payload(1, &[2u8; 64], b"cred", b"policy");
+
+        assert_ne!(payload, sub_account_payload(2, &[2u8; 64], b"cred", b"policy"));
+        assert_ne!(payload, sub_account_payload(1, &[3u8; 64], b"cred", b"policy"));
+        assert_ne!(payload, sub_account_payload(1, &[2u8; 64], b"credp", b"olicy"));
+        assert_ne!(payload, sub_account_payload(1, &[2u8; 64], b"cred", b"other"));
+    }
+}
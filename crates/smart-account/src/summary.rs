@@ -0,0 +1,568 @@
+//! What an account enforces right now, in one report
+//!
+//! Answering "what protections does this account have?" otherwise means
+//! reading the auth mode, the WebAuthn profile, every policy, the passkey
+//! registry and the lockout state separately, and decoding the policies by
+//! hand. `AttestaAccount::security_summary` gathers them into a
+//! `SecuritySummary`, whose `Display` is the plain-text report support and
+//! security reviewers read. With the `serde` feature it also serializes,
+//! for dashboards.
+//!
+//! The summary describes; it doesn't judge. Nothing here says whether an
+//! account is configured well.
+
+use std::fmt;
+use core_crypto::WebAuthnVerificationProfile;
+use recovery::{Amount, Policy, PolicyType};
+use crate::account::AttestaAccount;
+use crate::auth_mode::AuthMode;
+use crate::emergency::EmergencyOverride;
+
+/// What an account enforces at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SecuritySummary {
+    /// The time the summary was taken at (Unix timestamp)
+    pub taken_at: i64,
+
+    /// Which signers may authorize executions
+    pub auth_mode: AuthMode,
+
+    /// Whether `auth_mode` is locked until a recovery
+    pub auth_mode_locked: bool,
+
+    /// `WebAuthnVerificationProfile::to_bits` of the optional WebAuthn checks
+    pub webauthn_checks: u8,
+
+    /// Relayers allowed to submit executions (0 lets anyone)
+    pub executors: usize,
+
+    /// Each rule the account's policies apply, in order, with a composite's
+    /// rules in its place
+    pub policies: Vec<PolicySummary>,
+
+    /// Every passkey the account knows, primary first
+    pub passkeys: Vec<PasskeySummary>,
+
+    pub lockout: LockoutSummary,
+
+    /// The two-passkey override of time locks, if it's on (see `emergency`)
+    pub emergency_override: Option<EmergencyOverride>,
+
+    /// How guardians recover the account (`None` without a passkey
+    /// registry, when it can't be recovered)
+    pub recovery: Option<RecoverySummary>,
+
+    pub pending: PendingSummary,
+}
+
+/// One policy rule and the parameters that matter when reviewing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type", rename_all = "snake_case"))]
+pub enum PolicySummary {
+    Open,
+
+    /// A cap per transaction
+    SpendingLimit { max_lamports: u64, mint_limits: usize },
+
+    /// A cap per window of `window_seconds`
+    DailyLimit { max_lamports: u64, window_seconds: u32, mint_limits: usize },
+
+    /// Signers that must all sign
+    MultiSig { signers: usize },
+
+    /// Nothing runs before `unlock_at`
+    TimeLocked { unlock_at: i64, unlocked: bool },
+
+    /// Transfers only to listed destinations
+    DestinationAllowlist { destinations: usize },
+
+    /// Destination groups bound to a passkey, and whether everything else is too
+    CredentialBinding { bindings: usize, default_bound: bool },
+
+    /// A budget per destination per window of `window_seconds`
+    PerDestinationLimit { destinations: usize, default_max_amount: u64, window_seconds: u32 },
+
+    /// Transfers over `threshold_lamports` are announced and wait `delay_seconds`
+    Vault { threshold_lamports: u64, delay_seconds: u32 },
+
+    /// A stored policy that doesn't decode (it denies every transaction)
+    Malformed,
+}
+
+/// Whether a passkey is the account's primary or one of its guardians
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum PasskeyRole {
+    Primary,
+    Guardian,
+}
+
+/// One registered passkey
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PasskeySummary {
+    pub role: PasskeyRole,
+
+    /// The name it was registered under (empty for an account that never
+    /// had a registry)
+    pub name: String,
+
+    pub enabled: bool,
+
+    /// When it was added (Unix timestamp)
+    pub added_at: i64,
+}
+
+/// Failed-signature lockout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LockoutSummary {
+    /// Failures in a row that lock the account (0 when lockout is off)
+    pub threshold: u8,
+
+    /// Failures since the last good signature
+    pub failed_attempts: u8,
+
+    /// When the account unlocks, if it's locked now
+    pub locked_until: Option<i64>,
+}
+
+/// Guardian recovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RecoverySummary {
+    /// Approvals a recovery needs
+    pub threshold: u8,
+
+    /// Enabled passkeys whose approval counts
+    pub guardians: usize,
+
+    /// When a recovery drill last succeeded, if one has
+    pub last_drill_at: Option<i64>,
+}
+
+/// Things started but not finished
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PendingSummary {
+    /// A recovery waiting for approvals or its delay
+    pub recovery: Option<PendingRecovery>,
+
+    /// Whether a recovery drill is under way
+    pub drill: bool,
+
+    /// Proposals waiting for approvals, as counted by the caller (see
+    /// `SecuritySummary::with_open_proposals`)
+    pub open_proposals: usize,
+}
+
+/// A recovery in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PendingRecovery {
+    /// Approvals that currently count
+    pub approvals: usize,
+
+    /// When it can be finalized, once it has enough approvals
+    pub ready_at: Option<i64>,
+}
+
+impl AttestaAccount {
+    /// Reports what this account enforces at `now`
+    ///
+    /// Open proposals live in their own accounts, so they're counted as 0
+    /// here; a caller that has listed them adds the count with
+    /// `SecuritySummary::with_open_proposals`.
+    pub fn security_summary(&self, now: i64) -> SecuritySummary {
+        let registry = self.passkey_registry().ok().flatten();
+        let passkeys = match &registry {
+            Some(registry) => std::iter::once((PasskeyRole::Primary, &registry.primary))
+                .chain(registry.additional.iter().map(|entry| (PasskeyRole::Guardian, entry)))
+                .map(|(role, entry)| PasskeySummary {
+                    role,
+                    name: String::from_utf8_lossy(&entry.name).into_owned(),
+                    enabled: entry.enabled,
+                    added_at: entry.added_at,
+                })
+                .collect(),
+            None => vec![PasskeySummary { role: PasskeyRole::Primary, name: String::new(), enabled: true, added_at: self.created_at }],
+        };
+
+        SecuritySummary {
+            taken_at: now,
+            auth_mode: self.settings.auth_mode,
+            auth_mode_locked: self.settings.auth_mode_locked,
+            webauthn_checks: self.settings.webauthn_profile.to_bits(),
+            executors: self.settings.authorized_executors.len(),
+            policies: self.policies().into_iter().flat_map(|bytes| policy_summaries(bytes, now)).collect(),
+            passkeys,
+            lockout: LockoutSummary {
+                threshold: self.settings.lockout_threshold,
+                failed_attempts: self.failed_auth_count,
+                locked_until: self.is_locked_out(now).then_some(self.locked_until),
+            },
+            emergency_override: self.emergency_override.is_enabled().then_some(self.emergency_override),
+            recovery: registry.as_ref().map(|registry| RecoverySummary {
+                threshold: registry.recovery_threshold,
+                guardians: registry.enabled_passkeys().len(),
+                last_drill_at: (self.last_drill_at != 0).then_some(self.last_drill_at),
+            }),
+            pending: PendingSummary {
+                recovery: self.pending_recovery.as_ref().map(|request| PendingRecovery {
+                    approvals: registry.as_ref().map_or(0, |registry| registry.count_valid_approvals(&request.approvals)),
+                    ready_at: request.ready_at(),
+                }),
+                drill: self.pending_drill.is_some(),
+                open_proposals: 0,
+            },
+        }
+    }
+}
+
+impl SecuritySummary {
+    /// The same summary, with `count` proposals waiting for approvals
+    pub fn with_open_proposals(mut self, count: usize) -> Self {
+        self.pending.open_proposals = count;
+        self
+    }
+
+    /// Whether the account's passkeys can authorize executions at all
+    pub fn passkeys_can_execute(&self) -> bool {
+        self.auth_mode.allows_passkey() && self.lockout.locked_until.is_none()
+    }
+}
+
+/// The rules one stored policy applies
+fn policy_summaries(bytes: &[u8], now: i64) -> Vec<PolicySummary> {
+    match Policy::from_bytes(bytes) {
+        Ok(policy) => match policy.rules() {
+            Some(rules) => rules.iter().map(|rule| policy_summary(rule, now)).collect(),
+            None => vec![policy_summary(&policy, now)],
+        },
+        Err(_) => vec![PolicySummary::Malformed],
+    }
+}
+
+fn policy_summary(policy: &Policy, now: i64) -> PolicySummary {
+    let mint_limits = || policy.mint_limits().map_or(0, |limits| limits.limits.len());
+    let addresses = || policy.config.len() / 32;
+    let summary = match policy.policy_type {
+        PolicyType::Open => Some(PolicySummary::Open),
+        PolicyType::SpendingLimit => policy
+            .config
+            .get(..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(|bytes| PolicySummary::SpendingLimit { max_lamports: u64::from_le_bytes(bytes), mint_limits: mint_limits() }),
+        PolicyType::DailyLimit => policy.daily_limit_config().map(|limit| PolicySummary::DailyLimit {
+            max_lamports: limit.max_amount,
+            window_seconds: limit.window_seconds,
+            mint_limits: mint_limits(),
+        }),
+        PolicyType::MultiSig => Some(PolicySummary::MultiSig { signers: addresses() }),
+        PolicyType::TimeLocked => policy.config.as_slice().try_into().ok().map(|bytes| {
+            let unlock_at = i64::from_le_bytes(bytes);
+            PolicySummary::TimeLocked { unlock_at, unlocked: now >= unlock_at }
+        }),
+        PolicyType::DestinationAllowlist => Some(PolicySummary::DestinationAllowlist { destinations: addresses() }),
+        // Composites are flattened before they get here; one nested further is malformed
+        PolicyType::Composite => None,
+        PolicyType::CredentialBinding => policy.credential_bindings().map(|bindings| PolicySummary::CredentialBinding {
+            bindings: bindings.bindings.len(),
+            default_bound: bindings.default.is_some(),
+        }),
+        PolicyType::PerDestinationLimit => policy.destination_limits().map(|limits| PolicySummary::PerDestinationLimit {
+            destinations: limits.limits.len(),
+            default_max_amount: limits.default_max_amount,
+            window_seconds: limits.window_seconds,
+        }),
+        PolicyType::Vault => policy.vault_config().map(|vault| PolicySummary::Vault {
+            threshold_lamports: vault.threshold_lamports,
+            delay_seconds: vault.delay_seconds,
+        }),
+    };
+    summary.unwrap_or(PolicySummary::Malformed)
+}
+
+/// Names of the WebAuthn checks in `bits`, in bit order
+fn webauthn_check_names(bits: u8) -> Vec<&'static str> {
+    [
+        (WebAuthnVerificationProfile::RP_ID, "RP ID"),
+        (WebAuthnVerificationProfile::ORIGIN, "origin"),
+        (WebAuthnVerificationProfile::USER_PRESENT, "user presence"),
+        (WebAuthnVerificationProfile::USER_VERIFIED, "user verification"),
+        (WebAuthnVerificationProfile::SIGN_COUNT, "sign count"),
+        (WebAuthnVerificationProfile::LOW_S, "low S"),
+    ]
+    .into_iter()
+    .filter(|(bit, _)| bits & bit != 0)
+    .map(|(_, name)| name)
+    .collect()
+}
+
+impl fmt::Display for PolicySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PolicySummary::Open => write!(f, "open"),
+            PolicySummary::SpendingLimit { max_lamports, mint_limits } => {
+                write!(f, "spending limit of {} per transaction, {} mint limits", Amount::from_lamports(max_lamports), mint_limits)
+            }
+            PolicySummary::DailyLimit { max_lamports, window_seconds, mint_limits } => write!(
+                f,
+                "limit of {} per {}s window, {} mint limits",
+                Amount::from_lamports(max_lamports),
+                window_seconds,
+                mint_limits
+            ),
+            PolicySummary::MultiSig { signers } => write!(f, "multi-sig of {} signers", signers),
+            PolicySummary::TimeLocked { unlock_at, unlocked: true } => write!(f, "time lock, unlocked at {}", unlock_at),
+            PolicySummary::TimeLocked { unlock_at, unlocked: false } => write!(f, "time lock until {}", unlock_at),
+            PolicySummary::DestinationAllowlist { destinations } => write!(f, "allowlist of {} destinations", destinations),
+            PolicySummary::CredentialBinding { bindings, default_bound } => write!(
+                f,
+                "{} credential bindings, {}",
+                bindings,
+                if default_bound { "other destinations bound" } else { "other destinations open" }
+            ),
+            PolicySummary::PerDestinationLimit { destinations, default_max_amount, window_seconds } => write!(
+                f,
+                "per-destination limits for {} destinations per {}s window, {} for others",
+                destinations,
+                window_seconds,
+                Amount::from_lamports(default_max_amount)
+            ),
+            PolicySummary::Vault { threshold_lamports, delay_seconds } => write!(
+                f,
+                "vault, transfers over {} wait {}s after being announced",
+                Amount::from_lamports(threshold_lamports),
+                delay_seconds
+            ),
+            PolicySummary::Malformed => write!(f, "malformed (denies everything)"),
+        }
+    }
+}
+
+impl fmt::Display for SecuritySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let auth_mode = match self.auth_mode {
+            AuthMode::PasskeyOnly => "passkey only",
+            AuthMode::OwnerOrPasskey => "owner or passkey",
+            AuthMode::OwnerOnly => "owner only",
+        };
+        writeln!(f, "Auth mode: {}{}", auth_mode, if self.auth_mode_locked { " (locked)" } else { "" })?;
+
+        let checks = webauthn_check_names(self.webauthn_checks);
+        writeln!(f, "WebAuthn checks: {}", if checks.is_empty() { "none".to_string() } else { checks.join(", ") })?;
+
+        match self.executors {
+            0 => writeln!(f, "Executors: anyone")?,
+            count => writeln!(f, "Executors: {} listed", count)?,
+        }
+
+        if self.policies.is_empty() {
+            writeln!(f, "Policies: none")?;
+        } else {
+            writeln!(f, "Policies:")?;
+            for policy in &self.policies {
+                writeln!(f, "  - {}", policy)?;
+            }
+        }
+
+        let enabled = self.passkeys.iter().filter(|passkey| passkey.enabled).count();
+        writeln!(f, "Passkeys: {} of {} enabled", enabled, self.passkeys.len())?;
+        for passkey in &self.passkeys {
+            let role = match passkey.role {
+                PasskeyRole::Primary => "primary",
+                PasskeyRole::Guardian => "guardian",
+            };
+            write!(f, "  - {} {:?}, added at {}", role, passkey.name, passkey.added_at)?;
+            writeln!(f, "{}", if passkey.enabled { "" } else { " (disabled)" })?;
+        }
+
+        match self.lockout {
+            LockoutSummary { threshold: 0, .. } => writeln!(f, "Lockout: off")?,
+            LockoutSummary { locked_until: Some(until), .. } => writeln!(f, "Lockout: locked until {}", until)?,
+            LockoutSummary { threshold, failed_attempts, .. } => {
+                writeln!(f, "Lockout: after {} failures, {} so far", threshold, failed_attempts)?
+            }
+        }
+
+        if let Some(config) = &self.emergency_override {
+            write!(
+                f,
+                "Emergency override: up to {} past time locks, once every {} seconds",
+                config.max_lamports, config.period_seconds
+            )?;
+            match config.last_used_at {
+                0 => writeln!(f, ", never used")?,
+                at => writeln!(f, ", last used at {}", at)?,
+            }
+        }
+
+        match &self.recovery {
+            None => writeln!(f, "Recovery: not set up")?,
+            Some(recovery) => {
+                write!(f, "Recovery: {} of {} guardians", recovery.threshold, recovery.guardians)?;
+                match recovery.last_drill_at {
+                    Some(at) => writeln!(f, ", last drilled at {}", at)?,
+                    None => writeln!(f, ", never drilled")?,
+                }
+            }
+        }
+
+        let mut pending = Vec::new();
+        if let Some(recovery) = &self.pending.recovery {
+            pending.push(match recovery.ready_at {
+                Some(at) => format!("recovery with {} approvals, ready at {}", recovery.approvals, at),
+                None => format!("recovery with {} approvals", recovery.approvals),
+            });
+        }
+        if self.pending.drill {
+            pending.push("recovery drill".to_string());
+        }
+        if self.pending.open_proposals > 0 {
+            pending.push(format!("{} open proposals", self.pending.open_proposals));
+        }
+        write!(f, "Pending: {}", if pending.is_empty() { "nothing".to_string() } else { pending.join(", ") })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use recovery::multi_passkey::MultiPasskey;
+    use recovery::{CredentialBindings, MintLimit, MintLimits};
+    use solana_program::pubkey::Pubkey;
+    use crate::social_recovery::RecoveryRequest;
+
+    fn account() -> AttestaAccount {
+        AttestaAccount::new(Pubkey::new_unique(), TestPasskey::new(1).public_key(), b"phone".to_vec(), vec![], 100)
+    }
+
+    #[test]
+    fn test_new_account_enforces_nothing_but_its_passkey() {
+        let summary = account().security_summary(1_000);
+        assert_eq!(summary.policies, vec![]);
+        assert_eq!(summary.recovery, None);
+        assert!(summary.passkeys_can_execute());
+        assert_eq!(
+            summary.to_string(),
+            "Auth mode: passkey only\n\
+             WebAuthn checks: none\n\
+             Executors: anyone\n\
+             Policies: none\n\
+             Passkeys: 1 of 1 enabled\n  \
+               - primary \"\", added at 100\n\
+             Lockout: off\n\
+             Recovery: not set up\n\
+             Pending: nothing"
+        );
+    }
+
+    #[test]
+    fn test_hardened_account() {
+        let mut account = account();
+        let mint = Pubkey::new_unique();
+        let limit = Policy::spending_limit(Amount::from_lamports(2_000_000_000)).with_mint_limits(MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint, max_amount: 500, decimals: 6 }],
+        });
+        let composite = Policy::composite(vec![
+            Policy::time_locked(2_000),
+            Policy::destination_allowlist(vec![Pubkey::new_unique(), Pubkey::new_unique()]),
+        ]);
+        account.set_policies(vec![limit.to_bytes().unwrap(), composite.to_bytes().unwrap()]);
+        account.settings.webauthn_profile = WebAuthnVerificationProfile::standard();
+        account.settings.auth_mode_locked = true;
+        account.settings.lockout_threshold = 3;
+        account.failed_auth_count = 1;
+        account.settings.authorized_executors = vec![Pubkey::new_unique()];
+        account.emergency_override = EmergencyOverride { max_lamports: 100, period_seconds: 86_400, last_used_at: 0 };
+
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(TestPasskey::new(2).public_key(), b"laptop".to_vec(), "Laptop".to_string(), 200).unwrap();
+        registry.add_passkey(TestPasskey::new(3).public_key(), b"key".to_vec(), "Key".to_string(), 300).unwrap();
+        registry.recovery_threshold = 2;
+        registry.additional[1].enabled = false;
+        account.set_passkey_registry(&registry).unwrap();
+        account.last_drill_at = 400;
+
+        let summary = account.security_summary(1_000);
+        assert_eq!(summary.policies[1], PolicySummary::TimeLocked { unlock_at: 2_000, unlocked: false });
+        assert_eq!(summary.recovery, Some(RecoverySummary { threshold: 2, guardians: 2, last_drill_at: Some(400) }));
+        assert_eq!(
+            summary.to_string(),
+            "Auth mode: passkey only (locked)\n\
+             WebAuthn checks: RP ID, origin, user presence\n\
+             Executors: 1 listed\n\
+             Policies:\n  \
+               - spending limit of 2.00 SOL per transaction, 1 mint limits\n  \
+               - time lock until 2000\n  \
+               - allowlist of 2 destinations\n\
+             Passkeys: 2 of 3 enabled\n  \
+               - primary \"Primary\", added at 100\n  \
+               - guardian \"Laptop\", added at 200\n  \
+               - guardian \"Key\", added at 300 (disabled)\n\
+             Lockout: after 3 failures, 1 so far\n\
+             Emergency override: up to 100 past time locks, once every 86400 seconds, never used\n\
+             Recovery: 2 of 2 guardians, last drilled at 400\n\
+             Pending: nothing"
+        );
+    }
+
+    #[test]
+    fn test_locked_out_account_in_recovery() {
+        let mut account = account();
+        let bindings = CredentialBindings { bindings: vec![], default: Some([9; 32]) };
+        account.set_policies(vec![Policy::credential_binding(bindings).to_bytes().unwrap(), vec![0xff]]);
+        account.settings.auth_mode = AuthMode::OwnerOrPasskey;
+        account.settings.lockout_threshold = 2;
+        account.record_failed_auth(990);
+        account.record_failed_auth(990);
+        let registry = MultiPasskey::new(TestPasskey::new(1).public_key(), b"phone".to_vec(), "Phone".to_string(), 100, 1, 4);
+        account.set_passkey_registry(&registry).unwrap();
+        let mut request = RecoveryRequest::new([4; 64], b"new".to_vec(), 950);
+        request.threshold_met_at = Some(960);
+        account.pending_recovery = Some(request);
+        account.pending_drill = Some(RecoveryRequest::new([5; 64], b"drill".to_vec(), 970));
+
+        let summary = account.security_summary(1_000).with_open_proposals(3);
+        assert!(!summary.passkeys_can_execute());
+        assert_eq!(
+            summary.to_string(),
+            format!(
+                "Auth mode: owner or passkey\n\
+                 WebAuthn checks: none\n\
+                 Executors: anyone\n\
+                 Policies:\n  \
+                   - 0 credential bindings, other destinations bound\n  \
+                   - malformed (denies everything)\n\
+                 Passkeys: 1 of 1 enabled\n  \
+                   - primary \"Phone\", added at 100\n\
+                 Lockout: locked until {}\n\
+                 Recovery: 1 of 1 guardians, never drilled\n\
+                 Pending: recovery with 0 approvals, ready at {}, recovery drill, 3 open proposals",
+                account.locked_until,
+                960 + crate::social_recovery::RECOVERY_DELAY_SECONDS,
+            )
+        );
+
+        // Once the lockout runs out, the passkeys work again
+        let summary = account.security_summary(account.locked_until);
+        assert_eq!(summary.lockout.locked_until, None);
+        assert!(summary.passkeys_can_execute());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serializes_for_dashboards() {
+        let mut account = account();
+        account.set_policies(vec![Policy::time_locked(500).to_bytes().unwrap()]);
+        let json = serde_json::to_value(account.security_summary(1_000)).unwrap();
+        assert_eq!(json["auth_mode"], "PasskeyOnly");
+        assert_eq!(json["policies"][0], serde_json::json!({ "type": "time_locked", "unlock_at": 500, "unlocked": true }));
+        assert_eq!(json["passkeys"][0]["role"], "primary");
+        assert_eq!(json["pending"]["open_proposals"], 0);
+    }
+}
@@ -0,0 +1,96 @@
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+
+pub use attesta_types::transaction::{TokenTransfer, TOKEN_TRANSFER_PREFIX};
+
+/// The SPL Token program
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
+/// The SPL Associated Token Account program
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// The SPL Memo program, which `execute` can post a payment's memo through
+pub const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// Derives the associated token account for `account` and `mint`
+///
+/// This is the address to give someone sending SPL tokens to an Attesta
+/// account. It only exists once it has been created (for example by the
+/// sender, with the Associated Token Account program).
+pub fn derive_associated_token_address(account: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[account.as_ref(), TOKEN_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// SPL Token instruction index for `TransferChecked`
+const TRANSFER_CHECKED: u8 = 12;
+
+/// Whether `transfer` sends the tokens back to `account` (the Attesta PDA)
+///
+/// Covers the PDA itself and its associated token account for the mint.
+pub fn is_self_transfer(transfer: &TokenTransfer, account: &Pubkey) -> bool {
+    transfer.destination_ata == *account
+        || transfer.destination_ata == derive_associated_token_address(account, &transfer.mint)
+}
+
+/// Builds the SPL Token `transfer_checked` instruction for `transfer`
+///
+/// # Parameters
+/// - `transfer`: The signed transfer
+/// - `source`: The token account the tokens come from
+/// - `authority`: The owner of `source` (the Attesta PDA, signing via seeds)
+pub fn transfer_checked_instruction(transfer: &TokenTransfer, source: &Pubkey, authority: &Pubkey) -> Instruction {
+    let mut data = Vec::with_capacity(10);
+    data.push(TRANSFER_CHECKED);
+    data.extend_from_slice(&transfer.amount.to_le_bytes());
+    data.push(transfer.decimals);
+
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*source, false),
+            AccountMeta::new_readonly(transfer.mint, false),
+            AccountMeta::new(transfer.destination_ata, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer() -> TokenTransfer {
+        TokenTransfer {
+            mint: Pubkey::new_unique(),
+            amount: 1_500_000,
+            decimals: 6,
+            destination_ata: Pubkey::new_unique(),
+        }
+    }
+
+    #[test]
+    fn test_transfer_checked_instruction_layout() {
+        let transfer = transfer();
+        let source = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+
+        let ix = transfer_checked_instruction(&transfer, &source, &authority);
+
+        assert_eq!(ix.program_id, TOKEN_PROGRAM_ID);
+        assert_eq!(ix.data[0], TRANSFER_CHECKED);
+        assert_eq!(u64::from_le_bytes(ix.data[1..9].try_into().unwrap()), 1_500_000);
+        assert_eq!(ix.data[9], 6);
+
+        let keys: Vec<Pubkey> = ix.accounts.iter().map(|a| a.pubkey).collect();
+        assert_eq!(keys, vec![source, transfer.mint, transfer.destination_ata, authority]);
+        assert!(ix.accounts[3].is_signer);
+    }
+}
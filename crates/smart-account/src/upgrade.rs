@@ -0,0 +1,281 @@
+//! Pinning the program version an account executes under
+//!
+//! A program upgrade can change what `execute` does without the account's
+//! passkey being involved. An account that opts in (by setting
+//! `AccountSettings::pinned_program_version`) records the version hash of
+//! the program it trusts, and `execute` refuses to run under any other. Once
+//! the owner has looked at an upgrade, they sign its version hash with
+//! `UPGRADE_ACKNOWLEDGE_ACTION` to pin the new version.
+//!
+//! A deployment may also be built without some groups of instructions
+//! (`ProgramFeature`). `get_program_features` says which it has, so clients
+//! can tell a missing feature from a failed instruction.
+
+use attesta_types::consts::HASH_LEN;
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::Digest;
+use thiserror::Error;
+use core_crypto::{domain_hasher, CryptoError, Domain, WebAuthnSignature};
+use crate::account::AttestaAccount;
+use crate::auth::authorize_action;
+
+/// Action name a passkey signs, over the new version hash, to accept an upgrade
+pub const UPGRADE_ACKNOWLEDGE_ACTION: &[u8] = b"acknowledge_upgrade";
+
+/// Errors from checking or acknowledging the program version
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum UpgradeError {
+    #[error("Acknowledgment signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The account doesn't pin a program version")]
+    NotPinned,
+
+    #[error("The program changed since the account pinned its version; the owner must acknowledge the upgrade")]
+    VersionMismatch { pinned: [u8; HASH_LEN], deployed: [u8; HASH_LEN] },
+}
+
+/// What a deployed program says it is, from `get_program_version`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProgramVersion {
+    /// The program's declared version (its crate version)
+    pub version: String,
+
+    /// Build features that change how instructions behave, in a fixed order
+    pub features: Vec<String>,
+}
+
+impl ProgramVersion {
+    pub fn new(version: &str, features: &[&str]) -> Self {
+        Self {
+            version: version.to_string(),
+            features: features.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+
+    /// The hash accounts pin and owners sign to acknowledge an upgrade
+    pub fn hash(&self) -> [u8; HASH_LEN] {
+        let mut hasher = domain_hasher(Domain::ProgramVersion);
+        // Length-prefixed, so no two lists of strings hash the same
+        for part in std::iter::once(&self.version).chain(&self.features) {
+            hasher.update((part.len() as u32).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Encodes the version for `set_return_data`
+    pub fn to_return_data(&self) -> Vec<u8> {
+        // Serializing into a Vec can't fail
+        borsh::to_vec(self).unwrap_or_default()
+    }
+
+    /// Decodes a version from `get_program_version`'s return data
+    ///
+    /// # Returns
+    /// `None` if the data isn't a version
+    pub fn from_return_data(data: &[u8]) -> Option<Self> {
+        borsh::from_slice(data).ok()
+    }
+}
+
+/// A group of instructions a deployment can be built without
+///
+/// Each is a cargo feature of the program, on by default. Without it the
+/// group's instructions fail with `FeatureNotEnabled` before doing anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramFeature {
+    /// Claim tickets: `claim`
+    Claims,
+
+    /// Handing inactive accounts to a beneficiary: `configure_inheritance`,
+    /// `heartbeat` and `claim_inheritance`
+    Inheritance,
+
+    /// Sponsor pools paying new accounts' rent: `create_sponsor_pool`,
+    /// `sponsored_initialize` and `withdraw_pool`
+    Sponsorship,
+}
+
+impl ProgramFeature {
+    /// Every feature, in the order `get_program_features` lists them
+    pub const ALL: [ProgramFeature; 3] = [ProgramFeature::Claims, ProgramFeature::Inheritance, ProgramFeature::Sponsorship];
+
+    /// The name `get_program_features` reports (the cargo feature is
+    /// `feature-` followed by it)
+    pub const fn name(self) -> &'static str {
+        match self {
+            ProgramFeature::Claims => "claims",
+            ProgramFeature::Inheritance => "inheritance",
+            ProgramFeature::Sponsorship => "sponsorship",
+        }
+    }
+}
+
+/// The features a deployed program was built with, from `get_program_features`
+///
+/// Names rather than flags, so a client can read what a newer program
+/// reports even if it doesn't know every feature.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProgramFeatures {
+    /// Names of the enabled features (`ProgramFeature::name`)
+    pub enabled: Vec<String>,
+}
+
+impl ProgramFeatures {
+    pub fn new(enabled: &[ProgramFeature]) -> Self {
+        Self { enabled: enabled.iter().map(|feature| feature.name().to_string()).collect() }
+    }
+
+    /// Every feature, as programs from before `get_program_features` had
+    pub fn all() -> Self {
+        Self::new(&ProgramFeature::ALL)
+    }
+
+    pub fn is_enabled(&self, feature: ProgramFeature) -> bool {
+        self.enabled.iter().any(|name| name == feature.name())
+    }
+
+    /// Encodes the features for `set_return_data`
+    pub fn to_return_data(&self) -> Vec<u8> {
+        // Serializing into a Vec can't fail
+        borsh::to_vec(self).unwrap_or_default()
+    }
+
+    /// Decodes features from `get_program_features`' return data
+    ///
+    /// # Returns
+    /// `None` if the data isn't a feature list
+    pub fn from_return_data(data: &[u8]) -> Option<Self> {
+        borsh::from_slice(data).ok()
+    }
+}
+
+/// Checks that the account may execute under the program version `deployed`
+///
+/// Accounts that don't pin a version accept any.
+pub fn check_program_version(account: &AttestaAccount, deployed: &[u8; HASH_LEN]) -> Result<(), UpgradeError> {
+    match account.settings.pinned_program_version {
+        Some(pinned) if pinned != *deployed => Err(UpgradeError::VersionMismatch { pinned, deployed: *deployed }),
+        _ => Ok(()),
+    }
+}
+
+/// Pins the account to the program version `deployed`
+///
+/// # Parameters
+/// - `webauthn_sig`: The owner's signature over `UPGRADE_ACKNOWLEDGE_ACTION` for `deployed`
+/// - `deployed`: The hash of the version now running (`ProgramVersion::hash`)
+pub fn acknowledge_upgrade(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    deployed: [u8; HASH_LEN],
+) -> Result<(), UpgradeError> {
+    // Acknowledging isn't a way to opt in: that's a settings change
+    if account.settings.pinned_program_version.is_none() {
+        return Err(UpgradeError::NotPinned);
+    }
+    authorize_action(account, webauthn_sig, nonce, UPGRADE_ACKNOWLEDGE_ACTION, &deployed)?;
+    account.settings.pinned_program_version = Some(deployed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use solana_program::pubkey::Pubkey;
+    use crate::auth::action_message_hash;
+
+    fn sign_ack(passkey: &mut TestPasskey, account: &AttestaAccount, deployed: &[u8; HASH_LEN]) -> (WebAuthnSignature, u64) {
+        let nonce = account.nonce + 1;
+        let message_hash = action_message_hash(UPGRADE_ACKNOWLEDGE_ACTION, deployed);
+        (passkey.sign(&compute_challenge(&account.owner, nonce, &message_hash)), nonce)
+    }
+
+    /// An account pinned to version 1.0.0
+    fn setup() -> (AttestaAccount, TestPasskey) {
+        let passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        account.settings.pinned_program_version = Some(ProgramVersion::new("1.0.0", &[]).hash());
+        (account, passkey)
+    }
+
+    #[test]
+    fn test_version_hash_covers_version_and_features() {
+        let base = ProgramVersion::new("1.0.0", &[]).hash();
+        assert_eq!(base, ProgramVersion::new("1.0.0", &[]).hash());
+        assert_ne!(base, ProgramVersion::new("1.0.1", &[]).hash());
+        assert_ne!(base, ProgramVersion::new("1.0.0", &["strict"]).hash());
+        assert_ne!(
+            ProgramVersion::new("1.0.0", &["ab", "c"]).hash(),
+            ProgramVersion::new("1.0.0", &["a", "bc"]).hash()
+        );
+
+        let version = ProgramVersion::new("1.0.0", &["strict"]);
+        assert_eq!(ProgramVersion::from_return_data(&version.to_return_data()), Some(version));
+        assert_eq!(ProgramVersion::from_return_data(&[1, 2]), None);
+    }
+
+    #[test]
+    fn test_program_features_round_trip() {
+        let some = ProgramFeatures::new(&[ProgramFeature::Claims]);
+        assert!(some.is_enabled(ProgramFeature::Claims));
+        assert!(!some.is_enabled(ProgramFeature::Sponsorship));
+        assert!(ProgramFeature::ALL.iter().all(|feature| ProgramFeatures::all().is_enabled(*feature)));
+
+        // A feature this build doesn't know about still decodes
+        let newer = ProgramFeatures { enabled: vec!["claims".to_string(), "teleport".to_string()] };
+        assert_eq!(ProgramFeatures::from_return_data(&newer.to_return_data()), Some(newer.clone()));
+        assert!(newer.is_enabled(ProgramFeature::Claims));
+        assert_eq!(ProgramFeatures::from_return_data(&[1, 2]), None);
+    }
+
+    #[test]
+    fn test_unpinned_account_accepts_any_version() {
+        let (mut account, _) = setup();
+        account.settings.pinned_program_version = None;
+        assert_eq!(check_program_version(&account, &ProgramVersion::new("9.9.9", &[]).hash()), Ok(()));
+    }
+
+    #[test]
+    fn test_upgrade_is_refused_until_acknowledged() {
+        let (mut account, mut passkey) = setup();
+        let pinned = ProgramVersion::new("1.0.0", &[]).hash();
+        let deployed = ProgramVersion::new("1.1.0", &[]).hash();
+
+        assert_eq!(check_program_version(&account, &pinned), Ok(()));
+        assert_eq!(
+            check_program_version(&account, &deployed),
+            Err(UpgradeError::VersionMismatch { pinned, deployed })
+        );
+
+        // A signature over a different version doesn't acknowledge this one
+        let (sig, nonce) = sign_ack(&mut passkey, &account, &pinned);
+        assert!(matches!(
+            acknowledge_upgrade(&mut account, sig, nonce, deployed),
+            Err(UpgradeError::Unauthorized(_))
+        ));
+        assert!(check_program_version(&account, &deployed).is_err());
+
+        let (sig, nonce) = sign_ack(&mut passkey, &account, &deployed);
+        acknowledge_upgrade(&mut account, sig, nonce, deployed).unwrap();
+        assert_eq!(account.nonce, nonce);
+        assert_eq!(check_program_version(&account, &deployed), Ok(()));
+        // Going back is an upgrade like any other
+        assert!(check_program_version(&account, &pinned).is_err());
+    }
+
+    #[test]
+    fn test_acknowledging_needs_a_pin() {
+        let (mut account, mut passkey) = setup();
+        account.settings.pinned_program_version = None;
+        let deployed = ProgramVersion::new("1.1.0", &[]).hash();
+
+        let (sig, nonce) = sign_ack(&mut passkey, &account, &deployed);
+        assert_eq!(acknowledge_upgrade(&mut account, sig, nonce, deployed), Err(UpgradeError::NotPinned));
+        assert_eq!(account.settings.pinned_program_version, None);
+        assert_eq!(account.nonce, 0);
+    }
+}
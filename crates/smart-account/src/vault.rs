@@ -0,0 +1,405 @@
+//! Large transfers announced ahead of time, under a `Vault` policy
+//!
+//! A vault lets transfers at or under its threshold run at once. `execute`
+//! turns a larger one into an announcement: the proof is verified and its
+//! nonce used up, and the program keeps the transaction in an announcement
+//! PDA keyed by that nonce, but nothing moves. Once the vault's delay has
+//! passed, submitting the same proof again to `execute_announced` runs it,
+//! checked against the account's settings and policies as they are then.
+//! Until it runs, any enabled passkey on the account can veto it, so a
+//! stolen passkey can't empty the vault before the owner notices.
+//!
+//! The proof isn't verified a second time (its nonce is spent, and its sign
+//! count has been recorded); it has to match the one that was announced.
+//! It stays good for `ANNOUNCEMENT_GRACE_PERIOD` after the delay, and an
+//! announcement left longer than that can only be vetoed.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use core_crypto::{CryptoError, WebAuthnSignature};
+use recovery::{credential_id_hash, Policy};
+use crate::account::AttestaAccount;
+use crate::auth::{authorize_action, resolve_signing_key, AuthorizationProof};
+use crate::execute::{evaluate_policy, record_transfer, DenyReason, PolicyResult};
+use crate::token::TokenTransfer;
+
+/// PDA seed prefix for announcements: `[ANNOUNCEMENT_SEED, attesta_account, nonce (LE)]`
+pub const ANNOUNCEMENT_SEED: &[u8] = b"announcement";
+
+/// Action name a passkey signs, over the announcement's address, to veto it
+pub const ANNOUNCEMENT_VETO_ACTION: &[u8] = b"veto_announcement";
+
+/// How long after its delay an announced transfer can still be executed (7 days)
+pub const ANNOUNCEMENT_GRACE_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+/// Errors from executing or vetoing an announced transfer
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum VaultError {
+    #[error("Veto signature rejected: {0}")]
+    Unauthorized(#[from] CryptoError),
+
+    #[error("The announced transfer can't execute before {executable_at}")]
+    TooEarly { executable_at: i64 },
+
+    #[error("The announced transfer expired at {expires_at}")]
+    Expired { expires_at: i64 },
+
+    #[error("The proof isn't the one the transfer was announced with")]
+    ProofMismatch,
+
+    #[error("The passkey that announced the transfer is no longer on the account")]
+    SignerRemoved,
+
+    #[error("The account is a sub-account and its parent wasn't provided")]
+    MissingParent,
+
+    #[error("Invalid announcement data")]
+    InvalidData,
+}
+
+/// A transfer waiting in an announcement PDA for its vault's delay
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// The transaction to execute, as `execute` takes it
+    pub transaction_data: Vec<u8>,
+
+    /// The proof's message hash (covering the transaction and its memo)
+    pub message_hash: [u8; 32],
+
+    /// The nonce the proof signed, used up by the announcement (the PDA's seed)
+    pub nonce: u64,
+
+    /// The credential ID that signed it, as the authenticator reported it
+    pub credential_id: Vec<u8>,
+
+    /// The proof's signature, which the proof submitted to execute it must repeat
+    pub signature: Vec<u8>,
+
+    /// Who paid for the announcement PDA, and gets the rent back when it closes
+    pub rent_payer: Pubkey,
+
+    /// When it was announced (Unix timestamp)
+    pub announced_at: i64,
+
+    /// Unix timestamp before which it can't execute
+    pub executable_at: i64,
+}
+
+impl Announcement {
+    /// The announcement of `transaction_data`, signed with `proof`
+    ///
+    /// # Parameters
+    /// - `proof`: The verified proof `execute` answered with `PolicyResult::Announced`
+    /// - `executable_at`: The time `PolicyResult::Announced` gave
+    /// - `rent_payer`: Who pays for the announcement PDA
+    /// - `now`: The current Unix timestamp
+    pub fn new(transaction_data: Vec<u8>, proof: &AuthorizationProof, executable_at: i64, rent_payer: Pubkey, now: i64) -> Self {
+        Self {
+            transaction_data,
+            message_hash: proof.message_hash,
+            nonce: proof.nonce,
+            credential_id: proof.webauthn_sig.credential_id.clone(),
+            signature: proof.webauthn_sig.signature.clone(),
+            rent_payer,
+            announced_at: now,
+            executable_at,
+        }
+    }
+
+    /// Bytes the announcement takes when serialized
+    pub fn serialized_size(transaction_data_len: usize, credential_id_len: usize, signature_len: usize) -> usize {
+        4 + transaction_data_len // transaction_data
+            + 32                 // message_hash
+            + 8                  // nonce
+            + 4 + credential_id_len
+            + 4 + signature_len
+            + 32                 // rent_payer
+            + 8                  // announced_at
+            + 8                  // executable_at
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, VaultError> {
+        borsh::to_vec(self).map_err(|_| VaultError::InvalidData)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, VaultError> {
+        borsh::from_slice(data).map_err(|_| VaultError::InvalidData)
+    }
+
+    /// The first time it can no longer execute
+    pub fn expires_at(&self) -> i64 {
+        self.executable_at.saturating_add(ANNOUNCEMENT_GRACE_PERIOD)
+    }
+
+    /// Checks that `now` is between `executable_at` and `expires_at`
+    pub fn check_window(&self, now: i64) -> Result<(), VaultError> {
+        if now < self.executable_at {
+            return Err(VaultError::TooEarly { executable_at: self.executable_at });
+        }
+        if now >= self.expires_at() {
+            return Err(VaultError::Expired { expires_at: self.expires_at() });
+        }
+        Ok(())
+    }
+
+    /// Checks that `proof` is the one this was announced with
+    pub fn check_proof(&self, proof: &AuthorizationProof) -> Result<(), VaultError> {
+        let matches = proof.nonce == self.nonce
+            && proof.message_hash == self.message_hash
+            && proof.webauthn_sig.credential_id == self.credential_id
+            && proof.webauthn_sig.signature == self.signature;
+        if matches { Ok(()) } else { Err(VaultError::ProofMismatch) }
+    }
+}
+
+/// The announcement PDA for the transfer announced with `nonce`
+pub fn find_announcement_address(program_id: &Pubkey, attesta_account: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ANNOUNCEMENT_SEED, attesta_account.as_ref(), &nonce.to_le_bytes()], program_id)
+}
+
+/// How long `transaction_data` has to be announced before it runs
+///
+/// Only token transfers say how much they move, so only they are held back.
+pub(crate) fn announcement_delay(
+    account: &AttestaAccount,
+    parent: Option<&AttestaAccount>,
+    transaction_data: &[u8],
+) -> Option<u32> {
+    let transfer = TokenTransfer::from_transaction_data(transaction_data)?;
+    vault_delay(account, parent, transfer.amount)
+}
+
+/// How long a transfer of `amount` has to be announced before it runs
+///
+/// The longest delay of any vault on the account, or on its parent: a
+/// sub-account's transfers wait for the parent's vault as well. Paths that
+/// can't announce (schedules, claims, the owner's wallet) refuse a transfer
+/// this holds back rather than skip the delay.
+pub(crate) fn vault_delay(account: &AttestaAccount, parent: Option<&AttestaAccount>, amount: u64) -> Option<u32> {
+    [Some(account), parent]
+        .into_iter()
+        .flatten()
+        .flat_map(|account| account.policies())
+        .filter_map(|bytes| Policy::from_bytes(bytes).ok())
+        .filter_map(|policy| policy.announcement_delay(amount))
+        .max()
+}
+
+/// Checks whether an announced transfer may execute now
+///
+/// Anyone holding the announcing proof may call this once the delay has
+/// passed. The transfer goes through the account's settings and policies as
+/// they are at `now`; a vault never holds it back a second time.
+///
+/// # Returns
+/// - `Ok(PolicyResult::Allowed)` if it executes; `updated_at` becomes `now`
+///   and the nonce doesn't move
+/// - `Ok(PolicyResult::Denied(reason))` or `Ok(PolicyResult::RequiresApproval)`
+///   if the account no longer allows it; nothing changes
+/// - `Err(VaultError::ProofMismatch)` if `proof` isn't the announcing one
+/// - `Err(VaultError::TooEarly)` or `Err(VaultError::Expired)` outside the window
+/// - `Err(VaultError::SignerRemoved)` if its passkey has been removed since
+pub fn execute_announced(
+    account: &mut AttestaAccount,
+    account_address: &Pubkey,
+    parent: Option<&AttestaAccount>,
+    announcement: &Announcement,
+    proof: &AuthorizationProof,
+    now: i64,
+) -> Result<PolicyResult, VaultError> {
+    announcement.check_proof(proof)?;
+    announcement.check_window(now)?;
+    resolve_signing_key(account, &announcement.credential_id).map_err(|_| VaultError::SignerRemoved)?;
+
+    if account.settings.lockout_threshold > 0 && account.is_locked_out(now) {
+        return Ok(PolicyResult::Denied(DenyReason::LockedOut { until: account.locked_until }));
+    }
+
+    let signer = credential_id_hash(&announcement.credential_id);
+    let result = evaluate_policy(account, account_address, parent, Some(signer), &announcement.transaction_data, now)
+        .map_err(|_| VaultError::MissingParent)?;
+    if result == PolicyResult::Allowed {
+        account.updated_at = now;
+        record_transfer(account, &announcement.transaction_data, now);
+    }
+    Ok(result)
+}
+
+/// Checks a passkey's signature to veto the announcement at `announcement_address`
+///
+/// Any enabled passkey on the account may veto, not just the one that
+/// announced. Uses up `nonce`; the caller closes the PDA.
+///
+/// # Parameters
+/// - `webauthn_sig`: A signature over `ANNOUNCEMENT_VETO_ACTION` for the
+///   announcement PDA's address
+pub fn veto_announcement(
+    account: &mut AttestaAccount,
+    webauthn_sig: WebAuthnSignature,
+    nonce: u64,
+    announcement_address: &Pubkey,
+) -> Result<(), VaultError> {
+    authorize_action(account, webauthn_sig, nonce, ANNOUNCEMENT_VETO_ACTION, announcement_address.as_ref())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::policies::MIN_POLICY_TIMESTAMP;
+    use recovery::{Amount, MultiPasskey};
+    use crate::auth::action_message_hash;
+    use crate::execute::{execute_transaction_at, TransactionRequest};
+
+    const THRESHOLD: u64 = 1_000;
+    const DELAY: u32 = 2 * 24 * 60 * 60;
+    const NOW: i64 = MIN_POLICY_TIMESTAMP + 1_000;
+    const EXECUTABLE_AT: i64 = NOW + DELAY as i64;
+
+    /// A vault account with a second, backup passkey
+    fn setup() -> (AttestaAccount, TestPasskey, TestPasskey) {
+        let owner = TestPasskey::new(1);
+        let backup = TestPasskey::new(2);
+        let policy = Policy::vault(Amount::from_lamports(THRESHOLD), DELAY);
+        let mut account =
+            AttestaAccount::new(Pubkey::new_unique(), owner.public_key(), owner.credential_id(), policy.to_bytes().unwrap(), 100);
+        let mut registry = MultiPasskey::new(owner.public_key(), owner.credential_id(), "Phone".to_string(), 0, 1, 5);
+        registry.add_passkey(backup.public_key(), backup.credential_id(), "Backup".to_string(), 0).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+        (account, owner, backup)
+    }
+
+    fn transfer(amount: u64) -> TransactionRequest {
+        TransactionRequest::from_token_transfer(TokenTransfer {
+            mint: Pubkey::new_unique(),
+            amount,
+            decimals: 6,
+            destination_ata: Pubkey::new_unique(),
+        })
+    }
+
+    fn signed_proof(passkey: &mut TestPasskey, account: &AttestaAccount, request: &TransactionRequest) -> AuthorizationProof {
+        let nonce = account.nonce + 1;
+        let message_hash = request.message_hash();
+        AuthorizationProof::new(passkey.sign(&compute_challenge(&account.owner, nonce, &message_hash)), nonce, message_hash)
+    }
+
+    /// Announces a transfer of `amount`, returning the announcement and its proof
+    fn announce(account: &mut AttestaAccount, owner: &mut TestPasskey, amount: u64) -> (Announcement, AuthorizationProof) {
+        let request = transfer(amount);
+        let proof = signed_proof(owner, account, &request);
+        let result = execute_transaction_at(account, &Pubkey::new_unique(), None, &proof, &request.transaction_data, NOW);
+        assert_eq!(result, Ok(PolicyResult::Announced { executable_at: EXECUTABLE_AT }));
+        let announcement = Announcement::new(request.transaction_data, &proof, EXECUTABLE_AT, Pubkey::new_unique(), NOW);
+        (announcement, proof)
+    }
+
+    #[test]
+    fn test_under_threshold_runs_immediately() {
+        let (mut account, mut owner, _) = setup();
+        let request = transfer(THRESHOLD);
+        let proof = signed_proof(&mut owner, &account, &request);
+
+        let result = execute_transaction_at(&mut account, &Pubkey::new_unique(), None, &proof, &request.transaction_data, NOW);
+        assert_eq!(result, Ok(PolicyResult::Allowed));
+        assert_eq!(account.nonce, 1);
+        assert_eq!(account.updated_at, NOW);
+    }
+
+    #[test]
+    fn test_over_threshold_is_delayed() {
+        let (mut account, mut owner, _) = setup();
+        let (announcement, proof) = announce(&mut account, &mut owner, THRESHOLD + 1);
+        // The announcement reserves the nonce, so the proof can't run through `execute` again
+        assert_eq!(account.nonce, 1);
+        assert_eq!(Announcement::from_bytes(&announcement.to_bytes().unwrap()), Ok(announcement.clone()));
+        assert_eq!(
+            announcement.to_bytes().unwrap().len(),
+            Announcement::serialized_size(
+                announcement.transaction_data.len(),
+                announcement.credential_id.len(),
+                announcement.signature.len()
+            )
+        );
+
+        let address = Pubkey::new_unique();
+        assert_eq!(
+            execute_announced(&mut account, &address, None, &announcement, &proof, EXECUTABLE_AT),
+            Ok(PolicyResult::Allowed)
+        );
+        assert_eq!(account.updated_at, EXECUTABLE_AT);
+        assert_eq!(account.nonce, 1);
+
+        // The proof is good until the grace period runs out, and no longer
+        let expires_at = EXECUTABLE_AT + ANNOUNCEMENT_GRACE_PERIOD;
+        assert_eq!(announcement.expires_at(), expires_at);
+        assert_eq!(
+            execute_announced(&mut account, &address, None, &announcement, &proof, expires_at),
+            Err(VaultError::Expired { expires_at })
+        );
+    }
+
+    #[test]
+    fn test_early_execution_is_rejected() {
+        let (mut account, mut owner, _) = setup();
+        let (announcement, proof) = announce(&mut account, &mut owner, THRESHOLD + 1);
+        let address = Pubkey::new_unique();
+
+        assert_eq!(
+            execute_announced(&mut account, &address, None, &announcement, &proof, EXECUTABLE_AT - 1),
+            Err(VaultError::TooEarly { executable_at: EXECUTABLE_AT })
+        );
+
+        // Only the announcing proof runs it
+        let mut other = proof.clone();
+        other.webauthn_sig = owner.sign(&compute_challenge(&account.owner, proof.nonce, &proof.message_hash));
+        assert_eq!(
+            execute_announced(&mut account, &address, None, &announcement, &other, EXECUTABLE_AT),
+            Err(VaultError::ProofMismatch)
+        );
+        assert_eq!(account.updated_at, NOW);
+    }
+
+    #[test]
+    fn test_any_passkey_can_veto() {
+        let (mut account, mut owner, mut backup) = setup();
+        announce(&mut account, &mut owner, THRESHOLD + 1);
+        let announcement_address = Pubkey::new_unique();
+
+        let sign = |passkey: &mut TestPasskey, account: &AttestaAccount, payload: &[u8]| {
+            let nonce = account.nonce + 1;
+            let challenge = compute_challenge(&account.owner, nonce, &action_message_hash(ANNOUNCEMENT_VETO_ACTION, payload));
+            (passkey.sign(&challenge), nonce)
+        };
+
+        // A veto is signed over the announcement's address
+        let (sig, nonce) = sign(&mut backup, &account, Pubkey::new_unique().as_ref());
+        assert!(matches!(
+            veto_announcement(&mut account, sig, nonce, &announcement_address),
+            Err(VaultError::Unauthorized(_))
+        ));
+
+        // The backup passkey vetoes what the primary announced
+        let (sig, nonce) = sign(&mut backup, &account, announcement_address.as_ref());
+        assert_eq!(veto_announcement(&mut account, sig, nonce, &announcement_address), Ok(()));
+        assert_eq!(account.nonce, 2);
+    }
+
+    #[test]
+    fn test_schedules_cannot_skip_the_delay() {
+        use crate::schedule::{execute_scheduled, schedule_payload, schedule_transaction, SCHEDULE_ACTION};
+
+        let (mut account, mut owner, _) = setup();
+        let address = Pubkey::new_unique();
+        for (amount, expected) in [(THRESHOLD, PolicyResult::Allowed), (THRESHOLD + 1, PolicyResult::Denied(DenyReason::Policy))] {
+            let data = transfer(amount).transaction_data;
+            let nonce = account.nonce + 1;
+            let payload = schedule_payload(&data, NOW, None);
+            let sig = owner.sign(&compute_challenge(&account.owner, nonce, &action_message_hash(SCHEDULE_ACTION, &payload)));
+            let scheduled = schedule_transaction(&mut account, sig, nonce, data, NOW, None, NOW).unwrap();
+            assert_eq!(execute_scheduled(&mut account, &address, None, &scheduled, NOW), Ok(expected));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Execution against accounts set up by `ScenarioBuilder`
+//!
+//! The scenarios are built with the same operations the program runs, so
+//! these check executions against states an account can actually reach.
+
+use attesta_test_support::{Scenario, ScenarioBuilder, SCENARIO_TIME};
+use recovery::{DestinationLimits, Policy};
+use smart_account::{
+    execute_transaction, execute_transaction_at, AccountSettings, AuthorizationProof, DenyReason, PolicyResult,
+    TransactionRequest,
+};
+use solana_program::pubkey::Pubkey;
+
+/// Has passkey `seed` sign `transaction_data` with the account's next nonce, and executes it
+fn execute(scenario: &mut Scenario, seed: u8, transaction_data: Vec<u8>, now: i64) -> PolicyResult {
+    let request = TransactionRequest::new(transaction_data);
+    let proof = AuthorizationProof::from(scenario.envelope(seed, &request));
+    let address = scenario.address;
+    execute_transaction_at(&mut scenario.account, &address, None, &proof, &request.transaction_data, now).unwrap()
+}
+
+/// A transfer of `amount` to `destination_ata` instead of the scenario's recipient
+fn transfer_to(scenario: &Scenario, amount: u64, destination_ata: Pubkey) -> Vec<u8> {
+    let mut transfer = scenario.transfer(amount);
+    transfer.destination_ata = destination_ata;
+    transfer.to_transaction_data()
+}
+
+#[test]
+fn test_pending_recovery_holds_executions_to_the_allowance() {
+    let mut scenario = ScenarioBuilder::new()
+        .with_passkeys(2)
+        .with_settings(AccountSettings { recovery_allowance: 50, ..Default::default() })
+        .pending_recovery()
+        .build();
+    let nonce = scenario.account.nonce;
+
+    let data = transfer_to(&scenario, 50, Pubkey::new_unique());
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Allowed);
+    let data = transfer_to(&scenario, 51, Pubkey::new_unique());
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Denied(DenyReason::RecoveryPending));
+    // Data that doesn't state an amount could move anything
+    assert_eq!(execute(&mut scenario, 1, b"data".to_vec(), SCENARIO_TIME), PolicyResult::Denied(DenyReason::RecoveryPending));
+
+    // Without an allowance, nothing moves
+    scenario.account.settings.recovery_allowance = 0;
+    let data = transfer_to(&scenario, 1, Pubkey::new_unique());
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Denied(DenyReason::RecoveryPending));
+    assert_eq!(scenario.account.nonce, nonce + 1);
+
+    // A drill isn't a recovery
+    scenario.account.pending_drill = scenario.account.pending_recovery.take();
+    let data = transfer_to(&scenario, 1_000, Pubkey::new_unique());
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Allowed);
+}
+
+#[test]
+fn test_spent_today_counts_against_the_destination_limit() {
+    let policy = Policy::per_destination_limit(DestinationLimits {
+        limits: vec![],
+        default_max_amount: 1_000,
+        window_seconds: 86_400,
+        anchor_timestamp: 0,
+    });
+    let mut scenario = ScenarioBuilder::new().with_policy(policy).with_spent_today(800).build();
+
+    let data = scenario.transfer(201).to_transaction_data();
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Denied(DenyReason::Policy));
+    let data = scenario.transfer(200).to_transaction_data();
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Allowed);
+
+    // Another destination has its own budget, and the next window starts over
+    let data = transfer_to(&scenario, 1_000, Pubkey::new_unique());
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME), PolicyResult::Allowed);
+    let data = scenario.transfer(1_000).to_transaction_data();
+    assert_eq!(execute(&mut scenario, 1, data, SCENARIO_TIME + 86_400), PolicyResult::Allowed);
+}
+
+#[test]
+fn test_frozen_account_refuses_every_passkey_until_the_lock_ends() {
+    let mut scenario = ScenarioBuilder::new().with_passkeys(2).with_nonce(3).frozen().build();
+    let until = scenario.account.locked_until;
+
+    for seed in [1, 2] {
+        assert_eq!(
+            execute(&mut scenario, seed, b"data".to_vec(), SCENARIO_TIME),
+            PolicyResult::Denied(DenyReason::LockedOut { until })
+        );
+    }
+    assert_eq!(execute(&mut scenario, 2, b"data".to_vec(), until), PolicyResult::Allowed);
+    assert_eq!(scenario.account.nonce, 4);
+}
+
+#[test]
+fn test_every_passkey_signs_at_the_scenario_nonce() {
+    let mut scenario = ScenarioBuilder::new().with_passkeys(3).with_nonce(9).build();
+    let address = scenario.address;
+
+    for seed in 1..=3 {
+        let request = TransactionRequest::new(vec![seed]);
+        let proof = AuthorizationProof::from(scenario.envelope(seed, &request));
+        assert_eq!(
+            execute_transaction(&mut scenario.account, &address, None, &proof, &request.transaction_data),
+            Ok(PolicyResult::Allowed)
+        );
+    }
+    assert_eq!(scenario.account.nonce, 12);
+}
@@ -0,0 +1,145 @@
+//! Scenarios on a program test's bank, built with real instructions
+//!
+//! The bank needs the Attesta program loaded at the ID passed to `deploy`
+//! (`ProgramTest::new("attesta", attesta::ID, None)`), plus the SPL Token
+//! and Associated Token programs `ProgramTest` adds by default.
+
+use attesta_sdk::instructions;
+use smart_account::storage::decode_attesta_account;
+use smart_account::AttestaAccount;
+use solana_program::{instruction::Instruction, program_pack::Pack, pubkey::Pubkey};
+use solana_program_test::{BanksClient, BanksClientError};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_associated_token_account::instruction::create_associated_token_account;
+use crate::steps::{self, passkey_name, Step};
+use crate::{scenario_mint, Scenario, ScenarioBuilder, SCENARIO_DECIMALS, SCENARIO_RECIPIENT, SCENARIO_TOKEN_BALANCE};
+
+/// Compute budget for instructions that verify a P-256 signature
+const VERIFY_COMPUTE_UNITS: u32 = 1_400_000;
+
+impl ScenarioBuilder {
+    /// Builds the scenario on-chain, for `payer`, with one transaction per step
+    ///
+    /// Creates the scenario's mint first, with `SCENARIO_TOKEN_BALANCE` in
+    /// the account's token account and an empty one for the recipient.
+    ///
+    /// # Returns
+    /// The in-memory state the same steps produce, which the account on
+    /// the bank should match (see `assert_same_state`), and the passkeys
+    /// to sign with from there
+    pub async fn deploy(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        program_id: Pubkey,
+    ) -> Result<Scenario, BanksClientError> {
+        let builder = self.clone().with_owner(payer.pubkey()).with_program_id(program_id);
+        let (steps, scenario) = steps::plan(&builder);
+
+        create_token_accounts(banks_client, payer, &scenario).await?;
+        for step in &steps {
+            let instruction = step_instruction(step, &program_id, &payer.pubkey(), &scenario)?;
+            let budget = ComputeBudgetInstruction::set_compute_unit_limit(VERIFY_COMPUTE_UNITS);
+            send(banks_client, payer, &[budget, instruction], &[]).await?;
+        }
+        Ok(scenario)
+    }
+}
+
+/// Loads and decodes the Attesta account at `address`
+pub async fn load_account(banks_client: &mut BanksClient, address: Pubkey) -> AttestaAccount {
+    let stored = banks_client.get_account(address).await.unwrap().expect("the account exists");
+    decode_attesta_account(&stored.data).expect("an Attesta account")
+}
+
+/// The instruction that runs `step` on-chain
+fn step_instruction(step: &Step, program_id: &Pubkey, payer: &Pubkey, scenario: &Scenario) -> std::io::Result<Instruction> {
+    let address = &scenario.address;
+    match step {
+        Step::Initialize { registration_sig } => instructions::initialize(
+            program_id,
+            payer,
+            scenario.account.passkey_public_key,
+            scenario.account.credential_id.clone(),
+            None,
+            false,
+            registration_sig,
+            vec![],
+        ),
+        Step::AddPasskey { seed, webauthn_sig, nonce, expected_version } => {
+            let passkey = &scenario.passkeys[usize::from(*seed) - 1];
+            instructions::add_passkey(
+                program_id,
+                address,
+                payer,
+                webauthn_sig,
+                *nonce,
+                passkey.public_key(),
+                passkey.credential_id(),
+                passkey_name(*seed),
+                None,
+                *expected_version,
+            )
+        }
+        Step::UpdateSettings { settings, webauthn_sig, nonce, expected_version } => {
+            instructions::update_settings(program_id, address, payer, webauthn_sig, *nonce, settings, *expected_version)
+        }
+        Step::Execute { envelope, request, .. } => match request.token_transfer() {
+            Some(transfer) => instructions::execute_token_transfer(program_id, address, payer, envelope, &transfer),
+            None => instructions::execute(program_id, address, payer, envelope, request.transaction_data.clone()),
+        },
+        Step::UpdatePolicy { policy, expected_version } => {
+            instructions::update_policy(program_id, address, payer, Some(policy), *expected_version)
+        }
+        Step::InitiateRecovery { webauthn_sig, nonce } => instructions::initiate_recovery(
+            program_id,
+            address,
+            payer,
+            webauthn_sig,
+            *nonce,
+            scenario.recovery_passkey.public_key(),
+            scenario.recovery_passkey.credential_id(),
+            None,
+        ),
+    }
+}
+
+/// Creates the scenario's mint and both token accounts, funding the account's
+async fn create_token_accounts(banks_client: &mut BanksClient, payer: &Keypair, scenario: &Scenario) -> Result<(), BanksClientError> {
+    let mint = scenario_mint();
+    let rent = banks_client.get_rent().await?;
+    let payer_key = payer.pubkey();
+    let instructions = [
+        system_instruction::create_account(
+            &payer_key,
+            &scenario.mint,
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(&spl_token::id(), &scenario.mint, &payer_key, None, SCENARIO_DECIMALS).unwrap(),
+        create_associated_token_account(&payer_key, &scenario.address, &scenario.mint, &spl_token::id()),
+        create_associated_token_account(&payer_key, &SCENARIO_RECIPIENT, &scenario.mint, &spl_token::id()),
+        spl_token::instruction::mint_to(&spl_token::id(), &scenario.mint, &scenario.source_ata(), &payer_key, &[], SCENARIO_TOKEN_BALANCE)
+            .unwrap(),
+    ];
+    send(banks_client, payer, &instructions, &[&mint]).await
+}
+
+async fn send(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+    extra_signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let mut signers = vec![payer];
+    signers.extend_from_slice(extra_signers);
+    let blockhash = banks_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &signers, blockhash);
+    banks_client.process_transaction(transaction).await
+}
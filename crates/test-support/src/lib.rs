@@ -0,0 +1,431 @@
+//! Deterministic account scenarios for Attesta tests
+//!
+//! Tests across the workspace keep needing the same few account states: a
+//! policy, a handful of passkeys, a nonce some way along, a transfer
+//! already counted against a limit, a lockout, a pending recovery.
+//! `ScenarioBuilder` builds them one way, by running the operations the
+//! program itself runs, so a scenario is one expression:
+//!
+//! ```ignore
+//! let scenario = ScenarioBuilder::new().with_passkeys(2).with_nonce(5).pending_recovery().build();
+//! ```
+//!
+//! `build` runs them in memory. With the `banks` feature, `deploy` sends
+//! them as real instructions to a program test's `BanksClient` and returns
+//! the in-memory state they should have produced, which `assert_same_state`
+//! compares with what's on-chain.
+//!
+//! Everything is seeded: passkey `i` is `TestPasskey::new(i)` (1 is the
+//! primary), and the mint and recipient are the same every run.
+
+// Test fixtures panic on bad setup rather than threading errors through tests
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+#[cfg(feature = "banks")]
+pub mod banks;
+mod steps;
+
+use attesta_sdk::signing::SigningRequest;
+use attesta_sdk::ProofEnvelope;
+use core_crypto::test_utils::TestPasskey;
+use recovery::{MultiPasskey, Policy};
+use smart_account::{derive_associated_token_address, AccountSettings, AttestaAccount, TokenTransfer, TransactionRequest};
+use solana_program::{pubkey, pubkey::Pubkey};
+use solana_sdk::signature::{keypair_from_seed, Keypair, Signer};
+
+#[cfg(feature = "banks")]
+pub use banks::load_account;
+
+/// The program ID scenarios are built for unless told otherwise (the deployed program's)
+pub const SCENARIO_PROGRAM_ID: Pubkey = pubkey!("Attesta11111111111111111111111111111111");
+
+/// The owner of an in-memory scenario (a deployed one belongs to the payer)
+pub const SCENARIO_OWNER: Pubkey = Pubkey::new_from_array([0x0a; 32]);
+
+/// The wallet a scenario's token transfers pay
+pub const SCENARIO_RECIPIENT: Pubkey = Pubkey::new_from_array([0x0b; 32]);
+
+/// When an in-memory scenario runs (Unix timestamp)
+pub const SCENARIO_TIME: i64 = 1_700_000_000;
+
+/// Decimals of the scenario's mint
+pub const SCENARIO_DECIMALS: u8 = 6;
+
+/// Base units of the mint a deployed account starts with
+pub const SCENARIO_TOKEN_BALANCE: u64 = 1_000_000 * 10u64.pow(SCENARIO_DECIMALS as u32);
+
+/// Seed of the passkey a pending recovery would install
+pub const RECOVERY_PASSKEY_SEED: u8 = 100;
+
+/// The mint every scenario's token transfers use
+pub fn scenario_mint() -> Keypair {
+    keypair_from_seed(&[0x4d; 32]).expect("a 32-byte seed")
+}
+
+/// Describes an account state, then builds it (see the crate docs)
+///
+/// Setup runs in this order, each step consuming the next nonce where it
+/// signs: initialize with no policy, add passkeys 2 to n, update the
+/// settings, execute no-op transactions until the nonce is where
+/// `with_nonce` asked, set the policy, make the `with_spent_today`
+/// transfer, fail signatures until the account locks, start the recovery.
+#[derive(Debug, Clone)]
+pub struct ScenarioBuilder {
+    program_id: Pubkey,
+    owner: Pubkey,
+    policy: Option<Policy>,
+    passkeys: u8,
+    nonce: Option<u64>,
+    spent_today: u64,
+    settings: AccountSettings,
+    frozen: bool,
+    pending_recovery: bool,
+}
+
+impl Default for ScenarioBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScenarioBuilder {
+    /// An open account with one passkey, at nonce 0
+    pub fn new() -> Self {
+        Self {
+            program_id: SCENARIO_PROGRAM_ID,
+            owner: SCENARIO_OWNER,
+            policy: None,
+            passkeys: 1,
+            nonce: None,
+            spent_today: 0,
+            settings: AccountSettings::default(),
+            frozen: false,
+            pending_recovery: false,
+        }
+    }
+
+    /// Builds for the program at `program_id`, which the account address derives from
+    pub fn with_program_id(mut self, program_id: Pubkey) -> Self {
+        self.program_id = program_id;
+        self
+    }
+
+    /// Builds the account for `owner` (`deploy` always uses the payer)
+    pub fn with_owner(mut self, owner: Pubkey) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    /// The account's policy, set after the nonce is reached
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Registers passkeys 1 to `n`, with 1 as the primary
+    pub fn with_passkeys(mut self, n: u8) -> Self {
+        assert!(n >= 1, "an account has at least its primary passkey");
+        self.passkeys = n;
+        self
+    }
+
+    /// The account's nonce once set up
+    ///
+    /// Panics at build time if setup itself needs more nonces than `k`.
+    pub fn with_nonce(mut self, k: u64) -> Self {
+        self.nonce = Some(k);
+        self
+    }
+
+    /// A transfer of `amount` base units to the recipient, counted against
+    /// the policy's per-destination limits
+    pub fn with_spent_today(mut self, amount: u64) -> Self {
+        self.spent_today = amount;
+        self
+    }
+
+    /// The account's settings, applied with `update_settings`
+    ///
+    /// The auth mode and executors aren't `update_settings`' to change, so
+    /// they're left at their defaults.
+    pub fn with_settings(mut self, settings: AccountSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Locks the account out with failed signatures
+    ///
+    /// Turns lockout on (threshold 1) unless the settings already do.
+    pub fn frozen(mut self) -> Self {
+        self.frozen = true;
+        self
+    }
+
+    /// Has passkey 2 start a recovery to the passkey seeded with
+    /// `RECOVERY_PASSKEY_SEED`
+    ///
+    /// Needs at least two passkeys: the primary can't be its own guardian.
+    pub fn pending_recovery(mut self) -> Self {
+        self.pending_recovery = true;
+        self
+    }
+
+    /// Runs the scenario in memory
+    pub fn build(&self) -> Scenario {
+        steps::plan(self).1
+    }
+
+    /// The nonces setup consumes on its own, before any padding
+    fn setup_nonces(&self) -> u64 {
+        u64::from(self.passkeys - 1)
+            + u64::from(self.updates_settings())
+            + u64::from(self.spent_today > 0)
+            + u64::from(self.pending_recovery)
+    }
+
+    fn updates_settings(&self) -> bool {
+        self.frozen || self.settings != AccountSettings::default()
+    }
+
+    /// The settings `update_settings` sends
+    fn target_settings(&self) -> AccountSettings {
+        let mut settings = AccountSettings {
+            auth_mode: Default::default(),
+            auth_mode_locked: false,
+            authorized_executors: Vec::new(),
+            ..self.settings.clone()
+        };
+        if self.frozen {
+            settings.lockout_threshold = settings.lockout_threshold.max(1);
+        }
+        settings
+    }
+}
+
+/// An account state and the passkeys that sign for it
+pub struct Scenario {
+    /// The account as the steps left it
+    pub account: AttestaAccount,
+
+    /// Its address
+    pub address: Pubkey,
+
+    /// Passkeys 1 to n, in order (`passkeys[0]` is the primary)
+    pub passkeys: Vec<TestPasskey>,
+
+    /// The passkey a pending recovery would install
+    pub recovery_passkey: TestPasskey,
+
+    /// The mint of the scenario's token transfers
+    pub mint: Pubkey,
+
+    /// The recipient's token account
+    pub destination_ata: Pubkey,
+}
+
+impl Scenario {
+    /// The account's passkey registry (none with a single passkey)
+    pub fn registry(&self) -> Option<MultiPasskey> {
+        self.account.passkey_registry().expect("a registry the account wrote")
+    }
+
+    /// Passkey `seed` (1 is the primary)
+    pub fn passkey(&mut self, seed: u8) -> &mut TestPasskey {
+        &mut self.passkeys[usize::from(seed) - 1]
+    }
+
+    /// The account's next nonce
+    pub fn next_nonce(&self) -> u64 {
+        self.account.nonce + 1
+    }
+
+    /// The account's own token account for the mint
+    pub fn source_ata(&self) -> Pubkey {
+        derive_associated_token_address(&self.address, &self.mint)
+    }
+
+    /// A transfer of `amount` base units of the mint to the recipient
+    pub fn transfer(&self, amount: u64) -> TokenTransfer {
+        TokenTransfer { mint: self.mint, amount, decimals: SCENARIO_DECIMALS, destination_ata: self.destination_ata }
+    }
+
+    /// Passkey `seed`'s proof for `request` at the account's next nonce
+    ///
+    /// What a wallet would hand to `execute`; the nonce is only consumed
+    /// once the proof executes.
+    pub fn envelope(&mut self, seed: u8, request: &TransactionRequest) -> ProofEnvelope {
+        let signing = SigningRequest::new(&self.account, request, SCENARIO_TIME);
+        ProofEnvelope {
+            webauthn_sig: self.passkey(seed).sign(&signing.challenge),
+            nonce: signing.nonce,
+            message_hash: signing.message_hash,
+            memo: signing.memo,
+            idempotency_key: signing.idempotency_key,
+            parent_account: signing.parent_account,
+            logs_proofs: signing.logs_proofs,
+            emit_memo: false,
+        }
+    }
+}
+
+/// Asserts two accounts are in the same state, as far as their clocks allow
+///
+/// Compares everything setup changes except when it happened: an in-memory
+/// scenario runs at `SCENARIO_TIME`, a deployed one at the bank's clock.
+pub fn assert_same_state(actual: &AttestaAccount, expected: &AttestaAccount) {
+    assert_eq!(actual.owner, expected.owner, "owner");
+    assert_eq!(actual.passkey_public_key, expected.passkey_public_key, "primary public key");
+    assert_eq!(actual.credential_id, expected.credential_id, "primary credential ID");
+    assert_eq!(actual.nonce, expected.nonce, "nonce");
+    assert_eq!(actual.policies(), expected.policies(), "policies");
+    assert_eq!(actual.policy_hash, expected.policy_hash, "policy hash");
+    assert_eq!(actual.settings, expected.settings, "settings");
+    assert_eq!(actual.state_version, expected.state_version, "state version");
+    assert_eq!(actual.idempotency_records, expected.idempotency_records, "idempotency records");
+    assert_eq!(actual.sign_counts, expected.sign_counts, "sign counts");
+    assert_eq!(actual.failed_auth_count, expected.failed_auth_count, "failed signatures");
+    assert_eq!(actual.locked_until > 0, expected.locked_until > 0, "locked out");
+    assert_eq!(actual.destination_spends.entries, expected.destination_spends.entries, "destination spends");
+
+    let entries = |account: &AttestaAccount| {
+        account.passkey_registry().unwrap().map(|registry| {
+            let entries = registry
+                .entries()
+                .map(|entry| (entry.public_key, entry.credential_id.clone(), entry.name.clone()))
+                .collect::<Vec<_>>();
+            (entries, registry.recovery_threshold)
+        })
+    };
+    assert_eq!(entries(actual), entries(expected), "passkey registry");
+
+    let request = |account: &AttestaAccount| {
+        account
+            .pending_recovery
+            .as_ref()
+            .map(|request| (request.new_public_key, request.new_credential_id.clone(), request.approvals.clone()))
+    };
+    assert_eq!(request(actual), request(expected), "pending recovery");
+}
+
+/// The recipient's token account for the scenario's mint
+fn destination_ata(mint: &Pubkey) -> Pubkey {
+    derive_associated_token_address(&SCENARIO_RECIPIENT, mint)
+}
+
+fn mint_address() -> Pubkey {
+    scenario_mint().pubkey()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use recovery::DestinationLimits;
+    use smart_account::{execute_transaction, AuthorizationProof, DenyReason, PolicyResult};
+    use core_crypto::compute_challenge;
+
+    fn destination_limit(max_amount: u64) -> Policy {
+        Policy::per_destination_limit(DestinationLimits {
+            limits: vec![],
+            default_max_amount: max_amount,
+            window_seconds: 86_400,
+            anchor_timestamp: 0,
+        })
+    }
+
+    fn noop_proof(scenario: &mut Scenario, seed: u8) -> (AuthorizationProof, Vec<u8>) {
+        let request = TransactionRequest::new(b"check".to_vec());
+        let nonce = scenario.next_nonce();
+        let challenge = compute_challenge(&scenario.account.owner, nonce, &request.message_hash());
+        let proof = AuthorizationProof::new(scenario.passkey(seed).sign(&challenge), nonce, request.message_hash());
+        (proof, request.transaction_data)
+    }
+
+    #[test]
+    fn test_default_scenario_is_a_fresh_account() {
+        let scenario = ScenarioBuilder::new().build();
+        let primary = TestPasskey::new(1);
+
+        assert_eq!(scenario.account.owner, SCENARIO_OWNER);
+        assert_eq!(scenario.account.passkey_public_key, primary.public_key());
+        assert_eq!(scenario.account.nonce, 0);
+        assert!(scenario.account.policies().is_empty());
+        assert!(scenario.registry().is_none());
+        assert_eq!(scenario.passkeys.len(), 1);
+    }
+
+    #[test]
+    fn test_scenarios_are_deterministic() {
+        let builder = ScenarioBuilder::new()
+            .with_passkeys(3)
+            .with_nonce(7)
+            .with_policy(destination_limit(500))
+            .with_spent_today(200)
+            .pending_recovery();
+
+        assert_eq!(builder.build().account, builder.build().account);
+    }
+
+    #[test]
+    fn test_every_option_lands() {
+        let allowance = AccountSettings { recovery_allowance: 50, ..AccountSettings::default() };
+        let scenario = ScenarioBuilder::new()
+            .with_passkeys(3)
+            .with_nonce(10)
+            .with_settings(allowance)
+            .with_policy(destination_limit(500))
+            .with_spent_today(200)
+            .frozen()
+            .pending_recovery()
+            .build();
+        let account = &scenario.account;
+
+        assert_eq!(account.nonce, 10);
+        let registry = scenario.registry().unwrap();
+        assert_eq!(registry.entries().count(), 3);
+        assert!(registry.find_passkey(&TestPasskey::new(3).credential_id()).is_some());
+        assert_eq!(account.policy(), destination_limit(500).to_bytes().unwrap());
+        assert_eq!(account.settings.recovery_allowance, 50);
+        assert_eq!(account.settings.lockout_threshold, 1);
+        assert!(account.is_locked_out(SCENARIO_TIME));
+        let spent: u64 = account.destination_spends.entries.iter().map(|entry| entry.spent).sum();
+        assert_eq!(spent, 200);
+        let request = account.pending_recovery.as_ref().unwrap();
+        assert_eq!(request.new_public_key, scenario.recovery_passkey.public_key());
+    }
+
+    #[test]
+    fn test_passkeys_sign_for_the_scenario() {
+        let mut scenario = ScenarioBuilder::new().with_passkeys(2).with_nonce(4).build();
+        let address = scenario.address;
+
+        for seed in [1, 2] {
+            let (proof, data) = noop_proof(&mut scenario, seed);
+            assert_eq!(execute_transaction(&mut scenario.account, &address, None, &proof, &data), Ok(PolicyResult::Allowed));
+        }
+        assert_eq!(scenario.account.nonce, 6);
+    }
+
+    #[test]
+    fn test_frozen_scenario_refuses_good_signatures() {
+        let mut scenario = ScenarioBuilder::new().frozen().build();
+        let address = scenario.address;
+
+        let (proof, data) = noop_proof(&mut scenario, 1);
+        assert_eq!(
+            execute_transaction(&mut scenario.account, &address, None, &proof, &data),
+            Ok(PolicyResult::Denied(DenyReason::LockedOut { until: scenario.account.locked_until }))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "setup uses 3 nonces")]
+    fn test_nonce_below_setup_panics() {
+        ScenarioBuilder::new().with_passkeys(2).with_spent_today(1).pending_recovery().with_nonce(2).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "needs a guardian")]
+    fn test_recovery_needs_a_second_passkey() {
+        ScenarioBuilder::new().pending_recovery().build();
+    }
+}
@@ -0,0 +1,222 @@
+//! The operations a scenario runs, each signed once
+//!
+//! `plan` signs every step against the state the steps before it left, and
+//! runs it in memory the way the program's handler would. `deploy` sends
+//! the same signed steps as instructions, so both sides see the same
+//! signatures, nonces and sign counts.
+
+use attesta_sdk::instructions::derive_attesta_address;
+use attesta_sdk::ProofEnvelope;
+use core_crypto::{compute_challenge, test_utils::TestPasskey, WebAuthnSignature};
+use recovery::multi_passkey::PASSKEY_ADD_ACTION;
+use recovery::{PasskeyEntry, Policy};
+use smart_account::social_recovery::{self, recovery_request_hash};
+use smart_account::{
+    action_message_hash, authorize_action, authorize_admin_action, execute_transaction_at, registration_challenge,
+    AccountSettings, AttestaAccount, AuthorizationProof, DenyReason, PolicyResult, RecoveryMode, TransactionRequest,
+    SETTINGS_UPDATE_ACTION,
+};
+use crate::{destination_ata, mint_address, Scenario, ScenarioBuilder, RECOVERY_PASSKEY_SEED, SCENARIO_TIME};
+
+/// What no-op executions carry to move the nonce along
+const PADDING_DATA: &[u8] = b"scenario";
+
+/// One signed setup operation
+pub(crate) enum Step {
+    /// `initialize` with passkey 1 and no policy
+    Initialize { registration_sig: WebAuthnSignature },
+
+    /// `add_passkey` for passkey `seed`, signed by the primary
+    AddPasskey { seed: u8, webauthn_sig: WebAuthnSignature, nonce: u64, expected_version: u64 },
+
+    /// `update_settings`, signed by the primary
+    UpdateSettings { settings: AccountSettings, webauthn_sig: WebAuthnSignature, nonce: u64, expected_version: u64 },
+
+    /// `execute`, signed by the primary (a forged signature when `forged`)
+    Execute { envelope: ProofEnvelope, request: TransactionRequest, forged: bool },
+
+    /// `update_policy`, signed by the owner's wallet
+    UpdatePolicy { policy: Policy, expected_version: u64 },
+
+    /// `initiate_recovery` to the recovery passkey, signed by passkey 2
+    InitiateRecovery { webauthn_sig: WebAuthnSignature, nonce: u64 },
+}
+
+/// Signs and runs `builder`'s steps in memory
+///
+/// Panics if the builder asks for something setup can't do, or a step is
+/// refused (a policy that denies the `with_spent_today` transfer, say).
+pub(crate) fn plan(builder: &ScenarioBuilder) -> (Vec<Step>, Scenario) {
+    let setup = builder.setup_nonces();
+    let target_nonce = builder.nonce.unwrap_or(setup);
+    assert!(target_nonce >= setup, "with_nonce({}) is too low: setup uses {} nonces", target_nonce, setup);
+    assert!(
+        !builder.pending_recovery || builder.passkeys >= 2,
+        "a pending recovery needs a guardian: use with_passkeys(2) or more"
+    );
+
+    let passkeys: Vec<TestPasskey> = (1..=builder.passkeys).map(TestPasskey::new).collect();
+    let (address, _) = derive_attesta_address(&builder.program_id, &builder.owner);
+    let mint = mint_address();
+    let mut planner = Planner {
+        scenario: Scenario {
+            // What `initialize` creates, with no policy
+            account: AttestaAccount::new(
+                builder.owner,
+                passkeys[0].public_key(),
+                passkeys[0].credential_id(),
+                Vec::new(),
+                SCENARIO_TIME,
+            ),
+            address,
+            passkeys,
+            recovery_passkey: TestPasskey::new(RECOVERY_PASSKEY_SEED),
+            mint,
+            destination_ata: destination_ata(&mint),
+        },
+        steps: Vec::new(),
+    };
+
+    planner.initialize();
+    for seed in 2..=builder.passkeys {
+        planner.add_passkey(seed);
+    }
+    if builder.updates_settings() {
+        planner.update_settings(builder.target_settings());
+    }
+
+    let padding = target_nonce - setup;
+    for _ in 0..padding {
+        planner.execute(TransactionRequest::new(PADDING_DATA.to_vec()), false);
+    }
+
+    if let Some(policy) = &builder.policy {
+        planner.update_policy(policy.clone());
+    }
+    if builder.spent_today > 0 {
+        let transfer = planner.scenario.transfer(builder.spent_today);
+        planner.execute(TransactionRequest::from_token_transfer(transfer), false);
+    }
+    if builder.frozen {
+        for _ in 0..planner.scenario.account.settings.lockout_threshold {
+            planner.execute(TransactionRequest::new(PADDING_DATA.to_vec()), true);
+        }
+    }
+    if builder.pending_recovery {
+        planner.initiate_recovery();
+    }
+
+    assert_eq!(planner.scenario.account.nonce, target_nonce);
+    (planner.steps, planner.scenario)
+}
+
+struct Planner {
+    scenario: Scenario,
+    steps: Vec<Step>,
+}
+
+impl Planner {
+    fn initialize(&mut self) {
+        let account = &self.scenario.account;
+        let challenge =
+            registration_challenge(&account.owner, &self.scenario.address, &account.passkey_public_key, &account.credential_id);
+        let registration_sig = self.scenario.passkey(1).sign_create(&challenge);
+        self.steps.push(Step::Initialize { registration_sig });
+    }
+
+    fn add_passkey(&mut self, seed: u8) {
+        let new_passkey = TestPasskey::new(seed);
+        let payload = [new_passkey.public_key().as_ref(), new_passkey.credential_id().as_slice()].concat();
+        let (webauthn_sig, nonce) = self.sign_action(1, PASSKEY_ADD_ACTION, &payload);
+        let expected_version = self.scenario.account.state_version;
+
+        let account = &mut self.scenario.account;
+        authorize_action(account, webauthn_sig.clone(), nonce, PASSKEY_ADD_ACTION, &payload)
+            .expect("the primary passkey signed");
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        let lookup_id = account.credential_lookup_id(&new_passkey.credential_id());
+        let entry = PasskeyEntry::compact(new_passkey.public_key(), lookup_id, passkey_name(seed), SCENARIO_TIME);
+        registry.add_entry(entry).expect("room for the scenario's passkeys");
+        account.set_passkey_registry(&registry).unwrap();
+        account.bump_state_version();
+
+        self.steps.push(Step::AddPasskey { seed, webauthn_sig, nonce, expected_version });
+    }
+
+    fn update_settings(&mut self, settings: AccountSettings) {
+        let payload = settings.to_bytes();
+        let (webauthn_sig, nonce) = self.sign_action(1, SETTINGS_UPDATE_ACTION, &payload);
+        let expected_version = self.scenario.account.state_version;
+
+        let account = &mut self.scenario.account;
+        let authorized = if account.settings.changes_webauthn_checks(&settings) {
+            authorize_admin_action(account, webauthn_sig.clone(), nonce, SETTINGS_UPDATE_ACTION, &payload)
+        } else {
+            authorize_action(account, webauthn_sig.clone(), nonce, SETTINGS_UPDATE_ACTION, &payload)
+        };
+        authorized.expect("the primary passkey signed");
+        account.settings = settings.clone();
+        account.bump_state_version();
+
+        self.steps.push(Step::UpdateSettings { settings, webauthn_sig, nonce, expected_version });
+    }
+
+    fn execute(&mut self, request: TransactionRequest, forged: bool) {
+        let mut envelope = self.scenario.envelope(1, &request);
+        if forged {
+            envelope.webauthn_sig.signature[0] ^= 1;
+        }
+
+        let proof = AuthorizationProof::from(envelope.clone());
+        let address = self.scenario.address;
+        let result =
+            execute_transaction_at(&mut self.scenario.account, &address, None, &proof, &request.transaction_data, SCENARIO_TIME);
+        let expected = if forged { PolicyResult::Denied(DenyReason::AuthenticationFailed) } else { PolicyResult::Allowed };
+        assert_eq!(result, Ok(expected), "scenario step refused: {:?}", request.token_transfer());
+
+        self.steps.push(Step::Execute { envelope, request, forged });
+    }
+
+    fn update_policy(&mut self, policy: Policy) {
+        let expected_version = self.scenario.account.state_version;
+        let account = &mut self.scenario.account;
+        account.set_policies(vec![policy.to_bytes().expect("a policy that serializes")]);
+        account.bump_state_version();
+
+        self.steps.push(Step::UpdatePolicy { policy, expected_version });
+    }
+
+    fn initiate_recovery(&mut self) {
+        let new_public_key = self.scenario.recovery_passkey.public_key();
+        let new_credential_id = self.scenario.recovery_passkey.credential_id();
+        let lookup_id = self.scenario.account.credential_lookup_id(&new_credential_id);
+        let request_hash = recovery_request_hash(&new_public_key, &lookup_id);
+        let (webauthn_sig, nonce) = self.sign_action(2, RecoveryMode::Recovery.initiate_action(), &request_hash);
+
+        social_recovery::initiate_recovery(
+            &mut self.scenario.account,
+            RecoveryMode::Recovery,
+            webauthn_sig.clone(),
+            nonce,
+            new_public_key,
+            &new_credential_id,
+            None,
+            SCENARIO_TIME,
+        )
+        .expect("passkey 2 can start a recovery");
+
+        self.steps.push(Step::InitiateRecovery { webauthn_sig, nonce });
+    }
+
+    /// Passkey `seed`'s signature over `action` for `payload`, with the next nonce
+    fn sign_action(&mut self, seed: u8, action: &[u8], payload: &[u8]) -> (WebAuthnSignature, u64) {
+        let nonce = self.scenario.next_nonce();
+        let challenge = compute_challenge(&self.scenario.account.owner, nonce, &action_message_hash(action, payload));
+        (self.scenario.passkey(seed).sign(&challenge), nonce)
+    }
+}
+
+/// The name passkey `seed` is registered under
+pub(crate) fn passkey_name(seed: u8) -> String {
+    format!("Passkey {}", seed)
+}
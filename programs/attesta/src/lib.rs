@@ -4,16 +4,146 @@
 //! on Solana, enabling passkey-based authorization and policy-driven execution.
 
 use anchor_lang::prelude::*;
-use smart_account::{AttestaAccount, AuthorizationProof, execute_transaction, PolicyResult};
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::{invoke, invoke_signed, set_return_data};
+use smart_account::{AttestaAccount, AccountPolicyError, AccountSettings, AuthorizationProof, MAX_AAGUID_ALLOWLIST_LEN, check_memo, check_replay, execute_transaction, memo_hash, transaction_message_hash, DenyReason, ExecuteOutcome, PolicyResult, authorize_action, authorize_admin_action, verify_registration, TokenTransfer, MEMO_PROGRAM_ID, TOKEN_PROGRAM_ID, PRIVACY_MODE_ACTION, SETTINGS_UPDATE_ACTION};
+use smart_account::attestation::{self, AttestationError, PolicyAttestation};
+use smart_account::auth_mode::{self, AuthMode, AuthModeError};
+#[cfg(feature = "feature-claims")]
+use smart_account::claim::{self, ClaimError, ClaimTicket};
+use smart_account::emergency::{self, emergency_message_hash, EmergencyError};
+use smart_account::executors::{self, ExecutorError};
+use smart_account::idempotency::{IdempotencyKey, IDEMPOTENCY_RECORDS_SPACE};
+#[cfg(feature = "feature-inheritance")]
+use smart_account::inheritance::{self, InheritanceConfig, InheritanceError};
+use smart_account::policy_list::{self, PolicyListError};
+use smart_account::proof_log::{ProofLog, ProofLogEntry, PROOF_LOG_ENABLE_ACTION};
+use smart_account::protocol::ProtocolConstants;
+use smart_account::proposal::{self, Approval, CancelReason, PendingTransaction, ProposalError, PROPOSAL_SEED};
+use smart_account::sampling::{sample_allowed, MAX_SAMPLE_RATE};
+use smart_account::schedule::{self, schedule_payload, ScheduleError, ScheduledTransaction, SCHEDULE_ACTION};
+use smart_account::social_recovery::{self, RecoveryFlowError, RecoveryMode, RecoveryProgress};
+use smart_account::sponsorship::SponsorPool;
+#[cfg(feature = "feature-sponsorship")]
+use smart_account::sponsorship::SponsorshipError;
+use smart_account::token::transfer_checked_instruction;
+use smart_account::sub_account::{new_sub_account, sub_account_payload, SUB_ACCOUNT_CREATE_ACTION};
+use smart_account::upgrade::{self, ProgramFeature, ProgramFeatures, ProgramVersion, UpgradeError};
+use smart_account::vault::{self, Announcement, VaultError, ANNOUNCEMENT_SEED};
 use smart_account::storage::{load_attesta_account, save_attesta_account, init_attesta_account};
-use core_crypto::WebAuthnSignature;
+use attesta_types::consts::{AAGUID_LEN, ACCOUNT_DISCRIMINATOR_LEN, BORSH_LEN_PREFIX, MAX_CREDENTIAL_ID_LEN, MAX_INITIAL_POLICY_LEN, P256_PUBKEY_LEN, PUBKEY_LEN};
+use attesta_types::time::{validate_timestamp, TimeError, MAX_CLOCK_SKEW_SECONDS, MAX_PAST_SECONDS};
+use attesta_types::log::{codes, format_log_line};
+use core_crypto::{compute_challenge, display_code, CryptoError, RelyingParty, WebAuthnSignature, WebAuthnVerificationProfile};
+use recovery::credential_id_hash;
+use recovery::policies::{Policy, MAX_POLICY_COMPUTE_UNITS};
+use recovery::multi_passkey::{PasskeyEntry, PASSKEY_ADD_ACTION, PASSKEY_REMOVE_ACTION};
+use recovery::encrypted_backup::{
+    EncryptedBackup, BACKUP_DELETE_ACTION, BACKUP_WRITE_ACTION, MAX_ESCROW_BACKUP_SIZE,
+};
+
+/// The data layouts this program reads and writes, for clients that want them without the SDK
+pub use attesta_types;
 
 // TODO: Replace with your actual program ID after generating keypair
 // Generate with: solana-keygen new -o target/deploy/attesta-keypair.json
 declare_id!("Attesta11111111111111111111111111111111");
 
+/// Space allocated for a new Attesta account: discriminator + account data
+///
+/// Room for the core fields with the longest credential ID and initial
+/// policy we size for, plus `IDEMPOTENCY_RECORDS_SPACE` for retry records
+/// (the oldest are dropped once they don't fit). A new account has no
+/// records yet, so the later fields, up to a full AAGUID allowlist, fit in
+/// their share. Accounts grow as they need to after that (see
+/// `save_account_resized`).
+pub const ATTESTA_ACCOUNT_SPACE: usize = ACCOUNT_DISCRIMINATOR_LEN
+    + PUBKEY_LEN                                  // owner
+    + P256_PUBKEY_LEN                             // passkey_public_key
+    + BORSH_LEN_PREFIX + MAX_CREDENTIAL_ID_LEN    // credential_id
+    + BORSH_LEN_PREFIX + MAX_INITIAL_POLICY_LEN   // policy
+    + 8 + 8 + 8                                   // nonce, created_at, updated_at
+    + IDEMPOTENCY_RECORDS_SPACE;
+
+/// Bytes an `AttestaAccountData` takes before the serialized account:
+/// the discriminator and the vec length
+const ACCOUNT_DATA_HEADER_LEN: usize = ACCOUNT_DISCRIMINATOR_LEN + BORSH_LEN_PREFIX;
+
+/// PDA seed prefix for sub-accounts: `[SUB_ACCOUNT_SEED, parent, [index]]`
+const SUB_ACCOUNT_SEED: &[u8] = b"sub_account";
+
+/// PDA seed prefix for scheduled transactions: `[SCHEDULE_SEED, attesta_account, nonce (LE)]`
+const SCHEDULE_SEED: &[u8] = b"schedule";
+
+/// PDA seed prefix for sponsor pools: `[SPONSOR_POOL_SEED, authority]`
+const SPONSOR_POOL_SEED: &[u8] = b"sponsor_pool";
+
+/// PDA seed prefix for policy attestations: `[POLICY_ATTESTATION_SEED, attesta_account, nonce (LE)]`
+const POLICY_ATTESTATION_SEED: &[u8] = b"policy_attestation";
+
+/// This build's declared version, reported by `get_program_version`
+///
+/// Accounts that pin the program version stop executing when it changes, so
+/// bump it with every deployment that changes what an instruction does.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Which instruction groups this build has, from its `feature-*` cargo features
+const BUILT_FEATURES: [(ProgramFeature, bool); 3] = [
+    (ProgramFeature::Claims, cfg!(feature = "feature-claims")),
+    (ProgramFeature::Inheritance, cfg!(feature = "feature-inheritance")),
+    (ProgramFeature::Sponsorship, cfg!(feature = "feature-sponsorship")),
+];
+
+/// The features this build has
+fn built_features() -> Vec<ProgramFeature> {
+    BUILT_FEATURES.iter().filter(|(_, built)| *built).map(|(feature, _)| *feature).collect()
+}
+
+/// The features `get_program_features` reports
+fn program_features() -> ProgramFeatures {
+    ProgramFeatures::new(&built_features())
+}
+
+/// The version `get_program_version` reports and accounts pin the hash of
+///
+/// Its features are the instruction groups this build leaves out, as `no-`
+/// and the group's name, since an account pinned to a build that had them
+/// shouldn't quietly lose them. Other cargo features only shape the
+/// entrypoint, the IDL and instruction-name logging, and a default build
+/// leaves nothing out, so its hash is the same as before groups could be.
+fn program_version() -> ProgramVersion {
+    ProgramVersion {
+        version: VERSION.to_string(),
+        features: BUILT_FEATURES
+            .iter()
+            .filter(|(_, built)| !*built)
+            .map(|(feature, _)| format!("no-{}", feature.name()))
+            .collect(),
+    }
+}
+
+/// Runs the body of an instruction in an optional group (`ProgramFeature`)
+///
+/// Without the group's cargo feature the body isn't compiled at all, and
+/// the instruction fails with `FeatureNotEnabled`. The arguments are listed
+/// so that build doesn't warn they're unused.
+macro_rules! feature_gated {
+    ($feature:literal, ($($arg:ident),*), $body:block) => {{
+        #[cfg(not(feature = $feature))]
+        {
+            let _ = ($($arg,)*);
+            return Err(AttestaError::FeatureNotEnabled.into());
+        }
+        #[cfg(feature = $feature)]
+        return $body;
+    }};
+}
+
 #[program]
 pub mod attesta {
+    // Instruction arguments write array lengths out (`[u8; 64]` is a
+    // P256_PUBKEY_LEN key): Anchor's IDL generator can't resolve constants
+    // from other crates.
     use super::*;
 
     /// Initializes a new Attesta account
@@ -22,6 +152,12 @@ pub mod attesta {
     /// instead of traditional private keys. The user provides their passkey's
     /// public key and we store it on-chain.
     ///
+    /// The passkey must also sign `smart_account::registration_challenge`,
+    /// which commits to the owner, this account's address and the passkey.
+    /// Otherwise anyone watching the mempool could create the owner's PDA
+    /// first with a passkey of their own. With an AAGUID allowlist, that
+    /// signature must attest one of the listed authenticator models.
+    ///
     /// # Accounts
     /// - `attesta_account`: The account to initialize (must be a PDA)
     /// - `owner`: The user who owns this account (signer)
@@ -30,31 +166,102 @@ pub mod attesta {
     /// # Arguments
     /// - `passkey_public_key`: The public key from the user's passkey (64 bytes)
     /// - `credential_id`: The credential ID from WebAuthn
-    /// - `policy`: Policy configuration (can be empty for default)
+    /// - `policy`: Policy configuration (can be empty for default); held to
+    ///   `AttestaAccount::set_policy`'s checks
+    /// - `privacy_mode`: Store only the SHA-256 hash of credential IDs
+    /// - `registration_sig`: The passkey's signature over the registration challenge
+    /// - `aaguid_allowlist`: Authenticator models passkeys must come from (empty for any),
+    ///   at most `MAX_AAGUID_ALLOWLIST_LEN`
     pub fn initialize(
         ctx: Context<Initialize>,
         passkey_public_key: [u8; 64],
         credential_id: Vec<u8>,
         policy: Vec<u8>,
+        privacy_mode: bool,
+        registration_sig: Vec<u8>,
+        aaguid_allowlist: Vec<[u8; 16]>,
     ) -> Result<()> {
-        let clock = Clock::get()?;
-        
-        // Create the AttestaAccount
-        let account = AttestaAccount::new(
-            *ctx.accounts.owner.key,
+        let account = new_account(
+            ctx.accounts.owner.key,
+            &ctx.accounts.attesta_account.key(),
             passkey_public_key,
             credential_id,
             policy,
-            clock.unix_timestamp,
-        );
+            privacy_mode,
+            &registration_sig,
+            aaguid_allowlist,
+        )?;
 
         // Serialize and store
-        let account_data = account.to_bytes()
-            .map_err(|_| AttestaError::SerializationFailed)?;
-        
-        ctx.accounts.attesta_account.data = account_data;
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(codes::ACCOUNT_INITIALIZED, &[("owner", ctx.accounts.owner.key)]);
+        Ok(())
+    }
+
+    /// Initializes an Attesta account at one of the owner's indexed addresses
+    ///
+    /// Takes the same arguments and checks as `initialize`, with the index
+    /// first. The account lives at `[b"attesta", owner, account_index (LE)]`
+    /// instead of the owner's own address, so an owner can hold several and
+    /// know each address before creating its passkey
+    /// (`smart_account::derive_attesta_account_by_index`). The account records
+    /// its index (`account_index`), and is otherwise the same as one
+    /// `initialize` creates.
+    ///
+    /// Fails with `AccountAlreadyInitialized` if the index is taken.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The account to initialize (PDA: `[b"attesta", owner, account_index (LE)]`, mut)
+    /// - `owner`: The user who owns this account (signer, pays the rent)
+    /// - `system_program`: The Solana system program
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_with_index(
+        ctx: Context<InitializeWithIndex>,
+        account_index: u16,
+        passkey_public_key: [u8; 64],
+        credential_id: Vec<u8>,
+        policy: Vec<u8>,
+        privacy_mode: bool,
+        registration_sig: Vec<u8>,
+        aaguid_allowlist: Vec<[u8; 16]>,
+    ) -> Result<()> {
+        let attesta_info = ctx.accounts.attesta_account.to_account_info();
+        require_keys_eq!(*attesta_info.owner, System::id(), AttestaError::AccountAlreadyInitialized);
+
+        let mut account = new_account(
+            ctx.accounts.owner.key,
+            attesta_info.key,
+            passkey_public_key,
+            credential_id,
+            policy,
+            privacy_mode,
+            &registration_sig,
+            aaguid_allowlist,
+        )?;
+        account.account_index = Some(account_index);
+
+        // Dust someone sent to the address beforehand counts toward the rent
+        let lamports = Rent::get()?.minimum_balance(ATTESTA_ACCOUNT_SPACE).saturating_sub(attesta_info.lamports());
+        let system_program = ctx.accounts.system_program.to_account_info();
+        if lamports > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.owner.to_account_info(),
+                        to: attesta_info.clone(),
+                    },
+                ),
+                lamports,
+            )?;
+        }
+        let owner_key = ctx.accounts.owner.key();
+        let index = account_index.to_le_bytes();
+        let seeds: &[&[u8]] = &[b"attesta", owner_key.as_ref(), &index, &[ctx.bumps.attesta_account]];
+        create_attesta_account(&attesta_info, system_program, seeds, &account)?;
 
-        msg!("Attesta account initialized for owner: {}", ctx.accounts.owner.key());
+        log_event(codes::ACCOUNT_INITIALIZED, &[("owner", &owner_key), ("index", &account_index)]);
         Ok(())
     }
 
@@ -66,74 +273,419 @@ pub mod attesta {
     /// # Accounts
     /// - `attesta_account`: The user's Attesta account (mut)
     /// - `authority`: The transaction authority (can be the owner or a program)
+    /// - `parent_account`: The parent account, required for sub-accounts
+    /// - `memo_program`: The SPL Memo program, required with `emit_memo`
+    ///
+    /// For an SPL token transfer, pass these as remaining accounts:
+    /// 1. The source token account, owned by `attesta_account` (mut)
+    /// 2. The token mint
+    /// 3. The destination token account (mut)
+    /// 4. The SPL Token program
     ///
     /// # Arguments
     /// - `webauthn_sig`: The WebAuthn signature from the user's device
     /// - `nonce`: The nonce for this transaction (must be > account's current nonce)
     /// - `message_hash`: The hash of the transaction being authorized
     /// - `transaction_data`: The transaction data to execute
-    pub fn execute(
-        ctx: Context<Execute>,
+    /// - `idempotency_key`: Optional key that makes retrying this instruction safe
+    /// - `memo`: The payment reference signed with the transaction (up to
+    ///   `MAX_MEMO_LEN` bytes of UTF-8; empty for none)
+    /// - `emit_memo`: Also post the memo through the SPL Memo program, so
+    ///   explorers show it
+    ///
+    /// # Return data
+    /// An `ExecuteOutcome`: the nonce, the policy result, the amount moved,
+    /// any deny reason and the memo's hash. It's set when a denial fails the
+    /// instruction too, where simulations still report it. An execution
+    /// emits `TransactionExecuted`.
+    ///
+    /// A transaction that needs more approvals doesn't fail: it waits in its
+    /// proposal PDA (`[b"proposal", attesta_account, message_hash]`, created
+    /// here with `authority` signing to pay the rent), approved by the
+    /// passkey that signed it, and `ProposalCreated` is emitted. The nonce
+    /// isn't used up. Submitting the same transaction again finds the
+    /// proposal already there and leaves it as it is.
+    ///
+    /// A transfer over a `Vault` policy's threshold doesn't fail either: it's
+    /// announced in its announcement PDA (`[b"announcement", attesta_account,
+    /// nonce (LE)]`, created here with `authority` signing to pay the rent),
+    /// the nonce is used up, and `TransferAnnounced` is emitted. The same
+    /// proof runs it with `execute_announced` once the vault's delay has
+    /// passed; until then any passkey can `veto_announcement`.
+    ///
+    /// A retry whose idempotency key, nonce, and message hash match an
+    /// earlier execution succeeds without running again, and reports
+    /// `ALREADY_EXECUTED` with the original nonce. That, and the nonce
+    /// check, come before the signature is parsed or verified: when several
+    /// relayers submit the same envelope, the ones that lose the race pay
+    /// for a lookup, not a P-256 verification.
+    ///
+    /// With lockout on (`AccountSettings::lockout_threshold`), a bad
+    /// signature doesn't fail the instruction: the failure is counted and
+    /// the outcome is denied with `DenyReason::AuthenticationFailed`. While
+    /// the account is locked out it's denied with `DenyReason::LockedOut`.
+    /// Nothing is executed either way.
+    ///
+    /// An account that pins the program version
+    /// (`AccountSettings::pinned_program_version`) fails with
+    /// `UpgradeNotAcknowledged` under any other version, until the owner
+    /// calls `acknowledge_upgrade`.
+    ///
+    /// An account whose auth mode is `OwnerOnly` fails with
+    /// `PasskeyNotAllowed`; its owner signs with `execute_as_owner` instead.
+    ///
+    /// An account that lists executors (see `add_executor`) fails with
+    /// `ExecutorNotAllowed` unless `authority` is one of them and signed.
+    /// With none listed, anyone can submit.
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute<'info>(
+        ctx: Context<'_, '_, '_, 'info, Execute<'info>>,
         webauthn_sig: Vec<u8>, // Serialized WebAuthnSignature
         nonce: u64,
         message_hash: [u8; 32],
         transaction_data: Vec<u8>,
+        idempotency_key: Option<IdempotencyKey>,
+        memo: Vec<u8>,
+        emit_memo: bool,
     ) -> Result<()> {
         // Deserialize the account from the account data
-        let mut account = AttestaAccount::from_bytes(&ctx.accounts.attesta_account.data)
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
             .map_err(|_| AttestaError::InvalidAccountData)?;
 
+        upgrade::check_program_version(&account, &program_version().hash())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(upgrade_error(e))
+            })?;
+
+        executors::check_executor(&account, ctx.accounts.authority.key, ctx.accounts.authority.is_signer)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(executor_error(e))
+            })?;
+
+        // A copy another relayer already landed, or a used-up nonce, is
+        // answered before the signature is even deserialized
+        if let Some(nonce) = check_replay(&account, nonce, &message_hash, idempotency_key.as_ref())
+            .map_err(execution_error)?
+        {
+            let outcome = ExecuteOutcome::new(&PolicyResult::AlreadyExecuted { nonce }, account.nonce, 0)
+                .with_memo_hash(memo_hash(&memo));
+            set_return_data(&outcome.to_return_data());
+            log_event(codes::ALREADY_EXECUTED, &[("nonce", &nonce)]);
+            return Ok(());
+        }
+
+        account.settings.check_transaction_data_len(transaction_data.len())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(AttestaError::TransactionTooLarge)
+            })?;
+        check_memo(&memo).map_err(|e| {
+            msg!("{}", e);
+            rejected(AttestaError::InvalidMemo)
+        })?;
+
         // Deserialize the WebAuthn signature
         let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
             .map_err(|_| AttestaError::InvalidSignature)?;
 
         // Create the authorization proof
-        let proof = AuthorizationProof::new(
+        let mut proof = AuthorizationProof::new(
             webauthn_signature,
             nonce,
             message_hash,
         );
+        proof.idempotency_key = idempotency_key;
+        proof.memo = memo;
+
+        // The same code the wallet showed next to the passkey prompt
+        log_event(codes::DISPLAY_CODE, &[("code", &display_code(&compute_challenge(&account.owner, nonce, &message_hash)))]);
 
         // Execute the transaction
-        let result = execute_transaction(&mut account, &proof, &transaction_data)
-            .map_err(|e| AttestaError::ExecutionFailed)?;
+        // A sub-account's transactions must also pass its parent's policy
+        let parent = match account.parent {
+            Some(parent_key) => {
+                let parent_account = ctx.accounts.parent_account.as_ref()
+                    .ok_or(AttestaError::MissingParentAccount)?;
+                require_keys_eq!(parent_account.key(), parent_key, AttestaError::MissingParentAccount);
+                Some(AttestaAccount::from_bytes_strict(&parent_account.data)
+                    .map_err(|_| AttestaError::InvalidAccountData)?)
+            }
+            None => None,
+        };
+
+        let attesta_key = ctx.accounts.attesta_account.key();
+        let result = execute_transaction(&mut account, &attesta_key, parent.as_ref(), &proof, &transaction_data)
+            .map_err(execution_error)?;
+        if !matches!(result, PolicyResult::AlreadyExecuted { .. }) {
+            announce_primary_activity(&account, &attesta_key, &proof.webauthn_sig.credential_id, nonce, result == PolicyResult::Allowed);
+        }
+
+        let transfer = TokenTransfer::from_transaction_data(&transaction_data);
+        let amount_charged = match (&result, &transfer) {
+            (PolicyResult::Allowed, Some(transfer)) => transfer.amount,
+            _ => 0,
+        };
+        let memo_hash = memo_hash(&proof.memo);
+        let signer_hash = credential_id_hash(&proof.webauthn_sig.credential_id);
+        let outcome = ExecuteOutcome::new(&result, account.nonce, amount_charged)
+            .with_memo_hash(memo_hash)
+            .with_credential_id_hash(Some(signer_hash));
+        if !outcome.executed() {
+            set_return_data(&outcome.to_return_data());
+        }
 
         match result {
             PolicyResult::Allowed => {
+                if account.proof_log_enabled {
+                    let proof_log = ctx.accounts.proof_log.as_mut()
+                        .ok_or(AttestaError::MissingProofLog)?;
+                    require_keys_eq!(proof_log.attesta_account, attesta_key, AttestaError::MissingProofLog);
+                    proof_log.append(ProofLogEntry::new(&proof, Clock::get()?.unix_timestamp))?;
+                }
+
+                // Accounts created before idempotency records may not have room for all of them
+                let capacity = ctx.accounts.attesta_account.to_account_info().data_len();
+                fit_idempotency_records(&mut account, capacity);
+
                 // Serialize and save the updated account
                 let account_data = account.to_bytes()
                     .map_err(|_| AttestaError::SerializationFailed)?;
                 ctx.accounts.attesta_account.data = account_data;
-                msg!("Transaction executed successfully");
+
+                if let Some(transfer) = transfer {
+                    let attesta_info = ctx.accounts.attesta_account.to_account_info();
+                    transfer_tokens(&attesta_info, &account, &transfer, ctx.remaining_accounts)?;
+                }
+                if emit_memo && !proof.memo.is_empty() {
+                    let memo_program = ctx.accounts.memo_program.as_ref()
+                        .ok_or(AttestaError::MissingMemoProgram)?;
+                    let instruction = Instruction::new_with_bytes(MEMO_PROGRAM_ID, &proof.memo, vec![]);
+                    invoke(&instruction, &[memo_program.to_account_info()])?;
+                }
+                // Only now: invoking the token and memo programs clears any return data
+                set_return_data(&outcome.to_return_data());
+
+                emit!(TransactionExecuted {
+                    attesta_account: attesta_key,
+                    nonce: account.nonce,
+                    message_hash,
+                    memo_hash,
+                    credential_id_hash: Some(signer_hash),
+                });
+                emit_allowed_context(&account, &attesta_key, &transaction_data);
+                log_event(
+                    codes::EXECUTED,
+                    &[("nonce", &account.nonce), ("amount", &amount_charged), ("signer", &"passkey")],
+                );
+                Ok(())
+            }
+            PolicyResult::AlreadyExecuted { nonce } => {
+                log_event(codes::ALREADY_EXECUTED, &[("nonce", &nonce)]);
+                Ok(())
+            }
+            PolicyResult::Denied(DenyReason::AuthenticationFailed) => {
+                // Succeed so the failure count is written
+                let capacity = ctx.accounts.attesta_account.to_account_info().data_len();
+                fit_idempotency_records(&mut account, capacity);
+                save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+                log_event(codes::AUTH_FAILED, &[("count", &account.failed_auth_count)]);
+                Ok(())
+            }
+            PolicyResult::Denied(DenyReason::LockedOut { until }) => {
+                log_event(codes::LOCKED_OUT, &[("until", &until)]);
                 Ok(())
             }
             PolicyResult::RequiresApproval => {
-                msg!("Transaction requires additional approvals");
-                Err(AttestaError::RequiresApproval.into())
+                let proposed = PendingTransaction::propose(
+                    &account,
+                    transaction_data,
+                    &proof.webauthn_sig.credential_id,
+                    ctx.accounts.authority.key(),
+                    Clock::get()?.unix_timestamp,
+                )
+                .map_err(proposal_error)?;
+                let (proposal_key, pending, created) = open_proposal(ctx.accounts, &account, &attesta_key, proposed)?;
+                // Only now: creating the proposal invokes the system program, which clears any return data
+                set_return_data(&outcome.to_return_data());
+
+                if created {
+                    emit!(ProposalCreated {
+                        attesta_account: attesta_key,
+                        proposal: proposal_key,
+                        message_hash: pending.message_hash,
+                        required_approvals: pending.required_approvals,
+                        expires_at: pending.expires_at(),
+                    });
+                }
+                log_event(
+                    codes::NEEDS_APPROVAL,
+                    &[
+                        ("proposal", &proposal_key),
+                        ("approvals", &pending.approvals.len()),
+                        ("required", &pending.required_approvals),
+                    ],
+                );
+                Ok(())
+            }
+            PolicyResult::Announced { executable_at } => {
+                let amount = transfer.as_ref().map_or(0, |transfer| transfer.amount);
+                let announced = Announcement::new(
+                    transaction_data,
+                    &proof,
+                    executable_at,
+                    ctx.accounts.authority.key(),
+                    Clock::get()?.unix_timestamp,
+                );
+                let announcement_key = open_announcement(ctx.accounts, &attesta_key, &announced)?;
+                save_account(&mut ctx.accounts.attesta_account, &account)?;
+                // Only now: creating the announcement invokes the system program, which clears any return data
+                set_return_data(&outcome.to_return_data());
+
+                emit!(TransferAnnounced {
+                    attesta_account: attesta_key,
+                    announcement: announcement_key,
+                    nonce: announced.nonce,
+                    amount,
+                    executable_at,
+                });
+                log_event(codes::ANNOUNCED, &[("nonce", &announced.nonce), ("amount", &amount), ("at", &executable_at)]);
+                Ok(())
+            }
+            not_allowed => {
+                log_not_allowed(&not_allowed);
+                Err(denied_error(&not_allowed).into())
             }
-            PolicyResult::Denied => {
-                msg!("Transaction denied by policy");
-                Err(AttestaError::PolicyDenied.into())
+        }
+    }
+
+    /// Executes a transaction the account's owner signed with their wallet
+    ///
+    /// For accounts still migrating to passkeys: only accepted when the
+    /// account's auth mode (see `set_auth_mode`) allows the owner. The
+    /// transaction goes through the same settings and policy checks as
+    /// `execute`, and uses up `nonce` when it runs. Passkey-only features
+    /// don't apply: there's no proof to log, idempotency key or memo.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The account the transaction runs from (mut)
+    /// - `owner`: The account owner (signer)
+    /// - `parent_account`: The parent account, if `attesta_account` is a sub-account
+    /// - Remaining accounts: for a token transfer, as for `execute`
+    ///
+    /// # Arguments
+    /// - `nonce`: The next nonce (must be > the account's current nonce)
+    /// - `transaction_data`: The transaction data to execute
+    ///
+    /// # Return data
+    /// An `ExecuteOutcome`, as for `execute`.
+    pub fn execute_as_owner<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteAsOwner<'info>>,
+        nonce: u64,
+        transaction_data: Vec<u8>,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        upgrade::check_program_version(&account, &program_version().hash())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(upgrade_error(e))
+            })?;
+
+        account.settings.check_transaction_data_len(transaction_data.len())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(AttestaError::TransactionTooLarge)
+            })?;
+
+        let parent = match account.parent {
+            Some(parent_key) => {
+                let parent_account = ctx.accounts.parent_account.as_ref()
+                    .ok_or(AttestaError::MissingParentAccount)?;
+                require_keys_eq!(parent_account.key(), parent_key, AttestaError::MissingParentAccount);
+                Some(AttestaAccount::from_bytes_strict(&parent_account.data)
+                    .map_err(|_| AttestaError::InvalidAccountData)?)
             }
+            None => None,
+        };
+
+        let attesta_key = ctx.accounts.attesta_account.key();
+        let now = Clock::get()?.unix_timestamp;
+        let result = auth_mode::execute_as_owner(
+            &mut account,
+            &attesta_key,
+            parent.as_ref(),
+            ctx.accounts.owner.key,
+            nonce,
+            &transaction_data,
+            now,
+        )
+        .map_err(|e| {
+            msg!("{}", e);
+            rejected(auth_mode_error(e))
+        })?;
+
+        let transfer = TokenTransfer::from_transaction_data(&transaction_data);
+        let amount_charged = match (&result, &transfer) {
+            (PolicyResult::Allowed, Some(transfer)) => transfer.amount,
+            _ => 0,
+        };
+        let outcome = ExecuteOutcome::new(&result, account.nonce, amount_charged);
+        set_return_data(&outcome.to_return_data());
+        if result != PolicyResult::Allowed {
+            log_not_allowed(&result);
+            return Err(denied_error(&result).into());
+        }
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        if let Some(transfer) = transfer {
+            let attesta_info = ctx.accounts.attesta_account.to_account_info();
+            transfer_tokens(&attesta_info, &account, &transfer, ctx.remaining_accounts)?;
+            // Invoking the token program clears the return data
+            set_return_data(&outcome.to_return_data());
         }
+
+        emit!(TransactionExecuted {
+            attesta_account: attesta_key,
+            nonce: account.nonce,
+            message_hash: transaction_message_hash(&transaction_data),
+            memo_hash: None,
+            credential_id_hash: None,
+        });
+        emit_allowed_context(&account, &attesta_key, &transaction_data);
+        log_event(codes::EXECUTED, &[("nonce", &account.nonce), ("amount", &amount_charged), ("signer", &"owner")]);
+        Ok(())
     }
 
     /// Updates the policy for an account
     ///
     /// Allows the account owner to change their policy settings (spending limits, etc.)
+    /// This replaces every policy the account holds; use `add_policy` and
+    /// friends to change one of several.
     ///
     /// # Accounts
     /// - `attesta_account`: The account to update (mut)
     /// - `owner`: The account owner (signer)
     ///
     /// # Arguments
-    /// - `new_policy`: The new policy configuration
+    /// - `new_policy`: The new policy configuration (empty, or a serialized
+    ///   `Policy` that passes `Policy::validate_config` and is estimated to
+    ///   cost at most `MAX_POLICY_COMPUTE_UNITS`). Its time locks are held to
+    ///   `Policy::validate_timestamps` with no long locks: those need a
+    ///   passkey, through `add_policy` or `replace_policy`.
+    /// - `expected_version`: The account's `state_version` when the caller
+    ///   read it; fails with `ConcurrentModification` if it has changed
     pub fn update_policy(
         ctx: Context<UpdatePolicy>,
         new_policy: Vec<u8>,
+        expected_version: u64,
     ) -> Result<()> {
         // Deserialize the account
-        let mut account = AttestaAccount::from_bytes(&ctx.accounts.attesta_account.data)
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
             .map_err(|_| AttestaError::InvalidAccountData)?;
 
         // Verify the owner
@@ -141,89 +693,4076 @@ pub mod attesta {
             account.owner == *ctx.accounts.owner.key,
             AttestaError::Unauthorized
         );
+        check_no_recovery_pending(&account)?;
+        check_state_version(&account, expected_version)?;
 
-        // Update the policy
-        account.policy = new_policy;
+        check_policy(&new_policy, Clock::get()?.unix_timestamp)?;
+
+        // Update the policy, dropping any others
+        account.set_policies(vec![new_policy]);
+        account.bump_state_version();
+        check_destination_room(&account, ctx.accounts.attesta_account.to_account_info().data_len())?;
         
         // Serialize and save
         let account_data = account.to_bytes()
             .map_err(|_| AttestaError::SerializationFailed)?;
         ctx.accounts.attesta_account.data = account_data;
 
-        msg!("Policy updated for account: {}", ctx.accounts.attesta_account.key());
+        emit!(PolicyUpdated {
+            attesta_account: ctx.accounts.attesta_account.key(),
+            policy_count: account.policies().len() as u8,
+            policy_hash: account.policy_hash,
+        });
+        log_event(codes::POLICY_UPDATED, &[("account", &ctx.accounts.attesta_account.key()), ("count", &account.policies().len())]);
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = owner,
-        space = 8 + 32 + 64 + 4 + 256 + 4 + 256 + 8 + 8 + 8, // discriminator + account data
-        seeds = [b"attesta", owner.key.as_ref()],
-        bump
-    )]
-    pub attesta_account: Account<'info, AttestaAccountData>,
-    
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
-}
+    /// Stores an encrypted backup in the account's backup escrow PDA
+    ///
+    /// The backup is already encrypted with a key derived from the user's
+    /// recovery phrase, so it's safe for anyone to read. Writing it requires
+    /// passkey authorization, and the owner pays the rent.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `backup`: The escrow PDA to create (seeds: `[b"backup", attesta_account]`)
+    /// - `owner`: The account owner (signer, pays rent)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature authorizing the backup
+    /// - `nonce`: The nonce for this authorization
+    /// - `backup`: Serialized EncryptedBackup (at most 2KB)
+    pub fn store_backup(
+        ctx: Context<StoreBackup>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        backup: Vec<u8>,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
 
-impl<'info> Initialize<'info> {
-    // Helper to get the seed for PDA derivation
-    pub fn get_seed(&self) -> Vec<u8> {
-        // Use first 32 bytes of owner key as seed
-        self.owner.key().as_ref()[..32].to_vec()
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        authorize(&mut account, &webauthn_sig, nonce, BACKUP_WRITE_ACTION, &backup)?;
+
+        let escrow = &mut ctx.accounts.backup;
+        escrow.attesta_account = ctx.accounts.attesta_account.key();
+        escrow.bump = ctx.bumps.backup;
+        escrow.write(backup, Clock::get()?.unix_timestamp)?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(codes::BACKUP_STORED, &[("account", &ctx.accounts.attesta_account.key())]);
+        Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Execute<'info> {
-    #[account(mut)]
-    pub attesta_account: Account<'info, AttestaAccountData>,
-    
-    /// CHECK: Can be the owner or a program that's authorized to execute
-    pub authority: UncheckedAccount<'info>,
-}
+    /// Turns on the account's proof log
+    ///
+    /// Creates the proof log PDA; from then on every successful `execute`
+    /// must pass it and is recorded there. Requires passkey authorization,
+    /// and the owner pays the rent. There's no turning it off: a log that
+    /// could be stopped wouldn't prove much.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `proof_log`: The proof log PDA to create (seeds: `[b"proof_log", attesta_account]`)
+    /// - `owner`: The account owner (signer, pays rent)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over the proof log address
+    /// - `nonce`: The nonce for this authorization
+    pub fn enable_proof_log(
+        ctx: Context<EnableProofLog>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
 
-#[derive(Accounts)]
-pub struct UpdatePolicy<'info> {
-    #[account(mut)]
-    pub attesta_account: Account<'info, AttestaAccountData>,
-    
-    pub owner: Signer<'info>,
-}
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
 
-/// Wrapper account type for Anchor
-/// This wraps our AttestaAccount so Anchor can manage it
-#[account]
-pub struct AttestaAccountData {
-    pub data: Vec<u8>, // Serialized AttestaAccount
-}
+        let proof_log_key = ctx.accounts.proof_log.key();
+        authorize(&mut account, &webauthn_sig, nonce, PROOF_LOG_ENABLE_ACTION, proof_log_key.as_ref())?;
+        account.proof_log_enabled = true;
 
-#[error_code]
-pub enum AttestaError {
-    #[msg("Invalid signature format")]
-    InvalidSignature,
-    
-    #[msg("Transaction execution failed")]
-    ExecutionFailed,
-    
-    #[msg("Transaction requires additional approvals")]
-    RequiresApproval,
-    
-    #[msg("Transaction denied by policy")]
-    PolicyDenied,
-    
-    #[msg("Unauthorized: not the account owner")]
-    Unauthorized,
-    
-    #[msg("Failed to serialize account data")]
-    SerializationFailed,
-    
-    #[msg("Invalid account data format")]
-    InvalidAccountData,
+        let proof_log = &mut ctx.accounts.proof_log;
+        proof_log.attesta_account = ctx.accounts.attesta_account.key();
+        proof_log.log = ProofLog::default().to_bytes()
+            .map_err(|_| AttestaError::SerializationFailed)?;
+        proof_log.bump = ctx.bumps.proof_log;
+
+        let EnableProofLog { attesta_account, owner, system_program, .. } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(codes::PROOF_LOG_ENABLED, &[("account", &attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Replaces the encrypted backup held in the escrow PDA
+    ///
+    /// The previous backup is overwritten completely. Requires passkey
+    /// authorization over the new backup bytes. A backup rekeyed to a new
+    /// recovery phrase (`EncryptedBackup::rekey`) can only be replaced by one
+    /// of the same or a later rekey generation.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `backup`: The existing escrow PDA (mut)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature authorizing the update
+    /// - `nonce`: The nonce for this authorization
+    /// - `backup`: Serialized EncryptedBackup (at most 2KB)
+    pub fn update_backup(
+        ctx: Context<UpdateBackup>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        backup: Vec<u8>,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        authorize(&mut account, &webauthn_sig, nonce, BACKUP_WRITE_ACTION, &backup)?;
+
+        let generation = ctx.accounts.backup.write(backup, Clock::get()?.unix_timestamp)?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(
+            codes::BACKUP_UPDATED,
+            &[("account", &ctx.accounts.attesta_account.key()), ("generation", &generation)],
+        );
+        Ok(())
+    }
+
+    /// Deletes the backup escrow PDA and returns its rent to the owner
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `backup`: The escrow PDA to close (mut)
+    /// - `owner`: The account owner (receives the reclaimed rent)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature authorizing the deletion
+    /// - `nonce`: The nonce for this authorization
+    pub fn delete_backup(
+        ctx: Context<DeleteBackup>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        // Rent always goes back to the owner, never to whoever submits the instruction
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        let backup_key = ctx.accounts.backup.key();
+        authorize(&mut account, &webauthn_sig, nonce, BACKUP_DELETE_ACTION, backup_key.as_ref())?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(codes::BACKUP_DELETED, &[("account", &ctx.accounts.attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Registers an additional passkey on the account
+    ///
+    /// The first additional passkey turns the account into a multi-passkey
+    /// account, with the original passkey as its primary. The account grows
+    /// to fit the registry and the owner pays any extra rent: at most
+    /// `PASSKEY_SLOT_SPACE` per passkey, up to `MAX_PASSKEYS` of them,
+    /// whatever the registry's own `max_passkeys`. Credential IDs longer
+    /// than 32 bytes are stored as their SHA-256 hash to keep that rent
+    /// down; the client keeps the full ID (and backs it up).
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for the extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from an existing passkey
+    /// - `nonce`: The nonce for this authorization
+    /// - `public_key`: The new passkey's public key (64 bytes)
+    /// - `credential_id`: The new passkey's credential ID
+    /// - `name`: A label for the new passkey (e.g. "Laptop"), at most
+    ///   `MAX_PASSKEY_NAME_LEN` bytes
+    /// - `registration_sig`: The new passkey's signature over its registration
+    ///   challenge, attesting its model; may be empty if the account has no
+    ///   AAGUID allowlist
+    /// - `expected_version`: The account's `state_version` when the caller read it
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_passkey(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        public_key: [u8; 64],
+        credential_id: Vec<u8>,
+        name: String,
+        registration_sig: Vec<u8>,
+        expected_version: u64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+        check_state_version(&account, expected_version)?;
+
+        let aaguid = enrolled_aaguid(
+            &account.owner,
+            &ctx.accounts.attesta_account.key(),
+            &account.settings,
+            &public_key,
+            &credential_id,
+            optional_registration(&registration_sig),
+        )?;
+
+        let payload = [public_key.as_ref(), credential_id.as_slice()].concat();
+        authorize(&mut account, &webauthn_sig, nonce, PASSKEY_ADD_ACTION, &payload)?;
+
+        let mut registry = account.passkey_registry_or_default()
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        require!(!registry.is_full(), AttestaError::TooManyPasskeys);
+        let lookup_id = account.credential_lookup_id(&credential_id);
+        let entry = PasskeyEntry::compact(public_key, lookup_id, name, Clock::get()?.unix_timestamp).with_aaguid(aaguid);
+        registry
+            .add_entry(entry)
+            .map_err(|_| AttestaError::InvalidPasskey)?;
+        // Validates the registry too (keys on the curve, no duplicates, limits)
+        account.set_passkey_registry(&registry)
+            .map_err(|_| AttestaError::InvalidPasskey)?;
+        account.bump_state_version();
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(codes::PASSKEY_ADDED, &[("account", &attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Removes a passkey from the account
+    ///
+    /// The passkey is revoked rather than erased: a tombstone is kept so that
+    /// signatures and approvals it made before removal are rejected.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from a remaining passkey
+    /// - `nonce`: The nonce for this authorization
+    /// - `credential_id`: The credential ID of the passkey to remove
+    /// - `expected_version`: The account's `state_version` when the caller read it
+    pub fn remove_passkey(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        credential_id: Vec<u8>,
+        expected_version: u64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+        check_state_version(&account, expected_version)?;
+
+        authorize(&mut account, &webauthn_sig, nonce, PASSKEY_REMOVE_ACTION, &credential_id)?;
+
+        let mut registry = account.passkey_registry()
+            .map_err(|_| AttestaError::InvalidAccountData)?
+            .ok_or(AttestaError::InvalidPasskey)?;
+        let lookup_id = account.credential_lookup_id(&credential_id);
+        let removed_key = registry.find_passkey(&lookup_id).map(|entry| entry.public_key);
+        let now = Clock::get()?.unix_timestamp;
+        registry
+            .remove_passkey(&lookup_id, now)
+            .map_err(|_| AttestaError::InvalidPasskey)?;
+        account.set_passkey_registry(&registry)
+            .map_err(|_| AttestaError::InvalidPasskey)?;
+        // Proofs it signed before today can still be checked against the proof log
+        if let Some(public_key) = removed_key {
+            account.retire_key(&lookup_id, public_key, now);
+        }
+        account.bump_state_version();
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(codes::PASSKEY_REVOKED, &[("account", &attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Switches an existing account to privacy mode
+    ///
+    /// Replaces every stored credential ID with its SHA-256 hash, so the
+    /// account no longer lists the user's devices. Passkeys keep working:
+    /// signatures are matched by hashing the credential ID they present.
+    /// This can't be undone.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature authorizing the change
+    /// - `nonce`: The nonce for this authorization
+    pub fn enable_privacy_mode(
+        ctx: Context<UpdatePolicy>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+
+        let attesta_key = ctx.accounts.attesta_account.key();
+        authorize(&mut account, &webauthn_sig, nonce, PRIVACY_MODE_ACTION, attesta_key.as_ref())?;
+
+        account.enable_privacy_mode()
+            .map_err(|_| AttestaError::SerializationFailed)?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(codes::PRIVACY_MODE_ENABLED, &[("account", &attesta_key)]);
+        Ok(())
+    }
+
+    /// Creates a sub-account whose transactions must also pass this account's policy
+    ///
+    /// The sub-account gets its own passkey and policy and the parent's
+    /// owner. Sub-accounts can't have sub-accounts of their own.
+    ///
+    /// # Accounts
+    /// - `parent_account`: The parent Attesta account (mut, nonce is consumed)
+    /// - `sub_account`: The sub-account to create (PDA: `[b"sub_account", parent, [index]]`)
+    /// - `owner`: The parent's owner (signer, pays rent)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `index`: Tells the parent's sub-accounts apart
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from the parent's passkey over the
+    ///   `SUB_ACCOUNT_CREATE_ACTION` for `sub_account_payload(...)`
+    /// - `nonce`: The parent's nonce for this authorization
+    /// - `passkey_public_key`, `credential_id`, `policy`: As for `initialize`
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_sub_account(
+        ctx: Context<InitializeSubAccount>,
+        index: u8,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        passkey_public_key: [u8; 64],
+        credential_id: Vec<u8>,
+        policy: Vec<u8>,
+    ) -> Result<()> {
+        let mut parent = AttestaAccount::from_bytes_strict(&ctx.accounts.parent_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            parent.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        let payload = sub_account_payload(index, &passkey_public_key, &credential_id, &policy);
+        let sub_account = new_sub_account(
+            &parent,
+            ctx.accounts.parent_account.key(),
+            index,
+            passkey_public_key,
+            credential_id,
+            policy,
+            Clock::get()?.unix_timestamp,
+        )
+        .map_err(|_| AttestaError::NestedSubAccount)?;
+        sub_account.validate().map_err(|_| AttestaError::InvalidAccountData)?;
+
+        authorize(&mut parent, &webauthn_sig, nonce, SUB_ACCOUNT_CREATE_ACTION, &payload)?;
+
+        save_account(&mut ctx.accounts.parent_account, &parent)?;
+        save_account(&mut ctx.accounts.sub_account, &sub_account)?;
+
+        log_event(
+            codes::SUB_ACCOUNT_CREATED,
+            &[("account", &ctx.accounts.parent_account.key()), ("sub_account", &ctx.accounts.sub_account.key()), ("index", &index)],
+        );
+        Ok(())
+    }
+
+    /// Changes the account-level checks applied before the policy
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for any extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over the `SETTINGS_UPDATE_ACTION`
+    ///   for `AccountSettings::to_bytes()` of the new settings
+    /// - `nonce`: The nonce for this authorization
+    /// - `reject_zero_amount`: Deny transfers of a zero amount
+    /// - `reject_self_transfer`: Deny transfers back to the account itself
+    /// - `max_transaction_data_len`: Lower transaction data limit (0 for the global one)
+    /// - `lockout_threshold`: Failed signatures in a row before `execute` locks (0 for never)
+    /// - `aaguid_allowlist`: Authenticator models new passkeys must come from (empty for any)
+    /// - `pinned_program_version`: The program version hash `execute` may run
+    ///   under (see `get_program_version`), or `None` for any
+    /// - `webauthn_profile`: `WebAuthnVerificationProfile` bits: the optional
+    ///   WebAuthn checks passkey signatures are held to (0 for none)
+    /// - `rp_id_hash`, `origin_hash`: SHA-256 of the relying party ID and
+    ///   origin those checks expect; both or neither
+    /// - `log_allowed_sample_rate`: Percentage of allowed executions that emit
+    ///   `AllowedWithContext` (0 for none)
+    /// - `recovery_allowance`: Largest transfer `execute` allows while a
+    ///   recovery is pending, in base units (0 for none)
+    /// - `expected_version`: The account's `state_version` when the caller read it
+    ///
+    /// Changing the profile or the relying party takes the primary passkey's
+    /// signature; any passkey can change the rest.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_settings(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        reject_zero_amount: bool,
+        reject_self_transfer: bool,
+        max_transaction_data_len: u16,
+        lockout_threshold: u8,
+        aaguid_allowlist: Vec<[u8; 16]>,
+        pinned_program_version: Option<[u8; 32]>,
+        webauthn_profile: u8,
+        rp_id_hash: Option<[u8; 32]>,
+        origin_hash: Option<[u8; 32]>,
+        log_allowed_sample_rate: u8,
+        recovery_allowance: u64,
+        expected_version: u64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+        check_state_version(&account, expected_version)?;
+
+        let relying_party = match (rp_id_hash, origin_hash) {
+            (Some(rp_id_hash), Some(origin_hash)) => Some(RelyingParty { rp_id_hash, origin_hash }),
+            (None, None) => None,
+            _ => return Err(AttestaError::InvalidWebAuthnProfile.into()),
+        };
+        let settings = AccountSettings {
+            reject_zero_amount,
+            reject_self_transfer,
+            max_transaction_data_len,
+            lockout_threshold,
+            aaguid_allowlist,
+            pinned_program_version,
+            webauthn_profile: WebAuthnVerificationProfile::from_bits(webauthn_profile)
+                .ok_or(AttestaError::InvalidWebAuthnProfile)?,
+            relying_party,
+            // Only `set_auth_mode` changes these
+            auth_mode: account.settings.auth_mode,
+            auth_mode_locked: account.settings.auth_mode_locked,
+            // Only `add_executor` and `remove_executor` change this
+            authorized_executors: account.settings.authorized_executors.clone(),
+            log_allowed_sample_rate,
+            recovery_allowance,
+        };
+        require!(
+            settings.aaguid_allowlist.len() <= MAX_AAGUID_ALLOWLIST_LEN,
+            AttestaError::AllowlistTooLong
+        );
+        require!(
+            settings.relying_party.is_some() || !settings.webauthn_profile.needs_relying_party(),
+            AttestaError::InvalidWebAuthnProfile
+        );
+        require!(log_allowed_sample_rate <= MAX_SAMPLE_RATE, AttestaError::InvalidSampleRate);
+        require!(settings.is_valid(), AttestaError::TransactionTooLarge);
+        if account.settings.changes_webauthn_checks(&settings) {
+            authorize_admin(&mut account, &webauthn_sig, nonce, SETTINGS_UPDATE_ACTION, &settings.to_bytes())?;
+        } else {
+            authorize(&mut account, &webauthn_sig, nonce, SETTINGS_UPDATE_ACTION, &settings.to_bytes())?;
+        }
+
+        account.settings = settings;
+        account.bump_state_version();
+
+        // Accounts created before settings existed need a few more bytes
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(codes::SETTINGS_UPDATED, &[("account", &attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Starts replacing a lost primary passkey
+    ///
+    /// Signed by one of the account's other passkeys (a guardian), whose
+    /// approval counts towards the registry's recovery threshold.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `payer`: Pays fees and any extra space (anyone; the passkey authorizes)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from a guardian passkey
+    /// - `nonce`: The nonce for this authorization
+    /// - `new_public_key`: The replacement passkey's public key
+    /// - `new_credential_id`: The replacement passkey's credential ID
+    /// - `new_passkey_registration`: The replacement passkey's signature over
+    ///   its registration challenge, attesting its model; may be empty if the
+    ///   account has no AAGUID allowlist (checked again at finalization)
+    pub fn initiate_recovery(
+        ctx: Context<Recover>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        new_public_key: [u8; 64],
+        new_credential_id: Vec<u8>,
+        new_passkey_registration: Vec<u8>,
+    ) -> Result<()> {
+        let account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        let new_aaguid = enrolled_aaguid(
+            &account.owner,
+            &ctx.accounts.attesta_account.key(),
+            &account.settings,
+            &new_public_key,
+            &new_credential_id,
+            optional_registration(&new_passkey_registration),
+        )?;
+
+        start_recovery(
+            ctx.accounts,
+            RecoveryMode::Recovery,
+            &webauthn_sig,
+            nonce,
+            new_public_key,
+            &new_credential_id,
+            new_aaguid,
+        )?;
+        log_event(codes::RECOVERY_INITIATED, &[("account", &ctx.accounts.attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Adds a guardian's approval to the pending recovery
+    ///
+    /// Takes the same accounts as `initiate_recovery`.
+    pub fn approve_recovery(ctx: Context<Recover>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let progress = add_recovery_approval(ctx.accounts, RecoveryMode::Recovery, &webauthn_sig, nonce)?;
+        log_event(codes::RECOVERY_APPROVED, &[("approvals", &progress.approvals), ("threshold", &progress.threshold)]);
+        Ok(())
+    }
+
+    /// Installs the recovered passkey once approved and the delay has passed
+    ///
+    /// Permissionless: the guardians' approvals are what authorize it.
+    pub fn finalize_recovery(ctx: Context<Recover>) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        social_recovery::finalize_recovery(&mut account, Clock::get()?.unix_timestamp)
+            .map_err(recovery_error)?;
+
+        let Recover { attesta_account, payer, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, payer, system_program)?;
+
+        log_event(codes::RECOVERY_FINALIZED, &[("account", &attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Cancels a pending recovery
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from any registered passkey
+    /// - `nonce`: The nonce for this authorization
+    pub fn cancel_recovery(ctx: Context<Recover>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        social_recovery::cancel_recovery(&mut account, webauthn_signature, nonce)
+            .map_err(recovery_error)?;
+
+        let Recover { attesta_account, payer, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, payer, system_program)?;
+
+        log_event(codes::RECOVERY_CANCELLED, &[("account", &attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Starts a recovery drill
+    ///
+    /// Works exactly like `initiate_recovery`, but a successful drill only
+    /// records when it happened: no keys change and a real recovery in
+    /// progress isn't affected.
+    pub fn initiate_recovery_drill(
+        ctx: Context<Recover>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        new_public_key: [u8; 64],
+        new_credential_id: Vec<u8>,
+    ) -> Result<()> {
+        let progress = start_recovery(
+            ctx.accounts,
+            RecoveryMode::Drill,
+            &webauthn_sig,
+            nonce,
+            new_public_key,
+            &new_credential_id,
+            None,
+        )?;
+        finish_drill_step(ctx.accounts, progress)
+    }
+
+    /// Adds a guardian's approval to the pending recovery drill
+    pub fn approve_recovery_drill(ctx: Context<Recover>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let progress = add_recovery_approval(ctx.accounts, RecoveryMode::Drill, &webauthn_sig, nonce)?;
+        finish_drill_step(ctx.accounts, progress)
+    }
+
+    /// Names (or removes) the beneficiary who inherits the account after inactivity
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for any extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over the `INHERITANCE_CONFIGURE_ACTION`
+    ///   for `config`
+    /// - `nonce`: The nonce for this authorization
+    /// - `config`: Serialized `InheritanceConfig`, or empty to remove it
+    pub fn configure_inheritance(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        config: Vec<u8>,
+    ) -> Result<()> {
+        feature_gated!("feature-inheritance", (ctx, webauthn_sig, nonce, config), {
+            let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+                .map_err(|_| AttestaError::InvalidAccountData)?;
+
+            require!(
+                account.owner == *ctx.accounts.owner.key,
+                AttestaError::Unauthorized
+            );
+            check_no_recovery_pending(&account)?;
+
+            let config = if config.is_empty() {
+                None
+            } else {
+                Some(InheritanceConfig::from_bytes(&config).map_err(|_| AttestaError::InvalidInheritanceConfig)?)
+            };
+            let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+                .map_err(|_| AttestaError::InvalidSignature)?;
+            inheritance::configure_inheritance(&mut account, webauthn_signature, nonce, config, Clock::get()?.unix_timestamp)
+                .map_err(inheritance_error)?;
+
+            let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+            save_account_resized(attesta_account, &account, owner, system_program)?;
+
+            log_event(codes::INHERITANCE_CONFIGURED, &[("account", &attesta_account.key())]);
+            Ok(())
+        })
+    }
+
+    /// Shows the owner is still active, cancelling any pending inheritance claim
+    ///
+    /// Takes the same accounts as `initiate_recovery`.
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over the `HEARTBEAT_ACTION`
+    /// - `nonce`: The nonce for this authorization
+    pub fn heartbeat(ctx: Context<Recover>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        feature_gated!("feature-inheritance", (ctx, webauthn_sig, nonce), {
+            let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+                .map_err(|_| AttestaError::InvalidAccountData)?;
+
+            let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+                .map_err(|_| AttestaError::InvalidSignature)?;
+            inheritance::heartbeat(&mut account, webauthn_signature, nonce, Clock::get()?.unix_timestamp)
+                .map_err(inheritance_error)?;
+
+            let Recover { attesta_account, payer, system_program } = ctx.accounts;
+            save_account_resized(attesta_account, &account, payer, system_program)?;
+            log_event(codes::HEARTBEAT, &[("account", &attesta_account.key())]);
+            Ok(())
+        })
+    }
+
+    /// Inserts a policy into the account's ordered policy list
+    ///
+    /// Policies are evaluated in order on every execution: the first that
+    /// denies decides, and one that requires approval outranks any that allow.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for any extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over the `POLICY_ADD_ACTION`
+    ///   for `policy_change_payload(index, policy)`
+    /// - `nonce`: The nonce for this authorization
+    /// - `index`: Where to insert it, at most the number of policies held
+    /// - `policy`: Serialized `Policy`
+    /// - `allow_long_lock`: Accept a time lock more than `MAX_FUTURE_SECONDS`
+    ///   away; the signature is then over `POLICY_ADD_LONG_LOCK_ACTION`
+    pub fn add_policy(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        index: u8,
+        policy: Vec<u8>,
+        allow_long_lock: bool,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        change_policies(ctx, &webauthn_sig, |account, webauthn_signature| {
+            policy_list::add_policy(account, webauthn_signature, nonce, index, policy, now, allow_long_lock)
+        })
+    }
+
+    /// Removes the policy at `index` from the account's policy list
+    ///
+    /// Takes the same accounts as `add_policy`. The signature is over the
+    /// `POLICY_REMOVE_ACTION` for `policy_change_payload(index, &[])`.
+    pub fn remove_policy(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        index: u8,
+    ) -> Result<()> {
+        change_policies(ctx, &webauthn_sig, |account, webauthn_signature| {
+            policy_list::remove_policy(account, webauthn_signature, nonce, index)
+        })
+    }
+
+    /// Replaces the policy at `index`, keeping its place in the order
+    ///
+    /// Takes the same accounts and arguments as `add_policy`. The signature
+    /// is over the `POLICY_REPLACE_ACTION` (or `POLICY_REPLACE_LONG_LOCK_ACTION`)
+    /// for `policy_change_payload(index, policy)`.
+    pub fn replace_policy(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        index: u8,
+        policy: Vec<u8>,
+        allow_long_lock: bool,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        change_policies(ctx, &webauthn_sig, |account, webauthn_signature| {
+            policy_list::replace_policy(account, webauthn_signature, nonce, index, policy, now, allow_long_lock)
+        })
+    }
+
+    /// Attests the account's current policy for a third party
+    ///
+    /// Writes a `PolicyAttestation` (the account's `policy_hash`, now, and
+    /// the expiry) into a new PDA, which anyone can read and check with
+    /// `check_attestation`. It grants nothing, and stops holding once the
+    /// account's policies change or it expires. Uses up `nonce`, which also
+    /// keys the PDA.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `attestation`: The attestation PDA to create
+    ///   (seeds: `[b"policy_attestation", attesta_account, nonce (LE)]`)
+    /// - `owner`: The account owner (signer, pays rent)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over `POLICY_ATTEST_ACTION`
+    ///   for `attestation_payload(policy_hash, expires_at)`
+    /// - `nonce`: The nonce for this authorization
+    /// - `expires_at`: When the attestation lapses (Unix timestamp), at most
+    ///   `MAX_ATTESTATION_LIFETIME` from now
+    pub fn attest_policy(
+        ctx: Context<AttestPolicy>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        expires_at: i64,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        let attesta_address = ctx.accounts.attesta_account.key();
+        let attested = attestation::attest_policy(
+            &mut account,
+            &attesta_address,
+            webauthn_signature,
+            nonce,
+            expires_at,
+            Clock::get()?.unix_timestamp,
+        )
+        .map_err(|e| {
+            msg!("{}", e);
+            rejected(attestation_error(e))
+        })?;
+
+        let record = &mut ctx.accounts.attestation;
+        record.attesta_account = attesta_address;
+        record.attestation = attested.to_bytes().map_err(|_| AttestaError::SerializationFailed)?;
+        record.bump = ctx.bumps.attestation;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        emit!(PolicyAttested {
+            attesta_account: attesta_address,
+            attestation: ctx.accounts.attestation.key(),
+            policy_hash: attested.policy_hash,
+            expires_at,
+        });
+        log_event(codes::POLICY_ATTESTED, &[("nonce", &nonce), ("expires_at", &expires_at)]);
+        Ok(())
+    }
+
+    /// Hands an inactive account to its beneficiary
+    ///
+    /// Permissionless: succeeds only once the inactivity and grace periods
+    /// have both passed since the last execution or heartbeat.
+    pub fn claim_inheritance(ctx: Context<Recover>) -> Result<()> {
+        feature_gated!("feature-inheritance", (ctx), {
+            let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+                .map_err(|_| AttestaError::InvalidAccountData)?;
+
+            let now = Clock::get()?.unix_timestamp;
+            inheritance::claim_inheritance(&mut account, now).map_err(inheritance_error)?;
+
+            let Recover { attesta_account, payer, system_program } = ctx.accounts;
+            save_account_resized(attesta_account, &account, payer, system_program)?;
+
+            emit!(InheritanceClaimed { attesta_account: attesta_account.key(), timestamp: now });
+            log_event(codes::INHERITANCE_CLAIMED, &[("account", &attesta_account.key())]);
+            Ok(())
+        })
+    }
+
+    /// Reports this program's version as return data (a Borsh `ProgramVersion`)
+    ///
+    /// Takes no accounts, so it can be simulated without paying anything.
+    /// `ProgramVersion::hash` of the result is what accounts pin.
+    pub fn get_program_version(_ctx: Context<GetProgramVersion>) -> Result<()> {
+        set_return_data(&program_version().to_return_data());
+        Ok(())
+    }
+
+    /// Reports the optional instruction groups this build has, as return
+    /// data (a Borsh `ProgramFeatures`)
+    ///
+    /// Takes no accounts, like `get_program_version`. Instructions of a
+    /// group that isn't listed fail with `FeatureNotEnabled`.
+    pub fn get_program_features(_ctx: Context<GetProgramFeatures>) -> Result<()> {
+        set_return_data(&program_features().to_return_data());
+        Ok(())
+    }
+
+    /// Reports this build's version, features and limits as return data (a
+    /// Borsh `ProtocolConstants`)
+    ///
+    /// Takes no accounts, like `get_program_version`. The limits are the
+    /// ones the program enforces, so clients can check against them rather
+    /// than against the constants they were compiled with.
+    pub fn get_protocol_constants(_ctx: Context<GetProtocolConstants>) -> Result<()> {
+        let constants = ProtocolConstants::new(program_version(), &built_features());
+        set_return_data(&constants.to_return_data());
+        Ok(())
+    }
+
+    /// Accepts the deployed program version on an account that pins one
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for any extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over the `UPGRADE_ACKNOWLEDGE_ACTION`
+    ///   for the hash of the version `get_program_version` reports
+    /// - `nonce`: The nonce for this authorization
+    pub fn acknowledge_upgrade(ctx: Context<ManagePasskeys>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        upgrade::acknowledge_upgrade(&mut account, webauthn_signature, nonce, program_version().hash())
+            .map_err(upgrade_error)?;
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(codes::UPGRADE_ACKNOWLEDGED, &[("account", &attesta_account.key()), ("version", &VERSION)]);
+        Ok(())
+    }
+
+    /// Sets which signers may authorize the account's transactions
+    ///
+    /// With the owner allowed, `execute_as_owner` runs transactions the
+    /// owner's wallet signed; with passkeys disallowed, `execute` refuses
+    /// them. `lock` keeps an account `PasskeyOnly` for good: after that no
+    /// passkey can let the owner's wallet back in, and only finalizing a
+    /// recovery lifts the lock.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for any extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from the primary passkey
+    ///   over `AUTH_MODE_ACTION` for `auth_mode_payload(mode, lock)`
+    /// - `nonce`: The nonce for this authorization
+    /// - `mode`: The `AuthMode`, as its byte
+    /// - `lock`: Lock the account to `PasskeyOnly` (only with that mode)
+    pub fn set_auth_mode(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        mode: u8,
+        lock: bool,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+
+        let mode = AuthMode::from_u8(mode).ok_or(AttestaError::InvalidAuthMode)?;
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        auth_mode::set_auth_mode(&mut account, webauthn_signature, nonce, mode, lock)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(auth_mode_error(e))
+            })?;
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(
+            codes::AUTH_MODE_SET,
+            &[("account", &attesta_account.key()), ("mode", &format_args!("{:?}", mode)), ("locked", &lock)],
+        );
+        Ok(())
+    }
+
+    /// Lists a key allowed to submit `execute` for the account
+    ///
+    /// Once an account lists any executor, `execute` needs its `authority`
+    /// to be one of them, and to sign. Accounts list at most
+    /// `MAX_AUTHORIZED_EXECUTORS`.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for the extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from the primary passkey
+    ///   over `EXECUTOR_ADD_ACTION` for the executor's address
+    /// - `nonce`: The nonce for this authorization
+    /// - `executor`: The relayer or wallet to allow
+    pub fn add_executor(ctx: Context<ManagePasskeys>, webauthn_sig: Vec<u8>, nonce: u64, executor: Pubkey) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        executors::add_executor(&mut account, webauthn_signature, nonce, &executor)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(executor_error(e))
+            })?;
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(
+            codes::EXECUTOR_ADDED,
+            &[("account", &attesta_account.key()), ("executor", &executor), ("count", &account.settings.authorized_executors.len())],
+        );
+        Ok(())
+    }
+
+    /// Takes a key off the account's executor list
+    ///
+    /// Removing the last executor lets anyone submit `execute` again.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from the primary passkey
+    ///   over `EXECUTOR_REMOVE_ACTION` for the executor's address
+    /// - `nonce`: The nonce for this authorization
+    /// - `executor`: The listed executor to remove
+    pub fn remove_executor(ctx: Context<ManagePasskeys>, webauthn_sig: Vec<u8>, nonce: u64, executor: Pubkey) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        executors::remove_executor(&mut account, webauthn_signature, nonce, &executor)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(executor_error(e))
+            })?;
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(
+            codes::EXECUTOR_REMOVED,
+            &[("account", &attesta_account.key()), ("executor", &executor), ("count", &account.settings.authorized_executors.len())],
+        );
+        Ok(())
+    }
+
+    /// Sets the limits of the account's emergency override
+    ///
+    /// The override lets the primary passkey and one other run a transaction
+    /// past the account's time locks (see `execute_emergency`). A
+    /// `max_lamports` of 0, with a `period_seconds` of 0, turns it off.
+    /// Sub-accounts can't have one.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `owner`: The account owner (signer, pays for the extra space)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature from the primary passkey
+    ///   over `EMERGENCY_CONFIGURE_ACTION` for `EmergencyOverride::config_bytes`
+    /// - `nonce`: The nonce for this authorization
+    /// - `max_lamports`: Most one override may move (the raw amount, for a token transfer)
+    /// - `period_seconds`: Seconds after one override before the next may run
+    pub fn configure_emergency_override(
+        ctx: Context<ManagePasskeys>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        max_lamports: u64,
+        period_seconds: u32,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+        check_no_recovery_pending(&account)?;
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        emergency::configure_emergency_override(&mut account, webauthn_signature, nonce, max_lamports, period_seconds)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(emergency_error(e))
+            })?;
+
+        let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+        save_account_resized(attesta_account, &account, owner, system_program)?;
+
+        log_event(
+            codes::EMERGENCY_OVERRIDE_SET,
+            &[("account", &attesta_account.key()), ("max", &max_lamports), ("period", &period_seconds)],
+        );
+        Ok(())
+    }
+
+    /// Runs one transaction past the account's time locks, signed by two passkeys
+    ///
+    /// The primary passkey and another enabled passkey both sign
+    /// `emergency_message_hash(transaction_data)` at `nonce`; both
+    /// signatures are verified here. Every policy other than a time lock
+    /// still applies, destination allowlists included. Fails while the
+    /// account is locked out, above the configured cap, or before the
+    /// period since the last override has passed. Always emits
+    /// `EmergencyOverrideUsed`.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The account the transaction runs from (mut)
+    /// - `authority`: As for `execute`
+    /// - Remaining accounts: for a token transfer, as for `execute`
+    ///
+    /// # Arguments
+    /// - `admin_sig`: Serialized WebAuthnSignature from the primary passkey
+    /// - `cosigner_sig`: Serialized WebAuthnSignature from another enabled passkey
+    /// - `nonce`: The nonce both signed
+    /// - `transaction_data`: The transaction data to execute
+    ///
+    /// # Return data
+    /// An `ExecuteOutcome`, as for `execute`.
+    pub fn execute_emergency<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteEmergency<'info>>,
+        admin_sig: Vec<u8>,
+        cosigner_sig: Vec<u8>,
+        nonce: u64,
+        transaction_data: Vec<u8>,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        upgrade::check_program_version(&account, &program_version().hash())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(upgrade_error(e))
+            })?;
+
+        executors::check_executor(&account, ctx.accounts.authority.key, ctx.accounts.authority.is_signer)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(executor_error(e))
+            })?;
+
+        account.settings.check_transaction_data_len(transaction_data.len())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(AttestaError::TransactionTooLarge)
+            })?;
+
+        let admin_signature = WebAuthnSignature::from_bytes(&admin_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        let cosigner_signature = WebAuthnSignature::from_bytes(&cosigner_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+
+        let attesta_key = ctx.accounts.attesta_account.key();
+        let now = Clock::get()?.unix_timestamp;
+        let amount = emergency::execute_emergency_override(
+            &mut account,
+            &attesta_key,
+            &admin_signature,
+            &cosigner_signature,
+            nonce,
+            &transaction_data,
+            now,
+        )
+        .map_err(|e| {
+            msg!("{}", e);
+            rejected(emergency_error(e))
+        })?;
+
+        let capacity = ctx.accounts.attesta_account.to_account_info().data_len();
+        fit_idempotency_records(&mut account, capacity);
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        if let Some(transfer) = TokenTransfer::from_transaction_data(&transaction_data) {
+            let attesta_info = ctx.accounts.attesta_account.to_account_info();
+            transfer_tokens(&attesta_info, &account, &transfer, ctx.remaining_accounts)?;
+        }
+        // Only now: invoking the token program clears any return data
+        let admin_hash = credential_id_hash(&admin_signature.credential_id);
+        let outcome = ExecuteOutcome::new(&PolicyResult::Allowed, account.nonce, amount)
+            .with_credential_id_hash(Some(admin_hash));
+        set_return_data(&outcome.to_return_data());
+
+        let message_hash = emergency_message_hash(&transaction_data);
+        emit!(TransactionExecuted {
+            attesta_account: attesta_key,
+            nonce: account.nonce,
+            message_hash,
+            memo_hash: None,
+            credential_id_hash: Some(admin_hash),
+        });
+        emit!(EmergencyOverrideUsed {
+            attesta_account: attesta_key,
+            nonce: account.nonce,
+            message_hash,
+            amount,
+            cosigner: credential_id_hash(&cosigner_signature.credential_id),
+            available_again_at: account.emergency_override.available_at(),
+        });
+        announce_primary_activity(&account, &attesta_key, &admin_signature.credential_id, nonce, true);
+        log_event(
+            codes::EMERGENCY_OVERRIDE_USED,
+            &[("nonce", &account.nonce), ("amount", &amount), ("until", &account.emergency_override.available_at())],
+        );
+        Ok(())
+    }
+
+    /// Stores a passkey-signed transaction to be executed inside a time window
+    ///
+    /// Uses up `nonce`, which also keys the schedule PDA. The account's
+    /// policy isn't checked now: `execute_scheduled` checks it against the
+    /// account as it is when the transaction runs.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `schedule`: The schedule PDA to create (seeds: `[b"schedule", attesta_account, nonce (LE)]`)
+    /// - `owner`: The account owner (signer, pays rent)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over `SCHEDULE_ACTION` for
+    ///   `schedule_payload(transaction_data, executable_after, executable_before)`
+    /// - `nonce`: The nonce for this authorization
+    /// - `transaction_data`: The transaction to execute, as `execute` takes it
+    /// - `executable_after`: Unix timestamp the window opens at
+    /// - `executable_before`: Unix timestamp the window closes at, if it does
+    pub fn schedule_transaction(
+        ctx: Context<ScheduleTransaction>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        transaction_data: Vec<u8>,
+        executable_after: i64,
+        executable_before: Option<i64>,
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        let scheduled = schedule::schedule_transaction(
+            &mut account,
+            webauthn_signature,
+            nonce,
+            transaction_data,
+            executable_after,
+            executable_before,
+            Clock::get()?.unix_timestamp,
+        )
+        .map_err(|e| {
+            msg!("{}", e);
+            rejected(schedule_error(e))
+        })?;
+
+        let schedule = &mut ctx.accounts.schedule;
+        schedule.attesta_account = ctx.accounts.attesta_account.key();
+        schedule.scheduled = scheduled.to_bytes().map_err(|_| AttestaError::SerializationFailed)?;
+        schedule.bump = ctx.bumps.schedule;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(
+            codes::SCHEDULED,
+            &[
+                ("account", &ctx.accounts.attesta_account.key()),
+                ("nonce", &nonce),
+                ("after", &executable_after),
+                ("before", &executable_before.unwrap_or(i64::MAX)),
+            ],
+        );
+        Ok(())
+    }
+
+    /// Executes a scheduled transaction once its window is open
+    ///
+    /// Anyone may call this. The transaction is checked against the account's
+    /// settings and policies as they are now, then runs like `execute`, and
+    /// the schedule PDA is closed with its rent going to the owner. A schedule
+    /// whose window has closed is closed the same way without executing. One
+    /// the account's policy doesn't allow fails, and stays until its window
+    /// closes or the owner cancels it.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The account the transaction runs from (mut)
+    /// - `schedule`: The schedule PDA (mut, closed)
+    /// - `owner`: The account owner (receives the reclaimed rent)
+    /// - `parent_account`: The parent account, if `attesta_account` is a sub-account
+    /// - `proof_log`: The account's proof log, if it has enabled one
+    /// - Remaining accounts: for a token transfer, as for `execute`
+    pub fn execute_scheduled<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteScheduled<'info>>) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        upgrade::check_program_version(&account, &program_version().hash())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(upgrade_error(e))
+            })?;
+
+        let scheduled = ScheduledTransaction::from_bytes(&ctx.accounts.schedule.scheduled)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        let parent = match account.parent {
+            Some(parent_key) => {
+                let parent_account = ctx.accounts.parent_account.as_ref()
+                    .ok_or(AttestaError::MissingParentAccount)?;
+                require_keys_eq!(parent_account.key(), parent_key, AttestaError::MissingParentAccount);
+                Some(AttestaAccount::from_bytes_strict(&parent_account.data)
+                    .map_err(|_| AttestaError::InvalidAccountData)?)
+            }
+            None => None,
+        };
+
+        let attesta_key = ctx.accounts.attesta_account.key();
+        let now = Clock::get()?.unix_timestamp;
+        let result = match schedule::execute_scheduled(&mut account, &attesta_key, parent.as_ref(), &scheduled, now) {
+            Ok(result) => result,
+            Err(ScheduleError::Expired { executable_before }) => {
+                // Succeed so the schedule is closed and its rent returned
+                log_event(codes::SCHEDULE_EXPIRED, &[("before", &executable_before)]);
+                return Ok(());
+            }
+            Err(e) => {
+                msg!("{}", e);
+                return Err(rejected(schedule_error(e)).into());
+            }
+        };
+        if result != PolicyResult::Allowed {
+            log_not_allowed(&result);
+            return Err(denied_error(&result).into());
+        }
+
+        if account.proof_log_enabled {
+            let proof_log = ctx.accounts.proof_log.as_mut()
+                .ok_or(AttestaError::MissingProofLog)?;
+            require_keys_eq!(proof_log.attesta_account, attesta_key, AttestaError::MissingProofLog);
+            let payload = schedule_payload(&scheduled.transaction_data, scheduled.executable_after, scheduled.executable_before);
+            proof_log.append(ProofLogEntry {
+                nonce: scheduled.nonce,
+                message_hash: smart_account::action_message_hash(SCHEDULE_ACTION, &payload),
+                credential_id_hash: credential_id_hash(&scheduled.credential_id),
+                timestamp: now,
+            })?;
+        }
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        let transfer = TokenTransfer::from_transaction_data(&scheduled.transaction_data);
+        let amount_charged = transfer.as_ref().map_or(0, |transfer| transfer.amount);
+        if let Some(transfer) = transfer {
+            let attesta_info = ctx.accounts.attesta_account.to_account_info();
+            transfer_tokens(&attesta_info, &account, &transfer, ctx.remaining_accounts)?;
+        }
+        set_return_data(&ExecuteOutcome::new(&result, account.nonce, amount_charged).to_return_data());
+
+        log_event(codes::EXECUTED, &[("nonce", &scheduled.nonce), ("amount", &amount_charged), ("signer", &"schedule")]);
+        Ok(())
+    }
+
+    /// Cancels a scheduled transaction and returns the schedule's rent to the owner
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `schedule`: The schedule PDA to close (mut)
+    /// - `owner`: The account owner (receives the reclaimed rent)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over `SCHEDULE_CANCEL_ACTION`
+    ///   for the schedule PDA's address
+    /// - `nonce`: The nonce for this authorization
+    pub fn cancel_scheduled(ctx: Context<CancelScheduled>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        // Rent always goes back to the owner, never to whoever submits the instruction
+        require!(
+            account.owner == *ctx.accounts.owner.key,
+            AttestaError::Unauthorized
+        );
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        schedule::cancel_scheduled(&mut account, webauthn_signature, nonce, &ctx.accounts.schedule.key())
+            .map_err(schedule_error)?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        log_event(codes::SCHEDULE_CANCELLED, &[("account", &ctx.accounts.attesta_account.key())]);
+        Ok(())
+    }
+
+    /// Adds a passkey's approval to a proposed transaction
+    ///
+    /// Any of the account's passkeys can approve, once each, until the
+    /// proposal has its approvals or `PROPOSAL_LIFETIME` has passed. Anyone
+    /// can submit the approval: the passkey's signature is what counts. A
+    /// passkey that already approved succeeds again without changing
+    /// anything, nonce included. Once the account's policy changes, the
+    /// proposal takes no more approvals.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `proposal`: The proposal PDA (mut)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over `PROPOSAL_APPROVE_ACTION`
+    ///   for `approve_proposal_payload(proposal, message_hash)`
+    /// - `nonce`: The nonce for this authorization
+    pub fn approve_proposal(ctx: Context<ApproveProposal>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        let mut pending = PendingTransaction::from_bytes(&ctx.accounts.proposal.pending)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        let proposal_key = ctx.accounts.proposal.key();
+        let approval =
+            proposal::approve_proposal(&mut account, &mut pending, &proposal_key, webauthn_signature, nonce, Clock::get()?.unix_timestamp)
+                .map_err(|e| {
+                    msg!("{}", e);
+                    rejected(proposal_error(e))
+                })?;
+        if approval == Approval::AlreadyCounted {
+            msg!("The passkey already approved this proposal");
+            return Ok(());
+        }
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+        ctx.accounts.proposal.pending = pending.to_bytes().map_err(|_| AttestaError::SerializationFailed)?;
+
+        log_event(
+            codes::PROPOSAL_APPROVED,
+            &[
+                ("account", &ctx.accounts.attesta_account.key()),
+                ("approvals", &pending.approvals.len()),
+                ("required", &pending.required_approvals),
+            ],
+        );
+        Ok(())
+    }
+
+    /// Withdraws a proposed transaction before it has its approvals
+    ///
+    /// The passkey that proposed it, or the primary passkey, signs the
+    /// cancellation. The proposal PDA is closed and its rent goes back to
+    /// whoever paid for it. A proposal that already has enough approvals
+    /// can't be cancelled: execution takes priority. Approvals sent in the
+    /// meantime fail, as the proposal no longer exists.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `proposal`: The proposal PDA to close (mut)
+    /// - `rent_payer`: Whoever paid for the proposal (receives the reclaimed rent)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over `PROPOSAL_CANCEL_ACTION`
+    ///   for `cancel_proposal_payload(proposal, reason)`
+    /// - `nonce`: The nonce for this authorization
+    /// - `reason`: A `CancelReason` code, reported in `ProposalCancelled`
+    pub fn cancel_proposal(ctx: Context<CancelProposal>, webauthn_sig: Vec<u8>, nonce: u64, reason: u8) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        let pending = PendingTransaction::from_bytes(&ctx.accounts.proposal.pending)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        let reason = CancelReason::from_u8(reason).map_err(proposal_error)?;
+
+        // Rent goes back to whoever paid for the proposal, never to the submitter
+        require!(
+            pending.rent_payer == *ctx.accounts.rent_payer.key,
+            AttestaError::Unauthorized
+        );
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        proposal::cancel_proposal(&mut account, &pending, &ctx.accounts.proposal.key(), webauthn_signature, nonce, reason)
+            .map_err(proposal_error)?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        emit!(ProposalCancelled {
+            attesta_account: ctx.accounts.attesta_account.key(),
+            message_hash: pending.message_hash,
+            reason: reason as u8,
+        });
+        log_event(codes::PROPOSAL_CANCELLED, &[("account", &ctx.accounts.attesta_account.key()), ("reason", &(reason as u8))]);
+        Ok(())
+    }
+
+    /// Executes a transfer a vault held back, once its delay has passed
+    ///
+    /// Takes the proof `execute` announced it with, submitted again. The
+    /// transfer is checked against the account's settings and policies as
+    /// they are now, then runs like `execute`, and the announcement PDA is
+    /// closed with its rent going back to whoever paid for it. An
+    /// announcement left past `ANNOUNCEMENT_GRACE_PERIOD` is closed the same
+    /// way without executing. Executing doesn't move the account's nonce.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The account the transfer runs from (mut)
+    /// - `announcement`: The announcement PDA (mut, closed)
+    /// - `rent_payer`: Whoever paid for the announcement (receives the reclaimed rent)
+    /// - `authority`: As for `execute`, when the account lists executors
+    /// - `parent_account`: The parent account, if `attesta_account` is a sub-account
+    /// - `proof_log`: The account's proof log, if it has enabled one
+    /// - Remaining accounts: for a token transfer, as for `execute`
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`, `nonce`, `message_hash`: The announcing proof, as `execute` took it
+    ///
+    /// # Return data
+    /// An `ExecuteOutcome`, as for `execute`.
+    pub fn execute_announced<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteAnnounced<'info>>,
+        webauthn_sig: Vec<u8>,
+        nonce: u64,
+        message_hash: [u8; 32],
+    ) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        let announcement = Announcement::from_bytes(&ctx.accounts.announcement.announcement)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        // Rent goes back to whoever paid for the announcement, never to the submitter
+        require!(
+            announcement.rent_payer == *ctx.accounts.rent_payer.key,
+            AttestaError::Unauthorized
+        );
+
+        upgrade::check_program_version(&account, &program_version().hash())
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(upgrade_error(e))
+            })?;
+
+        executors::check_executor(&account, ctx.accounts.authority.key, ctx.accounts.authority.is_signer)
+            .map_err(|e| {
+                msg!("{}", e);
+                rejected(executor_error(e))
+            })?;
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        let proof = AuthorizationProof::new(webauthn_signature, nonce, message_hash);
+
+        let parent = match account.parent {
+            Some(parent_key) => {
+                let parent_account = ctx.accounts.parent_account.as_ref()
+                    .ok_or(AttestaError::MissingParentAccount)?;
+                require_keys_eq!(parent_account.key(), parent_key, AttestaError::MissingParentAccount);
+                Some(AttestaAccount::from_bytes_strict(&parent_account.data)
+                    .map_err(|_| AttestaError::InvalidAccountData)?)
+            }
+            None => None,
+        };
+
+        let attesta_key = ctx.accounts.attesta_account.key();
+        let now = Clock::get()?.unix_timestamp;
+        let result = match vault::execute_announced(&mut account, &attesta_key, parent.as_ref(), &announcement, &proof, now) {
+            Ok(result) => result,
+            Err(VaultError::Expired { expires_at }) => {
+                // Succeed so the announcement is closed and its rent returned
+                log_event(codes::ANNOUNCEMENT_EXPIRED, &[("expired", &expires_at)]);
+                return Ok(());
+            }
+            Err(e) => {
+                msg!("{}", e);
+                return Err(rejected(vault_error(e)).into());
+            }
+        };
+        if result != PolicyResult::Allowed {
+            log_not_allowed(&result);
+            return Err(denied_error(&result).into());
+        }
+
+        if account.proof_log_enabled {
+            let proof_log = ctx.accounts.proof_log.as_mut()
+                .ok_or(AttestaError::MissingProofLog)?;
+            require_keys_eq!(proof_log.attesta_account, attesta_key, AttestaError::MissingProofLog);
+            proof_log.append(ProofLogEntry::new(&proof, now))?;
+        }
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        let transfer = TokenTransfer::from_transaction_data(&announcement.transaction_data);
+        let amount_charged = transfer.as_ref().map_or(0, |transfer| transfer.amount);
+        if let Some(transfer) = transfer {
+            let attesta_info = ctx.accounts.attesta_account.to_account_info();
+            transfer_tokens(&attesta_info, &account, &transfer, ctx.remaining_accounts)?;
+        }
+        set_return_data(&ExecuteOutcome::new(&result, account.nonce, amount_charged).to_return_data());
+
+        log_event(
+            codes::EXECUTED,
+            &[("nonce", &announcement.nonce), ("amount", &amount_charged), ("signer", &"announcement")],
+        );
+        Ok(())
+    }
+
+    /// Stops an announced transfer from running
+    ///
+    /// Any enabled passkey on the account can veto, not only the one that
+    /// announced it. The announcement PDA is closed and its rent goes back
+    /// to whoever paid for it.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The user's Attesta account (mut, nonce is consumed)
+    /// - `announcement`: The announcement PDA to close (mut)
+    /// - `rent_payer`: Whoever paid for the announcement (receives the reclaimed rent)
+    ///
+    /// # Arguments
+    /// - `webauthn_sig`: Serialized WebAuthnSignature over `ANNOUNCEMENT_VETO_ACTION`
+    ///   for the announcement PDA's address
+    /// - `nonce`: The nonce for this authorization
+    pub fn veto_announcement(ctx: Context<VetoAnnouncement>, webauthn_sig: Vec<u8>, nonce: u64) -> Result<()> {
+        let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        let announcement = Announcement::from_bytes(&ctx.accounts.announcement.announcement)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        require!(
+            announcement.rent_payer == *ctx.accounts.rent_payer.key,
+            AttestaError::Unauthorized
+        );
+
+        let webauthn_signature = WebAuthnSignature::from_bytes(&webauthn_sig)
+            .map_err(|_| AttestaError::InvalidSignature)?;
+        let vetoed_by = credential_id_hash(&webauthn_signature.credential_id);
+        vault::veto_announcement(&mut account, webauthn_signature, nonce, &ctx.accounts.announcement.key())
+            .map_err(vault_error)?;
+
+        save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+        emit!(AnnouncementVetoed {
+            attesta_account: ctx.accounts.attesta_account.key(),
+            announcement: ctx.accounts.announcement.key(),
+            vetoed_by,
+        });
+        log_event(codes::ANNOUNCEMENT_VETOED, &[("nonce", &announcement.nonce)]);
+        Ok(())
+    }
+
+    /// Pays out a claim ticket to the destination its holder chose
+    ///
+    /// The ticket is a passkey's signature over an amount, an expiry and a
+    /// claim key (see `smart_account::claim`). The claim key must sign this
+    /// transaction, which is what ties the ticket to `destination`. The
+    /// amount is checked against the account's settings and policies like
+    /// any transfer, and must leave the account rent-exempt. Claiming uses
+    /// up the ticket's nonce, so a ticket pays out once.
+    ///
+    /// # Accounts
+    /// - `attesta_account`: The account paying out (mut, nonce is consumed)
+    /// - `claim_key`: The ticket's claim key (signer)
+    /// - `destination`: Where the lamports go (mut)
+    ///
+    /// # Arguments
+    /// - `ticket`: A serialized `ClaimTicket`
+    pub fn claim(ctx: Context<Claim>, ticket: Vec<u8>) -> Result<()> {
+        feature_gated!("feature-claims", (ctx, ticket), {
+            let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+                .map_err(|_| AttestaError::InvalidAccountData)?;
+            let ticket = ClaimTicket::from_bytes(&ticket).map_err(claim_error)?;
+
+            require_keys_eq!(ticket.claim_key, ctx.accounts.claim_key.key(), AttestaError::Unauthorized);
+
+            let attesta_key = ctx.accounts.attesta_account.key();
+            let destination_key = ctx.accounts.destination.key();
+            let now = Clock::get()?.unix_timestamp;
+            let result = claim::redeem_claim(&mut account, &attesta_key, &destination_key, &ticket, now)
+                .map_err(|e| {
+                    msg!("{}", e);
+                    rejected(claim_error(e))
+                })?;
+            if result != PolicyResult::Allowed {
+                log_not_allowed(&result);
+                return Err(denied_error(&result).into());
+            }
+
+            let attesta_info = ctx.accounts.attesta_account.to_account_info();
+            let rent_exempt = Rent::get()?.minimum_balance(attesta_info.data_len());
+            require!(
+                attesta_info.lamports().saturating_sub(rent_exempt) >= ticket.amount,
+                AttestaError::InsufficientFunds
+            );
+
+            save_account(&mut ctx.accounts.attesta_account, &account)?;
+
+            // The account is this program's, so its lamports move without a CPI
+            **attesta_info.try_borrow_mut_lamports()? -= ticket.amount;
+            **ctx.accounts.destination.try_borrow_mut_lamports()? += ticket.amount;
+
+            emit!(TicketClaimed {
+                attesta_account: attesta_key,
+                destination: destination_key,
+                amount: ticket.amount,
+                nonce: ticket.nonce,
+            });
+            log_event(codes::CLAIM_PAID, &[("nonce", &ticket.nonce), ("amount", &ticket.amount), ("to", &destination_key)]);
+            Ok(())
+        })
+    }
+
+    /// Creates a pool that pays the rent of new accounts
+    ///
+    /// Anyone can fund the pool by transferring lamports to it; only
+    /// `authority` can withdraw them (`withdraw_pool`). The limits can't be
+    /// changed later - create another pool instead.
+    ///
+    /// # Accounts
+    /// - `sponsor_pool`: The pool to create (PDA: `[b"sponsor_pool", authority]`)
+    /// - `authority`: Who controls the pool (signer, pays the pool's own rent)
+    /// - `system_program`: The Solana system program
+    ///
+    /// # Arguments
+    /// - `max_accounts_per_day`: Accounts the pool sponsors per UTC day at most
+    /// - `max_lamports_per_account`: Rent the pool pays for one account at most
+    pub fn create_sponsor_pool(
+        ctx: Context<CreateSponsorPool>,
+        max_accounts_per_day: u32,
+        max_lamports_per_account: u64,
+    ) -> Result<()> {
+        feature_gated!("feature-sponsorship", (ctx, max_accounts_per_day, max_lamports_per_account), {
+            let pool = &mut ctx.accounts.sponsor_pool;
+            pool.authority = ctx.accounts.authority.key();
+            pool.pool = SponsorPool::new(max_accounts_per_day, max_lamports_per_account)
+                .to_bytes()
+                .map_err(|_| AttestaError::SerializationFailed)?;
+            pool.bump = ctx.bumps.sponsor_pool;
+
+            log_event(codes::SPONSOR_POOL_CREATED, &[("authority", ctx.accounts.authority.key)]);
+            Ok(())
+        })
+    }
+
+    /// Initializes an Attesta account with its rent paid by a sponsor pool
+    ///
+    /// Takes the same arguments and checks as `initialize`. The owner signs
+    /// but needn't hold any SOL: the pool pays the account's rent, and
+    /// whoever pays the transaction fee (a relayer) doesn't need to be the
+    /// owner. The account is the same as one `initialize` creates.
+    ///
+    /// Fails with `SponsorRentTooHigh` over the pool's per-account cap,
+    /// `SponsorDailyLimitReached` once the pool has sponsored its accounts
+    /// for the day, and `SponsorPoolExhausted` if paying would leave the pool
+    /// below its own rent-exempt minimum. Emits `AccountSponsored`.
+    ///
+    /// # Accounts
+    /// - `sponsor_pool`: The pool paying the rent (mut)
+    /// - `attesta_account`: The account to initialize (PDA: `[b"attesta", owner]`, mut)
+    /// - `owner`: The user who owns the new account (signer)
+    /// - `system_program`: The Solana system program
+    #[allow(clippy::too_many_arguments)]
+    pub fn sponsored_initialize(
+        ctx: Context<SponsoredInitialize>,
+        passkey_public_key: [u8; 64],
+        credential_id: Vec<u8>,
+        policy: Vec<u8>,
+        privacy_mode: bool,
+        registration_sig: Vec<u8>,
+        aaguid_allowlist: Vec<[u8; 16]>,
+    ) -> Result<()> {
+        feature_gated!("feature-sponsorship", (ctx, passkey_public_key, credential_id, policy, privacy_mode, registration_sig, aaguid_allowlist), {
+            let attesta_info = ctx.accounts.attesta_account.to_account_info();
+            require_keys_eq!(*attesta_info.owner, System::id(), AttestaError::AccountAlreadyInitialized);
+
+            let account = new_account(
+                ctx.accounts.owner.key,
+                attesta_info.key,
+                passkey_public_key,
+                credential_id,
+                policy,
+                privacy_mode,
+                &registration_sig,
+                aaguid_allowlist,
+            )?;
+
+            // Dust someone sent to the address beforehand counts toward the rent
+            let rent = Rent::get()?;
+            let lamports = rent.minimum_balance(ATTESTA_ACCOUNT_SPACE).saturating_sub(attesta_info.lamports());
+            let pool_info = ctx.accounts.sponsor_pool.to_account_info();
+            let spare = pool_info.lamports().saturating_sub(rent.minimum_balance(pool_info.data_len()));
+            let mut pool = SponsorPool::from_bytes(&ctx.accounts.sponsor_pool.pool)
+                .map_err(sponsorship_error)?;
+            pool.sponsor(lamports, spare, Clock::get()?.unix_timestamp).map_err(|e| {
+                msg!("{}", e);
+                rejected(sponsorship_error(e))
+            })?;
+            ctx.accounts.sponsor_pool.pool = pool.to_bytes().map_err(|_| AttestaError::SerializationFailed)?;
+
+            // The pool is this program's, so its lamports move without a CPI
+            **pool_info.try_borrow_mut_lamports()? -= lamports;
+            **attesta_info.try_borrow_mut_lamports()? += lamports;
+            let owner_key = ctx.accounts.owner.key();
+            let seeds: &[&[u8]] = &[b"attesta", owner_key.as_ref(), &[ctx.bumps.attesta_account]];
+            create_attesta_account(&attesta_info, ctx.accounts.system_program.to_account_info(), seeds, &account)?;
+
+            emit!(AccountSponsored {
+                sponsor_pool: pool_info.key(),
+                attesta_account: attesta_info.key(),
+                owner: owner_key,
+                lamports,
+            });
+            log_event(codes::ACCOUNT_INITIALIZED, &[("owner", &owner_key), ("pool", &pool_info.key())]);
+            Ok(())
+        })
+    }
+
+    /// Takes lamports out of a sponsor pool
+    ///
+    /// The pool keeps its rent-exempt minimum; asking for more fails with
+    /// `InsufficientFunds`.
+    ///
+    /// # Accounts
+    /// - `sponsor_pool`: The pool (mut)
+    /// - `authority`: The pool's authority (signer, receives the lamports)
+    ///
+    /// # Arguments
+    /// - `amount`: Lamports to withdraw
+    pub fn withdraw_pool(ctx: Context<WithdrawPool>, amount: u64) -> Result<()> {
+        feature_gated!("feature-sponsorship", (ctx, amount), {
+            let pool_info = ctx.accounts.sponsor_pool.to_account_info();
+            let spare = pool_info.lamports().saturating_sub(Rent::get()?.minimum_balance(pool_info.data_len()));
+            require!(amount <= spare, AttestaError::InsufficientFunds);
+
+            **pool_info.try_borrow_mut_lamports()? -= amount;
+            **ctx.accounts.authority.try_borrow_mut_lamports()? += amount;
+
+            log_event(codes::SPONSOR_POOL_WITHDRAWN, &[("pool", &pool_info.key()), ("amount", &amount)]);
+            Ok(())
+        })
+    }
+}
+
+/// Allocates and assigns an already funded PDA, then writes `account` to it
+///
+/// For the initialize variants that don't use Anchor's `init`: `seeds` are
+/// the PDA's, bump included.
+fn create_attesta_account<'info>(
+    attesta_info: &AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    seeds: &[&[u8]],
+    account: &AttestaAccount,
+) -> Result<()> {
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate { account_to_allocate: attesta_info.clone() },
+            &[seeds],
+        ),
+        ATTESTA_ACCOUNT_SPACE as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program,
+            anchor_lang::system_program::Assign { account_to_assign: attesta_info.clone() },
+            &[seeds],
+        ),
+        &crate::ID,
+    )?;
+
+    let wrapper = AttestaAccountData {
+        data: account.to_bytes().map_err(|_| AttestaError::SerializationFailed)?,
+    };
+    wrapper.try_serialize(&mut &mut attesta_info.try_borrow_mut_data()?[..])?;
+    Ok(())
+}
+
+/// Builds a new account for `initialize` and its variants
+///
+/// Checks the passkey's registration signature against the AAGUID allowlist
+/// the account starts with.
+#[allow(clippy::too_many_arguments)]
+fn new_account(
+    owner: &Pubkey,
+    account_address: &Pubkey,
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Vec<u8>,
+    privacy_mode: bool,
+    registration_sig: &[u8],
+    aaguid_allowlist: Vec<[u8; AAGUID_LEN]>,
+) -> Result<AttestaAccount> {
+    require!(aaguid_allowlist.len() <= MAX_AAGUID_ALLOWLIST_LEN, AttestaError::AllowlistTooLong);
+    let settings = AccountSettings { aaguid_allowlist, ..AccountSettings::default() };
+    let aaguid = enrolled_aaguid(
+        owner,
+        account_address,
+        &settings,
+        &passkey_public_key,
+        &credential_id,
+        Some(registration_sig),
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut account = AttestaAccount::new(*owner, passkey_public_key, credential_id, Vec::new(), now);
+    if !policy.is_empty() {
+        let policy = Policy::from_bytes(&policy).map_err(|_| AttestaError::InvalidPolicy)?;
+        policy.validate_timestamps(now, false).map_err(time_error)?;
+        account = account.with_policy(policy).map_err(account_policy_error)?;
+    }
+    account.settings = settings;
+    account.passkey_aaguid = aaguid;
+
+    if privacy_mode {
+        account.enable_privacy_mode()
+            .map_err(|_| AttestaError::SerializationFailed)?;
+    }
+    // Instructions load accounts strictly, so don't create one they'd refuse
+    account.validate().map_err(|_| AttestaError::InvalidAccountData)?;
+    check_destination_room(&account, ATTESTA_ACCOUNT_SPACE)?;
+    Ok(account)
+}
+
+/// Runs `social_recovery::initiate_recovery` for either mode and saves the account
+fn start_recovery(
+    accounts: &mut Recover,
+    mode: RecoveryMode,
+    webauthn_sig: &[u8],
+    nonce: u64,
+    new_public_key: [u8; P256_PUBKEY_LEN],
+    new_credential_id: &[u8],
+    new_aaguid: Option<[u8; AAGUID_LEN]>,
+) -> Result<RecoveryProgress> {
+    let mut account = AttestaAccount::from_bytes_strict(&accounts.attesta_account.data)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+    let webauthn_signature = WebAuthnSignature::from_bytes(webauthn_sig)
+        .map_err(|_| AttestaError::InvalidSignature)?;
+
+    let progress = social_recovery::initiate_recovery(
+        &mut account,
+        mode,
+        webauthn_signature,
+        nonce,
+        new_public_key,
+        new_credential_id,
+        new_aaguid,
+        Clock::get()?.unix_timestamp,
+    )
+    .map_err(recovery_error)?;
+
+    save_account_resized(&mut accounts.attesta_account, &account, &accounts.payer, &accounts.system_program)?;
+    Ok(progress)
+}
+
+/// Runs `social_recovery::approve_recovery` for either mode and saves the account
+fn add_recovery_approval(
+    accounts: &mut Recover,
+    mode: RecoveryMode,
+    webauthn_sig: &[u8],
+    nonce: u64,
+) -> Result<RecoveryProgress> {
+    let mut account = AttestaAccount::from_bytes_strict(&accounts.attesta_account.data)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+    let webauthn_signature = WebAuthnSignature::from_bytes(webauthn_sig)
+        .map_err(|_| AttestaError::InvalidSignature)?;
+
+    let progress = social_recovery::approve_recovery(
+        &mut account,
+        mode,
+        webauthn_signature,
+        nonce,
+        Clock::get()?.unix_timestamp,
+    )
+    .map_err(recovery_error)?;
+
+    save_account_resized(&mut accounts.attesta_account, &account, &accounts.payer, &accounts.system_program)?;
+    Ok(progress)
+}
+
+/// Reports a drill step, emitting `RecoveryDrillSucceeded` once the threshold is met
+fn finish_drill_step(accounts: &Recover, progress: RecoveryProgress) -> Result<()> {
+    if progress.threshold_met() {
+        emit!(RecoveryDrillSucceeded {
+            attesta_account: accounts.attesta_account.key(),
+            approvals: progress.approvals as u8,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        log_event(codes::RECOVERY_DRILL_PASSED, &[("account", &accounts.attesta_account.key())]);
+    } else {
+        log_event(codes::RECOVERY_APPROVED, &[("approvals", &progress.approvals), ("threshold", &progress.threshold)]);
+    }
+    Ok(())
+}
+
+/// Maps recovery flow errors to program errors
+fn recovery_error(error: RecoveryFlowError) -> AttestaError {
+    match error {
+        RecoveryFlowError::Unauthorized(_) => AttestaError::Unauthorized,
+        RecoveryFlowError::Registry(_) => AttestaError::InvalidAccountData,
+        RecoveryFlowError::NoPasskeyRegistry | RecoveryFlowError::InvalidNewPasskey => AttestaError::InvalidPasskey,
+        RecoveryFlowError::AlreadyPending => AttestaError::RecoveryAlreadyPending,
+        RecoveryFlowError::NoPendingRecovery => AttestaError::NoPendingRecovery,
+        RecoveryFlowError::ThresholdNotMet => AttestaError::RecoveryThresholdNotMet,
+        RecoveryFlowError::DelayNotElapsed { .. } => AttestaError::RecoveryDelayNotElapsed,
+        RecoveryFlowError::AuthenticatorNotAllowed => AttestaError::AuthenticatorNotAllowed,
+    }
+}
+
+/// Loads the account, applies a passkey-authorized change to its policy
+/// list and saves it, growing or shrinking the account to fit
+fn change_policies(
+    ctx: Context<ManagePasskeys>,
+    webauthn_sig: &[u8],
+    change: impl FnOnce(&mut AttestaAccount, WebAuthnSignature) -> std::result::Result<(), PolicyListError>,
+) -> Result<()> {
+    let mut account = AttestaAccount::from_bytes_strict(&ctx.accounts.attesta_account.data)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+
+    require!(
+        account.owner == *ctx.accounts.owner.key,
+        AttestaError::Unauthorized
+    );
+    check_no_recovery_pending(&account)?;
+
+    let webauthn_signature = WebAuthnSignature::from_bytes(webauthn_sig)
+        .map_err(|_| AttestaError::InvalidSignature)?;
+    change(&mut account, webauthn_signature).map_err(policy_list_error)?;
+
+    let ManagePasskeys { attesta_account, owner, system_program } = ctx.accounts;
+    save_account_resized(attesta_account, &account, owner, system_program)?;
+
+    emit!(PolicyUpdated {
+        attesta_account: attesta_account.key(),
+        policy_count: account.policies().len() as u8,
+        policy_hash: account.policy_hash,
+    });
+    log_event(codes::POLICY_UPDATED, &[("account", &attesta_account.key()), ("count", &account.policies().len())]);
+    Ok(())
+}
+
+fn upgrade_error(error: UpgradeError) -> AttestaError {
+    match error {
+        UpgradeError::Unauthorized(_) => AttestaError::Unauthorized,
+        UpgradeError::NotPinned => AttestaError::ProgramVersionNotPinned,
+        UpgradeError::VersionMismatch { .. } => AttestaError::UpgradeNotAcknowledged,
+    }
+}
+
+fn auth_mode_error(error: AuthModeError) -> AttestaError {
+    match error {
+        AuthModeError::Unauthorized(_) | AuthModeError::NotOwner => AttestaError::Unauthorized,
+        AuthModeError::Locked => AttestaError::AuthModeLocked,
+        AuthModeError::CannotLock => AttestaError::InvalidAuthMode,
+        AuthModeError::OwnerNotAllowed => AttestaError::OwnerNotAllowed,
+        AuthModeError::InvalidNonce => AttestaError::ExecutionFailed,
+        AuthModeError::MissingParent => AttestaError::MissingParentAccount,
+    }
+}
+
+fn executor_error(error: ExecutorError) -> AttestaError {
+    match error {
+        ExecutorError::Unauthorized(_) => AttestaError::Unauthorized,
+        ExecutorError::AlreadyListed => AttestaError::ExecutorAlreadyListed,
+        ExecutorError::NotListed => AttestaError::ExecutorNotListed,
+        ExecutorError::TooManyExecutors => AttestaError::TooManyExecutors,
+        ExecutorError::ExecutorNotAllowed => AttestaError::ExecutorNotAllowed,
+    }
+}
+
+fn emergency_error(error: EmergencyError) -> AttestaError {
+    match error {
+        EmergencyError::Unauthorized(_) => AttestaError::Unauthorized,
+        EmergencyError::NotEnabled | EmergencyError::SubAccount => AttestaError::EmergencyOverrideOff,
+        EmergencyError::InvalidPeriod => AttestaError::InvalidEmergencyOverride,
+        EmergencyError::PasskeyNotAllowed => AttestaError::PasskeyNotAllowed,
+        EmergencyError::SecondCredentialRequired => AttestaError::SecondPasskeyRequired,
+        EmergencyError::LockedOut { .. } => AttestaError::AccountLockedOut,
+        EmergencyError::TooSoon { .. } => AttestaError::EmergencyOverrideTooSoon,
+        EmergencyError::OverCap { .. } => AttestaError::EmergencyOverrideOverCap,
+        EmergencyError::Denied(reason) => denied_error(&PolicyResult::Denied(reason)),
+    }
+}
+
+fn policy_list_error(error: PolicyListError) -> AttestaError {
+    match error {
+        PolicyListError::Unauthorized(_) => AttestaError::Unauthorized,
+        PolicyListError::InvalidPolicy => AttestaError::InvalidPolicy,
+        PolicyListError::InvalidTimestamp(error) => time_error(error),
+        PolicyListError::TooManyPolicies => AttestaError::TooManyPolicies,
+        PolicyListError::IndexOutOfRange(_) => AttestaError::PolicyIndexOutOfRange,
+        PolicyListError::TooExpensive { .. } => AttestaError::PolicyTooExpensive,
+    }
+}
+
+fn account_policy_error(error: AccountPolicyError) -> AttestaError {
+    match error {
+        AccountPolicyError::Invalid(_) | AccountPolicyError::Undecodable => AttestaError::InvalidPolicy,
+        AccountPolicyError::TooExpensive { .. } => AttestaError::PolicyTooExpensive,
+        AccountPolicyError::TooLong { .. } => AttestaError::PolicyTooLong,
+    }
+}
+
+fn attestation_error(error: AttestationError) -> AttestaError {
+    match error {
+        AttestationError::Unauthorized(_) => AttestaError::Unauthorized,
+        AttestationError::InvalidExpiry { .. } => AttestaError::InvalidAttestationExpiry,
+        AttestationError::WrongAccount
+        | AttestationError::UnexpectedPolicy
+        | AttestationError::Expired { .. }
+        | AttestationError::PolicyChanged
+        | AttestationError::InvalidData => AttestaError::InvalidAccountData,
+    }
+}
+
+fn time_error(error: TimeError) -> AttestaError {
+    msg!("{}", error);
+    match error {
+        TimeError::Negative(_) => AttestaError::NegativeTimestamp,
+        TimeError::TooFarInFuture { .. } => AttestaError::TimestampTooFarInFuture,
+        TimeError::TooFarInPast { .. } => AttestaError::TimestampTooFarInPast,
+    }
+}
+
+#[cfg(feature = "feature-inheritance")]
+fn inheritance_error(error: InheritanceError) -> AttestaError {
+    match error {
+        InheritanceError::Unauthorized(_) => AttestaError::Unauthorized,
+        InheritanceError::Registry(_) => AttestaError::InvalidAccountData,
+        InheritanceError::InvalidConfig => AttestaError::InvalidInheritanceConfig,
+        InheritanceError::InvalidTimestamp(error) => time_error(error),
+        InheritanceError::NotConfigured => AttestaError::InheritanceNotConfigured,
+        InheritanceError::NotYetClaimable { .. } => AttestaError::InheritanceNotClaimable,
+    }
+}
+
+fn schedule_error(error: ScheduleError) -> AttestaError {
+    match error {
+        ScheduleError::Unauthorized(_) => AttestaError::Unauthorized,
+        ScheduleError::InvalidWindow => AttestaError::InvalidScheduleWindow,
+        ScheduleError::InvalidTimestamp(error) => time_error(error),
+        ScheduleError::InvalidTransaction(_) => AttestaError::TransactionTooLarge,
+        ScheduleError::TooEarly { .. } => AttestaError::ScheduleNotYetExecutable,
+        ScheduleError::Expired { .. } => AttestaError::ScheduleExpired,
+        ScheduleError::SignerRemoved => AttestaError::ScheduleSignerRemoved,
+        ScheduleError::MissingParent => AttestaError::MissingParentAccount,
+        ScheduleError::InvalidData => AttestaError::InvalidAccountData,
+    }
+}
+
+fn vault_error(error: VaultError) -> AttestaError {
+    match error {
+        VaultError::Unauthorized(_) => AttestaError::Unauthorized,
+        VaultError::TooEarly { .. } => AttestaError::AnnouncementNotYetExecutable,
+        VaultError::Expired { .. } => AttestaError::AnnouncementExpired,
+        VaultError::ProofMismatch => AttestaError::AnnouncementProofMismatch,
+        VaultError::SignerRemoved => AttestaError::AnnouncementSignerRemoved,
+        VaultError::MissingParent => AttestaError::MissingParentAccount,
+        VaultError::InvalidData => AttestaError::InvalidAccountData,
+    }
+}
+
+fn proposal_error(error: ProposalError) -> AttestaError {
+    match error {
+        ProposalError::Unauthorized(_) | ProposalError::NotProposer => AttestaError::Unauthorized,
+        ProposalError::ThresholdReached => AttestaError::ProposalApproved,
+        ProposalError::UnknownReason(_) => AttestaError::InvalidCancelReason,
+        ProposalError::InvalidData => AttestaError::InvalidAccountData,
+        ProposalError::Expired(_) => AttestaError::ProposalExpired,
+        ProposalError::PolicyChanged => AttestaError::ProposalPolicyChanged,
+    }
+}
+
+#[cfg(feature = "feature-claims")]
+fn claim_error(error: ClaimError) -> AttestaError {
+    match error {
+        ClaimError::Unauthorized(_) | ClaimError::WrongAccount => AttestaError::Unauthorized,
+        ClaimError::Expired { .. } => AttestaError::ClaimTicketExpired,
+        ClaimError::SubAccount => AttestaError::ClaimFromSubAccount,
+        ClaimError::PasskeysDisabled => AttestaError::PasskeyNotAllowed,
+        ClaimError::InvalidTicket => AttestaError::InvalidClaimTicket,
+    }
+}
+
+#[cfg(feature = "feature-sponsorship")]
+fn sponsorship_error(error: SponsorshipError) -> AttestaError {
+    match error {
+        SponsorshipError::DailyLimitReached { .. } => AttestaError::SponsorDailyLimitReached,
+        SponsorshipError::RentTooHigh { .. } => AttestaError::SponsorRentTooHigh,
+        SponsorshipError::PoolExhausted { .. } => AttestaError::SponsorPoolExhausted,
+        SponsorshipError::InvalidData => AttestaError::InvalidAccountData,
+    }
+}
+
+/// The error for a transaction or claim the account's policy doesn't allow
+/// Maps an error from `execute_transaction` or `check_replay`
+fn execution_error(error: ProgramError) -> AttestaError {
+    match error {
+        ProgramError::Custom(code) if code == CryptoError::IdempotencyKeyReused as u32 => {
+            AttestaError::IdempotencyKeyReused
+        }
+        ProgramError::MissingRequiredSignature => AttestaError::PasskeyNotAllowed,
+        _ => AttestaError::ExecutionFailed,
+    }
+}
+
+fn denied_error(result: &PolicyResult) -> AttestaError {
+    match result {
+        PolicyResult::RequiresApproval => AttestaError::RequiresApproval,
+        PolicyResult::Denied(DenyReason::Policy) => AttestaError::PolicyDenied,
+        PolicyResult::Denied(DenyReason::ZeroAmount) => AttestaError::ZeroAmountTransfer,
+        PolicyResult::Denied(DenyReason::SelfTransfer) => AttestaError::SelfTransfer,
+        PolicyResult::Denied(DenyReason::ParentPolicy) => AttestaError::ParentPolicyDenied,
+        PolicyResult::Denied(DenyReason::LockedOut { .. }) => AttestaError::AccountLockedOut,
+        PolicyResult::Denied(DenyReason::RecoveryPending) => AttestaError::OverRecoveryAllowance,
+        _ => AttestaError::ExecutionFailed,
+    }
+}
+
+/// Writes one structured `ATST1` line for indexers (see `attesta_types::log`)
+fn log_event(code: &str, fields: &[(&str, &dyn std::fmt::Display)]) {
+    msg!("{}", format_log_line(code, fields));
+}
+
+/// Logs why an instruction failed and hands the error back
+fn rejected(error: AttestaError) -> AttestaError {
+    log_event(codes::REJECTED, &[("error", &format_args!("{:?}", error))]);
+    error
+}
+
+/// Emits `AllowedWithContext` if the account samples this execution
+///
+/// Call after the execution, so the account is at the transaction's nonce.
+fn emit_allowed_context(account: &AttestaAccount, attesta_key: &Pubkey, transaction_data: &[u8]) {
+    if let Some(context) = sample_allowed(account, attesta_key, transaction_data) {
+        emit!(AllowedWithContext {
+            attesta_account: *attesta_key,
+            nonce: account.nonce,
+            policy_hash: context.policy_hash,
+            amount: context.amount,
+            destination_hash: context.destination_hash,
+            matched_rule: context.matched_rule,
+            matched_entry: context.matched_entry,
+        });
+    }
+}
+
+/// Finds or creates the proposal PDA for a transaction that needs approvals
+///
+/// A proposal already made for the same transaction is left as it is. A new
+/// one is paid for by `authority`, which has to sign.
+///
+/// # Returns
+/// The proposal's address, the proposal as stored, and whether it was
+/// created here
+fn open_proposal<'info>(
+    accounts: &Execute<'info>,
+    account: &AttestaAccount,
+    attesta_key: &Pubkey,
+    proposed: PendingTransaction,
+) -> Result<(Pubkey, PendingTransaction, bool)> {
+    let proposal = accounts.proposal.as_ref().ok_or(AttestaError::MissingProposalAccount)?;
+    let system_program = accounts.system_program.as_ref().ok_or(AttestaError::MissingProposalAccount)?;
+    let (expected, bump) =
+        Pubkey::find_program_address(&[PROPOSAL_SEED, attesta_key.as_ref(), &proposed.message_hash], &crate::ID);
+    require_keys_eq!(proposal.key(), expected, AttestaError::MissingProposalAccount);
+
+    let info = proposal.to_account_info();
+    if info.owner == &crate::ID {
+        let existing = ProposalData::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        let pending = PendingTransaction::from_bytes(&existing.pending)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        return Ok((expected, pending, false));
+    }
+
+    require!(accounts.authority.is_signer, AttestaError::MissingProposalAccount);
+    let pending_len = PendingTransaction::space_for(account, proposed.transaction_data.len())
+        .map_err(proposal_error)?;
+    let space = ProposalData::space(pending_len);
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            anchor_lang::system_program::CreateAccount {
+                from: accounts.authority.to_account_info(),
+                to: info.clone(),
+            },
+            &[&[PROPOSAL_SEED, attesta_key.as_ref(), &proposed.message_hash, &[bump]]],
+        ),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+    )?;
+
+    let record = ProposalData {
+        attesta_account: *attesta_key,
+        pending: proposed.to_bytes().map_err(|_| AttestaError::SerializationFailed)?,
+        bump,
+    };
+    record.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+    Ok((expected, proposed, true))
+}
+
+/// Creates the announcement PDA for a transfer a vault holds back
+///
+/// `authority` signs to pay its rent. The nonce keying it is one the
+/// account has only just used up, so the PDA can't exist yet.
+fn open_announcement<'info>(accounts: &Execute<'info>, attesta_key: &Pubkey, announced: &Announcement) -> Result<Pubkey> {
+    let announcement = accounts.announcement.as_ref().ok_or(AttestaError::MissingAnnouncementAccount)?;
+    let system_program = accounts.system_program.as_ref().ok_or(AttestaError::MissingAnnouncementAccount)?;
+    require!(accounts.authority.is_signer, AttestaError::MissingAnnouncementAccount);
+    let nonce = announced.nonce.to_le_bytes();
+    let (expected, bump) = Pubkey::find_program_address(&[ANNOUNCEMENT_SEED, attesta_key.as_ref(), &nonce], &crate::ID);
+    require_keys_eq!(announcement.key(), expected, AttestaError::MissingAnnouncementAccount);
+
+    let space = AnnouncementData::space(announced);
+    let info = announcement.to_account_info();
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            anchor_lang::system_program::CreateAccount {
+                from: accounts.authority.to_account_info(),
+                to: info.clone(),
+            },
+            &[&[ANNOUNCEMENT_SEED, attesta_key.as_ref(), &nonce, &[bump]]],
+        ),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+    )?;
+
+    let record = AnnouncementData {
+        attesta_account: *attesta_key,
+        announcement: announced.to_bytes().map_err(|_| AttestaError::SerializationFailed)?,
+        bump,
+    };
+    record.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+    Ok(expected)
+}
+
+/// Emits `PrimaryActiveDuringRecovery` if `credential_id` is the primary
+/// passkey of an account with a recovery pending
+///
+/// Emitted before a denial fails the instruction too: a failed transaction's
+/// logs are still recorded, and a denied attempt is what guardians most
+/// need to see.
+fn announce_primary_activity(account: &AttestaAccount, attesta_key: &Pubkey, credential_id: &[u8], nonce: u64, allowed: bool) {
+    if !account.recovery_pending() || !account.is_primary_credential(credential_id) {
+        return;
+    }
+    emit!(PrimaryActiveDuringRecovery { attesta_account: *attesta_key, nonce, allowed });
+    log_event(codes::PRIMARY_ACTIVE_IN_RECOVERY, &[("nonce", &nonce), ("allowed", &allowed)]);
+}
+
+/// Logs a transaction or claim the policies didn't let through
+fn log_not_allowed(result: &PolicyResult) {
+    match result {
+        PolicyResult::RequiresApproval => log_event(codes::NEEDS_APPROVAL, &[]),
+        PolicyResult::Denied(DenyReason::LockedOut { until }) => log_event(codes::LOCKED_OUT, &[("until", until)]),
+        PolicyResult::Denied(reason) => {
+            let reason = match reason {
+                DenyReason::Policy => "policy",
+                DenyReason::ZeroAmount => "zero_amount",
+                DenyReason::SelfTransfer => "self_transfer",
+                DenyReason::ParentPolicy => "parent_policy",
+                DenyReason::RecoveryPending => "recovery_pending",
+                DenyReason::AuthenticationFailed | DenyReason::LockedOut { .. } => "auth_failed",
+            };
+            log_event(codes::DENIED, &[("reason", &reason)]);
+        }
+        PolicyResult::Allowed | PolicyResult::AlreadyExecuted { .. } | PolicyResult::Announced { .. } => {}
+    }
+}
+
+/// Verifies a passkey-authorized management action against the account
+///
+/// Wraps `smart_account::authorize_action` so every management instruction
+/// maps signature problems to the same program errors.
+fn authorize(
+    account: &mut AttestaAccount,
+    webauthn_sig: &[u8],
+    nonce: u64,
+    action: &[u8],
+    payload: &[u8],
+) -> Result<()> {
+    let webauthn_signature = WebAuthnSignature::from_bytes(webauthn_sig)
+        .map_err(|_| AttestaError::InvalidSignature)?;
+
+    authorize_action(account, webauthn_signature, nonce, action, payload)
+        .map_err(|_| AttestaError::Unauthorized)?;
+
+    Ok(())
+}
+
+/// `authorize` for actions only the account's primary passkey may take
+fn authorize_admin(
+    account: &mut AttestaAccount,
+    webauthn_sig: &[u8],
+    nonce: u64,
+    action: &[u8],
+    payload: &[u8],
+) -> Result<()> {
+    let webauthn_signature = WebAuthnSignature::from_bytes(webauthn_sig)
+        .map_err(|_| AttestaError::InvalidSignature)?;
+
+    authorize_admin_action(account, webauthn_signature, nonce, action, payload)
+        .map_err(|_| AttestaError::Unauthorized)?;
+
+    Ok(())
+}
+
+/// Checks a passkey being enrolled against the account's AAGUID allowlist
+///
+/// `registration_sig` is the passkey's signature over its registration
+/// challenge (see `smart_account::verify_registration`), if one was given.
+/// Without one the passkey has no attested model, which only an empty
+/// allowlist accepts.
+///
+/// # Returns
+/// The AAGUID to record for the passkey
+fn enrolled_aaguid(
+    owner: &Pubkey,
+    account_address: &Pubkey,
+    settings: &AccountSettings,
+    public_key: &[u8; P256_PUBKEY_LEN],
+    credential_id: &[u8],
+    registration_sig: Option<&[u8]>,
+) -> Result<Option<[u8; AAGUID_LEN]>> {
+    let aaguid = match registration_sig {
+        Some(registration_sig) => {
+            let registration_signature = WebAuthnSignature::from_bytes(registration_sig)
+                .map_err(|_| AttestaError::InvalidSignature)?;
+            verify_registration(owner, account_address, public_key, credential_id, &registration_signature)
+                .map_err(|_| AttestaError::PasskeyNotProven)?
+        }
+        None => None,
+    };
+
+    require!(settings.allows_authenticator(aaguid.as_ref()), AttestaError::AuthenticatorNotAllowed);
+    Ok(aaguid)
+}
+
+/// An optional registration signature argument: empty means none was given
+fn optional_registration(registration_sig: &[u8]) -> Option<&[u8]> {
+    (!registration_sig.is_empty()).then_some(registration_sig)
+}
+
+/// Moves SPL tokens out of the Attesta PDA with `transfer_checked`
+///
+/// The mint and destination were signed as part of the transfer, so the
+/// accounts passed in must match them exactly.
+fn transfer_tokens<'info>(
+    attesta_info: &AccountInfo<'info>,
+    account: &AttestaAccount,
+    transfer: &TokenTransfer,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let [source, mint, destination, token_program, ..] = remaining_accounts else {
+        return Err(AttestaError::InvalidTokenAccounts.into());
+    };
+
+    require_keys_eq!(*mint.key, transfer.mint, AttestaError::InvalidTokenAccounts);
+    require_keys_eq!(*destination.key, transfer.destination_ata, AttestaError::InvalidTokenAccounts);
+    require_keys_eq!(*token_program.key, TOKEN_PROGRAM_ID, AttestaError::InvalidTokenAccounts);
+
+    // The PDA owns the source token account, so it signs with its seeds
+    let index = [account.sub_account_index];
+    let account_index = account.account_index.map(u16::to_le_bytes);
+    let mut seeds: Vec<&[u8]> = match (&account.parent, &account_index) {
+        (Some(parent), _) => vec![SUB_ACCOUNT_SEED, parent.as_ref(), &index],
+        (None, Some(account_index)) => vec![b"attesta", account.owner.as_ref(), account_index],
+        (None, None) => vec![b"attesta", account.owner.as_ref()],
+    };
+    let (expected_pda, bump) = Pubkey::find_program_address(&seeds, &crate::ID);
+    require_keys_eq!(*attesta_info.key, expected_pda, AttestaError::InvalidTokenAccounts);
+    let bump = [bump];
+    seeds.push(&bump);
+
+    let instruction = transfer_checked_instruction(transfer, source.key, attesta_info.key);
+    invoke_signed(
+        &instruction,
+        &[
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            attesta_info.clone(),
+            token_program.clone(),
+        ],
+        &[&seeds],
+    )?;
+
+    Ok(())
+}
+
+/// Fails with `RecoveryPending` while a recovery of the primary passkey is
+/// pending: whoever holds it may not be the owner, so the account's
+/// policies, passkeys and settings stay as they are until it's finalized or
+/// cancelled
+fn check_no_recovery_pending(account: &AttestaAccount) -> Result<()> {
+    if account.recovery_pending() {
+        msg!("A recovery is pending: finalize or cancel it first");
+        return Err(rejected(AttestaError::RecoveryPending).into());
+    }
+    Ok(())
+}
+
+/// Fails with `ConcurrentModification` if the account's policies, settings
+/// or passkeys changed since the caller read it at `expected_version`
+fn check_state_version(account: &AttestaAccount, expected_version: u64) -> Result<()> {
+    if !account.is_at_version(expected_version) {
+        msg!("Account is at version {}, not {}", account.state_version, expected_version);
+        return Err(rejected(AttestaError::ConcurrentModification).into());
+    }
+    Ok(())
+}
+
+/// Checks a policy before it's stored
+///
+/// An empty policy means "no restrictions"; anything else must be a config
+/// that evaluates the way it reads, and cheaply enough that `execute` still
+/// fits its compute budget - otherwise the account couldn't execute at all.
+fn check_policy(policy: &[u8], now: i64) -> Result<()> {
+    if policy.is_empty() {
+        return Ok(());
+    }
+    let policy = Policy::from_bytes(policy)
+        .ok()
+        .filter(|policy| policy.validate_config().is_ok())
+        .ok_or(AttestaError::InvalidPolicy)?;
+    require!(
+        policy.estimated_compute_units() <= MAX_POLICY_COMPUTE_UNITS,
+        AttestaError::PolicyTooExpensive
+    );
+    policy.validate_timestamps(now, false).map_err(time_error)?;
+    Ok(())
+}
+
+/// Drops the oldest idempotency records until the account fits its allocation
+///
+/// `execute` has no payer to grow the account with. New accounts are
+/// allocated with room for every record; older ones keep as many as fit.
+fn fit_idempotency_records(account: &mut AttestaAccount, capacity: usize) {
+    while ACCOUNT_DATA_HEADER_LEN + account.serialized_size() > capacity && !account.idempotency_records.is_empty() {
+        account.idempotency_records.remove(0);
+    }
+}
+
+/// Serializes an AttestaAccount back into its Anchor wrapper
+fn save_account(
+    wrapper: &mut Account<AttestaAccountData>,
+    account: &AttestaAccount,
+) -> Result<()> {
+    wrapper.data = account.to_bytes()
+        .map_err(|_| AttestaError::SerializationFailed)?;
+    Ok(())
+}
+
+/// Checks that an account of `capacity` bytes has room for a full
+/// destination tracker, which `execute` has no payer to grow it for
+fn check_destination_room(account: &AttestaAccount, capacity: usize) -> Result<()> {
+    let required = ACCOUNT_DATA_HEADER_LEN + account.serialized_size() + account.destination_spends_headroom();
+    require!(required <= capacity, AttestaError::NoRoomForDestinationTracker);
+    Ok(())
+}
+
+/// Saves an AttestaAccount that may have outgrown its allocation
+///
+/// Grows the account when the serialized data no longer fits, with `payer`
+/// topping up the rent-exempt balance, and sets aside room for a full
+/// destination tracker (see `AttestaAccount::destination_spends_headroom`).
+/// Accounts never shrink here.
+fn save_account_resized<'info>(
+    wrapper: &mut Account<'info, AttestaAccountData>,
+    account: &AttestaAccount,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let required = ACCOUNT_DATA_HEADER_LEN + account.serialized_size() + account.destination_spends_headroom();
+    let info = wrapper.to_account_info();
+    if required > info.data_len() {
+        let rent_needed = Rent::get()?.minimum_balance(required);
+        let shortfall = rent_needed.saturating_sub(info.lamports());
+        if shortfall > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                shortfall,
+            )?;
+        }
+        info.realloc(required, false)?;
+    }
+
+    save_account(wrapper, account)
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = ATTESTA_ACCOUNT_SPACE,
+        seeds = [b"attesta", owner.key.as_ref()],
+        bump
+    )]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> Initialize<'info> {
+    // Helper to get the seed for PDA derivation
+    pub fn get_seed(&self) -> Vec<u8> {
+        // Use first 32 bytes of owner key as seed
+        self.owner.key().as_ref()[..32].to_vec()
+    }
+}
+
+#[derive(Accounts)]
+pub struct Execute<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+    
+    /// CHECK: Anyone, unless the account lists executors; then it must be one
+    /// of them and sign (checked in the handler)
+    pub authority: UncheckedAccount<'info>,
+
+    /// The parent account, when `attesta_account` is a sub-account (checked against its `parent`)
+    pub parent_account: Option<Account<'info, AttestaAccountData>>,
+
+    /// The account's proof log, when it has enabled one (checked against its `attesta_account`)
+    #[account(mut)]
+    pub proof_log: Option<Account<'info, ProofLogData>>,
+
+    /// CHECK: The SPL Memo program, when the memo is posted through it
+    #[account(address = MEMO_PROGRAM_ID)]
+    pub memo_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: The transaction's proposal PDA, for when it needs approvals
+    /// (checked against its seeds, and created, in the handler)
+    #[account(mut)]
+    pub proposal: Option<UncheckedAccount<'info>>,
+
+    /// Creates the proposal or announcement PDA
+    pub system_program: Option<Program<'info, System>>,
+
+    /// CHECK: The transfer's announcement PDA, for when a vault holds it back
+    /// (checked against its seeds, and created, in the handler)
+    #[account(mut)]
+    pub announcement: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteEmergency<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    /// CHECK: Anyone, unless the account lists executors; then it must be one
+    /// of them and sign (checked in the handler)
+    pub authority: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAsOwner<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    /// The account's owner, checked against its `owner`
+    pub owner: Signer<'info>,
+
+    /// The parent account, when `attesta_account` is a sub-account (checked against its `parent`)
+    pub parent_account: Option<Account<'info, AttestaAccountData>>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct InitializeSubAccount<'info> {
+    #[account(mut)]
+    pub parent_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = ATTESTA_ACCOUNT_SPACE,
+        seeds = [SUB_ACCOUNT_SEED, parent_account.key().as_ref(), &[index]],
+        bump
+    )]
+    pub sub_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePolicy<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+    
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(webauthn_sig: Vec<u8>, nonce: u64)]
+pub struct AttestPolicy<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = PolicyAttestationData::SPACE,
+        seeds = [POLICY_ATTESTATION_SEED, attesta_account.key().as_ref(), &nonce.to_le_bytes()],
+        bump
+    )]
+    pub attestation: Account<'info, PolicyAttestationData>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StoreBackup<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = BackupEscrow::SPACE,
+        seeds = [b"backup", attesta_account.key().as_ref()],
+        bump
+    )]
+    pub backup: Account<'info, BackupEscrow>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnableProofLog<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = ProofLogData::SPACE,
+        seeds = [b"proof_log", attesta_account.key().as_ref()],
+        bump
+    )]
+    pub proof_log: Account<'info, ProofLogData>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateBackup<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        mut,
+        seeds = [b"backup", attesta_account.key().as_ref()],
+        bump = backup.bump
+    )]
+    pub backup: Account<'info, BackupEscrow>,
+}
+
+#[derive(Accounts)]
+pub struct DeleteBackup<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        mut,
+        seeds = [b"backup", attesta_account.key().as_ref()],
+        bump = backup.bump,
+        close = owner
+    )]
+    pub backup: Account<'info, BackupEscrow>,
+
+    /// CHECK: Verified against the Attesta account's owner in the handler
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManagePasskeys<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetProgramVersion {}
+
+#[derive(Accounts)]
+pub struct GetProgramFeatures {}
+
+#[derive(Accounts)]
+pub struct GetProtocolConstants {}
+
+#[derive(Accounts)]
+#[instruction(webauthn_sig: Vec<u8>, nonce: u64, transaction_data: Vec<u8>)]
+pub struct ScheduleTransaction<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = ScheduleData::space(transaction_data.len()),
+        seeds = [SCHEDULE_SEED, attesta_account.key().as_ref(), &nonce.to_le_bytes()],
+        bump
+    )]
+    pub schedule: Account<'info, ScheduleData>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteScheduled<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut, has_one = attesta_account, close = owner)]
+    pub schedule: Account<'info, ScheduleData>,
+
+    /// CHECK: Verified against the Attesta account's owner in the handler
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    /// The parent account, when `attesta_account` is a sub-account (checked against its `parent`)
+    pub parent_account: Option<Account<'info, AttestaAccountData>>,
+
+    /// The account's proof log, when it has enabled one (checked against its `attesta_account`)
+    #[account(mut)]
+    pub proof_log: Option<Account<'info, ProofLogData>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelScheduled<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut, has_one = attesta_account, close = owner)]
+    pub schedule: Account<'info, ScheduleData>,
+
+    /// CHECK: Verified against the Attesta account's owner in the handler
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut, has_one = attesta_account)]
+    pub proposal: Account<'info, ProposalData>,
+}
+
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut, has_one = attesta_account, close = rent_payer)]
+    pub proposal: Account<'info, ProposalData>,
+
+    /// CHECK: Verified against the proposal's rent payer in the handler
+    #[account(mut)]
+    pub rent_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAnnounced<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut, has_one = attesta_account, close = rent_payer)]
+    pub announcement: Account<'info, AnnouncementData>,
+
+    /// CHECK: Verified against the announcement's rent payer in the handler
+    #[account(mut)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    /// CHECK: Anyone, unless the account lists executors; then it must be one
+    /// of them and sign (checked in the handler)
+    pub authority: UncheckedAccount<'info>,
+
+    /// The parent account, when `attesta_account` is a sub-account (checked against its `parent`)
+    pub parent_account: Option<Account<'info, AttestaAccountData>>,
+
+    /// The account's proof log, when it has enabled one (checked against its `attesta_account`)
+    #[account(mut)]
+    pub proof_log: Option<Account<'info, ProofLogData>>,
+}
+
+#[derive(Accounts)]
+pub struct VetoAnnouncement<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    #[account(mut, has_one = attesta_account, close = rent_payer)]
+    pub announcement: Account<'info, AnnouncementData>,
+
+    /// CHECK: Verified against the announcement's rent payer in the handler
+    #[account(mut)]
+    pub rent_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    /// Checked against the ticket's claim key in the handler
+    pub claim_key: Signer<'info>,
+
+    /// CHECK: Any account can receive lamports; the claim key's signature chose it
+    #[account(mut)]
+    pub destination: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateSponsorPool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = SponsorPoolData::SPACE,
+        seeds = [SPONSOR_POOL_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub sponsor_pool: Account<'info, SponsorPoolData>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(account_index: u16)]
+pub struct InitializeWithIndex<'info> {
+    /// CHECK: The owner's indexed PDA, created in the handler after checking it's free
+    #[account(mut, seeds = [b"attesta", owner.key.as_ref(), &account_index.to_le_bytes()], bump)]
+    pub attesta_account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SponsoredInitialize<'info> {
+    #[account(mut)]
+    pub sponsor_pool: Account<'info, SponsorPoolData>,
+
+    /// CHECK: The owner's PDA, created in the handler once the pool has funded it
+    #[account(mut, seeds = [b"attesta", owner.key.as_ref()], bump)]
+    pub attesta_account: UncheckedAccount<'info>,
+
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawPool<'info> {
+    #[account(mut, has_one = authority)]
+    pub sponsor_pool: Account<'info, SponsorPoolData>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Recover<'info> {
+    #[account(mut)]
+    pub attesta_account: Account<'info, AttestaAccountData>,
+
+    /// Pays fees and any extra space; passkeys (or elapsed time) authorize the change
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Emitted when a recovery drill reaches the recovery threshold
+#[event]
+pub struct RecoveryDrillSucceeded {
+    /// The Attesta account that ran the drill
+    pub attesta_account: Pubkey,
+
+    /// Approvals that counted
+    pub approvals: u8,
+
+    /// When the drill succeeded (Unix timestamp)
+    pub timestamp: i64,
+}
+
+/// Emitted when `execute` runs a transaction
+#[event]
+pub struct TransactionExecuted {
+    /// The Attesta account that executed it
+    pub attesta_account: Pubkey,
+
+    /// The nonce it consumed
+    pub nonce: u64,
+
+    /// The message hash the passkey signed
+    pub message_hash: [u8; 32],
+
+    /// SHA-256 of its memo, if it had one (the memo itself is in the
+    /// instruction data)
+    pub memo_hash: Option<[u8; 32]>,
+
+    /// SHA-256 of the credential ID of the passkey that signed it (the
+    /// admin passkey for an emergency override); `None` when the owner's
+    /// wallet signed
+    pub credential_id_hash: Option<[u8; 32]>,
+}
+
+/// Emitted whenever `execute_emergency` runs a transaction past the
+/// account's time locks, alongside its `TransactionExecuted`
+#[event]
+pub struct EmergencyOverrideUsed {
+    /// The Attesta account that used its override
+    pub attesta_account: Pubkey,
+
+    /// The nonce it consumed
+    pub nonce: u64,
+
+    /// The message hash both passkeys signed
+    pub message_hash: [u8; 32],
+
+    /// Raw token amount moved (0 unless it ran a token transfer)
+    pub amount: u64,
+
+    /// SHA-256 of the credential ID of the passkey that signed with the primary
+    pub cosigner: [u8; 32],
+
+    /// When the override may run again (Unix timestamp)
+    pub available_again_at: i64,
+}
+
+/// Emitted for the sampled share of allowed executions (see
+/// `AccountSettings::log_allowed_sample_rate`)
+#[event]
+pub struct AllowedWithContext {
+    /// The Attesta account that executed
+    pub attesta_account: Pubkey,
+
+    /// The nonce the execution consumed, which decided it was sampled
+    pub nonce: u64,
+
+    /// The account's policy hash when it was allowed
+    pub policy_hash: [u8; 32],
+
+    /// Base units a token transfer moved (0 for other transactions)
+    pub amount: u64,
+
+    /// SHA-256 of the token account paid into (all zeros for other transactions)
+    pub destination_hash: [u8; 32],
+
+    /// Rule that matched, counting a composite's rules in its place (255 for none)
+    pub matched_rule: u8,
+
+    /// Entry of that rule that matched: mint limit, destination or binding
+    pub matched_entry: u8,
+}
+
+/// Emitted when the primary passkey signs an `execute` (or an emergency
+/// override) while a recovery to replace it is pending, whether or not it
+/// was allowed
+///
+/// If the owner didn't sign it, whoever holds the passkey is trying to
+/// move funds before the recovery lands: guardians should watch for this.
+#[event]
+pub struct PrimaryActiveDuringRecovery {
+    /// The Attesta account with the pending recovery
+    pub attesta_account: Pubkey,
+
+    /// The nonce the passkey signed
+    pub nonce: u64,
+
+    /// Whether it executed in that instruction
+    pub allowed: bool,
+}
+
+/// Emitted whenever an account's policies change
+#[event]
+pub struct PolicyUpdated {
+    /// The Attesta account whose policies changed
+    pub attesta_account: Pubkey,
+
+    /// Policies it now holds
+    pub policy_count: u8,
+
+    /// The account's new `policy_hash`, for comparing against a policy
+    /// computed off-chain
+    pub policy_hash: [u8; 32],
+}
+
+/// Emitted when an account's policy is attested for a third party
+#[event]
+pub struct PolicyAttested {
+    /// The Attesta account attested
+    pub attesta_account: Pubkey,
+
+    /// The attestation PDA
+    pub attestation: Pubkey,
+
+    /// The `policy_hash` attested
+    pub policy_hash: [u8; 32],
+
+    /// When the attestation lapses (Unix timestamp)
+    pub expires_at: i64,
+}
+
+/// Emitted when `execute` leaves a transaction waiting for approvals
+#[event]
+pub struct ProposalCreated {
+    /// The Attesta account it was proposed from
+    pub attesta_account: Pubkey,
+
+    /// The proposal PDA
+    pub proposal: Pubkey,
+
+    /// The proposed transaction's message hash
+    pub message_hash: [u8; 32],
+
+    /// Approvals it needs, the proposer's included
+    pub required_approvals: u8,
+
+    /// When it stops taking approvals (Unix timestamp)
+    pub expires_at: i64,
+}
+
+/// Emitted when `execute` announces a transfer over a vault's threshold
+#[event]
+pub struct TransferAnnounced {
+    /// The Attesta account it runs from
+    pub attesta_account: Pubkey,
+
+    /// The announcement PDA
+    pub announcement: Pubkey,
+
+    /// The nonce it used up
+    pub nonce: u64,
+
+    /// What it moves
+    pub amount: u64,
+
+    /// When `execute_announced` can run it (Unix timestamp)
+    pub executable_at: i64,
+}
+
+/// Emitted when a passkey vetoes an announced transfer
+#[event]
+pub struct AnnouncementVetoed {
+    /// The Attesta account it would have run from
+    pub attesta_account: Pubkey,
+
+    /// The announcement PDA, now closed
+    pub announcement: Pubkey,
+
+    /// SHA-256 of the vetoing passkey's credential ID
+    pub vetoed_by: [u8; 32],
+}
+
+/// Emitted when a proposed transaction is withdrawn
+#[event]
+pub struct ProposalCancelled {
+    /// The Attesta account it was proposed from
+    pub attesta_account: Pubkey,
+
+    /// The proposed transaction's message hash
+    pub message_hash: [u8; 32],
+
+    /// The `CancelReason` code the canceller signed
+    pub reason: u8,
+}
+
+/// Emitted when a claim ticket is paid out
+#[event]
+pub struct TicketClaimed {
+    /// The Attesta account that paid
+    pub attesta_account: Pubkey,
+
+    /// Where the lamports went
+    pub destination: Pubkey,
+
+    /// Lamports paid
+    pub amount: u64,
+
+    /// The ticket's nonce
+    pub nonce: u64,
+}
+
+/// Emitted when a sponsor pool pays for a new account
+#[event]
+pub struct AccountSponsored {
+    /// The pool that paid
+    pub sponsor_pool: Pubkey,
+
+    /// The account created
+    pub attesta_account: Pubkey,
+
+    /// Its owner
+    pub owner: Pubkey,
+
+    /// Lamports the pool paid toward the account's rent
+    pub lamports: u64,
+}
+
+/// Emitted when a beneficiary takes over an inactive account
+#[event]
+pub struct InheritanceClaimed {
+    /// The Attesta account that changed hands
+    pub attesta_account: Pubkey,
+
+    /// When it was claimed (Unix timestamp)
+    pub timestamp: i64,
+}
+
+/// Wrapper account type for Anchor
+/// This wraps our AttestaAccount so Anchor can manage it
+#[account]
+pub struct AttestaAccountData {
+    pub data: Vec<u8>, // Serialized AttestaAccount
+}
+
+/// On-chain escrow for an encrypted backup
+///
+/// Holds a serialized `EncryptedBackup` so users can't lose it the way they
+/// lose backup files. The contents are encrypted, so anyone may read it.
+#[account]
+pub struct BackupEscrow {
+    /// The Attesta account this backup belongs to
+    pub attesta_account: Pubkey,
+
+    /// Serialized EncryptedBackup (at most `MAX_ESCROW_BACKUP_SIZE` bytes)
+    pub backup: Vec<u8>,
+
+    /// When the backup was last written (Unix timestamp)
+    pub updated_at: i64,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl BackupEscrow {
+    /// Space for the largest allowed backup
+    /// discriminator + attesta_account + vec length + backup + updated_at + bump
+    pub const SPACE: usize = ACCOUNT_DISCRIMINATOR_LEN + PUBKEY_LEN + BORSH_LEN_PREFIX + MAX_ESCROW_BACKUP_SIZE + 8 + 1;
+
+    /// Validates and stores a new backup, replacing whatever was there before
+    ///
+    /// Returns the new backup's rekey generation, which may not be lower
+    /// than the stored one's.
+    pub fn write(&mut self, backup: Vec<u8>, now: i64) -> Result<u16> {
+        if backup.len() > MAX_ESCROW_BACKUP_SIZE {
+            return Err(AttestaError::BackupTooLarge.into());
+        }
+
+        // Only accept blobs that actually decode as a backup, made around now
+        let decoded = EncryptedBackup::from_escrow_bytes(&backup)
+            .map_err(|_| AttestaError::InvalidBackup)?;
+        validate_timestamp(decoded.created_at, now, MAX_CLOCK_SKEW_SECONDS, MAX_PAST_SECONDS).map_err(time_error)?;
+
+        // A backup under a phrase that's since been rotated away from is stale
+        let stored_generation = EncryptedBackup::from_bytes(&self.backup).map_or(0, |stored| stored.rekey_generation);
+        if decoded.rekey_generation < stored_generation {
+            return Err(AttestaError::StaleBackup.into());
+        }
+
+        self.backup = backup;
+        self.updated_at = now;
+        Ok(decoded.rekey_generation)
+    }
+}
+
+/// Append-only record of an account's executions
+///
+/// Holds a serialized `ProofLog`: for each successful `execute`, the nonce,
+/// signed message hash, signing credential and time, so the authorization
+/// can be re-verified later. Anyone may read it.
+#[account]
+pub struct ProofLogData {
+    /// The Attesta account whose executions are logged
+    pub attesta_account: Pubkey,
+
+    /// Serialized ProofLog (at most `ProofLog::MAX_SERIALIZED_SIZE` bytes)
+    pub log: Vec<u8>,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl ProofLogData {
+    /// Space for a full log
+    /// discriminator + attesta_account + vec length + log + bump
+    pub const SPACE: usize = ACCOUNT_DISCRIMINATOR_LEN + PUBKEY_LEN + BORSH_LEN_PREFIX + ProofLog::MAX_SERIALIZED_SIZE + 1;
+
+    /// Records an execution, dropping the oldest entry once the log is full
+    pub fn append(&mut self, entry: ProofLogEntry) -> Result<()> {
+        let mut log = ProofLog::from_bytes(&self.log)
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        log.append(entry);
+        self.log = log.to_bytes().map_err(|_| AttestaError::SerializationFailed)?;
+        Ok(())
+    }
+}
+
+/// A transaction waiting for its window, from `schedule_transaction`
+#[account]
+pub struct ScheduleData {
+    /// The Attesta account the transaction runs from
+    pub attesta_account: Pubkey,
+
+    /// Serialized ScheduledTransaction
+    pub scheduled: Vec<u8>,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl ScheduleData {
+    /// Space for a schedule holding `transaction_data_len` bytes of transaction data
+    /// discriminator + attesta_account + vec length + scheduled + bump
+    pub fn space(transaction_data_len: usize) -> usize {
+        ACCOUNT_DISCRIMINATOR_LEN
+            + PUBKEY_LEN
+            + BORSH_LEN_PREFIX
+            + ScheduledTransaction::serialized_size(transaction_data_len, MAX_CREDENTIAL_ID_LEN)
+            + 1
+    }
+}
+
+/// A program-written record of an account's policy, from `attest_policy`
+///
+/// Holds a serialized `PolicyAttestation`. Only this program can write to
+/// its PDAs, so a verifier needs nothing else to trust it, but it only
+/// holds while the account's `policy_hash` still matches.
+#[account]
+pub struct PolicyAttestationData {
+    /// The Attesta account the attestation is about
+    pub attesta_account: Pubkey,
+
+    /// Serialized PolicyAttestation
+    pub attestation: Vec<u8>,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl PolicyAttestationData {
+    /// discriminator + attesta_account + vec length + attestation + bump
+    pub const SPACE: usize =
+        ACCOUNT_DISCRIMINATOR_LEN + PUBKEY_LEN + BORSH_LEN_PREFIX + PolicyAttestation::SERIALIZED_SIZE + 1;
+}
+
+/// A pool paying new accounts' rent, from `create_sponsor_pool`
+#[account]
+pub struct SponsorPoolData {
+    /// Who may withdraw the pool's lamports
+    pub authority: Pubkey,
+
+    /// Serialized SponsorPool
+    pub pool: Vec<u8>,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl SponsorPoolData {
+    /// discriminator + authority + vec length + pool + bump
+    pub const SPACE: usize = ACCOUNT_DISCRIMINATOR_LEN + PUBKEY_LEN + BORSH_LEN_PREFIX + SponsorPool::SERIALIZED_SIZE + 1;
+}
+
+/// A transaction waiting for approvals
+#[account]
+pub struct ProposalData {
+    /// The Attesta account the transaction runs from
+    pub attesta_account: Pubkey,
+
+    /// Serialized PendingTransaction
+    pub pending: Vec<u8>,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl ProposalData {
+    /// Space for a proposal whose `PendingTransaction` takes `pending_len` bytes
+    /// discriminator + attesta_account + vec length + pending + bump
+    pub fn space(pending_len: usize) -> usize {
+        ACCOUNT_DISCRIMINATOR_LEN + PUBKEY_LEN + BORSH_LEN_PREFIX + pending_len + 1
+    }
+}
+
+/// A transfer waiting for its vault's delay, from `execute`
+#[account]
+pub struct AnnouncementData {
+    /// The Attesta account the transfer runs from
+    pub attesta_account: Pubkey,
+
+    /// Serialized Announcement
+    pub announcement: Vec<u8>,
+
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl AnnouncementData {
+    /// Space for `announcement`
+    /// discriminator + attesta_account + vec length + announcement + bump
+    pub fn space(announcement: &Announcement) -> usize {
+        ACCOUNT_DISCRIMINATOR_LEN
+            + PUBKEY_LEN
+            + BORSH_LEN_PREFIX
+            + Announcement::serialized_size(
+                announcement.transaction_data.len(),
+                announcement.credential_id.len(),
+                announcement.signature.len(),
+            )
+            + 1
+    }
+}
+
+#[error_code]
+pub enum AttestaError {
+    #[msg("Invalid signature format")]
+    InvalidSignature,
+    
+    #[msg("Transaction execution failed")]
+    ExecutionFailed,
+    
+    #[msg("Transaction requires additional approvals")]
+    RequiresApproval,
+    
+    #[msg("Transaction denied by policy")]
+    PolicyDenied,
+    
+    #[msg("Unauthorized: not the account owner")]
+    Unauthorized,
+    
+    #[msg("Failed to serialize account data")]
+    SerializationFailed,
+    
+    #[msg("Invalid account data format")]
+    InvalidAccountData,
+
+    #[msg("Backup exceeds the 2KB escrow limit")]
+    BackupTooLarge,
+
+    #[msg("Backup data is not a valid encrypted backup")]
+    InvalidBackup,
+
+    #[msg("Passkey cannot be added or removed")]
+    InvalidPasskey,
+
+    #[msg("Token accounts don't match the signed transfer")]
+    InvalidTokenAccounts,
+
+    #[msg("Idempotency key was already used for a different transaction")]
+    IdempotencyKeyReused,
+
+    #[msg("A recovery is already pending")]
+    RecoveryAlreadyPending,
+
+    #[msg("No recovery is pending")]
+    NoPendingRecovery,
+
+    #[msg("Recovery doesn't have enough approvals")]
+    RecoveryThresholdNotMet,
+
+    #[msg("Recovery delay has not elapsed")]
+    RecoveryDelayNotElapsed,
+
+    #[msg("Zero-amount transfers are disabled for this account")]
+    ZeroAmountTransfer,
+
+    #[msg("Transfers to the account itself are disabled")]
+    SelfTransfer,
+
+    #[msg("Sub-account transactions need the parent account")]
+    MissingParentAccount,
+
+    #[msg("Transaction denied by the parent account's policy")]
+    ParentPolicyDenied,
+
+    #[msg("A sub-account can't have sub-accounts")]
+    NestedSubAccount,
+
+    #[msg("Policy config is malformed or out of range")]
+    InvalidPolicy,
+
+    // Keep in sync with MAX_TRANSACTION_DATA_LEN (checked in the tests below)
+    #[msg("Transaction data exceeds the account's limit (at most 1024 bytes)")]
+    TransactionTooLarge,
+
+    #[msg("Invalid inheritance config")]
+    InvalidInheritanceConfig,
+
+    #[msg("No inheritance is configured")]
+    InheritanceNotConfigured,
+
+    #[msg("The account has not been inactive long enough to claim")]
+    InheritanceNotClaimable,
+
+    #[msg("Policy would cost too much compute to evaluate")]
+    PolicyTooExpensive,
+
+    #[msg("This account logs its executions: pass its proof log")]
+    MissingProofLog,
+
+    // Keep in sync with MAX_ACCOUNT_POLICIES (checked in the tests below)
+    #[msg("An account holds at most 4 policies")]
+    TooManyPolicies,
+
+    #[msg("No policy at that index")]
+    PolicyIndexOutOfRange,
+
+    #[msg("The passkey did not sign this account's registration")]
+    PasskeyNotProven,
+
+    #[msg("The passkey's authenticator model isn't on the account's allowlist")]
+    AuthenticatorNotAllowed,
+
+    #[msg("The AAGUID allowlist is too long")]
+    AllowlistTooLong,
+
+    #[msg("The program was upgraded since this account pinned its version: acknowledge the upgrade first")]
+    UpgradeNotAcknowledged,
+
+    #[msg("This account doesn't pin a program version")]
+    ProgramVersionNotPinned,
+
+    #[msg("A schedule's window must close after it opens")]
+    InvalidScheduleWindow,
+
+    #[msg("The scheduled transaction's window hasn't opened yet")]
+    ScheduleNotYetExecutable,
+
+    #[msg("The scheduled transaction's window has closed")]
+    ScheduleExpired,
+
+    #[msg("The passkey that scheduled this transaction was removed")]
+    ScheduleSignerRemoved,
+
+    #[msg("The account is locked after repeated failed signatures")]
+    AccountLockedOut,
+
+    #[msg("The memo is too long or not UTF-8")]
+    InvalidMemo,
+
+    #[msg("Posting the memo needs the SPL Memo program")]
+    MissingMemoProgram,
+
+    #[msg("Unknown WebAuthn check, or RP ID and origin checks without both hashes")]
+    InvalidWebAuthnProfile,
+
+    #[msg("This account only accepts its owner's wallet signature: use execute_as_owner")]
+    PasskeyNotAllowed,
+
+    #[msg("This account doesn't accept its owner's wallet signature")]
+    OwnerNotAllowed,
+
+    #[msg("Unknown auth mode, or a lock on a mode other than passkey-only")]
+    InvalidAuthMode,
+
+    #[msg("The account is locked to passkey-only signing until a recovery")]
+    AuthModeLocked,
+
+    #[msg("The proposal has its approvals and can only be executed")]
+    ProposalApproved,
+
+    #[msg("Unknown proposal cancellation reason")]
+    InvalidCancelReason,
+
+    #[msg("The claim ticket has expired")]
+    ClaimTicketExpired,
+
+    #[msg("Sub-accounts can't pay out claim tickets")]
+    ClaimFromSubAccount,
+
+    #[msg("Invalid claim ticket")]
+    InvalidClaimTicket,
+
+    #[msg("The account can't pay this and stay rent-exempt")]
+    InsufficientFunds,
+
+    #[msg("The sponsor pool has sponsored its accounts for today")]
+    SponsorDailyLimitReached,
+
+    #[msg("The account's rent is over the sponsor pool's per-account limit")]
+    SponsorRentTooHigh,
+
+    #[msg("The sponsor pool can't pay this and stay rent-exempt")]
+    SponsorPoolExhausted,
+
+    #[msg("The account is already initialized")]
+    AccountAlreadyInitialized,
+
+    #[msg("Timestamps can't be negative")]
+    NegativeTimestamp,
+
+    #[msg("The timestamp is too far in the future")]
+    TimestampTooFarInFuture,
+
+    #[msg("The timestamp is too far in the past")]
+    TimestampTooFarInPast,
+
+    #[msg("An attestation must expire in the future, within a year")]
+    InvalidAttestationExpiry,
+
+    #[msg("The account has no room to track destinations; set the policy with add_policy or replace_policy")]
+    NoRoomForDestinationTracker,
+
+    #[msg("The account only accepts executions submitted by one of its executors")]
+    ExecutorNotAllowed,
+
+    #[msg("The executor is already listed")]
+    ExecutorAlreadyListed,
+
+    #[msg("The executor isn't listed")]
+    ExecutorNotListed,
+
+    #[msg("An account lists at most 8 executors")]
+    TooManyExecutors,
+
+    #[msg("The proposal no longer takes approvals")]
+    ProposalExpired,
+
+    /// No longer returned: approving twice succeeds without changing anything
+    #[msg("This passkey already approved the proposal")]
+    AlreadyApproved,
+
+    #[msg("The account changed since it was read; fetch it again")]
+    ConcurrentModification,
+
+    #[msg("The allowed-execution sample rate is a percentage, at most 100")]
+    InvalidSampleRate,
+
+    #[msg("A transaction that needs approvals takes its proposal PDA, the system program and a signing authority")]
+    MissingProposalAccount,
+
+    #[msg("The account's policy changed since the transaction was proposed")]
+    ProposalPolicyChanged,
+
+    #[msg("An account holds at most 8 passkeys")]
+    TooManyPasskeys,
+
+    #[msg("The account has no emergency override (sub-accounts can't have one)")]
+    EmergencyOverrideOff,
+
+    #[msg("An emergency override needs both a cap and a period, or neither to turn it off")]
+    InvalidEmergencyOverride,
+
+    #[msg("An emergency override takes the primary passkey and one other enabled passkey")]
+    SecondPasskeyRequired,
+
+    #[msg("The emergency override was used too recently")]
+    EmergencyOverrideTooSoon,
+
+    #[msg("The transaction moves more than the emergency override allows")]
+    EmergencyOverrideOverCap,
+
+    #[msg("A transfer a vault holds back takes its announcement PDA, the system program and a signing authority")]
+    MissingAnnouncementAccount,
+
+    #[msg("The vault's delay for the announced transfer hasn't passed yet")]
+    AnnouncementNotYetExecutable,
+
+    #[msg("The announced transfer is no longer executable")]
+    AnnouncementExpired,
+
+    #[msg("The proof isn't the one the transfer was announced with")]
+    AnnouncementProofMismatch,
+
+    #[msg("The passkey that announced the transfer has been removed")]
+    AnnouncementSignerRemoved,
+
+    #[msg("The backup is from an earlier rekey generation than the one stored")]
+    StaleBackup,
+
+    #[msg("This deployment was built without the feature this instruction belongs to")]
+    FeatureNotEnabled,
+
+    // Keep in sync with MAX_INITIAL_POLICY_LEN (checked in the tests below)
+    #[msg("Policy is too large for a new account (at most 256 bytes)")]
+    PolicyTooLong,
+
+    #[msg("A recovery is pending: the account's policies, passkeys and settings can't change until it's finalized or cancelled")]
+    RecoveryPending,
+
+    #[msg("A recovery is pending: only transfers within the account's recovery allowance can run")]
+    OverRecoveryAllowance,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_account::{MAX_ACCOUNT_POLICIES, MAX_TRANSACTION_DATA_LEN};
+    use attesta_types::time::MAX_FUTURE_SECONDS;
+    use core_crypto::test_utils::TestPasskey;
+
+    #[test]
+    fn test_transaction_too_large_names_the_limit() {
+        let message = AttestaError::TransactionTooLarge.to_string();
+        assert!(message.contains(&format!("{} bytes", MAX_TRANSACTION_DATA_LEN)), "{}", message);
+    }
+
+    #[test]
+    fn test_policy_too_long_names_the_limit() {
+        let message = AttestaError::PolicyTooLong.to_string();
+        assert!(message.contains(&format!("{} bytes", MAX_INITIAL_POLICY_LEN)), "{}", message);
+    }
+
+    #[test]
+    fn test_too_many_policies_names_the_limit() {
+        let message = AttestaError::TooManyPolicies.to_string();
+        assert!(message.contains(&format!("at most {} policies", MAX_ACCOUNT_POLICIES)), "{}", message);
+    }
+
+    #[test]
+    fn test_too_many_executors_names_the_limit() {
+        let message = AttestaError::TooManyExecutors.to_string();
+        assert!(message.contains(&format!("at most {} executors", executors::MAX_AUTHORIZED_EXECUTORS)), "{}", message);
+    }
+
+    #[test]
+    fn test_concurrent_modification_code_is_shared() {
+        assert_eq!(u32::from(AttestaError::ConcurrentModification), attesta_types::consts::CONCURRENT_MODIFICATION_ERROR);
+    }
+
+    #[test]
+    fn test_policy_denial_codes_are_shared() {
+        let denials = [DenyReason::Policy, DenyReason::ZeroAmount, DenyReason::SelfTransfer, DenyReason::ParentPolicy, DenyReason::RecoveryPending];
+        let codes = denials.map(|reason| u32::from(denied_error(&PolicyResult::Denied(reason))));
+        assert_eq!(codes, attesta_types::consts::POLICY_DENIAL_ERRORS);
+    }
+
+    fn empty_escrow() -> BackupEscrow {
+        BackupEscrow {
+            attesta_account: Pubkey::new_unique(),
+            backup: Vec::new(),
+            updated_at: 0,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn test_escrow_write_and_overwrite() {
+        let mut escrow = empty_escrow();
+
+        let first = EncryptedBackup::new(b"key", b"first", 100).to_bytes().unwrap();
+        escrow.write(first.clone(), 100).unwrap();
+        assert_eq!(escrow.backup, first);
+        assert_eq!(escrow.updated_at, 100);
+
+        // A second write replaces the old backup entirely
+        let second = EncryptedBackup::new(b"key", b"second backup", 200).to_bytes().unwrap();
+        escrow.write(second.clone(), 200).unwrap();
+        assert_eq!(escrow.backup, second);
+        assert_eq!(escrow.updated_at, 200);
+    }
+
+    #[test]
+    fn test_escrow_keeps_rekey_generation_monotonic() {
+        let mut escrow = empty_escrow();
+        let original = EncryptedBackup::new(b"old key", b"backup", 100);
+        assert_eq!(escrow.write(original.to_bytes().unwrap(), 100), Ok(0));
+
+        let rekeyed = original.rekey(b"old key", b"new key", 200).unwrap().to_bytes().unwrap();
+        assert_eq!(escrow.write(rekeyed.clone(), 200), Ok(1));
+
+        // The old backup can't come back, but a fresh one at the same generation can
+        assert_eq!(escrow.write(original.to_bytes().unwrap(), 300), Err(AttestaError::StaleBackup.into()));
+        assert_eq!(escrow.backup, rekeyed);
+        let fresh = EncryptedBackup::new(b"new key", b"rebuilt", 300).with_generation(1);
+        assert_eq!(escrow.write(fresh.to_bytes().unwrap(), 300), Ok(1));
+    }
+
+    #[test]
+    fn test_escrow_rejects_oversized_backup() {
+        let mut escrow = empty_escrow();
+        let oversized = EncryptedBackup::new(b"key", &[0u8; MAX_ESCROW_BACKUP_SIZE], 100)
+            .to_bytes()
+            .unwrap();
+
+        assert!(escrow.write(oversized, 100).is_err());
+        // A rejected write leaves the escrow untouched
+        assert!(escrow.backup.is_empty());
+        assert_eq!(escrow.updated_at, 0);
+    }
+
+    #[test]
+    fn test_escrow_rejects_invalid_backup() {
+        let mut escrow = empty_escrow();
+        assert!(escrow.write(vec![1, 2, 3], 100).is_err());
+    }
+
+    #[test]
+    fn test_escrow_checks_backup_date() {
+        const NOW: i64 = 1_700_000_000;
+        let cases = [
+            (NOW + MAX_CLOCK_SKEW_SECONDS, Ok(0)),
+            (NOW + MAX_CLOCK_SKEW_SECONDS + 1, Err(AttestaError::TimestampTooFarInFuture.into())),
+            (NOW - MAX_PAST_SECONDS, Ok(0)),
+            (NOW - MAX_PAST_SECONDS - 1, Err(AttestaError::TimestampTooFarInPast.into())),
+            (-1, Err(AttestaError::NegativeTimestamp.into())),
+        ];
+        for (created_at, expected) in cases {
+            let backup = EncryptedBackup::new(b"key", b"backup", created_at).to_bytes().unwrap();
+            assert_eq!(empty_escrow().write(backup, NOW), expected, "{created_at}");
+        }
+    }
+
+    #[test]
+    fn test_proof_log_space_fits_full_log() {
+        let mut proof_log = ProofLogData {
+            attesta_account: Pubkey::new_unique(),
+            log: ProofLog::default().to_bytes().unwrap(),
+            bump: 255,
+        };
+        for nonce in 0..smart_account::proof_log::MAX_PROOF_LOG_ENTRIES as u64 + 1 {
+            let entry = ProofLogEntry { nonce, message_hash: [1; 32], credential_id_hash: [2; 32], timestamp: 3 };
+            proof_log.append(entry).unwrap();
+        }
+        assert_eq!(8 + proof_log.try_to_vec().unwrap().len(), ProofLogData::SPACE);
+    }
+
+    #[test]
+    fn test_account_space_fits_largest_new_account() {
+        // Existing accounts were created with exactly this much
+        assert_eq!(ATTESTA_ACCOUNT_SPACE, 648 + IDEMPOTENCY_RECORDS_SPACE);
+
+        let mut account = AttestaAccount::new(
+            Pubkey::new_unique(),
+            [1u8; 64],
+            vec![2; MAX_CREDENTIAL_ID_LEN],
+            vec![3; MAX_INITIAL_POLICY_LEN],
+            100,
+        );
+        account.settings.aaguid_allowlist = vec![[4; AAGUID_LEN]; MAX_AAGUID_ALLOWLIST_LEN];
+        account.settings.pinned_program_version = Some(program_version().hash());
+        account.passkey_aaguid = Some([4; AAGUID_LEN]);
+        let wrapper = AttestaAccountData { data: account.to_bytes().unwrap() };
+        let len = ACCOUNT_DISCRIMINATOR_LEN + wrapper.try_to_vec().unwrap().len();
+        assert_eq!(len, ACCOUNT_DATA_HEADER_LEN + account.serialized_size());
+        assert!(len <= ATTESTA_ACCOUNT_SPACE);
+    }
+
+    #[test]
+    fn test_hashed_credential_ids_save_account_space() {
+        let key = TestPasskey::new(1).public_key();
+        let with_registry = |entry: fn([u8; 64], Vec<u8>, String, i64) -> PasskeyEntry| {
+            let mut account = AttestaAccount::new(Pubkey::new_unique(), key, b"phone".to_vec(), vec![], 100);
+            let mut registry = account.passkey_registry_or_default().unwrap();
+            for seed in 2..5u8 {
+                registry.add_entry(entry(key, vec![seed; MAX_CREDENTIAL_ID_LEN], "Key".to_string(), 100)).unwrap();
+            }
+            account.set_passkey_registry(&registry).unwrap();
+            account.serialized_size()
+        };
+
+        let full = with_registry(PasskeyEntry::new);
+        let compact = with_registry(PasskeyEntry::compact);
+        assert_eq!(full - compact, 3 * (MAX_CREDENTIAL_ID_LEN - 32));
+    }
+
+    #[test]
+    fn test_full_passkey_registry_grows_in_bounded_steps() {
+        use anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+        use attesta_types::consts::MAX_PASSKEY_NAME_LEN;
+        use recovery::multi_passkey::{MAX_PASSKEYS, PASSKEY_SLOT_SPACE};
+
+        let key = TestPasskey::new(1).public_key();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), key, vec![2; MAX_CREDENTIAL_ID_LEN], vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.max_passkeys = u8::MAX;
+        let name = || "k".repeat(MAX_PASSKEY_NAME_LEN);
+
+        // The first add moves the primary into the registry as well; every
+        // later one only adds its own slot
+        let mut size = account.serialized_size();
+        for seed in 3..MAX_PASSKEYS + 2 {
+            registry.add_entry(PasskeyEntry::new(key, vec![seed; MAX_CREDENTIAL_ID_LEN], name(), 100)).unwrap();
+            account.set_passkey_registry(&registry).unwrap();
+            let grown = account.serialized_size() - size;
+            assert!(grown <= MAX_PERMITTED_DATA_INCREASE);
+            if seed > 3 {
+                assert!(grown <= PASSKEY_SLOT_SPACE, "{} > {}", grown, PASSKEY_SLOT_SPACE);
+            }
+            size = account.serialized_size();
+        }
+        assert!(registry.is_full());
+        assert!(registry.add_entry(PasskeyEntry::new(key, vec![99; 8], name(), 100)).is_err());
+    }
+
+    #[test]
+    fn test_schedule_space_fits_largest_transaction() {
+        let scheduled = ScheduledTransaction {
+            transaction_data: vec![1; MAX_TRANSACTION_DATA_LEN],
+            executable_after: 1,
+            executable_before: Some(2),
+            nonce: 3,
+            credential_id: vec![4; MAX_CREDENTIAL_ID_LEN],
+        };
+        let schedule = ScheduleData {
+            attesta_account: Pubkey::new_unique(),
+            scheduled: scheduled.to_bytes().unwrap(),
+            bump: 255,
+        };
+        assert_eq!(8 + schedule.try_to_vec().unwrap().len(), ScheduleData::space(MAX_TRANSACTION_DATA_LEN));
+    }
+
+    #[test]
+    fn test_policy_attestation_space_is_exact() {
+        let attestation = PolicyAttestation {
+            attesta_account: Pubkey::new_unique(),
+            policy_hash: [1; 32],
+            attested_at: 2,
+            expires_at: 3,
+            nonce: 4,
+        };
+        let record = PolicyAttestationData {
+            attesta_account: attestation.attesta_account,
+            attestation: attestation.to_bytes().unwrap(),
+            bump: 255,
+        };
+        assert_eq!(8 + record.try_to_vec().unwrap().len(), PolicyAttestationData::SPACE);
+    }
+
+    #[test]
+    fn test_program_version_round_trips() {
+        let version = ProgramVersion::from_return_data(&program_version().to_return_data()).unwrap();
+        assert_eq!(version.version, VERSION);
+        assert_eq!(version.hash(), program_version().hash());
+    }
+
+    #[test]
+    fn test_program_features_match_the_build() {
+        let features = ProgramFeatures::from_return_data(&program_features().to_return_data()).unwrap();
+        let version = program_version();
+        for (feature, built) in BUILT_FEATURES {
+            assert_eq!(features.is_enabled(feature), built, "{:?}", feature);
+            assert_eq!(version.features.contains(&format!("no-{}", feature.name())), !built, "{:?}", feature);
+        }
+
+        // A default build reports the version it did before features could be left out
+        if BUILT_FEATURES.iter().all(|(_, built)| *built) {
+            assert_eq!(version.hash(), ProgramVersion::new(VERSION, &[]).hash());
+        }
+    }
+
+    #[test]
+    fn test_escrow_space_fits_largest_backup() {
+        let mut escrow = empty_escrow();
+        escrow.backup = vec![0u8; MAX_ESCROW_BACKUP_SIZE];
+        let serialized = escrow.try_to_vec().unwrap();
+        assert!(8 + serialized.len() <= BackupEscrow::SPACE);
+    }
+
+    #[test]
+    fn test_check_policy() {
+        use recovery::{Amount, MintLimit, MintLimits};
+
+        const NOW: i64 = 1_700_000_000;
+        assert!(check_policy(&[], NOW).is_ok());
+        assert!(check_policy(&Policy::time_locked(1_800_000_000).to_bytes().unwrap(), NOW).is_ok());
+        assert_eq!(check_policy(&[9, 9], NOW), Err(AttestaError::InvalidPolicy.into()));
+        assert_eq!(
+            check_policy(&Policy::time_locked(NOW + MAX_FUTURE_SECONDS + 1).to_bytes().unwrap(), NOW),
+            Err(AttestaError::TimestampTooFarInFuture.into())
+        );
+
+        let limits = MintLimits {
+            allow_unlisted: false,
+            limits: vec![MintLimit { mint: Pubkey::new_unique(), max_amount: 1, decimals: 6 }; 2_000],
+        };
+        let expensive = Policy::spending_limit(Amount::ZERO).with_mint_limits(limits);
+        assert!(expensive.validate_config().is_ok());
+        assert_eq!(
+            check_policy(&expensive.to_bytes().unwrap(), NOW),
+            Err(AttestaError::PolicyTooExpensive.into())
+        );
+    }
+
+    #[test]
+    fn test_fit_idempotency_records_drops_oldest() {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [1u8; 64], vec![1; 16], vec![], 100);
+        for i in 0..8u8 {
+            account.record_idempotency_key([i; 16], [0u8; 32], i as u64 + 1);
+        }
+        let full_len = ACCOUNT_DATA_HEADER_LEN + account.to_bytes().unwrap().len();
+
+        // Room for everything: nothing is dropped
+        fit_idempotency_records(&mut account, full_len);
+        assert_eq!(account.idempotency_records.len(), 8);
+
+        // Two records short: the two oldest go
+        let record_size = smart_account::idempotency::IDEMPOTENCY_RECORD_SIZE;
+        fit_idempotency_records(&mut account, full_len - 2 * record_size);
+        assert_eq!(account.idempotency_records.len(), 6);
+        assert!(account.find_idempotency_record(&[1u8; 16]).is_none());
+        assert!(account.find_idempotency_record(&[2u8; 16]).is_some());
+    }
 }
@@ -0,0 +1,192 @@
+//! Localnet tests for policy attestations
+//!
+//! These load the compiled program, so build it first with `anchor build`.
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use attesta::{AttestaAccountData, AttestaError, PolicyAttestationData};
+use core_crypto::{compute_challenge, test_utils::TestPasskey};
+use recovery::Policy;
+use smart_account::{
+    action_message_hash, attestation_payload, check_attestation, registration_challenge, AttestaAccount, AttestationError,
+    PolicyAttestation, MAX_ATTESTATION_LIFETIME, POLICY_ATTEST_ACTION,
+};
+use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    clock::Clock,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+/// The cluster time each test starts at
+const START: i64 = 1_800_000_000;
+
+/// A 30-day time lock, the policy being attested
+const LOCK_UNTIL: i64 = START + 30 * 24 * 60 * 60;
+
+struct Env {
+    context: ProgramTestContext,
+    owner: Keypair,
+    passkey: TestPasskey,
+    nonce: u64,
+    attesta_account: Pubkey,
+}
+
+async fn send_as_owner(env: &mut Env, instructions: &[Instruction]) -> Result<(), BanksClientError> {
+    let blockhash = env.context.banks_client.get_latest_blockhash().await?;
+    let transaction =
+        Transaction::new_signed_with_payer(instructions, Some(&env.owner.pubkey()), &[&env.owner], blockhash);
+    env.context.banks_client.process_transaction(transaction).await
+}
+
+/// The Attesta error code a failed instruction returned
+fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+/// Moves to the next slot with the cluster clock at `unix_timestamp`
+async fn set_time(env: &mut Env, unix_timestamp: i64) {
+    let clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    env.context.warp_to_slot(clock.slot + 1).unwrap();
+    let mut clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    env.context.set_sysvar(&clock);
+}
+
+async fn load_account(env: &mut Env) -> AttestaAccount {
+    let account = env.context.banks_client.get_account(env.attesta_account).await.unwrap().unwrap();
+    let wrapper = AttestaAccountData::try_deserialize(&mut account.data.as_slice()).unwrap();
+    AttestaAccount::from_bytes(&wrapper.data).unwrap()
+}
+
+async fn load_attestation(env: &mut Env, address: Pubkey) -> PolicyAttestation {
+    let account = env.context.banks_client.get_account(address).await.unwrap().unwrap();
+    let wrapper = PolicyAttestationData::try_deserialize(&mut account.data.as_slice()).unwrap();
+    PolicyAttestation::from_bytes(&wrapper.attestation).unwrap()
+}
+
+/// Creates an Attesta account locked until `LOCK_UNTIL`
+async fn setup() -> Env {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    let context = program_test.start_with_context().await;
+
+    let owner = context.payer.insecure_clone();
+    let (attesta_account, _) = Pubkey::find_program_address(&[b"attesta", owner.pubkey().as_ref()], &attesta::ID);
+    let mut env = Env { context, owner, passkey: TestPasskey::new(1), nonce: 0, attesta_account };
+    set_time(&mut env, START).await;
+
+    let challenge = registration_challenge(
+        &env.owner.pubkey(),
+        &env.attesta_account,
+        &env.passkey.public_key(),
+        &env.passkey.credential_id(),
+    );
+    let registration_sig = env.passkey.sign_create(&challenge);
+    let initialize = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::Initialize {
+            attesta_account: env.attesta_account,
+            owner: env.owner.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::Initialize {
+            passkey_public_key: env.passkey.public_key(),
+            credential_id: env.passkey.credential_id(),
+            policy: Policy::time_locked(LOCK_UNTIL).to_bytes().unwrap(),
+            privacy_mode: false,
+            registration_sig: registration_sig.to_bytes(),
+            aaguid_allowlist: vec![],
+        }
+        .data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send_as_owner(&mut env, &[budget, initialize]).await.unwrap();
+    env
+}
+
+fn attestation_address(env: &Env, nonce: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"policy_attestation", env.attesta_account.as_ref(), &nonce.to_le_bytes()], &attesta::ID)
+        .0
+}
+
+/// Attests the account's current policy until `expires_at`, returning the attestation's address
+async fn attest(env: &mut Env, expires_at: i64) -> Result<Pubkey, BanksClientError> {
+    let policy_hash = load_account(env).await.policy_hash;
+    let nonce = env.nonce + 1;
+    let payload = attestation_payload(&policy_hash, expires_at);
+    let challenge = compute_challenge(&env.owner.pubkey(), nonce, &action_message_hash(POLICY_ATTEST_ACTION, &payload));
+    let webauthn_sig = env.passkey.sign(&challenge);
+
+    let attestation = attestation_address(env, nonce);
+    let instruction = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::AttestPolicy {
+            attesta_account: env.attesta_account,
+            attestation,
+            owner: env.owner.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::AttestPolicy { webauthn_sig: webauthn_sig.to_bytes(), nonce, expires_at }.data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send_as_owner(env, &[budget, instruction]).await?;
+    env.nonce = nonce;
+    Ok(attestation)
+}
+
+#[tokio::test]
+async fn test_attestation_holds_until_the_policy_changes() {
+    let mut env = setup().await;
+    let expires_at = START + 7 * 24 * 60 * 60;
+    let address = attest(&mut env, expires_at).await.unwrap();
+
+    let expected = Policy::time_locked(LOCK_UNTIL).canonical_hash();
+    let attestation = load_attestation(&mut env, address).await;
+    assert_eq!(attestation.policy_hash, expected);
+    assert_eq!(attestation.attested_at, START);
+    assert_eq!(attestation.expires_at, expires_at);
+    assert_eq!(address, attestation_address(&env, attestation.nonce));
+    let account = load_account(&mut env).await;
+    assert_eq!(check_attestation(&attestation, &env.attesta_account, &account, &expected, START + 1), Ok(()));
+    assert_eq!(
+        check_attestation(&attestation, &env.attesta_account, &account, &expected, expires_at),
+        Err(AttestationError::Expired { expires_at })
+    );
+
+    let update_policy = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::UpdatePolicy { attesta_account: env.attesta_account, owner: env.owner.pubkey() }
+            .to_account_metas(None),
+        data: attesta::instruction::UpdatePolicy {
+            new_policy: Policy::time_locked(START + 1).to_bytes().unwrap(),
+            expected_version: account.state_version,
+        }
+        .data(),
+    };
+    send_as_owner(&mut env, &[update_policy]).await.unwrap();
+
+    let account = load_account(&mut env).await;
+    assert_eq!(
+        check_attestation(&attestation, &env.attesta_account, &account, &expected, START + 1),
+        Err(AttestationError::PolicyChanged)
+    );
+}
+
+#[tokio::test]
+async fn test_attestation_expiry_is_bounded() {
+    let mut env = setup().await;
+
+    for expires_at in [START, START + MAX_ATTESTATION_LIFETIME + 1] {
+        let error = attest(&mut env, expires_at).await.unwrap_err();
+        assert_eq!(error_code(error), Some(AttestaError::InvalidAttestationExpiry.into()));
+    }
+    assert_eq!(load_account(&mut env).await.nonce, 0);
+}
@@ -0,0 +1,268 @@
+//! Measures what policies cost `execute`, to calibrate the estimates
+//!
+//! Each policy is set on the account, then the same signed transfer is
+//! simulated against it. The cost of a policy is what the transfer takes
+//! beyond the same transfer with no policy, and `Policy::estimated_compute_units`
+//! must stay within 25% of it. If this fails after a change to policy
+//! evaluation, recalibrate `recovery::policies::compute_units` from the
+//! printed measurements.
+//!
+//! The same signed `execute` submitted again after it ran must be answered
+//! before the signature is verified, and stay under `DUPLICATE_EXECUTE_UNITS`.
+//!
+//! These load the compiled program, so build it first with `anchor build`.
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use core_crypto::{compute_challenge, test_utils::TestPasskey};
+use recovery::policies::MIN_POLICY_TIMESTAMP;
+use recovery::{Amount, MintLimit, MintLimits, Policy};
+use smart_account::{registration_challenge, TokenTransfer, TransactionRequest};
+use solana_program_test::{BanksClient, ProgramTest};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+const DECIMALS: u8 = 6;
+const AMOUNT: u64 = 10u64.pow(DECIMALS as u32);
+
+/// How far an estimate may be from the measured cost, as a fraction of it
+const TOLERANCE: f64 = 0.25;
+
+/// Most an `execute` that already ran may cost when it's submitted again
+const DUPLICATE_EXECUTE_UNITS: u64 = 25_000;
+
+struct Env {
+    banks_client: BanksClient,
+    payer: Keypair,
+    attesta_account: Pubkey,
+    mint: Pubkey,
+    source_ata: Pubkey,
+    recipient_ata: Pubkey,
+}
+
+async fn send(env: &mut Env, instructions: &[Instruction], extra_signers: &[&Keypair]) {
+    let mut signers: Vec<&Keypair> = vec![&env.payer];
+    signers.extend_from_slice(extra_signers);
+
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction =
+        Transaction::new_signed_with_payer(instructions, Some(&env.payer.pubkey()), &signers, blockhash);
+    env.banks_client.process_transaction(transaction).await.unwrap();
+}
+
+/// Creates a funded Attesta account with no policy
+async fn setup(passkey: &mut TestPasskey) -> Env {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    let (banks_client, payer, _) = program_test.start().await;
+
+    let mint = Keypair::new();
+    let (attesta_account, _) =
+        Pubkey::find_program_address(&[b"attesta", payer.pubkey().as_ref()], &attesta::ID);
+    let recipient = Pubkey::new_unique();
+
+    let mut env = Env {
+        banks_client,
+        payer,
+        attesta_account,
+        mint: mint.pubkey(),
+        source_ata: get_associated_token_address(&attesta_account, &mint.pubkey()),
+        recipient_ata: get_associated_token_address(&recipient, &mint.pubkey()),
+    };
+
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let payer = env.payer.pubkey();
+    let challenge = registration_challenge(&payer, &env.attesta_account, &passkey.public_key(), &passkey.credential_id());
+    let registration_sig = passkey.sign_create(&challenge);
+    let instructions = [
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        system_instruction::create_account(
+            &payer,
+            &env.mint,
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(&spl_token::id(), &env.mint, &payer, None, DECIMALS).unwrap(),
+        Instruction {
+            program_id: attesta::ID,
+            accounts: attesta::accounts::Initialize {
+                attesta_account: env.attesta_account,
+                owner: payer,
+                system_program: solana_sdk::system_program::id(),
+            }
+            .to_account_metas(None),
+            data: attesta::instruction::Initialize {
+                passkey_public_key: passkey.public_key(),
+                credential_id: passkey.credential_id(),
+                policy: vec![],
+                privacy_mode: false,
+                registration_sig: registration_sig.to_bytes(),
+            aaguid_allowlist: vec![],
+            }
+            .data(),
+        },
+        create_associated_token_account(&payer, &env.attesta_account, &env.mint, &spl_token::id()),
+        create_associated_token_account(&payer, &recipient, &env.mint, &spl_token::id()),
+        spl_token::instruction::mint_to(&spl_token::id(), &env.mint, &env.source_ata, &payer, &[], AMOUNT).unwrap(),
+    ];
+    send(&mut env, &instructions, &[&mint]).await;
+
+    env
+}
+
+/// A signed `execute` moving `AMOUNT` to the recipient with nonce 1
+fn execute_transfer(env: &Env, passkey: &mut TestPasskey, idempotency_key: Option<[u8; 16]>) -> Vec<Instruction> {
+    let request = TransactionRequest::from_token_transfer(TokenTransfer {
+        mint: env.mint,
+        amount: AMOUNT,
+        decimals: DECIMALS,
+        destination_ata: env.recipient_ata,
+    });
+    let message_hash = request.message_hash();
+    let webauthn_sig = passkey.sign(&compute_challenge(&env.payer.pubkey(), 1, &message_hash));
+
+    let mut accounts = attesta::accounts::Execute {
+        attesta_account: env.attesta_account,
+        authority: env.payer.pubkey(),
+        parent_account: None,
+        proof_log: None,
+        memo_program: None,
+        proposal: None,
+        system_program: None,
+        announcement: None,
+    }
+    .to_account_metas(None);
+    accounts.extend([
+        AccountMeta::new(env.source_ata, false),
+        AccountMeta::new_readonly(env.mint, false),
+        AccountMeta::new(env.recipient_ata, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts,
+            data: attesta::instruction::Execute {
+                webauthn_sig: webauthn_sig.to_bytes(),
+                nonce: 1,
+                message_hash,
+                transaction_data: request.transaction_data,
+                idempotency_key,
+                memo: vec![],
+                emit_memo: false,
+            }
+            .data(),
+        },
+    ]
+}
+
+/// Sets the account's policy, then simulates `execute` and returns the compute units it used
+async fn units_with_policy(env: &mut Env, policy: Option<&Policy>, execute: &[Instruction]) -> u64 {
+    let new_policy = policy.map(|policy| policy.to_bytes().unwrap()).unwrap_or_default();
+    let instruction = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::UpdatePolicy {
+            attesta_account: env.attesta_account,
+            owner: env.payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::UpdatePolicy { new_policy }.data(),
+    };
+    send(env, &[instruction], &[]).await;
+
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction =
+        Transaction::new_signed_with_payer(execute, Some(&env.payer.pubkey()), &[&env.payer], blockhash);
+    let simulation = env.banks_client.simulate_transaction(transaction).await.unwrap();
+    assert!(matches!(simulation.result, Some(Ok(()))), "{:?} failed: {:?}", policy, simulation.result);
+    simulation.simulation_details.unwrap().units_consumed
+}
+
+/// `count` mint limits, with `mint` last so every one is looked at
+fn mint_limits(mint: Pubkey, count: usize) -> MintLimits {
+    let mut limits: Vec<MintLimit> = (1..count)
+        .map(|_| MintLimit { mint: Pubkey::new_unique(), max_amount: AMOUNT, decimals: DECIMALS })
+        .collect();
+    limits.push(MintLimit { mint, max_amount: AMOUNT, decimals: DECIMALS });
+    MintLimits { allow_unlisted: false, limits }
+}
+
+/// `count` destinations, with `destination` last so every one is compared
+fn destinations(destination: Pubkey, count: usize) -> Vec<Pubkey> {
+    let mut destinations: Vec<Pubkey> = (1..count).map(|_| Pubkey::new_unique()).collect();
+    destinations.push(destination);
+    destinations
+}
+
+#[tokio::test]
+async fn test_policy_estimates_match_measured_cost() {
+    let mut passkey = TestPasskey::new(1);
+    let mut env = setup(&mut passkey).await;
+    // The same signed transfer throughout, so only the policy changes
+    let execute = execute_transfer(&env, &mut passkey, None);
+    let baseline = units_with_policy(&mut env, None, &execute).await;
+
+    let representative = [
+        Policy::spending_limit(Amount::ZERO).with_mint_limits(mint_limits(env.mint, 1)),
+        Policy::spending_limit(Amount::ZERO).with_mint_limits(mint_limits(env.mint, 8)),
+        Policy::daily_limit(Amount::ZERO, MIN_POLICY_TIMESTAMP).with_mint_limits(mint_limits(env.mint, 4)),
+        Policy::time_locked(MIN_POLICY_TIMESTAMP),
+        Policy::destination_allowlist(destinations(env.recipient_ata, 32)),
+        Policy::multi_sig((0..16).map(|_| Pubkey::new_unique()).collect()),
+        Policy::composite(vec![
+            Policy::time_locked(MIN_POLICY_TIMESTAMP),
+            Policy::destination_allowlist(destinations(env.recipient_ata, 16)),
+            Policy::spending_limit(Amount::ZERO).with_mint_limits(mint_limits(env.mint, 4)),
+        ]),
+    ];
+
+    let mut failures = Vec::new();
+    for policy in &representative {
+        let measured = units_with_policy(&mut env, Some(policy), &execute).await - baseline;
+        let estimated = policy.estimated_compute_units() as u64;
+        println!("{:?}: measured {}, estimated {}", policy.policy_type, measured, estimated);
+
+        if (estimated as f64 - measured as f64).abs() > TOLERANCE * measured as f64 {
+            failures.push((policy.policy_type, measured, estimated));
+        }
+    }
+    assert!(failures.is_empty(), "estimates off by more than 25%: {:?}", failures);
+}
+
+#[tokio::test]
+async fn test_duplicate_execute_is_cheap() {
+    let mut passkey = TestPasskey::new(1);
+    let mut env = setup(&mut passkey).await;
+    // Two relayers holding the same envelope
+    let execute = execute_transfer(&env, &mut passkey, Some([9; 16]));
+    send(&mut env, &execute, &[]).await;
+    let first = env.banks_client.get_account(env.recipient_ata).await.unwrap().unwrap();
+
+    // The compute price only keeps the runtime from dropping the copy as a duplicate transaction
+    let mut copy = vec![ComputeBudgetInstruction::set_compute_unit_price(1)];
+    copy.extend(execute);
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&copy, Some(&env.payer.pubkey()), &[&env.payer], blockhash);
+    let simulation = env.banks_client.simulate_transaction(transaction.clone()).await.unwrap();
+    assert!(matches!(simulation.result, Some(Ok(()))), "{:?}", simulation.result);
+    let units = simulation.simulation_details.unwrap().units_consumed;
+    println!("duplicate execute: {} units", units);
+    assert!(units <= DUPLICATE_EXECUTE_UNITS, "{} units", units);
+
+    // It lands without moving anything again
+    env.banks_client.process_transaction(transaction).await.unwrap();
+    let second = env.banks_client.get_account(env.recipient_ata).await.unwrap().unwrap();
+    assert_eq!(second.data, first.data);
+}
@@ -0,0 +1,953 @@
+//! Localnet test of an account's whole lifecycle, one instruction at a time
+//!
+//! Every step goes through the real program (account layout, space,
+//! discriminators) and checks both the outcome and the stored account.
+//! These load the compiled program, so build it first with `anchor build`.
+
+use anchor_lang::{AccountDeserialize, AccountSerialize, Discriminator, InstructionData, ToAccountMetas};
+use attesta::{AttestaAccountData, AttestaError, ProposalData};
+use core_crypto::{compute_challenge, test_utils::TestPasskey, WebAuthnSignature};
+use recovery::multi_passkey::{MAX_PASSKEYS, PASSKEY_ADD_ACTION};
+use recovery::{credential_id_hash, Amount, MintLimit, MintLimits, Policy};
+use smart_account::storage::{encode_attesta_account, ATTESTA_ACCOUNT_DATA_DISCRIMINATOR};
+use smart_account::{
+    action_message_hash, auth_mode_payload, cancel_proposal_payload, claim_ticket_payload, registration_challenge,
+    AccountSettings, AttestaAccount, AuthMode, CancelReason, ClaimTicket, DenyReason, ExecuteOutcome, PendingTransaction, ProgramFeature, ProgramVersion, ProtocolConstants, ProtocolLimits, TokenTransfer,
+    TransactionRequest, AUTH_MODE_ACTION, CLAIM_TICKET_ACTION, EXECUTOR_ADD_ACTION, PROPOSAL_CANCEL_ACTION, SETTINGS_UPDATE_ACTION, UPGRADE_ACKNOWLEDGE_ACTION,
+};
+use solana_program_test::{BanksClient, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+const DECIMALS: u8 = 6;
+const LIMIT: u64 = 100 * 10u64.pow(DECIMALS as u32);
+
+struct Env {
+    banks_client: BanksClient,
+    payer: Keypair,
+    attesta_account: Pubkey,
+    mint: Pubkey,
+    source_ata: Pubkey,
+    recipient_ata: Pubkey,
+}
+
+async fn send(env: &mut Env, instructions: &[Instruction], extra_signers: &[&Keypair]) -> Result<(), BanksClientError> {
+    let mut signers: Vec<&Keypair> = vec![&env.payer];
+    signers.extend_from_slice(extra_signers);
+
+    let blockhash = env.banks_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&env.payer.pubkey()),
+        &signers,
+        blockhash,
+    );
+    env.banks_client.process_transaction(transaction).await
+}
+
+/// The `ExecuteOutcome` the program returns when `instructions` are simulated
+async fn simulated_outcome(env: &mut Env, instructions: &[Instruction]) -> ExecuteOutcome {
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&env.payer.pubkey()),
+        &[&env.payer],
+        blockhash,
+    );
+    let simulation = env.banks_client.simulate_transaction(transaction).await.unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    assert_eq!(return_data.program_id, attesta::ID);
+    ExecuteOutcome::from_return_data(&return_data.data).unwrap()
+}
+
+/// The Attesta error code a failed instruction returned
+fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+/// Loads the Attesta account, checking the storage helpers agree with the program on its layout
+async fn load_account(env: &mut Env) -> AttestaAccount {
+    let account = env.banks_client.get_account(env.attesta_account).await.unwrap().unwrap();
+    let wrapper = AttestaAccountData::try_deserialize(&mut account.data.as_slice()).unwrap();
+    let loaded = AttestaAccount::from_bytes(&wrapper.data).unwrap();
+
+    let encoded = encode_attesta_account(&loaded).unwrap();
+    assert_eq!(encoded[..], account.data[..encoded.len()]);
+    assert_eq!(smart_account::storage::decode_attesta_account(&account.data).unwrap(), loaded);
+    loaded
+}
+
+async fn token_balance(env: &mut Env, token_account: Pubkey) -> u64 {
+    let account = env.banks_client.get_account(token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
+}
+
+/// Starts the program with a mint, a funded token account for the Attesta
+/// PDA and an empty one for the recipient (the Attesta account itself isn't
+/// created yet)
+async fn setup() -> Env {
+    let (banks_client, payer, _) = program_test().start().await;
+    setup_with(banks_client, payer).await
+}
+
+/// `setup`, keeping the test context to write accounts no instruction creates yet
+async fn setup_context() -> (Env, ProgramTestContext) {
+    let context = program_test().start_with_context().await;
+    let env = setup_with(context.banks_client.clone(), context.payer.insecure_clone()).await;
+    (env, context)
+}
+
+fn program_test() -> ProgramTest {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    program_test
+}
+
+async fn setup_with(banks_client: BanksClient, payer: Keypair) -> Env {
+    let mint = Keypair::new();
+    let (attesta_account, _) =
+        Pubkey::find_program_address(&[b"attesta", payer.pubkey().as_ref()], &attesta::ID);
+    let recipient = Pubkey::new_unique();
+
+    let mut env = Env {
+        banks_client,
+        payer,
+        attesta_account,
+        mint: mint.pubkey(),
+        source_ata: get_associated_token_address(&attesta_account, &mint.pubkey()),
+        recipient_ata: get_associated_token_address(&recipient, &mint.pubkey()),
+    };
+
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let payer = env.payer.pubkey();
+    let instructions = [
+        system_instruction::create_account(
+            &payer,
+            &env.mint,
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(&spl_token::id(), &env.mint, &payer, None, DECIMALS).unwrap(),
+        create_associated_token_account(&payer, &env.attesta_account, &env.mint, &spl_token::id()),
+        create_associated_token_account(&payer, &recipient, &env.mint, &spl_token::id()),
+        spl_token::instruction::mint_to(&spl_token::id(), &env.mint, &env.source_ata, &payer, &[], 5 * LIMIT).unwrap(),
+    ];
+    send(&mut env, &instructions, &[&mint]).await.unwrap();
+
+    env
+}
+
+/// `initialize` with the passkey's signature over its registration challenge
+fn initialize(env: &Env, passkey: &mut TestPasskey) -> Vec<Instruction> {
+    let challenge = registration_challenge(
+        &env.payer.pubkey(),
+        &env.attesta_account,
+        &passkey.public_key(),
+        &passkey.credential_id(),
+    );
+    let registration_sig = passkey.sign_create(&challenge);
+    initialize_signed(env, passkey, &registration_sig)
+}
+
+fn initialize_signed(env: &Env, passkey: &TestPasskey, registration_sig: &WebAuthnSignature) -> Vec<Instruction> {
+    let initialize = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::Initialize {
+            attesta_account: env.attesta_account,
+            owner: env.payer.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::Initialize {
+            passkey_public_key: passkey.public_key(),
+            credential_id: passkey.credential_id(),
+            policy: vec![],
+            privacy_mode: false,
+            registration_sig: registration_sig.to_bytes(),
+            aaguid_allowlist: vec![],
+        }
+        .data(),
+    };
+    vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000), initialize]
+}
+
+/// `initialize_with_index` at `account_index`, with the passkey's signature over its registration challenge
+fn initialize_with_index(env: &Env, passkey: &mut TestPasskey, account_index: u16) -> Vec<Instruction> {
+    let (attesta_account, _) = smart_account::derive_attesta_account_by_index(&attesta::ID, &env.payer.pubkey(), account_index);
+    let challenge =
+        registration_challenge(&env.payer.pubkey(), &attesta_account, &passkey.public_key(), &passkey.credential_id());
+    let initialize = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::InitializeWithIndex {
+            attesta_account,
+            owner: env.payer.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::InitializeWithIndex {
+            account_index,
+            passkey_public_key: passkey.public_key(),
+            credential_id: passkey.credential_id(),
+            policy: vec![],
+            privacy_mode: false,
+            registration_sig: passkey.sign_create(&challenge).to_bytes(),
+            aaguid_allowlist: vec![],
+        }
+        .data(),
+    };
+    vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000), initialize]
+}
+
+/// A passkey-signed `execute` moving `amount` tokens to the recipient
+fn execute_transfer(env: &Env, passkey: &mut TestPasskey, nonce: u64, amount: u64) -> Vec<Instruction> {
+    execute_transfer_submitted_by(env, passkey, nonce, amount, env.payer.pubkey(), true)
+}
+
+/// `execute_transfer`, with `authority` (signing or not) as the submitter
+fn execute_transfer_submitted_by(
+    env: &Env,
+    passkey: &mut TestPasskey,
+    nonce: u64,
+    amount: u64,
+    authority: Pubkey,
+    authority_signs: bool,
+) -> Vec<Instruction> {
+    let request = TransactionRequest::from_token_transfer(TokenTransfer {
+        mint: env.mint,
+        amount,
+        decimals: DECIMALS,
+        destination_ata: env.recipient_ata,
+    });
+    let message_hash = request.message_hash();
+    let challenge = compute_challenge(&env.payer.pubkey(), nonce, &message_hash);
+    let webauthn_sig = passkey.sign(&challenge);
+
+    let mut accounts = attesta::accounts::Execute {
+        attesta_account: env.attesta_account,
+        authority,
+        parent_account: None,
+        proof_log: None,
+        memo_program: None,
+        proposal: None,
+        system_program: None,
+        announcement: None,
+    }
+    .to_account_metas(None);
+    accounts[1].is_signer = authority_signs;
+    accounts.extend([
+        AccountMeta::new(env.source_ata, false),
+        AccountMeta::new_readonly(env.mint, false),
+        AccountMeta::new(env.recipient_ata, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    vec![
+        // P-256 verification needs more than the default compute budget
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts,
+            data: attesta::instruction::Execute {
+                webauthn_sig: webauthn_sig.to_bytes(),
+                nonce,
+                message_hash,
+                transaction_data: request.transaction_data,
+                idempotency_key: None,
+                memo: vec![],
+                emit_memo: false,
+            }
+            .data(),
+        },
+    ]
+}
+
+fn update_policy(env: &Env, policy: &Policy, expected_version: u64) -> Instruction {
+    Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::UpdatePolicy {
+            attesta_account: env.attesta_account,
+            owner: env.payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::UpdatePolicy { new_policy: policy.to_bytes().unwrap(), expected_version }.data(),
+    }
+}
+
+/// An `add_passkey` for `new_passkey`, signed by `signer`, against an
+/// account at `expected_version`
+fn add_passkey(
+    env: &Env,
+    signer: &mut TestPasskey,
+    nonce: u64,
+    new_passkey: &TestPasskey,
+    expected_version: u64,
+) -> Vec<Instruction> {
+    let payload = [new_passkey.public_key().as_ref(), new_passkey.credential_id().as_slice()].concat();
+    let message_hash = action_message_hash(PASSKEY_ADD_ACTION, &payload);
+    let webauthn_sig = signer.sign(&compute_challenge(&env.payer.pubkey(), nonce, &message_hash));
+
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts: attesta::accounts::ManagePasskeys {
+                attesta_account: env.attesta_account,
+                owner: env.payer.pubkey(),
+                system_program: solana_sdk::system_program::id(),
+            }
+            .to_account_metas(None),
+            data: attesta::instruction::AddPasskey {
+                webauthn_sig: webauthn_sig.to_bytes(),
+                nonce,
+                public_key: new_passkey.public_key(),
+                credential_id: new_passkey.credential_id(),
+                name: "Laptop".to_string(),
+                registration_sig: vec![],
+                expected_version,
+            }
+            .data(),
+        },
+    ]
+}
+
+fn manage_passkeys_accounts(env: &Env) -> Vec<AccountMeta> {
+    attesta::accounts::ManagePasskeys {
+        attesta_account: env.attesta_account,
+        owner: env.payer.pubkey(),
+        system_program: solana_sdk::system_program::id(),
+    }
+    .to_account_metas(None)
+}
+
+/// The version the deployed program reports, read by simulating `get_program_version`
+async fn program_version(env: &mut Env) -> ProgramVersion {
+    let instruction = Instruction {
+        program_id: attesta::ID,
+        accounts: vec![],
+        data: attesta::instruction::GetProgramVersion {}.data(),
+    };
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&env.payer.pubkey()), &[&env.payer], blockhash);
+    let simulation = env.banks_client.simulate_transaction(transaction).await.unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    ProgramVersion::from_return_data(&return_data.data).unwrap()
+}
+
+#[tokio::test]
+async fn test_protocol_constants_are_the_shared_ones() {
+    let mut env = setup().await;
+    let instruction = Instruction {
+        program_id: attesta::ID,
+        accounts: vec![],
+        data: attesta::instruction::GetProtocolConstants {}.data(),
+    };
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&env.payer.pubkey()), &[&env.payer], blockhash);
+    let simulation = env.banks_client.simulate_transaction(transaction).await.unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    let constants = ProtocolConstants::from_return_data(&return_data.data).unwrap();
+
+    // Built from the same declarations the crates use, not a copy of them
+    assert_eq!(constants, ProtocolConstants::new(program_version(&mut env).await, &ProgramFeature::ALL));
+    assert_eq!(constants.limits, ProtocolLimits::compiled());
+    assert_eq!(constants.limits.max_passkeys, MAX_PASSKEYS);
+}
+
+#[tokio::test]
+async fn test_account_lifecycle() {
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let mut laptop = TestPasskey::new(2);
+    let (source_ata, recipient_ata) = (env.source_ata, env.recipient_ata);
+
+    assert_eq!(AttestaAccountData::DISCRIMINATOR, ATTESTA_ACCOUNT_DATA_DISCRIMINATOR);
+
+    // A registration signed for another account can't be used to claim this one
+    let elsewhere = registration_challenge(
+        &env.payer.pubkey(),
+        &Pubkey::new_unique(),
+        &phone.public_key(),
+        &phone.credential_id(),
+    );
+    let registration_sig = phone.sign_create(&elsewhere);
+    let error = send(&mut env, &initialize_signed(&env, &phone, &registration_sig), &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::PasskeyNotProven.into()));
+
+    // Nor can an assertion over the right challenge: enrolling takes a create ceremony
+    let challenge = registration_challenge(&env.payer.pubkey(), &env.attesta_account, &phone.public_key(), &phone.credential_id());
+    let registration_sig = phone.sign(&challenge);
+    let error = send(&mut env, &initialize_signed(&env, &phone, &registration_sig), &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::PasskeyNotProven.into()));
+
+    // Initialize
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let account = load_account(&mut env).await;
+    assert_eq!(account.owner, env.payer.pubkey());
+    assert_eq!(account.passkey_public_key, phone.public_key());
+    assert_eq!(account.nonce, 0);
+
+    // A passkey-authorized transfer (no policy yet)
+    let first_transfer = execute_transfer(&env, &mut phone, 1, LIMIT + 1);
+    assert_eq!(
+        simulated_outcome(&mut env, &first_transfer).await,
+        ExecuteOutcome {
+            new_nonce: 1,
+            policy_result: ExecuteOutcome::ALLOWED,
+            amount_charged: LIMIT + 1,
+            deny_reason: None,
+            memo_hash: None,
+            credential_id_hash: Some(credential_id_hash(&phone.credential_id())),
+        }
+    );
+    send(&mut env, &first_transfer, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.nonce, 1);
+    assert_eq!(token_balance(&mut env, recipient_ata).await, LIMIT + 1);
+
+    // Switch to a spending limit
+    let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+        allow_unlisted: false,
+        limits: vec![MintLimit { mint: env.mint, max_amount: LIMIT, decimals: DECIMALS }],
+    });
+    let instruction = update_policy(&env, &policy, 0);
+    send(&mut env, &[instruction], &[]).await.unwrap();
+    let account = load_account(&mut env).await;
+    assert_eq!(account.policy(), policy.to_bytes().unwrap());
+    assert_eq!(account.policy_hash, policy.canonical_hash());
+    assert_eq!(account.policy_hash, account.compute_policy_hash());
+
+    // The same transfer is now over the limit; the nonce isn't used up
+    let instructions = execute_transfer(&env, &mut phone, 2, LIMIT + 1);
+    assert_eq!(
+        simulated_outcome(&mut env, &instructions).await,
+        ExecuteOutcome {
+            new_nonce: 1,
+            policy_result: ExecuteOutcome::DENIED,
+            amount_charged: 0,
+            deny_reason: Some(DenyReason::Policy.code()),
+            memo_hash: None,
+            credential_id_hash: Some(credential_id_hash(&phone.credential_id())),
+        }
+    );
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::PolicyDenied.into()));
+    assert_eq!(load_account(&mut env).await.nonce, 1);
+    assert_eq!(token_balance(&mut env, recipient_ata).await, LIMIT + 1);
+
+    // Register a second passkey
+    let instructions = add_passkey(&env, &mut phone, 2, &laptop, 1);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let account = load_account(&mut env).await;
+    assert_eq!(account.nonce, 2);
+    let registry = account.passkey_registry().unwrap().unwrap();
+    assert!(registry.find_passkey(&phone.credential_id()).is_some());
+    assert!(registry.find_passkey(&laptop.credential_id()).is_some());
+
+    // Execute with the second passkey
+    let instructions = execute_transfer(&env, &mut laptop, 3, LIMIT);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.nonce, 3);
+    assert_eq!(token_balance(&mut env, recipient_ata).await, 2 * LIMIT + 1);
+    assert_eq!(token_balance(&mut env, source_ata).await, 3 * LIMIT - 1);
+
+    // Replaying the first proof fails. A fee change keeps the runtime from
+    // dropping it as a duplicate before the program sees it.
+    let mut replay = first_transfer;
+    replay.insert(0, ComputeBudgetInstruction::set_compute_unit_price(1));
+    let error = send(&mut env, &replay, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ExecutionFailed.into()));
+    assert_eq!(load_account(&mut env).await.nonce, 3);
+    assert_eq!(token_balance(&mut env, recipient_ata).await, 2 * LIMIT + 1);
+}
+
+#[tokio::test]
+async fn test_pinned_program_version() {
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    // Pin a version other than the one deployed, as if the program was upgraded since
+    let settings = AccountSettings { pinned_program_version: Some([9; 32]), ..AccountSettings::default() };
+    let message_hash = action_message_hash(SETTINGS_UPDATE_ACTION, &settings.to_bytes());
+    let webauthn_sig = phone.sign(&compute_challenge(&env.payer.pubkey(), 1, &message_hash));
+    let update_settings = Instruction {
+        program_id: attesta::ID,
+        accounts: manage_passkeys_accounts(&env),
+        data: attesta::instruction::UpdateSettings {
+            webauthn_sig: webauthn_sig.to_bytes(),
+            nonce: 1,
+            reject_zero_amount: false,
+            reject_self_transfer: false,
+            max_transaction_data_len: 0,
+            lockout_threshold: 0,
+            aaguid_allowlist: vec![],
+            pinned_program_version: settings.pinned_program_version,
+            webauthn_profile: 0,
+            rp_id_hash: None,
+            origin_hash: None,
+            log_allowed_sample_rate: 0,
+            recovery_allowance: 0,
+            expected_version: 0,
+        }
+        .data(),
+    };
+    send(&mut env, &[ComputeBudgetInstruction::set_compute_unit_limit(1_400_000), update_settings], &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.settings, settings);
+
+    // Execution is refused, and the nonce isn't used up
+    let instructions = execute_transfer(&env, &mut phone, 2, LIMIT);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::UpgradeNotAcknowledged.into()));
+    assert_eq!(load_account(&mut env).await.nonce, 1);
+
+    // The owner acknowledges the deployed version
+    let deployed = program_version(&mut env).await;
+    assert_eq!(deployed.version, attesta::VERSION);
+    let message_hash = action_message_hash(UPGRADE_ACKNOWLEDGE_ACTION, &deployed.hash());
+    let webauthn_sig = phone.sign(&compute_challenge(&env.payer.pubkey(), 2, &message_hash));
+    let acknowledge = Instruction {
+        program_id: attesta::ID,
+        accounts: manage_passkeys_accounts(&env),
+        data: attesta::instruction::AcknowledgeUpgrade { webauthn_sig: webauthn_sig.to_bytes(), nonce: 2 }.data(),
+    };
+    send(&mut env, &[ComputeBudgetInstruction::set_compute_unit_limit(1_400_000), acknowledge], &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.settings.pinned_program_version, Some(deployed.hash()));
+
+    // Which lets the same transfer through
+    let instructions = execute_transfer(&env, &mut phone, 3, LIMIT);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.nonce, 3);
+    assert_eq!(token_balance(&mut env, env.recipient_ata).await, LIMIT);
+}
+
+/// A `set_auth_mode` signed by `passkey`
+fn set_auth_mode(env: &Env, passkey: &mut TestPasskey, nonce: u64, mode: AuthMode, lock: bool) -> Vec<Instruction> {
+    let message_hash = action_message_hash(AUTH_MODE_ACTION, &auth_mode_payload(mode, lock));
+    let webauthn_sig = passkey.sign(&compute_challenge(&env.payer.pubkey(), nonce, &message_hash));
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts: manage_passkeys_accounts(env),
+            data: attesta::instruction::SetAuthMode { webauthn_sig: webauthn_sig.to_bytes(), nonce, mode: mode as u8, lock }
+                .data(),
+        },
+    ]
+}
+
+/// An `execute_as_owner` transfer, signed by the owner's wallet (the payer)
+fn execute_transfer_as_owner(env: &Env, nonce: u64, amount: u64) -> Instruction {
+    let transfer = TokenTransfer { mint: env.mint, amount, decimals: DECIMALS, destination_ata: env.recipient_ata };
+    let mut accounts = attesta::accounts::ExecuteAsOwner {
+        attesta_account: env.attesta_account,
+        owner: env.payer.pubkey(),
+        parent_account: None,
+    }
+    .to_account_metas(None);
+    accounts.extend([
+        AccountMeta::new(env.source_ata, false),
+        AccountMeta::new_readonly(env.mint, false),
+        AccountMeta::new(env.recipient_ata, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    Instruction {
+        program_id: attesta::ID,
+        accounts,
+        data: attesta::instruction::ExecuteAsOwner { nonce, transaction_data: transfer.to_transaction_data() }.data(),
+    }
+}
+
+#[tokio::test]
+async fn test_migration_from_owner_signing() {
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    // Passkey-only by default: the wallet alone can't move funds
+    let error = send(&mut env, &[execute_transfer_as_owner(&env, 1, 1)], &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::OwnerNotAllowed.into()));
+
+    // During the migration either signer works, on one nonce sequence
+    let instructions = set_auth_mode(&env, &mut phone, 1, AuthMode::OwnerOrPasskey, false);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    send(&mut env, &[execute_transfer_as_owner(&env, 2, 1)], &[]).await.unwrap();
+    let instructions = execute_transfer(&env, &mut phone, 3, 1);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.nonce, 3);
+    assert_eq!(token_balance(&mut env, env.recipient_ata).await, 2);
+
+    // Owner-only refuses the passkey without using up the nonce
+    let instructions = set_auth_mode(&env, &mut phone, 4, AuthMode::OwnerOnly, false);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let instructions = execute_transfer(&env, &mut phone, 5, 1);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::PasskeyNotAllowed.into()));
+    send(&mut env, &[execute_transfer_as_owner(&env, 5, 1)], &[]).await.unwrap();
+
+    // Locked to passkeys, the wallet stays out
+    let instructions = set_auth_mode(&env, &mut phone, 6, AuthMode::PasskeyOnly, true);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let account = load_account(&mut env).await;
+    assert_eq!(account.settings.auth_mode, AuthMode::PasskeyOnly);
+    assert!(account.settings.auth_mode_locked);
+    let instructions = set_auth_mode(&env, &mut phone, 7, AuthMode::OwnerOrPasskey, false);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::AuthModeLocked.into()));
+    let error = send(&mut env, &[execute_transfer_as_owner(&env, 7, 1)], &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::OwnerNotAllowed.into()));
+    assert_eq!(token_balance(&mut env, env.recipient_ata).await, 3);
+}
+
+/// An `add_executor` for `executor`, signed by `passkey`
+fn add_executor(env: &Env, passkey: &mut TestPasskey, nonce: u64, executor: Pubkey) -> Vec<Instruction> {
+    let message_hash = action_message_hash(EXECUTOR_ADD_ACTION, executor.as_ref());
+    let webauthn_sig = passkey.sign(&compute_challenge(&env.payer.pubkey(), nonce, &message_hash));
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts: manage_passkeys_accounts(env),
+            data: attesta::instruction::AddExecutor { webauthn_sig: webauthn_sig.to_bytes(), nonce, executor }.data(),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn test_executor_allowlist() {
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    // With no executors listed, anyone can submit, signing or not
+    let stranger = Keypair::new();
+    let instructions = execute_transfer_submitted_by(&env, &mut phone, 1, 1, stranger.pubkey(), false);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    let relayer = Keypair::new();
+    let instructions = add_executor(&env, &mut phone, 2, relayer.pubkey());
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.settings.authorized_executors, vec![relayer.pubkey()]);
+
+    // Now only the relayer, signing, gets through; a refusal doesn't use up the nonce
+    let instructions = execute_transfer_submitted_by(&env, &mut phone, 3, 1, stranger.pubkey(), true);
+    let error = send(&mut env, &instructions, &[&stranger]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ExecutorNotAllowed.into()));
+    let instructions = execute_transfer_submitted_by(&env, &mut phone, 3, 1, relayer.pubkey(), false);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ExecutorNotAllowed.into()));
+
+    let instructions = execute_transfer_submitted_by(&env, &mut phone, 3, 1, relayer.pubkey(), true);
+    send(&mut env, &instructions, &[&relayer]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.nonce, 3);
+    assert_eq!(token_balance(&mut env, env.recipient_ata).await, 2);
+}
+
+#[tokio::test]
+async fn test_stale_changes_are_refused() {
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let laptop = TestPasskey::new(2);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.state_version, 0);
+
+    // Two devices read the account at version 0; the first change lands
+    let theirs = Policy::spending_limit(Amount::from_sol(5.0).unwrap());
+    send(&mut env, &[update_policy(&env, &theirs, 0)], &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.state_version, 1);
+
+    // The second is refused rather than overwriting it, and uses up no nonce
+    let mine = Policy::spending_limit(Amount::from_sol(1.0).unwrap());
+    let error = send(&mut env, &[update_policy(&env, &mine, 0)], &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ConcurrentModification.into()));
+    let instructions = add_passkey(&env, &mut phone, 1, &laptop, 0);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ConcurrentModification.into()));
+    let account = load_account(&mut env).await;
+    assert_eq!((account.policy(), account.nonce), (theirs.to_bytes().unwrap().as_slice(), 0));
+
+    // Sent again at the version it now reads, it goes through
+    let instructions = add_passkey(&env, &mut phone, 1, &laptop, 1);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.state_version, 2);
+}
+
+#[tokio::test]
+async fn test_passkey_registry_stops_at_the_protocol_limit() {
+    let (mut env, mut context) = setup_context().await;
+    let mut phone = TestPasskey::new(1);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let instructions = add_passkey(&env, &mut phone, 1, &TestPasskey::new(2), 0);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    // Lift the registry's own limit, so only the protocol's is left
+    let mut account = load_account(&mut env).await;
+    let mut registry = account.passkey_registry().unwrap().unwrap();
+    registry.max_passkeys = u8::MAX;
+    account.set_passkey_registry(&registry).unwrap();
+    let mut stored = env.banks_client.get_account(env.attesta_account).await.unwrap().unwrap();
+    let encoded = encode_attesta_account(&account).unwrap();
+    stored.data[..encoded.len()].copy_from_slice(&encoded);
+    context.set_account(&env.attesta_account, &stored.into());
+
+    // Fill it, each add growing the account and paying its own rent
+    let mut nonce = 2;
+    for seed in 3..=MAX_PASSKEYS {
+        let instructions = add_passkey(&env, &mut phone, nonce, &TestPasskey::new(seed), nonce - 1);
+        send(&mut env, &instructions, &[]).await.unwrap();
+        nonce += 1;
+    }
+    let account = load_account(&mut env).await;
+    assert_eq!(account.passkey_registry().unwrap().unwrap().entries().count(), usize::from(MAX_PASSKEYS));
+
+    let instructions = add_passkey(&env, &mut phone, nonce, &TestPasskey::new(MAX_PASSKEYS + 1), nonce - 1);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::TooManyPasskeys.into()));
+    assert_eq!(load_account(&mut env).await.nonce, nonce - 1);
+
+    // The last passkey, found after all the others, still executes within budget
+    let mut last = TestPasskey::new(MAX_PASSKEYS);
+    let instructions = execute_transfer(&env, &mut last, nonce, LIMIT);
+    let blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&instructions, Some(&env.payer.pubkey()), &[&env.payer], blockhash);
+    let simulation = env.banks_client.simulate_transaction(transaction).await.unwrap();
+    assert!(matches!(simulation.result, Some(Ok(()))), "{:?}", simulation.result);
+    let units = simulation.simulation_details.unwrap().units_consumed;
+    assert!(units < 1_400_000, "execute with {} passkeys used {} units", MAX_PASSKEYS, units);
+}
+
+#[tokio::test]
+async fn test_zeroed_account_fields_are_refused() {
+    let (mut env, mut context) = setup_context().await;
+    let mut phone = TestPasskey::new(1);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let stored = env.banks_client.get_account(env.attesta_account).await.unwrap().unwrap();
+
+    // Each still decodes, but isn't an account the program could have made
+    let edits: [fn(&mut AttestaAccount); 3] = [
+        |account| account.owner = Pubkey::default(),
+        |account| account.passkey_public_key = [0; 64],
+        |account| account.credential_id.clear(),
+    ];
+    for (attempt, edit) in edits.into_iter().enumerate() {
+        let mut account = load_account(&mut env).await;
+        edit(&mut account);
+        let mut spoofed = stored.clone();
+        let encoded = encode_attesta_account(&account).unwrap();
+        spoofed.data[..encoded.len()].copy_from_slice(&encoded);
+        context.set_account(&env.attesta_account, &spoofed.into());
+
+        // A different amount each time, so the transactions aren't duplicates
+        let instructions = execute_transfer(&env, &mut phone, 1, LIMIT - 1 - attempt as u64);
+        let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+        assert_eq!(error_code(error), Some(AttestaError::InvalidAccountData.into()));
+        context.set_account(&env.attesta_account, &stored.clone().into());
+    }
+
+    // Restored, the same transfer goes through
+    let instructions = execute_transfer(&env, &mut phone, 1, LIMIT);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.nonce, 1);
+}
+
+/// A `cancel_proposal` signed by `signer`, refunding rent to `rent_payer`
+fn cancel_proposal(
+    env: &Env,
+    signer: &mut TestPasskey,
+    nonce: u64,
+    proposal: Pubkey,
+    rent_payer: Pubkey,
+    reason: CancelReason,
+) -> Vec<Instruction> {
+    let message_hash = action_message_hash(PROPOSAL_CANCEL_ACTION, &cancel_proposal_payload(&proposal, reason));
+    let webauthn_sig = signer.sign(&compute_challenge(&env.payer.pubkey(), nonce, &message_hash));
+
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts: attesta::accounts::CancelProposal { attesta_account: env.attesta_account, proposal, rent_payer }
+                .to_account_metas(None),
+            data: attesta::instruction::CancelProposal { webauthn_sig: webauthn_sig.to_bytes(), nonce, reason: reason as u8 }
+                .data(),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn test_cancelled_proposal_refunds_its_rent_payer() {
+    let (mut env, mut context) = setup_context().await;
+    let mut phone = TestPasskey::new(1);
+    let mut laptop = TestPasskey::new(2);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let instructions = add_passkey(&env, &mut phone, 1, &laptop, 0);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    // A transfer the laptop proposed, paid for by a relayer
+    let transaction_data = TokenTransfer { mint: env.mint, amount: 1, decimals: DECIMALS, destination_ata: env.recipient_ata }
+        .to_transaction_data();
+    let laptop_hash = credential_id_hash(&laptop.credential_id());
+    let relayer = Pubkey::new_unique();
+    let pending = PendingTransaction {
+        message_hash: smart_account::transaction_message_hash(&transaction_data),
+        policy_hash: load_account(&mut env).await.policy_hash,
+        transaction_data,
+        proposer: laptop_hash,
+        rent_payer: relayer,
+        approvals: vec![laptop_hash],
+        required_approvals: 2,
+        reserved: 0,
+        created_at: 0,
+    };
+    let mut data = Vec::new();
+    ProposalData { attesta_account: env.attesta_account, pending: pending.to_bytes().unwrap(), bump: 255 }
+        .try_serialize(&mut data)
+        .unwrap();
+    let rent = env.banks_client.get_rent().await.unwrap().minimum_balance(data.len());
+    let proposal = Pubkey::new_unique();
+    context.set_account(&proposal, &Account { lamports: rent, data, owner: attesta::ID, executable: false, rent_epoch: 0 }.into());
+
+    // The rent can't be redirected to whoever submits the cancellation
+    let instructions = cancel_proposal(&env, &mut laptop, 2, proposal, env.payer.pubkey(), CancelReason::Mistake);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::Unauthorized.into()));
+
+    let instructions = cancel_proposal(&env, &mut laptop, 2, proposal, relayer, CancelReason::Mistake);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert!(env.banks_client.get_account(proposal).await.unwrap().is_none());
+    assert_eq!(env.banks_client.get_balance(relayer).await.unwrap(), rent);
+    assert_eq!(load_account(&mut env).await.nonce, 2);
+
+    // Anything still aimed at the proposal finds nothing to act on
+    let instructions = cancel_proposal(&env, &mut phone, 3, proposal, relayer, CancelReason::Rejected);
+    assert!(send(&mut env, &instructions, &[]).await.is_err());
+}
+
+/// A claim ticket for `amount` lamports, signed by `passkey` and bound to `claim_key`
+fn claim_ticket(env: &Env, passkey: &mut TestPasskey, nonce: u64, claim_key: &Keypair, amount: u64, expires_at: i64) -> ClaimTicket {
+    let payload = claim_ticket_payload(&env.attesta_account, &claim_key.pubkey(), amount, expires_at);
+    let message_hash = action_message_hash(CLAIM_TICKET_ACTION, &payload);
+    ClaimTicket {
+        account: env.attesta_account,
+        claim_key: claim_key.pubkey(),
+        amount,
+        expires_at,
+        nonce,
+        webauthn_sig: passkey.sign(&compute_challenge(&env.payer.pubkey(), nonce, &message_hash)),
+    }
+}
+
+/// A `claim` of `ticket` paying `destination`, to be signed by the ticket's claim key
+fn claim(env: &Env, ticket: &ClaimTicket, destination: Pubkey) -> Vec<Instruction> {
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts: attesta::accounts::Claim {
+                attesta_account: env.attesta_account,
+                claim_key: ticket.claim_key,
+                destination,
+            }
+            .to_account_metas(None),
+            data: attesta::instruction::Claim { ticket: ticket.to_bytes() }.data(),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn test_claim_ticket_pays_once() {
+    const AMOUNT: u64 = 100_000_000; // 0.1 SOL
+
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let fund = system_instruction::transfer(&env.payer.pubkey(), &env.attesta_account, 3 * AMOUNT / 2);
+    send(&mut env, &[fund], &[]).await.unwrap();
+
+    let claim_key = Keypair::new();
+    let friend = Pubkey::new_unique();
+    let ticket = claim_ticket(&env, &mut phone, 1, &claim_key, AMOUNT, i64::MAX);
+    send(&mut env, &claim(&env, &ticket, friend), &[&claim_key]).await.unwrap();
+    assert_eq!(env.banks_client.get_balance(friend).await.unwrap(), AMOUNT);
+    assert_eq!(load_account(&mut env).await.nonce, 1);
+
+    // The same link again pays nothing
+    let error = send(&mut env, &claim(&env, &ticket, friend), &[&claim_key]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::Unauthorized.into()));
+
+    // An expired ticket is refused
+    let claim_key = Keypair::new();
+    let expired = claim_ticket(&env, &mut phone, 2, &claim_key, AMOUNT / 10, 1);
+    let error = send(&mut env, &claim(&env, &expired, friend), &[&claim_key]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ClaimTicketExpired.into()));
+
+    // More than the account can spare and stay rent-exempt
+    let over = claim_ticket(&env, &mut phone, 2, &claim_key, AMOUNT, i64::MAX);
+    let error = send(&mut env, &claim(&env, &over, friend), &[&claim_key]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::InsufficientFunds.into()));
+
+    // Raising a ticket's amount breaks its signature
+    let mut raised = claim_ticket(&env, &mut phone, 2, &claim_key, AMOUNT / 10, i64::MAX);
+    raised.amount = AMOUNT / 5;
+    let error = send(&mut env, &claim(&env, &raised, friend), &[&claim_key]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::Unauthorized.into()));
+    assert_eq!(env.banks_client.get_balance(friend).await.unwrap(), AMOUNT);
+}
+
+#[tokio::test]
+async fn test_indexed_accounts() {
+    let mut env = setup().await;
+    let mut phone = TestPasskey::new(1);
+    let payer = env.payer.pubkey();
+    let (indexed, _) = smart_account::derive_attesta_account_by_index(&attesta::ID, &payer, 0);
+
+    // Lamports sent to the address early count toward its rent
+    send(&mut env, &[system_instruction::transfer(&payer, &indexed, 1_000)], &[]).await.unwrap();
+    let instructions = initialize_with_index(&env, &mut phone, 0);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    // A taken index fails cleanly; other indexes and the owner's own address are still free
+    let instructions = initialize_with_index(&env, &mut phone, 0);
+    let error = send(&mut env, &instructions, &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::AccountAlreadyInitialized.into()));
+    let instructions = initialize_with_index(&env, &mut phone, 1);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let instructions = initialize(&env, &mut phone);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(load_account(&mut env).await.account_index, None);
+
+    // The indexed account records its index, and signs for its tokens with it
+    env.attesta_account = indexed;
+    env.source_ata = get_associated_token_address(&indexed, &env.mint);
+    assert_eq!(load_account(&mut env).await.account_index, Some(0));
+    let instructions = [
+        create_associated_token_account(&payer, &indexed, &env.mint, &spl_token::id()),
+        spl_token::instruction::mint_to(&spl_token::id(), &env.mint, &env.source_ata, &payer, &[], LIMIT).unwrap(),
+    ];
+    send(&mut env, &instructions, &[]).await.unwrap();
+    let instructions = execute_transfer(&env, &mut phone, 1, 7);
+    send(&mut env, &instructions, &[]).await.unwrap();
+    assert_eq!(token_balance(&mut env, env.recipient_ata).await, 7);
+}
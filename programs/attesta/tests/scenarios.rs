@@ -0,0 +1,273 @@
+//! Localnet tests that start from a `ScenarioBuilder` scenario
+//!
+//! `deploy` builds each scenario with real instructions, so these first
+//! check the account the program stored is the one the builder worked out
+//! in memory, then test from states a fresh account takes several steps
+//! to reach. These load the compiled program, so build it first with
+//! `anchor build`.
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use attesta::AttestaError;
+use attesta_test_support::{assert_same_state, load_account, Scenario, ScenarioBuilder, SCENARIO_DECIMALS};
+use core_crypto::{compute_challenge, test_utils::TestPasskey, WebAuthnSignature};
+use recovery::multi_passkey::PASSKEY_ADD_ACTION;
+use recovery::{Amount, DestinationLimits, Policy};
+use smart_account::social_recovery::RECOVERY_CANCEL_ACTION;
+use smart_account::{action_message_hash, AccountSettings, TransactionRequest, EXECUTOR_ADD_ACTION};
+use solana_program_test::{BanksClient, BanksClientError, ProgramTest};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+/// Base units in a whole token of the scenario's mint
+const TOKEN: u64 = 10u64.pow(SCENARIO_DECIMALS as u32);
+
+async fn start() -> (BanksClient, Keypair) {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    let (banks_client, payer, _) = program_test.start().await;
+    (banks_client, payer)
+}
+
+/// Deploys `builder` for `payer`, checking the program stored what the builder worked out
+async fn deploy(banks_client: &mut BanksClient, payer: &Keypair, builder: &ScenarioBuilder) -> Scenario {
+    let scenario = builder.deploy(banks_client, payer, attesta::ID).await.unwrap();
+    assert_same_state(&load_account(banks_client, scenario.address).await, &scenario.account);
+    scenario
+}
+
+async fn send(banks_client: &mut BanksClient, payer: &Keypair, instructions: &[Instruction]) -> Result<(), BanksClientError> {
+    let blockhash = banks_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &[payer], blockhash);
+    banks_client.process_transaction(transaction).await
+}
+
+/// Runs `instructions`, returning how they ended and the program's
+/// structured log lines
+async fn send_logged(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+) -> (Result<(), TransactionError>, Vec<String>) {
+    let blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &[payer], blockhash);
+    let result = banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+    let logs = result.metadata.unwrap().log_messages.into_iter().filter(|log| log.contains("ATST1 ")).collect();
+    (result.result, logs)
+}
+
+/// The Attesta error code a failed instruction returned
+fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+async fn token_balance(banks_client: &mut BanksClient, token_account: Pubkey) -> u64 {
+    let account = banks_client.get_account(token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
+}
+
+/// Passkey `seed`'s signature over `action` for `payload`, with the account's next nonce
+fn sign_action(scenario: &mut Scenario, seed: u8, action: &[u8], payload: &[u8]) -> (WebAuthnSignature, u64) {
+    let nonce = scenario.next_nonce();
+    let challenge = compute_challenge(&scenario.account.owner, nonce, &action_message_hash(action, payload));
+    (scenario.passkey(seed).sign(&challenge), nonce)
+}
+
+/// An `execute` of the scenario's transfer of `amount`, signed by the primary passkey
+fn execute_transfer(scenario: &mut Scenario, payer: &Keypair, amount: u64) -> Vec<Instruction> {
+    let transfer = scenario.transfer(amount);
+    let request = TransactionRequest::from_token_transfer(transfer);
+    let envelope = scenario.envelope(1, &request);
+
+    let mut accounts = attesta::accounts::Execute {
+        attesta_account: scenario.address,
+        authority: payer.pubkey(),
+        parent_account: None,
+        proof_log: None,
+        memo_program: None,
+        proposal: None,
+        system_program: None,
+        announcement: None,
+    }
+    .to_account_metas(None);
+    accounts.extend([
+        AccountMeta::new(scenario.source_ata(), false),
+        AccountMeta::new_readonly(transfer.mint, false),
+        AccountMeta::new(transfer.destination_ata, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts,
+            data: attesta::instruction::Execute {
+                webauthn_sig: envelope.webauthn_sig.to_bytes(),
+                nonce: envelope.nonce,
+                message_hash: envelope.message_hash,
+                transaction_data: request.transaction_data,
+                idempotency_key: None,
+                memo: vec![],
+                emit_memo: false,
+            }
+            .data(),
+        },
+    ]
+}
+
+fn update_policy(scenario: &Scenario, payer: &Keypair, policy: &Policy) -> Instruction {
+    Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::UpdatePolicy { attesta_account: scenario.address, owner: payer.pubkey() }
+            .to_account_metas(None),
+        data: attesta::instruction::UpdatePolicy {
+            new_policy: policy.to_bytes().unwrap(),
+            expected_version: scenario.account.state_version,
+        }
+        .data(),
+    }
+}
+
+fn manage_passkeys_accounts(scenario: &Scenario, payer: &Keypair) -> Vec<AccountMeta> {
+    attesta::accounts::ManagePasskeys {
+        attesta_account: scenario.address,
+        owner: payer.pubkey(),
+        system_program: solana_sdk::system_program::id(),
+    }
+    .to_account_metas(None)
+}
+
+#[tokio::test]
+async fn test_deployed_scenarios_match_the_builder() {
+    let policy = Policy::per_destination_limit(DestinationLimits {
+        limits: vec![],
+        default_max_amount: 1_000 * TOKEN,
+        window_seconds: 86_400,
+        anchor_timestamp: 0,
+    });
+    let builders = [
+        ScenarioBuilder::new(),
+        ScenarioBuilder::new().with_passkeys(3).with_nonce(5),
+        ScenarioBuilder::new().with_policy(policy).with_spent_today(250 * TOKEN),
+        ScenarioBuilder::new().with_passkeys(2).frozen(),
+        ScenarioBuilder::new()
+            .with_passkeys(3)
+            .with_settings(AccountSettings { recovery_allowance: TOKEN, ..Default::default() })
+            .pending_recovery(),
+    ];
+
+    for builder in builders {
+        // Each scenario belongs to the payer, so each gets its own bank
+        let (mut banks_client, payer) = start().await;
+        let scenario = deploy(&mut banks_client, &payer, &builder).await;
+
+        // And `build` alone gives the same account, without a bank
+        let built = builder.with_owner(payer.pubkey()).with_program_id(attesta::ID).build();
+        assert_eq!(built.address, scenario.address);
+        assert_same_state(&load_account(&mut banks_client, built.address).await, &built.account);
+        let spent = scenario.account.destination_spends.entries.iter().map(|entry| entry.spent).sum::<u64>();
+        assert_eq!(token_balance(&mut banks_client, scenario.destination_ata).await, spent);
+    }
+}
+
+#[tokio::test]
+async fn test_pending_recovery_holds_the_account() {
+    // Guardians have started replacing the phone; its owner kept a small allowance
+    let (mut banks_client, payer) = start().await;
+    let allowance = 10 * TOKEN;
+    let builder = ScenarioBuilder::new()
+        .with_passkeys(2)
+        .with_settings(AccountSettings { recovery_allowance: allowance, ..Default::default() })
+        .pending_recovery();
+    let mut scenario = deploy(&mut banks_client, &payer, &builder).await;
+    let nonce = scenario.next_nonce();
+
+    // Up to the allowance the phone still pays, and every attempt is announced
+    let instructions = execute_transfer(&mut scenario, &payer, allowance);
+    let (result, logs) = send_logged(&mut banks_client, &payer, &instructions).await;
+    assert_eq!(result, Ok(()));
+    let announced = format!("recovery_primary_active nonce={} allowed=true", nonce);
+    assert!(logs.iter().any(|log| log.ends_with(&announced)), "{:?}", logs);
+    assert_eq!(token_balance(&mut banks_client, scenario.destination_ata).await, allowance);
+    scenario.account = load_account(&mut banks_client, scenario.address).await;
+
+    // One base unit over is refused, and still announced
+    let instructions = execute_transfer(&mut scenario, &payer, allowance + 1);
+    let (result, logs) = send_logged(&mut banks_client, &payer, &instructions).await;
+    let denied = InstructionError::Custom(AttestaError::OverRecoveryAllowance.into());
+    assert_eq!(result, Err(TransactionError::InstructionError(1, denied)));
+    let announced = format!("recovery_primary_active nonce={} allowed=false", nonce + 1);
+    assert!(logs.iter().any(|log| log.ends_with(&announced)), "{:?}", logs);
+    assert!(logs.iter().any(|log| log.ends_with("exec_denied reason=recovery_pending")), "{:?}", logs);
+    assert_eq!(load_account(&mut banks_client, scenario.address).await.nonce, nonce);
+
+    // Policies, passkeys and settings can't change at all
+    let open = Policy::spending_limit(Amount::from_sol(1_000.0).unwrap());
+    let error = send(&mut banks_client, &payer, &[update_policy(&scenario, &payer, &open)]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::RecoveryPending.into()));
+
+    let tablet = TestPasskey::new(3);
+    let payload = [tablet.public_key().as_ref(), tablet.credential_id().as_slice()].concat();
+    let (webauthn_sig, signed_nonce) = sign_action(&mut scenario, 1, PASSKEY_ADD_ACTION, &payload);
+    let add_passkey = Instruction {
+        program_id: attesta::ID,
+        accounts: manage_passkeys_accounts(&scenario, &payer),
+        data: attesta::instruction::AddPasskey {
+            webauthn_sig: webauthn_sig.to_bytes(),
+            nonce: signed_nonce,
+            public_key: tablet.public_key(),
+            credential_id: tablet.credential_id(),
+            name: "Tablet".to_string(),
+            registration_sig: vec![],
+            expected_version: scenario.account.state_version,
+        }
+        .data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    let error = send(&mut banks_client, &payer, &[budget.clone(), add_passkey]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::RecoveryPending.into()));
+
+    let executor = Pubkey::new_unique();
+    let (webauthn_sig, signed_nonce) = sign_action(&mut scenario, 1, EXECUTOR_ADD_ACTION, executor.as_ref());
+    let add_executor = Instruction {
+        program_id: attesta::ID,
+        accounts: manage_passkeys_accounts(&scenario, &payer),
+        data: attesta::instruction::AddExecutor { webauthn_sig: webauthn_sig.to_bytes(), nonce: signed_nonce, executor }
+            .data(),
+    };
+    let error = send(&mut banks_client, &payer, &[budget.clone(), add_executor]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::RecoveryPending.into()));
+
+    // Until the recovery is cancelled
+    let request_hash = scenario.account.pending_recovery.as_ref().unwrap().request_hash();
+    let (webauthn_sig, signed_nonce) = sign_action(&mut scenario, 1, RECOVERY_CANCEL_ACTION, &request_hash);
+    let cancel = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::Recover {
+            attesta_account: scenario.address,
+            payer: payer.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::CancelRecovery { webauthn_sig: webauthn_sig.to_bytes(), nonce: signed_nonce }.data(),
+    };
+    send(&mut banks_client, &payer, &[budget, cancel]).await.unwrap();
+    scenario.account = load_account(&mut banks_client, scenario.address).await;
+    assert!(!scenario.account.recovery_pending());
+
+    let instructions = execute_transfer(&mut scenario, &payer, allowance + 1);
+    let (result, logs) = send_logged(&mut banks_client, &payer, &instructions).await;
+    assert_eq!(result, Ok(()));
+    assert!(!logs.iter().any(|log| log.contains("recovery_primary_active")), "{:?}", logs);
+    scenario.account = load_account(&mut banks_client, scenario.address).await;
+    send(&mut banks_client, &payer, &[update_policy(&scenario, &payer, &open)]).await.unwrap();
+}
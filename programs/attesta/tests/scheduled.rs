@@ -0,0 +1,315 @@
+//! Localnet tests for scheduled transactions
+//!
+//! These load the compiled program, so build it first with `anchor build`.
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use core_crypto::{compute_challenge, test_utils::TestPasskey};
+use recovery::{Amount, MintLimit, MintLimits, Policy};
+use smart_account::{
+    action_message_hash, registration_challenge, schedule_payload, TokenTransfer, SCHEDULE_ACTION, SCHEDULE_CANCEL_ACTION,
+};
+use attesta::AttestaError;
+use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    clock::Clock,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+const DECIMALS: u8 = 6;
+const LIMIT: u64 = 100 * 10u64.pow(DECIMALS as u32);
+
+/// The cluster time each test starts at
+const START: i64 = 1_800_000_000;
+
+struct Env {
+    context: ProgramTestContext,
+    owner: Keypair,
+    /// Executes schedules; has nothing to do with the account
+    keeper: Keypair,
+    passkey: TestPasskey,
+    nonce: u64,
+    attesta_account: Pubkey,
+    mint: Pubkey,
+    recipient_ata: Pubkey,
+}
+
+async fn send(env: &mut Env, instructions: &[Instruction], payer: &Keypair) -> Result<(), BanksClientError> {
+    let blockhash = env.context.banks_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &[payer], blockhash);
+    env.context.banks_client.process_transaction(transaction).await
+}
+
+async fn send_as_owner(env: &mut Env, instructions: &[Instruction]) -> Result<(), BanksClientError> {
+    let owner = env.owner.insecure_clone();
+    send(env, instructions, &owner).await
+}
+
+/// The Attesta error code a failed instruction returned
+fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+/// Moves to the next slot with the cluster clock at `unix_timestamp`
+async fn set_time(env: &mut Env, unix_timestamp: i64) {
+    let clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    // A new slot also means a new blockhash, so a retried instruction isn't a duplicate
+    env.context.warp_to_slot(clock.slot + 1).unwrap();
+    let mut clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    env.context.set_sysvar(&clock);
+}
+
+async fn token_balance(env: &mut Env, token_account: &Pubkey) -> u64 {
+    let account = env.context.banks_client.get_account(*token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
+}
+
+async fn lamports(env: &mut Env, address: &Pubkey) -> Option<u64> {
+    env.context.banks_client.get_account(*address).await.unwrap().map(|account| account.lamports)
+}
+
+/// Creates an Attesta account with a limit of 100 tokens per transfer, funded with 500
+async fn setup() -> Env {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    let context = program_test.start_with_context().await;
+
+    let mint = Keypair::new();
+    let recipient = Pubkey::new_unique();
+    let owner = context.payer.insecure_clone();
+    let (attesta_account, _) = Pubkey::find_program_address(&[b"attesta", owner.pubkey().as_ref()], &attesta::ID);
+
+    let mut env = Env {
+        context,
+        owner,
+        keeper: Keypair::new(),
+        passkey: TestPasskey::new(1),
+        nonce: 0,
+        attesta_account,
+        mint: mint.pubkey(),
+        recipient_ata: get_associated_token_address(&recipient, &mint.pubkey()),
+    };
+    set_time(&mut env, START).await;
+
+    let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+        allow_unlisted: false,
+        limits: vec![MintLimit { mint: env.mint, max_amount: LIMIT, decimals: DECIMALS }],
+    });
+    let challenge = registration_challenge(
+        &env.owner.pubkey(),
+        &env.attesta_account,
+        &env.passkey.public_key(),
+        &env.passkey.credential_id(),
+    );
+    let registration_sig = env.passkey.sign_create(&challenge);
+    let initialize = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::Initialize {
+            attesta_account: env.attesta_account,
+            owner: env.owner.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::Initialize {
+            passkey_public_key: env.passkey.public_key(),
+            credential_id: env.passkey.credential_id(),
+            policy: policy.to_bytes().unwrap(),
+            privacy_mode: false,
+            registration_sig: registration_sig.to_bytes(),
+            aaguid_allowlist: vec![],
+        }
+        .data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send_as_owner(&mut env, &[budget, initialize]).await.unwrap();
+
+    let rent = env.context.banks_client.get_rent().await.unwrap();
+    let owner = env.owner.pubkey();
+    let source_ata = get_associated_token_address(&env.attesta_account, &env.mint);
+    let instructions = [
+        system_instruction::create_account(
+            &owner,
+            &env.mint,
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(&spl_token::id(), &env.mint, &owner, None, DECIMALS).unwrap(),
+        create_associated_token_account(&owner, &env.attesta_account, &env.mint, &spl_token::id()),
+        create_associated_token_account(&owner, &recipient, &env.mint, &spl_token::id()),
+        spl_token::instruction::mint_to(&spl_token::id(), &env.mint, &source_ata, &owner, &[], 5 * LIMIT).unwrap(),
+        system_instruction::transfer(&owner, &env.keeper.pubkey(), 1_000_000_000),
+    ];
+    let blockhash = env.context.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&instructions, Some(&owner), &[&env.owner, &mint], blockhash);
+    env.context.banks_client.process_transaction(transaction).await.unwrap();
+
+    env
+}
+
+fn schedule_address(env: &Env, nonce: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"schedule", env.attesta_account.as_ref(), &nonce.to_le_bytes()], &attesta::ID).0
+}
+
+/// Schedules a transfer of `amount` to the recipient, returning the schedule's address
+async fn schedule(env: &mut Env, amount: u64, executable_after: i64, executable_before: Option<i64>) -> Pubkey {
+    let transaction_data = TokenTransfer { mint: env.mint, amount, decimals: DECIMALS, destination_ata: env.recipient_ata }
+        .to_transaction_data();
+    env.nonce += 1;
+    let nonce = env.nonce;
+    let payload = schedule_payload(&transaction_data, executable_after, executable_before);
+    let challenge = compute_challenge(&env.owner.pubkey(), nonce, &action_message_hash(SCHEDULE_ACTION, &payload));
+    let webauthn_sig = env.passkey.sign(&challenge);
+
+    let schedule = schedule_address(env, nonce);
+    let instruction = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::ScheduleTransaction {
+            attesta_account: env.attesta_account,
+            schedule,
+            owner: env.owner.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::ScheduleTransaction {
+            webauthn_sig: webauthn_sig.to_bytes(),
+            nonce,
+            transaction_data,
+            executable_after,
+            executable_before,
+        }
+        .data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send_as_owner(env, &[budget, instruction]).await.unwrap();
+    schedule
+}
+
+/// Runs `execute_scheduled`, paid for by the keeper
+async fn execute(env: &mut Env, schedule: Pubkey) -> Result<(), BanksClientError> {
+    let mut accounts = attesta::accounts::ExecuteScheduled {
+        attesta_account: env.attesta_account,
+        schedule,
+        owner: env.owner.pubkey(),
+        parent_account: None,
+        proof_log: None,
+    }
+    .to_account_metas(None);
+    accounts.extend([
+        AccountMeta::new(get_associated_token_address(&env.attesta_account, &env.mint), false),
+        AccountMeta::new_readonly(env.mint, false),
+        AccountMeta::new(env.recipient_ata, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    let instruction = Instruction {
+        program_id: attesta::ID,
+        accounts,
+        data: attesta::instruction::ExecuteScheduled {}.data(),
+    };
+    let keeper = env.keeper.insecure_clone();
+    send(env, &[instruction], &keeper).await
+}
+
+#[tokio::test]
+async fn test_execute_before_window_fails() {
+    let mut env = setup().await;
+    let schedule = schedule(&mut env, LIMIT, START + 100, None).await;
+
+    let error = execute(&mut env, schedule).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::ScheduleNotYetExecutable.into()));
+    assert!(lamports(&mut env, &schedule).await.is_some());
+
+    set_time(&mut env, START + 100).await;
+    execute(&mut env, schedule).await.unwrap();
+    let recipient_ata = env.recipient_ata;
+    assert_eq!(token_balance(&mut env, &recipient_ata).await, LIMIT);
+    assert!(lamports(&mut env, &schedule).await.is_none());
+}
+
+#[tokio::test]
+async fn test_expired_schedule_returns_rent_without_executing() {
+    let mut env = setup().await;
+    let schedule = schedule(&mut env, LIMIT, START, Some(START + 100)).await;
+    let rent = lamports(&mut env, &schedule).await.unwrap();
+    let owner = env.owner.pubkey();
+    let owner_before = lamports(&mut env, &owner).await.unwrap();
+
+    set_time(&mut env, START + 100).await;
+    execute(&mut env, schedule).await.unwrap();
+
+    // The keeper paid the fee, the owner got the rent back, and nothing moved
+    assert!(lamports(&mut env, &schedule).await.is_none());
+    assert_eq!(lamports(&mut env, &owner).await.unwrap(), owner_before + rent);
+    let recipient_ata = env.recipient_ata;
+    assert_eq!(token_balance(&mut env, &recipient_ata).await, 0);
+}
+
+#[tokio::test]
+async fn test_policy_change_is_checked_at_execution() {
+    let mut env = setup().await;
+    let schedule = schedule(&mut env, LIMIT, START + 10, None).await;
+
+    // After scheduling, the owner locks the account for a while
+    let update_policy = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::UpdatePolicy { attesta_account: env.attesta_account, owner: env.owner.pubkey() }
+            .to_account_metas(None),
+        data: attesta::instruction::UpdatePolicy {
+            new_policy: Policy::time_locked(START + 1_000).to_bytes().unwrap(),
+            expected_version: 0,
+        }
+        .data(),
+    };
+    send_as_owner(&mut env, &[update_policy]).await.unwrap();
+
+    set_time(&mut env, START + 10).await;
+    let error = execute(&mut env, schedule).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::PolicyDenied.into()));
+    assert!(lamports(&mut env, &schedule).await.is_some());
+
+    // Still in the window once the lock is over
+    set_time(&mut env, START + 1_000).await;
+    execute(&mut env, schedule).await.unwrap();
+    let recipient_ata = env.recipient_ata;
+    assert_eq!(token_balance(&mut env, &recipient_ata).await, LIMIT);
+}
+
+#[tokio::test]
+async fn test_cancel_scheduled() {
+    let mut env = setup().await;
+    let schedule = schedule(&mut env, LIMIT, START + 10, None).await;
+
+    env.nonce += 1;
+    let nonce = env.nonce;
+    let challenge =
+        compute_challenge(&env.owner.pubkey(), nonce, &action_message_hash(SCHEDULE_CANCEL_ACTION, schedule.as_ref()));
+    let webauthn_sig = env.passkey.sign(&challenge);
+    let cancel = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::CancelScheduled {
+            attesta_account: env.attesta_account,
+            schedule,
+            owner: env.owner.pubkey(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::CancelScheduled { webauthn_sig: webauthn_sig.to_bytes(), nonce }.data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send_as_owner(&mut env, &[budget, cancel]).await.unwrap();
+    assert!(lamports(&mut env, &schedule).await.is_none());
+
+    set_time(&mut env, START + 10).await;
+    assert!(execute(&mut env, schedule).await.is_err());
+}
@@ -0,0 +1,269 @@
+//! Localnet tests for accounts created out of a sponsor pool
+//!
+//! These load the compiled program, so build it first with `anchor build`.
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use attesta::{AttestaAccountData, AttestaError, SponsorPoolData};
+use core_crypto::{compute_challenge, test_utils::TestPasskey};
+use smart_account::sponsorship::{SponsorPool, SECONDS_PER_DAY};
+use smart_account::{action_message_hash, claim_ticket_payload, registration_challenge, AttestaAccount, ClaimTicket, CLAIM_TICKET_ACTION};
+use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    clock::Clock,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+
+/// The cluster time each test starts at: noon UTC
+const START: i64 = 1_800_000_000 - 1_800_000_000 % SECONDS_PER_DAY + SECONDS_PER_DAY / 2;
+
+struct Env {
+    context: ProgramTestContext,
+    /// Controls the pool, and relays every transaction (pays the fees)
+    authority: Keypair,
+    sponsor_pool: Pubkey,
+    /// Rent for one Attesta account
+    account_rent: u64,
+}
+
+/// Someone with a passkey and no SOL
+struct NewUser {
+    owner: Keypair,
+    passkey: TestPasskey,
+    attesta_account: Pubkey,
+}
+
+impl NewUser {
+    fn new(seed: u8) -> Self {
+        let owner = Keypair::new();
+        let (attesta_account, _) = Pubkey::find_program_address(&[b"attesta", owner.pubkey().as_ref()], &attesta::ID);
+        Self { owner, passkey: TestPasskey::new(seed), attesta_account }
+    }
+}
+
+async fn send(env: &mut Env, instructions: &[Instruction], extra_signers: &[&Keypair]) -> Result<(), BanksClientError> {
+    let mut signers: Vec<&Keypair> = vec![&env.authority];
+    signers.extend_from_slice(extra_signers);
+
+    let blockhash = env.context.banks_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&env.authority.pubkey()), &signers, blockhash);
+    env.context.banks_client.process_transaction(transaction).await
+}
+
+/// The Attesta error code a failed instruction returned
+fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+/// Moves to the next slot with the cluster clock at `unix_timestamp`
+async fn set_time(env: &mut Env, unix_timestamp: i64) {
+    let clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    env.context.warp_to_slot(clock.slot + 1).unwrap();
+    let mut clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    env.context.set_sysvar(&clock);
+}
+
+async fn lamports(env: &mut Env, address: &Pubkey) -> u64 {
+    env.context.banks_client.get_balance(*address).await.unwrap()
+}
+
+async fn load_pool(env: &mut Env) -> SponsorPool {
+    let account = env.context.banks_client.get_account(env.sponsor_pool).await.unwrap().unwrap();
+    let wrapper = SponsorPoolData::try_deserialize(&mut account.data.as_slice()).unwrap();
+    SponsorPool::from_bytes(&wrapper.pool).unwrap()
+}
+
+/// Creates a pool sponsoring `max_per_day` accounts a day, funded for `funded_accounts` of them
+async fn setup(max_per_day: u32, funded_accounts: u64) -> Env {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    let context = program_test.start_with_context().await;
+
+    let authority = context.payer.insecure_clone();
+    let (sponsor_pool, _) = Pubkey::find_program_address(&[b"sponsor_pool", authority.pubkey().as_ref()], &attesta::ID);
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let account_rent = rent.minimum_balance(attesta::ATTESTA_ACCOUNT_SPACE);
+    let mut env = Env { context, authority, sponsor_pool, account_rent };
+    set_time(&mut env, START).await;
+
+    let create = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::CreateSponsorPool {
+            sponsor_pool: env.sponsor_pool,
+            authority: env.authority.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::CreateSponsorPool {
+            max_accounts_per_day: max_per_day,
+            max_lamports_per_account: account_rent,
+        }
+        .data(),
+    };
+    let fund = system_instruction::transfer(&env.authority.pubkey(), &env.sponsor_pool, funded_accounts * account_rent);
+    send(&mut env, &[create, fund], &[]).await.unwrap();
+    env
+}
+
+/// `sponsored_initialize` for `user`, signed by the user's passkey over its registration challenge
+fn sponsored_initialize(env: &Env, user: &mut NewUser) -> Vec<Instruction> {
+    let challenge = registration_challenge(
+        &user.owner.pubkey(),
+        &user.attesta_account,
+        &user.passkey.public_key(),
+        &user.passkey.credential_id(),
+    );
+    let registration_sig = user.passkey.sign_create(&challenge);
+    let initialize = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::SponsoredInitialize {
+            sponsor_pool: env.sponsor_pool,
+            attesta_account: user.attesta_account,
+            owner: user.owner.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::SponsoredInitialize {
+            passkey_public_key: user.passkey.public_key(),
+            credential_id: user.passkey.credential_id(),
+            policy: vec![],
+            privacy_mode: false,
+            registration_sig: registration_sig.to_bytes(),
+            aaguid_allowlist: vec![],
+        }
+        .data(),
+    };
+    vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000), initialize]
+}
+
+async fn onboard(env: &mut Env, user: &mut NewUser) -> Result<(), BanksClientError> {
+    let instructions = sponsored_initialize(env, user);
+    let owner = user.owner.insecure_clone();
+    send(env, &instructions, &[&owner]).await
+}
+
+fn withdraw_pool(env: &Env, amount: u64) -> Instruction {
+    Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::WithdrawPool { sponsor_pool: env.sponsor_pool, authority: env.authority.pubkey() }
+            .to_account_metas(None),
+        data: attesta::instruction::WithdrawPool { amount }.data(),
+    }
+}
+
+#[tokio::test]
+async fn test_sponsored_account_works_like_a_funded_one() {
+    const AMOUNT: u64 = 10_000_000;
+
+    let mut env = setup(5, 3).await;
+    let mut user = NewUser::new(1);
+    let sponsor_pool = env.sponsor_pool;
+    let pool_before = lamports(&mut env, &sponsor_pool).await;
+    onboard(&mut env, &mut user).await.unwrap();
+
+    // The pool paid the rent; the owner still holds nothing
+    assert_eq!(lamports(&mut env, &user.owner.pubkey()).await, 0);
+    assert_eq!(lamports(&mut env, &user.attesta_account).await, env.account_rent);
+    assert_eq!(lamports(&mut env, &sponsor_pool).await, pool_before - env.account_rent);
+    let pool = load_pool(&mut env).await;
+    assert_eq!((pool.sponsored_today, pool.total_sponsored, pool.total_lamports), (1, 1, env.account_rent));
+
+    let stored = env.context.banks_client.get_account(user.attesta_account).await.unwrap().unwrap();
+    assert_eq!(stored.owner, attesta::ID);
+    assert_eq!(stored.data.len(), attesta::ATTESTA_ACCOUNT_SPACE);
+    let wrapper = AttestaAccountData::try_deserialize(&mut stored.data.as_slice()).unwrap();
+    let account = AttestaAccount::from_bytes(&wrapper.data).unwrap();
+    assert_eq!(account.owner, user.owner.pubkey());
+    assert_eq!(account.passkey_public_key, user.passkey.public_key());
+
+    // Once funded, the passkey spends from it like any other account
+    let fund = system_instruction::transfer(&env.authority.pubkey(), &user.attesta_account, 2 * AMOUNT);
+    send(&mut env, &[fund], &[]).await.unwrap();
+    let claim_key = Keypair::new();
+    let payload = claim_ticket_payload(&user.attesta_account, &claim_key.pubkey(), AMOUNT, i64::MAX);
+    let message_hash = action_message_hash(CLAIM_TICKET_ACTION, &payload);
+    let ticket = ClaimTicket {
+        account: user.attesta_account,
+        claim_key: claim_key.pubkey(),
+        amount: AMOUNT,
+        expires_at: i64::MAX,
+        nonce: 1,
+        webauthn_sig: user.passkey.sign(&compute_challenge(&user.owner.pubkey(), 1, &message_hash)),
+    };
+    let friend = Pubkey::new_unique();
+    let claim = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::Claim { attesta_account: user.attesta_account, claim_key: claim_key.pubkey(), destination: friend }
+            .to_account_metas(None),
+        data: attesta::instruction::Claim { ticket: ticket.to_bytes() }.data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send(&mut env, &[budget, claim], &[&claim_key]).await.unwrap();
+    assert_eq!(lamports(&mut env, &friend).await, AMOUNT);
+
+    // The same owner can't be sponsored twice
+    let error = onboard(&mut env, &mut user).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::AccountAlreadyInitialized.into()));
+}
+
+#[tokio::test]
+async fn test_pool_exhaustion_and_withdrawal() {
+    let mut env = setup(10, 2).await;
+    onboard(&mut env, &mut NewUser::new(1)).await.unwrap();
+    onboard(&mut env, &mut NewUser::new(2)).await.unwrap();
+
+    // The pool is down to its own rent-exempt minimum
+    let mut third = NewUser::new(3);
+    let error = onboard(&mut env, &mut third).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::SponsorPoolExhausted.into()));
+    assert_eq!(load_pool(&mut env).await.total_sponsored, 2);
+
+    // Anyone can top it up
+    let top_up = system_instruction::transfer(&env.authority.pubkey(), &env.sponsor_pool, 3 * env.account_rent);
+    send(&mut env, &[top_up], &[]).await.unwrap();
+    onboard(&mut env, &mut third).await.unwrap();
+
+    // The authority takes back what's left, but not the pool's own rent
+    let spare = 2 * env.account_rent;
+    let withdraw = withdraw_pool(&env, spare + 1);
+    let error = send(&mut env, &[withdraw], &[]).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::InsufficientFunds.into()));
+    let sponsor_pool = env.sponsor_pool;
+    let pool_before = lamports(&mut env, &sponsor_pool).await;
+    let withdraw = withdraw_pool(&env, spare);
+    send(&mut env, &[withdraw], &[]).await.unwrap();
+    assert_eq!(lamports(&mut env, &sponsor_pool).await, pool_before - spare);
+
+    // Only the authority can withdraw
+    let stranger = Keypair::new();
+    let mut steal = withdraw_pool(&env, 1);
+    steal.accounts[1].pubkey = stranger.pubkey();
+    let transfer = system_instruction::transfer(&env.authority.pubkey(), &stranger.pubkey(), 1_000_000);
+    send(&mut env, &[transfer], &[]).await.unwrap();
+    assert!(send(&mut env, &[steal], &[&stranger]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_daily_limit_rolls_over() {
+    let mut env = setup(2, 10).await;
+    onboard(&mut env, &mut NewUser::new(1)).await.unwrap();
+    onboard(&mut env, &mut NewUser::new(2)).await.unwrap();
+
+    let mut late = NewUser::new(3);
+    let error = onboard(&mut env, &mut late).await.unwrap_err();
+    assert_eq!(error_code(error), Some(AttestaError::SponsorDailyLimitReached.into()));
+
+    // Just after midnight UTC the pool sponsors again
+    set_time(&mut env, START + SECONDS_PER_DAY / 2).await;
+    onboard(&mut env, &mut late).await.unwrap();
+    let pool = load_pool(&mut env).await;
+    assert_eq!((pool.sponsored_today, pool.total_sponsored), (1, 3));
+}
@@ -0,0 +1,307 @@
+//! Localnet tests for SPL token transfers out of an Attesta account
+//!
+//! These load the compiled program, so build it first with `anchor build`.
+
+use anchor_lang::{AnchorDeserialize, InstructionData, ToAccountMetas};
+use core_crypto::{compute_challenge, test_utils::TestPasskey};
+use recovery::{Amount, MintLimit, MintLimits, Policy};
+use smart_account::{
+    action_message_hash, registration_challenge, AccountSettings, ExecuteOutcome, TokenTransfer, TransactionRequest, MEMO_PROGRAM_ID,
+    SETTINGS_UPDATE_ACTION,
+};
+use solana_program_test::{BanksClient, ProgramTest};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+const DECIMALS: u8 = 6;
+const LIMIT: u64 = 100 * 10u64.pow(DECIMALS as u32);
+
+struct Env {
+    banks_client: BanksClient,
+    payer: Keypair,
+    blockhash: Hash,
+    passkey: TestPasskey,
+    attesta_account: Pubkey,
+    mint: Pubkey,
+    recipient_ata: Pubkey,
+}
+
+async fn send(env: &mut Env, instructions: &[Instruction], extra_signers: &[&Keypair]) -> Result<(), String> {
+    let mut signers: Vec<&Keypair> = vec![&env.payer];
+    signers.extend_from_slice(extra_signers);
+
+    env.blockhash = env.banks_client.get_latest_blockhash().await.map_err(|e| e.to_string())?;
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&env.payer.pubkey()),
+        &signers,
+        env.blockhash,
+    );
+    env.banks_client
+        .process_transaction(transaction)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn token_balance(env: &mut Env, token_account: &Pubkey) -> u64 {
+    let account = env.banks_client.get_account(*token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
+}
+
+/// Creates an Attesta account with a USDC-style limit, funded with 500 tokens
+async fn setup() -> Env {
+    let mut program_test = ProgramTest::new("attesta", attesta::ID, None);
+    program_test.prefer_bpf(true);
+    let (banks_client, payer, blockhash) = program_test.start().await;
+
+    let mint = Keypair::new();
+    let (attesta_account, _) =
+        Pubkey::find_program_address(&[b"attesta", payer.pubkey().as_ref()], &attesta::ID);
+    let recipient = Pubkey::new_unique();
+
+    let mut env = Env {
+        banks_client,
+        payer,
+        blockhash,
+        passkey: TestPasskey::new(1),
+        attesta_account,
+        mint: mint.pubkey(),
+        recipient_ata: get_associated_token_address(&recipient, &mint.pubkey()),
+    };
+
+    let policy = Policy::spending_limit(Amount::ZERO).with_mint_limits(MintLimits {
+        allow_unlisted: false,
+        limits: vec![MintLimit { mint: env.mint, max_amount: LIMIT, decimals: DECIMALS }],
+    });
+
+    let challenge = registration_challenge(
+        &env.payer.pubkey(),
+        &env.attesta_account,
+        &env.passkey.public_key(),
+        &env.passkey.credential_id(),
+    );
+    let registration_sig = env.passkey.sign_create(&challenge);
+    let initialize = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::Initialize {
+            attesta_account: env.attesta_account,
+            owner: env.payer.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::Initialize {
+            passkey_public_key: env.passkey.public_key(),
+            credential_id: env.passkey.credential_id(),
+            policy: policy.to_bytes().unwrap(),
+            privacy_mode: false,
+            registration_sig: registration_sig.to_bytes(),
+            aaguid_allowlist: vec![],
+        }
+        .data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send(&mut env, &[budget, initialize], &[]).await.unwrap();
+
+    // Create the test mint and token accounts for the PDA and the recipient
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let payer = env.payer.pubkey();
+    let source_ata = get_associated_token_address(&env.attesta_account, &env.mint);
+    let instructions = [
+        system_instruction::create_account(
+            &payer,
+            &env.mint,
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(&spl_token::id(), &env.mint, &payer, None, DECIMALS).unwrap(),
+        create_associated_token_account(&payer, &env.attesta_account, &env.mint, &spl_token::id()),
+        create_associated_token_account(&payer, &recipient, &env.mint, &spl_token::id()),
+        spl_token::instruction::mint_to(&spl_token::id(), &env.mint, &source_ata, &payer, &[], 5 * LIMIT).unwrap(),
+    ];
+    send(&mut env, &instructions, &[&mint]).await.unwrap();
+
+    env
+}
+
+/// Builds a passkey-signed `execute` for a token transfer to the recipient
+fn execute_transfer(env: &mut Env, nonce: u64, amount: u64) -> Vec<Instruction> {
+    execute_transfer_with_memo(env, nonce, amount, "", false)
+}
+
+/// `execute_transfer` with a signed memo, optionally posted through SPL Memo
+fn execute_transfer_with_memo(env: &mut Env, nonce: u64, amount: u64, memo: &str, emit_memo: bool) -> Vec<Instruction> {
+    let transfer = TokenTransfer {
+        mint: env.mint,
+        amount,
+        decimals: DECIMALS,
+        destination_ata: env.recipient_ata,
+    };
+    let request = TransactionRequest::from_token_transfer(transfer).memo(memo).unwrap();
+    let message_hash = request.message_hash();
+    let challenge = compute_challenge(&env.payer.pubkey(), nonce, &message_hash);
+    let webauthn_sig = env.passkey.sign(&challenge);
+
+    let mut accounts = attesta::accounts::Execute {
+        attesta_account: env.attesta_account,
+        authority: env.payer.pubkey(),
+        parent_account: None,
+        proof_log: None,
+        memo_program: emit_memo.then_some(MEMO_PROGRAM_ID),
+        proposal: None,
+        system_program: None,
+        announcement: None,
+    }
+    .to_account_metas(None);
+    accounts.extend([
+        AccountMeta::new(get_associated_token_address(&env.attesta_account, &env.mint), false),
+        AccountMeta::new_readonly(env.mint, false),
+        AccountMeta::new(env.recipient_ata, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    vec![
+        // P-256 verification needs more than the default compute budget
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+        Instruction {
+            program_id: attesta::ID,
+            accounts,
+            data: attesta::instruction::Execute {
+                webauthn_sig: webauthn_sig.to_bytes(),
+                nonce,
+                message_hash,
+                transaction_data: request.transaction_data,
+                idempotency_key: None,
+                memo: request.memo,
+                emit_memo,
+            }
+            .data(),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn test_token_transfer_under_limit() {
+    let mut env = setup().await;
+
+    let instructions = execute_transfer(&mut env, 1, LIMIT);
+    send(&mut env, &instructions, &[]).await.unwrap();
+
+    let recipient_ata = env.recipient_ata;
+    let source_ata = get_associated_token_address(&env.attesta_account, &env.mint);
+    assert_eq!(token_balance(&mut env, &recipient_ata).await, LIMIT);
+    assert_eq!(token_balance(&mut env, &source_ata).await, 4 * LIMIT);
+}
+
+#[tokio::test]
+async fn test_token_transfer_over_limit_denied() {
+    let mut env = setup().await;
+
+    let instructions = execute_transfer(&mut env, 1, LIMIT + 1);
+    assert!(send(&mut env, &instructions, &[]).await.is_err());
+
+    let recipient_ata = env.recipient_ata;
+    assert_eq!(token_balance(&mut env, &recipient_ata).await, 0);
+}
+
+#[tokio::test]
+async fn test_token_transfer_to_unsigned_destination_rejected() {
+    let mut env = setup().await;
+
+    // Swap the destination after signing - the program must refuse it
+    let mut instructions = execute_transfer(&mut env, 1, LIMIT);
+    let attacker_ata = Pubkey::new_unique();
+    let destination = instructions[1].accounts.iter().position(|meta| meta.pubkey == env.recipient_ata).unwrap();
+    instructions[1].accounts[destination] = AccountMeta::new(attacker_ata, false);
+
+    assert!(send(&mut env, &instructions, &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_token_transfer_with_memo() {
+    let mut env = setup().await;
+
+    // The memo is part of what was signed: attaching one afterwards fails
+    let mut instructions = execute_transfer(&mut env, 1, LIMIT);
+    let mut args = attesta::instruction::Execute::try_from_slice(&instructions[1].data[8..]).unwrap();
+    args.memo = b"order-1042".to_vec();
+    instructions[1].data = args.data();
+    assert!(send(&mut env, &instructions, &[]).await.is_err());
+
+    let instructions = execute_transfer_with_memo(&mut env, 1, LIMIT, "order-1042", true);
+    env.blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&instructions, Some(&env.payer.pubkey()), &[&env.payer], env.blockhash);
+    let result = env.banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+    assert!(result.result.is_ok());
+
+    let metadata = result.metadata.unwrap();
+    let outcome = ExecuteOutcome::from_return_data(&metadata.return_data.unwrap().data).unwrap();
+    assert_eq!(outcome.memo_hash, smart_account::memo_hash(b"order-1042"));
+    assert!(metadata.log_messages.iter().any(|log| log.contains(&format!("Program {} invoke", MEMO_PROGRAM_ID))));
+    assert!(metadata.log_messages.iter().any(|log| log.contains("order-1042")));
+}
+
+/// Runs `instructions`, returning how many events the program emitted
+async fn send_counting_events(env: &mut Env, instructions: &[Instruction]) -> usize {
+    env.blockhash = env.banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&env.payer.pubkey()), &[&env.payer], env.blockhash);
+    let result = env.banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+    assert!(result.result.is_ok());
+    result.metadata.unwrap().log_messages.iter().filter(|log| log.starts_with("Program data: ")).count()
+}
+
+#[tokio::test]
+async fn test_sampled_transfers_emit_their_context() {
+    let mut env = setup().await;
+
+    // Off by default: just `TransactionExecuted`
+    let instructions = execute_transfer(&mut env, 1, LIMIT / 4);
+    assert_eq!(send_counting_events(&mut env, &instructions).await, 1);
+
+    let settings = AccountSettings { log_allowed_sample_rate: 100, ..AccountSettings::default() };
+    let message_hash = action_message_hash(SETTINGS_UPDATE_ACTION, &settings.to_bytes());
+    let webauthn_sig = env.passkey.sign(&compute_challenge(&env.payer.pubkey(), 2, &message_hash));
+    let update_settings = Instruction {
+        program_id: attesta::ID,
+        accounts: attesta::accounts::ManagePasskeys {
+            attesta_account: env.attesta_account,
+            owner: env.payer.pubkey(),
+            system_program: solana_sdk::system_program::id(),
+        }
+        .to_account_metas(None),
+        data: attesta::instruction::UpdateSettings {
+            webauthn_sig: webauthn_sig.to_bytes(),
+            nonce: 2,
+            reject_zero_amount: false,
+            reject_self_transfer: false,
+            max_transaction_data_len: 0,
+            lockout_threshold: 0,
+            aaguid_allowlist: vec![],
+            pinned_program_version: None,
+            webauthn_profile: 0,
+            rp_id_hash: None,
+            origin_hash: None,
+            log_allowed_sample_rate: 100,
+            recovery_allowance: 0,
+            expected_version: 0,
+        }
+        .data(),
+    };
+    let budget = ComputeBudgetInstruction::set_compute_unit_limit(1_400_000);
+    send(&mut env, &[budget, update_settings], &[]).await.unwrap();
+
+    // Every transfer is sampled at 100%: `AllowedWithContext` follows
+    let instructions = execute_transfer(&mut env, 3, LIMIT / 4);
+    assert_eq!(send_counting_events(&mut env, &instructions).await, 2);
+}
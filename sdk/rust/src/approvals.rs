@@ -0,0 +1,193 @@
+//! Finding and approving proposals
+//!
+//! A transaction that needs more approvals than its signer gave waits in a
+//! proposal PDA (see `smart_account::proposal`) until the account's other
+//! passkeys approve it. `AttestaClient::list_pending_proposals` finds an
+//! account's proposals with one filtered `getProgramAccounts`, so a wallet
+//! can show them without knowing their addresses. `prepare_approval` then
+//! builds the passkey prompt for one of them, and `submit_approval` sends
+//! the signed approval.
+//!
+//! An approval is signed over the proposal's address and the transaction's
+//! message hash, so it can't be replayed against another proposal. It uses
+//! up a nonce like any other proof.
+
+use attesta_types::consts::ACCOUNT_DISCRIMINATOR_LEN;
+use borsh::BorshDeserialize;
+use recovery::credential_id_hash;
+use smart_account::{action_message_hash, TokenTransfer};
+use smart_account::proposal::{approve_proposal_payload, PendingTransaction, PROPOSAL_APPROVE_ACTION};
+use solana_program::pubkey::Pubkey;
+use crate::backend::DataFilter;
+use crate::client::AttestaError;
+use crate::instructions::account_discriminator;
+
+/// A proposal still collecting approvals, from `AttestaClient::list_pending_proposals`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProposalSummary {
+    /// The proposal PDA
+    pub address: Pubkey,
+
+    /// The transaction to execute, as `execute` takes it
+    pub transaction_data: Vec<u8>,
+
+    /// `transaction_message_hash` of `transaction_data`
+    pub message_hash: [u8; 32],
+
+    /// Base units a token transfer moves (`None` for other transactions)
+    pub amount: Option<u64>,
+
+    /// The mint a token transfer moves
+    pub mint: Option<Pubkey>,
+
+    /// The token account a token transfer pays into
+    pub destination: Option<Pubkey>,
+
+    /// SHA-256 of each credential ID that approved it, the proposer's included
+    pub approved_by: Vec<[u8; 32]>,
+
+    /// Approvals it needs before it can execute
+    pub required_approvals: u8,
+
+    /// When it was proposed (Unix timestamp)
+    pub created_at: i64,
+
+    /// When it stops taking approvals (Unix timestamp)
+    pub expires_at: i64,
+}
+
+impl ProposalSummary {
+    /// Summarizes the proposal stored at `address`
+    pub fn new(address: Pubkey, proposal: &PendingTransaction) -> Self {
+        let transfer = TokenTransfer::from_transaction_data(&proposal.transaction_data);
+        Self {
+            address,
+            transaction_data: proposal.transaction_data.clone(),
+            message_hash: proposal.message_hash,
+            amount: transfer.as_ref().map(|transfer| transfer.amount),
+            mint: transfer.as_ref().map(|transfer| transfer.mint),
+            destination: transfer.as_ref().map(|transfer| transfer.destination_ata),
+            approved_by: proposal.approvals.clone(),
+            required_approvals: proposal.required_approvals,
+            created_at: proposal.created_at,
+            expires_at: proposal.expires_at(),
+        }
+    }
+
+    /// Approvals collected so far
+    pub fn approvals(&self) -> usize {
+        self.approved_by.len()
+    }
+
+    /// Whether the passkey with `credential_id` has already approved it
+    pub fn has_approved(&self, credential_id: &[u8]) -> bool {
+        self.approved_by.contains(&credential_id_hash(credential_id))
+    }
+
+    /// The message hash a passkey signs to approve it
+    pub fn approval_message_hash(&self) -> [u8; 32] {
+        action_message_hash(PROPOSAL_APPROVE_ACTION, &approve_proposal_payload(&self.address, &self.message_hash))
+    }
+}
+
+/// The `getProgramAccounts` filters matching `attesta_account`'s proposals
+pub fn proposal_filters(attesta_account: &Pubkey) -> Vec<DataFilter> {
+    vec![
+        DataFilter::new(0, account_discriminator("ProposalData").to_vec()),
+        DataFilter::new(ACCOUNT_DISCRIMINATOR_LEN, attesta_account.to_bytes().to_vec()),
+    ]
+}
+
+/// Mirror of the program's `ProposalData` account layout
+#[derive(BorshDeserialize)]
+struct ProposalData {
+    attesta_account: Pubkey,
+    pending: Vec<u8>,
+    _bump: u8,
+}
+
+/// Decodes the raw data of a proposal account
+///
+/// # Returns
+/// The account the proposal belongs to, and the proposal
+pub fn decode_proposal(data: &[u8]) -> Result<(Pubkey, PendingTransaction), AttestaError> {
+    if data.len() < ACCOUNT_DISCRIMINATOR_LEN || data[..ACCOUNT_DISCRIMINATOR_LEN] != account_discriminator("ProposalData") {
+        return Err(AttestaError::InvalidAccountData);
+    }
+
+    let mut body = &data[ACCOUNT_DISCRIMINATOR_LEN..];
+    let wrapper = ProposalData::deserialize(&mut body)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+    let pending = PendingTransaction::from_bytes(&wrapper.pending).map_err(|_| AttestaError::InvalidAccountData)?;
+    Ok((wrapper.attesta_account, pending))
+}
+
+/// Summarizes the proposals of `attesta_account` that can still be approved at `now`
+///
+/// Expired proposals, proposals that already have their approvals, and
+/// anything that isn't one of `attesta_account`'s proposals are left out.
+/// The rest come soonest to expire first.
+pub fn pending_proposals(attesta_account: &Pubkey, accounts: &[(Pubkey, Vec<u8>)], now: i64) -> Vec<ProposalSummary> {
+    let mut summaries: Vec<ProposalSummary> = accounts
+        .iter()
+        .filter_map(|(address, data)| {
+            let (owner, proposal) = decode_proposal(data).ok()?;
+            let open = owner == *attesta_account && !proposal.is_expired(now) && !proposal.threshold_reached();
+            open.then(|| ProposalSummary::new(*address, &proposal))
+        })
+        .collect();
+    summaries.sort_by_key(|summary| (summary.expires_at, summary.address));
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::proposal_data;
+    use smart_account::proposal::PROPOSAL_LIFETIME;
+
+    fn proposal(transaction_data: Vec<u8>, approvals: usize, created_at: i64) -> PendingTransaction {
+        PendingTransaction {
+            message_hash: smart_account::transaction_message_hash(&transaction_data),
+            policy_hash: [0; 32],
+            transaction_data,
+            proposer: [1; 32],
+            rent_payer: Pubkey::new_unique(),
+            approvals: (0..approvals as u8).map(|i| [i + 1; 32]).collect(),
+            required_approvals: 2,
+            reserved: 0,
+            created_at,
+        }
+    }
+
+    #[test]
+    fn test_summary_reads_token_transfers() {
+        let transfer = TokenTransfer { mint: Pubkey::new_unique(), amount: 5_000, decimals: 6, destination_ata: Pubkey::new_unique() };
+        let address = Pubkey::new_unique();
+        let summary = ProposalSummary::new(address, &proposal(transfer.to_transaction_data(), 1, 100));
+        assert_eq!(summary.amount, Some(5_000));
+        assert_eq!(summary.mint, Some(transfer.mint));
+        assert_eq!(summary.destination, Some(transfer.destination_ata));
+        assert_eq!((summary.approvals(), summary.required_approvals), (1, 2));
+        assert_eq!(summary.expires_at, 100 + PROPOSAL_LIFETIME);
+
+        let other = ProposalSummary::new(address, &proposal(vec![7; 40], 1, 100));
+        assert_eq!((other.amount, other.mint, other.destination), (None, None, None));
+    }
+
+    #[test]
+    fn test_pending_proposals_skip_closed_and_foreign_ones() {
+        let account = Pubkey::new_unique();
+        let [open, expired, approved, foreign] = [(); 4].map(|_| Pubkey::new_unique());
+        let accounts = vec![
+            (open, proposal_data(&account, &proposal(vec![1; 40], 1, 1_000))),
+            (expired, proposal_data(&account, &proposal(vec![2; 40], 1, 0))),
+            (approved, proposal_data(&account, &proposal(vec![3; 40], 2, 1_000))),
+            (foreign, proposal_data(&Pubkey::new_unique(), &proposal(vec![4; 40], 1, 1_000))),
+            (Pubkey::new_unique(), vec![0; 20]),
+        ];
+
+        let pending = pending_proposals(&account, &accounts, PROPOSAL_LIFETIME);
+        assert_eq!(pending.iter().map(|summary| summary.address).collect::<Vec<_>>(), vec![open]);
+    }
+}
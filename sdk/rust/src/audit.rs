@@ -0,0 +1,370 @@
+//! Re-verifying archived proofs offline
+//!
+//! An auditor with an archive of submitted `ProofEnvelope`s, and the
+//! account data each one was checked against, can re-run the program's
+//! signature checks without a cluster. Each proof is verified against the
+//! key its snapshot holds, so proofs made before a passkey was rotated out
+//! still verify.
+//!
+//! With the `parallel` feature, `verify_archive` spreads the work over
+//! rayon's thread pool; the report is the same either way.
+//!
+//! `detect_unknown_credentials` checks execution receipts the other way
+//! round: it flags every execution signed by a credential that isn't one
+//! of the account's passkeys now.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use smart_account::{
+    detect_attesta_account, resolve_signing_key, verify_passkey_authorization, AttestaAccount, ExecutionReceipt,
+    ExecutionStatus,
+};
+use crate::signing::ProofEnvelope;
+
+/// One archived execution to re-verify
+#[derive(Debug, Clone)]
+pub struct ArchivedProof {
+    /// The proof as it was submitted
+    pub envelope: ProofEnvelope,
+
+    /// The account's data just before the proof executed, in any layout the
+    /// program has stored accounts in
+    pub account_snapshot: Vec<u8>,
+
+    /// The hash of the transaction the proof should authorize, from the
+    /// archived transaction itself
+    pub expected_message_hash: [u8; 32],
+}
+
+/// Why an archived proof didn't verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AuditFailure {
+    /// The snapshot isn't an Attesta account
+    InvalidSnapshot,
+
+    /// The proof signs a different transaction than the archive says ran
+    MessageMismatch,
+
+    /// The proof's nonce wasn't above the snapshot's, so it couldn't have executed
+    Expired,
+
+    /// The signing credential isn't an active passkey in the snapshot
+    UnknownCredential,
+
+    /// The signature doesn't verify with the snapshot's key for the credential
+    InvalidSignature,
+}
+
+impl fmt::Display for AuditFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuditFailure::InvalidSnapshot => "invalid account snapshot",
+            AuditFailure::MessageMismatch => "message hash mismatch",
+            AuditFailure::Expired => "nonce not above the snapshot's",
+            AuditFailure::UnknownCredential => "unknown credential",
+            AuditFailure::InvalidSignature => "invalid signature",
+        })
+    }
+}
+
+/// An execution signed by a credential the account doesn't hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownCredentialUse {
+    /// The nonce the execution consumed
+    pub nonce: u64,
+
+    /// SHA-256 of the credential ID that signed it
+    pub credential_id_hash: [u8; 32],
+
+    /// Whether the account ever had the credential
+    pub kind: UnknownCredentialKind,
+}
+
+/// What the account knows about a credential that isn't one of its passkeys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownCredentialKind {
+    /// A passkey the account has since removed, at `revoked_at` (an
+    /// execution from before then was legitimate at the time)
+    Removed { revoked_at: i64 },
+
+    /// Nothing the account holds or has a tombstone for; old tombstones are
+    /// evicted, so this can also be a passkey removed long ago
+    Unknown,
+}
+
+impl fmt::Display for UnknownCredentialUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nonce {}: credential ", self.nonce)?;
+        for byte in &self.credential_id_hash[..8] {
+            write!(f, "{:02x}", byte)?;
+        }
+        match self.kind {
+            UnknownCredentialKind::Removed { revoked_at } => write!(f, "... was removed at {}", revoked_at),
+            UnknownCredentialKind::Unknown => f.write_str("... is unknown to this account"),
+        }
+    }
+}
+
+/// Finds the executions in `receipts` that `account`'s passkeys didn't sign
+///
+/// Each executed receipt's credential hash is looked up in the account's
+/// passkeys, then in its removal tombstones. Receipts without a hash (the
+/// owner's wallet signed, a retry answered from the records, or a program
+/// from before receipts carried one) and receipts of executions that
+/// didn't run are skipped.
+pub fn detect_unknown_credentials(account: &AttestaAccount, receipts: &[ExecutionReceipt]) -> Vec<UnknownCredentialUse> {
+    receipts
+        .iter()
+        .filter(|receipt| receipt.status == ExecutionStatus::Executed)
+        .filter_map(|receipt| {
+            let hash = receipt.credential_id_hash?;
+            if account.holds_credential_hash(&hash) {
+                return None;
+            }
+            let kind = match account.credential_revoked_at(&hash) {
+                Some(revoked_at) => UnknownCredentialKind::Removed { revoked_at },
+                None => UnknownCredentialKind::Unknown,
+            };
+            Some(UnknownCredentialUse { nonce: receipt.nonce, credential_id_hash: hash, kind })
+        })
+        .collect()
+}
+
+/// The result of auditing an archive
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Proofs checked
+    pub checked: usize,
+
+    /// Failures of each kind
+    pub counts: BTreeMap<AuditFailure, usize>,
+
+    /// Each failing proof's position in the archive and why it failed, in order
+    pub failures: Vec<(usize, AuditFailure)>,
+}
+
+impl AuditReport {
+    fn from_results(results: Vec<Result<(), AuditFailure>>) -> Self {
+        let mut report = Self { checked: results.len(), ..Self::default() };
+        for (index, result) in results.into_iter().enumerate() {
+            if let Err(failure) = result {
+                *report.counts.entry(failure).or_default() += 1;
+                report.failures.push((index, failure));
+            }
+        }
+        report
+    }
+
+    /// Whether every proof verified
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Positions of the proofs that failed, in order
+    pub fn failing_indices(&self) -> Vec<usize> {
+        self.failures.iter().map(|(index, _)| *index).collect()
+    }
+}
+
+/// Re-verifies one archived proof
+///
+/// Runs the checks the program ran, in its order, against the snapshot
+/// rather than the live account.
+pub fn verify_archived(proof: &ArchivedProof) -> Result<(), AuditFailure> {
+    let (account, _) = detect_attesta_account(&proof.account_snapshot).map_err(|_| AuditFailure::InvalidSnapshot)?;
+    verify_against(&account, proof)
+}
+
+fn verify_against(account: &AttestaAccount, proof: &ArchivedProof) -> Result<(), AuditFailure> {
+    let envelope = &proof.envelope;
+    if envelope.message_hash != proof.expected_message_hash {
+        return Err(AuditFailure::MessageMismatch);
+    }
+    if !account.validate_nonce(envelope.nonce) {
+        return Err(AuditFailure::Expired);
+    }
+    resolve_signing_key(account, &envelope.webauthn_sig.credential_id).map_err(|_| AuditFailure::UnknownCredential)?;
+    verify_passkey_authorization(account, &envelope.webauthn_sig, envelope.nonce, &envelope.message_hash)
+        .map_err(|_| AuditFailure::InvalidSignature)
+}
+
+/// Re-verifies every proof in `entries` and reports the failures
+///
+/// In parallel with the `parallel` feature, otherwise one at a time.
+pub fn verify_archive(entries: impl IntoIterator<Item = ArchivedProof>) -> AuditReport {
+    #[cfg(feature = "parallel")]
+    return verify_archive_parallel(entries);
+
+    #[cfg(not(feature = "parallel"))]
+    verify_archive_serial(entries)
+}
+
+/// Re-verifies every proof in `entries` on the calling thread
+pub fn verify_archive_serial(entries: impl IntoIterator<Item = ArchivedProof>) -> AuditReport {
+    AuditReport::from_results(entries.into_iter().map(|proof| verify_archived(&proof)).collect())
+}
+
+/// Re-verifies every proof in `entries` on rayon's thread pool
+#[cfg(feature = "parallel")]
+pub fn verify_archive_parallel(entries: impl IntoIterator<Item = ArchivedProof>) -> AuditReport {
+    use rayon::prelude::*;
+
+    let entries: Vec<ArchivedProof> = entries.into_iter().collect();
+    AuditReport::from_results(entries.par_iter().map(verify_archived).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::credential_id_hash;
+    use smart_account::encode_attesta_account;
+    use solana_program::pubkey::Pubkey;
+
+    struct Archive {
+        owner: Pubkey,
+        phone: TestPasskey,
+    }
+
+    impl Archive {
+        /// A proof from the phone over `message_hash` with `nonce`, against a
+        /// snapshot of the account holding `key` at `account_nonce`
+        fn proof(&mut self, key: [u8; 64], account_nonce: u64, nonce: u64, message_hash: [u8; 32]) -> ArchivedProof {
+            let mut account = AttestaAccount::new(self.owner, key, self.phone.credential_id(), vec![], 100);
+            account.nonce = account_nonce;
+            let webauthn_sig = self.phone.sign(&compute_challenge(&self.owner, nonce, &message_hash));
+            ArchivedProof {
+                envelope: ProofEnvelope {
+                    webauthn_sig,
+                    nonce,
+                    message_hash,
+                    idempotency_key: [0; 16],
+                    parent_account: None,
+                    logs_proofs: false,
+                    memo: Vec::new(),
+                    emit_memo: false,
+                },
+                account_snapshot: encode_attesta_account(&account).unwrap(),
+                expected_message_hash: message_hash,
+            }
+        }
+    }
+
+    /// One proof of each kind, in the order of `expected`
+    fn mixed_batch() -> (Vec<ArchivedProof>, Vec<Result<(), AuditFailure>>) {
+        let mut archive = Archive { owner: Pubkey::new_unique(), phone: TestPasskey::new(1) };
+        let phone_key = archive.phone.public_key();
+        let laptop_key = TestPasskey::new(2).public_key();
+
+        let valid = archive.proof(phone_key, 0, 1, [1; 32]);
+        let later = archive.proof(phone_key, 6, 7, [2; 32]);
+        let expired = archive.proof(phone_key, 5, 3, [3; 32]);
+
+        // Signed by the phone, checked against the account after the credential
+        // was re-registered with the laptop's key, as the live account would be
+        let wrong_key = archive.proof(laptop_key, 0, 1, [4; 32]);
+
+        let mut tampered = archive.proof(phone_key, 0, 1, [5; 32]);
+        tampered.expected_message_hash = [6; 32];
+
+        let mut unknown_credential = archive.proof(phone_key, 0, 1, [7; 32]);
+        unknown_credential.envelope.webauthn_sig.credential_id = b"someone else".to_vec();
+
+        let mut garbage = archive.proof(phone_key, 0, 1, [8; 32]);
+        garbage.account_snapshot = vec![1, 2, 3];
+
+        (
+            vec![valid, later, expired, wrong_key, tampered, unknown_credential, garbage],
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(AuditFailure::Expired),
+                Err(AuditFailure::InvalidSignature),
+                Err(AuditFailure::MessageMismatch),
+                Err(AuditFailure::UnknownCredential),
+                Err(AuditFailure::InvalidSnapshot),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_mixed_batch_is_categorized() {
+        let (batch, expected) = mixed_batch();
+        for (proof, expected) in batch.iter().zip(&expected) {
+            assert_eq!(verify_archived(proof), *expected);
+        }
+
+        let report = verify_archive(batch);
+        assert_eq!(report.checked, 7);
+        assert!(!report.is_clean());
+        assert_eq!(report.failing_indices(), vec![2, 3, 4, 5, 6]);
+        assert_eq!(report.failures[1], (3, AuditFailure::InvalidSignature));
+        assert!(report.counts.values().all(|count| *count == 1));
+        assert_eq!(report.counts.len(), 5);
+
+        assert!(verify_archive(Vec::new()).is_clean());
+    }
+
+    #[test]
+    fn test_unknown_credentials_are_flagged() {
+        let phone = TestPasskey::new(1);
+        let laptop = TestPasskey::new(2);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), phone.public_key(), phone.credential_id(), vec![], 100);
+        let mut registry = account.passkey_registry_or_default().unwrap();
+        registry.add_passkey(laptop.public_key(), laptop.credential_id(), "Laptop".to_string(), 110).unwrap();
+        registry.remove_passkey(&laptop.credential_id(), 150).unwrap();
+        account.set_passkey_registry(&registry).unwrap();
+
+        let receipt = |nonce, status, credential_id: Option<&[u8]>| ExecutionReceipt {
+            status,
+            nonce,
+            memo_hash: None,
+            credential_id_hash: credential_id.map(credential_id_hash),
+        };
+        let receipts = [
+            receipt(1, ExecutionStatus::Executed, Some(phone.credential_id().as_slice())),
+            receipt(2, ExecutionStatus::Executed, Some(laptop.credential_id().as_slice())),
+            receipt(3, ExecutionStatus::Executed, Some(b"stranger".as_slice())),
+            // Signed by the owner's wallet, or refused: nothing to check
+            receipt(4, ExecutionStatus::Executed, None),
+            receipt(4, ExecutionStatus::AuthenticationFailed, Some(b"stranger".as_slice())),
+        ];
+
+        let findings = detect_unknown_credentials(&account, &receipts);
+        assert_eq!(
+            findings,
+            vec![
+                UnknownCredentialUse {
+                    nonce: 2,
+                    credential_id_hash: credential_id_hash(&laptop.credential_id()),
+                    kind: UnknownCredentialKind::Removed { revoked_at: 150 },
+                },
+                UnknownCredentialUse {
+                    nonce: 3,
+                    credential_id_hash: credential_id_hash(b"stranger"),
+                    kind: UnknownCredentialKind::Unknown,
+                },
+            ]
+        );
+        assert!(findings[1].to_string().ends_with("is unknown to this account"));
+
+        // The same answers once the account hashes its credential IDs
+        account.enable_privacy_mode().unwrap();
+        assert_eq!(detect_unknown_credentials(&account, &receipts), findings);
+    }
+
+    #[test]
+    fn test_parallel_and_serial_agree() {
+        let (batch, _) = mixed_batch();
+        let large: Vec<ArchivedProof> = batch.iter().cycle().take(batch.len() * 20).cloned().collect();
+
+        let serial = verify_archive_serial(large.clone());
+        assert_eq!(serial.checked, 140);
+        assert_eq!(serial.counts[&AuditFailure::Expired], 20);
+        assert_eq!(serial.failing_indices()[..5], [2, 3, 4, 5, 6]);
+        assert_eq!(verify_archive(large.clone()), serial);
+
+        #[cfg(feature = "parallel")]
+        assert_eq!(verify_archive_parallel(large), serial);
+    }
+}
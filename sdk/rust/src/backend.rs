@@ -0,0 +1,371 @@
+//! The RPC calls the SDK makes, behind a trait
+//!
+//! `AttestaClient` talks to the chain only through `RpcBackend`. The default
+//! `SolanaRpcBackend` uses a JSON-RPC connection; implement the trait to use
+//! a custom HTTP client, a proxy, or batching. `MockBackend` (with the
+//! `test-utils` feature) answers from canned data for tests.
+
+use anchor_client::{
+    solana_client::{
+        rpc_client::RpcClient,
+        rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+        rpc_filter::{Memcmp, RpcFilterType},
+        rpc_request::TokenAccountsFilter,
+        rpc_response::RpcKeyedAccount,
+    },
+    solana_sdk::{
+        commitment_config::{CommitmentConfig, CommitmentLevel},
+        hash::Hash,
+        signature::Signature,
+        transaction::{Transaction, TransactionError, VersionedTransaction},
+    },
+};
+use base64::Engine;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_transaction_status::UiTransactionEncoding;
+use solana_program::pubkey::Pubkey;
+use crate::client::AttestaError;
+
+/// The outcome of simulating a transaction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulationResult {
+    /// The transaction error, if the simulation failed
+    pub err: Option<String>,
+
+    /// Program log messages
+    pub logs: Vec<String>,
+
+    /// Compute units the transaction used
+    pub units_consumed: Option<u64>,
+
+    /// Return data set by the last program that set any (decoded)
+    pub return_data: Option<Vec<u8>>,
+}
+
+/// A transaction as it landed, with what the replay needs from its metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfirmedTransaction {
+    /// The transaction's signature
+    pub signature: Signature,
+
+    /// The slot it landed in
+    pub slot: u64,
+
+    /// When its block was produced (Unix timestamp), if the cluster knows
+    pub block_time: Option<i64>,
+
+    /// The transaction itself
+    pub transaction: Transaction,
+
+    /// The transaction error, if it failed
+    pub err: Option<String>,
+
+    /// Program log messages (including emitted events)
+    pub logs: Vec<String>,
+}
+
+/// A condition on an account's data: `bytes` at `offset` (a `memcmp` filter)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFilter {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl DataFilter {
+    pub fn new(offset: usize, bytes: impl Into<Vec<u8>>) -> Self {
+        Self { offset, bytes: bytes.into() }
+    }
+
+    /// Whether `data` holds `bytes` at `offset`
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.get(self.offset..).is_some_and(|rest| rest.starts_with(&self.bytes))
+    }
+}
+
+/// The chain access `AttestaClient` needs
+///
+/// Methods return `AttestaError::RpcError` for transport failures. Missing
+/// accounts aren't errors: they come back as `None`. Backends are shared
+/// between threads along with the client, so they must be `Send + Sync`.
+pub trait RpcBackend: Send + Sync {
+    /// Fetches an account's data
+    fn get_account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>, AttestaError>;
+
+    /// Fetches an account's data along with the slot it was read at
+    fn get_account_data_with_slot(&self, address: &Pubkey) -> Result<(Option<Vec<u8>>, u64), AttestaError>;
+
+    /// Fetches an account's balance in lamports
+    fn get_lamports(&self, address: &Pubkey) -> Result<Option<u64>, AttestaError>;
+
+    /// Lists the token accounts owned by `owner` under `token_program` (`jsonParsed`)
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        token_program: &Pubkey,
+    ) -> Result<Vec<RpcKeyedAccount>, AttestaError>;
+
+    /// Lists every account owned by `program_id`
+    fn get_program_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError>;
+
+    /// Lists the accounts owned by `program_id` whose data matches every filter
+    ///
+    /// The default lists them all and filters here. Backends that can
+    /// should have the node filter, so only the matches are sent.
+    fn get_program_accounts_matching(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        let mut accounts = self.get_program_accounts(program_id)?;
+        accounts.retain(|(_, data)| filters.iter().all(|filter| filter.matches(data)));
+        Ok(accounts)
+    }
+
+    /// Lists the accounts `get_program_accounts_matching` would, with only
+    /// `length` bytes of each account's data from `offset` (a `dataSlice`)
+    ///
+    /// Data shorter than the slice comes back short. The default lists the
+    /// accounts in full and slices them here; backends that can should
+    /// have the node slice, so only the slices are sent.
+    fn get_program_account_slices(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+        offset: usize,
+        length: usize,
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        let accounts = self.get_program_accounts_matching(program_id, filters)?;
+        Ok(accounts
+            .into_iter()
+            .map(|(address, data)| (address, data.iter().skip(offset).take(length).copied().collect()))
+            .collect())
+    }
+
+    /// Fetches several accounts' data, one entry per address (`None` for a missing account)
+    ///
+    /// The default fetches them one at a time.
+    fn get_multiple_account_data(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Vec<u8>>>, AttestaError> {
+        addresses.iter().map(|address| self.get_account_data(address)).collect()
+    }
+
+    /// Fetches a recent blockhash to sign transactions with
+    fn get_latest_blockhash(&self) -> Result<Hash, AttestaError>;
+
+    /// Submits a signed transaction, without waiting for it to land
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AttestaError>;
+
+    /// Looks up where each of `signatures` stands
+    ///
+    /// One entry per signature: `None` until it reaches `commitment`, then
+    /// the transaction's result.
+    fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+        commitment: CommitmentLevel,
+    ) -> Result<Vec<Option<Result<(), TransactionError>>>, AttestaError>;
+
+    /// Checks whether a transaction signed over `blockhash` can still land
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, AttestaError>;
+
+    /// Simulates a transaction without submitting it
+    fn simulate_transaction(&self, transaction: &Transaction) -> Result<SimulationResult, AttestaError>;
+
+    /// Fetches a confirmed transaction
+    ///
+    /// Fails for transactions the node doesn't have, and for versioned
+    /// transactions that use address lookup tables.
+    fn get_transaction(&self, signature: &Signature) -> Result<ConfirmedTransaction, AttestaError>;
+
+    /// Asks the cluster's faucet for `lamports`, without waiting for them to land
+    ///
+    /// Only localnet, devnet and testnet have a faucet. Backends that can't
+    /// reach one keep this default, which fails.
+    fn request_airdrop(&self, address: &Pubkey, lamports: u64) -> Result<Signature, AttestaError> {
+        let _ = (address, lamports);
+        Err(AttestaError::RpcError("this backend can't request airdrops".to_string()))
+    }
+}
+
+/// `RpcBackend` over a Solana JSON-RPC connection
+pub struct SolanaRpcBackend {
+    rpc: RpcClient,
+}
+
+impl SolanaRpcBackend {
+    /// Connects to the JSON-RPC endpoint at `url`
+    pub fn new(url: String) -> Self {
+        Self { rpc: RpcClient::new(url) }
+    }
+
+    /// Wraps an existing RPC client
+    pub fn from_rpc_client(rpc: RpcClient) -> Self {
+        Self { rpc }
+    }
+}
+
+fn rpc_error<E: std::fmt::Display>(e: E) -> AttestaError {
+    AttestaError::RpcError(e.to_string())
+}
+
+fn memcmp_filters(filters: &[DataFilter]) -> Vec<RpcFilterType> {
+    filters
+        .iter()
+        .map(|filter| RpcFilterType::Memcmp(Memcmp::new_raw_bytes(filter.offset, filter.bytes.clone())))
+        .collect()
+}
+
+impl RpcBackend for SolanaRpcBackend {
+    fn get_account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>, AttestaError> {
+        let response = self.rpc
+            .get_account_with_commitment(address, self.rpc.commitment())
+            .map_err(rpc_error)?;
+        Ok(response.value.map(|account| account.data))
+    }
+
+    fn get_account_data_with_slot(&self, address: &Pubkey) -> Result<(Option<Vec<u8>>, u64), AttestaError> {
+        let response = self.rpc
+            .get_account_with_commitment(address, self.rpc.commitment())
+            .map_err(rpc_error)?;
+        Ok((response.value.map(|account| account.data), response.context.slot))
+    }
+
+    fn get_lamports(&self, address: &Pubkey) -> Result<Option<u64>, AttestaError> {
+        let response = self.rpc
+            .get_account_with_commitment(address, self.rpc.commitment())
+            .map_err(rpc_error)?;
+        Ok(response.value.map(|account| account.lamports))
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        token_program: &Pubkey,
+    ) -> Result<Vec<RpcKeyedAccount>, AttestaError> {
+        self.rpc
+            .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(*token_program))
+            .map_err(rpc_error)
+    }
+
+    fn get_program_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        let accounts = self.rpc.get_program_accounts(program_id).map_err(rpc_error)?;
+        Ok(accounts.into_iter().map(|(address, account)| (address, account.data)).collect())
+    }
+
+    fn get_program_accounts_matching(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(memcmp_filters(filters)),
+            account_config: RpcAccountInfoConfig { encoding: Some(UiAccountEncoding::Base64), ..Default::default() },
+            ..Default::default()
+        };
+        let accounts = self.rpc.get_program_accounts_with_config(program_id, config).map_err(rpc_error)?;
+        Ok(accounts.into_iter().map(|(address, account)| (address, account.data)).collect())
+    }
+
+    fn get_program_account_slices(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+        offset: usize,
+        length: usize,
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(memcmp_filters(filters)),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig { offset, length }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let accounts = self.rpc.get_program_accounts_with_config(program_id, config).map_err(rpc_error)?;
+        Ok(accounts.into_iter().map(|(address, account)| (address, account.data)).collect())
+    }
+
+    fn get_multiple_account_data(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Vec<u8>>>, AttestaError> {
+        let accounts = self.rpc.get_multiple_accounts(addresses).map_err(rpc_error)?;
+        Ok(accounts.into_iter().map(|account| account.map(|account| account.data)).collect())
+    }
+
+    fn get_latest_blockhash(&self) -> Result<Hash, AttestaError> {
+        self.rpc.get_latest_blockhash().map_err(rpc_error)
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AttestaError> {
+        self.rpc.send_transaction(transaction).map_err(|e| match e.get_transaction_error() {
+            // Failed preflight: report it as the program's error, as if it had landed
+            Some(failure) => AttestaError::TransactionFailed(failure),
+            None => rpc_error(e),
+        })
+    }
+
+    fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+        commitment: CommitmentLevel,
+    ) -> Result<Vec<Option<Result<(), TransactionError>>>, AttestaError> {
+        let statuses = self.rpc.get_signature_statuses(signatures).map_err(rpc_error)?.value;
+        Ok(statuses
+            .into_iter()
+            .map(|status| {
+                status
+                    .filter(|status| status.satisfies_commitment(CommitmentConfig { commitment }))
+                    .map(|status| status.status)
+            })
+            .collect())
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, AttestaError> {
+        self.rpc
+            .is_blockhash_valid(blockhash, CommitmentConfig::processed())
+            .map_err(rpc_error)
+    }
+
+    fn simulate_transaction(&self, transaction: &Transaction) -> Result<SimulationResult, AttestaError> {
+        let result = self.rpc.simulate_transaction(transaction).map_err(rpc_error)?.value;
+
+        // Return data comes base64-encoded
+        let return_data = match result.return_data {
+            Some(return_data) => Some(
+                base64::engine::general_purpose::STANDARD
+                    .decode(&return_data.data.0)
+                    .map_err(rpc_error)?,
+            ),
+            None => None,
+        };
+
+        Ok(SimulationResult {
+            err: result.err.map(|e| e.to_string()),
+            logs: result.logs.unwrap_or_default(),
+            units_consumed: result.units_consumed,
+            return_data,
+        })
+    }
+
+    fn get_transaction(&self, signature: &Signature) -> Result<ConfirmedTransaction, AttestaError> {
+        let confirmed = self.rpc.get_transaction(signature, UiTransactionEncoding::Base64).map_err(rpc_error)?;
+        let transaction = confirmed
+            .transaction
+            .transaction
+            .decode()
+            .and_then(VersionedTransaction::into_legacy_transaction)
+            .ok_or_else(|| AttestaError::RpcError(format!("can't decode transaction {}", signature)))?;
+        let meta = confirmed.transaction.meta;
+
+        Ok(ConfirmedTransaction {
+            signature: *signature,
+            slot: confirmed.slot,
+            block_time: confirmed.block_time,
+            transaction,
+            err: meta.as_ref().and_then(|meta| meta.err.as_ref()).map(|e| e.to_string()),
+            logs: meta.and_then(|meta| Option::from(meta.log_messages)).unwrap_or_default(),
+        })
+    }
+
+    fn request_airdrop(&self, address: &Pubkey, lamports: u64) -> Result<Signature, AttestaError> {
+        self.rpc.request_airdrop(address, lamports).map_err(rpc_error)
+    }
+}
@@ -0,0 +1,206 @@
+//! Balances and deposit addresses for Attesta accounts
+//!
+//! An Attesta account's PDA is the user's wallet address: SOL is sent to it
+//! directly, and SPL tokens go to its associated token accounts (ATAs).
+
+use anchor_client::solana_client::rpc_response::RpcKeyedAccount;
+use solana_account_decoder::UiAccountData;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use recovery::Amount;
+pub use smart_account::{derive_associated_token_address, ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use crate::client::AttestaError;
+
+/// Size of an SPL token account, in bytes
+pub const TOKEN_ACCOUNT_LEN: usize = 165;
+
+/// Associated Token Account program instruction index for `CreateIdempotent`
+const CREATE_IDEMPOTENT: u8 = 1;
+
+/// Everything an Attesta account holds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Balances {
+    /// SOL balance (zero if the account doesn't exist yet)
+    pub sol: Amount,
+
+    /// SPL token balances, one entry per mint
+    pub tokens: Vec<TokenBalance>,
+}
+
+/// The balance of one SPL token
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBalance {
+    /// The token mint
+    pub mint: Pubkey,
+
+    /// The raw amount, in the token's smallest unit
+    pub amount: u64,
+
+    /// The mint's decimals (for display: `amount / 10^decimals`)
+    pub decimals: u8,
+}
+
+/// Builds `Balances` from RPC results
+///
+/// # Parameters
+/// - `sol_lamports`: The account's lamports, or `None` if it doesn't exist
+/// - `token_accounts`: The `getTokenAccountsByOwner` result (`jsonParsed` encoding)
+///
+/// Several token accounts can hold the same mint (the ATA plus any others
+/// someone created); their amounts are summed into a single entry.
+pub fn balances_from_rpc(
+    sol_lamports: Option<u64>,
+    token_accounts: &[RpcKeyedAccount],
+) -> Result<Balances, AttestaError> {
+    let mut tokens: Vec<TokenBalance> = Vec::new();
+
+    for keyed in token_accounts {
+        let balance = parse_token_account(&keyed.account.data)
+            .ok_or_else(|| AttestaError::RpcError(format!("Unexpected token account data for {}", keyed.pubkey)))?;
+
+        match tokens.iter_mut().find(|t| t.mint == balance.mint) {
+            // Amounts of one mint can't exceed its supply (a u64), so this can't saturate in practice
+            Some(existing) => existing.amount = existing.amount.saturating_add(balance.amount),
+            None => tokens.push(balance),
+        }
+    }
+
+    Ok(Balances {
+        sol: Amount::from_lamports(sol_lamports.unwrap_or(0)),
+        tokens,
+    })
+}
+
+/// Builds the instruction creating `wallet`'s associated token account for `mint`
+///
+/// Uses `CreateIdempotent`, so it succeeds (and charges nothing) if the
+/// account was created in the meantime.
+///
+/// # Parameters
+/// - `funder`: Pays the new account's rent (signer)
+/// - `wallet`: The token account's owner
+/// - `mint`: The token mint
+pub fn create_associated_token_account(funder: &Pubkey, wallet: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*funder, true),
+            AccountMeta::new(derive_associated_token_address(wallet, mint), false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ],
+        data: vec![CREATE_IDEMPOTENT],
+    }
+}
+
+/// Reads mint, amount, and decimals from a `jsonParsed` token account
+fn parse_token_account(data: &UiAccountData) -> Option<TokenBalance> {
+    let parsed = match data {
+        UiAccountData::Json(parsed) => &parsed.parsed,
+        _ => return None,
+    };
+
+    let info = parsed.get("info")?;
+    let token_amount = info.get("tokenAmount")?;
+
+    Some(TokenBalance {
+        mint: info.get("mint")?.as_str()?.parse().ok()?,
+        // The RPC sends amounts as strings so they don't lose precision in JSON
+        amount: token_amount.get("amount")?.as_str()?.parse().ok()?,
+        decimals: u8::try_from(token_amount.get("decimals")?.as_u64()?).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_account_decoder::{parse_account_data::ParsedAccount, UiAccount};
+
+    fn token_account(mint: &Pubkey, amount: u64, decimals: u8) -> RpcKeyedAccount {
+        let parsed = serde_json::json!({
+            "type": "account",
+            "info": {
+                "mint": mint.to_string(),
+                "owner": Pubkey::new_unique().to_string(),
+                "state": "initialized",
+                "isNative": false,
+                "tokenAmount": {
+                    "amount": amount.to_string(),
+                    "decimals": decimals,
+                    "uiAmountString": "",
+                },
+            },
+        });
+
+        RpcKeyedAccount {
+            pubkey: Pubkey::new_unique().to_string(),
+            account: UiAccount {
+                lamports: 2_039_280,
+                data: UiAccountData::Json(ParsedAccount {
+                    program: "spl-token".to_string(),
+                    parsed,
+                    space: 165,
+                }),
+                owner: TOKEN_PROGRAM_ID.to_string(),
+                executable: false,
+                rent_epoch: 0,
+                space: Some(165),
+            },
+        }
+    }
+
+    #[test]
+    fn test_balances_from_rpc() {
+        let usdc = Pubkey::new_unique();
+        let bonk = Pubkey::new_unique();
+        let accounts = vec![token_account(&usdc, 1_500_000, 6), token_account(&bonk, 42, 5)];
+
+        let balances = balances_from_rpc(Some(5_000_000_000), &accounts).unwrap();
+
+        assert_eq!(balances.sol, Amount::from_lamports(5_000_000_000));
+        assert_eq!(balances.sol.to_string(), "5.00 SOL");
+        assert_eq!(balances.tokens, vec![
+            TokenBalance { mint: usdc, amount: 1_500_000, decimals: 6 },
+            TokenBalance { mint: bonk, amount: 42, decimals: 5 },
+        ]);
+    }
+
+    #[test]
+    fn test_balances_sum_accounts_for_same_mint() {
+        let usdc = Pubkey::new_unique();
+        let accounts = vec![token_account(&usdc, 1_000_000, 6), token_account(&usdc, 250_000, 6)];
+
+        let balances = balances_from_rpc(Some(1), &accounts).unwrap();
+        assert_eq!(balances.tokens, vec![TokenBalance { mint: usdc, amount: 1_250_000, decimals: 6 }]);
+    }
+
+    #[test]
+    fn test_missing_account_has_zero_sol() {
+        let balances = balances_from_rpc(None, &[]).unwrap();
+        assert_eq!(balances, Balances::default());
+    }
+
+    #[test]
+    fn test_unparsed_token_account_rejected() {
+        let mut account = token_account(&Pubkey::new_unique(), 1, 0);
+        account.account.data = UiAccountData::LegacyBinary(String::new());
+        assert!(balances_from_rpc(Some(0), &[account]).is_err());
+    }
+
+    #[test]
+    fn test_derive_associated_token_address() {
+        let account = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+
+        let ata = derive_associated_token_address(&account, &mint);
+        assert_eq!(ata, derive_associated_token_address(&account, &mint));
+        assert_ne!(ata, derive_associated_token_address(&account, &Pubkey::new_unique()));
+        // ATAs are PDAs, so they're never on the curve
+        assert!(!ata.is_on_curve());
+    }
+}
@@ -0,0 +1,417 @@
+//! Rolling one policy out to many Attesta accounts
+//!
+//! `BatchPolicyUpdater` is for organizations that manage a fleet of
+//! accounts and want them all on the same policy. It reads every account
+//! first and skips the ones already on the target policy, then packs the
+//! remaining `update_policy` instructions into as few transactions as will
+//! fit, sends those from a bounded number of threads, and retries sends
+//! that failed at the RPC layer.
+//!
+//! Each transaction lands or fails as a whole, but the rollout as a whole
+//! doesn't: the report says what happened to every account, and running the
+//! updater again with the same policy picks up where it left off.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use anchor_client::solana_sdk::{
+    message::Message,
+    signature::{Keypair, Signature, Signer},
+};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use recovery::policies::Policy;
+use crate::client::{check_policy_cost_within, AttestaClient, AttestaError};
+use crate::instructions;
+
+/// Largest serialized transaction the cluster accepts (the IPv6 MTU minus headers)
+pub const MAX_TRANSACTION_SIZE: usize = 1232;
+
+/// What happened to one account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyUpdateOutcome {
+    /// The policy was set, in the transaction with this signature
+    Updated(Signature),
+
+    /// The account already had exactly this policy, and no other, so nothing was sent
+    AlreadyCurrent,
+
+    /// The account wasn't updated
+    Failed(String),
+}
+
+/// The outcome for one of the addresses passed to `BatchPolicyUpdater::run`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyUpdateReport {
+    pub account: Pubkey,
+    pub outcome: PolicyUpdateOutcome,
+}
+
+/// Sets one policy on many accounts, a transaction per batch of accounts
+///
+/// Every account's owner has to sign its `update_policy`, so register the
+/// owner keypairs with `with_owner` (the payer counts as one). Accounts owned
+/// by anyone else are reported as failed.
+pub struct BatchPolicyUpdater<'a> {
+    client: &'a AttestaClient,
+    payer: &'a Keypair,
+    owners: Vec<&'a Keypair>,
+    concurrency: usize,
+    max_attempts: u32,
+    retry_delay: Duration,
+}
+
+/// Instructions sent together, and the accounts they update
+struct Batch {
+    accounts: Vec<Pubkey>,
+    instructions: Vec<Instruction>,
+}
+
+impl<'a> BatchPolicyUpdater<'a> {
+    /// An updater that pays for its transactions from `payer`
+    ///
+    /// Defaults to 4 transactions in flight and 3 attempts per transaction,
+    /// half a second apart.
+    pub fn new(client: &'a AttestaClient, payer: &'a Keypair) -> Self {
+        Self {
+            client,
+            payer,
+            owners: Vec::new(),
+            concurrency: 4,
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+
+    /// Adds the keypair of an account owner
+    pub fn with_owner(mut self, owner: &'a Keypair) -> Self {
+        self.owners.push(owner);
+        self
+    }
+
+    /// How many transactions may be in flight at once (at least 1)
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How many times a transaction is sent before its accounts are reported failed
+    ///
+    /// Only RPC errors are retried. Resending is safe even if an earlier send
+    /// did land, since it sets the same policy again.
+    pub fn with_retries(mut self, max_attempts: u32, retry_delay: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Sets `policy` on every account in `accounts`
+    ///
+    /// # Returns
+    /// - `Ok(reports)`: One report per address, in the order given
+    /// - `Err(AttestaError::InvalidPolicy)` or `Err(AttestaError::PolicyTooExpensive)`
+    ///   if the program would refuse `policy`, before anything is read or sent
+    pub fn run(&self, accounts: &[Pubkey], policy: &Policy) -> Result<Vec<PolicyUpdateReport>, AttestaError> {
+        policy.validate_config()?;
+        check_policy_cost_within(policy, self.client.limits().max_policy_compute_units)?;
+        let policy_bytes = policy.to_bytes().map_err(|_| AttestaError::InvalidAccountData)?;
+
+        let mut outcomes: HashMap<Pubkey, PolicyUpdateOutcome> = HashMap::new();
+        let mut pending = Vec::new();
+        for address in accounts {
+            if outcomes.contains_key(address) || pending.iter().any(|(pending, _)| pending == address) {
+                continue;
+            }
+            match self.prepare(address, policy, &policy_bytes) {
+                Ok(Some(instruction)) => pending.push((*address, instruction)),
+                Ok(None) => {
+                    outcomes.insert(*address, PolicyUpdateOutcome::AlreadyCurrent);
+                }
+                Err(reason) => {
+                    outcomes.insert(*address, PolicyUpdateOutcome::Failed(reason));
+                }
+            }
+        }
+
+        let (batches, oversized) = self.pack(pending);
+        for address in oversized {
+            let reason = format!("update_policy alone exceeds {} bytes", MAX_TRANSACTION_SIZE);
+            outcomes.insert(address, PolicyUpdateOutcome::Failed(reason));
+        }
+        outcomes.extend(self.send_batches(batches));
+
+        Ok(accounts
+            .iter()
+            .map(|address| PolicyUpdateReport {
+                account: *address,
+                outcome: outcomes
+                    .get(address)
+                    .cloned()
+                    .unwrap_or_else(|| PolicyUpdateOutcome::Failed("not processed".to_string())),
+            })
+            .collect())
+    }
+
+    /// The `update_policy` for one account, or `None` if it's already on the policy
+    fn prepare(&self, address: &Pubkey, policy: &Policy, policy_bytes: &[u8]) -> Result<Option<Instruction>, String> {
+        let account = self.client.get_account(address).map_err(|e| e.to_string())?;
+        if account.policy() == policy_bytes && account.additional_policies.is_empty() {
+            return Ok(None);
+        }
+        if self.owner(&account.owner).is_none() {
+            return Err(format!("no keypair for owner {}", account.owner));
+        }
+
+        instructions::update_policy(&self.client.program_id(), address, &account.owner, Some(policy), account.state_version)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    fn owner(&self, owner: &Pubkey) -> Option<&'a Keypair> {
+        std::iter::once(self.payer)
+            .chain(self.owners.iter().copied())
+            .find(|keypair| keypair.pubkey() == *owner)
+    }
+
+    /// Packs instructions into transactions in order, starting a new one when
+    /// the next wouldn't fit
+    ///
+    /// Also returns the accounts whose instruction doesn't fit in a
+    /// transaction on its own.
+    fn pack(&self, pending: Vec<(Pubkey, Instruction)>) -> (Vec<Batch>, Vec<Pubkey>) {
+        let mut batches = Vec::new();
+        let mut oversized = Vec::new();
+        let mut current = Batch { accounts: Vec::new(), instructions: Vec::new() };
+
+        for (address, instruction) in pending {
+            current.instructions.push(instruction);
+            if transaction_size(&self.payer.pubkey(), &current.instructions) <= MAX_TRANSACTION_SIZE {
+                current.accounts.push(address);
+                continue;
+            }
+
+            let instruction = current.instructions.pop();
+            if !current.instructions.is_empty() {
+                batches.push(std::mem::replace(
+                    &mut current,
+                    Batch { accounts: Vec::new(), instructions: Vec::new() },
+                ));
+            }
+            if let Some(instruction) = instruction {
+                if transaction_size(&self.payer.pubkey(), std::slice::from_ref(&instruction)) <= MAX_TRANSACTION_SIZE {
+                    current.accounts.push(address);
+                    current.instructions.push(instruction);
+                } else {
+                    oversized.push(address);
+                }
+            }
+        }
+        if !current.instructions.is_empty() {
+            batches.push(current);
+        }
+
+        (batches, oversized)
+    }
+
+
+    /// Sends every batch, at most `concurrency` at a time
+    fn send_batches(&self, batches: Vec<Batch>) -> Vec<(Pubkey, PolicyUpdateOutcome)> {
+        let workers = self.concurrency.min(batches.len());
+        let queue = Mutex::new(batches.into_iter().collect::<VecDeque<_>>());
+        let results = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let batch = match lock(&queue).pop_front() {
+                        Some(batch) => batch,
+                        None => break,
+                    };
+                    let outcome = match self.send_batch(&batch) {
+                        Ok(signature) => PolicyUpdateOutcome::Updated(signature),
+                        Err(e) => PolicyUpdateOutcome::Failed(e.to_string()),
+                    };
+                    lock(&results).extend(batch.accounts.into_iter().map(|address| (address, outcome.clone())));
+                });
+            }
+        });
+
+        results.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send_batch(&self, batch: &Batch) -> Result<Signature, AttestaError> {
+        // Every account in a batch was checked to have a known owner
+        let signers: Vec<&Keypair> = batch.instructions
+            .iter()
+            .flat_map(|instruction| &instruction.accounts)
+            .filter(|account| account.is_signer)
+            .filter_map(|account| self.owner(&account.pubkey))
+            .collect();
+
+        let mut attempt = 1;
+        loop {
+            match self.client.send_instructions(self.payer, &batch.instructions, &signers) {
+                Err(AttestaError::RpcError(_)) if attempt < self.max_attempts => {
+                    attempt += 1;
+                    thread::sleep(self.retry_delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // Workers only push and pop whole items, so a panicked one leaves nothing half-done
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The serialized size of a signed transaction carrying `instructions`, paid for by `payer`
+pub(crate) fn transaction_size(payer: &Pubkey, instructions: &[Instruction]) -> usize {
+    let message = Message::new(instructions, Some(payer));
+    let signatures = message.header.num_required_signatures as usize;
+    shortvec_len(signatures) + signatures * 64 + message.serialize().len()
+}
+
+/// Bytes the compact-u16 length prefix takes for `len`
+fn shortvec_len(len: usize) -> usize {
+    match len {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_client::solana_sdk::transaction::Transaction;
+    use smart_account::AttestaAccount;
+    use recovery::Amount;
+    use crate::test_utils::{attesta_account_data, MockBackend};
+
+    fn account_with_policy(owner: Pubkey, policy: Vec<u8>) -> AttestaAccount {
+        AttestaAccount::new(owner, [3u8; 64], b"phone".to_vec(), policy, 100)
+    }
+
+    fn serialized_size(transaction: &Transaction) -> usize {
+        let signatures = transaction.signatures.len();
+        shortvec_len(signatures) + signatures * 64 + transaction.message.serialize().len()
+    }
+
+    #[test]
+    fn test_rollout_to_fifty_accounts() {
+        let backend = MockBackend::new();
+        let client = AttestaClient::with_backend(backend.clone(), Pubkey::new_unique());
+        let payer = Keypair::new();
+        let owners: Vec<Keypair> = (0..5).map(|_| Keypair::new()).collect();
+        let stranger = Pubkey::new_unique();
+
+        let policy = Policy::spending_limit(Amount::from_lamports(1_000_000));
+        let policy_bytes = policy.to_bytes().unwrap();
+
+        // 40 to update, 5 already on the policy, 3 missing and 2 owned by an unknown key
+        let addresses: Vec<Pubkey> = (0..50).map(|_| Pubkey::new_unique()).collect();
+        for (i, address) in addresses.iter().enumerate() {
+            let owner = owners[i % owners.len()].pubkey();
+            match i {
+                0..=39 => backend.set_account(*address, 1, attesta_account_data(&account_with_policy(owner, vec![]))),
+                40..=44 => backend.set_account(
+                    *address,
+                    1,
+                    attesta_account_data(&account_with_policy(owner, policy_bytes.clone())),
+                ),
+                45..=47 => {}
+                _ => backend.set_account(*address, 1, attesta_account_data(&account_with_policy(stranger, vec![]))),
+            }
+        }
+        // One send fails at the RPC layer and is retried
+        backend.push_send_result(Err(AttestaError::RpcError("node is behind".to_string())));
+
+        let updater = owners
+            .iter()
+            .fold(BatchPolicyUpdater::new(&client, &payer), |updater, owner| updater.with_owner(owner))
+            .with_concurrency(3)
+            .with_retries(3, Duration::ZERO);
+        let reports = updater.run(&addresses, &policy).unwrap();
+
+        assert_eq!(reports.len(), 50);
+        assert!(reports.iter().zip(&addresses).all(|(report, address)| report.account == *address));
+        for (i, report) in reports.iter().enumerate() {
+            match i {
+                0..=39 => assert!(matches!(report.outcome, PolicyUpdateOutcome::Updated(_)), "{:?}", report),
+                40..=44 => assert_eq!(report.outcome, PolicyUpdateOutcome::AlreadyCurrent),
+                _ => assert!(matches!(report.outcome, PolicyUpdateOutcome::Failed(_)), "{:?}", report),
+            }
+        }
+
+        // Packed into fewer transactions than accounts, each within the size
+        // limit and signed by the owners it needs
+        let sent = backend.sent_transactions();
+        let batches = sent.len() - 1;
+        assert!(batches < 40, "{} transactions for 40 updates", batches);
+        let mut updated = 0;
+        for transaction in &sent {
+            assert!(serialized_size(transaction) <= MAX_TRANSACTION_SIZE);
+            let required = transaction.message.header.num_required_signatures as usize;
+            assert_eq!(transaction.signatures.len(), required);
+            updated += transaction.message.instructions.len();
+        }
+        // The failed send was retried as the same transaction
+        let retried = &sent[0];
+        assert_eq!(sent.iter().filter(|transaction| *transaction == retried).count(), 2);
+        assert_eq!(updated, 40 + retried.message.instructions.len());
+    }
+
+    #[test]
+    fn test_rpc_errors_are_retried_up_to_the_limit() {
+        let backend = MockBackend::new();
+        let client = AttestaClient::with_backend(backend.clone(), Pubkey::new_unique());
+        let payer = Keypair::new();
+        let address = Pubkey::new_unique();
+        backend.set_account(address, 1, attesta_account_data(&account_with_policy(payer.pubkey(), vec![])));
+        for _ in 0..3 {
+            backend.push_send_result(Err(AttestaError::RpcError("timeout".to_string())));
+        }
+
+        let updater = BatchPolicyUpdater::new(&client, &payer).with_retries(3, Duration::ZERO);
+        let reports = updater.run(&[address], &Policy::time_locked(2_000_000_000)).unwrap();
+
+        assert!(matches!(&reports[0].outcome, PolicyUpdateOutcome::Failed(reason) if reason.contains("timeout")));
+        assert_eq!(backend.sent_transactions().len(), 3);
+    }
+
+    #[test]
+    fn test_repeated_addresses_are_updated_once() {
+        let backend = MockBackend::new();
+        let client = AttestaClient::with_backend(backend.clone(), Pubkey::new_unique());
+        let payer = Keypair::new();
+        let address = Pubkey::new_unique();
+        backend.set_account(address, 1, attesta_account_data(&account_with_policy(payer.pubkey(), vec![])));
+
+        let reports = BatchPolicyUpdater::new(&client, &payer)
+            .run(&[address, address], &Policy::time_locked(2_000_000_000))
+            .unwrap();
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0], reports[1]);
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message.instructions.len(), 1);
+    }
+
+    #[test]
+    fn test_expensive_policy_is_refused_before_any_send() {
+        let backend = MockBackend::new();
+        let client = AttestaClient::with_backend(backend.clone(), Pubkey::new_unique());
+        let payer = Keypair::new();
+        let policy = Policy::composite((0..40).map(|_| {
+            Policy::destination_allowlist((0..32).map(|_| Pubkey::new_unique()).collect())
+        }).collect());
+
+        let result = BatchPolicyUpdater::new(&client, &payer).run(&[Pubkey::new_unique()], &policy);
+
+        assert!(matches!(result, Err(AttestaError::PolicyTooExpensive { .. })), "{:?}", result.map(|_| ()));
+        assert!(backend.calls().is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+//! A cache of decoded Attesta accounts
+//!
+//! Backends that serve many requests for the same accounts can keep the
+//! decoded `AttestaAccount`s here instead of calling `getAccountInfo` every
+//! time. Each entry remembers the slot it was read at (under the backend's
+//! commitment), so callers choose how stale a read they accept. Entries also
+//! expire after a fixed time, and the least recently used ones are dropped
+//! once the cache is full.
+//!
+//! `AttestaClient` drops an address's entry whenever it sends a transaction
+//! that writes to it. Changes made by anyone else are only picked up once
+//! the entry is too stale for the caller, or expires.
+//!
+//! Only available with the `cache` feature.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use smart_account::AttestaAccount;
+use solana_program::pubkey::Pubkey;
+
+/// Target time between slots, used to age entries between fetches
+pub const SLOT_DURATION_MS: u64 = 400;
+
+/// How long entries live and how many are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Entries older than this are refetched whatever staleness the caller accepts
+    pub ttl: Duration,
+
+    /// Most accounts kept at once (the least recently used are dropped first)
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { ttl: Duration::from_secs(30), max_entries: 1024 }
+    }
+}
+
+/// Hit and miss counts since the cache was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+struct CachedAccount {
+    account: AttestaAccount,
+    slot: u64,
+    fetched_at: Instant,
+    // Bumped under the read lock, so lookups don't serialize
+    last_used: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<Pubkey, CachedAccount>,
+    /// The highest slot any fetch has reported, and when it was seen
+    latest_slot: Option<(u64, Instant)>,
+}
+
+/// Decoded accounts keyed by address, safe to share between threads
+pub struct AccountCache {
+    config: CacheConfig,
+    state: RwLock<CacheState>,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AccountCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            state: RwLock::new(CacheState::default()),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> CacheConfig {
+        self.config
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, CacheState> {
+        // Entries are replaced whole, so a panic elsewhere can't leave one half-written
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, CacheState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The cached account, if it was read at most `max_staleness_slots` slots ago
+    ///
+    /// The current slot is estimated from the newest slot any fetch reported,
+    /// plus the time since then. Counts a hit or a miss either way.
+    pub fn get(&self, address: &Pubkey, max_staleness_slots: u64) -> Option<AttestaAccount> {
+        let now = Instant::now();
+        let found = {
+            let state = self.read();
+            state.entries.get(address).and_then(|entry| {
+                let fresh = now.duration_since(entry.fetched_at) < self.config.ttl
+                    && slots_behind(state.latest_slot, entry.slot, now) <= max_staleness_slots;
+                fresh.then(|| {
+                    entry.last_used.store(self.tick(), Ordering::Relaxed);
+                    entry.account.clone()
+                })
+            })
+        };
+
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Stores an account read at `slot`, dropping the least recently used one if full
+    pub fn insert(&self, address: Pubkey, account: AttestaAccount, slot: u64) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.write();
+        if !matches!(state.latest_slot, Some((latest, _)) if latest >= slot) {
+            state.latest_slot = Some((slot, now));
+        }
+
+        // An older read racing a newer one mustn't overwrite it
+        if matches!(state.entries.get(&address), Some(entry) if entry.slot > slot) {
+            return;
+        }
+
+        if !state.entries.contains_key(&address) && state.entries.len() >= self.config.max_entries {
+            let oldest = state.entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(address, _)| *address);
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        let last_used = AtomicU64::new(self.tick());
+        state.entries.insert(address, CachedAccount { account, slot, fetched_at: now, last_used });
+    }
+
+    /// Drops the entry for `address`, so the next read fetches it
+    pub fn invalidate(&self, address: &Pubkey) {
+        self.write().entries.remove(address);
+    }
+
+    /// Drops every entry
+    pub fn clear(&self) {
+        self.write().entries.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.read().entries.len(),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// How many slots behind the estimated current slot a read at `slot` is
+fn slots_behind(latest_slot: Option<(u64, Instant)>, slot: u64, now: Instant) -> u64 {
+    let current = match latest_slot {
+        Some((latest, seen_at)) => {
+            let elapsed = now.duration_since(seen_at).as_millis() as u64;
+            latest.saturating_add(elapsed / SLOT_DURATION_MS)
+        }
+        None => slot,
+    };
+    current.saturating_sub(slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(nonce: u64) -> AttestaAccount {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        account.nonce = nonce;
+        account
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = AccountCache::new(CacheConfig::default());
+        let address = Pubkey::new_unique();
+        assert_eq!(cache.get(&address, 10), None);
+
+        let cached = account(1);
+        cache.insert(address, cached.clone(), 100);
+        assert_eq!(cache.get(&address, 10), Some(cached));
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1, entries: 1 });
+    }
+
+    #[test]
+    fn test_staleness_is_measured_against_newest_slot() {
+        let cache = AccountCache::new(CacheConfig::default());
+        let (old, new) = (Pubkey::new_unique(), Pubkey::new_unique());
+        cache.insert(old, account(1), 100);
+        cache.insert(new, account(1), 105);
+
+        assert!(cache.get(&old, 4).is_none());
+        assert!(cache.get(&old, 5).is_some());
+        assert!(cache.get(&new, 0).is_some());
+    }
+
+    #[test]
+    fn test_older_read_does_not_replace_newer() {
+        let cache = AccountCache::new(CacheConfig::default());
+        let address = Pubkey::new_unique();
+        cache.insert(address, account(2), 105);
+        cache.insert(address, account(1), 100);
+
+        assert_eq!(cache.get(&address, u64::MAX).unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn test_entries_expire_after_ttl() {
+        let cache = AccountCache::new(CacheConfig { ttl: Duration::ZERO, max_entries: 8 });
+        let address = Pubkey::new_unique();
+        cache.insert(address, account(1), 100);
+
+        assert!(cache.get(&address, u64::MAX).is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = AccountCache::new(CacheConfig { ttl: Duration::from_secs(60), max_entries: 2 });
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        cache.insert(a, account(1), 100);
+        cache.insert(b, account(1), 100);
+
+        // Reading `a` makes `b` the least recently used
+        assert!(cache.get(&a, u64::MAX).is_some());
+        cache.insert(c, account(1), 100);
+
+        assert!(cache.get(&a, u64::MAX).is_some());
+        assert!(cache.get(&b, u64::MAX).is_none());
+        assert!(cache.get(&c, u64::MAX).is_some());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = AccountCache::new(CacheConfig::default());
+        let address = Pubkey::new_unique();
+        cache.insert(address, account(1), 100);
+        cache.invalidate(&address);
+
+        assert!(cache.get(&address, u64::MAX).is_none());
+    }
+}
@@ -0,0 +1,264 @@
+//! One-time payment links
+//!
+//! `create_claim_ticket` prepares a passkey prompt authorizing one payout of
+//! a fixed amount to whoever holds a fresh claim key. Completing it gives a
+//! `ClaimLink`, which encodes the ticket and the claim key's secret as a
+//! base64url string to put in a URL fragment (fragments aren't sent to the
+//! server hosting the page). The recipient decodes the link, picks where the
+//! lamports go, and submits `claim` signed with the claim key.
+//!
+//! The ticket uses up a nonce like any other proof: if the owner authorizes
+//! something with a higher nonce first, the link stops working. A link that
+//! has to stay claimable while the owner keeps using the account should be
+//! given a nonce reserved ahead with `NonceTracker`.
+
+use anchor_client::solana_sdk::signature::{keypair_from_seed, Keypair, Signer};
+use base64::Engine;
+use smart_account::{AttestaAccount, ClaimTicket};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use crate::client::AttestaError;
+use crate::instructions;
+use crate::signing::{AssertionResponse, ProofEnvelope, SigningRequest};
+
+/// Bytes of the claim key's secret at the start of a link
+const CLAIM_SEED_LEN: usize = 32;
+
+/// A claim ticket waiting for the passkey's signature
+#[derive(Debug)]
+pub struct ClaimTicketRequest {
+    /// The passkey prompt to show; its challenge covers the ticket
+    pub signing: SigningRequest,
+
+    /// The account paying out
+    pub account: Pubkey,
+
+    /// Lamports the ticket pays
+    pub amount: u64,
+
+    /// From this time on (Unix timestamp) the ticket can't be claimed
+    pub expires_at: i64,
+
+    claim_key: Keypair,
+}
+
+/// Prepares a ticket paying `amount` lamports from `account_address` once
+///
+/// # Parameters
+/// - `account`: The paying account, for its owner and next nonce
+/// - `account_address`: Its address
+/// - `amount`: Lamports the claim transfers
+/// - `expires_at`: When the ticket stops being claimable (Unix timestamp)
+/// - `now`: The current Unix timestamp, for the signing request's expiry
+pub fn create_claim_ticket(
+    account: &AttestaAccount,
+    account_address: Pubkey,
+    amount: u64,
+    expires_at: i64,
+    now: i64,
+) -> ClaimTicketRequest {
+    ClaimTicketRequest::with_nonce(account, account_address, amount, expires_at, account.nonce.saturating_add(1), now)
+}
+
+impl ClaimTicketRequest {
+    /// `create_claim_ticket` using up a nonce reserved ahead of time
+    pub fn with_nonce(
+        account: &AttestaAccount,
+        account_address: Pubkey,
+        amount: u64,
+        expires_at: i64,
+        nonce: u64,
+        now: i64,
+    ) -> Self {
+        let claim_key = Keypair::new();
+        let message_hash = unsigned_ticket(account_address, claim_key.pubkey(), amount, expires_at, nonce).message_hash();
+        Self {
+            signing: SigningRequest::for_message_hash(account, message_hash, nonce, now),
+            account: account_address,
+            amount,
+            expires_at,
+            claim_key,
+        }
+    }
+
+    /// Checks the passkey's response and packs the ticket into a link
+    ///
+    /// # Returns
+    /// - `Ok(ClaimLink)` if the response matches this request
+    /// - `Err(AttestaError)` as `SigningRequest::complete` reports it
+    pub fn complete(self, assertion: AssertionResponse, now: i64) -> Result<ClaimLink, AttestaError> {
+        let envelope = self.signing.complete(assertion, now)?;
+        let ticket = ClaimTicket {
+            webauthn_sig: envelope.webauthn_sig.clone(),
+            ..unsigned_ticket(self.account, self.claim_key.pubkey(), self.amount, self.expires_at, envelope.nonce)
+        };
+        Ok(ClaimLink { ticket, envelope, claim_key: self.claim_key })
+    }
+}
+
+/// A signed claim ticket and the key that claims it
+#[derive(Debug)]
+pub struct ClaimLink {
+    /// The ticket the program checks
+    pub ticket: ClaimTicket,
+
+    /// The passkey's authorization, as `SigningRequest::complete` produced it
+    pub envelope: ProofEnvelope,
+
+    claim_key: Keypair,
+}
+
+impl ClaimLink {
+    /// The key that must sign the claim
+    pub fn claim_key(&self) -> &Keypair {
+        &self.claim_key
+    }
+
+    /// The link's payload: the claim key's secret, then the ticket, as unpadded base64url
+    ///
+    /// Anyone holding this can claim the ticket, so send it the way you'd send the money.
+    pub fn to_url_fragment(&self) -> String {
+        let mut bytes = self.claim_key.to_bytes()[..CLAIM_SEED_LEN].to_vec();
+        bytes.extend_from_slice(&self.ticket.to_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Reads a link made by `to_url_fragment`
+    ///
+    /// Only the envelope's signature, nonce and message hash come back;
+    /// the rest of it isn't in the link.
+    pub fn from_url_fragment(fragment: &str) -> Result<Self, AttestaError> {
+        let invalid = |reason: &str| AttestaError::InvalidClaimLink(reason.to_string());
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(fragment.trim_start_matches('#'))
+            .map_err(|_| invalid("not base64url"))?;
+        if bytes.len() < CLAIM_SEED_LEN {
+            return Err(invalid("too short"));
+        }
+        let (seed, ticket) = bytes.split_at(CLAIM_SEED_LEN);
+
+        let claim_key = keypair_from_seed(seed).map_err(|_| invalid("bad claim key"))?;
+        let ticket = ClaimTicket::from_bytes(ticket).map_err(|e| invalid(&e.to_string()))?;
+        if ticket.claim_key != claim_key.pubkey() {
+            return Err(invalid("the claim key doesn't match the ticket"));
+        }
+
+        let envelope = ProofEnvelope {
+            webauthn_sig: ticket.webauthn_sig.clone(),
+            nonce: ticket.nonce,
+            message_hash: ticket.message_hash(),
+            memo: vec![],
+            idempotency_key: Default::default(),
+            parent_account: None,
+            logs_proofs: false,
+            emit_memo: false,
+        };
+        Ok(Self { ticket, envelope, claim_key })
+    }
+
+    /// The `claim` instruction paying `destination`; sign it with `claim_key()`
+    pub fn claim_instruction(&self, program_id: &Pubkey, destination: &Pubkey) -> Result<Instruction, std::io::Error> {
+        instructions::claim(program_id, &self.ticket, destination)
+    }
+
+    /// Whether the ticket can still be claimed at `now`, as far as its expiry goes
+    pub fn is_expired(&self, now: i64) -> bool {
+        now >= self.ticket.expires_at
+    }
+}
+
+/// A ticket with an empty signature, for its message hash
+fn unsigned_ticket(account: Pubkey, claim_key: Pubkey, amount: u64, expires_at: i64, nonce: u64) -> ClaimTicket {
+    ClaimTicket {
+        account,
+        claim_key,
+        amount,
+        expires_at,
+        nonce,
+        webauthn_sig: core_crypto::WebAuthnSignature::new(vec![], vec![], vec![], vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use smart_account::{redeem_claim, ClaimError, PolicyResult};
+
+    const NOW: i64 = 1_700_000_000;
+    const AMOUNT: u64 = 100_000_000;
+
+    fn respond(passkey: &mut TestPasskey, challenge: &[u8]) -> AssertionResponse {
+        let sig = passkey.sign_der(challenge);
+        AssertionResponse {
+            credential_id: sig.credential_id,
+            authenticator_data: sig.authenticator_data,
+            client_data_json: sig.client_data_json,
+            signature: sig.signature,
+        }
+    }
+
+    fn setup() -> (AttestaAccount, Pubkey, TestPasskey) {
+        let passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        (account, Pubkey::new_unique(), passkey)
+    }
+
+    fn issue(account: &AttestaAccount, address: Pubkey, passkey: &mut TestPasskey, expires_at: i64) -> ClaimLink {
+        let request = create_claim_ticket(account, address, AMOUNT, expires_at, NOW);
+        let assertion = respond(passkey, &request.signing.challenge);
+        request.complete(assertion, NOW).unwrap()
+    }
+
+    #[test]
+    fn test_link_round_trips_and_claims_once() {
+        let (mut account, address, mut passkey) = setup();
+        let link = ClaimLink::from_url_fragment(&issue(&account, address, &mut passkey, NOW + 3_600).to_url_fragment()).unwrap();
+        assert_eq!(link.ticket.amount, AMOUNT);
+        assert_eq!(link.envelope.nonce, 1);
+
+        let friend = Pubkey::new_unique();
+        assert_eq!(redeem_claim(&mut account, &address, &friend, &link.ticket, NOW), Ok(PolicyResult::Allowed));
+        assert!(matches!(
+            redeem_claim(&mut account, &address, &friend, &link.ticket, NOW),
+            Err(ClaimError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_expired_link() {
+        let (mut account, address, mut passkey) = setup();
+        let link = issue(&account, address, &mut passkey, NOW + 60);
+
+        assert!(link.is_expired(NOW + 60));
+        assert_eq!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &link.ticket, NOW + 60),
+            Err(ClaimError::Expired { expires_at: NOW + 60 })
+        );
+    }
+
+    #[test]
+    fn test_raised_amount_in_link_is_rejected() {
+        let (mut account, address, mut passkey) = setup();
+        let mut link = issue(&account, address, &mut passkey, NOW + 3_600);
+        link.ticket.amount = AMOUNT * 10;
+        let tampered = ClaimLink::from_url_fragment(&link.to_url_fragment()).unwrap();
+
+        assert!(matches!(
+            redeem_claim(&mut account, &address, &Pubkey::new_unique(), &tampered.ticket, NOW),
+            Err(ClaimError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_link_with_another_claim_key_is_refused() {
+        let (account, address, mut passkey) = setup();
+        let link = issue(&account, address, &mut passkey, NOW + 3_600);
+        let other = issue(&account, address, &mut passkey, NOW + 3_600);
+
+        let mut bytes = other.claim_key.to_bytes()[..CLAIM_SEED_LEN].to_vec();
+        bytes.extend_from_slice(&link.ticket.to_bytes());
+        let fragment = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        assert!(matches!(ClaimLink::from_url_fragment(&fragment), Err(AttestaError::InvalidClaimLink(_))));
+        assert!(matches!(ClaimLink::from_url_fragment("not a link!"), Err(AttestaError::InvalidClaimLink(_))));
+    }
+}
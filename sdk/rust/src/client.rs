@@ -4,21 +4,105 @@
 //! Attesta accounts on Solana.
 
 use anchor_client::{
-    Client,
+    solana_sdk::{
+        compute_budget::ComputeBudgetInstruction,
+        message::Message,
+        signature::{Keypair, Signature, Signer},
+        transaction::{Transaction, TransactionError},
+    },
     Cluster,
 };
-use solana_program::pubkey::Pubkey;
-use smart_account::AttestaAccount;
+use attesta_types::consts::{ACCOUNT_DISCRIMINATOR_LEN, CONCURRENT_MODIFICATION_ERROR, P256_PUBKEY_LEN};
+#[cfg(feature = "metrics")]
+use attesta_types::consts::POLICY_DENIAL_ERRORS;
+use borsh::BorshDeserialize;
+use solana_program::{
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+};
+use smart_account::{
+    action_message_hash, memo_hash, resolve_signing_key, transaction_message_hash, AccountSettings, AttestaAccount,
+    ExecuteOutcome, ExecutionReceipt, InheritanceConfig, ExecutionStatus, TransactionRequest, TransactionRequestError,
+    SETTINGS_UPDATE_ACTION,
+};
+use smart_account::attestation::{attestation_payload, check_attestation, AttestationError, PolicyAttestation, POLICY_ATTEST_ACTION};
+use smart_account::auth_mode::{auth_mode_payload, AuthMode, AUTH_MODE_ACTION};
+use smart_account::emergency::{EmergencyOverride, EMERGENCY_CONFIGURE_ACTION};
+use smart_account::executors::{EXECUTOR_ADD_ACTION, EXECUTOR_REMOVE_ACTION};
+use smart_account::inheritance::{HEARTBEAT_ACTION, INHERITANCE_CONFIGURE_ACTION};
+#[cfg(feature = "serde")]
+use smart_account::json::{export_account, import_account, AccountJsonError};
+use smart_account::policy_list::policy_change_payload;
+use smart_account::proof_log::{ProofLog, ProofLogEntry, ProofLogError, PROOF_LOG_ENABLE_ACTION};
+use smart_account::proposal::{cancel_proposal_payload, CancelReason, PROPOSAL_CANCEL_ACTION};
+use smart_account::schedule::{schedule_payload, SCHEDULE_ACTION, SCHEDULE_CANCEL_ACTION};
+use smart_account::social_recovery::{recovery_request_hash, RecoveryMode};
+use smart_account::sponsorship::SponsorPool;
+use smart_account::summary::SecuritySummary;
+use smart_account::protocol::{ProtocolConstants, ProtocolLimits};
+use smart_account::upgrade::{ProgramFeature, ProgramFeatures, ProgramVersion, UPGRADE_ACKNOWLEDGE_ACTION};
 use core_crypto::WebAuthnSignature;
+use recovery::{credential_id_hash, EncryptedBackup, RecoveryError, BACKUP_DELETE_ACTION, BACKUP_WRITE_ACTION};
+use recovery::policies::{Policy, PolicyBuildError, PolicyError, MAX_POLICY_COMPUTE_UNITS};
+use std::sync::{Arc, OnceLock};
 use thiserror::Error;
+use crate::approvals::{decode_proposal, pending_proposals, proposal_filters, ProposalSummary};
+use crate::backend::{RpcBackend, SolanaRpcBackend};
+use crate::scan::{AccountScan, ScanOptions, MAX_SCAN_PAGE_SIZE};
+use crate::confirmation::{send_and_confirm, ConfirmationStrategy};
+use crate::balances::{balances_from_rpc, Balances, TOKEN_PROGRAM_ID};
+#[cfg(feature = "cache")]
+use crate::cache::{AccountCache, CacheConfig, CacheStats};
+use crate::logs::{codes, parse_program_logs};
+#[cfg(feature = "metrics")]
+use crate::metrics::{MeteredBackend, MetricsSink};
+use crate::instructions::{
+    self, account_discriminator, derive_backup_address, derive_policy_attestation_address, derive_proof_log_address,
+    derive_proposal_address, derive_schedule_address,
+    INITIALIZE_COMPUTE_UNITS,
+};
+use crate::nonces::{NonceSyncGuard, NonceTracker};
+use crate::observer::SentHook;
+use crate::preparation::{plan_first_transaction, FirstTransaction, NewAccountPasskey, PlanProgress, PreparationPlan};
+use crate::replay::{replay_transactions, ReconstructedState};
+use crate::signing::{AssertionResponse, ProofEnvelope, Registration, RegistrationRequest, SigningRequest};
 
 /// Client for interacting with Attesta program
+///
+/// The client is `Send + Sync`: share one between threads rather than
+/// creating one per request.
 pub struct AttestaClient {
-    /// The Anchor client
-    client: Client,
+    /// Chain access used for reads and transaction submission
+    backend: Arc<dyn RpcBackend>,
     
     /// The Attesta program ID
     program_id: Pubkey,
+
+    /// How every transaction the client sends is confirmed
+    confirmation: ConfirmationStrategy,
+
+    /// Nonces given to signing requests that haven't executed yet
+    nonces: NonceTracker,
+
+    /// Reports when a failed send shows `nonces` had fallen behind
+    nonce_sync: NonceSyncGuard,
+
+    /// How many times `update_policy` refetches and retries after losing a race
+    concurrency_retries: u8,
+
+    /// The deployed program's constants, once `protocol_constants` has read them
+    protocol_constants: OnceLock<ProtocolConstants>,
+
+    /// Told about every transaction the client lands (see `ObservedClient`)
+    on_sent: Option<SentHook>,
+
+    /// Decoded accounts for `get_account_cached`, if caching is on
+    #[cfg(feature = "cache")]
+    cache: Option<AccountCache>,
+
+    /// Told what the client does, if metrics are on (see `with_metrics`)
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn MetricsSink>>,
 }
 
 impl AttestaClient {
@@ -31,14 +115,111 @@ impl AttestaClient {
     /// # Returns
     /// A new AttestaClient instance
     pub fn new(cluster: Cluster, program_id: Pubkey) -> Self {
-        let client = Client::new(cluster, None);
-        
+        Self::with_backend(SolanaRpcBackend::new(cluster.url().to_string()), program_id)
+    }
+
+    /// Creates a client that makes its RPC calls through `backend`
+    ///
+    /// Use this for a custom transport, or with `MockBackend` in tests.
+    pub fn with_backend<B: RpcBackend + 'static>(backend: B, program_id: Pubkey) -> Self {
         Self {
-            client,
+            backend: Arc::new(backend),
             program_id,
+            confirmation: ConfirmationStrategy::default(),
+            nonces: NonceTracker::new(),
+            nonce_sync: NonceSyncGuard::default(),
+            concurrency_retries: 0,
+            protocol_constants: OnceLock::new(),
+            on_sent: None,
+            #[cfg(feature = "cache")]
+            cache: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
         }
     }
 
+    /// The Attesta program this client talks to
+    pub fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    /// Sets how the transactions this client sends are confirmed
+    ///
+    /// Applies to every method that sends one. The default waits up to a
+    /// minute for `confirmed`, without resubmitting.
+    pub fn with_confirmation(mut self, strategy: ConfirmationStrategy) -> Self {
+        self.confirmation = strategy;
+        self
+    }
+
+    /// The strategy sent transactions are confirmed with
+    pub fn confirmation(&self) -> ConfirmationStrategy {
+        self.confirmation
+    }
+
+    /// Lets `update_policy` retry `retries` times when another change to
+    /// the account lands first
+    ///
+    /// Each retry fetches the account again and reruns the edit on it. The
+    /// default is 0: the conflict comes back as `ConcurrentModification`.
+    pub fn with_concurrency_retries(mut self, retries: u8) -> Self {
+        self.concurrency_retries = retries;
+        self
+    }
+
+    /// Reports through `guard` when a failed `execute` finds the account
+    /// further along than this client expected
+    ///
+    /// A relayer that signs against cached accounts can use it to learn
+    /// which of its queued proofs have to be signed again, rather than
+    /// retrying them into a loop of failures.
+    pub fn with_nonce_sync_guard(mut self, guard: NonceSyncGuard) -> Self {
+        self.nonce_sync = guard;
+        self
+    }
+
+    /// Calls `hook` with every transaction this client lands
+    pub(crate) fn with_sent_hook(mut self, hook: SentHook) -> Self {
+        self.on_sent = Some(hook);
+        self
+    }
+
+    /// The backend, for work that outlives a call on the client
+    pub(crate) fn shared_backend(&self) -> Arc<dyn RpcBackend> {
+        Arc::clone(&self.backend)
+    }
+
+    /// The nonces handed out to this client's signing requests
+    pub(crate) fn nonces(&self) -> &NonceTracker {
+        &self.nonces
+    }
+
+    /// The backend this client makes its RPC calls through
+    #[cfg(feature = "devtools")]
+    pub(crate) fn backend(&self) -> &dyn RpcBackend {
+        self.backend.as_ref()
+    }
+
+    /// Turns on the account cache used by `get_account_cached`
+    #[cfg(feature = "cache")]
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache = Some(AccountCache::new(config));
+        self
+    }
+
+    /// Reports what the client does to `sink`: proofs received, transactions
+    /// submitted and confirmed, denials, replays, and every RPC call
+    ///
+    /// Pass an `Arc<RelayerMetrics>` and keep a clone to render from, or a
+    /// `MetricsSink` of your own. Call it once: each call wraps the backend
+    /// again, and only the last sink hears about executions.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.backend = Arc::new(MeteredBackend::new(self.backend, Arc::clone(&sink)));
+        self.metrics = Some(sink);
+        self
+    }
+
     /// Gets an Attesta account
     ///
     /// # Parameters
@@ -47,43 +228,2856 @@ impl AttestaClient {
     /// # Returns
     /// The AttestaAccount if found, or an error
     pub fn get_account(&self, account_address: &Pubkey) -> Result<AttestaAccount, AttestaError> {
-        // TODO: Implement account fetching from on-chain
-        // This would use the Anchor client to fetch and deserialize the account
-        Err(AttestaError::NotImplemented)
+        let account = match self.backend.get_account_data(account_address)? {
+            Some(data) => decode_attesta_account(&data)?,
+            None => return Err(AttestaError::AccountNotFound),
+        };
+        self.nonces.observe(&account);
+        Ok(account)
+    }
+
+    /// Scans the program for the Attesta accounts `owner` owns
+    ///
+    /// Lists only the start of each account, then fetches the matches a
+    /// page at a time as the scan is iterated (see `scan`). Nothing is
+    /// fetched until the first item is asked for. An account that won't
+    /// decode comes out as `UndecodableAccount` and the scan goes on.
+    pub fn scan_accounts_by_owner(&self, owner: &Pubkey, options: ScanOptions) -> AccountScan {
+        AccountScan::new(self.shared_backend(), self.program_id, *owner, options)
+    }
+
+    /// Gets an Attesta account, from the cache if a recent enough copy is there
+    ///
+    /// A cached account is used if it was read at most `max_staleness_slots`
+    /// slots ago and hasn't expired; otherwise the account is fetched and
+    /// cached. Sending a transaction through this client that writes to the
+    /// account drops its cached copy. Without `with_cache`, this is
+    /// `get_account`.
+    ///
+    /// # Parameters
+    /// - `account_address`: The address of the Attesta account
+    /// - `max_staleness_slots`: How many slots old a cached copy may be (0 for this slot only)
+    #[cfg(feature = "cache")]
+    pub fn get_account_cached(
+        &self,
+        account_address: &Pubkey,
+        max_staleness_slots: u64,
+    ) -> Result<AttestaAccount, AttestaError> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return self.get_account(account_address),
+        };
+        if let Some(account) = cache.get(account_address, max_staleness_slots) {
+            return Ok(account);
+        }
+
+        let (data, slot) = self.backend.get_account_data_with_slot(account_address)?;
+        let account = decode_attesta_account(&data.ok_or(AttestaError::AccountNotFound)?)?;
+        self.nonces.observe(&account);
+        cache.insert(*account_address, account.clone(), slot);
+        Ok(account)
+    }
+
+    /// Hit and miss counts of the account cache (`None` without `with_cache`)
+    #[cfg(feature = "cache")]
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(AccountCache::stats)
+    }
+
+    /// Finds the public key of the passkey that made an assertion
+    ///
+    /// Works the same for accounts in privacy mode, where credential IDs are
+    /// stored hashed: pass the credential ID from the assertion as-is and it's
+    /// hashed before the lookup.
+    ///
+    /// # Returns
+    /// - `Ok([u8; 64])` if the credential belongs to an active passkey on the account
+    /// - `Err(AttestaError::UnknownCredential)` if it's unknown or revoked
+    pub fn find_passkey(&self, account: &AttestaAccount, credential_id: &[u8]) -> Result<[u8; P256_PUBKEY_LEN], AttestaError> {
+        resolve_signing_key(account, credential_id)
+            .map_err(|e| AttestaError::UnknownCredential(e.to_string()))
     }
 
     /// Derives the Attesta account PDA for a user
     ///
+    /// For a credential ID, use `derive_account_address_for_credential`:
+    /// they're often longer than a seed may be.
+    ///
     /// # Parameters
     /// - `owner`: The owner's public key
-    /// - `seed`: Additional seed (e.g., credential ID)
+    /// - `seed`: Additional seed (at most 32 bytes)
+    ///
+    /// # Returns
+    /// - `Ok((address, bump))` with the PDA address and bump seed
+    /// - `Err(AttestaError::SeedTooLong)` if `seed` is over 32 bytes
+    pub fn derive_account_address(&self, owner: &Pubkey, seed: &[u8]) -> Result<(Pubkey, u8), AttestaError> {
+        smart_account::derive_attesta_account(&self.program_id, owner, seed)
+            .map_err(|_| AttestaError::SeedTooLong(seed.len()))
+    }
+
+    /// Derives the Attesta account PDA for a user's passkey
+    ///
+    /// Seeds with the credential ID's hash, so IDs of any length work.
+    pub fn derive_account_address_for_credential(&self, owner: &Pubkey, credential_id: &[u8]) -> (Pubkey, u8) {
+        smart_account::derive_attesta_account_for_credential(&self.program_id, owner, credential_id)
+    }
+
+    /// Derives the PDA of a user's Attesta account at `account_index`
+    ///
+    /// Known before the passkey exists, so an app can show the address (or
+    /// receive funds at it) while the user is still enrolling.
+    pub fn derive_account_address_by_index(&self, owner: &Pubkey, account_index: u16) -> (Pubkey, u8) {
+        smart_account::derive_attesta_account_by_index(&self.program_id, owner, account_index)
+    }
+
+    /// Finds the lowest index `owner` has no account at yet
+    ///
+    /// Probes the indexed addresses a page at a time. An address holding
+    /// only lamports (someone sent it SOL early) counts as free, as
+    /// `initialize_with_index` accepts it. Another client can still take
+    /// the index before this one initializes it; that initialization fails
+    /// and can be retried with the next.
+    ///
+    /// # Returns
+    /// - `Ok(index)` with the first free index
+    /// - `Err(AttestaError::NoFreeIndex)` if all 65,536 are taken
+    pub fn next_free_index(&self, owner: &Pubkey) -> Result<u16, AttestaError> {
+        let indexes: Vec<u16> = (0..=u16::MAX).collect();
+        for page in indexes.chunks(MAX_SCAN_PAGE_SIZE) {
+            let addresses: Vec<Pubkey> =
+                page.iter().map(|index| self.derive_account_address_by_index(owner, *index).0).collect();
+            let data = self.backend.get_multiple_account_data(&addresses)?;
+            let free = page.iter().zip(data).find(|(_, data)| data.as_ref().map_or(true, |data| data.is_empty()));
+            if let Some((index, _)) = free {
+                return Ok(*index);
+            }
+        }
+        Err(AttestaError::NoFreeIndex(*owner))
+    }
+
+    /// Gets the SOL and SPL token balances held by an Attesta account
+    ///
+    /// An account that doesn't exist on-chain yet reports zero SOL and no
+    /// tokens. Token accounts for the same mint are summed.
+    ///
+    /// # Parameters
+    /// - `account`: The Attesta account address (the PDA)
+    pub fn get_balances(&self, account: &Pubkey) -> Result<Balances, AttestaError> {
+        let sol_lamports = self.backend.get_lamports(account)?;
+        let token_accounts = self.backend.get_token_accounts_by_owner(account, &TOKEN_PROGRAM_ID)?;
+
+        balances_from_rpc(sol_lamports, &token_accounts)
+    }
+
+    /// Rebuilds an account's state by replaying its transactions
+    ///
+    /// Compare the result with the live account using `diff_against_chain`;
+    /// `ReconstructedState::warnings` lists what the replay couldn't apply.
+    ///
+    /// # Parameters
+    /// - `address`: The Attesta account
+    /// - `signatures`: Its transactions, oldest first (`getSignaturesForAddress`
+    ///   lists them newest first)
+    pub fn reconstruct_account(&self, address: &Pubkey, signatures: Vec<Signature>) -> Result<ReconstructedState, AttestaError> {
+        let transactions = signatures
+            .iter()
+            .map(|signature| self.backend.get_transaction(signature))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(replay_transactions(&self.program_id, address, &transactions))
+    }
+
+    /// Fetches an account and writes its whole state as JSON, for a support ticket
+    ///
+    /// Everything is included and decoded (policies, passkeys, settings),
+    /// with the SHA-256 of the stored bytes as `state_hash`. Read it back
+    /// with `import_account_json`; see `smart_account::json` for the schema.
+    #[cfg(feature = "serde")]
+    pub fn export_account_json(&self, address: &Pubkey) -> Result<String, AttestaError> {
+        let account = self.get_account(address)?;
+        Ok(export_account(Some(address), &account)?)
+    }
+
+    /// Reserves nonces for `count` transactions to be signed before any executes
+    ///
+    /// `prepare_execution` hands them out lowest first, so the requests of a
+    /// multi-step flow don't share a nonce. The transactions should land in
+    /// that order: once a later one executes, an earlier one may fall behind
+    /// the account's nonce, and `execute` then fails with `NonceSkipped`.
+    ///
+    /// Reservations the account's nonce overtakes (seen in an account fetched
+    /// through this client) are dropped.
+    pub fn reserve_nonces(&self, account: &AttestaAccount, count: u8) -> Vec<u64> {
+        self.nonces.reserve(account, count)
+    }
+
+    /// Prepares the challenge a passkey must sign to execute `request`
+    ///
+    /// Pass `challenge` (or `challenge_b64url`) to `navigator.credentials.get()`,
+    /// then hand the response to `complete_execution`.
+    ///
+    /// The request takes the lowest nonce from `reserve_nonces`, or else the
+    /// next one no earlier request from this client was given.
+    ///
+    /// # Parameters
+    /// - `account`: The account that will execute the transaction (its nonce must be current)
+    /// - `request`: The transaction to authorize
+    pub fn prepare_execution(&self, account: &AttestaAccount, request: &TransactionRequest) -> SigningRequest {
+        let nonce = self.nonces.assign(account, request.message_hash());
+        SigningRequest::with_nonce(account, request, nonce, unix_timestamp())
+    }
+
+    /// Checks a passkey's response to `signing_request` and builds the proof to submit
+    ///
+    /// Problems are caught here rather than on-chain: the error names the
+    /// field that didn't match (challenge, type, signature, ...).
+    pub fn complete_execution(
+        &self,
+        signing_request: &SigningRequest,
+        assertion: AssertionResponse,
+    ) -> Result<ProofEnvelope, AttestaError> {
+        signing_request.complete(assertion, unix_timestamp())
+    }
+
+    /// Submits a completed execution, retrying once if the outcome is unknown
+    ///
+    /// A failed send doesn't mean the transaction didn't land (the RPC may
+    /// have timed out after forwarding it). Before retrying, the account is
+    /// checked for the envelope's idempotency key; if the program already
+    /// ran the transaction, that's reported instead of sending again. A retry
+    /// that races the first attempt is answered by the program rather than
+    /// failing as a replay.
+    ///
+    /// On an account with lockout on, a refused signature (or a locked
+    /// account) also lands without error; only the instruction's return data
+    /// tells it apart. Simulate first, or check `failed_auth_count` and
+    /// `locked_until` on the account, where that matters.
+    ///
+    /// A proof whose nonce the account has already moved past (because a
+    /// transaction signed later landed first) is never sent: it fails with
+    /// `AttestaError::NonceSkipped`, and has to be prepared and signed again.
+    /// When a failed send is what shows the account had moved on, the nonce
+    /// sync guard (see `with_nonce_sync_guard`) reports it.
+    ///
+    /// With the owner's wallet among the credentials (see
+    /// `ExecutionCredentials`), the account is fetched to pick the path its
+    /// auth mode accepts. The owner signs `execute_as_owner` at the
+    /// account's next nonce, which passes over any nonce already handed to
+    /// a signing request.
+    ///
+    /// A transaction the account's policy needs more approvals for lands
+    /// too, as a proposal: `ExecuteResult::PendingApproval` says where, and
+    /// how many more passkeys have to approve it. Its nonce isn't used up.
+    ///
+    /// # Parameters
+    /// - `authority`: Submits the transaction and pays fees
+    /// - `attesta_account`: The user's Attesta account address
+    /// - `credentials`: The proof returned by `complete_execution`, the
+    ///   owner's wallet, or both
+    /// - `transaction_data`: The transaction data that was signed
     ///
     /// # Returns
-    /// The PDA address and bump seed
-    pub fn derive_account_address(&self, owner: &Pubkey, seed: &[u8]) -> (Pubkey, u8) {
-        Pubkey::find_program_address(
-            &[
-                b"attesta",
-                owner.as_ref(),
-                seed,
-            ],
+    /// The nonce the execution consumed and whether it ran on this call, or
+    /// the proposal it's waiting in
+    pub fn execute<'a>(
+        &self,
+        authority: &Keypair,
+        attesta_account: &Pubkey,
+        credentials: impl Into<ExecutionCredentials<'a>>,
+        transaction_data: Vec<u8>,
+    ) -> Result<ExecuteResult, AttestaError> {
+        let credentials = credentials.into();
+        let result = self.execute_with(authority, attesta_account, credentials, transaction_data);
+        #[cfg(feature = "metrics")]
+        self.record_execution(attesta_account, credentials, &result);
+        result
+    }
+
+    /// `execute`, once the credentials are known
+    fn execute_with(
+        &self,
+        authority: &Keypair,
+        attesta_account: &Pubkey,
+        credentials: ExecutionCredentials<'_>,
+        transaction_data: Vec<u8>,
+    ) -> Result<ExecuteResult, AttestaError> {
+        // Oversized data would only fail on-chain, after paying fees
+        self.limits().check_transaction_data_len(transaction_data.len())?;
+        TransactionRequest::from_bytes(&transaction_data)?;
+        let envelope = match credentials {
+            ExecutionCredentials::Passkey(envelope) => envelope,
+            ExecutionCredentials::Owner(owner) => {
+                let account = self.get_account(attesta_account)?;
+                return self
+                    .execute_as_owner(authority, attesta_account, &account, owner, transaction_data)
+                    .map(ExecuteResult::Executed);
+            }
+            ExecutionCredentials::Either { envelope, owner } => {
+                let account = self.get_account(attesta_account)?;
+                if !account.settings.auth_mode.allows_passkey() {
+                    return self
+                        .execute_as_owner(authority, attesta_account, &account, owner, transaction_data)
+                        .map(ExecuteResult::Executed);
+                }
+                envelope
+            }
+        };
+        self.limits().check_memo(&envelope.memo)?;
+        self.nonces.check(envelope.nonce, &envelope.message_hash)?;
+        let (proposal, _) =
+            derive_proposal_address(&self.program_id, attesta_account, &transaction_message_hash(&transaction_data));
+
+        let instruction = instructions::execute(
             &self.program_id,
+            attesta_account,
+            &authority.pubkey(),
+            envelope,
+            transaction_data,
         )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        if let Ok(signature) = self.send(authority, instruction.clone()) {
+            return self.landed(attesta_account, &proposal, envelope, &signature);
+        }
+
+        let account = self.reread_after_failure(attesta_account)?;
+        if let Some(receipt) = previous_execution(&account, envelope) {
+            return Ok(ExecuteResult::Executed(receipt));
+        }
+        if account.nonce >= envelope.nonce {
+            return Err(AttestaError::NonceSkipped { nonce: envelope.nonce, account_nonce: account.nonce });
+        }
+
+        let signature = self.send(authority, instruction)?;
+        self.landed(attesta_account, &proposal, envelope, &signature)
     }
-}
 
-/// Errors that can occur when using the Attesta client
-#[derive(Error, Debug)]
-pub enum AttestaError {
-    #[error("Not implemented yet")]
-    NotImplemented,
-    
-    #[error("Account not found")]
-    AccountNotFound,
-    
-    #[error("Invalid account data")]
-    InvalidAccountData,
-    
-    #[error("RPC error: {0}")]
-    RpcError(String),
+    /// Tells the metrics sink, if any, about an `execute` on `attesta_account`
+    ///
+    /// A passkey proof counts as received whatever became of it. A failure
+    /// with one of the program's denial codes is a policy denial; a proof
+    /// whose nonce the account had already used is a replay.
+    #[cfg(feature = "metrics")]
+    fn record_execution(
+        &self,
+        attesta_account: &Pubkey,
+        credentials: ExecutionCredentials<'_>,
+        result: &Result<ExecuteResult, AttestaError>,
+    ) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        if !matches!(credentials, ExecutionCredentials::Owner(_)) {
+            metrics.proof_received(attesta_account);
+        }
+        match result {
+            Err(AttestaError::NonceSkipped { .. }) => metrics.replay_rejected(attesta_account),
+            Err(AttestaError::TransactionFailed(TransactionError::InstructionError(_, InstructionError::Custom(code))))
+                if POLICY_DENIAL_ERRORS.contains(code) =>
+            {
+                metrics.policy_denied(attesta_account)
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads `attesta_account` again after a send failed, bypassing the cache
+    ///
+    /// The nonce sync guard compares it with the nonce the client last saw,
+    /// and drops the queued proofs it has passed over.
+    fn reread_after_failure(&self, attesta_account: &Pubkey) -> Result<AttestaAccount, AttestaError> {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = &self.cache {
+            cache.invalidate(attesta_account);
+        }
+        let account = match self.backend.get_account_data(attesta_account)? {
+            Some(data) => decode_attesta_account(&data)?,
+            None => return Err(AttestaError::AccountNotFound),
+        };
+        self.nonce_sync.resync(&self.nonces, attesta_account, &account);
+        self.nonces.observe(&account);
+        Ok(account)
+    }
+
+    /// What a passkey execution that landed did: ran, left a proposal, or
+    /// found it had already run
+    ///
+    /// An open proposal for the transaction means the program proposed it
+    /// instead of running it, and the nonce is still free. When another
+    /// relayer landed the same envelope first, the program answers with
+    /// `ALREADY_EXECUTED`: that's a success too, reported as
+    /// `AlreadyExecuted`. Its logs are read from `signature`; if the node
+    /// can't return them, the execution is reported as having run here.
+    fn landed(
+        &self,
+        attesta_account: &Pubkey,
+        proposal: &Pubkey,
+        envelope: &ProofEnvelope,
+        signature: &Signature,
+    ) -> Result<ExecuteResult, AttestaError> {
+        if let Some(data) = self.backend.get_account_data(proposal)? {
+            let (owner, pending) = decode_proposal(&data)?;
+            if owner == *attesta_account && !pending.threshold_reached() {
+                return Ok(ExecuteResult::PendingApproval {
+                    proposal_address: *proposal,
+                    approvals_needed: pending.approvals_needed(),
+                });
+            }
+        }
+        self.nonces.mark_executed(envelope.nonce, &envelope.message_hash);
+        let mut receipt = executed_receipt(envelope);
+        if self.answered_from_record(signature) {
+            // The record doesn't say who signed the first time
+            receipt.status = ExecutionStatus::AlreadyExecuted;
+            receipt.credential_id_hash = None;
+        }
+        Ok(ExecuteResult::Executed(receipt))
+    }
+
+    /// Whether the program answered `signature` from an earlier execution's record
+    fn answered_from_record(&self, signature: &Signature) -> bool {
+        self.backend.get_transaction(signature).is_ok_and(|transaction| {
+            parse_program_logs(&transaction.logs).iter().any(|event| event.code == codes::ALREADY_EXECUTED)
+        })
+    }
+
+    /// Sends `transaction_data` through `execute_as_owner`, signed by `owner`
+    ///
+    /// Not retried: without an idempotency key, a timed-out send that landed
+    /// can't be told apart from one that didn't, so check the account's
+    /// nonce before sending again.
+    fn execute_as_owner(
+        &self,
+        authority: &Keypair,
+        attesta_account: &Pubkey,
+        account: &AttestaAccount,
+        owner: &Keypair,
+        transaction_data: Vec<u8>,
+    ) -> Result<ExecutionReceipt, AttestaError> {
+        if !account.settings.auth_mode.allows_owner() {
+            return Err(AttestaError::CredentialsNotAccepted(account.settings.auth_mode));
+        }
+
+        let nonce = account.nonce + 1;
+        let instruction = instructions::execute_as_owner(
+            &self.program_id,
+            attesta_account,
+            &owner.pubkey(),
+            account.parent,
+            nonce,
+            transaction_data,
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+        self.send_instructions(authority, &[instruction], &[owner])?;
+        Ok(ExecutionReceipt { status: ExecutionStatus::Executed, nonce, memo_hash: None, credential_id_hash: None })
+    }
+
+    /// Simulates an `execute` and reports what it would do
+    ///
+    /// Denials and transactions that need approval come back as outcomes
+    /// rather than errors: the program reports them before failing.
+    ///
+    /// # Parameters
+    /// Those of `execute`; `authority` signs the simulated transaction.
+    ///
+    /// # Returns
+    /// - `Ok(outcome)` from the simulated instruction's return data
+    /// - `Err(AttestaError::SimulationFailed)` if it failed before reaching
+    ///   the policy (a bad signature or a replayed nonce, say)
+    pub fn simulate_execution(
+        &self,
+        authority: &Keypair,
+        attesta_account: &Pubkey,
+        envelope: &ProofEnvelope,
+        transaction_data: Vec<u8>,
+    ) -> Result<ExecuteOutcome, AttestaError> {
+        TransactionRequest::from_bytes(&transaction_data)?;
+
+        let instruction = instructions::execute(
+            &self.program_id,
+            attesta_account,
+            &authority.pubkey(),
+            envelope,
+            transaction_data,
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+        let blockhash = self.backend.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&authority.pubkey()),
+            &[authority],
+            blockhash,
+        );
+
+        let simulation = self.backend.simulate_transaction(&transaction)?;
+        match simulation.return_data {
+            Some(return_data) => decode_execute_outcome(&return_data),
+            None => Err(AttestaError::SimulationFailed(
+                simulation.err.unwrap_or_else(|| "no return data".to_string()),
+            )),
+        }
+    }
+
+    /// Returns the message hash a passkey must sign to upload `backup`
+    ///
+    /// Pass the resulting signature to `upload_backup` together with the
+    /// nonce that was signed.
+    pub fn backup_message_hash(&self, backup: &EncryptedBackup) -> Result<[u8; 32], AttestaError> {
+        let bytes = backup.to_bytes()
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+        Ok(action_message_hash(BACKUP_WRITE_ACTION, &bytes))
+    }
+
+    /// Returns the message hash a passkey must sign to delete the escrowed backup
+    pub fn delete_backup_message_hash(&self, attesta_account: &Pubkey) -> [u8; 32] {
+        let (backup_address, _) = derive_backup_address(&self.program_id, attesta_account);
+        action_message_hash(BACKUP_DELETE_ACTION, backup_address.as_ref())
+    }
+
+    /// Uploads an encrypted backup to the account's on-chain escrow
+    ///
+    /// Creates the escrow if it doesn't exist yet, otherwise overwrites the
+    /// backup that's already there. The owner pays rent for a new escrow.
+    /// After a rekey, the escrow only takes backups of that rekey generation
+    /// or later.
+    ///
+    /// # Parameters
+    /// - `owner`: The account owner (signs and pays fees)
+    /// - `attesta_account`: The user's Attesta account address
+    /// - `backup`: The encrypted backup to store (at most 2KB serialized)
+    /// - `webauthn_sig`: Passkey signature over `backup_message_hash(backup)`
+    /// - `nonce`: The nonce that was signed
+    ///
+    /// # Returns
+    /// The transaction signature
+    pub fn upload_backup(
+        &self,
+        owner: &Keypair,
+        attesta_account: &Pubkey,
+        backup: &EncryptedBackup,
+        webauthn_sig: &WebAuthnSignature,
+        nonce: u64,
+    ) -> Result<Signature, AttestaError> {
+        // Catch oversized and stale backups before paying for a transaction that would fail
+        backup.validate_escrow_size()?;
+        let stored = self.fetch_backup(attesta_account)?;
+        if let Some(stored) = &stored {
+            if backup.rekey_generation < stored.rekey_generation {
+                return Err(AttestaError::InvalidBackup(format!(
+                    "rekey generation {} is older than the stored backup's {}",
+                    backup.rekey_generation, stored.rekey_generation
+                )));
+            }
+        }
+
+        let instruction = if stored.is_some() {
+            instructions::update_backup(&self.program_id, attesta_account, webauthn_sig, nonce, backup)
+        } else {
+            instructions::store_backup(
+                &self.program_id,
+                attesta_account,
+                &owner.pubkey(),
+                webauthn_sig,
+                nonce,
+                backup,
+            )
+        }
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        self.send(owner, instruction)
+    }
+
+    /// Deletes the escrowed backup and returns its rent to the owner
+    pub fn delete_backup(
+        &self,
+        owner: &Keypair,
+        attesta_account: &Pubkey,
+        webauthn_sig: &WebAuthnSignature,
+        nonce: u64,
+    ) -> Result<Signature, AttestaError> {
+        let instruction = instructions::delete_backup(
+            &self.program_id,
+            attesta_account,
+            &owner.pubkey(),
+            webauthn_sig,
+            nonce,
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        self.send(owner, instruction)
+    }
+
+    /// Fetches the encrypted backup stored in the account's escrow
+    ///
+    /// # Returns
+    /// - `Ok(Some(backup))` if a backup is stored
+    /// - `Ok(None)` if the account has no backup escrow
+    pub fn fetch_backup(&self, attesta_account: &Pubkey) -> Result<Option<EncryptedBackup>, AttestaError> {
+        let (backup_address, _) = derive_backup_address(&self.program_id, attesta_account);
+        match self.backend.get_account_data(&backup_address)? {
+            Some(data) => decode_backup_escrow(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the message hash a passkey must sign to turn on the proof log
+    pub fn enable_proof_log_message_hash(&self, attesta_account: &Pubkey) -> [u8; 32] {
+        let (proof_log_address, _) = derive_proof_log_address(&self.program_id, attesta_account);
+        action_message_hash(PROOF_LOG_ENABLE_ACTION, proof_log_address.as_ref())
+    }
+
+    /// Fetches the account's proof log
+    ///
+    /// # Returns
+    /// - `Ok(Some(log))` with the most recent executions, oldest first
+    /// - `Ok(None)` if the account hasn't enabled its proof log
+    pub fn fetch_proof_log(&self, attesta_account: &Pubkey) -> Result<Option<ProofLog>, AttestaError> {
+        let (proof_log_address, _) = derive_proof_log_address(&self.program_id, attesta_account);
+        match self.backend.get_account_data(&proof_log_address)? {
+            Some(data) => decode_proof_log(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the message hash a passkey must sign to change the account's settings
+    pub fn settings_message_hash(&self, settings: &AccountSettings) -> [u8; 32] {
+        action_message_hash(SETTINGS_UPDATE_ACTION, &settings.to_bytes())
+    }
+
+    /// Returns the message hash the primary passkey must sign to set the account's auth mode
+    pub fn auth_mode_message_hash(&self, mode: AuthMode, lock: bool) -> [u8; 32] {
+        action_message_hash(AUTH_MODE_ACTION, &auth_mode_payload(mode, lock))
+    }
+
+    /// Returns the message hash the primary passkey must sign to let `executor` submit `execute`
+    pub fn add_executor_message_hash(&self, executor: &Pubkey) -> [u8; 32] {
+        action_message_hash(EXECUTOR_ADD_ACTION, executor.as_ref())
+    }
+
+    /// Returns the message hash the primary passkey must sign to take `executor` off the list
+    pub fn remove_executor_message_hash(&self, executor: &Pubkey) -> [u8; 32] {
+        action_message_hash(EXECUTOR_REMOVE_ACTION, executor.as_ref())
+    }
+
+    /// Returns the message hash the primary passkey must sign to set the
+    /// emergency override's limits (0 and 0 turn it off)
+    pub fn emergency_configure_message_hash(&self, max_lamports: u64, period_seconds: u32) -> [u8; 32] {
+        action_message_hash(EMERGENCY_CONFIGURE_ACTION, &EmergencyOverride::config_bytes(max_lamports, period_seconds))
+    }
+
+    /// Returns the message hash a passkey must sign to set (or, with `None`, remove)
+    /// the account's inheritance config
+    pub fn inheritance_message_hash(&self, config: Option<&InheritanceConfig>) -> Result<[u8; 32], AttestaError> {
+        let payload = match config {
+            Some(config) => config.to_bytes().map_err(|_| AttestaError::InvalidAccountData)?,
+            None => Vec::new(),
+        };
+        Ok(action_message_hash(INHERITANCE_CONFIGURE_ACTION, &payload))
+    }
+
+    /// Returns the message hash a passkey must sign to change the policy at `index`
+    ///
+    /// `action` is one of `POLICY_ADD_ACTION`, `POLICY_REMOVE_ACTION` (with an
+    /// empty `policy`) or `POLICY_REPLACE_ACTION`.
+    pub fn policy_change_message_hash(&self, action: &[u8], index: u8, policy: &[u8]) -> [u8; 32] {
+        action_message_hash(action, &policy_change_payload(index, policy))
+    }
+
+    /// Returns the message hash a passkey must sign for a heartbeat
+    pub fn heartbeat_message_hash(&self) -> [u8; 32] {
+        action_message_hash(HEARTBEAT_ACTION, &[])
+    }
+
+    /// Returns the message hash a passkey must sign to schedule `transaction_data`
+    /// for the window from `executable_after` until `executable_before`
+    pub fn schedule_message_hash(&self, transaction_data: &[u8], executable_after: i64, executable_before: Option<i64>) -> [u8; 32] {
+        action_message_hash(SCHEDULE_ACTION, &schedule_payload(transaction_data, executable_after, executable_before))
+    }
+
+    /// Returns the message hash a passkey must sign to cancel the transaction
+    /// the account scheduled with `scheduled_nonce`
+    pub fn cancel_scheduled_message_hash(&self, attesta_account: &Pubkey, scheduled_nonce: u64) -> [u8; 32] {
+        let (schedule_address, _) = derive_schedule_address(&self.program_id, attesta_account, scheduled_nonce);
+        action_message_hash(SCHEDULE_CANCEL_ACTION, schedule_address.as_ref())
+    }
+
+    /// Returns the message hash a passkey must sign to attest `policy_hash`
+    /// (the account's current one) until `expires_at`
+    pub fn attest_policy_message_hash(&self, policy_hash: &[u8; 32], expires_at: i64) -> [u8; 32] {
+        action_message_hash(POLICY_ATTEST_ACTION, &attestation_payload(policy_hash, expires_at))
+    }
+
+    /// Checks that the attestation at `attestation_address` vouches for
+    /// `expected_policy_hash` right now
+    ///
+    /// For a third party (a lender, say) that requires an account to have a
+    /// given policy. Fetches the attestation and the account it names, and
+    /// checks that the attestation is this program's, hasn't expired, and
+    /// that the account still has the attested policy.
+    ///
+    /// # Parameters
+    /// - `attestation_address`: The attestation, as given by the account's owner
+    /// - `expected_policy_hash`: The `Policy::canonical_hash` of the policy required
+    ///
+    /// # Returns
+    /// The attestation, or `AttestaError::InvalidAttestation` saying why it doesn't hold
+    pub fn verify_policy_attestation(
+        &self,
+        attestation_address: &Pubkey,
+        expected_policy_hash: &[u8; 32],
+    ) -> Result<PolicyAttestation, AttestaError> {
+        let data = self.backend.get_account_data(attestation_address)?.ok_or(AttestaError::AccountNotFound)?;
+        let attestation = decode_policy_attestation(&data)?;
+        // Only the program can write at its PDAs, so this rules out a look-alike account
+        let (expected_address, _) =
+            derive_policy_attestation_address(&self.program_id, &attestation.attesta_account, attestation.nonce);
+        if expected_address != *attestation_address {
+            return Err(AttestationError::InvalidData.into());
+        }
+
+        let account = self.get_account(&attestation.attesta_account)?;
+        check_attestation(&attestation, &attestation.attesta_account, &account, expected_policy_hash, unix_timestamp())?;
+        Ok(attestation)
+    }
+
+    /// Replaces `attesta_account`'s policies with the one `edit` returns
+    ///
+    /// `edit` is given the account as just fetched, and returns the new
+    /// policy (`None` for no restrictions). The update only applies if the
+    /// account is still at the `state_version` it was read at, so a change
+    /// that lands in between (from another device, say) is never silently
+    /// overwritten. See `with_concurrency_retries` for retrying.
+    ///
+    /// # Parameters
+    /// - `owner`: The account's owner; signs and pays
+    /// - `edit`: Builds the new policy from the current account
+    ///
+    /// # Returns
+    /// - The transaction signature
+    /// - `Err(AttestaError::ConcurrentModification)` if the account kept
+    ///   changing underneath and no retries were left
+    pub fn update_policy(
+        &self,
+        owner: &Keypair,
+        attesta_account: &Pubkey,
+        mut edit: impl FnMut(&AttestaAccount) -> Option<Policy>,
+    ) -> Result<Signature, AttestaError> {
+        let mut retries = self.concurrency_retries;
+        loop {
+            let account = self.get_account(attesta_account)?;
+            let policy = edit(&account);
+            if let Some(policy) = &policy {
+                check_policy_cost_within(policy, self.limits().max_policy_compute_units)?;
+            }
+            let instruction = instructions::update_policy(
+                &self.program_id,
+                attesta_account,
+                &owner.pubkey(),
+                policy.as_ref(),
+                account.state_version,
+            )
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+
+            match self.send(owner, instruction) {
+                Err(e) if is_concurrent_modification(&e) => {
+                    if retries == 0 {
+                        return Err(AttestaError::ConcurrentModification { read_version: account.state_version });
+                    }
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns the message hash the proposer (or the primary passkey) must
+    /// sign to cancel the proposal at `proposal` for `reason`
+    pub fn cancel_proposal_message_hash(&self, proposal: &Pubkey, reason: CancelReason) -> [u8; 32] {
+        action_message_hash(PROPOSAL_CANCEL_ACTION, &cancel_proposal_payload(proposal, reason))
+    }
+
+    /// Lists `attesta_account`'s proposals that can still be approved
+    ///
+    /// Only the account's proposal PDAs are fetched: the RPC node filters
+    /// on their layout. Expired proposals, and ones that already have their
+    /// approvals, are left out.
+    ///
+    /// # Returns
+    /// The proposals, soonest to expire first
+    pub fn list_pending_proposals(&self, attesta_account: &Pubkey) -> Result<Vec<ProposalSummary>, AttestaError> {
+        let accounts = self.backend.get_program_accounts_matching(&self.program_id, &proposal_filters(attesta_account))?;
+        Ok(pending_proposals(attesta_account, &accounts, unix_timestamp()))
+    }
+
+    /// Reports every check `attesta_account` currently enforces
+    ///
+    /// The account's own `security_summary`, with its open proposals
+    /// counted from `list_pending_proposals`. What an `inspect` command
+    /// prints.
+    pub fn security_summary(&self, attesta_account: &Pubkey) -> Result<SecuritySummary, AttestaError> {
+        let account = self.get_account(attesta_account)?;
+        let open_proposals = self.list_pending_proposals(attesta_account)?.len();
+        Ok(account.security_summary(unix_timestamp()).with_open_proposals(open_proposals))
+    }
+
+    /// Prepares the challenge a passkey must sign to approve `proposal`
+    ///
+    /// Takes a nonce the way `prepare_execution` does. Check
+    /// `ProposalSummary::has_approved` first: a passkey can only approve
+    /// once.
+    ///
+    /// # Parameters
+    /// - `account`: The account the proposal belongs to (its nonce must be current)
+    /// - `proposal`: The proposal, from `list_pending_proposals`
+    pub fn prepare_approval(&self, account: &AttestaAccount, proposal: &ProposalSummary) -> SigningRequest {
+        let message_hash = proposal.approval_message_hash();
+        let nonce = self.nonces.assign(account, message_hash);
+        SigningRequest::for_message_hash(account, message_hash, nonce, unix_timestamp())
+    }
+
+    /// Checks a passkey's response to a `prepare_approval` request and sends the approval
+    ///
+    /// # Parameters
+    /// - `payer`: Pays the transaction fee (needn't be the account's owner)
+    /// - `attesta_account`: The account the proposal belongs to
+    /// - `proposal`: The proposal `signing_request` was prepared for
+    /// - `signing_request`, `assertion`: The prompt and the passkey's response
+    ///
+    /// # Returns
+    /// The transaction signature
+    pub fn submit_approval(
+        &self,
+        payer: &Keypair,
+        attesta_account: &Pubkey,
+        proposal: &ProposalSummary,
+        signing_request: &SigningRequest,
+        assertion: AssertionResponse,
+    ) -> Result<Signature, AttestaError> {
+        if signing_request.message_hash != proposal.approval_message_hash() {
+            return Err(AttestaError::AssertionMismatch {
+                field: "challenge",
+                reason: format!("the signing request isn't for proposal {}", proposal.address),
+            });
+        }
+        let envelope = signing_request.complete(assertion, unix_timestamp())?;
+        self.nonces.check(envelope.nonce, &envelope.message_hash)?;
+
+        let instruction = instructions::approve_proposal(
+            &self.program_id,
+            attesta_account,
+            &proposal.address,
+            &envelope.webauthn_sig,
+            envelope.nonce,
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+        let signature = self.send(payer, instruction)?;
+        self.nonces.mark_executed(envelope.nonce, &envelope.message_hash);
+        Ok(signature)
+    }
+
+    /// Creates `owner`'s Attesta account with its rent paid by `sponsor_pool`
+    ///
+    /// `relayer` pays the transaction fee, so `owner` needs no SOL at all;
+    /// it only signs. The account is created at `registration.account_address`
+    /// exactly as `initialize` would create it.
+    ///
+    /// # Parameters
+    /// - `relayer`: Pays the transaction fee
+    /// - `owner`: The new account's owner (signs, pays nothing)
+    /// - `sponsor_pool`: The pool paying the rent (see `get_pool_status`)
+    /// - `request`, `registration`: The first passkey and its proof, from
+    ///   `RegistrationRequest::complete`
+    /// - `policy`: The account's policy (`None` for an open account)
+    ///
+    /// # Returns
+    /// - `Ok(signature)`: The transaction signature
+    /// - `Err(AttestaError::FeatureNotEnabled)` if the program was built
+    ///   without sponsorship
+    pub fn sponsor_initialize(
+        &self,
+        relayer: &Keypair,
+        owner: &Keypair,
+        sponsor_pool: &Pubkey,
+        request: &RegistrationRequest,
+        registration: &Registration,
+        policy: Option<&Policy>,
+    ) -> Result<Signature, AttestaError> {
+        self.require_feature(&relayer.pubkey(), ProgramFeature::Sponsorship)?;
+        let initialize = instructions::sponsored_initialize(
+            &self.program_id,
+            sponsor_pool,
+            &owner.pubkey(),
+            request.public_key,
+            request.credential_id.clone(),
+            policy,
+            false,
+            &registration.webauthn_sig,
+            Vec::new(),
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+
+        self.send_instructions(
+            relayer,
+            &[ComputeBudgetInstruction::set_compute_unit_limit(INITIALIZE_COMPUTE_UNITS), initialize],
+            &[owner],
+        )
+    }
+
+    /// Plans everything a new user's first transaction needs
+    ///
+    /// Checks which accounts `request` needs that don't exist yet (the
+    /// owner's Attesta account, a token transfer's destination account) and
+    /// returns the instructions creating them, followed by the `execute`,
+    /// with the rent and fees it will all cost. Nothing is sent: pass the
+    /// plan to `execute_plan`. See the `preparation` module for what isn't
+    /// covered.
+    ///
+    /// # Parameters
+    /// - `owner`: The wallet that owns (or will own) the account; it pays
+    /// - `passkey`: The passkey to create the account with, if it's missing
+    /// - `request`: The signed transaction
+    ///
+    /// # Returns
+    /// - `Ok(plan)` with the setup steps in order (none if nothing's missing)
+    /// - `Err(AttestaError::NonceSkipped)` if the account is already past the proof's nonce
+    /// - `Err(AttestaError::InvalidPlan)` if a missing account can't be
+    ///   created from what was given
+    pub fn prepare_first_transaction(
+        &self,
+        owner: &Pubkey,
+        passkey: &NewAccountPasskey,
+        request: &FirstTransaction,
+    ) -> Result<PreparationPlan, AttestaError> {
+        plan_first_transaction(self.backend.as_ref(), &self.program_id, owner, passkey, request)
+    }
+
+    /// Sends a plan from `prepare_first_transaction`, one transaction at a time
+    ///
+    /// Each transaction is confirmed before the next is sent, and
+    /// `on_progress` hears about each one as it confirms. If one fails, the
+    /// rest aren't sent; preparing again picks up from what exists by then.
+    ///
+    /// # Parameters
+    /// - `plan`: The plan to send
+    /// - `payer`: The owner the plan was prepared for
+    /// - `on_progress`: Called after each confirmed transaction
+    ///
+    /// # Returns
+    /// The signatures of the transactions sent, in order
+    pub fn execute_plan(
+        &self,
+        plan: &PreparationPlan,
+        payer: &Keypair,
+        mut on_progress: impl FnMut(PlanProgress),
+    ) -> Result<Vec<Signature>, AttestaError> {
+        if payer.pubkey() != plan.payer {
+            return Err(AttestaError::InvalidPlan(format!("the plan is paid for by {}, not {}", plan.payer, payer.pubkey())));
+        }
+
+        let transactions = plan.transactions();
+        let mut signatures = Vec::with_capacity(transactions.len());
+        for instructions in &transactions {
+            let signature = self.send_instructions(payer, instructions, &[])?;
+            signatures.push(signature);
+            on_progress(PlanProgress { completed: signatures.len(), total: transactions.len(), signature });
+        }
+        Ok(signatures)
+    }
+
+    /// Reads a sponsor pool's limits, funds, and what it has left today
+    ///
+    /// # Parameters
+    /// - `sponsor_pool`: The pool (see `derive_sponsor_pool_address`)
+    /// - `now`: The current Unix timestamp, which picks the day counted
+    pub fn get_pool_status(&self, sponsor_pool: &Pubkey, now: i64) -> Result<SponsorPoolStatus, AttestaError> {
+        let data = self.backend.get_account_data(sponsor_pool)?.ok_or(AttestaError::AccountNotFound)?;
+        let (authority, pool) = decode_sponsor_pool(&data)?;
+        let lamports = self.backend.get_lamports(sponsor_pool)?.unwrap_or_default();
+
+        Ok(SponsorPoolStatus {
+            authority,
+            remaining_today: pool.remaining_on(now),
+            lamports,
+            pool,
+        })
+    }
+
+    /// Reads the version of the deployed program
+    ///
+    /// Simulates `get_program_version`, so nothing is paid, but `payer` must
+    /// be an existing account to stand as the fee payer. Pin
+    /// `ProgramVersion::hash` in `AccountSettings::pinned_program_version`
+    /// to stop executing if the program changes.
+    pub fn get_program_version(&self, payer: &Pubkey) -> Result<ProgramVersion, AttestaError> {
+        let blockhash = self.backend.get_latest_blockhash()?;
+        let message = Message::new_with_blockhash(&[instructions::get_program_version(&self.program_id)], Some(payer), &blockhash);
+
+        let simulation = self.backend.simulate_transaction(&Transaction::new_unsigned(message))?;
+        match simulation.return_data {
+            Some(return_data) => ProgramVersion::from_return_data(&return_data).ok_or(AttestaError::InvalidReturnData),
+            None => Err(AttestaError::SimulationFailed(
+                simulation.err.unwrap_or_else(|| "no return data".to_string()),
+            )),
+        }
+    }
+
+    /// Reads which optional features the deployed program was built with
+    ///
+    /// Simulates `get_program_features` like `get_program_version`. A program
+    /// from before that instruction has every feature.
+    pub fn program_features(&self, payer: &Pubkey) -> Result<ProgramFeatures, AttestaError> {
+        let blockhash = self.backend.get_latest_blockhash()?;
+        let message = Message::new_with_blockhash(&[instructions::get_program_features(&self.program_id)], Some(payer), &blockhash);
+
+        let simulation = self.backend.simulate_transaction(&Transaction::new_unsigned(message))?;
+        match (simulation.return_data, simulation.err) {
+            (Some(return_data), _) => ProgramFeatures::from_return_data(&return_data).ok_or(AttestaError::InvalidReturnData),
+            (None, Some(err)) if err.ends_with(&format!("custom program error: {:#x}", INSTRUCTION_NOT_FOUND)) => {
+                Ok(ProgramFeatures::all())
+            }
+            (None, err) => Err(AttestaError::SimulationFailed(err.unwrap_or_else(|| "no return data".to_string()))),
+        }
+    }
+
+    /// Reads the deployed program's version, features and limits
+    ///
+    /// Simulates `get_protocol_constants` like `get_program_version`, the
+    /// first time only: the result is kept for the client's lifetime, and
+    /// from then on the client checks requests against the program's limits
+    /// rather than the ones it was compiled with (see `limits`).
+    pub fn protocol_constants(&self, payer: &Pubkey) -> Result<ProtocolConstants, AttestaError> {
+        if let Some(constants) = self.protocol_constants.get() {
+            return Ok(constants.clone());
+        }
+
+        let blockhash = self.backend.get_latest_blockhash()?;
+        let message = Message::new_with_blockhash(&[instructions::get_protocol_constants(&self.program_id)], Some(payer), &blockhash);
+        let simulation = self.backend.simulate_transaction(&Transaction::new_unsigned(message))?;
+        let constants = match simulation.return_data {
+            Some(return_data) => ProtocolConstants::from_return_data(&return_data).ok_or(AttestaError::InvalidReturnData)?,
+            None => {
+                return Err(AttestaError::SimulationFailed(
+                    simulation.err.unwrap_or_else(|| "no return data".to_string()),
+                ))
+            }
+        };
+        Ok(self.protocol_constants.get_or_init(|| constants).clone())
+    }
+
+    /// The limits requests are checked against before sending
+    ///
+    /// The deployed program's, once `protocol_constants` has read them;
+    /// until then, the ones these crates were compiled with.
+    pub fn limits(&self) -> ProtocolLimits {
+        self.protocol_constants.get().map_or_else(ProtocolLimits::compiled, |constants| constants.limits)
+    }
+
+    /// Checks that the deployed program was built with `feature`
+    ///
+    /// Without it, the feature's instructions only fail on-chain; call this
+    /// before building them (`sponsor_initialize` does).
+    ///
+    /// # Returns
+    /// - `Ok(())` if the program has the feature
+    /// - `Err(AttestaError::FeatureNotEnabled)` if it was built without it
+    pub fn require_feature(&self, payer: &Pubkey, feature: ProgramFeature) -> Result<(), AttestaError> {
+        if self.program_features(payer)?.is_enabled(feature) {
+            Ok(())
+        } else {
+            Err(AttestaError::FeatureNotEnabled(feature.name()))
+        }
+    }
+
+    /// Returns the message hash a passkey must sign to accept `version` after an upgrade
+    ///
+    /// Show the user what changed before asking: once signed, the account
+    /// executes under the new version.
+    pub fn upgrade_acknowledgment_message_hash(&self, version: &ProgramVersion) -> [u8; 32] {
+        action_message_hash(UPGRADE_ACKNOWLEDGE_ACTION, &version.hash())
+    }
+
+    /// Returns the message hashes guardians sign for a recovery drill
+    ///
+    /// The first approval signs the initiate hash, every later one the
+    /// approve hash. Both commit to the rehearsed replacement passkey.
+    ///
+    /// # Returns
+    /// `(initiate_hash, approve_hash)`
+    pub fn recovery_drill_message_hashes(
+        &self,
+        account: &AttestaAccount,
+        new_public_key: &[u8; P256_PUBKEY_LEN],
+        new_credential_id: &[u8],
+    ) -> ([u8; 32], [u8; 32]) {
+        let request_hash = recovery_request_hash(
+            new_public_key,
+            &account.credential_lookup_id(new_credential_id),
+        );
+        (
+            action_message_hash(RecoveryMode::Drill.initiate_action(), &request_hash),
+            action_message_hash(RecoveryMode::Drill.approve_action(), &request_hash),
+        )
+    }
+
+    /// Rehearses a social recovery end to end without changing any keys
+    ///
+    /// Starts a drill with the first approval and submits the rest one by
+    /// one, then checks that the account recorded a successful drill.
+    ///
+    /// # Parameters
+    /// - `payer`: Pays the transaction fees
+    /// - `attesta_account`: The user's Attesta account address
+    /// - `new_public_key`: The rehearsed replacement passkey's public key
+    /// - `new_credential_id`: The rehearsed replacement passkey's credential ID
+    /// - `approvals`: Guardian signatures (see `recovery_drill_message_hashes`)
+    ///   and the nonces they signed, in submission order
+    ///
+    /// # Returns
+    /// When the drill succeeded (the account's `last_drill_at`), or
+    /// `RecoveryDrillFailed` if the approvals didn't reach the threshold
+    pub fn run_recovery_drill(
+        &self,
+        payer: &Keypair,
+        attesta_account: &Pubkey,
+        new_public_key: [u8; P256_PUBKEY_LEN],
+        new_credential_id: &[u8],
+        approvals: &[(WebAuthnSignature, u64)],
+    ) -> Result<i64, AttestaError> {
+        let ((first_sig, first_nonce), rest) = approvals
+            .split_first()
+            .ok_or(AttestaError::RecoveryDrillFailed { approvals: 0 })?;
+
+        let instruction = instructions::initiate_recovery_drill(
+            &self.program_id,
+            attesta_account,
+            &payer.pubkey(),
+            first_sig,
+            *first_nonce,
+            new_public_key,
+            new_credential_id.to_vec(),
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+        self.send(payer, instruction)?;
+
+        for (webauthn_sig, nonce) in rest {
+            let instruction = instructions::approve_recovery_drill(
+                &self.program_id,
+                attesta_account,
+                &payer.pubkey(),
+                webauthn_sig,
+                *nonce,
+            )
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+            self.send(payer, instruction)?;
+        }
+
+        // A drill that reached the threshold is cleared; one still pending fell short
+        let account = self.get_account(attesta_account)?;
+        match account.pending_drill {
+            None => Ok(account.last_drill_at),
+            Some(drill) => Err(AttestaError::RecoveryDrillFailed { approvals: drill.approvals.len() }),
+        }
+    }
+
+    /// Signs and submits a single instruction, paid for by `payer`
+    fn send(&self, payer: &Keypair, instruction: Instruction) -> Result<Signature, AttestaError> {
+        self.send_instructions(payer, &[instruction], &[])
+    }
+
+    /// Signs and submits `instructions` as one transaction, paid for by `payer`
+    ///
+    /// `signers` are whoever else the instructions need signatures from.
+    /// Waits for the transaction as the client's `ConfirmationStrategy` says.
+    pub(crate) fn send_instructions(
+        &self,
+        payer: &Keypair,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<Signature, AttestaError> {
+        let mut all_signers = vec![payer];
+        for signer in signers {
+            if all_signers.iter().all(|added| added.pubkey() != signer.pubkey()) {
+                all_signers.push(signer);
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let result = send_and_confirm(self.backend.as_ref(), &self.confirmation, |blockhash| {
+            Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &all_signers, blockhash)
+        });
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.transaction_submitted();
+            if result.is_ok() {
+                metrics.transaction_confirmed(started.elapsed());
+            }
+        }
+
+        // Drop cached copies of what this may have changed, even on failure:
+        // a send that timed out can still land
+        #[cfg(feature = "cache")]
+        if let Some(cache) = &self.cache {
+            let written = instructions
+                .iter()
+                .flat_map(|instruction| &instruction.accounts)
+                .filter(|account| account.is_writable);
+            for account in written {
+                cache.invalidate(&account.pubkey);
+            }
+        }
+
+        if let (Ok(signature), Some(hook)) = (&result, &self.on_sent) {
+            hook(signature, instructions);
+        }
+        result
+    }
+}
+
+/// What `AttestaClient::execute` did with a transaction that landed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteResult {
+    /// It ran on this call or before, or (with lockout on) was refused
+    /// without failing; the receipt's status says which
+    Executed(ExecutionReceipt),
+
+    /// It needs more approvals and waits in a proposal, without having used
+    /// up its nonce
+    PendingApproval {
+        /// The proposal PDA, for `prepare_approval` and `cancel_proposal`
+        proposal_address: Pubkey,
+
+        /// How many more of the account's passkeys have to approve it
+        approvals_needed: u8,
+    },
+}
+
+impl ExecuteResult {
+    /// The receipt, unless the transaction is waiting for approvals
+    pub fn receipt(&self) -> Option<ExecutionReceipt> {
+        match self {
+            ExecuteResult::Executed(receipt) => Some(*receipt),
+            ExecuteResult::PendingApproval { .. } => None,
+        }
+    }
+}
+
+/// What `AttestaClient::execute` has to authorize a transaction with
+///
+/// A `&ProofEnvelope` converts into `Passkey`, so passkey-only callers
+/// pass the envelope as they always have.
+#[derive(Clone, Copy)]
+pub enum ExecutionCredentials<'a> {
+    /// A passkey proof from `complete_execution`
+    Passkey(&'a ProofEnvelope),
+
+    /// The owner's wallet, for accounts whose auth mode accepts it
+    Owner(&'a Keypair),
+
+    /// Both: the passkey, unless the account only accepts its owner
+    Either { envelope: &'a ProofEnvelope, owner: &'a Keypair },
+}
+
+impl<'a> From<&'a ProofEnvelope> for ExecutionCredentials<'a> {
+    fn from(envelope: &'a ProofEnvelope) -> Self {
+        ExecutionCredentials::Passkey(envelope)
+    }
+}
+
+/// Checks whether the account already ran the execution in `envelope`
+///
+/// # Returns
+/// - `Some(receipt)` with `AlreadyExecuted` if the account has a record of it
+/// - `None` if it hasn't run (or its record has been dropped)
+pub fn previous_execution(account: &AttestaAccount, envelope: &ProofEnvelope) -> Option<ExecutionReceipt> {
+    account
+        .find_idempotency_record(&envelope.idempotency_key)
+        .filter(|record| record.message_hash == envelope.message_hash && record.nonce == envelope.nonce)
+        .map(|record| ExecutionReceipt {
+            status: ExecutionStatus::AlreadyExecuted,
+            nonce: record.nonce,
+            memo_hash: memo_hash(&envelope.memo),
+            credential_id_hash: None,
+        })
+}
+
+/// The receipt for `envelope`'s transaction running on this call
+fn executed_receipt(envelope: &ProofEnvelope) -> ExecutionReceipt {
+    ExecutionReceipt {
+        status: ExecutionStatus::Executed,
+        nonce: envelope.nonce,
+        memo_hash: memo_hash(&envelope.memo),
+        credential_id_hash: Some(credential_id_hash(&envelope.webauthn_sig.credential_id)),
+    }
+}
+
+/// Checks that an archived proof is the authorization behind a logged execution
+///
+/// Verifies the signature against the key the signing credential had when
+/// the entry was logged, so proofs made before a passkey was replaced or
+/// removed still check out (as long as the account's key history reaches
+/// back that far).
+///
+/// # Parameters
+/// - `account`: The account's current state (from `get_account`)
+/// - `entry`: The execution, from `fetch_proof_log`
+/// - `envelope`: The proof that was submitted for it
+pub fn verify_logged_proof(
+    account: &AttestaAccount,
+    entry: &ProofLogEntry,
+    envelope: &ProofEnvelope,
+) -> Result<(), AttestaError> {
+    Ok(smart_account::verify_logged_proof(account, entry, envelope)?)
+}
+
+/// Anchor's `InstructionFallbackNotFound`, from a program without the instruction
+const INSTRUCTION_NOT_FOUND: u32 = 101;
+
+/// Whether `error` is the program refusing a change made against a stale account
+fn is_concurrent_modification(error: &AttestaError) -> bool {
+    matches!(
+        error,
+        AttestaError::TransactionFailed(TransactionError::InstructionError(_, InstructionError::Custom(code)))
+            if *code == CONCURRENT_MODIFICATION_ERROR
+    )
+}
+
+/// Checks that the program will accept `policy`'s compute cost
+///
+/// Run this before `instructions::update_policy`: the program refuses a
+/// policy estimated over `MAX_POLICY_COMPUTE_UNITS`, since `execute` might not
+/// fit its compute budget with it.
+///
+/// # Returns
+/// - `Ok(units)`: The estimated compute units `execute` spends on the policy
+/// - `Err(AttestaError::PolicyTooExpensive)` if the program would refuse it
+pub fn check_policy_cost(policy: &Policy) -> Result<u32, AttestaError> {
+    check_policy_cost_within(policy, MAX_POLICY_COMPUTE_UNITS)
+}
+
+/// `check_policy_cost` against a ceiling the program reported (`ProtocolLimits`)
+pub(crate) fn check_policy_cost_within(policy: &Policy, max: u32) -> Result<u32, AttestaError> {
+    let estimated = policy.estimated_compute_units();
+    if estimated > max {
+        return Err(AttestaError::PolicyTooExpensive { estimated, max });
+    }
+    Ok(estimated)
+}
+
+/// The current Unix timestamp from the system clock
+pub(crate) fn unix_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Decodes the raw data of an on-chain Attesta account
+///
+/// The program wraps the serialized `AttestaAccount` in an Anchor account,
+/// which is allocated with room to grow - trailing space is ignored. This is
+/// the same layout `smart_account::storage` reads and writes.
+pub fn decode_attesta_account(data: &[u8]) -> Result<AttestaAccount, AttestaError> {
+    smart_account::storage::decode_attesta_account(data)
+        .map_err(|_| AttestaError::InvalidAccountData)
+}
+
+/// Reads an account written by `AttestaClient::export_account_json`
+///
+/// Refuses accounts the program couldn't have stored (an invalid passkey
+/// registry or settings, extra policies without a first one, and so on),
+/// and exports whose `state_hash` no longer matches their fields.
+#[cfg(feature = "serde")]
+pub fn import_account_json(json: &str) -> Result<AttestaAccount, AttestaError> {
+    Ok(import_account(json)?)
+}
+
+/// Decodes the `ExecuteOutcome` an `execute` instruction returned
+///
+/// Takes the return data of a simulation (`SimulationResult::return_data`)
+/// or of a confirmed transaction's metadata, base64-decoded.
+pub fn decode_execute_outcome(return_data: &[u8]) -> Result<ExecuteOutcome, AttestaError> {
+    ExecuteOutcome::from_return_data(return_data).ok_or(AttestaError::InvalidReturnData)
+}
+
+/// Mirror of the program's `BackupEscrow` account layout
+#[derive(BorshDeserialize)]
+struct BackupEscrowData {
+    _attesta_account: Pubkey,
+    backup: Vec<u8>,
+    _updated_at: i64,
+    _bump: u8,
+}
+
+/// Decodes the raw data of a backup escrow account into an EncryptedBackup
+///
+/// The escrow is allocated for the largest allowed backup, so smaller
+/// backups leave unused space at the end of the account - that's expected.
+pub fn decode_backup_escrow(data: &[u8]) -> Result<EncryptedBackup, AttestaError> {
+    if data.len() < ACCOUNT_DISCRIMINATOR_LEN || data[..ACCOUNT_DISCRIMINATOR_LEN] != account_discriminator("BackupEscrow") {
+        return Err(AttestaError::InvalidAccountData);
+    }
+
+    let mut body = &data[ACCOUNT_DISCRIMINATOR_LEN..];
+    let escrow = BackupEscrowData::deserialize(&mut body)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+
+    EncryptedBackup::from_bytes(&escrow.backup)
+        .map_err(|e| AttestaError::InvalidBackup(e.to_string()))
+}
+
+/// Mirror of the program's `ProofLogData` account layout
+#[derive(BorshDeserialize)]
+struct ProofLogData {
+    _attesta_account: Pubkey,
+    log: Vec<u8>,
+    _bump: u8,
+}
+
+/// Decodes the raw data of a proof log account
+pub fn decode_proof_log(data: &[u8]) -> Result<ProofLog, AttestaError> {
+    if data.len() < ACCOUNT_DISCRIMINATOR_LEN || data[..ACCOUNT_DISCRIMINATOR_LEN] != account_discriminator("ProofLogData") {
+        return Err(AttestaError::InvalidAccountData);
+    }
+
+    let mut body = &data[ACCOUNT_DISCRIMINATOR_LEN..];
+    let wrapper = ProofLogData::deserialize(&mut body)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+    ProofLog::from_bytes(&wrapper.log).map_err(|_| AttestaError::InvalidAccountData)
+}
+
+/// Mirror of the program's `PolicyAttestationData` account layout
+#[derive(BorshDeserialize)]
+struct PolicyAttestationData {
+    _attesta_account: Pubkey,
+    attestation: Vec<u8>,
+    _bump: u8,
+}
+
+/// Decodes the raw data of a policy attestation account
+pub fn decode_policy_attestation(data: &[u8]) -> Result<PolicyAttestation, AttestaError> {
+    if data.len() < ACCOUNT_DISCRIMINATOR_LEN
+        || data[..ACCOUNT_DISCRIMINATOR_LEN] != account_discriminator("PolicyAttestationData")
+    {
+        return Err(AttestaError::InvalidAccountData);
+    }
+
+    let mut body = &data[ACCOUNT_DISCRIMINATOR_LEN..];
+    let wrapper = PolicyAttestationData::deserialize(&mut body)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+    PolicyAttestation::from_bytes(&wrapper.attestation).map_err(|_| AttestaError::InvalidAccountData)
+}
+
+/// A sponsor pool's state, from `AttestaClient::get_pool_status`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SponsorPoolStatus {
+    /// Who may withdraw the pool's lamports
+    pub authority: Pubkey,
+
+    /// The pool's limits and counters
+    pub pool: SponsorPool,
+
+    /// Lamports the pool holds, its own rent-exempt minimum included
+    pub lamports: u64,
+
+    /// Accounts it may still sponsor today, funds permitting
+    pub remaining_today: u32,
+}
+
+/// Mirror of the program's `SponsorPoolData` account layout
+#[derive(BorshDeserialize)]
+struct SponsorPoolData {
+    authority: Pubkey,
+    pool: Vec<u8>,
+    _bump: u8,
+}
+
+/// Decodes the raw data of a sponsor pool account
+///
+/// # Returns
+/// The pool's authority and its limits and counters
+pub fn decode_sponsor_pool(data: &[u8]) -> Result<(Pubkey, SponsorPool), AttestaError> {
+    if data.len() < ACCOUNT_DISCRIMINATOR_LEN || data[..ACCOUNT_DISCRIMINATOR_LEN] != account_discriminator("SponsorPoolData") {
+        return Err(AttestaError::InvalidAccountData);
+    }
+
+    let mut body = &data[ACCOUNT_DISCRIMINATOR_LEN..];
+    let wrapper = SponsorPoolData::deserialize(&mut body)
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+    let pool = SponsorPool::from_bytes(&wrapper.pool).map_err(|_| AttestaError::InvalidAccountData)?;
+    Ok((wrapper.authority, pool))
+}
+
+/// Errors that can occur when using the Attesta client
+#[derive(Error, Debug)]
+pub enum AttestaError {
+    #[error("Not implemented yet")]
+    NotImplemented,
+    
+    #[error("Account not found")]
+    AccountNotFound,
+    
+    #[error("Invalid account data")]
+    InvalidAccountData,
+    
+    #[error("RPC error: {0}")]
+    RpcError(String),
+
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+
+    #[error("Recovery failed: {0}")]
+    Recovery(#[from] RecoveryError),
+
+    #[error("Policy denies it: {0}")]
+    PolicyDenied(#[from] PolicyError),
+
+    #[error("Unknown credential: {0}")]
+    UnknownCredential(String),
+
+    #[error("Signing request expired at {0}")]
+    SigningRequestExpired(i64),
+
+    #[error("Assertion {field} does not match the signing request: {reason}")]
+    AssertionMismatch { field: &'static str, reason: String },
+
+    #[error("Recovery drill did not reach the threshold ({approvals} approvals)")]
+    RecoveryDrillFailed { approvals: usize },
+
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(#[from] TransactionRequestError),
+
+    #[error("Invalid policy: {0}")]
+    InvalidPolicy(#[from] PolicyBuildError),
+
+    #[error("Policy is estimated at {estimated} compute units (at most {max})")]
+    PolicyTooExpensive { estimated: u32, max: u32 },
+
+    #[error("Logged proof doesn't verify: {0}")]
+    InvalidLoggedProof(#[from] ProofLogError),
+
+    #[error("Return data is not an execute outcome")]
+    InvalidReturnData,
+
+    #[error("Simulation failed: {0}")]
+    SimulationFailed(String),
+
+    #[error("Transaction {signature} was not confirmed within {waited:?}")]
+    ConfirmationTimeout { waited: std::time::Duration, signature: Signature },
+
+    #[error("Transaction expired before it landed")]
+    BlockhashExpired,
+
+    #[error("Transaction failed: {0}")]
+    TransactionFailed(TransactionError),
+
+    #[error("The account changed after it was read at version {read_version}; fetch it and try again")]
+    ConcurrentModification { read_version: u64 },
+
+    #[error("PDA seed is {0} bytes (at most 32)")]
+    SeedTooLong(usize),
+
+    #[error("Nonce {nonce} was passed over (the account is at {account_nonce}); prepare the transaction again")]
+    NonceSkipped { nonce: u64, account_nonce: u64 },
+
+    #[error("The account's auth mode ({0:?}) doesn't accept these credentials")]
+    CredentialsNotAccepted(AuthMode),
+
+    #[error("Invalid claim link: {0}")]
+    InvalidClaimLink(String),
+
+    #[error("Policy attestation doesn't hold: {0}")]
+    InvalidAttestation(#[from] AttestationError),
+
+    #[error("Can't carry out the plan: {0}")]
+    InvalidPlan(String),
+
+    #[error("Account {0} isn't a readable Attesta account")]
+    UndecodableAccount(Pubkey),
+
+    #[error("The program was built without the {0} feature")]
+    FeatureNotEnabled(&'static str),
+
+    #[error("Every account index of {0} is taken")]
+    NoFreeIndex(Pubkey),
+
+    #[cfg(feature = "serde")]
+    #[error("Invalid account JSON: {0}")]
+    InvalidAccountJson(#[from] AccountJsonError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+    use recovery::Amount;
+    use smart_account::RecoveryRequest;
+    use smart_account::{DenyReason, PolicyResult};
+    use std::sync::Mutex;
+    use crate::backend::{ConfirmedTransaction, SimulationResult};
+    use crate::instructions::instruction_discriminator;
+    use crate::nonces::{DesyncCause, NonceDesync, NonceSyncEvent, ProofId};
+    use crate::test_utils::{
+        attesta_account_data, backup_escrow_data, policy_attestation_data, proof_log_data, proposal_data, sponsor_pool_data,
+        MockBackend, RpcCall,
+    };
+
+    fn mock_client() -> (AttestaClient, MockBackend, Pubkey) {
+        let backend = MockBackend::new();
+        let program_id = Pubkey::new_unique();
+        (AttestaClient::with_backend(backend.clone(), program_id), backend, program_id)
+    }
+
+    fn test_signature() -> WebAuthnSignature {
+        WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16])
+    }
+
+    /// The data of the only instruction in a sent transaction
+    fn sent_instruction_data(transaction: &Transaction) -> Vec<u8> {
+        assert_eq!(transaction.message.instructions.len(), 1);
+        transaction.message.instructions[0].data.clone()
+    }
+
+    fn escrow_account_data(backup: &EncryptedBackup, padding: usize) -> Vec<u8> {
+        let mut data = account_discriminator("BackupEscrow").to_vec();
+        Pubkey::new_unique().serialize(&mut data).unwrap();
+        backup.to_bytes().unwrap().serialize(&mut data).unwrap();
+        1234i64.serialize(&mut data).unwrap();
+        255u8.serialize(&mut data).unwrap();
+        data.resize(data.len() + padding, 0);
+        data
+    }
+
+    #[test]
+    fn test_account_addresses_match_the_program() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let client = AttestaClient::with_backend(MockBackend::new(), program_id);
+
+        // `initialize` creates the account at `[b"attesta", owner]`
+        assert_eq!(
+            client.derive_account_address(&owner, &[]).unwrap(),
+            crate::instructions::derive_attesta_address(&program_id, &owner)
+        );
+
+        for len in [16, 32, 96] {
+            let credential_id = vec![len as u8; len];
+            let seed = smart_account::credential_seed(&credential_id);
+            assert_eq!(
+                client.derive_account_address_for_credential(&owner, &credential_id),
+                Pubkey::find_program_address(&[b"attesta", owner.as_ref(), &seed], &program_id)
+            );
+        }
+        assert!(matches!(client.derive_account_address(&owner, &[0; 96]), Err(AttestaError::SeedTooLong(96))));
+    }
+
+    #[test]
+    fn test_next_free_index_skips_taken_addresses() {
+        let (client, backend, program_id) = mock_client();
+        let owner = Pubkey::new_unique();
+        assert_eq!(client.next_free_index(&owner).unwrap(), 0);
+
+        // The indexed address is the one `initialize_with_index` creates
+        let (first, _) = client.derive_account_address_by_index(&owner, 0);
+        assert_eq!(first, crate::instructions::derive_attesta_address_by_index(&program_id, &owner, 0).0);
+
+        // Taken indexes are skipped, across pages; an address with only lamports is free
+        for index in 0..=MAX_SCAN_PAGE_SIZE as u16 {
+            let (address, _) = client.derive_account_address_by_index(&owner, index);
+            backend.set_program_account(program_id, address, 1_000, vec![1; 8]);
+        }
+        let (dusted, _) = client.derive_account_address_by_index(&owner, MAX_SCAN_PAGE_SIZE as u16 + 1);
+        backend.set_account(dusted, 5_000, Vec::new());
+        assert_eq!(client.next_free_index(&owner).unwrap(), MAX_SCAN_PAGE_SIZE as u16 + 1);
+
+        // Another owner's indexes are its own
+        assert_eq!(client.next_free_index(&Pubkey::new_unique()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_decode_backup_escrow_round_trip() {
+        let backup = EncryptedBackup::new(b"key", b"account data", 1234);
+        let data = escrow_account_data(&backup, 100);
+
+        let decoded = decode_backup_escrow(&data).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), backup.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_decode_attesta_account_in_privacy_mode() {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        account.enable_privacy_mode().unwrap();
+
+        let mut data = account_discriminator("AttestaAccountData").to_vec();
+        account.to_bytes().unwrap().serialize(&mut data).unwrap();
+        data.extend([0u8; 64]);
+
+        let decoded = decode_attesta_account(&data).unwrap();
+        assert_eq!(decoded, account);
+
+        // The listing shows a hash, but the real credential ID still resolves
+        let client = AttestaClient::new(Cluster::Localnet, Pubkey::new_unique());
+        assert_ne!(decoded.credential_id, b"phone".to_vec());
+        assert_eq!(client.find_passkey(&decoded, b"phone").unwrap(), [3u8; 64]);
+        assert!(client.find_passkey(&decoded, &decoded.credential_id).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_export_account_json_round_trips() {
+        let (client, backend, _) = mock_client();
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        account.passkey_public_key = core_crypto::test_utils::TestPasskey::new(1).public_key();
+        account.set_policies(vec![Policy::time_locked(1_700_000_000).to_bytes().unwrap()]);
+        backend.set_account(address, 1_000_000, attesta_account_data(&account));
+
+        let json = client.export_account_json(&address).unwrap();
+        assert!(json.contains(&address.to_string()));
+        assert!(json.contains("\"type\": \"time_locked\""));
+        assert_eq!(import_account_json(&json).unwrap(), account);
+
+        let edited = json.replace("\"nonce\": 0", "\"nonce\": 5");
+        assert!(matches!(import_account_json(&edited), Err(AttestaError::InvalidAccountJson(_))));
+        assert!(matches!(client.export_account_json(&Pubkey::new_unique()), Err(AttestaError::AccountNotFound)));
+    }
+
+    #[test]
+    fn test_storage_helpers_write_the_programs_layout() {
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![1, 2], 100);
+
+        // `attesta_account_data` mirrors what the program writes
+        let encoded = smart_account::storage::encode_attesta_account(&account).unwrap();
+        assert_eq!(encoded, attesta_account_data(&account));
+        assert_eq!(decode_attesta_account(&encoded).unwrap(), account);
+    }
+
+    #[test]
+    fn test_previous_execution_matches_key_hash_and_nonce() {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        let envelope = ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![0; 37], vec![], vec![0; 64], b"phone".to_vec()),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+        assert_eq!(previous_execution(&account, &envelope), None);
+
+        account.record_idempotency_key([7u8; 16], [9u8; 32], 1);
+        assert_eq!(
+            previous_execution(&account, &envelope),
+            Some(ExecutionReceipt { status: ExecutionStatus::AlreadyExecuted, nonce: 1, memo_hash: None, credential_id_hash: None })
+        );
+
+        // Same key, different transaction: not a previous run of this envelope
+        let other = ProofEnvelope { message_hash: [8u8; 32], ..envelope };
+        assert_eq!(previous_execution(&account, &other), None);
+    }
+
+    #[test]
+    fn test_check_policy_cost() {
+        let policy = Policy::destination_allowlist(vec![Pubkey::new_unique(); 4]);
+        assert_eq!(check_policy_cost(&policy).unwrap(), policy.estimated_compute_units());
+
+        let policy = Policy::composite(vec![Policy::destination_allowlist(vec![Pubkey::new_unique(); 32]); 40]);
+        let estimated = policy.estimated_compute_units();
+        assert!(matches!(
+            check_policy_cost(&policy),
+            Err(AttestaError::PolicyTooExpensive { estimated: e, max: MAX_POLICY_COMPUTE_UNITS }) if e == estimated
+        ));
+    }
+
+    #[test]
+    fn test_get_account_reads_through_backend() {
+        let (client, backend, _) = mock_client();
+        let address = Pubkey::new_unique();
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1_000_000, attesta_account_data(&account));
+
+        assert_eq!(client.get_account(&address).unwrap(), account);
+        assert_eq!(backend.calls(), vec![RpcCall::GetAccountData(address)]);
+
+        assert!(matches!(client.get_account(&Pubkey::new_unique()), Err(AttestaError::AccountNotFound)));
+    }
+
+    #[test]
+    fn test_client_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<AttestaClient>();
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_get_account_cached_hits_until_stale() {
+        let (client, backend, _) = mock_client();
+        let client = client.with_cache(CacheConfig::default());
+        let (address, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+        backend.set_account(other, 1, attesta_account_data(&account));
+
+        backend.set_slot(100);
+        assert_eq!(client.get_account_cached(&address, 2).unwrap(), account);
+        assert_eq!(client.get_account_cached(&address, 2).unwrap(), account);
+        assert_eq!(backend.calls(), vec![RpcCall::GetAccountData(address)]);
+
+        // Reading another account at slot 103 shows the first copy is 3 slots old
+        backend.set_slot(103);
+        client.get_account_cached(&other, 2).unwrap();
+        client.get_account_cached(&address, 3).unwrap();
+        assert_eq!(backend.calls().len(), 2);
+        client.get_account_cached(&address, 2).unwrap();
+        assert_eq!(backend.calls().len(), 3);
+
+        assert_eq!(client.cache_stats().unwrap(), CacheStats { hits: 2, misses: 3, entries: 2 });
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_cached_account_expires_after_ttl() {
+        let (client, backend, _) = mock_client();
+        let client = client.with_cache(CacheConfig { ttl: std::time::Duration::ZERO, max_entries: 8 });
+        let address = Pubkey::new_unique();
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        client.get_account_cached(&address, u64::MAX).unwrap();
+        client.get_account_cached(&address, u64::MAX).unwrap();
+        assert_eq!(backend.calls().len(), 2);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_send_invalidates_written_accounts() {
+        let (client, backend, _) = mock_client();
+        let client = client.with_cache(CacheConfig::default());
+        let owner = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+        client.get_account_cached(&address, u64::MAX).unwrap();
+
+        // The transaction bumps the nonce; the next cached read must see it
+        account.nonce = 1;
+        backend.set_account(address, 1, attesta_account_data(&account));
+        client.delete_backup(&owner, &address, &test_signature(), 1).unwrap();
+
+        assert_eq!(client.get_account_cached(&address, u64::MAX).unwrap().nonce, 1);
+    }
+
+    #[test]
+    fn test_get_balances_makes_exactly_two_calls() {
+        let (client, backend, _) = mock_client();
+        let address = Pubkey::new_unique();
+        backend.set_account(address, 5_000, Vec::new());
+
+        let balances = client.get_balances(&address).unwrap();
+        assert_eq!(balances, Balances { sol: Amount::from_lamports(5_000), tokens: vec![] });
+
+        assert_eq!(backend.calls(), vec![
+            RpcCall::GetLamports(address),
+            RpcCall::GetTokenAccountsByOwner { owner: address, token_program: TOKEN_PROGRAM_ID },
+        ]);
+    }
+
+    #[test]
+    fn test_reconstruct_account_fetches_each_transaction_in_order() {
+        use crate::backend::ConfirmedTransaction;
+        use anchor_client::solana_sdk::transaction::Transaction;
+
+        let (client, backend, _) = mock_client();
+        let address = Pubkey::new_unique();
+        let (first, second) = (Signature::from([1; 64]), Signature::from([2; 64]));
+        for (slot, signature) in [first, second].into_iter().enumerate() {
+            backend.set_transaction(ConfirmedTransaction {
+                signature,
+                slot: slot as u64,
+                block_time: None,
+                transaction: Transaction::default(),
+                err: None,
+                logs: Vec::new(),
+            });
+        }
+
+        let state = client.reconstruct_account(&address, vec![second, first]).unwrap();
+        assert!(state.account.is_none() && state.applied.is_empty() && state.warnings.is_empty());
+        assert_eq!(backend.calls(), vec![RpcCall::GetTransaction(second), RpcCall::GetTransaction(first)]);
+
+        assert!(client.reconstruct_account(&address, vec![first, Signature::from([3; 64])]).is_err());
+    }
+
+    #[test]
+    fn test_fetch_backup() {
+        let (client, backend, program_id) = mock_client();
+        let attesta_account = Pubkey::new_unique();
+        assert!(client.fetch_backup(&attesta_account).unwrap().is_none());
+
+        let backup = EncryptedBackup::new(b"key", b"account data", 1234);
+        let (backup_address, _) = derive_backup_address(&program_id, &attesta_account);
+        backend.set_account(backup_address, 1, backup_escrow_data(&attesta_account, &backup));
+
+        let fetched = client.fetch_backup(&attesta_account).unwrap().unwrap();
+        assert_eq!(fetched.to_bytes().unwrap(), backup.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_fetch_and_verify_proof_log() {
+        use core_crypto::{compute_challenge, test_utils::TestPasskey};
+        use smart_account::AuthorizationProof;
+
+        let (client, backend, program_id) = mock_client();
+        let attesta_account = Pubkey::new_unique();
+        assert!(client.fetch_proof_log(&attesta_account).unwrap().is_none());
+
+        let mut passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 0);
+        let envelope = ProofEnvelope {
+            webauthn_sig: passkey.sign(&compute_challenge(&account.owner, 1, &[9; 32])),
+            nonce: 1,
+            message_hash: [9; 32],
+            idempotency_key: [0; 16],
+            parent_account: None,
+            logs_proofs: true,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+        let mut log = ProofLog::default();
+        log.append(ProofLogEntry::new(&AuthorizationProof::from(envelope.clone()), 100));
+        let (proof_log_address, _) = derive_proof_log_address(&program_id, &attesta_account);
+        backend.set_account(proof_log_address, 1, proof_log_data(&attesta_account, &log));
+
+        let fetched = client.fetch_proof_log(&attesta_account).unwrap().unwrap();
+        assert_eq!(fetched, log);
+        assert!(verify_logged_proof(&account, &fetched.entries[0], &envelope).is_ok());
+
+        let other = ProofEnvelope { message_hash: [8; 32], ..envelope };
+        assert!(matches!(
+            verify_logged_proof(&account, &fetched.entries[0], &other),
+            Err(AttestaError::InvalidLoggedProof(ProofLogError::EntryMismatch("message_hash")))
+        ));
+    }
+
+    /// A time-locked account at a new address, and an attestation of it at its PDA
+    fn attested_account(backend: &MockBackend, program_id: &Pubkey, expires_at: i64) -> (Pubkey, PolicyAttestation) {
+        let address = Pubkey::new_unique();
+        let lock = Policy::time_locked(1_900_000_000).to_bytes().unwrap();
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), lock, 100);
+        backend.set_account(address, 1_000_000, attesta_account_data(&account));
+
+        let attestation = PolicyAttestation {
+            attesta_account: address,
+            policy_hash: account.policy_hash,
+            attested_at: expires_at - 60,
+            expires_at,
+            nonce: 1,
+        };
+        let (attestation_address, _) = derive_policy_attestation_address(program_id, &address, 1);
+        backend.set_account(attestation_address, 1_000_000, policy_attestation_data(&attestation));
+        (attestation_address, attestation)
+    }
+
+    #[test]
+    fn test_verify_policy_attestation() {
+        let (client, backend, program_id) = mock_client();
+        let expected = Policy::time_locked(1_900_000_000).canonical_hash();
+        let (attestation_address, attestation) = attested_account(&backend, &program_id, unix_timestamp() + 3_600);
+
+        assert_eq!(client.verify_policy_attestation(&attestation_address, &expected).unwrap(), attestation);
+        assert!(matches!(
+            client.verify_policy_attestation(&attestation_address, &Policy::time_locked(1).canonical_hash()),
+            Err(AttestaError::InvalidAttestation(AttestationError::UnexpectedPolicy))
+        ));
+        assert!(matches!(
+            client.verify_policy_attestation(&Pubkey::new_unique(), &expected),
+            Err(AttestaError::AccountNotFound)
+        ));
+
+        // The same data anywhere but its PDA
+        let elsewhere = Pubkey::new_unique();
+        backend.set_account(elsewhere, 1_000_000, policy_attestation_data(&attestation));
+        assert!(matches!(
+            client.verify_policy_attestation(&elsewhere, &expected),
+            Err(AttestaError::InvalidAttestation(AttestationError::InvalidData))
+        ));
+    }
+
+    #[test]
+    fn test_policy_change_invalidates_attestation() {
+        let (client, backend, program_id) = mock_client();
+        let (attestation_address, attestation) = attested_account(&backend, &program_id, unix_timestamp() + 3_600);
+
+        let mut account = client.get_account(&attestation.attesta_account).unwrap();
+        account.set_policies(vec![Policy::time_locked(1_900_000_001).to_bytes().unwrap()]);
+        backend.set_account(attestation.attesta_account, 1_000_000, attesta_account_data(&account));
+        assert!(matches!(
+            client.verify_policy_attestation(&attestation_address, &attestation.policy_hash),
+            Err(AttestaError::InvalidAttestation(AttestationError::PolicyChanged))
+        ));
+    }
+
+    #[test]
+    fn test_expired_attestation_is_rejected() {
+        let (client, backend, program_id) = mock_client();
+        let expires_at = unix_timestamp() - 1;
+        let (attestation_address, attestation) = attested_account(&backend, &program_id, expires_at);
+
+        assert!(matches!(
+            client.verify_policy_attestation(&attestation_address, &attestation.policy_hash),
+            Err(AttestaError::InvalidAttestation(AttestationError::Expired { expires_at: at })) if at == expires_at
+        ));
+    }
+
+    #[test]
+    fn test_get_pool_status_counts_today() {
+        let (client, backend, program_id) = mock_client();
+        let authority = Pubkey::new_unique();
+        let (pool_address, _) = instructions::derive_sponsor_pool_address(&program_id, &authority);
+        assert!(matches!(client.get_pool_status(&pool_address, 0), Err(AttestaError::AccountNotFound)));
+
+        let now = 1_800_000_000;
+        let mut pool = SponsorPool::new(3, 2_000_000);
+        pool.sponsor(2_000_000, 10_000_000, now).unwrap();
+        backend.set_account(pool_address, 12_000_000, sponsor_pool_data(&authority, &pool));
+
+        let status = client.get_pool_status(&pool_address, now).unwrap();
+        assert_eq!((status.authority, status.lamports, status.remaining_today), (authority, 12_000_000, 2));
+        assert_eq!(status.pool, pool);
+        assert_eq!(client.get_pool_status(&pool_address, now + 86_400).unwrap().remaining_today, 3);
+    }
+
+    #[test]
+    fn test_sponsor_initialize_is_paid_by_the_relayer() {
+        use core_crypto::test_utils::TestPasskey;
+
+        let (client, backend, program_id) = mock_client();
+        let (relayer, owner) = (Keypair::new(), Keypair::new());
+        let mut passkey = TestPasskey::new(1);
+        let (address, _) = instructions::derive_attesta_address(&program_id, &owner.pubkey());
+        let request = RegistrationRequest::new(owner.pubkey(), address, passkey.public_key(), passkey.credential_id());
+        let registration = Registration { webauthn_sig: passkey.sign_create(&request.challenge), aaguid: None };
+        let (pool, _) = instructions::derive_sponsor_pool_address(&program_id, &Pubkey::new_unique());
+
+        backend.push_simulation(SimulationResult {
+            return_data: Some(ProgramFeatures::all().to_return_data()),
+            ..SimulationResult::default()
+        });
+        client.sponsor_initialize(&relayer, &owner, &pool, &request, &registration, None).unwrap();
+
+        let sent = backend.sent_transactions();
+        let message = &sent[0].message;
+        assert_eq!(message.account_keys[0], relayer.pubkey());
+        assert_eq!(message.header.num_required_signatures, 2);
+        assert!(!message.is_writable(message.account_keys.iter().position(|key| *key == owner.pubkey()).unwrap()));
+        let data = &message.instructions.last().unwrap().data;
+        assert_eq!(data[..8], instruction_discriminator("sponsored_initialize"));
+    }
+
+    #[test]
+    fn test_upload_backup_creates_or_updates_escrow() {
+        let (client, backend, program_id) = mock_client();
+        let owner = Keypair::new();
+        let attesta_account = Pubkey::new_unique();
+        let backup = EncryptedBackup::new(b"key", b"account data", 1234);
+
+        // No escrow yet: the backup is stored in a new one
+        client.upload_backup(&owner, &attesta_account, &backup, &test_signature(), 1).unwrap();
+        let sent = backend.sent_transactions();
+        assert_eq!(sent_instruction_data(&sent[0])[..8], instruction_discriminator("store_backup"));
+
+        // Once it exists, the same call overwrites it
+        let (backup_address, _) = derive_backup_address(&program_id, &attesta_account);
+        backend.set_account(backup_address, 1, backup_escrow_data(&attesta_account, &backup));
+        client.upload_backup(&owner, &attesta_account, &backup, &test_signature(), 2).unwrap();
+        let sent = backend.sent_transactions();
+        assert_eq!(sent_instruction_data(&sent[1])[..8], instruction_discriminator("update_backup"));
+
+        // After a rekey, a backup from before it isn't sent
+        let rekeyed = backup.rekey(b"key", b"new key", 1300).unwrap();
+        backend.set_account(backup_address, 1, backup_escrow_data(&attesta_account, &rekeyed));
+        let err = client.upload_backup(&owner, &attesta_account, &backup, &test_signature(), 3).unwrap_err();
+        assert!(matches!(err, AttestaError::InvalidBackup(_)), "{err}");
+        assert_eq!(backend.sent_transactions().len(), 2);
+    }
+
+    #[test]
+    fn test_delete_backup_sends_one_transaction() {
+        let (client, backend, _) = mock_client();
+        let owner = Keypair::new();
+
+        client.delete_backup(&owner, &Pubkey::new_unique(), &test_signature(), 1).unwrap();
+
+        let calls = backend.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], RpcCall::GetLatestBlockhash);
+        assert!(matches!(&calls[1], RpcCall::SendTransaction(tx)
+            if sent_instruction_data(tx)[..8] == instruction_discriminator("delete_backup")));
+        assert!(matches!(&calls[2], RpcCall::GetSignatureStatuses { signatures, .. } if signatures.len() == 1));
+    }
+
+    #[test]
+    fn test_execute_reports_earlier_landing_without_resending() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        // The first send timed out, but the transaction landed
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        account.record_idempotency_key([7u8; 16], [9u8; 32], 1);
+        backend.set_account(address, 1, attesta_account_data(&account));
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+
+        let receipt = client.execute(&authority, &address, &envelope, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt, ExecutionReceipt { status: ExecutionStatus::AlreadyExecuted, nonce: 1, memo_hash: None, credential_id_hash: None });
+        assert_eq!(backend.sent_transactions().len(), 1);
+        assert!(matches!(backend.calls().last(), Some(RpcCall::GetAccountData(a)) if *a == address));
+    }
+
+    #[test]
+    fn test_execute_treats_losing_a_relayer_race_as_success() {
+        let (client, backend, _) = mock_client();
+        let address = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        // Another relayer's copy landed first, so the program answered from its record
+        let signature = Signature::new_unique();
+        backend.push_send_result(Ok(signature));
+        backend.set_transaction(ConfirmedTransaction {
+            signature,
+            slot: 10,
+            block_time: None,
+            transaction: Transaction::default(),
+            err: None,
+            logs: vec!["Program log: ATST1 exec_dup nonce=1".to_string()],
+        });
+        let receipt = client.execute(&Keypair::new(), &address, &envelope, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt, ExecutionReceipt { status: ExecutionStatus::AlreadyExecuted, nonce: 1, memo_hash: None, credential_id_hash: None });
+        assert_eq!(backend.sent_transactions().len(), 1);
+
+        // Without the logs, it's taken to have run on this call
+        let envelope = ProofEnvelope { nonce: 2, ..envelope };
+        let receipt = client.execute(&Keypair::new(), &address, &envelope, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt.status, ExecutionStatus::Executed);
+    }
+
+    #[test]
+    fn test_execute_resends_when_first_attempt_did_not_land() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+
+        let receipt = client.execute(&authority, &address, &envelope, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(
+            receipt,
+            ExecutionReceipt {
+                status: ExecutionStatus::Executed,
+                nonce: 1,
+                memo_hash: None,
+                credential_id_hash: Some(credential_id_hash(&test_signature().credential_id)),
+            }
+        );
+
+        // Both attempts carry the same instruction, idempotency key included
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent_instruction_data(&sent[0]), sent_instruction_data(&sent[1]));
+    }
+
+    #[test]
+    fn test_execute_picks_the_path_the_auth_mode_accepts() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let owner = Keypair::new();
+        let address = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 5,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+        let set_mode = |mode| {
+            let mut account = AttestaAccount::new(owner.pubkey(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+            account.nonce = 4;
+            account.settings.auth_mode = mode;
+            backend.set_account(address, 1, attesta_account_data(&account));
+        };
+        let either = ExecutionCredentials::Either { envelope: &envelope, owner: &owner };
+
+        // Passkey-only: the wallet alone is refused before anything is sent
+        set_mode(AuthMode::PasskeyOnly);
+        assert!(matches!(
+            client.execute(&authority, &address, ExecutionCredentials::Owner(&owner), b"data".to_vec()),
+            Err(AttestaError::CredentialsNotAccepted(AuthMode::PasskeyOnly))
+        ));
+        assert!(backend.sent_transactions().is_empty());
+
+        // Both accepted: the passkey is preferred
+        set_mode(AuthMode::OwnerOrPasskey);
+        let receipt = client.execute(&authority, &address, either, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt.nonce, 5);
+        let sent = backend.sent_transactions();
+        assert_eq!(sent_instruction_data(&sent[0])[..8], instruction_discriminator("execute"));
+
+        // Owner-only: the wallet signs, at the account's next nonce
+        set_mode(AuthMode::OwnerOnly);
+        let receipt = client.execute(&authority, &address, either, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt, ExecutionReceipt { status: ExecutionStatus::Executed, nonce: 5, memo_hash: None, credential_id_hash: None });
+        let sent = backend.sent_transactions();
+        let data = sent_instruction_data(&sent[1]);
+        assert_eq!(data[..8], instruction_discriminator("execute_as_owner"));
+        assert_eq!(data[8..16], 5u64.to_le_bytes());
+        let owner_index = sent[1].message.account_keys.iter().position(|key| *key == owner.pubkey()).unwrap();
+        assert!(sent[1].message.is_signer(owner_index));
+
+        let receipt = client.execute(&authority, &address, ExecutionCredentials::Owner(&owner), b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt.nonce, 5);
+        assert_eq!(backend.sent_transactions().len(), 3);
+    }
+
+    #[test]
+    fn test_auth_mode_message_hash_covers_mode_and_lock() {
+        let (client, _, _) = mock_client();
+        let locked = client.auth_mode_message_hash(AuthMode::PasskeyOnly, true);
+        assert_eq!(locked, action_message_hash(AUTH_MODE_ACTION, &[0, 1]));
+        assert_ne!(locked, client.auth_mode_message_hash(AuthMode::PasskeyOnly, false));
+        assert_ne!(locked, client.auth_mode_message_hash(AuthMode::OwnerOnly, false));
+    }
+
+    #[test]
+    fn test_executor_message_hashes_differ_by_action() {
+        let (client, _, _) = mock_client();
+        let executor = Pubkey::new_unique();
+        assert_ne!(client.add_executor_message_hash(&executor), client.remove_executor_message_hash(&executor));
+        assert_ne!(client.add_executor_message_hash(&executor), client.add_executor_message_hash(&Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn test_execute_receipt_reports_the_memo_hash() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: b"order-1042".to_vec(),
+            emit_memo: true,
+        };
+
+        let receipt = client.execute(&authority, &address, &envelope, b"data".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt.memo_hash, memo_hash(b"order-1042"));
+
+        // An oversized memo is refused before anything is sent
+        envelope.nonce = 2;
+        envelope.memo = vec![b'm'; smart_account::MAX_MEMO_LEN + 1];
+        assert!(matches!(
+            client.execute(&authority, &address, &envelope, b"data".to_vec()),
+            Err(AttestaError::InvalidTransaction(TransactionRequestError::MemoTooLong { .. }))
+        ));
+        assert_eq!(backend.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_execute_reports_a_transaction_left_for_approval() {
+        use smart_account::PendingTransaction;
+
+        let (client, backend, program_id) = mock_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        // The program proposed the transaction instead of running it
+        let account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        let proposed = PendingTransaction::propose(&account, b"data".to_vec(), b"phone", Pubkey::new_unique(), 0).unwrap();
+        let (proposal_address, _) = derive_proposal_address(&program_id, &address, &proposed.message_hash);
+        backend.set_account(proposal_address, 1, proposal_data(&address, &proposed));
+
+        let result = client.execute(&authority, &address, &envelope, b"data".to_vec()).unwrap();
+        assert_eq!(result, ExecuteResult::PendingApproval { proposal_address, approvals_needed: 1 });
+        assert_eq!(result.receipt(), None);
+
+        // Its nonce wasn't used up, so the same envelope can be sent again
+        let result = client.execute(&authority, &address, &envelope, b"data".to_vec()).unwrap();
+        assert!(matches!(result, ExecuteResult::PendingApproval { .. }));
+        assert_eq!(backend.sent_transactions().len(), 2);
+
+        // A different transaction has no proposal and just runs
+        let mut other = envelope.clone();
+        other.idempotency_key = [8u8; 16];
+        let result = client.execute(&authority, &address, &other, b"other".to_vec()).unwrap();
+        assert_eq!(result.receipt().map(|receipt| receipt.status), Some(ExecutionStatus::Executed));
+    }
+
+    /// An envelope for `signing_request`, as `complete_execution` would build it
+    fn envelope_for(signing_request: &SigningRequest) -> ProofEnvelope {
+        ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: signing_request.nonce,
+            message_hash: signing_request.message_hash,
+            idempotency_key: signing_request.idempotency_key,
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        }
+    }
+
+    #[test]
+    fn test_reserved_nonces_and_out_of_order_landing() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        // Approve, swap, stake: signed up front, one nonce each
+        assert_eq!(client.reserve_nonces(&account, 3), vec![1, 2, 3]);
+        let requests: Vec<SigningRequest> = [b"approve".as_slice(), b"swap", b"stake"]
+            .iter()
+            .map(|data| client.prepare_execution(&account, &TransactionRequest::new(data.to_vec())))
+            .collect();
+        let nonces: Vec<u64> = requests.iter().map(|request| request.nonce).collect();
+        assert_eq!(nonces, vec![1, 2, 3]);
+
+        // The swap lands first, moving the account's nonce to 1
+        client.execute(&authority, &address, &envelope_for(&requests[1]), b"swap".to_vec()).unwrap();
+        account.increment_nonce(200);
+        account.record_idempotency_key(requests[1].idempotency_key, requests[1].message_hash, 2);
+        backend.set_account(address, 1, attesta_account_data(&account));
+        client.get_account(&address).unwrap();
+
+        // The approval can never land now, so it isn't sent
+        assert!(matches!(
+            client.execute(&authority, &address, &envelope_for(&requests[0]), b"approve".to_vec()),
+            Err(AttestaError::NonceSkipped { nonce: 1, account_nonce: 1 })
+        ));
+        assert_eq!(backend.sent_transactions().len(), 1);
+
+        // The stake is still above the account's nonce
+        let receipt = client.execute(&authority, &address, &envelope_for(&requests[2]), b"stake".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(
+            receipt,
+            ExecutionReceipt {
+                status: ExecutionStatus::Executed,
+                nonce: 3,
+                memo_hash: None,
+                credential_id_hash: Some(credential_id_hash(&test_signature().credential_id)),
+            }
+        );
+        assert_eq!(backend.sent_transactions().len(), 2);
+
+        // A new request doesn't reuse a nonce that was handed out
+        let retry = client.prepare_execution(&account, &TransactionRequest::new(b"approve".to_vec()));
+        assert_eq!(retry.nonce, 4);
+    }
+
+    #[test]
+    fn test_execute_does_not_resend_a_passed_over_nonce() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+
+        // Signed elsewhere, so this client's tracker knows nothing of it
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        let envelope = envelope_for(&SigningRequest::new(&account, &TransactionRequest::new(b"data".to_vec()), 0));
+
+        // The send failed, and meanwhile another transaction took nonce 1
+        account.increment_nonce(200);
+        backend.set_account(address, 1, attesta_account_data(&account));
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+
+        assert!(matches!(
+            client.execute(&authority, &address, &envelope, b"data".to_vec()),
+            Err(AttestaError::NonceSkipped { nonce: 1, account_nonce: 1 })
+        ));
+        assert_eq!(backend.sent_transactions().len(), 1);
+    }
+
+    /// A client whose nonce sync guard collects what it reports
+    fn guarded_client() -> (AttestaClient, MockBackend, Arc<Mutex<Vec<NonceSyncEvent>>>) {
+        let (client, backend, _) = mock_client();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let guard = NonceSyncGuard::new(move |event: &NonceSyncEvent| sink.lock().unwrap().push(event.clone()));
+        (client.with_nonce_sync_guard(guard), backend, events)
+    }
+
+    #[test]
+    fn test_nonce_sync_guard_recognizes_a_late_landing_of_its_own() {
+        let (client, backend, events) = guarded_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let first = client.prepare_execution(&account, &TransactionRequest::new(b"first".to_vec()));
+        let second = client.prepare_execution(&account, &TransactionRequest::new(b"second".to_vec()));
+
+        // Both sends of the first time out, and it looks lost
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+        assert!(client.execute(&authority, &address, &envelope_for(&first), b"first".to_vec()).is_err());
+        assert!(events.lock().unwrap().is_empty());
+
+        // Then it lands
+        account.increment_nonce(200);
+        account.record_idempotency_key(first.idempotency_key, first.message_hash, first.nonce);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+        let receipt = client.execute(&authority, &address, &envelope_for(&second), b"second".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt.nonce, 2);
+
+        let first_id = ProofId { nonce: first.nonce, message_hash: first.message_hash };
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![NonceSyncEvent::Desync(NonceDesync {
+                account: address,
+                expected_nonce: 0,
+                chain_nonce: 1,
+                cause: DesyncCause::OwnSubmissionLanded,
+                landed: vec![first_id],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_nonce_sync_guard_invalidates_proofs_passed_over_elsewhere() {
+        let (client, backend, events) = guarded_client();
+        let authority = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let requests: Vec<SigningRequest> = [b"approve".as_slice(), b"swap", b"stake"]
+            .iter()
+            .map(|data| client.prepare_execution(&account, &TransactionRequest::new(data.to_vec())))
+            .collect();
+
+        // Another relayer runs two unrelated transactions
+        for (nonce, hash) in [(1u64, [7u8; 32]), (2, [8u8; 32])] {
+            account.increment_nonce(200);
+            account.record_idempotency_key([nonce as u8; 16], hash, nonce);
+        }
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        // Signed against the stale account, the approval is refused as a replay
+        let replay = TransactionError::InstructionError(0, InstructionError::Custom(6001));
+        backend.push_send_result(Err(AttestaError::TransactionFailed(replay)));
+        assert!(matches!(
+            client.execute(&authority, &address, &envelope_for(&requests[0]), b"approve".to_vec()),
+            Err(AttestaError::NonceSkipped { nonce: 1, account_nonce: 2 })
+        ));
+
+        let invalidated: Vec<ProofId> = requests[..2]
+            .iter()
+            .map(|request| ProofId { nonce: request.nonce, message_hash: request.message_hash })
+            .collect();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                NonceSyncEvent::Desync(NonceDesync {
+                    account: address,
+                    expected_nonce: 0,
+                    chain_nonce: 2,
+                    cause: DesyncCause::ExecutedElsewhere,
+                    landed: vec![],
+                }),
+                NonceSyncEvent::ProofsInvalidated(invalidated),
+            ]
+        );
+
+        // The swap was invalidated with it and isn't sent; the stake still lands
+        assert!(matches!(
+            client.execute(&authority, &address, &envelope_for(&requests[1]), b"swap".to_vec()),
+            Err(AttestaError::NonceSkipped { nonce: 2, account_nonce: 2 })
+        ));
+        assert_eq!(backend.sent_transactions().len(), 1);
+        let receipt = client.execute(&authority, &address, &envelope_for(&requests[2]), b"stake".to_vec()).unwrap().receipt().unwrap();
+        assert_eq!(receipt.nonce, 3);
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_simulate_execution_decodes_each_outcome() {
+        let (client, backend, _) = mock_client();
+        let authority = Keypair::new();
+        let envelope = ProofEnvelope {
+            webauthn_sig: test_signature(),
+            nonce: 3,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        let outcomes = [
+            ExecuteOutcome::new(&PolicyResult::Allowed, 3, 1_000),
+            ExecuteOutcome::new(&PolicyResult::Denied(DenyReason::Policy), 2, 0),
+            ExecuteOutcome::new(&PolicyResult::RequiresApproval, 2, 0),
+        ];
+        for outcome in outcomes {
+            backend.push_simulation(SimulationResult {
+                err: (!outcome.executed()).then(|| "custom program error".to_string()),
+                return_data: Some(outcome.to_return_data()),
+                ..SimulationResult::default()
+            });
+            let simulated = client.simulate_execution(&authority, &Pubkey::new_unique(), &envelope, b"data".to_vec());
+            assert_eq!(simulated.unwrap(), outcome);
+        }
+        assert!(backend.sent_transactions().is_empty());
+
+        // Failing before the policy leaves no return data
+        backend.push_simulation(SimulationResult { err: Some("invalid signature".to_string()), ..SimulationResult::default() });
+        let failed = client.simulate_execution(&authority, &Pubkey::new_unique(), &envelope, b"data".to_vec());
+        assert!(matches!(failed, Err(AttestaError::SimulationFailed(e)) if e == "invalid signature"));
+
+        assert!(matches!(decode_execute_outcome(&[1, 2, 3]), Err(AttestaError::InvalidReturnData)));
+    }
+
+    #[test]
+    fn test_get_program_version_simulates_and_decodes() {
+        let (client, backend, program_id) = mock_client();
+        let payer = Pubkey::new_unique();
+        let version = ProgramVersion::new("1.2.0", &[]);
+        backend.push_simulation(SimulationResult {
+            return_data: Some(version.to_return_data()),
+            ..SimulationResult::default()
+        });
+
+        assert_eq!(client.get_program_version(&payer).unwrap(), version);
+        assert!(matches!(backend.calls().last(), Some(RpcCall::SimulateTransaction(tx))
+            if tx.message.account_keys == vec![payer, program_id]
+                && sent_instruction_data(tx) == instruction_discriminator("get_program_version")));
+        assert!(backend.sent_transactions().is_empty());
+
+        // What the owner signs commits to this exact version
+        let newer = ProgramVersion::new("1.3.0", &[]);
+        assert_eq!(
+            client.upgrade_acknowledgment_message_hash(&version),
+            action_message_hash(UPGRADE_ACKNOWLEDGE_ACTION, &version.hash())
+        );
+        assert_ne!(client.upgrade_acknowledgment_message_hash(&version), client.upgrade_acknowledgment_message_hash(&newer));
+
+        backend.push_simulation(SimulationResult { return_data: Some(vec![1]), ..SimulationResult::default() });
+        assert!(matches!(client.get_program_version(&payer), Err(AttestaError::InvalidReturnData)));
+    }
+
+    #[test]
+    fn test_program_features_simulates_and_decodes() {
+        let (client, backend, program_id) = mock_client();
+        let payer = Pubkey::new_unique();
+        let features = ProgramFeatures::new(&[ProgramFeature::Claims]);
+        backend.push_simulation(SimulationResult {
+            return_data: Some(features.to_return_data()),
+            ..SimulationResult::default()
+        });
+
+        assert_eq!(client.program_features(&payer).unwrap(), features);
+        assert!(matches!(backend.calls().last(), Some(RpcCall::SimulateTransaction(tx))
+            if tx.message.account_keys == vec![payer, program_id]
+                && sent_instruction_data(tx) == instruction_discriminator("get_program_features")));
+
+        // A program from before the instruction doesn't know it, and has everything
+        let unknown = TransactionError::InstructionError(0, InstructionError::Custom(INSTRUCTION_NOT_FOUND));
+        backend.push_simulation(SimulationResult { err: Some(unknown.to_string()), ..SimulationResult::default() });
+        assert_eq!(client.program_features(&payer).unwrap(), ProgramFeatures::all());
+
+        backend.push_simulation(SimulationResult { err: Some("out of funds".to_string()), ..SimulationResult::default() });
+        assert!(matches!(client.program_features(&payer), Err(AttestaError::SimulationFailed(_))));
+    }
+
+    #[test]
+    fn test_protocol_constants_are_read_once_and_checked_against() {
+        let (client, backend, program_id) = mock_client();
+        let payer = Pubkey::new_unique();
+        assert_eq!(client.limits(), ProtocolLimits::compiled());
+
+        // A deployment with a shorter memo limit than these crates
+        let mut constants = ProtocolConstants::new(ProgramVersion::new("0.2.0", &[]), &ProgramFeature::ALL);
+        constants.limits.max_memo_len = 8;
+        backend.push_simulation(SimulationResult {
+            return_data: Some(constants.to_return_data()),
+            ..SimulationResult::default()
+        });
+        assert_eq!(client.protocol_constants(&payer).unwrap(), constants);
+        assert!(matches!(backend.calls().last(), Some(RpcCall::SimulateTransaction(tx))
+            if tx.message.account_keys == vec![payer, program_id]
+                && sent_instruction_data(tx) == instruction_discriminator("get_protocol_constants")));
+
+        // Kept: asking again doesn't simulate
+        let calls = backend.calls().len();
+        assert_eq!(client.protocol_constants(&payer).unwrap(), constants);
+        assert_eq!(backend.calls().len(), calls);
+        assert_eq!(client.limits().max_memo_len, 8);
+
+        // A memo the compiled limit allows is refused before sending
+        let mut envelope = envelope_for(&SigningRequest::new(
+            &AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100),
+            &TransactionRequest::new(b"data".to_vec()),
+            0,
+        ));
+        envelope.memo = b"invoice 1234".to_vec();
+        assert!(matches!(
+            client.execute(&Keypair::new(), &Pubkey::new_unique(), &envelope, b"data".to_vec()),
+            Err(AttestaError::InvalidTransaction(TransactionRequestError::MemoTooLong { len: 12, max: 8 }))
+        ));
+        assert!(backend.sent_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_sponsor_initialize_needs_the_sponsorship_feature() {
+        use core_crypto::test_utils::TestPasskey;
+
+        let (client, backend, program_id) = mock_client();
+        let (relayer, owner) = (Keypair::new(), Keypair::new());
+        let mut passkey = TestPasskey::new(1);
+        let (address, _) = instructions::derive_attesta_address(&program_id, &owner.pubkey());
+        let request = RegistrationRequest::new(owner.pubkey(), address, passkey.public_key(), passkey.credential_id());
+        let registration = Registration { webauthn_sig: passkey.sign_create(&request.challenge), aaguid: None };
+        let (pool, _) = instructions::derive_sponsor_pool_address(&program_id, &Pubkey::new_unique());
+
+        backend.push_simulation(SimulationResult {
+            return_data: Some(ProgramFeatures::new(&[ProgramFeature::Claims, ProgramFeature::Inheritance]).to_return_data()),
+            ..SimulationResult::default()
+        });
+        let err = client.sponsor_initialize(&relayer, &owner, &pool, &request, &registration, None).unwrap_err();
+        assert!(matches!(err, AttestaError::FeatureNotEnabled("sponsorship")), "{err}");
+        assert!(backend.sent_transactions().is_empty());
+    }
+
+    fn drill_account() -> AttestaAccount {
+        AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100)
+    }
+
+    #[test]
+    fn test_run_recovery_drill_sends_initiate_then_approvals() {
+        let (client, backend, _) = mock_client();
+        let payer = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut account = drill_account();
+        account.last_drill_at = 1_700_000_000;
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let approvals = [(test_signature(), 1), (test_signature(), 2)];
+        let drilled_at = client
+            .run_recovery_drill(&payer, &address, [2; 64], b"new", &approvals)
+            .unwrap();
+
+        assert_eq!(drilled_at, 1_700_000_000);
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent_instruction_data(&sent[0])[..8], instruction_discriminator("initiate_recovery_drill"));
+        assert_eq!(sent_instruction_data(&sent[1])[..8], instruction_discriminator("approve_recovery_drill"));
+    }
+
+    #[test]
+    fn test_run_recovery_drill_reports_missing_approvals() {
+        let (client, backend, _) = mock_client();
+        let payer = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut account = drill_account();
+        let mut drill = RecoveryRequest::new([2; 64], b"new".to_vec(), 0);
+        drill.approvals.push([3; 32]);
+        account.pending_drill = Some(drill);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let result = client.run_recovery_drill(&payer, &address, [2; 64], b"new", &[(test_signature(), 1)]);
+
+        assert!(matches!(result, Err(AttestaError::RecoveryDrillFailed { approvals: 1 })));
+        assert!(matches!(
+            client.run_recovery_drill(&payer, &address, [2; 64], b"new", &[]),
+            Err(AttestaError::RecoveryDrillFailed { approvals: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_approve_pending_proposals() {
+        use core_crypto::{compute_challenge, test_utils::TestPasskey};
+        use smart_account::proposal::{approve_proposal, approve_proposal_payload, PROPOSAL_APPROVE_ACTION, PROPOSAL_LIFETIME};
+        use smart_account::{transaction_message_hash, PendingTransaction, TokenTransfer};
+
+        let (client, backend, program_id) = mock_client();
+        let payer = Keypair::new();
+        let address = Pubkey::new_unique();
+        let mut passkey = TestPasskey::new(1);
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 0);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let now = unix_timestamp();
+        let policy_hash = account.policy_hash;
+        let proposal = |transaction_data: Vec<u8>, created_at: i64| PendingTransaction {
+            message_hash: transaction_message_hash(&transaction_data),
+            policy_hash,
+            transaction_data,
+            proposer: [7; 32],
+            rent_payer: payer.pubkey(),
+            approvals: vec![[7; 32]],
+            required_approvals: 2,
+            reserved: 0,
+            created_at,
+        };
+        let transfer = TokenTransfer { mint: Pubkey::new_unique(), amount: 250, decimals: 6, destination_ata: Pubkey::new_unique() };
+        let [transfer_address, other_address, expired_address] = [(); 3].map(|_| Pubkey::new_unique());
+        let mut pending = proposal(transfer.to_transaction_data(), now - 60);
+        for (proposal_address, proposal) in [
+            (transfer_address, pending.clone()),
+            (other_address, proposal(vec![1; 40], now)),
+            (expired_address, proposal(vec![2; 40], now - PROPOSAL_LIFETIME - 1)),
+        ] {
+            backend.set_program_account(program_id, proposal_address, 1, proposal_data(&address, &proposal));
+        }
+        // Another account's proposal and another kind of account, both owned by the program
+        backend.set_program_account(program_id, Pubkey::new_unique(), 1, proposal_data(&Pubkey::new_unique(), &pending));
+        backend.set_program_account(program_id, Pubkey::new_unique(), 1, attesta_account_data(&account));
+
+        let proposals = client.list_pending_proposals(&address).unwrap();
+        assert_eq!(proposals.iter().map(|summary| summary.address).collect::<Vec<_>>(), vec![transfer_address, other_address]);
+        assert_eq!(client.security_summary(&address).unwrap().pending.open_proposals, 2);
+        let summary = &proposals[0];
+        assert_eq!((summary.amount, summary.destination), (Some(250), Some(transfer.destination_ata)));
+        assert_eq!((summary.approvals(), summary.required_approvals), (1, 2));
+        assert_eq!(summary.expires_at, now - 60 + PROPOSAL_LIFETIME);
+        assert!(!summary.has_approved(&passkey.credential_id()));
+
+        // The challenge is the one the program checks
+        let request = client.prepare_approval(&account, summary);
+        let payload = approve_proposal_payload(&transfer_address, &pending.message_hash);
+        assert_eq!(request.challenge, compute_challenge(&account.owner, 1, &action_message_hash(PROPOSAL_APPROVE_ACTION, &payload)));
+
+        let sig = passkey.sign_der(&request.challenge);
+        let assertion = AssertionResponse {
+            credential_id: sig.credential_id,
+            authenticator_data: sig.authenticator_data,
+            client_data_json: sig.client_data_json,
+            signature: sig.signature,
+        };
+        // Prepared for one proposal, it can't be submitted for another
+        assert!(matches!(
+            client.submit_approval(&payer, &address, &proposals[1], &request, assertion.clone()),
+            Err(AttestaError::AssertionMismatch { field: "challenge", .. })
+        ));
+        client.submit_approval(&payer, &address, summary, &request, assertion).unwrap();
+
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 1);
+        let message = &sent[0].message;
+        let instruction = &message.instructions[0];
+        let accounts: Vec<Pubkey> = instruction.accounts.iter().map(|&index| message.account_keys[index as usize]).collect();
+        assert_eq!(accounts, vec![address, transfer_address]);
+        let data = sent_instruction_data(&sent[0]);
+        assert_eq!(data[..8], instruction_discriminator("approve_proposal"));
+        let (sig_bytes, nonce) = <(Vec<u8>, u64)>::try_from_slice(&data[8..]).unwrap();
+        assert_eq!(nonce, 1);
+
+        // What was sent is accepted on-chain
+        let webauthn_sig = WebAuthnSignature::from_bytes(&sig_bytes).unwrap();
+        approve_proposal(&mut account, &mut pending, &transfer_address, webauthn_sig, nonce, now).unwrap();
+        assert!(pending.threshold_reached());
+    }
+
+    #[test]
+    fn test_decode_backup_escrow_rejects_wrong_discriminator() {
+        let backup = EncryptedBackup::new(b"key", b"account data", 1234);
+        let mut data = escrow_account_data(&backup, 0);
+        data[0] ^= 0xff;
+
+        assert!(matches!(decode_backup_escrow(&data), Err(AttestaError::InvalidAccountData)));
+    }
+
+    #[test]
+    fn test_update_policy_detects_a_lost_update() {
+        let (client, backend, program_id) = mock_client();
+        let owner = Keypair::new();
+        let address = Pubkey::new_unique();
+        let account = AttestaAccount::new(owner.pubkey(), [1; 64], vec![1], vec![], 0);
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        // Another device raises the limit after we read the account, before we send
+        let theirs = Policy::spending_limit(Amount::from_sol(5.0).unwrap());
+        let mut changed = account.clone();
+        changed.set_policies(vec![theirs.to_bytes().unwrap()]);
+        changed.bump_state_version();
+        let stale = TransactionError::InstructionError(0, InstructionError::Custom(CONCURRENT_MODIFICATION_ERROR));
+
+        let mine = Policy::time_locked(1_800_000_000);
+        let mut seen = Vec::new();
+        let mut edit = |current: &AttestaAccount| {
+            seen.push(current.state_version);
+            if current.state_version == 0 {
+                backend.set_account(address, 1, attesta_account_data(&changed));
+                backend.push_send_result(Err(AttestaError::TransactionFailed(stale.clone())));
+            }
+            Some(mine.clone())
+        };
+
+        // Without retries the conflict is reported instead of overwriting theirs
+        let error = client.update_policy(&owner, &address, &mut edit).unwrap_err();
+        assert!(matches!(error, AttestaError::ConcurrentModification { read_version: 0 }));
+
+        // With one, the edit runs again on their change and is sent at its version
+        backend.set_account(address, 1, attesta_account_data(&account));
+        backend.clear_calls();
+        let client = AttestaClient::with_backend(backend.clone(), program_id).with_concurrency_retries(1);
+        client.update_policy(&owner, &address, &mut edit).unwrap();
+        assert_eq!(seen, vec![0, 0, 1]);
+
+        let sent = backend.sent_transactions();
+        let data = sent_instruction_data(sent.last().unwrap());
+        assert_eq!(data[..8], instruction_discriminator("update_policy"));
+        assert!(data.ends_with(&1u64.to_le_bytes()));
+    }
 }
@@ -0,0 +1,256 @@
+//! How long to wait for a sent transaction, and at what commitment
+//!
+//! Every transaction `AttestaClient` sends is submitted once, then its
+//! signature status is polled until it reaches the strategy's commitment,
+//! fails on-chain, or the strategy's timeout passes. A payments backend
+//! might wait for `finalized`; a UI that only wants quick feedback can take
+//! `processed`.
+//!
+//! If the transaction's blockhash expires before it lands, it can never land,
+//! so with `resubmit_on_expiry` it's signed again over a fresh blockhash and
+//! resent. That's safe for Attesta instructions: the passkey proof and its
+//! nonce are the same in every copy, so at most one of them executes.
+
+use std::thread;
+use std::time::{Duration, Instant};
+use anchor_client::solana_sdk::{
+    commitment_config::CommitmentLevel,
+    hash::Hash,
+    signature::Signature,
+    transaction::Transaction,
+};
+use crate::backend::RpcBackend;
+use crate::client::AttestaError;
+
+/// When `AttestaClient` considers a sent transaction done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmationStrategy {
+    /// The commitment the transaction must reach
+    pub commitment: CommitmentLevel,
+
+    /// How long to wait for it, from the first submission
+    pub timeout: Duration,
+
+    /// Time between signature status checks
+    pub poll_interval: Duration,
+
+    /// Sign and resend over a new blockhash if the old one expires first
+    pub resubmit_on_expiry: bool,
+}
+
+impl Default for ConfirmationStrategy {
+    fn default() -> Self {
+        Self {
+            commitment: CommitmentLevel::Confirmed,
+            timeout: Duration::from_secs(60),
+            poll_interval: Duration::from_millis(500),
+            resubmit_on_expiry: false,
+        }
+    }
+}
+
+impl ConfirmationStrategy {
+    /// The default strategy, waiting for `commitment` instead
+    pub fn with_commitment(commitment: CommitmentLevel) -> Self {
+        Self { commitment, ..Self::default() }
+    }
+}
+
+/// Sends the transaction `sign` builds, and waits for it as `strategy` says
+///
+/// `sign` is called with a recent blockhash, again for each resubmission.
+///
+/// # Returns
+/// - `Ok(signature)` of the copy that reached the commitment
+/// - `Err(AttestaError::TransactionFailed)` if it landed but failed
+/// - `Err(AttestaError::BlockhashExpired)` if it expired and resubmitting is off
+/// - `Err(AttestaError::ConfirmationTimeout)` if nothing was decided in time
+pub(crate) fn send_and_confirm(
+    backend: &dyn RpcBackend,
+    strategy: &ConfirmationStrategy,
+    sign: impl Fn(Hash) -> Transaction,
+) -> Result<Signature, AttestaError> {
+    let started = Instant::now();
+    let mut blockhash = backend.get_latest_blockhash()?;
+    let mut signatures = vec![backend.send_transaction(&sign(blockhash))?];
+
+    loop {
+        if let Some(signature) = landed(backend, &signatures, strategy)? {
+            return Ok(signature);
+        }
+
+        if !backend.is_blockhash_valid(&blockhash)? {
+            // It may have landed just before the blockhash expired
+            if let Some(signature) = landed(backend, &signatures, strategy)? {
+                return Ok(signature);
+            }
+            if !strategy.resubmit_on_expiry {
+                return Err(AttestaError::BlockhashExpired);
+            }
+            blockhash = backend.get_latest_blockhash()?;
+            signatures.push(backend.send_transaction(&sign(blockhash))?);
+            continue;
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed >= strategy.timeout {
+            return Err(AttestaError::ConfirmationTimeout { waited: elapsed, signature: signatures[0] });
+        }
+        thread::sleep(strategy.poll_interval.min(strategy.timeout - elapsed));
+    }
+}
+
+/// Waits for a transaction something else sent, such as an airdrop
+///
+/// Nothing is resent; the blockhash isn't known, so only the timeout ends
+/// the wait early.
+#[cfg(feature = "devtools")]
+pub(crate) fn confirm_signature(
+    backend: &dyn RpcBackend,
+    strategy: &ConfirmationStrategy,
+    signature: Signature,
+) -> Result<(), AttestaError> {
+    let started = Instant::now();
+    loop {
+        if landed(backend, &[signature], strategy)?.is_some() {
+            return Ok(());
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= strategy.timeout {
+            return Err(AttestaError::ConfirmationTimeout { waited: elapsed, signature });
+        }
+        thread::sleep(strategy.poll_interval.min(strategy.timeout - elapsed));
+    }
+}
+
+/// The first of `signatures` that reached the commitment, if any
+///
+/// An earlier copy can land after a later one was sent, so all are checked.
+fn landed(
+    backend: &dyn RpcBackend,
+    signatures: &[Signature],
+    strategy: &ConfirmationStrategy,
+) -> Result<Option<Signature>, AttestaError> {
+    let statuses = backend.get_signature_statuses(signatures, strategy.commitment)?;
+    for (signature, status) in signatures.iter().zip(statuses) {
+        match status {
+            Some(Ok(())) => return Ok(Some(*signature)),
+            Some(Err(e)) => return Err(AttestaError::TransactionFailed(e)),
+            None => {}
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_client::solana_sdk::{
+        instruction::InstructionError,
+        signature::{Keypair, Signer},
+        system_instruction,
+        transaction::TransactionError,
+    };
+    use solana_program::pubkey::Pubkey;
+    use crate::test_utils::{MockBackend, RpcCall};
+
+    fn signer(payer: &Keypair) -> impl Fn(Hash) -> Transaction + '_ {
+        let instruction = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        move |blockhash| {
+            Transaction::new_signed_with_payer(std::slice::from_ref(&instruction), Some(&payer.pubkey()), &[payer], blockhash)
+        }
+    }
+
+    fn fast(resubmit_on_expiry: bool) -> ConfirmationStrategy {
+        ConfirmationStrategy {
+            commitment: CommitmentLevel::Finalized,
+            timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(1),
+            resubmit_on_expiry,
+        }
+    }
+
+    fn status_checks(backend: &MockBackend) -> Vec<CommitmentLevel> {
+        backend
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                RpcCall::GetSignatureStatuses { commitment, .. } => Some(commitment),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_waits_for_slow_confirmation() {
+        let backend = MockBackend::new();
+        let payer = Keypair::new();
+        for _ in 0..3 {
+            backend.push_signature_status(None);
+        }
+
+        let signature = send_and_confirm(&backend, &fast(false), signer(&payer)).unwrap();
+        assert_eq!(signature, backend.sent_transactions()[0].signatures[0]);
+        assert_eq!(backend.sent_transactions().len(), 1);
+        assert_eq!(status_checks(&backend), vec![CommitmentLevel::Finalized; 4]);
+    }
+
+    #[test]
+    fn test_times_out() {
+        let backend = MockBackend::new();
+        let payer = Keypair::new();
+        for _ in 0..1000 {
+            backend.push_signature_status(None);
+        }
+        let strategy = ConfirmationStrategy { timeout: Duration::from_millis(20), ..fast(true) };
+
+        let error = send_and_confirm(&backend, &strategy, signer(&payer)).unwrap_err();
+        assert!(matches!(error, AttestaError::ConfirmationTimeout { waited, .. } if waited >= strategy.timeout));
+        assert_eq!(backend.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_resubmits_after_expiry() {
+        let backend = MockBackend::new();
+        let payer = Keypair::new();
+        backend.push_signature_status(None);
+        backend.push_signature_status(None);
+        backend.push_blockhash_validity(false);
+        backend.push_blockhash(Hash::new_unique());
+        backend.push_blockhash(Hash::new_unique());
+
+        // Neither copy is seen before the blockhash expires; the second lands
+        let signature = send_and_confirm(&backend, &fast(true), signer(&payer)).unwrap();
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(signature, sent[1].signatures[0]);
+        assert_ne!(sent[0].message.recent_blockhash, sent[1].message.recent_blockhash);
+        assert_eq!(sent[0].message.instructions, sent[1].message.instructions);
+    }
+
+    #[test]
+    fn test_expiry_without_resubmitting() {
+        let backend = MockBackend::new();
+        let payer = Keypair::new();
+        backend.push_signature_status(None);
+        backend.push_signature_status(None);
+        backend.push_blockhash_validity(false);
+
+        let error = send_and_confirm(&backend, &fast(false), signer(&payer)).unwrap_err();
+        assert!(matches!(error, AttestaError::BlockhashExpired));
+        assert_eq!(backend.sent_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_on_chain_failure_is_not_a_timeout() {
+        let backend = MockBackend::new();
+        let payer = Keypair::new();
+        let failure = TransactionError::InstructionError(0, InstructionError::Custom(6000));
+        backend.push_signature_status(None);
+        backend.push_signature_status(Some(Err(failure.clone())));
+
+        let error = send_and_confirm(&backend, &fast(true), signer(&payer)).unwrap_err();
+        assert!(matches!(error, AttestaError::TransactionFailed(e) if e == failure));
+        assert_eq!(backend.sent_transactions().len(), 1);
+    }
+}
@@ -0,0 +1,359 @@
+//! Bootstrapping a funded account on a local validator
+//!
+//! `LocalEnv::bootstrap()` turns a running `solana-test-validator` (with the
+//! Attesta program loaded) into a ready-to-use setup: a funded payer, an
+//! Attesta account registered to a software test passkey, and a client to
+//! drive them. Examples and integration tests can start from there instead
+//! of repeating the airdrop and registration steps.
+//!
+//! The test passkey's private key is derived from a one-byte seed, so
+//! anyone can sign for these accounts. Never use them on mainnet.
+//!
+//! Only available with the `devtools` feature.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+use anchor_client::solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    signature::{Keypair, Signature, Signer},
+};
+use core_crypto::test_utils::TestPasskey;
+use recovery::{Amount, AmountError, Policy, LAMPORTS_PER_SOL};
+use smart_account::TransactionRequest;
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use crate::backend::SolanaRpcBackend;
+use crate::client::{AttestaClient, AttestaError};
+use crate::confirmation::confirm_signature;
+use crate::instructions::{self, derive_attesta_address, INITIALIZE_COMPUTE_UNITS};
+use crate::signing::{ProofEnvelope, RegistrationRequest};
+
+/// RPC URL of a `solana-test-validator` on its default port
+pub const LOCALNET_URL: &str = "http://127.0.0.1:8899";
+
+/// The most a single airdrop asks for
+///
+/// Devnet's faucet refuses larger requests, so bigger amounts are split.
+pub const MAX_AIRDROP_LAMPORTS: u64 = 2 * LAMPORTS_PER_SOL;
+
+/// How many times one airdrop is tried before giving up
+pub const AIRDROP_ATTEMPTS: u32 = 5;
+
+/// Errors from setting up a local environment
+#[derive(Debug, Error)]
+pub enum DevtoolsError {
+    #[error("no validator answering at {url} ({reason}); start one with `solana-test-validator`")]
+    ValidatorUnreachable { url: String, reason: String },
+
+    #[error("program {0} isn't deployed on this cluster")]
+    ProgramNotDeployed(Pubkey),
+
+    #[error("ATTESTA_PROGRAM_ID isn't set to the deployed program's address")]
+    ProgramIdNotSet,
+
+    #[error("invalid program ID in ATTESTA_PROGRAM_ID: {0}")]
+    InvalidProgramId(String),
+
+    #[error("airdrop to {address} failed after {attempts} attempts: {last_error}")]
+    AirdropFailed { address: Pubkey, attempts: u32, last_error: AttestaError },
+
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] AmountError),
+
+    #[error(transparent)]
+    Client(#[from] AttestaError),
+}
+
+/// What `LocalEnv` sets up, and where
+#[derive(Debug, Clone)]
+pub struct LocalEnvConfig {
+    /// The validator's RPC URL
+    pub rpc_url: String,
+
+    /// The deployed Attesta program
+    pub program_id: Pubkey,
+
+    /// SOL airdropped to the payer, which also pays the account's rent
+    pub payer_sol: f64,
+
+    /// SOL airdropped to the Attesta account once it exists
+    pub account_sol: f64,
+
+    /// The account's policy (`None` for an open account)
+    pub policy: Option<Policy>,
+
+    /// Seed of the test passkey the account is registered to
+    pub passkey_seed: u8,
+
+    /// Wait before the first retry of a failed airdrop, doubled for each one after
+    pub retry_delay: Duration,
+}
+
+impl LocalEnvConfig {
+    /// Defaults for a local validator running `program_id`
+    pub fn new(program_id: Pubkey) -> Self {
+        Self {
+            rpc_url: LOCALNET_URL.to_string(),
+            program_id,
+            payer_sol: 10.0,
+            account_sol: 5.0,
+            policy: None,
+            passkey_seed: 1,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+
+    /// Defaults, with the program ID and RPC URL taken from the environment
+    ///
+    /// `ATTESTA_PROGRAM_ID` is the address the program was deployed at
+    /// (`solana-test-validator --bpf-program <ID> attesta.so`).
+    /// `ATTESTA_RPC_URL`, if set, overrides `LOCALNET_URL`; point it at
+    /// devnet to run the same setup there.
+    pub fn from_env() -> Result<Self, DevtoolsError> {
+        let id = env::var("ATTESTA_PROGRAM_ID").map_err(|_| DevtoolsError::ProgramIdNotSet)?;
+        let program_id = id.parse().map_err(|_| DevtoolsError::InvalidProgramId(id))?;
+        let mut config = Self::new(program_id);
+        if let Ok(url) = env::var("ATTESTA_RPC_URL") {
+            config.rpc_url = url;
+        }
+        Ok(config)
+    }
+}
+
+/// A funded Attesta account on a running cluster, and the keys to drive it
+pub struct LocalEnv {
+    /// Client for the cluster the environment was set up on
+    pub client: AttestaClient,
+
+    /// Funded keypair that owns the account and pays for transactions
+    pub payer: Keypair,
+
+    /// The software passkey registered to the account
+    pub passkey: TestPasskey,
+
+    /// The Attesta account's address
+    pub account: Pubkey,
+
+    retry_delay: Duration,
+}
+
+impl LocalEnv {
+    /// Sets up an environment as `LocalEnvConfig::from_env()` describes
+    pub fn bootstrap() -> Result<Self, DevtoolsError> {
+        Self::bootstrap_with(LocalEnvConfig::from_env()?)
+    }
+
+    /// Sets up an environment on the validator at `config.rpc_url`
+    pub fn bootstrap_with(config: LocalEnvConfig) -> Result<Self, DevtoolsError> {
+        let client = AttestaClient::with_backend(SolanaRpcBackend::new(config.rpc_url.clone()), config.program_id);
+        Self::bootstrap_with_client(client, &config)
+    }
+
+    /// Sets up an environment through an existing client
+    ///
+    /// `config.rpc_url` and `config.program_id` are ignored; the client's
+    /// own are used.
+    ///
+    /// # Returns
+    /// - `Err(DevtoolsError::ValidatorUnreachable)` if the cluster doesn't answer
+    /// - `Err(DevtoolsError::ProgramNotDeployed)` if the program account is missing
+    /// - `Err(DevtoolsError::AirdropFailed)` if the faucet kept refusing
+    pub fn bootstrap_with_client(client: AttestaClient, config: &LocalEnvConfig) -> Result<Self, DevtoolsError> {
+        client.backend().get_latest_blockhash().map_err(|e| DevtoolsError::ValidatorUnreachable {
+            url: config.rpc_url.clone(),
+            reason: e.to_string(),
+        })?;
+
+        let program_id = client.program_id();
+        if client.backend().get_account_data(&program_id)?.is_none() {
+            return Err(DevtoolsError::ProgramNotDeployed(program_id));
+        }
+
+        let payer = Keypair::new();
+        fund(&client, &payer.pubkey(), config.payer_sol, config.retry_delay)?;
+
+        let mut passkey = TestPasskey::new(config.passkey_seed);
+        let (account, _) = derive_attesta_address(&program_id, &payer.pubkey());
+        let registration = RegistrationRequest::new(payer.pubkey(), account, passkey.public_key(), passkey.credential_id());
+        let registration_sig = passkey.sign_create(&registration.challenge);
+
+        let initialize = instructions::initialize(
+            &program_id,
+            &payer.pubkey(),
+            registration.public_key,
+            registration.credential_id,
+            config.policy.as_ref(),
+            false,
+            &registration_sig,
+            Vec::new(),
+        )
+        .map_err(|_| AttestaError::InvalidAccountData)?;
+        client.send_instructions(
+            &payer,
+            &[ComputeBudgetInstruction::set_compute_unit_limit(INITIALIZE_COMPUTE_UNITS), initialize],
+            &[],
+        )?;
+
+        fund(&client, &account, config.account_sol, config.retry_delay)?;
+
+        Ok(Self { client, payer, passkey, account, retry_delay: config.retry_delay })
+    }
+
+    /// Airdrops `sol` to `address` and waits for it to land
+    ///
+    /// Amounts over `MAX_AIRDROP_LAMPORTS` are requested in parts. A refused
+    /// request (devnet rate-limits its faucet) is retried up to
+    /// `AIRDROP_ATTEMPTS` times, backing off between tries.
+    ///
+    /// # Returns
+    /// The signatures of the airdrops, in order
+    pub fn fund_account(&self, address: &Pubkey, sol: f64) -> Result<Vec<Signature>, DevtoolsError> {
+        fund(&self.client, address, sol, self.retry_delay)
+    }
+
+    /// Has the test passkey authorize `request` with the account's next nonce
+    ///
+    /// The result is ready for `client.execute`.
+    pub fn sign(&mut self, request: &TransactionRequest) -> Result<ProofEnvelope, DevtoolsError> {
+        let account = self.client.get_account(&self.account)?;
+        let signing = self.client.prepare_execution(&account, request);
+        Ok(ProofEnvelope {
+            webauthn_sig: self.passkey.sign(&signing.challenge),
+            nonce: signing.nonce,
+            message_hash: signing.message_hash,
+            idempotency_key: signing.idempotency_key,
+            parent_account: signing.parent_account,
+            logs_proofs: signing.logs_proofs,
+            memo: signing.memo,
+            emit_memo: false,
+        })
+    }
+}
+
+fn fund(client: &AttestaClient, address: &Pubkey, sol: f64, retry_delay: Duration) -> Result<Vec<Signature>, DevtoolsError> {
+    let mut remaining = Amount::from_sol(sol)?.lamports();
+    let mut signatures = Vec::new();
+    while remaining > 0 {
+        let lamports = remaining.min(MAX_AIRDROP_LAMPORTS);
+        signatures.push(airdrop(client, address, lamports, retry_delay)?);
+        remaining -= lamports;
+    }
+    Ok(signatures)
+}
+
+fn airdrop(client: &AttestaClient, address: &Pubkey, lamports: u64, retry_delay: Duration) -> Result<Signature, DevtoolsError> {
+    let backend = client.backend();
+    let mut delay = retry_delay;
+    let mut attempt = 1;
+    loop {
+        let result = backend
+            .request_airdrop(address, lamports)
+            .and_then(|signature| confirm_signature(backend, &client.confirmation(), signature).map(|()| signature));
+        match result {
+            Ok(signature) => return Ok(signature),
+            Err(last_error) if attempt >= AIRDROP_ATTEMPTS => {
+                return Err(DevtoolsError::AirdropFailed { address: *address, attempts: attempt, last_error });
+            }
+            Err(_) => {
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smart_account::AttestaAccount;
+    use crate::test_utils::{attesta_account_data, MockBackend, RpcCall};
+
+    fn config(program_id: Pubkey) -> LocalEnvConfig {
+        LocalEnvConfig { retry_delay: Duration::ZERO, ..LocalEnvConfig::new(program_id) }
+    }
+
+    fn airdrops(backend: &MockBackend) -> Vec<(Pubkey, u64)> {
+        backend
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                RpcCall::RequestAirdrop { address, lamports } => Some((address, lamports)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bootstrap_registers_and_funds_an_account() {
+        let backend = MockBackend::new();
+        let program_id = Pubkey::new_unique();
+        backend.set_account(program_id, 1, vec![2; 36]);
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+
+        let mut env = LocalEnv::bootstrap_with_client(client, &config(program_id)).unwrap();
+
+        let payer = env.payer.pubkey();
+        assert_eq!(env.account, derive_attesta_address(&program_id, &payer).0);
+        assert_eq!(
+            airdrops(&backend),
+            vec![
+                (payer, MAX_AIRDROP_LAMPORTS),
+                (payer, MAX_AIRDROP_LAMPORTS),
+                (payer, MAX_AIRDROP_LAMPORTS),
+                (payer, MAX_AIRDROP_LAMPORTS),
+                (payer, MAX_AIRDROP_LAMPORTS),
+                (env.account, MAX_AIRDROP_LAMPORTS),
+                (env.account, MAX_AIRDROP_LAMPORTS),
+                (env.account, LAMPORTS_PER_SOL),
+            ]
+        );
+
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 1);
+        let initialize = &sent[0].message.instructions[1];
+        assert_eq!(initialize.data[..8], instructions::instruction_discriminator("initialize"));
+
+        // Proofs from the environment verify against the account it registered
+        let account = AttestaAccount::new(payer, env.passkey.public_key(), env.passkey.credential_id(), vec![], 0);
+        backend.set_account(env.account, 0, attesta_account_data(&account));
+        let request = TransactionRequest::new(vec![1, 2, 3]);
+        let envelope = env.sign(&request).unwrap();
+        assert_eq!(envelope.nonce, 1);
+        assert!(smart_account::verify_passkey_authorization(&account, &envelope.webauthn_sig, 1, &envelope.message_hash).is_ok());
+    }
+
+    #[test]
+    fn test_bootstrap_needs_a_validator_and_the_program() {
+        let backend = MockBackend::new();
+        let program_id = Pubkey::new_unique();
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+
+        let result = LocalEnv::bootstrap_with_client(client, &config(program_id));
+        assert!(matches!(result, Err(DevtoolsError::ProgramNotDeployed(id)) if id == program_id));
+        assert!(airdrops(&backend).is_empty());
+    }
+
+    #[test]
+    fn test_fund_account_retries_rate_limited_airdrops() {
+        let backend = MockBackend::new();
+        let program_id = Pubkey::new_unique();
+        backend.set_account(program_id, 1, vec![2; 36]);
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+        let env = LocalEnv::bootstrap_with_client(client, &LocalEnvConfig { payer_sol: 0.0, account_sol: 0.0, ..config(program_id) }).unwrap();
+
+        let address = Pubkey::new_unique();
+        for _ in 0..2 {
+            backend.push_airdrop_result(Err(AttestaError::RpcError("429 Too Many Requests".to_string())));
+        }
+        assert_eq!(env.fund_account(&address, 0.5).unwrap().len(), 1);
+        assert_eq!(airdrops(&backend), vec![(address, LAMPORTS_PER_SOL / 2); 3]);
+
+        for _ in 0..AIRDROP_ATTEMPTS {
+            backend.push_airdrop_result(Err(AttestaError::RpcError("429 Too Many Requests".to_string())));
+        }
+        let result = env.fund_account(&address, 1.0);
+        assert!(matches!(result, Err(DevtoolsError::AirdropFailed { attempts: AIRDROP_ATTEMPTS, .. })));
+    }
+}
@@ -0,0 +1,510 @@
+//! Enrolling a passkey in one guided flow
+//!
+//! Putting a new passkey on an account takes a WebAuthn ceremony, several
+//! checks on what it returns, and an `initialize` or `add_passkey` built to
+//! match. `PasskeyEnrollment` runs them in order:
+//!
+//! 1. `begin` makes a random challenge for `navigator.credentials.create()`
+//!    and records the relying party the credential must be created for.
+//! 2. `complete` checks the created credential against it (signature,
+//!    challenge, RP ID, origin, user presence), reads its public key out of
+//!    the COSE encoding, checks the key is on the curve, and keeps the
+//!    authenticator's AAGUID.
+//! 3. `registration_request` is the second prompt: the passkey signs a
+//!    challenge naming itself, the owner and the account it's joining. That
+//!    proof is what the program checks, so someone who intercepts the
+//!    `create()` response can't enroll the key on an account of their own
+//!    first.
+//! 4. `enroll` checks that proof and sends `initialize` for a new account,
+//!    or `add_passkey` for an existing one (approved by a passkey already on
+//!    it, from `approval_request`).
+//!
+//! Each step fails with an `EnrollmentError` saying what was wrong.
+
+use anchor_client::solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    signature::{Keypair, Signature, Signer},
+};
+use attesta_types::consts::{AAGUID_LEN, P256_PUBKEY_LEN};
+use core_crypto::{
+    challenge::base64url_encode,
+    p256_verify::{signature_to_raw, validate_p256_public_key},
+    parse_authenticator_data_detailed, verify_webauthn_signature_with_profile_detailed, CeremonyType, CryptoError,
+    FailureDetail, RelyingParty, VerifyFailure, WebAuthnExpectations, WebAuthnSignature, WebAuthnVerificationProfile,
+    CHALLENGE_LEN,
+};
+use recovery::multi_passkey::PASSKEY_ADD_ACTION;
+use recovery::Policy;
+use smart_account::{action_message_hash, AttestaAccount};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+use crate::client::{unix_timestamp, AttestaClient, AttestaError};
+use crate::instructions::{self, derive_attesta_address, ADD_PASSKEY_COMPUTE_UNITS, INITIALIZE_COMPUTE_UNITS};
+use crate::signing::{AssertionResponse, ProofEnvelope, RegistrationRequest, SigningRequest, DEFAULT_SIGNING_REQUEST_TTL};
+
+/// Errors from enrolling a passkey, naming the step that failed
+#[derive(Error, Debug)]
+pub enum EnrollmentError {
+    #[error("Enrollment challenge expired at {0}")]
+    ChallengeExpired(i64),
+
+    #[error("Created credential rejected: {0}")]
+    CredentialRejected(VerifyFailure),
+
+    #[error("The response has no attested credential data; it must come from navigator.credentials.create()")]
+    NoAttestedCredential,
+
+    #[error("The response's credential ID isn't the one its authenticator data attests")]
+    CredentialIdMismatch,
+
+    #[error("The attested public key isn't a P-256 point: {0}")]
+    InvalidPublicKey(CryptoError),
+
+    #[error("{payer} can't enroll a passkey for {owner}'s account")]
+    WrongOwner { owner: Pubkey, payer: Pubkey },
+
+    #[error("Registration proof rejected: {0}")]
+    RegistrationProofRejected(AttestaError),
+
+    #[error("The approval wasn't signed for adding this passkey")]
+    WrongApproval,
+
+    #[error("The account doesn't allow this authenticator model ({0:?})")]
+    AuthenticatorNotAllowed(Option<[u8; AAGUID_LEN]>),
+
+    #[error(transparent)]
+    Client(#[from] AttestaError),
+}
+
+/// The options for `navigator.credentials.create()`, from `PasskeyEnrollment::begin`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnrollmentChallenge {
+    /// The wallet that owns (or will own) the account
+    pub owner: Pubkey,
+
+    /// The random challenge to pass to `create()`
+    pub challenge: [u8; CHALLENGE_LEN],
+
+    /// The challenge as unpadded base64url (as it appears in `clientDataJSON`)
+    pub challenge_b64url: String,
+
+    /// The RP ID and origin the credential must be created for
+    pub relying_party: RelyingParty,
+
+    /// After this time (Unix timestamp) `complete` refuses the response
+    pub expires_at: i64,
+}
+
+/// A created passkey that passed `PasskeyEnrollment::complete`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedPasskey {
+    /// The wallet the passkey is being enrolled for
+    pub owner: Pubkey,
+
+    /// The passkey's public key, 64 bytes (x || y)
+    pub public_key: [u8; P256_PUBKEY_LEN],
+
+    /// Its credential ID
+    pub credential_id: Vec<u8>,
+
+    /// The authenticator model it attested
+    pub aaguid: [u8; AAGUID_LEN],
+
+    /// Whether the user was verified (biometric or PIN) when it was created
+    pub user_verified: bool,
+}
+
+/// Where `PasskeyEnrollment::enroll` puts the passkey
+#[derive(Debug, Clone, Copy)]
+pub enum EnrollmentTarget<'a> {
+    /// Create the owner's account, with this as its first passkey
+    NewAccount {
+        /// The new account's policy (`None` for an open account)
+        policy: Option<&'a Policy>,
+    },
+
+    /// Add it to an account that already exists
+    ExistingAccount {
+        /// The account's address
+        address: Pubkey,
+
+        /// A current passkey's approval, from `PasskeyEnrollment::approval_request`
+        approval: &'a ProofEnvelope,
+
+        /// The label to store with the passkey
+        name: &'a str,
+    },
+}
+
+impl EnrollmentTarget<'_> {
+    /// The address of the account the passkey joins
+    pub fn account_address(&self, program_id: &Pubkey, owner: &Pubkey) -> Pubkey {
+        match self {
+            EnrollmentTarget::NewAccount { .. } => derive_attesta_address(program_id, owner).0,
+            EnrollmentTarget::ExistingAccount { address, .. } => *address,
+        }
+    }
+}
+
+/// Generates, verifies and enrolls a passkey through one client
+pub struct PasskeyEnrollment<'a> {
+    client: &'a AttestaClient,
+}
+
+impl<'a> PasskeyEnrollment<'a> {
+    pub fn new(client: &'a AttestaClient) -> Self {
+        Self { client }
+    }
+
+    /// Starts a ceremony to create a passkey for `owner`
+    ///
+    /// # Parameters
+    /// - `rp_id`: The relying party ID passed to `create()`
+    /// - `origin`: The web origin the wallet runs on, e.g. `https://wallet.example`
+    pub fn begin(&self, owner: Pubkey, rp_id: &str, origin: &str) -> EnrollmentChallenge {
+        // A throwaway key pair's address: 32 bytes from the OS RNG
+        let challenge = Keypair::new().pubkey().to_bytes();
+        EnrollmentChallenge {
+            owner,
+            challenge,
+            challenge_b64url: base64url_encode(&challenge),
+            relying_party: RelyingParty::new(rp_id, origin),
+            expires_at: unix_timestamp() + DEFAULT_SIGNING_REQUEST_TTL,
+        }
+    }
+
+    /// Checks the `create()` response to `challenge`
+    ///
+    /// # Parameters
+    /// - `response`: The new credential's `rawId`, authenticator data,
+    ///   `clientDataJSON` and self-attestation signature
+    ///
+    /// # Returns
+    /// - `Ok(VerifiedPasskey)` with the passkey's key and model
+    /// - `Err(EnrollmentError)` naming the check that failed
+    pub fn complete(
+        &self,
+        challenge: &EnrollmentChallenge,
+        response: AssertionResponse,
+    ) -> Result<VerifiedPasskey, EnrollmentError> {
+        if unix_timestamp() > challenge.expires_at {
+            return Err(EnrollmentError::ChallengeExpired(challenge.expires_at));
+        }
+
+        let parsed = parse_authenticator_data_detailed(&response.authenticator_data)
+            .map_err(EnrollmentError::CredentialRejected)?;
+        let user_verified = parsed.user_verified();
+        let credential = parsed.attested_credential.ok_or(EnrollmentError::NoAttestedCredential)?;
+        if credential.credential_id != response.credential_id.as_slice() {
+            return Err(EnrollmentError::CredentialIdMismatch);
+        }
+        let (public_key, aaguid) = (credential.public_key, credential.aaguid);
+        validate_p256_public_key(&public_key).map_err(EnrollmentError::InvalidPublicKey)?;
+
+        let signature = signature_to_raw(&response.signature).map_err(|error| {
+            EnrollmentError::CredentialRejected(VerifyFailure::new(error, "signature", FailureDetail::None))
+        })?;
+        let webauthn_sig = WebAuthnSignature::new(
+            response.authenticator_data,
+            response.client_data_json,
+            signature.to_vec(),
+            response.credential_id,
+        );
+        let expectations = WebAuthnExpectations { relying_party: Some(challenge.relying_party), previous_sign_count: 0 };
+        verify_webauthn_signature_with_profile_detailed(
+            &webauthn_sig,
+            &public_key,
+            &challenge.challenge,
+            CeremonyType::Create,
+            &WebAuthnVerificationProfile::standard(),
+            &expectations,
+        )
+        .map_err(EnrollmentError::CredentialRejected)?;
+
+        Ok(VerifiedPasskey {
+            owner: challenge.owner,
+            public_key,
+            credential_id: webauthn_sig.credential_id,
+            aaguid,
+            user_verified,
+        })
+    }
+
+    /// The challenge the new passkey signs to bind itself to `target`
+    ///
+    /// Pass its `challenge_b64url` to `create()` with the same credential,
+    /// and the response to `enroll`.
+    pub fn registration_request(&self, verified: &VerifiedPasskey, target: &EnrollmentTarget) -> RegistrationRequest {
+        let account_address = target.account_address(&self.client.program_id(), &verified.owner);
+        RegistrationRequest::new(verified.owner, account_address, verified.public_key, verified.credential_id.clone())
+    }
+
+    /// The prompt a passkey already on `account` signs to approve adding `verified`
+    ///
+    /// Complete it with `SigningRequest::complete` and pass the envelope as
+    /// `EnrollmentTarget::ExistingAccount::approval`.
+    pub fn approval_request(&self, account: &AttestaAccount, verified: &VerifiedPasskey) -> SigningRequest {
+        let message_hash = add_passkey_message_hash(verified);
+        let nonce = self.client.nonces().assign(account, message_hash);
+        SigningRequest::for_message_hash(account, message_hash, nonce, unix_timestamp())
+    }
+
+    /// Puts `verified` on `target`
+    ///
+    /// # Parameters
+    /// - `registration`: The passkey's response to `registration_request`
+    /// - `payer`: The owner, who signs and pays the rent
+    ///
+    /// # Returns
+    /// The transaction signature
+    pub fn enroll(
+        &self,
+        verified: &VerifiedPasskey,
+        registration: AssertionResponse,
+        target: EnrollmentTarget,
+        payer: &Keypair,
+    ) -> Result<Signature, EnrollmentError> {
+        if payer.pubkey() != verified.owner {
+            return Err(EnrollmentError::WrongOwner { owner: verified.owner, payer: payer.pubkey() });
+        }
+        let registration = self
+            .registration_request(verified, &target)
+            .complete(registration)
+            .map_err(EnrollmentError::RegistrationProofRejected)?;
+        let program_id = self.client.program_id();
+        let invalid = |_| EnrollmentError::Client(AttestaError::InvalidAccountData);
+
+        match target {
+            EnrollmentTarget::NewAccount { policy } => {
+                let initialize = instructions::initialize(
+                    &program_id,
+                    &verified.owner,
+                    verified.public_key,
+                    verified.credential_id.clone(),
+                    policy,
+                    false,
+                    &registration.webauthn_sig,
+                    Vec::new(),
+                )
+                .map_err(invalid)?;
+                let budget = ComputeBudgetInstruction::set_compute_unit_limit(INITIALIZE_COMPUTE_UNITS);
+                Ok(self.client.send_instructions(payer, &[budget, initialize], &[])?)
+            }
+            EnrollmentTarget::ExistingAccount { address, approval, name } => {
+                if approval.message_hash != add_passkey_message_hash(verified) {
+                    return Err(EnrollmentError::WrongApproval);
+                }
+                let account = self.client.get_account(&address)?;
+                let allowlist = &account.settings.aaguid_allowlist;
+                if !allowlist.is_empty() && !registration.aaguid.is_some_and(|aaguid| allowlist.contains(&aaguid)) {
+                    return Err(EnrollmentError::AuthenticatorNotAllowed(registration.aaguid));
+                }
+                self.client.nonces().check(approval.nonce, &approval.message_hash)?;
+
+                let add = instructions::add_passkey(
+                    &program_id,
+                    &address,
+                    &verified.owner,
+                    &approval.webauthn_sig,
+                    approval.nonce,
+                    verified.public_key,
+                    verified.credential_id.clone(),
+                    name.to_string(),
+                    Some(&registration.webauthn_sig),
+                    account.state_version,
+                )
+                .map_err(invalid)?;
+                let budget = ComputeBudgetInstruction::set_compute_unit_limit(ADD_PASSKEY_COMPUTE_UNITS);
+                let signature = self.client.send_instructions(payer, &[budget, add], &[])?;
+                self.client.nonces().mark_executed(approval.nonce, &approval.message_hash);
+                Ok(signature)
+            }
+        }
+    }
+}
+
+/// The message hash a current passkey signs to approve adding `verified`
+fn add_passkey_message_hash(verified: &VerifiedPasskey) -> [u8; 32] {
+    let payload = [verified.public_key.as_slice(), &verified.credential_id].concat();
+    action_message_hash(PASSKEY_ADD_ACTION, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+    use core_crypto::test_utils::TestPasskey;
+    use smart_account::{authorize_action, verify_registration, AccountSettings};
+    use crate::instructions::instruction_discriminator;
+    use crate::test_utils::{attesta_account_data, MockBackend};
+
+    const AAGUID: [u8; AAGUID_LEN] = [7; AAGUID_LEN];
+
+    fn response(webauthn_sig: WebAuthnSignature) -> AssertionResponse {
+        AssertionResponse {
+            credential_id: webauthn_sig.credential_id,
+            authenticator_data: webauthn_sig.authenticator_data,
+            client_data_json: webauthn_sig.client_data_json,
+            signature: webauthn_sig.signature,
+        }
+    }
+
+    fn setup() -> (AttestaClient, MockBackend, Keypair) {
+        let backend = MockBackend::new();
+        (AttestaClient::with_backend(backend.clone(), Pubkey::new_unique()), backend, Keypair::new())
+    }
+
+    /// Runs `begin` and `complete` for `passkey`
+    fn create(enrollment: &PasskeyEnrollment, owner: &Keypair, passkey: &mut TestPasskey) -> VerifiedPasskey {
+        let challenge = enrollment.begin(owner.pubkey(), TestPasskey::RP_ID, TestPasskey::ORIGIN);
+        enrollment.complete(&challenge, response(passkey.sign_registration(&challenge.challenge, AAGUID))).unwrap()
+    }
+
+    /// The data of the last instruction in the only transaction sent
+    fn sent_data(backend: &MockBackend, name: &str) -> Vec<u8> {
+        let sent = backend.sent_transactions();
+        assert_eq!(sent.len(), 1);
+        let data = sent[0].message.instructions.last().unwrap().data.clone();
+        assert_eq!(data[..8], instruction_discriminator(name));
+        data[8..].to_vec()
+    }
+
+    #[test]
+    fn test_enroll_into_new_account() {
+        let (client, backend, owner) = setup();
+        let enrollment = PasskeyEnrollment::new(&client);
+        let mut passkey = TestPasskey::new(3);
+
+        let verified = create(&enrollment, &owner, &mut passkey);
+        assert_eq!((verified.public_key, verified.aaguid), (passkey.public_key(), AAGUID));
+        assert_eq!(verified.credential_id, passkey.credential_id());
+        assert!(verified.user_verified);
+
+        let target = EnrollmentTarget::NewAccount { policy: None };
+        let request = enrollment.registration_request(&verified, &target);
+        let proof = response(passkey.sign_registration(&request.challenge, AAGUID));
+        enrollment.enroll(&verified, proof, target, &owner).unwrap();
+
+        // The program's own check accepts what was sent
+        let data = sent_data(&backend, "initialize");
+        let (public_key, credential_id, _policy, _privacy, registration_sig, _allowlist) =
+            <([u8; 64], Vec<u8>, Vec<u8>, bool, Vec<u8>, Vec<[u8; 16]>)>::try_from_slice(&data).unwrap();
+        let address = derive_attesta_address(&client.program_id(), &owner.pubkey()).0;
+        let registration_sig = WebAuthnSignature::from_bytes(&registration_sig).unwrap();
+        assert_eq!(
+            verify_registration(&owner.pubkey(), &address, &public_key, &credential_id, &registration_sig),
+            Ok(Some(AAGUID))
+        );
+    }
+
+    #[test]
+    fn test_enroll_into_existing_account() {
+        let (client, backend, owner) = setup();
+        let enrollment = PasskeyEnrollment::new(&client);
+        let (mut phone, mut laptop) = (TestPasskey::new(1), TestPasskey::new(2));
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(owner.pubkey(), phone.public_key(), phone.credential_id(), vec![], 0);
+        account.settings = AccountSettings { aaguid_allowlist: vec![AAGUID], ..AccountSettings::default() };
+        account.state_version = 3;
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let verified = create(&enrollment, &owner, &mut laptop);
+        let approval_request = enrollment.approval_request(&account, &verified);
+        let approval = approval_request.complete(response(phone.sign(&approval_request.challenge)), unix_timestamp()).unwrap();
+        let target = EnrollmentTarget::ExistingAccount { address, approval: &approval, name: "Laptop" };
+        let request = enrollment.registration_request(&verified, &target);
+        let proof = response(laptop.sign_registration(&request.challenge, AAGUID));
+        enrollment.enroll(&verified, proof, target, &owner).unwrap();
+
+        let data = sent_data(&backend, "add_passkey");
+        let (webauthn_sig, nonce, public_key, credential_id, name, registration_sig, expected_version) =
+            <(Vec<u8>, u64, [u8; 64], Vec<u8>, String, Vec<u8>, u64)>::try_from_slice(&data).unwrap();
+        assert_eq!((name.as_str(), expected_version), ("Laptop", 3));
+        let registration_sig = WebAuthnSignature::from_bytes(&registration_sig).unwrap();
+        assert_eq!(verify_registration(&owner.pubkey(), &address, &public_key, &credential_id, &registration_sig), Ok(Some(AAGUID)));
+        let payload = [public_key.as_slice(), &credential_id].concat();
+        let webauthn_sig = WebAuthnSignature::from_bytes(&webauthn_sig).unwrap();
+        assert_eq!(authorize_action(&mut account, webauthn_sig, nonce, PASSKEY_ADD_ACTION, &payload), Ok(()));
+    }
+
+    #[test]
+    fn test_complete_names_what_failed() {
+        let (client, _, owner) = setup();
+        let enrollment = PasskeyEnrollment::new(&client);
+        let mut passkey = TestPasskey::new(1);
+        let challenge = enrollment.begin(owner.pubkey(), TestPasskey::RP_ID, TestPasskey::ORIGIN);
+
+        // Signed for another challenge
+        let other = enrollment.begin(owner.pubkey(), TestPasskey::RP_ID, TestPasskey::ORIGIN);
+        assert_ne!(other.challenge, challenge.challenge);
+        let error = enrollment.complete(&challenge, response(passkey.sign_registration(&other.challenge, AAGUID))).unwrap_err();
+        assert!(matches!(error, EnrollmentError::CredentialRejected(failure) if failure.field == "challenge"));
+
+        // Created for another relying party
+        let elsewhere = enrollment.begin(owner.pubkey(), "wallet.example", "https://wallet.example");
+        let error = enrollment.complete(&elsewhere, response(passkey.sign_registration(&elsewhere.challenge, AAGUID))).unwrap_err();
+        assert!(matches!(error, EnrollmentError::CredentialRejected(failure) if failure.error == CryptoError::RpIdMismatch));
+
+        // Without attested credential data there's no key to read
+        let error = enrollment.complete(&challenge, response(passkey.sign_create(&challenge.challenge))).unwrap_err();
+        assert!(matches!(error, EnrollmentError::NoAttestedCredential));
+
+        let mut mislabeled = response(passkey.sign_registration(&challenge.challenge, AAGUID));
+        mislabeled.credential_id = b"someone-else".to_vec();
+        assert!(matches!(enrollment.complete(&challenge, mislabeled), Err(EnrollmentError::CredentialIdMismatch)));
+
+        let stale = EnrollmentChallenge { expires_at: unix_timestamp() - 1, ..challenge };
+        let error = enrollment.complete(&stale, response(passkey.sign_registration(&stale.challenge, AAGUID))).unwrap_err();
+        assert!(matches!(error, EnrollmentError::ChallengeExpired(_)));
+    }
+
+    #[test]
+    fn test_enroll_refuses_unbound_proofs() {
+        let (client, backend, owner) = setup();
+        let enrollment = PasskeyEnrollment::new(&client);
+        let mut passkey = TestPasskey::new(1);
+        let challenge = enrollment.begin(owner.pubkey(), TestPasskey::RP_ID, TestPasskey::ORIGIN);
+        let created = passkey.sign_registration(&challenge.challenge, AAGUID);
+        let verified = enrollment.complete(&challenge, response(created.clone())).unwrap();
+        let target = EnrollmentTarget::NewAccount { policy: None };
+
+        // The create() response itself doesn't name the account, so it can't enroll
+        let error = enrollment.enroll(&verified, response(created), target, &owner).unwrap_err();
+        assert!(matches!(error, EnrollmentError::RegistrationProofRejected(_)));
+
+        // Nor can anyone but the owner it was created for
+        let request = enrollment.registration_request(&verified, &target);
+        let proof = response(passkey.sign_registration(&request.challenge, AAGUID));
+        let error = enrollment.enroll(&verified, proof, target, &Keypair::new()).unwrap_err();
+        assert!(matches!(error, EnrollmentError::WrongOwner { .. }));
+        assert!(backend.sent_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_enroll_checks_the_allowlist_and_approval() {
+        let (client, backend, owner) = setup();
+        let enrollment = PasskeyEnrollment::new(&client);
+        let (mut phone, mut laptop) = (TestPasskey::new(1), TestPasskey::new(2));
+        let address = Pubkey::new_unique();
+        let mut account = AttestaAccount::new(owner.pubkey(), phone.public_key(), phone.credential_id(), vec![], 0);
+        account.settings = AccountSettings { aaguid_allowlist: vec![[9; AAGUID_LEN]], ..AccountSettings::default() };
+        backend.set_account(address, 1, attesta_account_data(&account));
+
+        let verified = create(&enrollment, &owner, &mut laptop);
+        let approval_request = enrollment.approval_request(&account, &verified);
+        let approval = approval_request.complete(response(phone.sign(&approval_request.challenge)), unix_timestamp()).unwrap();
+        let target = EnrollmentTarget::ExistingAccount { address, approval: &approval, name: "Laptop" };
+        let request = enrollment.registration_request(&verified, &target);
+        let proof = response(laptop.sign_registration(&request.challenge, AAGUID));
+        let error = enrollment.enroll(&verified, proof.clone(), target, &owner).unwrap_err();
+        assert!(matches!(error, EnrollmentError::AuthenticatorNotAllowed(Some(AAGUID))));
+
+        // An approval for some other passkey doesn't carry over
+        let other = VerifiedPasskey { public_key: phone.public_key(), ..verified.clone() };
+        let other_request = enrollment.approval_request(&account, &other);
+        let wrong = other_request.complete(response(phone.sign(&other_request.challenge)), unix_timestamp()).unwrap();
+        let target = EnrollmentTarget::ExistingAccount { address, approval: &wrong, name: "Laptop" };
+        assert!(matches!(enrollment.enroll(&verified, proof, target, &owner), Err(EnrollmentError::WrongApproval)));
+        assert!(backend.sent_transactions().is_empty());
+    }
+}
@@ -0,0 +1,2088 @@
+//! Instruction builders for the Attesta program
+//!
+//! The SDK doesn't link against the program crate, so instructions are built
+//! by hand the same way Anchor builds them: an 8-byte discriminator followed
+//! by the Borsh-encoded instruction arguments. `initialize`, `execute` and
+//! `update_policy` data comes from `attesta_types::instructions`, which
+//! services without `anchor-client` use as well.
+
+use attesta_types::consts::{ACCOUNT_DISCRIMINATOR_LEN, P256_PUBKEY_LEN};
+use attesta_types::instructions::{
+    encode_execute, encode_initialize, encode_update_policy, ExecuteArgs, InitializeArgs, InstructionDataError,
+    UpdatePolicyArgs,
+};
+use borsh::BorshSerialize;
+use sha2::{Digest, Sha256};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use core_crypto::WebAuthnSignature;
+use recovery::{EncryptedBackup, Policy};
+use smart_account::{check_memo, find_announcement_address, find_proposal_address, transaction_message_hash, AccountSettings, AuthMode, CancelReason, ClaimTicket, InheritanceConfig, TokenTransfer, TransactionRequest, MEMO_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use crate::balances::derive_associated_token_address;
+use crate::signing::ProofEnvelope;
+
+/// Compute units to request for `initialize` and `sponsored_initialize`,
+/// which verify the passkey's P-256 signature
+pub const INITIALIZE_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Compute units to request for `add_passkey` with a registration proof,
+/// which verifies two P-256 signatures
+pub const ADD_PASSKEY_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Computes the Anchor discriminator for an instruction
+///
+/// Anchor identifies instructions by the first 8 bytes of
+/// `sha256("global:<instruction_name>")`.
+pub fn instruction_discriminator(name: &str) -> [u8; 8] {
+    attesta_types::instructions::instruction_discriminator(name)
+}
+
+/// Computes the Anchor discriminator for an account type
+///
+/// Anchor prefixes account data with the first 8 bytes of
+/// `sha256("account:<AccountTypeName>")`.
+pub fn account_discriminator(name: &str) -> [u8; ACCOUNT_DISCRIMINATOR_LEN] {
+    discriminator("account", name)
+}
+
+/// Computes the Anchor discriminator for an event
+///
+/// Anchor logs an emitted event as `Program data: ` and the base64 of the
+/// first 8 bytes of `sha256("event:<EventName>")`, then the Borsh-encoded event.
+pub fn event_discriminator(name: &str) -> [u8; 8] {
+    discriminator("event", name)
+}
+
+fn discriminator(namespace: &str, name: &str) -> [u8; 8] {
+    let hash = Sha256::digest(format!("{}:{}", namespace, name).as_bytes());
+    let mut out = [0u8; 8];
+    out.copy_from_slice(&hash[..8]);
+    out
+}
+
+/// Builds Anchor instruction data: discriminator + Borsh-encoded arguments
+fn instruction_data<T: BorshSerialize>(name: &str, args: &T) -> Result<Vec<u8>, std::io::Error> {
+    let mut data = instruction_discriminator(name).to_vec();
+    args.serialize(&mut data)?;
+    Ok(data)
+}
+
+fn encoding_error(error: InstructionDataError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, error)
+}
+
+/// Builds an `execute` instruction from a completed signing request
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `authority`: Whoever submits the transaction (signer). An account
+///   that lists executors only accepts one of them here.
+/// - `envelope`: The proof returned by `SigningRequest::complete`
+/// - `transaction_data`: The transaction data that was signed
+///
+/// Fails with `ErrorKind::InvalidInput` if `transaction_data` is over
+/// `MAX_TRANSACTION_DATA_LEN`, or the envelope's memo isn't one the program
+/// accepts, since the program would reject it.
+///
+/// With `envelope.emit_memo`, the SPL Memo program is passed so the program
+/// posts the memo through it.
+///
+/// The transaction's proposal PDA, the system program and the announcement
+/// PDA for the envelope's nonce are always passed, and `authority` is
+/// writable: if the transaction needs approvals, or a vault holds it back,
+/// the program creates the proposal or announcement with `authority`
+/// paying its rent.
+pub fn execute(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    authority: &Pubkey,
+    envelope: &ProofEnvelope,
+    transaction_data: Vec<u8>,
+) -> Result<Instruction, std::io::Error> {
+    TransactionRequest::from_bytes(&transaction_data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    check_memo(&envelope.memo).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let (proposal, _) = derive_proposal_address(program_id, attesta_account, &transaction_message_hash(&transaction_data));
+    let data = encode_execute(&ExecuteArgs {
+        webauthn_sig: envelope.webauthn_sig.to_bytes(),
+        nonce: envelope.nonce,
+        message_hash: envelope.message_hash,
+        transaction_data,
+        idempotency_key: Some(envelope.idempotency_key),
+        memo: envelope.memo.clone(),
+        emit_memo: envelope.emit_memo,
+    })
+    .map_err(encoding_error)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(*authority, true),
+            // Anchor reads the program ID in an optional account's slot as "none"
+            AccountMeta::new_readonly(envelope.parent_account.unwrap_or(*program_id), false),
+            if envelope.logs_proofs {
+                AccountMeta::new(derive_proof_log_address(program_id, attesta_account).0, false)
+            } else {
+                AccountMeta::new_readonly(*program_id, false)
+            },
+            AccountMeta::new_readonly(if envelope.emit_memo { MEMO_PROGRAM_ID } else { *program_id }, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(derive_announcement_address(program_id, attesta_account, envelope.nonce).0, false),
+        ],
+        data,
+    })
+}
+
+/// Builds an `execute` instruction for an SPL token transfer out of the account
+///
+/// Adds the token accounts the program needs to make the transfer. The
+/// tokens come from the Attesta account's associated token account.
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account (owns the source tokens)
+/// - `authority`: Whoever submits the transaction (signer)
+/// - `envelope`: The proof for `TransactionRequest::from_token_transfer(transfer)`
+/// - `transfer`: The transfer that was signed
+pub fn execute_token_transfer(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    authority: &Pubkey,
+    envelope: &ProofEnvelope,
+    transfer: &TokenTransfer,
+) -> Result<Instruction, std::io::Error> {
+    let mut instruction = execute(
+        program_id,
+        attesta_account,
+        authority,
+        envelope,
+        transfer.to_transaction_data(),
+    )?;
+
+    instruction.accounts.extend([
+        AccountMeta::new(derive_associated_token_address(attesta_account, &transfer.mint), false),
+        AccountMeta::new_readonly(transfer.mint, false),
+        AccountMeta::new(transfer.destination_ata, false),
+        AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+    ]);
+
+    Ok(instruction)
+}
+
+/// Builds an `execute_as_owner` instruction, signed by the owner's wallet instead of a passkey
+///
+/// Only for accounts whose `AccountSettings::auth_mode` allows the owner.
+/// For a token transfer, append the accounts `execute_token_transfer` adds.
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer)
+/// - `parent_account`: The parent account, for a sub-account
+/// - `nonce`: The account's next nonce
+/// - `transaction_data`: The transaction data to execute
+pub fn execute_as_owner(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    parent_account: Option<Pubkey>,
+    nonce: u64,
+    transaction_data: Vec<u8>,
+) -> Result<Instruction, std::io::Error> {
+    TransactionRequest::from_bytes(&transaction_data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let data = instruction_data("execute_as_owner", &(nonce, transaction_data))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(parent_account.unwrap_or(*program_id), false),
+        ],
+        data,
+    })
+}
+
+/// Derives the Attesta account address for an owner
+///
+/// # Returns
+/// The account address and its bump seed
+pub fn derive_attesta_address(program_id: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"attesta", owner.as_ref()], program_id)
+}
+
+/// Builds an `initialize` instruction, creating `owner`'s Attesta account
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `owner`: The wallet the account belongs to (signer, pays its rent)
+/// - `passkey_public_key`, `credential_id`: The account's first passkey
+/// - `policy`: The account's policy (`None` for an open account)
+/// - `privacy_mode`: Store only the hash of the credential ID
+/// - `registration`: The passkey's signature over its `RegistrationRequest`
+/// - `aaguid_allowlist`: Authenticator models allowed to enroll (empty for any)
+#[allow(clippy::too_many_arguments)]
+pub fn initialize(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Option<&Policy>,
+    privacy_mode: bool,
+    registration: &WebAuthnSignature,
+    aaguid_allowlist: Vec<[u8; 16]>,
+) -> Result<Instruction, std::io::Error> {
+    let (attesta_account, _) = derive_attesta_address(program_id, owner);
+    let args = initialize_args(passkey_public_key, credential_id, policy, privacy_mode, registration, aaguid_allowlist)?;
+    let data = encode_initialize(&args).map_err(encoding_error)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(attesta_account, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Derives the address of `owner`'s Attesta account at `account_index`
+///
+/// # Returns
+/// The account address and its bump seed
+pub fn derive_attesta_address_by_index(program_id: &Pubkey, owner: &Pubkey, account_index: u16) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"attesta", owner.as_ref(), &account_index.to_le_bytes()], program_id)
+}
+
+/// Builds an `initialize_with_index` instruction, creating `owner`'s Attesta
+/// account at `account_index`
+///
+/// Takes the same arguments as `initialize`. The account is created at
+/// `derive_attesta_address_by_index`, and the instruction fails if that
+/// index is already taken; `AttestaClient::next_free_index` finds one that isn't.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_with_index(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    account_index: u16,
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Option<&Policy>,
+    privacy_mode: bool,
+    registration: &WebAuthnSignature,
+    aaguid_allowlist: Vec<[u8; 16]>,
+) -> Result<Instruction, std::io::Error> {
+    let (attesta_account, _) = derive_attesta_address_by_index(program_id, owner, account_index);
+    let args = initialize_args(passkey_public_key, credential_id, policy, privacy_mode, registration, aaguid_allowlist)?;
+    let data = instruction_data("initialize_with_index", &(account_index, args))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(attesta_account, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Derives the address of the sponsor pool `authority` controls
+///
+/// # Returns
+/// The pool address and its bump seed
+pub fn derive_sponsor_pool_address(program_id: &Pubkey, authority: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"sponsor_pool", authority.as_ref()], program_id)
+}
+
+/// Builds a `create_sponsor_pool` instruction
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `authority`: Who controls the pool (signer, pays the pool's rent)
+/// - `max_accounts_per_day`: Accounts the pool sponsors per UTC day at most
+/// - `max_lamports_per_account`: Rent the pool pays for one account at most
+pub fn create_sponsor_pool(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    max_accounts_per_day: u32,
+    max_lamports_per_account: u64,
+) -> Result<Instruction, std::io::Error> {
+    let (sponsor_pool, _) = derive_sponsor_pool_address(program_id, authority);
+    let data = instruction_data("create_sponsor_pool", &(max_accounts_per_day, max_lamports_per_account))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(sponsor_pool, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Builds a `sponsored_initialize` instruction, creating `owner`'s Attesta
+/// account with its rent paid by `sponsor_pool`
+///
+/// Takes the same arguments as `initialize`. `owner` signs but pays nothing;
+/// the transaction's fee payer can be anyone, typically a relayer.
+#[allow(clippy::too_many_arguments)]
+pub fn sponsored_initialize(
+    program_id: &Pubkey,
+    sponsor_pool: &Pubkey,
+    owner: &Pubkey,
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Option<&Policy>,
+    privacy_mode: bool,
+    registration: &WebAuthnSignature,
+    aaguid_allowlist: Vec<[u8; 16]>,
+) -> Result<Instruction, std::io::Error> {
+    let (attesta_account, _) = derive_attesta_address(program_id, owner);
+    // Same arguments as `initialize`
+    let args = initialize_args(passkey_public_key, credential_id, policy, privacy_mode, registration, aaguid_allowlist)?;
+    let data = instruction_data("sponsored_initialize", &args)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*sponsor_pool, false),
+            AccountMeta::new(attesta_account, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Builds a `withdraw_pool` instruction taking `amount` lamports out of the
+/// pool `authority` controls
+pub fn withdraw_pool(program_id: &Pubkey, authority: &Pubkey, amount: u64) -> Result<Instruction, std::io::Error> {
+    let (sponsor_pool, _) = derive_sponsor_pool_address(program_id, authority);
+    let data = instruction_data("withdraw_pool", &(amount,))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new(sponsor_pool, false), AccountMeta::new(*authority, true)],
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn initialize_args(
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Option<&Policy>,
+    privacy_mode: bool,
+    registration: &WebAuthnSignature,
+    aaguid_allowlist: Vec<[u8; 16]>,
+) -> Result<InitializeArgs, std::io::Error> {
+    let policy = match policy {
+        Some(policy) => policy.to_bytes()?,
+        None => Vec::new(),
+    };
+    Ok(InitializeArgs {
+        passkey_public_key,
+        credential_id,
+        policy,
+        privacy_mode,
+        registration_sig: registration.to_bytes(),
+        aaguid_allowlist,
+    })
+}
+
+/// Derives the address of a sub-account of `parent`
+///
+/// # Returns
+/// The sub-account address and its bump seed
+pub fn derive_sub_account_address(program_id: &Pubkey, parent: &Pubkey, index: u8) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"sub_account", parent.as_ref(), &[index]], program_id)
+}
+
+/// Builds an `initialize_sub_account` instruction
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `parent`: The parent Attesta account
+/// - `owner`: The parent's owner (signer, pays rent for the sub-account)
+/// - `index`: Which of the parent's sub-accounts to create
+/// - `webauthn_sig`: Signature from the parent's passkey over the
+///   `SUB_ACCOUNT_CREATE_ACTION` for `sub_account_payload(...)`
+/// - `nonce`: The parent's nonce that was signed
+/// - `passkey_public_key`, `credential_id`, `policy`: The sub-account's passkey and policy
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_sub_account(
+    program_id: &Pubkey,
+    parent: &Pubkey,
+    owner: &Pubkey,
+    index: u8,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    passkey_public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    policy: Vec<u8>,
+) -> Result<Instruction, std::io::Error> {
+    let (sub_account, _) = derive_sub_account_address(program_id, parent, index);
+    let data = instruction_data(
+        "initialize_sub_account",
+        &(index, webauthn_sig.to_bytes(), nonce, passkey_public_key, credential_id, policy),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*parent, false),
+            AccountMeta::new(sub_account, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Derives the backup escrow PDA for an Attesta account
+///
+/// # Returns
+/// The escrow address and its bump seed
+pub fn derive_backup_address(program_id: &Pubkey, attesta_account: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"backup", attesta_account.as_ref()], program_id)
+}
+
+/// Derives the proof log PDA for an Attesta account
+///
+/// # Returns
+/// The proof log address and its bump seed
+pub fn derive_proof_log_address(program_id: &Pubkey, attesta_account: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"proof_log", attesta_account.as_ref()], program_id)
+}
+
+/// Builds an `enable_proof_log` instruction that creates the account's proof log
+///
+/// From then on every execution must pass the proof log, which
+/// `instructions::execute` does for envelopes with `logs_proofs` set.
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays rent for the log)
+/// - `webauthn_sig`: Passkey signature over the `PROOF_LOG_ENABLE_ACTION` for
+///   the proof log address
+/// - `nonce`: The nonce that was signed
+pub fn enable_proof_log(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let (proof_log_address, _) = derive_proof_log_address(program_id, attesta_account);
+    let data = instruction_data("enable_proof_log", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(proof_log_address, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Builds a `store_backup` instruction that creates the backup escrow
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays rent for the escrow)
+/// - `webauthn_sig`: Passkey signature over the `BACKUP_WRITE_ACTION` for `backup`
+/// - `nonce`: The nonce that was signed
+/// - `backup`: The encrypted backup to store
+pub fn store_backup(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    backup: &EncryptedBackup,
+) -> Result<Instruction, std::io::Error> {
+    let (backup_address, _) = derive_backup_address(program_id, attesta_account);
+    let data = instruction_data(
+        "store_backup",
+        &(webauthn_sig.to_bytes(), nonce, backup.to_bytes()?),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(backup_address, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Builds an `update_backup` instruction that overwrites the backup escrow
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `webauthn_sig`: Passkey signature over the `BACKUP_WRITE_ACTION` for `backup`
+/// - `nonce`: The nonce that was signed
+/// - `backup`: The new encrypted backup
+pub fn update_backup(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    backup: &EncryptedBackup,
+) -> Result<Instruction, std::io::Error> {
+    let (backup_address, _) = derive_backup_address(program_id, attesta_account);
+    let data = instruction_data(
+        "update_backup",
+        &(webauthn_sig.to_bytes(), nonce, backup.to_bytes()?),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(backup_address, false),
+        ],
+        data,
+    })
+}
+
+/// Builds a `delete_backup` instruction that closes the escrow and refunds its rent
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (receives the rent)
+/// - `webauthn_sig`: Passkey signature over the `BACKUP_DELETE_ACTION` for the escrow address
+/// - `nonce`: The nonce that was signed
+pub fn delete_backup(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let (backup_address, _) = derive_backup_address(program_id, attesta_account);
+    let data = instruction_data("delete_backup", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(backup_address, false),
+            AccountMeta::new(*owner, false),
+        ],
+        data,
+    })
+}
+
+/// Builds an `add_passkey` instruction that registers another passkey
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays for any extra space)
+/// - `webauthn_sig`: Signature from an existing passkey over the `PASSKEY_ADD_ACTION`
+///   for `public_key || credential_id`
+/// - `nonce`: The nonce that was signed
+/// - `public_key`: The new passkey's public key
+/// - `credential_id`: The new passkey's credential ID
+/// - `name`: A label for the new passkey
+/// - `registration`: The new passkey's signature over its registration
+///   challenge (see `signing::RegistrationRequest`); required if the account
+///   has an AAGUID allowlist
+/// - `expected_version`: The account's `state_version` as last read
+#[allow(clippy::too_many_arguments)]
+pub fn add_passkey(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    public_key: [u8; P256_PUBKEY_LEN],
+    credential_id: Vec<u8>,
+    name: String,
+    registration: Option<&WebAuthnSignature>,
+    expected_version: u64,
+) -> Result<Instruction, std::io::Error> {
+    let registration_sig = registration.map(WebAuthnSignature::to_bytes).unwrap_or_default();
+    let data = instruction_data(
+        "add_passkey",
+        &(webauthn_sig.to_bytes(), nonce, public_key, credential_id, name, registration_sig, expected_version),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `remove_passkey` instruction that revokes a passkey
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer)
+/// - `webauthn_sig`: Signature from a remaining passkey over the `PASSKEY_REMOVE_ACTION`
+///   for `credential_id`
+/// - `nonce`: The nonce that was signed
+/// - `credential_id`: The credential ID of the passkey to revoke
+/// - `expected_version`: The account's `state_version` as last read
+pub fn remove_passkey(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    credential_id: Vec<u8>,
+    expected_version: u64,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data(
+        "remove_passkey",
+        &(webauthn_sig.to_bytes(), nonce, credential_id, expected_version),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds an `update_settings` instruction
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays for any extra space)
+/// - `webauthn_sig`: Passkey signature over the `SETTINGS_UPDATE_ACTION` for
+///   `settings.to_bytes()`
+/// - `nonce`: The nonce that was signed
+/// - `settings`: The new account settings
+/// - `expected_version`: The account's `state_version` as last read
+///
+/// If `settings` changes the WebAuthn profile or relying party, only the
+/// primary passkey's signature is accepted.
+pub fn update_settings(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    settings: &AccountSettings,
+    expected_version: u64,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data(
+        "update_settings",
+        &(
+            webauthn_sig.to_bytes(),
+            nonce,
+            settings.reject_zero_amount,
+            settings.reject_self_transfer,
+            settings.max_transaction_data_len,
+            settings.lockout_threshold,
+            settings.aaguid_allowlist.clone(),
+            settings.pinned_program_version,
+            settings.webauthn_profile.to_bits(),
+            settings.relying_party.map(|party| party.rp_id_hash),
+            settings.relying_party.map(|party| party.origin_hash),
+            settings.log_allowed_sample_rate,
+            settings.recovery_allowance,
+            expected_version,
+        ),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds an `update_policy` instruction
+///
+/// The program refuses policies that are malformed or too expensive to
+/// evaluate; check first with `Policy::validate_config` and
+/// `client::check_policy_cost`.
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer)
+/// - `policy`: The new policy, or `None` for no restrictions
+/// - `expected_version`: The account's `state_version` as last read; the
+///   update fails with `ConcurrentModification` if it has moved on
+pub fn update_policy(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    policy: Option<&Policy>,
+    expected_version: u64,
+) -> Result<Instruction, std::io::Error> {
+    let new_policy = match policy {
+        Some(policy) => policy.to_bytes()?,
+        None => Vec::new(),
+    };
+    let data = encode_update_policy(&UpdatePolicyArgs { new_policy, expected_version }).map_err(encoding_error)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data,
+    })
+}
+
+/// Builds an `initiate_recovery` instruction, starting the replacement of the primary passkey
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `payer`: Pays fees and any extra space (signer)
+/// - `webauthn_sig`: A guardian's signature over the `RECOVERY_INITIATE_ACTION`
+///   for the recovery request hash
+/// - `nonce`: The nonce that was signed
+/// - `new_public_key`: The replacement passkey's public key
+/// - `new_credential_id`: The replacement passkey's credential ID
+/// - `registration`: The replacement passkey's signature over its
+///   registration challenge; required if the account has an AAGUID allowlist
+#[allow(clippy::too_many_arguments)]
+pub fn initiate_recovery(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    payer: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    new_public_key: [u8; P256_PUBKEY_LEN],
+    new_credential_id: Vec<u8>,
+    registration: Option<&WebAuthnSignature>,
+) -> Result<Instruction, std::io::Error> {
+    let registration_sig = registration.map(WebAuthnSignature::to_bytes).unwrap_or_default();
+    let data = instruction_data(
+        "initiate_recovery",
+        &(webauthn_sig.to_bytes(), nonce, new_public_key, new_credential_id, registration_sig),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, payer),
+        data,
+    })
+}
+
+/// Builds an `initiate_recovery_drill` instruction
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `payer`: Pays fees and any extra space (signer)
+/// - `webauthn_sig`: A guardian's signature over the `RECOVERY_DRILL_INITIATE_ACTION`
+///   for the recovery request hash
+/// - `nonce`: The nonce that was signed
+/// - `new_public_key`: The rehearsed replacement passkey's public key
+/// - `new_credential_id`: The rehearsed replacement passkey's credential ID
+pub fn initiate_recovery_drill(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    payer: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    new_public_key: [u8; P256_PUBKEY_LEN],
+    new_credential_id: Vec<u8>,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data(
+        "initiate_recovery_drill",
+        &(webauthn_sig.to_bytes(), nonce, new_public_key, new_credential_id),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, payer),
+        data,
+    })
+}
+
+/// Builds an `approve_recovery_drill` instruction
+///
+/// `webauthn_sig` is a guardian's signature over the
+/// `RECOVERY_DRILL_APPROVE_ACTION` for the recovery request hash.
+pub fn approve_recovery_drill(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    payer: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("approve_recovery_drill", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, payer),
+        data,
+    })
+}
+
+/// Builds an `add_policy` instruction, inserting `policy` at `index`
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays for any extra space)
+/// - `webauthn_sig`: A signature over the `POLICY_ADD_ACTION` (or, with
+///   `allow_long_lock`, the `POLICY_ADD_LONG_LOCK_ACTION`) for
+///   `policy_change_payload(index, policy)`
+/// - `nonce`: The nonce that was signed
+/// - `index`: Where to insert it, at most the number of policies held
+/// - `policy`: The policy to add
+/// - `allow_long_lock`: Whether its time lock may be more than
+///   `MAX_FUTURE_SECONDS` away
+#[allow(clippy::too_many_arguments)]
+pub fn add_policy(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    index: u8,
+    policy: &Policy,
+    allow_long_lock: bool,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data(
+        "add_policy",
+        &(webauthn_sig.to_bytes(), nonce, index, policy.to_bytes()?, allow_long_lock),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `remove_policy` instruction
+///
+/// `webauthn_sig` is a signature over the `POLICY_REMOVE_ACTION` for
+/// `policy_change_payload(index, &[])`.
+pub fn remove_policy(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    index: u8,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("remove_policy", &(webauthn_sig.to_bytes(), nonce, index))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `replace_policy` instruction
+///
+/// `webauthn_sig` is a signature over the `POLICY_REPLACE_ACTION` (or the
+/// `POLICY_REPLACE_LONG_LOCK_ACTION`, with `allow_long_lock`) for
+/// `policy_change_payload(index, policy)`.
+#[allow(clippy::too_many_arguments)]
+pub fn replace_policy(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    index: u8,
+    policy: &Policy,
+    allow_long_lock: bool,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data(
+        "replace_policy",
+        &(webauthn_sig.to_bytes(), nonce, index, policy.to_bytes()?, allow_long_lock),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `configure_inheritance` instruction
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays for any extra space)
+/// - `webauthn_sig`: A signature over the `INHERITANCE_CONFIGURE_ACTION` for
+///   `config.to_bytes()`, or for an empty payload when removing it
+/// - `nonce`: The nonce that was signed
+/// - `config`: The new config, or `None` to remove it
+pub fn configure_inheritance(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    config: Option<&InheritanceConfig>,
+) -> Result<Instruction, std::io::Error> {
+    let config = match config {
+        Some(config) => config.to_bytes()?,
+        None => Vec::new(),
+    };
+    let data = instruction_data("configure_inheritance", &(webauthn_sig.to_bytes(), nonce, config))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `heartbeat` instruction
+///
+/// `webauthn_sig` is a signature over the `HEARTBEAT_ACTION` with an empty payload.
+pub fn heartbeat(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    payer: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("heartbeat", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, payer),
+        data,
+    })
+}
+
+/// Builds a `get_program_version` instruction, to simulate for the deployed version
+pub fn get_program_version(program_id: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![],
+        data: instruction_discriminator("get_program_version").to_vec(),
+    }
+}
+
+/// Builds a `get_program_features` instruction, to simulate for the features built in
+pub fn get_program_features(program_id: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![],
+        data: instruction_discriminator("get_program_features").to_vec(),
+    }
+}
+
+/// Builds a `get_protocol_constants` instruction, to simulate for the program's limits
+pub fn get_protocol_constants(program_id: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![],
+        data: instruction_discriminator("get_protocol_constants").to_vec(),
+    }
+}
+
+/// Builds an `acknowledge_upgrade` instruction
+///
+/// `webauthn_sig` is a signature over the `UPGRADE_ACKNOWLEDGE_ACTION` for the
+/// deployed version's hash (see `AttestaClient::upgrade_acknowledgment_message_hash`).
+pub fn acknowledge_upgrade(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("acknowledge_upgrade", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `set_auth_mode` instruction
+///
+/// `webauthn_sig` is the primary passkey's signature over `AUTH_MODE_ACTION`
+/// for `auth_mode_payload(mode, lock)` (see `AttestaClient::auth_mode_message_hash`).
+pub fn set_auth_mode(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    mode: AuthMode,
+    lock: bool,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("set_auth_mode", &(webauthn_sig.to_bytes(), nonce, mode as u8, lock))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds an `add_executor` instruction
+///
+/// `webauthn_sig` is the primary passkey's signature over `EXECUTOR_ADD_ACTION`
+/// for the executor's address (see `AttestaClient::add_executor_message_hash`).
+pub fn add_executor(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    executor: &Pubkey,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("add_executor", &(webauthn_sig.to_bytes(), nonce, *executor))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `remove_executor` instruction
+///
+/// `webauthn_sig` is the primary passkey's signature over `EXECUTOR_REMOVE_ACTION`
+/// for the executor's address (see `AttestaClient::remove_executor_message_hash`).
+pub fn remove_executor(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    executor: &Pubkey,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("remove_executor", &(webauthn_sig.to_bytes(), nonce, *executor))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds a `configure_emergency_override` instruction
+///
+/// `webauthn_sig` is the primary passkey's signature over the limits (see
+/// `AttestaClient::emergency_configure_message_hash`).
+pub fn configure_emergency_override(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    max_lamports: u64,
+    period_seconds: u32,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data(
+        "configure_emergency_override",
+        &(webauthn_sig.to_bytes(), nonce, max_lamports, period_seconds),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, owner),
+        data,
+    })
+}
+
+/// Builds an `execute_emergency` instruction
+///
+/// Both signatures are over `emergency_message_hash(&transaction_data)` at
+/// `nonce`: `admin_sig` from the primary passkey, `cosigner_sig` from another
+/// enabled one. A token transfer gets the token accounts
+/// `execute_token_transfer` adds.
+pub fn execute_emergency(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    authority: &Pubkey,
+    admin_sig: &WebAuthnSignature,
+    cosigner_sig: &WebAuthnSignature,
+    nonce: u64,
+    transaction_data: Vec<u8>,
+) -> Result<Instruction, std::io::Error> {
+    TransactionRequest::from_bytes(&transaction_data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let mut accounts = vec![
+        AccountMeta::new(*attesta_account, false),
+        AccountMeta::new_readonly(*authority, true),
+    ];
+    if let Some(transfer) = TokenTransfer::from_transaction_data(&transaction_data) {
+        accounts.extend([
+            AccountMeta::new(derive_associated_token_address(attesta_account, &transfer.mint), false),
+            AccountMeta::new_readonly(transfer.mint, false),
+            AccountMeta::new(transfer.destination_ata, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ]);
+    }
+    let data = instruction_data(
+        "execute_emergency",
+        &(admin_sig.to_bytes(), cosigner_sig.to_bytes(), nonce, transaction_data),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Derives the schedule PDA for the transaction an account scheduled with `nonce`
+///
+/// # Returns
+/// The schedule address and its bump seed
+pub fn derive_schedule_address(program_id: &Pubkey, attesta_account: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"schedule", attesta_account.as_ref(), &nonce.to_le_bytes()], program_id)
+}
+
+/// Builds a `schedule_transaction` instruction that stores a transaction for later
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays rent for the schedule)
+/// - `webauthn_sig`: Passkey signature over the `SCHEDULE_ACTION` for
+///   `schedule_payload(transaction_data, executable_after, executable_before)`
+/// - `nonce`: The nonce that was signed (it also keys the schedule PDA)
+/// - `transaction_data`: The transaction to execute later
+/// - `executable_after`, `executable_before`: The window it may execute in
+#[allow(clippy::too_many_arguments)]
+pub fn schedule_transaction(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    transaction_data: Vec<u8>,
+    executable_after: i64,
+    executable_before: Option<i64>,
+) -> Result<Instruction, std::io::Error> {
+    TransactionRequest::from_bytes(&transaction_data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let (schedule_address, _) = derive_schedule_address(program_id, attesta_account, nonce);
+    let data = instruction_data(
+        "schedule_transaction",
+        &(webauthn_sig.to_bytes(), nonce, transaction_data, executable_after, executable_before),
+    )?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(schedule_address, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Derives the PDA of the policy attestation an account made with `nonce`
+///
+/// # Returns
+/// The attestation address and its bump seed
+pub fn derive_policy_attestation_address(program_id: &Pubkey, attesta_account: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"policy_attestation", attesta_account.as_ref(), &nonce.to_le_bytes()], program_id)
+}
+
+/// Builds an `attest_policy` instruction that records the account's current policy
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (signer, pays rent for the attestation)
+/// - `webauthn_sig`: Passkey signature over the `POLICY_ATTEST_ACTION` for
+///   `attestation_payload(policy_hash, expires_at)`
+/// - `nonce`: The nonce that was signed (it also keys the attestation PDA)
+/// - `expires_at`: When the attestation lapses (Unix timestamp)
+pub fn attest_policy(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    expires_at: i64,
+) -> Result<Instruction, std::io::Error> {
+    let (attestation_address, _) = derive_policy_attestation_address(program_id, attesta_account, nonce);
+    let data = instruction_data("attest_policy", &(webauthn_sig.to_bytes(), nonce, expires_at))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(attestation_address, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// Builds an `execute_scheduled` instruction (anyone can submit it)
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The account the transaction runs from
+/// - `owner`: The account owner (receives the schedule's rent)
+/// - `nonce`: The nonce the transaction was scheduled with
+/// - `parent_account`: The parent account, if `attesta_account` is a sub-account
+/// - `logs_proofs`: Whether the account has enabled its proof log
+/// - `transfer`: The scheduled token transfer, if it is one, for its token accounts
+pub fn execute_scheduled(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    nonce: u64,
+    parent_account: Option<&Pubkey>,
+    logs_proofs: bool,
+    transfer: Option<&TokenTransfer>,
+) -> Instruction {
+    let (schedule_address, _) = derive_schedule_address(program_id, attesta_account, nonce);
+    let mut accounts = vec![
+        AccountMeta::new(*attesta_account, false),
+        AccountMeta::new(schedule_address, false),
+        AccountMeta::new(*owner, false),
+        AccountMeta::new_readonly(*parent_account.unwrap_or(program_id), false),
+        if logs_proofs {
+            AccountMeta::new(derive_proof_log_address(program_id, attesta_account).0, false)
+        } else {
+            AccountMeta::new_readonly(*program_id, false)
+        },
+    ];
+    if let Some(transfer) = transfer {
+        accounts.extend([
+            AccountMeta::new(derive_associated_token_address(attesta_account, &transfer.mint), false),
+            AccountMeta::new_readonly(transfer.mint, false),
+            AccountMeta::new(transfer.destination_ata, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ]);
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: instruction_discriminator("execute_scheduled").to_vec(),
+    }
+}
+
+/// Builds a `cancel_scheduled` instruction that closes a schedule and refunds its rent
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `owner`: The account owner (receives the rent)
+/// - `scheduled_nonce`: The nonce the transaction was scheduled with
+/// - `webauthn_sig`: Passkey signature over the `SCHEDULE_CANCEL_ACTION` for the schedule address
+/// - `nonce`: The nonce that was signed
+pub fn cancel_scheduled(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    owner: &Pubkey,
+    scheduled_nonce: u64,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let (schedule_address, _) = derive_schedule_address(program_id, attesta_account, scheduled_nonce);
+    let data = instruction_data("cancel_scheduled", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(schedule_address, false),
+            AccountMeta::new(*owner, false),
+        ],
+        data,
+    })
+}
+
+/// Derives the proposal PDA `execute` creates for a transaction that needs approvals
+///
+/// # Parameters
+/// - `message_hash`: `transaction_message_hash` of the transaction data
+///
+/// # Returns
+/// The proposal address and its bump seed
+pub fn derive_proposal_address(program_id: &Pubkey, attesta_account: &Pubkey, message_hash: &[u8; 32]) -> (Pubkey, u8) {
+    find_proposal_address(program_id, attesta_account, message_hash)
+}
+
+/// Builds an `approve_proposal` instruction adding a passkey's approval
+///
+/// Anyone can submit it; the transaction's fee payer needn't be listed.
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `proposal`: The proposal PDA
+/// - `webauthn_sig`: The approving passkey's signature over `PROPOSAL_APPROVE_ACTION`
+///   for `approve_proposal_payload(proposal, message_hash)`
+/// - `nonce`: The nonce that was signed
+pub fn approve_proposal(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    proposal: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("approve_proposal", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new(*attesta_account, false), AccountMeta::new(*proposal, false)],
+        data,
+    })
+}
+
+/// Builds a `cancel_proposal` instruction that withdraws a proposed transaction
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The user's Attesta account
+/// - `proposal`: The proposal PDA to close
+/// - `rent_payer`: Who paid for the proposal (receives the rent; must match the proposal)
+/// - `webauthn_sig`: The proposer's or the primary passkey's signature over
+///   `PROPOSAL_CANCEL_ACTION` for `cancel_proposal_payload(proposal, reason)`
+/// - `nonce`: The nonce that was signed
+/// - `reason`: Why it's cancelled, as signed
+pub fn cancel_proposal(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    proposal: &Pubkey,
+    rent_payer: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+    reason: CancelReason,
+) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("cancel_proposal", &(webauthn_sig.to_bytes(), nonce, reason as u8))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(*proposal, false),
+            AccountMeta::new(*rent_payer, false),
+        ],
+        data,
+    })
+}
+
+/// Derives the announcement PDA `execute` creates for a transfer a vault holds back
+///
+/// # Parameters
+/// - `nonce`: The nonce of the proof that announced it
+///
+/// # Returns
+/// The announcement address and its bump seed
+pub fn derive_announcement_address(program_id: &Pubkey, attesta_account: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    find_announcement_address(program_id, attesta_account, nonce)
+}
+
+/// Builds an `execute_announced` instruction running a transfer a vault held back
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `attesta_account`: The account the transfer runs from
+/// - `authority`: Whoever submits it (signs if the account lists executors)
+/// - `rent_payer`: Who paid for the announcement (`authority` of the `execute`
+///   that announced it); the reclaimed rent goes back to them
+/// - `envelope`: The proof the transfer was announced with
+/// - `transfer`: The announced transfer, for its token accounts
+pub fn execute_announced(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    authority: &Pubkey,
+    rent_payer: &Pubkey,
+    envelope: &ProofEnvelope,
+    transfer: Option<&TokenTransfer>,
+) -> Result<Instruction, std::io::Error> {
+    let (announcement, _) = derive_announcement_address(program_id, attesta_account, envelope.nonce);
+    let data = instruction_data(
+        "execute_announced",
+        &(envelope.webauthn_sig.to_bytes(), envelope.nonce, envelope.message_hash),
+    )?;
+    let mut accounts = vec![
+        AccountMeta::new(*attesta_account, false),
+        AccountMeta::new(announcement, false),
+        AccountMeta::new(*rent_payer, false),
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new_readonly(envelope.parent_account.unwrap_or(*program_id), false),
+        if envelope.logs_proofs {
+            AccountMeta::new(derive_proof_log_address(program_id, attesta_account).0, false)
+        } else {
+            AccountMeta::new_readonly(*program_id, false)
+        },
+    ];
+    if let Some(transfer) = transfer {
+        accounts.extend([
+            AccountMeta::new(derive_associated_token_address(attesta_account, &transfer.mint), false),
+            AccountMeta::new_readonly(transfer.mint, false),
+            AccountMeta::new(transfer.destination_ata, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ]);
+    }
+
+    Ok(Instruction { program_id: *program_id, accounts, data })
+}
+
+/// Builds a `veto_announcement` instruction stopping an announced transfer
+///
+/// # Parameters
+/// - `announced_nonce`: The nonce the transfer was announced with
+/// - `rent_payer`: Who paid for the announcement (receives the reclaimed rent)
+/// - `webauthn_sig`: Any enabled passkey's signature over
+///   `ANNOUNCEMENT_VETO_ACTION` for the announcement's address
+/// - `nonce`: The nonce for this authorization
+pub fn veto_announcement(
+    program_id: &Pubkey,
+    attesta_account: &Pubkey,
+    announced_nonce: u64,
+    rent_payer: &Pubkey,
+    webauthn_sig: &WebAuthnSignature,
+    nonce: u64,
+) -> Result<Instruction, std::io::Error> {
+    let (announcement, _) = derive_announcement_address(program_id, attesta_account, announced_nonce);
+    let data = instruction_data("veto_announcement", &(webauthn_sig.to_bytes(), nonce))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*attesta_account, false),
+            AccountMeta::new(announcement, false),
+            AccountMeta::new(*rent_payer, false),
+        ],
+        data,
+    })
+}
+
+/// Builds a `claim` instruction paying out a claim ticket
+///
+/// The transaction must be signed by the ticket's claim key.
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `ticket`: The signed ticket, as in the payment link
+/// - `destination`: Where the lamports go (the claimer chooses)
+pub fn claim(program_id: &Pubkey, ticket: &ClaimTicket, destination: &Pubkey) -> Result<Instruction, std::io::Error> {
+    let data = instruction_data("claim", &(ticket.to_bytes(),))?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(ticket.account, false),
+            AccountMeta::new_readonly(ticket.claim_key, true),
+            AccountMeta::new(*destination, false),
+        ],
+        data,
+    })
+}
+
+/// Builds a `claim_inheritance` instruction (anyone can submit it)
+pub fn claim_inheritance(program_id: &Pubkey, attesta_account: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: manage_passkeys_accounts(attesta_account, payer),
+        data: instruction_discriminator("claim_inheritance").to_vec(),
+    }
+}
+
+fn manage_passkeys_accounts(attesta_account: &Pubkey, owner: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*attesta_account, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::{RelyingParty, WebAuthnVerificationProfile};
+    use smart_account::MAX_TRANSACTION_DATA_LEN;
+
+    #[test]
+    fn test_instruction_discriminator_matches_anchor() {
+        // sha256("global:initialize")[..8], as listed in Anchor IDLs
+        assert_eq!(
+            instruction_discriminator("initialize"),
+            [175, 175, 109, 31, 13, 152, 155, 237]
+        );
+    }
+
+    /// What Anchor builds: its own sighash, then the arguments through its Borsh
+    fn anchor_instruction_data(name: &str, args: impl anchor_lang::AnchorSerialize) -> Vec<u8> {
+        let sighash = anchor_lang::solana_program::hash::hash(format!("global:{}", name).as_bytes());
+        let mut data = sighash.to_bytes()[..8].to_vec();
+        args.serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_shared_instruction_data_matches_anchor() {
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let registration = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+        let policy = Policy::time_locked(1_800_000_000);
+
+        let ix = initialize(&program_id, &owner, [5; 64], b"phone".to_vec(), Some(&policy), true, &registration, vec![[6; 16]])
+            .unwrap();
+        let expected = anchor_instruction_data(
+            "initialize",
+            ([5u8; 64], b"phone".to_vec(), policy.to_bytes().unwrap(), true, registration.to_bytes(), vec![[6u8; 16]]),
+        );
+        assert_eq!(ix.data, expected);
+        assert_eq!(attesta_types::instructions::decode_initialize(&ix.data).unwrap().aaguid_allowlist, vec![[6; 16]]);
+
+        let transaction_data = b"transfer".to_vec();
+        let envelope = ProofEnvelope {
+            webauthn_sig: registration.clone(),
+            nonce: 9,
+            message_hash: transaction_message_hash(&transaction_data),
+            idempotency_key: [7; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: b"order-1042".to_vec(),
+            emit_memo: true,
+        };
+        let ix = execute(&program_id, &Pubkey::new_unique(), &owner, &envelope, transaction_data.clone()).unwrap();
+        let expected = anchor_instruction_data(
+            "execute",
+            (
+                registration.to_bytes(),
+                9u64,
+                envelope.message_hash,
+                transaction_data,
+                Some([7u8; 16]),
+                b"order-1042".to_vec(),
+                true,
+            ),
+        );
+        assert_eq!(ix.data, expected);
+
+        let ix = update_policy(&program_id, &Pubkey::new_unique(), &owner, Some(&policy), 4).unwrap();
+        assert_eq!(ix.data, anchor_instruction_data("update_policy", (policy.to_bytes().unwrap(), 4u64)));
+        let ix = update_policy(&program_id, &Pubkey::new_unique(), &owner, None, 0).unwrap();
+        assert_eq!(ix.data, anchor_instruction_data("update_policy", (Vec::<u8>::new(), 0u64)));
+    }
+
+    #[test]
+    fn test_execute_passes_parent_or_placeholder() {
+        let program_id = Pubkey::new_unique();
+        let parent = Pubkey::new_unique();
+        let mut envelope = ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce: 1,
+            message_hash: [5; 32],
+            idempotency_key: [6; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        let ix = execute(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &envelope, vec![]).unwrap();
+        assert_eq!(ix.accounts[2].pubkey, program_id);
+
+        assert_eq!(ix.accounts[3].pubkey, program_id);
+
+        envelope.parent_account = Some(parent);
+        let ix = execute(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &envelope, vec![]).unwrap();
+        assert_eq!(ix.accounts[2].pubkey, parent);
+        assert!(!ix.accounts[2].is_writable);
+    }
+
+    #[test]
+    fn test_execute_passes_proof_log_when_enabled() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce: 1,
+            message_hash: [5; 32],
+            idempotency_key: [6; 16],
+            parent_account: None,
+            logs_proofs: true,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+
+        let ix = execute(&program_id, &attesta_account, &Pubkey::new_unique(), &envelope, vec![]).unwrap();
+        assert_eq!(ix.accounts[3].pubkey, derive_proof_log_address(&program_id, &attesta_account).0);
+        assert!(ix.accounts[3].is_writable);
+    }
+
+    #[test]
+    fn test_execute_passes_the_transactions_proposal() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce: 1,
+            message_hash: [5; 32],
+            idempotency_key: [6; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: b"rent".to_vec(),
+            emit_memo: false,
+        };
+
+        let ix = execute(&program_id, &attesta_account, &Pubkey::new_unique(), &envelope, vec![7; 3]).unwrap();
+        assert_eq!(ix.accounts.len(), 8);
+        // Found by the transaction alone, whatever memo was signed with it
+        let (proposal, _) = derive_proposal_address(&program_id, &attesta_account, &transaction_message_hash(&[7; 3]));
+        assert_eq!(ix.accounts[5].pubkey, proposal);
+        assert!(ix.accounts[5].is_writable && !ix.accounts[5].is_signer);
+        assert_eq!(ix.accounts[6].pubkey, system_program::id());
+        // and for a vault, the announcement keyed by the proof's nonce
+        assert_eq!(ix.accounts[7].pubkey, derive_announcement_address(&program_id, &attesta_account, 1).0);
+        assert!(ix.accounts[7].is_writable);
+        // The authority pays for the proposal
+        assert!(ix.accounts[1].is_signer && ix.accounts[1].is_writable);
+    }
+
+    #[test]
+    fn test_executor_instruction_layouts() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let executor = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = add_executor(&program_id, &Pubkey::new_unique(), &owner, &sig, 3, &executor).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("add_executor"));
+        assert!(ix.data.ends_with(&[[3, 0, 0, 0, 0, 0, 0, 0].as_slice(), executor.as_ref()].concat()));
+        assert_eq!(ix.accounts[1].pubkey, owner);
+        assert!(ix.accounts[1].is_signer);
+
+        let ix = remove_executor(&program_id, &Pubkey::new_unique(), &owner, &sig, 4, &executor).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("remove_executor"));
+        assert!(ix.data.ends_with(executor.as_ref()));
+        // The program checks the submitter's signature against the list, and
+        // it pays for any proposal the execution opens
+        let envelope = ProofEnvelope {
+            webauthn_sig: sig,
+            nonce: 1,
+            message_hash: [5; 32],
+            idempotency_key: [6; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+        let ix = execute(&program_id, &Pubkey::new_unique(), &executor, &envelope, vec![]).unwrap();
+        assert_eq!(ix.accounts[1].pubkey, executor);
+        assert!(ix.accounts[1].is_signer && ix.accounts[1].is_writable);
+    }
+
+    #[test]
+    fn test_emergency_instruction_layouts() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = configure_emergency_override(&program_id, &attesta_account, &owner, &sig, 3, 500, 86_400).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("configure_emergency_override"));
+        assert!(ix.data.ends_with(&[500u64.to_le_bytes().as_slice(), &86_400u32.to_le_bytes()].concat()));
+        assert!(ix.accounts[1].is_signer);
+
+        let transfer = TokenTransfer { mint: Pubkey::new_unique(), amount: 1, decimals: 6, destination_ata: Pubkey::new_unique() };
+        let authority = Pubkey::new_unique();
+        let ix = execute_emergency(&program_id, &attesta_account, &authority, &sig, &sig, 4, transfer.to_transaction_data()).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("execute_emergency"));
+        assert!(ix.data.ends_with(&transfer.to_transaction_data()));
+        assert_eq!(ix.accounts.len(), 6);
+        assert_eq!(ix.accounts[1].pubkey, authority);
+        assert_eq!(ix.accounts[4].pubkey, transfer.destination_ata);
+        let oversized = vec![0; MAX_TRANSACTION_DATA_LEN + 1];
+        assert!(execute_emergency(&program_id, &attesta_account, &authority, &sig, &sig, 4, oversized).is_err());
+    }
+
+    #[test]
+    fn test_execute_refuses_oversized_data() {
+        let program_id = Pubkey::new_unique();
+        let envelope = ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce: 1,
+            message_hash: [5; 32],
+            idempotency_key: [6; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        };
+        let build = |len| execute(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &envelope, vec![0; len]);
+
+        assert!(build(MAX_TRANSACTION_DATA_LEN).is_ok());
+        assert_eq!(build(MAX_TRANSACTION_DATA_LEN + 1).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_execute_passes_memo() {
+        let program_id = Pubkey::new_unique();
+        let mut envelope = ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce: 1,
+            message_hash: [5; 32],
+            idempotency_key: [6; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: b"order-1042".to_vec(),
+            emit_memo: false,
+        };
+
+        let ix = execute(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &envelope, vec![7]).unwrap();
+        assert_eq!(ix.accounts[4].pubkey, program_id);
+        let expected = (envelope.webauthn_sig.to_bytes(), 1u64, [5u8; 32], vec![7u8], Some([6u8; 16]), envelope.memo.clone(), false);
+        assert_eq!(ix.data[8..], borsh::to_vec(&expected).unwrap()[..]);
+
+        envelope.emit_memo = true;
+        let ix = execute(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &envelope, vec![7]).unwrap();
+        assert_eq!(ix.accounts[4], AccountMeta::new_readonly(MEMO_PROGRAM_ID, false));
+        assert_eq!(ix.data.last(), Some(&1));
+
+        envelope.memo = vec![b'm'; smart_account::MAX_MEMO_LEN + 1];
+        let error = execute(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &envelope, vec![7]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_store_backup_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+        let backup = EncryptedBackup::new(b"key", b"data", 100);
+
+        let ix = store_backup(&program_id, &attesta_account, &owner, &sig, 7, &backup).unwrap();
+
+        assert_eq!(ix.data[..8], instruction_discriminator("store_backup"));
+        assert_eq!(ix.accounts.len(), 4);
+        assert_eq!(ix.accounts[1].pubkey, derive_backup_address(&program_id, &attesta_account).0);
+        assert!(ix.accounts[2].is_signer);
+
+        // Arguments follow the discriminator in declaration order
+        let sig_bytes = sig.to_bytes();
+        let mut offset = 8;
+        let sig_len = u32::from_le_bytes(ix.data[offset..offset + 4].try_into().unwrap()) as usize;
+        assert_eq!(sig_len, sig_bytes.len());
+        offset += 4 + sig_len;
+        assert_eq!(u64::from_le_bytes(ix.data[offset..offset + 8].try_into().unwrap()), 7);
+        offset += 8 + 4;
+        assert_eq!(&ix.data[offset..], backup.to_bytes().unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_schedule_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = schedule_transaction(&program_id, &attesta_account, &Pubkey::new_unique(), &sig, 7, vec![5; 3], 100, Some(200))
+            .unwrap();
+        let schedule_address = derive_schedule_address(&program_id, &attesta_account, 7).0;
+        assert_eq!(ix.data[..8], instruction_discriminator("schedule_transaction"));
+        assert_eq!(ix.accounts[1].pubkey, schedule_address);
+        // transaction data, then the window: 100, then Some(200)
+        let window = [100i64.to_le_bytes().as_slice(), &[1], &200i64.to_le_bytes()].concat();
+        assert!(ix.data.ends_with(&[[3, 0, 0, 0, 5, 5, 5].as_slice(), &window].concat()));
+
+        let transfer = TokenTransfer { mint: Pubkey::new_unique(), amount: 1, decimals: 6, destination_ata: Pubkey::new_unique() };
+        let ix = execute_scheduled(&program_id, &attesta_account, &Pubkey::new_unique(), 7, None, false, Some(&transfer));
+        assert_eq!(ix.data, instruction_discriminator("execute_scheduled"));
+        assert_eq!(ix.accounts.len(), 9);
+        assert_eq!(ix.accounts[1].pubkey, schedule_address);
+        assert!(ix.accounts.iter().all(|meta| !meta.is_signer));
+
+        let ix = cancel_scheduled(&program_id, &attesta_account, &Pubkey::new_unique(), 7, &sig, 8).unwrap();
+        assert_eq!(ix.accounts[1].pubkey, schedule_address);
+    }
+
+    #[test]
+    fn test_attest_policy_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = attest_policy(&program_id, &attesta_account, &Pubkey::new_unique(), &sig, 7, 100).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("attest_policy"));
+        assert_eq!(ix.accounts[1].pubkey, derive_policy_attestation_address(&program_id, &attesta_account, 7).0);
+        assert!(ix.accounts[2].is_signer);
+        assert!(ix.data.ends_with(&[7u64.to_le_bytes(), 100i64.to_le_bytes()].concat()));
+    }
+
+    #[test]
+    fn test_initialize_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let registration = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = initialize(&program_id, &owner, [5; 64], vec![4; 16], None, false, &registration, vec![[6; 16]]).unwrap();
+
+        assert_eq!(ix.data[..8], instruction_discriminator("initialize"));
+        assert_eq!(ix.accounts[0].pubkey, derive_attesta_address(&program_id, &owner).0);
+        assert!(ix.accounts[1].is_signer && ix.accounts[1].is_writable);
+        assert_eq!(ix.data[8..72], [5; 64]);
+        // credential_id, then an empty policy and privacy mode off
+        assert_eq!(ix.data[72..76], 16u32.to_le_bytes());
+        assert_eq!(ix.data[92..97], [0, 0, 0, 0, 0]);
+        assert!(ix.data.ends_with(&[[1, 0, 0, 0].as_slice(), &[6; 16]].concat()));
+    }
+
+    #[test]
+    fn test_initialize_with_index_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let registration = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        // initialize's arguments behind the index, at the indexed address
+        let plain = initialize(&program_id, &owner, [5; 64], vec![4; 16], None, false, &registration, vec![]).unwrap();
+        let ix = initialize_with_index(&program_id, &owner, 3, [5; 64], vec![4; 16], None, false, &registration, vec![])
+            .unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("initialize_with_index"));
+        assert_eq!(ix.data[8..10], 3u16.to_le_bytes());
+        assert_eq!(ix.data[10..], plain.data[8..]);
+        assert_eq!(ix.accounts[0].pubkey, derive_attesta_address_by_index(&program_id, &owner, 3).0);
+        assert_ne!(ix.accounts[0].pubkey, plain.accounts[0].pubkey);
+        assert!(ix.accounts[1].is_signer && ix.accounts[1].is_writable);
+    }
+
+    #[test]
+    fn test_sponsor_pool_instruction_layouts() {
+        let program_id = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (pool, _) = derive_sponsor_pool_address(&program_id, &authority);
+        let registration = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let create = create_sponsor_pool(&program_id, &authority, 10, 3_000_000).unwrap();
+        assert_eq!(create.data[..8], instruction_discriminator("create_sponsor_pool"));
+        assert_eq!(create.data[8..], [10u32.to_le_bytes().as_slice(), &3_000_000u64.to_le_bytes()].concat());
+        assert_eq!(create.accounts[0].pubkey, pool);
+
+        // Same arguments as initialize; only the pool joins the accounts and
+        // the owner no longer pays
+        let plain = initialize(&program_id, &owner, [5; 64], vec![4; 16], None, false, &registration, vec![]).unwrap();
+        let sponsored =
+            sponsored_initialize(&program_id, &pool, &owner, [5; 64], vec![4; 16], None, false, &registration, vec![])
+                .unwrap();
+        assert_eq!(sponsored.data[..8], instruction_discriminator("sponsored_initialize"));
+        assert_eq!(sponsored.data[8..], plain.data[8..]);
+        assert_eq!(sponsored.accounts[0].pubkey, pool);
+        assert_eq!(sponsored.accounts[1].pubkey, plain.accounts[0].pubkey);
+        assert!(sponsored.accounts[2].is_signer && !sponsored.accounts[2].is_writable);
+
+        let withdraw = withdraw_pool(&program_id, &authority, 42).unwrap();
+        assert_eq!(withdraw.data[8..], 42u64.to_le_bytes());
+        assert_eq!(withdraw.accounts[0].pubkey, pool);
+        assert!(withdraw.accounts[1].is_signer);
+    }
+
+    #[test]
+    fn test_update_policy_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let policy = Policy::time_locked(1_800_000_000);
+
+        let ix = update_policy(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), Some(&policy), 7).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("update_policy"));
+        assert!(ix.accounts[1].is_signer && !ix.accounts[1].is_writable);
+        assert_eq!(ix.data[8..12], (policy.serialized_size() as u32).to_le_bytes());
+        let policy_end = 12 + policy.serialized_size();
+        assert_eq!(&ix.data[12..policy_end], policy.to_bytes().unwrap().as_slice());
+        assert_eq!(ix.data[policy_end..], 7u64.to_le_bytes());
+
+        let ix = update_policy(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), None, 0).unwrap();
+        assert_eq!(ix.data[8..], [[0, 0, 0, 0].as_slice(), &0u64.to_le_bytes()].concat());
+    }
+
+    #[test]
+    fn test_remove_passkey_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = remove_passkey(&program_id, &attesta_account, &owner, &sig, 3, b"laptop".to_vec(), 2).unwrap();
+
+        assert_eq!(ix.data[..8], instruction_discriminator("remove_passkey"));
+        assert!(ix.accounts[1].is_signer);
+        assert!(ix.data.ends_with(&[b"laptop".as_slice(), &2u64.to_le_bytes()].concat()));
+    }
+
+    #[test]
+    fn test_initiate_recovery_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let attesta_account = Pubkey::new_unique();
+        let payer = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = initiate_recovery(&program_id, &attesta_account, &payer, &sig, 4, [5; 64], b"new-phone".to_vec(), None).unwrap();
+
+        assert_eq!(ix.data[..8], instruction_discriminator("initiate_recovery"));
+        assert!(ix.accounts[1].is_signer);
+        // An empty registration, Borsh-encoded, is its length prefix alone
+        assert!(ix.data.ends_with(&[b"new-phone".as_slice(), &0u32.to_le_bytes()].concat()));
+    }
+
+    #[test]
+    fn test_configure_inheritance_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+        let config = InheritanceConfig::new([5; 64], b"spouse".to_vec(), 1_000, 10);
+        let config_bytes = config.to_bytes().unwrap();
+
+        let set = configure_inheritance(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, Some(&config)).unwrap();
+        assert_eq!(set.data[..8], instruction_discriminator("configure_inheritance"));
+        assert!(set.data.ends_with(&config_bytes));
+        let len_at = set.data.len() - config_bytes.len() - 4;
+        assert_eq!(set.data[len_at..len_at + 4], (config_bytes.len() as u32).to_le_bytes());
+
+        // Removing it sends an empty config
+        let clear = configure_inheritance(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, None).unwrap();
+        assert!(clear.data.ends_with(&0u32.to_le_bytes()));
+    }
+
+    #[test]
+    fn test_update_settings_sends_pinned_version_then_profile() {
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+        let mut settings = AccountSettings::default();
+        // Legacy profile, no RP ID hash, no origin hash, no sampling
+        let no_profile = [0, 0, 0, 0];
+
+        let unpinned = update_settings(&Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, &settings, 5).unwrap();
+        // No recovery allowance, then the version
+        let mut version = [0u8; 16];
+        version[8..].copy_from_slice(&5u64.to_le_bytes());
+        assert!(unpinned.data.ends_with(&[[0, 0, 0, 0, 0].as_slice(), &no_profile, &version].concat()));
+
+        settings.pinned_program_version = Some([9; 32]);
+        let pinned = update_settings(&Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, &settings, 5).unwrap();
+        assert_eq!(pinned.data.len(), unpinned.data.len() + 32);
+        assert!(pinned.data.ends_with(&[[1].as_slice(), &[9; 32], &no_profile, &version].concat()));
+
+        settings.webauthn_profile = WebAuthnVerificationProfile::standard();
+        settings.relying_party = Some(RelyingParty { rp_id_hash: [5; 32], origin_hash: [6; 32] });
+        let profiled = update_settings(&Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, &settings, 5).unwrap();
+        let expected = [[WebAuthnVerificationProfile::standard().to_bits(), 1].as_slice(), &[5; 32], &[1], &[6; 32], &[0], &version].concat();
+        assert!(profiled.data.ends_with(&expected));
+
+        settings.log_allowed_sample_rate = 25;
+        let sampled = update_settings(&Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, &settings, 5).unwrap();
+        assert!(sampled.data.ends_with(&[[25].as_slice(), &version].concat()));
+
+        settings.recovery_allowance = 7;
+        let allowance = update_settings(&Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, &settings, 5).unwrap();
+        assert!(allowance.data.ends_with(&[[25].as_slice(), &7u64.to_le_bytes(), &5u64.to_le_bytes()].concat()));
+    }
+
+    #[test]
+    fn test_auth_mode_instruction_layouts() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = set_auth_mode(&program_id, &Pubkey::new_unique(), &owner, &sig, 3, AuthMode::PasskeyOnly, true).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("set_auth_mode"));
+        assert!(ix.data.ends_with(&[[3, 0, 0, 0, 0, 0, 0, 0].as_slice(), &[0, 1]].concat()));
+
+        let ix = execute_as_owner(&program_id, &Pubkey::new_unique(), &owner, None, 4, b"data".to_vec()).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("execute_as_owner"));
+        assert_eq!(ix.data[8..], [[4, 0, 0, 0, 0, 0, 0, 0].as_slice(), &[4, 0, 0, 0], b"data"].concat());
+        assert_eq!(ix.accounts[1].pubkey, owner);
+        assert!(ix.accounts[1].is_signer && !ix.accounts[1].is_writable);
+        assert_eq!(ix.accounts[2].pubkey, program_id);
+
+        let parent = Pubkey::new_unique();
+        let ix = execute_as_owner(&program_id, &Pubkey::new_unique(), &owner, Some(parent), 4, vec![]).unwrap();
+        assert_eq!(ix.accounts[2].pubkey, parent);
+    }
+
+    #[test]
+    fn test_cancel_proposal_layout() {
+        let (proposal, relayer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+
+        let ix = cancel_proposal(&Pubkey::new_unique(), &Pubkey::new_unique(), &proposal, &relayer, &sig, 5, CancelReason::Rejected)
+            .unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("cancel_proposal"));
+        assert!(ix.data.ends_with(&[5, 0, 0, 0, 0, 0, 0, 0, 2]));
+        assert_eq!((ix.accounts[1].pubkey, ix.accounts[2].pubkey), (proposal, relayer));
+        assert!(ix.accounts.iter().all(|meta| meta.is_writable && !meta.is_signer));
+    }
+
+    #[test]
+    fn test_claim_layout() {
+        let ticket = ClaimTicket {
+            account: Pubkey::new_unique(),
+            claim_key: Pubkey::new_unique(),
+            amount: 7,
+            expires_at: 100,
+            nonce: 2,
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+        };
+        let destination = Pubkey::new_unique();
+
+        let ix = claim(&Pubkey::new_unique(), &ticket, &destination).unwrap();
+        assert_eq!(ix.data[..8], instruction_discriminator("claim"));
+        assert_eq!(ix.data[12..], ticket.to_bytes());
+        assert_eq!(ix.accounts[0].pubkey, ticket.account);
+        assert!(ix.accounts[1].is_signer && !ix.accounts[1].is_writable);
+        assert_eq!(ix.accounts[2].pubkey, destination);
+    }
+
+    #[test]
+    fn test_policy_list_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let sig = WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]);
+        let policy = Policy::spending_limit(recovery::Amount::from_lamports(5));
+        let policy_bytes = policy.to_bytes().unwrap();
+
+        let add = add_policy(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, 1, &policy, false).unwrap();
+        assert_eq!(add.data[..8], instruction_discriminator("add_policy"));
+        assert!(add.accounts[1].is_signer);
+        assert!(add.data.ends_with(&[&policy_bytes[..], &[0]].concat()));
+        let index_at = add.data.len() - 1 - policy_bytes.len() - 4 - 1;
+        assert_eq!(add.data[index_at], 1);
+
+        let replace = replace_policy(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, 2, &policy, true).unwrap();
+        assert_eq!(replace.data[..8], instruction_discriminator("replace_policy"));
+        assert_eq!(replace.data[index_at], 2);
+        assert!(replace.data.ends_with(&[&policy_bytes[..], &[1]].concat()));
+
+        let remove = remove_policy(&program_id, &Pubkey::new_unique(), &Pubkey::new_unique(), &sig, 3, 2).unwrap();
+        assert_eq!(remove.data[..8], instruction_discriminator("remove_policy"));
+        assert!(remove.data.ends_with(&[3, 0, 0, 0, 0, 0, 0, 0, 2]));
+    }
+}
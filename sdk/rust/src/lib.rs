@@ -3,11 +3,62 @@
 //! This SDK provides Rust client functionality for interacting with
 //! Attesta accounts on Solana.
 
+pub mod approvals;
+pub mod audit;
+pub mod backend;
+pub mod balances;
+pub mod batch;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod claims;
 pub mod client;
+pub mod confirmation;
+#[cfg(feature = "devtools")]
+pub mod devtools;
+pub mod enrollment;
+pub mod instructions;
+pub mod logs;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod nonces;
+pub mod observer;
+pub mod preparation;
+pub mod replay;
+pub mod scan;
+pub mod signing;
+pub mod siwa;
 
-pub use client::AttestaClient;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+
+pub use approvals::{decode_proposal, ProposalSummary};
+pub use audit::{detect_unknown_credentials, verify_archive, ArchivedProof, AuditFailure, AuditReport, UnknownCredentialKind, UnknownCredentialUse};
+pub use backend::{ConfirmedTransaction, DataFilter, RpcBackend, SimulationResult, SolanaRpcBackend};
+pub use balances::{derive_associated_token_address, Balances, TokenBalance};
+pub use batch::{BatchPolicyUpdater, PolicyUpdateOutcome, PolicyUpdateReport};
+#[cfg(feature = "cache")]
+pub use cache::{CacheConfig, CacheStats};
+pub use claims::{create_claim_ticket, ClaimLink, ClaimTicketRequest};
+pub use confirmation::ConfirmationStrategy;
+#[cfg(feature = "devtools")]
+pub use devtools::{DevtoolsError, LocalEnv, LocalEnvConfig};
+pub use enrollment::{EnrollmentChallenge, EnrollmentError, EnrollmentTarget, PasskeyEnrollment, VerifiedPasskey};
+pub use logs::{parse_allowed_events, parse_log_line, parse_program_logs, AllowedWithContext, LogEvent};
+#[cfg(feature = "metrics")]
+pub use metrics::{MetricsSink, RelayerMetrics};
+pub use nonces::{DesyncCause, NonceDesync, NonceSyncEvent, NonceSyncGuard, NonceTracker, ProofId};
+pub use observer::{AttestaObserver, ObservedClient, DEFAULT_QUEUE_CAPACITY};
+pub use preparation::{FirstTransaction, NewAccountPasskey, PlanProgress, PreparationPlan, SetupKind, SetupStep};
+pub use replay::{diff_against_chain, FieldDiff, ReconstructedState, ReplayWarning, ReplayWarningKind};
+pub use scan::{AccountScan, ScanOptions};
+pub use client::{check_policy_cost, decode_execute_outcome, decode_policy_attestation, verify_logged_proof, AttestaClient, ExecuteResult, ExecutionCredentials, SponsorPoolStatus};
+#[cfg(feature = "serde")]
+pub use client::import_account_json;
+pub use signing::{AssertionResponse, ProofEnvelope, Registration, RegistrationRequest, SigningRequest};
+pub use siwa::{create_signin_request, verify_signin, MemoryNonceStore, NonceStore, SignInPayload, SiwaError, VerifiedSession};
 
 // Re-export commonly used types
-pub use smart_account::AttestaAccount;
-pub use core_crypto::WebAuthnSignature;
-pub use recovery::{Policy, PolicyType, MultiPasskey};
+pub use attesta_types;
+pub use smart_account::{AccountSettings, AttestaAccount, AttestationError, AuthMode, CancelReason, ClaimError, ClaimTicket, DenyReason, ExecuteOutcome, ExecutionReceipt, ExecutionStatus, InheritanceConfig, InheritanceStatus, PendingTransaction, PolicyAttestation, ProgramVersion, ProtocolConstants, ProtocolLimits, ProofLog, ProofLogEntry, RecoveryRequest, SecuritySummary, SponsorPool, SponsorshipError, TokenTransfer, TransactionRequest, TransactionRequestError, MAX_MEMO_LEN, MAX_TRANSACTION_DATA_LEN};
+pub use core_crypto::{RelyingParty, WebAuthnSignature, WebAuthnVerificationProfile};
+pub use recovery::{Amount, AmountError, CredentialBinding, CredentialBindings, DestinationLimit, DestinationLimits, Policy, PolicyBuilder, PolicyBuildError, PolicyType, MintLimit, MintLimits, MultiPasskey, EncryptedBackup, BackupContents, CredentialIdStorage, LAMPORTS_PER_SOL};
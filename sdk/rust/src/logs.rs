@@ -0,0 +1,185 @@
+//! Reading the program's structured log lines
+//!
+//! Alongside its free-text messages, the program logs every outcome as one
+//! `ATST1 <code> key=value ...` line (see `attesta_types::log`). An indexer
+//! hands `parse_program_logs` a transaction's `log_messages` and gets back
+//! just those events, without matching on wording that changes between
+//! releases.
+//!
+//! Accounts that sample their allowed executions (see
+//! `smart_account::sampling`) also emit `AllowedWithContext` Anchor events;
+//! `parse_allowed_events` decodes those.
+
+use attesta_types::log::{LOG_CODES, LOG_PREFIX, MAX_LOG_FIELDS};
+use base64::Engine;
+use borsh::BorshDeserialize;
+use smart_account::AllowedContext;
+use solana_program::pubkey::Pubkey;
+use crate::instructions::event_discriminator;
+
+pub use attesta_types::log::codes;
+
+/// What the runtime puts in front of every `msg!` line
+const PROGRAM_LOG_PREFIX: &str = "Program log: ";
+
+/// What the runtime puts in front of an emitted Anchor event
+const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+/// One structured log line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEvent {
+    /// The event code, one of `codes` unless the program is newer than this SDK
+    pub code: String,
+
+    /// The `key=value` pairs, in the order they were logged
+    pub fields: Vec<(String, String)>,
+}
+
+impl LogEvent {
+    /// The value logged under `key`, if any
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Whether this SDK knows the event's code
+    pub fn is_known(&self) -> bool {
+        LOG_CODES.contains(&self.code.as_str())
+    }
+}
+
+/// Parses one log line, with or without the runtime's `Program log: ` prefix
+///
+/// Returns `None` for anything that isn't a structured Attesta line. Words
+/// without an `=` are skipped, and at most `MAX_LOG_FIELDS` fields are kept,
+/// the same cap the program writes with.
+pub fn parse_log_line(line: &str) -> Option<LogEvent> {
+    let line = line.strip_prefix(PROGRAM_LOG_PREFIX).unwrap_or(line);
+    let mut words = line.split(' ');
+    if words.next() != Some(LOG_PREFIX) {
+        return None;
+    }
+    let code = words.next().filter(|code| !code.is_empty())?;
+    let fields = words
+        .filter_map(|word| word.split_once('='))
+        .take(MAX_LOG_FIELDS)
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    Some(LogEvent { code: code.to_string(), fields })
+}
+
+/// Pulls the structured events out of a transaction's log messages, in order
+///
+/// Lines from other programs, and the program's free-text messages, are
+/// ignored. Events from CPIs into Attesta are included like any other.
+pub fn parse_program_logs(logs: &[String]) -> Vec<LogEvent> {
+    logs.iter().filter_map(|line| parse_log_line(line)).collect()
+}
+
+/// A sampled allowed execution, as the program's `AllowedWithContext` event reports it
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllowedWithContext {
+    /// The account that executed
+    pub attesta_account: Pubkey,
+
+    /// The nonce the execution consumed
+    pub nonce: u64,
+
+    /// The policy hash, amount, destination hash and matching rule
+    pub context: AllowedContext,
+}
+
+impl AllowedWithContext {
+    /// Decodes one `Program data: ` log line, if it's an `AllowedWithContext` event
+    pub fn from_log(line: &str) -> Option<Self> {
+        let data = base64::engine::general_purpose::STANDARD.decode(line.strip_prefix(PROGRAM_DATA_PREFIX)?).ok()?;
+        let body = data.strip_prefix(&event_discriminator("AllowedWithContext")[..])?;
+        Self::try_from_slice(body).ok()
+    }
+}
+
+/// Pulls the `AllowedWithContext` events out of a transaction's log messages, in order
+pub fn parse_allowed_events(logs: &[String]) -> Vec<AllowedWithContext> {
+    logs.iter().filter_map(|line| AllowedWithContext::from_log(line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attesta_types::log::format_log_line;
+    use borsh::BorshSerialize;
+    use smart_account::sampling::NO_MATCH;
+
+    #[test]
+    fn test_every_code_round_trips() {
+        for code in LOG_CODES {
+            let line = format_log_line(code, &[("nonce", &7), ("to", &"abc")]);
+            let event = parse_log_line(&format!("Program log: {}", line)).unwrap();
+            assert_eq!(event.code, *code);
+            assert!(event.is_known());
+            assert_eq!(event.field("nonce"), Some("7"));
+            assert_eq!(event.field("to"), Some("abc"));
+            assert_eq!(event.field("amount"), None);
+        }
+    }
+
+    #[test]
+    fn test_field_count_is_capped() {
+        let line = format!("{} {} a=1 b=2 c=3 d=4 e=5 f=6", LOG_PREFIX, codes::EXECUTED);
+        let event = parse_log_line(&line).unwrap();
+        assert_eq!(event.fields.len(), MAX_LOG_FIELDS);
+        assert_eq!(event.field("d"), Some("4"));
+        assert_eq!(event.field("e"), None);
+
+        let written = format_log_line(codes::EXECUTED, &[("a", &1), ("b", &2), ("c", &3), ("d", &4), ("e", &5)]);
+        assert_eq!(parse_log_line(&written).unwrap().fields.len(), MAX_LOG_FIELDS);
+    }
+
+    #[test]
+    fn test_other_lines_are_ignored() {
+        let logs: Vec<String> = [
+            "Program 11111111111111111111111111111111 invoke [1]",
+            "Program log: Instruction: Execute",
+            "Program log: ATST1 exec_ok nonce=3 amount=0 signer=passkey",
+            "Program log: ATST1",
+            "Program log: ATST2 exec_ok nonce=4",
+            "Program log: ATST1 something_new x=1",
+            "Program 11111111111111111111111111111111 success",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+        let events = parse_program_logs(&logs);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].code, codes::EXECUTED);
+        assert_eq!(events[0].field("signer"), Some("passkey"));
+        assert_eq!(events[1].code, "something_new");
+        assert!(!events[1].is_known());
+    }
+
+    fn event_line(name: &str, body: &impl BorshSerialize) -> String {
+        let data = [&event_discriminator(name)[..], &borsh::to_vec(body).unwrap()].concat();
+        format!("{}{}", PROGRAM_DATA_PREFIX, base64::engine::general_purpose::STANDARD.encode(data))
+    }
+
+    #[test]
+    fn test_allowed_events_decode() {
+        let account = Pubkey::new_unique();
+        let context = AllowedContext { policy_hash: [1; 32], amount: 250, destination_hash: [2; 32], matched_rule: 1, matched_entry: 0 };
+        let unmatched = AllowedContext { matched_rule: NO_MATCH, matched_entry: NO_MATCH, ..context };
+        let logs = vec![
+            "Program log: ATST1 exec_ok nonce=3 amount=250 signer=passkey".to_string(),
+            // `TransactionExecuted` isn't one of them
+            event_line("TransactionExecuted", &(account, 3u64, [0u8; 32], None::<[u8; 32]>, Some([4u8; 32]))),
+            event_line("AllowedWithContext", &(account, 3u64, context)),
+            event_line("AllowedWithContext", &(account, 4u64, unmatched)),
+            "Program data: not base64".to_string(),
+        ];
+
+        let events = parse_allowed_events(&logs);
+        assert_eq!(events, vec![
+            AllowedWithContext { attesta_account: account, nonce: 3, context },
+            AllowedWithContext { attesta_account: account, nonce: 4, context: unmatched },
+        ]);
+    }
+}
@@ -0,0 +1,528 @@
+//! Prometheus-style metrics for relayers
+//!
+//! A relayer handing proofs to an `AttestaClient` wants to know how many
+//! arrive, how many it submits and sees confirmed (and how long that
+//! takes), how many the program denies or refuses as replays, and how often
+//! its RPC node fails. `AttestaClient::with_metrics` reports each of these
+//! to a `MetricsSink` as the client does the work.
+//!
+//! `RelayerMetrics` is the built-in sink: atomic counters and a latency
+//! histogram, rendered as Prometheus exposition text by
+//! `render_prometheus`. A service with a metrics backend of its own
+//! implements `MetricsSink` instead.
+//!
+//! A scrape stays the same size however many accounts the relayer serves:
+//! RPC counts are labeled by `RpcBackend` method, and nothing is labeled by
+//! account unless the account was named in
+//! `RelayerMetrics::with_account_labels`.
+//!
+//! Only available with the `metrics` feature.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anchor_client::{
+    solana_client::rpc_response::RpcKeyedAccount,
+    solana_sdk::{
+        commitment_config::CommitmentLevel,
+        hash::Hash,
+        signature::Signature,
+        transaction::{Transaction, TransactionError},
+    },
+};
+use solana_program::pubkey::Pubkey;
+use crate::backend::{ConfirmedTransaction, DataFilter, RpcBackend, SimulationResult};
+use crate::client::AttestaError;
+
+/// Most accounts `RelayerMetrics` keeps labeled counters for
+pub const MAX_LABELED_ACCOUNTS: usize = 64;
+
+/// Upper bounds of the confirmation latency buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 8] = [0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0];
+
+/// The `RpcBackend` methods RPC calls are counted under
+pub const RPC_METHODS: [&str; 15] = [
+    "get_account_data",
+    "get_account_data_with_slot",
+    "get_lamports",
+    "get_token_accounts_by_owner",
+    "get_program_accounts",
+    "get_program_accounts_matching",
+    "get_program_account_slices",
+    "get_multiple_account_data",
+    "get_latest_blockhash",
+    "send_transaction",
+    "get_signature_statuses",
+    "is_blockhash_valid",
+    "simulate_transaction",
+    "get_transaction",
+    "request_airdrop",
+];
+
+/// Prefix of every metric `render_prometheus` writes
+const PREFIX: &str = "attesta_relayer";
+
+/// What a metered `AttestaClient` reports as it works
+///
+/// Every method does nothing unless overridden. They're called on the
+/// thread doing the work, so they should return quickly.
+#[allow(unused_variables)]
+pub trait MetricsSink: Send + Sync {
+    /// `execute` was handed a passkey proof for `account`
+    fn proof_received(&self, account: &Pubkey) {}
+
+    /// The client set out to send a transaction (counted once, however
+    /// many times it's resubmitted)
+    fn transaction_submitted(&self) {}
+
+    /// A transaction reached the client's commitment, `latency` after the
+    /// client set out to send it
+    fn transaction_confirmed(&self, latency: Duration) {}
+
+    /// The program's policy checks denied an execution on `account`
+    fn policy_denied(&self, account: &Pubkey) {}
+
+    /// A proof for `account` was refused because the account had already
+    /// used its nonce
+    fn replay_rejected(&self, account: &Pubkey) {}
+
+    /// The client called `method` (one of `RPC_METHODS`) on its backend
+    fn rpc_call(&self, method: &'static str, failed: bool) {}
+}
+
+/// Counts kept in total, and again for each labeled account
+#[derive(Default)]
+struct ExecutionCounts {
+    proofs_received: AtomicU64,
+    policy_denials: AtomicU64,
+    replay_rejections: AtomicU64,
+}
+
+#[derive(Default)]
+struct RpcCounts {
+    requests: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// The built-in `MetricsSink`, safe to share between threads
+///
+/// Keep an `Arc` of it to render from while the client reports to it.
+#[derive(Default)]
+pub struct RelayerMetrics {
+    totals: ExecutionCounts,
+
+    /// The allowlisted accounts, fixed when the metrics are created
+    accounts: BTreeMap<Pubkey, ExecutionCounts>,
+
+    submissions: AtomicU64,
+    confirmations: AtomicU64,
+
+    /// Confirmations per latency bucket (not cumulative; those past the
+    /// last bucket are only in `confirmations`)
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_micros: AtomicU64,
+
+    /// Indexed like `RPC_METHODS`
+    rpc: [RpcCounts; RPC_METHODS.len()],
+}
+
+impl RelayerMetrics {
+    /// Metrics with no per-account labels
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Metrics that also count executions per account, for `accounts`
+    ///
+    /// Only the first `MAX_LABELED_ACCOUNTS` distinct accounts get labels;
+    /// any others are still counted in the totals.
+    pub fn with_account_labels(accounts: impl IntoIterator<Item = Pubkey>) -> Self {
+        let mut labeled = BTreeMap::new();
+        for account in accounts {
+            if labeled.len() == MAX_LABELED_ACCOUNTS {
+                break;
+            }
+            labeled.entry(account).or_insert_with(ExecutionCounts::default);
+        }
+        Self { accounts: labeled, ..Self::default() }
+    }
+
+    /// The mean time to confirmation so far (`None` before the first)
+    pub fn average_confirmation_latency(&self) -> Option<Duration> {
+        let count = self.confirmations.load(Ordering::Relaxed);
+        (count > 0).then(|| Duration::from_micros(self.latency_micros.load(Ordering::Relaxed) / count))
+    }
+
+    /// Renders every metric in the Prometheus text exposition format
+    ///
+    /// Counters are cumulative since the metrics were created. The average
+    /// confirmation latency is the histogram's `_sum` over its `_count`.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = Exposition::default();
+
+        let executions: [(&str, &str, fn(&ExecutionCounts) -> &AtomicU64); 3] = [
+            ("proofs_received_total", "Passkey proofs handed to execute", |counts| &counts.proofs_received),
+            ("policy_denials_total", "Executions the program's policy checks denied", |counts| &counts.policy_denials),
+            ("replay_rejections_total", "Proofs refused because their nonce was already used", |counts| &counts.replay_rejections),
+        ];
+        for (name, help, counter) in executions {
+            out.family(name, help, "counter");
+            out.sample(name, None, load(counter(&self.totals)));
+        }
+        out.family("submissions_total", "Transactions the client set out to send", "counter");
+        out.sample("submissions_total", None, load(&self.submissions));
+        out.family("confirmations_total", "Transactions that reached the client's commitment", "counter");
+        out.sample("confirmations_total", None, load(&self.confirmations));
+
+        out.family("confirmation_seconds", "Time from setting out to send a transaction to its confirmation", "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            cumulative += load(count);
+            out.sample("confirmation_seconds_bucket", Some(("le", bound.to_string())), cumulative);
+        }
+        let count = load(&self.confirmations).max(cumulative);
+        out.sample("confirmation_seconds_bucket", Some(("le", "+Inf".to_string())), count);
+        out.line(format!("{}_confirmation_seconds_sum {}", PREFIX, load(&self.latency_micros) as f64 / 1e6));
+        out.sample("confirmation_seconds_count", None, count);
+
+        let rpc: [(&str, &str, fn(&RpcCounts) -> &AtomicU64); 2] = [
+            ("rpc_requests_total", "RPC calls made, by backend method", |counts| &counts.requests),
+            ("rpc_errors_total", "RPC calls that failed, by backend method", |counts| &counts.errors),
+        ];
+        for (name, help, counter) in rpc {
+            out.family(name, help, "counter");
+            for (method, counts) in RPC_METHODS.iter().zip(&self.rpc) {
+                out.sample(name, Some(("method", method.to_string())), load(counter(counts)));
+            }
+        }
+
+        if !self.accounts.is_empty() {
+            for (name, help, counter) in executions {
+                let name = format!("account_{}", name);
+                out.family(&name, &format!("{}, for allowlisted accounts", help), "counter");
+                for (account, counts) in &self.accounts {
+                    out.sample(&name, Some(("account", account.to_string())), load(counter(counts)));
+                }
+            }
+        }
+        out.0
+    }
+
+    /// Adds one to `counter` of the totals, and of `account`'s if it's labeled
+    fn count(&self, account: &Pubkey, counter: fn(&ExecutionCounts) -> &AtomicU64) {
+        counter(&self.totals).fetch_add(1, Ordering::Relaxed);
+        if let Some(counts) = self.accounts.get(account) {
+            counter(counts).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl MetricsSink for RelayerMetrics {
+    fn proof_received(&self, account: &Pubkey) {
+        self.count(account, |counts| &counts.proofs_received);
+    }
+
+    fn transaction_submitted(&self) {
+        self.submissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn transaction_confirmed(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.latency_micros.fetch_add(micros, Ordering::Relaxed);
+        self.confirmations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn policy_denied(&self, account: &Pubkey) {
+        self.count(account, |counts| &counts.policy_denials);
+    }
+
+    fn replay_rejected(&self, account: &Pubkey) {
+        self.count(account, |counts| &counts.replay_rejections);
+    }
+
+    fn rpc_call(&self, method: &'static str, failed: bool) {
+        if let Some(index) = RPC_METHODS.iter().position(|known| *known == method) {
+            self.rpc[index].requests.fetch_add(1, Ordering::Relaxed);
+            if failed {
+                self.rpc[index].errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// Exposition text being written, one line at a time
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn line(&mut self, line: String) {
+        self.0.push_str(&line);
+        self.0.push('\n');
+    }
+
+    fn family(&mut self, name: &str, help: &str, kind: &str) {
+        self.line(format!("# HELP {}_{} {}", PREFIX, name, help));
+        self.line(format!("# TYPE {}_{} {}", PREFIX, name, kind));
+    }
+
+    /// A sample of `name`, with at most one label (whose values never need escaping)
+    fn sample(&mut self, name: &str, label: Option<(&str, String)>, value: u64) {
+        match label {
+            Some((key, label)) => self.line(format!("{}_{}{{{}=\"{}\"}} {}", PREFIX, name, key, label, value)),
+            None => self.line(format!("{}_{} {}", PREFIX, name, value)),
+        }
+    }
+}
+
+/// An `RpcBackend` that reports each call to a `MetricsSink`, then passes it on
+pub(crate) struct MeteredBackend {
+    inner: Arc<dyn RpcBackend>,
+    sink: Arc<dyn MetricsSink>,
+}
+
+impl MeteredBackend {
+    pub(crate) fn new(inner: Arc<dyn RpcBackend>, sink: Arc<dyn MetricsSink>) -> Self {
+        Self { inner, sink }
+    }
+
+    fn metered<T>(&self, method: &'static str, result: Result<T, AttestaError>) -> Result<T, AttestaError> {
+        self.sink.rpc_call(method, result.is_err());
+        result
+    }
+}
+
+impl RpcBackend for MeteredBackend {
+    fn get_account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>, AttestaError> {
+        self.metered("get_account_data", self.inner.get_account_data(address))
+    }
+
+    fn get_account_data_with_slot(&self, address: &Pubkey) -> Result<(Option<Vec<u8>>, u64), AttestaError> {
+        self.metered("get_account_data_with_slot", self.inner.get_account_data_with_slot(address))
+    }
+
+    fn get_lamports(&self, address: &Pubkey) -> Result<Option<u64>, AttestaError> {
+        self.metered("get_lamports", self.inner.get_lamports(address))
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        token_program: &Pubkey,
+    ) -> Result<Vec<RpcKeyedAccount>, AttestaError> {
+        self.metered("get_token_accounts_by_owner", self.inner.get_token_accounts_by_owner(owner, token_program))
+    }
+
+    fn get_program_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        self.metered("get_program_accounts", self.inner.get_program_accounts(program_id))
+    }
+
+    fn get_program_accounts_matching(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        self.metered("get_program_accounts_matching", self.inner.get_program_accounts_matching(program_id, filters))
+    }
+
+    fn get_program_account_slices(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+        offset: usize,
+        length: usize,
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        let result = self.inner.get_program_account_slices(program_id, filters, offset, length);
+        self.metered("get_program_account_slices", result)
+    }
+
+    fn get_multiple_account_data(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Vec<u8>>>, AttestaError> {
+        self.metered("get_multiple_account_data", self.inner.get_multiple_account_data(addresses))
+    }
+
+    fn get_latest_blockhash(&self) -> Result<Hash, AttestaError> {
+        self.metered("get_latest_blockhash", self.inner.get_latest_blockhash())
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AttestaError> {
+        self.metered("send_transaction", self.inner.send_transaction(transaction))
+    }
+
+    fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+        commitment: CommitmentLevel,
+    ) -> Result<Vec<Option<Result<(), TransactionError>>>, AttestaError> {
+        self.metered("get_signature_statuses", self.inner.get_signature_statuses(signatures, commitment))
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, AttestaError> {
+        self.metered("is_blockhash_valid", self.inner.is_blockhash_valid(blockhash))
+    }
+
+    fn simulate_transaction(&self, transaction: &Transaction) -> Result<SimulationResult, AttestaError> {
+        self.metered("simulate_transaction", self.inner.simulate_transaction(transaction))
+    }
+
+    fn get_transaction(&self, signature: &Signature) -> Result<ConfirmedTransaction, AttestaError> {
+        self.metered("get_transaction", self.inner.get_transaction(signature))
+    }
+
+    fn request_airdrop(&self, address: &Pubkey, lamports: u64) -> Result<Signature, AttestaError> {
+        self.metered("request_airdrop", self.inner.request_airdrop(address, lamports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use anchor_client::solana_sdk::instruction::InstructionError;
+    use anchor_client::solana_sdk::signature::Keypair;
+    use attesta_types::consts::POLICY_DENIAL_ERRORS;
+    use core_crypto::WebAuthnSignature;
+    use smart_account::AttestaAccount;
+    use crate::client::AttestaClient;
+    use crate::signing::ProofEnvelope;
+    use crate::test_utils::{attesta_account_data, MockBackend};
+
+    fn envelope(nonce: u64, message: u8) -> ProofEnvelope {
+        ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce,
+            message_hash: [message; 32],
+            idempotency_key: [message; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        }
+    }
+
+    fn failed_with(code: u32) -> Option<Result<(), TransactionError>> {
+        Some(Err(TransactionError::InstructionError(0, InstructionError::Custom(code))))
+    }
+
+    /// An account on the backend at `nonce`
+    fn set_account(backend: &MockBackend, address: Pubkey, nonce: u64) {
+        let mut account = AttestaAccount::new(Pubkey::new_unique(), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        (0..nonce).for_each(|_| account.increment_nonce(200));
+        backend.set_account(address, 1, attesta_account_data(&account));
+    }
+
+    fn assert_rendered(text: &str, lines: &[String]) {
+        for line in lines {
+            assert!(text.lines().any(|rendered| rendered == line), "missing `{}` in:\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn test_scripted_outcomes_are_rendered() {
+        let backend = MockBackend::new();
+        let (labeled, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let metrics = Arc::new(RelayerMetrics::with_account_labels([labeled]));
+        let client = AttestaClient::with_backend(backend.clone(), Pubkey::new_unique()).with_metrics(metrics.clone());
+        let authority = Keypair::new();
+
+        // Lands at once
+        client.execute(&authority, &labeled, &envelope(1, 1), b"one".to_vec()).unwrap();
+
+        // Denied by the policy, on the first send and the retry
+        set_account(&backend, other, 0);
+        backend.push_signature_status(failed_with(POLICY_DENIAL_ERRORS[0]));
+        backend.push_signature_status(failed_with(POLICY_DENIAL_ERRORS[0]));
+        assert!(client.execute(&authority, &other, &envelope(1, 2), b"two".to_vec()).is_err());
+
+        // Signed at a nonce the account has already used
+        set_account(&backend, labeled, 1);
+        backend.push_signature_status(failed_with(6001));
+        assert!(matches!(
+            client.execute(&authority, &labeled, &envelope(1, 3), b"three".to_vec()),
+            Err(AttestaError::NonceSkipped { .. })
+        ));
+
+        // The node times out on the first send, and the retry lands
+        backend.push_send_result(Err(AttestaError::RpcError("timed out".to_string())));
+        client.execute(&authority, &other, &envelope(1, 4), b"four".to_vec()).unwrap();
+
+        let text = metrics.render_prometheus();
+        assert_rendered(&text, &[
+            "# TYPE attesta_relayer_proofs_received_total counter".to_string(),
+            "attesta_relayer_proofs_received_total 4".to_string(),
+            "attesta_relayer_policy_denials_total 1".to_string(),
+            "attesta_relayer_replay_rejections_total 1".to_string(),
+            "attesta_relayer_submissions_total 6".to_string(),
+            "attesta_relayer_confirmations_total 2".to_string(),
+            "# TYPE attesta_relayer_confirmation_seconds histogram".to_string(),
+            "attesta_relayer_confirmation_seconds_bucket{le=\"0.5\"} 2".to_string(),
+            "attesta_relayer_confirmation_seconds_bucket{le=\"+Inf\"} 2".to_string(),
+            "attesta_relayer_confirmation_seconds_count 2".to_string(),
+            "attesta_relayer_rpc_requests_total{method=\"send_transaction\"} 6".to_string(),
+            "attesta_relayer_rpc_errors_total{method=\"send_transaction\"} 1".to_string(),
+            "attesta_relayer_rpc_errors_total{method=\"get_signature_statuses\"} 0".to_string(),
+            format!("attesta_relayer_account_proofs_received_total{{account=\"{}\"}} 2", labeled),
+            format!("attesta_relayer_account_policy_denials_total{{account=\"{}\"}} 0", labeled),
+            format!("attesta_relayer_account_replay_rejections_total{{account=\"{}\"}} 1", labeled),
+        ]);
+        // Only allowlisted accounts get a label
+        assert!(!text.contains(&other.to_string()));
+        assert!(metrics.average_confirmation_latency().unwrap() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_account_labels_are_bounded() {
+        let accounts: Vec<Pubkey> = (0..MAX_LABELED_ACCOUNTS + 10).map(|_| Pubkey::new_unique()).collect();
+        let metrics = RelayerMetrics::with_account_labels(accounts.iter().copied().chain(accounts.iter().copied()));
+        accounts.iter().for_each(|account| metrics.proof_received(account));
+
+        let text = metrics.render_prometheus();
+        let labeled = text.lines().filter(|line| line.starts_with("attesta_relayer_account_proofs_received_total{")).count();
+        assert_eq!(labeled, MAX_LABELED_ACCOUNTS);
+        assert_rendered(&text, &[format!("attesta_relayer_proofs_received_total {}", accounts.len())]);
+        assert!(!text.contains(&accounts[MAX_LABELED_ACCOUNTS].to_string()));
+
+        // Without an allowlist there are no account series at all
+        assert!(!RelayerMetrics::new().render_prometheus().contains("attesta_relayer_account_"));
+    }
+
+    /// A backend of the service's own, counting calls by method
+    #[derive(Default)]
+    struct CustomSink(Mutex<BTreeMap<&'static str, (u64, u64)>>);
+
+    impl MetricsSink for CustomSink {
+        fn rpc_call(&self, method: &'static str, failed: bool) {
+            let mut calls = self.0.lock().unwrap();
+            let entry = calls.entry(method).or_default();
+            entry.0 += 1;
+            entry.1 += u64::from(failed);
+        }
+    }
+
+    #[test]
+    fn test_custom_sink_hears_every_rpc_call() {
+        let backend = MockBackend::new();
+        let sink = Arc::new(CustomSink::default());
+        let client = AttestaClient::with_backend(backend.clone(), Pubkey::new_unique()).with_metrics(sink.clone());
+
+        let address = Pubkey::new_unique();
+        assert!(matches!(client.get_account(&address), Err(AttestaError::AccountNotFound)));
+        set_account(&backend, address, 0);
+        client.get_account(&address).unwrap();
+        client.execute(&Keypair::new(), &address, &envelope(1, 1), b"data".to_vec()).unwrap();
+
+        let calls = sink.0.lock().unwrap();
+        assert_eq!(calls["get_account_data"], (3, 0));
+        assert_eq!(calls["send_transaction"], (1, 0));
+        // The node doesn't have the transaction's logs
+        assert_eq!(calls["get_transaction"], (1, 1));
+        assert_eq!(backend.calls().len(), calls.values().map(|(count, _)| count).sum::<u64>() as usize);
+    }
+}
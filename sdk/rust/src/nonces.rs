@@ -0,0 +1,435 @@
+//! Handing out nonces to several transactions before any of them lands
+//!
+//! A UI that asks the user to approve a sequence (approve, swap, stake) has
+//! to give each signing request its own nonce up front. `NonceTracker`
+//! remembers what it handed out, per account, so a second
+//! `prepare_execution` doesn't reuse the first one's nonce.
+//!
+//! The program accepts any nonce above the account's, and every execution
+//! moves the account's nonce up by one. So once a later transaction lands,
+//! an earlier one may no longer be above the account's nonce: it can never
+//! execute. Each fetched account is checked for that, and `execute` refuses
+//! such a proof with `AttestaError::NonceSkipped` instead of paying for a
+//! transaction that would fail.
+//!
+//! A relayer signing against a cached account can fall behind the chain
+//! and have every send refused. `NonceSyncGuard` catches that: when a send
+//! fails and a fresh read shows the account moved on without the client
+//! noticing, it reports how far off the client was, whether its own
+//! earlier sends account for the gap, and which queued proofs can no
+//! longer land.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use smart_account::AttestaAccount;
+use solana_program::pubkey::Pubkey;
+use crate::client::AttestaError;
+
+/// Most skipped nonces remembered per account (the oldest are forgotten first)
+pub const MAX_SKIPPED_NONCES: usize = 64;
+
+/// What stays the same about an account for its whole life
+///
+/// `AttestaAccount` doesn't hold its own address; the owner, the parent and
+/// the creation time tell an owner's accounts apart instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct AccountKey {
+    owner: Pubkey,
+    parent: Option<Pubkey>,
+    sub_account_index: u8,
+    created_at: i64,
+}
+
+impl AccountKey {
+    fn of(account: &AttestaAccount) -> Self {
+        Self {
+            owner: account.owner,
+            parent: account.parent,
+            sub_account_index: account.sub_account_index,
+            created_at: account.created_at,
+        }
+    }
+}
+
+/// A signed proof the tracker is waiting on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProofId {
+    /// The nonce it was signed with
+    pub nonce: u64,
+
+    /// The hash of the transaction it signs
+    pub message_hash: [u8; 32],
+}
+
+/// What a fresh read of an account changed, for `NonceSyncGuard`
+#[derive(Debug)]
+struct Resync {
+    /// The account's nonce as the tracker last saw it
+    expected_nonce: u64,
+
+    /// This client's proofs the account's records show ran
+    landed: Vec<ProofId>,
+
+    /// This client's proofs the account's nonce passed over
+    invalidated: Vec<ProofId>,
+}
+
+#[derive(Debug, Default)]
+struct AccountNonces {
+    /// The highest nonce seen on-chain
+    chain_nonce: u64,
+
+    /// The highest nonce handed out
+    last_issued: u64,
+
+    /// Reserved and not yet given to a signing request
+    reserved: BTreeSet<u64>,
+
+    /// Given to a signing request (with its message hash), not known to have landed
+    signing: BTreeMap<u64, [u8; 32]>,
+
+    /// Signed, then passed over on-chain before it landed
+    skipped: BTreeMap<u64, [u8; 32]>,
+}
+
+impl AccountNonces {
+    fn observe(&mut self, account: &AttestaAccount) {
+        if account.nonce <= self.chain_nonce {
+            return;
+        }
+        self.chain_nonce = account.nonce;
+
+        // Unsigned reservations at or below the account's nonce are just gone
+        self.reserved = self.reserved.split_off(&(account.nonce + 1));
+
+        let still_valid = self.signing.split_off(&(account.nonce + 1));
+        for (nonce, message_hash) in std::mem::replace(&mut self.signing, still_valid) {
+            let landed = account
+                .idempotency_records
+                .iter()
+                .any(|record| record.nonce == nonce && record.message_hash == message_hash);
+            if !landed {
+                self.skipped.insert(nonce, message_hash);
+            }
+        }
+        while self.skipped.len() > MAX_SKIPPED_NONCES {
+            self.skipped.pop_first();
+        }
+    }
+
+    fn issue(&mut self) -> u64 {
+        self.last_issued = self.last_issued.max(self.chain_nonce).saturating_add(1);
+        self.last_issued
+    }
+}
+
+/// Nonces handed out to signing requests, per account
+///
+/// `AttestaClient` keeps one and feeds it every account it fetches; see
+/// `AttestaClient::reserve_nonces`.
+#[derive(Debug, Default)]
+pub struct NonceTracker {
+    accounts: Mutex<HashMap<AccountKey, AccountNonces>>,
+}
+
+impl NonceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn accounts(&self) -> MutexGuard<'_, HashMap<AccountKey, AccountNonces>> {
+        // A panic elsewhere can't leave the maps inconsistent
+        self.accounts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn with_account<T>(&self, account: &AttestaAccount, f: impl FnOnce(&mut AccountNonces) -> T) -> T {
+        let mut accounts = self.accounts();
+        let nonces = accounts.entry(AccountKey::of(account)).or_default();
+        nonces.observe(account);
+        f(nonces)
+    }
+
+    /// Updates the tracker with a freshly read account
+    ///
+    /// Reservations the account's nonce has caught up with are dropped, and
+    /// signed ones that didn't land are marked skipped.
+    pub fn observe(&self, account: &AttestaAccount) {
+        self.with_account(account, |_| ());
+    }
+
+    /// Reserves the next `count` nonces nobody has been given yet
+    pub fn reserve(&self, account: &AttestaAccount, count: u8) -> Vec<u64> {
+        self.with_account(account, |nonces| {
+            let reserved: Vec<u64> = (0..count).map(|_| nonces.issue()).collect();
+            nonces.reserved.extend(&reserved);
+            reserved
+        })
+    }
+
+    /// The nonce for a new signing request over `message_hash`
+    ///
+    /// The lowest reservation, if there is one, otherwise the next free nonce.
+    pub fn assign(&self, account: &AttestaAccount, message_hash: [u8; 32]) -> u64 {
+        self.with_account(account, |nonces| {
+            let nonce = nonces.reserved.pop_first().unwrap_or_else(|| nonces.issue());
+            nonces.signing.insert(nonce, message_hash);
+            nonce
+        })
+    }
+
+    /// Records that the transaction signed with `nonce` executed
+    pub fn mark_executed(&self, nonce: u64, message_hash: &[u8; 32]) {
+        for nonces in self.accounts().values_mut() {
+            if nonces.signing.get(&nonce) == Some(message_hash) {
+                nonces.signing.remove(&nonce);
+            }
+        }
+    }
+
+    /// Updates the tracker with a freshly read account, reporting what
+    /// changed if its nonce moved past the one last seen
+    ///
+    /// `None` for an account the tracker hadn't seen before.
+    fn resync(&self, account: &AttestaAccount) -> Option<Resync> {
+        let mut accounts = self.accounts();
+        let nonces = accounts.get_mut(&AccountKey::of(account))?;
+        if account.nonce <= nonces.chain_nonce {
+            return None;
+        }
+
+        let expected_nonce = nonces.chain_nonce;
+        let (landed, invalidated): (Vec<ProofId>, Vec<ProofId>) = nonces
+            .signing
+            .range(..=account.nonce)
+            .map(|(&nonce, &message_hash)| ProofId { nonce, message_hash })
+            .partition(|proof| {
+                account
+                    .idempotency_records
+                    .iter()
+                    .any(|record| record.nonce == proof.nonce && record.message_hash == proof.message_hash)
+            });
+        nonces.observe(account);
+        Some(Resync { expected_nonce, landed, invalidated })
+    }
+
+    /// Fails with `AttestaError::NonceSkipped` if `nonce` was passed over
+    /// before the transaction signed with it landed
+    pub fn check(&self, nonce: u64, message_hash: &[u8; 32]) -> Result<(), AttestaError> {
+        for nonces in self.accounts().values() {
+            if nonces.skipped.get(&nonce) == Some(message_hash) {
+                return Err(AttestaError::NonceSkipped { nonce, account_nonce: nonces.chain_nonce });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why an account's nonce was ahead of the client's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesyncCause {
+    /// Every execution the client missed was one of its own proofs: a
+    /// send it took for failed landed after all
+    OwnSubmissionLanded,
+
+    /// Something else ran on the account (another relayer or client, or
+    /// the owner's wallet)
+    ExecutedElsewhere,
+}
+
+/// An account found further along than the client expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonceDesync {
+    /// The account's address
+    pub account: Pubkey,
+
+    /// Its nonce as the client last saw it
+    pub expected_nonce: u64,
+
+    /// Its nonce on-chain
+    pub chain_nonce: u64,
+
+    /// Whether the client's own late sends explain the difference
+    pub cause: DesyncCause,
+
+    /// This client's proofs that turned out to have run
+    pub landed: Vec<ProofId>,
+}
+
+impl NonceDesync {
+    /// How many executions the client missed
+    pub fn behind(&self) -> u64 {
+        self.chain_nonce - self.expected_nonce
+    }
+}
+
+/// What a `NonceSyncGuard` tells the application
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonceSyncEvent {
+    /// An account was ahead of the client
+    Desync(NonceDesync),
+
+    /// Proofs the client was holding can never land; each transaction has
+    /// to be prepared and signed again
+    ProofsInvalidated(Vec<ProofId>),
+}
+
+/// Notices when the client has fallen behind an account's nonce
+///
+/// `AttestaClient` runs one whenever a send fails and it reads the account
+/// again (see `AttestaClient::with_nonce_sync_guard`). Without a callback
+/// the passed-over proofs are still dropped; `execute` refuses them with
+/// `AttestaError::NonceSkipped`.
+#[derive(Default)]
+pub struct NonceSyncGuard {
+    on_event: Option<Arc<dyn Fn(&NonceSyncEvent) + Send + Sync>>,
+}
+
+impl NonceSyncGuard {
+    /// A guard that calls `on_event` with each desync and invalidation
+    ///
+    /// It's called on the thread that made the failed call, before that
+    /// call returns.
+    pub fn new(on_event: impl Fn(&NonceSyncEvent) + Send + Sync + 'static) -> Self {
+        Self { on_event: Some(Arc::new(on_event)) }
+    }
+
+    /// Feeds `tracker` a freshly read `account`, reporting any desync
+    ///
+    /// # Parameters
+    /// - `tracker`: The tracker the proofs were assigned from
+    /// - `address`: Where `account` was read
+    /// - `account`: The account as it is on-chain
+    ///
+    /// # Returns
+    /// The desync, or `None` if the tracker was up to date (or had never
+    /// seen the account)
+    pub fn resync(&self, tracker: &NonceTracker, address: &Pubkey, account: &AttestaAccount) -> Option<NonceDesync> {
+        let resync = tracker.resync(account)?;
+        let missed = account.nonce - resync.expected_nonce;
+        let cause = if resync.landed.len() as u64 == missed {
+            DesyncCause::OwnSubmissionLanded
+        } else {
+            DesyncCause::ExecutedElsewhere
+        };
+        let desync = NonceDesync {
+            account: *address,
+            expected_nonce: resync.expected_nonce,
+            chain_nonce: account.nonce,
+            cause,
+            landed: resync.landed,
+        };
+
+        if let Some(on_event) = &self.on_event {
+            on_event(&NonceSyncEvent::Desync(desync.clone()));
+            if !resync.invalidated.is_empty() {
+                on_event(&NonceSyncEvent::ProofsInvalidated(resync.invalidated));
+            }
+        }
+        Some(desync)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(nonce: u64) -> AttestaAccount {
+        let mut account = AttestaAccount::new(Pubkey::new_from_array([1; 32]), [3u8; 64], b"phone".to_vec(), vec![], 100);
+        account.nonce = nonce;
+        account
+    }
+
+    #[test]
+    fn test_reservations_are_distinct_and_consumed_in_order() {
+        let tracker = NonceTracker::new();
+        assert_eq!(tracker.reserve(&account(5), 3), vec![6, 7, 8]);
+        assert_eq!(tracker.reserve(&account(5), 2), vec![9, 10]);
+
+        assert_eq!(tracker.assign(&account(5), [1; 32]), 6);
+        assert_eq!(tracker.assign(&account(5), [2; 32]), 7);
+
+        // Without reservations left, the next free nonce
+        assert_eq!(tracker.assign(&account(5), [3; 32]), 8);
+        assert_eq!(tracker.assign(&account(5), [4; 32]), 9);
+        assert_eq!(tracker.assign(&account(5), [5; 32]), 10);
+        assert_eq!(tracker.assign(&account(5), [6; 32]), 11);
+    }
+
+    #[test]
+    fn test_accounts_are_tracked_separately() {
+        let tracker = NonceTracker::new();
+        let mut other = account(0);
+        other.created_at = 200;
+
+        assert_eq!(tracker.reserve(&account(0), 2), vec![1, 2]);
+        assert_eq!(tracker.reserve(&other, 2), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_reservations_behind_the_chain_are_dropped() {
+        let tracker = NonceTracker::new();
+        assert_eq!(tracker.reserve(&account(0), 3), vec![1, 2, 3]);
+
+        // Two transactions from elsewhere landed
+        tracker.observe(&account(2));
+        assert_eq!(tracker.assign(&account(2), [1; 32]), 3);
+        assert_eq!(tracker.reserve(&account(2), 1), vec![4]);
+
+        // A stale copy of the account doesn't move anything back
+        tracker.observe(&account(0));
+        assert_eq!(tracker.assign(&account(0), [2; 32]), 4);
+    }
+
+    #[test]
+    fn test_passed_over_signature_is_reported() {
+        let tracker = NonceTracker::new();
+        let first = tracker.assign(&account(0), [1; 32]);
+        let second = tracker.assign(&account(0), [2; 32]);
+        assert_eq!((first, second), (1, 2));
+
+        // The second lands first: the account's nonce reaches 1
+        let mut after = account(1);
+        after.record_idempotency_key([9; 16], [2; 32], second);
+        tracker.mark_executed(second, &[2; 32]);
+        tracker.observe(&after);
+
+        assert!(matches!(
+            tracker.check(first, &[1; 32]),
+            Err(AttestaError::NonceSkipped { nonce: 1, account_nonce: 1 })
+        ));
+        assert!(tracker.check(second, &[2; 32]).is_ok());
+    }
+
+    #[test]
+    fn test_landed_signature_is_not_reported() {
+        let tracker = NonceTracker::new();
+        let nonce = tracker.assign(&account(0), [1; 32]);
+
+        // Executed through another client: only the account's record says so
+        let mut after = account(1);
+        after.record_idempotency_key([9; 16], [1; 32], nonce);
+        tracker.observe(&after);
+
+        assert!(tracker.check(nonce, &[1; 32]).is_ok());
+    }
+
+    #[test]
+    fn test_resync_reports_only_movement_past_what_was_seen() {
+        let tracker = NonceTracker::new();
+        let guard = NonceSyncGuard::default();
+        let address = Pubkey::new_unique();
+
+        // Never seen: nothing to compare against
+        assert!(guard.resync(&tracker, &address, &account(3)).is_none());
+
+        tracker.assign(&account(3), [1; 32]);
+        assert!(guard.resync(&tracker, &address, &account(3)).is_none());
+
+        let desync = guard.resync(&tracker, &address, &account(5)).unwrap();
+        assert_eq!((desync.expected_nonce, desync.chain_nonce, desync.behind()), (3, 5, 2));
+        assert_eq!(desync.cause, DesyncCause::ExecutedElsewhere);
+
+        // The tracker caught up
+        assert!(guard.resync(&tracker, &address, &account(5)).is_none());
+    }
+}
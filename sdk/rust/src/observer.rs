@@ -0,0 +1,478 @@
+//! Lifecycle callbacks for services built on the SDK
+//!
+//! A backend that wants to know when an account is created, runs a
+//! transaction, has one denied or starts a recovery implements
+//! `AttestaObserver` and wraps its client in an `ObservedClient`. Events
+//! reach it two ways: transactions the client sends itself, once they're
+//! confirmed, and whatever the service's own log subscription hands to
+//! `ObservedClient::handle_logs`. Either way they're read from the
+//! transaction's structured log lines (see `logs`), and a transaction
+//! reported by both is only notified once: the second report of a
+//! signature is ignored.
+//!
+//! Callbacks run on one dispatcher thread, in the order transactions were
+//! reported, so each account's events arrive in order. The client never
+//! waits for an observer: reports queue up to a fixed capacity, and past
+//! it the oldest are dropped and counted in `dropped_notifications`.
+
+use std::collections::{HashSet, VecDeque};
+use std::ops::Deref;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use anchor_client::solana_sdk::signature::Signature;
+use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+use crate::backend::RpcBackend;
+use crate::client::AttestaClient;
+use crate::instructions::instruction_discriminator;
+use crate::logs::{codes, parse_program_logs};
+
+/// Reports an `ObservedClient` queues before dropping the oldest
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Signatures remembered for deduplication
+///
+/// A subscription reports a transaction within seconds of the send path,
+/// so only the most recent ones are worth remembering.
+const SEEN_SIGNATURES: usize = 4096;
+
+/// What `AttestaClient::send_instructions` calls with each landed transaction
+pub(crate) type SentHook = Arc<dyn Fn(&Signature, &[Instruction]) + Send + Sync>;
+
+/// Callbacks for account lifecycle events
+///
+/// Every method does nothing unless overridden. They're called on the
+/// dispatcher thread, one at a time; a slow callback holds up the ones
+/// after it (and may get reports dropped), never the client. A callback
+/// that panics loses only that event.
+#[allow(unused_variables)]
+pub trait AttestaObserver: Send + Sync {
+    /// `account` was created for `owner`
+    fn account_created(&self, account: &Pubkey, signature: &Signature, owner: &Pubkey) {}
+
+    /// `account` ran a transaction: its new `nonce`, and the base units
+    /// moved if it was a token transfer (0 otherwise)
+    fn transaction_executed(&self, account: &Pubkey, signature: &Signature, nonce: u64, amount: u64) {}
+
+    /// `account`'s policy refused a transaction, for `reason` as the
+    /// program logs it (`policy`, `zero_amount`, ...)
+    fn policy_denied(&self, account: &Pubkey, signature: &Signature, reason: &str) {}
+
+    /// A recovery of `account` was started
+    fn recovery_initiated(&self, account: &Pubkey, signature: &Signature) {}
+}
+
+/// An `AttestaClient` that reports lifecycle events to an observer
+///
+/// It derefs to the client, so every client method is available as
+/// before. Dropping it stops the dispatcher once the queued reports have
+/// been delivered.
+pub struct ObservedClient {
+    client: AttestaClient,
+    dispatcher: Arc<Dispatcher>,
+}
+
+impl ObservedClient {
+    /// Wraps `client`, reporting to `observer`
+    pub fn new(client: AttestaClient, observer: impl AttestaObserver + 'static) -> Self {
+        Self::with_capacity(client, observer, DEFAULT_QUEUE_CAPACITY)
+    }
+
+    /// Wraps `client`, keeping at most `capacity` reports (at least 1) queued
+    pub fn with_capacity(client: AttestaClient, observer: impl AttestaObserver + 'static, capacity: usize) -> Self {
+        let dispatcher = Arc::new(Dispatcher {
+            queue: Mutex::new(Queue::default()),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+        });
+        let backend = client.shared_backend();
+        let worker = Arc::clone(&dispatcher);
+        thread::spawn(move || worker.run(&observer, backend.as_ref()));
+
+        let program_id = client.program_id();
+        let sender = Arc::clone(&dispatcher);
+        let client = client.with_sent_hook(Arc::new(move |signature: &Signature, instructions: &[Instruction]| {
+            if let Some(account) = instructions.iter().find_map(|instruction| attesta_account(&program_id, instruction)) {
+                sender.push(Report { account, signature: *signature, logs: None });
+            }
+        }));
+        Self { client, dispatcher }
+    }
+
+    /// The wrapped client
+    pub fn client(&self) -> &AttestaClient {
+        &self.client
+    }
+
+    /// Reports a transaction a log subscription saw mention `account`
+    ///
+    /// Pass the notification's signature and logs as they came, and whether
+    /// the transaction failed: a failed one is only reported if it was a
+    /// denial, since nothing else it logged took effect.
+    pub fn handle_logs(&self, account: Pubkey, signature: Signature, failed: bool, logs: Vec<String>) {
+        self.dispatcher.push(Report { account, signature, logs: Some(Logs { failed, lines: logs }) });
+    }
+
+    /// How many reports were dropped because the observer fell behind
+    pub fn dropped_notifications(&self) -> u64 {
+        self.dispatcher.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Waits until every report queued so far has been delivered
+    pub fn flush(&self) {
+        let mut queue = self.dispatcher.lock();
+        while !queue.reports.is_empty() || queue.busy {
+            queue = self.dispatcher.changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Deref for ObservedClient {
+    type Target = AttestaClient;
+
+    fn deref(&self) -> &AttestaClient {
+        &self.client
+    }
+}
+
+impl Drop for ObservedClient {
+    fn drop(&mut self) {
+        self.dispatcher.lock().closed = true;
+        self.dispatcher.changed.notify_all();
+    }
+}
+
+/// One transaction to notify about
+struct Report {
+    account: Pubkey,
+    signature: Signature,
+
+    /// The subscription's logs, or `None` to fetch them (the send path)
+    logs: Option<Logs>,
+}
+
+struct Logs {
+    failed: bool,
+    lines: Vec<String>,
+}
+
+#[derive(Default)]
+struct Queue {
+    reports: VecDeque<Report>,
+
+    /// A report is being delivered
+    busy: bool,
+
+    /// The `ObservedClient` is gone: stop once the queue is empty
+    closed: bool,
+}
+
+struct Dispatcher {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+impl Dispatcher {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        // Callbacks run outside the lock, so a poisoned queue is still consistent
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, report: Report) {
+        let mut queue = self.lock();
+        if queue.closed {
+            return;
+        }
+        if queue.reports.len() >= self.capacity {
+            queue.reports.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        queue.reports.push_back(report);
+        drop(queue);
+        self.changed.notify_all();
+    }
+
+    fn run(&self, observer: &dyn AttestaObserver, backend: &dyn RpcBackend) {
+        let mut seen = SeenSignatures::default();
+        loop {
+            let report = {
+                let mut queue = self.lock();
+                queue.busy = false;
+                self.changed.notify_all();
+                loop {
+                    if let Some(report) = queue.reports.pop_front() {
+                        queue.busy = true;
+                        break report;
+                    }
+                    if queue.closed {
+                        return;
+                    }
+                    queue = self.changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            if seen.contains(&report.signature) {
+                continue;
+            }
+            let logs = match report.logs {
+                Some(logs) => logs,
+                // Left unseen if it can't be fetched: the subscription may still report it
+                None => match backend.get_transaction(&report.signature) {
+                    Ok(transaction) => Logs { failed: transaction.err.is_some(), lines: transaction.logs },
+                    Err(_) => continue,
+                },
+            };
+            seen.insert(report.signature);
+            for event in lifecycle_events(&logs) {
+                let _ = catch_unwind(AssertUnwindSafe(|| event.notify(observer, &report.account, &report.signature)));
+            }
+        }
+    }
+}
+
+/// The most recent `SEEN_SIGNATURES` signatures notified about
+#[derive(Default)]
+struct SeenSignatures {
+    order: VecDeque<Signature>,
+    set: HashSet<Signature>,
+}
+
+impl SeenSignatures {
+    fn contains(&self, signature: &Signature) -> bool {
+        self.set.contains(signature)
+    }
+
+    fn insert(&mut self, signature: Signature) {
+        if self.set.insert(signature) {
+            self.order.push_back(signature);
+        }
+        if self.order.len() > SEEN_SIGNATURES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// A lifecycle event read from a transaction's logs
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LifecycleEvent {
+    AccountCreated { owner: Pubkey },
+    TransactionExecuted { nonce: u64, amount: u64 },
+    PolicyDenied { reason: String },
+    RecoveryInitiated,
+}
+
+impl LifecycleEvent {
+    fn notify(&self, observer: &dyn AttestaObserver, account: &Pubkey, signature: &Signature) {
+        match self {
+            LifecycleEvent::AccountCreated { owner } => observer.account_created(account, signature, owner),
+            LifecycleEvent::TransactionExecuted { nonce, amount } => {
+                observer.transaction_executed(account, signature, *nonce, *amount)
+            }
+            LifecycleEvent::PolicyDenied { reason } => observer.policy_denied(account, signature, reason),
+            LifecycleEvent::RecoveryInitiated => observer.recovery_initiated(account, signature),
+        }
+    }
+}
+
+/// The lifecycle events `logs` record, in order
+///
+/// Lines missing a field the event needs are skipped.
+fn lifecycle_events(logs: &Logs) -> Vec<LifecycleEvent> {
+    parse_program_logs(&logs.lines)
+        .into_iter()
+        .filter_map(|event| match event.code.as_str() {
+            codes::DENIED => Some(LifecycleEvent::PolicyDenied { reason: event.field("reason")?.to_string() }),
+            _ if logs.failed => None,
+            codes::ACCOUNT_INITIALIZED => Some(LifecycleEvent::AccountCreated { owner: event.field("owner")?.parse().ok()? }),
+            codes::EXECUTED => Some(LifecycleEvent::TransactionExecuted {
+                nonce: event.field("nonce")?.parse().ok()?,
+                amount: event.field("amount")?.parse().ok()?,
+            }),
+            codes::RECOVERY_INITIATED => Some(LifecycleEvent::RecoveryInitiated),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The Attesta account `instruction` acts on, if it's one of the program's
+///
+/// Every instruction lists the account first, except `sponsored_initialize`,
+/// which lists the sponsor pool before it.
+fn attesta_account(program_id: &Pubkey, instruction: &Instruction) -> Option<Pubkey> {
+    if instruction.program_id != *program_id {
+        return None;
+    }
+    let sponsored = instruction.data.get(..8) == Some(&instruction_discriminator("sponsored_initialize")[..]);
+    instruction.accounts.get(usize::from(sponsored)).map(|meta| meta.pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use anchor_client::solana_sdk::signature::Keypair;
+    use anchor_client::solana_sdk::transaction::Transaction;
+    use crate::backend::ConfirmedTransaction;
+    use crate::signing::ProofEnvelope;
+    use crate::test_utils::MockBackend;
+    use core_crypto::WebAuthnSignature;
+
+    /// Writes down every callback, in the order it came
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn record(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl AttestaObserver for Recorder {
+        fn account_created(&self, account: &Pubkey, _: &Signature, owner: &Pubkey) {
+            self.record(format!("created {} by {}", account, owner));
+        }
+
+        fn transaction_executed(&self, account: &Pubkey, _: &Signature, nonce: u64, amount: u64) {
+            self.record(format!("executed {} nonce {} amount {}", account, nonce, amount));
+        }
+
+        fn policy_denied(&self, account: &Pubkey, _: &Signature, reason: &str) {
+            self.record(format!("denied {} {}", account, reason));
+        }
+
+        fn recovery_initiated(&self, account: &Pubkey, _: &Signature) {
+            self.record(format!("recovery {}", account));
+        }
+    }
+
+    fn log(line: &str) -> Vec<String> {
+        vec!["Program log: Instruction: Execute".to_string(), format!("Program log: ATST1 {}", line)]
+    }
+
+    fn envelope() -> ProofEnvelope {
+        ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(vec![1; 37], vec![2; 10], vec![3; 64], vec![4; 16]),
+            nonce: 1,
+            message_hash: [9u8; 32],
+            idempotency_key: [7u8; 16],
+            parent_account: None,
+            logs_proofs: false,
+            memo: Vec::new(),
+            emit_memo: false,
+        }
+    }
+
+    #[test]
+    fn test_scripted_lifecycle_is_notified_once_in_order() {
+        let backend = MockBackend::new();
+        let recorder = Recorder::default();
+        let client = ObservedClient::new(AttestaClient::with_backend(backend.clone(), Pubkey::new_unique()), recorder.clone());
+        let (account, other, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        // The subscription sees the account created
+        client.handle_logs(account, Signature::from([1; 64]), false, log(&format!("account_init owner={}", owner)));
+
+        // The client runs a transaction, and the subscription reports it too
+        let executed = Signature::from([2; 64]);
+        backend.push_send_result(Ok(executed));
+        backend.set_transaction(ConfirmedTransaction {
+            signature: executed,
+            slot: 10,
+            block_time: None,
+            transaction: Transaction::default(),
+            err: None,
+            logs: log("exec_ok nonce=1 amount=250 signer=passkey"),
+        });
+        client.execute(&Keypair::new(), &account, &envelope(), b"data".to_vec()).unwrap();
+        client.handle_logs(account, executed, false, log("exec_ok nonce=1 amount=250 signer=passkey"));
+
+        // A denial fails its transaction but is still reported; a failed
+        // transaction's other events never took effect
+        client.handle_logs(other, Signature::from([3; 64]), true, log("exec_denied reason=policy"));
+        client.handle_logs(other, Signature::from([4; 64]), true, log("exec_ok nonce=9 amount=0 signer=owner"));
+        client.handle_logs(account, Signature::from([5; 64]), false, log(&format!("recovery_start account={}", account)));
+        client.flush();
+
+        assert_eq!(recorder.events(), vec![
+            format!("created {} by {}", account, owner),
+            format!("executed {} nonce 1 amount 250", account),
+            format!("denied {} policy", other),
+            format!("recovery {}", account),
+        ]);
+        assert_eq!(client.dropped_notifications(), 0);
+    }
+
+    #[test]
+    fn test_subscription_first_suppresses_the_send_path() {
+        let backend = MockBackend::new();
+        let recorder = Recorder::default();
+        let client = ObservedClient::new(AttestaClient::with_backend(backend.clone(), Pubkey::new_unique()), recorder.clone());
+        let account = Pubkey::new_unique();
+
+        let executed = Signature::from([2; 64]);
+        client.handle_logs(account, executed, false, log("exec_ok nonce=1 amount=0 signer=passkey"));
+        backend.push_send_result(Ok(executed));
+        client.execute(&Keypair::new(), &account, &envelope(), b"data".to_vec()).unwrap();
+        client.flush();
+
+        assert_eq!(recorder.events(), vec![format!("executed {} nonce 1 amount 0", account)]);
+    }
+
+    /// Holds each callback until the test lets it go
+    struct Gate {
+        entered: Mutex<mpsc::Sender<()>>,
+        release: Mutex<mpsc::Receiver<()>>,
+        recorder: Recorder,
+    }
+
+    impl AttestaObserver for Gate {
+        fn recovery_initiated(&self, account: &Pubkey, signature: &Signature) {
+            let _ = self.entered.lock().unwrap().send(());
+            let _ = self.release.lock().unwrap().recv();
+            self.recorder.recovery_initiated(account, signature);
+        }
+    }
+
+    #[test]
+    fn test_slow_observer_drops_oldest_without_blocking() {
+        let (entered_tx, entered) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel();
+        let recorder = Recorder::default();
+        let gate = Gate { entered: Mutex::new(entered_tx), release: Mutex::new(release_rx), recorder: recorder.clone() };
+        let client = ObservedClient::with_capacity(AttestaClient::with_backend(MockBackend::new(), Pubkey::new_unique()), gate, 2);
+        let accounts: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+        let report = |index: usize| {
+            client.handle_logs(accounts[index], Signature::from([index as u8 + 1; 64]), false, log("recovery_start"));
+        };
+
+        // The first is stuck in the observer; the rest queue behind it
+        report(0);
+        entered.recv().unwrap();
+        (1..5).for_each(report);
+        assert_eq!(client.dropped_notifications(), 2);
+
+        (0..3).for_each(|_| release.send(()).unwrap());
+        client.flush();
+        assert_eq!(recorder.events(), [0, 3, 4].map(|index| format!("recovery {}", accounts[index])));
+    }
+
+    #[test]
+    fn test_events_need_their_fields() {
+        let logs = Logs { failed: false, lines: log("exec_ok nonce=x amount=1") };
+        assert_eq!(lifecycle_events(&logs), vec![]);
+        let logs = Logs { failed: false, lines: log("account_init") };
+        assert_eq!(lifecycle_events(&logs), vec![]);
+        let logs = Logs { failed: false, lines: log("exec_ok nonce=3 amount=1 signer=owner") };
+        assert_eq!(lifecycle_events(&logs), vec![LifecycleEvent::TransactionExecuted { nonce: 3, amount: 1 }]);
+    }
+}
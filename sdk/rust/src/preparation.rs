@@ -0,0 +1,519 @@
+//! Creating everything a new user's first transaction needs
+//!
+//! A first payment from a brand-new account fails on whatever doesn't exist
+//! yet: the Attesta account itself, or the recipient's token account.
+//! `AttestaClient::prepare_first_transaction` looks up which of those are
+//! missing and returns a `PreparationPlan`: the setup instructions in the
+//! order they have to run, the final `execute`, and what it will all cost.
+//! `AttestaClient::execute_plan` sends it.
+//!
+//! A new account's nonce starts at 0, so its first transaction can be
+//! signed before the account exists: build the signing request from the
+//! account `initialize` will create (`AttestaAccount::new` with the same
+//! owner, passkey and policy).
+//!
+//! The plan doesn't fund anything it can't create. Tokens being sent must
+//! already be in the account's associated token account, and a proof log
+//! only exists once a passkey has enabled it.
+
+use anchor_client::solana_sdk::{compute_budget::ComputeBudgetInstruction, signature::Signature};
+use solana_program::{instruction::Instruction, pubkey::Pubkey, rent::Rent};
+use attesta_types::consts::{
+    ACCOUNT_DISCRIMINATOR_LEN, BORSH_LEN_PREFIX, MAX_CREDENTIAL_ID_LEN, MAX_INITIAL_POLICY_LEN, P256_PUBKEY_LEN, PUBKEY_LEN,
+};
+use recovery::Policy;
+use smart_account::idempotency::IDEMPOTENCY_RECORDS_SPACE;
+use smart_account::{TokenTransfer, TransactionRequest};
+use crate::backend::RpcBackend;
+use crate::balances::{create_associated_token_account, derive_associated_token_address, TOKEN_ACCOUNT_LEN};
+use crate::batch::{transaction_size, MAX_TRANSACTION_SIZE};
+use crate::client::{decode_attesta_account, AttestaError};
+use crate::instructions::{self, derive_attesta_address, INITIALIZE_COMPUTE_UNITS};
+use crate::signing::{ProofEnvelope, Registration, RegistrationRequest};
+
+/// Space `initialize` allocates for a new Attesta account (the program's
+/// `ATTESTA_ACCOUNT_SPACE`)
+pub const ATTESTA_ACCOUNT_SPACE: usize = ACCOUNT_DISCRIMINATOR_LEN
+    + PUBKEY_LEN
+    + P256_PUBKEY_LEN
+    + BORSH_LEN_PREFIX + MAX_CREDENTIAL_ID_LEN
+    + BORSH_LEN_PREFIX + MAX_INITIAL_POLICY_LEN
+    + 8 + 8 + 8
+    + IDEMPOTENCY_RECORDS_SPACE;
+
+/// The base fee for each signature on a transaction, in lamports
+pub const FEE_PER_SIGNATURE: u64 = 5_000;
+
+/// Compute units an instruction gets when the transaction doesn't ask for more
+const DEFAULT_INSTRUCTION_COMPUTE_UNITS: u32 = 200_000;
+
+/// Most compute units one transaction can ask for
+const MAX_TRANSACTION_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// The passkey a new account is created with
+///
+/// Only used when the Attesta account doesn't exist yet.
+#[derive(Debug, Clone, Copy)]
+pub struct NewAccountPasskey<'a> {
+    /// The registration challenge, for the owner's Attesta account
+    pub request: &'a RegistrationRequest,
+
+    /// The passkey's response, from `RegistrationRequest::complete`
+    pub registration: &'a Registration,
+
+    /// The new account's policy (`None` for an open account)
+    pub policy: Option<&'a Policy>,
+}
+
+/// The transaction to run once the accounts exist
+#[derive(Debug, Clone)]
+pub struct FirstTransaction<'a> {
+    /// The proof from `complete_execution`
+    pub envelope: &'a ProofEnvelope,
+
+    /// The transaction data that was signed
+    pub transaction_data: Vec<u8>,
+
+    /// For a token transfer, the wallet that owns `destination_ata`
+    ///
+    /// Needed only to create the destination token account if it's missing;
+    /// it must derive to exactly that address.
+    pub token_recipient: Option<Pubkey>,
+}
+
+/// An account a setup step creates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupKind {
+    /// The owner's Attesta account
+    AttestaAccount,
+
+    /// `wallet`'s associated token account for `mint`
+    TokenAccount { wallet: Pubkey, mint: Pubkey },
+}
+
+/// One instruction that creates a missing account
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupStep {
+    /// What it creates
+    pub kind: SetupKind,
+
+    /// The address of the account it creates
+    pub address: Pubkey,
+
+    /// The instruction itself
+    pub instruction: Instruction,
+
+    /// Rent the new account takes from the payer, in lamports
+    pub rent: u64,
+
+    /// Compute units to budget for the instruction
+    compute_units: u32,
+}
+
+/// Everything to send, in order, for a first transaction to go through
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparationPlan {
+    /// The owner, who signs every transaction and pays for all of it
+    pub payer: Pubkey,
+
+    /// The owner's Attesta account
+    pub attesta_account: Pubkey,
+
+    /// The accounts to create first, in order (empty if nothing is missing)
+    pub setup: Vec<SetupStep>,
+
+    /// The `execute` that runs the transaction
+    pub execute: Instruction,
+
+    /// Whether `transactions` packs instructions together
+    bundle: bool,
+}
+
+impl PreparationPlan {
+    /// Sends every instruction in its own transaction instead of packing them
+    ///
+    /// Costs a fee per instruction, but a failure points at one step.
+    pub fn without_bundling(mut self) -> Self {
+        self.bundle = false;
+        self
+    }
+
+    /// Every instruction, setup first, with `execute` last
+    pub fn instructions(&self) -> Vec<Instruction> {
+        self.steps().map(|(instruction, _)| instruction.clone()).collect()
+    }
+
+    /// Rent the plan's new accounts take, in lamports
+    ///
+    /// At the default rent rate, which every public cluster uses.
+    pub fn rent(&self) -> u64 {
+        self.setup.iter().map(|step| step.rent).sum()
+    }
+
+    /// The base fees for every transaction, in lamports (no priority fees)
+    pub fn estimated_fees(&self) -> u64 {
+        // The owner is the only signer
+        self.transactions().len() as u64 * FEE_PER_SIGNATURE
+    }
+
+    /// What the payer needs, in lamports: `rent` plus `estimated_fees`
+    pub fn estimated_cost(&self) -> u64 {
+        self.rent() + self.estimated_fees()
+    }
+
+    /// The instructions grouped into transactions, in the order to send them
+    ///
+    /// Bundled, instructions share a transaction as long as it stays under
+    /// the size and compute limits; `initialize` needs a whole transaction's
+    /// compute, so it always goes alone. A transaction is given a compute
+    /// budget instruction when one of its instructions needs more than the
+    /// default.
+    pub fn transactions(&self) -> Vec<Vec<Instruction>> {
+        let mut transactions = Vec::new();
+        let mut current: Vec<(&Instruction, u32)> = Vec::new();
+
+        for step in self.steps() {
+            current.push(step);
+            let fits = self.bundle
+                && current.iter().map(|(_, units)| units).sum::<u32>() <= MAX_TRANSACTION_COMPUTE_UNITS
+                && transaction_size(&self.payer, &with_budget(&current)) <= MAX_TRANSACTION_SIZE;
+            if current.len() > 1 && !fits {
+                current.pop();
+                transactions.push(with_budget(&current));
+                current = vec![step];
+            }
+        }
+        if !current.is_empty() {
+            transactions.push(with_budget(&current));
+        }
+        transactions
+    }
+
+    fn steps(&self) -> impl Iterator<Item = (&Instruction, u32)> {
+        self.setup
+            .iter()
+            .map(|step| (&step.instruction, step.compute_units))
+            .chain(std::iter::once((&self.execute, DEFAULT_INSTRUCTION_COMPUTE_UNITS)))
+    }
+}
+
+/// The instructions of one transaction, behind a compute budget if they need one
+fn with_budget(steps: &[(&Instruction, u32)]) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(steps.len() + 1);
+    if steps.iter().any(|(_, units)| *units > DEFAULT_INSTRUCTION_COMPUTE_UNITS) {
+        let units: u32 = steps.iter().map(|(_, units)| units).sum();
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(units.min(MAX_TRANSACTION_COMPUTE_UNITS)));
+    }
+    instructions.extend(steps.iter().map(|(instruction, _)| (*instruction).clone()));
+    instructions
+}
+
+/// How far `execute_plan` has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlanProgress {
+    /// Transactions confirmed so far, this one included
+    pub completed: usize,
+
+    /// Transactions in the plan
+    pub total: usize,
+
+    /// The transaction that just confirmed
+    pub signature: Signature,
+}
+
+/// Works out what's missing for `request` and plans around it
+///
+/// See `AttestaClient::prepare_first_transaction`.
+pub(crate) fn plan_first_transaction(
+    backend: &dyn RpcBackend,
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    passkey: &NewAccountPasskey,
+    request: &FirstTransaction,
+) -> Result<PreparationPlan, AttestaError> {
+    TransactionRequest::from_bytes(&request.transaction_data)?;
+    let rent = Rent::default();
+    let (attesta_account, _) = derive_attesta_address(program_id, owner);
+    let mut setup = Vec::new();
+
+    let account_nonce = match backend.get_account_data(&attesta_account)? {
+        Some(data) => decode_attesta_account(&data)?.nonce,
+        None => {
+            if passkey.request.owner != *owner || passkey.request.account_address != attesta_account {
+                return Err(AttestaError::InvalidPlan(format!(
+                    "the passkey was registered for {}, not {}'s account {}",
+                    passkey.request.account_address, owner, attesta_account
+                )));
+            }
+            let instruction = instructions::initialize(
+                program_id,
+                owner,
+                passkey.request.public_key,
+                passkey.request.credential_id.clone(),
+                passkey.policy,
+                false,
+                &passkey.registration.webauthn_sig,
+                Vec::new(),
+            )
+            .map_err(|_| AttestaError::InvalidAccountData)?;
+            setup.push(SetupStep {
+                kind: SetupKind::AttestaAccount,
+                address: attesta_account,
+                instruction,
+                rent: rent.minimum_balance(ATTESTA_ACCOUNT_SPACE),
+                compute_units: INITIALIZE_COMPUTE_UNITS,
+            });
+            0
+        }
+    };
+    if request.envelope.nonce <= account_nonce {
+        return Err(AttestaError::NonceSkipped { nonce: request.envelope.nonce, account_nonce });
+    }
+
+    let execute = match TokenTransfer::from_transaction_data(&request.transaction_data) {
+        Some(transfer) => {
+            if backend.get_account_data(&transfer.destination_ata)?.is_none() {
+                setup.push(create_destination(owner, &transfer, request.token_recipient, &rent)?);
+            }
+            instructions::execute_token_transfer(program_id, &attesta_account, owner, request.envelope, &transfer)
+        }
+        None => instructions::execute(program_id, &attesta_account, owner, request.envelope, request.transaction_data.clone()),
+    }
+    .map_err(|_| AttestaError::InvalidAccountData)?;
+
+    Ok(PreparationPlan { payer: *owner, attesta_account, setup, execute, bundle: true })
+}
+
+/// The step creating a token transfer's missing destination account
+fn create_destination(
+    payer: &Pubkey,
+    transfer: &TokenTransfer,
+    recipient: Option<Pubkey>,
+    rent: &Rent,
+) -> Result<SetupStep, AttestaError> {
+    let wallet = recipient
+        .filter(|wallet| derive_associated_token_address(wallet, &transfer.mint) == transfer.destination_ata)
+        .ok_or_else(|| {
+            AttestaError::InvalidPlan(format!(
+                "token account {} doesn't exist, and no recipient derives to it",
+                transfer.destination_ata
+            ))
+        })?;
+
+    Ok(SetupStep {
+        kind: SetupKind::TokenAccount { wallet, mint: transfer.mint },
+        address: transfer.destination_ata,
+        instruction: create_associated_token_account(payer, &wallet, &transfer.mint),
+        rent: rent.minimum_balance(TOKEN_ACCOUNT_LEN),
+        compute_units: DEFAULT_INSTRUCTION_COMPUTE_UNITS,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_client::solana_sdk::{message::Message, signature::{Keypair, Signer}};
+    use core_crypto::test_utils::TestPasskey;
+    use smart_account::AttestaAccount;
+    use crate::balances::ASSOCIATED_TOKEN_PROGRAM_ID;
+    use crate::client::AttestaClient;
+    use crate::signing::{AssertionResponse, SigningRequest};
+    use crate::test_utils::{attesta_account_data, MockBackend, RpcCall};
+
+    fn assertion(webauthn_sig: core_crypto::WebAuthnSignature) -> AssertionResponse {
+        AssertionResponse {
+            credential_id: webauthn_sig.credential_id,
+            authenticator_data: webauthn_sig.authenticator_data,
+            client_data_json: webauthn_sig.client_data_json,
+            signature: webauthn_sig.signature,
+        }
+    }
+
+    struct Setup {
+        backend: MockBackend,
+        client: AttestaClient,
+        owner: Keypair,
+        attesta_account: Pubkey,
+        registration_request: RegistrationRequest,
+        registration: Registration,
+        recipient: Pubkey,
+        transfer: TokenTransfer,
+        envelope: ProofEnvelope,
+    }
+
+    /// A new owner paying 5 tokens to `recipient`, with the transfer signed at nonce 1
+    fn setup() -> Setup {
+        let backend = MockBackend::new();
+        let program_id = Pubkey::new_unique();
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+        let owner = Keypair::new();
+        let (attesta_account, _) = derive_attesta_address(&program_id, &owner.pubkey());
+        let mut passkey = TestPasskey::new(1);
+
+        let registration_request =
+            RegistrationRequest::new(owner.pubkey(), attesta_account, passkey.public_key(), passkey.credential_id());
+        let registration = registration_request.complete(assertion(passkey.sign_create(&registration_request.challenge))).unwrap();
+
+        let recipient = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let transfer = TokenTransfer {
+            mint,
+            amount: 5_000_000,
+            decimals: 6,
+            destination_ata: derive_associated_token_address(&recipient, &mint),
+        };
+        let account = AttestaAccount::new(owner.pubkey(), passkey.public_key(), passkey.credential_id(), vec![], 0);
+        let signing_request =
+            SigningRequest::new(&account, &TransactionRequest::from_token_transfer(transfer), 0);
+        let envelope = signing_request.complete(assertion(passkey.sign(&signing_request.challenge)), 0).unwrap();
+
+        Setup {
+            backend,
+            client,
+            owner,
+            attesta_account,
+            registration_request,
+            registration,
+            recipient,
+            transfer,
+            envelope,
+        }
+    }
+
+    fn prepare(setup: &Setup) -> Result<PreparationPlan, AttestaError> {
+        setup.client.prepare_first_transaction(
+            &setup.owner.pubkey(),
+            &NewAccountPasskey {
+                request: &setup.registration_request,
+                registration: &setup.registration,
+                policy: None,
+            },
+            &FirstTransaction {
+                envelope: &setup.envelope,
+                transaction_data: setup.transfer.to_transaction_data(),
+                token_recipient: Some(setup.recipient),
+            },
+        )
+    }
+
+    fn existing_account(setup: &Setup) {
+        let account = AttestaAccount::new(setup.owner.pubkey(), [3u8; 64], b"phone".to_vec(), vec![], 0);
+        setup.backend.set_account(setup.attesta_account, 1, attesta_account_data(&account));
+    }
+
+    #[test]
+    fn test_everything_missing() {
+        let setup = setup();
+        let plan = prepare(&setup).unwrap();
+
+        let kinds: Vec<SetupKind> = plan.setup.iter().map(|step| step.kind).collect();
+        assert_eq!(kinds, vec![
+            SetupKind::AttestaAccount,
+            SetupKind::TokenAccount { wallet: setup.recipient, mint: setup.transfer.mint },
+        ]);
+        assert_eq!(plan.setup[0].address, setup.attesta_account);
+        assert_eq!(plan.setup[1].address, setup.transfer.destination_ata);
+        assert_eq!(plan.setup[1].instruction.program_id, ASSOCIATED_TOKEN_PROGRAM_ID);
+        assert_eq!(plan.execute.accounts[0].pubkey, setup.attesta_account);
+
+        let rent = Rent::default();
+        assert_eq!(plan.rent(), rent.minimum_balance(ATTESTA_ACCOUNT_SPACE) + rent.minimum_balance(TOKEN_ACCOUNT_LEN));
+
+        // initialize takes a whole transaction's compute; the rest share one
+        let transactions = plan.transactions();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].len(), 2);
+        assert_eq!(transactions[0][1], plan.setup[0].instruction);
+        assert_eq!(transactions[1], vec![plan.setup[1].instruction.clone(), plan.execute.clone()]);
+        assert_eq!(plan.estimated_fees(), 2 * FEE_PER_SIGNATURE);
+        assert_eq!(plan.estimated_cost(), plan.rent() + 2 * FEE_PER_SIGNATURE);
+
+        let unbundled = plan.clone().without_bundling();
+        assert_eq!(unbundled.transactions().len(), 3);
+        assert_eq!(unbundled.estimated_fees(), 3 * FEE_PER_SIGNATURE);
+        assert_eq!(unbundled.instructions(), plan.instructions());
+    }
+
+    #[test]
+    fn test_some_missing() {
+        let setup = setup();
+        existing_account(&setup);
+        let plan = prepare(&setup).unwrap();
+
+        assert_eq!(plan.setup.len(), 1);
+        assert_eq!(plan.setup[0].kind, SetupKind::TokenAccount { wallet: setup.recipient, mint: setup.transfer.mint });
+        assert_eq!(plan.rent(), Rent::default().minimum_balance(TOKEN_ACCOUNT_LEN));
+        assert_eq!(plan.transactions(), vec![vec![plan.setup[0].instruction.clone(), plan.execute.clone()]]);
+
+        // A recipient the destination doesn't derive from can't create it
+        let error = setup
+            .client
+            .prepare_first_transaction(
+                &setup.owner.pubkey(),
+                &NewAccountPasskey { request: &setup.registration_request, registration: &setup.registration, policy: None },
+                &FirstTransaction {
+                    envelope: &setup.envelope,
+                    transaction_data: setup.transfer.to_transaction_data(),
+                    token_recipient: Some(Pubkey::new_unique()),
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(error, AttestaError::InvalidPlan(_)));
+    }
+
+    #[test]
+    fn test_nothing_missing() {
+        let setup = setup();
+        existing_account(&setup);
+        setup.backend.set_account(setup.transfer.destination_ata, 2_039_280, vec![0; TOKEN_ACCOUNT_LEN]);
+        let plan = prepare(&setup).unwrap();
+
+        assert!(plan.setup.is_empty());
+        assert_eq!(plan.rent(), 0);
+        assert_eq!(plan.transactions(), vec![vec![plan.execute.clone()]]);
+        assert_eq!(plan.estimated_cost(), FEE_PER_SIGNATURE);
+
+        let mut progress = Vec::new();
+        let signatures = setup.client.execute_plan(&plan, &setup.owner, |step| progress.push(step)).unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(progress, vec![PlanProgress { completed: 1, total: 1, signature: signatures[0] }]);
+    }
+
+    #[test]
+    fn test_execute_plan_sends_in_order() {
+        let setup = setup();
+        let plan = prepare(&setup).unwrap();
+
+        let mut progress = Vec::new();
+        let signatures = setup.client.execute_plan(&plan, &setup.owner, |step| progress.push(step)).unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(progress.iter().map(|step| (step.completed, step.total)).collect::<Vec<_>>(), vec![(1, 2), (2, 2)]);
+
+        let sent = setup.backend.sent_transactions();
+        assert_eq!(sent.len(), 2);
+        for (transaction, expected) in sent.iter().zip(plan.transactions()) {
+            assert_eq!(transaction.message, Message::new_with_blockhash(&expected, Some(&plan.payer), &transaction.message.recent_blockhash));
+        }
+
+        // Only the owner can send it
+        let error = setup.client.execute_plan(&plan, &Keypair::new(), |_| {}).unwrap_err();
+        assert!(matches!(error, AttestaError::InvalidPlan(_)));
+    }
+
+    #[test]
+    fn test_passed_over_nonce_is_refused() {
+        let setup = setup();
+        let mut account = AttestaAccount::new(setup.owner.pubkey(), [3u8; 64], b"phone".to_vec(), vec![], 0);
+        account.nonce = 1;
+        setup.backend.set_account(setup.attesta_account, 1, attesta_account_data(&account));
+
+        let error = prepare(&setup).unwrap_err();
+        assert!(matches!(error, AttestaError::NonceSkipped { nonce: 1, account_nonce: 1 }));
+        assert!(!setup.backend.calls().contains(&RpcCall::GetAccountData(setup.transfer.destination_ata)));
+    }
+
+    #[test]
+    fn test_account_space_matches_the_program() {
+        assert_eq!(ATTESTA_ACCOUNT_SPACE, 648 + IDEMPOTENCY_RECORDS_SPACE);
+    }
+}
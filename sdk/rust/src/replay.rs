@@ -0,0 +1,856 @@
+//! Rebuilding an account's state from its transaction history
+//!
+//! `AttestaClient::reconstruct_account` fetches an account's transactions
+//! and replays the Attesta instructions in them, in order, through the same
+//! `smart_account` functions the program runs. `diff_against_chain` then
+//! compares the result with the live account, so a migration or a bug that
+//! left an account in a state its history doesn't explain shows up field
+//! by field.
+//!
+//! Replayed: `initialize`, `execute` and `execute_as_owner`, `update_policy`
+//! and the policy list instructions, `add_passkey`, `remove_passkey`,
+//! `set_auth_mode`, `add_executor`, `remove_executor`, and inheritance claims
+//! (from the `InheritanceClaimed` event they emit). Any other instruction
+//! that writes the account is reported as a warning rather than skipped
+//! silently, since the rebuilt state can't account for it. Transactions
+//! that failed on-chain changed nothing and are passed over.
+
+use std::fmt;
+use attesta_types::instructions::{decode_execute, decode_update_policy, ExecuteArgs, InitializeArgs, UpdatePolicyArgs};
+use anchor_client::solana_sdk::signature::Signature;
+use base64::Engine;
+use borsh::BorshDeserialize;
+use core_crypto::WebAuthnSignature;
+use recovery::multi_passkey::{PasskeyEntry, PASSKEY_ADD_ACTION, PASSKEY_REMOVE_ACTION};
+use smart_account::{
+    auth_mode, authorize_action, executors, execute_transaction_at, redeem_claim, inheritance, policy_list, verify_registration, AccountSettings,
+    AttestaAccount, AuthMode, AuthorizationProof, ClaimTicket, DenyReason, PolicyResult,
+};
+use solana_program::pubkey::Pubkey;
+use crate::backend::ConfirmedTransaction;
+use crate::instructions::{event_discriminator, instruction_discriminator};
+
+/// Program instructions that can write an account but aren't replayed
+const NOT_REPLAYED: &[&str] = &[
+    "store_backup",
+    "enable_proof_log",
+    "update_backup",
+    "delete_backup",
+    "enable_privacy_mode",
+    "initialize_sub_account",
+    "update_settings",
+    "initiate_recovery",
+    "approve_recovery",
+    "finalize_recovery",
+    "cancel_recovery",
+    "initiate_recovery_drill",
+    "approve_recovery_drill",
+    "configure_inheritance",
+    "heartbeat",
+    "get_program_version",
+    "get_program_features",
+    "get_protocol_constants",
+    "acknowledge_upgrade",
+    "schedule_transaction",
+    "execute_scheduled",
+    "cancel_scheduled",
+    "approve_proposal",
+    "cancel_proposal",
+    "attest_policy",
+    "configure_emergency_override",
+    "execute_emergency",
+    "execute_announced",
+    "veto_announcement",
+];
+
+/// Program instructions `replay_transactions` applies
+const REPLAYED: &[&str] = &[
+    "initialize",
+    "initialize_with_index",
+    "sponsored_initialize",
+    "execute",
+    "execute_as_owner",
+    "update_policy",
+    "add_policy",
+    "remove_policy",
+    "replace_policy",
+    "add_passkey",
+    "remove_passkey",
+    "claim_inheritance",
+    "set_auth_mode",
+    "add_executor",
+    "remove_executor",
+    "claim",
+];
+
+/// Prefix Anchor logs emitted events with
+const EVENT_LOG_PREFIX: &str = "Program data: ";
+
+/// What went wrong replaying one instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayWarningKind {
+    /// An instruction for the program that the SDK doesn't know
+    UnknownInstruction([u8; 8]),
+
+    /// A known instruction that writes the account, but isn't replayed
+    NotReplayed(&'static str),
+
+    /// An instruction that came before the account's `initialize`
+    BeforeInitialize(&'static str),
+
+    /// The instruction's arguments didn't decode
+    InvalidArguments(&'static str),
+
+    /// It succeeded on-chain, but fails when replayed
+    Rejected { instruction: &'static str, reason: String },
+}
+
+/// An instruction in the history that the rebuilt state doesn't reflect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayWarning {
+    /// The transaction it's in
+    pub signature: Signature,
+
+    /// Its position in the transaction
+    pub instruction_index: usize,
+
+    /// What went wrong
+    pub kind: ReplayWarningKind,
+}
+
+impl fmt::Display for ReplayWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} #{}: ", self.signature, self.instruction_index)?;
+        match &self.kind {
+            ReplayWarningKind::UnknownInstruction(discriminator) => {
+                write!(f, "unknown instruction {:02x?}", discriminator)
+            }
+            ReplayWarningKind::NotReplayed(name) => write!(f, "`{}` isn't replayed", name),
+            ReplayWarningKind::BeforeInitialize(name) => write!(f, "`{}` before the account was initialized", name),
+            ReplayWarningKind::InvalidArguments(name) => write!(f, "`{}` has invalid arguments", name),
+            ReplayWarningKind::Rejected { instruction, reason } => {
+                write!(f, "`{}` fails when replayed: {}", instruction, reason)
+            }
+        }
+    }
+}
+
+/// An account's state as its history says it should be
+#[derive(Debug, Clone, Default)]
+pub struct ReconstructedState {
+    /// The rebuilt account (`None` if the history has no `initialize` for it)
+    pub account: Option<AttestaAccount>,
+
+    /// Transactions that changed the rebuilt account, in order
+    pub applied: Vec<Signature>,
+
+    /// Instructions the rebuilt account doesn't reflect, in order
+    pub warnings: Vec<ReplayWarning>,
+}
+
+/// A field whose live value isn't what the history explains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    /// The `AttestaAccount` field
+    pub field: &'static str,
+
+    /// Its value in the rebuilt account
+    pub reconstructed: String,
+
+    /// Its value on-chain
+    pub live: String,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: expected {}, found {}", self.field, self.reconstructed, self.live)
+    }
+}
+
+/// Replays an account's transactions, oldest first
+///
+/// # Parameters
+/// - `program_id`: The Attesta program ID
+/// - `address`: The account to rebuild
+/// - `transactions`: Its history, in the order it executed
+///
+/// Instructions are replayed at their transaction's block time (or the
+/// account's last update, if the block time is unknown).
+pub fn replay_transactions(program_id: &Pubkey, address: &Pubkey, transactions: &[ConfirmedTransaction]) -> ReconstructedState {
+    let mut state = ReconstructedState::default();
+
+    for transaction in transactions.iter().filter(|transaction| transaction.err.is_none()) {
+        let message = &transaction.transaction.message;
+        let mut changed = false;
+
+        for (index, instruction) in message.instructions.iter().enumerate() {
+            if message.account_keys.get(usize::from(instruction.program_id_index)) != Some(program_id) {
+                continue;
+            }
+            let writes_account = instruction.accounts.iter().any(|&key| {
+                let key = usize::from(key);
+                message.account_keys.get(key) == Some(address) && message.is_writable(key)
+            });
+            if !writes_account {
+                continue;
+            }
+
+            let accounts: Vec<Pubkey> = instruction
+                .accounts
+                .iter()
+                .filter_map(|&key| message.account_keys.get(usize::from(key)).copied())
+                .collect();
+            let step = Step { address, accounts: &accounts, data: &instruction.data, transaction };
+
+            match step.apply(&mut state.account) {
+                Ok(()) => changed = true,
+                Err(kind) => state.warnings.push(ReplayWarning {
+                    signature: transaction.signature,
+                    instruction_index: index,
+                    kind,
+                }),
+            }
+        }
+
+        if changed {
+            state.applied.push(transaction.signature);
+        }
+    }
+
+    state
+}
+
+/// Compares a rebuilt account with the live one
+///
+/// # Returns
+/// Every field that differs, in declaration order (empty if they match)
+pub fn diff_against_chain(reconstructed: &AttestaAccount, live: &AttestaAccount) -> Vec<FieldDiff> {
+    let mut diffs = Vec::new();
+    let (r, l) = (reconstructed, live);
+    compare(&mut diffs, "owner", &r.owner, &l.owner);
+    compare(&mut diffs, "passkey_public_key", &r.passkey_public_key, &l.passkey_public_key);
+    compare(&mut diffs, "credential_id", &r.credential_id, &l.credential_id);
+    compare(&mut diffs, "nonce", &r.nonce, &l.nonce);
+    compare(&mut diffs, "policy", &r.policy(), &l.policy());
+    compare(&mut diffs, "created_at", &r.created_at, &l.created_at);
+    compare(&mut diffs, "updated_at", &r.updated_at, &l.updated_at);
+    compare(&mut diffs, "passkeys", &r.passkeys(), &l.passkeys());
+    compare(&mut diffs, "privacy_mode", &r.privacy_mode, &l.privacy_mode);
+    compare(&mut diffs, "idempotency_records", &r.idempotency_records, &l.idempotency_records);
+    compare(&mut diffs, "pending_recovery", &r.pending_recovery, &l.pending_recovery);
+    compare(&mut diffs, "pending_drill", &r.pending_drill, &l.pending_drill);
+    compare(&mut diffs, "last_drill_at", &r.last_drill_at, &l.last_drill_at);
+    compare(&mut diffs, "settings", &r.settings, &l.settings);
+    compare(&mut diffs, "parent", &r.parent, &l.parent);
+    compare(&mut diffs, "sub_account_index", &r.sub_account_index, &l.sub_account_index);
+    compare(&mut diffs, "inheritance", &r.inheritance, &l.inheritance);
+    compare(&mut diffs, "last_execution_at", &r.last_execution_at, &l.last_execution_at);
+    compare(&mut diffs, "failed_auth_count", &r.failed_auth_count, &l.failed_auth_count);
+    compare(&mut diffs, "locked_until", &r.locked_until, &l.locked_until);
+    compare(&mut diffs, "key_history", &r.key_history, &l.key_history);
+    compare(&mut diffs, "proof_log_enabled", &r.proof_log_enabled, &l.proof_log_enabled);
+    compare(&mut diffs, "additional_policies", &r.additional_policies, &l.additional_policies);
+    compare(&mut diffs, "passkey_aaguid", &r.passkey_aaguid, &l.passkey_aaguid);
+    compare(&mut diffs, "policy_hash", &r.policy_hash, &l.policy_hash);
+    compare(&mut diffs, "state_version", &r.state_version, &l.state_version);
+    diffs
+}
+
+fn compare<T: PartialEq + fmt::Debug>(diffs: &mut Vec<FieldDiff>, field: &'static str, reconstructed: &T, live: &T) {
+    if reconstructed != live {
+        diffs.push(FieldDiff { field, reconstructed: format!("{:?}", reconstructed), live: format!("{:?}", live) });
+    }
+}
+
+/// One program instruction that writes the account being rebuilt
+struct Step<'a> {
+    address: &'a Pubkey,
+    accounts: &'a [Pubkey],
+    data: &'a [u8],
+    transaction: &'a ConfirmedTransaction,
+}
+
+impl Step<'_> {
+    /// Applies the instruction to `account`, which is left alone if it fails
+    fn apply(&self, account: &mut Option<AttestaAccount>) -> Result<(), ReplayWarningKind> {
+        let name = self.instruction_name()?;
+        if NOT_REPLAYED.contains(&name) {
+            return Err(ReplayWarningKind::NotReplayed(name));
+        }
+        let args = &self.data[8..];
+
+        let mut next = match (name, account.as_ref()) {
+            ("initialize" | "initialize_with_index" | "sponsored_initialize", None) => {
+                *account = Some(self.initialize(name, args)?);
+                return Ok(());
+            }
+            ("initialize" | "initialize_with_index" | "sponsored_initialize", Some(_)) => {
+                return Err(rejected(name, "account already initialized"))
+            }
+            (_, None) => return Err(ReplayWarningKind::BeforeInitialize(name)),
+            (_, Some(current)) => current.clone(),
+        };
+        let now = self.transaction.block_time.unwrap_or(next.updated_at);
+
+        match name {
+            "execute" => {
+                let ExecuteArgs { webauthn_sig, nonce, message_hash, transaction_data, idempotency_key, memo, .. } =
+                    decode_execute(self.data).map_err(|_| ReplayWarningKind::InvalidArguments(name))?;
+                let mut proof = AuthorizationProof::new(signature(name, &webauthn_sig)?, nonce, message_hash);
+                proof.idempotency_key = idempotency_key;
+                proof.memo = memo;
+                let result = execute_transaction_at(&mut next, self.address, None, &proof, &transaction_data, now)
+                    .map_err(|e| rejected(name, e))?;
+                // The program fails the instruction for anything else, so this
+                // transaction couldn't have succeeded
+                if !matches!(
+                    result,
+                    PolicyResult::Allowed
+                        | PolicyResult::AlreadyExecuted { .. }
+                        | PolicyResult::Announced { .. }
+                        | PolicyResult::Denied(DenyReason::AuthenticationFailed | DenyReason::LockedOut { .. })
+                ) {
+                    return Err(rejected(name, format!("{:?}", result)));
+                }
+            }
+            "execute_as_owner" => {
+                let (nonce, transaction_data) = decode::<(u64, Vec<u8>)>(name, args)?;
+                let owner = self.accounts.get(1).ok_or(ReplayWarningKind::InvalidArguments(name))?;
+                let result = auth_mode::execute_as_owner(&mut next, self.address, None, owner, nonce, &transaction_data, now)
+                    .map_err(|e| rejected(name, e))?;
+                if result != PolicyResult::Allowed {
+                    return Err(rejected(name, format!("{:?}", result)));
+                }
+            }
+            "claim" => {
+                let ticket = decode::<Vec<u8>>(name, args)?;
+                let ticket = ClaimTicket::from_bytes(&ticket).map_err(|_| ReplayWarningKind::InvalidArguments(name))?;
+                let destination = self.accounts.get(2).ok_or(ReplayWarningKind::InvalidArguments(name))?;
+                let result = redeem_claim(&mut next, self.address, destination, &ticket, now).map_err(|e| rejected(name, e))?;
+                if result != PolicyResult::Allowed {
+                    return Err(rejected(name, format!("{:?}", result)));
+                }
+            }
+            "set_auth_mode" => {
+                let (webauthn_sig, nonce, mode, lock) = decode::<(Vec<u8>, u64, u8, bool)>(name, args)?;
+                let mode = AuthMode::from_u8(mode).ok_or(ReplayWarningKind::InvalidArguments(name))?;
+                auth_mode::set_auth_mode(&mut next, signature(name, &webauthn_sig)?, nonce, mode, lock)
+                    .map_err(|e| rejected(name, e))?;
+                next.updated_at = now;
+            }
+            "add_executor" | "remove_executor" => {
+                let (webauthn_sig, nonce, executor) = decode::<(Vec<u8>, u64, Pubkey)>(name, args)?;
+                let webauthn_sig = signature(name, &webauthn_sig)?;
+                if name == "add_executor" {
+                    executors::add_executor(&mut next, webauthn_sig, nonce, &executor)
+                } else {
+                    executors::remove_executor(&mut next, webauthn_sig, nonce, &executor)
+                }
+                .map_err(|e| rejected(name, e))?;
+                next.updated_at = now;
+            }
+            "update_policy" => {
+                let UpdatePolicyArgs { new_policy, .. } =
+                    decode_update_policy(self.data).map_err(|_| ReplayWarningKind::InvalidArguments(name))?;
+                next.set_policies(vec![new_policy]);
+                next.bump_state_version();
+            }
+            "add_policy" | "replace_policy" => {
+                let (webauthn_sig, nonce, index, policy, allow_long_lock) =
+                    decode::<(Vec<u8>, u64, u8, Vec<u8>, bool)>(name, args)?;
+                let webauthn_sig = signature(name, &webauthn_sig)?;
+                let result = if name == "add_policy" {
+                    policy_list::add_policy(&mut next, webauthn_sig, nonce, index, policy, now, allow_long_lock)
+                } else {
+                    policy_list::replace_policy(&mut next, webauthn_sig, nonce, index, policy, now, allow_long_lock)
+                };
+                result.map_err(|e| rejected(name, e))?;
+                next.updated_at = now;
+            }
+            "remove_policy" => {
+                let (webauthn_sig, nonce, index) = decode::<(Vec<u8>, u64, u8)>(name, args)?;
+                policy_list::remove_policy(&mut next, signature(name, &webauthn_sig)?, nonce, index)
+                    .map_err(|e| rejected(name, e))?;
+                next.updated_at = now;
+            }
+            "add_passkey" => self.add_passkey(&mut next, args, now)?,
+            "remove_passkey" => self.remove_passkey(&mut next, args, now)?,
+            "claim_inheritance" => {
+                let claimed_at = self
+                    .inheritance_claimed_at()
+                    .ok_or_else(|| rejected(name, "no InheritanceClaimed event"))?;
+                inheritance::claim_inheritance(&mut next, claimed_at).map_err(|e| rejected(name, e))?;
+            }
+            _ => unreachable!("every replayed instruction is handled"),
+        }
+
+        *account = Some(next);
+        Ok(())
+    }
+
+    fn instruction_name(&self) -> Result<&'static str, ReplayWarningKind> {
+        let mut discriminator = [0u8; 8];
+        let prefix = self.data.get(..8).ok_or(ReplayWarningKind::UnknownInstruction(discriminator))?;
+        discriminator.copy_from_slice(prefix);
+        REPLAYED
+            .iter()
+            .chain(NOT_REPLAYED)
+            .find(|name| instruction_discriminator(name) == discriminator)
+            .copied()
+            .ok_or(ReplayWarningKind::UnknownInstruction(discriminator))
+    }
+
+    /// Builds the account an `initialize` or one of its variants created
+    fn initialize(&self, name: &'static str, args: &[u8]) -> Result<AttestaAccount, ReplayWarningKind> {
+        // Every form takes the same arguments; the indexed one puts its index first
+        let (account_index, args) = if name == "initialize_with_index" {
+            let (account_index, args) = decode::<(u16, InitializeArgs)>(name, args)?;
+            (Some(account_index), args)
+        } else {
+            (None, decode::<InitializeArgs>(name, args)?)
+        };
+        let InitializeArgs { passkey_public_key: public_key, credential_id, policy, privacy_mode, registration_sig, aaguid_allowlist } =
+            args;
+        // The sponsored form puts the pool first
+        let owner_index = if name == "sponsored_initialize" { 2 } else { 1 };
+        let owner = *self.accounts.get(owner_index).ok_or(ReplayWarningKind::InvalidArguments(name))?;
+        let now = self.transaction.block_time.unwrap_or_default();
+
+        let registration = signature(name, &registration_sig)?;
+        let aaguid = verify_registration(&owner, self.address, &public_key, &credential_id, &registration)
+            .map_err(|e| rejected(name, e))?;
+        let mut account = AttestaAccount::new(owner, public_key, credential_id, policy, now);
+        account.settings = AccountSettings { aaguid_allowlist, ..AccountSettings::default() };
+        account.passkey_aaguid = aaguid;
+        account.account_index = account_index;
+        if privacy_mode {
+            account.enable_privacy_mode().map_err(|e| rejected(name, e))?;
+        }
+        Ok(account)
+    }
+
+    fn add_passkey(&self, account: &mut AttestaAccount, args: &[u8], now: i64) -> Result<(), ReplayWarningKind> {
+        let name = "add_passkey";
+        let (webauthn_sig, nonce, public_key, credential_id, label, registration_sig, _expected_version) =
+            decode::<(Vec<u8>, u64, [u8; 64], Vec<u8>, String, Vec<u8>, u64)>(name, args)?;
+
+        let aaguid = if registration_sig.is_empty() {
+            None
+        } else {
+            let registration = signature(name, &registration_sig)?;
+            verify_registration(&account.owner, self.address, &public_key, &credential_id, &registration)
+                .map_err(|e| rejected(name, e))?
+        };
+        let payload = [public_key.as_ref(), credential_id.as_slice()].concat();
+        authorize_action(account, signature(name, &webauthn_sig)?, nonce, PASSKEY_ADD_ACTION, &payload)
+            .map_err(|e| rejected(name, e))?;
+        account.updated_at = now;
+
+        let mut registry = account.passkey_registry_or_default().map_err(|e| rejected(name, e))?;
+        let lookup_id = account.credential_lookup_id(&credential_id);
+        registry
+            .add_entry(PasskeyEntry::compact(public_key, lookup_id, label, now).with_aaguid(aaguid))
+            .map_err(|e| rejected(name, e))?;
+        account.set_passkey_registry(&registry).map_err(|e| rejected(name, e))?;
+        account.bump_state_version();
+        Ok(())
+    }
+
+    fn remove_passkey(&self, account: &mut AttestaAccount, args: &[u8], now: i64) -> Result<(), ReplayWarningKind> {
+        let name = "remove_passkey";
+        let (webauthn_sig, nonce, credential_id, _expected_version) = decode::<(Vec<u8>, u64, Vec<u8>, u64)>(name, args)?;
+
+        authorize_action(account, signature(name, &webauthn_sig)?, nonce, PASSKEY_REMOVE_ACTION, &credential_id)
+            .map_err(|e| rejected(name, e))?;
+        account.updated_at = now;
+
+        let mut registry = account
+            .passkey_registry()
+            .map_err(|e| rejected(name, e))?
+            .ok_or_else(|| rejected(name, "account has no passkey registry"))?;
+        let lookup_id = account.credential_lookup_id(&credential_id);
+        let removed_key = registry.find_passkey(&lookup_id).map(|entry| entry.public_key);
+        registry.remove_passkey(&lookup_id, now).map_err(|e| rejected(name, e))?;
+        account.set_passkey_registry(&registry).map_err(|e| rejected(name, e))?;
+        if let Some(public_key) = removed_key {
+            account.retire_key(&lookup_id, public_key, now);
+        }
+        account.bump_state_version();
+        Ok(())
+    }
+
+    /// When the transaction's `InheritanceClaimed` event says this account was claimed
+    fn inheritance_claimed_at(&self) -> Option<i64> {
+        let discriminator = event_discriminator("InheritanceClaimed");
+        self.transaction
+            .logs
+            .iter()
+            .filter_map(|log| log.strip_prefix(EVENT_LOG_PREFIX))
+            .filter_map(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+            .filter_map(|data| data.strip_prefix(&discriminator[..]).and_then(|body| <(Pubkey, i64)>::try_from_slice(body).ok()))
+            .find(|(claimed, _)| claimed == self.address)
+            .map(|(_, timestamp)| timestamp)
+    }
+}
+
+fn decode<T: BorshDeserialize>(name: &'static str, args: &[u8]) -> Result<T, ReplayWarningKind> {
+    T::try_from_slice(args).map_err(|_| ReplayWarningKind::InvalidArguments(name))
+}
+
+fn signature(name: &'static str, bytes: &[u8]) -> Result<WebAuthnSignature, ReplayWarningKind> {
+    WebAuthnSignature::from_bytes(bytes).map_err(|_| ReplayWarningKind::InvalidArguments(name))
+}
+
+fn rejected(instruction: &'static str, reason: impl fmt::Display) -> ReplayWarningKind {
+    ReplayWarningKind::Rejected { instruction, reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_client::solana_sdk::transaction::Transaction;
+    use core_crypto::{compute_challenge, test_utils::TestPasskey};
+    use recovery::{Amount, Policy};
+    use smart_account::{action_message_hash, auth_mode_payload, registration_challenge, transaction_message_hash, AUTH_MODE_ACTION};
+    use solana_program::instruction::Instruction;
+    use crate::instructions::{self, derive_attesta_address};
+    use crate::ProofEnvelope;
+
+    struct History {
+        program_id: Pubkey,
+        owner: Pubkey,
+        address: Pubkey,
+        phone: TestPasskey,
+        transactions: Vec<ConfirmedTransaction>,
+    }
+
+    impl History {
+        fn new() -> Self {
+            let (program_id, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+            let address = derive_attesta_address(&program_id, &owner).0;
+            Self { program_id, owner, address, phone: TestPasskey::new(1), transactions: Vec::new() }
+        }
+
+        fn push(&mut self, instructions: &[Instruction], err: Option<&str>, logs: Vec<String>) -> Signature {
+            let signature = Signature::from([self.transactions.len() as u8 + 1; 64]);
+            self.transactions.push(ConfirmedTransaction {
+                signature,
+                slot: 100 + self.transactions.len() as u64,
+                block_time: Some(1_700_000_000 + 60 * self.transactions.len() as i64),
+                transaction: Transaction::new_with_payer(instructions, Some(&self.owner)),
+                err: err.map(str::to_string),
+                logs,
+            });
+            signature
+        }
+
+        fn sign(passkey: &mut TestPasskey, owner: &Pubkey, nonce: u64, message_hash: &[u8; 32]) -> WebAuthnSignature {
+            passkey.sign(&compute_challenge(owner, nonce, message_hash))
+        }
+
+        fn initialize(&mut self) -> Instruction {
+            let challenge = registration_challenge(&self.owner, &self.address, &self.phone.public_key(), &self.phone.credential_id());
+            let registration = self.phone.sign_create(&challenge);
+            instructions::initialize(&self.program_id, &self.owner, self.phone.public_key(), self.phone.credential_id(), None, false, &registration, vec![]).unwrap()
+        }
+
+        fn execute(&self, signer: &mut TestPasskey, nonce: u64, data: Vec<u8>) -> Instruction {
+            let message_hash = transaction_message_hash(&data);
+            let envelope = ProofEnvelope {
+                webauthn_sig: Self::sign(signer, &self.owner, nonce, &message_hash),
+                nonce,
+                message_hash,
+                idempotency_key: [nonce as u8; 16],
+                parent_account: None,
+                logs_proofs: false,
+                memo: Vec::new(),
+                emit_memo: false,
+            };
+            instructions::execute(&self.program_id, &self.address, &self.owner, &envelope, data).unwrap()
+        }
+    }
+
+    /// initialize, an execute, a policy change, a second passkey that then
+    /// executes, plus a failed transaction, an unknown instruction, and one
+    /// that isn't replayed
+    fn synthetic_history() -> (History, TestPasskey) {
+        let mut history = History::new();
+        let mut laptop = TestPasskey::new(2);
+
+        let initialize = history.initialize();
+        history.push(&[initialize], None, vec![]);
+
+        let mut phone = TestPasskey::new(1);
+        let execute = history.execute(&mut phone, 1, vec![1, 2, 3]);
+        history.push(&[execute], None, vec![]);
+
+        let policy = Policy::spending_limit(Amount::from_sol(1.0).unwrap());
+        let update = instructions::update_policy(&history.program_id, &history.address, &history.owner, Some(&policy), 0).unwrap();
+        history.push(&[update], None, vec![]);
+
+        let payload = [laptop.public_key().as_ref(), laptop.credential_id().as_slice()].concat();
+        let add_hash = action_message_hash(PASSKEY_ADD_ACTION, &payload);
+        let add = instructions::add_passkey(
+            &history.program_id,
+            &history.address,
+            &history.owner,
+            &History::sign(&mut phone, &history.owner, 2, &add_hash),
+            2,
+            laptop.public_key(),
+            laptop.credential_id(),
+            "Laptop".to_string(),
+            None,
+            1,
+        )
+        .unwrap();
+        history.push(&[add], None, vec![]);
+
+        // Failed on-chain, so it changed nothing
+        let failed = history.execute(&mut phone, 3, vec![9]);
+        history.push(&[failed], Some("InstructionError(0, Custom(6000))"), vec![]);
+
+        let mut unknown = history.execute(&mut phone, 3, vec![9]);
+        unknown.data[..8].copy_from_slice(&[0xee; 8]);
+        history.push(&[unknown], None, vec![]);
+
+        let mut heartbeat = history.execute(&mut phone, 3, vec![9]);
+        heartbeat.data = instruction_discriminator("heartbeat").to_vec();
+        history.push(&[heartbeat], None, vec![]);
+
+        let execute = history.execute(&mut laptop, 3, vec![4, 5]);
+        history.push(&[execute], None, vec![]);
+
+        (history, laptop)
+    }
+
+    #[test]
+    fn test_replay_rebuilds_the_account() {
+        let (history, laptop) = synthetic_history();
+        let state = replay_transactions(&history.program_id, &history.address, &history.transactions);
+
+        let account = state.account.unwrap();
+        assert_eq!(account.owner, history.owner);
+        assert_eq!(account.nonce, 3);
+        assert_eq!(account.created_at, 1_700_000_000);
+        assert_eq!(account.updated_at, 1_700_000_000 + 60 * 7);
+        assert_eq!(account.policies().len(), 1);
+        // The policy update and the new passkey
+        assert_eq!(account.state_version, 2);
+        assert!(account.passkey_registry().unwrap().unwrap().find_passkey(&laptop.credential_id()).is_some());
+        assert_eq!(account.idempotency_records.len(), 2);
+
+        let signatures = |indices: &[u8]| indices.iter().map(|i| Signature::from([*i; 64])).collect::<Vec<_>>();
+        assert_eq!(state.applied, signatures(&[1, 2, 3, 4, 8]));
+        assert_eq!(
+            state.warnings.iter().map(|warning| (warning.signature, warning.kind.clone())).collect::<Vec<_>>(),
+            vec![
+                (Signature::from([6; 64]), ReplayWarningKind::UnknownInstruction([0xee; 8])),
+                (Signature::from([7; 64]), ReplayWarningKind::NotReplayed("heartbeat")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_corrupted_live_state_is_diffed() {
+        let (history, _) = synthetic_history();
+        let reconstructed = replay_transactions(&history.program_id, &history.address, &history.transactions).account.unwrap();
+
+        let mut live = reconstructed.clone();
+        assert!(diff_against_chain(&reconstructed, &live).is_empty());
+
+        live.nonce = 1;
+        live.set_policies(vec![]);
+        live.failed_auth_count = 4;
+        let diffs = diff_against_chain(&reconstructed, &live);
+
+        assert_eq!(diffs.iter().map(|diff| diff.field).collect::<Vec<_>>(), vec!["nonce", "policy", "failed_auth_count", "policy_hash"]);
+        assert_eq!(diffs[0], FieldDiff { field: "nonce", reconstructed: "3".to_string(), live: "1".to_string() });
+        assert_eq!(diffs[2].to_string(), "failed_auth_count: expected 0, found 4");
+    }
+
+    #[test]
+    fn test_history_without_initialize_and_forged_proofs_are_reported() {
+        let (mut history, _) = synthetic_history();
+        history.transactions.remove(0);
+        let state = replay_transactions(&history.program_id, &history.address, &history.transactions);
+        assert!(state.account.is_none());
+        assert!(state.warnings.iter().any(|warning| warning.kind == ReplayWarningKind::BeforeInitialize("execute")));
+
+        // A proof the replayed account wouldn't accept
+        let (mut history, mut laptop) = synthetic_history();
+        let forged = history.execute(&mut laptop, 1, vec![7]);
+        history.transactions.truncate(2);
+        let signature = history.push(&[forged], None, vec![]);
+        let state = replay_transactions(&history.program_id, &history.address, &history.transactions);
+        assert_eq!(state.account.unwrap().nonce, 1);
+        assert!(matches!(
+            &state.warnings[..],
+            [ReplayWarning { signature: s, kind: ReplayWarningKind::Rejected { instruction: "execute", .. }, .. }] if *s == signature
+        ));
+    }
+
+    #[test]
+    fn test_owner_signed_executions_are_replayed() {
+        let mut history = History::new();
+        let mut phone = TestPasskey::new(1);
+        let initialize = history.initialize();
+        history.push(&[initialize], None, vec![]);
+
+        let mode_hash = action_message_hash(AUTH_MODE_ACTION, &auth_mode_payload(AuthMode::OwnerOrPasskey, false));
+        let webauthn_sig = History::sign(&mut phone, &history.owner, 1, &mode_hash);
+        let set_mode = instructions::set_auth_mode(
+            &history.program_id,
+            &history.address,
+            &history.owner,
+            &webauthn_sig,
+            1,
+            AuthMode::OwnerOrPasskey,
+            false,
+        )
+        .unwrap();
+        history.push(&[set_mode], None, vec![]);
+        let by_owner = instructions::execute_as_owner(&history.program_id, &history.address, &history.owner, None, 2, vec![1]).unwrap();
+        history.push(&[by_owner], None, vec![]);
+        let by_passkey = history.execute(&mut phone, 3, vec![2]);
+        history.push(&[by_passkey], None, vec![]);
+        // Someone else's wallet can't have executed
+        let by_stranger = instructions::execute_as_owner(&history.program_id, &history.address, &Pubkey::new_unique(), None, 4, vec![3]).unwrap();
+        history.push(&[by_stranger], None, vec![]);
+
+        let state = replay_transactions(&history.program_id, &history.address, &history.transactions);
+        let account = state.account.unwrap();
+        assert_eq!(account.nonce, 3);
+        assert_eq!(account.settings.auth_mode, AuthMode::OwnerOrPasskey);
+        assert!(matches!(
+            &state.warnings[..],
+            [ReplayWarning { kind: ReplayWarningKind::Rejected { instruction: "execute_as_owner", .. }, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_claimed_tickets_are_replayed() {
+        let mut history = History::new();
+        let mut phone = TestPasskey::new(1);
+        let initialize = history.initialize();
+        history.push(&[initialize], None, vec![]);
+
+        let mut ticket = ClaimTicket {
+            account: history.address,
+            claim_key: Pubkey::new_unique(),
+            amount: 1_000,
+            expires_at: 1_800_000_000,
+            nonce: 2,
+            webauthn_sig: WebAuthnSignature::new(vec![], vec![], vec![], vec![]),
+        };
+        ticket.webauthn_sig = History::sign(&mut phone, &history.owner, 2, &ticket.message_hash());
+        let claim = instructions::claim(&history.program_id, &ticket, &Pubkey::new_unique()).unwrap();
+        history.push(std::slice::from_ref(&claim), None, vec![]);
+        // The same ticket again can't have paid out twice
+        history.push(&[claim], None, vec![]);
+
+        let state = replay_transactions(&history.program_id, &history.address, &history.transactions);
+        assert_eq!(state.account.unwrap().nonce, 2);
+        assert!(matches!(
+            &state.warnings[..],
+            [ReplayWarning { kind: ReplayWarningKind::Rejected { instruction: "claim", .. }, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_sponsored_initialize_is_replayed() {
+        let mut plain = History::new();
+        let initialize = plain.initialize();
+        plain.push(&[initialize], None, vec![]);
+        let expected = replay_transactions(&plain.program_id, &plain.address, &plain.transactions).account;
+
+        let mut sponsored = History { transactions: Vec::new(), ..plain };
+        let challenge =
+            registration_challenge(&sponsored.owner, &sponsored.address, &sponsored.phone.public_key(), &sponsored.phone.credential_id());
+        let registration = sponsored.phone.sign_create(&challenge);
+        let (pool, _) = instructions::derive_sponsor_pool_address(&sponsored.program_id, &Pubkey::new_unique());
+        let initialize = instructions::sponsored_initialize(
+            &sponsored.program_id,
+            &pool,
+            &sponsored.owner,
+            sponsored.phone.public_key(),
+            sponsored.phone.credential_id(),
+            None,
+            false,
+            &registration,
+            vec![],
+        )
+        .unwrap();
+        sponsored.push(&[initialize], None, vec![]);
+
+        let state = replay_transactions(&sponsored.program_id, &sponsored.address, &sponsored.transactions);
+        assert!(state.warnings.is_empty(), "{:?}", state.warnings);
+        assert_eq!(state.account, expected);
+    }
+
+    #[test]
+    fn test_indexed_initialize_is_replayed() {
+        let mut plain = History::new();
+        let initialize = plain.initialize();
+        plain.push(&[initialize], None, vec![]);
+        let mut expected = replay_transactions(&plain.program_id, &plain.address, &plain.transactions).account.unwrap();
+        expected.account_index = Some(3);
+
+        let address = instructions::derive_attesta_address_by_index(&plain.program_id, &plain.owner, 3).0;
+        let mut indexed = History { transactions: Vec::new(), address, ..plain };
+        let challenge =
+            registration_challenge(&indexed.owner, &indexed.address, &indexed.phone.public_key(), &indexed.phone.credential_id());
+        let registration = indexed.phone.sign_create(&challenge);
+        let initialize = instructions::initialize_with_index(
+            &indexed.program_id,
+            &indexed.owner,
+            3,
+            indexed.phone.public_key(),
+            indexed.phone.credential_id(),
+            None,
+            false,
+            &registration,
+            vec![],
+        )
+        .unwrap();
+        indexed.push(&[initialize], None, vec![]);
+
+        let state = replay_transactions(&indexed.program_id, &indexed.address, &indexed.transactions);
+        assert!(state.warnings.is_empty(), "{:?}", state.warnings);
+        assert_eq!(state.account, Some(expected));
+    }
+
+    #[test]
+    fn test_inheritance_claim_is_applied_from_its_event() {
+        let mut history = History::new();
+        let initialize = history.initialize();
+        history.push(&[initialize], None, vec![]);
+        let mut account = replay_transactions(&history.program_id, &history.address, &history.transactions).account;
+        let beneficiary = TestPasskey::new(3);
+        account.as_mut().unwrap().inheritance =
+            Some(smart_account::InheritanceConfig::new(beneficiary.public_key(), beneficiary.credential_id(), 100, 10));
+
+        let claim = Instruction::new_with_bytes(
+            history.program_id,
+            &instruction_discriminator("claim_inheritance"),
+            vec![solana_program::instruction::AccountMeta::new(history.address, false)],
+        );
+        let event = [&event_discriminator("InheritanceClaimed")[..], &borsh::to_vec(&(history.address, 1_800_000_000i64)).unwrap()].concat();
+        let log = format!("{}{}", EVENT_LOG_PREFIX, base64::engine::general_purpose::STANDARD.encode(event));
+        history.push(std::slice::from_ref(&claim), None, vec![]);
+        history.push(&[claim], None, vec![log]);
+
+        let apply = |transaction: &ConfirmedTransaction, account: &mut Option<AttestaAccount>| {
+            let data = &transaction.transaction.message.instructions[0].data;
+            Step { address: &history.address, accounts: &[history.address], data, transaction }.apply(account)
+        };
+        assert!(matches!(
+            apply(&history.transactions[1], &mut account),
+            Err(ReplayWarningKind::Rejected { instruction: "claim_inheritance", .. })
+        ));
+        apply(&history.transactions[2], &mut account).unwrap();
+
+        let account = account.unwrap();
+        assert_eq!(account.passkey_public_key, beneficiary.public_key());
+        assert!(account.inheritance.is_none());
+    }
+}
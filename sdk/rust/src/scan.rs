@@ -0,0 +1,296 @@
+//! Scanning the program's accounts without holding them all
+//!
+//! A plain `getProgramAccounts` sends every match in full at once, and a
+//! busy program has enough Attesta accounts to run a small service out of
+//! memory. `AccountScan` lists only the start of each account (a
+//! `dataSlice` of `ACCOUNT_HEADER_LEN` bytes), keeps the addresses whose
+//! header matches, and fetches those in pages of `ScanOptions::page_size`
+//! as the caller iterates. At most one page of full accounts is held at a
+//! time, and the next page isn't fetched until the current one is used up.
+//!
+//! Failures don't end the scan. An account that won't decode comes out as
+//! `AttestaError::UndecodableAccount` in its place, and a page that fails
+//! to fetch comes out as one error before the scan moves to the next page.
+//! Accounts closed between the listing and their page are passed over.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use attesta_types::consts::{ACCOUNT_DISCRIMINATOR_LEN, BORSH_LEN_PREFIX, PUBKEY_LEN};
+use smart_account::AttestaAccount;
+use solana_program::pubkey::Pubkey;
+use crate::backend::{DataFilter, RpcBackend};
+use crate::client::{decode_attesta_account, AttestaError};
+use crate::instructions::account_discriminator;
+
+/// Where an account's owner starts: after the discriminator and the
+/// length of the wrapped `AttestaAccount`, whose first field it is
+pub const OWNER_OFFSET: usize = ACCOUNT_DISCRIMINATOR_LEN + BORSH_LEN_PREFIX;
+
+/// The bytes a scan lists of each account: enough to check its owner
+pub const ACCOUNT_HEADER_LEN: usize = OWNER_OFFSET + PUBKEY_LEN;
+
+/// Most accounts fetched in one page (what `getMultipleAccounts` accepts)
+pub const MAX_SCAN_PAGE_SIZE: usize = 100;
+
+/// How an `AccountScan` fetches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Full accounts fetched, and held, at a time (1 to `MAX_SCAN_PAGE_SIZE`)
+    pub page_size: usize,
+
+    /// Stop after this many matching accounts
+    pub max_accounts: Option<usize>,
+
+    /// List only each account's header, fetching the matches a page at a
+    /// time. Turn off for nodes that don't support `dataSlice`: the listing
+    /// then sends every match in full, held until the scan reaches it.
+    pub data_slice_only: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self { page_size: MAX_SCAN_PAGE_SIZE, max_accounts: None, data_slice_only: true }
+    }
+}
+
+/// The `getProgramAccounts` filters matching the Attesta accounts `owner` owns
+pub fn owner_filters(owner: &Pubkey) -> Vec<DataFilter> {
+    vec![
+        DataFilter::new(0, account_discriminator("AttestaAccountData").to_vec()),
+        DataFilter::new(OWNER_OFFSET, owner.to_bytes().to_vec()),
+    ]
+}
+
+/// An account the listing found, with its data once fetched
+type Pending = (Pubkey, Option<Vec<u8>>);
+
+/// The Attesta accounts one owner owns, fetched a page at a time
+///
+/// Made by `AttestaClient::scan_accounts_by_owner`.
+pub struct AccountScan {
+    backend: Arc<dyn RpcBackend>,
+    program_id: Pubkey,
+    owner: Pubkey,
+    options: ScanOptions,
+    /// Addresses still to fetch; `None` until the listing is made
+    listed: Option<VecDeque<Pubkey>>,
+    /// Accounts fetched but not yet yielded
+    page: VecDeque<Pending>,
+    /// An error to yield before anything else
+    failure: Option<AttestaError>,
+}
+
+impl AccountScan {
+    pub(crate) fn new(backend: Arc<dyn RpcBackend>, program_id: Pubkey, owner: Pubkey, options: ScanOptions) -> Self {
+        Self {
+            backend,
+            program_id,
+            owner,
+            options: ScanOptions { page_size: options.page_size.clamp(1, MAX_SCAN_PAGE_SIZE), ..options },
+            listed: None,
+            page: VecDeque::new(),
+            failure: None,
+        }
+    }
+
+    /// Lists the matching accounts: their addresses, or with
+    /// `data_slice_only` off, the accounts in full
+    fn list(&mut self) -> VecDeque<Pubkey> {
+        let filters = owner_filters(&self.owner);
+        let max = self.options.max_accounts.unwrap_or(usize::MAX);
+        let listing = if self.options.data_slice_only {
+            self.backend.get_program_account_slices(&self.program_id, &filters, 0, ACCOUNT_HEADER_LEN)
+        } else {
+            self.backend.get_program_accounts_matching(&self.program_id, &filters)
+        };
+        let accounts = match listing {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                self.failure = Some(e);
+                return VecDeque::new();
+            }
+        };
+
+        // The node should have filtered already; a backend that can't still
+        // mustn't let other owners' accounts through
+        let matching = accounts
+            .into_iter()
+            .filter(|(_, data)| filters.iter().all(|filter| filter.matches(data)))
+            .take(max);
+        if self.options.data_slice_only {
+            matching.map(|(address, _)| address).collect()
+        } else {
+            self.page = matching.map(|(address, data)| (address, Some(data))).collect();
+            VecDeque::new()
+        }
+    }
+
+    /// Fetches the next page of listed accounts; false once there are none
+    fn fetch_page(&mut self) -> bool {
+        let Some(listed) = self.listed.as_mut() else {
+            return false;
+        };
+        let len = listed.len().min(self.options.page_size);
+        if len == 0 {
+            return false;
+        }
+        let addresses: Vec<Pubkey> = listed.drain(..len).collect();
+        match self.backend.get_multiple_account_data(&addresses) {
+            Ok(data) => self.page = addresses.into_iter().zip(data).collect(),
+            Err(e) => self.failure = Some(e),
+        }
+        true
+    }
+}
+
+impl Iterator for AccountScan {
+    type Item = Result<(Pubkey, AttestaAccount), AttestaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.listed.is_none() {
+            let listed = self.list();
+            self.listed = Some(listed);
+        }
+        loop {
+            if let Some(e) = self.failure.take() {
+                return Some(Err(e));
+            }
+            match self.page.pop_front() {
+                // Closed since it was listed
+                Some((_, None)) => continue,
+                Some((address, Some(data))) => match decode_attesta_account(&data) {
+                    Ok(account) if account.owner == self.owner => return Some(Ok((address, account))),
+                    Ok(_) => continue,
+                    Err(_) => return Some(Err(AttestaError::UndecodableAccount(address))),
+                },
+                None if self.fetch_page() => continue,
+                None => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+    use crate::client::AttestaClient;
+    use crate::test_utils::{attesta_account_data, MockBackend, RpcCall};
+
+    const ACCOUNTS: usize = 1_000;
+
+    /// Every third account belongs to `owner`, the rest to someone else
+    fn populated_backend(owner: &Pubkey) -> (MockBackend, Pubkey, Vec<Pubkey>) {
+        let backend = MockBackend::new();
+        let program_id = Pubkey::new_unique();
+        let mut owned = Vec::new();
+        for i in 0..ACCOUNTS {
+            let account_owner = if i % 3 == 0 { *owner } else { Pubkey::new_unique() };
+            let account = AttestaAccount::new(account_owner, [3u8; 64], b"phone".to_vec(), vec![], 100);
+            let address = Pubkey::new_unique();
+            backend.set_program_account(program_id, address, 1, attesta_account_data(&account));
+            if account_owner == *owner {
+                owned.push(address);
+            }
+        }
+        owned.sort();
+        (backend, program_id, owned)
+    }
+
+    fn fetched_pages(backend: &MockBackend) -> Vec<Vec<Pubkey>> {
+        backend
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                RpcCall::GetMultipleAccounts(addresses) => Some(addresses),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scan_fetches_only_matches_a_page_at_a_time() {
+        let owner = Pubkey::new_unique();
+        let (backend, program_id, owned) = populated_backend(&owner);
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+        let options = ScanOptions { page_size: 25, ..ScanOptions::default() };
+
+        let mut scan = client.scan_accounts_by_owner(&owner, options);
+        assert!(backend.calls().is_empty());
+
+        // The first item costs the listing and one page, nothing more
+        let (_, first) = scan.next().unwrap().unwrap();
+        assert_eq!(first.owner, owner);
+        assert_eq!(
+            backend.calls()[0],
+            RpcCall::GetProgramAccountSlices { program_id, offset: 0, length: ACCOUNT_HEADER_LEN }
+        );
+        assert_eq!(fetched_pages(&backend).len(), 1);
+
+        let rest: Vec<_> = scan.map(|item| item.unwrap()).collect();
+        let addresses: Vec<Pubkey> = std::iter::once(owned[0]).chain(rest.iter().map(|(address, _)| *address)).collect();
+        assert_eq!(addresses, owned);
+        assert!(rest.iter().all(|(_, account)| account.owner == owner));
+
+        // Never more than a page in flight, and only the owner's accounts fetched
+        let pages = fetched_pages(&backend);
+        assert!(pages.iter().all(|page| page.len() <= 25));
+        assert_eq!(pages.len(), owned.len().div_ceil(25));
+        assert_eq!(pages.concat(), owned);
+        assert!(!backend.calls().contains(&RpcCall::GetProgramAccounts(program_id)));
+    }
+
+    #[test]
+    fn test_scan_reports_undecodable_accounts_and_goes_on() {
+        let owner = Pubkey::new_unique();
+        let (backend, program_id, owned) = populated_backend(&owner);
+
+        // A header that matches, over a body that doesn't decode
+        let mut data = account_discriminator("AttestaAccountData").to_vec();
+        vec![0xffu8; 40].serialize(&mut data).unwrap();
+        data[OWNER_OFFSET..ACCOUNT_HEADER_LEN].copy_from_slice(owner.as_ref());
+        let broken = Pubkey::new_unique();
+        backend.set_program_account(program_id, broken, 1, data);
+
+        let client = AttestaClient::with_backend(backend, program_id);
+        let items: Vec<_> = client.scan_accounts_by_owner(&owner, ScanOptions::default()).collect();
+        assert_eq!(items.len(), owned.len() + 1);
+        let failures: Vec<_> = items.iter().filter_map(|item| item.as_ref().err()).collect();
+        assert!(matches!(failures.as_slice(), [AttestaError::UndecodableAccount(address)] if *address == broken));
+    }
+
+    #[test]
+    fn test_scan_stops_at_max_accounts() {
+        let owner = Pubkey::new_unique();
+        let (backend, program_id, owned) = populated_backend(&owner);
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+
+        let options = ScanOptions { page_size: 10, max_accounts: Some(15), ..ScanOptions::default() };
+        let addresses: Vec<Pubkey> = client.scan_accounts_by_owner(&owner, options).map(|item| item.unwrap().0).collect();
+        assert_eq!(addresses, owned[..15]);
+        assert_eq!(fetched_pages(&backend).iter().map(Vec::len).collect::<Vec<_>>(), [10, 5]);
+    }
+
+    #[test]
+    fn test_scan_without_data_slices_lists_in_full() {
+        let owner = Pubkey::new_unique();
+        let (backend, program_id, owned) = populated_backend(&owner);
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+
+        let options = ScanOptions { data_slice_only: false, ..ScanOptions::default() };
+        let addresses: Vec<Pubkey> = client.scan_accounts_by_owner(&owner, options).map(|item| item.unwrap().0).collect();
+        assert_eq!(addresses, owned);
+        assert_eq!(backend.calls(), [RpcCall::GetProgramAccounts(program_id)]);
+    }
+
+    #[test]
+    fn test_page_size_is_bounded() {
+        let owner = Pubkey::new_unique();
+        let (backend, program_id, owned) = populated_backend(&owner);
+        let client = AttestaClient::with_backend(backend.clone(), program_id);
+
+        let options = ScanOptions { page_size: 10_000, ..ScanOptions::default() };
+        assert_eq!(client.scan_accounts_by_owner(&owner, options).count(), owned.len());
+        assert!(fetched_pages(&backend).iter().all(|page| page.len() <= MAX_SCAN_PAGE_SIZE));
+    }
+}
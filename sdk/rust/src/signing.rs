@@ -0,0 +1,529 @@
+//! Building and checking the data a passkey signs
+//!
+//! Executing a transaction takes two round trips through the user's device:
+//!
+//! 1. `SigningRequest::new` works out the challenge to pass to
+//!    `navigator.credentials.get()`, using the account's next nonce.
+//! 2. `SigningRequest::complete` takes the authenticator's response, checks
+//!    it locally, and produces a `ProofEnvelope` ready to submit.
+//!
+//! Checking locally means a wrong challenge or credential shows up as a clear
+//! error naming the problem, instead of a failed transaction.
+//!
+//! Each request also gets an idempotency key, so submitting the same proof
+//! twice (a retry after a timeout) is answered instead of failing as a replay.
+//!
+//! Enrolling a passkey works the same way with `RegistrationRequest`: the
+//! passkey signs a challenge naming itself and the account, and the result
+//! carries the authenticator model (AAGUID) it attested, if any.
+
+use core_crypto::{
+    challenge::{base64url_encode, client_data_field, compute_challenge},
+    p256_verify::signature_to_raw,
+    display_code, domain_hash, parse_authenticator_data_detailed, verify_webauthn_signature_detailed, CeremonyType, Domain,
+    VerifyFailure, WebAuthnSignature, CHALLENGE_LEN,
+};
+use attesta_types::consts::{AAGUID_LEN, P256_PUBKEY_LEN};
+use smart_account::{
+    idempotency::IDEMPOTENCY_KEY_LEN, registration_challenge, verify_registration, AttestaAccount,
+    IdempotencyKey, TransactionRequest,
+};
+use solana_program::pubkey::Pubkey;
+use crate::client::AttestaError;
+
+pub use attesta_types::envelope::ProofEnvelope;
+
+/// How long a signing request stays valid by default (in seconds)
+pub const DEFAULT_SIGNING_REQUEST_TTL: i64 = 300;
+
+/// Everything needed to ask a passkey to authorize a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningRequest {
+    /// The raw challenge the passkey must sign
+    pub challenge: [u8; CHALLENGE_LEN],
+
+    /// The challenge as unpadded base64url (as it appears in `clientDataJSON`)
+    pub challenge_b64url: String,
+
+    /// Short code to show next to the passkey prompt
+    ///
+    /// The program logs the same code when it executes, so the user can
+    /// check the dApp didn't swap the transaction.
+    pub display_code: String,
+
+    /// The nonce this authorization will consume
+    pub nonce: u64,
+
+    /// The hash of the transaction being authorized
+    pub message_hash: [u8; 32],
+
+    /// The request's memo, which `message_hash` covers (`execute` must pass it)
+    pub memo: Vec<u8>,
+
+    /// Idempotency key sent with the execution, derived from the challenge
+    pub idempotency_key: IdempotencyKey,
+
+    /// The account's parent, if it's a sub-account (`execute` must pass it)
+    pub parent_account: Option<Pubkey>,
+
+    /// Whether the account logs its proofs (`execute` must pass the proof log)
+    pub logs_proofs: bool,
+
+    /// After this time (Unix timestamp) the SDK won't complete the request
+    ///
+    /// This is a client-side check only: it stops stale prompts from being
+    /// submitted, but the program itself relies on the nonce.
+    pub expires_at: i64,
+}
+
+impl SigningRequest {
+    /// Prepares a signing request for `request` on `account`
+    ///
+    /// # Parameters
+    /// - `account`: The account that will execute the transaction
+    /// - `request`: The transaction to authorize
+    /// - `now`: The current Unix timestamp
+    pub fn new(account: &AttestaAccount, request: &TransactionRequest, now: i64) -> Self {
+        // The program accepts any nonce above the current one - use the next
+        Self::with_nonce(account, request, account.nonce.saturating_add(1), now)
+    }
+
+    /// Prepares a signing request that will consume `nonce`
+    ///
+    /// For a nonce reserved ahead of time, when several requests are signed
+    /// before any of them executes. It must be above the account's nonce
+    /// when the execution lands.
+    pub fn with_nonce(account: &AttestaAccount, request: &TransactionRequest, nonce: u64, now: i64) -> Self {
+        Self {
+            memo: request.memo.clone(),
+            ..Self::for_message_hash(account, request.message_hash(), nonce, now)
+        }
+    }
+
+    /// Prepares a signing request over a message hash that isn't a transaction's
+    ///
+    /// For proofs the program checks outside `execute`, such as claim tickets.
+    pub fn for_message_hash(account: &AttestaAccount, message_hash: [u8; 32], nonce: u64, now: i64) -> Self {
+        let challenge = compute_challenge(&account.owner, nonce, &message_hash);
+
+        Self {
+            challenge,
+            challenge_b64url: base64url_encode(&challenge),
+            display_code: display_code(&challenge),
+            nonce,
+            message_hash,
+            memo: Vec::new(),
+            idempotency_key: idempotency_key_for(&challenge),
+            parent_account: account.parent,
+            logs_proofs: account.proof_log_enabled,
+            expires_at: now.saturating_add(DEFAULT_SIGNING_REQUEST_TTL),
+        }
+    }
+
+    /// Checks an authenticator response and turns it into a submit-ready proof
+    ///
+    /// # Parameters
+    /// - `assertion`: The response from `navigator.credentials.get()`
+    /// - `now`: The current Unix timestamp
+    ///
+    /// # Returns
+    /// - `Ok(ProofEnvelope)` if the response matches this request
+    /// - `Err(AttestaError::SigningRequestExpired)` if the request is too old
+    /// - `Err(AttestaError::AssertionMismatch)` naming the field that's wrong
+    pub fn complete(&self, assertion: AssertionResponse, now: i64) -> Result<ProofEnvelope, AttestaError> {
+        if now > self.expires_at {
+            return Err(AttestaError::SigningRequestExpired(self.expires_at));
+        }
+
+        if assertion.credential_id.is_empty() {
+            return Err(mismatch("credential_id", "empty credential ID".to_string()));
+        }
+
+        parse_authenticator_data_detailed(&assertion.authenticator_data).map_err(detailed_mismatch)?;
+
+        let expected_type = CeremonyType::Get.client_data_type();
+        match client_data_field(&assertion.client_data_json, "type") {
+            Some(found) if found == expected_type => {}
+            other => {
+                return Err(mismatch("type", format!("expected {:?}, got {:?}", expected_type, other)));
+            }
+        }
+
+        match client_data_field(&assertion.client_data_json, "challenge") {
+            Some(challenge) if challenge == self.challenge_b64url => {}
+            other => {
+                return Err(mismatch(
+                    "challenge",
+                    format!("expected {:?}, got {:?}", self.challenge_b64url, other),
+                ));
+            }
+        }
+
+        // Authenticators return DER signatures - the program expects raw r || s
+        let signature = signature_to_raw(&assertion.signature)
+            .map_err(|_| mismatch("signature", "not a valid P-256 signature".to_string()))?;
+
+        Ok(ProofEnvelope {
+            webauthn_sig: WebAuthnSignature::new(
+                assertion.authenticator_data,
+                assertion.client_data_json,
+                signature.to_vec(),
+                assertion.credential_id,
+            ),
+            nonce: self.nonce,
+            message_hash: self.message_hash,
+            memo: self.memo.clone(),
+            idempotency_key: self.idempotency_key,
+            parent_account: self.parent_account,
+            logs_proofs: self.logs_proofs,
+            emit_memo: false,
+        })
+    }
+}
+
+/// Everything needed to ask a passkey to prove itself when it's enrolled
+///
+/// `initialize` requires this proof; `add_passkey` and `initiate_recovery`
+/// require it when the account has an AAGUID allowlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistrationRequest {
+    /// The wallet that owns (or will own) the account
+    pub owner: Pubkey,
+
+    /// The account's address
+    pub account_address: Pubkey,
+
+    /// The passkey being enrolled
+    pub public_key: [u8; P256_PUBKEY_LEN],
+
+    /// Its credential ID
+    pub credential_id: Vec<u8>,
+
+    /// The raw challenge the passkey must sign
+    pub challenge: [u8; CHALLENGE_LEN],
+
+    /// The challenge as unpadded base64url (as it appears in `clientDataJSON`)
+    pub challenge_b64url: String,
+}
+
+/// A passkey's verified registration proof
+#[derive(Debug, Clone)]
+pub struct Registration {
+    /// The signature to submit as the instruction's registration argument
+    pub webauthn_sig: WebAuthnSignature,
+
+    /// The authenticator model the passkey attested, if the response came
+    /// from `navigator.credentials.create()` with attested credential data
+    pub aaguid: Option<[u8; AAGUID_LEN]>,
+}
+
+impl RegistrationRequest {
+    /// Prepares the registration challenge for a passkey on an account
+    pub fn new(owner: Pubkey, account_address: Pubkey, public_key: [u8; P256_PUBKEY_LEN], credential_id: Vec<u8>) -> Self {
+        let challenge = registration_challenge(&owner, &account_address, &public_key, &credential_id);
+        Self {
+            owner,
+            account_address,
+            public_key,
+            credential_id,
+            challenge,
+            challenge_b64url: base64url_encode(&challenge),
+        }
+    }
+
+    /// Checks the passkey's response the way the program will
+    ///
+    /// # Parameters
+    /// - `response`: The response from `navigator.credentials.create()`
+    ///   (an assertion from `navigator.credentials.get()` is refused)
+    ///
+    /// # Returns
+    /// - `Ok(Registration)` if the passkey signed this challenge
+    /// - `Err(AttestaError::AssertionMismatch)` naming the field that's wrong
+    pub fn complete(&self, response: AssertionResponse) -> Result<Registration, AttestaError> {
+        if response.credential_id != self.credential_id {
+            return Err(mismatch("credential_id", "not the passkey being registered".to_string()));
+        }
+
+        let signature = signature_to_raw(&response.signature)
+            .map_err(|_| mismatch("signature", "not a valid P-256 signature".to_string()))?;
+        let webauthn_sig = WebAuthnSignature::new(
+            response.authenticator_data,
+            response.client_data_json,
+            signature.to_vec(),
+            response.credential_id,
+        );
+
+        // Verified here first for the detail; `verify_registration` then only
+        // has the attested credential left to object to
+        verify_webauthn_signature_detailed(&webauthn_sig, &self.public_key, &self.challenge, CeremonyType::Create)
+            .map_err(detailed_mismatch)?;
+        let aaguid = verify_registration(
+            &self.owner,
+            &self.account_address,
+            &self.public_key,
+            &self.credential_id,
+            &webauthn_sig,
+        )
+        .map_err(|e| mismatch("authenticator_data", format!("{}: attests a different passkey", e)))?;
+
+        Ok(Registration { webauthn_sig, aaguid })
+    }
+}
+
+/// Derives the idempotency key for a challenge
+///
+/// The challenge is already unique per account, nonce, and transaction, so
+/// every submission of the same proof carries the same key without the
+/// client having to store one.
+pub fn idempotency_key_for(challenge: &[u8; CHALLENGE_LEN]) -> IdempotencyKey {
+    let hash = domain_hash(Domain::IdempotencyKey, challenge);
+    let mut key = IdempotencyKey::default();
+    key.copy_from_slice(&hash[..IDEMPOTENCY_KEY_LEN]);
+    key
+}
+
+fn mismatch(field: &'static str, reason: String) -> AttestaError {
+    AttestaError::AssertionMismatch { field, reason }
+}
+
+fn detailed_mismatch(failure: VerifyFailure) -> AttestaError {
+    mismatch(failure.field, failure.to_string())
+}
+
+/// The parts of a WebAuthn assertion returned by `navigator.credentials.get()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionResponse {
+    /// The credential ID (`rawId`) of the passkey that signed
+    pub credential_id: Vec<u8>,
+
+    /// `response.authenticatorData`
+    pub authenticator_data: Vec<u8>,
+
+    /// `response.clientDataJSON`
+    pub client_data_json: Vec<u8>,
+
+    /// `response.signature` (DER-encoded, or raw 64 bytes)
+    pub signature: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+    use smart_account::{execute_transaction, AuthorizationProof, PolicyResult};
+    use solana_program::pubkey::Pubkey;
+
+    fn assertion(webauthn_sig: WebAuthnSignature) -> AssertionResponse {
+        AssertionResponse {
+            credential_id: webauthn_sig.credential_id,
+            authenticator_data: webauthn_sig.authenticator_data,
+            client_data_json: webauthn_sig.client_data_json,
+            signature: webauthn_sig.signature,
+        }
+    }
+
+    fn setup() -> (TestPasskey, AttestaAccount, TransactionRequest) {
+        let passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        let request = TransactionRequest::new(b"transfer 1 SOL".to_vec());
+        (passkey, account, request)
+    }
+
+    #[test]
+    fn test_prepare_sign_complete_verify() {
+        let (mut passkey, mut account, request) = setup();
+
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+        assert_eq!(signing_request.nonce, 1);
+        assert_eq!(signing_request.expires_at, 1000 + DEFAULT_SIGNING_REQUEST_TTL);
+        assert!(core_crypto::verify_display_code(&signing_request.display_code, &signing_request.challenge));
+
+        // Real authenticators return DER signatures
+        let response = assertion(passkey.sign_der(&signing_request.challenge));
+        let envelope = signing_request.complete(response, 1010).unwrap();
+        assert_eq!(envelope.webauthn_sig.signature.len(), 64);
+
+        let result = execute_transaction(&mut account, &Pubkey::new_unique(), None, &AuthorizationProof::from(envelope), &request.transaction_data);
+        assert_eq!(result, Ok(PolicyResult::Allowed));
+        assert_eq!(account.nonce, 1);
+
+        // The next request picks up the next nonce
+        assert_eq!(SigningRequest::new(&account, &request, 1000).nonce, 2);
+    }
+
+    #[test]
+    fn test_memo_is_signed_and_carried_to_the_envelope() {
+        let (mut passkey, mut account, request) = setup();
+        let with_memo = request.clone().memo("order-1042").unwrap();
+
+        let signing_request = SigningRequest::new(&account, &with_memo, 1000);
+        assert_ne!(signing_request.challenge, SigningRequest::new(&account, &request, 1000).challenge);
+
+        let envelope = signing_request.complete(assertion(passkey.sign(&signing_request.challenge)), 1000).unwrap();
+        assert_eq!(envelope.memo, b"order-1042");
+        assert!(!envelope.emit_memo);
+
+        let result = execute_transaction(&mut account, &Pubkey::new_unique(), None, &AuthorizationProof::from(envelope), &with_memo.transaction_data);
+        assert_eq!(result, Ok(PolicyResult::Allowed));
+    }
+
+    #[test]
+    fn test_resubmitted_envelope_is_answered_not_replayed() {
+        let (mut passkey, mut account, request) = setup();
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+        let response = assertion(passkey.sign(&signing_request.challenge));
+        let envelope = signing_request.complete(response, 1000).unwrap();
+
+        let proof = AuthorizationProof::from(envelope);
+        assert_eq!(proof.idempotency_key, Some(signing_request.idempotency_key));
+        execute_transaction(&mut account, &Pubkey::new_unique(), None, &proof, &request.transaction_data).unwrap();
+
+        assert_eq!(
+            execute_transaction(&mut account, &Pubkey::new_unique(), None, &proof, &request.transaction_data),
+            Ok(PolicyResult::AlreadyExecuted { nonce: 1 })
+        );
+
+        // The next request gets its own key
+        assert_ne!(SigningRequest::new(&account, &request, 1000).idempotency_key, signing_request.idempotency_key);
+    }
+
+    #[test]
+    fn test_complete_names_challenge_mismatch() {
+        let (mut passkey, account, request) = setup();
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+
+        let response = assertion(passkey.sign(&[0u8; 32]));
+        match signing_request.complete(response, 1000) {
+            Err(AttestaError::AssertionMismatch { field, .. }) => assert_eq!(field, "challenge"),
+            other => panic!("expected challenge mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_complete_names_type_mismatch() {
+        let (mut passkey, account, request) = setup();
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+
+        let client_data = format!(r#"{{"type":"webauthn.create","challenge":"{}"}}"#, signing_request.challenge_b64url);
+        let response = assertion(passkey.sign_client_data(client_data.into_bytes()));
+        match signing_request.complete(response, 1000) {
+            Err(AttestaError::AssertionMismatch { field, .. }) => assert_eq!(field, "type"),
+            other => panic!("expected type mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_complete_names_signature_mismatch() {
+        let (mut passkey, account, request) = setup();
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+
+        let mut response = assertion(passkey.sign(&signing_request.challenge));
+        response.signature = vec![1, 2, 3];
+        match signing_request.complete(response, 1000) {
+            Err(AttestaError::AssertionMismatch { field, .. }) => assert_eq!(field, "signature"),
+            other => panic!("expected signature mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_registration_reports_attested_aaguid() {
+        let mut passkey = TestPasskey::new(1);
+        let request = RegistrationRequest::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            passkey.public_key(),
+            passkey.credential_id(),
+        );
+
+        let registration = request.complete(assertion(passkey.sign_registration(&request.challenge, [3u8; 16]))).unwrap();
+        assert_eq!(registration.aaguid, Some([3u8; 16]));
+
+        // Without attested credential data it proves the key but attests no model
+        let registration = request.complete(assertion(passkey.sign_create(&request.challenge))).unwrap();
+        assert_eq!(registration.aaguid, None);
+        assert_eq!(registration.webauthn_sig.signature.len(), 64);
+
+        // An assertion is refused
+        match request.complete(assertion(passkey.sign_der(&request.challenge))) {
+            Err(AttestaError::AssertionMismatch { field, .. }) => assert_eq!(field, "type"),
+            other => panic!("expected type mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_registration_names_mismatch() {
+        let mut passkey = TestPasskey::new(1);
+        let other = TestPasskey::new(2);
+        let request = RegistrationRequest::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            passkey.public_key(),
+            passkey.credential_id(),
+        );
+
+        match request.complete(assertion(passkey.sign_create(&[0u8; 32]))) {
+            Err(AttestaError::AssertionMismatch { field, .. }) => assert_eq!(field, "challenge"),
+            other => panic!("expected challenge mismatch, got {:?}", other),
+        }
+
+        let mut response = assertion(passkey.sign(&request.challenge));
+        response.credential_id = other.credential_id();
+        match request.complete(response) {
+            Err(AttestaError::AssertionMismatch { field, .. }) => assert_eq!(field, "credential_id"),
+            other => panic!("expected credential mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mismatch_reasons_carry_detail() {
+        let (mut passkey, account, request) = setup();
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+
+        let mut response = assertion(passkey.sign(&signing_request.challenge));
+        response.authenticator_data.truncate(10);
+        match signing_request.complete(response, 1000) {
+            Err(AttestaError::AssertionMismatch { field, reason }) => {
+                assert_eq!(field, "authenticator_data");
+                assert!(reason.ends_with("expected at least 37 bytes, got 10)"), "{}", reason);
+            }
+            other => panic!("expected authenticator data mismatch, got {:?}", other),
+        }
+
+        let registration = RegistrationRequest::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            passkey.public_key(),
+            passkey.credential_id(),
+        );
+        match registration.complete(assertion(passkey.sign_create(&[0u8; 32]))) {
+            Err(AttestaError::AssertionMismatch { field, reason }) => {
+                assert_eq!(field, "challenge");
+                assert!(reason.contains("expected hash "), "{}", reason);
+            }
+            other => panic!("expected challenge mismatch, got {:?}", other),
+        }
+
+        let mut response = assertion(passkey.sign_create(&registration.challenge));
+        response.authenticator_data.push(0);
+        match registration.complete(response) {
+            Err(AttestaError::AssertionMismatch { field, reason }) => {
+                assert_eq!(field, "authenticator_data");
+                assert!(reason.ends_with("at byte 37)"), "{}", reason);
+            }
+            other => panic!("expected authenticator data mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_complete_rejects_expired_request() {
+        let (mut passkey, account, request) = setup();
+        let signing_request = SigningRequest::new(&account, &request, 1000);
+
+        let response = assertion(passkey.sign(&signing_request.challenge));
+        assert!(matches!(
+            signing_request.complete(response, signing_request.expires_at + 1),
+            Err(AttestaError::SigningRequestExpired(_))
+        ));
+    }
+}
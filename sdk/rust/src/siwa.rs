@@ -0,0 +1,366 @@
+//! Sign in with Attesta
+//!
+//! A website proves a visitor controls an Attesta account by having one of
+//! its passkeys sign a `SignInPayload`: the site's domain, the account's
+//! address, a one-time nonce and a validity window. Nothing goes on-chain;
+//! the site checks the assertion with `verify_signin` against the account it
+//! fetched from the payload's address.
+//!
+//! The passkey signs `SignInPayload::challenge`, a SHA-256 over
+//! `SIWA_DOMAIN_TAG` and the payload's canonical bytes, so a sign-in can
+//! never pass for a transaction challenge or the other way round. The
+//! payload format is versioned; `test-vectors/siwa.txt` has its encodings
+//! for other implementations to check against.
+
+use std::collections::HashMap;
+use borsh::{BorshDeserialize, BorshSerialize};
+use core_crypto::{
+    challenge::client_data_field, domain_hash, verify_webauthn_signature, CeremonyType, CryptoError, Domain, WebAuthnSignature,
+    CHALLENGE_LEN,
+};
+use smart_account::{resolve_signing_key, AttestaAccount};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+
+/// Version of the payload format this SDK writes and accepts
+pub const SIWA_VERSION: u8 = 1;
+
+/// Prefixes the payload bytes in the challenge, keeping sign-ins apart from anything else a passkey signs
+pub const SIWA_DOMAIN_TAG: &[u8] = Domain::SignIn.prefix();
+
+/// How far ahead of the verifier's clock `issued_at` may be (in seconds)
+pub const MAX_CLOCK_SKEW: i64 = 60;
+
+/// Errors from checking a sign-in
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SiwaError {
+    #[error("Unsupported sign-in payload version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Sign-in payload expires before it was issued")]
+    InvalidWindow,
+
+    #[error("Sign-in payload was issued in the future")]
+    NotYetValid,
+
+    #[error("Sign-in payload expired")]
+    Expired,
+
+    #[error("Assertion was made for origin {found:?}, not https://{expected}")]
+    WrongDomain { expected: String, found: Option<String> },
+
+    #[error("Sign-in assertion rejected: {0}")]
+    InvalidAssertion(#[from] CryptoError),
+
+    #[error("Sign-in nonce was already used")]
+    NonceReused,
+
+    #[error("Invalid sign-in payload")]
+    InvalidPayload,
+}
+
+/// What a passkey signs to sign in to `domain` as `account`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignInPayload {
+    /// Payload format version (`SIWA_VERSION`)
+    pub version: u8,
+
+    /// The site asking, as its origin's host (and port, if any), e.g. `wallet.example`
+    pub domain: String,
+
+    /// Address of the Attesta account signing in
+    pub account: Pubkey,
+
+    /// Chosen by the site; each may be used for one sign-in only
+    pub nonce: String,
+
+    /// When the site created the request (Unix timestamp)
+    pub issued_at: i64,
+
+    /// After this time (Unix timestamp) the sign-in is refused
+    pub expiration: i64,
+}
+
+impl SignInPayload {
+    /// The canonical encoding: Borsh, fields in declaration order
+    pub fn to_bytes(&self) -> Vec<u8> {
+        borsh::to_vec(self).unwrap_or_default()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SiwaError> {
+        borsh::from_slice(data).map_err(|_| SiwaError::InvalidPayload)
+    }
+
+    /// The challenge to pass to `navigator.credentials.get()`
+    pub fn challenge(&self) -> [u8; CHALLENGE_LEN] {
+        domain_hash(Domain::SignIn, &self.to_bytes())
+    }
+
+    /// The text to show the user next to the passkey prompt
+    pub fn message(&self) -> String {
+        format!(
+            "{} wants you to sign in with your Attesta account:\n{}\n\nVersion: {}\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+            self.domain, self.account, self.version, self.nonce, self.issued_at, self.expiration,
+        )
+    }
+}
+
+/// Builds a sign-in request for the current payload version
+pub fn create_signin_request(
+    domain: &str,
+    account: Pubkey,
+    nonce: &str,
+    issued_at: i64,
+    expiration: i64,
+) -> SignInPayload {
+    SignInPayload {
+        version: SIWA_VERSION,
+        domain: domain.to_string(),
+        account,
+        nonce: nonce.to_string(),
+        issued_at,
+        expiration,
+    }
+}
+
+/// Remembers which sign-in nonces were used, so none is accepted twice
+///
+/// Sites running several servers back this with their shared session
+/// store; `MemoryNonceStore` does for one process.
+pub trait NonceStore {
+    /// Marks `nonce` used for `domain` until `expiration`
+    ///
+    /// Returns `false` if it was already used. After `expiration` the payload
+    /// is refused as expired anyway, so the store may forget it then.
+    fn consume(&mut self, domain: &str, nonce: &str, expiration: i64) -> bool;
+}
+
+/// A `NonceStore` in memory
+#[derive(Debug, Default)]
+pub struct MemoryNonceStore {
+    used: HashMap<(String, String), i64>,
+}
+
+impl MemoryNonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets nonces whose payloads expired by `now`
+    pub fn prune(&mut self, now: i64) {
+        self.used.retain(|_, expiration| *expiration > now);
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn consume(&mut self, domain: &str, nonce: &str, expiration: i64) -> bool {
+        self.used.insert((domain.to_string(), nonce.to_string()), expiration).is_none()
+    }
+}
+
+/// A checked sign-in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedSession {
+    /// The account signed in
+    pub account: Pubkey,
+
+    /// The domain it signed in to
+    pub domain: String,
+
+    /// The passkey that signed
+    pub credential_id: Vec<u8>,
+
+    /// When the session should end (the payload's `expiration`)
+    pub expires_at: i64,
+}
+
+/// Checks a passkey's assertion over `payload`
+///
+/// `account` must be the account fetched from `payload.account`; the
+/// assertion is checked against its registered passkeys.
+///
+/// # Parameters
+/// - `assertion`: The authenticator's response to `payload.challenge()`
+/// - `nonces`: Where used nonces are recorded; the nonce is only used up
+///   once everything else checked out
+/// - `now`: The verifier's current time (Unix timestamp)
+///
+/// # Returns
+/// - `Ok(VerifiedSession)` if the sign-in is good
+/// - `Err(SiwaError)` naming the first check that failed
+pub fn verify_signin(
+    payload: &SignInPayload,
+    assertion: &WebAuthnSignature,
+    account: &AttestaAccount,
+    nonces: &mut impl NonceStore,
+    now: i64,
+) -> Result<VerifiedSession, SiwaError> {
+    if payload.version != SIWA_VERSION {
+        return Err(SiwaError::UnsupportedVersion(payload.version));
+    }
+    if payload.expiration <= payload.issued_at {
+        return Err(SiwaError::InvalidWindow);
+    }
+    if payload.issued_at > now.saturating_add(MAX_CLOCK_SKEW) {
+        return Err(SiwaError::NotYetValid);
+    }
+    if now >= payload.expiration {
+        return Err(SiwaError::Expired);
+    }
+
+    let origin = client_data_field(&assertion.client_data_json, "origin");
+    if origin.and_then(|origin| origin.strip_prefix("https://")) != Some(payload.domain.as_str()) {
+        return Err(SiwaError::WrongDomain { expected: payload.domain.clone(), found: origin.map(str::to_string) });
+    }
+
+    let public_key = resolve_signing_key(account, &assertion.credential_id)?;
+    verify_webauthn_signature(assertion, &public_key, &payload.challenge(), CeremonyType::Get)?;
+
+    if !nonces.consume(&payload.domain, &payload.nonce, payload.expiration) {
+        return Err(SiwaError::NonceReused);
+    }
+
+    Ok(VerifiedSession {
+        account: payload.account,
+        domain: payload.domain.clone(),
+        credential_id: assertion.credential_id.clone(),
+        expires_at: payload.expiration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_crypto::test_utils::TestPasskey;
+
+    const VECTORS: &str = include_str!("../test-vectors/siwa.txt");
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn vector(name: &str) -> &'static str {
+        VECTORS
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no test vector named {}", name))
+    }
+
+    /// The payload in `test-vectors/siwa.txt`
+    fn vector_payload() -> SignInPayload {
+        create_signin_request("localhost", Pubkey::new_from_array([7; 32]), "k3x9f2q8", 1_700_000_000, 1_700_000_600)
+    }
+
+    fn setup() -> (AttestaAccount, TestPasskey, MemoryNonceStore) {
+        let passkey = TestPasskey::new(1);
+        let account = AttestaAccount::new(Pubkey::new_unique(), passkey.public_key(), passkey.credential_id(), vec![], 100);
+        (account, passkey, MemoryNonceStore::new())
+    }
+
+    #[test]
+    fn test_published_vectors() {
+        let payload = vector_payload();
+        assert_eq!(hex(&payload.to_bytes()), vector("payload_v1"));
+        assert_eq!(hex(&payload.challenge()), vector("challenge_v1"));
+        assert_eq!(SignInPayload::from_bytes(&payload.to_bytes()), Ok(payload));
+    }
+
+    #[test]
+    fn test_message_names_domain_and_account() {
+        let payload = vector_payload();
+        let message = payload.message();
+        assert!(message.starts_with("localhost wants you to sign in with your Attesta account:\n"));
+        assert!(message.contains(&payload.account.to_string()));
+        assert!(message.contains("Nonce: k3x9f2q8"));
+    }
+
+    #[test]
+    fn test_signin_happy_path() {
+        let (account, mut passkey, mut nonces) = setup();
+        let payload = vector_payload();
+        let assertion = passkey.sign(&payload.challenge());
+
+        let session = verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_100).unwrap();
+        assert_eq!(session.account, payload.account);
+        assert_eq!(session.domain, "localhost");
+        assert_eq!(session.credential_id, passkey.credential_id());
+        assert_eq!(session.expires_at, 1_700_000_600);
+    }
+
+    #[test]
+    fn test_expired_signin() {
+        let (account, mut passkey, mut nonces) = setup();
+        let payload = vector_payload();
+        let assertion = passkey.sign(&payload.challenge());
+
+        assert_eq!(
+            verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_600),
+            Err(SiwaError::Expired)
+        );
+        assert_eq!(
+            verify_signin(&payload, &assertion, &account, &mut nonces, 1_699_999_000),
+            Err(SiwaError::NotYetValid)
+        );
+    }
+
+    #[test]
+    fn test_replayed_nonce() {
+        let (account, mut passkey, mut nonces) = setup();
+        let payload = vector_payload();
+        let assertion = passkey.sign(&payload.challenge());
+
+        verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_100).unwrap();
+        assert_eq!(
+            verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_200),
+            Err(SiwaError::NonceReused)
+        );
+
+        nonces.prune(1_700_000_600);
+        assert!(nonces.used.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_domain() {
+        let (account, mut passkey, mut nonces) = setup();
+        let payload = vector_payload();
+        let assertion = passkey.sign_as(&payload.challenge(), "localhost", "https://evil.example", 0x05);
+
+        assert_eq!(
+            verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_100),
+            Err(SiwaError::WrongDomain {
+                expected: "localhost".to_string(),
+                found: Some("https://evil.example".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_failed_signin_keeps_nonce() {
+        let (account, mut passkey, mut nonces) = setup();
+        let payload = vector_payload();
+        let mut other = payload.clone();
+        other.account = Pubkey::new_unique();
+        let assertion = passkey.sign(&other.challenge());
+
+        assert!(matches!(
+            verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_100),
+            Err(SiwaError::InvalidAssertion(_))
+        ));
+        let assertion = passkey.sign(&payload.challenge());
+        verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_100).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_version_refused() {
+        let (account, mut passkey, mut nonces) = setup();
+        let mut payload = vector_payload();
+        payload.version = 2;
+        let assertion = passkey.sign(&payload.challenge());
+
+        assert_eq!(
+            verify_signin(&payload, &assertion, &account, &mut nonces, 1_700_000_100),
+            Err(SiwaError::UnsupportedVersion(2))
+        );
+    }
+}
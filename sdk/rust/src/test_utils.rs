@@ -0,0 +1,364 @@
+//! An in-memory `RpcBackend` for tests
+//!
+//! `MockBackend` answers from canned accounts and queued results, and
+//! records every call so tests can check exactly what a client method did.
+//! Clones share state: keep one to inspect after handing another to
+//! `AttestaClient::with_backend`.
+//!
+//! Only available in tests, or with the `test-utils` feature.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use anchor_client::{
+    solana_client::rpc_response::RpcKeyedAccount,
+    solana_sdk::{
+        commitment_config::CommitmentLevel,
+        hash::Hash,
+        signature::Signature,
+        transaction::{Transaction, TransactionError},
+    },
+};
+use borsh::BorshSerialize;
+use recovery::EncryptedBackup;
+use smart_account::{AttestaAccount, PendingTransaction, PolicyAttestation, ProofLog, SponsorPool};
+use solana_program::pubkey::Pubkey;
+use crate::backend::{ConfirmedTransaction, DataFilter, RpcBackend, SimulationResult};
+use crate::client::AttestaError;
+use crate::instructions::account_discriminator;
+
+/// One call made to a `MockBackend`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcCall {
+    GetAccountData(Pubkey),
+    GetLamports(Pubkey),
+    GetTokenAccountsByOwner { owner: Pubkey, token_program: Pubkey },
+    GetProgramAccounts(Pubkey),
+    GetProgramAccountSlices { program_id: Pubkey, offset: usize, length: usize },
+    GetMultipleAccounts(Vec<Pubkey>),
+    GetLatestBlockhash,
+    SendTransaction(Transaction),
+    GetSignatureStatuses { signatures: Vec<Signature>, commitment: CommitmentLevel },
+    IsBlockhashValid(Hash),
+    SimulateTransaction(Transaction),
+    RequestAirdrop { address: Pubkey, lamports: u64 },
+    GetTransaction(Signature),
+}
+
+#[derive(Default)]
+struct MockState {
+    accounts: HashMap<Pubkey, (u64, Vec<u8>)>,
+    program_owners: HashMap<Pubkey, Pubkey>,
+    token_accounts: HashMap<Pubkey, Vec<RpcKeyedAccount>>,
+    blockhash: Hash,
+    blockhashes: VecDeque<Hash>,
+    slot: u64,
+    send_results: VecDeque<Result<Signature, AttestaError>>,
+    signature_statuses: VecDeque<Option<Result<(), TransactionError>>>,
+    blockhash_validity: VecDeque<bool>,
+    simulations: VecDeque<SimulationResult>,
+    airdrop_results: VecDeque<Result<Signature, AttestaError>>,
+    transactions: HashMap<Signature, ConfirmedTransaction>,
+    calls: Vec<RpcCall>,
+}
+
+/// An `RpcBackend` with canned responses and a call recorder
+///
+/// Unknown accounts don't exist. Sends succeed unless a result was queued
+/// with `push_send_result`, and land at once unless statuses were queued
+/// with `push_signature_status`; simulations return
+/// `SimulationResult::default()` unless one was queued. Airdrops credit the
+/// account at once unless a result was queued with `push_airdrop_result`.
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        // A test that panicked mid-call already failed; keep the state readable
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds (or replaces) an account
+    pub fn set_account(&self, address: Pubkey, lamports: u64, data: Vec<u8>) {
+        self.state().accounts.insert(address, (lamports, data));
+    }
+
+    /// Adds an account owned by `program_id`, so `get_program_accounts` lists it
+    pub fn set_program_account(&self, program_id: Pubkey, address: Pubkey, lamports: u64, data: Vec<u8>) {
+        let mut state = self.state();
+        state.accounts.insert(address, (lamports, data));
+        state.program_owners.insert(address, program_id);
+    }
+
+    /// Removes an account
+    pub fn remove_account(&self, address: &Pubkey) {
+        let mut state = self.state();
+        state.accounts.remove(address);
+        state.program_owners.remove(address);
+    }
+
+    /// Sets the token accounts returned for `owner`
+    pub fn set_token_accounts(&self, owner: Pubkey, accounts: Vec<RpcKeyedAccount>) {
+        self.state().token_accounts.insert(owner, accounts);
+    }
+
+    /// Sets the blockhash returned by `get_latest_blockhash`
+    pub fn set_blockhash(&self, blockhash: Hash) {
+        self.state().blockhash = blockhash;
+    }
+
+    /// Queues a blockhash for `get_latest_blockhash` to return before the set one
+    pub fn push_blockhash(&self, blockhash: Hash) {
+        self.state().blockhashes.push_back(blockhash);
+    }
+
+    /// Sets the slot reads report having been made at
+    pub fn set_slot(&self, slot: u64) {
+        self.state().slot = slot;
+    }
+
+    /// Queues the result of the next `send_transaction`
+    pub fn push_send_result(&self, result: Result<Signature, AttestaError>) {
+        self.state().send_results.push_back(result);
+    }
+
+    /// Queues the status the next `get_signature_statuses` reports
+    ///
+    /// It applies to the most recently sent of the signatures asked about;
+    /// the others are reported as not landed. With nothing queued, every
+    /// transaction has landed successfully.
+    pub fn push_signature_status(&self, status: Option<Result<(), TransactionError>>) {
+        self.state().signature_statuses.push_back(status);
+    }
+
+    /// Queues the answer of the next `is_blockhash_valid` (otherwise `true`)
+    pub fn push_blockhash_validity(&self, valid: bool) {
+        self.state().blockhash_validity.push_back(valid);
+    }
+
+    /// Queues the result of the next `simulate_transaction`
+    pub fn push_simulation(&self, result: SimulationResult) {
+        self.state().simulations.push_back(result);
+    }
+
+    /// Queues the result of the next `request_airdrop`
+    ///
+    /// A queued error leaves the account's balance alone; a queued signature
+    /// still credits it.
+    pub fn push_airdrop_result(&self, result: Result<Signature, AttestaError>) {
+        self.state().airdrop_results.push_back(result);
+    }
+
+    /// Adds (or replaces) a confirmed transaction for `get_transaction`
+    pub fn set_transaction(&self, transaction: ConfirmedTransaction) {
+        self.state().transactions.insert(transaction.signature, transaction);
+    }
+
+    /// Every call made so far, in order
+    pub fn calls(&self) -> Vec<RpcCall> {
+        self.state().calls.clone()
+    }
+
+    /// The transactions sent so far, in order
+    pub fn sent_transactions(&self) -> Vec<Transaction> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                RpcCall::SendTransaction(transaction) => Some(transaction),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Forgets the calls recorded so far
+    pub fn clear_calls(&self) {
+        self.state().calls.clear();
+    }
+
+    fn record(&self, call: RpcCall) {
+        self.state().calls.push(call);
+    }
+
+    fn program_accounts(&self, program_id: &Pubkey) -> Vec<(Pubkey, Vec<u8>)> {
+        let state = self.state();
+        let mut accounts: Vec<(Pubkey, Vec<u8>)> = state.program_owners
+            .iter()
+            .filter(|(_, owner)| *owner == program_id)
+            .filter_map(|(address, _)| state.accounts.get(address).map(|(_, data)| (*address, data.clone())))
+            .collect();
+        // HashMap order isn't stable; keep results deterministic
+        accounts.sort_by_key(|(address, _)| *address);
+        accounts
+    }
+}
+
+impl RpcBackend for MockBackend {
+    fn get_account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>, AttestaError> {
+        self.record(RpcCall::GetAccountData(*address));
+        Ok(self.state().accounts.get(address).map(|(_, data)| data.clone()))
+    }
+
+    fn get_account_data_with_slot(&self, address: &Pubkey) -> Result<(Option<Vec<u8>>, u64), AttestaError> {
+        self.record(RpcCall::GetAccountData(*address));
+        let state = self.state();
+        Ok((state.accounts.get(address).map(|(_, data)| data.clone()), state.slot))
+    }
+
+    fn get_lamports(&self, address: &Pubkey) -> Result<Option<u64>, AttestaError> {
+        self.record(RpcCall::GetLamports(*address));
+        Ok(self.state().accounts.get(address).map(|(lamports, _)| *lamports))
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        token_program: &Pubkey,
+    ) -> Result<Vec<RpcKeyedAccount>, AttestaError> {
+        self.record(RpcCall::GetTokenAccountsByOwner { owner: *owner, token_program: *token_program });
+        Ok(self.state().token_accounts.get(owner).cloned().unwrap_or_default())
+    }
+
+    fn get_program_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        self.record(RpcCall::GetProgramAccounts(*program_id));
+        Ok(self.program_accounts(program_id))
+    }
+
+    fn get_program_account_slices(
+        &self,
+        program_id: &Pubkey,
+        filters: &[DataFilter],
+        offset: usize,
+        length: usize,
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, AttestaError> {
+        self.record(RpcCall::GetProgramAccountSlices { program_id: *program_id, offset, length });
+        Ok(self
+            .program_accounts(program_id)
+            .into_iter()
+            .filter(|(_, data)| filters.iter().all(|filter| filter.matches(data)))
+            .map(|(address, data)| (address, data.iter().skip(offset).take(length).copied().collect()))
+            .collect())
+    }
+
+    fn get_multiple_account_data(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Vec<u8>>>, AttestaError> {
+        self.record(RpcCall::GetMultipleAccounts(addresses.to_vec()));
+        let state = self.state();
+        Ok(addresses.iter().map(|address| state.accounts.get(address).map(|(_, data)| data.clone())).collect())
+    }
+
+    fn get_latest_blockhash(&self) -> Result<Hash, AttestaError> {
+        self.record(RpcCall::GetLatestBlockhash);
+        let mut state = self.state();
+        let queued = state.blockhashes.pop_front();
+        Ok(queued.unwrap_or(state.blockhash))
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AttestaError> {
+        self.record(RpcCall::SendTransaction(transaction.clone()));
+        let queued = self.state().send_results.pop_front();
+        queued.unwrap_or_else(|| Ok(transaction.signatures.first().copied().unwrap_or_default()))
+    }
+
+    fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+        commitment: CommitmentLevel,
+    ) -> Result<Vec<Option<Result<(), TransactionError>>>, AttestaError> {
+        self.record(RpcCall::GetSignatureStatuses { signatures: signatures.to_vec(), commitment });
+        let queued = self.state().signature_statuses.pop_front();
+        let mut statuses = vec![None; signatures.len()];
+        if let Some(last) = statuses.last_mut() {
+            *last = queued.unwrap_or(Some(Ok(())));
+        }
+        Ok(statuses)
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, AttestaError> {
+        self.record(RpcCall::IsBlockhashValid(*blockhash));
+        Ok(self.state().blockhash_validity.pop_front().unwrap_or(true))
+    }
+
+    fn simulate_transaction(&self, transaction: &Transaction) -> Result<SimulationResult, AttestaError> {
+        self.record(RpcCall::SimulateTransaction(transaction.clone()));
+        Ok(self.state().simulations.pop_front().unwrap_or_default())
+    }
+
+    fn get_transaction(&self, signature: &Signature) -> Result<ConfirmedTransaction, AttestaError> {
+        self.record(RpcCall::GetTransaction(*signature));
+        self.state()
+            .transactions
+            .get(signature)
+            .cloned()
+            .ok_or_else(|| AttestaError::RpcError(format!("transaction {} not found", signature)))
+    }
+
+    fn request_airdrop(&self, address: &Pubkey, lamports: u64) -> Result<Signature, AttestaError> {
+        self.record(RpcCall::RequestAirdrop { address: *address, lamports });
+        let mut state = self.state();
+        let signature = state.airdrop_results.pop_front().unwrap_or_else(|| Ok(Signature::new_unique()))?;
+        state.accounts.entry(*address).or_insert_with(|| (0, Vec::new())).0 += lamports;
+        Ok(signature)
+    }
+}
+
+/// Encodes an `AttestaAccount` the way the program stores it on-chain
+pub fn attesta_account_data(account: &AttestaAccount) -> Vec<u8> {
+    let mut data = account_discriminator("AttestaAccountData").to_vec();
+    // Writing into a Vec can't fail
+    account.to_bytes().unwrap_or_default().serialize(&mut data).unwrap_or_default();
+    data
+}
+
+/// Encodes a backup escrow account holding `backup`
+pub fn backup_escrow_data(attesta_account: &Pubkey, backup: &EncryptedBackup) -> Vec<u8> {
+    let mut data = account_discriminator("BackupEscrow").to_vec();
+    (
+        *attesta_account,
+        backup.to_bytes().unwrap_or_default(),
+        0i64,
+        255u8,
+    )
+        .serialize(&mut data)
+        .unwrap_or_default();
+    data
+}
+
+/// Encodes a proof log account holding `log`
+pub fn proof_log_data(attesta_account: &Pubkey, log: &ProofLog) -> Vec<u8> {
+    let mut data = account_discriminator("ProofLogData").to_vec();
+    (*attesta_account, log.to_bytes().unwrap_or_default(), 255u8)
+        .serialize(&mut data)
+        .unwrap_or_default();
+    data
+}
+
+/// Encodes a policy attestation account holding `attestation`
+pub fn policy_attestation_data(attestation: &PolicyAttestation) -> Vec<u8> {
+    let mut data = account_discriminator("PolicyAttestationData").to_vec();
+    (attestation.attesta_account, attestation.to_bytes().unwrap_or_default(), 255u8)
+        .serialize(&mut data)
+        .unwrap_or_default();
+    data
+}
+
+/// Encodes a sponsor pool account controlled by `authority`
+pub fn sponsor_pool_data(authority: &Pubkey, pool: &SponsorPool) -> Vec<u8> {
+    let mut data = account_discriminator("SponsorPoolData").to_vec();
+    (*authority, pool.to_bytes().unwrap_or_default(), 255u8)
+        .serialize(&mut data)
+        .unwrap_or_default();
+    data
+}
+
+/// Encodes a proposal account of `attesta_account` holding `proposal`
+pub fn proposal_data(attesta_account: &Pubkey, proposal: &PendingTransaction) -> Vec<u8> {
+    let mut data = account_discriminator("ProposalData").to_vec();
+    (*attesta_account, proposal.to_bytes().unwrap_or_default(), 255u8)
+        .serialize(&mut data)
+        .unwrap_or_default();
+    data
+}
@@ -0,0 +1,41 @@
+//! End-to-end tests against a running validator
+//!
+//! Start `solana-test-validator --bpf-program <ID> attesta.so`, set
+//! `ATTESTA_PROGRAM_ID=<ID>`, then run
+//! `cargo test -p attesta-sdk --features devtools -- --ignored`.
+//! Set `ATTESTA_RPC_URL` as well to run them against devnet.
+
+#![cfg(feature = "devtools")]
+
+use anchor_client::solana_sdk::signature::Signer;
+use attesta_sdk::{Amount, ExecutionStatus, LocalEnv, TransactionRequest};
+use solana_program::pubkey::Pubkey;
+
+#[test]
+#[ignore = "needs a local validator with the program deployed"]
+fn test_bootstrapped_account_executes() {
+    let mut env = LocalEnv::bootstrap().unwrap();
+
+    let account = env.client.get_account(&env.account).unwrap();
+    assert_eq!(account.owner, env.payer.pubkey());
+    assert_eq!(account.nonce, 0);
+
+    let request = TransactionRequest::new(b"hello from localnet".to_vec());
+    let envelope = env.sign(&request).unwrap();
+    let receipt = env.client.execute(&env.payer, &env.account, &envelope, request.transaction_data).unwrap().receipt().unwrap();
+
+    assert_eq!(receipt.status, ExecutionStatus::Executed);
+    assert_eq!(env.client.get_account(&env.account).unwrap().nonce, 1);
+}
+
+#[test]
+#[ignore = "needs a local validator with the program deployed"]
+fn test_fund_account_splits_large_amounts() {
+    let env = LocalEnv::bootstrap().unwrap();
+    let recipient = Pubkey::new_unique();
+
+    let signatures = env.fund_account(&recipient, 4.5).unwrap();
+
+    assert_eq!(signatures.len(), 3);
+    assert_eq!(env.client.get_balances(&recipient).unwrap().sol, Amount::from_sol(4.5).unwrap());
+}
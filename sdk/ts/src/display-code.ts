@@ -0,0 +1,55 @@
+/**
+ * Display codes: short codes users compare to make sure they're signing
+ * the transaction the dApp claims
+ *
+ * Must match `core_crypto::digest::display_code` in the Rust crates - both
+ * are pinned by crates/core-crypto/test-vectors/display_code.txt.
+ */
+
+const ALPHABET = '0123456789ABCDEFGHJKMNPQRSTVWXYZ';
+const CHECK_ALPHABET = ALPHABET + '*~$=U';
+
+/** Length of a display code, including the check character */
+export const DISPLAY_CODE_LEN = 8;
+
+/**
+ * Derives the 8-character display code for a 32-byte execution challenge
+ */
+export async function displayCode(challenge: Uint8Array): Promise<string> {
+  if (challenge.length !== 32) {
+    throw new Error('Challenge must be 32 bytes');
+  }
+
+  const prefix = new TextEncoder().encode('attesta-display-code');
+  const input = new Uint8Array(prefix.length + challenge.length);
+  input.set(prefix);
+  input.set(challenge, prefix.length);
+  const digest = new Uint8Array(await crypto.subtle.digest('SHA-256', input));
+
+  // The top 35 bits of the hash (BigInt: doesn't fit in 32-bit bitwise ops)
+  let value = 0n;
+  for (const byte of digest.slice(0, 5)) {
+    value = (value << 8n) | BigInt(byte);
+  }
+  value >>= 5n;
+
+  let code = '';
+  for (let i = 6n; i >= 0n; i--) {
+    code += ALPHABET[Number((value >> (5n * i)) & 31n)];
+  }
+  return code + CHECK_ALPHABET[Number(value % 37n)];
+}
+
+/**
+ * Checks a code the user typed or read back against a challenge
+ *
+ * Accepts lowercase, `O` for `0`, `I`/`L` for `1`, and `-` separators.
+ */
+export async function verifyDisplayCode(code: string, challenge: Uint8Array): Promise<boolean> {
+  const normalized = code
+    .replace(/-/g, '')
+    .toUpperCase()
+    .replace(/O/g, '0')
+    .replace(/[IL]/g, '1');
+  return normalized === (await displayCode(challenge));
+}
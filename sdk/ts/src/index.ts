@@ -14,6 +14,7 @@ export * from './account';
 export * from './instructions';
 export * from './config';
 export * from './webauthn-utils';
+export * from './display-code';
 
 // Types
 export interface AttestaAccount {